@@ -0,0 +1,168 @@
+//! Docker archives
+//!
+//! Packs an image the way `docker save` does, so `docker load` can read it:
+//! `manifest.json` and `repositories` at the root, the config as
+//! `<hex>.json` and each layer as an uncompressed `<diff ID hex>/layer.tar`.
+
+use crate::oci::StoredImage;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Entry of `manifest.json`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveManifest {
+    config: String,
+    repo_tags: Vec<String>,
+    layers: Vec<String>,
+}
+
+/// Pack an image as a docker archive tar
+pub fn docker_archive(image: &StoredImage) -> Result<Vec<u8>, String> {
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+
+    let config = format!(
+        "{}.json",
+        crate::calculate_digest(&image.config).trim_start_matches("sha256:")
+    );
+    files.push((config.clone(), image.config.clone()));
+
+    let mut layers = Vec::new();
+    for layer in &image.layers {
+        let path = format!("{}/layer.tar", layer.diff_id.trim_start_matches("sha256:"));
+        // Identical layers are stored once
+        if !layers.contains(&path) {
+            files.push((path.clone(), layer.tar()?));
+        }
+        layers.push(path);
+    }
+
+    let repo_tags: Vec<String> = image.tags.iter().map(|tag| repo_tag(tag)).collect();
+    let mut repositories: BTreeMap<&str, BTreeMap<&str, &str>> = BTreeMap::new();
+    if let Some(top) = layers.last().and_then(|path| path.split('/').next()) {
+        for tag in &repo_tags {
+            if let Some((repo, tag)) = split_tag(tag) {
+                repositories.entry(repo).or_default().insert(tag, top);
+            }
+        }
+    }
+
+    let manifest = vec![ArchiveManifest {
+        config,
+        repo_tags: repo_tags.clone(),
+        layers: layers.clone(),
+    }];
+    files.push((
+        "manifest.json".to_string(),
+        serde_json::to_vec(&manifest).unwrap_or_default(),
+    ));
+    files.push((
+        "repositories".to_string(),
+        serde_json::to_vec(&repositories).unwrap_or_default(),
+    ));
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in &files {
+        if let Some((dir, _)) = path.split_once('/') {
+            let mut header = header(tar::EntryType::Directory, 0o755, 0);
+            // Writing to memory cannot fail
+            let _ = builder.append_data(&mut header, format!("{}/", dir), std::io::empty());
+        }
+        let mut header = header(tar::EntryType::Regular, 0o644, content.len() as u64);
+        let _ = builder.append_data(&mut header, path, content.as_slice());
+    }
+    builder
+        .into_inner()
+        .map_err(|e| format!("Failed to write archive: {}", e))
+}
+
+fn header(entry_type: tar::EntryType, mode: u32, size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header.set_mtime(0);
+    header
+}
+
+/// Tag with an explicit `:latest` when it names none
+fn repo_tag(tag: &str) -> String {
+    match split_tag(tag) {
+        Some(_) => tag.to_string(),
+        None => format!("{}:latest", tag),
+    }
+}
+
+/// Split `repo:tag`, ignoring the port of a registry host
+fn split_tag(tag: &str) -> Option<(&str, &str)> {
+    let name_start = tag.rfind('/').map_or(0, |i| i + 1);
+    let colon = tag[name_start..].rfind(':')? + name_start;
+    Some((&tag[..colon], &tag[colon + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::LayerBlob;
+    use std::io::Read;
+
+    #[test]
+    fn test_repo_tag() {
+        assert_eq!(repo_tag("app"), "app:latest");
+        assert_eq!(repo_tag("app:1.0"), "app:1.0");
+        assert_eq!(
+            repo_tag("localhost:5000/team/app"),
+            "localhost:5000/team/app:latest"
+        );
+        assert_eq!(
+            split_tag("localhost:5000/app:dev"),
+            Some(("localhost:5000/app", "dev"))
+        );
+    }
+
+    #[test]
+    fn test_docker_archive() {
+        let layer = LayerBlob::new(b"layer tar".to_vec(), true);
+        let image = StoredImage {
+            manifest: Vec::new(),
+            config: br#"{"os":"linux"}"#.to_vec(),
+            layers: vec![layer.clone(), layer.clone()],
+            tags: vec!["app".to_string(), "app:1.0".to_string()],
+        };
+        let archive = docker_archive(&image).unwrap();
+
+        let mut files = BTreeMap::new();
+        for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            files.insert(path, content);
+        }
+
+        let layer_dir = layer.diff_id.trim_start_matches("sha256:");
+        let layer_path = format!("{}/layer.tar", layer_dir);
+        // Layers are stored uncompressed, and only once
+        assert_eq!(files[&layer_path], b"layer tar");
+        assert_eq!(files.len(), 5);
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+        let config = manifest[0]["Config"].as_str().unwrap();
+        assert_eq!(files[config], image.config);
+        assert_eq!(
+            manifest[0]["RepoTags"],
+            serde_json::json!(["app:latest", "app:1.0"])
+        );
+        assert_eq!(
+            manifest[0]["Layers"],
+            serde_json::json!([layer_path, layer_path])
+        );
+
+        let repositories: serde_json::Value =
+            serde_json::from_slice(&files["repositories"]).unwrap();
+        assert_eq!(
+            repositories,
+            serde_json::json!({ "app": { "latest": layer_dir, "1.0": layer_dir } })
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Build arguments
+//!
+//! Tracks the variables an instruction can see and expands `$VAR`,
+//! `${VAR}`, `${VAR:-default}` and `${VAR:+alternative}` in it. ARGs before
+//! the first FROM are global and only visible to FROM lines, unless a stage
+//! declares them again. Inside a stage, ARG and ENV both define variables,
+//! and an ARG's value comes from the build args when one is given.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Build args every build accepts without an ARG instruction
+pub const PREDEFINED_ARGS: [&str; 8] = [
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "FTP_PROXY",
+    "ftp_proxy",
+    "NO_PROXY",
+    "no_proxy",
+];
+
+/// Variables in scope while reading a Runefile
+#[derive(Debug, Clone)]
+pub struct ArgScope<'a> {
+    build_args: &'a HashMap<String, String>,
+    global: HashMap<String, Option<String>>,
+    stage: Option<HashMap<String, String>>,
+    declared: BTreeSet<String>,
+    undefined: BTreeSet<String>,
+}
+
+impl<'a> ArgScope<'a> {
+    /// Start before the first FROM
+    pub fn new(build_args: &'a HashMap<String, String>) -> Self {
+        Self {
+            build_args,
+            global: HashMap::new(),
+            stage: None,
+            declared: BTreeSet::new(),
+            undefined: BTreeSet::new(),
+        }
+    }
+
+    /// Enter a new stage, forgetting the variables of the last one
+    pub fn start_stage(&mut self) {
+        self.stage = Some(HashMap::new());
+    }
+
+    /// Declare an ARG, returning its value
+    ///
+    /// The default is expanded first. A stage ARG without a default takes
+    /// the value of the global ARG of the same name.
+    pub fn declare(&mut self, name: &str, default: Option<&str>) -> Option<String> {
+        let default = default.map(|default| self.expand(default));
+        self.declared.insert(name.to_string());
+        let value = self.build_args.get(name).cloned().or(default);
+        match &mut self.stage {
+            None => {
+                self.global.insert(name.to_string(), value.clone());
+                value
+            }
+            Some(stage) => {
+                let value = value.or_else(|| self.global.get(name).cloned().flatten());
+                if let Some(value) = &value {
+                    stage.insert(name.to_string(), value.clone());
+                }
+                value
+            }
+        }
+    }
+
+    /// Define an ENV variable in the current stage
+    pub fn set_env(&mut self, key: &str, value: &str) {
+        if let Some(stage) = &mut self.stage {
+            stage.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// Expand variables, replacing unknown ones with nothing
+    pub fn expand(&mut self, text: &str) -> String {
+        self.substitute(text, false)
+    }
+
+    /// Expand known variables and leave the rest for the shell
+    pub fn expand_shell(&mut self, text: &str) -> String {
+        self.substitute(text, true)
+    }
+
+    /// Warnings about unused build args and undefined variables
+    pub fn warnings(&self) -> Vec<String> {
+        let mut unused: Vec<&String> = self
+            .build_args
+            .keys()
+            .filter(|name| {
+                !self.declared.contains(*name) && !PREDEFINED_ARGS.contains(&name.as_str())
+            })
+            .collect();
+        unused.sort();
+        unused
+            .into_iter()
+            .map(|name| format!("Build arg {} is not declared by an ARG instruction", name))
+            .chain(
+                self.undefined
+                    .iter()
+                    .map(|name| format!("Variable {} is not defined", name)),
+            )
+            .collect()
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        match &self.stage {
+            None => self.global.get(name).cloned().flatten(),
+            Some(stage) => stage.get(name).cloned(),
+        }
+        .or_else(|| {
+            PREDEFINED_ARGS
+                .contains(&name)
+                .then(|| self.build_args.get(name).cloned())
+                .flatten()
+        })
+    }
+
+    fn substitute(&mut self, text: &str, keep_unknown: bool) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '\\' if !keep_unknown && chars.peek().map(|(_, c)| *c) == Some('$') => {
+                    result.push('$');
+                    chars.next();
+                }
+                '$' => {
+                    let (name, modifier, end) = match chars.peek() {
+                        Some((_, '{')) => {
+                            let Some(close) = text[start..].find('}') else {
+                                result.push_str(&text[start..]);
+                                break;
+                            };
+                            let inner = &text[start + 2..start + close];
+                            let (name, modifier) = match inner.find(':') {
+                                Some(colon) => (&inner[..colon], Some(&inner[colon + 1..])),
+                                None => (inner, None),
+                            };
+                            (name, modifier, start + close + 1)
+                        }
+                        _ => {
+                            let len = text[start + 1..]
+                                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                                .unwrap_or(text.len() - start - 1);
+                            (&text[start + 1..start + 1 + len], None, start + 1 + len)
+                        }
+                    };
+                    while chars.peek().is_some_and(|(i, _)| *i < end) {
+                        chars.next();
+                    }
+                    if name.is_empty() {
+                        result.push_str(&text[start..end]);
+                        continue;
+                    }
+
+                    let value = self.lookup(name);
+                    match (modifier, value) {
+                        (Some(m), value) if m.starts_with('-') => {
+                            match value.filter(|v| !v.is_empty()) {
+                                Some(value) => result.push_str(&value),
+                                None => result.push_str(&m[1..]),
+                            }
+                        }
+                        (Some(m), value) if m.starts_with('+') => {
+                            if value.is_some_and(|v| !v.is_empty()) {
+                                result.push_str(&m[1..]);
+                            }
+                        }
+                        (_, Some(value)) => result.push_str(&value),
+                        (_, None) if keep_unknown => result.push_str(&text[start..end]),
+                        (_, None) => {
+                            self.undefined.insert(name.to_string());
+                        }
+                    }
+                }
+                c => result.push(c),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_args(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let args = build_args(&[("VERSION", "3.19")]);
+        let mut scope = ArgScope::new(&args);
+        scope.declare("BASE", Some("alpine"));
+        scope.declare("VERSION", Some("3.18"));
+        assert_eq!(scope.expand("${BASE}:$VERSION"), "alpine:3.19");
+
+        // Global args are only visible to FROM unless declared again
+        scope.start_stage();
+        assert_eq!(scope.expand("$BASE"), "");
+        assert_eq!(scope.declare("BASE", None), Some("alpine".to_string()));
+        scope.set_env("APP_DIR", "/srv/app");
+        assert_eq!(scope.expand("$APP_DIR/$BASE"), "/srv/app/alpine");
+        assert_eq!(scope.expand("${PORT:-8080} ${BASE:+set}"), "8080 set");
+        assert_eq!(scope.expand(r"cost \$5 $"), "cost $5 $");
+        assert_eq!(
+            scope.expand_shell("echo $HOME $APP_DIR"),
+            "echo $HOME /srv/app"
+        );
+
+        scope.start_stage();
+        assert_eq!(scope.expand("$APP_DIR"), "");
+    }
+
+    #[test]
+    fn test_warnings() {
+        let args = build_args(&[("USED", "1"), ("UNUSED", "2"), ("HTTP_PROXY", "p")]);
+        let mut scope = ArgScope::new(&args);
+        scope.start_stage();
+        scope.declare("USED", None);
+        scope.expand("$USED $MISSING");
+        assert_eq!(scope.expand("$HTTP_PROXY"), "p");
+        assert_eq!(
+            scope.warnings(),
+            vec![
+                "Build arg UNUSED is not declared by an ARG instruction".to_string(),
+                "Variable MISSING is not defined".to_string(),
+            ]
+        );
+    }
+}
@@ -1,18 +1,46 @@
 //! WASM Image Builder
 
+use crate::archive::docker_archive;
+use crate::cache::{cache_key, BuildCache};
 use crate::filesystem::BuilderFilesystem;
+use crate::glob;
+use crate::ignore::IgnoreRules;
+use crate::layer::{normalize_path, LayerBlob, LayerBuilder, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE};
+use crate::oci::{read_image, ImageLayout};
 use crate::parser::RunefileParser;
+use crate::registry::{BaseImage, ImageReference, RegistryAuth, RegistryClient};
 use crate::types::*;
 use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
 
 /// WASM Image Builder
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct WasmBuilder {
     #[wasm_bindgen(skip)]
     pub fs: BuilderFilesystem,
     #[wasm_bindgen(skip)]
     pub progress_callback: Option<js_sys::Function>,
+    #[wasm_bindgen(skip)]
+    pub fetch: Option<js_sys::Function>,
+    /// Set by `cancel()`, shared with the clone a running build works on
+    #[wasm_bindgen(skip)]
+    pub cancelled: Rc<Cell<bool>>,
+    /// Images this builder built, by image ID
+    #[wasm_bindgen(skip)]
+    pub images: Rc<RefCell<HashMap<String, BuiltImage>>>,
+}
+
+/// Where a built image was written
+#[derive(Debug, Clone)]
+pub struct BuiltImage {
+    /// Directory of its OCI image layout
+    pub output_dir: String,
+    pub tags: Vec<String>,
 }
 
 #[wasm_bindgen]
@@ -23,6 +51,9 @@ impl WasmBuilder {
         Self {
             fs,
             progress_callback: None,
+            fetch: None,
+            cancelled: Rc::new(Cell::new(false)),
+            images: Rc::new(RefCell::new(HashMap::new())),
         }
     }
 
@@ -32,53 +63,113 @@ impl WasmBuilder {
         self.progress_callback = Some(callback);
     }
 
-    /// Parse a Runefile and return the parsed structure as JSON
+    /// Parse a Runefile and return `{ stages, errors }` as JSON
     #[wasm_bindgen(js_name = parseRunefile)]
     pub fn parse_runefile(&self, content: &str) -> String {
-        match RunefileParser::parse_content(content) {
-            Ok(parsed) => serde_json::to_string(&parsed).unwrap_or_else(|_| "null".to_string()),
-            Err(e) => serde_json::json!({ "error": e }).to_string(),
-        }
+        RunefileParser::new().parse(content)
     }
 
-    /// Parse a Runefile from a path using the filesystem
+    /// Parse a Runefile from a path using the filesystem: Promise<string>
     #[wasm_bindgen(js_name = parseRunefileFromPath)]
-    pub fn parse_runefile_from_path(&self, path: &str) -> String {
-        let content = match self.fs.read_file_impl(path) {
-            Some(bytes) => match String::from_utf8(bytes) {
-                Ok(s) => s,
-                Err(_) => {
-                    return serde_json::json!({ "error": "Invalid UTF-8 in file" }).to_string()
+    pub fn parse_runefile_from_path(&self, path: &str) -> js_sys::Promise {
+        let builder = self.clone();
+        let path = path.to_string();
+        future_to_promise(async move {
+            let result = match builder.fs.read_file_impl(&path).await {
+                Some(bytes) => match String::from_utf8(bytes) {
+                    Ok(content) => builder.parse_runefile(&content),
+                    Err(_) => serde_json::json!({ "error": "Invalid UTF-8 in file" }).to_string(),
+                },
+                None => {
+                    serde_json::json!({ "error": format!("File not found: {}", path) }).to_string()
                 }
-            },
-            None => {
-                return serde_json::json!({ "error": format!("File not found: {}", path) })
-                    .to_string()
-            }
-        };
+            };
+            Ok(result.into())
+        })
+    }
 
-        self.parse_runefile(&content)
+    /// Set the callback registries are reached through, with the
+    /// signature of `fetch(url, init): Promise<Response>`
+    #[wasm_bindgen(js_name = setFetch)]
+    pub fn set_fetch(&mut self, callback: js_sys::Function) {
+        self.fetch = Some(callback);
     }
 
-    /// Build an image from configuration (JSON)
+    /// Build an image from configuration (JSON): Promise<string>
+    /// resolving to the build result JSON
+    ///
+    /// Filesystem callbacks may return Promises. When a fetch callback is
+    /// set, base images are pulled first so their layers and config become
+    /// part of the image; otherwise it holds only the layers the Runefile
+    /// adds.
     #[wasm_bindgen]
-    pub fn build(&mut self, config_json: &str) -> String {
-        let config: BuildConfig = match serde_json::from_str(config_json) {
-            Ok(c) => c,
-            Err(e) => {
-                return serde_json::to_string(&BuildResult {
-                    success: false,
-                    image_id: None,
-                    layers: Vec::new(),
-                    config: None,
-                    errors: vec![format!("Invalid config: {}", e)],
-                    warnings: Vec::new(),
-                })
-                .unwrap_or_default();
-            }
-        };
+    pub fn build(&self, config_json: &str) -> js_sys::Promise {
+        self.cancelled.set(false);
+        let mut builder = self.clone();
+        let config: Result<BuildConfig, _> = serde_json::from_str(config_json);
+        future_to_promise(async move {
+            let result = match config {
+                Ok(config) => builder.build_impl(config).await,
+                Err(e) => BuildResult::failed(format!("Invalid config: {}", e)),
+            };
+            Ok(serde_json::to_string(&result).unwrap_or_default().into())
+        })
+    }
+
+    /// Stop the running build
+    ///
+    /// The build checks between steps and while walking files, and resolves
+    /// to a result with `cancelled` set without writing an image. It can
+    /// only notice while waiting on a callback, so builds over synchronous
+    /// callbacks run to the end.
+    #[wasm_bindgen]
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
 
-        self.build_impl(config)
+    /// Write an image this builder built to a path as a `docker save`
+    /// archive that `docker load` accepts: Promise<string> resolving to
+    /// `{ "path": ... }` or `{ "error": ... }`
+    #[wasm_bindgen(js_name = exportImage)]
+    pub fn export_image(&self, image_id: &str, path: &str) -> js_sys::Promise {
+        let builder = self.clone();
+        let image_id = image_id.to_string();
+        let path = path.to_string();
+        future_to_promise(async move {
+            let result = match builder.export_impl(&image_id, &path).await {
+                Ok(()) => serde_json::json!({ "path": path }),
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            Ok(result.to_string().into())
+        })
+    }
+
+    /// Push the built image tagged with a reference to its registry
+    /// through the fetch callback: Promise<string> resolving to
+    /// `{ "digest": ... }` or `{ "error": ... }`
+    ///
+    /// `authJson` holds `username` and `password`, or a bearer `token`; an
+    /// empty string pushes anonymously. Each uploaded layer is reported to
+    /// the progress callback.
+    #[wasm_bindgen]
+    pub fn push(&self, image_ref: &str, auth_json: &str) -> js_sys::Promise {
+        let builder = self.clone();
+        let image_ref = image_ref.to_string();
+        let auth: Result<RegistryAuth, _> = match auth_json.trim() {
+            "" => Ok(RegistryAuth::default()),
+            auth_json => serde_json::from_str(auth_json),
+        };
+        future_to_promise(async move {
+            let result = match auth {
+                Ok(auth) => builder.push_impl(&image_ref, auth).await,
+                Err(e) => Err(format!("Invalid auth: {}", e)),
+            };
+            let result = match result {
+                Ok(digest) => serde_json::json!({ "digest": digest }),
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            Ok(result.to_string().into())
+        })
     }
 
     /// Validate a Runefile content
@@ -105,180 +196,238 @@ impl WasmBuilder {
 }
 
 impl WasmBuilder {
-    /// Build implementation
-    fn build_impl(&mut self, config: BuildConfig) -> String {
-        let errors: Vec<String> = Vec::new();
-        let mut warnings = Vec::new();
-        let mut layers = Vec::new();
-
-        // Find build file
-        let build_file = config.build_file.clone().unwrap_or_else(|| {
-            let runefile = format!("{}/Runefile", config.context_dir);
-            if self.fs.exists_impl(&runefile) {
-                runefile
-            } else {
-                format!("{}/Dockerfile", config.context_dir)
-            }
-        });
-
-        // Read and parse build file
-        let content = match self.fs.read_file_impl(&build_file) {
-            Some(bytes) => match String::from_utf8(bytes) {
-                Ok(s) => s,
-                Err(_) => {
-                    return serde_json::to_string(&BuildResult {
-                        success: false,
-                        image_id: None,
-                        layers: Vec::new(),
-                        config: None,
-                        errors: vec!["Invalid UTF-8 in build file".to_string()],
-                        warnings: Vec::new(),
-                    })
-                    .unwrap_or_default();
-                }
-            },
+    /// Read and parse the build file, with warnings about build args
+    async fn load_runefile(
+        &self,
+        config: &BuildConfig,
+    ) -> Result<(ParsedRunefile, Vec<String>), String> {
+        let build_file = match &config.build_file {
+            Some(build_file) => build_file.clone(),
             None => {
-                return serde_json::to_string(&BuildResult {
-                    success: false,
-                    image_id: None,
-                    layers: Vec::new(),
-                    config: None,
-                    errors: vec![format!("Build file not found: {}", build_file)],
-                    warnings: Vec::new(),
-                })
-                .unwrap_or_default();
+                let runefile = format!("{}/Runefile", config.context_dir);
+                if self.fs.exists_impl(&runefile).await {
+                    runefile
+                } else {
+                    format!("{}/Dockerfile", config.context_dir)
+                }
             }
         };
 
-        let parsed = match RunefileParser::parse_content(&content) {
-            Ok(p) => p,
-            Err(e) => {
-                return serde_json::to_string(&BuildResult {
-                    success: false,
-                    image_id: None,
-                    layers: Vec::new(),
-                    config: None,
-                    errors: vec![e],
-                    warnings: Vec::new(),
-                })
-                .unwrap_or_default();
+        let content = self
+            .fs
+            .read_file_impl(&build_file)
+            .await
+            .ok_or_else(|| format!("Build file not found: {}", build_file))?;
+        let content =
+            String::from_utf8(content).map_err(|_| "Invalid UTF-8 in build file".to_string())?;
+        RunefileParser::parse_with_args(&content, &config.build_args)
+    }
+
+    /// Pull the base images of a Runefile's stages
+    async fn pull_bases(
+        &self,
+        config: &BuildConfig,
+        parsed: &ParsedRunefile,
+    ) -> Result<HashMap<String, BaseImage>, String> {
+        let mut bases = HashMap::new();
+        let Some(fetch) = &self.fetch else {
+            return Ok(bases);
+        };
+        let client = RegistryClient::new(fetch.clone());
+        for (stage_idx, stage) in parsed.stages.iter().enumerate() {
+            if self.is_cancelled() {
+                break;
+            }
+            let base = base_reference(stage);
+            if stage.base_image == "scratch"
+                || bases.contains_key(&base)
+                || stage.base_tag.is_none()
+                    && find_stage(&parsed.stages[..stage_idx], &stage.base_image).is_some()
+            {
+                continue;
             }
+            self.emit_event(BuildEvent::Progress {
+                message: format!("Pulling {}", base),
+                percent: None,
+            });
+            let image = client
+                .pull(
+                    &ImageReference::parse(&base),
+                    &config.os,
+                    &config.architecture,
+                )
+                .await
+                .map_err(|e| format!("Failed to pull {}: {}", base, e))?;
+            bases.insert(base, image);
+        }
+        Ok(bases)
+    }
+
+    /// Build implementation
+    async fn build_impl(&mut self, config: BuildConfig) -> BuildResult {
+        let mut errors: Vec<String> = Vec::new();
+        let (parsed, mut warnings) = match self.load_runefile(&config).await {
+            Ok(loaded) => loaded,
+            Err(e) => return BuildResult::failed(e),
+        };
+        let bases = match self.pull_bases(&config, &parsed).await {
+            Ok(bases) => bases,
+            Err(e) => return BuildResult::failed(e),
         };
+        if self.is_cancelled() {
+            return BuildResult::cancelled(warnings);
+        }
 
-        // Process stages
-        let target_stage = config.target.as_ref();
-        let mut container_config = ContainerConfig::default();
-        let mut diff_ids = Vec::new();
-        let mut history = Vec::new();
+        // The builder's own output never belongs in the context
+        let mut ignore = IgnoreRules::load(&self.fs, &config.context_dir).await;
+        ignore.exclude(&config.output_dir());
+        ignore.exclude(&config.cache_dir());
+        let cache = BuildCache::new(&self.fs, &config.cache_dir());
 
-        for (stage_idx, stage) in parsed.stages.iter().enumerate() {
-            // Check if this is the target stage
-            if let Some(target) = target_stage {
-                if stage.name.as_ref() != Some(target) && stage_idx < parsed.stages.len() - 1 {
-                    continue;
-                }
-            }
+        // Build the stages up to the target; the image is the target stage
+        let target_idx = match &config.target {
+            Some(target) => match find_stage(&parsed.stages, target) {
+                Some(idx) => idx,
+                None => return BuildResult::failed(format!("Target stage not found: {}", target)),
+            },
+            None => parsed.stages.len() - 1,
+        };
+        let mut built: Vec<StageState> = Vec::new();
 
+        for (stage_idx, stage) in parsed.stages[..=target_idx].iter().enumerate() {
+            let base = base_reference(stage);
+            // A stage can start from an earlier one
+            let base_stage = match stage.base_tag {
+                Some(_) => None,
+                None => find_stage(&parsed.stages[..stage_idx], &stage.base_image),
+            };
+            let mut state = match (base_stage, bases.get(&base)) {
+                (Some(idx), _) => built[idx].clone(),
+                (None, Some(image)) => StageState::from_image(&base, image),
+                (None, None) => StageState {
+                    cache_key: cache_key(
+                        "",
+                        &BuildInstruction::From {
+                            image: base.clone(),
+                            tag: None,
+                            alias: None,
+                        },
+                        "",
+                    ),
+                    ..StageState::default()
+                },
+            };
             self.emit_event(BuildEvent::StageStart {
                 stage: stage_idx,
                 name: stage.name.clone(),
-                base: format!(
-                    "{}:{}",
-                    stage.base_image,
-                    stage.base_tag.as_deref().unwrap_or("latest")
-                ),
+                base,
             });
 
             // Process instructions
             for (step_idx, instruction) in stage.instructions.iter().enumerate() {
+                if self.is_cancelled() {
+                    return BuildResult::cancelled(warnings);
+                }
                 let instruction_str = format!("{:?}", instruction);
                 self.emit_event(BuildEvent::StepStart {
                     step: step_idx,
                     instruction: instruction_str.clone(),
                 });
 
+                let mut step_key = None;
+                let mut cached = false;
+                let container_config = &mut state.config;
                 let (layer_id, empty_layer) = match instruction {
                     BuildInstruction::Run { command, .. } => {
-                        let layer_digest = Self::calculate_digest(command.as_bytes());
-                        let layer_id = layer_digest[7..19].to_string();
-
-                        layers.push(ImageLayer {
-                            id: layer_id.clone(),
-                            digest: layer_digest.clone(),
-                            size: command.len() as u64,
-                            created_by: format!("RUN {}", command),
-                            empty_layer: false,
-                        });
-
-                        diff_ids.push(layer_digest);
-                        (Some(layer_id), false)
+                        // There is no container runtime to run commands in
+                        warnings.push(format!("RUN was not executed: {}", command));
+                        (None, true)
                     }
-                    BuildInstruction::Copy { src, dest, .. } => {
-                        let mut layer_content = Vec::new();
-
-                        for src_path in src {
-                            let full_path = if src_path.starts_with('/') {
-                                src_path.clone()
-                            } else {
-                                format!("{}/{}", config.context_dir, src_path)
-                            };
-
-                            if let Some(content) = self.fs.read_file_impl(&full_path) {
-                                layer_content.extend_from_slice(&content);
-                            } else {
-                                warnings.push(format!("Source file not found: {}", full_path));
+                    BuildInstruction::Copy {
+                        src, dest, chown, ..
+                    }
+                    | BuildInstruction::Add {
+                        src, dest, chown, ..
+                    } => {
+                        let (keyword, from) = match instruction {
+                            BuildInstruction::Copy { from, .. } => ("COPY", from.as_deref()),
+                            _ => ("ADD", None),
+                        };
+                        let layer = match from {
+                            None => {
+                                self.copy_layer(
+                                    &config.context_dir,
+                                    &ignore,
+                                    src,
+                                    dest,
+                                    chown.as_deref(),
+                                    &container_config.working_dir,
+                                    &mut warnings,
+                                )
+                                .await
                             }
-                        }
+                            Some(from) => match find_stage(&parsed.stages[..stage_idx], from) {
+                                Some(idx) => {
+                                    stage_copy_layer(
+                                        &built[idx].rootfs,
+                                        src,
+                                        dest,
+                                        chown.as_deref(),
+                                        &container_config.working_dir,
+                                        &mut warnings,
+                                    )
+                                    .await
+                                }
+                                None => {
+                                    warnings.push(format!(
+                                        "COPY --from={} is not an earlier stage, copying from images is not supported",
+                                        from
+                                    ));
+                                    LayerBuilder::new()
+                                }
+                            },
+                        };
 
-                        if !layer_content.is_empty() {
-                            let layer_digest = Self::calculate_digest(&layer_content);
-                            let layer_id = layer_digest[7..19].to_string();
+                        let key = cache_key(
+                            &state.cache_key,
+                            instruction,
+                            &format!("{} {}", layer.digest(), config.compress_layers),
+                        );
+                        step_key = Some(key.clone());
 
-                            layers.push(ImageLayer {
-                                id: layer_id.clone(),
-                                digest: layer_digest.clone(),
-                                size: layer_content.len() as u64,
-                                created_by: format!("COPY {} {}", src.join(" "), dest),
-                                empty_layer: false,
-                            });
-
-                            diff_ids.push(layer_digest);
-                            (Some(layer_id), false)
-                        } else {
+                        if layer.is_empty() {
                             (None, true)
-                        }
-                    }
-                    BuildInstruction::Add { src, dest, .. } => {
-                        let mut layer_content = Vec::new();
-
-                        for src_path in src {
-                            let full_path = if src_path.starts_with('/') {
-                                src_path.clone()
+                        } else {
+                            state.rootfs.extend(layer.clone());
+                            let hit = if config.no_cache {
+                                None
                             } else {
-                                format!("{}/{}", config.context_dir, src_path)
+                                cache.get(&key).await
                             };
+                            cached = hit.is_some();
+                            let blob = match hit {
+                                Some(blob) => blob,
+                                None => {
+                                    let blob =
+                                        LayerBlob::new(layer.into_tar(), config.compress_layers);
+                                    cache.put(&key, &blob).await;
+                                    blob
+                                }
+                            };
+                            let layer_id = blob.diff_id[7..19].to_string();
 
-                            if let Some(content) = self.fs.read_file_impl(&full_path) {
-                                layer_content.extend_from_slice(&content);
-                            }
-                        }
-
-                        if !layer_content.is_empty() {
-                            let layer_digest = Self::calculate_digest(&layer_content);
-                            let layer_id = layer_digest[7..19].to_string();
-
-                            layers.push(ImageLayer {
+                            state.layers.push(ImageLayer {
                                 id: layer_id.clone(),
-                                digest: layer_digest.clone(),
-                                size: layer_content.len() as u64,
-                                created_by: format!("ADD {} {}", src.join(" "), dest),
+                                digest: blob.digest(),
+                                diff_id: blob.diff_id.clone(),
+                                size: blob.content.len() as u64,
+                                created_by: format!("{} {} {}", keyword, src.join(" "), dest),
                                 empty_layer: false,
                             });
 
-                            diff_ids.push(layer_digest);
+                            state.diff_ids.push(blob.diff_id.clone());
+                            state.blobs.push(blob);
                             (Some(layer_id), false)
-                        } else {
-                            (None, true)
                         }
                     }
                     BuildInstruction::Env { key, value } => {
@@ -326,20 +475,42 @@ impl WasmBuilder {
                     _ => (None, true),
                 };
 
-                history.push(HistoryEntry {
-                    created: chrono_lite_now(),
+                state.history.push(HistoryEntry {
+                    created: config.created.clone().unwrap_or_else(now),
                     created_by: instruction_str,
                     empty_layer,
                     comment: None,
                 });
 
-                self.emit_event(BuildEvent::StepComplete {
-                    step: step_idx,
-                    layer_id,
-                });
+                state.cache_key =
+                    step_key.unwrap_or_else(|| cache_key(&state.cache_key, instruction, ""));
+                if cached {
+                    self.emit_event(BuildEvent::StepCached {
+                        step: step_idx,
+                        layer_id,
+                    });
+                } else {
+                    self.emit_event(BuildEvent::StepComplete {
+                        step: step_idx,
+                        layer_id,
+                    });
+                }
             }
 
             self.emit_event(BuildEvent::StageComplete { stage: stage_idx });
+            built.push(state);
+        }
+        let StageState {
+            config: mut container_config,
+            layers,
+            blobs: layer_blobs,
+            diff_ids,
+            history,
+            ..
+        } = built.swap_remove(target_idx);
+
+        if self.is_cancelled() {
+            return BuildResult::cancelled(warnings);
         }
 
         // Add build labels
@@ -347,14 +518,12 @@ impl WasmBuilder {
             container_config.labels.insert(key.clone(), value.clone());
         }
 
-        // Generate image ID
-        let config_json = serde_json::to_string(&container_config).unwrap_or_default();
-        let image_id = Self::calculate_digest(config_json.as_bytes())[7..19].to_string();
-
         // Create image config
         let image_config = ImageConfig {
-            architecture: "amd64".to_string(),
-            os: "linux".to_string(),
+            created: Some(config.created.clone().unwrap_or_else(now)),
+            architecture: config.architecture.clone(),
+            os: config.os.clone(),
+            variant: config.variant.clone(),
             config: container_config,
             rootfs: RootFs {
                 fs_type: "layers".to_string(),
@@ -363,19 +532,230 @@ impl WasmBuilder {
             history,
         };
 
+        // The image ID is the short form of the config digest
+        let layout = ImageLayout::new(&image_config, &layer_blobs, &config.tags);
+        let image_id = layout.config.digest[7..19].to_string();
+        let output_dir = config.output_dir();
+        match layout.write(&self.fs, &output_dir).await {
+            Ok(()) => {
+                self.images.borrow_mut().insert(
+                    image_id.clone(),
+                    BuiltImage {
+                        output_dir: output_dir.clone(),
+                        tags: config.tags.clone(),
+                    },
+                );
+            }
+            Err(e) => errors.push(e),
+        }
+
         self.emit_event(BuildEvent::BuildComplete {
             image_id: image_id.clone(),
         });
 
-        serde_json::to_string(&BuildResult {
+        BuildResult {
             success: errors.is_empty(),
+            cancelled: false,
             image_id: Some(image_id),
+            manifest_digest: Some(layout.manifest.digest),
+            output_dir: Some(output_dir),
             layers,
             config: Some(image_config),
             errors,
             warnings,
+        }
+    }
+
+    /// Read a built image back from its layout and write it as a docker
+    /// archive
+    async fn export_impl(&self, image_id: &str, path: &str) -> Result<(), String> {
+        let id = image_id.trim_start_matches("sha256:");
+        let dir = self
+            .images
+            .borrow()
+            .iter()
+            .find(|(built, _)| id.starts_with(built.as_str()))
+            .map(|(_, image)| image.output_dir.clone())
+            .ok_or_else(|| format!("Image not built by this builder: {}", image_id))?;
+        let image = read_image(&dir, id, |path| async move {
+            self.fs.read_file_impl(&path).await
+        })
+        .await?;
+        let archive = docker_archive(&image)?;
+        if self.fs.write_file_impl(path, &archive).await {
+            Ok(())
+        } else {
+            Err(format!("Failed to write {}", path))
+        }
+    }
+
+    /// Read the image tagged with a reference back from its layout and
+    /// upload it
+    async fn push_impl(&self, image_ref: &str, auth: RegistryAuth) -> Result<String, String> {
+        let fetch = self
+            .fetch
+            .clone()
+            .ok_or_else(|| "Pushing needs a fetch callback, see setFetch".to_string())?;
+        let (image_id, dir) = find_tagged(&self.images.borrow(), image_ref)
+            .ok_or_else(|| format!("No image built by this builder is tagged {}", image_ref))?;
+        let image = read_image(&dir, &image_id, |path| async move {
+            self.fs.read_file_impl(&path).await
         })
-        .unwrap_or_default()
+        .await?;
+
+        let reference = ImageReference::parse(image_ref);
+        RegistryClient::new(fetch)
+            .with_auth(auth)
+            .push(&reference, &image, |done, total, digest, existed| {
+                let action = if existed { "Already pushed" } else { "Pushed" };
+                self.emit_event(BuildEvent::Progress {
+                    message: format!("{} {}", action, &digest[..19.min(digest.len())]),
+                    percent: Some((done * 100 / total) as u8),
+                });
+            })
+            .await
+            .map_err(|e| format!("Failed to push {}: {}", reference, e))
+    }
+
+    /// Build the layer of a COPY or ADD from files in the build context
+    ///
+    /// Directories are copied recursively. With several sources, or a
+    /// destination ending in `/`, files keep their names inside the
+    /// destination directory. Relative destinations are taken from WORKDIR.
+    #[allow(clippy::too_many_arguments)]
+    async fn copy_layer(
+        &self,
+        context_dir: &str,
+        ignore: &IgnoreRules,
+        src: &[String],
+        dest: &str,
+        chown: Option<&str>,
+        workdir: &str,
+        warnings: &mut Vec<String>,
+    ) -> LayerBuilder {
+        let (uid, gid) = owner(chown, warnings);
+        let mut layer = LayerBuilder::new().with_owner(uid, gid);
+
+        let mut paths = Vec::new();
+        for source in src {
+            if source.contains("://") {
+                warnings.push(format!("Remote source not supported: {}", source));
+            } else if glob::is_pattern(source) {
+                let matches = glob::expand(context_dir, source, |dir| async move {
+                    self.fs.list_dir_impl(&dir).await
+                })
+                .await;
+                if matches.is_empty() {
+                    warnings.push(format!("No source files match {}", source));
+                }
+                paths.extend(
+                    matches
+                        .iter()
+                        .map(|m| context_path(context_dir, m))
+                        .filter(|path| !ignore.is_ignored(path)),
+                );
+            } else {
+                let path = context_path(context_dir, source);
+                if ignore.is_ignored(&path) {
+                    warnings.push(format!(
+                        "Source is excluded from the build context: {}",
+                        path
+                    ));
+                } else {
+                    paths.push(path);
+                }
+            }
+        }
+
+        let dest_is_dir = dest.ends_with('/') || paths.len() > 1;
+        let dest = destination(dest, workdir);
+
+        for path in paths {
+            if self.is_cancelled() {
+                break;
+            }
+            if self.is_dir(&path).await {
+                self.add_dir_contents(&mut layer, ignore, &path, &dest)
+                    .await;
+            } else if let Some(content) = self.fs.read_file_impl(&path).await {
+                let target = if dest_is_dir {
+                    let name = path.rsplit('/').next().unwrap_or(&path);
+                    format!("{}/{}", dest, name)
+                } else {
+                    dest.clone()
+                };
+                let mode = self.mode(&path, DEFAULT_FILE_MODE).await;
+                layer.add_file(&target, content, mode);
+            } else {
+                warnings.push(format!("Source file not found: {}", path));
+            }
+        }
+
+        layer
+    }
+
+    /// Add the files and directories under a context directory to a layer,
+    /// leaving out excluded paths
+    ///
+    /// Excluded directories are still walked when a negated pattern could
+    /// re-include something inside them.
+    async fn add_dir_contents(
+        &self,
+        layer: &mut LayerBuilder,
+        ignore: &IgnoreRules,
+        dir: &str,
+        dest: &str,
+    ) {
+        let mut pending = vec![(dir.to_string(), dest.to_string())];
+        while let Some((dir, dest)) = pending.pop() {
+            if self.is_cancelled() {
+                return;
+            }
+            if !ignore.is_ignored(&dir) {
+                layer.add_dir(&dest, self.mode(&dir, DEFAULT_DIR_MODE).await);
+            }
+            for entry in self.fs.list_dir_impl(&dir).await.unwrap_or_default() {
+                let path = format!("{}/{}", dir, entry.name);
+                let target = format!("{}/{}", dest, entry.name);
+                let ignored = ignore.is_ignored(&path);
+                if entry.is_dir {
+                    if !ignored || ignore.has_negations() {
+                        pending.push((path, target));
+                    }
+                } else if ignored {
+                    continue;
+                } else if let Some(content) = self.fs.read_file_impl(&path).await {
+                    let mode = self.mode(&path, DEFAULT_FILE_MODE).await;
+                    layer.add_file(&target, content, mode);
+                }
+            }
+        }
+    }
+
+    /// Whether a path is a directory, by `stat` or else by `listDir`
+    async fn is_dir(&self, path: &str) -> bool {
+        match self.fs.stat_impl(path).await {
+            Some(stat) => stat.is_dir,
+            None => {
+                self.fs.read_file_impl(path).await.is_none()
+                    && self.fs.list_dir_impl(path).await.is_some()
+            }
+        }
+    }
+
+    /// Permission bits of a path, from `stat` when it reports them
+    async fn mode(&self, path: &str, default: u32) -> u32 {
+        self.fs
+            .stat_impl(path)
+            .await
+            .map(|stat| stat.mode & 0o7777)
+            .filter(|mode| *mode != 0)
+            .unwrap_or(default)
+    }
+
+    /// Whether `cancel()` was called during this build
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
     }
 
     /// Emit a build event to the progress callback
@@ -389,14 +769,187 @@ impl WasmBuilder {
     }
 }
 
-/// Simple timestamp function
-fn chrono_lite_now() -> String {
+/// What a stage has built so far
+#[derive(Debug, Clone, Default)]
+struct StageState {
+    config: ContainerConfig,
+    layers: Vec<ImageLayer>,
+    blobs: Vec<LayerBlob>,
+    diff_ids: Vec<String>,
+    history: Vec<HistoryEntry>,
+    /// Files the stage's own layers add, later layers over earlier ones;
+    /// the files of a pulled base image are not unpacked
+    rootfs: LayerBuilder,
+    /// Cache key of the last step
+    cache_key: String,
+}
+
+impl StageState {
+    /// Start from the config, history and layers of a pulled image
+    fn from_image(reference: &str, image: &BaseImage) -> Self {
+        let layers = image
+            .layers
+            .iter()
+            .map(|blob| ImageLayer {
+                id: blob.diff_id[7..19].to_string(),
+                digest: blob.digest(),
+                diff_id: blob.diff_id.clone(),
+                size: blob.content.len() as u64,
+                created_by: format!("FROM {}", reference),
+                empty_layer: false,
+            })
+            .collect();
+        Self {
+            config: image.config.clone(),
+            layers,
+            blobs: image.layers.clone(),
+            diff_ids: image
+                .layers
+                .iter()
+                .map(|blob| blob.diff_id.clone())
+                .collect(),
+            history: image.history.clone(),
+            rootfs: LayerBuilder::new(),
+            cache_key: cache_key(
+                "",
+                &BuildInstruction::From {
+                    image: reference.to_string(),
+                    tag: None,
+                    alias: None,
+                },
+                &image.config_digest,
+            ),
+        }
+    }
+}
+
+/// Image a stage is built on, with its tag
+fn base_reference(stage: &BuildStage) -> String {
+    match &stage.base_tag {
+        Some(tag) => format!("{}:{}", stage.base_image, tag),
+        None => stage.base_image.clone(),
+    }
+}
+
+/// Index of a stage by name, case-insensitively, or by number
+fn find_stage(stages: &[BuildStage], name: &str) -> Option<usize> {
+    stages
+        .iter()
+        .position(|stage| {
+            stage
+                .name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+        .or_else(|| name.parse().ok().filter(|idx| *idx < stages.len()))
+}
+
+/// Build the layer of a `COPY --from` out of an earlier stage's files
+async fn stage_copy_layer(
+    rootfs: &LayerBuilder,
+    src: &[String],
+    dest: &str,
+    chown: Option<&str>,
+    workdir: &str,
+    warnings: &mut Vec<String>,
+) -> LayerBuilder {
+    let (uid, gid) = owner(chown, warnings);
+    let mut layer = LayerBuilder::new().with_owner(uid, gid);
+
+    let mut paths = Vec::new();
+    for source in src {
+        if glob::is_pattern(source) {
+            let matches =
+                glob::expand("/", source, |dir| std::future::ready(rootfs.list_dir(&dir))).await;
+            if matches.is_empty() {
+                warnings.push(format!("No source files match {}", source));
+            }
+            paths.extend(matches.iter().map(|m| normalize_path(m)));
+        } else {
+            paths.push(normalize_path(source));
+        }
+    }
+
+    let dest_is_dir = dest.ends_with('/') || paths.len() > 1;
+    let dest = destination(dest, workdir);
+    for path in paths {
+        let target = match rootfs.is_dir(&path) {
+            Some(false) if dest_is_dir => {
+                format!("{}/{}", dest, path.rsplit('/').next().unwrap_or(&path))
+            }
+            _ => dest.clone(),
+        };
+        if !rootfs.copy_to(&path, &mut layer, &target) {
+            warnings.push(format!("Source file not found in stage: {}", path));
+        }
+    }
+    layer
+}
+
+/// ID and layout directory of the built image with a tag naming the same
+/// image as a reference
+fn find_tagged(images: &HashMap<String, BuiltImage>, image_ref: &str) -> Option<(String, String)> {
+    let reference = ImageReference::parse(image_ref);
+    images
+        .iter()
+        .find(|(_, image)| {
+            image
+                .tags
+                .iter()
+                .any(|tag| ImageReference::parse(tag) == reference)
+        })
+        .map(|(id, image)| (id.clone(), image.output_dir.clone()))
+}
+
+/// Owner of copied files from `--chown`, root when it is missing or not
+/// numeric
+fn owner(chown: Option<&str>, warnings: &mut Vec<String>) -> (u64, u64) {
+    match chown {
+        Some(chown) => parse_chown(chown).unwrap_or_else(|| {
+            warnings.push(format!(
+                "--chown={} ignored, only numeric IDs are supported",
+                chown
+            ));
+            (0, 0)
+        }),
+        None => (0, 0),
+    }
+}
+
+/// Absolute COPY or ADD destination, relative ones being in the workdir
+fn destination(dest: &str, workdir: &str) -> String {
+    if dest.starts_with('/') {
+        dest.to_string()
+    } else {
+        format!("{}/{}", workdir, dest)
+    }
+}
+
+/// Path of a COPY or ADD source in the build context
+fn context_path(context_dir: &str, source: &str) -> String {
+    let context_dir = context_dir.trim_end_matches('/');
+    match source.trim_start_matches("./").trim_end_matches('/') {
+        "" | "." => context_dir.to_string(),
+        source if source.starts_with('/') => format!("{}{}", context_dir, source),
+        source => format!("{}/{}", context_dir, source),
+    }
+}
+
+/// Numeric `uid[:gid]` of `--chown`; the group defaults to the user
+fn parse_chown(chown: &str) -> Option<(u64, u64)> {
+    let (user, group) = chown.split_once(':').unwrap_or((chown, chown));
+    Some((user.parse().ok()?, group.parse().ok()?))
+}
+
+/// Current time as an RFC 3339 timestamp
+fn now() -> String {
     js_sys::Date::new_0().to_iso_string().into()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::executor::block_on;
 
     #[test]
     fn test_calculate_digest() {
@@ -405,6 +958,113 @@ mod tests {
         assert_eq!(digest.len(), 71);
     }
 
+    #[test]
+    fn test_context_path() {
+        assert_eq!(context_path("/project", "."), "/project");
+        assert_eq!(context_path("/project/", "./src/"), "/project/src");
+        assert_eq!(context_path("/project", "/app.js"), "/project/app.js");
+    }
+
+    fn stage(name: Option<&str>) -> BuildStage {
+        BuildStage {
+            name: name.map(str::to_string),
+            base_image: "alpine".to_string(),
+            base_tag: None,
+            instructions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_find_stage() {
+        let stages = vec![stage(Some("builder")), stage(None)];
+        assert_eq!(find_stage(&stages, "Builder"), Some(0));
+        assert_eq!(find_stage(&stages, "1"), Some(1));
+        assert_eq!(find_stage(&stages, "2"), None);
+        assert_eq!(find_stage(&stages, "alpine"), None);
+    }
+
+    #[test]
+    fn test_stage_copy_layer() {
+        let mut rootfs = LayerBuilder::new();
+        rootfs.add_file("/app/target/release/app", b"bin".to_vec(), 0o755);
+        rootfs.add_file("/app/target/release/app.d", b"deps".to_vec(), 0o644);
+        rootfs.add_file("/app/README.md", b"docs".to_vec(), 0o644);
+        let mut warnings = Vec::new();
+
+        let copy = |src: &[&str], dest: &str, warnings: &mut Vec<String>| {
+            let src: Vec<String> = src.iter().map(|s| s.to_string()).collect();
+            let mut listed = Vec::new();
+            let layer = block_on(stage_copy_layer(
+                &rootfs, &src, dest, None, "/srv", warnings,
+            ));
+            for dir in ["/usr/local/bin", "/srv", "/srv/docs"] {
+                for entry in layer.list_dir(dir).unwrap_or_default() {
+                    listed.push(format!("{}/{}", dir, entry.name));
+                }
+            }
+            listed
+        };
+
+        assert_eq!(
+            copy(
+                &["/app/target/release/app"],
+                "/usr/local/bin/",
+                &mut warnings
+            ),
+            vec!["/usr/local/bin/app"]
+        );
+        assert_eq!(
+            copy(&["/app/target/release/*"], "/usr/local/bin", &mut warnings),
+            vec!["/usr/local/bin/app", "/usr/local/bin/app.d"]
+        );
+        assert_eq!(
+            copy(&["app/README.md"], "docs/", &mut warnings),
+            vec!["/srv/docs", "/srv/docs/README.md"]
+        );
+        assert!(warnings.is_empty());
+        assert!(copy(&["/missing"], "/", &mut warnings).is_empty());
+        assert_eq!(warnings, vec!["Source file not found in stage: /missing"]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let builder = WasmBuilder::new(BuilderFilesystem::new());
+        // A running build works on a clone, which must see the flag
+        let running = builder.clone();
+        assert!(!running.is_cancelled());
+        builder.cancel();
+        assert!(running.is_cancelled());
+
+        let result = BuildResult::cancelled(vec!["RUN was not executed: make".to_string()]);
+        assert!(result.cancelled && !result.success);
+        assert_eq!(result.errors, vec!["Build cancelled"]);
+        assert_eq!(result.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_find_tagged() {
+        let mut images = HashMap::new();
+        images.insert(
+            "0123456789ab".to_string(),
+            BuiltImage {
+                output_dir: "/ctx/.rune/oci".to_string(),
+                tags: vec!["app".to_string(), "ghcr.io/org/app:1.0".to_string()],
+            },
+        );
+        let found = Some(("0123456789ab".to_string(), "/ctx/.rune/oci".to_string()));
+        // Tags match however the registry and tag are spelled
+        assert_eq!(find_tagged(&images, "docker.io/library/app:latest"), found);
+        assert_eq!(find_tagged(&images, "ghcr.io/org/app:1.0"), found);
+        assert_eq!(find_tagged(&images, "ghcr.io/org/app:2.0"), None);
+    }
+
+    #[test]
+    fn test_parse_chown() {
+        assert_eq!(parse_chown("1000"), Some((1000, 1000)));
+        assert_eq!(parse_chown("1000:50"), Some((1000, 50)));
+        assert_eq!(parse_chown("app:app"), None);
+    }
+
     #[test]
     fn test_default_build_file() {
         assert_eq!(WasmBuilder::get_default_build_file(), "Runefile");
@@ -0,0 +1,136 @@
+//! Build cache
+//!
+//! Remembers the layer each step produced so a repeated build can reuse it
+//! instead of archiving and compressing the files again. A step's key chains
+//! the key of the step before it with the instruction and, for COPY and ADD,
+//! the digest of the files it adds, so changing one step misses the cache for
+//! every step after it.
+//!
+//! Entries live in the cache directory as `<key>.json`, with layer blobs
+//! under `blobs/sha256/`.
+
+use crate::calculate_digest;
+use crate::filesystem::BuilderFilesystem;
+use crate::layer::LayerBlob;
+use crate::oci::{blob_path, MEDIA_TYPE_LAYER, MEDIA_TYPE_LAYER_GZIP};
+use crate::types::BuildInstruction;
+use serde::{Deserialize, Serialize};
+
+/// Cached result of a step that produced a layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    pub media_type: String,
+    pub digest: String,
+    pub diff_id: String,
+}
+
+/// Cache of step layers stored through the filesystem callbacks
+pub struct BuildCache<'a> {
+    fs: &'a BuilderFilesystem,
+    dir: String,
+}
+
+impl<'a> BuildCache<'a> {
+    /// Open the cache in a directory
+    pub fn new(fs: &'a BuilderFilesystem, dir: &str) -> Self {
+        Self {
+            fs,
+            dir: dir.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Layer cached under a key, if its blob is still intact
+    pub async fn get(&self, key: &str) -> Option<LayerBlob> {
+        let entry = self.fs.read_file_impl(&self.entry_path(key)).await?;
+        let entry: CacheEntry = serde_json::from_slice(&entry).ok()?;
+        let media_type = [MEDIA_TYPE_LAYER, MEDIA_TYPE_LAYER_GZIP]
+            .into_iter()
+            .find(|media_type| *media_type == entry.media_type)?;
+        let content = self
+            .fs
+            .read_file_impl(&format!("{}/{}", self.dir, blob_path(&entry.digest)))
+            .await?;
+        (calculate_digest(&content) == entry.digest).then_some(LayerBlob {
+            media_type,
+            diff_id: entry.diff_id,
+            content,
+        })
+    }
+
+    /// Store the layer of a step; failures only cost a later cache miss
+    pub async fn put(&self, key: &str, blob: &LayerBlob) {
+        if self.fs.write_file.is_none() {
+            return;
+        }
+        for sub_dir in ["", "/blobs", "/blobs/sha256"] {
+            let path = format!("{}{}", self.dir, sub_dir);
+            if !self.fs.exists_impl(&path).await {
+                self.fs.mkdir_impl(&path).await;
+            }
+        }
+        let entry = CacheEntry {
+            media_type: blob.media_type.to_string(),
+            digest: blob.digest(),
+            diff_id: blob.diff_id.clone(),
+        };
+        let blob_file = format!("{}/{}", self.dir, blob_path(&entry.digest));
+        if !self.fs.exists_impl(&blob_file).await
+            && !self.fs.write_file_impl(&blob_file, &blob.content).await
+        {
+            return;
+        }
+        self.fs
+            .write_file_impl(
+                &self.entry_path(key),
+                &serde_json::to_vec(&entry).unwrap_or_default(),
+            )
+            .await;
+    }
+
+    fn entry_path(&self, key: &str) -> String {
+        format!("{}/{}.json", self.dir, key.trim_start_matches("sha256:"))
+    }
+}
+
+/// Key of a step, from the key of the step before it, the instruction and
+/// the digest of its input files
+pub fn cache_key(parent: &str, instruction: &BuildInstruction, inputs: &str) -> String {
+    let instruction = serde_json::to_string(instruction).unwrap_or_default();
+    calculate_digest(format!("{}\n{}\n{}", parent, instruction, inputs).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_cache_key() {
+        let workdir = BuildInstruction::Workdir {
+            path: "/app".to_string(),
+        };
+        let env = BuildInstruction::Env {
+            key: "A".to_string(),
+            value: "1".to_string(),
+        };
+
+        let key = cache_key("alpine:3", &workdir, "");
+        assert!(key.starts_with("sha256:"));
+        assert_eq!(key, cache_key("alpine:3", &workdir, ""));
+        // The parent, the instruction and the inputs all change the key
+        assert_ne!(key, cache_key("alpine:4", &workdir, ""));
+        assert_ne!(key, cache_key("alpine:3", &env, ""));
+        assert_ne!(key, cache_key("alpine:3", &workdir, "sha256:abc"));
+    }
+
+    #[test]
+    fn test_cache_without_callbacks() {
+        let fs = BuilderFilesystem::new();
+        let cache = BuildCache::new(&fs, "/ctx/.rune/cache/");
+        let blob = LayerBlob::new(b"layer".to_vec(), false);
+        block_on(cache.put("sha256:abc", &blob));
+        assert!(block_on(cache.get("sha256:abc")).is_none());
+        assert_eq!(cache.entry_path("sha256:abc"), "/ctx/.rune/cache/abc.json");
+    }
+}
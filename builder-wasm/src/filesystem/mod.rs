@@ -6,6 +6,7 @@ pub use memory::InMemoryFilesystem;
 
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
 
 /// File entry returned by list_dir
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,8 +26,9 @@ pub struct FileStat {
 }
 
 /// Filesystem interface for WASM
-/// Users implement this via JavaScript callbacks
+/// Users implement this via JavaScript callbacks, which may be async
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct BuilderFilesystem {
     #[wasm_bindgen(skip)]
     pub read_file: Option<js_sys::Function>,
@@ -63,49 +65,49 @@ impl BuilderFilesystem {
         }
     }
 
-    /// Set the read_file callback: (path: string) => Uint8Array | null
+    /// Set the read_file callback: (path: string) => Uint8Array | null, or a Promise of one
     #[wasm_bindgen(js_name = setReadFile)]
     pub fn set_read_file(&mut self, callback: js_sys::Function) {
         self.read_file = Some(callback);
     }
 
-    /// Set the write_file callback: (path: string, contents: Uint8Array) => void
+    /// Set the write_file callback: (path: string, contents: Uint8Array) => void | Promise<void>
     #[wasm_bindgen(js_name = setWriteFile)]
     pub fn set_write_file(&mut self, callback: js_sys::Function) {
         self.write_file = Some(callback);
     }
 
-    /// Set the list_dir callback: (path: string) => Array<{name: string, isDir: boolean}>
+    /// Set the list_dir callback: (path: string) => Array<{name: string, isDir: boolean}>, or a Promise of one
     #[wasm_bindgen(js_name = setListDir)]
     pub fn set_list_dir(&mut self, callback: js_sys::Function) {
         self.list_dir = Some(callback);
     }
 
-    /// Set the exists callback: (path: string) => boolean
+    /// Set the exists callback: (path: string) => boolean | Promise<boolean>
     #[wasm_bindgen(js_name = setExists)]
     pub fn set_exists(&mut self, callback: js_sys::Function) {
         self.exists = Some(callback);
     }
 
-    /// Set the mkdir callback: (path: string) => void
+    /// Set the mkdir callback: (path: string) => void | Promise<void>
     #[wasm_bindgen(js_name = setMkdir)]
     pub fn set_mkdir(&mut self, callback: js_sys::Function) {
         self.mkdir = Some(callback);
     }
 
-    /// Set the stat callback: (path: string) => {size: number, isDir: boolean, mode: number} | null
+    /// Set the stat callback: (path: string) => {size: number, isDir: boolean, mode: number} | null, or a Promise of one
     #[wasm_bindgen(js_name = setStat)]
     pub fn set_stat(&mut self, callback: js_sys::Function) {
         self.stat = Some(callback);
     }
 
-    /// Set the remove callback: (path: string) => void
+    /// Set the remove callback: (path: string) => void | Promise<void>
     #[wasm_bindgen(js_name = setRemove)]
     pub fn set_remove(&mut self, callback: js_sys::Function) {
         self.remove = Some(callback);
     }
 
-    /// Set the copy callback: (src: string, dest: string) => void
+    /// Set the copy callback: (src: string, dest: string) => void | Promise<void>
     #[wasm_bindgen(js_name = setCopy)]
     pub fn set_copy(&mut self, callback: js_sys::Function) {
         self.copy = Some(callback);
@@ -120,128 +122,92 @@ impl Default for BuilderFilesystem {
 
 impl BuilderFilesystem {
     /// Read a file from the filesystem
-    pub fn read_file_impl(&self, path: &str) -> Option<Vec<u8>> {
-        let callback = self.read_file.as_ref()?;
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        match callback.call1(&this, &arg) {
-            Ok(result) => {
-                if result.is_null() || result.is_undefined() {
-                    None
-                } else if let Some(array) = result.dyn_ref::<js_sys::Uint8Array>() {
-                    Some(array.to_vec())
-                } else {
-                    None
-                }
-            }
-            Err(_) => None,
-        }
+    pub async fn read_file_impl(&self, path: &str) -> Option<Vec<u8>> {
+        let result = call(self.read_file.as_ref()?, &[path.into()]).await?;
+        result
+            .dyn_ref::<js_sys::Uint8Array>()
+            .map(|array| array.to_vec())
     }
 
     /// Write a file to the filesystem
-    pub fn write_file_impl(&self, path: &str, contents: &[u8]) -> bool {
-        let callback = match &self.write_file {
-            Some(cb) => cb,
-            None => return false,
-        };
-
-        let this = JsValue::null();
-        let path_arg = JsValue::from_str(path);
-        let contents_arg = js_sys::Uint8Array::from(contents);
-
-        callback.call2(&this, &path_arg, &contents_arg).is_ok()
+    pub async fn write_file_impl(&self, path: &str, contents: &[u8]) -> bool {
+        match &self.write_file {
+            Some(callback) => {
+                let contents = js_sys::Uint8Array::from(contents);
+                succeeded(callback, &[path.into(), contents.into()]).await
+            }
+            None => false,
+        }
     }
 
     /// List directory contents
-    pub fn list_dir_impl(&self, path: &str) -> Option<Vec<FileEntry>> {
-        let callback = self.list_dir.as_ref()?;
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        match callback.call1(&this, &arg) {
-            Ok(result) => {
-                if result.is_null() || result.is_undefined() {
-                    None
-                } else {
-                    serde_wasm_bindgen::from_value(result).ok()
-                }
-            }
-            Err(_) => None,
-        }
+    pub async fn list_dir_impl(&self, path: &str) -> Option<Vec<FileEntry>> {
+        let result = call(self.list_dir.as_ref()?, &[path.into()]).await?;
+        serde_wasm_bindgen::from_value(result).ok()
     }
 
     /// Check if a path exists
-    pub fn exists_impl(&self, path: &str) -> bool {
-        let callback = match &self.exists {
-            Some(cb) => cb,
-            None => return false,
-        };
-
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        match callback.call1(&this, &arg) {
-            Ok(result) => result.as_bool().unwrap_or(false),
-            Err(_) => false,
+    pub async fn exists_impl(&self, path: &str) -> bool {
+        match &self.exists {
+            Some(callback) => call(callback, &[path.into()])
+                .await
+                .and_then(|result| result.as_bool())
+                .unwrap_or(false),
+            None => false,
         }
     }
 
     /// Create a directory
-    pub fn mkdir_impl(&self, path: &str) -> bool {
-        let callback = match &self.mkdir {
-            Some(cb) => cb,
-            None => return false,
-        };
-
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        callback.call1(&this, &arg).is_ok()
+    pub async fn mkdir_impl(&self, path: &str) -> bool {
+        match &self.mkdir {
+            Some(callback) => succeeded(callback, &[path.into()]).await,
+            None => false,
+        }
     }
 
     /// Get file stats
-    pub fn stat_impl(&self, path: &str) -> Option<FileStat> {
-        let callback = self.stat.as_ref()?;
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        match callback.call1(&this, &arg) {
-            Ok(result) => {
-                if result.is_null() || result.is_undefined() {
-                    None
-                } else {
-                    serde_wasm_bindgen::from_value(result).ok()
-                }
-            }
-            Err(_) => None,
-        }
+    pub async fn stat_impl(&self, path: &str) -> Option<FileStat> {
+        let result = call(self.stat.as_ref()?, &[path.into()]).await?;
+        serde_wasm_bindgen::from_value(result).ok()
     }
 
     /// Remove a file or directory
-    pub fn remove_impl(&self, path: &str) -> bool {
-        let callback = match &self.remove {
-            Some(cb) => cb,
-            None => return false,
-        };
-
-        let this = JsValue::null();
-        let arg = JsValue::from_str(path);
-
-        callback.call1(&this, &arg).is_ok()
+    pub async fn remove_impl(&self, path: &str) -> bool {
+        match &self.remove {
+            Some(callback) => succeeded(callback, &[path.into()]).await,
+            None => false,
+        }
     }
 
     /// Copy a file
-    pub fn copy_impl(&self, src: &str, dest: &str) -> bool {
-        let callback = match &self.copy {
-            Some(cb) => cb,
-            None => return false,
-        };
+    pub async fn copy_impl(&self, src: &str, dest: &str) -> bool {
+        match &self.copy {
+            Some(callback) => succeeded(callback, &[src.into(), dest.into()]).await,
+            None => false,
+        }
+    }
+}
 
-        let this = JsValue::null();
-        let src_arg = JsValue::from_str(src);
-        let dest_arg = JsValue::from_str(dest);
+/// Call a callback and await its result when it returns a Promise
+///
+/// Returns `None` when the callback throws, rejects, or gives null or
+/// undefined.
+async fn call(callback: &js_sys::Function, args: &[JsValue]) -> Option<JsValue> {
+    let args: js_sys::Array = args.iter().collect();
+    let result = callback.apply(&JsValue::null(), &args).ok()?;
+    let result = JsFuture::from(js_sys::Promise::resolve(&result))
+        .await
+        .ok()?;
+    (!result.is_null() && !result.is_undefined()).then_some(result)
+}
 
-        callback.call2(&this, &src_arg, &dest_arg).is_ok()
+/// Call a callback for its effect, whether it neither throws nor rejects
+async fn succeeded(callback: &js_sys::Function, args: &[JsValue]) -> bool {
+    let args: js_sys::Array = args.iter().collect();
+    match callback.apply(&JsValue::null(), &args) {
+        Ok(result) => JsFuture::from(js_sys::Promise::resolve(&result))
+            .await
+            .is_ok(),
+        Err(_) => false,
     }
 }
@@ -0,0 +1,210 @@
+//! Wildcard sources
+//!
+//! Matches COPY and ADD sources such as `*.js`, `src/**/*.ts` or `data[0-9]`
+//! against the build context. `*` and `?` stay within one path component,
+//! `[...]` matches a character class and `**` matches any number of
+//! directories.
+
+use crate::filesystem::FileEntry;
+use std::future::Future;
+
+/// Whether a source contains wildcards
+pub fn is_pattern(source: &str) -> bool {
+    source.contains(['*', '?', '['])
+}
+
+/// Escape wildcards so a name matches only itself
+pub fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether a path component matches a pattern component
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), match_class(&pattern[1..])) {
+            (Some(c), Some((class, rest))) => class(*c) && match_from(rest, &name[1..]),
+            // An unclosed class matches a literal `[`
+            (Some('['), None) => match_from(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && match_from(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Parse a character class after its `[`, returning a matcher and the rest
+/// of the pattern
+fn match_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, &[char])> {
+    let (negated, mut i) = match pattern.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match pattern.get(i)? {
+            ']' if !first => break,
+            '\\' => {
+                i += 1;
+                *pattern.get(i)?
+            }
+            c => *c,
+        };
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|end| *end != ']') {
+            ranges.push((c, pattern[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    let class = move |c: char| ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != negated;
+    Some((class, &pattern[i + 1..]))
+}
+
+/// Expand a pattern relative to a directory, listing directories through
+/// `list_dir`
+///
+/// Returns the sorted paths of matching files and directories, relative to
+/// `root`.
+pub async fn expand<F, Fut>(root: &str, pattern: &str, list_dir: F) -> Vec<String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Option<Vec<FileEntry>>>,
+{
+    let components: Vec<&str> = pattern
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    let root = root.trim_end_matches('/');
+    let mut found = Vec::new();
+    // Paths matched so far, with the index of the next component to match
+    let mut pending = vec![(String::new(), 0)];
+
+    while let Some((relative, next)) = pending.pop() {
+        let Some(component) = components.get(next) else {
+            found.push(relative);
+            continue;
+        };
+        let join = |name: &str| {
+            if relative.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", relative, name)
+            }
+        };
+        let dir = match (root, relative.as_str()) {
+            ("", "") => "/".to_string(),
+            (root, "") => root.to_string(),
+            (root, relative) => format!("{}/{}", root, relative),
+        };
+        let entries = list_dir(dir).await.unwrap_or_default();
+        let last = next + 1 == components.len();
+
+        if *component == "**" {
+            // Match no directories, then descend into each one
+            pending.push((relative.clone(), next + 1));
+            for entry in entries.iter().filter(|entry| entry.is_dir) {
+                pending.push((join(&entry.name), next));
+            }
+        } else if is_pattern(component) {
+            for entry in &entries {
+                if matches(component, &entry.name) && (last || entry.is_dir) {
+                    pending.push((join(&entry.name), next + 1));
+                }
+            }
+        } else if let Some(entry) = entries.iter().find(|entry| entry.name == *component) {
+            // Literal components only need to exist
+            if last || entry.is_dir {
+                pending.push((join(component), next + 1));
+            }
+        }
+    }
+
+    found.sort();
+    found.dedup();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.js", "app.js"));
+        assert!(matches("*.js", ".js"));
+        assert!(!matches("*.js", "app.ts"));
+        assert!(matches("app?.js", "app1.js"));
+        assert!(!matches("app?.js", "app.js"));
+        assert!(matches("data[0-9]", "data7"));
+        assert!(!matches("data[!0-9]", "data7"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches(&escape("a*[b]?"), "a*[b]?"));
+        assert!(!matches(&escape("a*"), "ab"));
+        assert!(is_pattern("src/**/*.js"));
+        assert!(!is_pattern("src/app.js"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let tree = |path: String| async move {
+            let entries: &[(&str, bool)] = match path.as_str() {
+                "/ctx" => &[("src", true), ("README.md", false), ("app.js", false)],
+                "/ctx/src" => &[("index.js", false), ("lib", true), ("style.css", false)],
+                "/ctx/src/lib" => &[("util.js", false), ("deep", true)],
+                "/ctx/src/lib/deep" => &[("more.js", false)],
+                _ => return None,
+            };
+            Some::<Vec<FileEntry>>(
+                entries
+                    .iter()
+                    .map(|(name, is_dir)| FileEntry {
+                        name: name.to_string(),
+                        is_dir: *is_dir,
+                    })
+                    .collect(),
+            )
+        };
+
+        assert_eq!(block_on(expand("/ctx", "*.js", tree)), vec!["app.js"]);
+        assert_eq!(
+            block_on(expand("/ctx/", "src/**/*.js", tree)),
+            vec!["src/index.js", "src/lib/deep/more.js", "src/lib/util.js"]
+        );
+        assert_eq!(
+            block_on(expand("/ctx", "**/*.js", tree)),
+            vec![
+                "app.js",
+                "src/index.js",
+                "src/lib/deep/more.js",
+                "src/lib/util.js"
+            ]
+        );
+        assert_eq!(block_on(expand("/ctx", "./s*/l?b", tree)), vec!["src/lib"]);
+        assert!(block_on(expand("/ctx", "*.go", tree)).is_empty());
+        assert!(block_on(expand("/ctx", "README.md/*", tree)).is_empty());
+    }
+}
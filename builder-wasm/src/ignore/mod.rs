@@ -0,0 +1,185 @@
+//! Build context exclusions
+//!
+//! Reads `.runeignore`, or `.dockerignore` when there is none, from the root
+//! of the build context. Each line is a pattern relative to the context;
+//! excluding a directory excludes everything under it, `**` matches any
+//! number of directories and a leading `!` re-includes paths excluded by an
+//! earlier line. The last matching line wins.
+
+use crate::filesystem::BuilderFilesystem;
+use crate::glob;
+
+/// Ignore files, in order of preference
+pub const IGNORE_FILES: [&str; 2] = [".runeignore", ".dockerignore"];
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    components: Vec<String>,
+    negated: bool,
+}
+
+/// Exclusion patterns of a build context
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    root: String,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Read the ignore file of a context, if it has one
+    pub async fn load(fs: &BuilderFilesystem, context_dir: &str) -> Self {
+        let root = context_dir.trim_end_matches('/');
+        for name in IGNORE_FILES {
+            if let Some(content) = fs.read_file_impl(&format!("{}/{}", root, name)).await {
+                return Self::parse(context_dir, &String::from_utf8_lossy(&content));
+            }
+        }
+        Self::parse(context_dir, "")
+    }
+
+    /// Parse ignore file content for a context
+    pub fn parse(context_dir: &str, content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line.trim()),
+                    None => (false, line),
+                };
+                let components: Vec<String> = line
+                    .split('/')
+                    .filter(|c| !c.is_empty() && *c != ".")
+                    .map(str::to_string)
+                    .collect();
+                (!components.is_empty()).then_some(Pattern {
+                    components,
+                    negated,
+                })
+            })
+            .collect();
+        Self {
+            root: context_dir.trim_end_matches('/').to_string(),
+            patterns,
+        }
+    }
+
+    /// Exclude a path, after every pattern read from the ignore file
+    ///
+    /// Paths outside the context are left alone.
+    pub fn exclude(&mut self, path: &str) {
+        let Some(relative) = path.trim_end_matches('/').strip_prefix(&self.root) else {
+            return;
+        };
+        if !relative.starts_with('/') {
+            return;
+        }
+        self.patterns.push(Pattern {
+            components: relative
+                .split('/')
+                .filter(|c| !c.is_empty() && *c != ".")
+                .map(glob::escape)
+                .collect(),
+            negated: false,
+        });
+    }
+
+    /// Whether there are no patterns
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether any pattern re-includes paths
+    pub fn has_negations(&self) -> bool {
+        self.patterns.iter().any(|pattern| pattern.negated)
+    }
+
+    /// Whether a path in the context is excluded
+    ///
+    /// Paths outside the context and the context itself are never excluded.
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let Some(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let components: Vec<&str> = relative.split('/').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() || !relative.is_empty() && !relative.starts_with('/') {
+            return false;
+        }
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.negated == ignored && matches_prefix(&pattern.components, &components) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Whether a pattern matches a path or one of its parent directories
+fn matches_prefix(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some((component, rest)) if component == "**" => {
+            (0..=path.len()).any(|skip| matches_prefix(rest, &path[skip..]))
+        }
+        Some((component, rest)) => match path.split_first() {
+            Some((name, path)) => glob::matches(component, name) && matches_prefix(rest, path),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let rules = IgnoreRules::parse(
+            "/ctx/",
+            "# build output\n\
+             target\n\
+             /node_modules/\n\
+             **/*.log\n\
+             docs/*.md\n\
+             !docs/README.md\n\
+             secrets\n\
+             !secrets/public\n",
+        );
+
+        assert!(rules.is_ignored("/ctx/target"));
+        assert!(rules.is_ignored("/ctx/target/debug/app"));
+        assert!(rules.is_ignored("/ctx/node_modules/left-pad/index.js"));
+        assert!(rules.is_ignored("/ctx/build.log"));
+        assert!(rules.is_ignored("/ctx/src/deep/trace.log"));
+        assert!(rules.is_ignored("/ctx/docs/guide.md"));
+        assert!(!rules.is_ignored("/ctx/docs/README.md"));
+        assert!(rules.is_ignored("/ctx/secrets/key.pem"));
+        assert!(!rules.is_ignored("/ctx/secrets/public/key.pub"));
+        // Patterns are anchored at the context root
+        assert!(!rules.is_ignored("/ctx/src/target"));
+        assert!(!rules.is_ignored("/ctx/src/main.rs"));
+        assert!(!rules.is_ignored("/ctx"));
+        assert!(!rules.is_ignored("/ctx-other/target"));
+        assert!(rules.has_negations());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let mut rules = IgnoreRules::parse("/ctx", "!.rune/oci/keep\n");
+        rules.exclude("/ctx/.rune/oci/");
+        rules.exclude("/elsewhere/cache");
+        assert!(rules.is_ignored("/ctx/.rune/oci/index.json"));
+        assert!(rules.is_ignored("/ctx/.rune/oci/keep"));
+        assert!(!rules.is_ignored("/ctx/.rune/cache"));
+        assert!(!rules.is_ignored("/elsewhere/cache/x"));
+    }
+
+    #[test]
+    fn test_empty_rules() {
+        let rules = IgnoreRules::parse("/ctx", "\n# nothing\n");
+        assert!(rules.is_empty());
+        assert!(!rules.is_ignored("/ctx/anything"));
+    }
+}
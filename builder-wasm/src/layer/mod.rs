@@ -0,0 +1,412 @@
+//! Layer archives
+//!
+//! Collects the files and directories a step adds and writes them as a tar,
+//! optionally gzipped. Entries are sorted by path and carry no timestamps, so
+//! the same files always give the same digest.
+
+use crate::calculate_digest;
+use crate::filesystem::FileEntry;
+use crate::oci::{MEDIA_TYPE_LAYER, MEDIA_TYPE_LAYER_GZIP};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Mode of directories created for parents of added files
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+/// Mode of files whose mode is unknown
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Contents of a layer entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryKind {
+    Directory,
+    File(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: EntryKind,
+    mode: u32,
+}
+
+/// Builder of an uncompressed layer tar
+#[derive(Debug, Clone, Default)]
+pub struct LayerBuilder {
+    entries: BTreeMap<String, Entry>,
+    uid: u64,
+    gid: u64,
+}
+
+impl LayerBuilder {
+    /// Create an empty layer owned by root
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the owner of every entry
+    pub fn with_owner(mut self, uid: u64, gid: u64) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Add a directory and its parents
+    pub fn add_dir(&mut self, path: &str, mode: u32) {
+        self.insert(
+            path,
+            Entry {
+                kind: EntryKind::Directory,
+                mode: mode & 0o7777,
+            },
+        );
+    }
+
+    /// Add a file, creating its parent directories
+    pub fn add_file(&mut self, path: &str, content: Vec<u8>, mode: u32) {
+        self.insert(
+            path,
+            Entry {
+                kind: EntryKind::File(content),
+                mode: mode & 0o7777,
+            },
+        );
+    }
+
+    /// Lay another layer over this one
+    pub fn extend(&mut self, layer: LayerBuilder) {
+        self.entries.extend(layer.entries);
+    }
+
+    /// Whether a path is a directory, or `None` when it is not in the layer
+    ///
+    /// The root is always a directory.
+    pub fn is_dir(&self, path: &str) -> Option<bool> {
+        let path = normalize_path(path);
+        if path == "/" {
+            return Some(true);
+        }
+        self.entries
+            .get(&path)
+            .map(|entry| entry.kind == EntryKind::Directory)
+    }
+
+    /// Entries directly inside a directory
+    pub fn list_dir(&self, path: &str) -> Option<Vec<FileEntry>> {
+        if self.is_dir(path) != Some(true) {
+            return None;
+        }
+        let prefix = format!("{}/", normalize_path(path).trim_end_matches('/'));
+        Some(
+            self.entries
+                .range(prefix.clone()..)
+                .take_while(|(child, _)| child.starts_with(&prefix))
+                .filter(|(child, _)| !child[prefix.len()..].contains('/'))
+                .map(|(child, entry)| FileEntry {
+                    name: child[prefix.len()..].to_string(),
+                    is_dir: entry.kind == EntryKind::Directory,
+                })
+                .collect(),
+        )
+    }
+
+    /// Copy a path of this layer into another layer at `dest`
+    ///
+    /// A file becomes `dest`; a directory's contents go into `dest`.
+    /// Returns false when the path is not in the layer.
+    pub fn copy_to(&self, path: &str, target: &mut LayerBuilder, dest: &str) -> bool {
+        let path = normalize_path(path);
+        let dest = normalize_path(dest);
+        match self.is_dir(&path) {
+            None => false,
+            Some(false) => {
+                target.insert(&dest, self.entries[&path].clone());
+                true
+            }
+            Some(true) => {
+                let mode = self.entries.get(&path).map_or(DEFAULT_DIR_MODE, |e| e.mode);
+                target.add_dir(&dest, mode);
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                for (child, entry) in self
+                    .entries
+                    .range(prefix.clone()..)
+                    .take_while(|(child, _)| child.starts_with(&prefix))
+                {
+                    target.insert(
+                        &format!("{}/{}", dest, &child[prefix.len()..]),
+                        entry.clone(),
+                    );
+                }
+                true
+            }
+        }
+    }
+
+    /// Whether nothing has been added
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of files and directories in the layer
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Digest of the paths, modes, owner and contents of the entries
+    ///
+    /// Cheaper than archiving, and equal for layers that archive the same.
+    pub fn digest(&self) -> String {
+        let mut summary = format!("{}:{}\n", self.uid, self.gid);
+        for (path, entry) in &self.entries {
+            let content = match &entry.kind {
+                EntryKind::Directory => "dir".to_string(),
+                EntryKind::File(content) => calculate_digest(content),
+            };
+            summary.push_str(&format!("{} {:o} {}\n", path, entry.mode, content));
+        }
+        calculate_digest(summary.as_bytes())
+    }
+
+    /// Write the layer as a tar archive
+    pub fn into_tar(self) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, entry) in &self.entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(entry.mode);
+            header.set_uid(self.uid);
+            header.set_gid(self.gid);
+            header.set_mtime(0);
+            let relative = path.trim_start_matches('/');
+            // Writing to memory cannot fail, and normalized paths have no `..`
+            let _ = match &entry.kind {
+                EntryKind::Directory => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, format!("{}/", relative), std::io::empty())
+                }
+                EntryKind::File(content) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(content.len() as u64);
+                    builder.append_data(&mut header, relative, content.as_slice())
+                }
+            };
+        }
+        builder.into_inner().unwrap_or_default()
+    }
+
+    /// Add an entry and its missing parents
+    fn insert(&mut self, path: &str, entry: Entry) {
+        let path = normalize_path(path);
+        if path == "/" {
+            return;
+        }
+        self.add_parents(&path);
+        self.entries.insert(path, entry);
+    }
+
+    /// Add the missing parent directories of a path
+    fn add_parents(&mut self, path: &str) {
+        let mut parent = String::new();
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        for component in &components[..components.len().saturating_sub(1)] {
+            parent.push('/');
+            parent.push_str(component);
+            self.entries.entry(parent.clone()).or_insert(Entry {
+                kind: EntryKind::Directory,
+                mode: DEFAULT_DIR_MODE,
+            });
+        }
+    }
+}
+
+/// Layer as stored in the image
+#[derive(Debug, Clone)]
+pub struct LayerBlob {
+    /// Media type of the content
+    pub media_type: &'static str,
+    /// Digest of the uncompressed tar
+    pub diff_id: String,
+    /// Tar or gzipped tar
+    pub content: Vec<u8>,
+}
+
+impl LayerBlob {
+    /// Store a layer tar, gzipping it when `compress` is set
+    pub fn new(tar: Vec<u8>, compress: bool) -> Self {
+        let diff_id = calculate_digest(&tar);
+        if compress {
+            Self {
+                media_type: MEDIA_TYPE_LAYER_GZIP,
+                diff_id,
+                content: gzip(&tar),
+            }
+        } else {
+            Self {
+                media_type: MEDIA_TYPE_LAYER,
+                diff_id,
+                content: tar,
+            }
+        }
+    }
+
+    /// Digest of the stored content
+    pub fn digest(&self) -> String {
+        calculate_digest(&self.content)
+    }
+
+    /// Uncompressed tar of the layer
+    pub fn tar(&self) -> Result<Vec<u8>, String> {
+        if self.media_type != MEDIA_TYPE_LAYER_GZIP {
+            return Ok(self.content.clone());
+        }
+        let mut tar = Vec::new();
+        GzDecoder::new(self.content.as_slice())
+            .read_to_end(&mut tar)
+            .map_err(|e| format!("Invalid gzipped layer {}: {}", self.digest(), e))?;
+        Ok(tar)
+    }
+}
+
+/// Gzip data
+///
+/// The gzip header carries no timestamp or name, so the output depends only
+/// on the input.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to memory cannot fail
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Make a path absolute and resolve `.` and `..`, never leaving the root
+pub fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(tar: &[u8]) -> Vec<(String, u32, u64, String)> {
+        let mut archive = tar::Archive::new(tar);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mode = entry.header().mode().unwrap();
+                let uid = entry.header().uid().unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (path, mode, uid, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_layer_tar() {
+        let mut layer = LayerBuilder::new().with_owner(1000, 1000);
+        layer.add_file("/app/bin/run", b"#!/bin/sh".to_vec(), 0o100755);
+        layer.add_file("app/../app/config.json", b"{}".to_vec(), DEFAULT_FILE_MODE);
+        layer.add_dir("/app/data", 0o700);
+        assert_eq!(layer.len(), 5);
+        let digest = layer.digest();
+        assert_ne!(digest, layer.clone().with_owner(0, 0).digest());
+
+        let tar = layer.clone().into_tar();
+        assert_eq!(
+            entries(&tar),
+            vec![
+                ("app/".to_string(), 0o755, 1000, String::new()),
+                ("app/bin/".to_string(), 0o755, 1000, String::new()),
+                (
+                    "app/bin/run".to_string(),
+                    0o755,
+                    1000,
+                    "#!/bin/sh".to_string()
+                ),
+                ("app/config.json".to_string(), 0o644, 1000, "{}".to_string()),
+                ("app/data/".to_string(), 0o700, 1000, String::new()),
+            ]
+        );
+        // The same files give the same archive
+        assert_eq!(layer.digest(), digest);
+        assert_eq!(layer.into_tar(), tar);
+        assert!(LayerBuilder::new().is_empty());
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let mut rootfs = LayerBuilder::new();
+        rootfs.add_file("/app/bin/run", b"v1".to_vec(), 0o755);
+        rootfs.add_file("/app/lib.so", b"lib".to_vec(), DEFAULT_FILE_MODE);
+        let mut upper = LayerBuilder::new();
+        upper.add_file("/app/bin/run", b"v2".to_vec(), 0o755);
+        rootfs.extend(upper);
+
+        let names = |entries: Vec<FileEntry>| -> Vec<(String, bool)> {
+            entries.into_iter().map(|e| (e.name, e.is_dir)).collect()
+        };
+        assert_eq!(
+            names(rootfs.list_dir("/app").unwrap()),
+            vec![("bin".to_string(), true), ("lib.so".to_string(), false)]
+        );
+        assert_eq!(
+            names(rootfs.list_dir("/").unwrap()),
+            vec![("app".to_string(), true)]
+        );
+        assert!(rootfs.list_dir("/app/lib.so").is_none());
+        assert_eq!(rootfs.is_dir("/missing"), None);
+
+        let mut layer = LayerBuilder::new();
+        assert!(rootfs.copy_to("/app/bin", &mut layer, "/usr/local/bin"));
+        assert!(rootfs.copy_to("/app/lib.so", &mut layer, "/usr/lib/app.so"));
+        assert!(!rootfs.copy_to("/app/missing", &mut layer, "/tmp"));
+        let copied: Vec<(String, String)> = entries(&layer.into_tar())
+            .into_iter()
+            .map(|(path, _, _, content)| (path, content))
+            .collect();
+        assert!(copied.contains(&("usr/local/bin/run".to_string(), "v2".to_string())));
+        assert!(copied.contains(&("usr/lib/app.so".to_string(), "lib".to_string())));
+    }
+
+    #[test]
+    fn test_layer_blob() {
+        let mut layer = LayerBuilder::new();
+        layer.add_file("/etc/motd", b"hello ".repeat(100), DEFAULT_FILE_MODE);
+        let tar = layer.into_tar();
+
+        let plain = LayerBlob::new(tar.clone(), false);
+        assert_eq!(plain.media_type, MEDIA_TYPE_LAYER);
+        assert_eq!(plain.digest(), plain.diff_id);
+
+        let compressed = LayerBlob::new(tar.clone(), true);
+        assert_eq!(compressed.media_type, MEDIA_TYPE_LAYER_GZIP);
+        assert_eq!(compressed.diff_id, plain.diff_id);
+        assert_ne!(compressed.digest(), compressed.diff_id);
+        assert!(compressed.content.len() < tar.len());
+        // Compression is deterministic and reversible
+        assert_eq!(gzip(&tar), compressed.content);
+        assert_eq!(compressed.tar().unwrap(), tar);
+        assert_eq!(plain.tar().unwrap(), tar);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("a/./b/"), "/a/b");
+        assert_eq!(normalize_path("/../../etc"), "/etc");
+        assert_eq!(normalize_path("."), "/");
+    }
+}
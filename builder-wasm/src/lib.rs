@@ -21,21 +21,32 @@
 //! // Create filesystem adapter from in-memory fs
 //! const fs = new BuilderFilesystem();
 //! fs.setReadFile((path) => memFs.readFile(path));
+//! fs.setWriteFile((path, contents) => memFs.writeFile(path, contents));
 //! fs.setExists((path) => memFs.exists(path));
 //!
 //! // Create the builder and build (all local, no network)
 //! const builder = new WasmBuilder(fs);
-//! const result = builder.build(JSON.stringify({
+//! const result = await builder.build(JSON.stringify({
 //!     contextDir: '/project',
 //!     tags: ['myapp:latest'],
 //! }));
+//!
+//! // The image is written as an OCI image layout, in `/project/.rune/oci`
+//! // unless `outputDir` is given. Layers are gzipped; pass
+//! // `compressLayers: false` to store plain tars and build faster.
+//! // Images are linux/amd64 unless `os` and `architecture` say otherwise;
+//! // set `created` to an RFC 3339 timestamp for reproducible builds.
+//! const index = memFs.readTextFile('/project/.rune/oci/index.json');
 //! ```
 //!
 //! ## Usage with Custom Filesystem (Browser File API, etc.)
 //!
+//! Callbacks may return Promises, so async filesystems such as the File
+//! System Access API or OPFS can be used directly.
+//!
 //! ```javascript
 //! const fs = new BuilderFilesystem();
-//! fs.setReadFile((path) => myFilesystem.readFile(path));
+//! fs.setReadFile(async (path) => await myFilesystem.readFile(path));
 //! fs.setWriteFile((path, contents) => myFilesystem.writeFile(path, contents));
 //! fs.setListDir((path) => myFilesystem.listDir(path));
 //! fs.setExists((path) => myFilesystem.exists(path));
@@ -43,10 +54,48 @@
 //! const builder = new WasmBuilder(fs);
 //! const parsed = builder.parseRunefile(runefileContent);
 //! ```
+//!
+//! ## Exporting for `docker load`
+//!
+//! ```javascript
+//! const { imageId } = JSON.parse(await builder.build(JSON.stringify({ contextDir: '/project' })));
+//! await builder.exportImage(imageId, '/project/image.tar');
+//! ```
+//!
+//! ## Pushing to a Registry
+//!
+//! Images are pushed through the fetch callback, by one of the tags they
+//! were built with:
+//!
+//! ```javascript
+//! builder.setFetch((url, init) => fetch(url, init));
+//! await builder.build(JSON.stringify({ contextDir: '/project', tags: ['ghcr.io/org/app:1.0'] }));
+//! const { digest } = JSON.parse(await builder.push('ghcr.io/org/app:1.0',
+//!     JSON.stringify({ username: 'org', password: token })));
+//! ```
+//!
+//! ## Pulling Base Images
+//!
+//! Without a fetch callback, `build` only adds the Runefile's own layers.
+//! With one, it first pulls each FROM image through the callback, so its
+//! layers and config become part of the built image:
+//!
+//! ```javascript
+//! builder.setFetch((url, init) => fetch(`/registry-proxy?url=${encodeURIComponent(url)}`, init));
+//! const result = JSON.parse(await builder.build(JSON.stringify({ contextDir: '/project' })));
+//! ```
 
+pub mod archive;
+pub mod args;
 pub mod builder;
+pub mod cache;
 pub mod filesystem;
+pub mod glob;
+pub mod ignore;
+pub mod layer;
+pub mod oci;
 pub mod parser;
+pub mod registry;
 pub mod types;
 
 // Re-export main types
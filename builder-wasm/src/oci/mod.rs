@@ -0,0 +1,434 @@
+//! OCI image layout
+//!
+//! Lays out a built image as an OCI image layout directory: `oci-layout`,
+//! `index.json` and content-addressed blobs under `blobs/sha256/`, and reads
+//! images back from one.
+
+use crate::calculate_digest;
+use crate::filesystem::BuilderFilesystem;
+use crate::layer::LayerBlob;
+use crate::types::ImageConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+
+/// Version written to the `oci-layout` file
+pub const IMAGE_LAYOUT_VERSION: &str = "1.0.0";
+/// Media type of an image index
+pub const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+/// Media type of an image manifest
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+/// Media type of an image config
+pub const MEDIA_TYPE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+/// Media type of an uncompressed layer
+pub const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+/// Media type of a gzipped layer
+pub const MEDIA_TYPE_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+/// Annotation naming the tag of a manifest in the index
+pub const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Reference to a blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Platform of a manifest listed in an index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+}
+
+/// Platform an image runs on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Platform {
+    pub architecture: String,
+    pub os: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl Descriptor {
+    /// Describe a blob
+    pub fn new(media_type: &str, blob: &[u8]) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: calculate_digest(blob),
+            size: blob.len() as u64,
+            annotations: BTreeMap::new(),
+            platform: None,
+        }
+    }
+}
+
+/// Image manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub media_type: String,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+/// Image index, the entry point of a layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub media_type: String,
+    pub manifests: Vec<Descriptor>,
+}
+
+/// Files of an OCI image layout, relative to the layout directory
+#[derive(Debug, Clone)]
+pub struct ImageLayout {
+    /// Descriptor of the image config blob
+    pub config: Descriptor,
+    /// Descriptor of the manifest blob
+    pub manifest: Descriptor,
+    /// Paths and contents, blobs first and `index.json` last
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl ImageLayout {
+    /// Lay out an image from its config and layer blobs
+    ///
+    /// The index lists the manifest once per tag, or once without a name
+    /// when there are no tags.
+    pub fn new(config: &ImageConfig, layers: &[LayerBlob], tags: &[String]) -> Self {
+        let mut files = Vec::new();
+        let mut blob = |media_type: &str, content: Vec<u8>| {
+            let descriptor = Descriptor::new(media_type, &content);
+            let path = blob_path(&descriptor.digest);
+            if !files.iter().any(|(existing, _)| *existing == path) {
+                files.push((path, content));
+            }
+            descriptor
+        };
+
+        let layers: Vec<Descriptor> = layers
+            .iter()
+            .map(|layer| blob(layer.media_type, layer.content.clone()))
+            .collect();
+        let platform = Platform {
+            architecture: config.architecture.clone(),
+            os: config.os.clone(),
+            variant: config.variant.clone(),
+        };
+        let config = blob(
+            MEDIA_TYPE_CONFIG,
+            serde_json::to_vec(config).unwrap_or_default(),
+        );
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST.to_string(),
+            config: config.clone(),
+            layers,
+        };
+        let mut manifest = blob(
+            MEDIA_TYPE_MANIFEST,
+            serde_json::to_vec(&manifest).unwrap_or_default(),
+        );
+        manifest.platform = Some(platform);
+
+        let manifests = if tags.is_empty() {
+            vec![manifest.clone()]
+        } else {
+            tags.iter()
+                .map(|tag| {
+                    let mut descriptor = manifest.clone();
+                    descriptor
+                        .annotations
+                        .insert(ANNOTATION_REF_NAME.to_string(), tag.clone());
+                    descriptor
+                })
+                .collect()
+        };
+        let index = Index {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_INDEX.to_string(),
+            manifests,
+        };
+
+        files.push((
+            "oci-layout".to_string(),
+            serde_json::to_vec(&serde_json::json!({ "imageLayoutVersion": IMAGE_LAYOUT_VERSION }))
+                .unwrap_or_default(),
+        ));
+        files.push((
+            "index.json".to_string(),
+            serde_json::to_vec(&index).unwrap_or_default(),
+        ));
+
+        Self {
+            config,
+            manifest,
+            files,
+        }
+    }
+
+    /// Write the layout to a directory through the filesystem callbacks
+    pub async fn write(&self, fs: &BuilderFilesystem, dir: &str) -> Result<(), String> {
+        if fs.write_file.is_none() {
+            return Err("The filesystem has no writeFile callback".to_string());
+        }
+        let dir = dir.trim_end_matches('/');
+        for sub_dir in ["", "/blobs", "/blobs/sha256"] {
+            let path = format!("{}{}", dir, sub_dir);
+            if !fs.exists_impl(&path).await {
+                fs.mkdir_impl(&path).await;
+            }
+        }
+        for (path, content) in &self.files {
+            let path = format!("{}/{}", dir, path);
+            if !fs.write_file_impl(&path, content).await {
+                return Err(format!("Failed to write {}", path));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Image read back from a layout
+#[derive(Debug, Clone)]
+pub struct StoredImage {
+    /// Manifest blob
+    pub manifest: Vec<u8>,
+    /// Image config blob
+    pub config: Vec<u8>,
+    pub layers: Vec<LayerBlob>,
+    /// Tags the index names the image by
+    pub tags: Vec<String>,
+}
+
+/// Read an image from a layout directory by its ID or config digest,
+/// reading files through `read_file`
+pub async fn read_image<F, Fut>(
+    dir: &str,
+    image_id: &str,
+    read_file: F,
+) -> Result<StoredImage, String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    let dir = dir.trim_end_matches('/');
+    let read_blob = |digest: String| {
+        let path = format!("{}/{}", dir, blob_path(&digest));
+        let content = read_file(path);
+        async move {
+            let content = content
+                .await
+                .ok_or_else(|| format!("Missing blob {}", digest))?;
+            if calculate_digest(&content) != digest {
+                return Err(format!("Blob {} does not match its digest", digest));
+            }
+            Ok(content)
+        }
+    };
+    let index = read_file(format!("{}/index.json", dir))
+        .await
+        .ok_or_else(|| format!("No image layout in {}", dir))?;
+    let index: Index =
+        serde_json::from_slice(&index).map_err(|e| format!("Invalid index.json: {}", e))?;
+
+    let id = image_id.trim_start_matches("sha256:");
+    let mut found: Option<(Manifest, Vec<u8>)> = None;
+    let mut tags = Vec::new();
+    for descriptor in &index.manifests {
+        let (manifest, bytes) = match &found {
+            Some((manifest, bytes)) if calculate_digest(bytes) == descriptor.digest => {
+                (manifest.clone(), bytes.clone())
+            }
+            _ => {
+                let bytes = read_blob(descriptor.digest.clone()).await?;
+                let manifest = serde_json::from_slice::<Manifest>(&bytes)
+                    .map_err(|e| format!("Invalid manifest {}: {}", descriptor.digest, e))?;
+                (manifest, bytes)
+            }
+        };
+        let config_hex = manifest.config.digest.trim_start_matches("sha256:");
+        if id.is_empty() || !config_hex.starts_with(id) {
+            continue;
+        }
+        if let Some(tag) = descriptor.annotations.get(ANNOTATION_REF_NAME) {
+            tags.push(tag.clone());
+        }
+        found.get_or_insert((manifest, bytes));
+    }
+    let (manifest, manifest_bytes) =
+        found.ok_or_else(|| format!("Image not found: {}", image_id))?;
+
+    let config = read_blob(manifest.config.digest.clone()).await?;
+    let mut layers = Vec::new();
+    for descriptor in &manifest.layers {
+        let media_type = match descriptor.media_type.as_str() {
+            MEDIA_TYPE_LAYER => MEDIA_TYPE_LAYER,
+            MEDIA_TYPE_LAYER_GZIP => MEDIA_TYPE_LAYER_GZIP,
+            other => return Err(format!("Unsupported layer media type: {}", other)),
+        };
+        let content = read_blob(descriptor.digest.clone()).await?;
+        let blob = LayerBlob {
+            media_type,
+            diff_id: String::new(),
+            content,
+        };
+        let diff_id = calculate_digest(&blob.tar()?);
+        layers.push(LayerBlob { diff_id, ..blob });
+    }
+    Ok(StoredImage {
+        manifest: manifest_bytes,
+        config,
+        layers,
+        tags,
+    })
+}
+
+/// Path of a blob within the layout
+pub fn blob_path(digest: &str) -> String {
+    let (algorithm, hex) = digest.split_once(':').unwrap_or(("sha256", digest));
+    format!("blobs/{}/{}", algorithm, hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ContainerConfig, RootFs};
+    use futures::executor::block_on;
+
+    fn image_config(diff_ids: Vec<String>) -> ImageConfig {
+        ImageConfig {
+            created: None,
+            architecture: "arm64".to_string(),
+            os: "linux".to_string(),
+            variant: Some("v8".to_string()),
+            config: ContainerConfig::default(),
+            rootfs: RootFs {
+                fs_type: "layers".to_string(),
+                diff_ids,
+            },
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let layer = LayerBlob::new(b"layer".to_vec(), false);
+        let config = image_config(vec![layer.diff_id.clone()]);
+        let tags = vec!["app:latest".to_string(), "app:1.0".to_string()];
+        let layout = ImageLayout::new(&config, &[layer.clone(), layer], &tags);
+        let file = |path: &str| {
+            &layout
+                .files
+                .iter()
+                .find(|(name, _)| name == path)
+                .unwrap()
+                .1
+        };
+
+        // Every blob is named by the digest of its content
+        for (path, content) in &layout.files {
+            if let Some(hex) = path.strip_prefix("blobs/sha256/") {
+                assert_eq!(calculate_digest(content), format!("sha256:{}", hex));
+            }
+        }
+        // Identical layers share one blob
+        assert_eq!(layout.files.len(), 5);
+
+        let manifest: Manifest =
+            serde_json::from_slice(file(&blob_path(&layout.manifest.digest))).unwrap();
+        assert_eq!(manifest.config, layout.config);
+        assert_eq!(manifest.layers.len(), 2);
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_LAYER);
+
+        let index: Index = serde_json::from_slice(file("index.json")).unwrap();
+        let names: Vec<&str> = index
+            .manifests
+            .iter()
+            .map(|m| m.annotations[ANNOTATION_REF_NAME].as_str())
+            .collect();
+        assert_eq!(names, vec!["app:latest", "app:1.0"]);
+        assert!(index
+            .manifests
+            .iter()
+            .all(|m| m.digest == layout.manifest.digest));
+        assert_eq!(
+            index.manifests[0].platform,
+            Some(Platform {
+                architecture: "arm64".to_string(),
+                os: "linux".to_string(),
+                variant: Some("v8".to_string()),
+            })
+        );
+        assert_eq!(
+            file("oci-layout"),
+            br#"{"imageLayoutVersion":"1.0.0"}"#.as_slice()
+        );
+    }
+
+    #[test]
+    fn test_compressed_layout() {
+        let layer = LayerBlob::new(b"layer".to_vec(), true);
+        let config = image_config(vec![layer.diff_id.clone()]);
+        let layout = ImageLayout::new(&config, std::slice::from_ref(&layer), &[]);
+        let manifest = &layout
+            .files
+            .iter()
+            .find(|(name, _)| *name == blob_path(&layout.manifest.digest))
+            .unwrap()
+            .1;
+        let manifest: Manifest = serde_json::from_slice(manifest).unwrap();
+
+        // The manifest describes the gzipped blob, the config the tar
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_LAYER_GZIP);
+        assert_eq!(manifest.layers[0].digest, layer.digest());
+        assert_eq!(manifest.layers[0].size, layer.content.len() as u64);
+        assert!(layout
+            .files
+            .iter()
+            .any(|(name, _)| *name == blob_path(&layer.digest())));
+        assert_eq!(config.rootfs.diff_ids, vec![calculate_digest(b"layer")]);
+    }
+
+    #[test]
+    fn test_read_image() {
+        let plain = LayerBlob::new(b"first".to_vec(), false);
+        let compressed = LayerBlob::new(b"second".to_vec(), true);
+        let config = image_config(vec![plain.diff_id.clone(), compressed.diff_id.clone()]);
+        let tags = vec!["app:latest".to_string(), "app:1.0".to_string()];
+        let layout = ImageLayout::new(&config, &[plain.clone(), compressed.clone()], &tags);
+        let read_file = |path: String| {
+            let content = layout
+                .files
+                .iter()
+                .find(|(name, _)| format!("/out/{}", name) == path)
+                .map(|(_, content)| content.clone());
+            std::future::ready(content)
+        };
+
+        let image_id = &layout.config.digest[7..19];
+        let image = block_on(read_image("/out/", image_id, read_file)).unwrap();
+        assert_eq!(calculate_digest(&image.config), layout.config.digest);
+        assert_eq!(calculate_digest(&image.manifest), layout.manifest.digest);
+        assert_eq!(image.tags, tags);
+        let diff_ids: Vec<&str> = image.layers.iter().map(|l| l.diff_id.as_str()).collect();
+        assert_eq!(
+            diff_ids,
+            vec![plain.diff_id.as_str(), compressed.diff_id.as_str()]
+        );
+        assert_eq!(image.layers[1].media_type, MEDIA_TYPE_LAYER_GZIP);
+
+        let missing = block_on(read_image("/out", "0123456789ab", read_file));
+        assert_eq!(missing.unwrap_err(), "Image not found: 0123456789ab");
+    }
+
+    #[test]
+    fn test_blob_path() {
+        assert_eq!(blob_path("sha256:abc"), "blobs/sha256/abc");
+    }
+}
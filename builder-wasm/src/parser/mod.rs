@@ -1,6 +1,7 @@
 //! Runefile parser for WASM builder
 
-use crate::types::{BuildInstruction, BuildStage, ParsedRunefile};
+use crate::args::ArgScope;
+use crate::types::{BuildInstruction, BuildStage, ParseError, ParseErrorCode, ParsedRunefile};
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
@@ -17,12 +18,13 @@ impl RunefileParser {
     }
 
     /// Parse Runefile content
+    ///
+    /// Returns `{ stages, errors }`, where `stages` holds every instruction
+    /// that parsed and `errors` the rest, with their line and column.
     #[wasm_bindgen]
     pub fn parse(&self, content: &str) -> String {
-        match Self::parse_content(content) {
-            Ok(parsed) => serde_json::to_string(&parsed).unwrap_or_else(|_| "null".to_string()),
-            Err(e) => serde_json::json!({ "error": e }).to_string(),
-        }
+        let (parsed, errors) = Self::parse_with_errors(content);
+        serde_json::json!({ "stages": parsed.stages, "errors": errors }).to_string()
     }
 
     /// Validate Runefile content
@@ -31,58 +33,43 @@ impl RunefileParser {
         let mut errors = Vec::new();
         let mut warnings = Vec::new();
 
-        match Self::parse_content(content) {
-            Ok(parsed) => {
-                if parsed.stages.is_empty() {
-                    errors.push(
-                        "Runefile must have at least one stage (FROM instruction)".to_string(),
-                    );
-                }
+        let (parsed, parse_errors) = Self::parse_with_errors(content);
+        errors.extend(parse_errors.iter().map(ParseError::to_string));
 
-                for (i, stage) in parsed.stages.iter().enumerate() {
-                    if stage.base_image.is_empty() {
-                        errors.push(format!("Stage {} has empty base image", i));
-                    }
+        for (i, stage) in parsed.stages.iter().enumerate() {
+            if stage.base_image.is_empty() {
+                errors.push(format!("Stage {} has empty base image", i));
+            }
 
-                    for instruction in &stage.instructions {
-                        match instruction {
-                            BuildInstruction::Copy { src, dest, .. } => {
-                                if src.is_empty() {
-                                    errors.push("COPY instruction has no source files".to_string());
-                                }
-                                if dest.is_empty() {
-                                    errors.push("COPY instruction has no destination".to_string());
-                                }
-                            }
-                            BuildInstruction::Add { src, dest, .. } => {
-                                if src.is_empty() {
-                                    errors.push("ADD instruction has no source files".to_string());
-                                }
-                                if dest.is_empty() {
-                                    errors.push("ADD instruction has no destination".to_string());
-                                }
-                            }
-                            BuildInstruction::Expose { port, .. } => {
-                                if *port == 0 {
-                                    warnings.push("EXPOSE port 0 is unusual".to_string());
-                                }
-                            }
-                            BuildInstruction::Workdir { path } => {
-                                if !path.starts_with('/') && !path.starts_with('$') {
-                                    warnings.push(format!(
-                                        "WORKDIR '{}' should be an absolute path",
-                                        path
-                                    ));
-                                }
-                            }
-                            _ => {}
+            for instruction in &stage.instructions {
+                match instruction {
+                    BuildInstruction::Copy { src, dest, .. } => {
+                        if src.is_empty() {
+                            errors.push("COPY instruction has no source files".to_string());
+                        }
+                        if dest.is_empty() {
+                            errors.push("COPY instruction has no destination".to_string());
+                        }
+                    }
+                    BuildInstruction::Add { src, dest, .. } => {
+                        if src.is_empty() {
+                            errors.push("ADD instruction has no source files".to_string());
                         }
+                        if dest.is_empty() {
+                            errors.push("ADD instruction has no destination".to_string());
+                        }
+                    }
+                    BuildInstruction::Expose { port, .. } if *port == 0 => {
+                        warnings.push("EXPOSE port 0 is unusual".to_string());
                     }
+                    BuildInstruction::Workdir { path }
+                        if !path.starts_with('/') && !path.starts_with('$') =>
+                    {
+                        warnings.push(format!("WORKDIR '{}' should be an absolute path", path));
+                    }
+                    _ => {}
                 }
             }
-            Err(e) => {
-                errors.push(e);
-            }
         }
 
         serde_json::json!({
@@ -106,25 +93,91 @@ impl Default for RunefileParser {
     }
 }
 
+/// Instruction, or the kind and message of what is wrong with it
+type InstructionResult = Result<BuildInstruction, (ParseErrorCode, String)>;
+
+/// Instructions whose arguments have variables expanded
+const EXPANDED_INSTRUCTIONS: [&str; 12] = [
+    "FROM",
+    "COPY",
+    "ADD",
+    "ENV",
+    "EXPOSE",
+    "WORKDIR",
+    "USER",
+    "VOLUME",
+    "LABEL",
+    "STOPSIGNAL",
+    "ARG",
+    "RUN",
+];
+
 impl RunefileParser {
     /// Parse Runefile content
+    ///
+    /// Variables are left as written; ARGs before the first FROM are
+    /// skipped.
     pub fn parse_content(content: &str) -> Result<ParsedRunefile, String> {
+        match Self::parse_lines(content, None) {
+            (parsed, errors) if errors.is_empty() => Ok(parsed),
+            (_, errors) => Err(errors[0].to_string()),
+        }
+    }
+
+    /// Parse Runefile content, carrying on past errors
+    ///
+    /// Returns the stages and instructions that parsed along with every
+    /// error.
+    pub fn parse_with_errors(content: &str) -> (ParsedRunefile, Vec<ParseError>) {
+        Self::parse_lines(content, None)
+    }
+
+    /// Parse Runefile content for a build, expanding ARG and ENV variables
+    /// with the given build args
+    ///
+    /// Also returns warnings about unused build args and undefined
+    /// variables.
+    pub fn parse_with_args(
+        content: &str,
+        build_args: &HashMap<String, String>,
+    ) -> Result<(ParsedRunefile, Vec<String>), String> {
+        let mut scope = ArgScope::new(build_args);
+        let (parsed, errors) = Self::parse_lines(content, Some(&mut scope));
+        match errors.first() {
+            Some(error) => Err(error.to_string()),
+            None => Ok((parsed, scope.warnings())),
+        }
+    }
+
+    fn parse_lines(
+        content: &str,
+        mut scope: Option<&mut ArgScope>,
+    ) -> (ParsedRunefile, Vec<ParseError>) {
         let mut stages = Vec::new();
+        let mut errors = Vec::new();
         let mut current_stage: Option<BuildStage> = None;
         let mut continued_line = String::new();
+        // Line and indentation of the instruction being read
+        let mut start: Option<(usize, usize)> = None;
 
-        for (line_num, line) in content.lines().enumerate() {
-            let line = line.trim();
+        for (line_num, raw_line) in content.lines().enumerate() {
+            let line = raw_line.trim();
 
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
 
-            if line.ends_with('\\') {
-                continued_line.push_str(&line[..line.len() - 1]);
+            let (start_line, indent) = *start.get_or_insert_with(|| {
+                let indent = raw_line.len() - raw_line.trim_start().len();
+                (line_num + 1, raw_line[..indent].chars().count())
+            });
+
+            if let Some(continued) = line.strip_suffix('\\') {
+                continued_line.push_str(continued);
                 continued_line.push(' ');
                 continue;
             }
+            start = None;
 
             let full_line = if !continued_line.is_empty() {
                 let result = format!("{}{}", continued_line, line);
@@ -134,13 +187,26 @@ impl RunefileParser {
                 line.to_string()
             };
 
-            let instruction = Self::parse_instruction(&full_line, line_num + 1)?;
+            let full_line = match scope.as_deref_mut() {
+                Some(scope) => Self::expand_line(scope, &full_line),
+                None => full_line,
+            };
+            let instruction = match Self::parse_instruction(&full_line, start_line, indent) {
+                Ok(instruction) => instruction,
+                Err(error) => {
+                    errors.push(error);
+                    continue;
+                }
+            };
 
             match instruction {
                 BuildInstruction::From { image, tag, alias } => {
                     if let Some(stage) = current_stage.take() {
                         stages.push(stage);
                     }
+                    if let Some(scope) = scope.as_deref_mut() {
+                        scope.start_stage();
+                    }
                     current_stage = Some(BuildStage {
                         name: alias,
                         base_image: image,
@@ -148,11 +214,24 @@ impl RunefileParser {
                         instructions: Vec::new(),
                     });
                 }
+                BuildInstruction::Arg { .. } if current_stage.is_none() => {}
                 _ => {
                     if let Some(ref mut stage) = current_stage {
+                        if let (Some(scope), BuildInstruction::Env { key, value }) =
+                            (scope.as_deref_mut(), &instruction)
+                        {
+                            scope.set_env(key, value);
+                        }
                         stage.instructions.push(instruction);
                     } else {
-                        return Err(format!("Line {}: Instruction before FROM", line_num + 1));
+                        let keyword = full_line.split_whitespace().next().unwrap_or_default();
+                        errors.push(ParseError {
+                            line: start_line,
+                            column: indent + 1,
+                            instruction: Some(keyword.to_uppercase()),
+                            code: ParseErrorCode::InstructionBeforeFrom,
+                            message: "Instruction before FROM".to_string(),
+                        });
                     }
                 }
             }
@@ -163,50 +242,101 @@ impl RunefileParser {
         }
 
         if stages.is_empty() {
-            return Err("No FROM instruction found".to_string());
+            errors.push(ParseError {
+                line: 0,
+                column: 0,
+                instruction: None,
+                code: ParseErrorCode::MissingFrom,
+                message: "No FROM instruction found".to_string(),
+            });
         }
 
-        Ok(ParsedRunefile { stages })
+        (ParsedRunefile { stages }, errors)
+    }
+
+    /// Expand the variables of a line, declaring its ARG in the scope
+    fn expand_line(scope: &mut ArgScope, line: &str) -> String {
+        let (keyword, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let upper = keyword.to_uppercase();
+        if !EXPANDED_INSTRUCTIONS.contains(&upper.as_str()) {
+            return line.to_string();
+        }
+        match upper.as_str() {
+            "ARG" => {
+                let args = args.trim();
+                let (name, default) = match args.split_once('=') {
+                    Some((name, default)) => (name.trim(), Some(default.trim())),
+                    None => (args, None),
+                };
+                match scope.declare(name, default) {
+                    Some(value) => format!("{} {}={}", keyword, name, value),
+                    None => format!("{} {}", keyword, name),
+                }
+            }
+            "RUN" => format!("{} {}", keyword, scope.expand_shell(args)),
+            _ => format!("{} {}", keyword, scope.expand(args)),
+        }
     }
 
-    /// Parse a single instruction
-    fn parse_instruction(line: &str, line_num: usize) -> Result<BuildInstruction, String> {
-        let parts: Vec<&str> = line.splitn(2, char::is_whitespace).collect();
-        let instruction = parts[0].to_uppercase();
-        let args = parts.get(1).map(|s| s.trim()).unwrap_or("");
+    /// Parse a single instruction starting at a line and column
+    fn parse_instruction(
+        line: &str,
+        line_num: usize,
+        indent: usize,
+    ) -> Result<BuildInstruction, ParseError> {
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let instruction = keyword.to_uppercase();
+        let args = rest.trim();
+        let args_offset = line.len() - rest.trim_start().len();
+        let error = |column: usize, (code, message): (ParseErrorCode, String)| ParseError {
+            line: line_num,
+            column: indent + 1 + column,
+            instruction: Some(instruction.clone()),
+            code,
+            message,
+        };
 
-        match instruction.as_str() {
-            "FROM" => Self::parse_from(args, line_num),
+        let parsed = match instruction.as_str() {
+            "FROM" => Self::parse_from(args),
             "RUN" => Self::parse_run(args),
             "COPY" => Self::parse_copy(args),
             "ADD" => Self::parse_add(args),
             "CMD" => Self::parse_cmd(args),
             "ENTRYPOINT" => Self::parse_entrypoint(args),
-            "ENV" => Self::parse_env(args, line_num),
+            "ENV" => Self::parse_env(args),
             "ARG" => Self::parse_arg(args),
             "WORKDIR" => Ok(BuildInstruction::Workdir {
                 path: args.to_string(),
             }),
             "USER" => Self::parse_user(args),
-            "EXPOSE" => Self::parse_expose(args, line_num),
+            "EXPOSE" => Self::parse_expose(args),
             "VOLUME" => Self::parse_volume(args),
             "LABEL" => Self::parse_label(args),
             "HEALTHCHECK" => Self::parse_healthcheck(args),
             "STOPSIGNAL" => Ok(BuildInstruction::Stopsignal {
                 signal: args.to_string(),
             }),
-            "SHELL" => Self::parse_shell(args, line_num),
-            _ => Err(format!(
-                "Line {}: Unknown instruction: {}",
-                line_num, instruction
-            )),
-        }
+            "SHELL" => Self::parse_shell(args),
+            _ => {
+                return Err(error(
+                    0,
+                    (
+                        ParseErrorCode::UnknownInstruction,
+                        format!("Unknown instruction: {}", instruction),
+                    ),
+                ))
+            }
+        };
+        parsed.map_err(|e| error(line[..args_offset].chars().count(), e))
     }
 
-    fn parse_from(args: &str, line_num: usize) -> Result<BuildInstruction, String> {
+    fn parse_from(args: &str) -> InstructionResult {
         let parts: Vec<&str> = args.split_whitespace().collect();
         if parts.is_empty() {
-            return Err(format!("Line {}: FROM requires an image", line_num));
+            return Err((
+                ParseErrorCode::MissingArgument,
+                "FROM requires an image".to_string(),
+            ));
         }
 
         let image_parts: Vec<&str> = parts[0].splitn(2, ':').collect();
@@ -222,7 +352,7 @@ impl RunefileParser {
         Ok(BuildInstruction::From { image, tag, alias })
     }
 
-    fn parse_run(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_run(args: &str) -> InstructionResult {
         if args.starts_with('[') {
             Ok(BuildInstruction::Run {
                 command: args.to_string(),
@@ -236,7 +366,7 @@ impl RunefileParser {
         }
     }
 
-    fn parse_copy(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_copy(args: &str) -> InstructionResult {
         let mut from = None;
         let mut chown = None;
         let mut remaining = args;
@@ -279,7 +409,7 @@ impl RunefileParser {
         })
     }
 
-    fn parse_add(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_add(args: &str) -> InstructionResult {
         let mut chown = None;
         let mut remaining = args;
 
@@ -307,7 +437,7 @@ impl RunefileParser {
         Ok(BuildInstruction::Add { src, dest, chown })
     }
 
-    fn parse_cmd(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_cmd(args: &str) -> InstructionResult {
         if args.starts_with('[') {
             let command: Vec<String> = serde_json::from_str(args).unwrap_or_default();
             Ok(BuildInstruction::Cmd {
@@ -322,7 +452,7 @@ impl RunefileParser {
         }
     }
 
-    fn parse_entrypoint(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_entrypoint(args: &str) -> InstructionResult {
         if args.starts_with('[') {
             let command: Vec<String> = serde_json::from_str(args).unwrap_or_default();
             Ok(BuildInstruction::Entrypoint {
@@ -337,7 +467,7 @@ impl RunefileParser {
         }
     }
 
-    fn parse_env(args: &str, line_num: usize) -> Result<BuildInstruction, String> {
+    fn parse_env(args: &str) -> InstructionResult {
         if let Some(eq_pos) = args.find('=') {
             let key = args[..eq_pos].trim().to_string();
             let value = args[eq_pos + 1..].trim().trim_matches('"').to_string();
@@ -345,7 +475,10 @@ impl RunefileParser {
         } else {
             let parts: Vec<&str> = args.splitn(2, char::is_whitespace).collect();
             if parts.len() < 2 {
-                return Err(format!("Line {}: ENV requires a key and value", line_num));
+                return Err((
+                    ParseErrorCode::MissingArgument,
+                    "ENV requires a key and value".to_string(),
+                ));
             }
             Ok(BuildInstruction::Env {
                 key: parts[0].to_string(),
@@ -354,7 +487,7 @@ impl RunefileParser {
         }
     }
 
-    fn parse_arg(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_arg(args: &str) -> InstructionResult {
         if let Some(eq_pos) = args.find('=') {
             Ok(BuildInstruction::Arg {
                 name: args[..eq_pos].trim().to_string(),
@@ -368,7 +501,7 @@ impl RunefileParser {
         }
     }
 
-    fn parse_user(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_user(args: &str) -> InstructionResult {
         let parts: Vec<&str> = args.splitn(2, ':').collect();
         Ok(BuildInstruction::User {
             user: parts[0].to_string(),
@@ -376,17 +509,20 @@ impl RunefileParser {
         })
     }
 
-    fn parse_expose(args: &str, line_num: usize) -> Result<BuildInstruction, String> {
+    fn parse_expose(args: &str) -> InstructionResult {
         let parts: Vec<&str> = args.split('/').collect();
-        let port: u16 = parts[0]
-            .parse()
-            .map_err(|_| format!("Line {}: Invalid port number: {}", line_num, parts[0]))?;
+        let port: u16 = parts[0].parse().map_err(|_| {
+            (
+                ParseErrorCode::InvalidPort,
+                format!("Invalid port number: {}", parts[0]),
+            )
+        })?;
         let protocol = parts.get(1).unwrap_or(&"tcp").to_string();
 
         Ok(BuildInstruction::Expose { port, protocol })
     }
 
-    fn parse_volume(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_volume(args: &str) -> InstructionResult {
         let paths = if args.starts_with('[') {
             serde_json::from_str(args).unwrap_or_default()
         } else {
@@ -396,7 +532,7 @@ impl RunefileParser {
         Ok(BuildInstruction::Volume { paths })
     }
 
-    fn parse_label(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_label(args: &str) -> InstructionResult {
         let mut labels = HashMap::new();
 
         for part in args.split_whitespace() {
@@ -410,7 +546,7 @@ impl RunefileParser {
         Ok(BuildInstruction::Label { labels })
     }
 
-    fn parse_healthcheck(args: &str) -> Result<BuildInstruction, String> {
+    fn parse_healthcheck(args: &str) -> InstructionResult {
         if args.trim().to_uppercase() == "NONE" {
             return Ok(BuildInstruction::Healthcheck {
                 cmd: None,
@@ -455,9 +591,13 @@ impl RunefileParser {
         })
     }
 
-    fn parse_shell(args: &str, line_num: usize) -> Result<BuildInstruction, String> {
-        let shell: Vec<String> = serde_json::from_str(args)
-            .map_err(|_| format!("Line {}: SHELL requires JSON array format", line_num))?;
+    fn parse_shell(args: &str) -> InstructionResult {
+        let shell: Vec<String> = serde_json::from_str(args).map_err(|_| {
+            (
+                ParseErrorCode::InvalidJson,
+                "SHELL requires JSON array format".to_string(),
+            )
+        })?;
 
         Ok(BuildInstruction::Shell { shell })
     }
@@ -507,6 +647,107 @@ CMD ["myapp"]
         assert_eq!(parsed.stages[1].base_image, "debian");
     }
 
+    #[test]
+    fn test_parse_with_args() {
+        let content = r#"
+ARG BASE=alpine
+ARG VERSION=3.18
+FROM ${BASE}:${VERSION} AS build
+ARG PORT=8080
+ARG VERSION
+ENV APP_HOME=/srv/$BASE
+WORKDIR $APP_HOME
+EXPOSE $PORT
+COPY app-$VERSION.tar.gz ${APP_HOME}/
+RUN echo $HOME $PORT
+"#;
+        let build_args: HashMap<String, String> = [("VERSION", "3.19"), ("DEBUG", "1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let (parsed, warnings) = RunefileParser::parse_with_args(content, &build_args).unwrap();
+        let stage = &parsed.stages[0];
+        assert_eq!(stage.base_image, "alpine");
+        assert_eq!(stage.base_tag, Some("3.19".to_string()));
+        assert!(matches!(
+            &stage.instructions[1],
+            BuildInstruction::Arg { name, default: Some(v) } if name == "VERSION" && v == "3.19"
+        ));
+        assert!(matches!(
+            &stage.instructions[2],
+            BuildInstruction::Env { value, .. } if value == "/srv/"
+        ));
+        assert!(matches!(
+            &stage.instructions[3],
+            BuildInstruction::Workdir { path } if path == "/srv/"
+        ));
+        assert!(matches!(
+            &stage.instructions[4],
+            BuildInstruction::Expose { port: 8080, .. }
+        ));
+        assert!(matches!(
+            &stage.instructions[5],
+            BuildInstruction::Copy { src, dest, .. } if src == &["app-3.19.tar.gz"] && dest == "/srv//"
+        ));
+        assert!(matches!(
+            &stage.instructions[6],
+            BuildInstruction::Run { command, .. } if command == "echo $HOME 8080"
+        ));
+        assert_eq!(
+            warnings,
+            vec![
+                "Build arg DEBUG is not declared by an ARG instruction".to_string(),
+                "Variable BASE is not defined".to_string(),
+            ]
+        );
+
+        // Without build args, global ARGs are accepted and variables kept
+        let parsed = RunefileParser::parse_content("ARG BASE\nFROM ${BASE}").unwrap();
+        assert_eq!(parsed.stages[0].base_image, "${BASE}");
+    }
+
+    #[test]
+    fn test_parse_with_errors() {
+        let content = "RUN early\n\
+                       FROM alpine\n\
+                       \x20 EXPOSE http\n\
+                       WORKDIR /app\n\
+                       FETCH https://example.com\n\
+                       SHELL sh \\\n\
+                       \x20 -c\n\
+                       CMD [\"app\"]\n";
+        let (parsed, errors) = RunefileParser::parse_with_errors(content);
+
+        // Instructions around the errors are kept
+        assert_eq!(parsed.stages.len(), 1);
+        assert_eq!(parsed.stages[0].instructions.len(), 2);
+
+        let found: Vec<(usize, usize, Option<&str>, ParseErrorCode)> = errors
+            .iter()
+            .map(|e| (e.line, e.column, e.instruction.as_deref(), e.code))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 1, Some("RUN"), ParseErrorCode::InstructionBeforeFrom),
+                (3, 10, Some("EXPOSE"), ParseErrorCode::InvalidPort),
+                (5, 1, Some("FETCH"), ParseErrorCode::UnknownInstruction),
+                (6, 7, Some("SHELL"), ParseErrorCode::InvalidJson),
+            ]
+        );
+        assert_eq!(errors[1].to_string(), "Line 3: Invalid port number: http");
+        assert_eq!(
+            RunefileParser::parse_content(content).unwrap_err(),
+            "Line 1: Instruction before FROM"
+        );
+
+        let (parsed, errors) = RunefileParser::parse_with_errors("# empty\n");
+        assert!(parsed.stages.is_empty());
+        assert_eq!(errors[0].code, ParseErrorCode::MissingFrom);
+        assert_eq!(errors[0].to_string(), "No FROM instruction found");
+    }
+
     #[test]
     fn test_default_build_file() {
         assert_eq!(RunefileParser::get_default_build_file(), "Runefile");
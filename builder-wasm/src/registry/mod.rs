@@ -0,0 +1,834 @@
+//! Registry client
+//!
+//! Pulls base images and pushes built ones over the OCI distribution API
+//! through a callback with the signature of `fetch`, so a page can send
+//! requests through a proxy when a registry does not allow cross-origin
+//! requests. When a registry answers 401, a bearer token is requested from
+//! the realm its challenge names, anonymously unless credentials are given.
+
+use crate::calculate_digest;
+use crate::layer::LayerBlob;
+use crate::oci::{
+    Descriptor, Index, Manifest, StoredImage, MEDIA_TYPE_INDEX, MEDIA_TYPE_LAYER,
+    MEDIA_TYPE_LAYER_GZIP, MEDIA_TYPE_MANIFEST,
+};
+use crate::types::{ContainerConfig, HistoryEntry, RootFs};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+/// Registry of images named without one
+pub const DOCKER_HUB: &str = "docker.io";
+/// Host serving the Docker Hub API
+const DOCKER_HUB_API: &str = "registry-1.docker.io";
+
+/// Media type of a Docker image manifest
+pub const MEDIA_TYPE_DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+/// Media type of a Docker manifest list
+pub const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+/// Media type of a gzipped Docker layer
+pub const MEDIA_TYPE_DOCKER_LAYER_GZIP: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+/// Image name split into registry, repository and tag or digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReference {
+    pub registry: String,
+    pub repository: String,
+    pub reference: String,
+}
+
+impl ImageReference {
+    /// Parse a name like `alpine`, `ghcr.io/org/app:1.0` or `app@sha256:...`
+    ///
+    /// The registry defaults to Docker Hub, where single-component names are
+    /// official images under `library/`, and the tag to `latest`.
+    pub fn parse(image: &str) -> Self {
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, digest.to_string()),
+            None => match image.rsplit_once(':') {
+                Some((name, tag)) if !tag.contains('/') => (name, tag.to_string()),
+                _ => (image, "latest".to_string()),
+            },
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, path))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), path.to_string())
+            }
+            _ => (DOCKER_HUB.to_string(), name.to_string()),
+        };
+        let repository = if registry == DOCKER_HUB && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+        Self {
+            registry,
+            repository,
+            reference,
+        }
+    }
+
+    /// URL of a manifest by tag or digest
+    pub fn manifest_url(&self, reference: &str) -> String {
+        format!(
+            "https://{}/v2/{}/manifests/{}",
+            self.api_host(),
+            self.repository,
+            reference
+        )
+    }
+
+    /// URL to start a blob upload at
+    pub fn upload_start_url(&self) -> String {
+        format!(
+            "https://{}/v2/{}/blobs/uploads/",
+            self.api_host(),
+            self.repository
+        )
+    }
+
+    /// URL of a blob
+    pub fn blob_url(&self, digest: &str) -> String {
+        format!(
+            "https://{}/v2/{}/blobs/{}",
+            self.api_host(),
+            self.repository,
+            digest
+        )
+    }
+
+    fn api_host(&self) -> &str {
+        if self.registry == DOCKER_HUB {
+            DOCKER_HUB_API
+        } else {
+            &self.registry
+        }
+    }
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.reference.contains(':') {
+            '@'
+        } else {
+            ':'
+        };
+        write!(
+            f,
+            "{}/{}{}{}",
+            self.registry, self.repository, separator, self.reference
+        )
+    }
+}
+
+/// Image pulled from a registry, to build on
+#[derive(Debug, Clone)]
+pub struct BaseImage {
+    /// Digest of the image config
+    pub config_digest: String,
+    pub config: ContainerConfig,
+    pub history: Vec<HistoryEntry>,
+    pub layers: Vec<LayerBlob>,
+}
+
+/// Image config as registries serve it, where most fields may be missing
+/// or null
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RemoteImageConfig {
+    config: Option<RemoteContainerConfig>,
+    rootfs: Option<RootFs>,
+    history: Option<Vec<RemoteHistoryEntry>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct RemoteContainerConfig {
+    user: Option<String>,
+    env: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    working_dir: Option<String>,
+    labels: Option<HashMap<String, String>>,
+    exposed_ports: Option<HashMap<String, serde_json::Value>>,
+    volumes: Option<HashMap<String, serde_json::Value>>,
+    stop_signal: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RemoteHistoryEntry {
+    created: Option<String>,
+    created_by: Option<String>,
+    empty_layer: Option<bool>,
+    comment: Option<String>,
+}
+
+impl BaseImage {
+    /// Read an image config, pairing its diff IDs with the layer blobs and
+    /// media types of the manifest
+    pub fn from_config(config: &[u8], layers: Vec<(String, Vec<u8>)>) -> Result<Self, String> {
+        let remote: RemoteImageConfig =
+            serde_json::from_slice(config).map_err(|e| format!("Invalid image config: {}", e))?;
+        let diff_ids = remote
+            .rootfs
+            .map(|rootfs| rootfs.diff_ids)
+            .unwrap_or_default();
+        if diff_ids.len() != layers.len() {
+            return Err(format!(
+                "Image config lists {} layers but the manifest has {}",
+                diff_ids.len(),
+                layers.len()
+            ));
+        }
+        let layers = layers
+            .into_iter()
+            .zip(diff_ids)
+            .map(|((media_type, content), diff_id)| {
+                Ok(LayerBlob {
+                    media_type: layer_media_type(&media_type)?,
+                    diff_id,
+                    content,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let defaults = ContainerConfig::default();
+        let remote_config = remote.config.unwrap_or_default();
+        Ok(Self {
+            config_digest: calculate_digest(config),
+            config: ContainerConfig {
+                hostname: defaults.hostname,
+                user: remote_config.user.unwrap_or_default(),
+                env: remote_config.env.unwrap_or_default(),
+                cmd: remote_config.cmd.unwrap_or_default(),
+                entrypoint: remote_config.entrypoint.unwrap_or_default(),
+                working_dir: remote_config.working_dir.unwrap_or_default(),
+                labels: remote_config.labels.unwrap_or_default(),
+                exposed_ports: remote_config.exposed_ports.unwrap_or_default(),
+                volumes: remote_config.volumes.unwrap_or_default(),
+                stop_signal: remote_config
+                    .stop_signal
+                    .filter(|signal| !signal.is_empty())
+                    .unwrap_or(defaults.stop_signal),
+            },
+            history: remote
+                .history
+                .unwrap_or_default()
+                .into_iter()
+                .map(|entry| HistoryEntry {
+                    created: entry.created.unwrap_or_default(),
+                    created_by: entry.created_by.unwrap_or_default(),
+                    empty_layer: entry.empty_layer.unwrap_or(false),
+                    comment: entry.comment,
+                })
+                .collect(),
+            layers,
+        })
+    }
+}
+
+/// OCI media type of a pulled layer
+fn layer_media_type(media_type: &str) -> Result<&'static str, String> {
+    match media_type {
+        MEDIA_TYPE_LAYER_GZIP | MEDIA_TYPE_DOCKER_LAYER_GZIP => Ok(MEDIA_TYPE_LAYER_GZIP),
+        MEDIA_TYPE_LAYER => Ok(MEDIA_TYPE_LAYER),
+        other => Err(format!("Unsupported layer media type: {}", other)),
+    }
+}
+
+/// Manifest of an index for a platform
+pub fn select_manifest<'a>(
+    index: &'a Index,
+    os: &str,
+    architecture: &str,
+) -> Option<&'a Descriptor> {
+    index.manifests.iter().find(|descriptor| {
+        descriptor
+            .platform
+            .as_ref()
+            .is_some_and(|p| p.os == os && p.architecture == architecture)
+    })
+}
+
+/// Parameters of a `Bearer` WWW-Authenticate challenge
+pub fn parse_challenge(header: &str) -> Option<HashMap<String, String>> {
+    let (scheme, params) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut values = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        values.insert(key, value.to_string());
+        rest = after.trim_start_matches(',').trim();
+    }
+    Some(values)
+}
+
+/// URL to request a token for some actions on a repository from, for a
+/// challenge
+pub fn token_url(challenge: &str, repository: &str, actions: &str) -> Option<String> {
+    let params = parse_challenge(challenge)?;
+    let realm = params.get("realm")?;
+    let scope = format!("repository:{}:{}", repository, actions);
+    let mut url = format!("{}?scope={}", realm, encode_query(&scope));
+    if let Some(service) = params.get("service") {
+        url.push_str(&format!("&service={}", encode_query(service)));
+    }
+    Some(url)
+}
+
+/// URL to finish a blob upload at, from the `Location` of the upload
+pub fn upload_url(image: &ImageReference, location: &str, digest: &str) -> String {
+    let url = if location.starts_with("https://") || location.starts_with("http://") {
+        location.to_string()
+    } else {
+        format!("https://{}{}", image.api_host(), location)
+    };
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}digest={}", url, separator, encode_query(digest))
+}
+
+/// Percent-encode a query value
+fn encode_query(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Standard base64, for Basic credentials
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Credentials for a registry
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RegistryAuth {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Bearer token sent as is, instead of one requested with the username
+    /// and password
+    pub token: Option<String>,
+}
+
+impl RegistryAuth {
+    /// `Authorization` value for the username and password
+    pub fn basic(&self) -> Option<String> {
+        let credentials = format!(
+            "{}:{}",
+            self.username.as_ref()?,
+            self.password.as_deref().unwrap_or_default()
+        );
+        Some(format!("Basic {}", base64(credentials.as_bytes())))
+    }
+}
+
+/// Authorization for the requests of one pull or push
+struct Session<'a> {
+    image: &'a ImageReference,
+    /// Actions tokens are requested for, `pull` or `pull,push`
+    actions: &'static str,
+    authorization: Option<String>,
+}
+
+/// Pulls and pushes images through a `fetch`-like callback
+pub struct RegistryClient {
+    fetch: js_sys::Function,
+    auth: RegistryAuth,
+}
+
+impl RegistryClient {
+    /// Use a callback with the signature of `fetch(url, init)`
+    pub fn new(fetch: js_sys::Function) -> Self {
+        Self {
+            fetch,
+            auth: RegistryAuth::default(),
+        }
+    }
+
+    /// Authenticate with credentials instead of anonymously
+    pub fn with_auth(mut self, auth: RegistryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Pull the manifest, config and layers of an image for a platform
+    pub async fn pull(
+        &self,
+        image: &ImageReference,
+        os: &str,
+        architecture: &str,
+    ) -> Result<BaseImage, String> {
+        let accept = [
+            MEDIA_TYPE_MANIFEST,
+            MEDIA_TYPE_INDEX,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+        ]
+        .join(", ");
+        let mut session = self.session(image, "pull");
+
+        let mut manifest = self
+            .get(&mut session, &image.manifest_url(&image.reference), &accept)
+            .await?;
+        let is_index = serde_json::from_slice::<serde_json::Value>(&manifest)
+            .map_err(|e| format!("Invalid manifest: {}", e))?
+            .get("manifests")
+            .is_some();
+        if is_index {
+            let index: Index = serde_json::from_slice(&manifest)
+                .map_err(|e| format!("Invalid image index: {}", e))?;
+            let descriptor = select_manifest(&index, os, architecture)
+                .ok_or_else(|| format!("{} has no {}/{} image", image, os, architecture))?;
+            let url = image.manifest_url(&descriptor.digest);
+            manifest = self.get(&mut session, &url, &accept).await?;
+            verify(&manifest, &descriptor.digest)?;
+        }
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).map_err(|e| format!("Invalid manifest: {}", e))?;
+
+        let config = self.blob(&mut session, &manifest.config).await?;
+        let mut layers = Vec::new();
+        for descriptor in &manifest.layers {
+            let content = self.blob(&mut session, descriptor).await?;
+            layers.push((descriptor.media_type.clone(), content));
+        }
+        BaseImage::from_config(&config, layers)
+    }
+
+    /// Upload the blobs and manifest of an image, returning the manifest
+    /// digest
+    ///
+    /// `progress` is told the number of blobs done, the total, the digest of
+    /// the blob just handled and whether the registry already had it.
+    pub async fn push<P>(
+        &self,
+        image: &ImageReference,
+        stored: &StoredImage,
+        progress: P,
+    ) -> Result<String, String>
+    where
+        P: Fn(usize, usize, &str, bool),
+    {
+        let mut session = self.session(image, "pull,push");
+        let mut blobs: Vec<(String, &[u8])> = Vec::new();
+        for content in
+            std::iter::once(&stored.config).chain(stored.layers.iter().map(|layer| &layer.content))
+        {
+            let digest = calculate_digest(content);
+            if !blobs.iter().any(|(existing, _)| *existing == digest) {
+                blobs.push((digest, content));
+            }
+        }
+
+        for (done, (digest, content)) in blobs.iter().enumerate() {
+            let existed = self.upload_blob(&mut session, digest, content).await?;
+            progress(done + 1, blobs.len(), digest, existed);
+        }
+
+        let url = image.manifest_url(&image.reference);
+        let response = self
+            .send(
+                &mut session,
+                "PUT",
+                &url,
+                &[("Content-Type", MEDIA_TYPE_MANIFEST)],
+                Some(&stored.manifest),
+            )
+            .await?;
+        expect_ok(&response, "PUT", &url)?;
+        Ok(calculate_digest(&stored.manifest))
+    }
+
+    fn session<'a>(&self, image: &'a ImageReference, actions: &'static str) -> Session<'a> {
+        Session {
+            image,
+            actions,
+            authorization: self
+                .auth
+                .token
+                .as_ref()
+                .map(|token| format!("Bearer {}", token)),
+        }
+    }
+
+    /// Upload a blob unless the registry has it, returning whether it did
+    async fn upload_blob(
+        &self,
+        session: &mut Session<'_>,
+        digest: &str,
+        content: &[u8],
+    ) -> Result<bool, String> {
+        let url = session.image.blob_url(digest);
+        if self.send(session, "HEAD", &url, &[], None).await?.ok() {
+            return Ok(true);
+        }
+
+        let url = session.image.upload_start_url();
+        let response = self.send(session, "POST", &url, &[], None).await?;
+        expect_ok(&response, "POST", &url)?;
+        let location = header(&response, "location")
+            .ok_or_else(|| format!("POST {} returned no upload location", url))?;
+
+        let url = upload_url(session.image, &location, digest);
+        let response = self
+            .send(
+                session,
+                "PUT",
+                &url,
+                &[("Content-Type", "application/octet-stream")],
+                Some(content),
+            )
+            .await?;
+        expect_ok(&response, "PUT", &url)?;
+        Ok(false)
+    }
+
+    /// Download a blob and check its digest
+    async fn blob(
+        &self,
+        session: &mut Session<'_>,
+        descriptor: &Descriptor,
+    ) -> Result<Vec<u8>, String> {
+        let url = session.image.blob_url(&descriptor.digest);
+        let content = self.get(session, &url, "*/*").await?;
+        verify(&content, &descriptor.digest)?;
+        Ok(content)
+    }
+
+    /// GET a URL and read the body
+    async fn get(
+        &self,
+        session: &mut Session<'_>,
+        url: &str,
+        accept: &str,
+    ) -> Result<Vec<u8>, String> {
+        let response = self
+            .send(session, "GET", url, &[("Accept", accept)], None)
+            .await?;
+        expect_ok(&response, "GET", url)?;
+        self.body(response).await
+    }
+
+    /// Send a request, authorizing and retrying once when challenged
+    async fn send(
+        &self,
+        session: &mut Session<'_>,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<web_sys::Response, String> {
+        let response = self
+            .fetch(method, url, headers, body, session.authorization.as_deref())
+            .await?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+        let challenge = header(&response, "www-authenticate").unwrap_or_default();
+        session.authorization = Some(
+            self.authorize(session, &challenge)
+                .await?
+                .ok_or_else(|| format!("Unauthorized: {}", url))?,
+        );
+        self.fetch(method, url, headers, body, session.authorization.as_deref())
+            .await
+    }
+
+    /// `Authorization` value answering a challenge
+    async fn authorize(
+        &self,
+        session: &Session<'_>,
+        challenge: &str,
+    ) -> Result<Option<String>, String> {
+        let is_basic = challenge
+            .trim()
+            .get(..6)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("basic "));
+        if is_basic {
+            return Ok(self.auth.basic());
+        }
+        let Some(token_url) = token_url(challenge, &session.image.repository, session.actions)
+        else {
+            return Ok(None);
+        };
+        let basic = self.auth.basic();
+        let response = self
+            .fetch(
+                "GET",
+                &token_url,
+                &[("Accept", "application/json")],
+                None,
+                basic.as_deref(),
+            )
+            .await?;
+        expect_ok(&response, "GET", &token_url)?;
+        let body = self.body(response).await?;
+        let body: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|e| format!("Invalid token response: {}", e))?;
+        Ok(body
+            .get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(|token| token.as_str())
+            .map(|token| format!("Bearer {}", token)))
+    }
+
+    async fn fetch(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        authorization: Option<&str>,
+    ) -> Result<web_sys::Response, String> {
+        let header_values = js_sys::Object::new();
+        let set = |target: &js_sys::Object, key: &str, value: &JsValue| {
+            let _ = js_sys::Reflect::set(target, &key.into(), value);
+        };
+        for (key, value) in headers {
+            set(&header_values, key, &(*value).into());
+        }
+        if let Some(authorization) = authorization {
+            set(&header_values, "Authorization", &authorization.into());
+        }
+        let init = js_sys::Object::new();
+        set(&init, "method", &method.into());
+        set(&init, "headers", &header_values);
+        if let Some(body) = body {
+            set(&init, "body", &js_sys::Uint8Array::from(body));
+        }
+
+        let promise = self
+            .fetch
+            .call2(&JsValue::null(), &url.into(), &init)
+            .map_err(|e| format!("fetch {} failed: {:?}", url, e))?;
+        let response = JsFuture::from(js_sys::Promise::resolve(&promise))
+            .await
+            .map_err(|e| format!("fetch {} failed: {:?}", url, e))?;
+        response
+            .dyn_into::<web_sys::Response>()
+            .map_err(|_| format!("fetch {} did not resolve to a Response", url))
+    }
+
+    async fn body(&self, response: web_sys::Response) -> Result<Vec<u8>, String> {
+        let buffer = response
+            .array_buffer()
+            .map_err(|e| format!("Failed to read response: {:?}", e))?;
+        let buffer = JsFuture::from(buffer)
+            .await
+            .map_err(|e| format!("Failed to read response: {:?}", e))?;
+        Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+    }
+}
+
+/// Value of a response header
+fn header(response: &web_sys::Response, name: &str) -> Option<String> {
+    response.headers().get(name).ok().flatten()
+}
+
+/// Fail unless a response has a success status
+fn expect_ok(response: &web_sys::Response, method: &str, url: &str) -> Result<(), String> {
+    if response.ok() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} {} failed with status {}",
+            method,
+            url,
+            response.status()
+        ))
+    }
+}
+
+/// Check downloaded content against the digest it was requested by
+fn verify(content: &[u8], digest: &str) -> Result<(), String> {
+    if calculate_digest(content) == digest {
+        Ok(())
+    } else {
+        Err(format!("Digest mismatch for {}", digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::Platform;
+
+    #[test]
+    fn test_image_reference() {
+        let alpine = ImageReference::parse("alpine");
+        assert_eq!(alpine.registry, "docker.io");
+        assert_eq!(alpine.repository, "library/alpine");
+        assert_eq!(alpine.reference, "latest");
+        assert_eq!(
+            alpine.manifest_url("latest"),
+            "https://registry-1.docker.io/v2/library/alpine/manifests/latest"
+        );
+        assert_eq!(alpine.to_string(), "docker.io/library/alpine:latest");
+
+        let app = ImageReference::parse("localhost:5000/team/app:1.0");
+        assert_eq!(app.registry, "localhost:5000");
+        assert_eq!(app.repository, "team/app");
+        assert_eq!(app.reference, "1.0");
+        assert_eq!(
+            app.blob_url("sha256:abc"),
+            "https://localhost:5000/v2/team/app/blobs/sha256:abc"
+        );
+
+        let pinned = ImageReference::parse("org/tool@sha256:abc");
+        assert_eq!(pinned.repository, "org/tool");
+        assert_eq!(pinned.reference, "sha256:abc");
+        assert_eq!(pinned.to_string(), "docker.io/org/tool@sha256:abc");
+    }
+
+    #[test]
+    fn test_token_url() {
+        let challenge = r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull""#;
+        let params = parse_challenge(challenge).unwrap();
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(
+            token_url(challenge, "library/alpine", "pull").unwrap(),
+            "https://auth.docker.io/token?scope=repository:library/alpine:pull&service=registry.docker.io"
+        );
+        assert_eq!(
+            token_url(
+                r#"Bearer realm="https://ghcr.io/token""#,
+                "org/app a",
+                "pull,push"
+            )
+            .unwrap(),
+            "https://ghcr.io/token?scope=repository:org/app%20a:pull%2Cpush"
+        );
+        assert!(token_url(r#"Basic realm="registry""#, "app", "pull").is_none());
+    }
+
+    #[test]
+    fn test_upload_url() {
+        let app = ImageReference::parse("ghcr.io/org/app:1.0");
+        assert_eq!(
+            app.upload_start_url(),
+            "https://ghcr.io/v2/org/app/blobs/uploads/"
+        );
+        assert_eq!(
+            upload_url(&app, "/v2/org/app/blobs/uploads/abc", "sha256:123"),
+            "https://ghcr.io/v2/org/app/blobs/uploads/abc?digest=sha256:123"
+        );
+        assert_eq!(
+            upload_url(&app, "https://uploads.example.com/x?state=1", "sha256:123"),
+            "https://uploads.example.com/x?state=1&digest=sha256:123"
+        );
+    }
+
+    #[test]
+    fn test_registry_auth() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        let auth: RegistryAuth =
+            serde_json::from_str(r#"{"username": "user", "password": "pass"}"#).unwrap();
+        assert_eq!(auth.basic().unwrap(), "Basic dXNlcjpwYXNz");
+        assert!(RegistryAuth::default().basic().is_none());
+    }
+
+    #[test]
+    fn test_select_manifest() {
+        let descriptor = |digest: &str, architecture: &str| Descriptor {
+            digest: digest.to_string(),
+            platform: Some(Platform {
+                architecture: architecture.to_string(),
+                os: "linux".to_string(),
+                variant: None,
+            }),
+            ..Descriptor::new(MEDIA_TYPE_MANIFEST, b"")
+        };
+        let index = Index {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_INDEX.to_string(),
+            manifests: vec![
+                descriptor("sha256:arm", "arm64"),
+                descriptor("sha256:x86", "amd64"),
+            ],
+        };
+        assert_eq!(
+            select_manifest(&index, "linux", "amd64").unwrap().digest,
+            "sha256:x86"
+        );
+        assert!(select_manifest(&index, "windows", "amd64").is_none());
+    }
+
+    #[test]
+    fn test_base_image() {
+        let layer = b"layer".to_vec();
+        let config = format!(
+            r#"{{
+                "architecture": "amd64",
+                "os": "linux",
+                "config": {{"Env": ["PATH=/bin"], "Cmd": ["/bin/sh"], "Labels": null}},
+                "rootfs": {{"type": "layers", "diff_ids": ["{}"]}},
+                "history": [{{"created_by": "ADD rootfs /"}}]
+            }}"#,
+            calculate_digest(&layer)
+        );
+        let base = BaseImage::from_config(
+            config.as_bytes(),
+            vec![(MEDIA_TYPE_DOCKER_LAYER_GZIP.to_string(), layer.clone())],
+        )
+        .unwrap();
+        assert_eq!(base.config.env, vec!["PATH=/bin"]);
+        assert_eq!(base.config.cmd, vec!["/bin/sh"]);
+        assert!(base.config.labels.is_empty());
+        assert_eq!(base.config.stop_signal, "SIGTERM");
+        assert_eq!(base.history[0].created_by, "ADD rootfs /");
+        assert_eq!(base.layers[0].media_type, MEDIA_TYPE_LAYER_GZIP);
+        assert_eq!(base.layers[0].diff_id, calculate_digest(&layer));
+        assert_eq!(base.config_digest, calculate_digest(config.as_bytes()));
+
+        assert!(BaseImage::from_config(config.as_bytes(), Vec::new()).is_err());
+        assert!(BaseImage::from_config(
+            config.as_bytes(),
+            vec![("application/zstd".to_string(), layer)]
+        )
+        .is_err());
+    }
+}
@@ -92,9 +92,45 @@ pub struct ParsedRunefile {
     pub stages: Vec<BuildStage>,
 }
 
+/// Kind of a Runefile parse error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParseErrorCode {
+    UnknownInstruction,
+    InstructionBeforeFrom,
+    MissingFrom,
+    MissingArgument,
+    InvalidPort,
+    InvalidJson,
+}
+
+/// Error found while parsing a Runefile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseError {
+    /// 1-based line the instruction starts on, `0` for the whole file
+    pub line: usize,
+    /// 1-based column of the offending text, `0` for the whole file
+    pub column: usize,
+    /// Instruction keyword, upper case
+    pub instruction: Option<String>,
+    pub code: ParseErrorCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "Line {}: {}", self.line, self.message)
+        }
+    }
+}
+
 /// Build configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct BuildConfig {
     pub context_dir: String,
     pub build_file: Option<String>,
@@ -103,6 +139,22 @@ pub struct BuildConfig {
     pub target: Option<String>,
     pub no_cache: bool,
     pub labels: HashMap<String, String>,
+    /// Directory the OCI image layout is written to, `.rune/oci` in the
+    /// context by default
+    pub output_dir: Option<String>,
+    /// Gzip layer blobs, on by default; turn off for faster builds
+    pub compress_layers: bool,
+    /// Directory of the build cache, `.rune/cache` in the context by default
+    pub cache_dir: Option<String>,
+    /// Operating system of the image, `linux` by default
+    pub os: String,
+    /// CPU architecture of the image, `amd64` by default
+    pub architecture: String,
+    /// CPU variant of the image, such as `v8` for `arm64`
+    pub variant: Option<String>,
+    /// RFC 3339 timestamp recorded in the image and its history, the
+    /// current time by default; fix it for reproducible builds
+    pub created: Option<String>,
 }
 
 impl Default for BuildConfig {
@@ -115,16 +167,42 @@ impl Default for BuildConfig {
             target: None,
             no_cache: false,
             labels: HashMap::new(),
+            output_dir: None,
+            compress_layers: true,
+            cache_dir: None,
+            os: "linux".to_string(),
+            architecture: "amd64".to_string(),
+            variant: None,
+            created: None,
         }
     }
 }
 
+impl BuildConfig {
+    /// Directory the OCI image layout is written to
+    pub fn output_dir(&self) -> String {
+        self.output_dir
+            .clone()
+            .unwrap_or_else(|| format!("{}/.rune/oci", self.context_dir.trim_end_matches('/')))
+    }
+
+    /// Build cache directory
+    pub fn cache_dir(&self) -> String {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| format!("{}/.rune/cache", self.context_dir.trim_end_matches('/')))
+    }
+}
+
 /// Image layer
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageLayer {
     pub id: String,
+    /// Digest of the stored blob, compressed when compression is on
     pub digest: String,
+    /// Digest of the uncompressed tar
+    pub diff_id: String,
     pub size: u64,
     pub created_by: String,
     pub empty_layer: bool,
@@ -135,19 +213,55 @@ pub struct ImageLayer {
 #[serde(rename_all = "camelCase")]
 pub struct BuildResult {
     pub success: bool,
+    /// Whether the build was stopped by `cancel()`
+    pub cancelled: bool,
     pub image_id: Option<String>,
+    /// Digest of the image manifest
+    pub manifest_digest: Option<String>,
+    /// Directory holding the OCI image layout
+    pub output_dir: Option<String>,
     pub layers: Vec<ImageLayer>,
     pub config: Option<ImageConfig>,
     pub errors: Vec<String>,
     pub warnings: Vec<String>,
 }
 
+impl BuildResult {
+    /// Result of a build that failed before producing an image
+    pub fn failed(error: String) -> Self {
+        Self {
+            success: false,
+            cancelled: false,
+            image_id: None,
+            manifest_digest: None,
+            output_dir: None,
+            layers: Vec::new(),
+            config: None,
+            errors: vec![error],
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Result of a build stopped before it wrote an image
+    pub fn cancelled(warnings: Vec<String>) -> Self {
+        Self {
+            cancelled: true,
+            warnings,
+            ..Self::failed("Build cancelled".to_string())
+        }
+    }
+}
+
 /// Image configuration (OCI config)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImageConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
     pub architecture: String,
     pub os: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
     pub config: ContainerConfig,
     pub rootfs: RootFs,
     pub history: Vec<HistoryEntry>,
@@ -221,6 +335,10 @@ pub enum BuildEvent {
         step: usize,
         layer_id: Option<String>,
     },
+    StepCached {
+        step: usize,
+        layer_id: Option<String>,
+    },
     StageComplete {
         stage: usize,
     },
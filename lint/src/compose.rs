@@ -0,0 +1,660 @@
+//! Compose files
+//!
+//! Completion, hover and validation for `compose.yaml`, shared by the
+//! language servers. Keys are located from the indentation of the text, so
+//! completion works while the YAML is incomplete; validation reads the
+//! parsed YAML. Columns are byte offsets.
+
+use crate::ranges::Span;
+use crate::Severity;
+use serde_yaml::Value;
+
+/// Compose file names
+pub const COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Top-level keys of a compose file
+const TOP_LEVEL_KEYS: &[(&str, &str)] = &[
+    (
+        "services",
+        "The containers that make up the application, by name.",
+    ),
+    ("networks", "Networks services can be attached to."),
+    ("volumes", "Named volumes services can mount."),
+    (
+        "secrets",
+        "Secrets services can be granted, from files or the environment.",
+    ),
+    ("configs", "Configuration files services can mount."),
+    (
+        "name",
+        "Project name, used to prefix containers, networks and volumes.",
+    ),
+    (
+        "version",
+        "Compose file format version. Informational only.",
+    ),
+];
+
+/// Keys of a service
+const SERVICE_KEYS: &[(&str, &str)] = &[
+    ("image", "Image to start the container from."),
+    (
+        "build",
+        "Build the image from a Runefile, given a context path or build options.",
+    ),
+    ("command", "Overrides the image's default command (CMD)."),
+    (
+        "entrypoint",
+        "Overrides the image's entrypoint (ENTRYPOINT).",
+    ),
+    (
+        "container_name",
+        "Custom container name instead of the generated one.",
+    ),
+    ("hostname", "Hostname of the container."),
+    (
+        "environment",
+        "Environment variables, as a map or a list of `KEY=value`.",
+    ),
+    ("env_file", "Files to read environment variables from."),
+    (
+        "expose",
+        "Ports exposed to linked services, without publishing them.",
+    ),
+    (
+        "ports",
+        "Published ports, as `[[ip:]host:]container[/protocol]`.",
+    ),
+    (
+        "volumes",
+        "Bind mounts and named volumes, as `source:target[:mode]`.",
+    ),
+    ("networks", "Networks the service is attached to."),
+    ("depends_on", "Services that must start before this one."),
+    (
+        "deploy",
+        "Replicas, resources and placement for swarm deployments.",
+    ),
+    (
+        "healthcheck",
+        "Check run to determine whether the container is healthy.",
+    ),
+    ("labels", "Metadata labels added to the container."),
+    ("logging", "Logging driver and options."),
+    (
+        "restart",
+        "Restart policy: `no`, `always`, `on-failure` or `unless-stopped`.",
+    ),
+    ("working_dir", "Working directory of the command."),
+    ("user", "User the command runs as."),
+    ("privileged", "Give the container extended privileges."),
+    ("read_only", "Mount the root filesystem read-only."),
+    ("stdin_open", "Keep stdin open."),
+    ("tty", "Allocate a pseudo-TTY."),
+    ("stop_signal", "Signal sent to stop the container."),
+    (
+        "stop_grace_period",
+        "Time to wait after the stop signal before killing.",
+    ),
+    ("sysctls", "Kernel parameters set in the container."),
+    ("ulimits", "Resource limits of the container's processes."),
+    (
+        "extra_hosts",
+        "Additional `/etc/hosts` entries, as `host:ip`.",
+    ),
+    ("dns", "Custom DNS servers."),
+    ("dns_search", "Custom DNS search domains."),
+    ("cap_add", "Linux capabilities to add."),
+    ("cap_drop", "Linux capabilities to drop."),
+    (
+        "security_opt",
+        "Security options such as seccomp or AppArmor profiles.",
+    ),
+    ("secrets", "Secrets granted to the service."),
+    ("configs", "Configs mounted into the service."),
+    ("devices", "Host devices mapped into the container."),
+    (
+        "init",
+        "Run an init process that forwards signals and reaps processes.",
+    ),
+];
+
+/// Keys of a service's `build`
+const BUILD_KEYS: &[(&str, &str)] = &[
+    (
+        "context",
+        "Directory sent to the builder, relative to the compose file.",
+    ),
+    (
+        "dockerfile",
+        "Build file, relative to the context. Defaults to Runefile, then Dockerfile.",
+    ),
+    ("args", "Build arguments passed to ARG instructions."),
+    ("target", "Stage to build in a multi-stage Runefile."),
+    ("cache_from", "Images used as cache sources."),
+    ("cache_to", "Where to export the build cache."),
+    (
+        "extra_hosts",
+        "Additional `/etc/hosts` entries during the build.",
+    ),
+    ("labels", "Labels added to the image."),
+    ("network", "Network RUN instructions use."),
+    ("ssh", "SSH agent sockets or keys exposed to the build."),
+    ("secrets", "Secrets exposed to the build."),
+    ("tags", "Additional tags for the image."),
+    ("platforms", "Platforms to build for."),
+    ("privileged", "Run the build with extended privileges."),
+    ("no_cache", "Build without using the cache."),
+    ("pull", "Always pull newer versions of base images."),
+];
+
+/// Keys of a service's `healthcheck`
+const HEALTHCHECK_KEYS: &[(&str, &str)] = &[
+    (
+        "test",
+        "Command to run, e.g. `[\"CMD\", \"curl\", \"-f\", \"http://localhost\"]`.",
+    ),
+    ("interval", "Time between checks."),
+    ("timeout", "Time after which a check is considered failed."),
+    (
+        "retries",
+        "Consecutive failures needed to report unhealthy.",
+    ),
+    (
+        "start_period",
+        "Initialization time during which failures do not count.",
+    ),
+    ("disable", "Disable the image's healthcheck."),
+];
+
+/// Keys of a service's `deploy`
+const DEPLOY_KEYS: &[(&str, &str)] = &[
+    ("mode", "`replicated` or `global`."),
+    ("replicas", "Number of containers to run."),
+    ("labels", "Labels added to the service."),
+    (
+        "placement",
+        "Constraints and preferences for where containers run.",
+    ),
+    ("resources", "CPU and memory limits and reservations."),
+    (
+        "restart_policy",
+        "When and how often to restart containers.",
+    ),
+    ("update_config", "How rolling updates are applied."),
+];
+
+/// Values of `restart`
+const RESTART_POLICIES: &[&str] = &["no", "always", "on-failure", "unless-stopped"];
+
+/// Values of a `depends_on` condition
+const DEPENDS_ON_CONDITIONS: &[&str] = &[
+    "service_started",
+    "service_healthy",
+    "service_completed_successfully",
+];
+
+/// What a completion completes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// A key, inserted with its colon
+    Key,
+    /// A service name
+    Service,
+    /// A known value of a key
+    Value,
+}
+
+/// A completion for a compose file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// Documentation of a key
+    pub documentation: Option<&'static str>,
+}
+
+/// A problem found in a compose file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Whether a document URI names a compose file
+pub fn is_compose_file(uri: &str) -> bool {
+    let name = uri.rsplit('/').next().unwrap_or(uri);
+    COMPOSE_FILES.contains(&name)
+        || name.ends_with(".compose.yaml")
+        || name.ends_with(".compose.yml")
+}
+
+/// Get completions for keys, service names and known values
+pub fn completions(content: &str, line: usize, column: usize) -> Vec<Completion> {
+    let lines: Vec<&str> = content.lines().collect();
+    let text = lines.get(line).copied().unwrap_or("");
+    let before = &text[..column.min(text.len())];
+    let item = before.trim_start().trim_start_matches("- ");
+    let path = key_path(&lines, line, indent(before));
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    let completion = |label: &str, kind, documentation| Completion {
+        label: label.to_string(),
+        kind,
+        documentation,
+    };
+
+    // After `key:` complete the value
+    if let Some((key, _)) = item.split_once(':').filter(|(key, _)| is_key(key)) {
+        let values = match key {
+            "restart" => RESTART_POLICIES,
+            "condition" => DEPENDS_ON_CONDITIONS,
+            _ => &[],
+        };
+        return values
+            .iter()
+            .map(|value| completion(value, CompletionKind::Value, None))
+            .collect();
+    }
+
+    match path.as_slice() {
+        ["services", service, "depends_on", ..] => service_names(&lines)
+            .into_iter()
+            .filter(|name| name != service)
+            .map(|name| completion(&name, CompletionKind::Service, None))
+            .collect(),
+        _ => keys_for(&path)
+            .unwrap_or_default()
+            .iter()
+            .map(|(key, documentation)| completion(key, CompletionKind::Key, Some(*documentation)))
+            .collect(),
+    }
+}
+
+/// Get markdown documentation for the key at a position, with the key's span
+pub fn hover(content: &str, line: usize, column: usize) -> Option<(String, Span)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let text = lines.get(line)?;
+    let key = line_key(text)?;
+    let start = text.find(key)?;
+    if column < start || column > start + key.len() {
+        return None;
+    }
+
+    let path = key_path(&lines, line, indent(text));
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    let (_, documentation) = keys_for(&path)?.iter().find(|(name, _)| *name == key)?;
+    Some((
+        format!("**{}**\n\n{}", key, documentation),
+        line_span(line, start, start + key.len()),
+    ))
+}
+
+/// Validate a compose file
+///
+/// Reports YAML errors, services without an image or build, `depends_on`
+/// entries naming unknown services and malformed ports.
+pub fn validate(content: &str) -> Vec<Problem> {
+    let document: Value = match serde_yaml::from_str(content) {
+        Ok(document) => document,
+        Err(e) => return vec![yaml_error(content, &e)],
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let Some(services) = document.get("services").and_then(Value::as_mapping) else {
+        return Vec::new();
+    };
+    let names: Vec<&str> = services.keys().filter_map(Value::as_str).collect();
+    let mut problems = Vec::new();
+    let mut error = |span, message| {
+        problems.push(Problem {
+            span,
+            severity: Severity::Error,
+            message,
+        })
+    };
+
+    for (name, service) in services {
+        let Some(name) = name.as_str() else {
+            continue;
+        };
+        if service.get("image").is_none() && service.get("build").is_none() {
+            error(
+                key_range(&lines, &["services", name]),
+                format!(
+                    "Service '{}' must have either 'image' or 'build' specified",
+                    name
+                ),
+            );
+        }
+
+        let dependencies: Vec<&str> = match service.get("depends_on") {
+            Some(Value::Sequence(names)) => names.iter().filter_map(Value::as_str).collect(),
+            Some(Value::Mapping(map)) => map.keys().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        for dependency in dependencies {
+            if !names.contains(&dependency) {
+                error(
+                    entry_range(&lines, &["services", name, "depends_on"], dependency),
+                    format!(
+                        "Service '{}' depends on unknown service '{}'",
+                        name, dependency
+                    ),
+                );
+            }
+        }
+
+        let ports = service.get("ports").and_then(Value::as_sequence);
+        for port in ports.into_iter().flatten() {
+            let port = match port {
+                Value::String(port) => port.clone(),
+                Value::Number(port) => port.to_string(),
+                _ => continue,
+            };
+            if !is_valid_port_mapping(&port) {
+                error(
+                    entry_range(&lines, &["services", name, "ports"], &port),
+                    format!(
+                        "Invalid port '{}'; expected [[ip:]host:]container[/protocol]",
+                        port
+                    ),
+                );
+            }
+        }
+    }
+
+    problems
+}
+
+/// Span of the key at `path`, such as `["services", "web"]`, or of the
+/// deepest enclosing key found
+pub fn key_span(content: &str, path: &[&str]) -> Span {
+    let lines: Vec<&str> = content.lines().collect();
+    key_range(&lines, path)
+}
+
+/// A YAML error at the character it points to
+pub fn yaml_error(content: &str, error: &serde_yaml::Error) -> Problem {
+    // YAML locations count characters
+    let (line, column) = error.location().map_or((0, 0), |l| {
+        (l.line().saturating_sub(1), l.column().saturating_sub(1))
+    });
+    let text = content.lines().nth(line).unwrap_or("");
+    let mut chars = text.char_indices().skip(column).map(|(i, _)| i);
+    let start = chars.next().unwrap_or(text.len());
+    let end = chars.next().unwrap_or(text.len());
+    Problem {
+        span: line_span(line, start, end),
+        severity: Severity::Error,
+        message: error.to_string(),
+    }
+}
+
+/// Keys that can appear under a key path
+fn keys_for(path: &[&str]) -> Option<&'static [(&'static str, &'static str)]> {
+    match path {
+        [] => Some(TOP_LEVEL_KEYS),
+        ["services", _] => Some(SERVICE_KEYS),
+        ["services", _, "build"] => Some(BUILD_KEYS),
+        ["services", _, "healthcheck"] => Some(HEALTHCHECK_KEYS),
+        ["services", _, "deploy"] => Some(DEPLOY_KEYS),
+        _ => None,
+    }
+}
+
+/// Whether `[[ip:]host[-range]:]container[-range][/protocol]` is well formed
+///
+/// Ports with variables are not checked.
+fn is_valid_port_mapping(port: &str) -> bool {
+    if port.contains('$') {
+        return true;
+    }
+    let (mapping, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    if !matches!(protocol, "tcp" | "udp" | "sctp") {
+        return false;
+    }
+    // Bracketed IPv6 addresses contain colons of their own
+    let mapping = match mapping.strip_prefix('[') {
+        Some(rest) => match rest.split_once("]:") {
+            Some((_, ports)) if ports.contains(':') => ports,
+            _ => return false,
+        },
+        None => mapping,
+    };
+
+    let parts: Vec<&str> = mapping.split(':').collect();
+    let (host, container) = match parts.as_slice() {
+        [container] => (None, *container),
+        [host, container] => (Some(*host), *container),
+        [ip, host, container] if !ip.is_empty() => {
+            (Some(*host).filter(|h| !h.is_empty()), *container)
+        }
+        _ => return false,
+    };
+    is_port_range(container) && host.is_none_or(is_port_range)
+}
+
+/// Whether text is a port or a `start-end` range of ports
+fn is_port_range(text: &str) -> bool {
+    let port = |text: &str| text.parse::<u16>().is_ok_and(|p| p > 0);
+    match text.split_once('-') {
+        Some((start, end)) => {
+            port(start) && port(end) && start.parse::<u16>().ok() <= end.parse::<u16>().ok()
+        }
+        None => port(text),
+    }
+}
+
+/// Names of the services defined in a document
+fn service_names(lines: &[&str]) -> Vec<String> {
+    let Some(services) = find_key(lines, &["services"]) else {
+        return Vec::new();
+    };
+    child_lines(lines, services)
+        .filter_map(|i| line_key(lines[i]))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Number of leading spaces
+fn indent(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+/// Whether text is a plain YAML key
+fn is_key(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The key a line defines, if any
+fn line_key(text: &str) -> Option<&str> {
+    let item = text.trim_start().trim_start_matches("- ");
+    let (key, rest) = item.split_once(':')?;
+    let key = key.trim_matches(|c| c == '"' || c == '\'');
+    (is_key(key) && (rest.is_empty() || rest.starts_with([' ', '\t']))).then_some(key)
+}
+
+/// Keys enclosing a position at `column` on `line`, outermost first
+fn key_path(lines: &[&str], line: usize, column: usize) -> Vec<String> {
+    let mut path = Vec::new();
+    let mut column = column;
+    for text in lines[..line.min(lines.len())].iter().rev() {
+        let trimmed = text.trim_start();
+        if column == 0 {
+            break;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') || indent(text) >= column {
+            continue;
+        }
+        column = indent(text);
+        if let Some(key) = line_key(text) {
+            path.push(key.to_string());
+        }
+    }
+    path.reverse();
+    path
+}
+
+/// Lines directly inside the block of the key on `line`
+fn child_lines<'a>(lines: &'a [&str], line: usize) -> impl Iterator<Item = usize> + 'a {
+    let parent = indent(lines[line]);
+    let block: Vec<usize> = (line + 1..lines.len())
+        .filter(|&i| !lines[i].trim().is_empty() && !lines[i].trim_start().starts_with('#'))
+        .take_while(|&i| indent(lines[i]) > parent)
+        .collect();
+    let child = block.first().map(|&i| indent(lines[i]));
+    block
+        .into_iter()
+        .filter(move |&i| Some(indent(lines[i])) == child)
+}
+
+/// Line of the key at `path`
+fn find_key(lines: &[&str], path: &[&str]) -> Option<usize> {
+    let (first, rest) = path.split_first()?;
+    let mut line =
+        (0..lines.len()).find(|&i| indent(lines[i]) == 0 && line_key(lines[i]) == Some(first))?;
+    for key in rest {
+        line = child_lines(lines, line).find(|&i| line_key(lines[i]) == Some(key))?;
+    }
+    Some(line)
+}
+
+/// Range of the key at `path`, or of the deepest enclosing key found
+fn key_range(lines: &[&str], path: &[&str]) -> Span {
+    (1..=path.len())
+        .rev()
+        .find_map(|len| find_key(lines, &path[..len]))
+        .map_or(line_span(0, 0, 0), |line| {
+            let text = lines[line];
+            let start = indent(text) + text.trim_start().len()
+                - text.trim_start().trim_start_matches("- ").len();
+            let end = text.find(':').unwrap_or(text.len());
+            line_span(line, start, end)
+        })
+}
+
+/// Range of an entry in the list or map of the key at `path`
+fn entry_range(lines: &[&str], path: &[&str], entry: &str) -> Span {
+    let Some(line) = find_key(lines, path) else {
+        return key_range(lines, path);
+    };
+    child_lines(lines, line)
+        .find_map(|i| {
+            let text = lines[i];
+            let item = text.trim_start().trim_start_matches("- ").trim_end();
+            let unquoted = item.trim_matches(|c| c == '"' || c == '\'');
+            (unquoted == entry || line_key(text) == Some(entry)).then(|| {
+                let start = text.find(entry).unwrap_or(indent(text));
+                line_span(i, start, start + entry.len())
+            })
+        })
+        .unwrap_or_else(|| key_range(lines, path))
+}
+
+fn line_span(line: usize, start: usize, end: usize) -> Span {
+    Span {
+        start_line: line,
+        start,
+        end_line: line,
+        end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "services:\n  web:\n    image: nginx\n    ports:\n      - \"80:80\"\n      - \"70000:80\"\n    depends_on:\n      - db\n      - cache\n    \n  db:\n    build:\n      context: .\n      \n";
+
+    fn labels(items: &[Completion]) -> Vec<&str> {
+        items.iter().map(|item| item.label.as_str()).collect()
+    }
+
+    #[test]
+    fn test_completions() {
+        let items = completions(CONTENT, 9, 4);
+        assert!(labels(&items).contains(&"build"));
+        assert!(labels(&items).contains(&"healthcheck"));
+
+        let items = completions(CONTENT, 13, 6);
+        assert!(labels(&items).contains(&"dockerfile"));
+
+        let content = "services:\n  web:\n    depends_on:\n      - \n  db:\n    image: postgres\n";
+        let items = completions(content, 3, 8);
+        assert_eq!(labels(&items), vec!["db"]);
+        assert_eq!(items[0].kind, CompletionKind::Service);
+
+        let items = completions("services:\n  web:\n    restart: ", 2, 13);
+        assert!(labels(&items).contains(&"unless-stopped"));
+
+        let items = completions("", 0, 0);
+        assert!(labels(&items).contains(&"services"));
+    }
+
+    #[test]
+    fn test_hover() {
+        let (documentation, span) = hover(CONTENT, 6, 6).unwrap();
+        assert!(documentation.contains("start before"));
+        assert_eq!(span.start, 4);
+
+        // Service names are not compose keys
+        assert!(hover(CONTENT, 1, 3).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let problems = validate(CONTENT);
+        let found: Vec<(usize, usize, &str)> = problems
+            .iter()
+            .map(|p| (p.span.start_line, p.span.start, p.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (8, 8, "Service 'web' depends on unknown service 'cache'"),
+                (
+                    5,
+                    9,
+                    "Invalid port '70000:80'; expected [[ip:]host:]container[/protocol]"
+                ),
+            ]
+        );
+
+        let problems = validate("services:\n  web: [\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_port_mappings() {
+        for port in [
+            "80",
+            "8080:80",
+            "127.0.0.1:8080:80",
+            "127.0.0.1::80",
+            "[::1]:80:80",
+            "9000-9001:9000-9001/udp",
+            "${PORT}:80",
+        ] {
+            assert!(is_valid_port_mapping(port), "{}", port);
+        }
+        for port in ["", "0", "80:", "a:80", "80/http", "9001-9000:80", "1:2:3:4"] {
+            assert!(!is_valid_port_mapping(port), "{}", port);
+        }
+    }
+
+    #[test]
+    fn test_is_compose_file() {
+        assert!(is_compose_file("file:///app/compose.yaml"));
+        assert!(is_compose_file("file:///app/dev.compose.yml"));
+        assert!(!is_compose_file("file:///app/Runefile"));
+    }
+}
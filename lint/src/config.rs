@@ -0,0 +1,131 @@
+//! Lint configuration
+
+use crate::rules::{Rule, RULES};
+use crate::Severity;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Name of the lint configuration file
+pub const CONFIG_FILE: &str = ".runelint.toml";
+
+/// Error in a lint configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Contents of `.runelint.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    #[serde(default)]
+    rules: BTreeMap<String, String>,
+    max_line_length: Option<usize>,
+    preferred_registry: Option<String>,
+}
+
+/// Rule severities and rule settings; rules not mentioned keep their default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintConfig {
+    /// Severity per rule ID, `None` for disabled rules
+    overrides: HashMap<&'static str, Option<Severity>>,
+    /// Longest allowed line, checked by RUNE1006 when set
+    pub max_line_length: Option<usize>,
+    /// Registry base images must come from, checked by RUNE1007 when set
+    pub preferred_registry: Option<String>,
+}
+
+impl LintConfig {
+    /// Parse a `.runelint.toml`
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let file: ConfigFile =
+            toml::from_str(content).map_err(|e| ConfigError(e.message().to_string()))?;
+
+        let mut config = Self {
+            overrides: HashMap::new(),
+            max_line_length: file.max_line_length,
+            preferred_registry: file.preferred_registry,
+        };
+        for (id, level) in file.rules {
+            config.set_rule(&id, &level)?;
+        }
+        Ok(config)
+    }
+
+    /// Set the severity of a rule as written in the config file, or turn
+    /// it off with `off`
+    pub fn set_rule(&mut self, id: &str, level: &str) -> Result<(), ConfigError> {
+        let rule = RULES
+            .iter()
+            .find(|rule| rule.id.eq_ignore_ascii_case(id))
+            .ok_or_else(|| ConfigError(format!("Unknown rule '{}'", id)))?;
+        let severity = match level.to_lowercase().as_str() {
+            "off" => None,
+            _ => Some(Severity::parse(level).ok_or_else(|| {
+                ConfigError(format!(
+                    "Invalid severity '{}' for {}, expected error, warning, info, hint or off",
+                    level, rule.id
+                ))
+            })?),
+        };
+        self.overrides.insert(rule.id, severity);
+        Ok(())
+    }
+
+    /// Read a `.runelint.toml`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&content).map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Find the `.runelint.toml` in a directory or the closest parent
+    pub fn find(dir: impl AsRef<Path>) -> Option<PathBuf> {
+        dir.as_ref()
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Severity of a rule, `None` if it is disabled
+    pub fn severity(&self, rule: &Rule) -> Option<Severity> {
+        self.overrides
+            .get(rule.id)
+            .copied()
+            .unwrap_or(Some(rule.severity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config =
+            LintConfig::parse("[rules]\nRUNE1002 = \"error\"\nrune1003 = \"off\"\n").unwrap();
+        let severity = |id| config.severity(RULES.iter().find(|r| r.id == id).unwrap());
+
+        assert_eq!(severity("RUNE1001"), Some(Severity::Warning));
+        assert_eq!(severity("RUNE1002"), Some(Severity::Error));
+        assert_eq!(severity("RUNE1003"), None);
+
+        assert!(LintConfig::parse("[rules]\nRUNE9999 = \"error\"").is_err());
+        assert!(LintConfig::parse("[rules]\nRUNE1001 = \"fatal\"").is_err());
+        assert!(LintConfig::parse("ignore = []").is_err());
+
+        let config =
+            LintConfig::parse("max-line-length = 100\npreferred-registry = \"ghcr.io\"").unwrap();
+        assert_eq!(config.max_line_length, Some(100));
+        assert_eq!(config.preferred_registry.as_deref(), Some("ghcr.io"));
+    }
+}
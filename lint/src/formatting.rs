@@ -0,0 +1,336 @@
+//! Document formatting
+//!
+//! Formats whole instructions, including their continuation lines. Heredoc
+//! bodies are copied untouched.
+
+use crate::semantic_tokens::heredoc_marker;
+
+/// Formatting style
+#[derive(Debug, Clone)]
+pub struct Style {
+    /// Text before continuation lines
+    pub indent: String,
+    /// Line up the `\` of continued lines
+    pub align_continuations: bool,
+    /// Sort multi-key LABEL and ENV instructions, one key per line
+    pub sort_keys: bool,
+    /// Rewrite CMD and ENTRYPOINT JSON arrays as `["a", "b"]`
+    pub normalize_json: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            indent: "    ".to_string(),
+            align_continuations: false,
+            sort_keys: false,
+            normalize_json: true,
+        }
+    }
+}
+
+/// A physical line of an instruction
+enum Part<'a> {
+    /// Text with the line continuation removed
+    Text(&'a str),
+    /// A comment inside a continued instruction
+    Comment(&'a str),
+}
+
+/// Format a document
+///
+/// Keywords are uppercased, runs of blank lines collapsed and comments
+/// unindented.
+pub fn format(content: &str, style: &Style) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut output: Vec<String> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let trimmed = lines[i].trim();
+        if trimmed.is_empty() {
+            if output.last().is_some_and(|line| !line.is_empty()) {
+                output.push(String::new());
+            }
+            i += 1;
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            output.push(trimmed.to_string());
+            i += 1;
+            continue;
+        }
+
+        // The instruction runs until a line without a continuation
+        let mut parts = Vec::new();
+        let mut unterminated = false;
+        while i < lines.len() {
+            let text = lines[i].trim();
+            i += 1;
+            if text.starts_with('#') {
+                parts.push(Part::Comment(text));
+                continue;
+            }
+            match text.strip_suffix('\\') {
+                Some(text) => {
+                    parts.push(Part::Text(text.trim_end()));
+                    // A continuation before a blank line or the end is kept
+                    if lines.get(i).is_none_or(|next| next.trim().is_empty()) {
+                        unterminated = true;
+                        break;
+                    }
+                }
+                None => {
+                    parts.push(Part::Text(text));
+                    break;
+                }
+            }
+        }
+
+        let heredocs = heredocs(&parts);
+        output.extend(instruction(&parts, unterminated, style));
+
+        // Heredoc bodies are copied as they are
+        for (terminator, strip_tabs) in heredocs {
+            while let Some(line) = lines.get(i) {
+                output.push(line.to_string());
+                i += 1;
+                let body = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line
+                };
+                if body.trim_end() == terminator {
+                    break;
+                }
+            }
+        }
+    }
+
+    if output.last().is_some_and(|line| line.is_empty()) {
+        output.pop();
+    }
+    let mut formatted = output.join("\n");
+    if content.ends_with('\n') {
+        formatted.push('\n');
+    }
+    formatted
+}
+
+/// Format the lines of one instruction
+fn instruction(parts: &[Part], unterminated: bool, style: &Style) -> Vec<String> {
+    let Some(Part::Text(first)) = parts.first() else {
+        return Vec::new();
+    };
+    let (keyword, arguments) = split_keyword(first);
+    let mut keyword = keyword.to_uppercase();
+    // ONBUILD wraps another instruction
+    let (inner, arguments) = match keyword.as_str() {
+        "ONBUILD" => split_keyword(arguments),
+        _ => ("", arguments),
+    };
+    if !inner.is_empty() {
+        keyword = format!("{} {}", keyword, inner.to_uppercase());
+    }
+    let kind = keyword.rsplit(' ').next().unwrap_or("");
+
+    let has_comments = parts.iter().any(|p| matches!(p, Part::Comment(_)));
+    let joined: Vec<&str> = std::iter::once(arguments)
+        .chain(parts[1..].iter().filter_map(|part| match part {
+            Part::Text(text) => Some(*text),
+            Part::Comment(_) => None,
+        }))
+        .filter(|text| !text.is_empty())
+        .collect();
+    let joined = joined.join(" ");
+
+    if !has_comments && !unterminated {
+        if style.normalize_json && matches!(kind, "CMD" | "ENTRYPOINT") {
+            if let Some(array) = normalize_json_array(&joined) {
+                return vec![format!("{} {}", keyword, array)];
+            }
+        }
+        if style.sort_keys && matches!(kind, "LABEL" | "ENV") {
+            if let Some(mut pairs) = key_value_pairs(&joined) {
+                pairs.sort_by_key(|pair| pair.split('=').next().unwrap_or(pair));
+                let mut lines = vec![format!("{} {}", keyword, pairs[0])];
+                lines.extend(
+                    pairs[1..]
+                        .iter()
+                        .map(|pair| format!("{}{}", style.indent, pair)),
+                );
+                return continue_lines(lines, &[], style.align_continuations, false);
+            }
+        }
+    }
+
+    let mut lines = vec![if arguments.is_empty() {
+        keyword
+    } else {
+        format!("{} {}", keyword, arguments)
+    }];
+    let mut comments = Vec::new();
+    for part in &parts[1..] {
+        match part {
+            Part::Text("") => {}
+            Part::Text(text) => lines.push(format!("{}{}", style.indent, text)),
+            Part::Comment(comment) => {
+                comments.push(lines.len());
+                lines.push(format!("{}{}", style.indent, comment));
+            }
+        }
+    }
+    continue_lines(lines, &comments, style.align_continuations, unterminated)
+}
+
+/// Add `\` to every line but the last, skipping comment lines
+///
+/// When aligning, the backslashes line up one space after the longest line.
+fn continue_lines(
+    mut lines: Vec<String>,
+    comments: &[usize],
+    align: bool,
+    unterminated: bool,
+) -> Vec<String> {
+    let last = (0..lines.len())
+        .rev()
+        .find(|i| !comments.contains(i))
+        .unwrap_or(0);
+    let continued: Vec<usize> = (0..lines.len())
+        .filter(|i| !comments.contains(i) && (*i < last || unterminated))
+        .collect();
+    let width = continued
+        .iter()
+        .map(|&i| lines[i].chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for i in continued {
+        let padding = if align {
+            width - lines[i].chars().count()
+        } else {
+            0
+        };
+        lines[i].push_str(&" ".repeat(padding + 1));
+        lines[i].push('\\');
+    }
+    lines
+}
+
+/// Split off the first word of a line
+fn split_keyword(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Terminators of the heredocs an instruction opens
+fn heredocs(parts: &[Part]) -> Vec<(String, bool)> {
+    let Some(Part::Text(first)) = parts.first() else {
+        return Vec::new();
+    };
+    let keyword = split_keyword(first).0.to_uppercase();
+    if !matches!(keyword.as_str(), "RUN" | "COPY" | "ADD") {
+        return Vec::new();
+    }
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::Text(text) => Some(*text),
+            Part::Comment(_) => None,
+        })
+        .flat_map(|text| {
+            text.match_indices("<<")
+                .filter_map(|(pos, _)| heredoc_marker(&text[pos..]))
+                .map(|(_, terminator, strip_tabs)| (terminator, strip_tabs))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Rewrite a JSON array of strings as `["a", "b"]`
+fn normalize_json_array(text: &str) -> Option<String> {
+    let items: Vec<String> = serde_json::from_str(text).ok()?;
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| serde_json::to_string(item).unwrap_or_default())
+        .collect();
+    Some(format!("[{}]", items.join(", ")))
+}
+
+/// The `key=value` words of LABEL or ENV, if there are several and every
+/// word is one
+fn key_value_pairs(text: &str) -> Option<Vec<&str>> {
+    let words = words(text);
+    let all_pairs = words
+        .iter()
+        .all(|word| word.split_once('=').is_some_and(|(key, _)| !key.is_empty()));
+    (words.len() > 1 && all_pairs).then_some(words)
+}
+
+/// Split on whitespace outside quotes and escapes
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (_, '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                if let Some(s) = start.take() {
+                    words.push(&text[s..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        words.push(&text[s..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_default() {
+        let content = "\n\nfrom alpine\n\n\n  # comment\nrun apk add \\\n  curl \\\n      # tools\n  git\nrun <<EOF\n  echo   hi\nrun this\nEOF\ncmd [\"a\",\"b\"]\n";
+        assert_eq!(
+            format(content, &Style::default()),
+            "FROM alpine\n\n# comment\nRUN apk add \\\n    curl \\\n    # tools\n    git\nRUN <<EOF\n  echo   hi\nrun this\nEOF\nCMD [\"a\", \"b\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_format_style() {
+        let style = Style {
+            align_continuations: true,
+            sort_keys: true,
+            normalize_json: false,
+            ..Style::default()
+        };
+        let content = "ENV b=2 a=\"x y\" c=3\nRUN make \\\n    install && \\\n  clean\nCMD [\"a\",\"b\"]\nENV KEY value";
+        assert_eq!(
+            format(content, &style),
+            "ENV a=\"x y\" \\\n    b=2     \\\n    c=3\nRUN make       \\\n    install && \\\n    clean\nCMD [\"a\",\"b\"]\nENV KEY value"
+        );
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words(r#"a="x y" b='1 2' c=\ d"#),
+            vec![r#"a="x y""#, "b='1 2'", r"c=\ d"]
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Runefile Lint Rules
+//!
+//! A small hadolint-style rule engine shared by `rune lint`, the native
+//! language server and the WebAssembly language server. Every rule has an
+//! ID such as `RUNE1001` and a default severity. The instruction metadata
+//! and the editor features both language servers build on live alongside
+//! it: [`syntax`], [`semantic_tokens`], [`signature_help`], [`ranges`],
+//! [`formatting`] and [`compose`].
+//!
+//! Severities can be changed, or rules turned off, in a `.runelint.toml`:
+//!
+//! ```toml
+//! [rules]
+//! RUNE1002 = "error"
+//! RUNE1005 = "off"
+//! ```
+//!
+//! Two rules only run once they are given a setting:
+//!
+//! ```toml
+//! max-line-length = 120        # RUNE1006
+//! preferred-registry = "ghcr.io" # RUNE1007
+//! ```
+//!
+//! Comments silence rules in the file itself. `# rune-lint ignore=RUNE1001`
+//! applies to the next instruction, `# rune-lint global ignore=RUNE1003` to
+//! the whole file. Several IDs can be given separated by commas.
+
+pub mod compose;
+mod config;
+pub mod formatting;
+pub mod ranges;
+mod rules;
+pub mod semantic_tokens;
+pub mod signature_help;
+pub mod syntax;
+
+pub use config::{ConfigError, LintConfig, CONFIG_FILE};
+pub use rules::{Rule, RULES};
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Severity of a lint issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// Parse a severity as written in the config file
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "info" => Some(Self::Info),
+            "hint" => Some(Self::Hint),
+            _ => None,
+        }
+    }
+
+    /// LSP diagnostic severity number
+    pub fn lsp_code(&self) -> u8 {
+        match self {
+            Self::Error => 1,
+            Self::Warning => 2,
+            Self::Info => 3,
+            Self::Hint => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+            Self::Hint => "hint",
+        };
+        f.pad(name)
+    }
+}
+
+/// An instruction as seen by the rules, with line continuations joined
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    /// Upper-cased keyword
+    pub keyword: String,
+    /// Everything after the keyword
+    pub arguments: String,
+    /// Line number (0-indexed)
+    pub line: usize,
+    /// Column of the keyword
+    pub column: usize,
+    /// End column of the instruction's first line
+    pub end_column: usize,
+    /// Length in characters of the instruction's longest line
+    pub width: usize,
+    /// Rules ignored by a `# rune-lint ignore=` comment
+    ignored: HashSet<String>,
+}
+
+/// A rule violation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    /// Rule ID, e.g. `RUNE1001`
+    pub rule: &'static str,
+    /// Configured severity
+    pub severity: Severity,
+    /// What is wrong
+    pub message: String,
+    /// Line number (0-indexed)
+    pub line: usize,
+    /// Start column
+    pub column: usize,
+    /// End column
+    pub end_column: usize,
+}
+
+/// Check a Runefile against every enabled rule
+///
+/// Issues are ordered by line.
+pub fn lint(content: &str, config: &LintConfig) -> Vec<Issue> {
+    let (instructions, global_ignores) = split_instructions(content);
+
+    let mut issues: Vec<Issue> = RULES
+        .iter()
+        .filter(|rule| !global_ignores.contains(rule.id))
+        .filter_map(|rule| Some((rule, config.severity(rule)?)))
+        .flat_map(|(rule, severity)| {
+            (rule.check)(&instructions, config)
+                .into_iter()
+                .map(|(index, message)| (&instructions[index], message))
+                .filter(|(inst, _)| !inst.ignored.contains(rule.id))
+                .map(move |(inst, message)| Issue {
+                    rule: rule.id,
+                    severity,
+                    message,
+                    line: inst.line,
+                    column: inst.column,
+                    end_column: inst.end_column,
+                })
+        })
+        .collect();
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// Split a Runefile into instructions and collect the globally ignored rules
+fn split_instructions(content: &str) -> (Vec<Instruction>, HashSet<String>) {
+    let mut instructions = Vec::new();
+    let mut global_ignores = HashSet::new();
+    let mut ignored = HashSet::new();
+    let mut pending: Option<(String, usize, usize, usize, usize)> = None;
+
+    for (line_num, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some(directive) = comment.trim().strip_prefix("rune-lint") {
+                let directive = directive.trim();
+                let (target, ids) = match directive.strip_prefix("global") {
+                    Some(rest) => (&mut global_ignores, rest.trim()),
+                    None => (&mut ignored, directive),
+                };
+                if let Some(ids) = ids.strip_prefix("ignore=") {
+                    target.extend(ids.split(',').map(|id| id.trim().to_uppercase()));
+                }
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let line_width = line.trim_end().chars().count();
+        let (text, start, column, end_column, width) = match pending.take() {
+            Some((mut text, start, column, end_column, width)) => {
+                text.push(' ');
+                text.push_str(trimmed);
+                (text, start, column, end_column, width.max(line_width))
+            }
+            None => {
+                let column = line.len() - line.trim_start().len();
+                let end_column = line.trim_end().len();
+                (
+                    trimmed.to_string(),
+                    line_num,
+                    column,
+                    end_column,
+                    line_width,
+                )
+            }
+        };
+        if let Some(continued) = text.strip_suffix('\\') {
+            let continued = continued.trim_end().to_string();
+            pending = Some((continued, start, column, end_column, width));
+            continue;
+        }
+
+        let (keyword, arguments) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+        instructions.push(Instruction {
+            keyword: keyword.to_uppercase(),
+            arguments: arguments.trim().to_string(),
+            line: start,
+            column,
+            end_column,
+            width,
+            ignored: std::mem::take(&mut ignored),
+        });
+    }
+
+    (instructions, global_ignores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let content =
+            "FROM ubuntu:latest\nRUN apt-get update && \\\n    apt-get install curl\nCMD a\nCMD b\n";
+
+        let issues = lint(content, &LintConfig::default());
+        let found: Vec<(&str, usize)> = issues.iter().map(|i| (i.rule, i.line)).collect();
+        assert_eq!(
+            found,
+            vec![("RUNE1002", 0), ("RUNE1001", 1), ("RUNE1004", 3)]
+        );
+        assert_eq!(issues[1].end_column, "RUN apt-get update && \\".len());
+    }
+
+    #[test]
+    fn test_ignore_comments() {
+        let content = "# rune-lint global ignore=RUNE1002\nFROM ubuntu:latest\n# rune-lint ignore=RUNE1003, rune1005\nRUN sudo cd /tmp\nRUN sudo ls\n";
+
+        let issues = lint(content, &LintConfig::default());
+        let found: Vec<(&str, usize)> = issues.iter().map(|i| (i.rule, i.line)).collect();
+        assert_eq!(found, vec![("RUNE1003", 4)]);
+    }
+}
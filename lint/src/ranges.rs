@@ -0,0 +1,306 @@
+//! Folding and selection ranges
+//!
+//! Ranges are given in lines and byte columns; the language servers convert
+//! them to their clients' types and encoding.
+
+use crate::semantic_tokens::heredoc_marker;
+
+/// What a block of lines holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Comment,
+    Instruction { from: bool },
+    Heredoc,
+}
+
+/// Lines `start..=end` of the document
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    start: usize,
+    end: usize,
+    kind: BlockKind,
+}
+
+/// Lines `start_line..=end_line` that can be folded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fold {
+    pub start_line: usize,
+    pub end_line: usize,
+    /// `comment` or `region`, if either
+    pub kind: Option<&'static str>,
+}
+
+/// A range of a document, with byte columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start_line: usize,
+    pub start: usize,
+    pub end_line: usize,
+    pub end: usize,
+}
+
+/// Get folding ranges for stages, continued instructions, heredocs and
+/// comment blocks
+pub fn folding_ranges(content: &str) -> Vec<Fold> {
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = blocks(&lines);
+    let mut ranges: Vec<(usize, usize, Option<&'static str>)> = Vec::new();
+
+    for (start, end) in stages(&blocks) {
+        ranges.push((start, end, Some("region")));
+    }
+    for block in blocks.iter().filter(|b| b.kind != BlockKind::Comment) {
+        ranges.push((block.start, block.end, None));
+    }
+    // Runs of comment lines
+    let mut comments = blocks.iter().filter(|b| b.kind == BlockKind::Comment);
+    if let Some(first) = comments.next() {
+        let (mut start, mut end) = (first.start, first.end);
+        for comment in comments {
+            if comment.start != end + 1 {
+                ranges.push((start, end, Some("comment")));
+                start = comment.start;
+            }
+            end = comment.end;
+        }
+        ranges.push((start, end, Some("comment")));
+    }
+
+    ranges.retain(|(start, end, _)| end > start);
+    ranges.sort_by_key(|(start, end, _)| (*start, std::cmp::Reverse(*end)));
+    ranges.dedup_by_key(|(start, end, _)| (*start, *end));
+    ranges
+        .into_iter()
+        .map(|(start_line, end_line, kind)| Fold {
+            start_line,
+            end_line,
+            kind,
+        })
+        .collect()
+}
+
+/// Get the selection ranges around a position, innermost first
+///
+/// The selection grows from the word (or the value of a `--flag=value`) to
+/// the heredoc, the instruction, the stage and the whole document. There is
+/// always at least one range, empty at the position if nothing is there.
+pub fn selection_ranges(content: &str, line: usize, column: usize) -> Vec<Span> {
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = blocks(&lines);
+    let stages = stages(&blocks);
+    let line_range = |start: usize, end: usize| {
+        let indent = lines[start].len() - lines[start].trim_start().len();
+        span(start, indent, end, lines[end].trim_end().len())
+    };
+    let mut ranges = Vec::new();
+
+    if let Some(text) = lines.get(line) {
+        ranges.extend(word_ranges(text, column).map(|(s, e)| span(line, s, line, e)));
+    }
+    for kind in [
+        BlockKind::Heredoc,
+        BlockKind::Instruction { from: false },
+        BlockKind::Comment,
+    ] {
+        let block = blocks.iter().find(|b| {
+            b.start <= line
+                && line <= b.end
+                && match kind {
+                    BlockKind::Instruction { .. } => {
+                        matches!(b.kind, BlockKind::Instruction { .. })
+                    }
+                    kind => b.kind == kind,
+                }
+        });
+        if let Some(block) = block {
+            ranges.push(line_range(block.start, block.end));
+        }
+    }
+    if let Some(&(start, end)) = stages.iter().find(|(s, e)| *s <= line && line <= *e) {
+        ranges.push(line_range(start, end));
+    }
+    if let Some(last) = lines.len().checked_sub(1) {
+        ranges.push(span(0, 0, last, lines[last].len()));
+    }
+
+    // Drop ranges equal to the one inside them
+    ranges.dedup();
+    if ranges.is_empty() {
+        ranges.push(span(line, column, line, column));
+    }
+    ranges
+}
+
+fn span(start_line: usize, start: usize, end_line: usize, end: usize) -> Span {
+    Span {
+        start_line,
+        start,
+        end_line,
+        end,
+    }
+}
+
+/// The value of a `--flag=value` under the cursor, then the whole word
+fn word_ranges(text: &str, column: usize) -> impl Iterator<Item = (usize, usize)> {
+    let column = (0..=column.min(text.len()))
+        .rev()
+        .find(|&c| text.is_char_boundary(c))
+        .unwrap_or(0);
+    let start = text[..column]
+        .rfind(char::is_whitespace)
+        .map_or(0, |i| i + 1);
+    let end = text[column..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |i| column + i);
+
+    let word = &text[start..end];
+    let value = word
+        .find('=')
+        .map(|i| start + i + 1)
+        .filter(|&value| value <= column && value < end);
+    value
+        .map(|value| (value, end))
+        .into_iter()
+        .chain(Some((start, end)).filter(|(s, e)| e > s))
+}
+
+/// Split a document into comments, instructions and heredoc bodies
+///
+/// Instructions include their continuation lines and heredocs.
+fn blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let trimmed = lines[i].trim();
+        if trimmed.is_empty() {
+            i += 1;
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            blocks.push(Block {
+                start: i,
+                end: i,
+                kind: BlockKind::Comment,
+            });
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let keyword = trimmed
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_uppercase();
+        let mut heredocs = Vec::new();
+        loop {
+            let text = lines[i].trim_end();
+            if matches!(keyword.as_str(), "RUN" | "COPY" | "ADD") {
+                heredocs.extend(
+                    text.match_indices("<<")
+                        .filter_map(|(pos, _)| heredoc_marker(&text[pos..])),
+                );
+            }
+            // Comment lines inside a continuation are skipped
+            let next = (i + 1..lines.len()).find(|&l| !lines[l].trim_start().starts_with('#'));
+            match next {
+                Some(next) if text.ends_with('\\') => i = next,
+                _ => break,
+            }
+        }
+
+        let mut end = i;
+        for (_, terminator, strip_tabs) in heredocs {
+            let body_end = (end + 1..lines.len()).find(|&l| {
+                let line = if strip_tabs {
+                    lines[l].trim_start_matches('\t')
+                } else {
+                    lines[l]
+                };
+                line.trim_end() == terminator
+            });
+            let body_end = body_end.unwrap_or(lines.len() - 1);
+            blocks.push(Block {
+                start: end,
+                end: body_end,
+                kind: BlockKind::Heredoc,
+            });
+            end = body_end;
+        }
+
+        blocks.push(Block {
+            start,
+            end,
+            kind: BlockKind::Instruction {
+                from: keyword == "FROM",
+            },
+        });
+        i = end + 1;
+    }
+
+    blocks.sort_by_key(|block| block.start);
+    blocks
+}
+
+/// Lines of each build stage, from its FROM to its last instruction
+fn stages(blocks: &[Block]) -> Vec<(usize, usize)> {
+    let mut stages: Vec<(usize, usize)> = Vec::new();
+    for block in blocks {
+        match block.kind {
+            BlockKind::Instruction { from: true } => stages.push((block.start, block.end)),
+            BlockKind::Instruction { from: false } | BlockKind::Heredoc => {
+                if let Some(stage) = stages.last_mut() {
+                    stage.1 = stage.1.max(block.end);
+                }
+            }
+            BlockKind::Comment => {}
+        }
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "# Build\n# stage\nFROM rust AS build\nRUN cargo build \\\n    # comment\n    --release\nCOPY <<EOF /etc/conf\na=1\nEOF\n\n# Runtime\nFROM alpine\nCMD [\"app\"]\n";
+
+    #[test]
+    fn test_folding_ranges() {
+        let ranges: Vec<(usize, usize, Option<&str>)> = folding_ranges(CONTENT)
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect();
+
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 1, Some("comment")),
+                (2, 8, Some("region")),
+                (3, 5, None),
+                (6, 8, None),
+                (11, 12, Some("region")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selection_ranges() {
+        let chain: Vec<(usize, usize, usize, usize)> = selection_ranges(CONTENT, 5, 8)
+            .into_iter()
+            .map(|r| (r.start_line, r.start, r.end_line, r.end))
+            .collect();
+        assert_eq!(
+            chain,
+            vec![(5, 4, 5, 13), (3, 0, 5, 13), (2, 0, 8, 3), (0, 0, 12, 11)]
+        );
+
+        // Inside a flag value
+        let flag = selection_ranges("COPY --from=build /a /b", 0, 14);
+        assert_eq!(flag[0].start, 12);
+        assert_eq!(flag[1].start, 5);
+
+        assert_eq!(selection_ranges("", 0, 0), vec![span(0, 0, 0, 0)]);
+    }
+}
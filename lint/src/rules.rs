@@ -0,0 +1,291 @@
+//! Lint rules
+
+use crate::{Instruction, LintConfig, Severity};
+
+/// Finds violations, as instruction indices with a message
+type Check = fn(&[Instruction], &LintConfig) -> Vec<(usize, String)>;
+
+/// A lint rule
+pub struct Rule {
+    /// Rule ID, e.g. `RUNE1001`
+    pub id: &'static str,
+    /// Short description
+    pub description: &'static str,
+    /// Severity unless configured otherwise
+    pub severity: Severity,
+    /// Find violations
+    pub check: Check,
+}
+
+/// All rules
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "RUNE1001",
+        description: "apt-get install without -y",
+        severity: Severity::Warning,
+        check: apt_get_without_yes,
+    },
+    Rule {
+        id: "RUNE1002",
+        description: "Image uses the latest tag",
+        severity: Severity::Warning,
+        check: latest_tag,
+    },
+    Rule {
+        id: "RUNE1003",
+        description: "sudo in RUN",
+        severity: Severity::Warning,
+        check: sudo,
+    },
+    Rule {
+        id: "RUNE1004",
+        description: "Multiple CMD instructions in a stage",
+        severity: Severity::Warning,
+        check: multiple_cmd,
+    },
+    Rule {
+        id: "RUNE1005",
+        description: "cd in RUN instead of WORKDIR",
+        severity: Severity::Info,
+        check: cd_in_run,
+    },
+    Rule {
+        id: "RUNE1006",
+        description: "Line longer than max-line-length",
+        severity: Severity::Info,
+        check: line_too_long,
+    },
+    Rule {
+        id: "RUNE1007",
+        description: "Base image not from preferred-registry",
+        severity: Severity::Warning,
+        check: unpreferred_registry,
+    },
+];
+
+/// Shell commands of each shell-form RUN, split at `&&`, `||`, `;` and `|`
+fn run_commands(instructions: &[Instruction]) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| inst.keyword == "RUN" && !inst.arguments.starts_with('['))
+        .flat_map(|(index, inst)| {
+            inst.arguments
+                .split(['&', '|', ';'])
+                .map(move |command| (index, command.split_whitespace().collect()))
+        })
+}
+
+fn apt_get_without_yes(instructions: &[Instruction], _config: &LintConfig) -> Vec<(usize, String)> {
+    let assumes_yes = |word: &&str| match word.strip_prefix("--") {
+        Some(long) => matches!(long, "yes" | "assume-yes"),
+        None => word.starts_with('-') && word.contains('y'),
+    };
+    run_commands(instructions)
+        .map(|(index, words)| {
+            let command: Vec<&str> = words.into_iter().skip_while(|w| *w == "sudo").collect();
+            (index, command)
+        })
+        .filter(|(_, words)| {
+            words.first() == Some(&"apt-get")
+                && words.contains(&"install")
+                && !words.iter().any(assumes_yes)
+        })
+        .map(|(index, _)| {
+            (
+                index,
+                "apt-get install without -y waits for confirmation".to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Images pulled by FROM, leaving out earlier stages, `scratch` and images
+/// named by variables
+fn base_images(instructions: &[Instruction]) -> impl Iterator<Item = (usize, &str)> {
+    let mut stages: Vec<&str> = Vec::new();
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| inst.keyword == "FROM")
+        .filter_map(move |(index, inst)| {
+            let mut words = inst
+                .arguments
+                .split_whitespace()
+                .filter(|w| !w.starts_with("--"));
+            let image = words.next()?;
+            let is_stage = stages.iter().any(|stage| stage.eq_ignore_ascii_case(image));
+            if let (Some(keyword), Some(alias)) = (words.next(), words.next()) {
+                if keyword.eq_ignore_ascii_case("AS") {
+                    stages.push(alias);
+                }
+            }
+            let pulled =
+                !is_stage && !image.contains('$') && !image.eq_ignore_ascii_case("scratch");
+            pulled.then_some((index, image))
+        })
+}
+
+fn latest_tag(instructions: &[Instruction], _config: &LintConfig) -> Vec<(usize, String)> {
+    base_images(instructions)
+        .filter(|(_, image)| !image.contains('@'))
+        // A colon after the last slash starts the tag; one before is a port
+        .filter(|(_, image)| {
+            image
+                .rsplit('/')
+                .next()
+                .unwrap_or(image)
+                .ends_with(":latest")
+        })
+        .map(|(index, image)| {
+            (
+                index,
+                format!("'{}' uses the latest tag; pin a version", image),
+            )
+        })
+        .collect()
+}
+
+fn sudo(instructions: &[Instruction], _config: &LintConfig) -> Vec<(usize, String)> {
+    run_commands(instructions)
+        .filter(|(_, words)| words.first() == Some(&"sudo"))
+        .map(|(index, _)| (index, "Avoid sudo; use USER to change the user".to_string()))
+        .collect()
+}
+
+fn multiple_cmd(instructions: &[Instruction], _config: &LintConfig) -> Vec<(usize, String)> {
+    let mut issues = Vec::new();
+    let mut last_cmd = None;
+
+    for (index, inst) in instructions.iter().enumerate() {
+        match inst.keyword.as_str() {
+            "FROM" => last_cmd = None,
+            "CMD" => {
+                if let Some(previous) = last_cmd.replace(index) {
+                    issues.push((
+                        previous,
+                        "Only the last CMD of a stage takes effect".to_string(),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    issues
+}
+
+fn line_too_long(instructions: &[Instruction], config: &LintConfig) -> Vec<(usize, String)> {
+    let Some(max) = config.max_line_length else {
+        return Vec::new();
+    };
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| inst.width > max)
+        .map(|(index, inst)| {
+            (
+                index,
+                format!("Line is {} characters long, more than {}", inst.width, max),
+            )
+        })
+        .collect()
+}
+
+fn unpreferred_registry(instructions: &[Instruction], config: &LintConfig) -> Vec<(usize, String)> {
+    let Some(registry) = &config.preferred_registry else {
+        return Vec::new();
+    };
+    let registry = registry.trim_end_matches('/');
+    base_images(instructions)
+        .filter(|(_, image)| {
+            // Images without a registry host come from Docker Hub
+            let first = image.split('/').next().unwrap_or(image);
+            let has_host =
+                image.contains('/') && (first.contains(['.', ':']) || first == "localhost");
+            let qualified = if has_host {
+                image.to_string()
+            } else {
+                format!("docker.io/{}", image)
+            };
+            !qualified
+                .strip_prefix(registry)
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .map(|(index, image)| {
+            (
+                index,
+                format!("'{}' is not pulled from {}", image, registry),
+            )
+        })
+        .collect()
+}
+
+fn cd_in_run(instructions: &[Instruction], _config: &LintConfig) -> Vec<(usize, String)> {
+    run_commands(instructions)
+        .filter(|(_, words)| words.first() == Some(&"cd"))
+        .map(|(index, _)| (index, "Use WORKDIR to change directory".to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lint, LintConfig};
+
+    fn rules(content: &str) -> Vec<&'static str> {
+        lint(content, &LintConfig::default())
+            .into_iter()
+            .map(|issue| issue.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            rules("FROM alpine:3.19\nRUN apt-get -qy install curl"),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            rules("FROM alpine:3.19\nRUN apt-get install --yes curl"),
+            Vec::<&str>::new()
+        );
+        assert_eq!(rules("FROM registry:5000/app:latest"), vec!["RUNE1002"]);
+        assert_eq!(rules("FROM registry:5000/app"), Vec::<&str>::new());
+        assert_eq!(
+            rules("FROM rust:1.80 AS latest\nFROM latest\nFROM scratch"),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            rules("FROM alpine:3.19\nRUN echo a | sudo tee /x; cd /tmp"),
+            vec!["RUNE1003", "RUNE1005"]
+        );
+        assert_eq!(
+            rules("FROM alpine:3.19 AS a\nCMD x\nFROM a\nCMD y\nCMD [\"z\"]"),
+            vec!["RUNE1004"]
+        );
+    }
+
+    #[test]
+    fn test_configured_rules() {
+        let mut config = LintConfig::default();
+        config.max_line_length = Some(20);
+        config.preferred_registry = Some("ghcr.io/acme/".to_string());
+        let content = "FROM ghcr.io/acme/rust:1.80 AS build\nRUN make \\\n    a-much-longer-target-name\nFROM alpine:3.19\nFROM build\nFROM ghcr.io/other/app:1\n";
+        let found: Vec<(&str, usize)> = lint(content, &config)
+            .iter()
+            .map(|issue| (issue.rule, issue.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("RUNE1006", 0),
+                ("RUNE1006", 1),
+                ("RUNE1007", 3),
+                ("RUNE1006", 5),
+                ("RUNE1007", 5)
+            ]
+        );
+        // Both rules stay quiet without a setting
+        assert_eq!(rules(content), Vec::<&str>::new());
+    }
+}
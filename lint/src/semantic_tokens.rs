@@ -0,0 +1,497 @@
+//! Semantic tokens
+//!
+//! Classifies the document line by line rather than through a parser, so
+//! line continuations and heredoc bodies are highlighted where they are.
+//! Positions are byte offsets; the language servers convert them to their
+//! clients' encoding.
+
+use crate::syntax::KEYWORDS;
+
+/// Token type names, in the order of [`TokenType`]
+pub const TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "parameter",
+    "namespace",
+    "variable",
+    "string",
+    "operator",
+    "comment",
+];
+
+/// Semantic token types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenType {
+    /// Instruction keywords and `AS`
+    Keyword = 0,
+    /// Flags such as `--from`
+    Flag = 1,
+    /// Build stage names
+    Stage = 2,
+    /// `$VAR` and `${VAR}`
+    Variable = 3,
+    /// Quoted strings and heredoc bodies
+    String = 4,
+    /// JSON array punctuation and heredoc markers
+    Operator = 5,
+    /// Comments
+    Comment = 6,
+}
+
+/// A classified span of a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub line: usize,
+    pub start: usize,
+    pub length: usize,
+    pub kind: TokenType,
+}
+
+/// Instruction being continued on the next line
+struct Continued {
+    keyword: String,
+    json: bool,
+    words: usize,
+}
+
+/// Get the tokens of a document, in document order
+pub fn tokens(content: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer {
+        stages: stage_names(content),
+        tokens: Vec::new(),
+        heredocs: Vec::new(),
+        line: 0,
+    };
+    let mut continued: Option<Continued> = None;
+    let mut heredocs: Vec<(String, bool)> = Vec::new();
+
+    for (line_num, line) in content.lines().enumerate() {
+        tokenizer.line = line_num;
+
+        // Heredoc bodies run until their terminator
+        if continued.is_none() && !heredocs.is_empty() {
+            let (terminator, strip_tabs) = &heredocs[0];
+            let body = if *strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            let start = line.len() - body.len();
+            if body.trim_end() == terminator {
+                tokenizer.push(start, terminator.len(), TokenType::Operator);
+                heredocs.remove(0);
+            } else if !body.is_empty() {
+                tokenizer.push(start, body.len(), TokenType::String);
+            }
+            continue;
+        }
+
+        let text = line.trim_end();
+        let indent = text.len() - text.trim_start().len();
+        if text.is_empty() {
+            continue;
+        }
+        if text[indent..].starts_with('#') {
+            tokenizer.push(indent, text.len() - indent, TokenType::Comment);
+            continue;
+        }
+
+        let (text, continues) = match text.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        let mut state = match continued.take() {
+            Some(state) => tokenizer.arguments(text, indent, state),
+            None => tokenizer.instruction(text, indent),
+        };
+
+        if continues {
+            continued = Some(state);
+        } else {
+            heredocs.append(&mut tokenizer.heredocs);
+            state.words = 0;
+        }
+    }
+
+    tokenizer.tokens
+}
+
+/// Encode tokens as LSP relative positions
+pub fn encode(tokens: &[Token]) -> Vec<u32> {
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut line, mut start) = (0, 0);
+    for token in tokens {
+        let delta_line = token.line - line;
+        let delta_start = if delta_line == 0 {
+            token.start - start
+        } else {
+            token.start
+        };
+        data.extend([
+            delta_line as u32,
+            delta_start as u32,
+            token.length as u32,
+            token.kind as u32,
+            0,
+        ]);
+        line = token.line;
+        start = token.start;
+    }
+    data
+}
+
+/// Stage aliases defined anywhere in the document
+fn stage_names(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.first()?.eq_ignore_ascii_case("FROM") {
+                return None;
+            }
+            let position = words.iter().position(|w| w.eq_ignore_ascii_case("AS"))?;
+            words.get(position + 1).map(|name| name.to_lowercase())
+        })
+        .collect()
+}
+
+struct Tokenizer {
+    stages: Vec<String>,
+    tokens: Vec<Token>,
+    /// Heredocs opened on the current instruction
+    heredocs: Vec<(String, bool)>,
+    line: usize,
+}
+
+impl Tokenizer {
+    fn push(&mut self, start: usize, length: usize, kind: TokenType) {
+        if length > 0 {
+            self.tokens.push(Token {
+                line: self.line,
+                start,
+                length,
+                kind,
+            });
+        }
+    }
+
+    fn is_stage(&self, name: &str) -> bool {
+        self.stages
+            .iter()
+            .any(|stage| stage.eq_ignore_ascii_case(name))
+    }
+
+    /// Tokenize a line starting a new instruction
+    fn instruction(&mut self, text: &str, start: usize) -> Continued {
+        let end = word_end(text, start, text.len(), &[]);
+        let keyword = text[start..end].to_uppercase();
+        if KEYWORDS.contains(&keyword.as_str()) {
+            self.push(start, end - start, TokenType::Keyword);
+        }
+
+        // ONBUILD wraps another instruction
+        let next = end + (text.len() - end - text[end..].trim_start().len());
+        if keyword == "ONBUILD" && next < text.len() {
+            return self.instruction(text, next);
+        }
+
+        let json = text[end..].trim_start().starts_with('[');
+        self.arguments(
+            text,
+            end,
+            Continued {
+                keyword,
+                json,
+                words: 0,
+            },
+        )
+    }
+
+    /// Tokenize instruction arguments from `start` to the end of the line
+    fn arguments(&mut self, text: &str, mut i: usize, mut state: Continued) -> Continued {
+        let heredoc_allowed =
+            !state.json && matches!(state.keyword.as_str(), "RUN" | "COPY" | "ADD");
+
+        while i < text.len() {
+            let c = text[i..].chars().next().unwrap_or(' ');
+            if c.is_whitespace() {
+                i += c.len_utf8();
+                continue;
+            }
+            if state.json && matches!(c, '[' | ']' | ',') {
+                self.push(i, 1, TokenType::Operator);
+                i += 1;
+                continue;
+            }
+            if heredoc_allowed && text[i..].starts_with("<<") {
+                if let Some((length, terminator, strip_tabs)) = heredoc_marker(&text[i..]) {
+                    self.push(i, length, TokenType::Operator);
+                    self.heredocs.push((terminator, strip_tabs));
+                    i += length;
+                    continue;
+                }
+            }
+
+            let stops: &[char] = if state.json { &[',', ']'] } else { &[] };
+            let end = word_end(text, i, text.len(), stops);
+            let word = &text[i..end];
+            if let Some(flag) = word.strip_prefix("--").filter(|_| !state.json) {
+                self.flag(text, i, flag);
+            } else if state.keyword == "FROM" && !state.json {
+                match state.words {
+                    0 if self.is_stage(word) => self.push(i, word.len(), TokenType::Stage),
+                    1 if word.eq_ignore_ascii_case("AS") => {
+                        self.push(i, word.len(), TokenType::Keyword)
+                    }
+                    2 => self.push(i, word.len(), TokenType::Stage),
+                    _ => self.plain(text, i, end),
+                }
+                state.words += 1;
+            } else {
+                self.plain(text, i, end);
+            }
+            i = end;
+        }
+
+        state
+    }
+
+    /// Tokenize a `--name[=value]` flag; `flag` is the text after `--`
+    fn flag(&mut self, text: &str, start: usize, flag: &str) {
+        let (name, value) = flag.split_once('=').unwrap_or((flag, ""));
+        self.push(start, name.len() + 2, TokenType::Flag);
+
+        let mut value_start = start + 2 + name.len() + 1;
+        match name {
+            "from" if self.is_stage(value) => self.push(value_start, value.len(), TokenType::Stage),
+            // RUN --mount=type=cache,from=stage
+            "mount" => {
+                for option in value.split(',') {
+                    match option.strip_prefix("from=") {
+                        Some(stage) if self.is_stage(stage) => {
+                            self.push(value_start + 5, stage.len(), TokenType::Stage)
+                        }
+                        _ => self.plain(text, value_start, value_start + option.len()),
+                    }
+                    value_start += option.len() + 1;
+                }
+            }
+            _ if !value.is_empty() => self.plain(text, value_start, value_start + value.len()),
+            _ => {}
+        }
+    }
+
+    /// Tokenize quoted strings and variables between `i` and `end`
+    fn plain(&mut self, text: &str, mut i: usize, end: usize) {
+        while i < end {
+            let c = text[i..].chars().next().unwrap_or(' ');
+            match c {
+                '\\' => i = skip_escape(text, i, end),
+                '$' => i += self.variable(text, i, end).max(1),
+                '\'' => {
+                    let close = text[i + 1..end].find('\'').map_or(end, |p| i + p + 2);
+                    self.push(i, close - i, TokenType::String);
+                    i = close;
+                }
+                '"' => {
+                    // Variables inside double quotes are expanded
+                    let mut segment = i;
+                    i += 1;
+                    while i < end {
+                        match text.as_bytes()[i] {
+                            b'\\' => i = skip_escape(text, i, end),
+                            b'"' => {
+                                i += 1;
+                                break;
+                            }
+                            b'$' => {
+                                let length = self.variable_length(&text[i..end]);
+                                if length > 1 {
+                                    self.push(segment, i - segment, TokenType::String);
+                                    self.push(i, length, TokenType::Variable);
+                                    segment = i + length;
+                                }
+                                i += length;
+                            }
+                            _ => i += text[i..].chars().next().map_or(1, char::len_utf8),
+                        }
+                    }
+                    self.push(segment, i - segment, TokenType::String);
+                }
+                _ => i += c.len_utf8(),
+            }
+        }
+    }
+
+    /// Push a variable at `i`, returning its length, or 0 if there is none
+    fn variable(&mut self, text: &str, i: usize, end: usize) -> usize {
+        let length = self.variable_length(&text[i..end]);
+        if length > 1 {
+            self.push(i, length, TokenType::Variable);
+            length
+        } else {
+            0
+        }
+    }
+
+    /// Length of `$NAME` or `${...}` at the start of `text`; 1 for a bare `$`
+    fn variable_length(&self, text: &str) -> usize {
+        if text[1..].starts_with('{') {
+            return text.find('}').map_or(text.len(), |p| p + 1);
+        }
+        1 + text[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len() - 1)
+    }
+}
+
+/// End of the word at `i`, treating quoted text as part of the word
+///
+/// Words also end at any of `stops`.
+fn word_end(text: &str, mut i: usize, end: usize, stops: &[char]) -> usize {
+    while i < end {
+        let c = text[i..].chars().next().unwrap_or(' ');
+        match c {
+            c if c.is_whitespace() || stops.contains(&c) => break,
+            '\\' => i = skip_escape(text, i, end),
+            '"' | '\'' => {
+                i = text[i + 1..end].find(c).map_or(end, |p| i + p + 2);
+            }
+            _ => i += c.len_utf8(),
+        }
+    }
+    i
+}
+
+/// Skip a backslash and the character it escapes
+fn skip_escape(text: &str, i: usize, end: usize) -> usize {
+    let escaped = text[i + 1..end].chars().next().map_or(0, char::len_utf8);
+    i + 1 + escaped
+}
+
+/// Parse `<<EOF`, `<<-EOF` or `<<"EOF"`, returning its length, terminator
+/// and whether leading tabs are stripped
+pub fn heredoc_marker(text: &str) -> Option<(usize, String, bool)> {
+    let rest = &text[2..];
+    let (strip_tabs, rest) = match rest.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''));
+    let name_start = usize::from(quote.is_some());
+    let name_len = rest[name_start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len() - name_start);
+    if name_len == 0 {
+        return None;
+    }
+    let name = &rest[name_start..name_start + name_len];
+    let closing = match quote {
+        Some(q) if rest[name_start + name_len..].starts_with(q) => 1,
+        Some(_) => return None,
+        None => 0,
+    };
+    let length = 2 + usize::from(strip_tabs) + name_start + name_len + closing;
+    Some((length, name.to_string(), strip_tabs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(content: &str) -> Vec<(usize, String, TokenType)> {
+        let lines: Vec<&str> = content.lines().collect();
+        super::tokens(content)
+            .into_iter()
+            .map(|t| {
+                let text = lines[t.line][t.start..t.start + t.length].to_string();
+                (t.line, text, t.kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_classification() {
+        use TokenType::*;
+        let content = r#"# syntax
+FROM rust:${VERSION} AS builder
+COPY --from=builder --chown=app /a "/b $HOME"
+CMD ["run", \
+     "--fast"]
+RUN --mount=type=cache,from=builder,target=/c echo 'x $Y'
+"#;
+
+        let expected = vec![
+            (0, "# syntax", Comment),
+            (1, "FROM", Keyword),
+            (1, "${VERSION}", Variable),
+            (1, "AS", Keyword),
+            (1, "builder", Stage),
+            (2, "COPY", Keyword),
+            (2, "--from", Flag),
+            (2, "builder", Stage),
+            (2, "--chown", Flag),
+            (2, "\"/b ", String),
+            (2, "$HOME", Variable),
+            (2, "\"", String),
+            (3, "CMD", Keyword),
+            (3, "[", Operator),
+            (3, "\"run\"", String),
+            (3, ",", Operator),
+            (4, "\"--fast\"", String),
+            (4, "]", Operator),
+            (5, "RUN", Keyword),
+            (5, "--mount", Flag),
+            (5, "builder", Stage),
+            (5, "'x $Y'", String),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(line, text, kind)| (line, text.to_string(), kind))
+            .collect();
+        assert_eq!(tokens(content), expected);
+    }
+
+    #[test]
+    fn test_heredocs() {
+        use TokenType::*;
+        let content =
+            "FROM alpine\nRUN <<EOF\necho $HOME\n\nEOF\nCOPY <<-\"A\" /a\n\tline\n\tA\nUSER app\n";
+
+        let found = tokens(content);
+        assert!(found.contains(&(1, "<<EOF".to_string(), Operator)));
+        assert!(found.contains(&(2, "echo $HOME".to_string(), String)));
+        assert!(found.contains(&(4, "EOF".to_string(), Operator)));
+        assert!(found.contains(&(5, "<<-\"A\"".to_string(), Operator)));
+        assert!(found.contains(&(6, "line".to_string(), String)));
+        assert!(found.contains(&(7, "A".to_string(), Operator)));
+        assert!(found.contains(&(8, "USER".to_string(), Keyword)));
+    }
+
+    #[test]
+    fn test_encode() {
+        let tokens = [
+            Token {
+                line: 0,
+                start: 0,
+                length: 4,
+                kind: TokenType::Keyword,
+            },
+            Token {
+                line: 0,
+                start: 10,
+                length: 2,
+                kind: TokenType::Keyword,
+            },
+            Token {
+                line: 2,
+                start: 4,
+                length: 3,
+                kind: TokenType::Stage,
+            },
+        ];
+        assert_eq!(
+            encode(&tokens),
+            vec![0, 0, 4, 0, 0, 0, 10, 2, 0, 0, 2, 4, 3, 2, 0]
+        );
+    }
+}
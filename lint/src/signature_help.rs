@@ -0,0 +1,146 @@
+//! Signature help for instruction flags
+//!
+//! Built on the flag tables in [`crate::syntax`]. Positions are given in
+//! lines and byte columns; parameters are byte offsets into the signature
+//! label, which is always ASCII.
+
+use crate::syntax::{flags, Flag, MOUNT_OPTIONS};
+
+/// A flag within a signature label, `label[start..end]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub start: usize,
+    pub end: usize,
+    pub documentation: String,
+}
+
+/// The flags of an instruction, with the one being typed active
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub label: String,
+    pub documentation: Option<&'static str>,
+    pub parameters: Vec<Parameter>,
+    /// Index into `parameters`
+    pub active: Option<usize>,
+}
+
+/// Get the flags of the instruction whose flag is being typed
+///
+/// Inside `RUN --mount=` the mount options are shown instead. Returns
+/// `None` unless the cursor is in a flag before the first argument.
+pub fn signature_help(content: &str, line: usize, column: usize) -> Option<Signature> {
+    let lines: Vec<&str> = content.lines().collect();
+    let current = lines.get(line)?;
+
+    // The instruction starts after the last line not ending in a backslash
+    let start = (0..line)
+        .rev()
+        .take_while(|&l| lines[l].trim_end().ends_with('\\'))
+        .last()
+        .unwrap_or(line);
+
+    // The instruction up to the cursor, with continuations joined
+    let mut text: String = lines[start..line]
+        .iter()
+        .map(|l| format!("{} ", l.trim_end().trim_end_matches('\\')))
+        .collect();
+    text.push_str(current.get(..column).unwrap_or(current));
+
+    // Every word before the one being typed must be a flag
+    if text.ends_with(char::is_whitespace) {
+        return None;
+    }
+    let mut words = text.split_whitespace();
+    let keyword = words.next()?.to_uppercase();
+    let flags = flags(&keyword);
+    if flags.is_empty() {
+        return None;
+    }
+    let words: Vec<&str> = words.collect();
+    let (typed, previous) = words.split_last()?;
+    if !typed.starts_with("--") || previous.iter().any(|w| !w.starts_with("--")) {
+        return None;
+    }
+
+    let typed = &typed[2..];
+    if let Some(options) = typed.strip_prefix("mount=").filter(|_| keyword == "RUN") {
+        let option = options.rsplit(',').next().unwrap_or("");
+        return Some(signature("--mount=", ",", MOUNT_OPTIONS, option));
+    }
+
+    let prefix = format!("{} ", keyword);
+    Some(signature(&prefix, " ", flags, &format!("--{}", typed)))
+}
+
+/// Build a signature listing `flags`, with the one being typed active
+fn signature(prefix: &str, separator: &str, flags: &[Flag], typed: &str) -> Signature {
+    let mut label = prefix.to_string();
+    let mut parameters = Vec::new();
+    for (index, flag) in flags.iter().enumerate() {
+        if index > 0 {
+            label.push_str(separator);
+        }
+        let start = label.len();
+        label.push_str(&flag.label());
+        let documentation = match flag.default {
+            Some(default) => format!("{} (default: {})", flag.documentation, default),
+            None => flag.documentation.to_string(),
+        };
+        parameters.push(Parameter {
+            start,
+            end: label.len(),
+            documentation,
+        });
+    }
+
+    // The exact flag once its name is complete, otherwise the first match
+    let name = typed.split('=').next().unwrap_or(typed);
+    let active = flags
+        .iter()
+        .position(|flag| flag.name == name)
+        .or_else(|| flags.iter().position(|flag| flag.name.starts_with(name)));
+
+    Signature {
+        label,
+        documentation: active.map(|i| flags[i].documentation),
+        parameters,
+        active,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active_label(signature: &Signature) -> &str {
+        let parameter = &signature.parameters[signature.active.unwrap()];
+        &signature.label[parameter.start..parameter.end]
+    }
+
+    #[test]
+    fn test_instruction_flags() {
+        let copy = signature_help("FROM alpine\nCOPY --ch", 1, 9).unwrap();
+        assert!(copy.label.starts_with("COPY --from="));
+        assert_eq!(active_label(&copy), "--chown=<user>:<group>");
+
+        let healthcheck = signature_help("HEALTHCHECK --interval=5s --ret", 0, 31).unwrap();
+        assert_eq!(active_label(&healthcheck), "--retries=<n>");
+        assert_eq!(
+            healthcheck.parameters[0].documentation,
+            "Time between health checks (default: 30s)"
+        );
+
+        // Not in flag position
+        assert!(signature_help("FROM alpine\nCOPY a --ch", 1, 11).is_none());
+        assert!(signature_help("FROM alpine\nCOPY --link ", 1, 12).is_none());
+        assert!(signature_help("FROM alpine\nUSER --x", 1, 8).is_none());
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let content = "FROM alpine\nRUN --network=none \\\n    --mount=type=cache,tar";
+        let mount = signature_help(content, 2, 26).unwrap();
+        assert!(mount.label.starts_with("--mount=type="));
+        assert_eq!(active_label(&mount), "target=<path>");
+    }
+}
@@ -0,0 +1,239 @@
+//! Instruction metadata
+//!
+//! Instruction keywords, the flags of each instruction, the options of
+//! `RUN --mount=` and the predefined build arguments, shared by the
+//! language servers.
+
+/// All instruction keywords
+pub const KEYWORDS: &[&str] = &[
+    "FROM",
+    "RUN",
+    "CMD",
+    "LABEL",
+    "EXPOSE",
+    "ENV",
+    "ADD",
+    "COPY",
+    "ENTRYPOINT",
+    "VOLUME",
+    "USER",
+    "WORKDIR",
+    "ARG",
+    "ONBUILD",
+    "STOPSIGNAL",
+    "HEALTHCHECK",
+    "SHELL",
+    "MAINTAINER",
+];
+
+/// Flags accepted by an instruction, given its upper-cased keyword
+pub fn flags(keyword: &str) -> &'static [Flag] {
+    match keyword {
+        "FROM" => FROM_FLAGS,
+        "RUN" => RUN_FLAGS,
+        "COPY" => COPY_FLAGS,
+        "ADD" => ADD_FLAGS,
+        "HEALTHCHECK" => HEALTHCHECK_FLAGS,
+        _ => &[],
+    }
+}
+
+/// An instruction flag, or an option of `RUN --mount=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flag {
+    /// Name, with the leading dashes for flags
+    pub name: &'static str,
+    /// Format of the value, empty for switches
+    pub value: &'static str,
+    /// Value used when the flag is left out, if any
+    pub default: Option<&'static str>,
+    /// What the flag does
+    pub documentation: &'static str,
+}
+
+impl Flag {
+    const fn new(name: &'static str, value: &'static str, documentation: &'static str) -> Self {
+        Self {
+            name,
+            value,
+            default: None,
+            documentation,
+        }
+    }
+
+    const fn with_default(mut self, default: &'static str) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    /// Flag as written, e.g. `--chown=<user>:<group>`
+    pub fn label(&self) -> String {
+        if self.value.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{}={}", self.name, self.value)
+        }
+    }
+}
+
+const FROM_FLAGS: &[Flag] = &[Flag::new(
+    "--platform",
+    "<os>/<arch>",
+    "Platform of the base image, e.g. linux/amd64",
+)];
+
+const RUN_FLAGS: &[Flag] = &[
+    Flag::new(
+        "--mount",
+        "type=<type>[,<option>=<value>...]",
+        "Mount a cache, secret, SSH agent, tmpfs or bind source for this command",
+    ),
+    Flag::new(
+        "--network",
+        "default|none|host",
+        "Network the command runs in",
+    )
+    .with_default("default"),
+    Flag::new(
+        "--security",
+        "sandbox|insecure",
+        "Run with or without the default sandbox",
+    )
+    .with_default("sandbox"),
+];
+
+const COPY_FLAGS: &[Flag] = &[
+    Flag::new(
+        "--from",
+        "<stage|image|index>",
+        "Copy files from a build stage or image",
+    ),
+    Flag::new(
+        "--chown",
+        "<user>:<group>",
+        "Set user:group ownership of copied files",
+    ),
+    Flag::new("--chmod", "<perms>", "Set permissions of copied files"),
+    Flag::new(
+        "--link",
+        "",
+        "Copy into a separate layer that survives base changes",
+    ),
+    Flag::new("--parents", "", "Keep the parent directories of sources"),
+    Flag::new(
+        "--exclude",
+        "<pattern>",
+        "Leave out paths matching a pattern",
+    ),
+];
+
+const ADD_FLAGS: &[Flag] = &[
+    Flag::new(
+        "--chown",
+        "<user>:<group>",
+        "Set user:group ownership of added files",
+    ),
+    Flag::new("--chmod", "<perms>", "Set permissions of added files"),
+    Flag::new(
+        "--checksum",
+        "sha256:<digest>",
+        "Verify the checksum of a remote source",
+    ),
+    Flag::new(
+        "--keep-git-dir",
+        "<bool>",
+        "Keep the .git directory of a Git source",
+    )
+    .with_default("false"),
+    Flag::new(
+        "--link",
+        "",
+        "Add into a separate layer that survives base changes",
+    ),
+    Flag::new(
+        "--exclude",
+        "<pattern>",
+        "Leave out paths matching a pattern",
+    ),
+];
+
+const HEALTHCHECK_FLAGS: &[Flag] = &[
+    Flag::new("--interval", "<duration>", "Time between health checks").with_default("30s"),
+    Flag::new("--timeout", "<duration>", "Health check timeout").with_default("30s"),
+    Flag::new(
+        "--start-period",
+        "<duration>",
+        "Initialization grace period",
+    )
+    .with_default("0s"),
+    Flag::new(
+        "--start-interval",
+        "<duration>",
+        "Time between checks during the start period",
+    )
+    .with_default("5s"),
+    Flag::new("--retries", "<n>", "Consecutive failures needed").with_default("3"),
+];
+
+/// Build arguments every build defines, with their descriptions
+pub const PREDEFINED_ARGS: &[(&str, &str)] = &[
+    (
+        "BUILDPLATFORM",
+        "Platform of the build host, e.g. linux/amd64",
+    ),
+    ("BUILDOS", "OS of the build host"),
+    ("BUILDARCH", "Architecture of the build host"),
+    ("BUILDVARIANT", "CPU variant of the build host"),
+    (
+        "TARGETPLATFORM",
+        "Platform being built for, e.g. linux/arm64",
+    ),
+    ("TARGETOS", "OS being built for"),
+    ("TARGETARCH", "Architecture being built for"),
+    ("TARGETVARIANT", "CPU variant being built for"),
+    ("HTTP_PROXY", "Proxy for HTTP requests"),
+    ("HTTPS_PROXY", "Proxy for HTTPS requests"),
+    ("NO_PROXY", "Hosts that bypass the proxy"),
+];
+
+/// Options of `RUN --mount=`
+pub const MOUNT_OPTIONS: &[Flag] = &[
+    Flag::new("type", "bind|cache|tmpfs|secret|ssh", "Kind of mount").with_default("bind"),
+    Flag::new("target", "<path>", "Where the mount appears"),
+    Flag::new("source", "<path>", "Path within the `from` source"),
+    Flag::new("from", "<stage|image>", "Stage or image to mount from"),
+    Flag::new("id", "<id>", "Cache, secret or SSH agent ID"),
+    Flag::new(
+        "sharing",
+        "shared|private|locked",
+        "How concurrent builds use a cache",
+    )
+    .with_default("shared"),
+    Flag::new("readonly", "", "Mount read-only"),
+    Flag::new("mode", "<perms>", "Permissions of the cache or secret"),
+    Flag::new("uid", "<uid>", "Owner user ID"),
+    Flag::new("gid", "<gid>", "Owner group ID"),
+    Flag::new(
+        "required",
+        "<bool>",
+        "Fail if the secret or agent is missing",
+    ),
+    Flag::new(
+        "env",
+        "<name>",
+        "Expose a secret as an environment variable",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(flags("COPY")[0].label(), "--from=<stage|image|index>");
+        assert_eq!(flags("HEALTHCHECK")[0].default, Some("30s"));
+        assert_eq!(flags("COPY")[3].label(), "--link");
+        assert!(flags("USER").is_empty());
+    }
+}
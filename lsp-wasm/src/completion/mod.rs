@@ -1,12 +1,27 @@
 //! Code completion for Runefile LSP
 
 use crate::parser::types::*;
+use crate::position::byte_column;
+use runefile_lint::syntax::PREDEFINED_ARGS;
 use wasm_bindgen::prelude::*;
 
 /// Completion kind constants (LSP spec)
 pub const COMPLETION_KIND_KEYWORD: u8 = 14;
 pub const COMPLETION_KIND_SNIPPET: u8 = 15;
 pub const COMPLETION_KIND_VALUE: u8 = 12;
+pub const COMPLETION_KIND_VARIABLE: u8 = 6;
+pub const COMPLETION_KIND_CLASS: u8 = 7;
+pub const COMPLETION_KIND_PROPERTY: u8 = 10;
+
+/// A variable declared with ARG or ENV
+pub(crate) struct Variable {
+    pub name: String,
+    /// The default or value, if declared with one
+    pub value: Option<String>,
+    /// `ARG` or `ENV`
+    pub source: &'static str,
+    pub line: usize,
+}
 
 /// Completion provider for Runefile
 #[wasm_bindgen]
@@ -20,9 +35,17 @@ impl CompletionProvider {
         Self
     }
 
-    /// Get completions at position (works offline)
+    /// Get completions at position as JSON (works offline)
     #[wasm_bindgen(js_name = getCompletions)]
     pub fn get_completions(&self, content: &str, line: u32, character: u32) -> String {
+        serde_json::to_string(&self.completions(content, line, character))
+            .unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+impl CompletionProvider {
+    /// Get completions at a position
+    pub fn completions(&self, content: &str, line: u32, character: u32) -> Vec<CompletionItem> {
         let lines: Vec<&str> = content.lines().collect();
 
         if (line as usize) >= lines.len() {
@@ -30,14 +53,16 @@ impl CompletionProvider {
         }
 
         let current_line = lines[line as usize];
-        let prefix = if (character as usize) <= current_line.len() {
-            &current_line[..character as usize]
-        } else {
-            current_line
-        };
+        let prefix = &current_line[..byte_column(current_line, character)];
 
         let trimmed = prefix.trim();
 
+        // Variable references in any instruction
+        if let Some((name, braced)) = variable_prefix(prefix) {
+            let closed = current_line[prefix.len()..].starts_with('}');
+            return self.get_variable_completions(content, line as usize, name, braced && !closed);
+        }
+
         // At start of line or after whitespace - suggest instructions
         if trimmed.is_empty() || prefix.ends_with(' ') && trimmed.len() < 3 {
             return self.get_instruction_completions();
@@ -61,11 +86,62 @@ impl CompletionProvider {
             "ENV" => self.get_env_completions(),
             "HEALTHCHECK" => self.get_healthcheck_completions(),
             "CMD" | "ENTRYPOINT" => self.get_cmd_completions(),
-            _ => "[]".to_string(),
+            _ => Vec::new(),
         }
     }
 
-    fn get_instruction_completions(&self) -> String {
+    /// ARG and ENV names declared before a line, and the predefined build
+    /// arguments
+    fn get_variable_completions(
+        &self,
+        content: &str,
+        line: usize,
+        prefix: &str,
+        close_brace: bool,
+    ) -> Vec<CompletionItem> {
+        let variables = variables_before(content, line);
+        let declared = variables.iter().map(|variable| {
+            let detail = match &variable.value {
+                Some(value) => format!("{} {}={}", variable.source, variable.name, value),
+                None => format!("{} {}", variable.source, variable.name),
+            };
+            (
+                variable.name.as_str(),
+                detail,
+                format!("Declared on line {}", variable.line + 1),
+            )
+        });
+        let predefined = PREDEFINED_ARGS
+            .iter()
+            .filter(|(name, _)| !variables.iter().any(|v| v.name == *name))
+            .map(|(name, description)| {
+                (
+                    *name,
+                    "Predefined build argument".to_string(),
+                    description.to_string(),
+                )
+            });
+
+        let completions: Vec<CompletionItem> = declared
+            .chain(predefined)
+            .filter(|(name, _, _)| name.starts_with(prefix))
+            .map(|(name, detail, documentation)| CompletionItem {
+                label: name.to_string(),
+                kind: COMPLETION_KIND_VARIABLE,
+                detail: Some(detail),
+                documentation: Some(documentation),
+                insert_text: Some(if close_brace {
+                    format!("{}}}", name)
+                } else {
+                    name.to_string()
+                }),
+                insert_text_format: Some(1),
+            })
+            .collect();
+        completions
+    }
+
+    fn get_instruction_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.instruction_completion("FROM", "Base image", "FROM ${1:image}:${2:tag}"),
             self.instruction_completion("RUN", "Execute command", "RUN ${1:command}"),
@@ -97,20 +173,19 @@ impl CompletionProvider {
             self.instruction_completion("STOPSIGNAL", "Stop signal", "STOPSIGNAL ${1:SIGTERM}"),
         ];
 
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_filtered_instruction_completions(&self, prefix: &str) -> String {
-        let all: Vec<CompletionItem> =
-            serde_json::from_str(&self.get_instruction_completions()).unwrap_or_default();
+    fn get_filtered_instruction_completions(&self, prefix: &str) -> Vec<CompletionItem> {
+        let all: Vec<CompletionItem> = self.get_instruction_completions();
         let filtered: Vec<CompletionItem> = all
             .into_iter()
             .filter(|c| c.label.to_uppercase().starts_with(prefix))
             .collect();
-        serde_json::to_string(&filtered).unwrap_or_else(|_| "[]".to_string())
+        filtered
     }
 
-    fn get_from_completions(&self) -> String {
+    fn get_from_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.value_completion("alpine", "Minimal Linux", "alpine:${1:latest}"),
             self.value_completion("ubuntu", "Ubuntu Linux", "ubuntu:${1:22.04}"),
@@ -122,10 +197,10 @@ impl CompletionProvider {
             self.value_completion("nginx", "Nginx", "nginx:${1:alpine}"),
             self.value_completion("scratch", "Empty image", "scratch"),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_run_completions(&self) -> String {
+    fn get_run_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.snippet_completion(
                 "apt-get install",
@@ -151,10 +226,10 @@ impl CompletionProvider {
             self.snippet_completion("chmod", "Change permissions", "chmod +x ${1:file}"),
             self.snippet_completion("mkdir", "Create directory", "mkdir -p ${1:/app}"),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_copy_completions(&self) -> String {
+    fn get_copy_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.snippet_completion(
                 "--from",
@@ -174,10 +249,10 @@ impl CompletionProvider {
                 "requirements.txt .",
             ),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_expose_completions(&self) -> String {
+    fn get_expose_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.value_completion("80", "HTTP", "80"),
             self.value_completion("443", "HTTPS", "443"),
@@ -186,10 +261,10 @@ impl CompletionProvider {
             self.value_completion("8080", "Alt HTTP", "8080"),
             self.value_completion("8000", "Django default", "8000"),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_env_completions(&self) -> String {
+    fn get_env_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.snippet_completion("PATH", "Add to PATH", "PATH=\"/app/bin:$PATH\""),
             self.snippet_completion("NODE_ENV", "Node environment", "NODE_ENV=${1:production}"),
@@ -200,10 +275,10 @@ impl CompletionProvider {
             ),
             self.snippet_completion("RUST_LOG", "Rust logging", "RUST_LOG=${1:info}"),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_healthcheck_completions(&self) -> String {
+    fn get_healthcheck_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.snippet_completion("NONE", "Disable healthcheck", "NONE"),
             self.snippet_completion(
@@ -232,10 +307,10 @@ impl CompletionProvider {
                 "--retries=${1:3} CMD ${2:command}",
             ),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
-    fn get_cmd_completions(&self) -> String {
+    fn get_cmd_completions(&self) -> Vec<CompletionItem> {
         let completions = vec![
             self.snippet_completion(
                 "exec form",
@@ -244,7 +319,7 @@ impl CompletionProvider {
             ),
             self.snippet_completion("shell form", "Shell form", "${1:command} ${2:args}"),
         ];
-        serde_json::to_string(&completions).unwrap_or_else(|_| "[]".to_string())
+        completions
     }
 
     fn instruction_completion(&self, label: &str, detail: &str, insert: &str) -> CompletionItem {
@@ -281,6 +356,76 @@ impl CompletionProvider {
     }
 }
 
+/// The variable name typed so far after `$` or `${`, and whether it is
+/// braced
+fn variable_prefix(before_cursor: &str) -> Option<(&str, bool)> {
+    let head = before_cursor.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    let prefix = &before_cursor[head.len()..];
+    if let Some(head) = head.strip_suffix("${") {
+        (!head.ends_with('\\')).then_some((prefix, true))
+    } else if let Some(head) = head.strip_suffix('$') {
+        (!head.ends_with('\\')).then_some((prefix, false))
+    } else {
+        None
+    }
+}
+
+/// Variables declared by instructions ending before a line, in order of
+/// their latest declaration
+pub(crate) fn variables_before(content: &str, line: usize) -> Vec<Variable> {
+    let mut variables: Vec<Variable> = Vec::new();
+    let mut instruction = String::new();
+    let mut start = 0;
+
+    for (line_num, text) in content.lines().enumerate().take(line) {
+        let trimmed = text.trim();
+        if instruction.is_empty() {
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            start = line_num;
+        }
+        if let Some(continued) = trimmed.strip_suffix('\\') {
+            instruction.push_str(continued);
+            instruction.push(' ');
+            continue;
+        }
+        instruction.push_str(trimmed);
+
+        let text = std::mem::take(&mut instruction);
+        let (keyword, arguments) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+        let source = match keyword.to_uppercase().as_str() {
+            "ARG" => "ARG",
+            "ENV" => "ENV",
+            _ => continue,
+        };
+        let arguments = arguments.trim();
+        let pairs: Vec<(&str, Option<&str>)> = match arguments.split_once(char::is_whitespace) {
+            // Legacy `ENV KEY value`
+            Some((name, value)) if source == "ENV" && !name.contains('=') => {
+                vec![(name, Some(value.trim()))]
+            }
+            _ => arguments
+                .split_whitespace()
+                .map(|word| match word.split_once('=') {
+                    Some((name, value)) => (name, Some(value.trim_matches(['"', '\'']))),
+                    None => (word, None),
+                })
+                .collect(),
+        };
+        for (name, value) in pairs {
+            variables.retain(|v| v.name != name);
+            variables.push(Variable {
+                name: name.to_string(),
+                value: value.map(str::to_string),
+                source,
+                line: start,
+            });
+        }
+    }
+    variables
+}
+
 impl Default for CompletionProvider {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,161 @@
+//! Compose file support for Runefile LSP
+//!
+//! Completion, hover and validation come from `runefile-lint`, shared with
+//! the native server; this module converts positions to UTF-16.
+
+use crate::completion::{COMPLETION_KIND_CLASS, COMPLETION_KIND_PROPERTY, COMPLETION_KIND_VALUE};
+use crate::parser::types::*;
+use crate::position::{byte_column, line_text, utf16_diagnostics, utf16_range};
+use runefile_lint::compose::{self, CompletionKind, Problem};
+use runefile_lint::ranges::Span;
+use wasm_bindgen::prelude::*;
+
+/// Compose file provider
+#[wasm_bindgen]
+pub struct ComposeProvider;
+
+#[wasm_bindgen]
+impl ComposeProvider {
+    /// Create a new compose provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Whether a document URI names a compose file
+    #[wasm_bindgen(js_name = isComposeFile)]
+    pub fn is_compose_file(&self, uri: &str) -> bool {
+        compose::is_compose_file(uri)
+    }
+
+    /// Get completions as JSON (works offline)
+    #[wasm_bindgen(js_name = getCompletions)]
+    pub fn get_completions_json(&self, content: &str, line: u32, character: u32) -> String {
+        serde_json::to_string(&self.get_completions(content, line as usize, character as usize))
+            .unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Get hover documentation as JSON (works offline)
+    #[wasm_bindgen(js_name = getHover)]
+    pub fn get_hover_json(&self, content: &str, line: u32, character: u32) -> String {
+        self.get_hover(content, line as usize, character as usize)
+            .and_then(|hover| serde_json::to_string(&hover).ok())
+            .unwrap_or_else(|| "null".to_string())
+    }
+
+    /// Get diagnostics as JSON (works offline)
+    #[wasm_bindgen(js_name = getDiagnostics)]
+    pub fn get_diagnostics_json(&self, content: &str) -> String {
+        serde_json::to_string(&self.get_diagnostics(content)).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+impl ComposeProvider {
+    /// Get completions for keys, service names and known values
+    ///
+    /// Positions here and in the results are in UTF-16 code units.
+    pub fn get_completions(
+        &self,
+        content: &str,
+        line: usize,
+        character: usize,
+    ) -> Vec<CompletionItem> {
+        let column = byte_column(line_text(content, line as u32), character as u32);
+        compose::completions(content, line, column)
+            .into_iter()
+            .map(|completion| {
+                let (kind, detail, insert_text) = match completion.kind {
+                    CompletionKind::Key => (
+                        COMPLETION_KIND_PROPERTY,
+                        None,
+                        Some(format!("{}: ", completion.label)),
+                    ),
+                    CompletionKind::Service => {
+                        (COMPLETION_KIND_CLASS, Some("Service".to_string()), None)
+                    }
+                    CompletionKind::Value => (COMPLETION_KIND_VALUE, None, None),
+                };
+                CompletionItem {
+                    label: completion.label,
+                    kind,
+                    detail,
+                    documentation: completion.documentation.map(str::to_string),
+                    insert_text,
+                    insert_text_format: Some(1),
+                }
+            })
+            .collect()
+    }
+
+    /// Get hover documentation for a key
+    pub fn get_hover(&self, content: &str, line: usize, character: usize) -> Option<HoverResult> {
+        let column = byte_column(line_text(content, line as u32), character as u32);
+        let (documentation, span) = compose::hover(content, line, column)?;
+        Some(HoverResult {
+            contents: documentation,
+            range: Some(utf16_range(content, range(span))),
+        })
+    }
+
+    /// Get diagnostics for a compose file
+    pub fn get_diagnostics(&self, content: &str) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = compose::validate(content)
+            .into_iter()
+            .map(diagnostic)
+            .collect();
+        utf16_diagnostics(content, &mut diagnostics);
+        diagnostics
+    }
+}
+
+impl Default for ComposeProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn range(span: Span) -> Range {
+    Range {
+        start: Position {
+            line: span.start_line as u32,
+            character: span.start as u32,
+        },
+        end: Position {
+            line: span.end_line as u32,
+            character: span.end as u32,
+        },
+    }
+}
+
+fn diagnostic(problem: Problem) -> Diagnostic {
+    Diagnostic {
+        range: range(problem.span),
+        severity: problem.severity.lsp_code(),
+        code: None,
+        message: problem.message,
+        source: "runefile-lsp".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_positions() {
+        let provider = ComposeProvider::new();
+        // 'é' is two bytes and one UTF-16 unit
+        let content = "services:\n  é:\n    image: x\n    depends_on: [db]\n";
+        let diagnostics = provider.get_diagnostics(content);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, 1);
+
+        let content = "services:\n  web:\n    restart: é";
+        let hover = provider.get_hover(content, 2, 6).unwrap();
+        assert_eq!(hover.range.unwrap().start.character, 4);
+        let items = provider.get_completions("x: é\nservices:\n  web:\n    ", 3, 4);
+        assert!(items.iter().any(|item| item.label == "image"
+            && item.kind == COMPLETION_KIND_PROPERTY
+            && item.insert_text.as_deref() == Some("image: ")));
+    }
+}
@@ -0,0 +1,71 @@
+//! Document formatting for Runefile LSP
+//!
+//! The formatter is shared with the native server through `runefile-lint`.
+
+use crate::parser::types::*;
+use runefile_lint::formatting::{format, Style};
+use wasm_bindgen::prelude::*;
+
+/// Formatting provider for Runefile
+#[wasm_bindgen]
+pub struct FormattingProvider;
+
+#[wasm_bindgen]
+impl FormattingProvider {
+    /// Create a new formatting provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Format a document with the default style (works offline)
+    #[wasm_bindgen]
+    pub fn format(&self, content: &str) -> String {
+        self.format_with(content, &FormatOptions::default())
+    }
+
+    /// Format a document with options given as JSON (works offline)
+    #[wasm_bindgen(js_name = formatWithOptions)]
+    pub fn format_with_options(&self, content: &str, options: &str) -> Result<String, JsValue> {
+        let options: FormatOptions =
+            serde_json::from_str(options).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(self.format_with(content, &options))
+    }
+}
+
+impl FormattingProvider {
+    /// Format a document
+    pub fn format_with(&self, content: &str, options: &FormatOptions) -> String {
+        let style = Style {
+            indent: " ".repeat(options.continuation_indent),
+            align_continuations: options.align_continuations,
+            sort_keys: options.sort_keys,
+            normalize_json: options.normalize_json,
+        };
+        format(content, &style)
+    }
+}
+
+impl Default for FormattingProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_with_options() {
+        let options = r#"{"continuationIndent": 2, "alignContinuations": true, "sortKeys": true, "normalizeJson": false}"#;
+        let content =
+            "LABEL b=2 a=\"x y\"\nRUN make \\\n  install && \\\nclean\nENTRYPOINT [\"a\",\"b\"]";
+        assert_eq!(
+            FormattingProvider::new()
+                .format_with_options(content, options)
+                .unwrap(),
+            "LABEL a=\"x y\" \\\n  b=2\nRUN make     \\\n  install && \\\n  clean\nENTRYPOINT [\"a\",\"b\"]"
+        );
+    }
+}
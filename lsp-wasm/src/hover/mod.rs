@@ -1,6 +1,9 @@
 //! Hover documentation for Runefile LSP
 
+use crate::completion::variables_before;
 use crate::parser::types::*;
+use crate::position::{byte_column, line_text, utf16_range};
+use runefile_lint::syntax::PREDEFINED_ARGS;
 use wasm_bindgen::prelude::*;
 
 /// Hover provider for Runefile
@@ -15,33 +18,50 @@ impl HoverProvider {
         Self
     }
 
-    /// Get hover information at position (works offline)
+    /// Get hover information at position as JSON (works offline)
     #[wasm_bindgen(js_name = getHover)]
     pub fn get_hover(&self, content: &str, line: u32, character: u32) -> String {
-        let lines: Vec<&str> = content.lines().collect();
+        self.hover(content, line, character)
+            .and_then(|hover| serde_json::to_string(&hover).ok())
+            .unwrap_or_else(|| "null".to_string())
+    }
+}
 
-        if (line as usize) >= lines.len() {
-            return "null".to_string();
-        }
+impl HoverProvider {
+    /// Get hover information at a position
+    pub fn hover(&self, content: &str, line: u32, character: u32) -> Option<HoverResult> {
+        let column = byte_column(line_text(content, line), character);
+        let mut hover = self.hover_at(content, line, column)?;
+        hover.range = hover.range.map(|range| utf16_range(content, range));
+        Some(hover)
+    }
+
+    /// Get hover information at a byte column
+    fn hover_at(&self, content: &str, line: u32, column: usize) -> Option<HoverResult> {
+        let lines: Vec<&str> = content.lines().collect();
+        let current_line = *lines.get(line as usize)?;
 
-        let current_line = lines[line as usize];
         let trimmed = current_line.trim();
 
         // Skip comments and empty lines
         if trimmed.is_empty() || trimmed.starts_with('#') {
-            return "null".to_string();
+            return None;
+        }
+
+        if let Some(hover) = self.get_variable_hover(content, line, column) {
+            return Some(hover);
         }
 
         // Get the word at cursor position
-        let word = self.get_word_at_position(current_line, character as usize);
+        let word = self.get_word_at_position(current_line, column);
 
         // Check if it's an instruction keyword
         let parts: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
-        let instruction = parts.get(0).unwrap_or(&"").to_uppercase();
+        let instruction = parts.first().unwrap_or(&"").to_uppercase();
 
         // If cursor is on the instruction keyword
         if let Some(doc) = self.get_instruction_documentation(&instruction) {
-            let result = HoverResult {
+            return Some(HoverResult {
                 contents: doc,
                 range: Some(Range {
                     start: Position { line, character: 0 },
@@ -50,40 +70,73 @@ impl HoverProvider {
                         character: instruction.len() as u32,
                     },
                 }),
-            };
-            return serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string());
+            });
         }
 
         // Check for common patterns in arguments
-        if let Some(doc) = self.get_pattern_documentation(&word) {
-            let result = HoverResult {
+        self.get_pattern_documentation(&word)
+            .map(|doc| HoverResult {
                 contents: doc,
                 range: None,
-            };
-            return serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string());
-        }
+            })
+    }
 
-        "null".to_string()
+    /// Show where a `$VAR` reference was declared, and its value
+    fn get_variable_hover(&self, content: &str, line: u32, column: usize) -> Option<HoverResult> {
+        let text = content.lines().nth(line as usize)?;
+        let (start, name) = variable_at(text, column)?;
+
+        let contents = match variables_before(content, line as usize)
+            .into_iter()
+            .rfind(|v| v.name == name)
+        {
+            Some(variable) => {
+                let declaration = match &variable.value {
+                    Some(value) => format!("{} {}={}", variable.source, variable.name, value),
+                    None => format!("{} {}", variable.source, variable.name),
+                };
+                format!(
+                    "```dockerfile\n{}\n```\n\nDeclared on line {}",
+                    declaration,
+                    variable.line + 1
+                )
+            }
+            None => {
+                let (_, description) = PREDEFINED_ARGS.iter().find(|(n, _)| *n == name)?;
+                format!("`{}`: {}\n\nPredefined build argument", name, description)
+            }
+        };
+
+        Some(HoverResult {
+            contents,
+            range: Some(Range {
+                start: Position {
+                    line,
+                    character: start as u32,
+                },
+                end: Position {
+                    line,
+                    character: (start + name.len()) as u32,
+                },
+            }),
+        })
     }
 
     fn get_word_at_position(&self, line: &str, position: usize) -> String {
-        let chars: Vec<char> = line.chars().collect();
-        if position >= chars.len() {
+        if position >= line.len() {
             return String::new();
         }
-
-        let mut start = position;
-        let mut end = position;
+        let is_boundary = |c: char| c.is_whitespace() || c == '=';
 
         // Find word boundaries
-        while start > 0 && !chars[start - 1].is_whitespace() && chars[start - 1] != '=' {
-            start -= 1;
-        }
-        while end < chars.len() && !chars[end].is_whitespace() && chars[end] != '=' {
-            end += 1;
-        }
-
-        chars[start..end].iter().collect()
+        let start = line[..position].rfind(is_boundary).map_or(0, |i| {
+            i + line[i..].chars().next().map_or(1, char::len_utf8)
+        });
+        let end = line[position..]
+            .find(is_boundary)
+            .map_or(line.len(), |i| position + i);
+
+        line[start..end].to_string()
     }
 
     fn get_instruction_documentation(&self, instruction: &str) -> Option<String> {
@@ -438,6 +491,33 @@ STOPSIGNAL 9
     }
 }
 
+/// The start and name of the `$NAME` or `${NAME}` reference at a column
+pub(crate) fn variable_at(text: &str, column: usize) -> Option<(usize, &str)> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' => {
+                let start = i + if bytes.get(i + 1) == Some(&b'{') {
+                    2
+                } else {
+                    1
+                };
+                let len = text[start..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(text.len() - start);
+                if len > 0 && (i..=start + len).contains(&column) {
+                    return Some((start, &text[start..start + len]));
+                }
+                i = start + len;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
 impl Default for HoverProvider {
     fn default() -> Self {
         Self::new()
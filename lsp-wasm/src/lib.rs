@@ -7,8 +7,19 @@
 //!
 //! - **Code Completion**: Context-aware completions for instructions and arguments
 //! - **Hover Documentation**: Detailed docs for all Dockerfile/Runefile instructions
-//! - **Diagnostics**: Real-time error and warning detection
-//! - **Formatting**: Basic code formatting
+//! - **Signature Help**: Available flags and `RUN --mount=` options while typing them
+//! - **Diagnostics**: Real-time error and warning detection, including the
+//!   Rune lint rules configured with `setLintConfig` or `setConfiguration`
+//! - **Folding and Selection Ranges**: Fold stages, continuations, heredocs
+//!   and comment blocks; expand the selection from a word to the document
+//! - **Semantic Tokens**: Highlighting of keywords, flags, stages, variables,
+//!   strings and JSON arrays, including line continuations and heredocs
+//! - **Compose Files**: Key completion and hover, `depends_on` and port
+//!   validation for `compose.yaml` documents
+//! - **Document Symbols and Definitions**: An outline of stages with their
+//!   ARG and ENV declarations; go to the stage or variable under the cursor
+//! - **Formatting**: Continuation indentation and alignment, sorted LABEL
+//!   and ENV keys and normalized JSON arrays; heredocs are left untouched
 //!
 //! ## Offline Usage (No Server Required)
 //!
@@ -29,8 +40,21 @@
 //! // Get hover for content
 //! const hover = lsp.getHoverForContent('FROM alpine', 0, 0);
 //!
+//! // Get semantic tokens for content
+//! const tokens = lsp.getSemanticTokensForContent('FROM alpine AS base');
+//!
+//! // Get the outline and stage navigation for content
+//! const symbols = lsp.getDocumentSymbolsForContent('FROM rust AS build');
+//!
 //! // Format content
 //! const formatted = lsp.format('from alpine\nrun echo hello');
+//! const aligned = lsp.formatWithOptions(content, '{"alignContinuations": true}');
+//!
+//! // Configure lint rules and the formatting style
+//! lsp.setConfiguration('{"maxLineLength": 120, "disabledRules": ["RUNE1005"]}');
+//!
+//! // Every JSON method has an `...Object` twin returning a typed object
+//! const items = lsp.getCompletionsForContentObject('FROM alp', 0, 8);
 //!
 //! // Or work with documents
 //! lsp.openDocument('file:///Runefile', content, 1);
@@ -38,12 +62,25 @@
 //! ```
 
 pub mod completion;
+pub mod compose;
+pub mod formatting;
 pub mod hover;
 pub mod parser;
+pub mod position;
+pub mod ranges;
+pub mod semantic_tokens;
 pub mod server;
+pub mod signature_help;
+pub mod symbols;
 
 // Re-export main types
 pub use completion::CompletionProvider;
+pub use compose::ComposeProvider;
+pub use formatting::FormattingProvider;
 pub use hover::HoverProvider;
 pub use parser::{types::*, RunefileParser};
+pub use ranges::RangeProvider;
+pub use semantic_tokens::SemanticTokensProvider;
 pub use server::RunefileLspServer;
+pub use signature_help::SignatureHelpProvider;
+pub use symbols::SymbolProvider;
@@ -4,6 +4,7 @@ pub mod types;
 
 pub use types::*;
 
+use runefile_lint::syntax::flags;
 use wasm_bindgen::prelude::*;
 
 /// Runefile parser
@@ -13,8 +14,12 @@ pub struct RunefileParser {
     pub instructions: Vec<Instruction>,
     #[wasm_bindgen(skip)]
     pub errors: Vec<ParseError>,
+    /// First line of a line continuation that runs to the end of the file
+    unclosed_continuation: Option<usize>,
 }
 
+const MISSING_FROM: &str = "Runefile must start with FROM instruction";
+
 #[wasm_bindgen]
 impl RunefileParser {
     /// Create a new parser
@@ -23,72 +28,171 @@ impl RunefileParser {
         Self {
             instructions: Vec::new(),
             errors: Vec::new(),
+            unclosed_continuation: None,
         }
     }
 
     /// Parse Runefile content
     #[wasm_bindgen]
     pub fn parse(&mut self, content: &str) {
+        let lines: Vec<&str> = content.lines().collect();
         self.instructions.clear();
         self.errors.clear();
+        self.unclosed_continuation = None;
+        self.split(&lines, 0, |_| false);
+        self.check_from();
+    }
+
+    /// Re-parse after an edit replaced lines `start_line..=old_end_line`
+    /// with lines `start_line..=new_end_line` of `content`
+    ///
+    /// Instructions after the edit, and their errors, are kept and moved.
+    #[wasm_bindgen]
+    pub fn reparse(
+        &mut self,
+        content: &str,
+        start_line: usize,
+        old_end_line: usize,
+        new_end_line: usize,
+    ) {
+        let lines: Vec<&str> = content.lines().collect();
+        let shift = |line: usize| line + new_end_line - old_end_line;
+        self.errors.retain(|e| e.message != MISSING_FROM);
+
+        let first = self
+            .instructions
+            .iter()
+            .position(|i| i.end_line >= start_line)
+            .unwrap_or(self.instructions.len());
+        let mut tail = self.instructions.split_off(first);
+        let mut from = tail.iter().map(|i| i.line).fold(start_line, usize::min);
+
+        // A continuation running to the end of the file swallows every line
+        // after it, so the whole rest is split again
+        let unclosed = self.unclosed_continuation.take();
+        let split_to_end = unclosed.is_some_and(|line| line <= old_end_line);
+        if let Some(line) = unclosed.filter(|_| split_to_end) {
+            from = from.min(line);
+        }
+
+        // Comments inside a continuation come before the instruction
+        self.instructions.retain(|i| i.line < from);
+        let split_through = tail
+            .iter()
+            .filter(|i| i.line <= old_end_line && i.end_line > old_end_line)
+            .map(|i| shift(i.end_line))
+            .max()
+            .unwrap_or(new_end_line);
+        tail.retain(|i| i.line > old_end_line);
+        let (errors, tail_errors): (Vec<_>, Vec<_>) = std::mem::take(&mut self.errors)
+            .into_iter()
+            .filter(|e| e.line < from || e.line > old_end_line)
+            .partition(|e| e.line < from);
+        self.errors = errors;
+
+        let stop = self.split(&lines, from, |line| !split_to_end && line > split_through);
+        for mut inst in tail {
+            inst.line = shift(inst.line);
+            inst.end_line = shift(inst.end_line);
+            if inst.line >= stop {
+                self.instructions.push(inst);
+            }
+        }
+        for mut error in tail_errors {
+            error.line = shift(error.line);
+            if error.line >= stop {
+                self.errors.push(error);
+            }
+        }
+        if stop < lines.len() {
+            self.unclosed_continuation = unclosed.filter(|&line| line > old_end_line).map(shift);
+        }
 
-        let mut has_from = false;
+        self.check_from();
+    }
+    /// Split lines into instructions, starting at line `from`
+    ///
+    /// Stops after the first instruction ending on a line for which `stop`
+    /// returns true, and returns the next line, or the line count at the end.
+    fn split(&mut self, lines: &[&str], from: usize, stop: impl Fn(usize) -> bool) -> usize {
         let mut in_multiline = false;
         let mut multiline_buffer = String::new();
         let mut multiline_start_line = 0;
 
-        for (line_num, line) in content.lines().enumerate() {
+        for (line_num, line) in lines.iter().enumerate().skip(from) {
             let trimmed = line.trim();
 
             if trimmed.is_empty() {
                 continue;
             }
 
-            if trimmed.starts_with('#') {
+            if let Some(comment) = trimmed.strip_prefix('#') {
                 self.instructions.push(Instruction {
                     kind: InstructionKind::Comment,
                     line: line_num,
+                    end_line: line_num,
                     raw: line.to_string(),
                     keyword: "#".to_string(),
-                    arguments: trimmed[1..].trim().to_string(),
+                    arguments: comment.trim().to_string(),
                 });
                 continue;
             }
 
             if in_multiline {
-                if trimmed.ends_with('\\') {
+                if let Some(continued) = trimmed.strip_suffix('\\') {
                     multiline_buffer.push(' ');
-                    multiline_buffer.push_str(&trimmed[..trimmed.len() - 1]);
-                } else {
-                    multiline_buffer.push(' ');
-                    multiline_buffer.push_str(trimmed);
-                    self.parse_instruction(&multiline_buffer, multiline_start_line, &mut has_from);
-                    in_multiline = false;
-                    multiline_buffer.clear();
+                    multiline_buffer.push_str(continued);
+                    continue;
                 }
-                continue;
-            }
-
-            if trimmed.ends_with('\\') {
+                multiline_buffer.push(' ');
+                multiline_buffer.push_str(trimmed);
+                let buffer = std::mem::take(&mut multiline_buffer);
+                self.parse_instruction(&buffer, multiline_start_line, line_num);
+                in_multiline = false;
+            } else if let Some(continued) = trimmed.strip_suffix('\\') {
                 in_multiline = true;
                 multiline_start_line = line_num;
-                multiline_buffer = trimmed[..trimmed.len() - 1].to_string();
+                multiline_buffer = continued.to_string();
                 continue;
+            } else {
+                self.parse_instruction(line, line_num, line_num);
             }
 
-            self.parse_instruction(line, line_num, &mut has_from);
+            if stop(line_num) {
+                return line_num + 1;
+            }
         }
 
+        if in_multiline {
+            self.unclosed_continuation = Some(multiline_start_line);
+        }
+        lines.len()
+    }
+
+    /// Report a missing FROM on the first instruction's keyword
+    fn check_from(&mut self) {
+        let has_from = self
+            .instructions
+            .iter()
+            .any(|i| i.kind == InstructionKind::From);
+        let first = self
+            .instructions
+            .iter()
+            .find(|i| i.kind != InstructionKind::Comment);
         if !has_from && !self.instructions.is_empty() {
+            let (line, (column, end_column)) =
+                first.map_or((0, (0, 0)), |i| (i.line, keyword_span(&i.raw)));
             self.errors.push(ParseError {
-                line: 0,
-                message: "Runefile must start with FROM instruction".to_string(),
+                line,
+                column,
+                end_column,
+                message: MISSING_FROM.to_string(),
                 severity: ErrorSeverity::Error,
             });
         }
     }
 
-    fn parse_instruction(&mut self, line: &str, line_num: usize, has_from: &mut bool) {
+    fn parse_instruction(&mut self, line: &str, line_num: usize, end_line: usize) {
         let trimmed = line.trim();
         let parts: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
 
@@ -103,10 +207,7 @@ impl RunefileParser {
             .unwrap_or_default();
 
         let kind = match keyword.as_str() {
-            "FROM" => {
-                *has_from = true;
-                InstructionKind::From
-            }
+            "FROM" => InstructionKind::From,
             "RUN" => InstructionKind::Run,
             "COPY" => InstructionKind::Copy,
             "ADD" => InstructionKind::Add,
@@ -125,8 +226,11 @@ impl RunefileParser {
             "STOPSIGNAL" => InstructionKind::Stopsignal,
             "ONBUILD" => InstructionKind::Onbuild,
             _ => {
+                let (column, end_column) = keyword_span(line);
                 self.errors.push(ParseError {
                     line: line_num,
+                    column,
+                    end_column,
                     message: format!("Unknown instruction: {}", keyword),
                     severity: ErrorSeverity::Warning,
                 });
@@ -134,85 +238,116 @@ impl RunefileParser {
             }
         };
 
-        self.validate_instruction(kind, &arguments, line_num);
+        self.validate_instruction(kind, &keyword, line, line_num);
 
         self.instructions.push(Instruction {
             kind,
             line: line_num,
+            end_line,
             raw: line.to_string(),
             keyword,
             arguments,
         });
     }
 
-    fn validate_instruction(&mut self, kind: InstructionKind, arguments: &str, line_num: usize) {
+    /// Check the flags and arguments of an instruction, reporting each
+    /// problem on the word it is about
+    fn validate_instruction(
+        &mut self,
+        kind: InstructionKind,
+        name: &str,
+        line: &str,
+        line_num: usize,
+    ) {
+        let keyword = keyword_span(line);
+        let mut words = words(line).skip(1).peekable();
+        let mut error = |message: String, (column, end_column), severity| {
+            self.errors.push(ParseError {
+                line: line_num,
+                column,
+                end_column,
+                message,
+                severity,
+            })
+        };
+        let span = |(offset, word): (usize, &str)| (offset, offset + word.len());
+
+        let flags = flags(name);
+        while let Some((offset, flag)) = words.next_if(|(_, word)| word.starts_with("--")) {
+            let name = flag.split('=').next().unwrap_or(flag);
+            if !flags.is_empty() && !flags.iter().any(|f| f.name == name) {
+                error(
+                    format!("Unknown flag: {}", name),
+                    span((offset, name)),
+                    ErrorSeverity::Warning,
+                );
+            }
+        }
+        let arguments: Vec<(usize, &str)> = words.collect();
+        let all_arguments = match (arguments.first(), arguments.last()) {
+            (Some(&first), Some(&last)) => (first.0, span(last).1),
+            _ => keyword,
+        };
+
         match kind {
-            InstructionKind::From => {
-                if arguments.is_empty() {
-                    self.errors.push(ParseError {
-                        line: line_num,
-                        message: "FROM requires an image argument".to_string(),
-                        severity: ErrorSeverity::Error,
-                    });
-                }
+            InstructionKind::From if arguments.is_empty() => {
+                error(
+                    "FROM requires an image argument".to_string(),
+                    keyword,
+                    ErrorSeverity::Error,
+                );
             }
-            InstructionKind::Copy | InstructionKind::Add => {
-                let args: Vec<&str> = arguments.split_whitespace().collect();
-                let non_flag_args: Vec<&&str> =
-                    args.iter().filter(|a| !a.starts_with("--")).collect();
-                if non_flag_args.len() < 2 {
-                    self.errors.push(ParseError {
-                        line: line_num,
-                        message: format!(
-                            "{} requires at least two arguments (source and destination)",
-                            if kind == InstructionKind::Copy {
-                                "COPY"
-                            } else {
-                                "ADD"
-                            }
-                        ),
-                        severity: ErrorSeverity::Error,
-                    });
-                }
+            InstructionKind::Copy | InstructionKind::Add if arguments.len() < 2 => {
+                error(
+                    format!(
+                        "{} requires at least two arguments (source and destination)",
+                        if kind == InstructionKind::Copy {
+                            "COPY"
+                        } else {
+                            "ADD"
+                        }
+                    ),
+                    all_arguments,
+                    ErrorSeverity::Error,
+                );
             }
             InstructionKind::Expose => {
-                for port in arguments.split_whitespace() {
+                for &(offset, port) in &arguments {
                     let port_num = port.split('/').next().unwrap_or("");
                     if port_num.parse::<u16>().is_err() {
-                        self.errors.push(ParseError {
-                            line: line_num,
-                            message: format!("Invalid port number: {}", port),
-                            severity: ErrorSeverity::Warning,
-                        });
+                        error(
+                            format!("Invalid port number: {}", port),
+                            span((offset, port)),
+                            ErrorSeverity::Warning,
+                        );
                     }
                 }
             }
             InstructionKind::Workdir => {
                 if arguments.is_empty() {
-                    self.errors.push(ParseError {
-                        line: line_num,
-                        message: "WORKDIR requires a path argument".to_string(),
-                        severity: ErrorSeverity::Error,
-                    });
-                } else if !arguments.starts_with('/') && !arguments.starts_with('$') {
-                    self.errors.push(ParseError {
-                        line: line_num,
-                        message: "WORKDIR should use absolute path".to_string(),
-                        severity: ErrorSeverity::Warning,
-                    });
+                    error(
+                        "WORKDIR requires a path argument".to_string(),
+                        keyword,
+                        ErrorSeverity::Error,
+                    );
+                } else if !arguments[0].1.starts_with('/') && !arguments[0].1.starts_with('$') {
+                    error(
+                        "WORKDIR should use absolute path".to_string(),
+                        all_arguments,
+                        ErrorSeverity::Warning,
+                    );
                 }
             }
-            InstructionKind::Healthcheck => {
+            InstructionKind::Healthcheck
                 if !arguments.is_empty()
-                    && !arguments.starts_with("NONE")
-                    && !arguments.starts_with("CMD")
-                {
-                    self.errors.push(ParseError {
-                        line: line_num,
-                        message: "HEALTHCHECK must be NONE or CMD".to_string(),
-                        severity: ErrorSeverity::Error,
-                    });
-                }
+                    && !arguments[0].1.starts_with("NONE")
+                    && !arguments[0].1.starts_with("CMD") =>
+            {
+                error(
+                    "HEALTHCHECK must be NONE or CMD".to_string(),
+                    all_arguments,
+                    ErrorSeverity::Error,
+                );
             }
             _ => {}
         }
@@ -221,18 +356,36 @@ impl RunefileParser {
     /// Get diagnostics as JSON
     #[wasm_bindgen]
     pub fn get_diagnostics_json(&self) -> String {
-        let diagnostics: Vec<Diagnostic> = self
-            .errors
+        serde_json::to_string(&self.diagnostics()).unwrap_or_default()
+    }
+
+    /// Get instruction count
+    #[wasm_bindgen]
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Get error count
+    #[wasm_bindgen]
+    pub fn error_count(&self) -> usize {
+        self.errors.len()
+    }
+}
+
+impl RunefileParser {
+    /// Get diagnostics
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors
             .iter()
             .map(|e| Diagnostic {
                 range: Range {
                     start: Position {
                         line: e.line as u32,
-                        character: 0,
+                        character: e.column as u32,
                     },
                     end: Position {
                         line: e.line as u32,
-                        character: 100,
+                        character: e.end_column as u32,
                     },
                 },
                 severity: match e.severity {
@@ -241,25 +394,25 @@ impl RunefileParser {
                     ErrorSeverity::Information => 3,
                     ErrorSeverity::Hint => 4,
                 },
+                code: None,
                 message: e.message.clone(),
                 source: "runefile-lsp".to_string(),
             })
-            .collect();
-
-        serde_json::to_string(&diagnostics).unwrap_or_default()
+            .collect()
     }
+}
 
-    /// Get instruction count
-    #[wasm_bindgen]
-    pub fn instruction_count(&self) -> usize {
-        self.instructions.len()
-    }
+/// Span of the keyword of an instruction line
+fn keyword_span(line: &str) -> (usize, usize) {
+    words(line)
+        .next()
+        .map_or((0, 0), |(offset, keyword)| (offset, offset + keyword.len()))
+}
 
-    /// Get error count
-    #[wasm_bindgen]
-    pub fn error_count(&self) -> usize {
-        self.errors.len()
-    }
+/// Words of a line with their byte offsets
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
 }
 
 impl Default for RunefileParser {
@@ -286,4 +439,56 @@ mod tests {
         parser.parse("RUN echo hello");
         assert!(parser.error_count() > 0);
     }
+
+    #[test]
+    fn test_parser_error_columns() {
+        let mut parser = RunefileParser::new();
+        parser.parse("FROM alpine\nEXPOSE 80 8o\nCOPY --chwon=app src\n");
+        let mut spans: Vec<(usize, usize, usize)> = parser
+            .errors
+            .iter()
+            .map(|e| (e.line, e.column, e.end_column))
+            .collect();
+        spans.sort();
+        assert_eq!(spans, vec![(1, 10, 12), (2, 5, 12), (2, 17, 20)]);
+    }
+
+    #[test]
+    fn test_parser_reparse() {
+        let summary = |parser: &RunefileParser| {
+            let mut instructions: Vec<_> = parser
+                .instructions
+                .iter()
+                .map(|i| (i.line, i.end_line, i.raw.clone()))
+                .collect();
+            instructions.sort();
+            let mut errors: Vec<_> = parser
+                .errors
+                .iter()
+                .map(|e| (e.line, e.message.clone()))
+                .collect();
+            errors.sort();
+            (instructions, errors)
+        };
+
+        let before = "FROM alpine\nRUN apk add \\\n    # tools\n    curl\nEXPOSE 80x\nCOPY a\n";
+        // (content after the edit, start line, old end line, new end line)
+        let edits = [
+            ("FROM alpine\nRUN apk add \\\n    # tools\n    git\nEXPOSE 80x\nCOPY a\n", 3, 3, 3),
+            ("FROM alpine\nRUN apk add\n    # tools\n    curl\nEXPOSE 80x\nCOPY a\n", 1, 1, 1),
+            ("RUN true\nRUN apk add \\\n    # tools\n    curl\nEXPOSE 80x\nCOPY a\n", 0, 0, 0),
+            ("FROM alpine\nUSER app\n\nRUN apk add \\\n    # tools\n    curl\nEXPOSE 80x\nCOPY a\n", 1, 1, 3),
+            ("FROM alpine\nRUN apk add \\\n", 2, 5, 2),
+        ];
+
+        for (after, start_line, old_end_line, new_end_line) in edits {
+            let mut incremental = RunefileParser::new();
+            incremental.parse(before);
+            incremental.reparse(after, start_line, old_end_line, new_end_line);
+
+            let mut full = RunefileParser::new();
+            full.parse(after);
+            assert_eq!(summary(&incremental), summary(&full), "{}", after);
+        }
+    }
 }
@@ -1,8 +1,100 @@
 //! LSP types for Runefile
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use wasm_bindgen::prelude::*;
 
+/// TypeScript types of the objects returned by the `...Object` methods
+#[wasm_bindgen(typescript_custom_section)]
+const TYPESCRIPT_TYPES: &str = r#"
+export interface Position {
+    line: number;
+    character: number;
+}
+
+export interface Range {
+    start: Position;
+    end: Position;
+}
+
+export interface Location {
+    uri: string;
+    range: Range;
+}
+
+export interface Diagnostic {
+    range: Range;
+    severity: number;
+    code?: string;
+    message: string;
+    source: string;
+}
+
+export interface CompletionItem {
+    label: string;
+    kind: number;
+    detail: string | null;
+    documentation: string | null;
+    insertText: string | null;
+    insertTextFormat: number | null;
+}
+
+export interface HoverResult {
+    contents: string;
+    range: Range | null;
+}
+
+export interface ParameterInformation {
+    label: [number, number];
+    documentation: string | null;
+}
+
+export interface SignatureInformation {
+    label: string;
+    documentation: string | null;
+    parameters: ParameterInformation[];
+}
+
+export interface SignatureHelp {
+    signatures: SignatureInformation[];
+    activeSignature: number | null;
+    activeParameter: number | null;
+}
+
+export interface FoldingRange {
+    startLine: number;
+    endLine: number;
+    kind?: "comment" | "region";
+}
+
+export interface SelectionRange {
+    range: Range;
+    parent?: SelectionRange;
+}
+
+export interface DocumentSymbol {
+    name: string;
+    detail?: string;
+    kind: number;
+    range: Range;
+    selectionRange: Range;
+    children?: DocumentSymbol[];
+}
+
+export interface SemanticTokens {
+    data: number[];
+}
+
+export interface ValidationResult {
+    valid: boolean;
+    errorCount: number;
+    instructionCount: number;
+    diagnostics: Diagnostic[];
+}
+
+export type ServerCapabilities = Record<string, unknown>;
+"#;
+
 /// Runefile instruction types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[wasm_bindgen]
@@ -34,6 +126,7 @@ pub enum InstructionKind {
 pub struct Instruction {
     pub kind: InstructionKind,
     pub line: usize,
+    pub end_line: usize,
     pub raw: String,
     pub keyword: String,
     pub arguments: String,
@@ -53,19 +146,23 @@ pub enum ErrorSeverity {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParseError {
     pub line: usize,
+    /// Start column of the text the error is about
+    pub column: usize,
+    /// End column of the text the error is about
+    pub end_column: usize,
     pub message: String,
     pub severity: ErrorSeverity,
 }
 
 /// Position in a document
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Position {
     pub line: u32,
     pub character: u32,
 }
 
 /// Range in a document
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Range {
     pub start: Position,
     pub end: Position,
@@ -76,6 +173,8 @@ pub struct Range {
 pub struct Diagnostic {
     pub range: Range,
     pub severity: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     pub message: String,
     pub source: String,
 }
@@ -98,3 +197,123 @@ pub struct HoverResult {
     pub contents: String,
     pub range: Option<Range>,
 }
+
+/// Folding range; `kind` is "comment" or "region" when set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldingRange {
+    pub start_line: u32,
+    pub end_line: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// Selection range and the range containing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionRange {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Box<SelectionRange>>,
+}
+
+/// Signature help result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureHelp {
+    pub signatures: Vec<SignatureInformation>,
+    pub active_signature: Option<u32>,
+    pub active_parameter: Option<u32>,
+}
+
+/// A signature and its parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureInformation {
+    pub label: String,
+    pub documentation: Option<String>,
+    pub parameters: Vec<ParameterInformation>,
+}
+
+/// A parameter, labelled by its offsets within the signature label
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterInformation {
+    pub label: [u32; 2],
+    pub documentation: Option<String>,
+}
+
+/// Formatting style
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatOptions {
+    /// Spaces before continuation lines
+    pub continuation_indent: usize,
+    /// Line up the `\` of continued lines
+    pub align_continuations: bool,
+    /// Sort multi-key LABEL and ENV instructions, one key per line
+    pub sort_keys: bool,
+    /// Rewrite CMD and ENTRYPOINT JSON arrays as `["a", "b"]`
+    pub normalize_json: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            continuation_indent: 4,
+            align_continuations: false,
+            sort_keys: false,
+            normalize_json: true,
+        }
+    }
+}
+
+/// Semantic tokens in the LSP relative encoding
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SemanticTokens {
+    pub data: Vec<u32>,
+}
+
+/// Result of validating a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub error_count: usize,
+    pub instruction_count: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Settings given to `setConfiguration`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerConfiguration {
+    /// Longest allowed line, checked by RUNE1006
+    pub max_line_length: Option<usize>,
+    /// Severity per rule ID: error, warning, info, hint or off
+    pub rules: BTreeMap<String, String>,
+    /// Rule IDs to turn off
+    pub disabled_rules: Vec<String>,
+    /// Registry base images must come from, checked by RUNE1007
+    pub preferred_registry: Option<String>,
+    /// Style used by `format`
+    pub format: FormatOptions,
+}
+
+/// Symbol in the document outline, with the symbols it contains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSymbol {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub kind: u8,
+    pub range: Range,
+    pub selection_range: Range,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// Location in a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
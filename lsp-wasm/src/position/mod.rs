@@ -0,0 +1,86 @@
+//! UTF-16 position encoding
+//!
+//! LSP positions count UTF-16 code units, while the providers work with byte
+//! offsets into each line. The JavaScript entry points convert between the
+//! two, so columns stay correct on lines with emoji or CJK text.
+
+use crate::parser::types::*;
+
+/// The position encoding advertised to clients
+pub const POSITION_ENCODING: &str = "utf-16";
+
+/// Byte offset of a UTF-16 column in a line, clamped to the end of the line
+///
+/// A column inside a surrogate pair resolves to the start of its character.
+pub fn byte_column(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        units += c.len_utf16() as u32;
+        if units > character {
+            return i;
+        }
+    }
+    line.len()
+}
+
+/// UTF-16 column of a byte offset in a line
+pub fn utf16_column(line: &str, byte: usize) -> u32 {
+    let mut byte = byte.min(line.len());
+    while !line.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    line[..byte].encode_utf16().count() as u32
+}
+
+/// Text of a line, empty past the end of the document
+pub fn line_text(content: &str, line: u32) -> &str {
+    content.lines().nth(line as usize).unwrap_or("")
+}
+
+/// Convert a position with a byte column to a UTF-16 column
+pub fn utf16_position(content: &str, position: Position) -> Position {
+    Position {
+        line: position.line,
+        character: utf16_column(
+            line_text(content, position.line),
+            position.character as usize,
+        ),
+    }
+}
+
+/// Convert a range with byte columns to UTF-16 columns
+pub fn utf16_range(content: &str, range: Range) -> Range {
+    Range {
+        start: utf16_position(content, range.start),
+        end: utf16_position(content, range.end),
+    }
+}
+
+/// Convert the ranges of diagnostics to UTF-16 columns
+pub fn utf16_diagnostics(content: &str, diagnostics: &mut [Diagnostic]) {
+    for diagnostic in diagnostics {
+        diagnostic.range = utf16_range(content, diagnostic.range);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns() {
+        // 'é' is two bytes and one unit, '🦀' four bytes and two units
+        let line = "é🦀 x";
+        assert_eq!(byte_column(line, 0), 0);
+        assert_eq!(byte_column(line, 1), 2);
+        assert_eq!(byte_column(line, 2), 2);
+        assert_eq!(byte_column(line, 3), 6);
+        assert_eq!(byte_column(line, 4), 7);
+        assert_eq!(byte_column(line, 99), line.len());
+
+        assert_eq!(utf16_column(line, 2), 1);
+        assert_eq!(utf16_column(line, 6), 3);
+        assert_eq!(utf16_column(line, 4), 1);
+        assert_eq!(utf16_column(line, 99), 5);
+    }
+}
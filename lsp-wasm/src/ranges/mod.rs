@@ -0,0 +1,105 @@
+//! Folding and selection ranges for Runefile LSP
+//!
+//! The ranges come from `runefile-lint`, shared with the native server;
+//! this module converts their byte columns to UTF-16.
+
+use crate::parser::types::*;
+use crate::position::{byte_column, line_text, utf16_range};
+use runefile_lint::ranges::{folding_ranges, selection_ranges, Span};
+use wasm_bindgen::prelude::*;
+
+/// Folding and selection range provider for Runefile
+#[wasm_bindgen]
+pub struct RangeProvider;
+
+#[wasm_bindgen]
+impl RangeProvider {
+    /// Create a new range provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Get folding ranges as JSON (works offline)
+    #[wasm_bindgen(js_name = getFoldingRanges)]
+    pub fn get_folding_ranges_json(&self, content: &str) -> String {
+        serde_json::to_string(&self.get_folding_ranges(content))
+            .unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Get the selection range at a position as JSON (works offline)
+    #[wasm_bindgen(js_name = getSelectionRange)]
+    pub fn get_selection_range_json(&self, content: &str, line: u32, character: u32) -> String {
+        serde_json::to_string(&self.selection_range(content, line, character))
+            .unwrap_or_else(|_| "null".to_string())
+    }
+}
+
+impl RangeProvider {
+    /// Get the selection range at a position and the ranges containing it
+    pub fn selection_range(&self, content: &str, line: u32, character: u32) -> SelectionRange {
+        let column = byte_column(line_text(content, line), character);
+        let mut spans = selection_ranges(content, line as usize, column);
+        // Innermost first, and never empty
+        let innermost = spans.remove(0);
+        let range = |span: Span| {
+            utf16_range(
+                content,
+                Range {
+                    start: Position {
+                        line: span.start_line as u32,
+                        character: span.start as u32,
+                    },
+                    end: Position {
+                        line: span.end_line as u32,
+                        character: span.end as u32,
+                    },
+                },
+            )
+        };
+        let parent = spans.into_iter().rev().fold(None, |parent, span| {
+            Some(Box::new(SelectionRange {
+                range: range(span),
+                parent,
+            }))
+        });
+        SelectionRange {
+            range: range(innermost),
+            parent,
+        }
+    }
+
+    /// Get folding ranges for stages, continued instructions, heredocs and
+    /// comment blocks
+    pub fn get_folding_ranges(&self, content: &str) -> Vec<FoldingRange> {
+        folding_ranges(content)
+            .into_iter()
+            .map(|fold| FoldingRange {
+                start_line: fold.start_line as u32,
+                end_line: fold.end_line as u32,
+                kind: fold.kind.map(str::to_string),
+            })
+            .collect()
+    }
+}
+
+impl Default for RangeProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_selection() {
+        // 'é' is two bytes and one UTF-16 unit
+        let selection = RangeProvider::new().selection_range("LABEL é=1 x=2", 0, 8);
+        assert_eq!(selection.range.start.character, 8);
+        assert_eq!(selection.range.end.character, 9);
+        let parent = selection.parent.unwrap();
+        assert_eq!(parent.range.start.character, 6);
+    }
+}
@@ -0,0 +1,74 @@
+//! Semantic tokens for Runefile LSP
+//!
+//! The tokenizer is shared with the native server through `runefile-lint`;
+//! this module converts its byte offsets to UTF-16 columns.
+
+use crate::parser::types::SemanticTokens;
+use crate::position::utf16_column;
+use runefile_lint::semantic_tokens::{encode, tokens, Token};
+use wasm_bindgen::prelude::*;
+
+pub use runefile_lint::semantic_tokens::TOKEN_TYPES;
+
+/// Semantic tokens provider for Runefile
+#[wasm_bindgen]
+pub struct SemanticTokensProvider;
+
+#[wasm_bindgen]
+impl SemanticTokensProvider {
+    /// Create a new semantic tokens provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Get semantic tokens as JSON `{"data": [...]}` (works offline)
+    #[wasm_bindgen(js_name = getSemanticTokens)]
+    pub fn get_semantic_tokens(&self, content: &str) -> String {
+        serde_json::to_string(&self.semantic_tokens(content)).unwrap_or_else(|_| "null".to_string())
+    }
+}
+
+impl SemanticTokensProvider {
+    /// Get the encoded semantic tokens of a document
+    pub fn semantic_tokens(&self, content: &str) -> SemanticTokens {
+        let lines: Vec<&str> = content.lines().collect();
+        let tokens: Vec<Token> = tokens(content)
+            .into_iter()
+            .map(|token| {
+                let text = lines.get(token.line).copied().unwrap_or("");
+                let start = utf16_column(text, token.start);
+                let end = utf16_column(text, token.start + token.length);
+                Token {
+                    start: start as usize,
+                    length: (end - start) as usize,
+                    ..token
+                }
+            })
+            .collect();
+        SemanticTokens {
+            data: encode(&tokens),
+        }
+    }
+}
+
+impl Default for SemanticTokensProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_columns() {
+        let tokens = SemanticTokensProvider::new().semantic_tokens("LABEL é=\"ü\" $X");
+        // LABEL, then the string and the variable after a two-byte character
+        assert_eq!(
+            tokens.data,
+            vec![0, 0, 5, 0, 0, 0, 8, 3, 4, 0, 0, 4, 2, 3, 0]
+        );
+    }
+}
@@ -1,16 +1,52 @@
 //! LSP Server for Runefile - works entirely offline
 
 use crate::completion::CompletionProvider;
+use crate::compose::ComposeProvider;
+use crate::formatting::FormattingProvider;
 use crate::hover::HoverProvider;
-use crate::parser::RunefileParser;
+use crate::parser::{
+    CompletionItem, Diagnostic, FormatOptions, HoverResult, Position, Range, RunefileParser,
+    ServerConfiguration, ValidationResult,
+};
+use crate::position::{byte_column, utf16_diagnostics, POSITION_ENCODING};
+use crate::ranges::RangeProvider;
+use crate::semantic_tokens::{SemanticTokensProvider, TOKEN_TYPES};
+use crate::signature_help::SignatureHelpProvider;
+use crate::symbols::SymbolProvider;
+use runefile_lint::LintConfig;
+use serde::Serialize;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+/// Serialize a result as JSON
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
+}
+
+/// Convert a result to a plain JavaScript object, with `null` for `None`
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap_or(JsValue::NULL)
+}
+
 /// Document stored in the server
-#[derive(Debug, Clone)]
 struct Document {
     content: String,
     version: i32,
+    parser: RunefileParser,
+}
+
+impl Document {
+    fn new(content: &str, version: i32) -> Self {
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        Self {
+            content: content.to_string(),
+            version,
+            parser,
+        }
+    }
 }
 
 /// Runefile LSP Server - works entirely offline with local files
@@ -24,6 +60,22 @@ pub struct RunefileLspServer {
     completion: CompletionProvider,
     #[wasm_bindgen(skip)]
     hover: HoverProvider,
+    #[wasm_bindgen(skip)]
+    signature_help: SignatureHelpProvider,
+    #[wasm_bindgen(skip)]
+    ranges: RangeProvider,
+    #[wasm_bindgen(skip)]
+    semantic_tokens: SemanticTokensProvider,
+    #[wasm_bindgen(skip)]
+    formatting: FormattingProvider,
+    #[wasm_bindgen(skip)]
+    compose: ComposeProvider,
+    #[wasm_bindgen(skip)]
+    symbols: SymbolProvider,
+    #[wasm_bindgen(skip)]
+    lint_config: LintConfig,
+    #[wasm_bindgen(skip)]
+    format_options: FormatOptions,
 }
 
 #[wasm_bindgen]
@@ -36,19 +88,59 @@ impl RunefileLspServer {
             parser: RunefileParser::new(),
             completion: CompletionProvider::new(),
             hover: HoverProvider::new(),
+            signature_help: SignatureHelpProvider::new(),
+            ranges: RangeProvider::new(),
+            semantic_tokens: SemanticTokensProvider::new(),
+            formatting: FormattingProvider::new(),
+            compose: ComposeProvider::new(),
+            symbols: SymbolProvider::new(),
+            lint_config: LintConfig::default(),
+            format_options: FormatOptions::default(),
+        }
+    }
+
+    /// Configure lint rules from the contents of a `.runelint.toml`
+    #[wasm_bindgen(js_name = setLintConfig)]
+    pub fn set_lint_config(&mut self, config: &str) -> Result<(), JsValue> {
+        self.lint_config =
+            LintConfig::parse(config).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(())
+    }
+
+    /// Configure lint rules and the formatting style from a JSON object
+    ///
+    /// Replaces any earlier configuration, including one given to
+    /// `setLintConfig`.
+    #[wasm_bindgen(js_name = setConfiguration)]
+    pub fn set_configuration(&mut self, json: &str) -> Result<(), JsValue> {
+        let error = |e: String| JsValue::from_str(&e);
+        let config: ServerConfiguration =
+            serde_json::from_str(json).map_err(|e| error(e.to_string()))?;
+
+        let mut lint_config = LintConfig::default();
+        lint_config.max_line_length = config.max_line_length;
+        lint_config.preferred_registry = config.preferred_registry;
+        for (id, level) in &config.rules {
+            lint_config
+                .set_rule(id, level)
+                .map_err(|e| error(e.to_string()))?;
+        }
+        for id in &config.disabled_rules {
+            lint_config
+                .set_rule(id, "off")
+                .map_err(|e| error(e.to_string()))?;
         }
+
+        self.lint_config = lint_config;
+        self.format_options = config.format;
+        Ok(())
     }
 
     /// Open a document
     #[wasm_bindgen(js_name = openDocument)]
     pub fn open_document(&mut self, uri: &str, content: &str, version: i32) {
-        self.documents.insert(
-            uri.to_string(),
-            Document {
-                content: content.to_string(),
-                version,
-            },
-        );
+        self.documents
+            .insert(uri.to_string(), Document::new(content, version));
     }
 
     /// Update a document
@@ -57,11 +149,44 @@ impl RunefileLspServer {
         if let Some(doc) = self.documents.get_mut(uri) {
             doc.content = content.to_string();
             doc.version = version;
+            doc.parser.parse(content);
         } else {
             self.open_document(uri, content, version);
         }
     }
 
+    /// Replace a range of a document, re-parsing only the instructions the
+    /// edit touches
+    #[wasm_bindgen(js_name = applyChange)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_change(
+        &mut self,
+        uri: &str,
+        start_line: u32,
+        start_character: u32,
+        end_line: u32,
+        end_character: u32,
+        text: &str,
+        version: i32,
+    ) {
+        let doc = self
+            .documents
+            .entry(uri.to_string())
+            .or_insert_with(|| Document::new("", version));
+        let start = offset_at(&doc.content, start_line, start_character);
+        let end = offset_at(&doc.content, end_line, end_character).max(start);
+        doc.content.replace_range(start..end, text);
+        doc.version = version;
+
+        let start_line = start_line as usize;
+        doc.parser.reparse(
+            &doc.content,
+            start_line,
+            (end_line as usize).max(start_line),
+            start_line + text.matches('\n').count(),
+        );
+    }
+
     /// Close a document
     #[wasm_bindgen(js_name = closeDocument)]
     pub fn close_document(&mut self, uri: &str) {
@@ -74,110 +199,314 @@ impl RunefileLspServer {
         self.documents.get(uri).map(|d| d.content.clone())
     }
 
-    /// Get diagnostics for a document (works offline)
+    /// Get diagnostics for a document as JSON (works offline)
     #[wasm_bindgen(js_name = getDiagnostics)]
-    pub fn get_diagnostics(&mut self, uri: &str) -> String {
-        if let Some(doc) = self.documents.get(uri) {
-            self.parser.parse(&doc.content);
-            self.parser.get_diagnostics_json()
-        } else {
-            "[]".to_string()
-        }
+    pub fn get_diagnostics(&self, uri: &str) -> String {
+        to_json(&self.document_diagnostics(uri))
     }
 
-    /// Get diagnostics for content directly (works offline)
+    /// Get diagnostics for a document as an object (works offline)
+    #[wasm_bindgen(js_name = getDiagnosticsObject, unchecked_return_type = "Diagnostic[]")]
+    pub fn get_diagnostics_object(&self, uri: &str) -> JsValue {
+        to_js(&self.document_diagnostics(uri))
+    }
+
+    /// Get diagnostics for content directly as JSON (works offline)
     #[wasm_bindgen(js_name = getDiagnosticsForContent)]
     pub fn get_diagnostics_for_content(&mut self, content: &str) -> String {
-        self.parser.parse(content);
-        self.parser.get_diagnostics_json()
+        to_json(&self.diagnostics(content))
     }
 
-    /// Get completions at position (works offline)
+    /// Get diagnostics for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getDiagnosticsForContentObject, unchecked_return_type = "Diagnostic[]")]
+    pub fn get_diagnostics_for_content_object(&mut self, content: &str) -> JsValue {
+        to_js(&self.diagnostics(content))
+    }
+
+    /// Get completions at position as JSON (works offline)
     #[wasm_bindgen(js_name = getCompletions)]
     pub fn get_completions(&self, uri: &str, line: u32, character: u32) -> String {
-        if let Some(doc) = self.documents.get(uri) {
-            self.completion
-                .get_completions(&doc.content, line, character)
-        } else {
-            "[]".to_string()
-        }
+        to_json(&self.document_completions(uri, line, character))
+    }
+
+    /// Get completions at position as an object (works offline)
+    #[wasm_bindgen(js_name = getCompletionsObject, unchecked_return_type = "CompletionItem[]")]
+    pub fn get_completions_object(&self, uri: &str, line: u32, character: u32) -> JsValue {
+        to_js(&self.document_completions(uri, line, character))
     }
 
-    /// Get completions for content directly (works offline)
+    /// Get completions for content directly as JSON (works offline)
     #[wasm_bindgen(js_name = getCompletionsForContent)]
     pub fn get_completions_for_content(&self, content: &str, line: u32, character: u32) -> String {
-        self.completion.get_completions(content, line, character)
+        to_json(&self.completion.completions(content, line, character))
     }
 
-    /// Get hover information (works offline)
+    /// Get completions for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getCompletionsForContentObject, unchecked_return_type = "CompletionItem[]")]
+    pub fn get_completions_for_content_object(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> JsValue {
+        to_js(&self.completion.completions(content, line, character))
+    }
+
+    /// Get hover information as JSON (works offline)
     #[wasm_bindgen(js_name = getHover)]
     pub fn get_hover(&self, uri: &str, line: u32, character: u32) -> String {
-        if let Some(doc) = self.documents.get(uri) {
-            self.hover.get_hover(&doc.content, line, character)
-        } else {
-            "null".to_string()
-        }
+        to_json(&self.document_hover(uri, line, character))
+    }
+
+    /// Get hover information as an object (works offline)
+    #[wasm_bindgen(js_name = getHoverObject, unchecked_return_type = "HoverResult | null")]
+    pub fn get_hover_object(&self, uri: &str, line: u32, character: u32) -> JsValue {
+        to_js(&self.document_hover(uri, line, character))
     }
 
-    /// Get hover for content directly (works offline)
+    /// Get hover for content directly as JSON (works offline)
     #[wasm_bindgen(js_name = getHoverForContent)]
     pub fn get_hover_for_content(&self, content: &str, line: u32, character: u32) -> String {
-        self.hover.get_hover(content, line, character)
+        to_json(&self.hover.hover(content, line, character))
     }
 
-    /// Validate content (works offline)
+    /// Get hover for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getHoverForContentObject, unchecked_return_type = "HoverResult | null")]
+    pub fn get_hover_for_content_object(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> JsValue {
+        to_js(&self.hover.hover(content, line, character))
+    }
+
+    /// Get signature help for the flag being typed as JSON (works offline)
+    #[wasm_bindgen(js_name = getSignatureHelp)]
+    pub fn get_signature_help(&self, uri: &str, line: u32, character: u32) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .and_then(|content| self.signature_help.signature_help(content, line, character)),
+        )
+    }
+
+    /// Get signature help for the flag being typed as an object (works offline)
+    #[wasm_bindgen(js_name = getSignatureHelpObject, unchecked_return_type = "SignatureHelp | null")]
+    pub fn get_signature_help_object(&self, uri: &str, line: u32, character: u32) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .and_then(|content| self.signature_help.signature_help(content, line, character)),
+        )
+    }
+
+    /// Get signature help for content directly as JSON (works offline)
+    #[wasm_bindgen(js_name = getSignatureHelpForContent)]
+    pub fn get_signature_help_for_content(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> String {
+        to_json(&self.signature_help.signature_help(content, line, character))
+    }
+
+    /// Get signature help for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getSignatureHelpForContentObject, unchecked_return_type = "SignatureHelp | null")]
+    pub fn get_signature_help_for_content_object(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> JsValue {
+        to_js(&self.signature_help.signature_help(content, line, character))
+    }
+
+    /// Get folding ranges for a document as JSON (works offline)
+    #[wasm_bindgen(js_name = getFoldingRanges)]
+    pub fn get_folding_ranges(&self, uri: &str) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .map(|content| self.ranges.get_folding_ranges(content))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Get folding ranges for a document as an object (works offline)
+    #[wasm_bindgen(js_name = getFoldingRangesObject, unchecked_return_type = "FoldingRange[]")]
+    pub fn get_folding_ranges_object(&self, uri: &str) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .map(|content| self.ranges.get_folding_ranges(content))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Get folding ranges for content directly as JSON (works offline)
+    #[wasm_bindgen(js_name = getFoldingRangesForContent)]
+    pub fn get_folding_ranges_for_content(&self, content: &str) -> String {
+        to_json(&self.ranges.get_folding_ranges(content))
+    }
+
+    /// Get folding ranges for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getFoldingRangesForContentObject, unchecked_return_type = "FoldingRange[]")]
+    pub fn get_folding_ranges_for_content_object(&self, content: &str) -> JsValue {
+        to_js(&self.ranges.get_folding_ranges(content))
+    }
+
+    /// Get the selection range at a position as JSON (works offline)
+    #[wasm_bindgen(js_name = getSelectionRange)]
+    pub fn get_selection_range(&self, uri: &str, line: u32, character: u32) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .map(|content| self.ranges.selection_range(content, line, character)),
+        )
+    }
+
+    /// Get the selection range at a position as an object (works offline)
+    #[wasm_bindgen(js_name = getSelectionRangeObject, unchecked_return_type = "SelectionRange | null")]
+    pub fn get_selection_range_object(&self, uri: &str, line: u32, character: u32) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .map(|content| self.ranges.selection_range(content, line, character)),
+        )
+    }
+
+    /// Get the selection range for content directly as JSON (works offline)
+    #[wasm_bindgen(js_name = getSelectionRangeForContent)]
+    pub fn get_selection_range_for_content(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> String {
+        to_json(&self.ranges.selection_range(content, line, character))
+    }
+
+    /// Get the selection range for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getSelectionRangeForContentObject, unchecked_return_type = "SelectionRange")]
+    pub fn get_selection_range_for_content_object(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> JsValue {
+        to_js(&self.ranges.selection_range(content, line, character))
+    }
+
+    /// Get the outline of stages and variables for a document as JSON (works offline)
+    #[wasm_bindgen(js_name = getDocumentSymbols)]
+    pub fn get_document_symbols(&self, uri: &str) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .map(|content| self.symbols.document_symbols(content))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Get the outline of stages and variables for a document as an object (works offline)
+    #[wasm_bindgen(js_name = getDocumentSymbolsObject, unchecked_return_type = "DocumentSymbol[]")]
+    pub fn get_document_symbols_object(&self, uri: &str) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .map(|content| self.symbols.document_symbols(content))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Get the outline for content directly as JSON (works offline)
+    #[wasm_bindgen(js_name = getDocumentSymbolsForContent)]
+    pub fn get_document_symbols_for_content(&self, content: &str) -> String {
+        to_json(&self.symbols.document_symbols(content))
+    }
+
+    /// Get the outline for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getDocumentSymbolsForContentObject, unchecked_return_type = "DocumentSymbol[]")]
+    pub fn get_document_symbols_for_content_object(&self, content: &str) -> JsValue {
+        to_js(&self.symbols.document_symbols(content))
+    }
+
+    /// Get the stage or variable declaration at a position as JSON (works offline)
+    #[wasm_bindgen(js_name = getDefinition)]
+    pub fn get_definition(&self, uri: &str, line: u32, character: u32) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .and_then(|content| self.symbols.definition(uri, content, line, character)),
+        )
+    }
+
+    /// Get the stage or variable declaration at a position as an object (works offline)
+    #[wasm_bindgen(js_name = getDefinitionObject, unchecked_return_type = "Location | null")]
+    pub fn get_definition_object(&self, uri: &str, line: u32, character: u32) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .and_then(|content| self.symbols.definition(uri, content, line, character)),
+        )
+    }
+
+    /// Get semantic tokens for a document as JSON (works offline)
+    #[wasm_bindgen(js_name = getSemanticTokens)]
+    pub fn get_semantic_tokens(&self, uri: &str) -> String {
+        to_json(
+            &self
+                .document(uri)
+                .map(|content| self.semantic_tokens.semantic_tokens(content)),
+        )
+    }
+
+    /// Get semantic tokens for a document as an object (works offline)
+    #[wasm_bindgen(js_name = getSemanticTokensObject, unchecked_return_type = "SemanticTokens | null")]
+    pub fn get_semantic_tokens_object(&self, uri: &str) -> JsValue {
+        to_js(
+            &self
+                .document(uri)
+                .map(|content| self.semantic_tokens.semantic_tokens(content)),
+        )
+    }
+
+    /// Get semantic tokens for content directly as JSON (works offline)
+    #[wasm_bindgen(js_name = getSemanticTokensForContent)]
+    pub fn get_semantic_tokens_for_content(&self, content: &str) -> String {
+        to_json(&self.semantic_tokens.semantic_tokens(content))
+    }
+
+    /// Get semantic tokens for content directly as an object (works offline)
+    #[wasm_bindgen(js_name = getSemanticTokensForContentObject, unchecked_return_type = "SemanticTokens")]
+    pub fn get_semantic_tokens_for_content_object(&self, content: &str) -> JsValue {
+        to_js(&self.semantic_tokens.semantic_tokens(content))
+    }
+
+    /// Validate content as JSON (works offline)
     #[wasm_bindgen]
     pub fn validate(&mut self, content: &str) -> String {
-        self.parser.parse(content);
-
-        let errors = self.parser.error_count();
-        let instructions = self.parser.instruction_count();
+        to_json(&self.validation(content))
+    }
 
-        serde_json::json!({
-            "valid": errors == 0,
-            "errorCount": errors,
-            "instructionCount": instructions,
-            "diagnostics": serde_json::from_str::<serde_json::Value>(&self.parser.get_diagnostics_json()).unwrap_or(serde_json::json!([]))
-        }).to_string()
+    /// Validate content as an object (works offline)
+    #[wasm_bindgen(js_name = validateObject, unchecked_return_type = "ValidationResult")]
+    pub fn validate_object(&mut self, content: &str) -> JsValue {
+        to_js(&self.validation(content))
     }
 
-    /// Format a Runefile (basic formatting, works offline)
+    /// Format a Runefile with the configured style (works offline)
     #[wasm_bindgen]
     pub fn format(&self, content: &str) -> String {
-        let mut result = Vec::new();
-        let mut prev_was_empty = false;
-
-        for line in content.lines() {
-            let trimmed = line.trim();
-
-            // Handle empty lines
-            if trimmed.is_empty() {
-                if !prev_was_empty && !result.is_empty() {
-                    result.push(String::new());
-                    prev_was_empty = true;
-                }
-                continue;
-            }
-            prev_was_empty = false;
-
-            // Handle comments
-            if trimmed.starts_with('#') {
-                result.push(trimmed.to_string());
-                continue;
-            }
-
-            // Handle instructions
-            let parts: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
-            if parts.len() == 2 {
-                let instruction = parts[0].to_uppercase();
-                let args = parts[1].trim();
-                result.push(format!("{} {}", instruction, args));
-            } else if parts.len() == 1 {
-                result.push(parts[0].to_uppercase());
-            }
-        }
+        self.formatting.format_with(content, &self.format_options)
+    }
 
-        result.join("\n")
+    /// Format a Runefile with options given as JSON (works offline)
+    #[wasm_bindgen(js_name = formatWithOptions)]
+    pub fn format_with_options(&self, content: &str, options: &str) -> Result<String, JsValue> {
+        self.formatting.format_with_options(content, options)
     }
 
     /// Get document count
@@ -195,23 +524,155 @@ impl RunefileLspServer {
     /// Get server capabilities as JSON
     #[wasm_bindgen(js_name = getCapabilities)]
     pub fn get_capabilities() -> String {
+        Self::capabilities().to_string()
+    }
+
+    /// Get server capabilities as an object
+    #[wasm_bindgen(js_name = getCapabilitiesObject, unchecked_return_type = "ServerCapabilities")]
+    pub fn get_capabilities_object() -> JsValue {
+        to_js(&Self::capabilities())
+    }
+}
+
+impl RunefileLspServer {
+    /// Capabilities advertised to clients
+    fn capabilities() -> serde_json::Value {
         serde_json::json!({
-            "textDocumentSync": 1,
+            "positionEncoding": POSITION_ENCODING,
+            "textDocumentSync": 2,
             "completionProvider": {
-                "triggerCharacters": [" ", "\n"],
+                "triggerCharacters": [" ", "\n", "$", "{"],
                 "resolveProvider": false
             },
             "hoverProvider": true,
+            "signatureHelpProvider": {
+                "triggerCharacters": ["-", "=", ","]
+            },
             "diagnosticProvider": {
                 "interFileDependencies": false,
                 "workspaceDiagnostics": false
             },
+            "foldingRangeProvider": true,
+            "selectionRangeProvider": true,
+            "documentSymbolProvider": true,
+            "definitionProvider": true,
+            "semanticTokensProvider": {
+                "legend": {
+                    "tokenTypes": TOKEN_TYPES,
+                    "tokenModifiers": []
+                },
+                "full": true
+            },
             "documentFormattingProvider": true
         })
-        .to_string()
+    }
+
+    /// Content of an open document
+    fn document(&self, uri: &str) -> Option<&str> {
+        self.documents.get(uri).map(|doc| doc.content.as_str())
+    }
+
+    /// Diagnostics of an open document, compose or Runefile
+    fn document_diagnostics(&self, uri: &str) -> Vec<Diagnostic> {
+        let Some(doc) = self.documents.get(uri) else {
+            return Vec::new();
+        };
+        if self.compose.is_compose_file(uri) {
+            self.compose.get_diagnostics(&doc.content)
+        } else {
+            self.lint_diagnostics(&doc.parser, &doc.content)
+        }
+    }
+
+    /// Completions in an open document, compose or Runefile
+    fn document_completions(&self, uri: &str, line: u32, character: u32) -> Vec<CompletionItem> {
+        let Some(content) = self.document(uri) else {
+            return Vec::new();
+        };
+        if self.compose.is_compose_file(uri) {
+            self.compose
+                .get_completions(content, line as usize, character as usize)
+        } else {
+            self.completion.completions(content, line, character)
+        }
+    }
+
+    /// Hover in an open document, compose or Runefile
+    fn document_hover(&self, uri: &str, line: u32, character: u32) -> Option<HoverResult> {
+        let content = self.document(uri)?;
+        if self.compose.is_compose_file(uri) {
+            self.compose
+                .get_hover(content, line as usize, character as usize)
+        } else {
+            self.hover.hover(content, line, character)
+        }
+    }
+
+    /// Validate content, counting parser errors and lint errors
+    fn validation(&mut self, content: &str) -> ValidationResult {
+        let diagnostics = self.diagnostics(content);
+        let error_count = self.parser.error_count()
+            + diagnostics
+                .iter()
+                .filter(|d| d.code.is_some() && d.severity == 1)
+                .count();
+        ValidationResult {
+            valid: error_count == 0,
+            error_count,
+            instruction_count: self.parser.instruction_count(),
+            diagnostics,
+        }
+    }
+    /// Parser and lint rule diagnostics for content
+    fn diagnostics(&mut self, content: &str) -> Vec<Diagnostic> {
+        self.parser.parse(content);
+        self.lint_diagnostics(&self.parser, content)
+    }
+
+    /// Diagnostics of a parsed document, followed by lint rule diagnostics
+    fn lint_diagnostics(&self, parser: &RunefileParser, content: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = parser.diagnostics();
+        diagnostics.extend(
+            runefile_lint::lint(content, &self.lint_config)
+                .into_iter()
+                .map(|issue| Diagnostic {
+                    range: Range {
+                        start: Position {
+                            line: issue.line as u32,
+                            character: issue.column as u32,
+                        },
+                        end: Position {
+                            line: issue.line as u32,
+                            character: issue.end_column as u32,
+                        },
+                    },
+                    severity: issue.severity.lsp_code(),
+                    code: Some(issue.rule.to_string()),
+                    message: issue.message,
+                    source: "rune-lint".to_string(),
+                }),
+        );
+        utf16_diagnostics(content, &mut diagnostics);
+        diagnostics
     }
 }
 
+/// Byte offset of a UTF-16 position, clamped to the document
+fn offset_at(content: &str, line: u32, character: u32) -> usize {
+    let line_start = if line == 0 {
+        0
+    } else {
+        match content.match_indices('\n').nth(line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return content.len(),
+        }
+    };
+    let line_end = content[line_start..]
+        .find('\n')
+        .map_or(content.len(), |i| line_start + i);
+    line_start + byte_column(&content[line_start..line_end], character)
+}
+
 impl Default for RunefileLspServer {
     fn default() -> Self {
         Self::new()
@@ -231,6 +692,56 @@ mod tests {
         assert!(diagnostics.contains("[]") || !diagnostics.contains("error"));
     }
 
+    #[test]
+    fn test_apply_change() {
+        let mut server = RunefileLspServer::new();
+        let uri = "file:///Runefile";
+        server.open_document(uri, "FROM alpine\nRUN echo hello\n", 1);
+
+        server.apply_change(uri, 1, 9, 1, 14, "bye", 2);
+        server.apply_change(uri, 1, 12, 1, 12, "\nEXPOSE 80x", 3);
+        assert_eq!(
+            server.get_document_content(uri).unwrap(),
+            "FROM alpine\nRUN echo bye\nEXPOSE 80x\n"
+        );
+        assert!(server
+            .get_diagnostics(uri)
+            .contains("Invalid port number: 80x"));
+        assert!(RunefileLspServer::get_capabilities().contains("\"textDocumentSync\":2"));
+    }
+
+    #[test]
+    fn test_utf16_positions() {
+        let mut server = RunefileLspServer::new();
+        let uri = "file:///Runefile";
+        // The crab is two UTF-16 code units and four bytes
+        server.open_document(
+            uri,
+            "FROM alpine\nARG HOME=/root\nRUN echo 🦀 $HOME\nFRÖM x\n",
+            1,
+        );
+
+        let hover: crate::parser::HoverResult =
+            serde_json::from_str(&server.get_hover(uri, 2, 14)).unwrap();
+        let range = hover.range.unwrap();
+        assert_eq!((range.start.character, range.end.character), (13, 17));
+
+        let diagnostics: Vec<Diagnostic> =
+            serde_json::from_str(&server.get_diagnostics(uri)).unwrap();
+        let unknown = diagnostics
+            .iter()
+            .find(|d| d.range.start.line == 3)
+            .unwrap();
+        assert_eq!(unknown.range.end.character, 4);
+
+        server.apply_change(uri, 2, 11, 2, 17, "", 2);
+        assert_eq!(
+            server.get_document_content(uri).unwrap(),
+            "FROM alpine\nARG HOME=/root\nRUN echo 🦀\nFRÖM x\n"
+        );
+        assert!(RunefileLspServer::get_capabilities().contains("\"positionEncoding\":\"utf-16\""));
+    }
+
     #[test]
     fn test_validate() {
         let mut server = RunefileLspServer::new();
@@ -238,11 +749,174 @@ mod tests {
         assert!(result.contains("\"valid\":true"));
     }
 
+    #[test]
+    fn test_lint_diagnostics() {
+        let mut server = RunefileLspServer::new();
+        let content = "FROM alpine:latest\nRUN sudo apk add curl";
+
+        let result = server.validate(content);
+        assert!(result.contains("\"code\":\"RUNE1002\""));
+        assert!(result.contains("\"valid\":true"));
+
+        server
+            .set_lint_config("[rules]\nRUNE1003 = \"error\"\nRUNE1002 = \"off\"")
+            .unwrap();
+        let diagnostics = server.get_diagnostics_for_content(content);
+        assert!(!diagnostics.contains("RUNE1002"));
+        assert!(server.validate(content).contains("\"valid\":false"));
+    }
+
+    #[test]
+    fn test_configuration() {
+        let mut server = RunefileLspServer::new();
+        let content = "FROM ubuntu:latest\nRUN sudo make \\\n  install\n";
+        server
+            .set_configuration(
+                r#"{
+                    "maxLineLength": 15,
+                    "rules": {"RUNE1003": "error"},
+                    "disabledRules": ["rune1002"],
+                    "preferredRegistry": "ghcr.io",
+                    "format": {"continuationIndent": 2}
+                }"#,
+            )
+            .unwrap();
+        let diagnostics = server.get_diagnostics_for_content(content);
+        for rule in ["RUNE1003", "RUNE1006", "RUNE1007"] {
+            assert!(diagnostics.contains(rule), "{}", rule);
+        }
+        assert!(!diagnostics.contains("RUNE1002"));
+        assert!(server.validate(content).contains("\"valid\":false"));
+        assert_eq!(
+            server.format(content),
+            "FROM ubuntu:latest\nRUN sudo make \\\n  install\n"
+        );
+
+        assert!(server.set_configuration("{}").is_ok());
+        assert!(!server
+            .get_diagnostics_for_content(content)
+            .contains("RUNE1006"));
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let server = RunefileLspServer::new();
+        let tokens =
+            server.get_semantic_tokens_for_content("FROM alpine AS base\nCOPY --from=base /a /b");
+        assert_eq!(
+            tokens,
+            r#"{"data":[0,0,4,0,0,0,12,2,0,0,0,3,4,2,0,1,0,4,0,0,0,5,6,1,0,0,7,4,2,0]}"#
+        );
+        assert!(RunefileLspServer::get_capabilities().contains("semanticTokensProvider"));
+    }
+
+    #[test]
+    fn test_compose_documents() {
+        let mut server = RunefileLspServer::new();
+        let uri = "file:///app/docker-compose.yml";
+        server.open_document(
+            uri,
+            "services:\n  web:\n    image: nginx\n    ports:\n      - 80\n      - \"0:80\"\n    \n",
+            1,
+        );
+
+        let diagnostics: Vec<Diagnostic> =
+            serde_json::from_str(&server.get_diagnostics(uri)).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 5);
+
+        let completions = server.get_completions(uri, 6, 4);
+        assert!(completions.contains("\"depends_on\""));
+        assert!(!completions.contains("\"FROM\""));
+        assert!(server.get_hover(uri, 3, 5).contains("Published ports"));
+    }
+
+    #[test]
+    fn test_variables() {
+        let server = RunefileLspServer::new();
+        let content = "ARG VERSION=1.0\nFROM alpine:${VER\nENV HOME_DIR /srv\nRUN cd $\n";
+
+        let completions = server.get_completions_for_content(content, 1, 17);
+        assert!(completions.contains(r#""insertText":"VERSION}""#));
+        let completions = server.get_completions_for_content(content, 3, 8);
+        assert!(completions.contains(r#""detail":"ENV HOME_DIR=/srv""#));
+        assert!(completions.contains(r#""label":"TARGETARCH""#));
+
+        let hover = server.get_hover_for_content("ARG V=2\nFROM a:${V}", 1, 9);
+        assert!(hover.contains("ARG V=2"));
+        assert!(hover.contains(r#""start":{"line":1,"character":9}"#));
+    }
+
+    #[test]
+    fn test_signature_help() {
+        let server = RunefileLspServer::new();
+        let help =
+            server.get_signature_help_for_content("FROM alpine\nRUN --mount=type=cache,ta", 1, 26);
+        assert!(help.contains("\"activeParameter\":1"));
+        assert_eq!(
+            server.get_signature_help_for_content("FROM alpine\nRUN ls", 1, 6),
+            "null"
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let server = RunefileLspServer::new();
+        let content = "FROM alpine\nRUN <<EOF\necho hi\nEOF\n";
+        assert_eq!(
+            server.get_folding_ranges_for_content(content),
+            r#"[{"startLine":0,"endLine":3,"kind":"region"},{"startLine":1,"endLine":3}]"#
+        );
+        let selection = server.get_selection_range_for_content(content, 2, 1);
+        assert!(selection.starts_with(r#"{"range":{"start":{"line":2,"character":0}"#));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut server = RunefileLspServer::new();
+        let uri = "file:///Runefile";
+        server.open_document(
+            uri,
+            "FROM rust AS 🦀
+FROM alpine
+COPY --from=🦀 /a /a
+",
+            1,
+        );
+
+        let symbols = server.get_document_symbols(uri);
+        assert!(symbols.starts_with(r#"[{"name":"🦀","detail":"rust","kind":2"#));
+        assert_eq!(
+            server.get_definition(uri, 2, 12),
+            r#"{"uri":"file:///Runefile","range":{"start":{"line":0,"character":13},"end":{"line":0,"character":15}}}"#
+        );
+        assert_eq!(server.get_definition(uri, 1, 6), "null");
+        assert_eq!(server.get_definition("file:///missing", 0, 0), "null");
+    }
+
+    #[test]
+    fn test_missing_document() {
+        let server = RunefileLspServer::new();
+        let uri = "file:///missing";
+        assert_eq!(server.get_diagnostics(uri), "[]");
+        assert_eq!(server.get_completions(uri, 0, 0), "[]");
+        assert_eq!(server.get_hover(uri, 0, 0), "null");
+        assert_eq!(server.get_folding_ranges(uri), "[]");
+        assert_eq!(server.get_selection_range(uri, 0, 0), "null");
+        assert_eq!(server.get_semantic_tokens(uri), "null");
+        assert_eq!(server.get_semantic_tokens_for_content(""), r#"{"data":[]}"#);
+    }
+
     #[test]
     fn test_format() {
         let server = RunefileLspServer::new();
         let formatted = server.format("from alpine\nrun echo hello");
         assert!(formatted.contains("FROM alpine"));
         assert!(formatted.contains("RUN echo hello"));
+
+        let formatted = server
+            .format_with_options("run a \\\nb", r#"{"continuationIndent": 2}"#)
+            .unwrap();
+        assert_eq!(formatted, "RUN a \\\n  b");
     }
 }
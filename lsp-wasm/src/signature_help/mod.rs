@@ -0,0 +1,85 @@
+//! Signature help for Runefile LSP
+//!
+//! The signatures come from `runefile-lint`, shared with the native server.
+//! Their labels are ASCII, so parameter offsets need no conversion.
+
+use crate::parser::types::*;
+use crate::position::{byte_column, line_text};
+use runefile_lint::signature_help::{signature_help, Signature};
+use wasm_bindgen::prelude::*;
+
+/// Signature help provider for Runefile
+#[wasm_bindgen]
+pub struct SignatureHelpProvider;
+
+#[wasm_bindgen]
+impl SignatureHelpProvider {
+    /// Create a new signature help provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Get signature help at position as JSON, `null` outside flags (works offline)
+    #[wasm_bindgen(js_name = getSignatureHelp)]
+    pub fn get_signature_help(&self, content: &str, line: u32, character: u32) -> String {
+        self.signature_help(content, line, character)
+            .and_then(|help| serde_json::to_string(&help).ok())
+            .unwrap_or_else(|| "null".to_string())
+    }
+}
+
+impl SignatureHelpProvider {
+    /// Get signature help at a position, `None` outside flags
+    pub fn signature_help(
+        &self,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> Option<SignatureHelp> {
+        let column = byte_column(line_text(content, line), character);
+        signature_help(content, line as usize, column).map(convert)
+    }
+}
+
+impl Default for SignatureHelpProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn convert(signature: Signature) -> SignatureHelp {
+    SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: signature.label,
+            documentation: signature.documentation.map(str::to_string),
+            parameters: signature
+                .parameters
+                .into_iter()
+                .map(|parameter| ParameterInformation {
+                    label: [parameter.start as u32, parameter.end as u32],
+                    documentation: Some(parameter.documentation),
+                })
+                .collect(),
+        }],
+        active_signature: Some(0),
+        active_parameter: signature.active.map(|i| i as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_position() {
+        // 'é' is two bytes and one UTF-16 unit
+        let content = "FROM alpine\nCOPY --chown=é --li";
+        let help = SignatureHelpProvider::new()
+            .signature_help(content, 1, 19)
+            .unwrap();
+        let signature = &help.signatures[0];
+        let [start, end] = signature.parameters[help.active_parameter.unwrap() as usize].label;
+        assert_eq!(&signature.label[start as usize..end as usize], "--link");
+    }
+}
@@ -0,0 +1,427 @@
+//! Document symbols and go to definition for Runefile LSP
+
+use crate::completion::variables_before;
+use crate::hover::variable_at;
+use crate::parser::{types::*, RunefileParser};
+use crate::position::{byte_column, line_text, utf16_range};
+use wasm_bindgen::prelude::*;
+
+/// LSP symbol kind of build stages
+pub const SYMBOL_KIND_MODULE: u8 = 2;
+/// LSP symbol kind of ARG and ENV declarations
+pub const SYMBOL_KIND_VARIABLE: u8 = 13;
+
+/// A word of a line with its byte offset
+type Word<'a> = (usize, &'a str);
+
+/// A FROM instruction and the lines of its stage
+struct Stage<'a> {
+    image: Word<'a>,
+    alias: Option<Word<'a>>,
+    line: usize,
+    end_line: usize,
+}
+
+/// A name declared by ARG or ENV
+struct Declaration<'a> {
+    line: usize,
+    name: Word<'a>,
+    value: Option<&'a str>,
+}
+
+/// Document symbol and definition provider for Runefile
+#[wasm_bindgen]
+pub struct SymbolProvider;
+
+#[wasm_bindgen]
+impl SymbolProvider {
+    /// Create a new symbol provider
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Get document symbols as JSON (works offline)
+    #[wasm_bindgen(js_name = getDocumentSymbols)]
+    pub fn get_document_symbols_json(&self, content: &str) -> String {
+        serde_json::to_string(&self.document_symbols(content)).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Get the definition at a position as JSON (works offline)
+    #[wasm_bindgen(js_name = getDefinition)]
+    pub fn get_definition_json(
+        &self,
+        uri: &str,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> String {
+        self.definition(uri, content, line, character)
+            .and_then(|location| serde_json::to_string(&location).ok())
+            .unwrap_or_else(|| "null".to_string())
+    }
+}
+
+impl SymbolProvider {
+    /// Get the document symbols with UTF-16 ranges
+    pub fn document_symbols(&self, content: &str) -> Vec<DocumentSymbol> {
+        let mut symbols = self.get_document_symbols(content);
+        let mut pending: Vec<&mut DocumentSymbol> = symbols.iter_mut().collect();
+        while let Some(symbol) = pending.pop() {
+            symbol.range = utf16_range(content, symbol.range);
+            symbol.selection_range = utf16_range(content, symbol.selection_range);
+            pending.extend(symbol.children.iter_mut());
+        }
+        symbols
+    }
+
+    /// Get the definition at a position, with a UTF-16 range
+    pub fn definition(
+        &self,
+        uri: &str,
+        content: &str,
+        line: u32,
+        character: u32,
+    ) -> Option<Location> {
+        let column = byte_column(line_text(content, line), character);
+        let mut location = self.get_definition(uri, content, line as usize, column)?;
+        location.range = utf16_range(content, location.range);
+        Some(location)
+    }
+
+    /// Get the build stages of a document, with the ARG and ENV
+    /// declarations of each stage as children
+    ///
+    /// ARGs declared before the first FROM are top-level symbols.
+    pub fn get_document_symbols(&self, content: &str) -> Vec<DocumentSymbol> {
+        let lines: Vec<&str> = content.lines().collect();
+        let instructions = instructions(content);
+        let stages = stages(&lines, &instructions);
+        let mut symbols: Vec<DocumentSymbol> = Vec::new();
+        let mut in_stage = false;
+
+        for instruction in &instructions {
+            if instruction.kind == InstructionKind::From {
+                let Some(stage) = stages.iter().find(|s| s.line == instruction.line) else {
+                    continue;
+                };
+                let (start, name) = stage.alias.unwrap_or(stage.image);
+                let end_text = lines.get(stage.end_line).copied().unwrap_or("");
+                symbols.push(DocumentSymbol {
+                    name: name.to_string(),
+                    detail: stage.alias.map(|_| stage.image.1.to_string()),
+                    kind: SYMBOL_KIND_MODULE,
+                    range: span(
+                        stage.line,
+                        indent(lines[stage.line]),
+                        stage.end_line,
+                        end_text.trim_end().len(),
+                    ),
+                    selection_range: span(stage.line, start, stage.line, start + name.len()),
+                    children: Vec::new(),
+                });
+                in_stage = true;
+                continue;
+            }
+
+            let keyword = match instruction.kind {
+                InstructionKind::Arg => "ARG",
+                InstructionKind::Env => "ENV",
+                _ => continue,
+            };
+            let end_text = lines.get(instruction.end_line).copied().unwrap_or("");
+            let range = span(
+                instruction.line,
+                indent(lines[instruction.line]),
+                instruction.end_line,
+                end_text.trim_end().len(),
+            );
+            for declaration in declarations(&lines, instruction) {
+                let (start, name) = declaration.name;
+                let symbol = DocumentSymbol {
+                    name: name.to_string(),
+                    detail: Some(match declaration.value {
+                        Some(value) => format!("{} {}", keyword, value),
+                        None => keyword.to_string(),
+                    }),
+                    kind: SYMBOL_KIND_VARIABLE,
+                    range,
+                    selection_range: span(
+                        declaration.line,
+                        start,
+                        declaration.line,
+                        start + name.len(),
+                    ),
+                    children: Vec::new(),
+                };
+                match symbols.last_mut() {
+                    Some(stage) if in_stage => stage.children.push(symbol),
+                    _ => symbols.push(symbol),
+                }
+            }
+        }
+
+        symbols
+    }
+
+    /// Get the declaration of the stage name or variable at a byte column
+    ///
+    /// Stage names used by FROM, `--from=` and `--mount=...,from=` resolve to
+    /// their `AS` alias, and `$VAR` references to the latest ARG or ENV
+    /// declaring them.
+    pub fn get_definition(
+        &self,
+        uri: &str,
+        content: &str,
+        line: usize,
+        column: usize,
+    ) -> Option<Location> {
+        let lines: Vec<&str> = content.lines().collect();
+        let text = lines.get(line)?;
+        let location = |line: usize, (start, name): Word| Location {
+            uri: uri.to_string(),
+            range: span(line, start, line, start + name.len()),
+        };
+
+        if let Some((_, name)) = variable_at(text, column) {
+            let variable = variables_before(content, line)
+                .into_iter()
+                .rfind(|v| v.name == name)?;
+            let instructions = instructions(content);
+            let instruction = instructions.iter().find(|i| i.line == variable.line)?;
+            let declaration = declarations(&lines, instruction)
+                .into_iter()
+                .find(|d| d.name.1 == name)?;
+            return Some(location(declaration.line, declaration.name));
+        }
+
+        let (start, name) = stage_reference(content, &lines, line, column)?;
+        if !(start..=start + name.len()).contains(&column) {
+            return None;
+        }
+        let instructions = instructions(content);
+        stages(&lines, &instructions)
+            .iter()
+            .filter(|stage| stage.line < line)
+            .filter_map(|stage| stage.alias.map(|alias| (stage.line, alias)))
+            .rfind(|(_, (_, alias))| alias.eq_ignore_ascii_case(name))
+            .map(|(line, alias)| location(line, alias))
+    }
+}
+
+impl Default for SymbolProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn instructions(content: &str) -> Vec<Instruction> {
+    let mut parser = RunefileParser::new();
+    parser.parse(content);
+    parser.instructions
+}
+
+/// The FROM instructions of a document, each running until the next
+fn stages<'a>(lines: &[&'a str], instructions: &[Instruction]) -> Vec<Stage<'a>> {
+    let mut stages: Vec<Stage> = Vec::new();
+    for instruction in instructions {
+        if instruction.kind == InstructionKind::Comment {
+            continue;
+        }
+        if instruction.kind != InstructionKind::From {
+            if let Some(stage) = stages.last_mut() {
+                stage.end_line = instruction.end_line;
+            }
+            continue;
+        }
+        let Some(text) = lines.get(instruction.line) else {
+            continue;
+        };
+        let mut arguments = words(text)
+            .skip(1)
+            .skip_while(|(_, word)| word.starts_with("--"));
+        let Some(image) = arguments.next() else {
+            continue;
+        };
+        let alias = match arguments.next() {
+            Some((_, word)) if word.eq_ignore_ascii_case("AS") => arguments.next(),
+            _ => None,
+        };
+        stages.push(Stage {
+            image,
+            alias,
+            line: instruction.line,
+            end_line: instruction.end_line,
+        });
+    }
+    stages
+}
+
+/// The names declared by an ARG or ENV instruction
+fn declarations<'a>(lines: &[&'a str], instruction: &Instruction) -> Vec<Declaration<'a>> {
+    let mut declarations = Vec::new();
+    let last = instruction.end_line.min(lines.len().saturating_sub(1));
+    for (line, text) in lines
+        .iter()
+        .enumerate()
+        .take(last + 1)
+        .skip(instruction.line)
+    {
+        let skip = usize::from(line == instruction.line);
+        let mut line_words = words(text).skip(skip).peekable();
+        while let Some((offset, word)) = line_words.next() {
+            if word == "\\" || word.starts_with('#') {
+                break;
+            }
+            let (name, value) = match word.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim_matches(['"', '\'']))),
+                None => (word, None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                continue;
+            }
+            // Legacy `ENV KEY value` declares a single name
+            if instruction.kind == InstructionKind::Env && value.is_none() {
+                let value = line_words
+                    .peek()
+                    .map(|(start, _)| text[*start..].trim_end().trim_end_matches('\\').trim_end());
+                declarations.push(Declaration {
+                    line,
+                    name: (offset, name),
+                    value,
+                });
+                return declarations;
+            }
+            declarations.push(Declaration {
+                line,
+                name: (offset, name),
+                value,
+            });
+        }
+    }
+    declarations
+}
+
+/// The stage name used by the FROM, `--from=` or `from=` mount option
+/// under a byte column
+fn stage_reference<'a>(
+    content: &str,
+    lines: &[&'a str],
+    line: usize,
+    column: usize,
+) -> Option<Word<'a>> {
+    let text = lines.get(line)?;
+    let (offset, word) =
+        words(text).find(|(start, word)| (*start..=start + word.len()).contains(&column))?;
+
+    if let Some(name) = word.strip_prefix("--from=") {
+        return Some((offset + word.len() - name.len(), name));
+    }
+    if let Some(options) = word.strip_prefix("--mount=") {
+        let options_start = offset + word.len() - options.len();
+        let mut start = options_start;
+        for option in options.split(',') {
+            if let Some(name) = option.strip_prefix("from=") {
+                return Some((start + "from=".len(), name));
+            }
+            start += option.len() + 1;
+        }
+        return None;
+    }
+
+    let instructions = instructions(content);
+    let stage = stages(lines, &instructions)
+        .into_iter()
+        .find(|stage| stage.line == line)?;
+    (stage.image.0 == offset).then_some(stage.image)
+}
+
+/// Words of a line with their byte offsets
+fn words(line: &str) -> impl Iterator<Item = Word<'_>> {
+    line.split_whitespace()
+        .map(move |word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn span(start_line: usize, start: usize, end_line: usize, end: usize) -> Range {
+    Range {
+        start: Position {
+            line: start_line as u32,
+            character: start as u32,
+        },
+        end: Position {
+            line: end_line as u32,
+            character: end as u32,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "ARG VERSION=1.0\nFROM rust:$VERSION AS build\nENV PROFILE=release \\\n    TARGET=x86\nRUN cargo build\n\nFROM --platform=linux/amd64 alpine AS runtime\nENV PATH /app/bin\nCOPY --from=build /app /app\nRUN --mount=type=cache,from=build,target=/cache ls\nRUN echo $PROFILE\nFROM build\n";
+
+    fn range(range: Range) -> (u32, u32, u32, u32) {
+        (
+            range.start.line,
+            range.start.character,
+            range.end.line,
+            range.end.character,
+        )
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let symbols = SymbolProvider::new().get_document_symbols(CONTENT);
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["VERSION", "build", "runtime", "build"]);
+
+        let build = &symbols[1];
+        assert_eq!(build.kind, SYMBOL_KIND_MODULE);
+        assert_eq!(build.detail.as_deref(), Some("rust:$VERSION"));
+        assert_eq!(range(build.range), (1, 0, 4, 15));
+        assert_eq!(range(build.selection_range), (1, 22, 1, 27));
+        let children: Vec<(&str, Option<&str>)> = build
+            .children
+            .iter()
+            .map(|c| (c.name.as_str(), c.detail.as_deref()))
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                ("PROFILE", Some("ENV release")),
+                ("TARGET", Some("ENV x86"))
+            ]
+        );
+        assert_eq!(range(build.children[1].selection_range), (3, 4, 3, 10));
+
+        let runtime = &symbols[2];
+        assert_eq!(range(runtime.range), (6, 0, 10, 17));
+        assert_eq!(runtime.children[0].name, "PATH");
+        assert_eq!(runtime.children[0].detail.as_deref(), Some("ENV /app/bin"));
+        assert_eq!(symbols[3].detail, None);
+    }
+
+    #[test]
+    fn test_definition() {
+        let provider = SymbolProvider::new();
+        let definition =
+            |line, column| provider.get_definition("file:///Runefile", CONTENT, line, column);
+
+        // --from=, mount from= and FROM all jump to the alias
+        for (line, column) in [(8, 14), (9, 30), (11, 6)] {
+            let location = definition(line, column).unwrap();
+            assert_eq!(range(location.range), (1, 22, 1, 27));
+        }
+        // Variables jump to their declaration
+        assert_eq!(range(definition(10, 11).unwrap().range), (2, 4, 2, 11));
+        assert_eq!(range(definition(1, 12).unwrap().range), (0, 4, 0, 11));
+        // Images and unknown stages have no definition
+        assert!(definition(6, 30).is_none());
+        assert!(definition(4, 5).is_none());
+    }
+}
@@ -0,0 +1,178 @@
+//! Generates the TypeScript declarations of the types `RuneClient` returns
+//!
+//! Each struct listed in `INTERFACES` is read from `src/types/mod.rs` and
+//! written to `$OUT_DIR/types.ts` as an interface, naming its fields the way
+//! serde does. The crate adds the file to its TypeScript bindings.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::{env, fs};
+use syn::{Attribute, Fields, GenericArgument, Item, ItemStruct, LitStr, PathArguments, Type};
+
+const TYPES: &str = "src/types/mod.rs";
+
+/// Structs declared as interfaces, in order
+const INTERFACES: &[&str] = &[
+    "Container",
+    "PortBinding",
+    "ContainerInspect",
+    "ContainerInspectState",
+    "ContainerInspectConfig",
+    "ContainerMount",
+    "Image",
+    "Network",
+    "IpamConfig",
+    "IpamPoolConfig",
+    "Volume",
+    "VolumeList",
+    "SystemInfo",
+    "Version",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", TYPES);
+    let source = fs::read_to_string(TYPES).expect("failed to read the API types");
+    let file = syn::parse_file(&source).expect("failed to parse the API types");
+    let structs: Vec<&ItemStruct> = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Struct(item) => Some(item),
+            _ => None,
+        })
+        .collect();
+
+    let exported: HashSet<&str> = INTERFACES.iter().copied().collect();
+    let mut out = String::new();
+    for name in INTERFACES {
+        let item = structs
+            .iter()
+            .find(|item| item.ident == name)
+            .unwrap_or_else(|| panic!("no struct {} in {}", name, TYPES));
+        out.push_str(&interface(item, &exported));
+    }
+
+    let path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("types.ts");
+    fs::write(path, out).expect("failed to write the TypeScript types");
+}
+
+/// The interface of a struct
+fn interface(item: &ItemStruct, exported: &HashSet<&str>) -> String {
+    let rename_all = serde_option(&item.attrs, "rename_all");
+    let Fields::Named(fields) = &item.fields else {
+        panic!("{} has no named fields", item.ident);
+    };
+
+    let mut out = format!("\nexport interface {} {{\n", item.ident);
+    for field in &fields.named {
+        if serde_flag(&field.attrs, "skip") || serde_flag(&field.attrs, "skip_serializing") {
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap().to_string();
+        let name =
+            serde_option(&field.attrs, "rename").unwrap_or_else(|| match rename_all.as_deref() {
+                Some("PascalCase") => pascal_case(&ident),
+                Some("camelCase") => {
+                    let pascal = pascal_case(&ident);
+                    pascal[..1].to_lowercase() + &pascal[1..]
+                }
+                Some(other) => panic!("unsupported rename_all = \"{}\"", other),
+                None => ident.clone(),
+            });
+        let ty = typescript(&field.ty, exported)
+            .unwrap_or_else(|| panic!("no TypeScript type for {}.{}", item.ident, ident));
+        out.push_str(&format!("    {}: {};\n", name, ty));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The TypeScript type a Rust type serializes as
+fn typescript(ty: &Type, exported: &HashSet<&str>) -> Option<String> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    let arguments: Vec<&Type> = match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => arguments
+            .args
+            .iter()
+            .filter_map(|argument| match argument {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let name = segment.ident.to_string();
+    Some(match (name.as_str(), arguments.as_slice()) {
+        ("String", []) => "string".to_string(),
+        ("bool", []) => "boolean".to_string(),
+        ("i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "f32" | "f64", []) => {
+            "number".to_string()
+        }
+        ("Option", [inner]) => format!("{} | null", typescript(inner, exported)?),
+        ("Vec", [inner]) => {
+            let inner = typescript(inner, exported)?;
+            if inner.contains(' ') {
+                format!("({})[]", inner)
+            } else {
+                format!("{}[]", inner)
+            }
+        }
+        ("HashMap", [key, value]) if typescript(key, exported)? == "string" => {
+            format!("Record<string, {}>", typescript(value, exported)?)
+        }
+        (name, []) if exported.contains(name) => name.to_string(),
+        _ => return None,
+    })
+}
+
+/// `snake_case` as `PascalCase`
+fn pascal_case(ident: &str) -> String {
+    ident
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The value of a `#[serde(key = "...")]` option
+fn serde_option(attrs: &[Attribute], key: &str) -> Option<String> {
+    let mut value = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(key) {
+                value = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<LitStr>()?;
+            }
+            Ok(())
+        })
+        .expect("malformed serde attribute");
+    }
+    value
+}
+
+/// Whether a `#[serde(flag)]` is set
+fn serde_flag(attrs: &[Attribute], flag: &str) -> bool {
+    let mut set = false;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(flag) {
+                set = true;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<LitStr>()?;
+            }
+            Ok(())
+        })
+        .expect("malformed serde attribute");
+    }
+    set
+}
@@ -150,10 +150,10 @@ impl RunefileBuilder {
                                     errors.push("COPY/ADD has no destination".to_string());
                                 }
                             }
-                            BuildInstruction::Workdir { path } => {
-                                if !path.starts_with('/') && !path.starts_with('$') {
-                                    warnings.push(format!("WORKDIR '{}' should be absolute", path));
-                                }
+                            BuildInstruction::Workdir { path }
+                                if !path.starts_with('/') && !path.starts_with('$') =>
+                            {
+                                warnings.push(format!("WORKDIR '{}' should be absolute", path));
                             }
                             _ => {}
                         }
@@ -191,8 +191,8 @@ impl RunefileBuilder {
                 continue;
             }
 
-            if line.ends_with('\\') {
-                continued_line.push_str(&line[..line.len() - 1]);
+            if let Some(continued) = line.strip_suffix('\\') {
+                continued_line.push_str(continued);
                 continued_line.push(' ');
                 continue;
             }
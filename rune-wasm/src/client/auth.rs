@@ -0,0 +1,79 @@
+//! Client authentication
+//!
+//! Headers sent with every HTTP request. Browsers cannot set headers on a
+//! WebSocket handshake, so the same headers go to the daemon in an
+//! `authenticate` message once the socket opens.
+
+use crate::utils::base64_encode;
+
+/// Credentials and extra headers of a client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientAuth {
+    authorization: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl ClientAuth {
+    /// Authorize with a bearer token
+    pub fn set_bearer(&mut self, token: &str) {
+        self.authorization = Some(format!("Bearer {}", token));
+    }
+
+    /// Authorize with a username and password
+    pub fn set_basic(&mut self, username: &str, password: &str) {
+        let credentials = format!("{}:{}", username, password);
+        self.authorization = Some(format!("Basic {}", base64_encode(credentials.as_bytes())));
+    }
+
+    /// Send a header with every request, replacing one of the same name
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Forget the credentials and headers
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Headers to send, `Authorization` first
+    pub fn headers(&self) -> Vec<(String, String)> {
+        self.authorization
+            .iter()
+            .map(|value| ("Authorization".to_string(), value.clone()))
+            .chain(self.headers.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_auth() {
+        let mut auth = ClientAuth::default();
+        assert!(auth.headers().is_empty());
+
+        auth.set_basic("user", "pass");
+        auth.set_header("X-Tenant", "a");
+        auth.set_header("x-tenant", "b");
+        assert_eq!(
+            auth.headers(),
+            vec![
+                (
+                    "Authorization".to_string(),
+                    "Basic dXNlcjpwYXNz".to_string()
+                ),
+                ("x-tenant".to_string(), "b".to_string()),
+            ]
+        );
+
+        auth.set_bearer("abc");
+        assert_eq!(auth.headers()[0].1, "Bearer abc");
+
+        auth.clear();
+        assert!(auth.headers().is_empty());
+    }
+}
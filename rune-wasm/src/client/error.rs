@@ -0,0 +1,98 @@
+//! Client errors
+
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Failed call, with the HTTP status and the daemon's message
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuneClientError {
+    status: u16,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl RuneClientError {
+    /// HTTP status, 0 when the call failed without a daemon response
+    #[wasm_bindgen(getter)]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Error message
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn display(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl RuneClientError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Error of a failed response, with the daemon's `message` or the body
+    pub fn from_response(status: u16, body: &serde_json::Value) -> Self {
+        let message = body
+            .get("message")
+            .and_then(|m| m.as_str())
+            .or_else(|| body.as_str())
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map_or_else(|| format!("HTTP {}", status), str::to_string);
+        Self::new(status, message)
+    }
+
+    /// Error of a call that failed in the browser, such as a network error
+    pub fn from_js(error: &JsValue) -> Self {
+        let message = error
+            .dyn_ref::<js_sys::Error>()
+            .map(|e| String::from(e.message()))
+            .or_else(|| error.as_string())
+            .unwrap_or_else(|| "Request failed".to_string());
+        Self::new(0, message)
+    }
+}
+
+impl fmt::Display for RuneClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.status == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} ({})", self.message, self.status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_error_from_response() {
+        let error =
+            RuneClientError::from_response(404, &json!({ "message": "No such container: abc" }));
+        assert_eq!(error.status(), 404);
+        assert_eq!(error.message(), "No such container: abc");
+        assert_eq!(error.to_string(), "No such container: abc (404)");
+
+        let error = RuneClientError::from_response(500, &json!("daemon panicked\n"));
+        assert_eq!(error.message(), "daemon panicked");
+        let error = RuneClientError::from_response(502, &serde_json::Value::Null);
+        assert_eq!(error.message(), "HTTP 502");
+
+        assert_eq!(
+            RuneClientError::new(0, "Not connected").to_string(),
+            "Not connected"
+        );
+    }
+}
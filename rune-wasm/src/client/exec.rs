@@ -0,0 +1,81 @@
+//! Interactive exec sessions
+//!
+//! A session runs a command in a container and carries its terminal over
+//! the client's WebSocket, next to requests and other streams.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::WebSocket;
+
+use super::{send_message, ClientMessage, Subscription};
+
+/// Duplex terminal session of a command running in a container
+#[wasm_bindgen]
+pub struct ExecSession {
+    #[wasm_bindgen(skip)]
+    pub id: String,
+    #[wasm_bindgen(skip)]
+    pub ws: WebSocket,
+    #[wasm_bindgen(skip)]
+    pub subscriptions: Rc<RefCell<HashMap<String, Subscription>>>,
+}
+
+#[wasm_bindgen]
+impl ExecSession {
+    /// Session ID
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Set the output callback: (data: string) => void
+    ///
+    /// The callback receives `null` when the command exits.
+    #[wasm_bindgen(setter = onData)]
+    pub fn set_on_data(&self, callback: js_sys::Function) {
+        if let Some(subscription) = self.subscriptions.borrow_mut().get_mut(&self.id) {
+            subscription.callback = callback;
+        }
+    }
+
+    /// Send input to the command
+    #[wasm_bindgen]
+    pub fn write(&self, data: &str) -> Result<(), JsValue> {
+        send_message(
+            &self.ws,
+            &ClientMessage::Input {
+                subscription_id: self.id.clone(),
+                data: data.to_string(),
+            },
+        )
+    }
+
+    /// Tell the command its terminal changed size
+    #[wasm_bindgen]
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), JsValue> {
+        send_message(
+            &self.ws,
+            &ClientMessage::Resize {
+                subscription_id: self.id.clone(),
+                cols,
+                rows,
+            },
+        )
+    }
+
+    /// End the session
+    #[wasm_bindgen]
+    pub fn close(&self) -> Result<(), JsValue> {
+        if self.subscriptions.borrow_mut().remove(&self.id).is_none() {
+            return Ok(());
+        }
+        send_message(
+            &self.ws,
+            &ClientMessage::Unsubscribe {
+                subscription_id: self.id.clone(),
+            },
+        )
+    }
+}
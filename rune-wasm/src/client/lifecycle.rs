@@ -0,0 +1,291 @@
+//! Simulated container lifecycle
+//!
+//! Models what a daemon does with a running container: log lines at an
+//! interval, healthchecks moving its health between starting, healthy and
+//! unhealthy, and an exit handled by its restart policy. `Lifecycle` takes
+//! the time as an argument; `LocalContainerManager` drives it from timers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::RestartPolicy;
+
+/// Longest wait before restarting a container that keeps exiting
+const MAX_RESTART_DELAY_MS: u64 = 60_000;
+
+/// How a simulated container behaves
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct Simulation {
+    /// Delay between starting a container and it running
+    pub startup_delay_ms: u64,
+    /// Interval between log lines, no logs when 0
+    pub log_interval_ms: u64,
+    /// Lines logged in turn, numbered lines when empty
+    pub log_lines: Vec<String>,
+    /// Run time before the container exits, running until stopped when unset
+    pub exit_after_ms: Option<u64>,
+    pub exit_code: i32,
+    /// Run time after which healthchecks fail, passing forever when unset
+    pub unhealthy_after_ms: Option<u64>,
+}
+
+/// Healthcheck of a container, with Docker's durations in nanoseconds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct Healthcheck {
+    pub test: Vec<String>,
+    pub interval: u64,
+    pub retries: u32,
+    pub start_period: u64,
+}
+
+impl Healthcheck {
+    fn interval_ms(&self) -> u64 {
+        match self.interval / 1_000_000 {
+            0 => 30_000,
+            ms => ms,
+        }
+    }
+
+    fn retries(&self) -> u32 {
+        match self.retries {
+            0 => 3,
+            retries => retries,
+        }
+    }
+
+    fn start_period_ms(&self) -> u64 {
+        self.start_period / 1_000_000
+    }
+}
+
+/// Health of a container with a healthcheck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl Health {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// Something that happened to a running container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition {
+    Log(String),
+    Health(Health),
+    Exit(i32),
+}
+
+/// Schedule of a running container
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    simulation: Simulation,
+    healthcheck: Option<Healthcheck>,
+    started_ms: u64,
+    next_log_ms: Option<u64>,
+    next_check_ms: Option<u64>,
+    exit_ms: Option<u64>,
+    logged: usize,
+    failures: u32,
+    health: Option<Health>,
+}
+
+impl Lifecycle {
+    /// Lifecycle of a container that began running at `now`
+    pub fn new(simulation: Simulation, healthcheck: Option<Healthcheck>, now: u64) -> Self {
+        let next_log_ms =
+            (simulation.log_interval_ms > 0).then(|| now + simulation.log_interval_ms);
+        let next_check_ms = healthcheck.as_ref().map(|h| now + h.interval_ms());
+        let exit_ms = simulation.exit_after_ms.map(|after| now + after);
+        Self {
+            health: healthcheck.as_ref().map(|_| Health::Starting),
+            simulation,
+            healthcheck,
+            started_ms: now,
+            next_log_ms,
+            next_check_ms,
+            exit_ms,
+            logged: 0,
+            failures: 0,
+        }
+    }
+
+    pub fn health(&self) -> Option<Health> {
+        self.health
+    }
+
+    /// Time of the next transition, `None` when nothing more happens
+    pub fn next_due(&self) -> Option<u64> {
+        [self.next_log_ms, self.next_check_ms, self.exit_ms]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Apply the transitions due by `now`, in order; an exit ends the
+    /// lifecycle
+    pub fn advance(&mut self, now: u64) -> Vec<Transition> {
+        let mut transitions = Vec::new();
+        while let Some(due) = self.next_due().filter(|due| *due <= now) {
+            if self.next_log_ms == Some(due) {
+                transitions.push(Transition::Log(self.log_line()));
+                self.next_log_ms = Some(due + self.simulation.log_interval_ms);
+            } else if self.next_check_ms == Some(due) {
+                transitions.extend(self.check(due).map(Transition::Health));
+            } else {
+                transitions.push(Transition::Exit(self.simulation.exit_code));
+                self.next_log_ms = None;
+                self.next_check_ms = None;
+                self.exit_ms = None;
+            }
+        }
+        transitions
+    }
+
+    fn log_line(&mut self) -> String {
+        self.logged += 1;
+        let lines = &self.simulation.log_lines;
+        if lines.is_empty() {
+            format!("log line {}", self.logged)
+        } else {
+            lines[(self.logged - 1) % lines.len()].clone()
+        }
+    }
+
+    /// Run a healthcheck, returning the new health when it changed
+    fn check(&mut self, now: u64) -> Option<Health> {
+        let healthcheck = self.healthcheck.as_ref()?;
+        self.next_check_ms = Some(now + healthcheck.interval_ms());
+        let running_ms = now - self.started_ms;
+        let passed = self
+            .simulation
+            .unhealthy_after_ms
+            .is_none_or(|after| running_ms < after);
+
+        let health = if passed {
+            self.failures = 0;
+            Health::Healthy
+        } else if running_ms < healthcheck.start_period_ms() {
+            // Failures while the container starts up do not count
+            return None;
+        } else {
+            self.failures += 1;
+            if self.failures < healthcheck.retries() {
+                return None;
+            }
+            Health::Unhealthy
+        };
+        (self.health != Some(health)).then(|| {
+            self.health = Some(health);
+            health
+        })
+    }
+}
+
+/// Whether a container that exited should be started again
+pub fn should_restart(policy: Option<&RestartPolicy>, exit_code: i32, restart_count: u32) -> bool {
+    let Some(policy) = policy else {
+        return false;
+    };
+    match policy.name.as_str() {
+        "always" | "unless-stopped" => true,
+        "on-failure" => {
+            exit_code != 0
+                && policy
+                    .maximum_retry_count
+                    .is_none_or(|max| max <= 0 || restart_count < max as u32)
+        }
+        _ => false,
+    }
+}
+
+/// Wait before a restart, doubling from 100ms like the daemon does
+pub fn restart_delay(restart_count: u32) -> u64 {
+    (100u64 << restart_count.min(20)).min(MAX_RESTART_DELAY_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logs_and_exit() {
+        let simulation = Simulation {
+            log_interval_ms: 100,
+            log_lines: vec!["a".to_string(), "b".to_string()],
+            exit_after_ms: Some(350),
+            exit_code: 1,
+            ..Default::default()
+        };
+        let mut lifecycle = Lifecycle::new(simulation, None, 1000);
+        assert_eq!(lifecycle.next_due(), Some(1100));
+        assert_eq!(lifecycle.advance(1050), vec![]);
+        assert_eq!(
+            lifecycle.advance(1200),
+            vec![
+                Transition::Log("a".to_string()),
+                Transition::Log("b".to_string())
+            ]
+        );
+        assert_eq!(
+            lifecycle.advance(2000),
+            vec![Transition::Log("a".to_string()), Transition::Exit(1)]
+        );
+        assert_eq!(lifecycle.next_due(), None);
+    }
+
+    #[test]
+    fn test_healthchecks() {
+        let simulation = Simulation {
+            unhealthy_after_ms: Some(250),
+            ..Default::default()
+        };
+        let healthcheck = Healthcheck {
+            interval: 100_000_000,
+            retries: 2,
+            ..Default::default()
+        };
+        let mut lifecycle = Lifecycle::new(simulation, Some(healthcheck), 0);
+        assert_eq!(lifecycle.health(), Some(Health::Starting));
+        assert_eq!(
+            lifecycle.advance(100),
+            vec![Transition::Health(Health::Healthy)]
+        );
+        // Passing again changes nothing, and one failure is not enough
+        assert_eq!(lifecycle.advance(300), vec![]);
+        assert_eq!(
+            lifecycle.advance(400),
+            vec![Transition::Health(Health::Unhealthy)]
+        );
+        assert_eq!(lifecycle.next_due(), Some(500));
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let policy = |name: &str, max: Option<i32>| RestartPolicy {
+            name: name.to_string(),
+            maximum_retry_count: max,
+        };
+        assert!(!should_restart(None, 1, 0));
+        assert!(!should_restart(Some(&policy("no", None)), 1, 0));
+        assert!(should_restart(Some(&policy("always", None)), 0, 5));
+        assert!(should_restart(Some(&policy("unless-stopped", None)), 0, 0));
+        assert!(!should_restart(Some(&policy("on-failure", None)), 0, 0));
+        assert!(should_restart(Some(&policy("on-failure", Some(2))), 1, 1));
+        assert!(!should_restart(Some(&policy("on-failure", Some(2))), 1, 2));
+
+        assert_eq!(restart_delay(0), 100);
+        assert_eq!(restart_delay(3), 800);
+        assert_eq!(restart_delay(30), 60_000);
+    }
+}
@@ -1,14 +1,46 @@
 //! Local/Offline container management
 //!
 //! This module provides container management that works without a server connection.
-//! It stores container state in memory and can optionally persist to localStorage.
+//! It stores container state in memory and can optionally persist to localStorage,
+//! or to IndexedDB when the state outgrows the localStorage quota.
+//!
+//! Started containers follow a simulated lifecycle driven by timers; see
+//! `lifecycle`. Its transitions change the state the manager shares with the
+//! timers, and are saved by auto-persist with the next change made through
+//! the manager.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+use super::lifecycle::{
+    restart_delay, should_restart, Health, Healthcheck, Lifecycle, Simulation, Transition,
+};
+use super::to_js;
+use crate::types::{HostConfig, RestartPolicy};
+use crate::utils::{gloo_timers_sleep, indexed_db};
+
+/// Log lines kept per container
+const MAX_LOG_LINES: usize = 1000;
+
+/// Version of the state snapshot format; snapshots without one are version 1
+const STATE_VERSION: u32 = 2;
+
+/// States a container can be in
+const CONTAINER_STATES: [&str; 7] = [
+    "created",
+    "running",
+    "paused",
+    "restarting",
+    "removing",
+    "exited",
+    "dead",
+];
+
 /// Container state for local storage
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LocalContainer {
     pub id: String,
@@ -22,6 +54,32 @@ pub struct LocalContainer {
     pub labels: HashMap<String, String>,
     pub ports: Vec<String>,
     pub volumes: Vec<String>,
+    #[serde(default)]
+    pub health: Option<Health>,
+    #[serde(default)]
+    pub restart_count: u32,
+    #[serde(default)]
+    pub simulation: Simulation,
+    #[serde(default)]
+    pub healthcheck: Option<Healthcheck>,
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
+    /// Bumped on every start and stop, ending the timers of the run before
+    #[serde(skip)]
+    pub run: u64,
+}
+
+/// Event of a simulated container, passed to the event callback
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalEvent {
+    pub id: String,
+    /// Docker-style action, such as `start`, `die` or
+    /// `health_status: healthy`, or `log` for a log line
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+    pub time: u64,
 }
 
 /// Image state for local storage
@@ -35,15 +93,119 @@ pub struct LocalImage {
     pub labels: HashMap<String, String>,
 }
 
+/// Volume state for local storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalVolume {
+    pub name: String,
+    pub driver: String,
+    pub mountpoint: String,
+    pub created: String,
+    pub labels: HashMap<String, String>,
+}
+
+/// Snapshot of a manager's state, sorted so equal states export equally
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalState {
+    #[serde(default = "legacy_state_version")]
+    pub version: u32,
+    pub containers: BTreeMap<String, LocalContainer>,
+    pub images: BTreeMap<String, LocalImage>,
+    // Absent from state saved before volumes and logs were kept
+    #[serde(default)]
+    pub volumes: BTreeMap<String, LocalVolume>,
+    #[serde(default)]
+    pub logs: BTreeMap<String, Vec<String>>,
+    pub id_counter: u64,
+}
+
+fn legacy_state_version() -> u32 {
+    1
+}
+
+impl LocalState {
+    /// Read and validate a snapshot
+    pub fn parse(json: &str) -> Result<Self, Vec<String>> {
+        let state: Self = serde_json::from_str(json).map_err(|e| vec![e.to_string()])?;
+        let errors = state.validate();
+        if errors.is_empty() {
+            Ok(state)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Problems that would leave a manager inconsistent
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.version > STATE_VERSION {
+            errors.push(format!(
+                "Unsupported state version {} (newest is {})",
+                self.version, STATE_VERSION
+            ));
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for (id, container) in &self.containers {
+            if container.id != *id {
+                errors.push(format!(
+                    "Container '{}' is stored under '{}'",
+                    container.id, id
+                ));
+            }
+            if !CONTAINER_STATES.contains(&container.state.as_str()) {
+                errors.push(format!(
+                    "Container '{}' has unknown state '{}'",
+                    id, container.state
+                ));
+            }
+            if !names.insert(container.name.as_str()) {
+                errors.push(format!("Container name '{}' is used twice", container.name));
+            }
+        }
+        for (id, image) in &self.images {
+            if image.id != *id {
+                errors.push(format!("Image '{}' is stored under '{}'", image.id, id));
+            }
+        }
+        for (name, volume) in &self.volumes {
+            if volume.name != *name {
+                errors.push(format!(
+                    "Volume '{}' is stored under '{}'",
+                    volume.name, name
+                ));
+            }
+        }
+        for id in self.logs.keys() {
+            if !self.containers.contains_key(id) {
+                errors.push(format!("Logs belong to unknown container '{}'", id));
+            }
+        }
+        errors
+    }
+}
+
 /// Local container manager - works entirely offline
 #[wasm_bindgen]
 pub struct LocalContainerManager {
+    /// Shared with the lifecycle timers
     #[wasm_bindgen(skip)]
-    pub containers: HashMap<String, LocalContainer>,
+    pub containers: Rc<RefCell<HashMap<String, LocalContainer>>>,
     #[wasm_bindgen(skip)]
     pub images: HashMap<String, LocalImage>,
     #[wasm_bindgen(skip)]
+    pub volumes: HashMap<String, LocalVolume>,
+    /// Log lines by container ID, shared with the lifecycle timers
+    #[wasm_bindgen(skip)]
+    pub logs: Rc<RefCell<HashMap<String, Vec<String>>>>,
+    #[wasm_bindgen(skip)]
+    pub on_event: Rc<RefCell<Option<js_sys::Function>>>,
+    #[wasm_bindgen(skip)]
     pub id_counter: u64,
+    /// IndexedDB key saved to after every change
+    #[wasm_bindgen(skip)]
+    pub auto_persist: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -52,9 +214,13 @@ impl LocalContainerManager {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            containers: HashMap::new(),
+            containers: Rc::new(RefCell::new(HashMap::new())),
             images: HashMap::new(),
+            volumes: HashMap::new(),
+            logs: Rc::new(RefCell::new(HashMap::new())),
+            on_event: Rc::new(RefCell::new(None)),
             id_counter: 0,
+            auto_persist: None,
         }
     }
 
@@ -77,6 +243,9 @@ impl LocalContainerManager {
             cmd: Option<Vec<String>>,
             env: Option<Vec<String>>,
             labels: Option<HashMap<String, String>>,
+            healthcheck: Option<Healthcheck>,
+            host_config: Option<HostConfig>,
+            simulate: Option<Simulation>,
         }
 
         let config: CreateConfig = match serde_json::from_str(config_json) {
@@ -101,9 +270,15 @@ impl LocalContainerManager {
             labels: config.labels.unwrap_or_default(),
             ports: Vec::new(),
             volumes: Vec::new(),
+            simulation: config.simulate.unwrap_or_default(),
+            healthcheck: config.healthcheck,
+            restart_policy: config.host_config.and_then(|h| h.restart_policy),
+            ..Default::default()
         };
 
-        self.containers.insert(id.clone(), container);
+        self.containers.borrow_mut().insert(id.clone(), container);
+        self.changed();
+        self.engine().emit(&id, "create", None);
 
         serde_json::json!({
             "Id": id,
@@ -113,33 +288,46 @@ impl LocalContainerManager {
     }
 
     /// Start a container (simulated)
+    ///
+    /// The container runs after its startup delay, then follows its
+    /// simulated lifecycle.
     #[wasm_bindgen(js_name = startContainer)]
     pub fn start_container(&mut self, id: &str) -> String {
-        if let Some(container) = self.containers.get_mut(id) {
-            container.state = "running".to_string();
-            container.status = "Up".to_string();
-            serde_json::json!({ "success": true }).to_string()
-        } else {
-            serde_json::json!({ "error": "Container not found" }).to_string()
-        }
+        let delay = match self.containers.borrow().get(id) {
+            Some(container) => container.simulation.startup_delay_ms,
+            None => return serde_json::json!({ "error": "Container not found" }).to_string(),
+        };
+        self.engine().start(id, delay);
+        self.changed();
+        serde_json::json!({ "success": true }).to_string()
     }
 
     /// Stop a container (simulated)
     #[wasm_bindgen(js_name = stopContainer)]
     pub fn stop_container(&mut self, id: &str) -> String {
-        if let Some(container) = self.containers.get_mut(id) {
+        if let Some(container) = self.containers.borrow_mut().get_mut(id) {
+            container.run += 1;
             container.state = "exited".to_string();
             container.status = "Exited (0)".to_string();
-            serde_json::json!({ "success": true }).to_string()
+            container.health = None;
         } else {
-            serde_json::json!({ "error": "Container not found" }).to_string()
+            return serde_json::json!({ "error": "Container not found" }).to_string();
         }
+        self.changed();
+        let engine = self.engine();
+        engine.emit(id, "die", None);
+        engine.emit(id, "stop", None);
+        serde_json::json!({ "success": true }).to_string()
     }
 
     /// Remove a container
     #[wasm_bindgen(js_name = removeContainer)]
     pub fn remove_container(&mut self, id: &str) -> String {
-        if self.containers.remove(id).is_some() {
+        let removed = self.containers.borrow_mut().remove(id);
+        if removed.is_some() {
+            self.logs.borrow_mut().remove(id);
+            self.changed();
+            self.engine().emit(id, "destroy", None);
             serde_json::json!({ "success": true }).to_string()
         } else {
             serde_json::json!({ "error": "Container not found" }).to_string()
@@ -149,8 +337,8 @@ impl LocalContainerManager {
     /// List all containers
     #[wasm_bindgen(js_name = listContainers)]
     pub fn list_containers(&self, all: bool) -> String {
-        let containers: Vec<&LocalContainer> = self
-            .containers
+        let containers = self.containers.borrow();
+        let containers: Vec<&LocalContainer> = containers
             .values()
             .filter(|c| all || c.state == "running")
             .collect();
@@ -160,7 +348,7 @@ impl LocalContainerManager {
     /// Get a container by ID
     #[wasm_bindgen(js_name = getContainer)]
     pub fn get_container(&self, id: &str) -> String {
-        match self.containers.get(id) {
+        match self.containers.borrow().get(id) {
             Some(c) => serde_json::to_string(c).unwrap_or_else(|_| "null".to_string()),
             None => "null".to_string(),
         }
@@ -177,6 +365,7 @@ impl LocalContainerManager {
             labels: HashMap::new(),
         };
         self.images.insert(id.to_string(), image);
+        self.changed();
     }
 
     /// List all images
@@ -190,38 +379,118 @@ impl LocalContainerManager {
     #[wasm_bindgen(js_name = removeImage)]
     pub fn remove_image(&mut self, id: &str) -> String {
         if self.images.remove(id).is_some() {
+            self.changed();
             serde_json::json!({ "success": true }).to_string()
         } else {
             serde_json::json!({ "error": "Image not found" }).to_string()
         }
     }
 
-    /// Export state as JSON (for persistence)
+    /// Create a volume (local only)
+    #[wasm_bindgen(js_name = createVolume)]
+    pub fn create_volume(&mut self, config_json: &str) -> String {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct VolumeConfig {
+            name: Option<String>,
+            driver: Option<String>,
+            labels: Option<HashMap<String, String>>,
+        }
+
+        let config: VolumeConfig = match serde_json::from_str(config_json) {
+            Ok(c) => c,
+            Err(e) => return serde_json::json!({ "error": e.to_string() }).to_string(),
+        };
+
+        let name = match config.name {
+            Some(name) => name,
+            None => self.generate_id(),
+        };
+        if self.volumes.contains_key(&name) {
+            return serde_json::json!({ "error": "Volume already exists" }).to_string();
+        }
+
+        let volume = LocalVolume {
+            name: name.clone(),
+            driver: config.driver.unwrap_or_else(|| "local".to_string()),
+            mountpoint: format!("/var/lib/rune/volumes/{}/_data", name),
+            created: js_sys::Date::new_0().to_iso_string().into(),
+            labels: config.labels.unwrap_or_default(),
+        };
+        self.volumes.insert(name.clone(), volume);
+        self.changed();
+
+        serde_json::json!({ "Name": name }).to_string()
+    }
+
+    /// List all volumes
+    #[wasm_bindgen(js_name = listVolumes)]
+    pub fn list_volumes(&self) -> String {
+        let volumes: Vec<&LocalVolume> = self.volumes.values().collect();
+        serde_json::to_string(&volumes).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Remove a volume
+    #[wasm_bindgen(js_name = removeVolume)]
+    pub fn remove_volume(&mut self, name: &str) -> String {
+        if self.volumes.remove(name).is_some() {
+            self.changed();
+            serde_json::json!({ "success": true }).to_string()
+        } else {
+            serde_json::json!({ "error": "Volume not found" }).to_string()
+        }
+    }
+
+    /// Append a line to a container's logs, keeping the last
+    /// `MAX_LOG_LINES`
+    #[wasm_bindgen(js_name = appendLog)]
+    pub fn append_log(&mut self, id: &str, line: &str) -> bool {
+        if !self.containers.borrow().contains_key(id) {
+            return false;
+        }
+        push_log(&mut self.logs.borrow_mut(), id, line);
+        self.changed();
+        true
+    }
+
+    /// Get a container's logs as a JSON array, the last `tail` lines when
+    /// given
+    #[wasm_bindgen(js_name = getLogs)]
+    pub fn get_logs(&self, id: &str, tail: Option<usize>) -> String {
+        let logs = self.logs.borrow();
+        let logs = logs.get(id).map(Vec::as_slice).unwrap_or_default();
+        let start = tail.map_or(0, |tail| logs.len().saturating_sub(tail));
+        serde_json::to_string(&logs[start..]).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Export state as a versioned JSON snapshot (for persistence and
+    /// sharing)
     #[wasm_bindgen(js_name = exportState)]
     pub fn export_state(&self) -> String {
-        serde_json::json!({
-            "containers": self.containers,
-            "images": self.images,
-            "idCounter": self.id_counter
-        })
-        .to_string()
+        let state = LocalState {
+            version: STATE_VERSION,
+            containers: self.containers.borrow().clone().into_iter().collect(),
+            images: self.images.clone().into_iter().collect(),
+            volumes: self.volumes.clone().into_iter().collect(),
+            logs: self.logs.borrow().clone().into_iter().collect(),
+            id_counter: self.id_counter,
+        };
+        serde_json::to_string(&state).unwrap_or_default()
     }
 
-    /// Import state from JSON (for restoration)
+    /// Import state from a JSON snapshot (for restoration)
+    ///
+    /// Nothing changes unless the snapshot is valid. Simulated timers do not
+    /// resume; running containers follow their lifecycle again once
+    /// restarted.
     #[wasm_bindgen(js_name = importState)]
     pub fn import_state(&mut self, json: &str) -> bool {
-        #[derive(Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct State {
-            containers: HashMap<String, LocalContainer>,
-            images: HashMap<String, LocalImage>,
-            id_counter: u64,
-        }
-
-        match serde_json::from_str::<State>(json) {
+        match LocalState::parse(json) {
             Ok(state) => {
-                self.containers = state.containers;
-                self.images = state.images;
+                *self.containers.borrow_mut() = state.containers.into_iter().collect();
+                self.images = state.images.into_iter().collect();
+                self.volumes = state.volumes.into_iter().collect();
+                *self.logs.borrow_mut() = state.logs.into_iter().collect();
                 self.id_counter = state.id_counter;
                 true
             }
@@ -229,6 +498,15 @@ impl LocalContainerManager {
         }
     }
 
+    /// Check a JSON snapshot without importing it
+    ///
+    /// Returns `{ valid, errors }`.
+    #[wasm_bindgen(js_name = validateState)]
+    pub fn validate_state(&self, json: &str) -> String {
+        let errors = LocalState::parse(json).err().unwrap_or_default();
+        serde_json::json!({ "valid": errors.is_empty(), "errors": errors }).to_string()
+    }
+
     /// Save to localStorage (browser only)
     #[wasm_bindgen(js_name = saveToLocalStorage)]
     pub fn save_to_local_storage(&self, key: &str) -> bool {
@@ -254,10 +532,64 @@ impl LocalContainerManager {
         false
     }
 
+    /// Save to IndexedDB (browser only)
+    ///
+    /// The state is taken when called, so the manager can be used while the
+    /// save is pending.
+    #[wasm_bindgen(js_name = saveToIndexedDB)]
+    pub fn save_to_indexed_db(&self, key: &str) -> js_sys::Promise {
+        let key = key.to_string();
+        let state = self.export_state();
+        wasm_bindgen_futures::future_to_promise(async move {
+            indexed_db::put(&key, &state).await?;
+            Ok(JsValue::TRUE)
+        })
+    }
+
+    /// Load from IndexedDB (browser only), resolving to whether state was
+    /// found and imported
+    #[wasm_bindgen(js_name = loadFromIndexedDB)]
+    pub async fn load_from_indexed_db(&mut self, key: &str) -> Result<bool, JsValue> {
+        match indexed_db::get(key).await? {
+            Some(state) => Ok(self.import_state(&state)),
+            None => Ok(false),
+        }
+    }
+
+    /// Save to IndexedDB under `key` after every change, or stop when
+    /// `key` is null
+    #[wasm_bindgen(js_name = setAutoPersist)]
+    pub fn set_auto_persist(&mut self, key: Option<String>) {
+        self.auto_persist = key;
+        self.changed();
+    }
+
+    /// Save the state when auto-persist is on
+    fn changed(&self) {
+        let Some(key) = self.auto_persist.clone() else {
+            return;
+        };
+        let state = self.export_state();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = indexed_db::put(&key, &state).await {
+                web_sys::console::error_1(&e);
+            }
+        });
+    }
+
+    /// Set the event callback: (event) => void
+    ///
+    /// Events carry the container `id`, the `action` and its `time`, plus
+    /// the `line` of `log` events.
+    #[wasm_bindgen(setter = onEvent)]
+    pub fn set_on_event(&self, callback: Option<js_sys::Function>) {
+        *self.on_event.borrow_mut() = callback;
+    }
+
     /// Get container count
     #[wasm_bindgen(js_name = containerCount)]
     pub fn container_count(&self) -> usize {
-        self.containers.len()
+        self.containers.borrow().len()
     }
 
     /// Get image count
@@ -269,9 +601,22 @@ impl LocalContainerManager {
     /// Clear all state
     #[wasm_bindgen]
     pub fn clear(&mut self) {
-        self.containers.clear();
+        self.containers.borrow_mut().clear();
         self.images.clear();
+        self.volumes.clear();
+        self.logs.borrow_mut().clear();
         self.id_counter = 0;
+        self.changed();
+    }
+}
+
+impl LocalContainerManager {
+    fn engine(&self) -> Engine {
+        Engine {
+            containers: self.containers.clone(),
+            logs: self.logs.clone(),
+            on_event: self.on_event.clone(),
+        }
     }
 }
 
@@ -281,6 +626,163 @@ impl Default for LocalContainerManager {
     }
 }
 
+/// Append a log line, keeping the last `MAX_LOG_LINES`
+fn push_log(logs: &mut HashMap<String, Vec<String>>, id: &str, line: &str) {
+    let logs = logs.entry(id.to_string()).or_default();
+    logs.push(line.to_string());
+    if logs.len() > MAX_LOG_LINES {
+        logs.drain(..logs.len() - MAX_LOG_LINES);
+    }
+}
+
+fn now() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+/// Runs simulated lifecycles on the state shared with the manager
+#[derive(Clone)]
+struct Engine {
+    containers: Rc<RefCell<HashMap<String, LocalContainer>>>,
+    logs: Rc<RefCell<HashMap<String, Vec<String>>>>,
+    on_event: Rc<RefCell<Option<js_sys::Function>>>,
+}
+
+impl Engine {
+    fn emit(&self, id: &str, action: &str, line: Option<&str>) {
+        let callback = self.on_event.borrow().clone();
+        if let Some(callback) = callback {
+            let event = LocalEvent {
+                id: id.to_string(),
+                action: action.to_string(),
+                line: line.map(str::to_string),
+                time: now(),
+            };
+            let _ = callback.call1(&JsValue::NULL, &to_js(&event));
+        }
+    }
+
+    /// Start a new run of a container, running it after `delay_ms`
+    fn start(&self, id: &str, delay_ms: u64) {
+        let Some(run) = self.containers.borrow_mut().get_mut(id).map(|c| {
+            c.run += 1;
+            c.run
+        }) else {
+            return;
+        };
+        if delay_ms == 0 {
+            if let Some(lifecycle) = self.set_running(id) {
+                wasm_bindgen_futures::spawn_local(self.clone().simulate(
+                    id.to_string(),
+                    run,
+                    lifecycle,
+                ));
+            }
+            return;
+        }
+
+        let engine = self.clone();
+        let id = id.to_string();
+        wasm_bindgen_futures::spawn_local(async move {
+            gloo_timers_sleep(delay_ms as u32).await;
+            if !engine.is_current(&id, run) {
+                return;
+            }
+            if let Some(lifecycle) = engine.set_running(&id) {
+                engine.simulate(id, run, lifecycle).await;
+            }
+        });
+    }
+
+    /// Mark a container running, returning its lifecycle when anything is
+    /// scheduled in it
+    fn set_running(&self, id: &str) -> Option<Lifecycle> {
+        let lifecycle = {
+            let mut containers = self.containers.borrow_mut();
+            let container = containers.get_mut(id)?;
+            let lifecycle = Lifecycle::new(
+                container.simulation.clone(),
+                container.healthcheck.clone(),
+                now(),
+            );
+            container.state = "running".to_string();
+            container.status = "Up".to_string();
+            container.health = lifecycle.health();
+            lifecycle
+        };
+        self.emit(id, "start", None);
+        lifecycle.next_due().is_some().then_some(lifecycle)
+    }
+
+    /// Apply a run's transitions as they fall due, until it exits or a
+    /// newer run replaces it
+    async fn simulate(self, id: String, run: u64, mut lifecycle: Lifecycle) {
+        while let Some(due) = lifecycle.next_due() {
+            gloo_timers_sleep(due.saturating_sub(now()) as u32).await;
+            if !self.is_current(&id, run) {
+                return;
+            }
+            for transition in lifecycle.advance(now()) {
+                match transition {
+                    Transition::Log(line) => {
+                        push_log(&mut self.logs.borrow_mut(), &id, &line);
+                        self.emit(&id, "log", Some(&line));
+                    }
+                    Transition::Health(health) => {
+                        if let Some(container) = self.containers.borrow_mut().get_mut(&id) {
+                            container.health = Some(health);
+                        }
+                        self.emit(&id, &format!("health_status: {}", health.as_str()), None);
+                    }
+                    Transition::Exit(code) => {
+                        self.exit(&id, code);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record an exit and restart the container if its policy says so
+    fn exit(&self, id: &str, code: i32) {
+        let restart = {
+            let mut containers = self.containers.borrow_mut();
+            let Some(container) = containers.get_mut(id) else {
+                return;
+            };
+            container.health = None;
+            if should_restart(
+                container.restart_policy.as_ref(),
+                code,
+                container.restart_count,
+            ) {
+                container.state = "restarting".to_string();
+                container.status = format!("Restarting ({})", code);
+                container.restart_count += 1;
+                Some(
+                    restart_delay(container.restart_count - 1)
+                        + container.simulation.startup_delay_ms,
+                )
+            } else {
+                container.state = "exited".to_string();
+                container.status = format!("Exited ({})", code);
+                None
+            }
+        };
+        self.emit(id, "die", None);
+        if let Some(delay) = restart {
+            self.emit(id, "restart", None);
+            self.start(id, delay);
+        }
+    }
+
+    fn is_current(&self, id: &str, run: u64) -> bool {
+        self.containers
+            .borrow()
+            .get(id)
+            .is_some_and(|container| container.run == run)
+    }
+}
+
 // Tests that use js-sys must run in wasm-bindgen-test
 // These tests only run in WASM environment
 #[cfg(all(test, target_arch = "wasm32"))]
@@ -312,6 +814,17 @@ mod wasm_tests {
         let container = manager.get_container(container_id);
         assert!(container.contains("exited"));
     }
+
+    #[wasm_bindgen_test]
+    fn test_volume_lifecycle() {
+        let mut manager = LocalContainerManager::new();
+        let result = manager.create_volume(r#"{"Name": "data"}"#);
+        assert!(result.contains("data"));
+        assert!(manager
+            .list_volumes()
+            .contains("/var/lib/rune/volumes/data/_data"));
+        assert!(manager.remove_volume("data").contains("success"));
+    }
 }
 
 // Native tests that don't use js-sys
@@ -341,4 +854,100 @@ mod tests {
         assert!(new_manager.import_state(&state));
         assert_eq!(new_manager.id_counter, 5);
     }
+
+    #[test]
+    fn test_state_validation() {
+        let manager = LocalContainerManager::new();
+        manager.containers.borrow_mut().insert(
+            "abc".to_string(),
+            LocalContainer {
+                id: "abc".to_string(),
+                name: "web".to_string(),
+                state: "running".to_string(),
+                ..Default::default()
+            },
+        );
+        let state = manager.export_state();
+        assert!(state.contains(r#""version":2"#));
+        assert_eq!(
+            manager.validate_state(&state),
+            r#"{"errors":[],"valid":true}"#
+        );
+
+        let mut snapshot: serde_json::Value = serde_json::from_str(&state).unwrap();
+        snapshot["version"] = 3.into();
+        snapshot["containers"]["abc"]["state"] = "sleeping".into();
+        snapshot["logs"]["gone"] = serde_json::json!(["hi"]);
+        let result: serde_json::Value =
+            serde_json::from_str(&manager.validate_state(&snapshot.to_string())).unwrap();
+        assert_eq!(result["valid"], false);
+        assert_eq!(
+            result["errors"],
+            serde_json::json!([
+                "Unsupported state version 3 (newest is 2)",
+                "Container 'abc' has unknown state 'sleeping'",
+                "Logs belong to unknown container 'gone'"
+            ])
+        );
+
+        // An invalid snapshot leaves the state as it was
+        let mut other = LocalContainerManager::new();
+        assert!(!other.import_state(&snapshot.to_string()));
+        assert_eq!(other.container_count(), 0);
+        assert!(other.import_state(&state));
+        assert_eq!(other.export_state(), state);
+    }
+
+    #[test]
+    fn test_volumes_and_logs_state() {
+        let mut manager = LocalContainerManager::new();
+        manager.volumes.insert(
+            "data".to_string(),
+            LocalVolume {
+                name: "data".to_string(),
+                driver: "local".to_string(),
+                mountpoint: "/var/lib/rune/volumes/data/_data".to_string(),
+                created: String::new(),
+                labels: HashMap::new(),
+            },
+        );
+        assert!(manager
+            .create_volume(r#"{"Name": "data"}"#)
+            .contains("already exists"));
+
+        // Logs are only kept for known containers
+        assert!(!manager.append_log("abc", "hello"));
+        manager.containers.borrow_mut().insert(
+            "abc".to_string(),
+            LocalContainer {
+                id: "abc".to_string(),
+                name: "web".to_string(),
+                image: "alpine".to_string(),
+                state: "running".to_string(),
+                status: "Up".to_string(),
+                ..Default::default()
+            },
+        );
+        for i in 0..MAX_LOG_LINES + 5 {
+            assert!(manager.append_log("abc", &format!("line {}", i)));
+        }
+        assert_eq!(manager.logs.borrow()["abc"].len(), MAX_LOG_LINES);
+        assert_eq!(
+            manager.get_logs("abc", Some(2)),
+            format!(
+                r#"["line {}","line {}"]"#,
+                MAX_LOG_LINES + 3,
+                MAX_LOG_LINES + 4
+            )
+        );
+
+        let mut restored = LocalContainerManager::new();
+        assert!(restored.import_state(&manager.export_state()));
+        assert!(restored.volumes.contains_key("data"));
+        assert_eq!(restored.logs.borrow()["abc"].len(), MAX_LOG_LINES);
+
+        // State saved before volumes and logs were kept still imports
+        assert!(restored.import_state(r#"{"containers": {}, "images": {}, "idCounter": 1}"#));
+        assert!(restored.volumes.is_empty());
+    }
 }
@@ -2,31 +2,118 @@
 //!
 //! Provides both remote (WebSocket) and local (offline) container management.
 
+mod auth;
+mod error;
+mod exec;
+mod lifecycle;
 mod local;
+mod protocol;
+mod reconnect;
 
+pub use auth::ClientAuth;
+pub use error::RuneClientError;
+pub use exec::ExecSession;
+pub use lifecycle::{Health, Healthcheck, Simulation};
 pub use local::LocalContainerManager;
+pub use protocol::{ClientMessage, ServerMessage, Topic};
+pub use reconnect::{ConnectionState, ReconnectOptions};
 
 use futures::channel::oneshot;
-use std::cell::RefCell;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{MessageEvent, WebSocket};
 
-use crate::utils::gloo_timers_sleep;
+use crate::types::{
+    Container, ContainerInspect, ExecOptions, Image, Network, SystemInfo, Version, VolumeList,
+};
+use crate::utils::{generate_id, gloo_timers_sleep};
+
+/// Send a protocol message as a text frame
+fn send_message(ws: &WebSocket, message: &ClientMessage) -> Result<(), JsValue> {
+    let text = serde_json::to_string(message).map_err(|e| client_error(&e.to_string()))?;
+    ws.send_with_str(&text)
+}
+
+/// Error of a call that never reached the daemon
+fn client_error(message: &str) -> JsValue {
+    RuneClientError::new(0, message).into()
+}
+
+/// Convert a result to a plain JavaScript object
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap_or(JsValue::NULL)
+}
+
+/// Read a response body as JSON, or as a string when it is not JSON
+fn parse_body(text: &str) -> serde_json::Value {
+    if text.trim().is_empty() {
+        return serde_json::Value::Null;
+    }
+    serde_json::from_str(text).unwrap_or_else(|_| serde_json::Value::String(text.to_string()))
+}
+
+/// Read a response as its type, with `null` members taking their defaults
+fn typed<T: DeserializeOwned>(mut value: serde_json::Value) -> Result<T, RuneClientError> {
+    fn drop_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(members) => {
+                members.retain(|_, member| !member.is_null());
+                members.values_mut().for_each(drop_nulls);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(drop_nulls),
+            _ => {}
+        }
+    }
+    drop_nulls(&mut value);
+    serde_json::from_value(value)
+        .map_err(|e| RuneClientError::new(0, format!("Unexpected response: {}", e)))
+}
+
+/// Open stream and the callback its messages go to
+#[derive(Clone)]
+pub struct Subscription {
+    pub topic: Topic,
+    pub callback: js_sys::Function,
+}
 
 /// WebSocket-based client for connecting to Rune/Docker daemon
+///
+/// Clones share the connection, so socket handlers can hold one.
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct RuneClient {
     #[wasm_bindgen(skip)]
     pub url: String,
     #[wasm_bindgen(skip)]
-    pub ws: Option<WebSocket>,
+    pub ws: Rc<RefCell<Option<WebSocket>>>,
     #[wasm_bindgen(skip)]
     pub connected: Rc<RefCell<bool>>,
     #[wasm_bindgen(skip)]
     pub pending_requests: Rc<RefCell<HashMap<String, oneshot::Sender<String>>>>,
+    /// Open streams, by subscription ID
+    #[wasm_bindgen(skip)]
+    pub subscriptions: Rc<RefCell<HashMap<String, Subscription>>>,
+    #[wasm_bindgen(skip)]
+    pub auth: Rc<RefCell<ClientAuth>>,
+    /// Callback asked for a new bearer token after a 401
+    #[wasm_bindgen(skip)]
+    pub token_refresh: Rc<RefCell<Option<js_sys::Function>>>,
+    #[wasm_bindgen(skip)]
+    pub reconnect: Rc<RefCell<ReconnectOptions>>,
+    #[wasm_bindgen(skip)]
+    pub state: Rc<Cell<ConnectionState>>,
+    #[wasm_bindgen(skip)]
+    pub on_state_change: Rc<RefCell<Option<js_sys::Function>>>,
+    /// Set by `disconnect` so the close is not treated as a drop
+    #[wasm_bindgen(skip)]
+    pub closing: Rc<Cell<bool>>,
 }
 
 #[wasm_bindgen]
@@ -36,75 +123,90 @@ impl RuneClient {
     pub fn new(url: &str) -> Self {
         Self {
             url: url.to_string(),
-            ws: None,
+            ws: Rc::new(RefCell::new(None)),
             connected: Rc::new(RefCell::new(false)),
             pending_requests: Rc::new(RefCell::new(HashMap::new())),
+            subscriptions: Rc::new(RefCell::new(HashMap::new())),
+            auth: Rc::new(RefCell::new(ClientAuth::default())),
+            token_refresh: Rc::new(RefCell::new(None)),
+            reconnect: Rc::new(RefCell::new(ReconnectOptions::default())),
+            state: Rc::new(Cell::new(ConnectionState::Disconnected)),
+            on_state_change: Rc::new(RefCell::new(None)),
+            closing: Rc::new(Cell::new(false)),
         }
     }
 
-    /// Connect to the daemon
-    #[wasm_bindgen]
-    pub async fn connect(&mut self) -> Result<(), JsValue> {
-        let ws = WebSocket::new(&self.url)?;
+    /// Authorize requests with a bearer token
+    #[wasm_bindgen(js_name = setBearerToken)]
+    pub fn set_bearer_token(&self, token: &str) -> Result<(), JsValue> {
+        self.auth.borrow_mut().set_bearer(token);
+        self.authenticate()
+    }
 
-        let connected = self.connected.clone();
-        let pending = self.pending_requests.clone();
+    /// Authorize requests with a username and password
+    #[wasm_bindgen(js_name = setBasicAuth)]
+    pub fn set_basic_auth(&self, username: &str, password: &str) -> Result<(), JsValue> {
+        self.auth.borrow_mut().set_basic(username, password);
+        self.authenticate()
+    }
 
-        // Set up onopen handler
-        let onopen = Closure::wrap(Box::new(move || {
-            *connected.borrow_mut() = true;
-            web_sys::console::log_1(&"Connected to Rune daemon".into());
-        }) as Box<dyn FnMut()>);
-        ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
-        onopen.forget();
+    /// Send a header with every request
+    #[wasm_bindgen(js_name = setHeader)]
+    pub fn set_header(&self, name: &str, value: &str) -> Result<(), JsValue> {
+        self.auth.borrow_mut().set_header(name, value);
+        self.authenticate()
+    }
 
-        // Set up onmessage handler
-        let pending_clone = pending.clone();
-        let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
-            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                let msg: String = txt.into();
-                if let Ok(response) = serde_json::from_str::<serde_json::Value>(&msg) {
-                    if let Some(id) = response.get("requestId").and_then(|v| v.as_str()) {
-                        if let Some(sender) = pending_clone.borrow_mut().remove(id) {
-                            let _ = sender.send(msg);
-                        }
-                    }
-                }
-            }
-        }) as Box<dyn FnMut(MessageEvent)>);
-        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-        onmessage.forget();
+    /// Forget the credentials and headers
+    #[wasm_bindgen(js_name = clearAuth)]
+    pub fn clear_auth(&self) {
+        self.auth.borrow_mut().clear();
+    }
 
-        // Set up onerror handler
-        let onerror = Closure::wrap(Box::new(move |_e: web_sys::ErrorEvent| {
-            web_sys::console::error_1(&"WebSocket error".into());
-        }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
-        ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-        onerror.forget();
+    /// Set the token refresh callback: () => Promise<string> | string
+    ///
+    /// Called when the daemon answers 401; the request is retried once with
+    /// the returned token as bearer token.
+    #[wasm_bindgen(js_name = setTokenRefresh)]
+    pub fn set_token_refresh(&self, callback: Option<js_sys::Function>) {
+        *self.token_refresh.borrow_mut() = callback;
+    }
 
-        // Set up onclose handler
-        let connected_close = self.connected.clone();
-        let onclose = Closure::wrap(Box::new(move |_e: web_sys::CloseEvent| {
-            *connected_close.borrow_mut() = false;
-            web_sys::console::log_1(&"Disconnected from Rune daemon".into());
-        }) as Box<dyn FnMut(web_sys::CloseEvent)>);
-        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
-        onclose.forget();
+    /// Configure reconnection after the socket drops
+    ///
+    /// `options_json` takes `Enabled` (on by default), `InitialDelayMs`,
+    /// `MaxDelayMs` and `MaxAttempts`.
+    #[wasm_bindgen(js_name = setReconnect)]
+    pub fn set_reconnect(&self, options_json: &str) -> Result<(), JsValue> {
+        let options = serde_json::from_str(options_json)
+            .map_err(|e| client_error(&format!("Invalid options: {}", e)))?;
+        *self.reconnect.borrow_mut() = options;
+        Ok(())
+    }
 
-        self.ws = Some(ws);
+    /// Connection state: connecting, connected, reconnecting or
+    /// disconnected
+    #[wasm_bindgen(getter = connectionState)]
+    pub fn connection_state(&self) -> String {
+        self.state.get().as_str().to_string()
+    }
 
-        // Wait for connection
-        let connected_wait = self.connected.clone();
-        let mut attempts = 0;
-        while !*connected_wait.borrow() && attempts < 50 {
-            gloo_timers_sleep(100).await;
-            attempts += 1;
-        }
+    /// Set the connection state callback: (state: string) => void
+    #[wasm_bindgen(setter = onConnectionStateChange)]
+    pub fn set_on_connection_state_change(&self, callback: Option<js_sys::Function>) {
+        *self.on_state_change.borrow_mut() = callback;
+    }
 
-        if !*connected_wait.borrow() {
-            return Err(JsValue::from_str("Connection timeout"));
+    /// Connect to the daemon
+    #[wasm_bindgen]
+    pub async fn connect(&self) -> Result<(), JsValue> {
+        self.closing.set(false);
+        self.set_state(ConnectionState::Connecting);
+        if let Err(e) = self.open().await {
+            self.set_state(ConnectionState::Disconnected);
+            return Err(e);
         }
-
+        self.set_state(ConnectionState::Connected);
         Ok(())
     }
 
@@ -114,32 +216,150 @@ impl RuneClient {
         *self.connected.borrow()
     }
 
-    /// Disconnect from the daemon
+    /// Disconnect from the daemon, ending every stream
     #[wasm_bindgen]
-    pub fn disconnect(&mut self) {
-        if let Some(ws) = &self.ws {
+    pub fn disconnect(&self) {
+        self.closing.set(true);
+        if let Some(ws) = self.ws.borrow_mut().take() {
             let _ = ws.close();
         }
-        self.ws = None;
         *self.connected.borrow_mut() = false;
+        self.pending_requests.borrow_mut().clear();
+        self.end_subscriptions(false);
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+    /// Send an API request over the WebSocket and wait for its response
+    ///
+    /// Resolves to the response body, or rejects with it when the status
+    /// is an error.
+    #[wasm_bindgen]
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body_json: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let payload = match body_json {
+            Some(body) => Some(
+                serde_json::from_str(&body)
+                    .map_err(|e| client_error(&format!("Invalid body: {}", e)))?,
+            ),
+            None => None,
+        };
+        let method = method.to_uppercase();
+        let (mut status, mut body) = self.send_request(&method, path, payload.clone()).await?;
+        if status == 401 && self.refresh_token().await? {
+            (status, body) = self.send_request(&method, path, payload).await?;
+        }
+        if status >= 400 {
+            Err(RuneClientError::from_response(status, &body).into())
+        } else {
+            Ok(to_js(&body))
+        }
+    }
+    /// Stream a container's logs to a callback: (data) => void
+    ///
+    /// The callback receives `null` when the stream ends. Returns the
+    /// subscription ID to pass to `unsubscribe`.
+    #[wasm_bindgen(js_name = streamLogs)]
+    pub fn stream_logs(
+        &self,
+        id: &str,
+        callback: js_sys::Function,
+        tail: Option<i32>,
+    ) -> Result<String, JsValue> {
+        self.subscribe(
+            Topic::Logs {
+                container_id: id.to_string(),
+                tail,
+            },
+            callback,
+        )
+    }
+
+    /// Stream daemon events to a callback: (event) => void
+    ///
+    /// The callback receives `null` when the stream ends. Returns the
+    /// subscription ID to pass to `unsubscribe`.
+    #[wasm_bindgen(js_name = streamEvents)]
+    pub fn stream_events(&self, callback: js_sys::Function) -> Result<String, JsValue> {
+        self.subscribe(Topic::Events, callback)
+    }
+
+    /// Run a command in a container with its terminal carried over the
+    /// WebSocket
+    ///
+    /// `options_json` takes `Tty` (on by default), `Env`, `WorkingDir` and
+    /// `User`.
+    #[wasm_bindgen]
+    pub fn exec(
+        &self,
+        container_id: &str,
+        cmd: Vec<String>,
+        options_json: Option<String>,
+    ) -> Result<ExecSession, JsValue> {
+        let options: ExecOptions = match options_json {
+            Some(options) => serde_json::from_str(&options)
+                .map_err(|e| client_error(&format!("Invalid options: {}", e)))?,
+            None => ExecOptions::default(),
+        };
+        let ws = self
+            .ws
+            .borrow()
+            .clone()
+            .ok_or_else(|| client_error("Not connected"))?;
+        let id = self.subscribe(
+            Topic::Exec {
+                container_id: container_id.to_string(),
+                cmd,
+                tty: options.tty.unwrap_or(true),
+                env: options.env.unwrap_or_default(),
+                working_dir: options.working_dir,
+                user: options.user,
+            },
+            // Output before onData is set is dropped
+            js_sys::Function::new_no_args(""),
+        )?;
+        Ok(ExecSession {
+            id,
+            ws,
+            subscriptions: self.subscriptions.clone(),
+        })
+    }
+
+    /// Stop a stream
+    #[wasm_bindgen]
+    pub fn unsubscribe(&self, subscription_id: &str) -> Result<(), JsValue> {
+        if self
+            .subscriptions
+            .borrow_mut()
+            .remove(subscription_id)
+            .is_none()
+        {
+            return Ok(());
+        }
+        self.send(&ClientMessage::Unsubscribe {
+            subscription_id: subscription_id.to_string(),
+        })
     }
 
     /// List containers
-    #[wasm_bindgen(js_name = listContainers)]
+    #[wasm_bindgen(js_name = listContainers, unchecked_return_type = "Container[]")]
     pub async fn list_containers(&self, all: bool) -> Result<JsValue, JsValue> {
         let endpoint = if all {
             "/containers/json?all=true"
         } else {
             "/containers/json"
         };
-        self.http_get(endpoint).await
+        self.http_get_as::<Vec<Container>>(endpoint).await
     }
 
     /// Get container details
-    #[wasm_bindgen(js_name = getContainer)]
+    #[wasm_bindgen(js_name = getContainer, unchecked_return_type = "ContainerInspect")]
     pub async fn get_container(&self, id: &str) -> Result<JsValue, JsValue> {
         let endpoint = format!("/containers/{}/json", id);
-        self.http_get(&endpoint).await
+        self.http_get_as::<ContainerInspect>(&endpoint).await
     }
 
     /// Create a container
@@ -212,9 +432,9 @@ impl RuneClient {
     }
 
     /// List images
-    #[wasm_bindgen(js_name = listImages)]
+    #[wasm_bindgen(js_name = listImages, unchecked_return_type = "Image[]")]
     pub async fn list_images(&self) -> Result<JsValue, JsValue> {
-        self.http_get("/images/json").await
+        self.http_get_as::<Vec<Image>>("/images/json").await
     }
 
     /// Get image details
@@ -236,9 +456,9 @@ impl RuneClient {
     }
 
     /// List networks
-    #[wasm_bindgen(js_name = listNetworks)]
+    #[wasm_bindgen(js_name = listNetworks, unchecked_return_type = "Network[]")]
     pub async fn list_networks(&self) -> Result<JsValue, JsValue> {
-        self.http_get("/networks").await
+        self.http_get_as::<Vec<Network>>("/networks").await
     }
 
     /// Create a network
@@ -255,9 +475,9 @@ impl RuneClient {
     }
 
     /// List volumes
-    #[wasm_bindgen(js_name = listVolumes)]
+    #[wasm_bindgen(js_name = listVolumes, unchecked_return_type = "VolumeList")]
     pub async fn list_volumes(&self) -> Result<JsValue, JsValue> {
-        self.http_get("/volumes").await
+        self.http_get_as::<VolumeList>("/volumes").await
     }
 
     /// Create a volume
@@ -274,15 +494,15 @@ impl RuneClient {
     }
 
     /// Get system info
-    #[wasm_bindgen(js_name = getInfo)]
+    #[wasm_bindgen(js_name = getInfo, unchecked_return_type = "SystemInfo")]
     pub async fn get_info(&self) -> Result<JsValue, JsValue> {
-        self.http_get("/info").await
+        self.http_get_as::<SystemInfo>("/info").await
     }
 
     /// Get version
-    #[wasm_bindgen(js_name = getVersion)]
+    #[wasm_bindgen(js_name = getVersion, unchecked_return_type = "Version")]
     pub async fn get_version(&self) -> Result<JsValue, JsValue> {
-        self.http_get("/version").await
+        self.http_get_as::<Version>("/version").await
     }
 
     /// Ping the daemon
@@ -291,47 +511,297 @@ impl RuneClient {
         self.http_get("/_ping").await
     }
 
-    // Internal HTTP methods
-    async fn http_get(&self, endpoint: &str) -> Result<JsValue, JsValue> {
-        let url = format!(
-            "{}{}",
-            self.url
-                .replace("ws://", "http://")
-                .replace("wss://", "https://"),
-            endpoint
-        );
+    /// Open a socket and wait for it, then send the credentials
+    async fn open(&self) -> Result<(), JsValue> {
+        let ws = WebSocket::new(&self.url)?;
+
+        let connected = self.connected.clone();
 
-        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
-        let resp_value = JsFuture::from(window.fetch_with_str(&url)).await?;
-        let resp: web_sys::Response = resp_value.dyn_into()?;
-        let json = JsFuture::from(resp.json()?).await?;
-        Ok(json)
+        // Set up onopen handler
+        let onopen = Closure::wrap(Box::new(move || {
+            *connected.borrow_mut() = true;
+            web_sys::console::log_1(&"Connected to Rune daemon".into());
+        }) as Box<dyn FnMut()>);
+        ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        onopen.forget();
+
+        // Set up onmessage handler, routing responses to their requests and
+        // stream messages to their callbacks
+        let pending = self.pending_requests.clone();
+        let subscriptions = self.subscriptions.clone();
+        let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
+            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
+                let msg: String = txt.into();
+                match ServerMessage::parse(&msg) {
+                    Some(ServerMessage::Response { request_id, .. }) => {
+                        if let Some(sender) = pending.borrow_mut().remove(&request_id) {
+                            let _ = sender.send(msg);
+                        }
+                    }
+                    Some(ServerMessage::Stream {
+                        subscription_id,
+                        data,
+                    }) => {
+                        let callback = subscriptions
+                            .borrow()
+                            .get(&subscription_id)
+                            .map(|s| s.callback.clone());
+                        if let Some(callback) = callback {
+                            let _ = callback.call1(&JsValue::NULL, &to_js(&data));
+                        }
+                    }
+                    Some(ServerMessage::End {
+                        subscription_id,
+                        error,
+                    }) => {
+                        let subscription = subscriptions.borrow_mut().remove(&subscription_id);
+                        if let Some(error) = error {
+                            web_sys::console::error_1(&error.into());
+                        }
+                        if let Some(subscription) = subscription {
+                            let _ = subscription.callback.call1(&JsValue::NULL, &JsValue::NULL);
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+
+        // Set up onerror handler
+        let onerror = Closure::wrap(Box::new(move |_e: web_sys::ErrorEvent| {
+            web_sys::console::error_1(&"WebSocket error".into());
+        }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
+        ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+        onerror.forget();
+
+        // Set up onclose handler; dropping the pending senders fails the
+        // requests waiting on them. A socket that never opened is left to
+        // whoever opened it.
+        let client = self.clone();
+        let onclose = Closure::wrap(Box::new(move |_e: web_sys::CloseEvent| {
+            client.pending_requests.borrow_mut().clear();
+            if !client.connected.replace(false) {
+                return;
+            }
+            web_sys::console::log_1(&"Disconnected from Rune daemon".into());
+            let resume = !client.closing.get() && client.reconnect.borrow().enabled;
+            client.end_subscriptions(resume);
+            if resume {
+                client.set_state(ConnectionState::Reconnecting);
+                wasm_bindgen_futures::spawn_local(client.clone().reconnect());
+            } else {
+                client.set_state(ConnectionState::Disconnected);
+            }
+        }) as Box<dyn FnMut(web_sys::CloseEvent)>);
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        onclose.forget();
+
+        *self.ws.borrow_mut() = Some(ws.clone());
+
+        // Wait for connection
+        let mut attempts = 0;
+        while !self.is_connected() && ws.ready_state() != WebSocket::CLOSED && attempts < 50 {
+            gloo_timers_sleep(100).await;
+            attempts += 1;
+        }
+
+        if !self.is_connected() {
+            let _ = ws.close();
+            return Err(client_error("Connection timeout"));
+        }
+
+        self.authenticate()
     }
 
-    async fn http_post(&self, endpoint: &str, body: &str) -> Result<JsValue, JsValue> {
-        let url = format!(
-            "{}{}",
-            self.url
-                .replace("ws://", "http://")
-                .replace("wss://", "https://"),
-            endpoint
+    /// Retry with backoff until a socket opens, then resume the streams
+    async fn reconnect(self) {
+        let mut attempt = 0;
+        loop {
+            let options = self.reconnect.borrow().clone();
+            if !options.should_retry(attempt) {
+                self.end_subscriptions(false);
+                self.set_state(ConnectionState::Disconnected);
+                return;
+            }
+            gloo_timers_sleep(options.delay(attempt, js_sys::Math::random())).await;
+            attempt += 1;
+            if self.closing.get() {
+                return;
+            }
+            if self.open().await.is_ok() {
+                self.resubscribe();
+                self.set_state(ConnectionState::Connected);
+                return;
+            }
+        }
+    }
+
+    /// Subscribe again to the streams that survive a reconnect
+    fn resubscribe(&self) {
+        let topics: Vec<(String, Topic)> = self
+            .subscriptions
+            .borrow()
+            .iter()
+            .filter_map(|(id, s)| Some((id.clone(), s.topic.resumed()?)))
+            .collect();
+        for (subscription_id, topic) in topics {
+            let _ = self.send(&ClientMessage::Subscribe {
+                subscription_id,
+                topic,
+            });
+        }
+    }
+
+    /// End the streams, or only those that cannot be resumed, calling their
+    /// callbacks with `null`
+    fn end_subscriptions(&self, keep_resumable: bool) {
+        let ended: Vec<Subscription> = {
+            let mut subscriptions = self.subscriptions.borrow_mut();
+            let ids: Vec<String> = subscriptions
+                .iter()
+                .filter(|(_, s)| !keep_resumable || s.topic.resumed().is_none())
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter()
+                .filter_map(|id| subscriptions.remove(id))
+                .collect()
+        };
+        for subscription in ended {
+            let _ = subscription.callback.call1(&JsValue::NULL, &JsValue::NULL);
+        }
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        if self.state.replace(state) == state {
+            return;
+        }
+        let callback = self.on_state_change.borrow().clone();
+        if let Some(callback) = callback {
+            let _ = callback.call1(&JsValue::NULL, &state.as_str().into());
+        }
+    }
+
+    fn subscribe(&self, topic: Topic, callback: js_sys::Function) -> Result<String, JsValue> {
+        let subscription_id = generate_id();
+        self.subscriptions.borrow_mut().insert(
+            subscription_id.clone(),
+            Subscription {
+                topic: topic.clone(),
+                callback,
+            },
         );
+        let sent = self.send(&ClientMessage::Subscribe {
+            subscription_id: subscription_id.clone(),
+            topic,
+        });
+        if let Err(e) = sent {
+            self.subscriptions.borrow_mut().remove(&subscription_id);
+            return Err(e);
+        }
+        Ok(subscription_id)
+    }
+
+    /// Send one request and wait for its status and body
+    async fn send_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<(u16, serde_json::Value), JsValue> {
+        let request_id = generate_id();
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests
+            .borrow_mut()
+            .insert(request_id.clone(), sender);
+        let sent = self.send(&ClientMessage::Request {
+            request_id: request_id.clone(),
+            method: method.to_string(),
+            path: path.to_string(),
+            body,
+        });
+        if let Err(e) = sent {
+            self.pending_requests.borrow_mut().remove(&request_id);
+            return Err(e);
+        }
+
+        let msg = receiver
+            .await
+            .map_err(|_| client_error("Connection closed"))?;
+        match ServerMessage::parse(&msg) {
+            Some(ServerMessage::Response { status, body, .. }) => Ok((status, body)),
+            _ => Err(client_error("Invalid response")),
+        }
+    }
 
-        let mut opts = web_sys::RequestInit::new();
-        opts.method("POST");
-        opts.body(Some(&JsValue::from_str(body)));
+    /// Send the credentials to the daemon, if connected
+    fn authenticate(&self) -> Result<(), JsValue> {
+        let headers = self.auth.borrow().headers();
+        if headers.is_empty() || !self.is_connected() {
+            return Ok(());
+        }
+        self.send(&ClientMessage::Authenticate {
+            headers: headers.into_iter().collect(),
+        })
+    }
 
-        let request = web_sys::Request::new_with_str_and_init(&url, &opts)?;
-        request.headers().set("Content-Type", "application/json")?;
+    /// Ask the refresh callback for a new token, returning whether it gave
+    /// one
+    async fn refresh_token(&self) -> Result<bool, JsValue> {
+        let Some(callback) = self.token_refresh.borrow().clone() else {
+            return Ok(false);
+        };
+        let token = callback.call0(&JsValue::NULL)?;
+        let token = JsFuture::from(js_sys::Promise::resolve(&token)).await?;
+        match token.as_string() {
+            Some(token) if !token.is_empty() => {
+                self.auth.borrow_mut().set_bearer(&token);
+                self.authenticate()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
 
-        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
-        let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-        let resp: web_sys::Response = resp_value.dyn_into()?;
-        let json = JsFuture::from(resp.json()?).await?;
-        Ok(json)
+    fn send(&self, message: &ClientMessage) -> Result<(), JsValue> {
+        match &*self.ws.borrow() {
+            Some(ws) if self.is_connected() => send_message(ws, message),
+            _ => Err(client_error("Not connected")),
+        }
+    }
+
+    // Internal HTTP methods
+    async fn http_get(&self, endpoint: &str) -> Result<JsValue, JsValue> {
+        Ok(to_js(&self.http_request("GET", endpoint, None).await?))
+    }
+
+    /// Get an endpoint as its response type
+    async fn http_get_as<T: DeserializeOwned + Serialize>(
+        &self,
+        endpoint: &str,
+    ) -> Result<JsValue, JsValue> {
+        let value = self.http_request("GET", endpoint, None).await?;
+        Ok(to_js(&typed::<T>(value)?))
+    }
+
+    async fn http_post(&self, endpoint: &str, body: &str) -> Result<JsValue, JsValue> {
+        Ok(to_js(
+            &self.http_request("POST", endpoint, Some(body)).await?,
+        ))
     }
 
     async fn http_delete(&self, endpoint: &str) -> Result<JsValue, JsValue> {
+        Ok(to_js(&self.http_request("DELETE", endpoint, None).await?))
+    }
+
+    /// Fetch an endpoint, retrying once with a refreshed token after a 401
+    async fn http_request(
+        &self,
+        method: &str,
+        endpoint: &str,
+        body: Option<&str>,
+    ) -> Result<serde_json::Value, JsValue> {
         let url = format!(
             "{}{}",
             self.url
@@ -340,15 +810,81 @@ impl RuneClient {
             endpoint
         );
 
-        let mut opts = web_sys::RequestInit::new();
-        opts.method("DELETE");
+        let mut resp = self.fetch(method, &url, body).await?;
+        if resp.status() == 401 && self.refresh_token().await? {
+            resp = self.fetch(method, &url, body).await?;
+        }
+        let text = JsFuture::from(resp.text()?)
+            .await
+            .map_err(|e| RuneClientError::from_js(&e))?;
+        let body = parse_body(&text.as_string().unwrap_or_default());
+        if !resp.ok() {
+            return Err(RuneClientError::from_response(resp.status(), &body).into());
+        }
+        Ok(body)
+    }
+
+    async fn fetch(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&str>,
+    ) -> Result<web_sys::Response, JsValue> {
+        let opts = web_sys::RequestInit::new();
+        opts.set_method(method);
+        if let Some(body) = body {
+            opts.set_body(&JsValue::from_str(body));
+        }
+
+        let request = web_sys::Request::new_with_str_and_init(url, &opts)?;
+        if body.is_some() {
+            request.headers().set("Content-Type", "application/json")?;
+        }
+        for (name, value) in self.auth.borrow().headers() {
+            request.headers().set(&name, &value)?;
+        }
 
-        let request = web_sys::Request::new_with_str_and_init(&url, &opts)?;
+        let window = web_sys::window().ok_or_else(|| client_error("No window"))?;
+        let resp_value = JsFuture::from(window.fetch_with_request(&request))
+            .await
+            .map_err(|e| RuneClientError::from_js(&e))?;
+        resp_value.dyn_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_body() {
+        assert_eq!(parse_body(r#"{"Id": "abc"}"#), json!({ "Id": "abc" }));
+        assert_eq!(parse_body("OK"), json!("OK"));
+        assert_eq!(parse_body(""), serde_json::Value::Null);
+    }
 
-        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
-        let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-        let resp: web_sys::Response = resp_value.dyn_into()?;
-        let json = JsFuture::from(resp.json()?).await?;
-        Ok(json)
+    #[test]
+    fn test_typed_response() {
+        let images: Vec<Image> = typed(json!([{
+            "Id": "sha256:abc",
+            "RepoTags": null,
+            "Labels": null,
+            "Size": 42
+        }]))
+        .unwrap();
+        assert_eq!(images[0].id, "sha256:abc");
+        assert!(images[0].repo_tags.is_empty());
+        assert_eq!(images[0].size, 42);
+
+        let containers: Vec<Container> =
+            typed(json!([{ "Id": "abc", "ImageID": "sha256:def", "Ports": [{ "IP": null, "PrivatePort": 80, "Type": "tcp" }] }]))
+                .unwrap();
+        assert_eq!(containers[0].image_id, "sha256:def");
+        assert_eq!(containers[0].ports[0].ip, None);
+
+        let error = typed::<Vec<Image>>(json!({ "message": "oops" })).unwrap_err();
+        assert_eq!(error.status(), 0);
+        assert!(error.message().starts_with("Unexpected response"));
     }
 }
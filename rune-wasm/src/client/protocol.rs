@@ -0,0 +1,290 @@
+//! WebSocket protocol
+//!
+//! Messages are JSON text frames. A request carries a `requestId` that its
+//! response echoes. A subscription starts a stream whose messages carry the
+//! `subscriptionId` the client picked, until one arrives with `end` set or
+//! the client unsubscribes. An exec session is a stream of the command's
+//! output that also takes input and terminal resizes.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Message sent to the daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ClientMessage {
+    /// Headers to authorize the connection with, in place of handshake
+    /// headers
+    Authenticate { headers: BTreeMap<String, String> },
+    /// API call answered by one response
+    #[serde(rename_all = "camelCase")]
+    Request {
+        request_id: String,
+        method: String,
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        body: Option<serde_json::Value>,
+    },
+    /// Start a stream
+    #[serde(rename_all = "camelCase")]
+    Subscribe {
+        subscription_id: String,
+        #[serde(flatten)]
+        topic: Topic,
+    },
+    /// Stop a stream
+    #[serde(rename_all = "camelCase")]
+    Unsubscribe { subscription_id: String },
+    /// Input for an exec session
+    #[serde(rename_all = "camelCase")]
+    Input {
+        subscription_id: String,
+        data: String,
+    },
+    /// New terminal size of an exec session
+    #[serde(rename_all = "camelCase")]
+    Resize {
+        subscription_id: String,
+        cols: u16,
+        rows: u16,
+    },
+}
+
+/// What a subscription streams
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "topic", rename_all = "camelCase")]
+pub enum Topic {
+    /// Output of a container, from the last `tail` lines when given
+    #[serde(rename_all = "camelCase")]
+    Logs {
+        container_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tail: Option<i32>,
+    },
+    /// Daemon events
+    Events,
+    /// Output of a command run in a container
+    #[serde(rename_all = "camelCase")]
+    Exec {
+        container_id: String,
+        cmd: Vec<String>,
+        tty: bool,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        env: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        working_dir: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+    },
+}
+
+impl Topic {
+    /// Topic to subscribe to again on a new connection, `None` for a stream
+    /// that cannot outlive its connection
+    ///
+    /// Logs pick up from new output rather than repeating the tail.
+    pub fn resumed(&self) -> Option<Topic> {
+        match self {
+            Self::Logs { container_id, .. } => Some(Self::Logs {
+                container_id: container_id.clone(),
+                tail: Some(0),
+            }),
+            Self::Events => Some(Self::Events),
+            Self::Exec { .. } => None,
+        }
+    }
+}
+
+/// Message received from the daemon
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    Response {
+        request_id: String,
+        status: u16,
+        body: serde_json::Value,
+    },
+    Stream {
+        subscription_id: String,
+        data: serde_json::Value,
+    },
+    End {
+        subscription_id: String,
+        error: Option<String>,
+    },
+}
+
+impl ServerMessage {
+    /// Read a text frame, `None` when it is not a protocol message
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut message: serde_json::Value = serde_json::from_str(text).ok()?;
+        let field = |message: &serde_json::Value, name: &str| {
+            message
+                .get(name)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+
+        if let Some(request_id) = field(&message, "requestId") {
+            let status = message
+                .get("status")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(200) as u16;
+            return Some(Self::Response {
+                request_id,
+                status,
+                body: message
+                    .get_mut("body")
+                    .map(serde_json::Value::take)
+                    .unwrap_or_default(),
+            });
+        }
+
+        let subscription_id = field(&message, "subscriptionId")?;
+        if message.get("end").and_then(|v| v.as_bool()) == Some(true) {
+            Some(Self::End {
+                subscription_id,
+                error: field(&message, "error"),
+            })
+        } else {
+            Some(Self::Stream {
+                subscription_id,
+                data: message.get_mut("data").map(serde_json::Value::take)?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_client_messages() {
+        let auth = ClientMessage::Authenticate {
+            headers: [("Authorization".to_string(), "Bearer abc".to_string())].into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&auth).unwrap(),
+            json!({ "type": "authenticate", "headers": { "Authorization": "Bearer abc" } })
+        );
+
+        let request = ClientMessage::Request {
+            request_id: "r1".to_string(),
+            method: "GET".to_string(),
+            path: "/containers/json".to_string(),
+            body: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({ "type": "request", "requestId": "r1", "method": "GET", "path": "/containers/json" })
+        );
+
+        let logs = ClientMessage::Subscribe {
+            subscription_id: "s1".to_string(),
+            topic: Topic::Logs {
+                container_id: "abc".to_string(),
+                tail: Some(10),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&logs).unwrap(),
+            json!({ "type": "subscribe", "subscriptionId": "s1", "topic": "logs", "containerId": "abc", "tail": 10 })
+        );
+
+        let events = ClientMessage::Subscribe {
+            subscription_id: "s2".to_string(),
+            topic: Topic::Events,
+        };
+        assert_eq!(
+            serde_json::to_value(&events).unwrap(),
+            json!({ "type": "subscribe", "subscriptionId": "s2", "topic": "events" })
+        );
+
+        let exec = ClientMessage::Subscribe {
+            subscription_id: "e1".to_string(),
+            topic: Topic::Exec {
+                container_id: "abc".to_string(),
+                cmd: vec!["sh".to_string()],
+                tty: true,
+                env: Vec::new(),
+                working_dir: Some("/app".to_string()),
+                user: None,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&exec).unwrap(),
+            json!({
+                "type": "subscribe",
+                "subscriptionId": "e1",
+                "topic": "exec",
+                "containerId": "abc",
+                "cmd": ["sh"],
+                "tty": true,
+                "workingDir": "/app"
+            })
+        );
+        let resize = ClientMessage::Resize {
+            subscription_id: "e1".to_string(),
+            cols: 120,
+            rows: 40,
+        };
+        assert_eq!(
+            serde_json::to_value(&resize).unwrap(),
+            json!({ "type": "resize", "subscriptionId": "e1", "cols": 120, "rows": 40 })
+        );
+    }
+
+    #[test]
+    fn test_resumed_topics() {
+        let logs = Topic::Logs {
+            container_id: "abc".to_string(),
+            tail: Some(10),
+        };
+        assert_eq!(
+            logs.resumed(),
+            Some(Topic::Logs {
+                container_id: "abc".to_string(),
+                tail: Some(0),
+            })
+        );
+        assert_eq!(Topic::Events.resumed(), Some(Topic::Events));
+        let exec = Topic::Exec {
+            container_id: "abc".to_string(),
+            cmd: vec!["sh".to_string()],
+            tty: true,
+            env: Vec::new(),
+            working_dir: None,
+            user: None,
+        };
+        assert_eq!(exec.resumed(), None);
+    }
+
+    #[test]
+    fn test_server_messages() {
+        assert_eq!(
+            ServerMessage::parse(r#"{"requestId": "r1", "body": [1]}"#),
+            Some(ServerMessage::Response {
+                request_id: "r1".to_string(),
+                status: 200,
+                body: json!([1]),
+            })
+        );
+        assert_eq!(
+            ServerMessage::parse(r#"{"subscriptionId": "s1", "data": {"line": "hi"}}"#),
+            Some(ServerMessage::Stream {
+                subscription_id: "s1".to_string(),
+                data: json!({ "line": "hi" }),
+            })
+        );
+        assert_eq!(
+            ServerMessage::parse(r#"{"subscriptionId": "s1", "end": true, "error": "gone"}"#),
+            Some(ServerMessage::End {
+                subscription_id: "s1".to_string(),
+                error: Some("gone".to_string()),
+            })
+        );
+        assert_eq!(ServerMessage::parse(r#"{"hello": "world"}"#), None);
+        assert_eq!(ServerMessage::parse("not json"), None);
+    }
+}
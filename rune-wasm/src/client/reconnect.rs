@@ -0,0 +1,94 @@
+//! Reconnection
+//!
+//! When the socket drops, the client retries with exponential backoff and
+//! jitter, then sends its credentials again and resumes its streams.
+
+use serde::Deserialize;
+
+/// State of the connection to the daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connecting => "connecting",
+            Self::Connected => "connected",
+            Self::Reconnecting => "reconnecting",
+            Self::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// How to reconnect after the socket drops
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ReconnectOptions {
+    pub enabled: bool,
+    pub initial_delay_ms: u32,
+    pub max_delay_ms: u32,
+    /// Attempts before giving up, unlimited when unset
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectOptions {
+    /// Whether to make another attempt after `attempt` failed ones
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        self.enabled && self.max_attempts.is_none_or(|max| attempt < max)
+    }
+
+    /// Delay before an attempt, doubling each time up to the maximum
+    ///
+    /// Half the delay is fixed and half scaled by `random` (0 to 1), so
+    /// clients dropped together do not all come back at once.
+    pub fn delay(&self, attempt: u32, random: f64) -> u32 {
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay_ms);
+        let half = delay / 2;
+        half + (half as f64 * random.clamp(0.0, 1.0)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        let options = ReconnectOptions::default();
+        assert_eq!(options.delay(0, 0.0), 250);
+        assert_eq!(options.delay(0, 1.0), 500);
+        assert_eq!(options.delay(3, 1.0), 4000);
+        assert_eq!(options.delay(20, 1.0), 30_000);
+        assert_eq!(options.delay(40, 0.5), 22_500);
+    }
+
+    #[test]
+    fn test_reconnect_options() {
+        let options: ReconnectOptions = serde_json::from_str(r#"{"MaxAttempts": 2}"#).unwrap();
+        assert!(options.enabled);
+        assert!(options.should_retry(1));
+        assert!(!options.should_retry(2));
+
+        let options: ReconnectOptions = serde_json::from_str(r#"{"Enabled": false}"#).unwrap();
+        assert!(!options.should_retry(0));
+    }
+}
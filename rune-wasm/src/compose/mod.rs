@@ -1,7 +1,12 @@
 //! Compose file parser for WASM
 
+mod normalize;
+mod orchestrator;
+
+pub use orchestrator::LocalComposeOrchestrator;
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use wasm_bindgen::prelude::*;
 
 /// Compose service
@@ -18,6 +23,25 @@ pub struct ComposeService {
     pub networks: Option<Vec<String>>,
     pub labels: Option<HashMap<String, String>>,
     pub restart: Option<String>,
+    pub deploy: Option<ComposeDeploy>,
+    pub scale: Option<u32>,
+}
+
+impl ComposeService {
+    /// Number of containers to run, from `deploy.replicas` or `scale`
+    pub fn replicas(&self) -> u32 {
+        self.deploy
+            .as_ref()
+            .and_then(|d| d.replicas)
+            .or(self.scale)
+            .unwrap_or(1)
+    }
+}
+
+/// Compose deploy configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComposeDeploy {
+    pub replicas: Option<u32>,
 }
 
 /// Compose build configuration
@@ -49,10 +73,10 @@ impl ComposeParser {
         Self
     }
 
-    /// Parse a compose file (YAML as JSON)
+    /// Parse a compose file (YAML or JSON)
     #[wasm_bindgen]
-    pub fn parse(&self, json_content: &str) -> String {
-        match serde_json::from_str::<ParsedCompose>(json_content) {
+    pub fn parse(&self, content: &str) -> String {
+        match parse_compose(content) {
             Ok(compose) => serde_json::to_string(&compose).unwrap_or_default(),
             Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
         }
@@ -60,50 +84,23 @@ impl ComposeParser {
 
     /// Get the start order for services based on depends_on
     #[wasm_bindgen(js_name = getStartOrder)]
-    pub fn get_start_order(&self, json_content: &str) -> String {
-        match serde_json::from_str::<ParsedCompose>(json_content) {
-            Ok(compose) => {
-                let mut order = Vec::new();
-                let mut visited = std::collections::HashSet::new();
-
-                fn visit(
-                    name: &str,
-                    services: &HashMap<String, ComposeService>,
-                    visited: &mut std::collections::HashSet<String>,
-                    order: &mut Vec<String>,
-                ) {
-                    if visited.contains(name) {
-                        return;
-                    }
-                    visited.insert(name.to_string());
-
-                    if let Some(service) = services.get(name) {
-                        if let Some(deps) = &service.depends_on {
-                            for dep in deps {
-                                visit(dep, services, visited, order);
-                            }
-                        }
-                    }
-                    order.push(name.to_string());
-                }
-
-                for name in compose.services.keys() {
-                    visit(name, &compose.services, &mut visited, &mut order);
-                }
-
-                serde_json::to_string(&order).unwrap_or_default()
-            }
+    pub fn get_start_order(&self, content: &str) -> String {
+        match parse_compose(content) {
+            Ok(compose) => match start_order(&compose.services) {
+                Ok(order) => serde_json::to_string(&order).unwrap_or_default(),
+                Err(e) => serde_json::json!({ "error": e }).to_string(),
+            },
             Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
         }
     }
 
     /// Validate a compose file
     #[wasm_bindgen]
-    pub fn validate(&self, json_content: &str) -> String {
+    pub fn validate(&self, content: &str) -> String {
         let mut errors = Vec::new();
         let warnings: Vec<String> = Vec::new();
 
-        match serde_json::from_str::<ParsedCompose>(json_content) {
+        match parse_compose(content) {
             Ok(compose) => {
                 for (name, service) in &compose.services {
                     if service.image.is_none() && service.build.is_none() {
@@ -134,6 +131,59 @@ impl ComposeParser {
     }
 }
 
+/// Parse a compose file, YAML with anchors, aliases and merge keys, or JSON
+///
+/// Short and long syntax variants are accepted; see `normalize`.
+pub fn parse_compose(content: &str) -> Result<ParsedCompose, String> {
+    let mut document: serde_json::Value = match serde_json::from_str(content) {
+        Ok(document) => document,
+        Err(_) => {
+            let mut yaml: serde_yaml::Value =
+                serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+            yaml.apply_merge().map_err(|e| e.to_string())?;
+            serde_json::to_value(yaml).map_err(|e| e.to_string())?
+        }
+    };
+    normalize::normalize(&mut document);
+    serde_json::from_value(document).map_err(|e| e.to_string())
+}
+
+/// Services ordered so each comes after the services it depends on, and
+/// otherwise by name
+pub fn start_order(services: &HashMap<String, ComposeService>) -> Result<Vec<String>, String> {
+    fn visit(
+        name: &str,
+        services: &HashMap<String, ComposeService>,
+        visiting: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_string()) {
+            return Err(format!("Dependency cycle at service '{}'", name));
+        }
+        if let Some(deps) = services.get(name).and_then(|s| s.depends_on.as_ref()) {
+            let mut deps: Vec<&String> = deps.iter().collect();
+            deps.sort();
+            for dep in deps {
+                visit(dep, services, visiting, order)?;
+            }
+        }
+        visiting.remove(name);
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    let mut order = Vec::new();
+    for name in names {
+        visit(name, services, &mut HashSet::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
 impl Default for ComposeParser {
     fn default() -> Self {
         Self::new()
@@ -162,4 +212,73 @@ mod tests {
         let result = parser.validate(json);
         assert!(result.contains("no image or build"));
     }
+
+    #[test]
+    fn test_parse_yaml() {
+        let yaml = r#"
+x-common: &common
+  restart: always
+  environment:
+    LOG_LEVEL: debug
+    WORKERS: 4
+
+services:
+  web:
+    <<: *common
+    image: nginx
+    ports:
+      - "8080:80"
+      - target: 443
+        published: 8443
+    depends_on:
+      api:
+        condition: service_healthy
+  api:
+    <<: *common
+    build: ./api
+    command: serve --port 3000
+    volumes:
+      - type: volume
+        source: data
+        target: /data
+volumes:
+  data:
+"#;
+        let compose = parse_compose(yaml).unwrap();
+        let web = &compose.services["web"];
+        assert_eq!(web.name, "web");
+        assert_eq!(web.restart.as_deref(), Some("always"));
+        assert_eq!(web.environment.as_ref().unwrap()["WORKERS"], "4");
+        assert_eq!(web.ports.as_ref().unwrap(), &["8080:80", "8443:443"]);
+        assert_eq!(web.depends_on.as_ref().unwrap(), &["api"]);
+
+        let api = &compose.services["api"];
+        assert_eq!(api.build.as_ref().unwrap().context, "./api");
+        assert_eq!(api.command.as_ref().unwrap(), &["serve", "--port", "3000"]);
+        assert_eq!(api.volumes.as_ref().unwrap(), &["data:/data"]);
+        assert!(compose.volumes.unwrap().contains_key("data"));
+
+        assert!(parse_compose("services: [").is_err());
+    }
+
+    #[test]
+    fn test_start_order() {
+        let parser = ComposeParser::new();
+        let json = r#"{"services":{
+            "web":{"name":"web","image":"nginx","depends_on":["api"]},
+            "api":{"name":"api","image":"api","depends_on":["db","cache"]},
+            "db":{"name":"db","image":"postgres"},
+            "cache":{"name":"cache","image":"redis"}
+        }}"#;
+        assert_eq!(
+            parser.get_start_order(json),
+            r#"["cache","db","api","web"]"#
+        );
+
+        let cycle = r#"{"services":{
+            "a":{"name":"a","image":"x","depends_on":["b"]},
+            "b":{"name":"b","image":"x","depends_on":["a"]}
+        }}"#;
+        assert!(parser.get_start_order(cycle).contains("Dependency cycle"));
+    }
 }
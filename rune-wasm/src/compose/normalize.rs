@@ -0,0 +1,167 @@
+//! Compose syntax variants
+//!
+//! Compose allows several spellings of the same setting. These rewrite the
+//! short and long forms of a parsed document to the one `ParsedCompose`
+//! reads: environment and labels as maps, ports and volumes as short
+//! strings, and `depends_on` and `networks` as lists of names.
+
+use serde_json::{json, Map, Value};
+
+/// Rewrite the services of a compose document in place
+pub fn normalize(compose: &mut Value) {
+    let Some(services) = compose.get_mut("services").and_then(Value::as_object_mut) else {
+        return;
+    };
+    for (name, service) in services.iter_mut() {
+        let Some(service) = service.as_object_mut() else {
+            continue;
+        };
+        service
+            .entry("name")
+            .or_insert_with(|| Value::String(name.clone()));
+        if let Some(build) = service.get_mut("build") {
+            if let Some(context) = build.as_str() {
+                *build = json!({ "context": context });
+            }
+        }
+        if let Some(command) = service.get_mut("command") {
+            if let Some(line) = command.as_str() {
+                *command = json!(line.split_whitespace().collect::<Vec<_>>());
+            }
+        }
+        for key in ["environment", "labels"] {
+            if let Some(value) = service.get_mut(key) {
+                *value = Value::Object(key_values(value));
+            }
+        }
+        for (key, to_short) in [("ports", port as fn(&Value) -> String), ("volumes", volume)] {
+            if let Some(items) = service.get_mut(key).and_then(Value::as_array_mut) {
+                for item in items {
+                    *item = Value::String(to_short(item));
+                }
+            }
+        }
+        for key in ["depends_on", "networks"] {
+            if let Some(value) = service.get_mut(key) {
+                if let Some(names) = value.as_object() {
+                    *value = json!(names.keys().collect::<Vec<_>>());
+                }
+            }
+        }
+    }
+}
+
+/// `KEY=value` list or map as a map of strings
+fn key_values(value: &Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(scalar(value))))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                let item = scalar(item);
+                let (key, value) = item.split_once('=').unwrap_or((&item, ""));
+                (key.to_string(), Value::String(value.to_string()))
+            })
+            .collect(),
+        _ => Map::new(),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Port as `[host_ip:][published:]target[/protocol]`
+fn port(value: &Value) -> String {
+    let Some(long) = value.as_object() else {
+        return scalar(value);
+    };
+    let field = |name: &str| long.get(name).map(scalar).filter(|s| !s.is_empty());
+    let mut port = String::new();
+    if let Some(host_ip) = field("host_ip") {
+        port.push_str(&host_ip);
+        port.push(':');
+    }
+    if let Some(published) = field("published") {
+        port.push_str(&published);
+        port.push(':');
+    }
+    port.push_str(&field("target").unwrap_or_default());
+    if let Some(protocol) = field("protocol").filter(|p| p != "tcp") {
+        port.push('/');
+        port.push_str(&protocol);
+    }
+    port
+}
+
+/// Volume as `[source:]target[:ro]`
+fn volume(value: &Value) -> String {
+    let Some(long) = value.as_object() else {
+        return scalar(value);
+    };
+    let field = |name: &str| long.get(name).map(scalar).filter(|s| !s.is_empty());
+    let mut volume = field("target").unwrap_or_default();
+    if let Some(source) = field("source") {
+        volume = format!("{}:{}", source, volume);
+    }
+    if long.get("read_only").and_then(Value::as_bool) == Some(true) {
+        volume.push_str(":ro");
+    }
+    volume
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut compose = json!({
+            "services": {
+                "web": {
+                    "image": "nginx",
+                    "build": "./web",
+                    "command": "nginx -g 'daemon off;'",
+                    "environment": ["A=1", "B"],
+                    "labels": { "tier": "front", "replicas": 2 },
+                    "ports": [
+                        80,
+                        "8443:443",
+                        { "target": 53, "published": 5353, "protocol": "udp", "host_ip": "127.0.0.1" }
+                    ],
+                    "volumes": [
+                        "./conf:/etc/nginx:ro",
+                        { "type": "volume", "source": "data", "target": "/data", "read_only": true },
+                        { "type": "tmpfs", "target": "/tmp" }
+                    ],
+                    "depends_on": { "db": { "condition": "service_healthy" } },
+                    "networks": ["front"]
+                }
+            }
+        });
+        normalize(&mut compose);
+        let web = &compose["services"]["web"];
+        assert_eq!(web["name"], "web");
+        assert_eq!(web["build"], json!({ "context": "./web" }));
+        assert_eq!(web["command"][0], "nginx");
+        assert_eq!(web["environment"], json!({ "A": "1", "B": "" }));
+        assert_eq!(web["labels"], json!({ "tier": "front", "replicas": "2" }));
+        assert_eq!(
+            web["ports"],
+            json!(["80", "8443:443", "127.0.0.1:5353:53/udp"])
+        );
+        assert_eq!(
+            web["volumes"],
+            json!(["./conf:/etc/nginx:ro", "data:/data:ro", "/tmp"])
+        );
+        assert_eq!(web["depends_on"], json!(["db"]));
+        assert_eq!(web["networks"], json!(["front"]));
+    }
+}
@@ -0,0 +1,415 @@
+//! Offline compose orchestration
+//!
+//! Runs a compose project on a `LocalContainerManager`: services start in
+//! `depends_on` order with their replica count and stop in reverse.
+//! Containers are named `<project>-<service>-<n>` and labelled the way the
+//! compose CLI labels them.
+
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+use super::{parse_compose, start_order, ParsedCompose};
+use crate::client::LocalContainerManager;
+
+/// Container a project runs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedContainer {
+    pub service: String,
+    pub name: String,
+    pub number: u32,
+    /// Config passed to `LocalContainerManager::create_container`
+    pub config: serde_json::Value,
+    pub ports: Vec<String>,
+    pub volumes: Vec<String>,
+}
+
+/// Step of `up` or `down`, passed to the progress callback
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComposeProgress {
+    /// `volume`, `create`, `start`, `stop`, `remove` or `removeVolume`
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Container or volume name
+    pub name: String,
+    pub step: usize,
+    pub total: usize,
+}
+
+/// Runs a compose project on a local container manager
+#[wasm_bindgen]
+pub struct LocalComposeOrchestrator {
+    project: String,
+    compose: ParsedCompose,
+    /// Container IDs by name
+    containers: BTreeMap<String, String>,
+    on_progress: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl LocalComposeOrchestrator {
+    /// Create an orchestrator for a compose file (YAML or JSON)
+    #[wasm_bindgen(constructor)]
+    pub fn new(project: &str, content: &str) -> Result<LocalComposeOrchestrator, JsValue> {
+        let compose = parse_compose(content).map_err(|e| JsValue::from_str(&e))?;
+        plan(project, &compose).map_err(|e| JsValue::from_str(&e))?;
+        Ok(Self {
+            project: project.to_string(),
+            compose,
+            containers: BTreeMap::new(),
+            on_progress: None,
+        })
+    }
+
+    /// Set the progress callback: (progress) => void
+    #[wasm_bindgen(setter = onProgress)]
+    pub fn set_on_progress(&mut self, callback: Option<js_sys::Function>) {
+        self.on_progress = callback;
+    }
+
+    /// Create the project's volumes and containers and start them
+    ///
+    /// Containers from an earlier `up` are started again rather than
+    /// recreated. Returns the container IDs by name.
+    #[wasm_bindgen]
+    pub fn up(&mut self, manager: &mut LocalContainerManager) -> String {
+        let planned = match plan(&self.project, &self.compose) {
+            Ok(planned) => planned,
+            Err(e) => return json!({ "error": e }).to_string(),
+        };
+        let volumes = self.volume_names();
+        let total = volumes.len() + planned.len() * 2;
+        let mut step = 0;
+
+        for volume in volumes {
+            if !manager.volumes.contains_key(&volume) {
+                let config = json!({
+                    "Name": volume,
+                    "Labels": { "com.docker.compose.project": self.project }
+                });
+                manager.create_volume(&config.to_string());
+            }
+            step += 1;
+            self.progress("volume", None, &volume, step, total);
+        }
+
+        for container in planned {
+            let existing = self
+                .containers
+                .get(&container.name)
+                .filter(|id| manager.containers.borrow().contains_key(*id))
+                .cloned();
+            let id = match existing {
+                Some(id) => id,
+                None => {
+                    let result: serde_json::Value = serde_json::from_str(
+                        &manager.create_container(&container.config.to_string()),
+                    )
+                    .unwrap_or_default();
+                    let Some(id) = result["Id"].as_str().map(str::to_string) else {
+                        let error =
+                            format!("Failed to create {}: {}", container.name, result["error"]);
+                        return json!({ "error": error }).to_string();
+                    };
+                    if let Some(created) = manager.containers.borrow_mut().get_mut(&id) {
+                        created.ports = container.ports;
+                        created.volumes = container.volumes;
+                    }
+                    self.containers.insert(container.name.clone(), id.clone());
+                    id
+                }
+            };
+            step += 1;
+            self.progress(
+                "create",
+                Some(&container.service),
+                &container.name,
+                step,
+                total,
+            );
+
+            let running = manager
+                .containers
+                .borrow()
+                .get(&id)
+                .is_some_and(|c| c.state == "running");
+            if !running {
+                manager.start_container(&id);
+            }
+            step += 1;
+            self.progress(
+                "start",
+                Some(&container.service),
+                &container.name,
+                step,
+                total,
+            );
+        }
+
+        json!({ "containers": self.containers }).to_string()
+    }
+
+    /// Stop and remove the project's containers in reverse start order, and
+    /// its volumes when `remove_volumes` is set
+    #[wasm_bindgen]
+    pub fn down(&mut self, manager: &mut LocalContainerManager, remove_volumes: bool) -> String {
+        let planned = plan(&self.project, &self.compose).unwrap_or_default();
+        let containers: Vec<(&str, &str, String)> = planned
+            .iter()
+            .rev()
+            .filter_map(|c| {
+                Some((
+                    c.service.as_str(),
+                    c.name.as_str(),
+                    self.containers.remove(&c.name)?,
+                ))
+            })
+            .collect();
+        let volumes = if remove_volumes {
+            self.volume_names()
+        } else {
+            Vec::new()
+        };
+        let total = containers.len() * 2 + volumes.len();
+        let mut step = 0;
+
+        let mut removed = Vec::new();
+        for (service, name, id) in containers {
+            manager.stop_container(&id);
+            step += 1;
+            self.progress("stop", Some(service), name, step, total);
+            manager.remove_container(&id);
+            step += 1;
+            self.progress("remove", Some(service), name, step, total);
+            removed.push(name.to_string());
+        }
+        for volume in volumes {
+            manager.remove_volume(&volume);
+            step += 1;
+            self.progress("removeVolume", None, &volume, step, total);
+        }
+
+        json!({ "removed": removed }).to_string()
+    }
+
+    /// List the project's containers with their service and state, as JSON
+    #[wasm_bindgen]
+    pub fn ps(&self, manager: &LocalContainerManager) -> String {
+        let planned = plan(&self.project, &self.compose).unwrap_or_default();
+        let containers = manager.containers.borrow();
+        let rows: Vec<serde_json::Value> = planned
+            .iter()
+            .filter_map(|c| {
+                let id = self.containers.get(&c.name)?;
+                let container = containers.get(id)?;
+                Some(json!({
+                    "id": id,
+                    "name": c.name,
+                    "service": c.service,
+                    "state": container.state,
+                    "status": container.status,
+                    "health": container.health,
+                }))
+            })
+            .collect();
+        serde_json::to_string(&rows).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+impl LocalComposeOrchestrator {
+    /// Names of the project's volumes
+    fn volume_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .compose
+            .volumes
+            .iter()
+            .flatten()
+            .map(|(name, _)| volume_name(&self.project, name))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn progress(&self, action: &str, service: Option<&str>, name: &str, step: usize, total: usize) {
+        if let Some(callback) = &self.on_progress {
+            let progress = ComposeProgress {
+                action: action.to_string(),
+                service: service.map(str::to_string),
+                name: name.to_string(),
+                step,
+                total,
+            };
+            let value = serde_json::to_string(&progress).unwrap_or_default();
+            let value = js_sys::JSON::parse(&value).unwrap_or(JsValue::NULL);
+            let _ = callback.call1(&JsValue::NULL, &value);
+        }
+    }
+}
+
+/// Containers of a project, in start order
+pub fn plan(project: &str, compose: &ParsedCompose) -> Result<Vec<PlannedContainer>, String> {
+    let mut planned = Vec::new();
+    for service_name in start_order(&compose.services)? {
+        let Some(service) = compose.services.get(&service_name) else {
+            return Err(format!("Unknown service '{}'", service_name));
+        };
+        let image = match (&service.image, &service.build) {
+            (Some(image), _) => image.clone(),
+            (None, Some(_)) => format!("{}-{}", project, service_name),
+            (None, None) => {
+                return Err(format!("Service '{}' has no image or build", service_name))
+            }
+        };
+        let mut env: Vec<String> = service
+            .environment
+            .iter()
+            .flatten()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        env.sort();
+        let volumes: Vec<String> = service
+            .volumes
+            .iter()
+            .flatten()
+            .map(|mount| match mount.split_once(':') {
+                Some((source, target))
+                    if compose
+                        .volumes
+                        .as_ref()
+                        .is_some_and(|volumes| volumes.contains_key(source)) =>
+                {
+                    format!("{}:{}", volume_name(project, source), target)
+                }
+                _ => mount.clone(),
+            })
+            .collect();
+
+        for number in 1..=service.replicas() {
+            let name = format!("{}-{}-{}", project, service_name, number);
+            let mut labels: BTreeMap<String, String> = service
+                .labels
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            labels.insert(
+                "com.docker.compose.project".to_string(),
+                project.to_string(),
+            );
+            labels.insert(
+                "com.docker.compose.service".to_string(),
+                service_name.clone(),
+            );
+            labels.insert(
+                "com.docker.compose.container-number".to_string(),
+                number.to_string(),
+            );
+
+            let mut config = json!({
+                "Image": image,
+                "Name": name,
+                "Env": env,
+                "Labels": labels,
+            });
+            if let Some(command) = &service.command {
+                config["Cmd"] = json!(command);
+            }
+            if let Some(restart) = &service.restart {
+                config["HostConfig"] = json!({ "RestartPolicy": restart_policy(restart) });
+            }
+
+            planned.push(PlannedContainer {
+                service: service_name.clone(),
+                name,
+                number,
+                config,
+                ports: service.ports.clone().unwrap_or_default(),
+                volumes: volumes.clone(),
+            });
+        }
+    }
+    Ok(planned)
+}
+
+fn volume_name(project: &str, volume: &str) -> String {
+    format!("{}_{}", project, volume)
+}
+
+/// Restart policy of a compose `restart` value such as `on-failure:3`
+fn restart_policy(restart: &str) -> serde_json::Value {
+    let (name, max) = match restart.split_once(':') {
+        Some((name, max)) => (name, max.parse::<i32>().ok()),
+        None => (restart, None),
+    };
+    json!({ "Name": name, "MaximumRetryCount": max })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compose(json: &str) -> ParsedCompose {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_plan() {
+        let compose = compose(
+            r#"{
+                "services": {
+                    "web": {
+                        "name": "web",
+                        "image": "nginx",
+                        "depends_on": ["api"],
+                        "restart": "on-failure:3",
+                        "volumes": ["static:/srv", "./conf:/etc/nginx"]
+                    },
+                    "api": {
+                        "name": "api",
+                        "build": { "context": "./api" },
+                        "deploy": { "replicas": 2 },
+                        "environment": { "B": "2", "A": "1" }
+                    }
+                },
+                "volumes": { "static": {} }
+            }"#,
+        );
+        let planned = plan("demo", &compose).unwrap();
+        let names: Vec<&str> = planned.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["demo-api-1", "demo-api-2", "demo-web-1"]);
+
+        let api = &planned[1];
+        assert_eq!(api.number, 2);
+        assert_eq!(api.config["Image"], "demo-api");
+        assert_eq!(api.config["Env"], json!(["A=1", "B=2"]));
+        assert_eq!(api.config["Labels"]["com.docker.compose.service"], "api");
+        assert_eq!(
+            api.config["Labels"]["com.docker.compose.container-number"],
+            "2"
+        );
+
+        let web = &planned[2];
+        assert_eq!(
+            web.config["HostConfig"]["RestartPolicy"],
+            json!({ "Name": "on-failure", "MaximumRetryCount": 3 })
+        );
+        assert_eq!(web.volumes, ["demo_static:/srv", "./conf:/etc/nginx"]);
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let missing = compose(
+            r#"{"services": {"web": {"name": "web", "image": "nginx", "depends_on": ["db"]}}}"#,
+        );
+        assert_eq!(plan("demo", &missing).unwrap_err(), "Unknown service 'db'");
+
+        let no_image = compose(r#"{"services": {"web": {"name": "web"}}}"#);
+        assert!(plan("demo", &no_image)
+            .unwrap_err()
+            .contains("no image or build"));
+    }
+}
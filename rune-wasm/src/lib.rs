@@ -26,11 +26,33 @@
 //! manager.startContainer(containerId);
 //! manager.stopContainer(containerId);
 //!
+//! // Simulate startup, logs, healthchecks and restarts on timers
+//! manager.onEvent = (event) => console.log(event.id, event.action);
+//! manager.createContainer(JSON.stringify({
+//!     Image: 'nginx',
+//!     Healthcheck: { Test: ['CMD', 'true'], Interval: 1e9, Retries: 3 },
+//!     HostConfig: { RestartPolicy: { Name: 'on-failure', MaximumRetryCount: 3 } },
+//!     Simulate: { StartupDelayMs: 500, LogIntervalMs: 1000, ExitAfterMs: 10000, ExitCode: 1 }
+//! }));
+//!
 //! // Persist to localStorage (browser only)
 //! manager.saveToLocalStorage('rune-containers');
 //!
 //! // Restore from localStorage
 //! manager.loadFromLocalStorage('rune-containers');
+//!
+//! // Or keep larger state, with volumes and logs, in IndexedDB
+//! await manager.loadFromIndexedDB('rune-containers');
+//! manager.setAutoPersist('rune-containers');
+//! ```
+//!
+//! ## Local Compose (No Server Required)
+//!
+//! ```javascript
+//! const project = new LocalComposeOrchestrator('demo', composeYaml);
+//! project.onProgress = ({ action, name, step, total }) => render(action, name, step / total);
+//! project.up(manager);
+//! project.down(manager, true);
 //! ```
 //!
 //! ## Remote Usage (With Server)
@@ -41,9 +63,55 @@
 //! await init();
 //!
 //! const client = new RuneClient('ws://localhost:2375');
+//! client.onConnectionStateChange = (state) => status.textContent = state;
 //! await client.connect();
 //! const containers = await client.listContainers();
 //! ```
+//!
+//! List and info calls resolve to typed objects (`Container[]`, `Image[]`,
+//! `Network[]`, `VolumeList`, `SystemInfo`, `Version`). Failed calls reject
+//! with a `RuneClientError` carrying the HTTP `status` and the daemon's
+//! `message`.
+//!
+//! A dropped connection is retried with backoff; log and event streams
+//! resume once it is back, while exec sessions end. `setReconnect` tunes
+//! or disables this.
+//!
+//! ## Streaming (With Server)
+//!
+//! Logs and events arrive over the WebSocket as they happen; each callback
+//! receives `null` when its stream ends.
+//!
+//! ```javascript
+//! const logs = client.streamLogs(containerId, (line) => console.log(line));
+//! const events = client.streamEvents((event) => console.log(event));
+//! client.unsubscribe(logs);
+//!
+//! // Any API call can also go over the WebSocket
+//! const info = await client.request('GET', '/info');
+//! ```
+//!
+//! ## Terminals (With Server)
+//!
+//! ```javascript
+//! const session = client.exec(containerId, ['sh'], JSON.stringify({ Tty: true }));
+//! session.onData = (data) => data === null ? term.dispose() : term.write(data);
+//! term.onData((input) => session.write(input));
+//! term.onResize(({ cols, rows }) => session.resize(cols, rows));
+//! ```
+//!
+//! ## Authentication (With Server)
+//!
+//! Credentials and headers go with every HTTP request, and to the daemon in
+//! an `authenticate` message once the WebSocket opens, since browsers cannot
+//! set headers on the handshake.
+//!
+//! ```javascript
+//! client.setBearerToken(token);
+//! client.setHeader('X-Tenant', 'team-a');
+//! client.setTokenRefresh(async () => (await login()).token);
+//! await client.connect();
+//! ```
 
 pub mod builder;
 pub mod client;
@@ -53,7 +121,7 @@ pub mod utils;
 
 // Re-export main types for convenience
 pub use builder::RunefileBuilder;
-pub use client::{LocalContainerManager, RuneClient};
-pub use compose::ComposeParser;
+pub use client::{ExecSession, LocalContainerManager, RuneClient, RuneClientError};
+pub use compose::{ComposeParser, LocalComposeOrchestrator};
 pub use types::*;
 pub use utils::{calculate_digest, generate_id, get_current_timestamp};
@@ -4,6 +4,11 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+/// TypeScript types of the objects returned by `RuneClient`, generated
+/// from the types below by the build script
+#[wasm_bindgen(typescript_custom_section)]
+const TYPESCRIPT_TYPES: &str = include_str!(concat!(env!("OUT_DIR"), "/types.ts"));
+
 /// Container state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[wasm_bindgen]
@@ -18,12 +23,13 @@ pub enum ContainerState {
 }
 
 /// Container information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct Container {
     pub id: String,
     pub names: Vec<String>,
     pub image: String,
+    #[serde(rename = "ImageID")]
     pub image_id: String,
     pub command: String,
     pub created: i64,
@@ -34,8 +40,8 @@ pub struct Container {
 }
 
 /// Port binding
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct PortBinding {
     #[serde(rename = "IP")]
     pub ip: Option<String>,
@@ -45,6 +51,68 @@ pub struct PortBinding {
     pub port_type: String,
 }
 
+/// Container details, as inspected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerInspect {
+    pub id: String,
+    pub created: String,
+    pub path: String,
+    pub args: Vec<String>,
+    pub state: ContainerInspectState,
+    pub image: String,
+    pub name: String,
+    pub restart_count: i32,
+    pub platform: String,
+    pub config: ContainerInspectConfig,
+    pub mounts: Vec<ContainerMount>,
+}
+
+/// State of an inspected container
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerInspectState {
+    pub status: String,
+    pub running: bool,
+    pub paused: bool,
+    pub restarting: bool,
+    #[serde(rename = "OOMKilled")]
+    pub oom_killed: bool,
+    pub dead: bool,
+    pub pid: i64,
+    pub exit_code: i32,
+    pub error: String,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// Configuration of an inspected container
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerInspectConfig {
+    pub hostname: String,
+    pub user: String,
+    pub env: Vec<String>,
+    pub cmd: Vec<String>,
+    pub image: String,
+    pub working_dir: String,
+    pub entrypoint: Option<Vec<String>>,
+    pub labels: HashMap<String, String>,
+}
+
+/// Mount of an inspected container
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerMount {
+    #[serde(rename = "Type")]
+    pub mount_type: String,
+    pub source: String,
+    pub destination: String,
+    pub mode: String,
+    #[serde(rename = "RW")]
+    pub rw: bool,
+}
+
 /// Container creation options
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -60,6 +128,17 @@ pub struct ContainerCreateOptions {
     pub host_config: Option<HostConfig>,
 }
 
+/// Exec options
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExecOptions {
+    /// Allocate a terminal, on by default
+    pub tty: Option<bool>,
+    pub env: Option<Vec<String>>,
+    pub working_dir: Option<String>,
+    pub user: Option<String>,
+}
+
 /// Host configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -91,8 +170,8 @@ pub struct RestartPolicy {
 }
 
 /// Image information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct Image {
     pub id: String,
     pub parent_id: String,
@@ -105,8 +184,8 @@ pub struct Image {
 }
 
 /// Network information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct Network {
     pub name: String,
     pub id: String,
@@ -122,24 +201,24 @@ pub struct Network {
 }
 
 /// IPAM configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct IpamConfig {
     pub driver: String,
     pub config: Vec<IpamPoolConfig>,
 }
 
 /// IPAM pool configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct IpamPoolConfig {
     pub subnet: Option<String>,
     pub gateway: Option<String>,
 }
 
 /// Volume information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct Volume {
     pub name: String,
     pub driver: String,
@@ -149,9 +228,17 @@ pub struct Volume {
     pub scope: String,
 }
 
+/// Volumes listed by the daemon
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct VolumeList {
+    pub volumes: Vec<Volume>,
+    pub warnings: Vec<String>,
+}
+
 /// System information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct SystemInfo {
     pub id: String,
     pub containers: i32,
@@ -164,6 +251,7 @@ pub struct SystemInfo {
     pub swap_limit: bool,
     pub kernel_version: String,
     pub operating_system: String,
+    #[serde(rename = "OSType")]
     pub os_type: String,
     pub architecture: String,
     #[serde(rename = "NCPU")]
@@ -174,11 +262,12 @@ pub struct SystemInfo {
 }
 
 /// Version information
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
 pub struct Version {
     pub version: String,
     pub api_version: String,
+    #[serde(rename = "MinAPIVersion")]
     pub min_api_version: String,
     pub git_commit: String,
     pub go_version: String,
@@ -187,3 +276,184 @@ pub struct Version {
     pub kernel_version: String,
     pub build_time: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Fields of each interface in `TYPESCRIPT_TYPES`, with their types
+    ///
+    /// The custom section's constant is taken by `wasm_bindgen`, so the
+    /// generated file is read again.
+    fn interfaces() -> HashMap<String, Vec<(String, String)>> {
+        let types = include_str!(concat!(env!("OUT_DIR"), "/types.ts"));
+
+        let mut interfaces = HashMap::new();
+        let mut current: Option<(String, Vec<(String, String)>)> = None;
+        for line in types.lines().map(str::trim) {
+            if let Some(name) = line
+                .strip_prefix("export interface ")
+                .and_then(|rest| rest.strip_suffix(" {"))
+            {
+                current = Some((name.to_string(), Vec::new()));
+            } else if line == "}" {
+                let (name, fields) = current.take().expect("interface closed before opened");
+                interfaces.insert(name, fields);
+            } else if let Some((_, fields)) = &mut current {
+                let (field, ty) = line
+                    .strip_suffix(';')
+                    .and_then(|field| field.split_once(": "))
+                    .unwrap_or_else(|| panic!("unexpected line: {}", line));
+                fields.push((field.to_string(), ty.to_string()));
+            }
+        }
+        interfaces
+    }
+
+    /// Check a serialized value against a TypeScript type
+    fn check(
+        interfaces: &HashMap<String, Vec<(String, String)>>,
+        ty: &str,
+        value: &Value,
+        at: &str,
+    ) {
+        if let Some(ty) = ty.strip_suffix(" | null") {
+            if !value.is_null() {
+                check(interfaces, ty, value, at);
+            }
+            return;
+        }
+        if let Some(item) = ty.strip_suffix("[]") {
+            let items = value
+                .as_array()
+                .unwrap_or_else(|| panic!("{} isn't an array", at));
+            for (index, value) in items.iter().enumerate() {
+                check(interfaces, item, value, &format!("{}[{}]", at, index));
+            }
+            return;
+        }
+        let matches = match ty {
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "Record<string, string>" => value
+                .as_object()
+                .is_some_and(|map| map.values().all(Value::is_string)),
+            name => {
+                let fields = interfaces
+                    .get(name)
+                    .unwrap_or_else(|| panic!("no interface {} for {}", name, at));
+                let object = value
+                    .as_object()
+                    .unwrap_or_else(|| panic!("{} isn't an object", at));
+                let mut keys: Vec<&String> = object.keys().collect();
+                let mut declared: Vec<&String> = fields.iter().map(|(field, _)| field).collect();
+                keys.sort();
+                declared.sort();
+                assert_eq!(keys, declared, "fields of {} ({})", at, name);
+                for (field, ty) in fields {
+                    check(interfaces, ty, &object[field], &format!("{}.{}", at, field));
+                }
+                true
+            }
+        };
+        assert!(matches, "{} isn't a {}: {}", at, ty, value);
+    }
+
+    /// Every interface matches how its Rust type serializes, with optional
+    /// fields both unset and set
+    #[test]
+    fn test_typescript_types() {
+        let interfaces = interfaces();
+        let labels = HashMap::from([("app".to_string(), "web".to_string())]);
+        let port = PortBinding {
+            ip: Some("0.0.0.0".to_string()),
+            private_port: 80,
+            public_port: Some(8080),
+            port_type: "tcp".to_string(),
+        };
+        let network = Network {
+            ipam: IpamConfig {
+                driver: "default".to_string(),
+                config: vec![
+                    IpamPoolConfig::default(),
+                    IpamPoolConfig {
+                        subnet: Some("10.0.0.0/24".to_string()),
+                        gateway: Some("10.0.0.1".to_string()),
+                    },
+                ],
+            },
+            labels: labels.clone(),
+            ..Network::default()
+        };
+        let volume = Volume {
+            labels: labels.clone(),
+            ..Volume::default()
+        };
+        let values = [
+            (
+                "Container",
+                serde_json::to_value(Container {
+                    names: vec!["/web".to_string()],
+                    ports: vec![PortBinding::default(), port],
+                    labels: labels.clone(),
+                    ..Container::default()
+                }),
+            ),
+            (
+                "ContainerInspect",
+                serde_json::to_value(ContainerInspect {
+                    args: vec!["-g".to_string()],
+                    config: ContainerInspectConfig {
+                        env: vec!["PATH=/bin".to_string()],
+                        entrypoint: Some(vec!["nginx".to_string()]),
+                        labels: labels.clone(),
+                        ..ContainerInspectConfig::default()
+                    },
+                    mounts: vec![ContainerMount::default()],
+                    ..ContainerInspect::default()
+                }),
+            ),
+            (
+                "Image",
+                serde_json::to_value(Image {
+                    repo_tags: vec!["nginx:latest".to_string()],
+                    repo_digests: vec!["nginx@sha256:0".to_string()],
+                    labels,
+                    ..Image::default()
+                }),
+            ),
+            ("Network", serde_json::to_value(network)),
+            (
+                "VolumeList",
+                serde_json::to_value(VolumeList {
+                    volumes: vec![volume],
+                    warnings: vec!["warning".to_string()],
+                }),
+            ),
+            ("SystemInfo", serde_json::to_value(SystemInfo::default())),
+            ("Version", serde_json::to_value(Version::default())),
+        ];
+
+        let mut checked: Vec<&str> = Vec::new();
+        for (name, value) in values {
+            check(&interfaces, name, &value.unwrap(), name);
+            checked.push(name);
+        }
+        // Nested interfaces are checked through the ones holding them
+        checked.extend([
+            "PortBinding",
+            "ContainerInspectState",
+            "ContainerInspectConfig",
+            "ContainerMount",
+            "IpamConfig",
+            "IpamPoolConfig",
+            "Volume",
+        ]);
+        let mut declared: Vec<&str> = interfaces.keys().map(String::as_str).collect();
+        checked.sort();
+        declared.sort();
+        assert_eq!(checked, declared);
+    }
+}
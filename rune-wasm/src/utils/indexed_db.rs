@@ -0,0 +1,99 @@
+//! IndexedDB storage
+//!
+//! Keeps strings under keys in one object store, for state that outgrows
+//! the localStorage quota.
+
+use futures::channel::oneshot;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::{IdbDatabase, IdbRequest, IdbTransactionMode};
+
+const DB_NAME: &str = "rune-wasm";
+const STORE: &str = "state";
+
+/// Store a value under a key
+pub async fn put(key: &str, value: &str) -> Result<(), JsValue> {
+    let db = open().await?;
+    let result = async {
+        let store = db
+            .transaction_with_str_and_mode(STORE, IdbTransactionMode::Readwrite)?
+            .object_store(STORE)?;
+        wait(&store.put_with_key(&JsValue::from_str(value), &JsValue::from_str(key))?).await
+    }
+    .await;
+    db.close();
+    result.map(|_| ())
+}
+
+/// Value stored under a key, `None` when there is none
+pub async fn get(key: &str) -> Result<Option<String>, JsValue> {
+    let db = open().await?;
+    let result = async {
+        let store = db.transaction_with_str(STORE)?.object_store(STORE)?;
+        wait(&store.get(&JsValue::from_str(key))?).await
+    }
+    .await;
+    db.close();
+    Ok(result?.as_string())
+}
+
+/// Open the database, creating the object store on first use
+async fn open() -> Result<IdbDatabase, JsValue> {
+    let factory = web_sys::window()
+        .ok_or_else(|| JsValue::from_str("No window"))?
+        .indexed_db()?
+        .ok_or_else(|| JsValue::from_str("IndexedDB is not available"))?;
+    let request = factory.open_with_u32(DB_NAME, 1)?;
+
+    let upgrade = {
+        let request = request.clone();
+        Closure::<dyn FnMut()>::new(move || {
+            if let Ok(db) = request.result() {
+                let _ = db
+                    .unchecked_into::<IdbDatabase>()
+                    .create_object_store(STORE);
+            }
+        })
+    };
+    request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+    let db = wait(&request).await;
+    request.set_onupgradeneeded(None);
+    Ok(db?.unchecked_into())
+}
+
+/// Wait for a request to finish, resolving to its result
+async fn wait(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+
+    let on_success = {
+        let (sender, request) = (sender.clone(), request.clone());
+        Closure::<dyn FnMut()>::new(move || {
+            if let Some(sender) = sender.borrow_mut().take() {
+                let _ = sender.send(request.result());
+            }
+        })
+    };
+    let on_error = {
+        let (sender, request) = (sender.clone(), request.clone());
+        Closure::<dyn FnMut()>::new(move || {
+            if let Some(sender) = sender.borrow_mut().take() {
+                let error = match request.error() {
+                    Ok(Some(error)) => error.into(),
+                    _ => JsValue::from_str("IndexedDB request failed"),
+                };
+                let _ = sender.send(Err(error));
+            }
+        })
+    };
+    request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+    request.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+    let result = receiver
+        .await
+        .unwrap_or_else(|_| Err(JsValue::from_str("IndexedDB request failed")));
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+    result
+}
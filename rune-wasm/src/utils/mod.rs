@@ -1,5 +1,7 @@
 //! Utility functions for WASM
 
+pub mod indexed_db;
+
 use chrono::Utc;
 use sha2::{Digest, Sha256};
 use wasm_bindgen::prelude::*;
@@ -26,6 +28,28 @@ pub fn get_current_timestamp() -> String {
     Utc::now().to_rfc3339()
 }
 
+/// Standard base64 encoding
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 /// Simple sleep function for WASM
 pub async fn gloo_timers_sleep(ms: u32) {
     let promise = js_sys::Promise::new(&mut |resolve, _| {
@@ -47,6 +71,14 @@ mod tests {
         assert!(digest.starts_with("sha256:"));
     }
 
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+    }
+
     #[test]
     fn test_generate_id() {
         let id = generate_id();
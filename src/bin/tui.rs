@@ -1,12 +1,13 @@
 //! Rune TUI - Standalone Terminal User Interface
 //!
 //! Launch the TUI directly with: rune-tui
+//!
+//! It connects to the daemon of the current context; set `RUNE_CONTEXT` to
+//! use another one, or switch contexts from within the TUI.
 
-use rune::container::ContainerManager;
+use rune::daemon::ContextStore;
 use rune::error::Result;
-use rune::tui::App;
-use std::path::PathBuf;
-use std::sync::Arc;
+use rune::tui::{App, TuiConfig};
 use tracing_subscriber::EnvFilter;
 
 fn main() -> Result<()> {
@@ -15,15 +16,9 @@ fn main() -> Result<()> {
         .with_env_filter(EnvFilter::new("warn"))
         .init();
 
-    // Get base path for rune data
-    let base_path = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("/var/lib"))
-        .join("rune");
-
-    // Initialize container manager
-    let container_manager = Arc::new(ContainerManager::new(base_path.join("containers"))?);
-
-    // Run TUI
-    let mut app = App::new(container_manager);
+    // Connect to the current context's daemon
+    let store = ContextStore::open_default()?;
+    let mut app =
+        App::with_contexts(store.list(), &store.current_name()).with_config(TuiConfig::load());
     app.run()
 }
@@ -151,6 +151,9 @@ pub struct ServiceConfig {
     /// Security options
     #[serde(default)]
     pub security_opt: Option<Vec<String>>,
+    /// User namespace mode
+    #[serde(default)]
+    pub userns_mode: Option<String>,
     /// Secrets
     #[serde(default)]
     pub secrets: Option<Vec<SecretRef>>,
@@ -163,6 +166,12 @@ pub struct ServiceConfig {
     /// Init process
     #[serde(default)]
     pub init: Option<bool>,
+    /// Disable the OOM killer
+    #[serde(default)]
+    pub oom_kill_disable: Option<bool>,
+    /// OOM score adjustment
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
     /// IPC mode
     #[serde(default)]
     pub ipc: Option<String>,
@@ -6,6 +6,7 @@
 pub mod config;
 pub mod orchestrator;
 pub mod parser;
+pub mod schema;
 
 pub use config::{ComposeConfig, ServiceConfig};
 pub use orchestrator::ComposeOrchestrator;
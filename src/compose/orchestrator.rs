@@ -1,12 +1,45 @@
 //! Docker Compose orchestrator
-
-use super::config::{ComposeConfig, DependsOnConfig, ServiceConfig};
-use crate::container::{ContainerConfig, ContainerManager, ContainerStatus};
+//!
+//! Every container the orchestrator creates carries labels naming its
+//! project, its service and its number among the service's replicas, as
+//! Docker Compose sets them. The orchestrator finds a project's containers
+//! by these labels rather than by what it remembers, so containers of
+//! services since deleted from the compose file are found too: those are
+//! the project's orphans.
+
+use super::config::{
+    BuildConfig, BuildConfigFull, ComposeConfig, DependsOnConfig, HealthcheckConfig,
+    HealthcheckTest, ServiceConfig, UlimitConfig,
+};
+use crate::container::{ContainerConfig, ContainerManager, ContainerStatus, Ulimit};
 use crate::error::{Result, RuneError};
+use crate::filter::Filters;
 use crate::image::builder::{BuildContext, ImageBuilder};
+use crate::image::{ContextSource, ImagePuller, ImageStore};
+use crate::runtime::cdi::GpuRequest;
+use crate::runtime::seccomp::read_seccomp_profiles;
+use crate::swarm::logs::{LogCursor, LogLine, LogRequest};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Label naming the project a container belongs to
+pub const PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// Label naming the service a container runs
+pub const SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// Label numbering a container among its service's replicas, from 1
+pub const CONTAINER_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+
+/// Label marking containers of `run` rather than of a service
+pub const ONEOFF_LABEL: &str = "com.docker.compose.oneoff";
+
+/// How often followed logs are read for new lines
+const LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Compose project state
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -36,6 +69,36 @@ pub struct ServiceState {
     pub state: ContainerStatus,
 }
 
+/// Progress of pulling or pushing a service's image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageProgress {
+    /// Pulling the image
+    Pulling(String),
+    /// Pulled the image, with this manifest digest
+    Pulled(String),
+    /// Pushing the image
+    Pushing(String),
+    /// Pushed the image, with this manifest digest
+    Pushed(String),
+    /// Nothing to do, for this reason
+    Skipped(String),
+    /// Failed, with this error
+    Failed(String),
+}
+
+impl fmt::Display for ImageProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageProgress::Pulling(image) => write!(f, "Pulling {}", image),
+            ImageProgress::Pulled(digest) => write!(f, "Pulled {}", digest),
+            ImageProgress::Pushing(image) => write!(f, "Pushing {}", image),
+            ImageProgress::Pushed(digest) => write!(f, "Pushed {}", digest),
+            ImageProgress::Skipped(reason) => write!(f, "Skipped - {}", reason),
+            ImageProgress::Failed(error) => write!(f, "Error: {}", error),
+        }
+    }
+}
+
 /// Compose orchestrator
 pub struct ComposeOrchestrator {
     /// Project name
@@ -48,6 +111,8 @@ pub struct ComposeOrchestrator {
     service_states: HashMap<String, ServiceState>,
     /// Project working directory
     working_dir: PathBuf,
+    /// Store service images are built into
+    image_store: Option<Arc<ImageStore>>,
 }
 
 impl ComposeOrchestrator {
@@ -64,16 +129,43 @@ impl ComposeOrchestrator {
             container_manager,
             service_states: HashMap::new(),
             working_dir,
+            image_store: None,
         }
     }
 
-    /// Start the compose project
-    pub async fn up(&mut self, detach: bool, build: bool) -> Result<()> {
+    /// Set the image store to build service images into
+    pub fn with_image_store(mut self, store: Arc<ImageStore>) -> Self {
+        self.image_store = Some(store);
+        self
+    }
+
+    /// Name of the project
+    pub fn project_name(&self) -> &str {
+        &self.project_name
+    }
+
+    /// Configuration of the project
+    pub fn config(&self) -> &ComposeConfig {
+        &self.config
+    }
+
+    /// Image a service runs: its `image`, or the one built for it
+    pub fn service_image(&self, service_name: &str, service: &ServiceConfig) -> String {
+        service
+            .image
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}:latest", self.project_name, service_name))
+    }
+
+    /// Start the compose project, removing its orphans if asked to
+    pub async fn up(&mut self, detach: bool, build: bool, remove_orphans: bool) -> Result<()> {
         tracing::info!("Starting compose project: {}", self.project_name);
 
+        self.handle_orphans(remove_orphans)?;
+
         // Build images if requested
         if build {
-            self.build_services().await?;
+            self.build_services(&[], false).await?;
         }
 
         // Get service start order
@@ -95,15 +187,19 @@ impl ComposeOrchestrator {
         Ok(())
     }
 
-    /// Stop the compose project
-    pub async fn down(&mut self, remove_volumes: bool) -> Result<()> {
+    /// Stop and remove the project's containers, and its orphans if asked
+    /// to
+    pub async fn down(&mut self, remove_volumes: bool, remove_orphans: bool) -> Result<()> {
         tracing::info!("Stopping compose project: {}", self.project_name);
 
-        // Stop services in reverse order
+        // Remove services in reverse order
         let order = self.get_start_order()?;
         for service_name in order.into_iter().rev() {
-            self.stop_service(&service_name).await?;
+            let containers = self.containers(Some(&service_name))?;
+            self.remove_containers(&containers)?;
+            self.service_states.remove(&service_name);
         }
+        self.handle_orphans(remove_orphans)?;
 
         // Remove volumes if requested
         if remove_volumes {
@@ -113,7 +209,8 @@ impl ComposeOrchestrator {
         Ok(())
     }
 
-    /// Start a specific service
+    /// Start a specific service, starting the containers it already has
+    /// and creating those it lacks
     pub async fn start_service(&mut self, service_name: &str) -> Result<()> {
         let service = self
             .config
@@ -134,20 +231,22 @@ impl ComposeOrchestrator {
             replicas
         );
 
+        let existing = self.containers(Some(service_name))?;
         let mut container_ids = Vec::new();
 
-        for i in 0..replicas {
-            let container_name = if replicas == 1 {
-                format!("{}-{}-1", self.project_name, service_name)
-            } else {
-                format!("{}-{}-{}", self.project_name, service_name, i + 1)
+        for number in 1..=replicas {
+            let id = match existing
+                .iter()
+                .find(|c| container_number(c) == Some(number))
+            {
+                Some(container) => {
+                    if container.status != ContainerStatus::Running {
+                        self.container_manager.start(&container.id)?;
+                    }
+                    container.id.clone()
+                }
+                None => self.create_container(service_name, &service, number)?,
             };
-
-            let container_config =
-                self.service_to_container_config(service_name, &service, &container_name)?;
-
-            let id = self.container_manager.create(container_config)?;
-            self.container_manager.start(&id)?;
             container_ids.push(id);
         }
 
@@ -166,11 +265,12 @@ impl ComposeOrchestrator {
 
     /// Stop a specific service
     pub async fn stop_service(&mut self, service_name: &str) -> Result<()> {
-        if let Some(state) = self.service_states.get(service_name) {
-            for id in &state.container_ids {
-                if let Err(e) = self.container_manager.stop(id) {
-                    tracing::warn!("Failed to stop container {}: {}", id, e);
-                }
+        for container in self.containers(Some(service_name))? {
+            if container.status != ContainerStatus::Running {
+                continue;
+            }
+            if let Err(e) = self.container_manager.stop(&container.id) {
+                tracing::warn!("Failed to stop container {}: {}", container.name, e);
             }
         }
 
@@ -188,103 +288,353 @@ impl ComposeOrchestrator {
         Ok(())
     }
 
-    /// Scale a service
+    /// Scale a service, creating the containers numbered up to `replicas`
+    /// and removing those numbered above
     pub async fn scale(&mut self, service_name: &str, replicas: u32) -> Result<()> {
-        let current = self
-            .service_states
+        let service = self
+            .config
+            .services
             .get(service_name)
-            .map(|s| s.replicas)
-            .unwrap_or(0);
-
-        if replicas > current {
-            // Scale up
-            let service = self
-                .config
-                .services
-                .get(service_name)
-                .ok_or_else(|| RuneError::ServiceNotFound(service_name.to_string()))?
-                .clone();
-
-            for i in current..replicas {
-                let container_name = format!("{}-{}-{}", self.project_name, service_name, i + 1);
-                let container_config =
-                    self.service_to_container_config(service_name, &service, &container_name)?;
-
-                let id = self.container_manager.create(container_config)?;
-                self.container_manager.start(&id)?;
-
-                if let Some(state) = self.service_states.get_mut(service_name) {
-                    state.container_ids.push(id);
-                    state.replicas = replicas;
+            .ok_or_else(|| RuneError::ServiceNotFound(service_name.to_string()))?
+            .clone();
+
+        let existing = self.containers(Some(service_name))?;
+        let (kept, removed): (Vec<_>, Vec<_>) = existing
+            .into_iter()
+            .partition(|c| container_number(c).is_some_and(|number| number <= replicas));
+        self.remove_containers(&removed)?;
+
+        let mut container_ids = Vec::new();
+        for number in 1..=replicas {
+            let id = match kept.iter().find(|c| container_number(c) == Some(number)) {
+                Some(container) => container.id.clone(),
+                None => self.create_container(service_name, &service, number)?,
+            };
+            container_ids.push(id);
+        }
+
+        if let Some(state) = self.service_states.get_mut(service_name) {
+            state.container_ids = container_ids;
+            state.replicas = replicas;
+        }
+
+        Ok(())
+    }
+
+    /// Containers of the project, or of one of its services, by their
+    /// labels; ordered by service and number
+    pub fn containers(&self, service_name: Option<&str>) -> Result<Vec<ContainerConfig>> {
+        let mut filters =
+            Filters::new().with("label", &format!("{}={}", PROJECT_LABEL, self.project_name));
+        if let Some(service_name) = service_name {
+            filters = filters.with("label", &format!("{}={}", SERVICE_LABEL, service_name));
+        }
+        let mut containers: Vec<ContainerConfig> = self
+            .container_manager
+            .list_filtered(true, &filters)?
+            .into_iter()
+            .filter(|c| {
+                c.labels
+                    .get(ONEOFF_LABEL)
+                    .is_none_or(|oneoff| oneoff != "True")
+            })
+            .collect();
+        containers.sort_by(|a, b| {
+            (a.labels.get(SERVICE_LABEL), container_number(a))
+                .cmp(&(b.labels.get(SERVICE_LABEL), container_number(b)))
+        });
+        Ok(containers)
+    }
+
+    /// Containers of the project whose service is no longer in the compose
+    /// file
+    pub fn orphans(&self) -> Result<Vec<ContainerConfig>> {
+        Ok(self
+            .containers(None)?
+            .into_iter()
+            .filter(|c| {
+                c.labels
+                    .get(SERVICE_LABEL)
+                    .is_none_or(|service| !self.config.services.contains_key(service))
+            })
+            .collect())
+    }
+
+    /// Remove the project's orphans if asked to, or else warn of them
+    fn handle_orphans(&self, remove_orphans: bool) -> Result<()> {
+        let orphans = self.orphans()?;
+        if remove_orphans {
+            self.remove_containers(&orphans)?;
+        } else if !orphans.is_empty() {
+            let names: Vec<&str> = orphans.iter().map(|c| c.name.as_str()).collect();
+            tracing::warn!(
+                "Found orphan containers ({}) for project {}. If you removed or renamed their \
+                 service in the compose file, run with --remove-orphans to clean them up.",
+                names.join(", "),
+                self.project_name
+            );
+        }
+        Ok(())
+    }
+
+    /// Stop and remove containers
+    fn remove_containers(&self, containers: &[ContainerConfig]) -> Result<()> {
+        for container in containers {
+            tracing::info!("Removing container {}", container.name);
+            if container.status == ContainerStatus::Running {
+                if let Err(e) = self.container_manager.stop(&container.id) {
+                    tracing::warn!("Failed to stop container {}: {}", container.name, e);
                 }
             }
-        } else if replicas < current {
-            // Scale down
-            if let Some(state) = self.service_states.get_mut(service_name) {
-                while state.container_ids.len() > replicas as usize {
-                    if let Some(id) = state.container_ids.pop() {
-                        self.container_manager.stop(&id)?;
-                        self.container_manager.remove(&id, true)?;
-                    }
+            self.container_manager.remove(&container.id, true)?;
+        }
+        Ok(())
+    }
+
+    /// Create and start a service's container with the given number
+    fn create_container(
+        &self,
+        service_name: &str,
+        service: &ServiceConfig,
+        number: u32,
+    ) -> Result<String> {
+        let container_name = format!("{}-{}-{}", self.project_name, service_name, number);
+        let container_config =
+            self.service_to_container_config(service_name, service, &container_name, number)?;
+
+        let id = self.container_manager.create(container_config)?;
+        self.container_manager.start(&id)?;
+        Ok(id)
+    }
+
+    /// Build the images of services, or of all of them if none are given
+    pub async fn build_services(&self, services: &[String], no_cache: bool) -> Result<()> {
+        let store = self
+            .image_store
+            .clone()
+            .ok_or_else(|| RuneError::Compose("No image store to build with".to_string()))?;
+        for name in self.service_names(services)? {
+            let service = &self.config.services[&name];
+            let Some(build_config) = &service.build else {
+                continue;
+            };
+            tracing::info!("Building image for service: {}", name);
+            let mut context = self.build_context(&name, service, build_config);
+            context.no_cache |= no_cache;
+            ImageBuilder::new(context)
+                .with_store(store.clone())
+                .build()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Build context of a service: its context, local to the project or
+    /// remote, build file, arguments, target stage and cache images
+    fn build_context(
+        &self,
+        name: &str,
+        service: &ServiceConfig,
+        build: &BuildConfig,
+    ) -> BuildContext {
+        let full = match build {
+            BuildConfig::Simple(context) => BuildConfigFull {
+                context: Some(context.clone()),
+                ..Default::default()
+            },
+            BuildConfig::Full(full) => full.clone(),
+        };
+        let source = full.context.as_deref().unwrap_or(".");
+        let mut context = match ContextSource::parse(source) {
+            ContextSource::Local(path) => {
+                let dir = self.working_dir.join(path);
+                match &full.dockerfile {
+                    Some(file) => BuildContext::new(dir.clone()).build_file(dir.join(file)),
+                    None => BuildContext::new(dir),
                 }
-                state.replicas = replicas;
             }
+            _ => match &full.dockerfile {
+                Some(file) => BuildContext::from_source(source).build_file(PathBuf::from(file)),
+                None => BuildContext::from_source(source),
+            },
         }
+        .tag(&self.service_image(name, service));
 
-        Ok(())
+        for (key, value) in full.args.iter().flatten() {
+            context = context.arg(key, value);
+        }
+        if let Some(target) = &full.target {
+            context = context.target(target);
+        }
+        for image in full.cache_from.iter().flatten() {
+            context = context.cache_from(image);
+        }
+        for (key, value) in full.labels.iter().flatten() {
+            context = context.label(key, value);
+        }
+        for tag in full.tags.iter().flatten() {
+            context = context.tag(tag);
+        }
+        context.no_cache = full.no_cache.unwrap_or(false);
+        context.pull = full.pull.unwrap_or(false);
+        context
     }
 
-    /// Build service images
-    pub async fn build_services(&self) -> Result<()> {
-        for (name, service) in &self.config.services {
-            if let Some(ref build_config) = service.build {
-                tracing::info!("Building image for service: {}", name);
+    /// Pull the images of services, or of all of them if none are given, in
+    /// parallel. Failing to pull the image of a service that is built is
+    /// not an error; others are, unless failures are ignored.
+    pub async fn pull(
+        &self,
+        services: &[String],
+        puller: Arc<ImagePuller>,
+        store: Arc<ImageStore>,
+        ignore_failures: bool,
+        mut progress: impl FnMut(&str, &ImageProgress),
+    ) -> Result<()> {
+        let mut running = JoinSet::new();
+        for name in self.service_names(services)? {
+            let Some(image) = self.config.services[&name].image.clone() else {
+                progress(
+                    &name,
+                    &ImageProgress::Skipped("No image to be pulled".to_string()),
+                );
+                continue;
+            };
+            progress(&name, &ImageProgress::Pulling(image.clone()));
+            let (puller, store) = (puller.clone(), store.clone());
+            running.spawn(async move {
+                let result = puller.pull(&image, &store).await;
+                (name, result.map(|report| report.digest))
+            });
+        }
 
-                let context_path = match build_config {
-                    super::config::BuildConfig::Simple(path) => self.working_dir.join(path),
-                    super::config::BuildConfig::Full(full) => full
-                        .context
-                        .as_ref()
-                        .map(|p| self.working_dir.join(p))
-                        .unwrap_or_else(|| self.working_dir.clone()),
-                };
+        let mut failed = Vec::new();
+        while let Some(joined) = running.join_next().await {
+            let (name, result) = joined.map_err(|e| RuneError::Compose(e.to_string()))?;
+            match result {
+                Ok(digest) => progress(&name, &ImageProgress::Pulled(digest)),
+                Err(e) => {
+                    progress(&name, &ImageProgress::Failed(e.to_string()));
+                    if !ignore_failures && self.config.services[&name].build.is_none() {
+                        failed.push(name);
+                    }
+                }
+            }
+        }
+        check_failures("pull", failed)
+    }
 
-                let build_context = BuildContext::new(context_path)
-                    .tag(&format!("{}-{}:latest", self.project_name, name));
+    /// Push the images of services that are built and name their image, or
+    /// of all of them if none are given, in parallel
+    pub async fn push(
+        &self,
+        services: &[String],
+        puller: Arc<ImagePuller>,
+        store: Arc<ImageStore>,
+        mut progress: impl FnMut(&str, &ImageProgress),
+    ) -> Result<()> {
+        let mut running = JoinSet::new();
+        for name in self.service_names(services)? {
+            let service = &self.config.services[&name];
+            let image = match (&service.build, &service.image) {
+                (Some(_), Some(image)) => image.clone(),
+                _ => continue,
+            };
+            progress(&name, &ImageProgress::Pushing(image.clone()));
+            let (puller, store) = (puller.clone(), store.clone());
+            running.spawn(async move {
+                let result = puller.push(&image, &store).await;
+                (name, result)
+            });
+        }
 
-                let builder = ImageBuilder::new(build_context);
-                builder.build().await?;
+        let mut failed = Vec::new();
+        while let Some(joined) = running.join_next().await {
+            let (name, result) = joined.map_err(|e| RuneError::Compose(e.to_string()))?;
+            match result {
+                Ok(digest) => progress(&name, &ImageProgress::Pushed(digest)),
+                Err(e) => {
+                    progress(&name, &ImageProgress::Failed(e.to_string()));
+                    failed.push(name);
+                }
             }
         }
+        check_failures("push", failed)
+    }
 
-        Ok(())
+    /// Names of the given services, checking they exist, or of all services
+    /// if none are given; sorted
+    fn service_names(&self, services: &[String]) -> Result<Vec<String>> {
+        let mut names: Vec<String> = match services {
+            [] => self.config.services.keys().cloned().collect(),
+            services => services
+                .iter()
+                .map(|name| match self.config.services.contains_key(name) {
+                    true => Ok(name.clone()),
+                    false => Err(RuneError::ServiceNotFound(name.clone())),
+                })
+                .collect::<Result<_>>()?,
+        };
+        names.sort();
+        Ok(names)
     }
 
-    /// Get service logs
+    /// Logs of the project's containers, or of one service's, each line
+    /// prefixed with its container's name
     pub async fn logs(
         &self,
         service_name: Option<&str>,
-        _follow: bool,
-        _tail: Option<usize>,
+        tail: Option<usize>,
     ) -> Result<Vec<String>> {
-        let mut logs = Vec::new();
-
-        let services: Vec<&str> = if let Some(name) = service_name {
-            vec![name]
-        } else {
-            self.config.services.keys().map(|s| s.as_str()).collect()
-        };
+        let containers = self.containers(service_name)?;
+        let mut lines: Vec<(LogLine, &str)> = Vec::new();
+        for container in &containers {
+            let request = LogRequest {
+                container_id: container.id.clone(),
+                tail,
+                since: None,
+            };
+            for line in self.container_manager.read_logs(&request)? {
+                lines.push((line, &container.name));
+            }
+        }
+        Ok(prefixed_lines(lines, name_width(&containers)))
+    }
 
-        for service in services {
-            if let Some(state) = self.service_states.get(service) {
-                for id in &state.container_ids {
-                    // In a real implementation, we would get container logs here
-                    logs.push(format!("[{}] Container {} logs...", service, id));
+    /// Follow the logs of the project's containers, or of one service's,
+    /// passing new lines to `emit` as they are written
+    ///
+    /// Runs until an error occurs; containers created later are picked up
+    /// as they appear.
+    pub async fn follow_logs(
+        &self,
+        service_name: Option<&str>,
+        tail: Option<usize>,
+        mut emit: impl FnMut(&str),
+    ) -> Result<()> {
+        let mut cursors: HashMap<String, LogCursor> = HashMap::new();
+        let mut first = true;
+        loop {
+            let polled_at = chrono::Utc::now();
+            let containers = self.containers(service_name)?;
+            let mut lines: Vec<(LogLine, &str)> = Vec::new();
+            for container in &containers {
+                let cursor = cursors.entry(container.id.clone()).or_default();
+                let request = cursor.request(&container.id, tail.filter(|_| first));
+                let read = self.container_manager.read_logs(&request)?;
+                for line in cursor.advance(read) {
+                    lines.push((line, &container.name));
                 }
+                cursor.start_at(polled_at);
+            }
+            for line in prefixed_lines(lines, name_width(&containers)) {
+                emit(&line);
             }
-        }
 
-        Ok(logs)
+            first = false;
+            tokio::time::sleep(LOG_POLL_INTERVAL).await;
+        }
     }
 
     /// Get project status
@@ -352,11 +702,9 @@ impl ComposeOrchestrator {
         service_name: &str,
         service: &ServiceConfig,
         container_name: &str,
+        number: u32,
     ) -> Result<ContainerConfig> {
-        let image = service
-            .image
-            .clone()
-            .unwrap_or_else(|| format!("{}-{}:latest", self.project_name, service_name));
+        let image = self.service_image(service_name, service);
 
         let mut config = ContainerConfig::new(container_name, &image);
 
@@ -418,20 +766,150 @@ impl ComposeOrchestrator {
             config.privileged = privileged;
         }
 
+        // Set capabilities
+        if let Some(ref cap_add) = service.cap_add {
+            config.cap_add = cap_add.clone();
+        }
+        if let Some(ref cap_drop) = service.cap_drop {
+            config.cap_drop = cap_drop.clone();
+        }
+
+        // Set security options, reading seccomp profiles from the project
+        if let Some(ref security_opt) = service.security_opt {
+            config.security_opt = read_seccomp_profiles(security_opt, &self.working_dir)?;
+        }
+        if let Some(ref userns_mode) = service.userns_mode {
+            config.userns_mode = userns_mode.clone();
+        }
+        config.init = service.init.unwrap_or(false);
+        for (name, limit) in service.ulimits.iter().flatten() {
+            let (soft, hard) = match limit {
+                UlimitConfig::Single(limit) => (*limit, *limit),
+                UlimitConfig::SoftHard { soft, hard } => (*soft, *hard),
+            };
+            config.ulimits.push(Ulimit {
+                name: name.clone(),
+                soft,
+                hard,
+            });
+        }
+        config.sysctls = service.sysctls.clone().unwrap_or_default();
+        config.oom_kill_disable = service.oom_kill_disable.unwrap_or(false);
+        config.oom_score_adj = service.oom_score_adj;
+        for device in service.devices.iter().flatten() {
+            config.add_device(device)?;
+        }
+        // GPUs are reserved as devices with the gpu capability
+        let gpu = service
+            .deploy
+            .as_ref()
+            .and_then(|deploy| deploy.resources.as_ref())
+            .and_then(|resources| resources.reservations.as_ref())
+            .and_then(|reservations| reservations.devices.as_ref())
+            .into_iter()
+            .flatten()
+            .find(|device| {
+                device
+                    .capabilities
+                    .iter()
+                    .flatten()
+                    .any(|capability| capability == "gpu")
+            });
+        if let Some(gpu) = gpu {
+            config.gpus = Some(match (&gpu.device_ids, gpu.count) {
+                (Some(ids), _) => GpuRequest::Devices(ids.clone()),
+                (None, Some(count)) if count >= 0 => GpuRequest::Count(count as usize),
+                _ => GpuRequest::All,
+            });
+        }
+
+        // Set healthcheck
+        if let Some(ref healthcheck) = service.healthcheck {
+            config.healthcheck = Some(container_healthcheck(healthcheck)?);
+        }
+
         // Add labels
-        config.labels.insert(
-            "com.docker.compose.project".to_string(),
-            self.project_name.clone(),
-        );
-        config.labels.insert(
-            "com.docker.compose.service".to_string(),
-            service_name.to_string(),
-        );
+        config
+            .labels
+            .insert(PROJECT_LABEL.to_string(), self.project_name.clone());
+        config
+            .labels
+            .insert(SERVICE_LABEL.to_string(), service_name.to_string());
+        config
+            .labels
+            .insert(CONTAINER_NUMBER_LABEL.to_string(), number.to_string());
+        config
+            .labels
+            .insert(ONEOFF_LABEL.to_string(), "False".to_string());
 
         Ok(config)
     }
 }
 
+/// An error naming the services whose images failed to pull or push
+fn check_failures(action: &str, mut failed: Vec<String>) -> Result<()> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    failed.sort();
+    Err(RuneError::Compose(format!(
+        "Failed to {} the images of {}",
+        action,
+        failed.join(", ")
+    )))
+}
+
+/// Width of the widest container name, which log lines are padded to
+fn name_width(containers: &[ContainerConfig]) -> usize {
+    containers.iter().map(|c| c.name.len()).max().unwrap_or(0)
+}
+
+/// Log lines in the order they were written, each prefixed with the name
+/// of its container
+fn prefixed_lines(mut lines: Vec<(LogLine, &str)>, width: usize) -> Vec<String> {
+    lines.sort_by_key(|(line, _)| line.timestamp);
+    lines
+        .into_iter()
+        .map(|(line, name)| format!("{:<width$} | {}", name, line.message, width = width))
+        .collect()
+}
+
+/// Number of a container among its service's replicas, from its label
+fn container_number(container: &ContainerConfig) -> Option<u32> {
+    container
+        .labels
+        .get(CONTAINER_NUMBER_LABEL)
+        .and_then(|number| number.parse().ok())
+}
+
+/// Container healthcheck of a service's `healthcheck`
+fn container_healthcheck(
+    healthcheck: &HealthcheckConfig,
+) -> Result<crate::container::HealthcheckConfig> {
+    use crate::swarm::stack::parse_duration;
+
+    let test = if healthcheck.disable.unwrap_or(false) {
+        vec!["NONE".to_string()]
+    } else {
+        match &healthcheck.test {
+            Some(HealthcheckTest::Command(cmd)) => vec!["CMD-SHELL".to_string(), cmd.clone()],
+            Some(HealthcheckTest::Array(arr)) => arr.clone(),
+            None => Vec::new(),
+        }
+    };
+    let duration = |value: &Option<String>| -> Result<i64> {
+        value.as_deref().map(parse_duration).unwrap_or(Ok(0))
+    };
+
+    Ok(crate::container::HealthcheckConfig {
+        test,
+        interval: duration(&healthcheck.interval)?,
+        timeout: duration(&healthcheck.timeout)?,
+        start_period: duration(&healthcheck.start_period)?,
+        retries: healthcheck.retries.unwrap_or(0),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -471,6 +949,150 @@ services:
         assert!(api_pos < web_pos);
     }
 
+    #[test]
+    fn test_service_healthcheck() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres
+    healthcheck:
+      test: pg_isready
+      interval: 5s
+      retries: 5
+"#;
+
+        let config = ComposeParser::parse_str(yaml).unwrap();
+        let temp = tempdir().unwrap();
+        let manager = Arc::new(ContainerManager::new(temp.path().to_path_buf()).unwrap());
+        let orchestrator =
+            ComposeOrchestrator::new("test", config.clone(), manager, temp.path().to_path_buf());
+
+        let service = &config.services["db"];
+        let container = orchestrator
+            .service_to_container_config("db", service, "test-db-1", 1)
+            .unwrap();
+        let healthcheck = container.healthcheck.unwrap();
+        assert_eq!(healthcheck.test, ["CMD-SHELL", "pg_isready"]);
+        assert_eq!(healthcheck.interval, 5_000_000_000);
+        assert_eq!(healthcheck.retries, 5);
+    }
+
+    #[tokio::test]
+    async fn test_labels_and_orphans() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    deploy:
+      replicas: 2
+  worker:
+    image: busybox
+"#;
+        let temp = tempdir().unwrap();
+        let manager = Arc::new(ContainerManager::new(temp.path().to_path_buf()).unwrap());
+        let mut orchestrator = ComposeOrchestrator::new(
+            "shop",
+            ComposeParser::parse_str(yaml).unwrap(),
+            manager.clone(),
+            temp.path().to_path_buf(),
+        );
+        orchestrator.up(true, false, false).await.unwrap();
+
+        let containers = orchestrator.containers(None).unwrap();
+        let names: Vec<&str> = containers.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["shop-web-1", "shop-web-2", "shop-worker-1"]);
+        assert_eq!(containers[1].labels[PROJECT_LABEL], "shop");
+        assert_eq!(containers[1].labels[SERVICE_LABEL], "web");
+        assert_eq!(containers[1].labels[CONTAINER_NUMBER_LABEL], "2");
+
+        // Starting again finds the containers instead of creating more
+        orchestrator.up(true, false, false).await.unwrap();
+        assert_eq!(orchestrator.containers(None).unwrap().len(), 3);
+
+        manager
+            .write_log(&containers[2].id, "stdout", "working")
+            .unwrap();
+        assert_eq!(
+            orchestrator.logs(Some("worker"), None).await.unwrap(),
+            ["shop-worker-1 | working"]
+        );
+        let mut followed = Vec::new();
+        let follow =
+            orchestrator.follow_logs(Some("worker"), None, |line| followed.push(line.to_string()));
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), follow)
+                .await
+                .is_err()
+        );
+        assert_eq!(followed, ["shop-worker-1 | working"]);
+
+        // Without the worker service, its container is an orphan
+        let yaml = "services:\n  web:\n    image: nginx\n";
+        let mut orchestrator = ComposeOrchestrator::new(
+            "shop",
+            ComposeParser::parse_str(yaml).unwrap(),
+            manager.clone(),
+            temp.path().to_path_buf(),
+        );
+        let orphans = orchestrator.orphans().unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].name, "shop-worker-1");
+
+        orchestrator.down(false, false).await.unwrap();
+        assert_eq!(orchestrator.containers(None).unwrap().len(), 1);
+        orchestrator.up(true, false, true).await.unwrap();
+        let names: Vec<String> = orchestrator
+            .containers(None)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["shop-web-1"]);
+        orchestrator.down(false, true).await.unwrap();
+        assert!(orchestrator.containers(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_build_context() {
+        let yaml = r#"
+services:
+  api:
+    build:
+      context: ./api
+      dockerfile: Dockerfile.dev
+      args:
+        VERSION: "1.2"
+      target: runtime
+      cache_from:
+        - registry.example.com/api:cache
+  docs:
+    image: docs:edge
+    build: https://github.com/example/docs.git#main:site
+"#;
+        let config = ComposeParser::parse_str(yaml).unwrap();
+        let temp = tempdir().unwrap();
+        let manager = Arc::new(ContainerManager::new(temp.path().to_path_buf()).unwrap());
+        let orchestrator =
+            ComposeOrchestrator::new("shop", config.clone(), manager, temp.path().to_path_buf());
+
+        let api = &config.services["api"];
+        let context = orchestrator.build_context("api", api, api.build.as_ref().unwrap());
+        assert_eq!(context.context_dir, temp.path().join("./api"));
+        assert_eq!(
+            context.build_file,
+            temp.path().join("./api").join("Dockerfile.dev")
+        );
+        assert_eq!(context.build_args["VERSION"], "1.2");
+        assert_eq!(context.target.as_deref(), Some("runtime"));
+        assert_eq!(context.cache_from, ["registry.example.com/api:cache"]);
+        assert_eq!(context.tags, ["shop-api:latest"]);
+
+        let docs = &config.services["docs"];
+        let context = orchestrator.build_context("docs", docs, docs.build.as_ref().unwrap());
+        assert!(matches!(context.remote, Some(ContextSource::Git { .. })));
+        assert_eq!(context.tags, ["docs:edge"]);
+    }
+
     #[test]
     fn test_circular_dependency_detection() {
         let yaml = r#"
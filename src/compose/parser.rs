@@ -1,8 +1,26 @@
 //! Docker Compose file parser
-
-use super::config::ComposeConfig;
+//!
+//! Reading a compose file interpolates variables into it, from the
+//! environment and the `.env` file next to it, and checks it against the
+//! compose spec's [schema](super::schema) before it becomes a
+//! [`ComposeConfig`]. Normalizing a configuration turns short syntaxes into
+//! the long ones they stand for, as `compose config` shows them.
+
+use super::config::{
+    BindOptions, BuildConfig, BuildConfigFull, ComposeConfig, DependsOnCondition, DependsOnConfig,
+    EnvFileConfig, EnvironmentConfig, LabelsConfig, NetworksConfig, PortConfig, PortConfigLong,
+    ServiceConfig, ServiceNetworkConfig, VolumeMount, VolumeMountLong, VolumeOptions,
+};
+use super::schema;
 use crate::error::{Result, RuneError};
-use std::path::Path;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// File variables are read from besides the environment
+pub const ENV_FILE: &str = ".env";
 
 /// Default compose file names
 pub const DEFAULT_COMPOSE_FILES: &[&str] = &[
@@ -27,19 +45,68 @@ impl ComposeParser {
         None
     }
 
-    /// Parse compose file from path
+    /// Parse compose file from path, interpolating variables from the
+    /// environment and the `.env` file next to it
     pub fn parse_file(path: &Path) -> Result<ComposeConfig> {
         let content = std::fs::read_to_string(path)
             .map_err(|e| RuneError::ComposeParse(format!("Failed to read file: {}", e)))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
 
-        Self::parse_str(&content)
+        Self::parse_with_env(&content, &Self::environment(dir)?)
     }
 
-    /// Parse compose file from string
+    /// Parse compose file from string, interpolating variables from the
+    /// environment
     pub fn parse_str(content: &str) -> Result<ComposeConfig> {
-        // Try YAML first
-        serde_yaml::from_str(content)
-            .map_err(|e| RuneError::ComposeParse(format!("Failed to parse YAML: {}", e)))
+        Self::parse_with_env(content, &std::env::vars().collect())
+    }
+
+    /// Parse compose file from string, interpolating variables from `env`,
+    /// and check it against the compose spec's schema
+    pub fn parse_with_env(content: &str, env: &HashMap<String, String>) -> Result<ComposeConfig> {
+        let mut value: Value = serde_yaml::from_str(content)
+            .map_err(|e| RuneError::ComposeParse(format!("Failed to parse YAML: {}", e)))?;
+        if value.is_null() {
+            value = Value::Mapping(Mapping::new());
+        }
+        value
+            .apply_merge()
+            .map_err(|e| RuneError::ComposeParse(format!("Failed to parse YAML: {}", e)))?;
+        Self::interpolate(&mut value, env)?;
+
+        let errors = schema::validate(&mut value);
+        if !errors.is_empty() {
+            return Err(RuneError::ComposeParse(format!(
+                "Invalid compose file:\n  {}",
+                errors.join("\n  ")
+            )));
+        }
+        // `networks: {front:}` declares a network with the defaults
+        for section in ["networks", "volumes", "secrets", "configs"] {
+            if let Some(Value::Mapping(map)) = value.get_mut(section) {
+                for (_, definition) in map.iter_mut().filter(|(_, d)| d.is_null()) {
+                    *definition = Value::Mapping(Mapping::new());
+                }
+            }
+        }
+
+        serde_yaml::from_value(value)
+            .map_err(|e| RuneError::ComposeParse(format!("Invalid compose file: {}", e)))
+    }
+
+    /// Variables compose files in `dir` are interpolated with: those of its
+    /// `.env` file, overridden by the environment's
+    pub fn environment(dir: &Path) -> Result<HashMap<String, String>> {
+        let mut env = HashMap::new();
+        let path = dir.join(ENV_FILE);
+        if path.is_file() {
+            let content = std::fs::read_to_string(&path).map_err(|e| {
+                RuneError::ComposeParse(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            env.extend(parse_env_file(&content));
+        }
+        env.extend(std::env::vars());
+        Ok(env)
     }
 
     /// Parse multiple compose files (with merging)
@@ -180,60 +247,403 @@ impl ComposeParser {
         Ok(warnings)
     }
 
-    /// Interpolate environment variables in config
-    pub fn interpolate(
-        config: &mut ComposeConfig,
-        env: &std::collections::HashMap<String, String>,
-    ) {
-        // This is a simplified version - full implementation would recursively
-        // interpolate all string fields
+    /// Interpolate `$VAR`, `${VAR}`, `${VAR:-default}`, `${VAR:+value}`
+    /// and `${VAR:?error}` into every string of a compose file; `$$` is a
+    /// literal `$`
+    pub fn interpolate(value: &mut Value, env: &HashMap<String, String>) -> Result<()> {
+        match value {
+            Value::String(s) => *s = interpolate_string(s, env)?,
+            Value::Sequence(items) => {
+                for item in items {
+                    Self::interpolate(item, env)?;
+                }
+            }
+            Value::Mapping(map) => {
+                for (_, item) in map.iter_mut() {
+                    Self::interpolate(item, env)?;
+                }
+            }
+            Value::Tagged(tagged) => Self::interpolate(&mut tagged.value, env)?,
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+        Ok(())
+    }
 
+    /// Turn short syntaxes into the long ones they stand for, and make
+    /// relative paths absolute, relative to `working_dir`
+    pub fn normalize(config: &mut ComposeConfig, working_dir: &Path) -> Result<()> {
         for service in config.services.values_mut() {
-            // Interpolate image
-            if let Some(ref mut image) = service.image {
-                *image = interpolate_string(image, env);
-            }
+            normalize_service(service, working_dir)?;
+        }
+        for network in config.networks.values_mut() {
+            normalize_labels(&mut network.labels);
+        }
+        for volume in config.volumes.values_mut() {
+            normalize_labels(&mut volume.labels);
+        }
+        Ok(())
+    }
 
-            // Interpolate environment
-            if let Some(ref mut environment) = service.environment {
-                match environment {
-                    super::config::EnvironmentConfig::Map(map) => {
-                        for value in map.values_mut().flatten() {
-                            *value = interpolate_string(value, env);
-                        }
-                    }
-                    super::config::EnvironmentConfig::Array(arr) => {
-                        for item in arr.iter_mut() {
-                            *item = interpolate_string(item, env);
-                        }
-                    }
-                }
+    /// Compose file of a configuration, its keys sorted and unset
+    /// properties left out
+    pub fn to_yaml(config: &ComposeConfig) -> Result<String> {
+        serde_yaml::to_string(&clean_value(config)?)
+            .map_err(|e| RuneError::Compose(format!("Failed to write compose file: {}", e)))
+    }
+
+    /// Hash of a service's configuration, which changes whenever the
+    /// configuration does; normalize the configuration first, so the
+    /// syntax it is written in makes no difference
+    pub fn service_hash(service: &ServiceConfig) -> Result<String> {
+        let json = clean_value(service)?.to_string();
+        Ok(format!("{:x}", Sha256::digest(json.as_bytes())))
+    }
+}
+
+/// Variables of an env file: `KEY=value` lines, optionally quoted or
+/// `export`ed, with `#` comments
+fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value
+                    .strip_prefix(quote)
+                    .and_then(|v| v.rfind(quote).map(|end| &v[..end]))
+                    .unwrap_or(value),
+                _ => value.split(" #").next().unwrap_or(value).trim_end(),
+            };
+            Some((key.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Interpolate variables into a string
+fn interpolate_string(s: &str, env: &HashMap<String, String>) -> Result<String> {
+    let invalid = || RuneError::ComposeParse(format!("Invalid interpolation format for {:?}", s));
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = closing_brace(after).ok_or_else(invalid)?;
+            result.push_str(&substitute(&after[..end], env).ok_or_else(invalid)??);
+            rest = &after[end + 1..];
+        } else {
+            let len = variable_name_len(rest);
+            if len == 0 {
+                result.push('$');
+                continue;
             }
+            result.push_str(&lookup(&rest[..len], env));
+            rest = &rest[len..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Index of the `}` closing a `${`, with nested `${...}` in defaults
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
         }
     }
+    None
 }
 
-/// Interpolate environment variables in a string
-fn interpolate_string(s: &str, env: &std::collections::HashMap<String, String>) -> String {
-    let mut result = s.to_string();
+/// Length of the variable name a string starts with
+fn variable_name_len(s: &str) -> usize {
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(s.len())
+}
+
+/// Value of a variable, blank if it is unset
+fn lookup(name: &str, env: &HashMap<String, String>) -> String {
+    env.get(name).cloned().unwrap_or_else(|| {
+        tracing::warn!(
+            "The \"{}\" variable is not set. Defaulting to a blank string.",
+            name
+        );
+        String::new()
+    })
+}
 
-    // Handle ${VAR} and $VAR syntax
-    for (key, value) in env {
-        result = result.replace(&format!("${{{}}}", key), value);
-        result = result.replace(&format!("${}", key), value);
+/// Value of what is inside `${...}`; `None` if it is malformed
+fn substitute(expr: &str, env: &HashMap<String, String>) -> Option<Result<String>> {
+    let len = variable_name_len(expr);
+    if len == 0 {
+        return None;
     }
+    let (name, modifier) = expr.split_at(len);
+    let value = env.get(name);
+    // With a colon, an empty variable counts as unset
+    let (modifier, set) = match modifier.strip_prefix(':') {
+        Some(modifier) => (modifier, value.is_some_and(|v| !v.is_empty())),
+        None => (modifier, value.is_some()),
+    };
+    let (op, operand) = match modifier.chars().next() {
+        Some(op) => (Some(op), &modifier[op.len_utf8()..]),
+        None => (None, ""),
+    };
+    Some(match (op, set) {
+        (None, _) if expr.len() == len => Ok(lookup(name, env)),
+        (Some('-'), false) => interpolate_string(operand, env),
+        (Some('-' | '?'), true) => Ok(value.cloned().unwrap_or_default()),
+        (Some('+'), true) => interpolate_string(operand, env),
+        (Some('+'), false) => Ok(String::new()),
+        (Some('?'), false) => Err(RuneError::ComposeParse(format!(
+            "Required variable {} is missing a value: {}",
+            name, operand
+        ))),
+        _ => return None,
+    })
+}
 
-    // Handle ${VAR:-default} syntax
-    let re = regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*):-([^}]*)\}").unwrap();
-    result = re
-        .replace_all(&result, |caps: &regex::Captures| {
-            let var = &caps[1];
-            let default = &caps[2];
-            env.get(var).cloned().unwrap_or_else(|| default.to_string())
+/// Normalize a service's short syntaxes
+fn normalize_service(service: &mut ServiceConfig, working_dir: &Path) -> Result<()> {
+    if let Some(build) = service.build.take() {
+        let mut full = match build {
+            BuildConfig::Simple(context) => BuildConfigFull {
+                context: Some(context),
+                ..Default::default()
+            },
+            BuildConfig::Full(full) => full,
+        };
+        let context = full.context.as_deref().unwrap_or(".");
+        full.context = Some(absolute(working_dir, context).display().to_string());
+        service.build = Some(BuildConfig::Full(full));
+    }
+    if let Some(EnvironmentConfig::Array(vars)) = &service.environment {
+        let map = vars
+            .iter()
+            .map(|var| match var.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (var.clone(), None),
+            })
+            .collect();
+        service.environment = Some(EnvironmentConfig::Map(map));
+    }
+    if let Some(EnvFileConfig::Single(file)) = &service.env_file {
+        service.env_file = Some(EnvFileConfig::Multiple(vec![file.clone()]));
+    }
+    if let Some(ports) = &service.ports {
+        let mut long = Vec::new();
+        for port in ports {
+            long.extend(normalize_port(port)?.into_iter().map(PortConfig::Long));
+        }
+        service.ports = Some(long);
+    }
+    if let Some(volumes) = &service.volumes {
+        let long = volumes
+            .iter()
+            .map(|volume| normalize_volume(volume, working_dir).map(VolumeMount::Long))
+            .collect::<Result<Vec<_>>>()?;
+        service.volumes = Some(long);
+    }
+    if let Some(NetworksConfig::Array(networks)) = &service.networks {
+        let map = networks
+            .iter()
+            .map(|network| (network.clone(), None))
+            .collect();
+        service.networks = Some(NetworksConfig::Map(map));
+    }
+    if let Some(NetworksConfig::Map(networks)) = &mut service.networks {
+        for config in networks.values_mut() {
+            config.get_or_insert_with(ServiceNetworkConfig::default);
+        }
+    }
+    if let Some(DependsOnConfig::Array(services)) = &service.depends_on {
+        let map = services
+            .iter()
+            .map(|name| {
+                (
+                    name.clone(),
+                    DependsOnCondition {
+                        condition: "service_started".to_string(),
+                    },
+                )
+            })
+            .collect();
+        service.depends_on = Some(DependsOnConfig::Map(map));
+    }
+    normalize_labels(&mut service.labels);
+    if let Some(deploy) = &mut service.deploy {
+        normalize_labels(&mut deploy.labels);
+    }
+    Ok(())
+}
+
+/// Labels as a map
+fn normalize_labels(labels: &mut Option<LabelsConfig>) {
+    if let Some(LabelsConfig::Array(list)) = labels {
+        let map = list
+            .iter()
+            .map(|label| match label.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (label.clone(), String::new()),
+            })
+            .collect();
+        *labels = Some(LabelsConfig::Map(map));
+    }
+}
+
+/// Long syntax of a port; a short port range stands for several
+fn normalize_port(port: &PortConfig) -> Result<Vec<PortConfigLong>> {
+    let spec = match port {
+        PortConfig::Long(long) => {
+            let mut long = long.clone();
+            long.protocol.get_or_insert_with(|| "tcp".to_string());
+            long.mode.get_or_insert_with(|| "ingress".to_string());
+            return Ok(vec![long]);
+        }
+        PortConfig::Short(spec) => spec,
+    };
+    let invalid = || RuneError::ComposeParse(format!("Invalid port specification: {}", spec));
+    let (ports, protocol) = spec.rsplit_once('/').unwrap_or((spec, "tcp"));
+    let mut parts = ports.rsplitn(3, ':');
+    let target = parts.next().ok_or_else(invalid)?;
+    let published = parts.next().filter(|p| !p.is_empty());
+    let host_ip = parts
+        .next()
+        .map(|ip| ip.trim_start_matches('[').trim_end_matches(']').to_string());
+
+    let targets = port_range(target).ok_or_else(invalid)?;
+    let published: Vec<Option<String>> = match published {
+        None => vec![None; targets.len()],
+        Some(published) => {
+            let range = port_range(published).ok_or_else(invalid)?;
+            match (range.len(), targets.len()) {
+                (p, t) if p == t => range.iter().map(|p| Some(p.to_string())).collect(),
+                // A range to publish a single port on
+                (_, 1) => vec![Some(published.to_string())],
+                _ => return Err(invalid()),
+            }
+        }
+    };
+    Ok(targets
+        .into_iter()
+        .zip(published)
+        .map(|(target, published)| PortConfigLong {
+            target,
+            published,
+            host_ip: host_ip.clone(),
+            protocol: Some(protocol.to_string()),
+            mode: Some("ingress".to_string()),
         })
-        .to_string();
+        .collect())
+}
 
-    result
+/// Ports of `port` or `start-end`
+fn port_range(s: &str) -> Option<Vec<u16>> {
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let (start, end): (u16, u16) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end).then(|| (start..=end).collect())
+        }
+        None => s.parse().ok().map(|port| vec![port]),
+    }
+}
+
+/// Long syntax of a volume: `[source:]target[:mode]`, where a source that
+/// is a path makes a bind mount
+fn normalize_volume(volume: &VolumeMount, working_dir: &Path) -> Result<VolumeMountLong> {
+    let spec = match volume {
+        VolumeMount::Long(long) => {
+            let mut long = long.clone();
+            if long.mount_type.as_deref() == Some("bind") {
+                long.source = long
+                    .source
+                    .map(|source| absolute(working_dir, &source).display().to_string());
+            }
+            return Ok(long);
+        }
+        VolumeMount::Short(spec) => spec,
+    };
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (source, target, mode) = match parts.as_slice() {
+        [target] => (None, *target, ""),
+        [source, target] => (Some(*source), *target, ""),
+        [source, target, mode] => (Some(*source), *target, *mode),
+        _ => {
+            return Err(RuneError::ComposeParse(format!(
+                "Invalid volume specification: {}",
+                spec
+            )))
+        }
+    };
+    let options: Vec<&str> = mode.split(',').filter(|o| !o.is_empty()).collect();
+    let bind = source.is_some_and(|source| source.starts_with(['/', '.', '~']));
+    Ok(VolumeMountLong {
+        mount_type: Some(if bind { "bind" } else { "volume" }.to_string()),
+        source: source.map(|source| match bind {
+            true => absolute(working_dir, source).display().to_string(),
+            false => source.to_string(),
+        }),
+        target: target.to_string(),
+        read_only: options.contains(&"ro").then_some(true),
+        bind: bind.then(|| BindOptions {
+            propagation: options
+                .iter()
+                .find(|o| o.ends_with("shared") || o.ends_with("slave") || o.ends_with("private"))
+                .map(|o| o.to_string()),
+            create_host_path: Some(true),
+            selinux: options
+                .iter()
+                .find(|o| **o == "z" || **o == "Z")
+                .map(|o| o.to_string()),
+        }),
+        volume: (!bind && options.contains(&"nocopy"))
+            .then_some(VolumeOptions { nocopy: Some(true) }),
+        ..Default::default()
+    })
+}
+
+/// A path relative to `working_dir`, or to the home directory for `~`
+fn absolute(working_dir: &Path, path: &str) -> PathBuf {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => working_dir.join(path),
+    };
+    // Leave out `.` components, as in `./data`
+    path.components()
+        .filter(|c| !matches!(c, std::path::Component::CurDir))
+        .collect()
+}
+
+/// JSON value of a configuration with its unset properties left out; the
+/// keys of its maps come out sorted
+fn clean_value<T: Serialize>(config: &T) -> Result<serde_json::Value> {
+    fn clean(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|_, v| !v.is_null());
+                map.values_mut().for_each(clean);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(clean),
+            _ => {}
+        }
+    }
+    let mut value = serde_json::to_value(config)?;
+    clean(&mut value);
+    Ok(value)
 }
 
 #[cfg(test)]
@@ -283,7 +693,107 @@ services:
         env.insert("TAG".to_string(), "1.0.0".to_string());
 
         let s = "nginx:${TAG}";
-        let result = interpolate_string(s, &env);
+        let result = interpolate_string(s, &env).unwrap();
         assert_eq!(result, "nginx:1.0.0");
+
+        env.insert("EMPTY".to_string(), String::new());
+        let interpolate = |s: &str| interpolate_string(s, &env);
+        assert_eq!(interpolate("$TAG-${MISSING}").unwrap(), "1.0.0-");
+        assert_eq!(interpolate("${EMPTY:-a}/${EMPTY-b}").unwrap(), "a/");
+        assert_eq!(interpolate("${MISSING:-${TAG}}").unwrap(), "1.0.0");
+        assert_eq!(interpolate("${TAG:+set}${EMPTY:+set}").unwrap(), "set");
+        assert_eq!(interpolate("$$TAG costs $$5").unwrap(), "$TAG costs $5");
+        assert!(interpolate("${MISSING:?is required}")
+            .unwrap_err()
+            .to_string()
+            .contains("is required"));
+        assert!(interpolate("${TAG").is_err());
+    }
+
+    #[test]
+    fn test_parse_with_env_and_schema() {
+        let env = HashMap::from([("REPLICAS".to_string(), "3".to_string())]);
+        let yaml = r#"
+x-base: &base
+  restart: always
+services:
+  web:
+    <<: *base
+    image: nginx
+    environment:
+      PORT: 8080
+    deploy:
+      replicas: ${REPLICAS}
+networks:
+  front:
+"#;
+        let config = ComposeParser::parse_with_env(yaml, &env).unwrap();
+        let web = &config.services["web"];
+        assert_eq!(web.restart.as_deref(), Some("always"));
+        assert_eq!(web.deploy.as_ref().unwrap().replicas, Some(3));
+        assert!(config.networks.contains_key("front"));
+
+        let error = ComposeParser::parse_with_env("services: {web: {imag: x}}", &env)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("services.web: additional property imag is not allowed"));
+
+        let file = parse_env_file("# comment\nexport A=1\nB=\"two words\"\nC=3 # note\n");
+        assert_eq!(
+            file,
+            [
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two words".to_string()),
+                ("C".to_string(), "3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_and_hash() {
+        let short = r#"
+services:
+  web:
+    build: ./web
+    ports: ["127.0.0.1:8080:80", "9000-9001:9000-9001/udp"]
+    volumes: ["./data:/data:ro", "cache:/cache"]
+    environment: [A=1]
+    networks: [front]
+    depends_on: [db]
+  db:
+    image: postgres
+"#;
+        let long = r#"
+services:
+  web:
+    build:
+      context: web
+    ports:
+      - {target: 80, published: "8080", host_ip: 127.0.0.1}
+      - {target: 9000, published: "9000", protocol: udp}
+      - {target: 9001, published: "9001", protocol: udp}
+    volumes:
+      - {type: bind, source: ./data, target: /data, read_only: true,
+         bind: {create_host_path: true}}
+      - {type: volume, source: cache, target: /cache}
+    environment: {A: "1"}
+    networks: {front: {}}
+    depends_on: {db: {condition: service_started}}
+  db:
+    image: postgres
+"#;
+        let dir = Path::new("/srv/app");
+        let mut hashes = Vec::new();
+        for yaml in [short, long] {
+            let mut config = ComposeParser::parse_str(yaml).unwrap();
+            ComposeParser::normalize(&mut config, dir).unwrap();
+            hashes.push(ComposeParser::service_hash(&config.services["web"]).unwrap());
+            let yaml = ComposeParser::to_yaml(&config).unwrap();
+            assert!(yaml.contains("context: /srv/app/web"));
+            assert!(yaml.contains("source: /srv/app/data"));
+            assert!(!yaml.contains("null"));
+        }
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(hashes[0].len(), 64);
     }
 }
@@ -0,0 +1,667 @@
+//! Compose file schema
+//!
+//! The structure of the compose specification: which properties each part
+//! of a compose file may have, and of what types. Checking a file against
+//! it reports every misspelled property and wrongly typed value at once,
+//! before the file is read into a [`ComposeConfig`](super::ComposeConfig).
+//!
+//! Interpolation leaves every value it touched a string, so a string where
+//! the schema wants a number or a boolean is converted when it parses as
+//! one, as Docker Compose does. The other way around, sizes, ports and
+//! environment values written as numbers become the strings they are read
+//! as.
+
+use serde_yaml::{Mapping, Value};
+
+/// What a value of a compose file may be
+#[derive(Debug, Clone, Copy)]
+enum Schema {
+    /// Anything
+    Any,
+    /// Null
+    Null,
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// Sizes, durations and the like: a string or a number, which becomes a
+    /// string
+    StringOrNumber,
+    /// `KEY=value` strings, or a map of keys to scalars, which become
+    /// strings
+    ListOrDict,
+    /// List of values of a schema
+    List(&'static Schema),
+    /// Map of any keys to values of a schema
+    Map(&'static Schema),
+    /// Object with these properties; `x-` extensions are allowed besides
+    Object(&'static [(&'static str, Schema)]),
+    /// The first of several schemas the value matches
+    OneOf(&'static [Schema]),
+}
+
+impl Schema {
+    /// What the schema wants, for errors
+    fn describe(&self) -> String {
+        match self {
+            Schema::Any => "anything".to_string(),
+            Schema::Null => "null".to_string(),
+            Schema::String => "a string".to_string(),
+            Schema::Integer => "an integer".to_string(),
+            Schema::Number => "a number".to_string(),
+            Schema::Boolean => "a boolean".to_string(),
+            Schema::StringOrNumber => "a string or number".to_string(),
+            Schema::ListOrDict => "a list or mapping".to_string(),
+            Schema::List(_) => "a list".to_string(),
+            Schema::Map(_) | Schema::Object(_) => "a mapping".to_string(),
+            Schema::OneOf(schemas) => {
+                let kinds: Vec<String> = schemas.iter().map(Schema::describe).collect();
+                kinds.join(" or ")
+            }
+        }
+    }
+}
+
+const STRINGS: Schema = Schema::List(&Schema::String);
+
+/// A string, or a list of strings
+const STRING_OR_LIST: Schema = Schema::OneOf(&[Schema::String, STRINGS]);
+
+/// A command, in the shell or the exec form
+const COMMAND: Schema = Schema::OneOf(&[Schema::Null, Schema::String, STRINGS]);
+
+const EXTERNAL: Schema =
+    Schema::OneOf(&[Schema::Boolean, Schema::Object(&[("name", Schema::String)])]);
+
+const ULIMITS: Schema = Schema::Map(&Schema::OneOf(&[
+    Schema::Integer,
+    Schema::Object(&[("soft", Schema::Integer), ("hard", Schema::Integer)]),
+]));
+
+const BUILD: Schema = Schema::OneOf(&[
+    Schema::String,
+    Schema::Object(&[
+        ("context", Schema::String),
+        ("dockerfile", Schema::String),
+        ("dockerfile_inline", Schema::String),
+        ("entitlements", STRINGS),
+        ("args", Schema::ListOrDict),
+        ("ssh", Schema::ListOrDict),
+        ("labels", Schema::ListOrDict),
+        ("cache_from", STRINGS),
+        ("cache_to", STRING_OR_LIST),
+        ("no_cache", Schema::Boolean),
+        ("additional_contexts", Schema::ListOrDict),
+        ("network", Schema::String),
+        ("pull", Schema::Boolean),
+        ("target", Schema::String),
+        ("shm_size", Schema::StringOrNumber),
+        ("extra_hosts", Schema::ListOrDict),
+        ("isolation", Schema::String),
+        ("privileged", Schema::Boolean),
+        ("secrets", Schema::List(&Schema::Any)),
+        ("tags", STRINGS),
+        ("ulimits", ULIMITS),
+        ("platforms", STRINGS),
+    ]),
+]);
+
+const HEALTHCHECK: Schema = Schema::Object(&[
+    ("disable", Schema::Boolean),
+    ("interval", Schema::String),
+    ("retries", Schema::Integer),
+    ("test", STRING_OR_LIST),
+    ("timeout", Schema::String),
+    ("start_period", Schema::String),
+    ("start_interval", Schema::String),
+]);
+
+const DEPENDS_ON: Schema = Schema::OneOf(&[
+    STRINGS,
+    Schema::Map(&Schema::Object(&[
+        ("condition", Schema::String),
+        ("restart", Schema::Boolean),
+        ("required", Schema::Boolean),
+    ])),
+]);
+
+const RESOURCES: Schema = Schema::Object(&[
+    ("cpus", Schema::StringOrNumber),
+    ("memory", Schema::String),
+    ("pids", Schema::Integer),
+    (
+        "devices",
+        Schema::List(&Schema::Object(&[
+            ("capabilities", STRINGS),
+            ("driver", Schema::String),
+            ("count", Schema::OneOf(&[Schema::Integer, Schema::String])),
+            ("device_ids", STRINGS),
+            ("options", Schema::ListOrDict),
+        ])),
+    ),
+    ("generic_resources", Schema::List(&Schema::Any)),
+]);
+
+const UPDATE_CONFIG: Schema = Schema::Object(&[
+    ("parallelism", Schema::Integer),
+    ("delay", Schema::String),
+    ("failure_action", Schema::String),
+    ("monitor", Schema::String),
+    ("max_failure_ratio", Schema::Number),
+    ("order", Schema::String),
+]);
+
+const DEPLOY: Schema = Schema::OneOf(&[
+    Schema::Null,
+    Schema::Object(&[
+        ("mode", Schema::String),
+        ("endpoint_mode", Schema::String),
+        ("replicas", Schema::Integer),
+        ("labels", Schema::ListOrDict),
+        ("rollback_config", UPDATE_CONFIG),
+        ("update_config", UPDATE_CONFIG),
+        (
+            "resources",
+            Schema::Object(&[("limits", RESOURCES), ("reservations", RESOURCES)]),
+        ),
+        (
+            "restart_policy",
+            Schema::Object(&[
+                ("condition", Schema::String),
+                ("delay", Schema::String),
+                ("max_attempts", Schema::Integer),
+                ("window", Schema::String),
+            ]),
+        ),
+        (
+            "placement",
+            Schema::Object(&[
+                ("constraints", STRINGS),
+                (
+                    "preferences",
+                    Schema::List(&Schema::Object(&[("spread", Schema::String)])),
+                ),
+                ("max_replicas_per_node", Schema::Integer),
+            ]),
+        ),
+    ]),
+]);
+
+const PORTS: Schema = Schema::List(&Schema::OneOf(&[
+    Schema::StringOrNumber,
+    Schema::Object(&[
+        ("name", Schema::String),
+        ("mode", Schema::String),
+        ("host_ip", Schema::String),
+        ("target", Schema::Integer),
+        ("published", Schema::StringOrNumber),
+        ("protocol", Schema::String),
+        ("app_protocol", Schema::String),
+    ]),
+]));
+
+const VOLUMES: Schema = Schema::List(&Schema::OneOf(&[
+    Schema::String,
+    Schema::Object(&[
+        ("type", Schema::String),
+        ("source", Schema::String),
+        ("target", Schema::String),
+        ("read_only", Schema::Boolean),
+        ("consistency", Schema::String),
+        (
+            "bind",
+            Schema::Object(&[
+                ("propagation", Schema::String),
+                ("create_host_path", Schema::Boolean),
+                ("selinux", Schema::String),
+            ]),
+        ),
+        (
+            "volume",
+            Schema::Object(&[("nocopy", Schema::Boolean), ("subpath", Schema::String)]),
+        ),
+        (
+            "tmpfs",
+            Schema::Object(&[("size", Schema::Integer), ("mode", Schema::Integer)]),
+        ),
+    ]),
+]));
+
+const SERVICE_NETWORKS: Schema = Schema::OneOf(&[
+    STRINGS,
+    Schema::Map(&Schema::OneOf(&[
+        Schema::Null,
+        Schema::Object(&[
+            ("aliases", STRINGS),
+            ("ipv4_address", Schema::String),
+            ("ipv6_address", Schema::String),
+            ("link_local_ips", STRINGS),
+            ("mac_address", Schema::String),
+            ("driver_opts", Schema::Map(&Schema::StringOrNumber)),
+            ("priority", Schema::Integer),
+            ("gw_priority", Schema::Integer),
+        ]),
+    ])),
+]);
+
+/// Secrets and configs a service uses
+const FILE_REFS: Schema = Schema::List(&Schema::OneOf(&[
+    Schema::String,
+    Schema::Object(&[
+        ("source", Schema::String),
+        ("target", Schema::String),
+        ("uid", Schema::String),
+        ("gid", Schema::String),
+        ("mode", Schema::Number),
+    ]),
+]));
+
+const LOGGING: Schema = Schema::Object(&[
+    ("driver", Schema::String),
+    (
+        "options",
+        Schema::Map(&Schema::OneOf(&[Schema::StringOrNumber, Schema::Null])),
+    ),
+]);
+
+const SERVICE: Schema = Schema::Object(&[
+    ("annotations", Schema::ListOrDict),
+    ("attach", Schema::Boolean),
+    ("build", BUILD),
+    ("blkio_config", Schema::Any),
+    ("cap_add", STRINGS),
+    ("cap_drop", STRINGS),
+    ("cgroup", Schema::String),
+    ("cgroup_parent", Schema::String),
+    ("command", COMMAND),
+    ("configs", FILE_REFS),
+    ("container_name", Schema::String),
+    ("cpu_count", Schema::Integer),
+    ("cpu_percent", Schema::Integer),
+    ("cpu_shares", Schema::StringOrNumber),
+    ("cpu_quota", Schema::StringOrNumber),
+    ("cpu_period", Schema::StringOrNumber),
+    ("cpu_rt_period", Schema::StringOrNumber),
+    ("cpu_rt_runtime", Schema::StringOrNumber),
+    ("cpus", Schema::StringOrNumber),
+    ("cpuset", Schema::String),
+    ("credential_spec", Schema::Any),
+    ("depends_on", DEPENDS_ON),
+    ("deploy", DEPLOY),
+    ("develop", Schema::Any),
+    ("device_cgroup_rules", STRINGS),
+    ("devices", Schema::List(&Schema::Any)),
+    ("dns", STRING_OR_LIST),
+    ("dns_opt", STRINGS),
+    ("dns_search", STRING_OR_LIST),
+    ("domainname", Schema::String),
+    ("entrypoint", COMMAND),
+    (
+        "env_file",
+        Schema::OneOf(&[Schema::String, Schema::List(&Schema::Any)]),
+    ),
+    ("environment", Schema::ListOrDict),
+    ("expose", Schema::List(&Schema::StringOrNumber)),
+    ("extends", Schema::OneOf(&[Schema::String, Schema::Any])),
+    ("external_links", STRINGS),
+    ("extra_hosts", Schema::ListOrDict),
+    ("gpus", Schema::Any),
+    ("group_add", Schema::List(&Schema::StringOrNumber)),
+    ("healthcheck", HEALTHCHECK),
+    ("hostname", Schema::String),
+    ("image", Schema::String),
+    ("init", Schema::Boolean),
+    ("ipc", Schema::String),
+    ("isolation", Schema::String),
+    ("label_file", STRING_OR_LIST),
+    ("labels", Schema::ListOrDict),
+    ("links", STRINGS),
+    ("logging", LOGGING),
+    ("mac_address", Schema::String),
+    ("mem_limit", Schema::StringOrNumber),
+    ("mem_reservation", Schema::StringOrNumber),
+    ("mem_swappiness", Schema::Integer),
+    ("memswap_limit", Schema::StringOrNumber),
+    ("network_mode", Schema::String),
+    ("networks", SERVICE_NETWORKS),
+    ("oom_kill_disable", Schema::Boolean),
+    ("oom_score_adj", Schema::Integer),
+    ("pid", Schema::OneOf(&[Schema::String, Schema::Null])),
+    ("pids_limit", Schema::StringOrNumber),
+    ("platform", Schema::String),
+    ("ports", PORTS),
+    ("post_start", Schema::List(&Schema::Any)),
+    ("pre_stop", Schema::List(&Schema::Any)),
+    ("privileged", Schema::Boolean),
+    ("profiles", STRINGS),
+    ("pull_policy", Schema::String),
+    ("read_only", Schema::Boolean),
+    ("restart", Schema::String),
+    ("runtime", Schema::String),
+    ("scale", Schema::Integer),
+    ("secrets", FILE_REFS),
+    ("security_opt", STRINGS),
+    ("shm_size", Schema::StringOrNumber),
+    ("stdin_open", Schema::Boolean),
+    ("stop_grace_period", Schema::String),
+    ("stop_signal", Schema::String),
+    ("storage_opt", Schema::Map(&Schema::Any)),
+    ("sysctls", Schema::ListOrDict),
+    ("tmpfs", STRING_OR_LIST),
+    ("tty", Schema::Boolean),
+    ("ulimits", ULIMITS),
+    ("user", Schema::String),
+    ("userns_mode", Schema::String),
+    ("uts", Schema::String),
+    ("volumes", VOLUMES),
+    ("volumes_from", STRINGS),
+    ("working_dir", Schema::String),
+]);
+
+const IPAM: Schema = Schema::Object(&[
+    ("driver", Schema::String),
+    (
+        "config",
+        Schema::List(&Schema::Object(&[
+            ("subnet", Schema::String),
+            ("ip_range", Schema::String),
+            ("gateway", Schema::String),
+            ("aux_addresses", Schema::Map(&Schema::String)),
+        ])),
+    ),
+    ("options", Schema::Map(&Schema::String)),
+]);
+
+const NETWORK: Schema = Schema::OneOf(&[
+    Schema::Null,
+    Schema::Object(&[
+        ("name", Schema::String),
+        ("driver", Schema::String),
+        ("driver_opts", Schema::Map(&Schema::StringOrNumber)),
+        ("ipam", IPAM),
+        ("external", EXTERNAL),
+        ("internal", Schema::Boolean),
+        ("enable_ipv4", Schema::Boolean),
+        ("enable_ipv6", Schema::Boolean),
+        ("attachable", Schema::Boolean),
+        ("labels", Schema::ListOrDict),
+    ]),
+]);
+
+const VOLUME: Schema = Schema::OneOf(&[
+    Schema::Null,
+    Schema::Object(&[
+        ("name", Schema::String),
+        ("driver", Schema::String),
+        ("driver_opts", Schema::Map(&Schema::StringOrNumber)),
+        ("external", EXTERNAL),
+        ("labels", Schema::ListOrDict),
+    ]),
+]);
+
+/// Secrets and configs of the project
+const FILE_OBJECT: Schema = Schema::Object(&[
+    ("name", Schema::String),
+    ("file", Schema::String),
+    ("environment", Schema::String),
+    ("content", Schema::String),
+    ("external", EXTERNAL),
+    ("labels", Schema::ListOrDict),
+    ("driver", Schema::String),
+    ("driver_opts", Schema::Map(&Schema::StringOrNumber)),
+    ("template_driver", Schema::String),
+]);
+
+const COMPOSE_FILE: Schema = Schema::Object(&[
+    ("version", Schema::String),
+    ("name", Schema::String),
+    ("include", Schema::List(&Schema::Any)),
+    ("services", Schema::Map(&SERVICE)),
+    ("networks", Schema::Map(&NETWORK)),
+    ("volumes", Schema::Map(&VOLUME)),
+    ("secrets", Schema::Map(&FILE_OBJECT)),
+    ("configs", Schema::Map(&FILE_OBJECT)),
+    ("models", Schema::Map(&Schema::Any)),
+]);
+
+/// Check a compose file against the schema, converting strings the schema
+/// wants as numbers or booleans; returns every violation
+pub fn validate(file: &mut Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(&COMPOSE_FILE, file, "", &mut errors);
+    errors
+}
+
+/// Check a value, reporting violations at `path`
+fn check(schema: &Schema, value: &mut Value, path: &str, errors: &mut Vec<String>) {
+    let matches = match (schema, &*value) {
+        (Schema::Any, _) => true,
+        (Schema::Null, Value::Null) => true,
+        (Schema::String, Value::String(_)) => true,
+        (Schema::Integer, Value::Number(n)) => n.is_i64() || n.is_u64(),
+        (Schema::Number, Value::Number(_)) => true,
+        (Schema::Boolean, Value::Bool(_)) => true,
+        (Schema::StringOrNumber, Value::String(_)) => true,
+        (Schema::StringOrNumber, Value::Number(n)) => {
+            *value = Value::String(n.to_string());
+            true
+        }
+        (Schema::Integer | Schema::Number | Schema::Boolean, Value::String(s)) => {
+            match convert(schema, s) {
+                Some(converted) => {
+                    *value = converted;
+                    true
+                }
+                None => false,
+            }
+        }
+        (Schema::ListOrDict, Value::Sequence(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                if !item.is_string() {
+                    errors.push(format!("{}.{} must be a string", path, i));
+                }
+            }
+            true
+        }
+        (Schema::ListOrDict, Value::Mapping(_)) => {
+            if let Value::Mapping(map) = value {
+                for (key, item) in map.iter_mut() {
+                    match item {
+                        Value::Number(n) => *item = Value::String(n.to_string()),
+                        Value::Bool(b) => *item = Value::String(b.to_string()),
+                        Value::Sequence(_) | Value::Mapping(_) => errors.push(format!(
+                            "{} must be a string, number, boolean or null",
+                            join(path, key)
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+            true
+        }
+        (Schema::List(item), Value::Sequence(_)) => {
+            if let Value::Sequence(items) = value {
+                for (i, value) in items.iter_mut().enumerate() {
+                    check(item, value, &format!("{}.{}", path, i), errors);
+                }
+            }
+            true
+        }
+        (Schema::Map(item), Value::Mapping(_)) => {
+            if let Value::Mapping(map) = value {
+                for (key, value) in map.iter_mut() {
+                    check(item, value, &join(path, key), errors);
+                }
+            }
+            true
+        }
+        (Schema::Object(properties), Value::Mapping(_)) => {
+            if let Value::Mapping(map) = value {
+                check_object(properties, map, path, errors);
+            }
+            true
+        }
+        (Schema::OneOf(schemas), _) => {
+            // The first alternative without violations wins
+            let matched = schemas.iter().find_map(|schema| {
+                let mut candidate = value.clone();
+                let mut violations = Vec::new();
+                check(schema, &mut candidate, path, &mut violations);
+                violations.is_empty().then_some(candidate)
+            });
+            match matched {
+                Some(candidate) => {
+                    *value = candidate;
+                    true
+                }
+                None => {
+                    // Report the violations of the alternative of the
+                    // value's own kind, if there is one
+                    match schemas.iter().find(|schema| same_kind(schema, value)) {
+                        Some(schema) => {
+                            check(schema, value, path, errors);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+            }
+        }
+        _ => false,
+    };
+    if !matches {
+        let path = if path.is_empty() { "(root)" } else { path };
+        errors.push(format!("{} must be {}", path, schema.describe()));
+    }
+}
+
+/// Check an object's properties, allowing `x-` extensions
+fn check_object(
+    properties: &[(&str, Schema)],
+    map: &mut Mapping,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    for (key, value) in map.iter_mut() {
+        let name = key_name(key);
+        if name.starts_with("x-") {
+            continue;
+        }
+        match properties.iter().find(|(property, _)| *property == name) {
+            Some((_, schema)) => check(schema, value, &join(path, key), errors),
+            None => {
+                let path = if path.is_empty() { "(root)" } else { path };
+                errors.push(format!(
+                    "{}: additional property {} is not allowed",
+                    path, name
+                ));
+            }
+        }
+    }
+}
+
+/// Whether a value is of the kind a schema takes, lists for lists and
+/// mappings for mappings
+fn same_kind(schema: &Schema, value: &Value) -> bool {
+    match schema {
+        Schema::List(_) => value.is_sequence(),
+        Schema::Map(_) | Schema::Object(_) => value.is_mapping(),
+        _ => false,
+    }
+}
+
+/// A string as the number or boolean a schema wants
+fn convert(schema: &Schema, s: &str) -> Option<Value> {
+    match schema {
+        Schema::Integer => s.parse::<i64>().ok().map(Value::from),
+        Schema::Number => s.parse::<f64>().ok().map(Value::from),
+        Schema::Boolean => match s.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "y" | "on" => Some(Value::Bool(true)),
+            "false" | "no" | "n" | "off" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+fn join(path: &str, key: &Value) -> String {
+    match path {
+        "" => key_name(key),
+        _ => format!("{}.{}", path, key_name(key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(yaml: &str) -> Vec<String> {
+        let mut value: Value = serde_yaml::from_str(yaml).unwrap();
+        validate(&mut value)
+    }
+
+    #[test]
+    fn test_schema() {
+        assert!(errors(
+            r#"
+services:
+  web:
+    image: nginx
+    ports: ["80:80", 443, {target: 8080, published: "8080"}]
+    environment: [A=1]
+    deploy: {replicas: 2}
+    x-custom: anything
+networks:
+  front:
+x-common: {}
+"#
+        )
+        .is_empty());
+
+        assert_eq!(
+            errors(
+                r#"
+servics: {}
+services:
+  web:
+    imag: nginx
+    ports: 80
+    depends_on: [{db: x}]
+    deploy: {replicas: two}
+    healthcheck: {retries: 3, intervall: 5s}
+"#
+            ),
+            [
+                "(root): additional property servics is not allowed",
+                "services.web: additional property imag is not allowed",
+                "services.web.ports must be a list",
+                "services.web.depends_on.0 must be a string",
+                "services.web.deploy.replicas must be an integer",
+                "services.web.healthcheck: additional property intervall is not allowed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_converts_interpolated_values() {
+        let mut value: Value = serde_yaml::from_str(
+            "services: {web: {image: x, deploy: {replicas: '3'}, init: 'true'}}",
+        )
+        .unwrap();
+        assert!(validate(&mut value).is_empty());
+        assert_eq!(
+            value["services"]["web"]["deploy"]["replicas"],
+            Value::from(3)
+        );
+        assert_eq!(value["services"]["web"]["init"], Value::Bool(true));
+    }
+}
@@ -0,0 +1,155 @@
+//! Container checkpoints
+//!
+//! A checkpoint is a directory named after it, holding the CRIU images of
+//! the container's processes in `criu/` and the options it was taken with
+//! in `checkpoint.json`. Checkpoints live in the container's directory
+//! unless a checkpoint directory is given, which makes them easy to carry
+//! to another host.
+
+use crate::error::{Result, RuneError};
+use crate::runtime::criu::CheckpointOptions;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Metadata file of a checkpoint
+const METADATA_FILE: &str = "checkpoint.json";
+
+/// Checkpoint of a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Checkpoint {
+    /// Name of the checkpoint
+    pub name: String,
+    /// Container the checkpoint was taken of
+    #[serde(rename = "ContainerID")]
+    pub container_id: String,
+    /// When the checkpoint was taken
+    pub created: DateTime<Utc>,
+    /// Options it was taken with
+    pub options: CheckpointOptions,
+    /// Directory of the checkpoint
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl Checkpoint {
+    /// Directory of CRIU images
+    pub fn images(&self) -> PathBuf {
+        self.path.join("criu")
+    }
+
+    /// Create the directory of a new checkpoint
+    pub fn create(
+        dir: &Path,
+        name: &str,
+        container_id: &str,
+        options: CheckpointOptions,
+    ) -> Result<Self> {
+        validate_name(name)?;
+        let path = dir.join(name);
+        if path.exists() {
+            return Err(RuneError::InvalidConfig(format!(
+                "Checkpoint {} already exists",
+                name
+            )));
+        }
+        std::fs::create_dir_all(&path)?;
+        Ok(Self {
+            name: name.to_string(),
+            container_id: container_id.to_string(),
+            created: Utc::now(),
+            options,
+            path,
+        })
+    }
+
+    /// Record the checkpoint's metadata once its images are written
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(
+            self.path.join(METADATA_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Load a checkpoint
+    pub fn load(dir: &Path, name: &str) -> Result<Self> {
+        validate_name(name)?;
+        let path = dir.join(name);
+        let contents = std::fs::read_to_string(path.join(METADATA_FILE))
+            .map_err(|_| RuneError::InvalidConfig(format!("No such checkpoint: {}", name)))?;
+        let mut checkpoint: Self = serde_json::from_str(&contents)?;
+        checkpoint.path = path;
+        Ok(checkpoint)
+    }
+
+    /// Checkpoints in a directory, oldest first
+    pub fn list(dir: &Path) -> Result<Vec<Self>> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(Vec::new());
+        };
+        let mut checkpoints = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Ok(checkpoint) = Self::load(dir, &name) {
+                checkpoints.push(checkpoint);
+            }
+        }
+        checkpoints.sort_by_key(|checkpoint| checkpoint.created);
+        Ok(checkpoints)
+    }
+
+    /// Delete a checkpoint
+    pub fn remove(dir: &Path, name: &str) -> Result<()> {
+        let checkpoint = Self::load(dir, name)?;
+        std::fs::remove_dir_all(checkpoint.path)?;
+        Ok(())
+    }
+}
+
+/// Check a checkpoint name like Docker does, so it is a safe directory name
+fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid {
+        return Err(RuneError::InvalidConfig(format!(
+            "Invalid checkpoint name '{}'; use [a-zA-Z0-9][a-zA-Z0-9_.-]*",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::criu::TcpMode;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checkpoint_storage() {
+        let dir = TempDir::new().unwrap();
+        let options = CheckpointOptions {
+            tcp: TcpMode::Established,
+            ..CheckpointOptions::default()
+        };
+
+        let checkpoint = Checkpoint::create(dir.path(), "cp1", "abc", options.clone()).unwrap();
+        assert_eq!(checkpoint.images(), dir.path().join("cp1/criu"));
+        // Not listed until its images are written
+        assert!(Checkpoint::list(dir.path()).unwrap().is_empty());
+        checkpoint.save().unwrap();
+        assert!(Checkpoint::create(dir.path(), "cp1", "abc", options.clone()).is_err());
+
+        let loaded = Checkpoint::load(dir.path(), "cp1").unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.options.tcp, TcpMode::Established);
+        assert_eq!(Checkpoint::list(dir.path()).unwrap().len(), 1);
+
+        Checkpoint::remove(dir.path(), "cp1").unwrap();
+        assert!(Checkpoint::load(dir.path(), "cp1").is_err());
+        assert!(Checkpoint::create(dir.path(), "../escape", "abc", options).is_err());
+    }
+}
@@ -1,5 +1,18 @@
 //! Container configuration
 
+use super::health::{Health, HealthcheckConfig};
+use super::logging::LogConfig;
+use crate::error::{Result, RuneError};
+use crate::network::EndpointConfig;
+use crate::runtime::capabilities::effective_capabilities;
+use crate::runtime::cdi::{self, GpuRequest};
+use crate::runtime::devices::valid_permissions;
+use crate::runtime::lsm::SecurityLabels;
+use crate::runtime::oci::{Hook, Hooks, PosixRlimit};
+use crate::runtime::rlimit;
+use crate::runtime::seccomp::Seccomp;
+use crate::runtime::signal;
+use crate::runtime::sysctl;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -73,10 +86,76 @@ pub struct ContainerConfig {
     pub domainname: String,
     /// Network mode
     pub network_mode: String,
+    /// Settings of the container's endpoints, by network; what the network
+    /// allocates is filled in once the container is connected
+    #[serde(default)]
+    pub endpoints: HashMap<String, EndpointConfig>,
     /// Privileged mode
     pub privileged: bool,
     /// Read-only root filesystem
     pub read_only_rootfs: bool,
+    /// Capabilities to add to the default set
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    /// Capabilities to drop from the default set
+    #[serde(default)]
+    pub cap_drop: Vec<String>,
+    /// Security options, such as `seccomp=unconfined`
+    #[serde(default)]
+    pub security_opt: Vec<String>,
+    /// User namespace mode; `host` opts out of the daemon's remapping
+    #[serde(default)]
+    pub userns_mode: String,
+    /// AppArmor profile and SELinux labels applied, set on creation
+    #[serde(default)]
+    pub security_labels: SecurityLabels,
+    /// Run the command under Rune's init, which forwards signals and reaps
+    /// zombies
+    #[serde(default)]
+    pub init: bool,
+    /// Host devices
+    #[serde(default)]
+    pub devices: Vec<DeviceMapping>,
+    /// CDI devices, by qualified name such as `nvidia.com/gpu=0`
+    #[serde(default)]
+    pub cdi_devices: Vec<String>,
+    /// GPUs, picked from the CDI devices
+    #[serde(default)]
+    pub gpus: Option<GpuRequest>,
+    /// OCI lifecycle hooks, run after the daemon's
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Process resource limits
+    #[serde(default)]
+    pub ulimits: Vec<Ulimit>,
+    /// Namespaced kernel parameters
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+    /// Keep the OOM killer from killing the container's processes
+    #[serde(default)]
+    pub oom_kill_disable: bool,
+    /// OOM score adjustment of the container process, -1000 to 1000
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+    /// Give the process a pseudo-terminal
+    #[serde(default)]
+    pub tty: bool,
+    /// Keep the process's standard input open
+    #[serde(default)]
+    pub open_stdin: bool,
+    /// Remove the container once its process exits
+    #[serde(default)]
+    pub auto_remove: bool,
+    /// Signal that stops the container, SIGTERM if unset
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    /// Seconds a stopped container gets to exit before it is killed
+    #[serde(default)]
+    pub stop_timeout: Option<u64>,
+    /// Volumes created for the container's anonymous mounts, removed with
+    /// it when it is removed automatically
+    #[serde(default)]
+    pub anonymous_volumes: Vec<String>,
     /// Resource limits
     pub resources: ResourceLimits,
     /// Current status
@@ -91,6 +170,12 @@ pub struct ContainerConfig {
     pub exit_code: Option<i32>,
     /// Process ID
     pub pid: Option<u32>,
+    /// Healthcheck
+    pub healthcheck: Option<HealthcheckConfig>,
+    /// Health, set while a container with a healthcheck runs
+    pub health: Option<Health>,
+    /// Log driver, or the daemon's default when unset
+    pub log_config: Option<LogConfig>,
 }
 
 impl Default for ContainerConfig {
@@ -110,8 +195,29 @@ impl Default for ContainerConfig {
             hostname: String::new(),
             domainname: String::new(),
             network_mode: "bridge".to_string(),
+            endpoints: HashMap::new(),
             privileged: false,
             read_only_rootfs: false,
+            cap_add: Vec::new(),
+            cap_drop: Vec::new(),
+            security_opt: Vec::new(),
+            userns_mode: String::new(),
+            security_labels: SecurityLabels::default(),
+            init: false,
+            devices: Vec::new(),
+            cdi_devices: Vec::new(),
+            gpus: None,
+            hooks: Hooks::default(),
+            ulimits: Vec::new(),
+            sysctls: HashMap::new(),
+            oom_kill_disable: false,
+            oom_score_adj: None,
+            tty: false,
+            open_stdin: false,
+            auto_remove: false,
+            stop_signal: None,
+            stop_timeout: None,
+            anonymous_volumes: Vec::new(),
             resources: ResourceLimits::default(),
             status: ContainerStatus::Creating,
             created_at: Utc::now(),
@@ -119,6 +225,9 @@ impl Default for ContainerConfig {
             finished_at: None,
             exit_code: None,
             pid: None,
+            healthcheck: None,
+            health: None,
+            log_config: None,
         }
     }
 }
@@ -167,10 +276,169 @@ impl ContainerConfig {
         self.volumes.push(VolumeMount {
             host_path: host_path.to_string(),
             container_path: container_path.to_string(),
-            read_only: false,
+            ..VolumeMount::default()
         });
         self
     }
+
+    /// Add a mount in `--volume` syntax, `host:container[:options]`, or a
+    /// bare container path for an anonymous volume created with the
+    /// container
+    ///
+    /// Options are comma-separated: `ro` or `rw`, `z` or `Z` to relabel the
+    /// source for SELinux, shared with other containers or private to this
+    /// one, a bind propagation such as `rshared` or `slave`, and `nocopy`.
+    /// Volumes are never populated from the image, so `nocopy` has nothing
+    /// to turn off.
+    pub fn add_volume(&mut self, volume: &str) -> Result<()> {
+        let parts: Vec<&str> = volume.split(':').collect();
+        let (host_path, container_path, options) = match parts[..] {
+            [container_path] => ("", container_path, ""),
+            [host_path, container_path] => (host_path, container_path, ""),
+            [host_path, container_path, options] => (host_path, container_path, options),
+            _ => ("", "", ""),
+        };
+        if !container_path.starts_with('/') {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid volume '{}'; expected host:container[:options] or an absolute container path",
+                volume
+            )));
+        }
+
+        let mut mount = VolumeMount {
+            host_path: host_path.to_string(),
+            container_path: container_path.to_string(),
+            ..VolumeMount::default()
+        };
+        let (mut mode, mut relabel, mut propagation) = (None, None, None);
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            let slot = match option {
+                "ro" | "rw" => &mut mode,
+                "z" | "Z" => &mut relabel,
+                "private" | "rprivate" | "shared" | "rshared" | "slave" | "rslave" => {
+                    &mut propagation
+                }
+                "nocopy" => continue,
+                _ => {
+                    return Err(RuneError::InvalidConfig(format!(
+                        "Invalid volume option '{}' in '{}'",
+                        option, volume
+                    )))
+                }
+            };
+            if slot.replace(option).is_some() {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Conflicting volume options in '{}'",
+                    volume
+                )));
+            }
+        }
+        mount.read_only = mode == Some("ro");
+        mount.relabel = relabel.map(str::to_string);
+        mount.propagation = propagation.map(str::to_string);
+        self.volumes.push(mount);
+        Ok(())
+    }
+
+    /// Network the container is connected to, unless it shares the host's
+    /// network, another container's or has none
+    pub fn network(&self) -> Option<&str> {
+        match self.network_mode.as_str() {
+            "host" | "none" => None,
+            mode if mode.starts_with("container:") => None,
+            "" | "default" => Some("bridge"),
+            mode => Some(mode),
+        }
+    }
+
+    /// Signal that stops the container
+    pub fn stop_signal(&self) -> Result<i32> {
+        self.stop_signal
+            .as_deref()
+            .map_or(Ok(libc::SIGTERM), signal::parse_signal)
+    }
+
+    /// Add a device in `--device` syntax: a host device, or a CDI device by
+    /// its qualified name
+    pub fn add_device(&mut self, device: &str) -> Result<()> {
+        if cdi::is_qualified_name(device) {
+            self.cdi_devices.push(device.to_string());
+        } else {
+            self.devices.push(DeviceMapping::parse(device)?);
+        }
+        Ok(())
+    }
+
+    /// Add a hook in `--hook` syntax, `stage=/path/to/binary [args...]`
+    pub fn add_hook(&mut self, hook: &str) -> Result<()> {
+        let (stage, command) = hook.split_once('=').ok_or_else(|| {
+            RuneError::InvalidConfig(format!(
+                "Invalid hook '{}'; expected stage=/path/to/binary [args...]",
+                hook
+            ))
+        })?;
+        self.hooks.add(stage, Hook::parse(command)?)
+    }
+
+    /// Add a limit in `--ulimit` syntax, replacing any of the same name
+    pub fn add_ulimit(&mut self, ulimit: &str) -> Result<()> {
+        let ulimit = Ulimit::parse(ulimit)?;
+        self.ulimits.retain(|existing| existing.name != ulimit.name);
+        self.ulimits.push(ulimit);
+        Ok(())
+    }
+
+    /// Add a sysctl in `--sysctl` syntax, `name=value`
+    pub fn add_sysctl(&mut self, sysctl: &str) -> Result<()> {
+        let (name, value) = sysctl.split_once('=').ok_or_else(|| {
+            RuneError::InvalidConfig(format!("Invalid sysctl '{}'; expected name=value", sysctl))
+        })?;
+        self.sysctls.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Check the limits and kernel parameters of the container process
+    pub fn validate_limits(&self) -> Result<()> {
+        for ulimit in &self.ulimits {
+            ulimit.validate()?;
+        }
+        for name in self.sysctls.keys() {
+            sysctl::validate(name, self.network_mode == "host")?;
+        }
+        match self.oom_score_adj {
+            Some(adj) if !(-1000..=1000).contains(&adj) => Err(RuneError::InvalidConfig(format!(
+                "Invalid OOM score adjustment {}; expected -1000 to 1000",
+                adj
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Capabilities of the container process
+    pub fn capabilities(&self) -> Result<Vec<String>> {
+        effective_capabilities(&self.cap_add, &self.cap_drop, self.privileged)
+    }
+
+    /// Seccomp mode selected by the security options, unconfined for
+    /// privileged containers
+    pub fn seccomp(&self) -> Result<Seccomp> {
+        if self.privileged {
+            return Ok(Seccomp::Unconfined);
+        }
+        Seccomp::from_security_opts(&self.security_opt)
+    }
+
+    /// Whether the container opts out of user namespace remapping
+    pub fn userns_host(&self) -> Result<bool> {
+        match self.userns_mode.as_str() {
+            "" => Ok(false),
+            "host" => Ok(true),
+            mode => Err(RuneError::InvalidConfig(format!(
+                "Invalid userns mode '{}'; only 'host' is supported",
+                mode
+            ))),
+        }
+    }
 }
 
 /// Port mapping
@@ -190,11 +458,114 @@ pub enum Protocol {
 }
 
 /// Volume mount
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct VolumeMount {
     pub host_path: String,
     pub container_path: String,
     pub read_only: bool,
+    /// Bind propagation, `rprivate` when unset
+    #[serde(default)]
+    pub propagation: Option<String>,
+    /// `z` to label the source for SELinux so all containers can share it,
+    /// `Z` so only this container can use it
+    #[serde(default)]
+    pub relabel: Option<String>,
+}
+
+/// Host device mapped into a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceMapping {
+    pub path_on_host: String,
+    pub path_in_container: String,
+    /// Device cgroup permissions, any of `r`, `w` and `m`
+    pub cgroup_permissions: String,
+}
+
+impl DeviceMapping {
+    /// Parse `--device` syntax, `/dev/host[:/dev/container][:rwm]`
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || RuneError::InvalidConfig(format!("Invalid device '{}'", value));
+        let parts: Vec<&str> = value.split(':').collect();
+        let (host, container, permissions) = match parts[..] {
+            [host] => (host, host, "rwm"),
+            [host, permissions] if valid_permissions(permissions) => (host, host, permissions),
+            [host, container] => (host, container, "rwm"),
+            [host, container, permissions] => (host, container, permissions),
+            _ => return Err(invalid()),
+        };
+        if !valid_permissions(permissions) || !container.starts_with('/') || host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            path_on_host: host.to_string(),
+            path_in_container: container.to_string(),
+            cgroup_permissions: permissions.to_string(),
+        })
+    }
+}
+
+/// Process resource limit; -1 is unlimited
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ulimit {
+    /// Resource, like `nofile`
+    pub name: String,
+    pub soft: i64,
+    pub hard: i64,
+}
+
+impl Ulimit {
+    /// Parse `--ulimit` syntax, `name=soft[:hard]`
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || {
+            RuneError::InvalidConfig(format!(
+                "Invalid ulimit '{}'; expected name=soft[:hard]",
+                value
+            ))
+        };
+        let (name, limits) = value.split_once('=').ok_or_else(invalid)?;
+        let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+        let ulimit = Self {
+            name: name.to_string(),
+            soft: soft.parse().map_err(|_| invalid())?,
+            hard: hard.parse().map_err(|_| invalid())?,
+        };
+        ulimit.validate()?;
+        Ok(ulimit)
+    }
+
+    /// Check the resource exists and the soft limit is within the hard one
+    pub fn validate(&self) -> Result<()> {
+        if rlimit::oci_type(&self.name).is_none() {
+            return Err(RuneError::InvalidConfig(format!(
+                "Unknown ulimit '{}'",
+                self.name
+            )));
+        }
+        if self.soft < -1 || self.hard < -1 {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid ulimit {}: limits are -1 for unlimited or at least 0",
+                self.name
+            )));
+        }
+        let oci = self.to_oci();
+        if oci.soft > oci.hard {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid ulimit {}: soft limit {} exceeds hard limit {}",
+                self.name, self.soft, self.hard
+            )));
+        }
+        Ok(())
+    }
+
+    /// OCI limit of the process
+    pub fn to_oci(&self) -> PosixRlimit {
+        let limit = |value: i64| if value < 0 { u64::MAX } else { value as u64 };
+        PosixRlimit {
+            kind: rlimit::oci_type(&self.name).unwrap_or_default(),
+            hard: limit(self.hard),
+            soft: limit(self.soft),
+        }
+    }
 }
 
 /// Resource limits
@@ -0,0 +1,674 @@
+//! Container healthchecks
+//!
+//! A container's healthcheck command runs inside it at an interval. Its
+//! health starts as `starting`, turns `healthy` when a check passes and
+//! `unhealthy` after `retries` failures in a row; failures during the start
+//! period do not count while the container is still starting.
+//! `HealthChecker` schedules the checks of all running containers and
+//! records the results on them.
+
+use super::config::ContainerConfig;
+use super::lifecycle::ContainerManager;
+use crate::error::{Result, RuneError};
+use crate::image::store::HealthConfig;
+use crate::runtime::process::enter_namespaces;
+use crate::swarm::stack::parse_duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Interval and timeout used when a healthcheck does not set them
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Failures in a row that make a container unhealthy by default
+const DEFAULT_RETRIES: u32 = 3;
+/// Results kept in a container's health log
+const MAX_LOG_ENTRIES: usize = 5;
+/// Bytes of a check's output kept in the log
+const MAX_OUTPUT_BYTES: usize = 4096;
+/// Health events kept for the events endpoint
+const MAX_EVENTS: usize = 256;
+/// How often the checker looks for due checks
+const TICK: Duration = Duration::from_secs(1);
+
+/// Healthcheck of a container, with Docker's durations in nanoseconds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct HealthcheckConfig {
+    /// `["CMD", args...]`, `["CMD-SHELL", command]` or `["NONE"]`
+    pub test: Vec<String>,
+    /// Time between checks, 30s when 0
+    pub interval: i64,
+    /// Time before a check fails, 30s when 0
+    pub timeout: i64,
+    /// Time after starting in which failures do not count
+    pub start_period: i64,
+    /// Failures in a row before the container is unhealthy, 3 when 0
+    pub retries: u32,
+}
+
+impl HealthcheckConfig {
+    /// Command to run, `None` when the healthcheck is disabled
+    pub fn command(&self) -> Option<Vec<String>> {
+        match self.test.split_first() {
+            Some((kind, args)) if kind == "CMD" && !args.is_empty() => Some(args.to_vec()),
+            Some((kind, [command])) if kind == "CMD-SHELL" => Some(vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                command.clone(),
+            ]),
+            _ => None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        nanos(self.interval).unwrap_or(DEFAULT_INTERVAL)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        nanos(self.timeout).unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    pub fn start_period(&self) -> Duration {
+        nanos(self.start_period).unwrap_or_default()
+    }
+
+    pub fn retries(&self) -> u32 {
+        match self.retries {
+            0 => DEFAULT_RETRIES,
+            retries => retries,
+        }
+    }
+}
+
+impl From<&HealthConfig> for HealthcheckConfig {
+    fn from(image: &HealthConfig) -> Self {
+        Self {
+            test: image.test.clone(),
+            interval: image.interval as i64,
+            timeout: image.timeout as i64,
+            start_period: image.start_period as i64,
+            retries: image.retries,
+        }
+    }
+}
+
+/// Healthcheck flags of `run` and `create`, overriding the image's
+/// HEALTHCHECK; durations are in Docker's syntax, e.g. `1m30s`
+#[derive(Debug, Clone, Default)]
+pub struct HealthcheckOverrides {
+    /// Shell command to run, for `--health-cmd`
+    pub cmd: Option<String>,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub start_period: Option<String>,
+    pub retries: Option<u32>,
+    /// `--no-healthcheck`, disabling the image's
+    pub disable: bool,
+}
+
+impl HealthcheckOverrides {
+    /// Healthcheck of a container of an image with `image`: the image's
+    /// with the flags given replacing its settings
+    pub fn apply(&self, image: Option<HealthcheckConfig>) -> Result<Option<HealthcheckConfig>> {
+        let overridden = self.cmd.is_some()
+            || self.interval.is_some()
+            || self.timeout.is_some()
+            || self.start_period.is_some()
+            || self.retries.is_some();
+        if self.disable {
+            if overridden {
+                return Err(RuneError::InvalidConfig(
+                    "--no-healthcheck conflicts with --health-* options".to_string(),
+                ));
+            }
+            return Ok(Some(HealthcheckConfig {
+                test: vec!["NONE".to_string()],
+                ..Default::default()
+            }));
+        }
+        if !overridden {
+            return Ok(image);
+        }
+
+        let mut healthcheck = image.unwrap_or_default();
+        if let Some(cmd) = &self.cmd {
+            healthcheck.test = vec!["CMD-SHELL".to_string(), cmd.clone()];
+        }
+        for (value, field) in [
+            (&self.interval, &mut healthcheck.interval),
+            (&self.timeout, &mut healthcheck.timeout),
+            (&self.start_period, &mut healthcheck.start_period),
+        ] {
+            if let Some(value) = value {
+                *field = parse_duration(value)?;
+            }
+        }
+        if let Some(retries) = self.retries {
+            healthcheck.retries = retries;
+        }
+        Ok(Some(healthcheck))
+    }
+}
+
+fn nanos(value: i64) -> Option<Duration> {
+    (value > 0).then(|| Duration::from_nanos(value as u64))
+}
+
+/// Health of a container with a healthcheck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthStatus::Starting => write!(f, "starting"),
+            HealthStatus::Healthy => write!(f, "healthy"),
+            HealthStatus::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+/// Result of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct HealthcheckResult {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// 0 when the check passed, -1 when it could not run or timed out
+    pub exit_code: i32,
+    pub output: String,
+}
+
+/// Health of a running container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Failed checks since the last one that passed
+    pub failing_streak: u32,
+    /// Latest results, oldest first
+    pub log: Vec<HealthcheckResult>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            status: HealthStatus::Starting,
+            failing_streak: 0,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Health {
+    /// Record the result of a check of a container started at `started_at`,
+    /// returning the new status when it changed
+    pub fn record(
+        &mut self,
+        result: HealthcheckResult,
+        healthcheck: &HealthcheckConfig,
+        started_at: DateTime<Utc>,
+    ) -> Option<HealthStatus> {
+        let running_for = (result.start - started_at).to_std().unwrap_or_default();
+        let passed = result.exit_code == 0;
+        self.log.push(result);
+        if self.log.len() > MAX_LOG_ENTRIES {
+            self.log.remove(0);
+        }
+
+        let status = if passed {
+            self.failing_streak = 0;
+            HealthStatus::Healthy
+        } else if self.status == HealthStatus::Starting && running_for < healthcheck.start_period()
+        {
+            return None;
+        } else {
+            self.failing_streak += 1;
+            if self.failing_streak < healthcheck.retries() {
+                return None;
+            }
+            HealthStatus::Unhealthy
+        };
+        (self.status != status).then(|| {
+            self.status = status;
+            status
+        })
+    }
+}
+
+/// Exit code and output of a check command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeOutput {
+    pub exit_code: i32,
+    pub output: String,
+}
+
+impl ProbeOutput {
+    /// Output of a check that could not run or did not finish
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self {
+            exit_code: -1,
+            output: message.into(),
+        }
+    }
+}
+
+/// Runs healthcheck commands in containers
+pub trait HealthProbe: Send + Sync {
+    /// Run `cmd` in a running container, failing it after `timeout`
+    fn run(&self, container: &ContainerConfig, cmd: &[String], timeout: Duration) -> ProbeOutput;
+}
+
+/// Runs checks as processes in the container's namespaces
+pub struct ExecProbe;
+
+impl HealthProbe for ExecProbe {
+    fn run(&self, container: &ContainerConfig, cmd: &[String], timeout: Duration) -> ProbeOutput {
+        let Some(pid) = container.pid else {
+            return ProbeOutput::failed("Container has no running process");
+        };
+        let Some((program, args)) = cmd.split_first() else {
+            return ProbeOutput::failed("Healthcheck has no command");
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env_clear()
+            .envs(&container.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if !container.env.contains_key("PATH") {
+            command.env(
+                "PATH",
+                "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+            );
+        }
+        let working_dir = std::ffi::CString::new(container.working_dir.as_str()).ok();
+        // SAFETY: runs in the forked child before exec and only makes syscalls
+        unsafe {
+            command.pre_exec(move || {
+                enter_namespaces(pid).map_err(io::Error::other)?;
+                if let Some(ref dir) = working_dir {
+                    libc::chdir(dir.as_ptr());
+                }
+                Ok(())
+            });
+        }
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => return ProbeOutput::failed(format!("Failed to run healthcheck: {}", e)),
+        };
+        let child_pid = child.id();
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            // Read stderr after stdout, as `docker inspect` shows them joined
+            for mut stream in [
+                stdout.map(|s| Box::new(s) as Box<dyn Read>),
+                stderr.map(|s| Box::new(s) as Box<dyn Read>),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let _ = stream.read_to_end(&mut output);
+            }
+            let _ = tx.send((child.wait(), output));
+        });
+
+        match rx.recv_timeout(timeout) {
+            Ok((Ok(status), output)) => ProbeOutput {
+                exit_code: status.code().unwrap_or(-1),
+                output: truncate_output(&output),
+            },
+            Ok((Err(e), _)) => {
+                ProbeOutput::failed(format!("Failed to wait for healthcheck: {}", e))
+            }
+            Err(_) => {
+                let _ = crate::runtime::syscall::kill(child_pid as i32, libc::SIGKILL);
+                ProbeOutput::failed(format!("Health check exceeded timeout ({:?})", timeout))
+            }
+        }
+    }
+}
+
+fn truncate_output(output: &[u8]) -> String {
+    let output = &output[..output.len().min(MAX_OUTPUT_BYTES)];
+    String::from_utf8_lossy(output).into_owned()
+}
+
+/// A container's health changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthEvent {
+    pub container_id: String,
+    pub name: String,
+    pub image: String,
+    pub status: HealthStatus,
+    pub time: DateTime<Utc>,
+}
+
+/// When a running container's next check is due
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    started_at: DateTime<Utc>,
+    next_check: DateTime<Utc>,
+}
+
+/// Runs the healthchecks of running containers
+pub struct HealthChecker {
+    containers: Arc<ContainerManager>,
+    probe: Arc<dyn HealthProbe>,
+    schedules: Mutex<HashMap<String, Schedule>>,
+    events: Mutex<VecDeque<HealthEvent>>,
+}
+
+impl HealthChecker {
+    /// Create a checker for the containers of a manager
+    pub fn new(containers: Arc<ContainerManager>, probe: Arc<dyn HealthProbe>) -> Self {
+        Self {
+            containers,
+            probe,
+            schedules: Mutex::new(HashMap::new()),
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Run checks as they fall due, on a thread of their own
+    pub fn spawn(self: Arc<Self>) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            if let Err(e) = self.tick(Utc::now()) {
+                warn!("Failed to run healthchecks: {}", e);
+            }
+            thread::sleep(TICK);
+        })
+    }
+
+    /// Run the checks due by `now` and record their results
+    ///
+    /// A container's first check is due one interval after it started.
+    /// Checks run in parallel; this returns once all of them finish.
+    pub fn tick(&self, now: DateTime<Utc>) -> Result<()> {
+        let running = self.containers.list(false)?;
+        let mut due = Vec::new();
+        {
+            let mut schedules = self.schedules.lock().unwrap();
+            schedules.retain(|id, _| running.iter().any(|c| c.id == *id));
+            for container in running {
+                let Some(healthcheck) = container.healthcheck.clone() else {
+                    continue;
+                };
+                let (Some(cmd), Some(started_at)) = (healthcheck.command(), container.started_at)
+                else {
+                    continue;
+                };
+                let interval = chrono::Duration::from_std(healthcheck.interval())
+                    .unwrap_or(chrono::Duration::MAX);
+                let schedule = schedules.entry(container.id.clone()).or_insert(Schedule {
+                    started_at,
+                    next_check: started_at + interval,
+                });
+                // The container restarted since its last check
+                if schedule.started_at != started_at {
+                    *schedule = Schedule {
+                        started_at,
+                        next_check: started_at + interval,
+                    };
+                }
+                if schedule.next_check <= now {
+                    schedule.next_check = now + interval;
+                    due.push((container, healthcheck, cmd));
+                }
+            }
+        }
+
+        let results: Vec<(ContainerConfig, HealthcheckResult)> = thread::scope(|scope| {
+            let checks: Vec<_> = due
+                .into_iter()
+                .map(|(container, healthcheck, cmd)| {
+                    scope.spawn(move || {
+                        let started = Instant::now();
+                        let output = self.probe.run(&container, &cmd, healthcheck.timeout());
+                        let elapsed =
+                            chrono::Duration::from_std(started.elapsed()).unwrap_or_default();
+                        let result = HealthcheckResult {
+                            start: now,
+                            end: now + elapsed,
+                            exit_code: output.exit_code,
+                            output: output.output,
+                        };
+                        (container, result)
+                    })
+                })
+                .collect();
+            checks.into_iter().filter_map(|c| c.join().ok()).collect()
+        });
+
+        for (container, result) in results {
+            let time = result.end;
+            match self.containers.record_health(&container.id, result) {
+                Ok(Some(status)) => self.push_event(HealthEvent {
+                    container_id: container.id,
+                    name: container.name,
+                    image: container.image,
+                    status,
+                    time,
+                }),
+                Ok(None) => {}
+                // Removed while its check ran
+                Err(e) => warn!("Failed to record health of {}: {}", container.id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Health changes at or after `since`, oldest first
+    pub fn events(&self, since: Option<DateTime<Utc>>) -> Vec<HealthEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| since.is_none_or(|since| e.time >= since))
+            .cloned()
+            .collect()
+    }
+
+    fn push_event(&self, event: HealthEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use tempfile::TempDir;
+
+    fn healthcheck(test: &[&str], retries: u32, start_period_secs: i64) -> HealthcheckConfig {
+        HealthcheckConfig {
+            test: test.iter().map(|s| s.to_string()).collect(),
+            interval: 10_000_000_000,
+            retries,
+            start_period: start_period_secs * 1_000_000_000,
+            ..Default::default()
+        }
+    }
+
+    fn result(start: DateTime<Utc>, exit_code: i32) -> HealthcheckResult {
+        HealthcheckResult {
+            start,
+            end: start,
+            exit_code,
+            output: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_healthcheck_overrides() {
+        let image = healthcheck(&["CMD", "true"], 2, 5);
+        let none = HealthcheckOverrides::default();
+        assert_eq!(
+            none.apply(Some(image.clone())).unwrap(),
+            Some(image.clone())
+        );
+        assert_eq!(none.apply(None).unwrap(), None);
+
+        let overrides = HealthcheckOverrides {
+            cmd: Some("curl -f localhost".to_string()),
+            interval: Some("1m30s".to_string()),
+            retries: Some(5),
+            ..Default::default()
+        };
+        let applied = overrides.apply(Some(image.clone())).unwrap().unwrap();
+        assert_eq!(applied.test, ["CMD-SHELL", "curl -f localhost"]);
+        assert_eq!(applied.interval, 90_000_000_000);
+        assert_eq!(applied.retries, 5);
+        // What the flags leave alone comes from the image
+        assert_eq!(applied.start_period, image.start_period);
+
+        let disable = HealthcheckOverrides {
+            disable: true,
+            ..Default::default()
+        };
+        let disabled = disable.apply(Some(image.clone())).unwrap().unwrap();
+        assert_eq!(disabled.command(), None);
+        let conflicting = HealthcheckOverrides {
+            retries: Some(1),
+            ..disable
+        };
+        assert!(conflicting.apply(Some(image)).is_err());
+        assert!(HealthcheckOverrides {
+            timeout: Some("soon".to_string()),
+            ..Default::default()
+        }
+        .apply(None)
+        .is_err());
+    }
+
+    #[test]
+    fn test_healthcheck_command() {
+        assert_eq!(
+            healthcheck(&["CMD", "curl", "-f", "localhost"], 0, 0).command(),
+            Some(vec![
+                "curl".to_string(),
+                "-f".to_string(),
+                "localhost".to_string()
+            ])
+        );
+        assert_eq!(
+            healthcheck(&["CMD-SHELL", "pg_isready || exit 1"], 0, 0).command(),
+            Some(vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                "pg_isready || exit 1".to_string()
+            ])
+        );
+        assert_eq!(healthcheck(&["NONE"], 0, 0).command(), None);
+        assert_eq!(healthcheck(&[], 0, 0).command(), None);
+
+        let defaults = HealthcheckConfig::default();
+        assert_eq!(defaults.interval(), Duration::from_secs(30));
+        assert_eq!(defaults.timeout(), Duration::from_secs(30));
+        assert_eq!(defaults.retries(), 3);
+    }
+
+    #[test]
+    fn test_health_transitions() {
+        let config = healthcheck(&["CMD", "true"], 2, 30);
+        let started = Utc::now();
+        let at = |secs| started + chrono::Duration::seconds(secs);
+        let mut health = Health::default();
+
+        // Failures in the start period do not count
+        assert_eq!(health.record(result(at(10), 1), &config, started), None);
+        assert_eq!(health.failing_streak, 0);
+        assert_eq!(
+            health.record(result(at(20), 0), &config, started),
+            Some(HealthStatus::Healthy)
+        );
+        // Once healthy, they do
+        assert_eq!(health.record(result(at(25), 1), &config, started), None);
+        assert_eq!(
+            health.record(result(at(35), 1), &config, started),
+            Some(HealthStatus::Unhealthy)
+        );
+        assert_eq!(health.failing_streak, 2);
+        assert_eq!(
+            health.record(result(at(45), 0), &config, started),
+            Some(HealthStatus::Healthy)
+        );
+        assert_eq!(health.failing_streak, 0);
+
+        for secs in 50..60 {
+            health.record(result(at(secs), 0), &config, started);
+        }
+        assert_eq!(health.log.len(), MAX_LOG_ENTRIES);
+        assert_eq!(health.log[0].start, at(55));
+    }
+
+    struct FixedProbe(AtomicI32);
+
+    impl HealthProbe for FixedProbe {
+        fn run(&self, _: &ContainerConfig, _: &[String], _: Duration) -> ProbeOutput {
+            ProbeOutput {
+                exit_code: self.0.load(Ordering::SeqCst),
+                output: String::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_health_checker() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = Arc::new(ContainerManager::new(temp_dir.path().to_path_buf()).unwrap());
+        let mut config = ContainerConfig::new("web", "nginx");
+        config.healthcheck = Some(healthcheck(&["CMD", "true"], 2, 0));
+        let id = manager.create(config).unwrap();
+        manager.start(&id).unwrap();
+        let started = manager.get(&id).unwrap().started_at.unwrap();
+        let at = |secs| started + chrono::Duration::seconds(secs);
+
+        let probe = Arc::new(FixedProbe(AtomicI32::new(0)));
+        let checker = HealthChecker::new(manager.clone(), probe.clone());
+        let status = || manager.get(&id).unwrap().health.unwrap().status;
+        assert_eq!(status(), HealthStatus::Starting);
+
+        // The first check is due one interval after starting
+        checker.tick(at(5)).unwrap();
+        assert_eq!(status(), HealthStatus::Starting);
+        checker.tick(at(10)).unwrap();
+        assert_eq!(status(), HealthStatus::Healthy);
+
+        probe.0.store(1, Ordering::SeqCst);
+        checker.tick(at(15)).unwrap();
+        checker.tick(at(20)).unwrap();
+        assert_eq!(status(), HealthStatus::Healthy);
+        checker.tick(at(30)).unwrap();
+        assert_eq!(status(), HealthStatus::Unhealthy);
+
+        let events = checker.events(None);
+        let statuses: Vec<HealthStatus> = events.iter().map(|e| e.status).collect();
+        assert_eq!(statuses, [HealthStatus::Healthy, HealthStatus::Unhealthy]);
+        assert_eq!(events[0].name, "web");
+        assert_eq!(checker.events(Some(at(25))).len(), 1);
+    }
+}
@@ -1,18 +1,95 @@
 //! Container lifecycle management
 
+use super::checkpoint::Checkpoint;
 use super::config::{ContainerConfig, ContainerStatus};
+use super::health::{HealthStatus, HealthcheckResult};
+use super::logging::{LogConfig, LogDriver};
 use super::runtime::Container;
-use crate::error::{Result, RuneError};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use crate::error::{ErrorKind, Result, RuneError};
+use crate::filter::{self, Filters};
+use crate::network::bridge::NetworkManager;
+use crate::network::EndpointConfig;
+use crate::plugin::PluginManager;
+use crate::runtime::cdi;
+use crate::runtime::criu::CheckpointOptions;
+use crate::runtime::executor::Executor;
+use crate::runtime::lsm::{self, LabelOptions, Lsm, SecurityLabels};
+use crate::runtime::oci::Hooks;
+use crate::runtime::process::ProcessStreams;
+use crate::runtime::userns::UsernsRemap;
+use crate::storage::VolumeManager;
+use crate::swarm::logs::{LogLine, LogRequest};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Filters containers can be listed by
+pub const CONTAINER_FILTERS: &[&str] = &[
+    "ancestor", "before", "health", "id", "label", "name", "since", "status",
+];
+
+/// Statuses the `status` filter accepts
+const STATUSES: [ContainerStatus; 8] = [
+    ContainerStatus::Creating,
+    ContainerStatus::Created,
+    ContainerStatus::Running,
+    ContainerStatus::Paused,
+    ContainerStatus::Stopped,
+    ContainerStatus::Exited,
+    ContainerStatus::Removing,
+    ContainerStatus::Dead,
+];
+
+/// How long a stopped container gets to exit before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often running containers are checked for processes that exited
+const REAP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Containers of a manager, locked for writing
+type Containers<'a> = RwLockWriteGuard<'a, HashMap<String, Container>>;
 
 /// Container manager for handling container lifecycle
 pub struct ContainerManager {
     /// All containers indexed by ID
     containers: Arc<RwLock<HashMap<String, Container>>>,
+    /// IDs of named containers indexed by name, only changed while the
+    /// containers are locked for writing
+    names: RwLock<HashMap<String, String>>,
+    /// IDs of containers whose processes are being stopped, which the
+    /// reaper leaves to whoever stops them
+    stopping: Mutex<HashSet<String>>,
+    /// IDs of containers started for attached clients, which are removed
+    /// once the clients collect their exits rather than when they exit
+    waited: Mutex<HashSet<String>>,
     /// Base path for container storage
     base_path: PathBuf,
+    /// Log driver of containers that don't set one
+    default_log_config: LogConfig,
+    /// Open log drivers indexed by container ID
+    log_drivers: RwLock<HashMap<String, Arc<dyn LogDriver>>>,
+    /// Daemon-wide uid/gid remapping
+    userns_remap: Option<UsernsRemap>,
+    /// Runtime that runs container bundles; without one, containers are
+    /// only tracked
+    executor: Option<Arc<dyn Executor>>,
+    /// Directories CDI devices are looked up in
+    cdi_spec_dirs: Vec<PathBuf>,
+    /// Hooks every container runs
+    hooks: Hooks,
+    /// Security module confining containers
+    lsm: Option<Lsm>,
+    /// Plugins log drivers other than the built-in ones are looked up in
+    plugins: Option<Arc<PluginManager>>,
+    /// Volumes anonymous mounts are created in
+    volumes: Option<Arc<VolumeManager>>,
+    /// Networks containers are connected to
+    networks: Option<Arc<NetworkManager>>,
 }
 
 impl ContainerManager {
@@ -22,14 +99,159 @@ impl ContainerManager {
 
         Ok(Self {
             containers: Arc::new(RwLock::new(HashMap::new())),
+            names: RwLock::new(HashMap::new()),
+            stopping: Mutex::new(HashSet::new()),
+            waited: Mutex::new(HashSet::new()),
             base_path,
+            default_log_config: LogConfig::default(),
+            log_drivers: RwLock::new(HashMap::new()),
+            userns_remap: None,
+            executor: None,
+            cdi_spec_dirs: cdi::DEFAULT_SPEC_DIRS.iter().map(PathBuf::from).collect(),
+            hooks: Hooks::default(),
+            lsm: None,
+            plugins: None,
+            volumes: None,
+            networks: None,
         })
     }
 
+    /// Set the log driver of containers that don't set one
+    pub fn with_log_config(mut self, log_config: LogConfig) -> Self {
+        self.default_log_config = log_config;
+        self
+    }
+
+    /// Run containers under a uid/gid remapping unless they opt out
+    pub fn with_userns_remap(mut self, remap: UsernsRemap) -> Self {
+        self.userns_remap = Some(remap);
+        self
+    }
+
+    /// Daemon-wide uid/gid remapping
+    pub fn userns_remap(&self) -> Option<&UsernsRemap> {
+        self.userns_remap.as_ref()
+    }
+
+    /// Run container bundles with an executor
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// Executor that runs container bundles
+    pub fn executor(&self) -> Option<&Arc<dyn Executor>> {
+        self.executor.as_ref()
+    }
+
+    /// Look CDI devices up in these directories instead of the defaults
+    pub fn with_cdi_spec_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.cdi_spec_dirs = dirs;
+        self
+    }
+
+    /// Run these hooks in every container, before the container's own
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Confine containers with a security module
+    pub fn with_lsm(mut self, lsm: Lsm) -> Self {
+        self.lsm = Some(lsm);
+        self
+    }
+
+    /// Security module confining containers
+    pub fn lsm(&self) -> Option<Lsm> {
+        self.lsm
+    }
+
+    /// Look log drivers other than the built-in ones up in plugins
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Create volumes for anonymous mounts in a volume manager
+    pub fn with_volumes(mut self, volumes: Arc<VolumeManager>) -> Self {
+        self.volumes = Some(volumes);
+        self
+    }
+
+    /// Connect containers to the networks of a network manager when they
+    /// are created, and disconnect them when they are removed
+    pub fn with_networks(mut self, networks: Arc<NetworkManager>) -> Self {
+        self.networks = Some(networks);
+        self
+    }
+
+    /// Check a log config's driver is built in or an enabled log plugin
+    pub fn validate_log_config(&self, log_config: &LogConfig) -> Result<()> {
+        match &self.plugins {
+            Some(plugins) if !log_config.is_builtin() => {
+                plugins.log_driver(&log_config.driver).map(|_| ())
+            }
+            _ => log_config.validate(),
+        }
+    }
+
     /// Create a new container
-    pub fn create(&self, config: ContainerConfig) -> Result<String> {
-        let container = Container::new(config, &self.base_path)?;
+    pub fn create(&self, mut config: ContainerConfig) -> Result<String> {
+        config.name = config.name.trim_start_matches('/').to_string();
+        // Checked again once the container is added, but before any of its
+        // volumes are created
+        if let Some(existing) = self
+            .names
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?
+            .get(&config.name)
+        {
+            return Err(name_in_use(&config.name, existing));
+        }
+        if let Some(log_config) = &config.log_config {
+            self.validate_log_config(log_config)?;
+        }
+        config.capabilities()?;
+        config.seccomp()?;
+        config.hooks.validate()?;
+        config.validate_limits()?;
+        config.stop_signal()?;
+        let label_options = LabelOptions::from_security_opts(&config.security_opt)?;
+        config.security_labels = SecurityLabels::new(self.lsm, &label_options, config.privileged);
+        if self.lsm == Some(Lsm::AppArmor) {
+            lsm::ensure_apparmor_profile(&config.security_labels.apparmor_profile)?;
+        }
+        let userns_host = config.userns_host()?;
+        let remap = match self.userns_remap.as_ref() {
+            Some(_) if userns_host => None,
+            Some(_) if config.privileged => return Err(RuneError::InvalidConfig(
+                "Privileged containers must use --userns=host when user namespaces are remapped"
+                    .to_string(),
+            )),
+            remap => remap.cloned(),
+        };
+        if let Some(name) = config
+            .cdi_devices
+            .iter()
+            .find(|name| !cdi::is_qualified_name(name))
+        {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid CDI device '{}'; expected vendor.com/class=name",
+                name
+            )));
+        }
+        self.create_anonymous_volumes(&mut config)?;
+        let mut container = Container::new(config, &self.base_path)?;
+        container.userns_remap = remap;
+        container.cdi_spec_dirs = self.cdi_spec_dirs.clone();
+        container.daemon_hooks = self.hooks.clone();
+        // Unknown devices fail now rather than when the container starts
+        if let Some((registry, names)) = container.cdi_devices()? {
+            registry.check(&names)?;
+        }
         let id = container.id().to_string();
+        self.connect_networks(&mut container.config)?;
 
         let mut containers = self
             .containers
@@ -39,13 +261,297 @@ impl ContainerManager {
         if containers.contains_key(&id) {
             return Err(RuneError::ContainerExists(id));
         }
+        let name = container.name().to_string();
+        if !name.is_empty() {
+            let mut names = self
+                .names
+                .write()
+                .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+            if let Some(existing) = names.get(&name) {
+                return Err(name_in_use(&name, existing));
+            }
+            names.insert(name, id.clone());
+        }
 
         containers.insert(id.clone(), container);
         Ok(id)
     }
 
+    /// ID of the container with an ID, a name, or an ID prefix no other
+    /// container's ID starts with
+    pub fn resolve(&self, id_or_name: &str) -> Result<String> {
+        let containers = self
+            .containers
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
+        if containers.contains_key(id_or_name) {
+            return Ok(id_or_name.to_string());
+        }
+        let names = self
+            .names
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
+        if let Some(id) = names.get(id_or_name.trim_start_matches('/')) {
+            return Ok(id.clone());
+        }
+        if id_or_name.is_empty() {
+            return Err(RuneError::ContainerNotFound(id_or_name.to_string()));
+        }
+
+        let mut matches = containers.keys().filter(|id| id.starts_with(id_or_name));
+        match (matches.next(), matches.next()) {
+            (Some(id), None) => Ok(id.clone()),
+            (Some(_), Some(_)) => Err(RuneError::new(
+                ErrorKind::InvalidArgument,
+                format!(
+                    "Multiple containers found with ID prefix {}; give more of the ID",
+                    id_or_name
+                ),
+            )),
+            _ => Err(RuneError::ContainerNotFound(id_or_name.to_string())),
+        }
+    }
+
+    /// Connect a container to its networks with the settings it asks for,
+    /// recording what the networks allocated
+    fn connect_networks(&self, config: &mut ContainerConfig) -> Result<()> {
+        let Some(networks) = &self.networks else {
+            return Ok(());
+        };
+        let Some(primary) = config.network().map(str::to_string) else {
+            if let Some(network) = config.endpoints.keys().next() {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Containers with network mode {} can't join network {}",
+                    config.network_mode, network
+                )));
+            }
+            return Ok(());
+        };
+        let mut names: Vec<String> = config
+            .endpoints
+            .keys()
+            .filter(|name| **name != primary)
+            .cloned()
+            .collect();
+        names.sort();
+        names.insert(0, primary);
+
+        for (index, network) in names.iter().enumerate() {
+            let endpoint = config.endpoints.get(network).cloned().unwrap_or_default();
+            match connect_endpoint(networks, config, network, &endpoint) {
+                Ok(connected) => {
+                    config.endpoints.insert(network.clone(), connected);
+                }
+                Err(e) => {
+                    for network in &names[..index] {
+                        let _ = networks.disconnect(network, &config.id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Give a running container the interfaces of its endpoints, the one
+    /// on its own network first
+    fn attach_networks(&self, config: &ContainerConfig, pid: u32) -> Result<()> {
+        let Some(networks) = &self.networks else {
+            return Ok(());
+        };
+        networks.reset(&config.id)?;
+        let mut names: Vec<&String> = config.endpoints.keys().collect();
+        names.sort_by_key(|name| (Some(name.as_str()) != config.network(), *name));
+        for network in names {
+            networks.attach(network, &config.id, pid)?;
+        }
+        Ok(())
+    }
+
+    /// Connect a container to another network, giving it an interface on
+    /// it right away if it is running
+    pub fn connect_network(&self, id: &str, network: &str, endpoint: EndpointConfig) -> Result<()> {
+        let id = &self.resolve(id)?;
+        let networks = self.networks.as_ref().ok_or_else(|| {
+            RuneError::Network("No network manager to connect containers with".to_string())
+        })?;
+        let network = networks.get(network)?;
+        let config = self.get(id)?;
+        if config.network().is_none() {
+            return Err(RuneError::InvalidConfig(format!(
+                "Containers with network mode {} can't join network {}",
+                config.network_mode, network.name
+            )));
+        }
+        if config.endpoints.contains_key(&network.name) || network.containers.contains_key(id) {
+            return Err(RuneError::Network(format!(
+                "Container {} is already connected to network {}",
+                id, network.name
+            )));
+        }
+
+        let connected = connect_endpoint(networks, &config, &network.name, &endpoint)?;
+        if let (ContainerStatus::Running, Some(pid)) = (&config.status, config.pid) {
+            if let Err(e) = networks.attach(&network.name, id, pid) {
+                let _ = networks.disconnect(&network.name, id);
+                return Err(e);
+            }
+        }
+        let mut containers = self
+            .containers
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        container.config.endpoints.insert(network.name, connected);
+        Ok(())
+    }
+
+    /// Disconnect a container from a network, removing its interface on it
+    /// if it is running; with `force`, the endpoint goes even if the
+    /// interface can't be removed
+    pub fn disconnect_network(&self, id: &str, network: &str, force: bool) -> Result<()> {
+        let id = &self.resolve(id)?;
+        let networks = self.networks.as_ref().ok_or_else(|| {
+            RuneError::Network("No network manager to disconnect containers with".to_string())
+        })?;
+        let network = networks.get(network)?;
+        if !network.containers.contains_key(id) {
+            return Err(RuneError::Network(format!(
+                "Container {} is not connected to network {}",
+                id, network.name
+            )));
+        }
+        if let Err(e) = networks.detach(&network.name, id) {
+            if !force {
+                return Err(e);
+            }
+            warn!(
+                "Failed to remove {}'s interface on {}: {}",
+                id, network.name, e
+            );
+        }
+        networks.disconnect(&network.name, id)?;
+
+        let mut containers = self
+            .containers
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        if let Some(container) = containers.get_mut(id) {
+            container.config.endpoints.remove(&network.name);
+        }
+        Ok(())
+    }
+
+    /// Back mounts without a host path with new volumes
+    fn create_anonymous_volumes(&self, config: &mut ContainerConfig) -> Result<()> {
+        if config
+            .volumes
+            .iter()
+            .all(|mount| !mount.host_path.is_empty())
+        {
+            return Ok(());
+        }
+        let volumes = self.volumes.as_ref().ok_or_else(|| {
+            RuneError::Volume("Anonymous volumes need a volume manager".to_string())
+        })?;
+        for mount in config
+            .volumes
+            .iter_mut()
+            .filter(|mount| mount.host_path.is_empty())
+        {
+            let volume = volumes.create("", None, HashMap::new(), HashMap::new())?;
+            mount.host_path = volume.mountpoint.to_string_lossy().into_owned();
+            config.anonymous_volumes.push(volume.name);
+        }
+        Ok(())
+    }
+
     /// Start a container
     pub fn start(&self, id: &str) -> Result<()> {
+        let id = &self.resolve(id)?;
+        self.launch(id, None, None)
+    }
+
+    /// Start a container for a client that stays attached to it, with its
+    /// standard output and error going to `stdout` and `stderr` unless it
+    /// has a terminal
+    ///
+    /// Its exit is collected, and the container removed if it removes
+    /// itself, by [`Self::wait_attached`], which the client must call.
+    pub fn start_attached(
+        &self,
+        id: &str,
+        stdout: Option<OwnedFd>,
+        stderr: Option<OwnedFd>,
+    ) -> Result<()> {
+        let id = &self.resolve(id)?;
+        self.executor.as_ref().ok_or_else(|| {
+            RuneError::Runtime("Attaching needs containers run with a runtime".to_string())
+        })?;
+        let streams = ProcessStreams {
+            open_stdin: false,
+            stdout,
+            stderr,
+        };
+        self.launch(id, None, Some(streams))
+    }
+
+    /// Wait for a container started attached to exit, returning its exit
+    /// code once it is recorded, and remove it then if it removes itself
+    pub fn wait_attached(&self, id: &str) -> Result<i32> {
+        let id = &self.resolve(id)?;
+        let (exit_code, auto_remove) = loop {
+            {
+                let containers = self
+                    .containers
+                    .read()
+                    .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
+                let container = containers.get(id);
+                if !container.is_some_and(|c| {
+                    matches!(
+                        c.status(),
+                        ContainerStatus::Running | ContainerStatus::Paused
+                    )
+                }) {
+                    // Whoever recorded the exit left the removal to us
+                    if let Ok(mut waited) = self.waited.lock() {
+                        waited.remove(id);
+                    }
+                    let container =
+                        container.ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+                    break (
+                        container.config.exit_code.unwrap_or_default(),
+                        self.auto_removal(container),
+                    );
+                }
+            }
+            thread::sleep(REAP_INTERVAL);
+        };
+        if let Some(anonymous_volumes) = auto_remove {
+            self.auto_remove(id, anonymous_volumes)?;
+        }
+        Ok(exit_code)
+    }
+
+    /// Start a container with its processes restored from a checkpoint,
+    /// looked up in `dir` if given
+    pub fn restore(&self, id: &str, checkpoint: &str, dir: Option<&Path>) -> Result<()> {
+        let id = &self.resolve(id)?;
+        self.executor.as_ref().ok_or_else(no_executor)?;
+        let checkpoint = Checkpoint::load(&self.checkpoint_dir(id, dir), checkpoint)?;
+        self.launch(id, Some(&checkpoint), None)
+    }
+
+    /// Start a container's process, with the standard streams of an
+    /// attached client if given, or restore it from a checkpoint
+    fn launch(
+        &self,
+        id: &str,
+        checkpoint: Option<&Checkpoint>,
+        streams: Option<ProcessStreams>,
+    ) -> Result<()> {
         let mut containers = self
             .containers
             .write()
@@ -55,11 +561,69 @@ impl ContainerManager {
             .get_mut(id)
             .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
 
-        container.start()
+        container.start()?;
+        if let Some(executor) = &self.executor {
+            let attached = streams.is_some();
+            let streams = streams.map(|streams| ProcessStreams {
+                open_stdin: container.config.open_stdin,
+                ..streams
+            });
+            let started = match checkpoint {
+                Some(checkpoint) => executor.restore(
+                    id,
+                    &container.bundle,
+                    &checkpoint.images(),
+                    &checkpoint.options,
+                ),
+                None => executor
+                    .create(id, &container.bundle)
+                    .and_then(|_| match streams {
+                        Some(streams) => executor.set_stdio(id, streams),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| executor.start(id)),
+            };
+            // The process is in its network namespace once it's created
+            let started = started.and_then(|pid| {
+                self.attach_networks(&container.config, pid)
+                    .inspect_err(|_| {
+                        let _ = executor.kill(id, 9);
+                    })
+                    .map(|_| pid)
+            });
+            match started {
+                Ok(pid) => {
+                    container.config.pid = Some(pid);
+                    if attached {
+                        self.waited
+                            .lock()
+                            .map_err(|_| {
+                                RuneError::Lock("Failed to acquire waited lock".to_string())
+                            })?
+                            .insert(id.to_string());
+                    }
+                }
+                Err(e) => {
+                    let _ = executor.delete(id);
+                    container.config.status = ContainerStatus::Created;
+                    container.config.started_at = None;
+                    container.config.health = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
     }
 
-    /// Stop a container
+    /// Stop a container, giving it its own stop timeout to exit
     pub fn stop(&self, id: &str) -> Result<()> {
+        self.stop_with_timeout(id, None)
+    }
+
+    /// Stop a container with its stop signal, killing it if it hasn't
+    /// exited once the timeout, or else its own stop timeout, passes
+    pub fn stop_with_timeout(&self, id: &str, timeout: Option<Duration>) -> Result<()> {
+        let id = &self.resolve(id)?;
         let mut containers = self
             .containers
             .write()
@@ -69,11 +633,70 @@ impl ContainerManager {
             .get_mut(id)
             .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
 
-        container.stop()
+        let mut exit_code = None;
+        if let Some(executor) = self.executor.as_ref().filter(|_| container.is_running()) {
+            let timeout = timeout
+                .or(container.config.stop_timeout.map(Duration::from_secs))
+                .unwrap_or(STOP_TIMEOUT);
+            let signal = container.config.stop_signal()?;
+            (containers, exit_code) =
+                self.stop_unlocked(containers, executor.as_ref(), id, signal, timeout)?;
+        }
+        // The container may have been removed while its process exited
+        let Some(container) = containers.get_mut(id) else {
+            return Ok(());
+        };
+        container.stop()?;
+        if exit_code.is_some() {
+            container.config.exit_code = exit_code;
+        }
+        let auto_remove = self.auto_removal(container);
+        drop(containers);
+
+        match auto_remove {
+            Some(anonymous_volumes) => self.auto_remove(id, anonymous_volumes),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop a container's process without holding the containers' lock
+    /// while it exits, so the rest of the daemon isn't blocked for its
+    /// grace period; returns the lock, taken again, and the exit code the
+    /// executor reports
+    fn stop_unlocked<'a>(
+        &'a self,
+        containers: Containers<'a>,
+        executor: &dyn Executor,
+        id: &str,
+        signal: i32,
+        timeout: Duration,
+    ) -> Result<(Containers<'a>, Option<i32>)> {
+        let stopping = || {
+            self.stopping
+                .lock()
+                .map_err(|_| RuneError::Lock("Failed to acquire stopping lock".to_string()))
+        };
+        if !stopping()?.insert(id.to_string()) {
+            return Err(RuneError::new(
+                ErrorKind::Conflict,
+                format!("Container {} is already being stopped", id),
+            ));
+        }
+        drop(containers);
+
+        let exit_code = stop_process(executor, id, signal, timeout);
+
+        // The reaper is only let back at the container once it's locked
+        let containers = self.containers.write();
+        stopping()?.remove(id);
+        let containers =
+            containers.map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        Ok((containers, exit_code?))
     }
 
     /// Pause a container
     pub fn pause(&self, id: &str) -> Result<()> {
+        let id = &self.resolve(id)?;
         let mut containers = self
             .containers
             .write()
@@ -83,11 +706,17 @@ impl ContainerManager {
             .get_mut(id)
             .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
 
+        if let Some(executor) = &self.executor {
+            if container.is_running() {
+                executor.pause(id)?;
+            }
+        }
         container.pause()
     }
 
     /// Unpause a container
     pub fn unpause(&self, id: &str) -> Result<()> {
+        let id = &self.resolve(id)?;
         let mut containers = self
             .containers
             .write()
@@ -97,11 +726,21 @@ impl ContainerManager {
             .get_mut(id)
             .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
 
+        if let Some(executor) = &self.executor {
+            if container.status() == ContainerStatus::Paused {
+                executor.resume(id)?;
+            }
+        }
         container.unpause()
     }
 
-    /// Kill a container
+    /// Send a signal to a container's process, SIGKILL if none is given
+    ///
+    /// Only SIGKILL is waited for; a process may handle other signals, and
+    /// its exit is recorded when it is reaped if it doesn't.
     pub fn kill(&self, id: &str, signal: Option<i32>) -> Result<()> {
+        let id = &self.resolve(id)?;
+        let signal = signal.unwrap_or(libc::SIGKILL);
         let mut containers = self
             .containers
             .write()
@@ -111,11 +750,212 @@ impl ContainerManager {
             .get_mut(id)
             .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
 
-        container.kill(signal)
+        let auto_remove = match &self.executor {
+            None => {
+                container.kill(Some(signal))?;
+                self.auto_removal(container)
+            }
+            Some(executor) => {
+                if !matches!(
+                    container.status(),
+                    ContainerStatus::Running | ContainerStatus::Paused
+                ) {
+                    return Err(RuneError::ContainerNotRunning(id.to_string()));
+                }
+                if signal != libc::SIGKILL {
+                    return signal_process(executor.as_ref(), id, signal);
+                }
+                let exit_code;
+                (containers, exit_code) =
+                    self.stop_unlocked(containers, executor.as_ref(), id, signal, STOP_TIMEOUT)?;
+                let Some(container) = containers.get_mut(id) else {
+                    return Ok(());
+                };
+                self.record_exit(container, exit_code.or(Some(128 + signal)))
+            }
+        };
+        drop(containers);
+
+        match auto_remove {
+            Some(anonymous_volumes) => self.auto_remove(id, anonymous_volumes),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the exit of a container's process, returning its anonymous
+    /// volumes if the container removes itself now
+    fn record_exit(
+        &self,
+        container: &mut Container,
+        exit_code: Option<i32>,
+    ) -> Option<Vec<String>> {
+        container.exited(exit_code);
+        self.auto_removal(container)
+    }
+
+    /// Anonymous volumes of a container that no longer runs, if it removes
+    /// itself now; a container started attached is removed once its exit
+    /// is collected instead. Call this with the containers locked.
+    fn auto_removal(&self, container: &Container) -> Option<Vec<String>> {
+        let waited = self
+            .waited
+            .lock()
+            .is_ok_and(|waited| waited.contains(container.id()));
+        (container.config.auto_remove && !waited)
+            .then(|| container.config.anonymous_volumes.clone())
+    }
+
+    /// Side of a running container's standard input pipe, for the one
+    /// caller that attaches to it
+    pub fn stdin(&self, id: &str) -> Result<OwnedFd> {
+        let id = &self.resolve(id)?;
+        let executor = self.executor.as_ref().ok_or_else(|| {
+            RuneError::Runtime("Standard input needs containers run with a runtime".to_string())
+        })?;
+        executor.stdin(id)
+    }
+
+    /// Master side of the terminal of a running container's process, for
+    /// the one caller that attaches to it
+    pub fn console(&self, id: &str) -> Result<OwnedFd> {
+        let id = &self.resolve(id)?;
+        let executor = self.executor.as_ref().ok_or_else(|| {
+            RuneError::Runtime("Terminals need containers run with a runtime".to_string())
+        })?;
+        let containers = self
+            .containers
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
+        let container = containers
+            .get(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        if !container.config.tty {
+            return Err(RuneError::Container(format!(
+                "Container {} has no terminal",
+                id
+            )));
+        }
+        executor.console(id)
+    }
+
+    /// Wait for a running container's process to exit and record it,
+    /// returning its exit code
+    pub fn wait(&self, id: &str) -> Result<i32> {
+        let id = &self.resolve(id)?;
+        let executor = self.executor.as_ref().ok_or_else(|| {
+            RuneError::Runtime("Waiting needs containers run with a runtime".to_string())
+        })?;
+        // The lock isn't held while the process runs
+        let exit_code = executor.wait(id)?;
+        executor.delete(id)?;
+        self.finish(id, Some(exit_code))?;
+        Ok(exit_code)
+    }
+
+    /// Record the exit of running containers whose processes exited,
+    /// removing those that remove themselves, and return their IDs
+    pub fn reap(&self) -> Result<Vec<String>> {
+        let Some(executor) = &self.executor else {
+            return Ok(Vec::new());
+        };
+        let running: Vec<String> = self
+            .containers
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?
+            .values()
+            .filter(|container| container.is_running())
+            .map(|container| container.id().to_string())
+            .collect();
+
+        let mut exited = Vec::new();
+        for id in running {
+            // The lock is held, and containers being stopped are skipped,
+            // so that stopping or killing the container doesn't race the
+            // exit being recorded
+            let auto_remove = {
+                let mut containers = self
+                    .containers
+                    .write()
+                    .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+                let Some(container) = containers.get_mut(&id).filter(|c| c.is_running()) else {
+                    continue;
+                };
+                if self
+                    .stopping
+                    .lock()
+                    .map_err(|_| RuneError::Lock("Failed to acquire stopping lock".to_string()))?
+                    .contains(&id)
+                {
+                    continue;
+                }
+                match executor.state(&id) {
+                    Ok(state) if state.is_stopped() => {}
+                    _ => continue,
+                }
+                // Runtimes that can't wait for the process report no exit
+                // code
+                let exit_code = executor.wait(&id).ok();
+                let _ = executor.delete(&id);
+                self.record_exit(container, exit_code)
+            };
+            if let Some(anonymous_volumes) = auto_remove {
+                self.auto_remove(&id, anonymous_volumes)?;
+            }
+            exited.push(id);
+        }
+        Ok(exited)
+    }
+
+    /// Reap exited containers periodically, on a thread of their own
+    pub fn spawn_reaper(self: Arc<Self>) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            if let Err(e) = self.reap() {
+                warn!("Failed to reap containers: {}", e);
+            }
+            thread::sleep(REAP_INTERVAL);
+        })
+    }
+
+    /// Record the exit of a container's process, removing the container
+    /// and its anonymous volumes if it removes itself
+    fn finish(&self, id: &str, exit_code: Option<i32>) -> Result<()> {
+        let auto_remove = {
+            let mut containers = self
+                .containers
+                .write()
+                .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+            let container = containers
+                .get_mut(id)
+                .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+            self.record_exit(container, exit_code)
+        };
+        match auto_remove {
+            Some(anonymous_volumes) => self.auto_remove(id, anonymous_volumes),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove an exited container that removes itself, with its anonymous
+    /// volumes
+    fn auto_remove(&self, id: &str, anonymous_volumes: Vec<String>) -> Result<()> {
+        self.remove(id, false)?;
+        info!("Removed container {} after it exited", id);
+        if let Some(volumes) = &self.volumes {
+            for name in anonymous_volumes {
+                if let Err(e) = volumes.remove(&name, true) {
+                    warn!(
+                        "Failed to remove volume {} of container {}: {}",
+                        name, id, e
+                    );
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Remove a container
     pub fn remove(&self, id: &str, force: bool) -> Result<()> {
+        let id = &self.resolve(id)?;
         let mut containers = self
             .containers
             .write()
@@ -130,13 +970,175 @@ impl ContainerManager {
         }
 
         container.remove()?;
-        containers.remove(id);
+        if let Some(executor) = &self.executor {
+            let _ = executor.delete(id);
+        }
+        if let Some(removed) = containers.remove(id) {
+            if let Ok(mut names) = self.names.write() {
+                names.remove(&removed.config.name);
+            }
+            if let Some(networks) = &self.networks {
+                for network in removed.config.endpoints.keys() {
+                    if let Err(e) = networks.disconnect(network, id) {
+                        warn!("Failed to disconnect {} from {}: {}", id, network, e);
+                    }
+                }
+            }
+        }
+        if let Ok(mut drivers) = self.log_drivers.write() {
+            drivers.remove(id);
+        }
 
         Ok(())
     }
 
+    /// Checkpoint a running container into `dir` if given, stopping it
+    /// unless the options leave it running
+    pub fn checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        dir: Option<&Path>,
+        options: CheckpointOptions,
+    ) -> Result<Checkpoint> {
+        let id = &self.resolve(id)?;
+        let executor = self.executor.as_ref().ok_or_else(no_executor)?;
+        let mut containers = self
+            .containers
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        if !container.is_running() {
+            return Err(RuneError::ContainerNotRunning(id.to_string()));
+        }
+
+        let checkpoint = Checkpoint::create(&self.checkpoint_dir(id, dir), name, id, options)?;
+        if let Err(e) = executor.checkpoint(id, &checkpoint.images(), &checkpoint.options) {
+            let _ = std::fs::remove_dir_all(&checkpoint.path);
+            return Err(e);
+        }
+        checkpoint.save()?;
+
+        if !checkpoint.options.leave_running {
+            let _ = executor.delete(id);
+            container.stop()?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Checkpoints of a container, in `dir` if given
+    pub fn checkpoints(&self, id: &str, dir: Option<&Path>) -> Result<Vec<Checkpoint>> {
+        let id = &self.resolve(id)?;
+        let config = self.get(id)?;
+        Checkpoint::list(&self.checkpoint_dir(&config.id, dir))
+    }
+
+    /// Delete a checkpoint of a container, in `dir` if given
+    pub fn remove_checkpoint(&self, id: &str, name: &str, dir: Option<&Path>) -> Result<()> {
+        let id = &self.resolve(id)?;
+        let config = self.get(id)?;
+        Checkpoint::remove(&self.checkpoint_dir(&config.id, dir), name)
+    }
+
+    /// Directory of a container's checkpoints
+    fn checkpoint_dir(&self, id: &str, dir: Option<&Path>) -> PathBuf {
+        dir.map(Path::to_path_buf)
+            .unwrap_or_else(|| self.base_path.join(id).join("checkpoints"))
+    }
+
+    /// Record a healthcheck result, returning the container's new health
+    /// status when it changed
+    ///
+    /// Results of checks that began before the container last started are
+    /// ignored.
+    pub fn record_health(
+        &self,
+        id: &str,
+        result: HealthcheckResult,
+    ) -> Result<Option<HealthStatus>> {
+        let mut containers = self
+            .containers
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+
+        let config = &mut containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?
+            .config;
+
+        let (Some(healthcheck), Some(started_at), Some(health)) =
+            (&config.healthcheck, config.started_at, &mut config.health)
+        else {
+            return Ok(None);
+        };
+        if config.status != ContainerStatus::Running || result.start < started_at {
+            return Ok(None);
+        }
+        Ok(health.record(result, healthcheck, started_at))
+    }
+
+    /// Log driver config of a container, falling back to the default
+    pub fn log_config(&self, id: &str) -> Result<LogConfig> {
+        let id = &self.resolve(id)?;
+        Ok(self
+            .get(id)?
+            .log_config
+            .unwrap_or_else(|| self.default_log_config.clone()))
+    }
+
+    /// Write a line of a container's output to its log driver
+    pub fn write_log(&self, id: &str, stream: &str, message: &str) -> Result<()> {
+        let id = &self.resolve(id)?;
+        self.log_driver(id)?.log(&LogLine {
+            timestamp: Utc::now(),
+            stream: stream.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    /// Read a container's logs back from its log driver
+    pub fn read_logs(&self, request: &LogRequest) -> Result<Vec<LogLine>> {
+        self.log_driver(&request.container_id)?.read(request)
+    }
+
+    /// Open log driver of a container, opening it on first use
+    fn log_driver(&self, id: &str) -> Result<Arc<dyn LogDriver>> {
+        let config = self.get(id)?;
+        if let Some(driver) = self
+            .log_drivers
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?
+            .get(&config.id)
+        {
+            return Ok(driver.clone());
+        }
+
+        let log_config = config
+            .log_config
+            .clone()
+            .unwrap_or_else(|| self.default_log_config.clone());
+        let dir = self.base_path.join(&config.id);
+        let driver: Arc<dyn LogDriver> = match &self.plugins {
+            Some(plugins) if !log_config.is_builtin() => Arc::new(
+                plugins
+                    .log_driver(&log_config.driver)?
+                    .open(&config, &log_config, &dir)?,
+            ),
+            _ => log_config.open(&config, &dir)?.into(),
+        };
+        self.log_drivers
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?
+            .insert(config.id.clone(), driver.clone());
+        Ok(driver)
+    }
+
     /// Get container by ID
     pub fn get(&self, id: &str) -> Result<ContainerConfig> {
+        let id = &self.resolve(id)?;
         let containers = self
             .containers
             .read()
@@ -164,19 +1166,82 @@ impl ContainerManager {
         Ok(result)
     }
 
+    /// List containers matching filters; filtering on status lists
+    /// stopped containers too
+    pub fn list_filtered(&self, all: bool, filters: &Filters) -> Result<Vec<ContainerConfig>> {
+        filters.validate(CONTAINER_FILTERS)?;
+        for status in filters.get("status") {
+            if !STATUSES.iter().any(|s| s.to_string() == *status) {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Invalid filter 'status={}'",
+                    status
+                )));
+            }
+        }
+        for health in filters.get("health") {
+            if !["starting", "healthy", "unhealthy", "none"].contains(&health.as_str()) {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Invalid filter 'health={}'",
+                    health
+                )));
+            }
+        }
+
+        let containers = self.list(true)?;
+        // Containers `before` and `since` refer to
+        let created_at = |key: &str| -> Result<Vec<_>> {
+            filters
+                .get(key)
+                .iter()
+                .map(|reference| {
+                    containers
+                        .iter()
+                        .find(|c| c.name == *reference)
+                        .or_else(|| {
+                            containers
+                                .iter()
+                                .find(|c| c.id.starts_with(reference.as_str()))
+                        })
+                        .map(|c| c.created_at)
+                        .ok_or_else(|| RuneError::ContainerNotFound(reference.clone()))
+                })
+                .collect()
+        };
+        let before = created_at("before")?;
+        let since = created_at("since")?;
+
+        let all = all || filters.contains("status");
+        Ok(containers
+            .into_iter()
+            .filter(|c| all || c.status == ContainerStatus::Running)
+            .filter(|c| {
+                filters.matches("id", |id| c.id.starts_with(id))
+                    && filters.matches("name", |name| c.name.contains(name.trim_start_matches('/')))
+                    && filters.matches_labels(&c.labels)
+                    && filters.matches_exact("status", &c.status.to_string())
+                    && filters.matches("ancestor", |image| filter::image_matches(&c.image, image))
+                    && filters.matches_exact(
+                        "health",
+                        &c.health
+                            .as_ref()
+                            .map(|h| h.status.to_string())
+                            .unwrap_or_else(|| "none".to_string()),
+                    )
+                    && before.iter().all(|t| c.created_at < *t)
+                    && since.iter().all(|t| c.created_at > *t)
+            })
+            .collect())
+    }
+
     /// Find container by name
     pub fn find_by_name(&self, name: &str) -> Result<Option<ContainerConfig>> {
-        let containers = self
-            .containers
+        let id = self
+            .names
             .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
-
-        let result = containers
-            .values()
-            .find(|c| c.config.name == name)
-            .map(|c| c.config.clone());
-
-        Ok(result)
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?
+            .get(name.trim_start_matches('/'))
+            .cloned();
+        id.map(|id| self.get(&id)).transpose()
     }
 
     /// Get container count
@@ -204,3 +1269,809 @@ impl ContainerManager {
         Ok(count)
     }
 }
+
+/// Stop a container's process with a signal, killing it if it outlives the
+/// timeout, and delete it from the executor, returning its exit code if the
+/// executor reports one
+fn stop_process(
+    executor: &dyn Executor,
+    id: &str,
+    signal: i32,
+    timeout: Duration,
+) -> Result<Option<i32>> {
+    signal_process(executor, id, signal)?;
+    let deadline = Instant::now() + timeout;
+    while !executor.state(id)?.is_stopped() {
+        if Instant::now() >= deadline {
+            signal_process(executor, id, libc::SIGKILL)?;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    // Nothing outlives SIGKILL, so this doesn't wait long
+    let exit_code = executor.wait(id).ok();
+    executor.delete(id)?;
+    Ok(exit_code)
+}
+
+/// Error of a container name another container has
+fn name_in_use(name: &str, id: &str) -> RuneError {
+    RuneError::new(
+        ErrorKind::Conflict,
+        format!(
+            "The container name \"/{}\" is already in use by container \"{}\"; remove or rename that container to reuse the name",
+            name, id
+        ),
+    )
+}
+
+/// Send a signal to a container's process, which may already have exited
+fn signal_process(executor: &dyn Executor, id: &str, signal: i32) -> Result<()> {
+    match executor.kill(id, signal) {
+        Err(_) if executor.state(id)?.is_stopped() => Ok(()),
+        result => result,
+    }
+}
+
+/// Connect a container to a network, returning the endpoint the network
+/// allocated
+fn connect_endpoint(
+    networks: &NetworkManager,
+    config: &ContainerConfig,
+    network: &str,
+    endpoint: &EndpointConfig,
+) -> Result<EndpointConfig> {
+    let connected = networks.connect(network, &config.id, &config.name, endpoint)?;
+    Ok(EndpointConfig {
+        ipv4_address: connected
+            .ipv4_address
+            .as_deref()
+            .and_then(|address| address.split('/').next())
+            .map(str::to_string),
+        mac_address: Some(connected.mac_address),
+        aliases: connected.aliases,
+    })
+}
+
+fn no_executor() -> RuneError {
+    RuneError::Runtime("Checkpoints need the daemon to run containers with a runtime".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::oci::State;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// Executor that only records what it is asked to do
+    #[derive(Default)]
+    struct RecordingExecutor {
+        calls: Mutex<Vec<String>>,
+        /// Processes that ignore every signal but SIGKILL, if they're
+        /// tracked; untracked processes are always reported stopped
+        stubborn: Option<Mutex<HashSet<String>>>,
+    }
+
+    impl RecordingExecutor {
+        fn record(&self, call: String) -> Result<()> {
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    impl Executor for RecordingExecutor {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        fn path(&self) -> PathBuf {
+            PathBuf::from("/bin/true")
+        }
+
+        fn create(&self, id: &str, _bundle: &Path) -> Result<()> {
+            self.record(format!("create {}", id))
+        }
+
+        fn start(&self, id: &str) -> Result<u32> {
+            self.record(format!("start {}", id))?;
+            if let Some(stubborn) = &self.stubborn {
+                stubborn.lock().unwrap().insert(id.to_string());
+            }
+            Ok(100)
+        }
+
+        fn set_stdio(&self, id: &str, stdio: ProcessStreams) -> Result<()> {
+            self.record(format!(
+                "stdio {} stdin={} stdout={}",
+                id,
+                stdio.open_stdin,
+                stdio.stdout.is_some()
+            ))
+        }
+
+        fn state(&self, id: &str) -> Result<State> {
+            let running = self
+                .stubborn
+                .as_ref()
+                .is_some_and(|stubborn| stubborn.lock().unwrap().contains(id));
+            Ok(State {
+                oci_version: String::new(),
+                id: id.to_string(),
+                status: if running { "running" } else { "stopped" }.to_string(),
+                pid: 0,
+                bundle: String::new(),
+            })
+        }
+
+        fn kill(&self, id: &str, signal: i32) -> Result<()> {
+            if let Some(stubborn) = self.stubborn.as_ref().filter(|_| signal == libc::SIGKILL) {
+                stubborn.lock().unwrap().remove(id);
+            }
+            self.record(format!("kill {} {}", id, signal))
+        }
+
+        fn delete(&self, id: &str) -> Result<()> {
+            self.record(format!("delete {}", id))
+        }
+
+        fn pause(&self, id: &str) -> Result<()> {
+            self.record(format!("pause {}", id))
+        }
+
+        fn resume(&self, id: &str) -> Result<()> {
+            self.record(format!("resume {}", id))
+        }
+
+        fn checkpoint(&self, id: &str, images: &Path, _options: &CheckpointOptions) -> Result<()> {
+            std::fs::create_dir_all(images)?;
+            self.record(format!("checkpoint {}", id))
+        }
+
+        fn restore(
+            &self,
+            id: &str,
+            _bundle: &Path,
+            images: &Path,
+            _options: &CheckpointOptions,
+        ) -> Result<u32> {
+            assert!(images.exists());
+            self.record(format!("restore {}", id))?;
+            Ok(200)
+        }
+
+        fn wait(&self, id: &str) -> Result<i32> {
+            self.record(format!("wait {}", id))?;
+            Ok(3)
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_and_restore() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor::default());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(executor.clone());
+        let id = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+
+        let options = CheckpointOptions::default();
+        assert!(manager
+            .checkpoint(&id, "cp1", None, options.clone())
+            .is_err());
+        manager.start(&id).unwrap();
+        assert_eq!(manager.get(&id).unwrap().pid, Some(100));
+
+        manager
+            .checkpoint(&id, "cp1", None, options.clone())
+            .unwrap();
+        assert_eq!(manager.get(&id).unwrap().status, ContainerStatus::Stopped);
+        let checkpoints = manager.checkpoints(&id, None).unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].name, "cp1");
+
+        manager.restore(&id, "cp1", None).unwrap();
+        assert_eq!(manager.get(&id).unwrap().status, ContainerStatus::Running);
+        assert_eq!(manager.get(&id).unwrap().pid, Some(200));
+
+        // A checkpoint directory keeps checkpoints apart from the container
+        let exported = dir.path().join("exported");
+        let leave_running = CheckpointOptions {
+            leave_running: true,
+            ..options
+        };
+        manager
+            .checkpoint(&id, "cp2", Some(&exported), leave_running)
+            .unwrap();
+        assert!(manager.get(&id).unwrap().status == ContainerStatus::Running);
+        assert!(exported.join("cp2/checkpoint.json").exists());
+        assert_eq!(manager.checkpoints(&id, None).unwrap().len(), 1);
+
+        manager.remove_checkpoint(&id, "cp1", None).unwrap();
+        assert!(manager.checkpoints(&id, None).unwrap().is_empty());
+        assert!(manager.restore(&id, "cp1", None).is_err());
+
+        let calls = executor.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            [
+                format!("create {}", id),
+                format!("start {}", id),
+                format!("checkpoint {}", id),
+                format!("delete {}", id),
+                format!("restore {}", id),
+                format!("checkpoint {}", id),
+            ]
+        );
+
+        let untracked = ContainerManager::new(dir.path().join("untracked")).unwrap();
+        let id = untracked
+            .create(ContainerConfig::new("db", "redis"))
+            .unwrap();
+        untracked.start(&id).unwrap();
+        assert!(untracked
+            .checkpoint(&id, "cp1", None, CheckpointOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_wait_and_console() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor::default());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(executor.clone());
+        let mut config = ContainerConfig::new("shell", "alpine");
+        config.tty = true;
+        let shell = manager.create(config).unwrap();
+        let web = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+        manager.start(&shell).unwrap();
+
+        // Only containers with a terminal have one to attach to, and the
+        // recording executor hands over none
+        assert!(manager.console(&web).is_err());
+        assert!(manager.console(&shell).is_err());
+
+        assert_eq!(manager.wait(&shell).unwrap(), 3);
+        let exited = manager.get(&shell).unwrap();
+        assert_eq!(exited.status, ContainerStatus::Exited);
+        assert_eq!(exited.exit_code, Some(3));
+        assert_eq!(exited.pid, None);
+        assert!(executor
+            .calls
+            .lock()
+            .unwrap()
+            .ends_with(&[format!("wait {}", shell), format!("delete {}", shell)]));
+
+        let untracked = ContainerManager::new(dir.path().join("untracked")).unwrap();
+        let id = untracked
+            .create(ContainerConfig::new("db", "redis"))
+            .unwrap();
+        assert!(untracked.wait(&id).is_err());
+    }
+
+    #[test]
+    fn test_resolve_names_and_prefixes() {
+        let dir = TempDir::new().unwrap();
+        let manager = ContainerManager::new(dir.path().join("containers")).unwrap();
+        let mut config = ContainerConfig::new("/web", "nginx");
+        config.id = "abc123".to_string();
+        let web = manager.create(config).unwrap();
+        let mut config = ContainerConfig::new("db", "redis");
+        config.id = "abd456".to_string();
+        let db = manager.create(config).unwrap();
+
+        let err = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert!(err.to_string().contains(&web));
+        assert_eq!(manager.count().unwrap(), 2);
+
+        assert_eq!(manager.resolve("abc123").unwrap(), web);
+        assert_eq!(manager.resolve("web").unwrap(), web);
+        assert_eq!(manager.resolve("/web").unwrap(), web);
+        assert_eq!(manager.resolve("abd").unwrap(), db);
+        assert_eq!(
+            manager.resolve("ab").unwrap_err().kind(),
+            ErrorKind::InvalidArgument
+        );
+        assert!(matches!(
+            manager.resolve("xyz"),
+            Err(RuneError::ContainerNotFound(_))
+        ));
+        assert!(manager.resolve("").is_err());
+        assert_eq!(manager.get("db").unwrap().id, db);
+        assert_eq!(manager.get(&web).unwrap().name, "web");
+        assert_eq!(manager.find_by_name("/db").unwrap().unwrap().id, db);
+
+        // Removing a container frees its name
+        manager.remove("web", false).unwrap();
+        assert!(manager.find_by_name("web").unwrap().is_none());
+        let web = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+        manager.start("web").unwrap();
+        assert_eq!(manager.get(&web).unwrap().status, ContainerStatus::Running);
+    }
+
+    #[test]
+    fn test_stop_and_kill_signals() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor::default());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(executor.clone());
+
+        let mut config = ContainerConfig::new("web", "nginx");
+        config.stop_signal = Some("SIGNOPE".to_string());
+        assert!(manager.create(config.clone()).is_err());
+        config.stop_signal = Some("SIGQUIT".to_string());
+        let id = manager.create(config).unwrap();
+
+        manager.start(&id).unwrap();
+        manager
+            .stop_with_timeout(&id, Some(Duration::ZERO))
+            .unwrap();
+        let stopped = manager.get(&id).unwrap();
+        assert_eq!(stopped.status, ContainerStatus::Stopped);
+        assert_eq!(stopped.exit_code, Some(3));
+
+        // Signals other than SIGKILL leave the process to exit, or not
+        manager.start(&id).unwrap();
+        manager.kill(&id, Some(libc::SIGHUP)).unwrap();
+        assert_eq!(manager.get(&id).unwrap().status, ContainerStatus::Running);
+        manager.kill(&id, None).unwrap();
+        let killed = manager.get(&id).unwrap();
+        assert_eq!(killed.status, ContainerStatus::Exited);
+        assert_eq!(killed.exit_code, Some(3));
+        assert!(manager.kill(&id, None).is_err());
+
+        let calls = executor.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls[2..],
+            [
+                format!("kill {} {}", id, libc::SIGQUIT),
+                format!("wait {}", id),
+                format!("delete {}", id),
+                format!("create {}", id),
+                format!("start {}", id),
+                format!("kill {} {}", id, libc::SIGHUP),
+                format!("kill {} {}", id, libc::SIGKILL),
+                format!("wait {}", id),
+                format!("delete {}", id),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stop_without_blocking() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor {
+            stubborn: Some(Mutex::default()),
+            ..RecordingExecutor::default()
+        });
+        let manager = Arc::new(
+            ContainerManager::new(dir.path().join("containers"))
+                .unwrap()
+                .with_executor(executor.clone()),
+        );
+        let id = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+        manager.start(&id).unwrap();
+
+        let stopping = thread::spawn({
+            let (manager, id) = (manager.clone(), id.clone());
+            move || manager.stop_with_timeout(&id, Some(Duration::from_secs(1)))
+        });
+        while !executor
+            .calls
+            .lock()
+            .unwrap()
+            .contains(&format!("kill {} {}", id, libc::SIGTERM))
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The container can be looked at, and isn't reaped or stopped
+        // twice, while its process is given time to exit
+        let started = Instant::now();
+        assert_eq!(manager.get(&id).unwrap().status, ContainerStatus::Running);
+        assert!(manager.list(true).is_ok());
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(manager.reap().unwrap().is_empty());
+        assert!(manager.stop(&id).is_err());
+
+        stopping.join().unwrap().unwrap();
+        assert_eq!(manager.get(&id).unwrap().status, ContainerStatus::Stopped);
+        assert!(executor
+            .calls
+            .lock()
+            .unwrap()
+            .contains(&format!("kill {} {}", id, libc::SIGKILL)));
+    }
+
+    #[test]
+    fn test_reap_auto_remove() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor::default());
+        let volumes = Arc::new(VolumeManager::new(dir.path().join("volumes")).unwrap());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(executor.clone())
+            .with_volumes(volumes.clone());
+
+        let mut config = ContainerConfig::new("scratch", "alpine");
+        config.auto_remove = true;
+        config.add_volume("/data").unwrap();
+        config.add_volume("/srv/www:/www:ro").unwrap();
+        config
+            .add_volume("/srv/logs:/logs:Z,rshared,nocopy")
+            .unwrap();
+        assert!(config.add_volume("/srv/www:/www:rx").is_err());
+        assert!(config.add_volume("/srv/www:/www:ro,rw").is_err());
+        assert!(config.add_volume("data").is_err());
+        let scratch = manager.create(config).unwrap();
+        let created = manager.get(&scratch).unwrap();
+        assert_eq!(created.anonymous_volumes.len(), 1);
+        let volume = volumes.get(&created.anonymous_volumes[0]).unwrap();
+        assert_eq!(
+            created.volumes[0].host_path,
+            volume.mountpoint.to_string_lossy()
+        );
+        assert!(created.volumes[1].read_only);
+        assert!(!created.volumes[2].read_only);
+        assert_eq!(created.volumes[2].relabel.as_deref(), Some("Z"));
+        assert_eq!(created.volumes[2].propagation.as_deref(), Some("rshared"));
+
+        let web = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+        manager.start(&scratch).unwrap();
+        manager.start(&web).unwrap();
+
+        // The recording executor reports every process stopped
+        let mut reaped = manager.reap().unwrap();
+        reaped.sort();
+        let mut expected = vec![scratch.clone(), web.clone()];
+        expected.sort();
+        assert_eq!(reaped, expected);
+        assert!(manager.get(&scratch).is_err());
+        assert!(volumes.list().unwrap().is_empty());
+        let exited = manager.get(&web).unwrap();
+        assert_eq!(exited.status, ContainerStatus::Exited);
+        assert_eq!(exited.exit_code, Some(3));
+        assert!(manager.reap().unwrap().is_empty());
+
+        // Anonymous volumes can't be created without a volume manager
+        let untracked = ContainerManager::new(dir.path().join("untracked")).unwrap();
+        let mut config = ContainerConfig::new("db", "redis");
+        config.add_volume("/data").unwrap();
+        assert!(untracked.create(config).is_err());
+    }
+
+    #[test]
+    fn test_stop_and_kill_auto_remove() {
+        let dir = TempDir::new().unwrap();
+        let volumes = Arc::new(VolumeManager::new(dir.path().join("volumes")).unwrap());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(Arc::new(RecordingExecutor::default()))
+            .with_volumes(volumes.clone());
+
+        let mut config = ContainerConfig::new("scratch", "alpine");
+        config.auto_remove = true;
+        config.add_volume("/data").unwrap();
+        let stopped = manager.create(config.clone()).unwrap();
+        manager.start(&stopped).unwrap();
+        manager
+            .stop_with_timeout(&stopped, Some(Duration::ZERO))
+            .unwrap();
+        assert!(manager.get(&stopped).is_err());
+        assert!(volumes.list().unwrap().is_empty());
+
+        config.name = "killed".to_string();
+        let killed = manager.create(config).unwrap();
+        manager.start(&killed).unwrap();
+        manager.kill(&killed, Some(libc::SIGHUP)).unwrap();
+        assert!(manager.get(&killed).is_ok());
+        manager.kill(&killed, None).unwrap();
+        assert!(manager.get(&killed).is_err());
+        assert!(volumes.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_start_attached() {
+        let dir = TempDir::new().unwrap();
+        let executor = Arc::new(RecordingExecutor::default());
+        let volumes = Arc::new(VolumeManager::new(dir.path().join("volumes")).unwrap());
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(executor.clone())
+            .with_volumes(volumes.clone());
+
+        let mut config = ContainerConfig::new("scratch", "alpine");
+        config.auto_remove = true;
+        config.open_stdin = true;
+        config.add_volume("/data").unwrap();
+        let id = manager.create(config).unwrap();
+        let (_, stdout) = std::io::pipe().unwrap();
+        manager
+            .start_attached(&id, Some(stdout.into()), None)
+            .unwrap();
+        assert!(executor
+            .calls
+            .lock()
+            .unwrap()
+            .contains(&format!("stdio {} stdin=true stdout=true", id)));
+
+        // The exit is recorded, but the container is left for its client
+        assert_eq!(manager.reap().unwrap(), vec![id.clone()]);
+        assert_eq!(manager.get(&id).unwrap().exit_code, Some(3));
+        assert_eq!(manager.wait_attached(&id).unwrap(), 3);
+        assert!(manager.get(&id).is_err());
+        assert!(volumes.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_network_endpoints() {
+        use crate::network::NetworkConfig;
+
+        let dir = TempDir::new().unwrap();
+        let networks = Arc::new(NetworkManager::new().unwrap());
+        networks
+            .create(NetworkConfig::new("backend").subnet("10.20.0.0/24"))
+            .unwrap();
+        let manager = ContainerManager::new(dir.path().to_path_buf())
+            .unwrap()
+            .with_networks(networks.clone());
+
+        let mut config = ContainerConfig::new("db", "postgres");
+        config.network_mode = "backend".to_string();
+        config.endpoints.insert(
+            "backend".to_string(),
+            EndpointConfig {
+                ipv4_address: Some("10.20.0.10".to_string()),
+                aliases: vec!["database".to_string()],
+                ..Default::default()
+            },
+        );
+        let db = manager.create(config.clone()).unwrap();
+        let endpoint = &manager.get(&db).unwrap().endpoints["backend"];
+        assert_eq!(endpoint.ipv4_address.as_deref(), Some("10.20.0.10"));
+        assert!(endpoint.mac_address.is_some());
+        assert_eq!(
+            networks.get("backend").unwrap().containers[&db].aliases,
+            ["database"]
+        );
+
+        // The address is taken until the container is removed
+        config.name = "db2".to_string();
+        config.id = "other".to_string();
+        assert!(manager.create(config.clone()).is_err());
+        manager.remove(&db, false).unwrap();
+        assert!(networks.get("backend").unwrap().containers.is_empty());
+        manager.create(config).unwrap();
+
+        // Containers on the host's network have no endpoint
+        let mut host = ContainerConfig::new("agent", "alpine");
+        host.network_mode = "host".to_string();
+        let agent = manager.create(host).unwrap();
+        assert!(manager.get(&agent).unwrap().endpoints.is_empty());
+    }
+
+    /// Interfaces that are only recorded
+    #[derive(Default)]
+    struct RecordingInterfaces {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl crate::network::Interfaces for RecordingInterfaces {
+        fn attach(
+            &self,
+            network: &crate::network::NetworkConfig,
+            _endpoint: &crate::network::config::NetworkContainer,
+            pid: u32,
+            interface: &str,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("attach {} {} {}", network.name, pid, interface));
+            Ok(())
+        }
+
+        fn detach(
+            &self,
+            network: &crate::network::NetworkConfig,
+            endpoint: &crate::network::config::NetworkContainer,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("detach {} {}", network.name, endpoint.interface));
+            Ok(())
+        }
+
+        fn remove(&self, network: &crate::network::NetworkConfig) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("remove {}", network.name));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_connect_running_container() {
+        use crate::network::NetworkConfig;
+
+        let dir = TempDir::new().unwrap();
+        let interfaces = Arc::new(RecordingInterfaces::default());
+        let networks = Arc::new(
+            NetworkManager::new()
+                .unwrap()
+                .with_interfaces(interfaces.clone()),
+        );
+        for (name, subnet) in [("backend", "10.20.0.0/24"), ("frontend", "10.30.0.0/24")] {
+            networks
+                .create(NetworkConfig::new(name).subnet(subnet))
+                .unwrap();
+        }
+        let manager = ContainerManager::new(dir.path().to_path_buf())
+            .unwrap()
+            .with_executor(Arc::new(RecordingExecutor::default()))
+            .with_networks(networks.clone());
+
+        let mut config = ContainerConfig::new("web", "nginx");
+        config.network_mode = "frontend".to_string();
+        config
+            .endpoints
+            .insert("backend".to_string(), EndpointConfig::default());
+        let web = manager.create(config).unwrap();
+        manager.start(&web).unwrap();
+        // The container's own network gets eth0
+        assert_eq!(
+            *interfaces.calls.lock().unwrap(),
+            ["attach frontend 100 eth0", "attach backend 100 eth1"]
+        );
+
+        manager.disconnect_network(&web, "backend", false).unwrap();
+        assert!(!manager.get(&web).unwrap().endpoints.contains_key("backend"));
+        assert!(networks.resolve("backend", "web").unwrap().is_empty());
+
+        manager
+            .connect_network(
+                "web",
+                "backend",
+                EndpointConfig {
+                    ipv4_address: Some("10.20.0.5".to_string()),
+                    aliases: vec!["www".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            manager.get(&web).unwrap().endpoints["backend"]
+                .ipv4_address
+                .as_deref(),
+            Some("10.20.0.5")
+        );
+        assert_eq!(
+            networks.resolve("backend", "www").unwrap(),
+            ["10.20.0.5".parse::<std::net::Ipv4Addr>().unwrap()]
+        );
+        assert!(manager
+            .connect_network(&web, "backend", EndpointConfig::default())
+            .is_err());
+        assert_eq!(
+            interfaces.calls.lock().unwrap()[2..],
+            ["detach backend eth1", "attach backend 100 eth1"]
+        );
+    }
+
+    #[test]
+    fn test_plugin_log_driver() {
+        use crate::plugin::client::tests::fake_plugin;
+
+        let dir = TempDir::new().unwrap();
+        let (socket, calls) = fake_plugin(
+            &dir,
+            &[("Plugin.Activate", r#"{"Implements": ["LogDriver"]}"#)],
+        );
+        let plugins = Arc::new(PluginManager::open(dir.path().join("plugins")).unwrap());
+        let manager = ContainerManager::new(dir.path().join("containers")).unwrap();
+        let mut config = ContainerConfig::new("web", "nginx");
+        config.log_config = Some(LogConfig::new("fluent"));
+        assert!(manager.create(config.clone()).is_err());
+
+        let manager = manager.with_plugins(plugins.clone());
+        assert!(manager.create(config.clone()).is_err());
+        plugins.install("fluent", &socket, true).unwrap();
+        let id = manager.create(config).unwrap();
+        manager.write_log(&id, "stdout", "ready").unwrap();
+        manager.remove(&id, true).unwrap();
+
+        let calls = calls.lock().unwrap();
+        let methods: Vec<&str> = calls.iter().map(|(method, _)| method.as_str()).collect();
+        assert_eq!(
+            methods,
+            [
+                "Plugin.Activate",
+                "LogDriver.StartLogging",
+                "LogDriver.Capabilities",
+                "LogDriver.StopLogging"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_filtered() {
+        let dir = TempDir::new().unwrap();
+        let manager = ContainerManager::new(dir.path().join("containers"))
+            .unwrap()
+            .with_executor(Arc::new(RecordingExecutor::default()));
+        let mut ids = Vec::new();
+        for (i, (name, image)) in [
+            ("web", "nginx:1.25"),
+            ("db", "postgres"),
+            ("cache", "redis"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut config = ContainerConfig::new(name, image);
+            config.created_at = Utc::now() + chrono::Duration::seconds(i as i64);
+            if name != "cache" {
+                config.labels.insert("tier".to_string(), name.to_string());
+            }
+            ids.push(manager.create(config).unwrap());
+        }
+        manager.start(&ids[0]).unwrap();
+
+        let names = |all: bool, filters: Filters| -> Vec<String> {
+            let mut names: Vec<String> = manager
+                .list_filtered(all, &filters)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(false, Filters::new()), ["web"]);
+        assert_eq!(
+            names(false, Filters::new().with("status", "creating")),
+            ["cache", "db"]
+        );
+        assert_eq!(
+            names(true, Filters::new().with("label", "tier")),
+            ["db", "web"]
+        );
+        assert_eq!(names(true, Filters::new().with("label", "tier=db")), ["db"]);
+        assert_eq!(
+            names(true, Filters::new().with("ancestor", "nginx")),
+            ["web"]
+        );
+        assert_eq!(names(true, Filters::new().with("name", "ca")), ["cache"]);
+        assert_eq!(names(true, Filters::new().with("id", &ids[1][..6])), ["db"]);
+        assert_eq!(names(true, Filters::new().with("before", "db")), ["web"]);
+        assert_eq!(names(true, Filters::new().with("since", "db")), ["cache"]);
+        assert_eq!(names(true, Filters::new().with("health", "none")).len(), 3);
+
+        assert!(manager
+            .list_filtered(true, &Filters::new().with("status", "sleeping"))
+            .is_err());
+        assert!(manager
+            .list_filtered(true, &Filters::new().with("color", "red"))
+            .is_err());
+        assert!(manager
+            .list_filtered(true, &Filters::new().with("before", "nothing"))
+            .is_err());
+    }
+}
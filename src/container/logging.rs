@@ -0,0 +1,706 @@
+//! Container log drivers
+//!
+//! A container's output goes to the log driver its `LogConfig` names, or to
+//! the daemon's default one:
+//!
+//! - `json-file`: one JSON object per line in `<id>-json.log`, the default
+//! - `local`: length-prefixed binary records in `container.log`
+//! - `journald`: entries sent to the systemd journal
+//! - `syslog`: RFC 5424 messages sent to a syslog server
+//!
+//! The file drivers rotate their file once it reaches `max-size`, keeping
+//! `max-file` files, and can read logs back; the others only write. Any
+//! other driver is looked up among the log plugins.
+
+use super::config::ContainerConfig;
+use crate::error::{Result, RuneError};
+use crate::swarm::logs::{LogLine, LogRequest};
+use crate::swarm::stack::parse_bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Default log driver
+pub const DEFAULT_LOG_DRIVER: &str = "json-file";
+
+/// Socket journald receives entries on
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// Syslog server used when `syslog-address` is not set
+const DEFAULT_SYSLOG_ADDRESS: &str = "unixgram:///dev/log";
+
+/// Drivers and the options each takes
+const DRIVERS: &[(&str, &[&str])] = &[
+    ("json-file", &["max-size", "max-file"]),
+    ("local", &["max-size", "max-file"]),
+    ("journald", &["tag"]),
+    ("syslog", &["syslog-address", "syslog-facility", "tag"]),
+];
+
+/// Syslog facilities by name
+const FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// Log driver of a container and its options
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LogConfig {
+    /// Driver name
+    #[serde(rename = "Type")]
+    pub driver: String,
+    /// Driver options, such as `max-size`
+    #[serde(default)]
+    pub config: HashMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_DRIVER)
+    }
+}
+
+impl LogConfig {
+    /// Create a config for a driver without options
+    pub fn new(driver: &str) -> Self {
+        Self {
+            driver: driver.to_string(),
+            config: HashMap::new(),
+        }
+    }
+
+    /// Create a config from a driver and `key=value` options, as given to
+    /// `--log-driver` and `--log-opt`
+    ///
+    /// Options of plugin drivers are left to the plugin to check.
+    pub fn from_args(driver: Option<&str>, options: &[String]) -> Result<Self> {
+        let mut config = Self::new(driver.unwrap_or(DEFAULT_LOG_DRIVER));
+        for option in options {
+            let (key, value) = option.split_once('=').ok_or_else(|| {
+                RuneError::InvalidConfig(format!("Invalid log option: {}", option))
+            })?;
+            config.config.insert(key.to_string(), value.to_string());
+        }
+        if config.is_builtin() {
+            config.validate()?;
+        }
+        Ok(config)
+    }
+
+    /// Whether the driver is built in rather than a plugin
+    pub fn is_builtin(&self) -> bool {
+        DRIVERS.iter().any(|(name, _)| *name == self.driver)
+    }
+
+    /// Check the driver exists and takes the options given
+    pub fn validate(&self) -> Result<()> {
+        let (_, allowed) = DRIVERS
+            .iter()
+            .find(|(name, _)| *name == self.driver)
+            .ok_or_else(|| {
+                RuneError::InvalidConfig(format!("Unknown log driver: {}", self.driver))
+            })?;
+        for key in self.config.keys() {
+            if !allowed.contains(&key.as_str()) {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Unknown log opt '{}' for {} log driver",
+                    key, self.driver
+                )));
+            }
+        }
+        self.rotation(None, 1)?;
+        if let Some(facility) = self.config.get("syslog-facility") {
+            facility_code(facility)?;
+        }
+        Ok(())
+    }
+
+    /// Open the driver for a container whose files live in `dir`
+    pub fn open(&self, container: &ContainerConfig, dir: &Path) -> Result<Box<dyn LogDriver>> {
+        self.validate()?;
+        let tag = self
+            .config
+            .get("tag")
+            .cloned()
+            .unwrap_or_else(|| short_id(&container.id).to_string());
+
+        Ok(match self.driver.as_str() {
+            "json-file" => Box::new(JsonFileDriver {
+                file: RotatingFile::new(
+                    dir.join(format!("{}-json.log", container.id)),
+                    self.rotation(None, 1)?,
+                ),
+            }),
+            "local" => Box::new(LocalDriver {
+                file: RotatingFile::new(
+                    dir.join("container.log"),
+                    self.rotation(Some(20 * 1024 * 1024), 5)?,
+                ),
+            }),
+            "journald" => Box::new(JournaldDriver {
+                socket: PathBuf::from(JOURNALD_SOCKET),
+                container_id: container.id.clone(),
+                container_name: container.name.clone(),
+                tag,
+            }),
+            _ => Box::new(SyslogDriver {
+                transport: SyslogTransport::parse(
+                    self.config
+                        .get("syslog-address")
+                        .map(String::as_str)
+                        .unwrap_or(DEFAULT_SYSLOG_ADDRESS),
+                )?,
+                facility: facility_code(
+                    self.config
+                        .get("syslog-facility")
+                        .map(String::as_str)
+                        .unwrap_or("daemon"),
+                )?,
+                hostname: gethostname::gethostname().to_string_lossy().to_string(),
+                tag,
+            }),
+        })
+    }
+
+    /// `max-size` and `max-file`, with the driver's defaults
+    fn rotation(&self, max_size: Option<u64>, max_file: u32) -> Result<(Option<u64>, u32)> {
+        let max_size = match self.config.get("max-size") {
+            Some(size) => match parse_bytes(size)? {
+                size if size > 0 => Some(size as u64),
+                _ => None,
+            },
+            None => max_size,
+        };
+        let max_file = match self.config.get("max-file") {
+            Some(count) => count
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| RuneError::InvalidConfig(format!("Invalid max-file: {}", count)))?,
+            None => max_file,
+        };
+        Ok((max_size, max_file))
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    &id[..id.len().min(12)]
+}
+
+fn facility_code(name: &str) -> Result<u8> {
+    FACILITIES
+        .iter()
+        .find(|(facility, _)| *facility == name)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| RuneError::InvalidConfig(format!("Invalid syslog facility: {}", name)))
+}
+
+/// Where a container's output goes
+pub trait LogDriver: Send + Sync {
+    /// Driver name
+    fn name(&self) -> &str;
+
+    /// Write a line of output
+    fn log(&self, line: &LogLine) -> Result<()>;
+
+    /// Read lines back, oldest first
+    fn read(&self, _request: &LogRequest) -> Result<Vec<LogLine>> {
+        Err(RuneError::Container(format!(
+            "The {} log driver does not support reading",
+            self.name()
+        )))
+    }
+}
+
+/// Log file rotated to `<path>.1`, `<path>.2`, ... as it fills up
+struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_file: u32,
+    lock: Mutex<()>,
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf, (max_size, max_file): (Option<u64>, u32)) -> Self {
+        Self {
+            path,
+            max_size,
+            max_file,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn append(&self, record: &[u8]) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if self
+            .max_size
+            .is_some_and(|max| size > 0 && size + record.len() as u64 > max)
+        {
+            self.rotate()?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(record)?;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<()> {
+        if self.max_file <= 1 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        for n in (1..self.max_file - 1).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        Ok(())
+    }
+
+    /// Contents of all files, oldest first
+    fn read(&self) -> Result<Vec<Vec<u8>>> {
+        let _lock = self.lock.lock().unwrap();
+        let mut paths: Vec<PathBuf> = (1..self.max_file).rev().map(|n| self.rotated(n)).collect();
+        paths.push(self.path.clone());
+        paths
+            .into_iter()
+            .filter(|path| path.exists())
+            .map(|path| Ok(fs::read(path)?))
+            .collect()
+    }
+}
+
+/// Entry of a json-file log
+#[derive(Serialize, Deserialize)]
+struct JsonLogEntry {
+    log: String,
+    #[serde(default)]
+    stream: String,
+    time: DateTime<Utc>,
+}
+
+/// Writes Docker's json-file format
+pub struct JsonFileDriver {
+    file: RotatingFile,
+}
+
+impl LogDriver for JsonFileDriver {
+    fn name(&self) -> &'static str {
+        "json-file"
+    }
+
+    fn log(&self, line: &LogLine) -> Result<()> {
+        let entry = JsonLogEntry {
+            log: format!("{}\n", line.message),
+            stream: line.stream.clone(),
+            time: line.timestamp,
+        };
+        let mut record = serde_json::to_vec(&entry)?;
+        record.push(b'\n');
+        self.file.append(&record)
+    }
+
+    fn read(&self, request: &LogRequest) -> Result<Vec<LogLine>> {
+        let mut lines = Vec::new();
+        for content in self.file.read()? {
+            for entry in String::from_utf8_lossy(&content).lines() {
+                if entry.trim().is_empty() {
+                    continue;
+                }
+                let entry: JsonLogEntry = serde_json::from_str(entry)?;
+                lines.push(LogLine {
+                    timestamp: entry.time,
+                    stream: entry.stream,
+                    message: entry.log.trim_end_matches('\n').to_string(),
+                });
+            }
+        }
+        Ok(request.select(lines))
+    }
+}
+
+/// Writes records of a big-endian length, then the time in nanoseconds, the
+/// stream (0 for stdout, 1 for stderr) and the message
+pub struct LocalDriver {
+    file: RotatingFile,
+}
+
+impl LogDriver for LocalDriver {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn log(&self, line: &LogLine) -> Result<()> {
+        let time = line.timestamp.timestamp_nanos_opt().unwrap_or_default();
+        let stream = u8::from(line.stream == "stderr");
+        let length = 8 + 1 + line.message.len();
+        let mut record = Vec::with_capacity(4 + length);
+        record.extend_from_slice(&(length as u32).to_be_bytes());
+        record.extend_from_slice(&time.to_be_bytes());
+        record.push(stream);
+        record.extend_from_slice(line.message.as_bytes());
+        self.file.append(&record)
+    }
+
+    fn read(&self, request: &LogRequest) -> Result<Vec<LogLine>> {
+        let corrupt = || RuneError::Container("Corrupt local log file".to_string());
+        let mut lines = Vec::new();
+        for content in self.file.read()? {
+            let mut rest = content.as_slice();
+            while !rest.is_empty() {
+                let (length, record) = rest.split_at_checked(4).ok_or_else(corrupt)?;
+                let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+                let (record, next) = record.split_at_checked(length).ok_or_else(corrupt)?;
+                if record.len() < 9 {
+                    return Err(corrupt());
+                }
+                let time = i64::from_be_bytes(record[..8].try_into().unwrap());
+                lines.push(LogLine {
+                    timestamp: DateTime::from_timestamp_nanos(time),
+                    stream: if record[8] == 1 { "stderr" } else { "stdout" }.to_string(),
+                    message: String::from_utf8_lossy(&record[9..]).into_owned(),
+                });
+                rest = next;
+            }
+        }
+        Ok(request.select(lines))
+    }
+}
+
+/// Sends entries to journald over its native protocol
+pub struct JournaldDriver {
+    socket: PathBuf,
+    container_id: String,
+    container_name: String,
+    tag: String,
+}
+
+impl LogDriver for JournaldDriver {
+    fn name(&self) -> &'static str {
+        "journald"
+    }
+
+    fn log(&self, line: &LogLine) -> Result<()> {
+        let priority = if line.stream == "stderr" { "3" } else { "6" };
+        let entry = journal_entry(&[
+            ("MESSAGE", &line.message),
+            ("PRIORITY", priority),
+            ("CONTAINER_ID", short_id(&self.container_id)),
+            ("CONTAINER_ID_FULL", &self.container_id),
+            ("CONTAINER_NAME", &self.container_name),
+            ("CONTAINER_TAG", &self.tag),
+            ("SYSLOG_IDENTIFIER", &self.tag),
+        ]);
+        UnixDatagram::unbound()?.send_to(&entry, &self.socket)?;
+        Ok(())
+    }
+}
+
+/// Fields in journald's native format: `NAME=value` lines, with values
+/// holding a newline written as the name, a length and the raw value
+fn journal_entry(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// How to reach a syslog server
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SyslogTransport {
+    Unix(PathBuf),
+    Udp(String),
+    Tcp(String),
+}
+
+impl SyslogTransport {
+    /// Parse `unixgram:///dev/log`, `udp://host:port` or `tcp://host:port`
+    fn parse(address: &str) -> Result<Self> {
+        match address.split_once("://") {
+            Some(("unixgram" | "unix", path)) => Ok(Self::Unix(PathBuf::from(path))),
+            Some(("udp", host)) => Ok(Self::Udp(with_port(host))),
+            Some(("tcp", host)) => Ok(Self::Tcp(with_port(host))),
+            _ => Err(RuneError::InvalidConfig(format!(
+                "Invalid syslog address: {}",
+                address
+            ))),
+        }
+    }
+}
+
+fn with_port(host: &str) -> String {
+    if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        host.to_string()
+    } else {
+        format!("{}:514", host)
+    }
+}
+
+/// Sends RFC 5424 messages to a syslog server
+pub struct SyslogDriver {
+    transport: SyslogTransport,
+    facility: u8,
+    hostname: String,
+    tag: String,
+}
+
+impl SyslogDriver {
+    fn message(&self, line: &LogLine) -> String {
+        let severity = if line.stream == "stderr" { 3 } else { 6 };
+        format!(
+            "<{}>1 {} {} {} - - - {}",
+            self.facility * 8 + severity,
+            line.timestamp.format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+            self.hostname,
+            self.tag,
+            line.message
+        )
+    }
+}
+
+impl LogDriver for SyslogDriver {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn log(&self, line: &LogLine) -> Result<()> {
+        let message = self.message(line);
+        match &self.transport {
+            SyslogTransport::Unix(path) => {
+                UnixDatagram::unbound()?.send_to(message.as_bytes(), path)?;
+            }
+            SyslogTransport::Udp(address) => {
+                UdpSocket::bind("0.0.0.0:0")?.send_to(message.as_bytes(), address)?;
+            }
+            SyslogTransport::Tcp(address) => {
+                TcpStream::connect(address)?.write_all(format!("{}\n", message).as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn container() -> ContainerConfig {
+        ContainerConfig {
+            id: "0123456789abcdef".to_string(),
+            name: "web".to_string(),
+            ..ContainerConfig::default()
+        }
+    }
+
+    fn line(secs: i64, stream: &str, message: &str) -> LogLine {
+        LogLine {
+            timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+            stream: stream.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn config(driver: &str, options: &[&str]) -> LogConfig {
+        let options: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+        LogConfig::from_args(Some(driver), &options).unwrap()
+    }
+
+    #[test]
+    fn test_log_config_validation() {
+        assert_eq!(LogConfig::default().driver, "json-file");
+        assert!(LogConfig::new("fluentd").validate().is_err());
+        // Plugin drivers check their own options
+        let plugin = LogConfig::from_args(Some("fluentd"), &["tag=web".to_string()]).unwrap();
+        assert!(!plugin.is_builtin());
+        assert!(LogConfig::from_args(None, &["max-size".to_string()]).is_err());
+        let error = LogConfig::from_args(Some("journald"), &["max-size=1m".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown log opt 'max-size' for journald"));
+        assert!(LogConfig::from_args(None, &["max-file=0".to_string()]).is_err());
+        assert!(
+            LogConfig::from_args(Some("syslog"), &["syslog-facility=nope".to_string()]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_json_file_rotation() {
+        let dir = TempDir::new().unwrap();
+        let driver = config("json-file", &["max-size=200", "max-file=2"])
+            .open(&container(), dir.path())
+            .unwrap();
+        for n in 0..6 {
+            driver
+                .log(&line(n, "stdout", &format!("line {}", n)))
+                .unwrap();
+        }
+        assert!(dir.path().join("0123456789abcdef-json.log.1").exists());
+        assert!(!dir.path().join("0123456789abcdef-json.log.2").exists());
+
+        // Only the two newest files are kept
+        let lines = driver.read(&LogRequest::default()).unwrap();
+        let messages: Vec<&str> = lines.iter().map(|l| l.message.as_str()).collect();
+        assert_eq!(messages, ["line 2", "line 3", "line 4", "line 5"]);
+
+        let request = LogRequest {
+            tail: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(driver.read(&request).unwrap()[0].message, "line 5");
+    }
+
+    #[test]
+    fn test_local_driver() {
+        let dir = TempDir::new().unwrap();
+        let driver = config("local", &[]).open(&container(), dir.path()).unwrap();
+        driver.log(&line(1, "stdout", "ready")).unwrap();
+        driver.log(&line(2, "stderr", "oops")).unwrap();
+
+        let lines = driver.read(&LogRequest::default()).unwrap();
+        assert_eq!(
+            lines,
+            [line(1, "stdout", "ready"), line(2, "stderr", "oops")]
+        );
+        let request = LogRequest {
+            since: Some(DateTime::from_timestamp(1, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(driver.read(&request).unwrap(), [line(2, "stderr", "oops")]);
+    }
+
+    #[test]
+    fn test_journald_driver() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("journal.sock");
+        let journal = UnixDatagram::bind(&socket).unwrap();
+        let driver = JournaldDriver {
+            socket,
+            container_id: "0123456789abcdef".to_string(),
+            container_name: "web".to_string(),
+            tag: "web".to_string(),
+        };
+        driver.log(&line(1, "stderr", "oops")).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = journal.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..n]);
+        assert!(entry.starts_with("MESSAGE=oops\nPRIORITY=3\nCONTAINER_ID=0123456789ab\n"));
+        assert!(entry.contains("CONTAINER_NAME=web\n"));
+        assert!(driver.read(&LogRequest::default()).is_err());
+
+        assert_eq!(
+            journal_entry(&[("MESSAGE", "a\nb")]),
+            b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_syslog_driver() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = format!("syslog-address=udp://{}", server.local_addr().unwrap());
+        let driver = config("syslog", &[&address, "syslog-facility=local0", "tag=app"])
+            .open(&container(), Path::new("/nonexistent"))
+            .unwrap();
+        driver.log(&line(0, "stdout", "hello")).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..n]);
+        assert!(message.starts_with("<134>1 1970-01-01T00:00:00.000000Z "));
+        assert!(message.ends_with(" app - - - hello"));
+
+        assert_eq!(
+            SyslogTransport::parse("tcp://logs.example.com").unwrap(),
+            SyslogTransport::Tcp("logs.example.com:514".to_string())
+        );
+        assert!(SyslogTransport::parse("/dev/log").is_err());
+    }
+
+    #[test]
+    fn test_manager_log_drivers() {
+        let dir = TempDir::new().unwrap();
+        let manager = super::super::ContainerManager::new(dir.path().to_path_buf())
+            .unwrap()
+            .with_log_config(LogConfig::new("local"));
+
+        let mut config = ContainerConfig::new("web", "nginx");
+        config.log_config = Some(LogConfig::new("fluentd"));
+        assert!(manager.create(config).is_err());
+
+        let local = manager
+            .create(ContainerConfig::new("web", "nginx"))
+            .unwrap();
+        manager.write_log(&local, "stdout", "hello").unwrap();
+        assert!(dir.path().join(&local).join("container.log").exists());
+
+        let mut config = ContainerConfig::new("db", "postgres");
+        config.log_config = Some(LogConfig::default());
+        let json = manager.create(config).unwrap();
+        manager.write_log(&json, "stderr", "ready").unwrap();
+        assert!(dir
+            .path()
+            .join(&json)
+            .join(format!("{}-json.log", json))
+            .exists());
+
+        let request = LogRequest {
+            container_id: json,
+            ..Default::default()
+        };
+        let lines = manager.read_logs(&request).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].stream, "stderr");
+        assert_eq!(manager.log_config(&local).unwrap().driver, "local");
+    }
+}
@@ -3,12 +3,22 @@
 //! This module provides core functionality for managing containers,
 //! including creation, lifecycle management, and resource isolation.
 
+pub mod checkpoint;
 pub mod config;
+pub mod health;
 pub mod lifecycle;
+pub mod logging;
 pub mod runtime;
 
+pub use checkpoint::Checkpoint;
 pub use config::{
-    ContainerConfig, ContainerStatus, PortMapping, Protocol, ResourceLimits, VolumeMount,
+    ContainerConfig, ContainerStatus, DeviceMapping, PortMapping, Protocol, ResourceLimits, Ulimit,
+    VolumeMount,
 };
-pub use lifecycle::ContainerManager;
+pub use health::{
+    ExecProbe, Health, HealthChecker, HealthEvent, HealthProbe, HealthStatus, HealthcheckConfig,
+    HealthcheckOverrides, HealthcheckResult,
+};
+pub use lifecycle::{ContainerManager, CONTAINER_FILTERS};
+pub use logging::{LogConfig, LogDriver};
 pub use runtime::Container;
@@ -1,10 +1,48 @@
 //! Container runtime implementation
 
-use super::config::{ContainerConfig, ContainerStatus};
+use super::config::{ContainerConfig, ContainerStatus, Ulimit};
+use super::health::Health;
 use crate::error::{Result, RuneError};
+use crate::runtime::cdi::{self, Registry};
+use crate::runtime::devices;
+use crate::runtime::init;
+use crate::runtime::lsm;
+use crate::runtime::oci::{
+    Capabilities, Hooks, Linux, LinuxCpu, LinuxMemory, LinuxNamespace, LinuxPids, LinuxResources,
+    Mount, Process, Root, Spec, User, OCI_VERSION,
+};
+use crate::runtime::seccomp::SeccompFilter;
+use crate::runtime::userns::UsernsRemap;
 use chrono::Utc;
 use std::path::{Path, PathBuf};
 
+/// `PATH` of containers whose environment doesn't set one
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Paths unprivileged containers can't read
+const MASKED_PATHS: &[&str] = &[
+    "/proc/asound",
+    "/proc/acpi",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+/// Paths unprivileged containers can't write
+const READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
 /// Container instance
 #[derive(Debug)]
 pub struct Container {
@@ -14,6 +52,16 @@ pub struct Container {
     pub rootfs: PathBuf,
     /// Container bundle path
     pub bundle: PathBuf,
+    /// Capabilities the container process runs with
+    pub capabilities: Vec<String>,
+    /// Seccomp filter the container process runs under
+    pub seccomp: Option<SeccompFilter>,
+    /// Uid/gid remapping the container runs under
+    pub userns_remap: Option<UsernsRemap>,
+    /// Directories CDI devices are looked up in
+    pub cdi_spec_dirs: Vec<PathBuf>,
+    /// Daemon-wide hooks, run before the container's own
+    pub daemon_hooks: Hooks,
 }
 
 impl Container {
@@ -27,6 +75,11 @@ impl Container {
             config,
             rootfs,
             bundle,
+            capabilities: Vec::new(),
+            seccomp: None,
+            userns_remap: None,
+            cdi_spec_dirs: cdi::DEFAULT_SPEC_DIRS.iter().map(PathBuf::from).collect(),
+            daemon_hooks: Hooks::default(),
         })
     }
 
@@ -56,14 +109,36 @@ impl Container {
             return Err(RuneError::ContainerAlreadyRunning(self.config.id.clone()));
         }
 
+        self.capabilities = self.config.capabilities()?;
+        self.seccomp = self.config.seccomp()?.filter(&self.capabilities)?;
+        // Confined containers can only use files of their own label
+        let mount_label = &self.config.security_labels.mount_label;
+        if !mount_label.is_empty() && self.rootfs.exists() {
+            lsm::relabel(&self.rootfs, mount_label)?;
+        }
+        if !mount_label.is_empty() {
+            for volume in &self.config.volumes {
+                let label = match volume.relabel.as_deref() {
+                    Some("Z") => mount_label.clone(),
+                    Some(_) => lsm::shared_label(mount_label),
+                    None => continue,
+                };
+                lsm::relabel(Path::new(&volume.host_path), &label)?;
+            }
+        }
+        self.oci_spec()?.save(&self.bundle)?;
         self.config.status = ContainerStatus::Running;
         self.config.started_at = Some(Utc::now());
+        self.config.health = self
+            .config
+            .healthcheck
+            .as_ref()
+            .and_then(|h| h.command())
+            .map(|_| Health::default());
 
-        // In a real implementation, this would:
-        // 1. Create namespaces (PID, NET, MNT, UTS, IPC, USER)
-        // 2. Set up cgroups for resource limits
-        // 3. Set up the root filesystem
-        // 4. Execute the container process
+        // The bundle's config.json describes the namespaces, cgroups, root
+        // filesystem, capabilities and seccomp filter of the process; the
+        // manager's executor, if any, runs it
 
         Ok(())
     }
@@ -101,6 +176,16 @@ impl Container {
         Ok(())
     }
 
+    /// Record the exit of the container's process, with its exit code if
+    /// the runtime reports one
+    pub fn exited(&mut self, exit_code: Option<i32>) {
+        self.config.status = ContainerStatus::Exited;
+        self.config.finished_at = Some(Utc::now());
+        self.config.exit_code = exit_code;
+        self.config.pid = None;
+        self.config.health = None;
+    }
+
     /// Kill the container
     pub fn kill(&mut self, signal: Option<i32>) -> Result<()> {
         let _signal = signal.unwrap_or(15); // SIGTERM
@@ -118,6 +203,247 @@ impl Container {
         Ok(())
     }
 
+    /// CDI devices of the container, with the GPUs it requests, and the
+    /// registry they resolve in; `None` without any
+    pub fn cdi_devices(&self) -> Result<Option<(Registry, Vec<String>)>> {
+        if self.config.cdi_devices.is_empty() && self.config.gpus.is_none() {
+            return Ok(None);
+        }
+        let registry = Registry::load(&self.cdi_spec_dirs)?;
+        let mut names = self.config.cdi_devices.clone();
+        if let Some(gpus) = &self.config.gpus {
+            names.extend(registry.gpus(gpus)?);
+        }
+        Ok(Some((registry, names)))
+    }
+
+    /// OCI runtime spec of the container
+    pub fn oci_spec(&self) -> Result<Spec> {
+        let config = &self.config;
+        let capabilities = config.capabilities()?;
+
+        let mut env: Vec<String> = config
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        env.sort();
+        if !config.env.contains_key("PATH") {
+            env.insert(0, format!("PATH={}", DEFAULT_PATH));
+        }
+        let (uid, gid) = resolve_user(&config.user, &self.rootfs)?;
+        let no_new_privileges = config.security_opt.iter().any(|option| {
+            matches!(
+                option.as_str(),
+                "no-new-privileges" | "no-new-privileges:true" | "no-new-privileges=true"
+            )
+        });
+
+        let mut mounts = vec![
+            Mount::new("/proc", "proc", "proc", &["nosuid", "noexec", "nodev"]),
+            Mount::new(
+                "/dev",
+                "tmpfs",
+                "tmpfs",
+                &["nosuid", "strictatime", "mode=755", "size=65536k"],
+            ),
+            Mount::new(
+                "/dev/pts",
+                "devpts",
+                "devpts",
+                &[
+                    "nosuid",
+                    "noexec",
+                    "newinstance",
+                    "ptmxmode=0666",
+                    "mode=0620",
+                    "gid=5",
+                ],
+            ),
+            Mount::new(
+                "/sys",
+                "sysfs",
+                "sysfs",
+                &["nosuid", "noexec", "nodev", "ro"],
+            ),
+            Mount::new(
+                "/sys/fs/cgroup",
+                "cgroup",
+                "cgroup",
+                &["ro", "nosuid", "noexec", "nodev"],
+            ),
+            Mount::new(
+                "/dev/mqueue",
+                "mqueue",
+                "mqueue",
+                &["nosuid", "noexec", "nodev"],
+            ),
+            Mount::new(
+                "/dev/shm",
+                "tmpfs",
+                "shm",
+                &["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"],
+            ),
+        ];
+        for volume in &config.volumes {
+            let mode = if volume.read_only { "ro" } else { "rw" };
+            let propagation = volume.propagation.as_deref().unwrap_or("rprivate");
+            mounts.push(Mount::new(
+                &volume.container_path,
+                "bind",
+                &volume.host_path,
+                &["rbind", propagation, mode],
+            ));
+        }
+        let mut args: Vec<String> = config
+            .entrypoint
+            .iter()
+            .chain(&config.cmd)
+            .cloned()
+            .collect();
+        if config.init {
+            // The rune binary is the init
+            let binary = std::env::current_exe()?;
+            mounts.push(Mount::new(
+                init::INIT_PATH,
+                "bind",
+                &binary.to_string_lossy(),
+                &["bind", "ro"],
+            ));
+            args = init::wrap(&args);
+        }
+
+        let mut device_rules = if config.privileged {
+            devices::privileged_rules()
+        } else {
+            devices::default_rules()
+        };
+        let mut linux_devices = Vec::new();
+        for mapping in &config.devices {
+            for device in
+                devices::host_devices(Path::new(&mapping.path_on_host), &mapping.path_in_container)?
+            {
+                device_rules.push(devices::rule(&device, &mapping.cgroup_permissions));
+                linux_devices.push(device);
+            }
+        }
+
+        let mut namespaces: Vec<LinuxNamespace> = ["pid", "ipc", "uts", "mount", "cgroup"]
+            .into_iter()
+            .map(LinuxNamespace::new)
+            .collect();
+        if config.network_mode != "host" {
+            namespaces.push(LinuxNamespace::new("network"));
+        }
+        if self.userns_remap.is_some() {
+            namespaces.push(LinuxNamespace::new("user"));
+        }
+
+        let limits = &config.resources;
+        let cpu_period = limits.cpu_period.or(limits.cpus.map(|_| 100_000));
+        let cpu_quota = limits.cpu_quota.or(limits
+            .cpus
+            .map(|cpus| (cpus * cpu_period.unwrap_or(100_000) as f64) as i64));
+        let resources = LinuxResources {
+            devices: device_rules,
+            memory: (limits.memory_limit.is_some()
+                || limits.memory_reservation.is_some()
+                || config.oom_kill_disable)
+                .then(|| LinuxMemory {
+                    limit: limits.memory_limit.map(|limit| limit as i64),
+                    reservation: limits
+                        .memory_reservation
+                        .map(|reservation| reservation as i64),
+                    swap: None,
+                    disable_oom_killer: config.oom_kill_disable.then_some(true),
+                }),
+            cpu: (limits.cpu_shares.is_some() || cpu_quota.is_some()).then(|| LinuxCpu {
+                shares: limits.cpu_shares,
+                quota: cpu_quota,
+                period: cpu_period,
+                ..LinuxCpu::default()
+            }),
+            pids: limits.pids_limit.map(|limit| LinuxPids { limit }),
+            block_io: None,
+        };
+        let seccomp = config
+            .seccomp()?
+            .profile()
+            .map(|profile| profile.resolve(&capabilities));
+        let unprivileged_paths = |paths: &[&str]| {
+            if config.privileged {
+                Vec::new()
+            } else {
+                paths.iter().map(|path| path.to_string()).collect()
+            }
+        };
+
+        let mut spec = Spec {
+            oci_version: OCI_VERSION.to_string(),
+            process: Process {
+                terminal: config.tty,
+                user: User {
+                    uid,
+                    gid,
+                    additional_gids: Vec::new(),
+                },
+                args,
+                env,
+                cwd: config.working_dir.clone(),
+                capabilities: Some(Capabilities {
+                    bounding: capabilities.clone(),
+                    effective: capabilities.clone(),
+                    permitted: capabilities,
+                    ..Capabilities::default()
+                }),
+                no_new_privileges,
+                oom_score_adj: config.oom_score_adj,
+                rlimits: config.ulimits.iter().map(Ulimit::to_oci).collect(),
+                apparmor_profile: config.security_labels.apparmor_profile.clone(),
+                selinux_label: config.security_labels.process_label.clone(),
+            },
+            root: Root {
+                path: "rootfs".to_string(),
+                readonly: config.read_only_rootfs,
+            },
+            hostname: config.hostname.clone(),
+            domainname: config.domainname.clone(),
+            mounts,
+            hooks: None,
+            annotations: Default::default(),
+            linux: Linux {
+                namespaces,
+                uid_mappings: self
+                    .userns_remap
+                    .as_ref()
+                    .map(|remap| remap.uid_maps.clone())
+                    .unwrap_or_default(),
+                gid_mappings: self
+                    .userns_remap
+                    .as_ref()
+                    .map(|remap| remap.gid_maps.clone())
+                    .unwrap_or_default(),
+                resources: Some(resources),
+                devices: linux_devices,
+                cgroups_path: format!("/rune/{}", config.id),
+                seccomp,
+                masked_paths: unprivileged_paths(MASKED_PATHS),
+                readonly_paths: unprivileged_paths(READONLY_PATHS),
+                sysctl: config.sysctls.clone(),
+                mount_label: config.security_labels.mount_label.clone(),
+            },
+        };
+        let mut hooks = self.daemon_hooks.clone();
+        hooks.extend(&config.hooks)?;
+        if !hooks.is_empty() {
+            spec.hooks = Some(hooks);
+        }
+        if let Some((registry, names)) = self.cdi_devices()? {
+            registry.apply(&mut spec, &names)?;
+        }
+        Ok(spec)
+    }
+
     /// Remove the container
     pub fn remove(&mut self) -> Result<()> {
         if self.config.status == ContainerStatus::Running {
@@ -136,3 +462,217 @@ impl Container {
         Ok(())
     }
 }
+
+/// Uid and gid of a `user[:group]` setting, names being looked up in the
+/// container's `/etc/passwd` and `/etc/group`
+fn resolve_user(user: &str, rootfs: &Path) -> Result<(u32, u32)> {
+    if user.is_empty() {
+        return Ok((0, 0));
+    }
+    let (user, group) = match user.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (user, None),
+    };
+    let passwd = std::fs::read_to_string(rootfs.join("etc/passwd")).unwrap_or_default();
+    let entry = passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() > 3)
+        .find(|fields| fields[0] == user || fields[2] == user);
+
+    let uid = match (user.parse::<u32>(), &entry) {
+        (Ok(uid), _) => uid,
+        (Err(_), Some(fields)) => fields[2].parse().unwrap_or(0),
+        (Err(_), None) => {
+            return Err(RuneError::InvalidConfig(format!(
+                "Unable to find user {}: no matching entries in passwd file",
+                user
+            )))
+        }
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => std::fs::read_to_string(rootfs.join("etc/group"))
+                .unwrap_or_default()
+                .lines()
+                .map(|line| line.split(':').collect::<Vec<_>>())
+                .find(|fields| fields.len() > 2 && fields[0] == group)
+                .and_then(|fields| fields[2].parse().ok())
+                .ok_or_else(|| {
+                    RuneError::InvalidConfig(format!(
+                        "Unable to find group {}: no matching entries in group file",
+                        group
+                    ))
+                })?,
+        },
+        None => entry.and_then(|fields| fields[3].parse().ok()).unwrap_or(0),
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::oci::Hook;
+    use crate::runtime::userns::UsernsRemap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_oci_spec() {
+        let dir = TempDir::new().unwrap();
+        let mut config = ContainerConfig::new("web", "nginx")
+            .cmd(vec!["nginx".to_string(), "-g".to_string()])
+            .env("LANG", "C")
+            .volume("/srv/data", "/data");
+        config.user = "www:web".to_string();
+        config.cap_drop = vec!["ALL".to_string()];
+        config.cap_add = vec!["NET_BIND_SERVICE".to_string()];
+        config.resources.memory_limit = Some(64 << 20);
+        config.resources.cpus = Some(0.5);
+        config.add_device("/dev/null:/dev/mynull:rw").unwrap();
+        config.add_hook("prestart=/usr/bin/own-setup").unwrap();
+        config.add_hook("poststop=/usr/bin/cleanup --all").unwrap();
+        assert!(config.add_hook("/usr/bin/cleanup").is_err());
+        assert!(config.add_hook("prestop=/usr/bin/cleanup").is_err());
+        config.add_ulimit("nofile=1024:2048").unwrap();
+        config.add_ulimit("core=-1").unwrap();
+        assert!(config.add_ulimit("nofile=2048:1024").is_err());
+        assert!(config.add_ulimit("files=1").is_err());
+        config.add_sysctl("net.ipv4.ip_forward=1").unwrap();
+        config.oom_kill_disable = true;
+        config.oom_score_adj = Some(-500);
+        let mut container = Container::new(config, dir.path()).unwrap();
+        container.userns_remap = Some(
+            UsernsRemap::from_ranges(
+                "default",
+                "runeremap:100000:65536\n",
+                "runeremap:100000:65536\n",
+            )
+            .unwrap(),
+        );
+        container
+            .daemon_hooks
+            .add("prestart", Hook::parse("/usr/bin/daemon-setup").unwrap())
+            .unwrap();
+
+        std::fs::create_dir_all(container.rootfs.join("etc")).unwrap();
+        std::fs::write(
+            container.rootfs.join("etc/passwd"),
+            "root:x:0:0::/root:/bin/sh\nwww:x:33:33::/var/www:/bin/sh\n",
+        )
+        .unwrap();
+        std::fs::write(container.rootfs.join("etc/group"), "web:x:82:\n").unwrap();
+
+        container.start().unwrap();
+        let spec = Spec::load(&container.bundle).unwrap();
+        assert_eq!(spec.process.args, ["nginx", "-g"]);
+        assert_eq!(spec.process.env[0], format!("PATH={}", DEFAULT_PATH));
+        assert!(spec.process.env.contains(&"LANG=C".to_string()));
+        assert_eq!((spec.process.user.uid, spec.process.user.gid), (33, 82));
+        assert_eq!(
+            spec.process.capabilities.as_ref().unwrap().bounding,
+            ["CAP_NET_BIND_SERVICE"]
+        );
+        assert!(spec.has_namespace("user") && spec.has_namespace("network"));
+        assert_eq!(spec.linux.uid_mappings[0].host_id, 100000);
+        assert_eq!(spec.linux.devices[0].path, "/dev/mynull");
+        let hooks = spec.hooks.unwrap();
+        let prestart: Vec<_> = hooks.prestart.iter().map(|hook| &hook.path).collect();
+        assert_eq!(prestart, ["/usr/bin/daemon-setup", "/usr/bin/own-setup"]);
+        assert_eq!(hooks.poststop[0].args, ["/usr/bin/cleanup", "--all"]);
+        let resources = spec.linux.resources.unwrap();
+        assert_eq!(resources.devices[0].to_v1(), "a *:* rwm");
+        assert_eq!(resources.devices.last().unwrap().to_v1(), "c 1:3 rw");
+        let memory = resources.memory.unwrap();
+        assert_eq!(memory.limit, Some(64 << 20));
+        assert_eq!(memory.disable_oom_killer, Some(true));
+        assert_eq!(spec.process.oom_score_adj, Some(-500));
+        assert_eq!(spec.process.rlimits[0].kind, "RLIMIT_NOFILE");
+        assert_eq!(
+            (spec.process.rlimits[0].soft, spec.process.rlimits[0].hard),
+            (1024, 2048)
+        );
+        assert_eq!(spec.process.rlimits[1].hard, u64::MAX);
+        assert_eq!(spec.linux.sysctl["net.ipv4.ip_forward"], "1");
+        assert_eq!(resources.cpu.unwrap().quota, Some(50_000));
+        assert!(spec
+            .mounts
+            .iter()
+            .any(|mount| mount.destination == "/data" && mount.source == "/srv/data"));
+
+        // Conditional seccomp rules are resolved for the capabilities
+        let seccomp = spec.linux.seccomp.unwrap();
+        assert!(seccomp
+            .syscalls
+            .iter()
+            .all(|rule| rule.includes.caps.is_empty()));
+        assert!(!seccomp
+            .syscalls
+            .iter()
+            .any(|rule| rule.names.contains(&"mount".to_string())));
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(container.bundle.join("config.json")).unwrap())
+                .unwrap();
+        assert_eq!(json["ociVersion"], OCI_VERSION);
+        assert_eq!(json["linux"]["uidMappings"][0]["containerID"], 0);
+        assert_eq!(json["root"]["path"], "rootfs");
+
+        let mut config = ContainerConfig::new("app", "alpine").cmd(vec!["sh".to_string()]);
+        config.init = true;
+        let spec = Container::new(config, dir.path())
+            .unwrap()
+            .oci_spec()
+            .unwrap();
+        assert_eq!(spec.process.args, ["/sbin/rune-init", "--", "sh"]);
+        assert!(spec
+            .mounts
+            .iter()
+            .any(|mount| mount.destination == "/sbin/rune-init" && mount.kind == "bind"));
+
+        let mut config = ContainerConfig::new("app", "alpine");
+        config.user = "nobody".to_string();
+        let mut container = Container::new(config, dir.path()).unwrap();
+        assert!(container.start().is_err());
+    }
+
+    #[test]
+    fn test_devices() {
+        let mut config = ContainerConfig::new("app", "alpine");
+        config.add_device("/dev/fuse").unwrap();
+        config.add_device("/dev/sda:r").unwrap();
+        config.add_device("nvidia.com/gpu=0").unwrap();
+        assert_eq!(config.devices[0].path_in_container, "/dev/fuse");
+        assert_eq!(config.devices[0].cgroup_permissions, "rwm");
+        assert_eq!(
+            (
+                config.devices[1].path_in_container.as_str(),
+                config.devices[1].cgroup_permissions.as_str()
+            ),
+            ("/dev/sda", "r")
+        );
+        assert_eq!(config.cdi_devices, ["nvidia.com/gpu=0"]);
+        assert!(config.add_device("/dev/sda:/dev/xvda:rwx").is_err());
+        assert!(config.add_device("/dev/sda:xvda").is_err());
+        assert!(config.add_device("a:b:c:d").is_err());
+
+        // CDI devices resolve in the container's spec directories
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("vendor.json"),
+            r#"{"cdiVersion": "0.6.0", "kind": "vendor.com/gpu", "devices": [
+                {"name": "0", "containerEdits": {"env": ["GPU=0"]}}]}"#,
+        )
+        .unwrap();
+        let mut config = ContainerConfig::new("app", "alpine");
+        config.gpus = Some(cdi::GpuRequest::All);
+        let mut container = Container::new(config, dir.path()).unwrap();
+        container.cdi_spec_dirs = vec![dir.path().to_path_buf()];
+        let spec = container.oci_spec().unwrap();
+        assert!(spec.process.env.contains(&"GPU=0".to_string()));
+
+        container.cdi_spec_dirs = vec![dir.path().join("missing")];
+        assert!(container.oci_spec().is_err());
+    }
+}
@@ -3,12 +3,33 @@
 //! Implements Docker Engine API v1.24+ compatible endpoints.
 //! This API is compatible with Portainer and other Docker management tools.
 
-use crate::container::{ContainerConfig, ContainerManager};
-use crate::error::{Result, RuneError};
+use super::audit::{request_action, AuditEntry, AuditLog, Caller, AUDIT_FILTERS};
+use super::debug::{Diagnostics, LogBuffer};
+use crate::container::{
+    ContainerConfig, ContainerManager, DeviceMapping, ExecProbe, Health, HealthChecker,
+    HealthStatus, HealthcheckConfig, LogConfig, Ulimit,
+};
+use crate::error::{ErrorKind, Result, ResultExt, RuneError};
+use crate::filter::Filters;
+use crate::image::ImageStore;
+use crate::network::bridge::NetworkManager;
+use crate::network::{NetworkConfig, NetworkDriver};
+use crate::plugin::{Plugin, PluginManager, LOG_DRIVER, NETWORK_DRIVER, VOLUME_DRIVER};
+use crate::runtime::cdi::{self, GpuRequest};
+use crate::runtime::criu::{CheckpointOptions, TcpMode};
+use crate::runtime::lsm::Lsm;
+use crate::runtime::oci::Hooks;
+use crate::runtime::signal::parse_signal;
+use crate::runtime::{ContainerMetrics, MetricsSource, Pressure, PressureValues};
+use crate::storage::{MetricsStore, Volume, VolumeDriver, VolumeManager};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::debug;
+use tracing::{debug, warn};
 
 /// API request/response structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +48,8 @@ pub struct ContainerCreateRequest {
     pub user: Option<String>,
     #[serde(rename = "Tty")]
     pub tty: Option<bool>,
+    #[serde(rename = "OpenStdin")]
+    pub open_stdin: Option<bool>,
     #[serde(rename = "ExposedPorts")]
     pub exposed_ports: Option<std::collections::HashMap<String, Value>>,
     #[serde(rename = "HostConfig")]
@@ -35,6 +58,12 @@ pub struct ContainerCreateRequest {
     pub networking_config: Option<NetworkingConfig>,
     #[serde(rename = "Labels")]
     pub labels: Option<std::collections::HashMap<String, String>>,
+    #[serde(rename = "Healthcheck")]
+    pub healthcheck: Option<HealthcheckConfig>,
+    #[serde(rename = "StopSignal")]
+    pub stop_signal: Option<String>,
+    #[serde(rename = "StopTimeout")]
+    pub stop_timeout: Option<u64>,
 }
 
 /// Host configuration for container
@@ -53,6 +82,74 @@ pub struct HostConfig {
     pub privileged: Option<bool>,
     pub publish_all_ports: Option<bool>,
     pub auto_remove: Option<bool>,
+    pub log_config: Option<LogConfig>,
+    pub cap_add: Option<Vec<String>>,
+    pub cap_drop: Option<Vec<String>>,
+    pub security_opt: Option<Vec<String>>,
+    pub userns_mode: Option<String>,
+    pub init: Option<bool>,
+    pub devices: Option<Vec<HostDevice>>,
+    pub device_requests: Option<Vec<DeviceRequest>>,
+    /// OCI lifecycle hooks, as in a runtime spec
+    pub hooks: Option<Hooks>,
+    pub ulimits: Option<Vec<HostUlimit>>,
+    pub sysctls: Option<std::collections::HashMap<String, String>>,
+    pub oom_kill_disable: Option<bool>,
+    pub oom_score_adj: Option<i32>,
+}
+
+/// Process resource limit of a container; -1 is unlimited
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct HostUlimit {
+    pub name: String,
+    pub soft: i64,
+    pub hard: i64,
+}
+
+/// Host device mapped into a container
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct HostDevice {
+    pub path_on_host: String,
+    #[serde(default)]
+    pub path_in_container: String,
+    #[serde(default)]
+    pub cgroup_permissions: String,
+}
+
+/// Request for devices from a driver: CDI devices, or GPUs by capability
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceRequest {
+    #[serde(default)]
+    pub driver: String,
+    /// Number of devices, -1 for all
+    #[serde(default)]
+    pub count: i64,
+    #[serde(rename = "DeviceIDs", default)]
+    pub device_ids: Option<Vec<String>>,
+    #[serde(default)]
+    pub capabilities: Option<Vec<Vec<String>>>,
+}
+
+/// Body of a checkpoint create request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CheckpointCreateRequest {
+    #[serde(rename = "CheckpointID")]
+    checkpoint_id: String,
+    #[serde(default)]
+    checkpoint_dir: Option<String>,
+    /// Stop the container once checkpointed
+    #[serde(default)]
+    exit: bool,
+    #[serde(default)]
+    tcp_established: bool,
+    #[serde(default)]
+    tcp_close: bool,
+    #[serde(default)]
+    file_locks: bool,
 }
 
 /// Port binding configuration
@@ -84,6 +181,7 @@ pub struct NetworkingConfig {
 pub struct EndpointConfig {
     pub ipam_config: Option<IpamConfig>,
     pub aliases: Option<Vec<String>>,
+    pub mac_address: Option<String>,
 }
 
 /// IPAM configuration
@@ -296,6 +394,11 @@ struct ContainerInspect {
     restart_count: i32,
     driver: String,
     platform: String,
+    seccomp_profile: String,
+    #[serde(rename = "AppArmorProfile")]
+    apparmor_profile: String,
+    process_label: String,
+    mount_label: String,
     config: ContainerConfigResponse,
     host_config: HostConfigResponse,
     network_settings: NetworkSettingsResponse,
@@ -323,6 +426,12 @@ struct ContainerConfigResponse {
     working_dir: String,
     entrypoint: Option<Vec<String>>,
     labels: std::collections::HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    healthcheck: Option<HealthcheckConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_signal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_timeout: Option<u64>,
 }
 
 /// Host config in inspect response
@@ -346,6 +455,19 @@ struct HostConfigResponse {
     cpuset_cpus: String,
     cpuset_mems: String,
     pids_limit: Option<i64>,
+    log_config: LogConfig,
+    cap_add: Option<Vec<String>>,
+    cap_drop: Option<Vec<String>>,
+    security_opt: Option<Vec<String>>,
+    userns_mode: String,
+    init: bool,
+    devices: Vec<HostDevice>,
+    device_requests: Option<Vec<DeviceRequest>>,
+    hooks: Option<Hooks>,
+    ulimits: Option<Vec<HostUlimit>>,
+    sysctls: std::collections::HashMap<String, String>,
+    oom_kill_disable: bool,
+    oom_score_adj: i32,
 }
 
 /// Restart policy in response
@@ -406,6 +528,8 @@ struct ContainerState {
     error: String,
     started_at: String,
     finished_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<Health>,
 }
 
 /// Exec create request
@@ -494,24 +618,238 @@ pub struct ExecInstance {
     pub pid: Option<i64>,
 }
 
+/// Unlocks the swarm this node manages with its unlock key
+pub type SwarmUnlock = Arc<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
 /// API Handler for processing requests
 #[derive(Clone)]
 pub struct ApiHandler {
     container_manager: Arc<ContainerManager>,
     exec_instances: Arc<std::sync::RwLock<std::collections::HashMap<String, ExecInstance>>>,
     config_manager: Arc<crate::swarm::ConfigManager>,
+    health_checker: Arc<HealthChecker>,
+    /// Source of the stats of running containers
+    metrics: Option<Arc<dyn MetricsSource>>,
+    /// Installed plugins
+    plugins: Option<Arc<PluginManager>>,
+    /// Images containers take their defaults from
+    images: Option<Arc<ImageStore>>,
+    /// Networks containers are connected to
+    networks: Option<Arc<NetworkManager>>,
+    /// Volumes containers mount
+    volumes: Option<Arc<VolumeManager>>,
+    /// Samples of containers kept over time
+    stats_history: Option<Arc<MetricsStore>>,
+    /// Daemon config the debug endpoints report, which they are only
+    /// served with
+    debug: Option<Value>,
+    /// Where mutating requests are recorded
+    audit: Option<Arc<AuditLog>>,
+    /// Unlocks the swarm after the daemon started with it locked
+    swarm_unlock: Option<SwarmUnlock>,
+    /// Async runtime the daemon runs its tasks on, which the debug
+    /// endpoints report; requests are served outside of it
+    runtime: Option<tokio::runtime::Handle>,
 }
 
 impl ApiHandler {
     /// Create a new API handler
     pub fn new(container_manager: Arc<ContainerManager>) -> Self {
+        let health_checker = Arc::new(HealthChecker::new(
+            container_manager.clone(),
+            Arc::new(ExecProbe),
+        ));
         Self {
             container_manager,
             exec_instances: Arc::new(std::sync::RwLock::new(std::collections::HashMap::new())),
             config_manager: Arc::new(crate::swarm::ConfigManager::new()),
+            health_checker,
+            metrics: None,
+            plugins: None,
+            images: None,
+            networks: None,
+            volumes: None,
+            stats_history: None,
+            debug: None,
+            audit: None,
+            swarm_unlock: None,
+            runtime: None,
+        }
+    }
+
+    /// Manage the plugins installed with a plugin manager
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Take the defaults of created containers from the images in a store
+    pub fn with_images(mut self, images: Arc<ImageStore>) -> Self {
+        self.images = Some(images);
+        self
+    }
+
+    /// Manage the networks of a network manager
+    pub fn with_networks(mut self, networks: Arc<NetworkManager>) -> Self {
+        self.networks = Some(networks);
+        self
+    }
+
+    /// Manage the volumes of a volume manager
+    pub fn with_volumes(mut self, volumes: Arc<VolumeManager>) -> Self {
+        self.volumes = Some(volumes);
+        self
+    }
+
+    /// Report the stats history of containers from the samples in a store
+    pub fn with_stats_history(mut self, store: Arc<MetricsStore>) -> Self {
+        self.stats_history = Some(store);
+        self
+    }
+
+    /// Serve the debug endpoints, reporting the daemon's config with its
+    /// diagnostics
+    pub fn with_debug(mut self, config: Value) -> Self {
+        self.debug = Some(config);
+        self
+    }
+
+    /// Record mutating requests in an audit log, and serve it
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Report the metrics of the async runtime `handle` is of with the
+    /// daemon's diagnostics
+    pub fn with_runtime(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// Unlock the swarm with `unlock` when a client sends its unlock key
+    pub fn with_swarm_unlock(mut self, unlock: SwarmUnlock) -> Self {
+        self.swarm_unlock = Some(unlock);
+        self
+    }
+
+    /// Report the stats of running containers from a metrics source
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSource>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Checker whose health events the events endpoint reports
+    pub fn health_checker(&self) -> Arc<HealthChecker> {
+        self.health_checker.clone()
+    }
+
+    /// Handle a request from `caller`, recording it in the audit log if it
+    /// changes anything
+    pub fn handle_audited_request(
+        &self,
+        caller: &Caller,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Result<String> {
+        let result = self.handle_request(method, path, body);
+        self.audit(caller, method, path, body, &result);
+        result
+    }
+
+    /// Record a request from `caller` in the audit log if it changes
+    /// anything
+    fn audit<T>(&self, caller: &Caller, method: &str, path: &str, body: &str, result: &Result<T>) {
+        if let (Some(audit), Some((action, target))) = (&self.audit, request_action(method, path)) {
+            let status = match result {
+                Ok(_) => 200,
+                Err(e) => e.status_code(),
+            };
+            let entry = AuditEntry::new(caller.clone(), action, target, body, status);
+            if let Err(e) = audit.record(&entry) {
+                warn!(
+                    "Failed to record {} {} in the audit log: {}",
+                    method, path, e
+                );
+            }
         }
     }
 
+    /// Create and start a container for a client that stays attached to
+    /// it, from the config in `body` of a `POST /containers/run` request
+    ///
+    /// Without a terminal, the container writes to the client's standard
+    /// output and error, the `fds` it sent. Returned are its ID and, for the
+    /// client to hold, its terminal if it has one, or else its standard
+    /// input if it keeps that open. The client's connection must then wait
+    /// for it with [`Self::wait_attached`].
+    pub fn run_attached(
+        &self,
+        caller: &Caller,
+        path: &str,
+        body: &str,
+        fds: Vec<OwnedFd>,
+    ) -> Result<(String, Option<OwnedFd>)> {
+        let result = self.create_attached(body, fds);
+        self.audit(caller, "POST", path, body, &result);
+        result
+    }
+
+    fn create_attached(&self, body: &str, fds: Vec<OwnedFd>) -> Result<(String, Option<OwnedFd>)> {
+        let config: ContainerConfig = parse_body(body)?;
+        let (tty, open_stdin) = (config.tty, config.open_stdin);
+        let (stdout, stderr) = match (tty, <[OwnedFd; 2]>::try_from(fds)) {
+            (true, Ok(_)) | (false, Err(_)) => {
+                return Err(RuneError::new(
+                    ErrorKind::InvalidArgument,
+                    "Containers without a terminal need the client's standard output and error",
+                ))
+            }
+            (true, Err(fds)) if !fds.is_empty() => {
+                return Err(RuneError::new(
+                    ErrorKind::InvalidArgument,
+                    "Containers with a terminal take no streams of the client",
+                ))
+            }
+            (true, Err(_)) => (None, None),
+            (false, Ok([stdout, stderr])) => (Some(stdout), Some(stderr)),
+        };
+
+        let name = config.name.clone();
+        let id = self
+            .container_manager
+            .create(config)
+            .with_context(|| format!("Cannot create container {}", name))?;
+        if let Err(e) = self.container_manager.start_attached(&id, stdout, stderr) {
+            let _ = self.container_manager.remove(&id, true);
+            return Err(e).with_context(|| format!("Cannot start container {}", name));
+        }
+
+        let input = if tty {
+            self.container_manager.console(&id).map(Some)
+        } else if open_stdin {
+            self.container_manager.stdin(&id).map(Some)
+        } else {
+            Ok(None)
+        };
+        match input {
+            Ok(input) => Ok((id, input)),
+            Err(e) => {
+                let _ = self.container_manager.kill(&id, None);
+                let _ = self.container_manager.wait_attached(&id);
+                let _ = self.container_manager.remove(&id, true);
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait for a container run attached to exit, removing it then if it
+    /// removes itself, and return its exit code
+    pub fn wait_attached(&self, id: &str) -> Result<i32> {
+        self.container_manager.wait_attached(id)
+    }
+
     /// Handle an incoming API request
     /// Supports Docker Engine API v1.24+ for Portainer compatibility
     pub fn handle_request(&self, method: &str, path: &str, body: &str) -> Result<String> {
@@ -544,9 +882,12 @@ impl ApiHandler {
             ("GET", ["containers", id, "json"]) => self.inspect_container(id),
             ("GET", ["containers", id, "top"]) => self.container_top(id, path),
             ("GET", ["containers", id, "stats"]) => self.container_stats(id, path),
-            ("POST", ["containers", id, "start"]) => self.start_container(id),
-            ("POST", ["containers", id, "stop"]) => self.stop_container(id),
-            ("POST", ["containers", id, "restart"]) => self.restart_container(id),
+            ("GET", ["containers", id, "stats", "history"]) => {
+                self.container_stats_history(id, path)
+            }
+            ("POST", ["containers", id, "start"]) => self.start_container(id, path),
+            ("POST", ["containers", id, "stop"]) => self.stop_container(id, path),
+            ("POST", ["containers", id, "restart"]) => self.restart_container(id, path),
             ("POST", ["containers", id, "kill"]) => self.kill_container(id, path),
             ("POST", ["containers", id, "pause"]) => self.pause_container(id),
             ("POST", ["containers", id, "unpause"]) => self.unpause_container(id),
@@ -554,6 +895,11 @@ impl ApiHandler {
             ("POST", ["containers", id, "update"]) => self.update_container(id, body),
             ("DELETE", ["containers", id]) => self.remove_container(id, path),
             ("GET", ["containers", id, "logs"]) => self.container_logs(id, path),
+            ("GET", ["containers", id, "checkpoints"]) => self.list_checkpoints(id, path),
+            ("POST", ["containers", id, "checkpoints"]) => self.create_checkpoint(id, body),
+            ("DELETE", ["containers", id, "checkpoints", name]) => {
+                self.remove_checkpoint(id, name, path)
+            }
             ("POST", ["containers", id, "wait"]) => self.wait_container(id),
             ("POST", ["containers", "prune"]) => self.prune_containers(path),
             // Attach and console endpoints
@@ -575,7 +921,7 @@ impl ApiHandler {
             ("POST", ["build"]) => self.build_image(path, body),
 
             // Networks - required for Portainer
-            ("GET", ["networks"]) => self.list_networks(),
+            ("GET", ["networks"]) => self.list_networks(path),
             ("GET", ["networks", id]) => self.inspect_network(id),
             ("POST", ["networks", "create"]) => self.create_network(body),
             ("DELETE", ["networks", id]) => self.remove_network(id),
@@ -606,6 +952,7 @@ impl ApiHandler {
             ("POST", ["swarm", "join"]) => self.join_swarm(body),
             ("POST", ["swarm", "leave"]) => self.leave_swarm(path),
             ("POST", ["swarm", "update"]) => self.update_swarm(path, body),
+            ("POST", ["swarm", "unlock"]) => self.unlock_swarm(body),
             ("GET", ["swarm", "unlockkey"]) => self.get_unlock_key(),
 
             // Nodes
@@ -643,15 +990,27 @@ impl ApiHandler {
             // Plugins
             ("GET", ["plugins"]) => self.list_plugins(path),
             ("GET", ["plugins", name, "json"]) => self.inspect_plugin(name),
+            ("POST", ["plugins", name, "enable"]) => self.enable_plugin(name),
+            ("POST", ["plugins", name, "disable"]) => self.disable_plugin(name),
+            ("DELETE", ["plugins", name]) => self.remove_plugin(name, path),
 
             // Distribution
             ("GET", ["distribution", image, "json"]) => self.get_distribution_info(image),
 
+            // Diagnostics
+            ("GET", ["debug"]) if self.debug.is_some() => self.get_debug(),
+            ("GET", ["debug", "logs"]) if self.debug.is_some() => {
+                Ok(LogBuffer::global().lines().join("\n"))
+            }
+
+            // Audit log
+            ("GET", ["audit"]) if self.audit.is_some() => self.list_audit(path),
+
             // Default
-            _ => Err(RuneError::Api(format!(
-                "Unknown endpoint: {} {}",
-                method, path
-            ))),
+            _ => Err(RuneError::new(
+                ErrorKind::NotFound,
+                format!("page not found: {} {}", method, path),
+            )),
         }
     }
 
@@ -692,6 +1051,27 @@ impl ApiHandler {
                 runtime_args: None,
             },
         );
+        let mut default_runtime = "rune".to_string();
+        if let Some(executor) = self.container_manager.executor() {
+            default_runtime = executor.name().to_string();
+            runtimes.insert(
+                default_runtime.clone(),
+                RuntimeInfo {
+                    path: executor.path().display().to_string(),
+                    runtime_args: None,
+                },
+            );
+        }
+
+        let mut security_options = vec!["name=seccomp,profile=default".to_string()];
+        if self.container_manager.userns_remap().is_some() {
+            security_options.push("name=userns".to_string());
+        }
+        match self.container_manager.lsm() {
+            Some(Lsm::AppArmor) => security_options.push("name=apparmor".to_string()),
+            Some(Lsm::SeLinux) => security_options.push("name=selinux".to_string()),
+            None => {}
+        }
 
         let response = InfoResponse {
             id: uuid::Uuid::new_v4().to_string(),
@@ -710,7 +1090,7 @@ impl ApiHandler {
             docker_root_dir: "/var/lib/rune".to_string(),
             name: gethostname::gethostname().to_string_lossy().to_string(),
             server_version: env!("CARGO_PKG_VERSION").to_string(),
-            default_runtime: "rune".to_string(),
+            default_runtime,
             os_type: "linux".to_string(),
             operating_system: get_os_name(),
             architecture: std::env::consts::ARCH.to_string(),
@@ -722,90 +1102,91 @@ impl ApiHandler {
                 ..Default::default()
             },
             runtimes,
-            security_options: vec!["name=seccomp,profile=default".to_string()],
+            security_options,
             plugins: PluginsInfo {
-                volume: vec!["local".to_string()],
-                network: vec![
-                    "bridge".to_string(),
-                    "host".to_string(),
-                    "overlay".to_string(),
-                    "null".to_string(),
-                ],
+                volume: [
+                    vec!["local".to_string()],
+                    self.plugin_drivers(VOLUME_DRIVER),
+                ]
+                .concat(),
+                network: [
+                    vec![
+                        "bridge".to_string(),
+                        "host".to_string(),
+                        "overlay".to_string(),
+                        "null".to_string(),
+                    ],
+                    self.plugin_drivers(NETWORK_DRIVER),
+                ]
+                .concat(),
                 authorization: None,
-                log: vec!["json-file".to_string(), "local".to_string()],
+                log: [
+                    vec!["json-file".to_string(), "local".to_string()],
+                    self.plugin_drivers(LOG_DRIVER),
+                ]
+                .concat(),
             },
             registries: vec![],
         };
         Ok(serde_json::to_string(&response)?)
     }
 
-    fn get_events(&self, _path: &str) -> Result<String> {
-        // Return empty events stream for now
-        Ok("".to_string())
+    fn get_debug(&self) -> Result<String> {
+        let mut diagnostics = serde_json::to_value(Diagnostics::collect(self.runtime.as_ref())?)?;
+        diagnostics["Config"] = self.debug.clone().unwrap_or_default();
+        diagnostics["Containers"] = json!(self.container_manager.count()?);
+        diagnostics["ContainersRunning"] = json!(self.container_manager.running_count()?);
+        Ok(diagnostics.to_string())
+    }
+
+    fn list_audit(&self, path: &str) -> Result<String> {
+        let audit = self.audit.as_ref().ok_or_else(|| {
+            RuneError::new(
+                ErrorKind::NotFound,
+                "The daemon keeps no audit log".to_string(),
+            )
+        })?;
+        let filters = query_filters(path)?;
+        filters.validate(AUDIT_FILTERS)?;
+        let since = parse_query_param(path, "since")
+            .and_then(|since| chrono::DateTime::from_timestamp(since as i64, 0));
+        Ok(serde_json::to_string(&audit.entries(since, &filters)?)?)
+    }
+
+    fn get_events(&self, path: &str) -> Result<String> {
+        let since = parse_query_param(path, "since")
+            .and_then(|since| chrono::DateTime::from_timestamp(since as i64, 0));
+        let mut lines = String::new();
+        for event in self.health_checker.events(since) {
+            let action = format!("health_status: {}", event.status);
+            let line = json!({
+                "status": action,
+                "id": event.container_id,
+                "from": event.image,
+                "Type": "container",
+                "Action": action,
+                "Actor": {
+                    "ID": event.container_id,
+                    "Attributes": { "image": event.image, "name": event.name }
+                },
+                "scope": "local",
+                "time": event.time.timestamp(),
+                "timeNano": event.time.timestamp_nanos_opt().unwrap_or_default(),
+            });
+            lines.push_str(&line.to_string());
+            lines.push('\n');
+        }
+        Ok(lines)
     }
 
     fn list_containers(&self, path: &str) -> Result<String> {
         let all = path.contains("all=true") || path.contains("all=1");
-        let containers = self.container_manager.list(all)?;
-
-        // Parse label filter if present
-        let label_filter: Option<Vec<(String, Option<String>)>> =
-            if let Some(pos) = path.find("filters=") {
-                let start = pos + 8;
-                let end = path[start..]
-                    .find('&')
-                    .map(|i| start + i)
-                    .unwrap_or(path.len());
-                let filter_str = &path[start..end];
-                // URL decode and parse JSON filter
-                if let Ok(decoded) = urlencoding_decode(filter_str) {
-                    if let Ok(filters) = serde_json::from_str::<Value>(&decoded) {
-                        filters
-                            .get("label")
-                            .and_then(|v| v.as_array())
-                            .map(|labels| {
-                                labels
-                                    .iter()
-                                    .filter_map(|l| {
-                                        l.as_str().map(|s| {
-                                            if let Some((k, v)) = s.split_once('=') {
-                                                (k.to_string(), Some(v.to_string()))
-                                            } else {
-                                                (s.to_string(), None)
-                                            }
-                                        })
-                                    })
-                                    .collect()
-                            })
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
+        let containers = self
+            .container_manager
+            .list_filtered(all, &query_filters(path)?)?;
 
         let response: Vec<ContainerListItem> = containers
             .iter()
-            .filter(|c| {
-                // Apply label filter if present
-                if let Some(ref filters) = label_filter {
-                    for (key, value) in filters {
-                        if let Some(container_value) = c.labels.get(key) {
-                            if let Some(expected_value) = value {
-                                if container_value != expected_value {
-                                    return false;
-                                }
-                            }
-                        } else {
-                            return false;
-                        }
-                    }
-                }
-                true
-            })
             .map(|c| {
                 // Convert ports to PortInfo
                 let ports: Vec<PortInfo> = c
@@ -839,18 +1220,7 @@ impl ApiHandler {
                     .collect();
 
                 // Build network settings
-                let mut networks = std::collections::HashMap::new();
-                networks.insert(
-                    c.network_mode.clone(),
-                    EndpointSettings {
-                        network_id: c.network_mode.clone(),
-                        endpoint_id: format!("{}-ep", c.id),
-                        gateway: "172.17.0.1".to_string(),
-                        ip_address: "172.17.0.2".to_string(),
-                        ip_prefix_len: 16,
-                        ..Default::default()
-                    },
-                );
+                let networks = endpoint_settings(c);
 
                 ContainerListItem {
                     id: c.id.clone(),
@@ -860,7 +1230,12 @@ impl ApiHandler {
                     command: c.cmd.join(" "),
                     created: c.created_at.timestamp(),
                     state: c.status.to_string().to_lowercase(),
-                    status: format_container_status(&c.status, c.started_at, c.finished_at),
+                    status: format_container_status(
+                        &c.status,
+                        c.started_at,
+                        c.finished_at,
+                        c.health.as_ref().map(|h| h.status),
+                    ),
                     ports,
                     labels: c.labels.clone(),
                     network_settings: NetworkSettingsSummary { networks },
@@ -884,7 +1259,7 @@ impl ApiHandler {
             format!("rune-{}", &uuid::Uuid::new_v4().to_string()[..8])
         };
 
-        let request: ContainerCreateRequest = serde_json::from_str(body)?;
+        let request: ContainerCreateRequest = parse_body(body)?;
         let mut config = ContainerConfig::new(&name, &request.image);
 
         // Set command
@@ -911,6 +1286,29 @@ impl ApiHandler {
             config.working_dir = wd;
         }
 
+        config.healthcheck = request.healthcheck;
+        config.tty = request.tty.unwrap_or(false);
+        config.open_stdin = request.open_stdin.unwrap_or(false);
+
+        // Stop the container as its image says unless told otherwise
+        let image_config = self
+            .images
+            .as_ref()
+            .and_then(|images| images.get(&request.image).ok())
+            .map(|image| image.config);
+        config.stop_signal = request.stop_signal.or_else(|| {
+            image_config
+                .as_ref()
+                .map(|c| c.stop_signal.clone())
+                .filter(|signal| !signal.is_empty())
+        });
+        config.stop_timeout = request.stop_timeout.or_else(|| {
+            image_config
+                .as_ref()
+                .and_then(|c| c.stop_timeout)
+                .map(u64::from)
+        });
+
         // Set hostname
         if let Some(hostname) = request.hostname {
             config.hostname = hostname;
@@ -921,6 +1319,22 @@ impl ApiHandler {
             config.user = user;
         }
 
+        // Addresses and aliases asked for on networks
+        let endpoints = request
+            .networking_config
+            .and_then(|networking| networking.endpoints_config)
+            .unwrap_or_default();
+        for (network, endpoint) in endpoints {
+            config.endpoints.insert(
+                network,
+                crate::network::EndpointConfig {
+                    ipv4_address: endpoint.ipam_config.and_then(|ipam| ipam.ipv4_address),
+                    mac_address: endpoint.mac_address,
+                    aliases: endpoint.aliases.unwrap_or_default(),
+                },
+            );
+        }
+
         // Handle host config options
         if let Some(host_config) = request.host_config {
             // Set network mode
@@ -933,6 +1347,8 @@ impl ApiHandler {
                 config.privileged = privileged;
             }
 
+            config.auto_remove = host_config.auto_remove.unwrap_or(false);
+
             // Set memory limit
             if let Some(memory) = host_config.memory {
                 config.resources.memory_limit = Some(memory as u64);
@@ -951,17 +1367,37 @@ impl ApiHandler {
                 config.resources.cpu_quota = Some(cpu_quota);
             }
 
+            config.log_config = host_config.log_config;
+            config.cap_add = host_config.cap_add.unwrap_or_default();
+            config.cap_drop = host_config.cap_drop.unwrap_or_default();
+            config.security_opt = host_config.security_opt.unwrap_or_default();
+            config.userns_mode = host_config.userns_mode.unwrap_or_default();
+            config.init = host_config.init.unwrap_or(false);
+            for device in host_config.devices.unwrap_or_default() {
+                add_device(&mut config, device)?;
+            }
+            for request in host_config.device_requests.unwrap_or_default() {
+                add_device_request(&mut config, request)?;
+            }
+            config.hooks = host_config.hooks.unwrap_or_default();
+            config.ulimits = host_config
+                .ulimits
+                .unwrap_or_default()
+                .into_iter()
+                .map(|ulimit| Ulimit {
+                    name: ulimit.name,
+                    soft: ulimit.soft,
+                    hard: ulimit.hard,
+                })
+                .collect();
+            config.sysctls = host_config.sysctls.unwrap_or_default();
+            config.oom_kill_disable = host_config.oom_kill_disable.unwrap_or(false);
+            config.oom_score_adj = host_config.oom_score_adj;
+
             // Handle volume binds
             if let Some(binds) = host_config.binds {
                 for bind in binds {
-                    let parts: Vec<&str> = bind.split(':').collect();
-                    if parts.len() >= 2 {
-                        config.volumes.push(crate::container::VolumeMount {
-                            host_path: parts[0].to_string(),
-                            container_path: parts[1].to_string(),
-                            read_only: parts.get(2).map(|m| *m == "ro").unwrap_or(false),
-                        });
-                    }
+                    config.add_volume(&bind)?;
                 }
             }
 
@@ -1004,7 +1440,10 @@ impl ApiHandler {
             }
         }
 
-        let id = self.container_manager.create(config)?;
+        let id = self
+            .container_manager
+            .create(config)
+            .with_context(|| format!("Cannot create container {}", name))?;
         let response = ContainerCreateResponse {
             id,
             warnings: vec![],
@@ -1130,18 +1569,11 @@ impl ApiHandler {
         };
 
         // Build network settings
-        let mut networks = std::collections::HashMap::new();
-        networks.insert(
-            container.network_mode.clone(),
-            EndpointSettings {
-                network_id: container.network_mode.clone(),
-                endpoint_id: format!("{}-ep", container.id),
-                gateway: "172.17.0.1".to_string(),
-                ip_address: "172.17.0.2".to_string(),
-                ip_prefix_len: 16,
-                ..Default::default()
-            },
-        );
+        let networks = endpoint_settings(&container);
+        let primary = networks
+            .get(&container.network_mode)
+            .cloned()
+            .unwrap_or_default();
 
         let response = ContainerInspect {
             id: container.id.clone(),
@@ -1170,12 +1602,17 @@ impl ApiHandler {
                     .finished_at
                     .map(|t| t.to_rfc3339())
                     .unwrap_or_default(),
+                health: container.health.clone(),
             },
             image: container.image.clone(),
             name: format!("/{}", container.name),
             restart_count: 0,
             driver: "overlay2".to_string(),
             platform: "linux".to_string(),
+            seccomp_profile: container.seccomp()?.name().to_string(),
+            apparmor_profile: container.security_labels.apparmor_profile.clone(),
+            process_label: container.security_labels.process_label.clone(),
+            mount_label: container.security_labels.mount_label.clone(),
             config: ContainerConfigResponse {
                 hostname: container.hostname.clone(),
                 domainname: container.domainname.clone(),
@@ -1184,8 +1621,8 @@ impl ApiHandler {
                 attach_stdout: true,
                 attach_stderr: true,
                 exposed_ports,
-                tty: false,
-                open_stdin: false,
+                tty: container.tty,
+                open_stdin: container.open_stdin,
                 stdin_once: false,
                 env,
                 cmd: container.cmd.clone(),
@@ -1198,13 +1635,16 @@ impl ApiHandler {
                     Some(container.entrypoint.clone())
                 },
                 labels: container.labels.clone(),
+                healthcheck: container.healthcheck.clone(),
+                stop_signal: container.stop_signal.clone(),
+                stop_timeout: container.stop_timeout,
             },
             host_config: HostConfigResponse {
                 binds,
                 network_mode: container.network_mode.clone(),
                 port_bindings,
                 restart_policy: RestartPolicyResponse::default(),
-                auto_remove: false,
+                auto_remove: container.auto_remove,
                 privileged: container.privileged,
                 publish_all_ports: false,
                 read_only_rootfs: container.read_only_rootfs,
@@ -1217,6 +1657,37 @@ impl ApiHandler {
                 cpuset_cpus: "".to_string(),
                 cpuset_mems: "".to_string(),
                 pids_limit: container.resources.pids_limit,
+                log_config: self.container_manager.log_config(&container.id)?,
+                cap_add: non_empty(&container.cap_add),
+                cap_drop: non_empty(&container.cap_drop),
+                security_opt: non_empty(&container.security_opt),
+                userns_mode: container.userns_mode.clone(),
+                init: container.init,
+                devices: container
+                    .devices
+                    .iter()
+                    .map(|device| HostDevice {
+                        path_on_host: device.path_on_host.clone(),
+                        path_in_container: device.path_in_container.clone(),
+                        cgroup_permissions: device.cgroup_permissions.clone(),
+                    })
+                    .collect(),
+                device_requests: device_requests(&container),
+                hooks: (!container.hooks.is_empty()).then(|| container.hooks.clone()),
+                ulimits: (!container.ulimits.is_empty()).then(|| {
+                    container
+                        .ulimits
+                        .iter()
+                        .map(|ulimit| HostUlimit {
+                            name: ulimit.name.clone(),
+                            soft: ulimit.soft,
+                            hard: ulimit.hard,
+                        })
+                        .collect()
+                }),
+                sysctls: container.sysctls.clone(),
+                oom_kill_disable: container.oom_kill_disable,
+                oom_score_adj: container.oom_score_adj.unwrap_or(0),
             },
             network_settings: NetworkSettingsResponse {
                 bridge: "".to_string(),
@@ -1232,10 +1703,10 @@ impl ApiHandler {
                 gateway: "172.17.0.1".to_string(),
                 global_ipv6_address: "".to_string(),
                 global_ipv6_prefix_len: 0,
-                ip_address: "172.17.0.2".to_string(),
+                ip_address: primary.ip_address,
                 ip_prefix_len: 16,
                 ipv6_gateway: "".to_string(),
-                mac_address: "02:42:ac:11:00:02".to_string(),
+                mac_address: primary.mac_address,
                 networks,
             },
             mounts,
@@ -1244,18 +1715,73 @@ impl ApiHandler {
         Ok(serde_json::to_string(&response)?)
     }
 
-    fn start_container(&self, id: &str) -> Result<String> {
-        self.container_manager.start(id)?;
+    fn start_container(&self, id: &str, path: &str) -> Result<String> {
+        match parse_query_string(path, "checkpoint") {
+            Some(checkpoint) => {
+                let dir = parse_query_string(path, "checkpoint-dir").map(PathBuf::from);
+                self.container_manager
+                    .restore(id, &checkpoint, dir.as_deref())
+                    .with_context(|| {
+                        format!("Cannot restore container {} from {}", id, checkpoint)
+                    })?
+            }
+            None => self
+                .container_manager
+                .start(id)
+                .with_context(|| format!("Cannot start container {}", id))?,
+        }
+        Ok("".to_string())
+    }
+
+    fn list_checkpoints(&self, id: &str, path: &str) -> Result<String> {
+        let dir = parse_query_string(path, "dir").map(PathBuf::from);
+        let checkpoints = self.container_manager.checkpoints(id, dir.as_deref())?;
+        Ok(serde_json::to_string(&checkpoints)?)
+    }
+
+    fn create_checkpoint(&self, id: &str, body: &str) -> Result<String> {
+        let request: CheckpointCreateRequest = parse_body(body)?;
+        let tcp = match (request.tcp_established, request.tcp_close) {
+            (true, true) => {
+                return Err(RuneError::InvalidConfig(
+                    "TcpEstablished and TcpClose are exclusive".to_string(),
+                ))
+            }
+            (true, false) => TcpMode::Established,
+            (false, true) => TcpMode::Close,
+            (false, false) => TcpMode::Refuse,
+        };
+        let options = CheckpointOptions {
+            leave_running: !request.exit,
+            tcp,
+            file_locks: request.file_locks,
+        };
+        let dir = request
+            .checkpoint_dir
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        self.container_manager
+            .checkpoint(id, &request.checkpoint_id, dir.as_deref(), options)?;
+        Ok("".to_string())
+    }
+
+    fn remove_checkpoint(&self, id: &str, name: &str, path: &str) -> Result<String> {
+        let dir = parse_query_string(path, "dir").map(PathBuf::from);
+        self.container_manager
+            .remove_checkpoint(id, name, dir.as_deref())?;
         Ok("".to_string())
     }
 
-    fn stop_container(&self, id: &str) -> Result<String> {
-        self.container_manager.stop(id)?;
+    fn stop_container(&self, id: &str, path: &str) -> Result<String> {
+        self.container_manager
+            .stop_with_timeout(id, stop_timeout(path))?;
         Ok("".to_string())
     }
 
-    fn restart_container(&self, id: &str) -> Result<String> {
-        let _ = self.container_manager.stop(id);
+    fn restart_container(&self, id: &str, path: &str) -> Result<String> {
+        let _ = self
+            .container_manager
+            .stop_with_timeout(id, stop_timeout(path));
         self.container_manager.start(id)?;
         Ok("".to_string())
     }
@@ -1266,8 +1792,10 @@ impl ApiHandler {
         Ok("".to_string())
     }
 
-    fn list_networks(&self) -> Result<String> {
-        let response = json!([
+    fn list_networks(&self, path: &str) -> Result<String> {
+        let filters = query_filters(path)?;
+        filters.validate(crate::network::NETWORK_FILTERS)?;
+        let networks = json!([
             {
                 "Name": "bridge",
                 "Id": "bridge",
@@ -1314,7 +1842,23 @@ impl ApiHandler {
                 "Labels": {}
             }
         ]);
-        Ok(response.to_string())
+        // The default networks: built in, unlabelled and never dangling
+        let response: Vec<&Value> = networks
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|network| {
+                let field = |name: &str| network[name].as_str().unwrap_or_default().to_string();
+                filters.bool("dangling").ok().flatten() != Some(true)
+                    && filters.get("label").is_empty()
+                    && filters.matches_exact("driver", &field("Driver"))
+                    && filters.matches("id", |id| field("Id").starts_with(id))
+                    && filters.matches("name", |name| field("Name").contains(name))
+                    && filters.matches_exact("scope", &field("Scope"))
+                    && filters.matches_exact("type", "builtin")
+            })
+            .collect();
+        Ok(json!(response).to_string())
     }
 
     // Additional container methods for Portainer compatibility
@@ -1326,7 +1870,22 @@ impl ApiHandler {
         .to_string())
     }
 
-    fn container_stats(&self, _id: &str, _path: &str) -> Result<String> {
+    fn container_stats(&self, id: &str, _path: &str) -> Result<String> {
+        let container = self.container_manager.get(id)?;
+        // Stopped containers have no cgroup to sample
+        let metrics = match &self.metrics {
+            Some(metrics)
+                if matches!(container.status, crate::container::ContainerStatus::Running) =>
+            {
+                metrics
+                    .sample(&container.id, container.pid)
+                    .unwrap_or_else(|e| {
+                        debug!("Failed to sample {}: {}", container.id, e);
+                        ContainerMetrics::default()
+                    })
+            }
+            _ => ContainerMetrics::default(),
+        };
         Ok(json!({
             "read": chrono::Utc::now().to_rfc3339(),
             "preread": chrono::Utc::now().to_rfc3339(),
@@ -1335,20 +1894,51 @@ impl ApiHandler {
             "num_procs": 0,
             "storage_stats": {},
             "cpu_stats": {
-                "cpu_usage": {"total_usage": 0, "percpu_usage": [], "usage_in_kernelmode": 0, "usage_in_usermode": 0},
+                "cpu_usage": {"total_usage": metrics.cpu_usage_usec * 1000, "percpu_usage": [], "usage_in_kernelmode": 0, "usage_in_usermode": 0},
                 "system_cpu_usage": 0,
                 "online_cpus": num_cpus::get(),
                 "throttling_data": {"periods": 0, "throttled_periods": 0, "throttled_time": 0}
             },
             "precpu_stats": {},
-            "memory_stats": {"usage": 0, "max_usage": 0, "stats": {}, "limit": 0},
-            "name": "",
-            "id": ""
+            "memory_stats": {
+                "usage": metrics.memory_usage,
+                "max_usage": 0,
+                "stats": {"oom_kill": metrics.oom_kills},
+                "limit": metrics.memory_limit.unwrap_or(0)
+            },
+            "networks": {
+                "eth0": {"rx_bytes": metrics.network.rx_bytes, "tx_bytes": metrics.network.tx_bytes}
+            },
+            "pressure_stats": {
+                "cpu": pressure_json(metrics.pressure.cpu),
+                "memory": pressure_json(metrics.pressure.memory),
+                "io": pressure_json(metrics.pressure.io)
+            },
+            "name": format!("/{}", container.name),
+            "id": container.id
         }).to_string())
     }
 
-    fn kill_container(&self, id: &str, _path: &str) -> Result<String> {
-        self.container_manager.stop(id)?;
+    fn container_stats_history(&self, id: &str, path: &str) -> Result<String> {
+        let store = self.stats_history.as_ref().ok_or_else(|| {
+            RuneError::new(
+                ErrorKind::NotFound,
+                "The daemon keeps no stats history".to_string(),
+            )
+        })?;
+        let container = self.container_manager.get(id)?;
+        let since = parse_query_param(path, "since")
+            .and_then(|since| chrono::DateTime::from_timestamp(since as i64, 0));
+        Ok(serde_json::to_string(
+            &store.history(&container.id, since)?,
+        )?)
+    }
+
+    fn kill_container(&self, id: &str, path: &str) -> Result<String> {
+        let signal = parse_query_string(path, "signal")
+            .map(|signal| parse_signal(&signal))
+            .transpose()?;
+        self.container_manager.kill(id, signal)?;
         Ok("".to_string())
     }
 
@@ -1371,8 +1961,41 @@ impl ApiHandler {
         Ok(json!({"Warnings": []}).to_string())
     }
 
-    fn container_logs(&self, _id: &str, _path: &str) -> Result<String> {
-        Ok("".to_string())
+    fn container_logs(&self, id: &str, path: &str) -> Result<String> {
+        let container = self.container_manager.get(id)?;
+        let flag = |name: &str| {
+            parse_query_string(path, name)
+                .map(|value| value == "1" || value == "true")
+                .unwrap_or(false)
+        };
+        let (stdout, stderr) = match (flag("stdout"), flag("stderr")) {
+            (false, false) => (true, true),
+            streams => streams,
+        };
+        let request = crate::swarm::logs::LogRequest {
+            container_id: container.id,
+            tail: parse_query_param(path, "tail").map(|tail| tail as usize),
+            since: parse_query_param(path, "since")
+                .and_then(|since| chrono::DateTime::from_timestamp(since as i64, 0)),
+        };
+
+        let mut output = String::new();
+        for line in self.container_manager.read_logs(&request)? {
+            if !(if line.stream == "stderr" {
+                stderr
+            } else {
+                stdout
+            }) {
+                continue;
+            }
+            if flag("timestamps") {
+                output.push_str(&line.timestamp.to_rfc3339());
+                output.push(' ');
+            }
+            output.push_str(&line.message);
+            output.push('\n');
+        }
+        Ok(output)
     }
 
     fn wait_container(&self, _id: &str) -> Result<String> {
@@ -1384,7 +2007,10 @@ impl ApiHandler {
     }
 
     // Image methods for Portainer compatibility
-    fn list_images(&self, _path: &str) -> Result<String> {
+    fn list_images(&self, path: &str) -> Result<String> {
+        let filters = query_filters(path)?;
+        filters.validate(crate::image::IMAGE_FILTERS)?;
+        filters.bool("dangling")?;
         Ok("[]".to_string())
     }
 
@@ -1440,6 +2066,9 @@ impl ApiHandler {
 
     // Network methods
     fn inspect_network(&self, id: &str) -> Result<String> {
+        if let Some(networks) = &self.networks {
+            return Ok(network_json(&networks.get(id)?).to_string());
+        }
         let driver = match id {
             "bridge" => "bridge",
             "host" => "host",
@@ -1465,6 +2094,11 @@ impl ApiHandler {
     }
 
     fn create_network(&self, body: &str) -> Result<String> {
+        if let Some(networks) = &self.networks {
+            let request: Value = parse_body(body)?;
+            let id = networks.create(network_config(&request)?)?;
+            return Ok(json!({"Id": id, "Warning": ""}).to_string());
+        }
         let request: Value = serde_json::from_str(body).unwrap_or(json!({}));
         let _name = request
             .get("Name")
@@ -1474,15 +2108,51 @@ impl ApiHandler {
         Ok(json!({"Id": id, "Warning": ""}).to_string())
     }
 
-    fn remove_network(&self, _id: &str) -> Result<String> {
+    fn remove_network(&self, id: &str) -> Result<String> {
+        if let Some(networks) = &self.networks {
+            networks.remove(id)?;
+        }
         Ok("".to_string())
     }
 
-    fn connect_network(&self, _id: &str, _body: &str) -> Result<String> {
+    fn connect_network(&self, id: &str, body: &str) -> Result<String> {
+        let request: Value = parse_body(body)?;
+        let container = request_container(&request)?;
+        let settings = request.get("EndpointConfig");
+        let string = |value: Option<&Value>| {
+            value
+                .and_then(|v| v.as_str())
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        let endpoint = crate::network::EndpointConfig {
+            ipv4_address: string(settings.and_then(|s| s.pointer("/IPAMConfig/IPv4Address"))),
+            mac_address: string(settings.and_then(|s| s.get("MacAddress"))),
+            aliases: settings
+                .and_then(|s| s.get("Aliases"))
+                .and_then(|v| v.as_array())
+                .map(|aliases| {
+                    aliases
+                        .iter()
+                        .filter_map(|alias| alias.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        self.container_manager
+            .connect_network(container, id, endpoint)?;
         Ok("".to_string())
     }
 
-    fn disconnect_network(&self, _id: &str, _body: &str) -> Result<String> {
+    fn disconnect_network(&self, id: &str, body: &str) -> Result<String> {
+        let request: Value = parse_body(body)?;
+        let container = request_container(&request)?;
+        let force = request
+            .get("Force")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        self.container_manager
+            .disconnect_network(container, id, force)?;
         Ok("".to_string())
     }
 
@@ -1491,11 +2161,25 @@ impl ApiHandler {
     }
 
     // Volume methods
-    fn list_volumes(&self, _path: &str) -> Result<String> {
+    fn list_volumes(&self, path: &str) -> Result<String> {
+        let filters = query_filters(path)?;
+        if let Some(volumes) = &self.volumes {
+            let volumes: Vec<Value> = volumes
+                .list_filtered(&filters)?
+                .iter()
+                .map(volume_json)
+                .collect();
+            return Ok(json!({"Volumes": volumes, "Warnings": []}).to_string());
+        }
+        filters.validate(crate::storage::VOLUME_FILTERS)?;
+        filters.bool("dangling")?;
         Ok(json!({"Volumes": [], "Warnings": []}).to_string())
     }
 
     fn inspect_volume(&self, name: &str) -> Result<String> {
+        if let Some(volumes) = &self.volumes {
+            return Ok(volume_json(&volumes.get(name)?).to_string());
+        }
         Ok(json!({
             "Name": name,
             "Driver": "local",
@@ -1510,6 +2194,20 @@ impl ApiHandler {
     }
 
     fn create_volume(&self, body: &str) -> Result<String> {
+        if let Some(volumes) = &self.volumes {
+            let request: Value = parse_body(body)?;
+            let driver = request["Driver"]
+                .as_str()
+                .filter(|driver| !driver.is_empty())
+                .map(VolumeDriver::from);
+            let volume = volumes.create(
+                request["Name"].as_str().unwrap_or_default(),
+                driver,
+                string_map(&request["DriverOpts"]),
+                string_map(&request["Labels"]),
+            )?;
+            return Ok(volume_json(&volume).to_string());
+        }
         let request: Value = serde_json::from_str(body).unwrap_or(json!({}));
         let default_name = uuid::Uuid::new_v4().to_string();
         let name = request
@@ -1529,7 +2227,11 @@ impl ApiHandler {
         .to_string())
     }
 
-    fn remove_volume(&self, _name: &str, _path: &str) -> Result<String> {
+    fn remove_volume(&self, name: &str, path: &str) -> Result<String> {
+        if let Some(volumes) = &self.volumes {
+            let force = path.contains("force=true") || path.contains("force=1");
+            volumes.remove(name, force)?;
+        }
         Ok("".to_string())
     }
 
@@ -1556,7 +2258,7 @@ impl ApiHandler {
     // Exec methods for Portainer terminal
     fn create_exec(&self, container_id: &str, body: &str) -> Result<String> {
         // Verify container exists
-        let _container = self.container_manager.get(container_id)?;
+        let container = self.container_manager.get(container_id)?;
 
         let request: ExecCreateRequest = serde_json::from_str(body).unwrap_or(ExecCreateRequest {
             attach_stdin: Some(false),
@@ -1576,7 +2278,7 @@ impl ApiHandler {
         // Store exec instance
         let instance = ExecInstance {
             id: exec_id.clone(),
-            container_id: container_id.to_string(),
+            container_id: container.id,
             cmd: request.cmd.unwrap_or_else(|| vec!["/bin/sh".to_string()]),
             env: request.env.unwrap_or_default(),
             tty: request.tty.unwrap_or(false),
@@ -1614,10 +2316,10 @@ impl ApiHandler {
                 instance.pid = Some(std::process::id() as i64);
                 instance.clone()
             } else {
-                return Err(RuneError::Api(format!(
-                    "No such exec instance: {}",
-                    exec_id
-                )));
+                return Err(RuneError::new(
+                    ErrorKind::NotFound,
+                    format!("No such exec instance: {}", exec_id),
+                ));
             }
         };
 
@@ -1716,7 +2418,7 @@ impl ApiHandler {
         let container = self.container_manager.get(container_id)?;
 
         if !matches!(container.status, crate::container::ContainerStatus::Running) {
-            return Err(RuneError::Api("Container is not running".to_string()));
+            return Err(RuneError::ContainerNotRunning(container_id.to_string()));
         }
 
         // Parse attach options from query string
@@ -1744,7 +2446,7 @@ impl ApiHandler {
         let container = self.container_manager.get(container_id)?;
 
         if !matches!(container.status, crate::container::ContainerStatus::Running) {
-            return Err(RuneError::Api("Container is not running".to_string()));
+            return Err(RuneError::ContainerNotRunning(container_id.to_string()));
         }
 
         // Parse options
@@ -1799,6 +2501,20 @@ impl ApiHandler {
         Ok("".to_string())
     }
 
+    fn unlock_swarm(&self, body: &str) -> Result<String> {
+        let unlock = self
+            .swarm_unlock
+            .as_ref()
+            .ok_or_else(|| RuneError::Swarm("This node is not part of a swarm".to_string()))?;
+        let request: Value = parse_body(body)?;
+        let key = request
+            .get("UnlockKey")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RuneError::new(ErrorKind::InvalidArgument, "UnlockKey is required"))?;
+        unlock(key)?;
+        Ok("".to_string())
+    }
+
     fn get_unlock_key(&self) -> Result<String> {
         Ok(json!({"UnlockKey": ""}).to_string())
     }
@@ -1983,7 +2699,7 @@ impl ApiHandler {
     }
 
     fn create_config(&self, body: &str) -> Result<String> {
-        let request: crate::swarm::config::ConfigCreateRequest = serde_json::from_str(body)?;
+        let request: crate::swarm::config::ConfigCreateRequest = parse_body(body)?;
         let spec: crate::swarm::ConfigSpec = request.into();
         let id = self.config_manager.create(spec)?;
         Ok(json!({"ID": id}).to_string())
@@ -1996,11 +2712,11 @@ impl ApiHandler {
 
     fn update_config(&self, id: &str, path: &str, body: &str) -> Result<String> {
         // Parse version from query string (required for optimistic locking)
-        let version = parse_query_param(path, "version")
-            .ok_or_else(|| RuneError::Api("version query parameter is required".to_string()))?
-            as u64;
+        let version = parse_query_param(path, "version").ok_or_else(|| {
+            RuneError::InvalidConfig("version query parameter is required".to_string())
+        })? as u64;
 
-        let request: crate::swarm::config::ConfigCreateRequest = serde_json::from_str(body)?;
+        let request: crate::swarm::config::ConfigCreateRequest = parse_body(body)?;
         let spec: crate::swarm::ConfigSpec = request.into();
 
         self.config_manager.update(id, spec, version)?;
@@ -2008,20 +2724,51 @@ impl ApiHandler {
     }
 
     // Plugin methods
+    fn plugin_manager(&self) -> Result<&PluginManager> {
+        self.plugins
+            .as_deref()
+            .ok_or_else(|| RuneError::Plugin("Plugins are not supported".to_string()))
+    }
+
+    /// Names of the enabled plugins implementing a driver subsystem
+    fn plugin_drivers(&self, kind: &str) -> Vec<String> {
+        self.plugins
+            .as_ref()
+            .and_then(|plugins| plugins.drivers(kind).ok())
+            .unwrap_or_default()
+    }
+
     fn list_plugins(&self, _path: &str) -> Result<String> {
-        Ok("[]".to_string())
+        let plugins = match &self.plugins {
+            Some(plugins) => plugins.list()?,
+            None => Vec::new(),
+        };
+        let plugins: Vec<Value> = plugins.iter().map(plugin_json).collect();
+        Ok(serde_json::to_string(&plugins)?)
     }
 
     fn inspect_plugin(&self, name: &str) -> Result<String> {
-        Ok(json!({
-            "Id": name,
-            "Name": name,
-            "Enabled": true,
-            "Settings": {"Mounts": [], "Env": [], "Args": [], "Devices": []},
-            "PluginReference": "",
-            "Config": {}
-        })
-        .to_string())
+        let plugin = match &self.plugins {
+            Some(plugins) => plugins.get(name)?,
+            None => return Err(RuneError::PluginNotFound(name.to_string())),
+        };
+        Ok(plugin_json(&plugin).to_string())
+    }
+
+    fn enable_plugin(&self, name: &str) -> Result<String> {
+        self.plugin_manager()?.enable(name)?;
+        Ok("".to_string())
+    }
+
+    fn disable_plugin(&self, name: &str) -> Result<String> {
+        self.plugin_manager()?.disable(name)?;
+        Ok("".to_string())
+    }
+
+    fn remove_plugin(&self, name: &str, path: &str) -> Result<String> {
+        let force = path.contains("force=true") || path.contains("force=1");
+        self.plugin_manager()?.remove(name, force)?;
+        Ok("".to_string())
     }
 
     // Distribution methods
@@ -2100,7 +2847,32 @@ fn get_os_name() -> String {
     }
 }
 
+/// A request's JSON body, which the caller is to blame for when malformed
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T> {
+    serde_json::from_str(body).map_err(|e| {
+        RuneError::new(
+            ErrorKind::InvalidArgument,
+            format!("Invalid request body: {}", e),
+        )
+    })
+}
+
 /// Simple URL decoding for filter parameters
+/// Filters in the `filters` query parameter
+fn query_filters(path: &str) -> Result<Filters> {
+    let query = path.split_once('?').map(|(_, query)| query).unwrap_or("");
+    match query
+        .split('&')
+        .find_map(|param| param.strip_prefix("filters="))
+    {
+        Some(filters) => Filters::from_json(
+            &urlencoding_decode(filters)
+                .map_err(|_| RuneError::InvalidConfig("Invalid filter encoding".to_string()))?,
+        ),
+        None => Ok(Filters::new()),
+    }
+}
+
 fn urlencoding_decode(input: &str) -> std::result::Result<String, ()> {
     let mut result = String::with_capacity(input.len());
     let mut chars = input.chars().peekable();
@@ -2147,21 +2919,56 @@ fn parse_port_spec(spec: &str) -> (u16, crate::container::Protocol) {
     (port, protocol)
 }
 
+/// Plugin as the plugin endpoints describe it
+fn plugin_json(plugin: &Plugin) -> Value {
+    let types: Vec<String> = plugin
+        .implements
+        .iter()
+        .map(|kind| format!("docker.{}/1.0", kind.to_lowercase()))
+        .collect();
+    json!({
+        "Id": plugin.id,
+        "Name": plugin.name,
+        "Enabled": plugin.enabled,
+        "Settings": {"Mounts": [], "Env": [], "Args": [], "Devices": []},
+        "PluginReference": "",
+        "Config": {
+            "Description": "",
+            "Documentation": "",
+            "Interface": {
+                "Types": types,
+                "Socket": plugin.socket,
+                "ProtocolScheme": "moby.plugins.http/v1"
+            },
+            "Entrypoint": [],
+            "WorkDir": "",
+            "Env": [],
+            "Args": {"Name": "", "Description": "", "Settable": [], "Value": []}
+        }
+    })
+}
+
 /// Format container status string like Docker does
 fn format_container_status(
     status: &crate::container::ContainerStatus,
     started_at: Option<chrono::DateTime<chrono::Utc>>,
     finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    health: Option<HealthStatus>,
 ) -> String {
     use crate::container::ContainerStatus;
 
     match status {
         ContainerStatus::Running => {
-            if let Some(started) = started_at {
+            let up = if let Some(started) = started_at {
                 let duration = chrono::Utc::now().signed_duration_since(started);
                 format!("Up {}", format_duration(duration))
             } else {
                 "Up".to_string()
+            };
+            match health {
+                Some(HealthStatus::Starting) => format!("{} (health: starting)", up),
+                Some(health) => format!("{} ({})", up, health),
+                None => up,
             }
         }
         ContainerStatus::Exited | ContainerStatus::Stopped => {
@@ -2193,6 +3000,245 @@ fn format_duration(duration: chrono::Duration) -> String {
     }
 }
 
+/// A list, or None when it's empty as Docker reports it
+/// Add a host device, or a CDI device given by its qualified name
+fn add_device(config: &mut ContainerConfig, device: HostDevice) -> Result<()> {
+    if cdi::is_qualified_name(&device.path_on_host) {
+        config.cdi_devices.push(device.path_on_host);
+        return Ok(());
+    }
+    let container_path = if device.path_in_container.is_empty() {
+        &device.path_on_host
+    } else {
+        &device.path_in_container
+    };
+    let permissions = if device.cgroup_permissions.is_empty() {
+        "rwm"
+    } else {
+        &device.cgroup_permissions
+    };
+    config.devices.push(DeviceMapping::parse(&format!(
+        "{}:{}:{}",
+        device.path_on_host, container_path, permissions
+    ))?);
+    Ok(())
+}
+
+/// Add the devices of a device request, as `--gpus` sends it
+fn add_device_request(config: &mut ContainerConfig, request: DeviceRequest) -> Result<()> {
+    let device_ids = request.device_ids.unwrap_or_default();
+    if request.driver == "cdi" {
+        config.cdi_devices.extend(device_ids);
+        return Ok(());
+    }
+    let gpu = request
+        .capabilities
+        .iter()
+        .flatten()
+        .any(|capabilities| capabilities.iter().any(|capability| capability == "gpu"));
+    if !gpu && !matches!(request.driver.as_str(), "nvidia" | "amd") {
+        return Err(RuneError::InvalidConfig(format!(
+            "Could not select device driver '{}' with capabilities: {:?}",
+            request.driver,
+            request.capabilities.unwrap_or_default()
+        )));
+    }
+    config.gpus = Some(if !device_ids.is_empty() {
+        GpuRequest::Devices(device_ids)
+    } else if request.count < 0 {
+        GpuRequest::All
+    } else {
+        GpuRequest::Count(request.count as usize)
+    });
+    Ok(())
+}
+
+/// Device requests of a container, as the API reports them
+fn device_requests(container: &ContainerConfig) -> Option<Vec<DeviceRequest>> {
+    let mut requests = Vec::new();
+    if !container.cdi_devices.is_empty() {
+        requests.push(DeviceRequest {
+            driver: "cdi".to_string(),
+            device_ids: Some(container.cdi_devices.clone()),
+            ..DeviceRequest::default()
+        });
+    }
+    if let Some(gpus) = &container.gpus {
+        let (count, device_ids) = match gpus {
+            GpuRequest::All => (-1, None),
+            GpuRequest::Count(count) => (*count as i64, None),
+            GpuRequest::Devices(ids) => (0, Some(ids.clone())),
+        };
+        requests.push(DeviceRequest {
+            driver: String::new(),
+            count,
+            device_ids,
+            capabilities: Some(vec![vec!["gpu".to_string()]]),
+        });
+    }
+    (!requests.is_empty()).then_some(requests)
+}
+
+/// Pressure stall information as the stats API reports it, `null` where
+/// the kernel has none
+/// Endpoint settings of a container by network, with the addresses and
+/// aliases its networks gave it
+fn endpoint_settings(
+    container: &ContainerConfig,
+) -> std::collections::HashMap<String, EndpointSettings> {
+    let mut networks = std::collections::HashMap::new();
+    let networks_joined =
+        std::iter::once(&container.network_mode).chain(container.endpoints.keys());
+    for network in networks_joined {
+        let endpoint = container.endpoints.get(network);
+        let settings = EndpointSettings {
+            network_id: network.clone(),
+            endpoint_id: format!("{}-ep", container.id),
+            gateway: "172.17.0.1".to_string(),
+            ip_address: endpoint
+                .and_then(|e| e.ipv4_address.clone())
+                .unwrap_or_else(|| "172.17.0.2".to_string()),
+            ip_prefix_len: 16,
+            mac_address: endpoint
+                .and_then(|e| e.mac_address.clone())
+                .unwrap_or_else(|| "02:42:ac:11:00:02".to_string()),
+            aliases: endpoint.and_then(|e| non_empty(&e.aliases)),
+            ..Default::default()
+        };
+        networks.insert(network.clone(), settings);
+    }
+    networks
+}
+
+/// Network a network create request asks for
+fn network_config(request: &Value) -> Result<NetworkConfig> {
+    let name = request
+        .get("Name")
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| RuneError::InvalidConfig("Name is required".to_string()))?;
+    let mut config = NetworkConfig::new(name)
+        .internal(request["Internal"].as_bool().unwrap_or(false))
+        .driver(request["Driver"].as_str().unwrap_or("bridge").parse()?);
+    if let Some(pool) = request.pointer("/IPAM/Config/0") {
+        if let Some(subnet) = pool["Subnet"].as_str() {
+            config = config.subnet(subnet);
+        }
+        if let Some(gateway) = pool["Gateway"].as_str() {
+            config = config.gateway(gateway);
+        }
+    }
+    if let Some(attachable) = request["Attachable"].as_bool() {
+        config.attachable = attachable;
+    }
+    config.options = string_map(&request["Options"]);
+    config.labels = string_map(&request["Labels"]);
+    Ok(config)
+}
+
+/// String values of a JSON object, such as options or labels
+fn string_map(value: &Value) -> HashMap<String, String> {
+    value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+        .collect()
+}
+
+/// Volume as the API reports it
+fn volume_json(volume: &Volume) -> Value {
+    json!({
+        "Name": volume.name,
+        "Driver": volume.driver.to_string(),
+        "Mountpoint": volume.mountpoint,
+        "CreatedAt": volume.created_at.to_rfc3339(),
+        "Status": volume.status,
+        "Labels": volume.labels,
+        "Scope": volume.scope,
+        "Options": volume.options,
+    })
+}
+
+/// Network as the API reports it, with its endpoints
+fn network_json(network: &NetworkConfig) -> Value {
+    let containers: serde_json::Map<String, Value> = network
+        .containers
+        .iter()
+        .map(|(id, endpoint)| {
+            (
+                id.clone(),
+                json!({
+                    "Name": endpoint.name,
+                    "EndpointID": endpoint.endpoint_id,
+                    "MacAddress": endpoint.mac_address,
+                    "IPv4Address": endpoint.ipv4_address.clone().unwrap_or_default(),
+                    "IPv6Address": endpoint.ipv6_address.clone().unwrap_or_default(),
+                }),
+            )
+        })
+        .collect();
+    json!({
+        "Name": network.name,
+        "Id": network.id,
+        "Created": network.created.to_rfc3339(),
+        "Scope": network.scope,
+        "Driver": match network.driver {
+            NetworkDriver::None => "null".to_string(),
+            ref driver => driver.to_string(),
+        },
+        "EnableIPv6": network.enable_ipv6,
+        "IPAM": {
+            "Driver": "default",
+            "Config": network.ipam.config.iter().map(|pool| json!({
+                "Subnet": pool.subnet,
+                "Gateway": pool.gateway.clone().unwrap_or_default(),
+            })).collect::<Vec<_>>(),
+        },
+        "Internal": network.internal,
+        "Attachable": network.attachable,
+        "Ingress": network.ingress,
+        "Containers": containers,
+        "Options": network.options,
+        "Labels": network.labels,
+    })
+}
+
+/// Container a network connect or disconnect request is for
+fn request_container(request: &Value) -> Result<&str> {
+    request
+        .get("Container")
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| RuneError::InvalidConfig("Container is required".to_string()))
+}
+
+fn pressure_json(pressure: Option<Pressure>) -> Value {
+    let values = |values: PressureValues| {
+        json!({
+            "avg10": values.avg10,
+            "avg60": values.avg60,
+            "avg300": values.avg300,
+            "total": values.total
+        })
+    };
+    match pressure {
+        Some(pressure) => json!({
+            "some": values(pressure.some),
+            "full": pressure.full.map(values)
+        }),
+        None => Value::Null,
+    }
+}
+
+fn non_empty(values: &[String]) -> Option<Vec<String>> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.to_vec())
+    }
+}
+
 /// Parse a query parameter as u32
 fn parse_query_param(path: &str, param: &str) -> Option<u32> {
     let query = path.split('?').nth(1)?;
@@ -2207,6 +3253,11 @@ fn parse_query_param(path: &str, param: &str) -> Option<u32> {
     None
 }
 
+/// Grace period of the `t` query parameter, in seconds
+fn stop_timeout(path: &str) -> Option<std::time::Duration> {
+    parse_query_param(path, "t").map(|secs| std::time::Duration::from_secs(secs.into()))
+}
+
 /// Parse a query parameter as string
 fn parse_query_string(path: &str, param: &str) -> Option<String> {
     let query = path.split('?').nth(1)?;
@@ -2248,6 +3299,38 @@ mod tests {
         assert!(result.unwrap().contains("Containers"));
     }
 
+    #[test]
+    fn test_run_attached() {
+        let handler = create_test_handler();
+        let caller = Caller::default();
+        let mut config = ContainerConfig::new("web", "nginx");
+        let body = serde_json::to_string(&config).unwrap();
+        let path = "/containers/run?name=web";
+
+        let error = handler
+            .run_attached(&caller, path, &body, Vec::new())
+            .unwrap_err();
+        assert_eq!(error.status_code(), 400);
+
+        // Started without a runtime, the container isn't left behind
+        let streams = || {
+            let (_, stdout) = std::io::pipe().unwrap();
+            let (_, stderr) = std::io::pipe().unwrap();
+            vec![stdout.into(), stderr.into()]
+        };
+        assert!(handler
+            .run_attached(&caller, path, &body, streams())
+            .is_err());
+        assert!(handler.container_manager.get("web").is_err());
+
+        config.tty = true;
+        let body = serde_json::to_string(&config).unwrap();
+        let error = handler
+            .run_attached(&caller, path, &body, streams())
+            .unwrap_err();
+        assert_eq!(error.status_code(), 400);
+    }
+
     #[test]
     fn test_ping() {
         let handler = create_test_handler();
@@ -2255,4 +3338,835 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "OK");
     }
+
+    #[test]
+    fn test_container_logs() {
+        let handler = create_test_handler();
+        let body = r#"{
+            "Image": "nginx",
+            "HostConfig": { "LogConfig": { "Type": "json-file", "Config": { "max-size": "1m" } } }
+        }"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(inspect["HostConfig"]["LogConfig"]["Type"], "json-file");
+        assert_eq!(
+            inspect["HostConfig"]["LogConfig"]["Config"]["max-size"],
+            "1m"
+        );
+
+        for (stream, message) in [("stdout", "one"), ("stderr", "two"), ("stdout", "three")] {
+            handler
+                .container_manager
+                .write_log(id, stream, message)
+                .unwrap();
+        }
+        let logs = |query: &str| {
+            handler
+                .handle_request("GET", &format!("/containers/{}/logs?{}", id, query), "")
+                .unwrap()
+        };
+        assert_eq!(logs("stdout=1&stderr=1"), "one\ntwo\nthree\n");
+        assert_eq!(logs("stdout=1"), "one\nthree\n");
+        assert_eq!(logs("tail=1"), "three\n");
+
+        let body = r#"{"Image": "nginx", "HostConfig": {"LogConfig": {"Type": "fluentd"}}}"#;
+        assert!(handler
+            .handle_request("POST", "/containers/create", body)
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_seccomp() {
+        let handler = create_test_handler();
+        let inspect = |body: &str| -> Value {
+            let created: Value = serde_json::from_str(
+                &handler
+                    .handle_request("POST", "/containers/create", body)
+                    .unwrap(),
+            )
+            .unwrap();
+            let path = format!("/containers/{}/json", created["Id"].as_str().unwrap());
+            serde_json::from_str(&handler.handle_request("GET", &path, "").unwrap()).unwrap()
+        };
+
+        let default = inspect(r#"{"Image": "nginx"}"#);
+        assert_eq!(default["SeccompProfile"], "default");
+        assert!(default["HostConfig"]["SecurityOpt"].is_null());
+
+        let unconfined =
+            inspect(r#"{"Image": "nginx", "HostConfig": {"SecurityOpt": ["seccomp=unconfined"]}}"#);
+        assert_eq!(unconfined["SeccompProfile"], "unconfined");
+        assert_eq!(
+            unconfined["HostConfig"]["SecurityOpt"][0],
+            "seccomp=unconfined"
+        );
+
+        let profile = r#"{\"defaultAction\": \"SCMP_ACT_ALLOW\"}"#;
+        let custom = inspect(&format!(
+            r#"{{"Image": "nginx", "HostConfig": {{"SecurityOpt": ["seccomp={}"]}}}}"#,
+            profile
+        ));
+        assert_eq!(custom["SeccompProfile"], "custom");
+
+        let privileged = inspect(r#"{"Image": "nginx", "HostConfig": {"Privileged": true}}"#);
+        assert_eq!(privileged["SeccompProfile"], "unconfined");
+
+        let invalid = r#"{"Image": "nginx", "HostConfig": {"SecurityOpt": ["seccomp={"]}}"#;
+        assert!(handler
+            .handle_request("POST", "/containers/create", invalid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_capabilities() {
+        let handler = create_test_handler();
+        let body = r#"{
+            "Image": "nginx",
+            "HostConfig": { "CapAdd": ["NET_ADMIN"], "CapDrop": ["MKNOD"] }
+        }"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(inspect["HostConfig"]["CapAdd"][0], "NET_ADMIN");
+        assert_eq!(inspect["HostConfig"]["CapDrop"][0], "MKNOD");
+
+        let body = r#"{"Image": "nginx", "HostConfig": {"CapAdd": ["NET_ADMN"]}}"#;
+        let error = handler
+            .handle_request("POST", "/containers/create", body)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("did you mean CAP_NET_ADMIN?"));
+    }
+
+    #[test]
+    fn test_container_security_labels() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = ContainerManager::new(temp_dir.path().to_path_buf())
+            .unwrap()
+            .with_lsm(Lsm::SeLinux);
+        let handler = ApiHandler::new(Arc::new(manager));
+        let info: Value = serde_json::from_str(&handler.get_info().unwrap()).unwrap();
+        assert!(info["SecurityOptions"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("name=selinux")));
+
+        let inspect = |body: &str| -> Value {
+            let created: Value = serde_json::from_str(
+                &handler
+                    .handle_request("POST", "/containers/create", body)
+                    .unwrap(),
+            )
+            .unwrap();
+            let id = created["Id"].as_str().unwrap();
+            serde_json::from_str(
+                &handler
+                    .handle_request("GET", &format!("/containers/{}/json", id), "")
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        let confined = inspect(r#"{"Image": "nginx"}"#);
+        assert!(confined["ProcessLabel"]
+            .as_str()
+            .unwrap()
+            .starts_with("system_u:system_r:container_t:s0:c"));
+        assert!(confined["MountLabel"]
+            .as_str()
+            .unwrap()
+            .starts_with("system_u:object_r:container_file_t:s0:c"));
+        assert_eq!(confined["AppArmorProfile"], "");
+
+        let custom = inspect(
+            r#"{"Image": "nginx", "HostConfig": {"SecurityOpt": ["label=level:s0:c1,c2"]}}"#,
+        );
+        assert_eq!(
+            custom["ProcessLabel"],
+            "system_u:system_r:container_t:s0:c1,c2"
+        );
+        let disabled =
+            inspect(r#"{"Image": "nginx", "HostConfig": {"SecurityOpt": ["label=disable"]}}"#);
+        assert_eq!(disabled["ProcessLabel"], "");
+
+        let invalid = r#"{"Image": "nginx", "HostConfig": {"SecurityOpt": ["label=bogus"]}}"#;
+        assert!(handler
+            .handle_request("POST", "/containers/create", invalid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_userns_remap() {
+        let temp_dir = TempDir::new().unwrap();
+        let remap = crate::runtime::UsernsRemap::from_ranges(
+            "default",
+            "runeremap:100000:65536\n",
+            "runeremap:100000:65536\n",
+        )
+        .unwrap();
+        let manager = ContainerManager::new(temp_dir.path().to_path_buf())
+            .unwrap()
+            .with_userns_remap(remap);
+        let handler = ApiHandler::new(Arc::new(manager));
+
+        let info: Value = serde_json::from_str(&handler.get_info().unwrap()).unwrap();
+        assert!(info["SecurityOptions"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("name=userns")));
+
+        let privileged = r#"{"Image": "nginx", "HostConfig": {"Privileged": true}}"#;
+        let error = handler
+            .handle_request("POST", "/containers/create", privileged)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--userns=host"));
+
+        let body =
+            r#"{"Image": "nginx", "HostConfig": {"Privileged": true, "UsernsMode": "host"}}"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(inspect["HostConfig"]["UsernsMode"], "host");
+
+        let invalid = r#"{"Image": "nginx", "HostConfig": {"UsernsMode": "private"}}"#;
+        assert!(handler
+            .handle_request("POST", "/containers/create", invalid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_devices() {
+        let handler = create_test_handler();
+        let body = r#"{"Image": "nginx", "HostConfig": {"Devices": [
+            {"PathOnHost": "/dev/null", "PathInContainer": "/dev/mynull", "CgroupPermissions": "rw"},
+            {"PathOnHost": "/dev/zero"}]}}"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        let devices = &inspect["HostConfig"]["Devices"];
+        assert_eq!(devices[0]["PathInContainer"], "/dev/mynull");
+        assert_eq!(devices[1]["PathInContainer"], "/dev/zero");
+        assert_eq!(devices[1]["CgroupPermissions"], "rwm");
+        assert!(inspect["HostConfig"]["DeviceRequests"].is_null());
+
+        // Without CDI specs there are no GPUs to give
+        let gpus = r#"{"Image": "nginx", "HostConfig": {"DeviceRequests": [
+            {"Count": -1, "Capabilities": [["gpu"]]}]}}"#;
+        let error = handler
+            .handle_request("POST", "/containers/create", gpus)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No GPUs found"));
+        let invalid = r#"{"Image": "nginx", "HostConfig": {"Devices": [
+            {"PathOnHost": "/dev/null", "CgroupPermissions": "rwx"}]}}"#;
+        assert!(handler
+            .handle_request("POST", "/containers/create", invalid)
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_hooks() {
+        let handler = create_test_handler();
+        let body = r#"{"Image": "nginx", "HostConfig": {"Hooks": {
+            "prestart": [{"path": "/usr/bin/netns-setup", "args": ["netns-setup", "-v"]}],
+            "poststop": [{"path": "/usr/bin/cleanup", "timeout": 5}]}}}"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        let hooks = &inspect["HostConfig"]["Hooks"];
+        assert_eq!(hooks["prestart"][0]["args"][1], "-v");
+        assert_eq!(hooks["poststop"][0]["timeout"], 5);
+
+        let relative = r#"{"Image": "nginx", "HostConfig": {"Hooks": {
+            "poststart": [{"path": "cleanup"}]}}}"#;
+        let error = handler
+            .handle_request("POST", "/containers/create", relative)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("must be absolute"));
+    }
+
+    #[test]
+    fn test_container_limits() {
+        let handler = create_test_handler();
+        let body = r#"{"Image": "nginx", "HostConfig": {
+            "Ulimits": [{"Name": "nofile", "Soft": 1024, "Hard": 2048}],
+            "Sysctls": {"net.ipv4.ip_forward": "1"},
+            "OomKillDisable": true, "OomScoreAdj": 500}}"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        let host_config = &inspect["HostConfig"];
+        assert_eq!(host_config["Ulimits"][0]["Hard"], 2048);
+        assert_eq!(host_config["Sysctls"]["net.ipv4.ip_forward"], "1");
+        assert_eq!(host_config["OomKillDisable"], true);
+        assert_eq!(host_config["OomScoreAdj"], 500);
+
+        for invalid in [
+            r#"{"Ulimits": [{"Name": "nofile", "Soft": 4096, "Hard": 1024}]}"#,
+            r#"{"Sysctls": {"vm.swappiness": "10"}}"#,
+            r#"{"NetworkMode": "host", "Sysctls": {"net.ipv4.ip_forward": "1"}}"#,
+            r#"{"OomScoreAdj": 2000}"#,
+        ] {
+            let body = format!(r#"{{"Image": "nginx", "HostConfig": {}}}"#, invalid);
+            assert!(handler
+                .handle_request("POST", "/containers/create", &body)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_container_stats() {
+        struct FixedMetrics;
+        impl MetricsSource for FixedMetrics {
+            fn sample(&self, _: &str, _: Option<u32>) -> Result<ContainerMetrics> {
+                let mut metrics = ContainerMetrics {
+                    cpu_usage_usec: 5,
+                    memory_usage: 1024,
+                    oom_kills: 2,
+                    ..ContainerMetrics::default()
+                };
+                metrics.pressure.memory = Some(Pressure {
+                    some: PressureValues {
+                        avg10: 1.5,
+                        total: 300,
+                        ..PressureValues::default()
+                    },
+                    full: None,
+                });
+                Ok(metrics)
+            }
+        }
+
+        let handler = create_test_handler().with_metrics(Arc::new(FixedMetrics));
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", r#"{"Image": "nginx"}"#)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let path = format!("/containers/{}/stats", id);
+
+        // Only running containers are sampled
+        let stats: Value =
+            serde_json::from_str(&handler.handle_request("GET", &path, "").unwrap()).unwrap();
+        assert_eq!(stats["memory_stats"]["usage"], 0);
+
+        handler
+            .handle_request("POST", &format!("/containers/{}/start", id), "")
+            .unwrap();
+        let stats: Value =
+            serde_json::from_str(&handler.handle_request("GET", &path, "").unwrap()).unwrap();
+        assert_eq!(stats["cpu_stats"]["cpu_usage"]["total_usage"], 5000);
+        assert_eq!(stats["memory_stats"]["usage"], 1024);
+        assert_eq!(stats["memory_stats"]["stats"]["oom_kill"], 2);
+        let memory = &stats["pressure_stats"]["memory"];
+        assert_eq!(memory["some"]["avg10"], 1.5);
+        assert_eq!(memory["some"]["total"], 300);
+        assert!(memory["full"].is_null());
+        assert!(stats["pressure_stats"]["cpu"].is_null());
+
+        assert!(handler
+            .handle_request("GET", "/containers/missing/stats", "")
+            .is_err());
+    }
+
+    #[test]
+    fn test_plugins() {
+        let temp_dir = TempDir::new().unwrap();
+        let (socket, _) = crate::plugin::client::tests::fake_plugin(
+            &temp_dir,
+            &[("Plugin.Activate", r#"{"Implements": ["VolumeDriver"]}"#)],
+        );
+        let plugins = Arc::new(PluginManager::open(temp_dir.path().join("plugins")).unwrap());
+        plugins.install("sshfs", &socket, true).unwrap();
+        let manager = Arc::new(ContainerManager::new(temp_dir.path().join("containers")).unwrap());
+        let handler = ApiHandler::new(manager).with_plugins(plugins);
+
+        let list: Value =
+            serde_json::from_str(&handler.handle_request("GET", "/plugins", "").unwrap()).unwrap();
+        assert_eq!(list[0]["Name"], "sshfs");
+        assert_eq!(list[0]["Enabled"], true);
+        assert_eq!(
+            list[0]["Config"]["Interface"]["Types"][0],
+            "docker.volumedriver/1.0"
+        );
+        let info: Value =
+            serde_json::from_str(&handler.handle_request("GET", "/info", "").unwrap()).unwrap();
+        assert_eq!(info["Plugins"]["Volume"], json!(["local", "sshfs"]));
+
+        let error = handler
+            .handle_request("DELETE", "/v1.43/plugins/sshfs", "")
+            .unwrap_err();
+        assert_eq!(error.status_code(), 409);
+        handler
+            .handle_request("POST", "/plugins/sshfs/disable", "")
+            .unwrap();
+        handler
+            .handle_request("DELETE", "/plugins/sshfs", "")
+            .unwrap();
+        let error = handler
+            .handle_request("GET", "/plugins/sshfs/json", "")
+            .unwrap_err();
+        assert_eq!(error.api_message(), "plugin \"sshfs\" not found");
+    }
+
+    #[test]
+    fn test_list_filters() {
+        let handler = create_test_handler();
+        let mut config = ContainerConfig::new("web", "nginx:1.25");
+        config.labels.insert("tier".to_string(), "web".to_string());
+        handler.container_manager.create(config).unwrap();
+        handler
+            .container_manager
+            .create(ContainerConfig::new("db", "postgres"))
+            .unwrap();
+
+        let names = |path: &str| -> Vec<String> {
+            let list: Value =
+                serde_json::from_str(&handler.handle_request("GET", path, "").unwrap()).unwrap();
+            list.as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["Names"][0].as_str().unwrap().to_string())
+                .collect()
+        };
+        // {"label":["tier=web"]}
+        assert_eq!(
+            names("/containers/json?all=1&filters=%7B%22label%22%3A%5B%22tier%3Dweb%22%5D%7D"),
+            ["/web"]
+        );
+        // {"status":{"creating":true},"ancestor":["postgres"]}
+        assert_eq!(
+            names("/containers/json?filters=%7B%22status%22%3A%7B%22creating%22%3Atrue%7D%2C%22ancestor%22%3A%5B%22postgres%22%5D%7D"),
+            ["/db"]
+        );
+
+        // {"driver":["host"]}
+        let networks: Value = serde_json::from_str(
+            &handler
+                .handle_request(
+                    "GET",
+                    "/networks?filters=%7B%22driver%22%3A%5B%22host%22%5D%7D",
+                    "",
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(networks.as_array().unwrap().len(), 1);
+        assert_eq!(networks[0]["Name"], "host");
+
+        // {"color":["red"]}
+        for path in ["/containers/json", "/images/json", "/networks", "/volumes"] {
+            let error = handler
+                .handle_request(
+                    "GET",
+                    &format!("{}?filters=%7B%22color%22%3A%5B%22red%22%5D%7D", path),
+                    "",
+                )
+                .unwrap_err();
+            assert_eq!(error.status_code(), 400);
+        }
+    }
+
+    #[test]
+    fn test_error_status() {
+        let handler = create_test_handler();
+        let error = handler
+            .handle_request("GET", "/containers/missing/json", "")
+            .unwrap_err();
+        assert_eq!(error.status_code(), 404);
+        assert_eq!(error.api_message(), "No such container: missing");
+
+        let error = handler
+            .handle_request("POST", "/containers/create", "{")
+            .unwrap_err();
+        assert_eq!(error.status_code(), 400);
+        let error = handler.handle_request("GET", "/nothing", "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_container_checkpoints() {
+        let handler = create_test_handler();
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create", r#"{"Image": "nginx"}"#)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        handler
+            .handle_request("POST", &format!("/containers/{}/start", id), "")
+            .unwrap();
+
+        let checkpoints = handler
+            .handle_request("GET", &format!("/containers/{}/checkpoints", id), "")
+            .unwrap();
+        assert_eq!(checkpoints, "[]");
+
+        // Without a runtime there is no process to checkpoint
+        let path = format!("/containers/{}/checkpoints", id);
+        let error = handler
+            .handle_request("POST", &path, r#"{"CheckpointID": "cp1", "Exit": true}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Checkpoints need"));
+        let body = r#"{"CheckpointID": "cp1", "TcpEstablished": true, "TcpClose": true}"#;
+        assert!(handler.handle_request("POST", &path, body).is_err());
+        assert!(handler
+            .handle_request(
+                "POST",
+                &format!("/containers/{}/start?checkpoint=cp1", id),
+                ""
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_health() {
+        let handler = create_test_handler();
+        let body = r#"{
+            "Image": "nginx",
+            "Healthcheck": { "Test": ["CMD-SHELL", "curl -f localhost"], "Retries": 2 }
+        }"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/containers/create?name=web", body)
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        handler
+            .handle_request("POST", &format!("/containers/{}/start", id), "")
+            .unwrap();
+
+        let inspect: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", &format!("/containers/{}/json", id), "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(inspect["Config"]["Healthcheck"]["Retries"], 2);
+        assert_eq!(inspect["State"]["Health"]["Status"], "starting");
+
+        let list: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", "/containers/json", "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(list[0]["Status"]
+            .as_str()
+            .unwrap()
+            .ends_with("(health: starting)"));
+
+        // Without a process to exec into, checks fail
+        let started = handler
+            .container_manager
+            .get(id)
+            .unwrap()
+            .started_at
+            .unwrap();
+        let checker = handler.health_checker();
+        for secs in [30, 60, 90] {
+            checker
+                .tick(started + chrono::Duration::seconds(secs))
+                .unwrap();
+        }
+        let health = handler.container_manager.get(id).unwrap().health.unwrap();
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+        assert_eq!(health.log[0].output, "Container has no running process");
+
+        let events = handler.handle_request("GET", "/events", "").unwrap();
+        let event: Value = serde_json::from_str(events.lines().next().unwrap()).unwrap();
+        assert_eq!(event["Action"], "health_status: unhealthy");
+        assert_eq!(event["Actor"]["Attributes"]["name"], "web");
+    }
+
+    #[test]
+    fn test_volumes() {
+        let temp_dir = TempDir::new().unwrap();
+        let volumes = Arc::new(VolumeManager::new(temp_dir.path().join("volumes")).unwrap());
+        let manager = Arc::new(ContainerManager::new(temp_dir.path().join("containers")).unwrap());
+        let handler = ApiHandler::new(manager).with_volumes(volumes);
+
+        let body = r#"{"Name": "data", "Driver": "local", "Labels": {"tier": "db"}}"#;
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request("POST", "/volumes/create", body)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(created["Driver"], "local");
+        assert_eq!(created["Labels"]["tier"], "db");
+        assert!(temp_dir.path().join("volumes").join("data").is_dir());
+
+        let listed: Value =
+            serde_json::from_str(&handler.handle_request("GET", "/volumes", "").unwrap()).unwrap();
+        assert_eq!(listed["Volumes"][0]["Name"], "data");
+
+        // A driver no plugin provides is an error, not a local volume
+        let body = r#"{"Name": "remote", "Driver": "sshfs"}"#;
+        assert!(handler
+            .handle_request("POST", "/volumes/create", body)
+            .is_err());
+        assert!(handler
+            .handle_request("GET", "/volumes/remote", "")
+            .is_err());
+
+        handler
+            .handle_request("DELETE", "/volumes/data", "")
+            .unwrap();
+        assert!(handler.handle_request("GET", "/volumes/data", "").is_err());
+    }
+
+    #[test]
+    fn test_networks() {
+        let temp_dir = TempDir::new().unwrap();
+        let networks = Arc::new(NetworkManager::new().unwrap());
+        let manager = Arc::new(
+            ContainerManager::new(temp_dir.path().to_path_buf())
+                .unwrap()
+                .with_networks(networks.clone()),
+        );
+        let handler = ApiHandler::new(manager).with_networks(networks);
+
+        let body = r#"{
+            "Name": "backend",
+            "Internal": true,
+            "IPAM": {"Config": [{"Subnet": "10.20.0.0/24"}]},
+            "Options": {"com.docker.network.bridge.enable_icc": "false"}
+        }"#;
+        handler
+            .handle_request("POST", "/networks/create", body)
+            .unwrap();
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request(
+                    "POST",
+                    "/containers/create?name=db",
+                    r#"{"Image": "postgres"}"#,
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+
+        let body = r#"{"Container": "db", "EndpointConfig": {"IPAMConfig": {"IPv4Address": "10.20.0.7"}, "Aliases": ["database"]}}"#;
+        handler
+            .handle_request("POST", "/networks/backend/connect", body)
+            .unwrap();
+        let network: Value = serde_json::from_str(
+            &handler
+                .handle_request("GET", "/networks/backend", "")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(network["Internal"], true);
+        assert_eq!(network["IPAM"]["Config"][0]["Subnet"], "10.20.0.0/24");
+        assert_eq!(
+            network["Options"]["com.docker.network.bridge.enable_icc"],
+            "false"
+        );
+        assert_eq!(network["Containers"][id]["IPv4Address"], "10.20.0.7/24");
+
+        // Connected networks can't be removed
+        assert!(handler
+            .handle_request("DELETE", "/networks/backend", "")
+            .is_err());
+        handler
+            .handle_request(
+                "POST",
+                "/networks/backend/disconnect",
+                r#"{"Container": "db"}"#,
+            )
+            .unwrap();
+        handler
+            .handle_request("DELETE", "/networks/backend", "")
+            .unwrap();
+        assert!(handler
+            .handle_request("GET", "/networks/backend", "")
+            .is_err());
+    }
+
+    #[test]
+    fn test_debug_endpoints() {
+        let handler = create_test_handler();
+        let error = handler.handle_request("GET", "/debug", "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let handler = handler.with_debug(json!({"debug": true}));
+        let debug: Value =
+            serde_json::from_str(&handler.handle_request("GET", "/debug", "").unwrap()).unwrap();
+        assert_eq!(debug["Pid"], std::process::id());
+        assert_eq!(debug["Config"]["debug"], true);
+        assert!(!debug["Threads"].as_array().unwrap().is_empty());
+        assert!(handler.handle_request("GET", "/debug/logs", "").is_ok());
+    }
+
+    #[test]
+    fn test_container_stats_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let handler = create_test_handler();
+        let created: Value = serde_json::from_str(
+            &handler
+                .handle_request(
+                    "POST",
+                    "/containers/create?name=web",
+                    r#"{"Image": "nginx"}"#,
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        let id = created["Id"].as_str().unwrap();
+        let error = handler
+            .handle_request("GET", "/containers/web/stats/history", "")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let store = Arc::new(
+            MetricsStore::new(
+                temp_dir.path().to_path_buf(),
+                std::time::Duration::from_secs(3600),
+            )
+            .unwrap(),
+        );
+        let now = chrono::Utc::now();
+        for minutes in [30, 5] {
+            let metrics = ContainerMetrics {
+                memory_usage: minutes,
+                ..ContainerMetrics::default()
+            };
+            let time = now - chrono::Duration::minutes(minutes as i64);
+            store
+                .record(id, &crate::storage::MetricsSample::new(time, &metrics))
+                .unwrap();
+        }
+        let handler = handler.with_stats_history(store);
+        let path = format!(
+            "/containers/web/stats/history?since={}",
+            (now - chrono::Duration::minutes(10)).timestamp()
+        );
+        let history: Value =
+            serde_json::from_str(&handler.handle_request("GET", &path, "").unwrap()).unwrap();
+        assert_eq!(history.as_array().unwrap().len(), 1);
+        assert_eq!(history[0]["MemoryUsage"], 5);
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let handler = create_test_handler();
+        assert!(handler.handle_request("GET", "/audit", "").is_err());
+
+        let audit = Arc::new(AuditLog::new(temp_dir.path().join("audit.log")).unwrap());
+        let handler = handler.with_audit(audit);
+        let caller = Caller {
+            uid: Some(1000),
+            context: Some("prod".to_string()),
+            remote_addr: None,
+        };
+        handler
+            .handle_audited_request(
+                &caller,
+                "POST",
+                "/containers/create?name=web",
+                r#"{"Image": "nginx"}"#,
+            )
+            .unwrap();
+        handler
+            .handle_audited_request(&caller, "GET", "/containers/json", "")
+            .unwrap();
+        assert!(handler
+            .handle_audited_request(&caller, "POST", "/containers/missing/start", "")
+            .is_err());
+
+        let entries: Value =
+            serde_json::from_str(&handler.handle_request("GET", "/audit", "").unwrap()).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["Action"], "container.create");
+        assert_eq!(entries[0]["Target"], "web");
+        assert_eq!(entries[0]["Uid"], 1000);
+        assert_eq!(entries[0]["Context"], "prod");
+        assert_eq!(entries[0]["Status"], 200);
+        assert_eq!(entries[1]["Action"], "container.start");
+        assert_eq!(entries[1]["Status"], 404);
+
+        let filtered: Value = serde_json::from_str(
+            &handler
+                .handle_request(
+                    "GET",
+                    "/audit?filters=%7B%22action%22%3A%5B%22container.start%22%5D%7D",
+                    "",
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(filtered.as_array().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,380 @@
+//! Audit log
+//!
+//! An append-only record of who changed what: every mutating request the
+//! daemon serves, and every mutating command the CLI runs itself, becomes a
+//! JSON line naming the caller's uid, context and remote address, the
+//! action and its target, and how it went. Request payloads may hold
+//! secrets, so only their SHA-256 is kept. Once the log outgrows its size
+//! it is rotated, keeping a number of older files next to it.
+
+use crate::error::{Result, RuneError};
+use crate::filter::Filters;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Size past which the log is rotated by default
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept by default
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Filters audit entries can be listed by
+pub const AUDIT_FILTERS: &[&str] = &["action", "target", "uid", "context"];
+
+/// Header clients name the context they reach the daemon through in
+pub const CONTEXT_HEADER: &str = "X-Rune-Context";
+
+/// Who performed an action
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Caller {
+    /// User ID of the calling process, when known
+    pub uid: Option<u32>,
+    /// Context the caller reached the daemon through
+    pub context: Option<String>,
+    /// Address of a caller connected over TCP
+    pub remote_addr: Option<String>,
+}
+
+impl Caller {
+    /// The user running this process
+    pub fn local() -> Self {
+        Self {
+            uid: Some(unsafe { libc::getuid() }),
+            ..Self::default()
+        }
+    }
+
+    /// The process at the other end of a Unix socket
+    pub fn unix(stream: &UnixStream) -> Self {
+        let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        Self {
+            uid: (result == 0).then_some(cred.uid),
+            ..Self::default()
+        }
+    }
+
+    /// A caller connected from `addr`
+    pub fn remote(addr: impl ToString) -> Self {
+        Self {
+            remote_addr: Some(addr.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+/// An action recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuditEntry {
+    /// When the action was performed
+    pub time: DateTime<Utc>,
+    /// Who performed it
+    #[serde(flatten)]
+    pub caller: Caller,
+    /// What was done, e.g. `container.create` or `image.pull`
+    pub action: String,
+    /// What it was done to, e.g. a container or an image
+    pub target: String,
+    /// SHA-256 of the request payload, if there was one
+    pub payload_hash: Option<String>,
+    /// HTTP status the action ended with
+    pub status: u16,
+}
+
+impl AuditEntry {
+    /// Entry for an action performed now with `payload`
+    pub fn new(
+        caller: Caller,
+        action: impl Into<String>,
+        target: impl Into<String>,
+        payload: &str,
+        status: u16,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            caller,
+            action: action.into(),
+            target: target.into(),
+            payload_hash: (!payload.is_empty())
+                .then(|| format!("sha256:{:x}", Sha256::digest(payload.as_bytes()))),
+            status,
+        }
+    }
+
+    /// Whether the entry passes `action`, `target`, `uid` and `context`
+    /// filters
+    pub fn matches(&self, filters: &Filters) -> bool {
+        filters.matches_exact("action", &self.action)
+            && filters.matches_exact("target", &self.target)
+            && filters.matches("uid", |uid| {
+                self.caller.uid.is_some_and(|own| own.to_string() == uid)
+            })
+            && filters.matches("context", |context| {
+                self.caller.context.as_deref() == Some(context)
+            })
+    }
+}
+
+/// Action and target of a request, if it changes anything
+pub fn request_action(method: &str, path: &str) -> Option<(String, String)> {
+    if matches!(method, "GET" | "HEAD") {
+        return None;
+    }
+    let path_clean = path.split('?').next().unwrap_or(path);
+    let parts: Vec<&str> = path_clean
+        .trim_start_matches('/')
+        .split('/')
+        .skip_while(|part| part.starts_with("v1."))
+        .collect();
+    let kind = |resource: &str| resource.trim_end_matches('s').to_string();
+    let query = |param: &str| {
+        path.split('?')
+            .nth(1)?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(param)?.strip_prefix('='))
+            .map(str::to_string)
+    };
+
+    let action = match (method, parts.as_slice()) {
+        ("POST", ["images", "create"]) => {
+            let image = query("fromImage").unwrap_or_default();
+            let target = match query("tag") {
+                Some(tag) => format!("{}:{}", image, tag),
+                None => image,
+            };
+            ("image.pull".to_string(), target)
+        }
+        (_, [resource, verb @ ("create" | "run")]) => (
+            format!("{}.{}", kind(resource), verb),
+            query("name").unwrap_or_default(),
+        ),
+        ("DELETE", [resource, id]) => (format!("{}.remove", kind(resource)), id.to_string()),
+        ("DELETE", [resource, id, sub, ..]) => (
+            format!("{}.{}.remove", kind(resource), kind(sub)),
+            id.to_string(),
+        ),
+        (_, [resource]) => (resource.to_string(), String::new()),
+        (_, [resource, verb]) => (format!("{}.{}", kind(resource), verb), String::new()),
+        (_, [resource, id, verb, ..]) => (format!("{}.{}", kind(resource), verb), id.to_string()),
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// An append-only audit log, rotated by size
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    /// Held while an entry is written, so rotating doesn't race appends
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, rotating at the default size
+    pub fn new(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            path,
+            max_size: DEFAULT_MAX_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Rotate once the log reaches `max_size` bytes, keeping `max_files`
+    /// rotated files
+    pub fn with_rotation(mut self, max_size: u64, max_files: usize) -> Self {
+        self.max_size = max_size;
+        self.max_files = max_files;
+        self
+    }
+
+    /// Path of the current log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry
+    pub fn record(&self, entry: &AuditEntry) -> Result<()> {
+        let line = serde_json::to_string(entry)? + "\n";
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire audit log lock".to_string()))?;
+        let size = fs::metadata(&self.path).map(|meta| meta.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Entries kept, in rotated files too, oldest first, that were made
+    /// since `since` and pass `filters`
+    pub fn entries(
+        &self,
+        since: Option<DateTime<Utc>>,
+        filters: &Filters,
+    ) -> Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for index in (0..=self.max_files).rev() {
+            let content = match fs::read_to_string(self.rotated(index)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            entries.extend(
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                    .filter(|entry| since.is_none_or(|since| entry.time >= since))
+                    .filter(|entry| entry.matches(filters)),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Path of the `index`th rotated file, the current one for 0
+    fn rotated(&self, index: usize) -> PathBuf {
+        match index {
+            0 => self.path.clone(),
+            _ => PathBuf::from(format!("{}.{}", self.path.display(), index)),
+        }
+    }
+
+    /// Shift each file one place older, dropping the oldest
+    fn rotate(&self) -> Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        for index in (0..self.max_files).rev() {
+            match fs::rename(self.rotated(index), self.rotated(index + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_request_action() {
+        let action = |method, path| request_action(method, path);
+        assert_eq!(action("GET", "/containers/json"), None);
+        assert_eq!(
+            action("POST", "/v1.43/containers/create?name=web"),
+            Some(("container.create".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("POST", "/containers/run?name=web"),
+            Some(("container.run".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("POST", "/containers/web/start"),
+            Some(("container.start".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("POST", "/containers/web/exec"),
+            Some(("container.exec".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("DELETE", "/containers/web?force=true"),
+            Some(("container.remove".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("POST", "/images/create?fromImage=nginx&tag=1.25"),
+            Some(("image.pull".to_string(), "nginx:1.25".to_string()))
+        );
+        assert_eq!(
+            action("DELETE", "/containers/web/checkpoints/cp1"),
+            Some(("container.checkpoint.remove".to_string(), "web".to_string()))
+        );
+        assert_eq!(
+            action("POST", "/containers/prune"),
+            Some(("container.prune".to_string(), String::new()))
+        );
+    }
+
+    #[test]
+    fn test_record_and_rotate() {
+        let dir = TempDir::new().unwrap();
+        let entry = |action: &str, context: &str| {
+            AuditEntry::new(
+                Caller {
+                    uid: Some(1000),
+                    context: Some(context.to_string()),
+                    remote_addr: None,
+                },
+                action,
+                "web",
+                r#"{"Image": "nginx"}"#,
+                200,
+            )
+        };
+        let size = serde_json::to_string(&entry("container.create", "prod"))
+            .unwrap()
+            .len() as u64;
+        let log = AuditLog::new(dir.path().join("audit.log"))
+            .unwrap()
+            .with_rotation(size * 2 + 2, 1);
+        for action in ["container.create", "container.start", "container.exec"] {
+            log.record(&entry(action, "prod")).unwrap();
+        }
+        assert!(dir.path().join("audit.log.1").exists());
+
+        let all = log.entries(None, &Filters::new()).unwrap();
+        let actions: Vec<&str> = all.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(
+            actions,
+            ["container.create", "container.start", "container.exec"]
+        );
+        assert!(all[0]
+            .payload_hash
+            .as_ref()
+            .is_some_and(|hash| hash.starts_with("sha256:")));
+
+        // The oldest file is dropped past the files kept
+        for action in ["container.stop", "container.remove"] {
+            log.record(&entry(action, "dev")).unwrap();
+        }
+        let kept = log.entries(None, &Filters::new()).unwrap();
+        assert_eq!(kept.first().unwrap().action, "container.exec");
+        let filters = Filters::new().with("context", "dev").with("uid", "1000");
+        assert_eq!(log.entries(None, &filters).unwrap().len(), 2);
+        let filters = Filters::new().with("action", "container.exec");
+        assert_eq!(log.entries(None, &filters).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,681 @@
+//! Daemon Client
+//!
+//! A blocking client for the daemon API, reaching the daemon through the
+//! endpoint of a context. Every request opens its own connection, as the
+//! daemon answers one request per connection, so a client keeps working
+//! across daemon restarts without having to be recreated.
+
+use super::audit::{AuditEntry, CONTEXT_HEADER};
+use super::context::{Context, Endpoint, TlsFiles};
+use super::fds::FdStream;
+use crate::container::{ContainerConfig, ContainerStatus};
+use crate::error::{Result, RuneError};
+use crate::filter::Filters;
+use crate::runtime::metrics::{ContainerMetrics, NetworkStats};
+use crate::storage::MetricsSample;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for the daemon to accept or answer
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to the daemon, plain or TLS
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// Container as listed by `GET /containers/json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerSummary {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    image: String,
+    created: i64,
+    state: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+impl From<ContainerSummary> for ContainerConfig {
+    fn from(summary: ContainerSummary) -> Self {
+        let name = summary
+            .names
+            .first()
+            .map(|name| name.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+        Self {
+            id: summary.id,
+            name,
+            image: summary.image,
+            labels: summary.labels,
+            status: serde_json::from_value(serde_json::Value::String(summary.state))
+                .unwrap_or(ContainerStatus::Dead),
+            created_at: chrono::DateTime::from_timestamp(summary.created, 0).unwrap_or_default(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Stats of a container as reported by `GET /containers/{id}/stats`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StatsResponse {
+    cpu_stats: CpuStats,
+    memory_stats: MemoryStats,
+    networks: HashMap<String, NetworkCounters>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CpuStats {
+    cpu_usage: CpuUsage,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CpuUsage {
+    /// Nanoseconds
+    total_usage: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MemoryStats {
+    usage: u64,
+    /// Zero when no limit is set
+    limit: u64,
+    stats: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NetworkCounters {
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+impl From<StatsResponse> for ContainerMetrics {
+    fn from(stats: StatsResponse) -> Self {
+        let network = stats
+            .networks
+            .values()
+            .fold(NetworkStats::default(), |total, counters| NetworkStats {
+                rx_bytes: total.rx_bytes + counters.rx_bytes,
+                tx_bytes: total.tx_bytes + counters.tx_bytes,
+            });
+        Self {
+            cpu_usage_usec: stats.cpu_stats.cpu_usage.total_usage / 1000,
+            memory_usage: stats.memory_stats.usage,
+            memory_limit: (stats.memory_stats.limit != 0).then_some(stats.memory_stats.limit),
+            network,
+            oom_kills: stats
+                .memory_stats
+                .stats
+                .get("oom_kill")
+                .copied()
+                .unwrap_or(0),
+            ..Self::default()
+        }
+    }
+}
+
+/// Client for a daemon reached through a context
+pub struct DaemonClient {
+    context: String,
+    endpoint: Endpoint,
+    tls: Option<Arc<ClientConfig>>,
+    timeout: Duration,
+}
+
+impl DaemonClient {
+    /// Create a client for a context's daemon
+    ///
+    /// No connection is made until the first request.
+    pub fn new(context: &Context) -> Result<Self> {
+        let endpoint = context.endpoint()?;
+        let tls = match (&context.tls, &endpoint) {
+            (Some(files), Endpoint::Tcp { .. }) => Some(tls_config(files)?),
+            (Some(_), Endpoint::Unix(_)) => {
+                return Err(RuneError::InvalidConfig(
+                    "TLS is only supported for tcp:// endpoints".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+        Ok(Self {
+            context: context.name.clone(),
+            endpoint,
+            tls,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Name of the client's context
+    pub fn context_name(&self) -> &str {
+        &self.context
+    }
+
+    /// Endpoint of the client's daemon
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Open a new connection to the daemon
+    fn connect(&self) -> Result<Box<dyn Connection>> {
+        let unreachable = |e: &dyn std::fmt::Display| {
+            RuneError::Daemon(format!("Cannot connect to {}: {}", self.endpoint, e))
+        };
+
+        match &self.endpoint {
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path).map_err(|e| unreachable(&e))?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Ok(Box::new(stream))
+            }
+            Endpoint::Tcp { host, port } => {
+                let addr = (host.as_str(), *port)
+                    .to_socket_addrs()
+                    .map_err(|e| unreachable(&e))?
+                    .next()
+                    .ok_or_else(|| unreachable(&"no address found"))?;
+                let stream =
+                    TcpStream::connect_timeout(&addr, self.timeout).map_err(|e| unreachable(&e))?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+
+                let Some(ref config) = self.tls else {
+                    return Ok(Box::new(stream));
+                };
+                let server_name =
+                    ServerName::try_from(host.clone()).map_err(|e| unreachable(&e))?;
+                let connection = ClientConnection::new(config.clone(), server_name)
+                    .map_err(|e| unreachable(&e))?;
+                Ok(Box::new(StreamOwned::new(connection, stream)))
+            }
+        }
+    }
+
+    /// Send a request and return the response body
+    ///
+    /// Failures to reach the daemon are `Daemon` errors; error responses
+    /// from the daemon are `Api` errors.
+    pub fn request(&self, method: &str, path: &str, body: Option<&str>) -> Result<String> {
+        let mut stream = self.connect()?;
+        let lost = |e: std::io::Error| {
+            RuneError::Daemon(format!("Connection to {} failed: {}", self.endpoint, e))
+        };
+
+        let body = body.unwrap_or("");
+        let request = format!(
+            "{} {} HTTP/1.1\r\n\
+             Host: rune\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             {}: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            method,
+            path,
+            body.len(),
+            CONTEXT_HEADER,
+            self.context,
+            body
+        );
+        stream.write_all(request.as_bytes()).map_err(lost)?;
+        stream.flush().map_err(lost)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        if reader.read_line(&mut status_line).map_err(lost)? == 0 {
+            return Err(RuneError::Daemon(format!(
+                "{} closed the connection without answering",
+                self.endpoint
+            )));
+        }
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                RuneError::Daemon(format!("Malformed response: {}", status_line.trim()))
+            })?;
+
+        let mut content_length = None;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).map_err(lost)?;
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let mut body = Vec::new();
+        match content_length {
+            Some(len) => {
+                body.resize(len, 0);
+                reader.read_exact(&mut body).map_err(lost)?;
+            }
+            None => {
+                reader.read_to_end(&mut body).map_err(lost)?;
+            }
+        }
+        let body = String::from_utf8_lossy(&body).into_owned();
+
+        if !(200..300).contains(&status) {
+            return Err(response_error(status, &body));
+        }
+        Ok(body)
+    }
+
+    /// Create and start a container that stays attached to this process
+    ///
+    /// Without a terminal, the container writes to this process's standard
+    /// output and error. Only daemons reached over their Unix socket can
+    /// be handed them.
+    pub fn run_attached(&self, config: &ContainerConfig) -> Result<AttachedRun> {
+        let Endpoint::Unix(path) = &self.endpoint else {
+            return Err(RuneError::InvalidConfig(format!(
+                "Containers are only run attached through a daemon's Unix socket, not {}",
+                self.endpoint
+            )));
+        };
+        let stream = UnixStream::connect(path).map_err(|e| {
+            RuneError::Daemon(format!("Cannot connect to {}: {}", self.endpoint, e))
+        })?;
+        // The connection stays open for as long as the container runs
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut stream = FdStream::new(stream);
+        let lost = |e: std::io::Error| {
+            RuneError::Daemon(format!("Connection to {} failed: {}", self.endpoint, e))
+        };
+
+        let body = serde_json::to_string(config)?;
+        let request = format!(
+            "POST /containers/run?name={} HTTP/1.1\r\n\
+             Host: rune\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             {}: {}\r\n\
+             \r\n\
+             {}",
+            encode_query(&config.name),
+            body.len(),
+            CONTEXT_HEADER,
+            self.context,
+            body
+        );
+        let (stdout, stderr) = (std::io::stdout(), std::io::stderr());
+        let streams = match config.tty {
+            true => Vec::new(),
+            false => vec![stdout.as_fd(), stderr.as_fd()],
+        };
+        stream.send(request.as_bytes(), &streams).map_err(lost)?;
+
+        // Read no further than the response's head, as what follows it
+        // comes once the container exits
+        let status_line = read_line(&mut stream).map_err(lost)?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                RuneError::Daemon(format!("Malformed response: {}", status_line.trim()))
+            })?;
+        while !read_line(&mut stream).map_err(lost)?.trim().is_empty() {}
+        if status != 200 {
+            let mut body = String::new();
+            stream.read_to_string(&mut body).map_err(lost)?;
+            return Err(response_error(status, &body));
+        }
+
+        #[derive(Deserialize)]
+        struct Started {
+            #[serde(rename = "Id")]
+            id: String,
+        }
+        let started: Started = serde_json::from_str(&read_line(&mut stream).map_err(lost)?)?;
+        Ok(AttachedRun {
+            id: started.id,
+            input: stream.take_fds().into_iter().next(),
+            stream,
+        })
+    }
+
+    /// Send a signal to a container's process
+    pub fn kill_container(&self, id: &str, signal: i32) -> Result<()> {
+        let path = format!("/containers/{}/kill?signal={}", id, signal);
+        self.request("POST", &path, None).map(|_| ())
+    }
+
+    /// Check that the daemon is reachable
+    pub fn ping(&self) -> Result<()> {
+        self.request("GET", "/_ping", None).map(|_| ())
+    }
+
+    /// List containers, including stopped ones if `all` is set
+    pub fn list_containers(&self, all: bool) -> Result<Vec<ContainerConfig>> {
+        let path = if all {
+            "/containers/json?all=1"
+        } else {
+            "/containers/json"
+        };
+        let summaries: Vec<ContainerSummary> =
+            serde_json::from_str(&self.request("GET", path, None)?)?;
+        Ok(summaries.into_iter().map(ContainerConfig::from).collect())
+    }
+
+    /// Start a container
+    pub fn start_container(&self, id: &str) -> Result<()> {
+        self.container_action(id, "start")
+    }
+
+    /// Stop a container
+    pub fn stop_container(&self, id: &str) -> Result<()> {
+        self.container_action(id, "stop")
+    }
+
+    /// Restart a container
+    pub fn restart_container(&self, id: &str) -> Result<()> {
+        self.container_action(id, "restart")
+    }
+
+    /// Pause a container
+    pub fn pause_container(&self, id: &str) -> Result<()> {
+        self.container_action(id, "pause")
+    }
+
+    /// Unpause a container
+    pub fn unpause_container(&self, id: &str) -> Result<()> {
+        self.container_action(id, "unpause")
+    }
+
+    /// Remove a container
+    pub fn remove_container(&self, id: &str, force: bool) -> Result<()> {
+        let path = format!("/containers/{}?force={}", id, force);
+        self.request("DELETE", &path, None).map(|_| ())
+    }
+
+    /// Current resource use of a container, sampled by the daemon
+    pub fn container_stats(&self, id: &str) -> Result<ContainerMetrics> {
+        let path = format!("/containers/{}/stats?stream=false", id);
+        let stats: StatsResponse = serde_json::from_str(&self.request("GET", &path, None)?)?;
+        Ok(stats.into())
+    }
+
+    /// Samples of a container the daemon kept since `since`, oldest first
+    pub fn stats_history(
+        &self,
+        id: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<MetricsSample>> {
+        let path = format!(
+            "/containers/{}/stats/history?since={}",
+            id,
+            since.timestamp()
+        );
+        Ok(serde_json::from_str(&self.request("GET", &path, None)?)?)
+    }
+
+    /// Entries of the daemon's audit log since `since` passing `filters`,
+    /// oldest first
+    pub fn audit(
+        &self,
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        filters: &Filters,
+    ) -> Result<Vec<AuditEntry>> {
+        let mut path = format!("/audit?filters={}", encode_query(&filters.to_json()));
+        if let Some(since) = since {
+            path.push_str(&format!("&since={}", since.timestamp()));
+        }
+        Ok(serde_json::from_str(&self.request("GET", &path, None)?)?)
+    }
+
+    /// Unlock the swarm the daemon's node manages with its unlock key
+    pub fn unlock_swarm(&self, unlock_key: &str) -> Result<()> {
+        let body = serde_json::json!({ "UnlockKey": unlock_key }).to_string();
+        self.request("POST", "/swarm/unlock", Some(&body))
+            .map(|_| ())
+    }
+
+    fn container_action(&self, id: &str, action: &str) -> Result<()> {
+        let path = format!("/containers/{}/{}", id, action);
+        self.request("POST", &path, None).map(|_| ())
+    }
+}
+
+/// A container run attached through the daemon; its connection becomes
+/// readable once the container exits
+pub struct AttachedRun {
+    /// ID of the container
+    pub id: String,
+    /// The container's terminal, or else its standard input if it keeps
+    /// that open
+    pub input: Option<OwnedFd>,
+    stream: FdStream,
+}
+
+impl AttachedRun {
+    /// Wait for the container to exit and return its exit code
+    pub fn exit_code(mut self) -> Result<i32> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Exited {
+            status_code: i32,
+            error: Option<WaitError>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct WaitError {
+            message: String,
+        }
+
+        let line = read_line(&mut self.stream)?;
+        if line.is_empty() {
+            return Err(RuneError::Daemon(format!(
+                "Daemon closed the connection before container {} exited",
+                self.id
+            )));
+        }
+        let exited: Exited = serde_json::from_str(&line)?;
+        match exited.error {
+            Some(error) => Err(RuneError::Api(error.message)),
+            None => Ok(exited.status_code),
+        }
+    }
+}
+
+impl AsFd for AttachedRun {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+/// Read a line a byte at a time, so nothing past it is consumed; empty at
+/// the end of the stream
+fn read_line(stream: &mut impl Read) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while stream.read(&mut byte)? == 1 {
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Error of a response with an error status
+fn response_error(status: u16, body: &str) -> RuneError {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(String::from))
+        .unwrap_or_else(|| format!("Daemon returned {}", status));
+    RuneError::from_status_code(status, message)
+}
+
+/// Percent-encode a query parameter's value
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// TLS configuration trusting a context's CA
+fn tls_config(files: &TlsFiles) -> Result<Arc<ClientConfig>> {
+    let tls_error = |e: &dyn std::fmt::Display| RuneError::InvalidConfig(format!("TLS: {}", e));
+
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(&files.ca).map_err(|e| tls_error(&e))? {
+        roots
+            .add(cert.map_err(|e| tls_error(&e))?)
+            .map_err(|e| tls_error(&e))?;
+    }
+
+    let builder =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| tls_error(&e))?
+            .with_root_certificates(roots);
+
+    let config = match (&files.cert, &files.key) {
+        (Some(cert), Some(key)) => {
+            let chain = CertificateDer::pem_file_iter(cert)
+                .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+                .map_err(|e| tls_error(&e))?;
+            let key = PrivateKeyDer::from_pem_file(key).map_err(|e| tls_error(&e))?;
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|e| tls_error(&e))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(RuneError::InvalidConfig(
+                "TLS client authentication needs both a certificate and a key".to_string(),
+            ))
+        }
+    };
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use tempfile::TempDir;
+
+    /// Answer each connection with the next canned response
+    fn fake_daemon(
+        socket: &std::path::Path,
+        responses: Vec<(u16, &'static str)>,
+    ) -> std::thread::JoinHandle<Vec<String>> {
+        let listener = UnixListener::bind(socket).unwrap();
+        std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                requests.push(request_line.trim().to_string());
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        })
+    }
+
+    #[test]
+    fn test_client_requests() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("rune.sock");
+        let server = fake_daemon(
+            &socket,
+            vec![
+                (
+                    200,
+                    r#"[{"Id":"0123456789abcdef","Names":["/web"],"Image":"nginx","Created":1700000000,"State":"running","Labels":{}}]"#,
+                ),
+                (500, r#"{"message":"Container not found"}"#),
+                (
+                    200,
+                    r#"{"cpu_stats":{"cpu_usage":{"total_usage":5000000}},"memory_stats":{"usage":4096,"limit":0,"stats":{"oom_kill":1}},"networks":{"eth0":{"rx_bytes":10,"tx_bytes":20},"eth1":{"rx_bytes":5,"tx_bytes":0}}}"#,
+                ),
+            ],
+        );
+
+        let context = Context::new("test", format!("unix://{}", socket.display()));
+        let client = DaemonClient::new(&context).unwrap();
+
+        let containers = client.list_containers(true).unwrap();
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].name, "web");
+        assert_eq!(containers[0].status, ContainerStatus::Running);
+        assert_eq!(containers[0].created_at.timestamp(), 1700000000);
+
+        match client.start_container("missing") {
+            Err(RuneError::Api(message)) => assert_eq!(message, "Container not found"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let stats = client.container_stats("web").unwrap();
+        assert_eq!(stats.cpu_usage_usec, 5000);
+        assert_eq!(stats.memory_usage, 4096);
+        assert_eq!(stats.memory_limit, None);
+        assert_eq!(stats.oom_kills, 1);
+        assert_eq!(
+            stats.network,
+            NetworkStats {
+                rx_bytes: 15,
+                tx_bytes: 20
+            }
+        );
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "GET /containers/json?all=1 HTTP/1.1",
+                "POST /containers/missing/start HTTP/1.1",
+                "GET /containers/web/stats?stream=false HTTP/1.1"
+            ]
+        );
+
+        // Nothing listens any more
+        assert!(matches!(client.ping(), Err(RuneError::Daemon(_))));
+    }
+}
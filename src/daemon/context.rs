@@ -0,0 +1,324 @@
+//! Daemon Contexts
+//!
+//! A context names a daemon endpoint, either a Unix socket or a TCP address
+//! optionally secured with TLS, so clients can switch between daemons by
+//! name. Contexts are kept in `contexts.json` in the Rune config directory
+//! together with the name of the current one. The built-in `default`
+//! context always points at the local daemon socket.
+
+use super::server::DEFAULT_SOCKET_PATH;
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the built-in context
+pub const DEFAULT_CONTEXT: &str = "default";
+
+/// Environment variable that overrides the current context
+pub const CONTEXT_ENV: &str = "RUNE_CONTEXT";
+
+/// Where a daemon listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Unix socket path
+    Unix(PathBuf),
+    /// TCP host and port
+    Tcp {
+        /// Host name or address
+        host: String,
+        /// Port
+        port: u16,
+    },
+}
+
+impl Endpoint {
+    /// Parse `unix:///path/to/socket` or `tcp://host:port`
+    pub fn parse(endpoint: &str) -> Result<Self> {
+        if let Some(path) = endpoint.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err(RuneError::InvalidConfig(
+                    "Unix endpoint needs a socket path".to_string(),
+                ));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        let Some(address) = endpoint.strip_prefix("tcp://") else {
+            return Err(RuneError::InvalidConfig(format!(
+                "Unsupported endpoint '{}', expected unix:// or tcp://",
+                endpoint
+            )));
+        };
+        let (host, port) = address
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.trim_end_matches('/').parse().ok()?)))
+            .ok_or_else(|| {
+                RuneError::InvalidConfig(format!("TCP endpoint '{}' needs a port", endpoint))
+            })?;
+        // Allow bracketed IPv6 literals
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(RuneError::InvalidConfig(format!(
+                "TCP endpoint '{}' needs a host",
+                endpoint
+            )));
+        }
+        Ok(Self::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            Endpoint::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp://[{}]:{}", host, port)
+            }
+            Endpoint::Tcp { host, port } => write!(f, "tcp://{}:{}", host, port),
+        }
+    }
+}
+
+/// TLS files for a TCP context
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsFiles {
+    /// CA certificate the daemon's certificate is verified against
+    pub ca: PathBuf,
+    /// Client certificate, for daemons that verify clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    /// Client private key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+}
+
+/// A named daemon endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Context {
+    /// Context name
+    pub name: String,
+    /// Description shown in listings
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// `unix://` or `tcp://` endpoint
+    pub endpoint: String,
+    /// TLS settings for TCP endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsFiles>,
+}
+
+impl Context {
+    /// Create a context without TLS
+    pub fn new(name: impl Into<String>, endpoint: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: String::new(),
+            endpoint: endpoint.into(),
+            tls: None,
+        }
+    }
+
+    /// The built-in context for the local daemon
+    pub fn local() -> Self {
+        Self {
+            description: "Local daemon".to_string(),
+            ..Self::new(DEFAULT_CONTEXT, format!("unix://{}", DEFAULT_SOCKET_PATH))
+        }
+    }
+
+    /// Parsed endpoint
+    pub fn endpoint(&self) -> Result<Endpoint> {
+        Endpoint::parse(&self.endpoint)
+    }
+}
+
+/// Contents of `contexts.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ContextFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current: Option<String>,
+    #[serde(default)]
+    contexts: Vec<Context>,
+}
+
+/// Saved contexts
+#[derive(Debug, Clone)]
+pub struct ContextStore {
+    path: PathBuf,
+    file: ContextFile,
+}
+
+impl ContextStore {
+    /// Default location of the context file
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("/etc"))
+            .join("rune")
+            .join("contexts.json")
+    }
+
+    /// Open the context file at its default location
+    pub fn open_default() -> Result<Self> {
+        Self::open(Self::default_path())
+    }
+
+    /// Open a context file, which need not exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            ContextFile::default()
+        };
+        Ok(Self { path, file })
+    }
+
+    /// Write the contexts back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.file)?)?;
+        Ok(())
+    }
+
+    /// All contexts, the built-in one first
+    pub fn list(&self) -> Vec<Context> {
+        std::iter::once(Context::local())
+            .chain(self.file.contexts.iter().cloned())
+            .collect()
+    }
+
+    /// Look up a context by name
+    pub fn get(&self, name: &str) -> Result<Context> {
+        self.list()
+            .into_iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| RuneError::InvalidConfig(format!("No such context: {}", name)))
+    }
+
+    /// Name of the current context
+    ///
+    /// `RUNE_CONTEXT` takes precedence over the saved choice.
+    pub fn current_name(&self) -> String {
+        std::env::var(CONTEXT_ENV)
+            .ok()
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.file.current.clone())
+            .unwrap_or_else(|| DEFAULT_CONTEXT.to_string())
+    }
+
+    /// The current context
+    pub fn current(&self) -> Result<Context> {
+        self.get(&self.current_name())
+    }
+
+    /// Add a context, or replace one of the same name
+    pub fn add(&mut self, context: Context) -> Result<()> {
+        if context.name == DEFAULT_CONTEXT {
+            return Err(RuneError::InvalidConfig(format!(
+                "The {} context cannot be changed",
+                DEFAULT_CONTEXT
+            )));
+        }
+        let endpoint = context.endpoint()?;
+        if context.tls.is_some() && matches!(endpoint, Endpoint::Unix(_)) {
+            return Err(RuneError::InvalidConfig(
+                "TLS is only supported for tcp:// endpoints".to_string(),
+            ));
+        }
+
+        match self
+            .file
+            .contexts
+            .iter_mut()
+            .find(|c| c.name == context.name)
+        {
+            Some(existing) => *existing = context,
+            None => self.file.contexts.push(context),
+        }
+        Ok(())
+    }
+
+    /// Remove a context; the current context falls back to the default
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let before = self.file.contexts.len();
+        self.file.contexts.retain(|c| c.name != name);
+        if self.file.contexts.len() == before {
+            return Err(RuneError::InvalidConfig(format!(
+                "No such context: {}",
+                name
+            )));
+        }
+        if self.file.current.as_deref() == Some(name) {
+            self.file.current = None;
+        }
+        Ok(())
+    }
+
+    /// Make a context the current one
+    pub fn use_context(&mut self, name: &str) -> Result<()> {
+        self.get(name)?;
+        self.file.current = (name != DEFAULT_CONTEXT).then(|| name.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            Endpoint::parse("unix:///var/run/rune.sock").unwrap(),
+            Endpoint::Unix(PathBuf::from("/var/run/rune.sock"))
+        );
+        let tcp = Endpoint::parse("tcp://[::1]:2376").unwrap();
+        assert_eq!(
+            tcp,
+            Endpoint::Tcp {
+                host: "::1".to_string(),
+                port: 2376
+            }
+        );
+        assert_eq!(tcp.to_string(), "tcp://[::1]:2376");
+        assert!(Endpoint::parse("tcp://host").is_err());
+        assert!(Endpoint::parse("http://host:80").is_err());
+    }
+
+    #[test]
+    fn test_context_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("contexts.json");
+
+        let mut store = ContextStore::open(&path).unwrap();
+        assert_eq!(store.current().unwrap(), Context::local());
+
+        let mut remote = Context::new("remote", "tcp://build-host:2376");
+        remote.tls = Some(TlsFiles {
+            ca: PathBuf::from("ca.pem"),
+            ..TlsFiles::default()
+        });
+        store.add(remote.clone()).unwrap();
+        store.use_context("remote").unwrap();
+        store.save().unwrap();
+
+        let mut store = ContextStore::open(&path).unwrap();
+        assert_eq!(store.list().len(), 2);
+        assert_eq!(store.file.current.as_deref(), Some("remote"));
+        assert_eq!(store.get("remote").unwrap(), remote);
+
+        assert!(store
+            .add(Context::new(DEFAULT_CONTEXT, "tcp://x:1"))
+            .is_err());
+        store.remove("remote").unwrap();
+        assert!(store.file.current.is_none());
+        assert!(store.use_context("remote").is_err());
+    }
+}
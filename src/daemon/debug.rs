@@ -0,0 +1,313 @@
+//! Daemon diagnostics
+//!
+//! Introspection of a running process for troubleshooting: its threads as
+//! `/proc` reports them, the metrics of the async runtime it runs its
+//! tasks on, and
+//! the log lines it wrote last. The daemon serves these on `/debug` when
+//! debugging is on, and `rune debug bundle` gathers them, with the
+//! daemon's config and recent events, into a tarball.
+
+use crate::error::{Result, RuneError};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log lines a log buffer keeps
+pub const LOG_BUFFER_LINES: usize = 2000;
+
+/// The most recent log lines of the process, for diagnostics
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    /// Create a buffer keeping the last `capacity` lines
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Buffer the process' logs are written to, when the logger tees into it
+    pub fn global() -> &'static LogBuffer {
+        static BUFFER: OnceLock<LogBuffer> = OnceLock::new();
+        BUFFER.get_or_init(|| LogBuffer::new(LOG_BUFFER_LINES))
+    }
+
+    /// Buffered lines, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn push(&self, text: &str) {
+        let Ok(mut lines) = self.lines.lock() else {
+            return;
+        };
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+impl Write for &LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.push(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = &'a LogBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+/// A thread of the process
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ThreadInfo {
+    /// Thread ID
+    pub tid: u32,
+    /// Thread name
+    pub name: String,
+    /// Scheduler state, e.g. `R` running or `S` sleeping
+    pub state: String,
+    /// Kernel function the thread is waiting in, if it is
+    pub wchan: String,
+    /// CPU time the thread used
+    #[serde(with = "millis")]
+    pub cpu_time: Duration,
+}
+
+/// Metrics of the async runtime a process runs its tasks on
+///
+/// Dumping the tasks themselves needs tokio built with `tokio_unstable`,
+/// so what is reported is how busy each worker is.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RuntimeInfo {
+    /// Worker threads
+    pub workers: usize,
+    /// Tasks that haven't finished
+    pub alive_tasks: usize,
+    /// Tasks queued for any worker to pick up
+    pub queued_tasks: usize,
+    /// Each worker, in the runtime's order
+    pub worker_stats: Vec<WorkerInfo>,
+}
+
+/// A worker thread of an async runtime
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct WorkerInfo {
+    /// Time the worker spent running tasks
+    #[serde(with = "millis")]
+    pub busy_time: Duration,
+    /// Times the worker ran out of tasks and parked
+    pub parks: u64,
+}
+
+/// A snapshot of a process for diagnostics
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Diagnostics {
+    /// Process ID
+    pub pid: u32,
+    /// Version of rune
+    pub version: String,
+    /// Threads of the process
+    pub threads: Vec<ThreadInfo>,
+    /// Async runtime the process runs its tasks on, if it is given one
+    pub runtime: Option<RuntimeInfo>,
+    /// Log lines buffered
+    pub log_lines: usize,
+}
+
+impl Diagnostics {
+    /// Take a snapshot of this process, with the async runtime `handle`
+    /// is of
+    pub fn collect(handle: Option<&Handle>) -> Result<Self> {
+        Ok(Self {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            threads: threads()?,
+            runtime: handle.map(runtime),
+            log_lines: LogBuffer::global().lines().len(),
+        })
+    }
+}
+
+/// Threads of this process, by thread ID
+pub fn threads() -> Result<Vec<ThreadInfo>> {
+    let entries = fs::read_dir("/proc/self/task")
+        .map_err(|e| RuneError::Daemon(format!("Failed to list threads: {}", e)))?;
+    let mut threads: Vec<ThreadInfo> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let tid = entry.file_name().to_str()?.parse().ok()?;
+            let read = |file: &str| fs::read_to_string(entry.path().join(file)).ok();
+            let (state, ticks) = parse_stat(&read("stat")?)?;
+            Some(ThreadInfo {
+                tid,
+                name: read("comm").unwrap_or_default().trim().to_string(),
+                state,
+                wchan: read("wchan")
+                    .filter(|wchan| wchan != "0")
+                    .unwrap_or_default(),
+                cpu_time: Duration::from_millis(ticks * 1000 / clock_ticks()),
+            })
+        })
+        .collect();
+    threads.sort_by_key(|thread| thread.tid);
+    Ok(threads)
+}
+
+/// Metrics of the tokio runtime `handle` is of, which callers outside
+/// of it, like the daemon's API threads, hold on to
+pub fn runtime(handle: &Handle) -> RuntimeInfo {
+    let metrics = handle.metrics();
+    RuntimeInfo {
+        workers: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        queued_tasks: metrics.global_queue_depth(),
+        worker_stats: (0..metrics.num_workers())
+            .map(|worker| WorkerInfo {
+                busy_time: metrics.worker_total_busy_duration(worker),
+                parks: metrics.worker_park_count(worker),
+            })
+            .collect(),
+    }
+}
+
+/// State and CPU ticks, user and system, from a thread's `stat`
+fn parse_stat(stat: &str) -> Option<(String, u64)> {
+    // The name, in parentheses, may hold spaces; the fields follow it
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((fields.first()?.to_string(), utime + stime))
+}
+
+/// Clock ticks per second `/proc` reports CPU time in
+fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Write files into a gzipped tarball, each named by its path in it
+pub fn write_bundle(out: impl Write, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let mut archive = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now);
+        header.set_cksum();
+        archive.append_data(&mut header, name, contents.as_slice())?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+mod millis {
+    use serde::Serializer;
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_log_buffer() {
+        let buffer = LogBuffer::new(2);
+        let mut writer = buffer.make_writer();
+        writer.write_all(b"one\ntwo\n").unwrap();
+        writer.write_all(b"three\n").unwrap();
+        assert_eq!(buffer.lines(), ["two", "three"]);
+    }
+
+    #[test]
+    fn test_threads() {
+        let (state, ticks) =
+            parse_stat("42 (rune worker) S 1 42 42 0 -1 0 0 0 0 0 7 3 0 0").unwrap();
+        assert_eq!((state.as_str(), ticks), ("S", 10));
+
+        let threads = threads().unwrap();
+        let pid = std::process::id();
+        assert!(threads.iter().any(|thread| thread.tid == pid));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        // Reported from a thread outside of the runtime, as the daemon's
+        // API threads are
+        let info = std::thread::scope(|scope| {
+            scope
+                .spawn(|| Diagnostics::collect(Some(runtime.handle())).unwrap())
+                .join()
+                .unwrap()
+        })
+        .runtime
+        .unwrap();
+        assert_eq!(info.workers, 2);
+        assert_eq!(info.worker_stats.len(), 2);
+        assert!(Diagnostics::collect(None).unwrap().runtime.is_none());
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let mut bundle = Vec::new();
+        let files = [
+            ("info.json".to_string(), b"{}".to_vec()),
+            ("logs.txt".to_string(), b"started\n".to_vec()),
+        ];
+        write_bundle(&mut bundle, &files).unwrap();
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bundle.as_slice()));
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            names.push((entry.path().unwrap().display().to_string(), contents));
+        }
+        assert_eq!(
+            names,
+            [
+                ("info.json".to_string(), "{}".to_string()),
+                ("logs.txt".to_string(), "started\n".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Passing file descriptors over the daemon's Unix socket
+//!
+//! A client running a container in the foreground hands the daemon the
+//! streams the container's output goes to, and gets back the container's
+//! terminal or standard input, as `SCM_RIGHTS` messages on the connection
+//! of its request. Only local clients can attach to containers this way.
+
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+/// Most file descriptors a message carries
+const MAX_FDS: usize = 4;
+
+/// Room for the control message of `MAX_FDS` file descriptors, aligned for
+/// its header
+type ControlBuffer = [u64; 8];
+
+/// A Unix stream that keeps the file descriptors the peer sends with its
+/// data
+pub struct FdStream {
+    stream: UnixStream,
+    received: Vec<OwnedFd>,
+}
+
+impl FdStream {
+    pub fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            received: Vec::new(),
+        }
+    }
+
+    /// Take the file descriptors received so far, in the order they were
+    /// sent
+    pub fn take_fds(&mut self) -> Vec<OwnedFd> {
+        std::mem::take(&mut self.received)
+    }
+
+    /// Send data, with file descriptors attached to its first byte
+    pub fn send(&mut self, data: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        if data.is_empty() || fds.len() > MAX_FDS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file descriptors need data to go with, and no more than a few at once",
+            ));
+        }
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut control = ControlBuffer::default();
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            let len = std::mem::size_of_val(fds) as u32;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = unsafe { libc::CMSG_SPACE(len) } as _;
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for (i, fd) in fds.iter().enumerate() {
+                    data.add(i).write_unaligned(fd.as_raw_fd());
+                }
+            }
+        }
+
+        let sent = loop {
+            let n = unsafe { libc::sendmsg(self.stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+            if n >= 0 {
+                break n as usize;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        };
+        self.stream.write_all(&data[sent..])
+    }
+}
+
+impl AsFd for FdStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl Read for FdStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let mut control = ControlBuffer::default();
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let n = loop {
+            let n =
+                unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+            if n >= 0 {
+                break n as usize;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        };
+
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+                let len = header.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+                let data = unsafe { libc::CMSG_DATA(cmsg) }.cast::<RawFd>();
+                for i in 0..len / std::mem::size_of::<RawFd>() {
+                    let fd = unsafe { data.add(i).read_unaligned() };
+                    self.received.push(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        Ok(n)
+    }
+}
+
+impl Write for FdStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_pass_fds() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (mut client, server) = (FdStream::new(client), FdStream::new(server));
+        let (mut reader, writer) = std::io::pipe().unwrap();
+
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        client.send(b"fds\n", &[writer.as_fd()]).unwrap();
+        client.write_all(b"rest\n").unwrap();
+        drop(writer);
+
+        let mut server = BufReader::new(server);
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            let mut line = String::new();
+            server.read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(lines, ["GET / HTTP/1.1\r\n", "fds\n", "rest\n"]);
+
+        let fds = server.get_mut().take_fds();
+        assert_eq!(fds.len(), 1);
+        let mut writer = std::fs::File::from(fds.into_iter().next().unwrap());
+        writer.write_all(b"through").unwrap();
+        drop(writer);
+        let mut passed = String::new();
+        reader.read_to_string(&mut passed).unwrap();
+        assert_eq!(passed, "through");
+        assert!(server.get_mut().take_fds().is_empty());
+
+        assert!(client.send(b"", &[reader.as_fd()]).is_err());
+    }
+}
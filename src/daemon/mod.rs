@@ -2,9 +2,26 @@
 //!
 //! This module implements a Docker-like daemon that listens on a Unix socket
 //! at `/var/run/rune.sock` and provides a REST API for container management.
+//! Clients reach a daemon through a context naming its endpoint, and its
+//! diagnostics through the debug endpoints it serves when debugging. What
+//! clients change through it is kept in an audit log.
 
 mod api;
+mod audit;
+mod client;
+mod context;
+mod debug;
+mod fds;
 mod server;
 
-pub use api::ApiHandler;
-pub use server::RuneDaemon;
+pub use api::{ApiHandler, SwarmUnlock};
+pub use audit::{
+    request_action, AuditEntry, AuditLog, Caller, AUDIT_FILTERS, CONTEXT_HEADER, DEFAULT_MAX_FILES,
+    DEFAULT_MAX_SIZE,
+};
+pub use client::{AttachedRun, DaemonClient};
+pub use context::{Context, ContextStore, Endpoint, TlsFiles, CONTEXT_ENV, DEFAULT_CONTEXT};
+pub use debug::{
+    threads, write_bundle, Diagnostics, LogBuffer, RuntimeInfo, ThreadInfo, WorkerInfo,
+};
+pub use server::{DaemonConfig, RuneDaemon, DEFAULT_SOCKET_PATH};
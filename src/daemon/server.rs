@@ -1,22 +1,66 @@
 //! Unix Socket Server for Rune Daemon
 //!
-//! Implements a Docker-compatible daemon that listens on a Unix socket.
+//! Implements a Docker-compatible daemon that listens on a Unix socket and,
+//! optionally, on a TCP address secured with TLS for remote clients.
 
 use super::api::ApiHandler;
-use crate::container::ContainerManager;
-use crate::error::{Result, RuneError};
+use super::audit::{AuditLog, Caller, CONTEXT_HEADER, DEFAULT_MAX_FILES, DEFAULT_MAX_SIZE};
+use super::fds::FdStream;
+use crate::container::{ContainerManager, LogConfig};
+use crate::error::{Result, ResultExt, RuneError};
+use crate::image::{ImagePuller, ImageStore, RegistryHosts};
+use crate::network::bridge::NetworkManager;
+use crate::network::Veth;
+use crate::plugin::PluginManager;
+use crate::runtime::cdi::DEFAULT_SPEC_DIRS;
+use crate::runtime::executor::executor_for;
+use crate::runtime::lsm::Lsm;
+use crate::runtime::metrics::CgroupMetrics;
+use crate::runtime::oci::Hooks;
+use crate::runtime::userns::UsernsRemap;
+use crate::runtime::MetricsSource;
+use crate::storage::metrics::{DEFAULT_RETENTION, DEFAULT_SAMPLE_INTERVAL};
+use crate::storage::{MetricsStore, VolumeManager};
+use crate::swarm::cluster::DEFAULT_STATE_DIR;
+use crate::swarm::ingress::SYNC_INTERVAL;
+use crate::swarm::{DataKey, FileLogSource, KeyStore, RoutingMesh, SwarmCluster};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use serde::Serialize;
 use std::fs;
 use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsFd;
 use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tracing::{debug, error, info};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 /// Default socket path for the Rune daemon
 pub const DEFAULT_SOCKET_PATH: &str = "/var/run/rune.sock";
 
+/// A connection a request comes in on
+trait Connection: Read + Write {
+    /// The connection, if file descriptors can be passed over it
+    fn fds(&mut self) -> Option<&mut FdStream> {
+        None
+    }
+}
+
+impl Connection for TcpStream {}
+
+impl Connection for StreamOwned<ServerConnection, TcpStream> {}
+
+impl Connection for FdStream {
+    fn fds(&mut self) -> Option<&mut FdStream> {
+        Some(self)
+    }
+}
+
 /// Rune Daemon configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DaemonConfig {
     /// Unix socket path
     pub socket_path: PathBuf,
@@ -26,6 +70,48 @@ pub struct DaemonConfig {
     pub debug: bool,
     /// PID file path
     pub pid_file: PathBuf,
+    /// TCP address to listen on as well, e.g. `0.0.0.0:2376`
+    pub tcp_address: Option<String>,
+    /// Certificate for TLS on the TCP listener
+    pub tls_cert: Option<PathBuf>,
+    /// Private key for TLS on the TCP listener
+    pub tls_key: Option<PathBuf>,
+    /// Log driver of containers that don't set one
+    pub log_config: LogConfig,
+    /// Remap container uids and gids into the subordinate ranges of this
+    /// user: `default`, `user` or `user:group`
+    pub userns_remap: Option<String>,
+    /// OCI runtime that runs containers: `rune` for the built-in one, or
+    /// the name or path of an external one such as `runc`, `crun` or
+    /// `youki`. Unset, containers are only tracked.
+    pub runtime: Option<String>,
+    /// Directories CDI device specs are read from
+    pub cdi_spec_dirs: Vec<PathBuf>,
+    /// OCI hooks every container runs, before its own
+    pub hooks: Hooks,
+    /// Docker Hub mirrors, tried in order before Docker Hub itself
+    pub registry_mirrors: Vec<String>,
+    /// Registries reached over HTTP or unverified TLS, as `host[:port]`
+    /// or CIDR ranges
+    pub insecure_registries: Vec<String>,
+    /// Directory whose image archives are loaded at startup, for hosts
+    /// without a registry to pull from
+    pub image_preload_dir: Option<PathBuf>,
+    /// How long samples of running containers' resource use are kept;
+    /// zero keeps none
+    pub stats_retention: Duration,
+    /// Time between samples of running containers' resource use
+    pub stats_interval: Duration,
+    /// Whether containers on the same network can reach each other;
+    /// networks can also turn it off for themselves
+    pub icc: bool,
+    /// Size past which the audit log is rotated
+    pub audit_max_size: u64,
+    /// Rotated audit logs kept
+    pub audit_max_files: usize,
+    /// Where this node's swarm state is kept; while it holds an unlocked
+    /// manager's state, the daemon serves the swarm's routing mesh
+    pub swarm_state_dir: PathBuf,
 }
 
 impl Default for DaemonConfig {
@@ -35,6 +121,23 @@ impl Default for DaemonConfig {
             data_dir: PathBuf::from("/var/lib/rune"),
             debug: false,
             pid_file: PathBuf::from("/var/run/rune.pid"),
+            tcp_address: None,
+            tls_cert: None,
+            tls_key: None,
+            log_config: LogConfig::default(),
+            userns_remap: None,
+            runtime: None,
+            cdi_spec_dirs: DEFAULT_SPEC_DIRS.iter().map(PathBuf::from).collect(),
+            hooks: Hooks::default(),
+            registry_mirrors: Vec::new(),
+            insecure_registries: Vec::new(),
+            image_preload_dir: None,
+            stats_retention: DEFAULT_RETENTION,
+            stats_interval: DEFAULT_SAMPLE_INTERVAL,
+            icc: true,
+            audit_max_size: DEFAULT_MAX_SIZE,
+            audit_max_files: DEFAULT_MAX_FILES,
+            swarm_state_dir: PathBuf::from(DEFAULT_STATE_DIR),
         }
     }
 }
@@ -44,28 +147,145 @@ pub struct RuneDaemon {
     config: DaemonConfig,
     container_manager: Arc<ContainerManager>,
     api_handler: ApiHandler,
+    image_store: Arc<ImageStore>,
+    image_puller: Arc<ImagePuller>,
+    /// Where samples of running containers are recorded, and their source
+    stats_recorder: Option<(Arc<MetricsStore>, Arc<dyn MetricsSource>)>,
+    /// Whether the swarm this node is part of is being served
+    swarm_serving: Arc<Mutex<bool>>,
     listener: Option<UnixListener>,
 }
 
 impl RuneDaemon {
     /// Create a new daemon instance
-    pub fn new(config: DaemonConfig) -> Result<Self> {
-        // Create data directories
-        fs::create_dir_all(&config.data_dir)?;
-        fs::create_dir_all(config.data_dir.join("containers"))?;
-        fs::create_dir_all(config.data_dir.join("images"))?;
-        fs::create_dir_all(config.data_dir.join("volumes"))?;
-        fs::create_dir_all(config.data_dir.join("networks"))?;
+    pub fn new(mut config: DaemonConfig) -> Result<Self> {
+        // Remapped containers keep their state apart, owned by the remapped
+        // root
+        let userns_remap = config
+            .userns_remap
+            .as_deref()
+            .map(UsernsRemap::load)
+            .transpose()?;
+        if let Some(remap) = &userns_remap {
+            let (uid, gid) = remap.root_pair();
+            config.data_dir = config.data_dir.join(format!("{}.{}", uid, gid));
+            info!(
+                "Remapping container users to {}:{} ({}:{})",
+                remap.user, remap.group, uid, gid
+            );
+        }
 
-        let container_manager =
-            Arc::new(ContainerManager::new(config.data_dir.join("containers"))?);
+        // Create data directories
+        for dir in ["", "containers", "images", "volumes", "networks"] {
+            let path = config.data_dir.join(dir);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create data directory {}", path.display()))?;
+        }
 
-        let api_handler = ApiHandler::new(container_manager.clone());
+        config.hooks.validate()?;
+        let hosts = RegistryHosts::new(&config.registry_mirrors, &config.insecure_registries)?;
+        for mirror in hosts.mirrors() {
+            info!("Pulling Docker Hub images through {}", mirror);
+        }
+        let image_puller = Arc::new(ImagePuller::new(hosts)?);
+        let image_store = Arc::new(ImageStore::new(config.data_dir.join("images"))?);
+        if let Some(dir) = &config.image_preload_dir {
+            let images = image_store
+                .load(dir)
+                .with_context(|| format!("Failed to preload images from {}", dir.display()))?;
+            info!("Preloaded {} image(s) from {}", images.len(), dir.display());
+        }
+        let plugins = Arc::new(PluginManager::open(config.data_dir.join("plugins"))?);
+        let network_manager = Arc::new(
+            NetworkManager::new()?
+                .with_plugins(plugins.clone())
+                .with_interfaces(Arc::new(Veth::default().with_icc(config.icc))),
+        );
+        let volume_manager = Arc::new(
+            VolumeManager::new(config.data_dir.join("volumes"))?.with_plugins(plugins.clone()),
+        );
+        let mut container_manager = ContainerManager::new(config.data_dir.join("containers"))?
+            .with_log_config(config.log_config.clone())
+            .with_cdi_spec_dirs(config.cdi_spec_dirs.clone())
+            .with_hooks(config.hooks.clone())
+            .with_plugins(plugins.clone())
+            .with_volumes(volume_manager.clone())
+            .with_networks(network_manager.clone());
+        container_manager.validate_log_config(&config.log_config)?;
+        if let Some(lsm) = Lsm::detect() {
+            info!("Confining containers with {:?}", lsm);
+            container_manager = container_manager.with_lsm(lsm);
+        }
+        if let Some(remap) = userns_remap {
+            let (uid, gid) = remap.root_pair();
+            if let Err(e) =
+                std::os::unix::fs::chown(config.data_dir.join("containers"), Some(uid), Some(gid))
+            {
+                warn!("Failed to hand container state to the remapped root: {}", e);
+            }
+            container_manager = container_manager.with_userns_remap(remap);
+        }
+        if let Some(runtime) = &config.runtime {
+            let executor = executor_for(runtime, &config.data_dir.join("runtime"))?;
+            info!(
+                "Running containers with {} ({})",
+                executor.name(),
+                executor.path().display()
+            );
+            container_manager = container_manager.with_executor(executor);
+        }
+        let container_manager = Arc::new(container_manager);
+
+        let mut api_handler = ApiHandler::new(container_manager.clone())
+            .with_plugins(plugins)
+            .with_images(image_store.clone())
+            .with_networks(network_manager)
+            .with_volumes(volume_manager)
+            .with_audit(Arc::new(
+                AuditLog::new(config.data_dir.join("audit").join("audit.log"))
+                    .context("Failed to open the audit log")?
+                    .with_rotation(config.audit_max_size, config.audit_max_files),
+            ));
+        let swarm_state_dir = config.swarm_state_dir.clone();
+        let container_root = config.data_dir.join("containers");
+        let swarm_serving = Arc::new(Mutex::new(false));
+        let serving = swarm_serving.clone();
+        api_handler = api_handler.with_swarm_unlock(Arc::new(move |key| {
+            unlock_swarm(&swarm_state_dir, &container_root, key, &serving)
+        }));
+        if config.debug {
+            api_handler = api_handler.with_debug(serde_json::to_value(&config)?);
+            // Requests are served on threads of their own, outside of the
+            // runtime the daemon is started on
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                api_handler = api_handler.with_runtime(handle);
+            }
+        }
+        let mut stats_recorder = None;
+        match CgroupMetrics::new() {
+            Ok(metrics) => {
+                let metrics: Arc<dyn MetricsSource> = Arc::new(metrics);
+                api_handler = api_handler.with_metrics(metrics.clone());
+                if !config.stats_retention.is_zero() {
+                    let store = Arc::new(MetricsStore::new(
+                        config.data_dir.join("metrics"),
+                        config.stats_retention,
+                    )?);
+                    api_handler = api_handler.with_stats_history(store.clone());
+                    stats_recorder = Some((store, metrics));
+                }
+            }
+            Err(e) => warn!("Container stats are unavailable: {}", e),
+        }
 
         Ok(Self {
             config,
             container_manager,
             api_handler,
+            image_store,
+            image_puller,
+            stats_recorder,
+            swarm_serving,
             listener: None,
         })
     }
@@ -84,10 +304,17 @@ impl RuneDaemon {
 
         // Write PID file
         let pid = std::process::id();
-        fs::write(&self.config.pid_file, pid.to_string())?;
+        fs::write(&self.config.pid_file, pid.to_string()).with_context(|| {
+            format!(
+                "Failed to write PID file {}",
+                self.config.pid_file.display()
+            )
+        })?;
 
         // Create Unix socket listener
-        let listener = UnixListener::bind(&self.config.socket_path)?;
+        let listener = UnixListener::bind(&self.config.socket_path).with_context(|| {
+            format!("Failed to listen on {}", self.config.socket_path.display())
+        })?;
 
         // Set socket permissions (rw-rw-rw-)
         #[cfg(unix)]
@@ -104,10 +331,43 @@ impl RuneDaemon {
 
         self.listener = Some(listener);
 
+        self.api_handler.health_checker().spawn();
+        self.container_manager.clone().spawn_reaper();
+        if let Some((store, metrics)) = &self.stats_recorder {
+            store.clone().spawn_recorder(
+                self.container_manager.clone(),
+                metrics.clone(),
+                self.config.stats_interval,
+            );
+        }
+
+        self.start_swarm();
+
+        if let Some(ref address) = self.config.tcp_address {
+            self.listen_tcp(address)?;
+        }
+
         // Accept connections
         self.accept_connections()
     }
 
+    /// Serve the swarm this node is part of once its state directory holds
+    /// it, so a swarm initialized or joined while the daemon runs is served
+    /// too
+    fn start_swarm(&self) {
+        let state_dir = self.config.swarm_state_dir.clone();
+        let container_root = self.config.data_dir.join("containers");
+        let serving = self.swarm_serving.clone();
+        std::thread::spawn(move || {
+            while !SwarmCluster::exists(&state_dir) {
+                std::thread::sleep(SYNC_INTERVAL);
+            }
+            if let Err(e) = start_unlocked_swarm(&state_dir, &container_root, &serving) {
+                warn!("Failed to serve the swarm: {}", e);
+            }
+        });
+    }
+
     /// Accept and handle incoming connections
     fn accept_connections(&self) -> Result<()> {
         let listener = self
@@ -117,14 +377,18 @@ impl RuneDaemon {
 
         for stream in listener.incoming() {
             match stream {
-                Ok(mut stream) => {
+                Ok(stream) => {
                     let api_handler = self.api_handler.clone();
-
-                    // Handle connection in current thread for simplicity
-                    // In production, this should spawn threads or use async
-                    if let Err(e) = Self::handle_connection(&mut stream, &api_handler) {
-                        error!("Error handling connection: {}", e);
-                    }
+                    let caller = Caller::unix(&stream);
+
+                    // Containers run attached hold their connection until
+                    // they exit
+                    std::thread::spawn(move || {
+                        let mut stream = FdStream::new(stream);
+                        if let Err(e) = Self::handle_connection(&mut stream, &api_handler, caller) {
+                            error!("Error handling connection: {}", e);
+                        }
+                    });
                 }
                 Err(e) => {
                     error!("Error accepting connection: {}", e);
@@ -135,12 +399,81 @@ impl RuneDaemon {
         Ok(())
     }
 
-    /// Handle a single connection
-    fn handle_connection(
-        stream: &mut std::os::unix::net::UnixStream,
+    /// Listen on a TCP address and serve it from a thread of its own
+    fn listen_tcp(&self, address: &str) -> Result<()> {
+        let tls = match (&self.config.tls_cert, &self.config.tls_key) {
+            (Some(cert), Some(key)) => Some(Self::tls_config(cert, key)?),
+            (None, None) => None,
+            _ => {
+                return Err(RuneError::InvalidConfig(
+                    "TLS requires both a certificate and a key".to_string(),
+                ))
+            }
+        };
+        if tls.is_none() {
+            warn!("Daemon TCP listener on {} is not using TLS", address);
+        }
+
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on tcp://{}", address))?;
+        info!("Rune daemon listening on tcp://{}", address);
+
+        let api_handler = self.api_handler.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Error accepting connection: {}", e);
+                        continue;
+                    }
+                };
+                let caller = stream.peer_addr().map(Caller::remote).unwrap_or_default();
+                let result = match tls {
+                    Some(ref config) => ServerConnection::new(config.clone())
+                        .map_err(|e| RuneError::Daemon(format!("TLS: {}", e)))
+                        .and_then(|connection| {
+                            let mut stream = StreamOwned::new(connection, stream);
+                            Self::handle_connection(&mut stream, &api_handler, caller)?;
+                            stream.conn.send_close_notify();
+                            stream.flush()?;
+                            Ok(())
+                        }),
+                    None => Self::handle_connection(&mut stream, &api_handler, caller),
+                };
+                if let Err(e) = result {
+                    error!("Error handling connection: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// TLS configuration for the TCP listener
+    fn tls_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+        let tls_error = |e: &dyn std::fmt::Display| RuneError::InvalidConfig(format!("TLS: {}", e));
+
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| tls_error(&e))?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| tls_error(&e))?;
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|e| tls_error(&e))?
+                .with_no_client_auth()
+                .with_single_cert(chain, key)
+                .map_err(|e| tls_error(&e))?;
+        Ok(Arc::new(config))
+    }
+
+    /// Handle a single connection from `caller`
+    fn handle_connection<S: Connection>(
+        stream: &mut S,
         api_handler: &ApiHandler,
+        mut caller: Caller,
     ) -> Result<()> {
-        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut reader = BufReader::new(stream);
         let mut request_line = String::new();
         reader.read_line(&mut request_line)?;
 
@@ -149,7 +482,7 @@ impl RuneDaemon {
         // Parse HTTP request line
         let parts: Vec<&str> = request_line.split_whitespace().collect();
         if parts.len() < 2 {
-            Self::send_error(stream, 400, "Bad Request")?;
+            Self::send_error(reader.get_mut(), 400, "Bad Request")?;
             return Ok(());
         }
 
@@ -169,6 +502,11 @@ impl RuneDaemon {
                     content_length = len.trim().parse().unwrap_or(0);
                 }
             }
+            if let Some((name, value)) = header_line.split_once(':') {
+                if name.eq_ignore_ascii_case(CONTEXT_HEADER) {
+                    caller.context = Some(value.trim().to_string());
+                }
+            }
         }
 
         // Read body if present
@@ -180,17 +518,73 @@ impl RuneDaemon {
             String::new()
         };
 
-        // Route request to API handler
-        let response = api_handler.handle_request(method, path, &body)?;
+        if method == "POST" && is_run(path) {
+            let fds = match reader.get_mut().fds() {
+                Some(stream) => stream.take_fds(),
+                None => {
+                    return Self::send_error(
+                        reader.get_mut(),
+                        400,
+                        "Containers are only run attached over the daemon's Unix socket",
+                    )
+                }
+            };
+            let stream = reader.into_inner().fds().expect("checked above");
+            return Self::run_attached(stream, api_handler, &caller, path, &body, fds);
+        }
 
-        // Send response
-        Self::send_response(stream, &response)?;
+        // Route request to API handler and send the response
+        match api_handler.handle_audited_request(&caller, method, path, &body) {
+            Ok(response) => Self::send_response(reader.get_mut(), &response),
+            Err(e) => Self::send_error(reader.get_mut(), e.status_code(), &e.api_message()),
+        }
+    }
 
+    /// Run a container for the client on `stream` and stay with it until
+    /// the container exits, then send its exit code
+    ///
+    /// The response to the request, sent as soon as the container started,
+    /// is a line with its ID, passing the client its terminal or standard
+    /// input if it has them. A line with its exit status follows once it
+    /// exits, whether or not the client is still there.
+    fn run_attached(
+        stream: &mut FdStream,
+        api_handler: &ApiHandler,
+        caller: &Caller,
+        path: &str,
+        body: &str,
+        fds: Vec<std::os::fd::OwnedFd>,
+    ) -> Result<()> {
+        let (id, input) = match api_handler.run_attached(caller, path, body, fds) {
+            Ok(run) => run,
+            Err(e) => return Self::send_error(stream, e.status_code(), &e.api_message()),
+        };
+        let head = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {}\n",
+            serde_json::json!({ "Id": id })
+        );
+        let input: Vec<_> = input.iter().map(|fd| fd.as_fd()).collect();
+        let sent = stream.send(head.as_bytes(), &input);
+        drop(input);
+
+        let status = match api_handler.wait_attached(&id) {
+            Ok(code) => serde_json::json!({ "StatusCode": code }),
+            Err(e) => serde_json::json!({
+                "StatusCode": -1,
+                "Error": { "Message": e.api_message() },
+            }),
+        };
+        sent?;
+        stream.write_all(format!("{}\n", status).as_bytes())?;
+        stream.flush()?;
         Ok(())
     }
 
     /// Send HTTP response
-    fn send_response(stream: &mut std::os::unix::net::UnixStream, body: &str) -> Result<()> {
+    fn send_response(stream: &mut impl Write, body: &str) -> Result<()> {
         let response = format!(
             "HTTP/1.1 200 OK\r\n\
              Content-Type: application/json\r\n\
@@ -206,15 +600,18 @@ impl RuneDaemon {
     }
 
     /// Send HTTP error response
-    fn send_error(
-        stream: &mut std::os::unix::net::UnixStream,
-        code: u16,
-        message: &str,
-    ) -> Result<()> {
+    fn send_error(stream: &mut impl Write, code: u16, message: &str) -> Result<()> {
         let body = serde_json::json!({
             "message": message
         });
         let body_str = body.to_string();
+        let reason = match code {
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        };
         let response = format!(
             "HTTP/1.1 {} {}\r\n\
              Content-Type: application/json\r\n\
@@ -222,7 +619,7 @@ impl RuneDaemon {
              \r\n\
              {}",
             code,
-            message,
+            reason,
             body_str.len(),
             body_str
         );
@@ -256,6 +653,16 @@ impl RuneDaemon {
     pub fn container_manager(&self) -> Arc<ContainerManager> {
         self.container_manager.clone()
     }
+
+    /// Get the image store
+    pub fn image_store(&self) -> Arc<ImageStore> {
+        self.image_store.clone()
+    }
+
+    /// Get the image puller, honoring the configured registries
+    pub fn image_puller(&self) -> Arc<ImagePuller> {
+        self.image_puller.clone()
+    }
 }
 
 impl Drop for RuneDaemon {
@@ -264,9 +671,88 @@ impl Drop for RuneDaemon {
     }
 }
 
+/// Whether a request path is that of running a container attached
+fn is_run(path: &str) -> bool {
+    path.split('?')
+        .next()
+        .unwrap_or(path)
+        .trim_start_matches('/')
+        .split('/')
+        .skip_while(|part| part.starts_with("v1."))
+        .eq(["containers", "run"])
+}
+
+/// Serve the routing mesh of the swarm whose state is committed to
+/// `state_dir`, following it as it changes
+fn serve_routing_mesh(state_dir: PathBuf, data_key: DataKey) -> Result<()> {
+    let state = move || SwarmCluster::read_state(&state_dir, &data_key);
+    RoutingMesh::spawn(state, std::net::Ipv4Addr::UNSPECIFIED.into(), SYNC_INTERVAL)?;
+    info!("Serving the swarm routing mesh");
+    Ok(())
+}
+
+/// Serve the swarm node whose state is in `state_dir`: the RPC other nodes
+/// reach it through, which includes the logs of the containers in
+/// `container_root`, and on managers the routing mesh
+fn serve_swarm(state_dir: &Path, container_root: &Path, unlock_key: Option<&str>) -> Result<()> {
+    let logs = Arc::new(FileLogSource::new(container_root.to_path_buf()));
+    let cluster = Arc::new(SwarmCluster::restore(state_dir, unlock_key)?.with_log_source(logs));
+    let manager = cluster.raft().is_some();
+    let addr = cluster.spawn()?;
+    info!("Serving swarm RPC on {}", addr);
+    if manager {
+        let data_key = KeyStore::open(state_dir)?.load(unlock_key)?;
+        serve_routing_mesh(state_dir.to_path_buf(), data_key)?;
+    }
+    Ok(())
+}
+
+/// Serve the swarm at `state_dir` unless it is locked or already served
+fn start_unlocked_swarm(
+    state_dir: &Path,
+    container_root: &Path,
+    serving: &Mutex<bool>,
+) -> Result<()> {
+    let mut serving = serving
+        .lock()
+        .map_err(|_| RuneError::Lock("Failed to acquire swarm lock".to_string()))?;
+    if *serving {
+        return Ok(());
+    }
+    if KeyStore::open(state_dir)?.is_locked()? {
+        warn!("Swarm is locked; it is not served until it is unlocked with \"rune swarm unlock\"");
+        return Ok(());
+    }
+    serve_swarm(state_dir, container_root, None)?;
+    *serving = true;
+    Ok(())
+}
+
+/// Unlock the swarm at `state_dir`, which the daemon found locked, and
+/// serve it
+fn unlock_swarm(
+    state_dir: &Path,
+    container_root: &Path,
+    unlock_key: &str,
+    serving: &Mutex<bool>,
+) -> Result<()> {
+    let mut serving = serving
+        .lock()
+        .map_err(|_| RuneError::Lock("Failed to acquire swarm lock".to_string()))?;
+    if *serving || !SwarmCluster::exists(state_dir) || !KeyStore::open(state_dir)?.is_locked()? {
+        return Err(RuneError::Swarm("Swarm is not locked".to_string()));
+    }
+    serve_swarm(state_dir, container_root, Some(unlock_key))?;
+    *serving = true;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::swarm::cluster::TokenType;
+    use crate::swarm::service::ServiceMode;
+    use crate::swarm::{NodeRole, StoreAction};
     use tempfile::TempDir;
 
     #[test]
@@ -285,9 +771,224 @@ mod tests {
             data_dir: temp_dir.path().join("data"),
             debug: false,
             pid_file: temp_dir.path().join("rune.pid"),
+            ..DaemonConfig::default()
         };
 
         let daemon = RuneDaemon::new(config);
         assert!(daemon.is_ok());
     }
+
+    #[test]
+    fn test_is_run() {
+        assert!(is_run("/containers/run?name=web"));
+        assert!(is_run("/v1.43/containers/run"));
+        assert!(!is_run("/containers/run/start"));
+        assert!(!is_run("/containers/create"));
+    }
+
+    #[test]
+    fn test_unlock_swarm() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = crate::swarm::SwarmConfig {
+            listen_addr: "127.0.0.1:0".to_string(),
+            state_dir: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let mut cluster = SwarmCluster::init(config).unwrap();
+        let key = cluster.set_autolock(true).unwrap().unwrap();
+        drop(cluster);
+
+        let serving = Mutex::new(false);
+        let containers = temp_dir.path().join("containers");
+        start_unlocked_swarm(temp_dir.path(), &containers, &serving).unwrap();
+        assert!(!*serving.lock().unwrap());
+        assert!(unlock_swarm(temp_dir.path(), &containers, "SWMKEY-1-wrong", &serving).is_err());
+        unlock_swarm(temp_dir.path(), &containers, &key, &serving).unwrap();
+        assert!(*serving.lock().unwrap());
+        assert!(unlock_swarm(temp_dir.path(), &containers, &key, &serving).is_err());
+    }
+
+    /// A loopback address nothing listens on
+    fn free_addr() -> String {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_join_through_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let addr = free_addr();
+        let config = crate::swarm::SwarmConfig {
+            listen_addr: addr.clone(),
+            advertise_addr: addr.clone(),
+            state_dir: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let token = SwarmCluster::init(config)
+            .unwrap()
+            .join_token(TokenType::Worker)
+            .to_string();
+
+        let serving = Mutex::new(false);
+        let containers = temp_dir.path().join("containers");
+        start_unlocked_swarm(temp_dir.path(), &containers, &serving).unwrap();
+        assert!(*serving.lock().unwrap());
+
+        // The daemon checks the token and issues the node a certificate
+        let worker = SwarmCluster::join(&token, vec![addr.clone()], "127.0.0.1:0", "10.0.0.2:2377")
+            .await
+            .unwrap();
+        let identity = worker.credentials().identity().unwrap();
+        assert_eq!(identity.role, NodeRole::Worker);
+        let mut manager = SwarmCluster::restore(temp_dir.path(), None).unwrap();
+        assert_eq!(
+            manager.get_node(&identity.node_id).unwrap().role,
+            NodeRole::Worker
+        );
+
+        // A token the CLI rotated no longer gets a node in
+        let rotated = manager.rotate_join_token(TokenType::Worker).unwrap();
+        drop(manager);
+        for (token, joins) in [(token, false), (rotated, true)] {
+            let joined =
+                SwarmCluster::join(&token, vec![addr.clone()], "127.0.0.1:0", "10.0.0.3:2377")
+                    .await;
+            assert_eq!(joined.is_ok(), joins);
+        }
+    }
+
+    /// Write a json-file log line for container `id` under `root`
+    fn write_log(root: &Path, id: &str, message: &str, secs: i64) {
+        let dir = root.join(id);
+        fs::create_dir_all(&dir).unwrap();
+        let entry = serde_json::json!({
+            "log": format!("{}\n", message),
+            "stream": "stdout",
+            "time": chrono::DateTime::from_timestamp(secs, 0).unwrap(),
+        });
+        fs::write(dir.join(format!("{}-json.log", id)), format!("{}\n", entry)).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_service_logs_through_daemon() {
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+        let addrs = [free_addr(), free_addr()];
+        let containers = dirs.each_ref().map(|dir| dir.path().join("containers"));
+        let state_dirs = dirs.each_ref().map(|dir| dir.path().join("swarm"));
+
+        let config = crate::swarm::SwarmConfig {
+            listen_addr: addrs[0].clone(),
+            advertise_addr: addrs[0].clone(),
+            state_dir: Some(state_dirs[0].clone()),
+            ..Default::default()
+        };
+        let token = SwarmCluster::init(config)
+            .unwrap()
+            .join_token(TokenType::Worker)
+            .to_string();
+        start_unlocked_swarm(&state_dirs[0], &containers[0], &Mutex::new(false)).unwrap();
+
+        let mut worker = SwarmCluster::join(&token, vec![addrs[0].clone()], &addrs[1], &addrs[1])
+            .await
+            .unwrap();
+        worker.persist(&state_dirs[1]).unwrap();
+        let worker_id = worker.credentials().identity().unwrap().node_id;
+        drop(worker);
+        start_unlocked_swarm(&state_dirs[1], &containers[1], &Mutex::new(false)).unwrap();
+
+        // The CLI on the manager reads the worker's logs from its daemon
+        let manager = SwarmCluster::restore(&state_dirs[0], None)
+            .unwrap()
+            .with_log_source(Arc::new(FileLogSource::new(containers[0].clone())));
+        let id = manager
+            .create_service(crate::swarm::Service::new(crate::swarm::ServiceSpec {
+                name: "web".to_string(),
+                mode: Some(ServiceMode::Replicated { replicas: 2 }),
+                ..Default::default()
+            }))
+            .unwrap();
+        for mut task in manager.list_tasks(Some(&id)).unwrap() {
+            let (root, message, secs) = if task.node_id.as_deref() == Some(worker_id.as_str()) {
+                (&containers[1], "from the worker", 2)
+            } else {
+                (&containers[0], "from the manager", 1)
+            };
+            write_log(root, &task.id, message, secs);
+            task.set_running(&task.id.clone());
+            let raft = manager.raft().unwrap();
+            raft.lock()
+                .unwrap()
+                .propose(StoreAction::PutTask(task))
+                .unwrap();
+        }
+
+        let lines = manager.service_logs(&id, None).await.unwrap();
+        let messages: Vec<&str> = lines.iter().map(|l| l.line.message.as_str()).collect();
+        assert_eq!(messages, ["from the manager", "from the worker"]);
+    }
+
+    #[test]
+    fn test_daemon_runtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            runtime: Some("rune".to_string()),
+            ..DaemonConfig::default()
+        };
+        let daemon = RuneDaemon::new(config).unwrap();
+        assert_eq!(daemon.container_manager.executor().unwrap().name(), "rune");
+
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            runtime: Some("no-such-runtime".to_string()),
+            ..DaemonConfig::default()
+        };
+        assert!(RuneDaemon::new(config).is_err());
+    }
+
+    #[test]
+    fn test_daemon_registries() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            registry_mirrors: vec!["https://mirror.example.com".to_string()],
+            insecure_registries: vec!["registry.lan:5000".to_string()],
+            ..DaemonConfig::default()
+        };
+        let daemon = RuneDaemon::new(config).unwrap();
+        let hosts = daemon.image_puller().hosts().clone();
+        assert_eq!(hosts.mirrors(), ["https://mirror.example.com"]);
+        assert!(hosts.is_insecure("registry.lan:5000"));
+
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            registry_mirrors: vec!["mirror.example.com".to_string()],
+            ..DaemonConfig::default()
+        };
+        assert!(RuneDaemon::new(config).is_err());
+    }
+
+    #[test]
+    fn test_daemon_image_preload() {
+        let temp_dir = TempDir::new().unwrap();
+        let preload = temp_dir.path().join("preload");
+        fs::create_dir(&preload).unwrap();
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            image_preload_dir: Some(preload.clone()),
+            ..DaemonConfig::default()
+        };
+        let daemon = RuneDaemon::new(config).unwrap();
+        assert!(daemon.image_store().list().unwrap().is_empty());
+
+        let config = DaemonConfig {
+            data_dir: temp_dir.path().join("data"),
+            image_preload_dir: Some(temp_dir.path().join("missing")),
+            ..DaemonConfig::default()
+        };
+        assert!(RuneDaemon::new(config).is_err());
+    }
 }
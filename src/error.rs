@@ -1,4 +1,8 @@
 //! Error types for Rune
+//!
+//! Every error has an [`ErrorKind`], which decides the HTTP status the
+//! daemon API answers it with and the exit code of the CLI, the way
+//! Docker's error definitions do.
 
 use thiserror::Error;
 
@@ -109,4 +113,257 @@ pub enum RuneError {
 
     #[error("Health check failed: {0}")]
     Healthcheck(String),
+
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+
+    #[error("Plugin not found: {0}")]
+    PluginNotFound(String),
+
+    /// Error of an explicit kind, like one answered by the daemon API
+    #[error("{message}")]
+    Status { kind: ErrorKind, message: String },
+
+    /// Error with what was being done when it happened
+    #[error("{message}: {source}")]
+    Context {
+        message: String,
+        #[source]
+        source: Box<RuneError>,
+    },
+}
+
+/// Broad kind of an error, independent of the subsystem it comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The object operated on doesn't exist
+    NotFound,
+    /// The object is in a state that doesn't allow the operation
+    Conflict,
+    /// The request or configuration is malformed
+    InvalidArgument,
+    /// The caller isn't allowed to do this
+    PermissionDenied,
+    /// Anything else failed while carrying out the operation
+    RuntimeFailure,
+}
+
+impl ErrorKind {
+    /// HTTP status code the daemon API answers errors of this kind with
+    pub fn status_code(self) -> u16 {
+        match self {
+            ErrorKind::NotFound => 404,
+            ErrorKind::Conflict => 409,
+            ErrorKind::InvalidArgument => 400,
+            ErrorKind::PermissionDenied => 403,
+            ErrorKind::RuntimeFailure => 500,
+        }
+    }
+
+    /// Kind of error an HTTP status code stands for
+    pub fn from_status_code(code: u16) -> Self {
+        match code {
+            404 => ErrorKind::NotFound,
+            409 => ErrorKind::Conflict,
+            400 => ErrorKind::InvalidArgument,
+            401 | 403 => ErrorKind::PermissionDenied,
+            _ => ErrorKind::RuntimeFailure,
+        }
+    }
+
+    /// Exit code of the CLI when failing with an error of this kind: 2 for
+    /// bad usage, like argument parsing, and 125 when the daemon or runtime
+    /// failed, like `docker run`
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::InvalidArgument => 2,
+            ErrorKind::RuntimeFailure => 125,
+            _ => 1,
+        }
+    }
+}
+
+impl RuneError {
+    /// Error of an explicit kind
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuneError::Status {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Error answered by the daemon API with a status code
+    ///
+    /// Server errors stay [`RuneError::Api`] errors.
+    pub fn from_status_code(code: u16, message: impl Into<String>) -> Self {
+        match ErrorKind::from_status_code(code) {
+            ErrorKind::RuntimeFailure => RuneError::Api(message.into()),
+            kind => RuneError::new(kind, message),
+        }
+    }
+
+    /// Wrap the error with what was being done when it happened
+    pub fn context(self, message: impl Into<String>) -> Self {
+        RuneError::Context {
+            message: message.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RuneError::ContainerNotFound(_)
+            | RuneError::ImageNotFound(_)
+            | RuneError::NetworkNotFound(_)
+            | RuneError::VolumeNotFound(_)
+            | RuneError::ServiceNotFound(_)
+            | RuneError::NodeNotFound(_)
+            | RuneError::PluginNotFound(_) => ErrorKind::NotFound,
+            RuneError::ContainerExists(_)
+            | RuneError::ContainerAlreadyRunning(_)
+            | RuneError::ContainerNotRunning(_)
+            | RuneError::ImageExists(_) => ErrorKind::Conflict,
+            RuneError::DockerfileParse { .. }
+            | RuneError::ComposeParse(_)
+            | RuneError::Yaml(_)
+            | RuneError::InvalidConfig(_) => ErrorKind::InvalidArgument,
+            RuneError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            // A file missing on the daemon's side is its own failure, not
+            // something the caller asked for that doesn't exist
+            RuneError::Io(e) => match e.kind() {
+                std::io::ErrorKind::AlreadyExists => ErrorKind::Conflict,
+                std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => {
+                    ErrorKind::InvalidArgument
+                }
+                std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                _ => ErrorKind::RuntimeFailure,
+            },
+            RuneError::Status { kind, .. } => *kind,
+            RuneError::Context { source, .. } => source.kind(),
+            _ => ErrorKind::RuntimeFailure,
+        }
+    }
+
+    /// HTTP status code the daemon API answers the error with
+    pub fn status_code(&self) -> u16 {
+        self.kind().status_code()
+    }
+
+    /// Exit code of the CLI when failing with the error
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+
+    /// Message of the error as the Docker API words it, which clients
+    /// like the Docker CLI match on to tell missing objects apart
+    pub fn api_message(&self) -> String {
+        match self {
+            RuneError::ContainerNotFound(id) => format!("No such container: {}", id),
+            RuneError::ImageNotFound(id) => format!("No such image: {}", id),
+            RuneError::NetworkNotFound(id) => format!("network {} not found", id),
+            RuneError::VolumeNotFound(name) => format!("get {}: no such volume", name),
+            RuneError::ServiceNotFound(id) => format!("service {} not found", id),
+            RuneError::NodeNotFound(id) => format!("node {} not found", id),
+            RuneError::PluginNotFound(name) => format!("plugin \"{}\" not found", name),
+            RuneError::ContainerNotRunning(id) => format!("Container {} is not running", id),
+            RuneError::ContainerAlreadyRunning(id) => {
+                format!("Container {} is already running", id)
+            }
+            RuneError::Context { message, source } => {
+                format!("{}: {}", message, source.api_message())
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    /// Body of the daemon API's answer to the error
+    pub fn to_api_json(&self) -> serde_json::Value {
+        serde_json::json!({ "message": self.api_message() })
+    }
+}
+
+/// Adding context to the errors of results
+pub trait ResultExt<T> {
+    /// Wrap an error with what was being done when it happened
+    fn context(self, message: impl Into<String>) -> Result<T>;
+
+    /// Wrap an error with context computed only on failure
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: Into<RuneError>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| e.into().context(message))
+    }
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|e| e.into().context(message()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_error_kinds() {
+        let missing = RuneError::ContainerNotFound("web".to_string());
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        assert_eq!(missing.status_code(), 404);
+        assert_eq!(missing.exit_code(), 1);
+        assert_eq!(missing.to_api_json()["message"], "No such container: web");
+
+        let conflict = RuneError::ContainerNotRunning("web".to_string());
+        assert_eq!(conflict.status_code(), 409);
+        assert_eq!(RuneError::InvalidConfig("x".to_string()).status_code(), 400);
+        assert_eq!(RuneError::InvalidConfig("x".to_string()).exit_code(), 2);
+        assert_eq!(RuneError::Runtime("x".to_string()).status_code(), 500);
+        assert_eq!(RuneError::Runtime("x".to_string()).exit_code(), 125);
+
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(RuneError::from(io).status_code(), 403);
+
+        match RuneError::from_status_code(404, "No such image: x") {
+            RuneError::Status { kind, message } => {
+                assert_eq!(kind, ErrorKind::NotFound);
+                assert_eq!(message, "No such image: x");
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(matches!(
+            RuneError::from_status_code(500, "boom"),
+            RuneError::Api(_)
+        ));
+    }
+
+    #[test]
+    fn test_error_context() {
+        let result: Result<()> = Err(RuneError::ImageNotFound("nginx".to_string()));
+        let error = result.context("Failed to create web").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            error.to_string(),
+            "Failed to create web: Image not found: nginx"
+        );
+        assert_eq!(
+            error.api_message(),
+            "Failed to create web: No such image: nginx"
+        );
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "Image not found: nginx"
+        );
+
+        let io: std::result::Result<(), _> =
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let error = io.with_context(|| "Reading config").unwrap_err();
+        assert_eq!(error.status_code(), 500);
+        assert!(error.source().unwrap().source().is_some());
+
+        // Only the daemon API decides a bad JSON body is the caller's fault
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(RuneError::from(json).status_code(), 500);
+    }
 }
@@ -0,0 +1,236 @@
+//! Filters for listing containers, images, networks and volumes
+//!
+//! Filters come from `--filter key=value` flags on the CLI or the API's
+//! `filters` query parameter, a JSON object of keys to values. Values of
+//! one key match if any of them does; every key given must match. Labels
+//! are the exception: each `label` filter must match on its own.
+
+use crate::error::{Result, RuneError};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Filters for a listing, as keys to the values they accept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filters {
+    filters: BTreeMap<String, Vec<String>>,
+}
+
+impl Filters {
+    /// No filters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse `key=value` filters given on the command line
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut filters = Self::new();
+        for arg in args {
+            let (key, value) = arg.split_once('=').ok_or_else(|| {
+                RuneError::InvalidConfig(format!(
+                    "Bad format of filter (expected name=value): {}",
+                    arg
+                ))
+            })?;
+            filters = filters.with(key.trim(), value);
+        }
+        Ok(filters)
+    }
+
+    /// Parse the API's JSON filters, either `{"key": ["value"]}` or the
+    /// older `{"key": {"value": true}}`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            RuneError::InvalidConfig(format!("Invalid filter JSON: {}", e))
+        };
+        if json.trim().is_empty() {
+            return Ok(Self::new());
+        }
+        let object: HashMap<String, Value> = serde_json::from_str(json).map_err(|e| invalid(&e))?;
+        let mut filters = Self::new();
+        for (key, values) in object {
+            let values: Vec<String> = match values {
+                Value::Array(values) => values
+                    .into_iter()
+                    .map(|v| v.as_str().map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid(&format!("values of {} must be strings", key)))?,
+                Value::Object(values) => values
+                    .into_iter()
+                    .filter(|(_, on)| on.as_bool().unwrap_or(false))
+                    .map(|(value, _)| value)
+                    .collect(),
+                _ => return Err(invalid(&format!("bad values of {}", key))),
+            };
+            for value in values {
+                filters = filters.with(&key, &value);
+            }
+        }
+        Ok(filters)
+    }
+
+    /// The filters as the API's JSON, `{"key": ["value"]}`
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.filters).unwrap_or_default()
+    }
+
+    /// Add a filter
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.filters
+            .entry(key.to_string())
+            .or_default()
+            .push(value.to_string());
+        self
+    }
+
+    /// Whether there are no filters
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Whether a key is filtered on
+    pub fn contains(&self, key: &str) -> bool {
+        self.filters.contains_key(key)
+    }
+
+    /// Values given for a key
+    pub fn get(&self, key: &str) -> &[String] {
+        self.filters.get(key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Fail on keys a listing doesn't filter on
+    pub fn validate(&self, allowed: &[&str]) -> Result<()> {
+        match self
+            .filters
+            .keys()
+            .find(|key| !allowed.contains(&key.as_str()))
+        {
+            Some(key) => Err(RuneError::InvalidConfig(format!(
+                "Invalid filter '{}'",
+                key
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether any value of a key matches, or the key isn't filtered on
+    pub fn matches(&self, key: &str, matches: impl Fn(&str) -> bool) -> bool {
+        let values = self.get(key);
+        values.is_empty() || values.iter().any(|value| matches(value))
+    }
+
+    /// Whether a key, if filtered on, has a value equal to `value`
+    pub fn matches_exact(&self, key: &str, value: &str) -> bool {
+        self.matches(key, |v| v == value)
+    }
+
+    /// Whether labels match every `label` filter, either `key` or
+    /// `key=value`
+    pub fn matches_labels(&self, labels: &HashMap<String, String>) -> bool {
+        self.get("label")
+            .iter()
+            .all(|filter| match filter.split_once('=') {
+                Some((key, value)) => labels.get(key).is_some_and(|v| v == value),
+                None => labels.contains_key(filter.as_str()),
+            })
+    }
+
+    /// Value of a boolean filter like `dangling=true`
+    pub fn bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.get(key) {
+            [] => Ok(None),
+            [value] => match value.as_str() {
+                "true" | "1" => Ok(Some(true)),
+                "false" | "0" => Ok(Some(false)),
+                _ => Err(RuneError::InvalidConfig(format!(
+                    "Invalid filter '{}={}'",
+                    key, value
+                ))),
+            },
+            _ => Err(RuneError::InvalidConfig(format!(
+                "Filter '{}' takes one value",
+                key
+            ))),
+        }
+    }
+}
+
+/// Whether an image reference matches one given in a filter, which
+/// matches any tag of its repository unless it names one
+pub fn image_matches(reference: &str, filter: &str) -> bool {
+    let (repository, tag) = split_reference(reference);
+    match split_reference(filter) {
+        (filter_repository, Some(filter_tag)) => {
+            filter_repository == repository && filter_tag == tag.unwrap_or("latest")
+        }
+        (filter_repository, None) => filter_repository == repository,
+    }
+}
+
+fn split_reference(reference: &str) -> (&str, Option<&str>) {
+    let reference = reference.split('@').next().unwrap_or(reference);
+    match reference.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => (repository, Some(tag)),
+        _ => (reference, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters() {
+        let filters = Filters::parse(&[
+            "status=running".to_string(),
+            "status=paused".to_string(),
+            "label=tier=web".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(filters.get("status"), ["running", "paused"]);
+        assert_eq!(filters.get("label"), ["tier=web"]);
+        assert!(Filters::parse(&["running".to_string()]).is_err());
+
+        assert!(filters.validate(&["status", "label"]).is_ok());
+        assert!(filters.validate(&["status"]).is_err());
+
+        let json =
+            Filters::from_json(r#"{"status": ["running", "paused"], "label": ["tier=web"]}"#)
+                .unwrap();
+        assert_eq!(json, filters);
+        assert_eq!(Filters::from_json(&filters.to_json()).unwrap(), filters);
+        let legacy = Filters::from_json(r#"{"dangling": {"true": true}}"#).unwrap();
+        assert_eq!(legacy.bool("dangling").unwrap(), Some(true));
+        assert!(Filters::from_json(r#"{"status": "running"}"#).is_err());
+        assert!(Filters::from_json("").unwrap().is_empty());
+        assert!(Filters::new()
+            .with("dangling", "maybe")
+            .bool("dangling")
+            .is_err());
+    }
+
+    #[test]
+    fn test_match_filters() {
+        let filters = Filters::new()
+            .with("status", "running")
+            .with("status", "paused")
+            .with("label", "tier")
+            .with("label", "env=prod");
+        assert!(filters.matches_exact("status", "paused"));
+        assert!(!filters.matches_exact("status", "exited"));
+        assert!(filters.matches_exact("name", "anything"));
+
+        let mut labels = HashMap::from([("tier".to_string(), "web".to_string())]);
+        assert!(!filters.matches_labels(&labels));
+        labels.insert("env".to_string(), "prod".to_string());
+        assert!(filters.matches_labels(&labels));
+
+        assert!(image_matches("nginx:1.25", "nginx"));
+        assert!(image_matches("nginx", "nginx:latest"));
+        assert!(!image_matches("nginx:1.25", "nginx:latest"));
+        assert!(image_matches(
+            "localhost:5000/app",
+            "localhost:5000/app:latest"
+        ));
+        assert!(!image_matches("nginx-proxy", "nginx"));
+    }
+}
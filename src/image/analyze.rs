@@ -0,0 +1,536 @@
+//! Image layer analysis
+//!
+//! Layers are replayed in order, the way unpacking them would apply them,
+//! to see which files each one adds, modifies and removes. A file a later
+//! layer overwrites or deletes still takes up space in the layer that
+//! shipped it: that is the image's wasted space.
+
+use super::layer::{decompress, OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
+use crate::error::Result;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use tar::EntryType;
+
+/// How a layer changed a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+impl std::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "+"),
+            ChangeKind::Modified => write!(f, "~"),
+            ChangeKind::Removed => write!(f, "-"),
+        }
+    }
+}
+
+/// A file a layer changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// Path in the root filesystem, like `/etc/hostname`
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Size of the file the layer ships, or of the one it removes
+    pub size: u64,
+}
+
+/// The files one layer changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerAnalysis {
+    /// Layer digest
+    pub digest: String,
+    /// Changes, by path
+    pub changes: Vec<FileChange>,
+}
+
+impl LayerAnalysis {
+    /// Bytes of the files the layer adds or modifies
+    pub fn size(&self) -> u64 {
+        self.changes
+            .iter()
+            .filter(|change| change.kind != ChangeKind::Removed)
+            .map(|change| change.size)
+            .sum()
+    }
+
+    /// Number of changes of a kind
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|c| c.kind == kind).count()
+    }
+}
+
+/// A file whose copies in lower layers are overwritten or deleted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WastedFile {
+    pub path: String,
+    /// Bytes of the copies no longer seen
+    pub size: u64,
+    /// Number of such copies
+    pub count: usize,
+}
+
+/// Changes of each layer of an image, and the space they waste
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageAnalysis {
+    pub layers: Vec<LayerAnalysis>,
+    /// Wasted files, largest first
+    pub wasted: Vec<WastedFile>,
+}
+
+impl ImageAnalysis {
+    /// Bytes of all files in all layers
+    pub fn size(&self) -> u64 {
+        self.layers.iter().map(LayerAnalysis::size).sum()
+    }
+
+    /// Bytes of files overwritten or deleted by a later layer
+    pub fn wasted_size(&self) -> u64 {
+        self.wasted.iter().map(|file| file.size).sum()
+    }
+
+    /// Share of the image's bytes still seen in its root filesystem
+    pub fn efficiency(&self) -> f64 {
+        match self.size() {
+            0 => 1.0,
+            size => 1.0 - self.wasted_size() as f64 / size as f64,
+        }
+    }
+}
+
+/// How one image's root filesystem differs from another's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageDiff {
+    /// Number of bottom layers both images share
+    pub shared_layers: usize,
+    /// Changes, grouped by the layer they come from: the layer of the
+    /// second image that writes or deletes a file, or for files it just
+    /// doesn't have, the layer of the first image that had them
+    pub layers: Vec<LayerAnalysis>,
+}
+
+/// An entry of a layer archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerEntry {
+    /// A file, symlink or other non-directory, with a hash of its content
+    File {
+        path: String,
+        size: u64,
+        hash: [u8; 32],
+    },
+    /// A directory
+    Dir { path: String },
+    /// Deletes the file or directory at a path
+    Whiteout { path: String },
+    /// Empties the directory at a path
+    Opaque { path: String },
+}
+
+/// Read the entries of a layer, plain or gzipped
+pub fn read_layer<R: Read>(reader: R) -> Result<Vec<LayerEntry>> {
+    let mut archive = tar::Archive::new(decompress(reader)?);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?.to_string_lossy());
+        if path == "/" {
+            continue;
+        }
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        let parent = if parent.is_empty() { "/" } else { parent };
+        if name == OPAQUE_WHITEOUT {
+            entries.push(LayerEntry::Opaque {
+                path: parent.to_string(),
+            });
+            continue;
+        }
+        if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            entries.push(LayerEntry::Whiteout {
+                path: join(parent, hidden),
+            });
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type == EntryType::Directory {
+            entries.push(LayerEntry::Dir { path });
+            continue;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update([entry_type.as_byte()]);
+        if let Some(target) = entry.link_name()? {
+            hasher.update(target.to_string_lossy().as_bytes());
+        }
+        std::io::copy(&mut entry, &mut hasher)?;
+        entries.push(LayerEntry::File {
+            path,
+            size: entry.header().size()?,
+            hash: hasher.finalize().into(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Replay an image's layers, given as digests and their entries
+pub fn analyze_layers(layers: &[(String, Vec<LayerEntry>)]) -> ImageAnalysis {
+    let mut root = RootFs::default();
+    let mut wasted: BTreeMap<String, WastedFile> = BTreeMap::new();
+    let mut analyses = Vec::new();
+    for (index, (digest, entries)) in layers.iter().enumerate() {
+        let mut changes = BTreeMap::new();
+        for entry in entries {
+            for (change, replaced) in root.apply(index, entry) {
+                if let Some(replaced) = replaced.filter(|file| file.layer < index) {
+                    let file = wasted
+                        .entry(change.path.clone())
+                        .or_insert_with(|| WastedFile {
+                            path: change.path.clone(),
+                            size: 0,
+                            count: 0,
+                        });
+                    file.size += replaced.size;
+                    file.count += 1;
+                }
+                changes.insert(change.path.clone(), change);
+            }
+        }
+        analyses.push(LayerAnalysis {
+            digest: digest.clone(),
+            changes: changes.into_values().collect(),
+        });
+    }
+
+    let mut wasted: Vec<WastedFile> = wasted.into_values().collect();
+    wasted.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    ImageAnalysis {
+        layers: analyses,
+        wasted,
+    }
+}
+
+/// Compare the root filesystems two images' layers build
+pub fn diff_layers(
+    from: &[(String, Vec<LayerEntry>)],
+    to: &[(String, Vec<LayerEntry>)],
+) -> ImageDiff {
+    let shared_layers = from
+        .iter()
+        .zip(to)
+        .take_while(|((a, _), (b, _))| a == b)
+        .count();
+    let from_root = RootFs::build(from);
+    let to_root = RootFs::build(to);
+
+    let mut changes: HashMap<&str, Vec<FileChange>> = HashMap::new();
+    for (path, file) in &to_root.files {
+        let kind = match from_root.files.get(path) {
+            None => ChangeKind::Added,
+            Some(old) if old.hash != file.hash => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        changes
+            .entry(to[file.layer].0.as_str())
+            .or_default()
+            .push(FileChange {
+                path: path.clone(),
+                kind,
+                size: file.size,
+            });
+    }
+    for (path, file) in &from_root.files {
+        if !to_root.files.contains_key(path) {
+            let layer = match to_root.removed.get(path) {
+                Some(&layer) => &to[layer].0,
+                None => &from[file.layer].0,
+            };
+            changes.entry(layer.as_str()).or_default().push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Removed,
+                size: file.size,
+            });
+        }
+    }
+
+    let mut layers = Vec::new();
+    for (digest, _) in to.iter().chain(from) {
+        if let Some(mut changes) = changes.remove(digest.as_str()) {
+            changes.sort_by(|a, b| a.path.cmp(&b.path));
+            layers.push(LayerAnalysis {
+                digest: digest.clone(),
+                changes,
+            });
+        }
+    }
+    ImageDiff {
+        shared_layers,
+        layers,
+    }
+}
+
+/// A file in a root filesystem
+#[derive(Debug, Clone, Copy)]
+struct File {
+    size: u64,
+    hash: [u8; 32],
+    /// Index of the layer that last wrote it
+    layer: usize,
+}
+
+/// Files of a root filesystem, as layers build it
+#[derive(Debug, Default)]
+struct RootFs {
+    files: BTreeMap<String, File>,
+    /// Paths of removed files, to the index of the layer removing them
+    removed: HashMap<String, usize>,
+}
+
+impl RootFs {
+    fn build(layers: &[(String, Vec<LayerEntry>)]) -> Self {
+        let mut root = Self::default();
+        for (index, (_, entries)) in layers.iter().enumerate() {
+            for entry in entries {
+                root.apply(index, entry);
+            }
+        }
+        root
+    }
+
+    /// Apply an entry of a layer, returning the changes it makes, each
+    /// with the file it replaces or removes
+    fn apply(&mut self, layer: usize, entry: &LayerEntry) -> Vec<(FileChange, Option<File>)> {
+        match entry {
+            LayerEntry::File { path, size, hash } => {
+                let file = File {
+                    size: *size,
+                    hash: *hash,
+                    layer,
+                };
+                self.removed.remove(path);
+                let replaced = self.files.insert(path.clone(), file);
+                let kind = match replaced {
+                    Some(old) if old.layer < layer => ChangeKind::Modified,
+                    _ => ChangeKind::Added,
+                };
+                vec![(
+                    FileChange {
+                        path: path.clone(),
+                        kind,
+                        size: *size,
+                    },
+                    replaced,
+                )]
+            }
+            LayerEntry::Dir { .. } => Vec::new(),
+            LayerEntry::Whiteout { path } => {
+                let mut removed = self.remove_under(path, layer);
+                if let Some(file) = self.files.remove(path) {
+                    removed.push((path.clone(), file));
+                }
+                self.removals(removed, layer)
+            }
+            LayerEntry::Opaque { path } => {
+                let removed = self.remove_under(path, layer);
+                self.removals(removed, layer)
+            }
+        }
+    }
+
+    /// Remove what lower layers put under a directory
+    fn remove_under(&mut self, dir: &str, layer: usize) -> Vec<(String, File)> {
+        let prefix = if dir == "/" {
+            "/".to_string()
+        } else {
+            format!("{}/", dir)
+        };
+        let paths: Vec<String> = self
+            .files
+            .range(prefix.clone()..)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .filter(|(_, file)| file.layer < layer)
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths
+            .into_iter()
+            .filter_map(|path| self.files.remove(&path).map(|file| (path, file)))
+            .collect()
+    }
+
+    fn removals(
+        &mut self,
+        removed: Vec<(String, File)>,
+        layer: usize,
+    ) -> Vec<(FileChange, Option<File>)> {
+        removed
+            .into_iter()
+            .map(|(path, file)| {
+                self.removed.insert(path.clone(), layer);
+                let change = FileChange {
+                    path,
+                    kind: ChangeKind::Removed,
+                    size: file.size,
+                };
+                (change, Some(file))
+            })
+            .collect()
+    }
+}
+
+/// Absolute form of an archive path, like `/etc/hostname` for
+/// `./etc/hostname`
+fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    format!("/{}", parts.join("/"))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, &[u8])]) -> Vec<LayerEntry> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            if path.ends_with('/') {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+            } else {
+                header.set_size(data.len() as u64);
+            }
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        read_layer(&builder.into_inner().unwrap()[..]).unwrap()
+    }
+
+    fn summary(layer: &LayerAnalysis) -> Vec<String> {
+        layer
+            .changes
+            .iter()
+            .map(|c| format!("{} {} {}", c.kind, c.path, c.size))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_layer() {
+        let entries = layer(&[
+            ("./etc/", b""),
+            ("./etc/motd", b"hello"),
+            ("etc/.wh.hostname", b""),
+            ("var/cache/.wh..wh..opq", b""),
+        ]);
+        assert_eq!(
+            entries[0],
+            LayerEntry::Dir {
+                path: "/etc".to_string()
+            }
+        );
+        assert!(
+            matches!(&entries[1], LayerEntry::File { path, size: 5, .. } if path == "/etc/motd")
+        );
+        assert_eq!(
+            entries[2],
+            LayerEntry::Whiteout {
+                path: "/etc/hostname".to_string()
+            }
+        );
+        assert_eq!(
+            entries[3],
+            LayerEntry::Opaque {
+                path: "/var/cache".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_analyze_layers() {
+        let layers = vec![
+            (
+                "sha256:base".to_string(),
+                layer(&[
+                    ("etc/motd", b"hello"),
+                    ("etc/hostname", b"base"),
+                    ("var/cache/apt/a.deb", b"0123456789"),
+                ]),
+            ),
+            (
+                "sha256:app".to_string(),
+                layer(&[
+                    ("etc/motd", b"hi"),
+                    ("app/bin", b"binary"),
+                    ("var/.wh.cache", b""),
+                ]),
+            ),
+        ];
+        let analysis = analyze_layers(&layers);
+        assert_eq!(
+            summary(&analysis.layers[0]),
+            [
+                "+ /etc/hostname 4",
+                "+ /etc/motd 5",
+                "+ /var/cache/apt/a.deb 10"
+            ]
+        );
+        assert_eq!(
+            summary(&analysis.layers[1]),
+            ["+ /app/bin 6", "~ /etc/motd 2", "- /var/cache/apt/a.deb 10"]
+        );
+        assert_eq!(analysis.layers[1].size(), 8);
+        assert_eq!(analysis.layers[1].count(ChangeKind::Removed), 1);
+
+        assert_eq!(analysis.size(), 27);
+        assert_eq!(analysis.wasted_size(), 15);
+        assert_eq!(analysis.wasted[0].path, "/var/cache/apt/a.deb");
+        assert_eq!(analysis.wasted[1].path, "/etc/motd");
+        assert!((analysis.efficiency() - 12.0 / 27.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_diff_layers() {
+        let base = (
+            "sha256:base".to_string(),
+            layer(&[("etc/motd", b"hello"), ("etc/hostname", b"base")]),
+        );
+        let old = (
+            "sha256:old".to_string(),
+            layer(&[("app/v1", b"one"), ("app/config", b"a=1")]),
+        );
+        let new = (
+            "sha256:new".to_string(),
+            layer(&[
+                ("app/v2", b"two"),
+                ("app/config", b"a=2"),
+                ("etc/.wh.motd", b""),
+            ]),
+        );
+
+        let diff = diff_layers(&[base.clone(), old], &[base, new]);
+        assert_eq!(diff.shared_layers, 1);
+        assert_eq!(diff.layers.len(), 2);
+        assert_eq!(diff.layers[0].digest, "sha256:new");
+        assert_eq!(
+            summary(&diff.layers[0]),
+            ["~ /app/config 3", "+ /app/v2 3", "- /etc/motd 5"]
+        );
+        // Files the second image just doesn't have belong to the layers
+        // that had them
+        assert_eq!(diff.layers[1].digest, "sha256:old");
+        assert_eq!(summary(&diff.layers[1]), ["- /app/v1 3"]);
+    }
+}
@@ -0,0 +1,618 @@
+//! Image archives
+//!
+//! Images are loaded without a registry from the archives `docker save` and
+//! OCI tools write: tarballs, optionally gzipped, or directories holding
+//! their unpacked contents. A `docker save` archive has a `manifest.json`
+//! listing each image's config, layers and tags; an OCI layout has an
+//! `index.json` of manifests whose blobs are under `blobs/`. A directory
+//! that is neither is a preload directory, whose archives are each loaded.
+//! Images are saved as OCI layout tarballs.
+
+use super::layer::decompress;
+use super::pull::{go_arch, ConfigBlob, ImageReference};
+use super::registry::{media_types, sha256_digest, Descriptor, ImageManifest, Platform};
+use super::store::{Image, ImageStore};
+use crate::error::{Result, RuneError};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Annotation naming an image's full reference, as containerd writes it
+const IMAGE_NAME_ANNOTATION: &str = "io.containerd.image.name";
+
+/// Annotation naming an image's reference, or only its tag
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// Extensions of the archives a preload directory's files are loaded from
+const ARCHIVE_EXTENSIONS: [&str; 3] = [".tar", ".tar.gz", ".tgz"];
+
+/// Load the images of an archive, an unpacked archive or a preload
+/// directory into a store. Archives of a preload directory that fail to
+/// load are skipped.
+pub fn load(store: &ImageStore, path: &Path) -> Result<Vec<Image>> {
+    if !path.is_dir() {
+        let staging = store
+            .storage_path()
+            .join(format!("load-{}", uuid::Uuid::new_v4()));
+        let result = unpack_archive(path, &staging).and_then(|_| load_unpacked(store, &staging));
+        let _ = fs::remove_dir_all(&staging);
+        return result;
+    }
+    if is_unpacked_archive(path) {
+        return load_unpacked(store, path);
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    let mut images = Vec::new();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let archive = if entry.is_dir() {
+            is_unpacked_archive(&entry)
+        } else {
+            ARCHIVE_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+        };
+        if !archive || name.starts_with('.') {
+            debug!("Skipping {}, which is no image archive", entry.display());
+            continue;
+        }
+        match load(store, &entry) {
+            Ok(loaded) => {
+                info!("Loaded {} image(s) from {}", loaded.len(), entry.display());
+                images.extend(loaded);
+            }
+            Err(e) => warn!("Failed to load images from {}: {}", entry.display(), e),
+        }
+    }
+    Ok(images)
+}
+
+fn is_unpacked_archive(dir: &Path) -> bool {
+    dir.join("manifest.json").is_file() || dir.join("index.json").is_file()
+}
+
+fn unpack_archive(path: &Path, dest: &Path) -> Result<()> {
+    let file = fs::File::open(path)
+        .map_err(|e| RuneError::Image(format!("Failed to open {}: {}", path.display(), e)))?;
+    fs::create_dir_all(dest)?;
+    tar::Archive::new(decompress(file)?)
+        .unpack(dest)
+        .map_err(|e| RuneError::Image(format!("Failed to unpack {}: {}", path.display(), e)))
+}
+
+/// Load an unpacked archive; `docker save` writes an `index.json` as well
+/// nowadays, but only its `manifest.json` has the images' tags
+fn load_unpacked(store: &ImageStore, dir: &Path) -> Result<Vec<Image>> {
+    if dir.join("manifest.json").is_file() {
+        load_docker(store, dir)
+    } else {
+        load_oci(store, dir)
+    }
+}
+
+/// An image of a `docker save` archive
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SavedImage {
+    config: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+fn load_docker(store: &ImageStore, dir: &Path) -> Result<Vec<Image>> {
+    let saved: Vec<SavedImage> = serde_json::from_slice(&read(&dir.join("manifest.json"))?)?;
+    saved
+        .into_iter()
+        .map(|saved| {
+            let config = read(&archive_path(dir, &saved.config)?)?;
+            let id = sha256_digest(&config);
+            let config: ConfigBlob = serde_json::from_slice(&config)?;
+            let mut layers = Vec::new();
+            let mut size = 0;
+            for layer in &saved.layers {
+                let (digest, layer_size) = import_layer(store, &archive_path(dir, layer)?, None)?;
+                layers.push(digest);
+                size += layer_size;
+            }
+            let image = Image {
+                id,
+                repo_tags: saved.repo_tags.unwrap_or_default(),
+                size,
+                virtual_size: size,
+                layers,
+                ..config.into_image()
+            };
+            store.store(image.clone())?;
+            Ok(image)
+        })
+        .collect()
+}
+
+/// OCI image index
+#[derive(Debug, Deserialize)]
+struct Index {
+    manifests: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+fn load_oci(store: &ImageStore, dir: &Path) -> Result<Vec<Image>> {
+    let index: Index = serde_json::from_slice(&read(&dir.join("index.json"))?)?;
+    index
+        .manifests
+        .iter()
+        .map(|entry| {
+            let (manifest, digest) = resolve_manifest(dir, entry)?;
+            let config = read_blob(dir, &manifest.config.digest)?;
+            let config: ConfigBlob = serde_json::from_slice(&config)?;
+            let mut size = 0;
+            for layer in &manifest.layers {
+                let path = blob_path(dir, &layer.digest)?;
+                size += import_layer(store, &path, Some(&layer.digest))?.1;
+            }
+            let reference = entry
+                .annotations
+                .get(IMAGE_NAME_ANNOTATION)
+                .or_else(|| {
+                    // A bare tag names no repository to tag
+                    entry
+                        .annotations
+                        .get(REF_NAME_ANNOTATION)
+                        .filter(|name| name.contains(['/', ':']))
+                })
+                .and_then(|name| ImageReference::parse(name).ok());
+            let image = Image {
+                id: manifest.config.digest.clone(),
+                repo_tags: reference
+                    .iter()
+                    .flat_map(|r| {
+                        r.tag
+                            .iter()
+                            .map(|tag| format!("{}:{}", r.familiar_name(), tag))
+                    })
+                    .collect(),
+                repo_digests: reference
+                    .iter()
+                    .map(|r| format!("{}@{}", r.familiar_name(), digest))
+                    .collect(),
+                size,
+                virtual_size: size,
+                layers: manifest.layers.iter().map(|l| l.digest.clone()).collect(),
+                ..config.into_image()
+            };
+            store.store(image.clone())?;
+            Ok(image)
+        })
+        .collect()
+}
+
+/// The manifest an index entry names, picking this platform's from nested
+/// indexes, and its digest
+fn resolve_manifest(dir: &Path, entry: &IndexEntry) -> Result<(ImageManifest, String)> {
+    let mut digest = entry.digest.clone();
+    let mut media_type = entry.media_type.clone();
+    loop {
+        let body = read_blob(dir, &digest)?;
+        if media_type != media_types::OCI_INDEX && media_type != media_types::MANIFEST_LIST_V2 {
+            return Ok((serde_json::from_slice(&body)?, digest));
+        }
+        let index: Index = serde_json::from_slice(&body)?;
+        let platform = index
+            .manifests
+            .into_iter()
+            .find(|m| {
+                m.platform
+                    .as_ref()
+                    .is_some_and(|p| p.os == "linux" && p.architecture == go_arch())
+            })
+            .ok_or_else(|| {
+                RuneError::Image(format!(
+                    "{} has no manifest for linux/{}",
+                    digest,
+                    go_arch()
+                ))
+            })?;
+        digest = platform.digest;
+        media_type = platform.media_type;
+    }
+}
+
+/// An image as OCI blobs: its config and manifest, and the descriptors of
+/// its layers, which are in the store
+pub(super) struct OciImage {
+    pub config: Vec<u8>,
+    pub manifest: Vec<u8>,
+    pub layers: Vec<Descriptor>,
+}
+
+/// Save an image as an OCI layout tarball, named by `name`
+pub fn save_oci<W: Write>(
+    store: &ImageStore,
+    image: &Image,
+    name: Option<&str>,
+    out: W,
+) -> Result<()> {
+    let OciImage {
+        config,
+        manifest,
+        layers,
+    } = oci_image(store, image)?;
+    let mut annotations = HashMap::new();
+    if let Some(name) = name {
+        let reference = ImageReference::parse(name)?;
+        annotations.insert(
+            IMAGE_NAME_ANNOTATION.to_string(),
+            format!(
+                "{}/{}:{}",
+                reference.registry,
+                reference.repository,
+                reference.tag.as_deref().unwrap_or("latest")
+            ),
+        );
+        if let Some(tag) = &reference.tag {
+            annotations.insert(REF_NAME_ANNOTATION.to_string(), tag.clone());
+        }
+    }
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": media_types::OCI_INDEX,
+        "manifests": [{
+            "mediaType": media_types::OCI_MANIFEST,
+            "digest": sha256_digest(&manifest),
+            "size": manifest.len(),
+            "annotations": annotations,
+        }],
+    });
+
+    let mut archive = tar::Builder::new(out);
+    let mut append = |path: &str, data: &[u8]| -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        archive.append_data(&mut header, path, data)?;
+        Ok(())
+    };
+    append("oci-layout", br#"{"imageLayoutVersion":"1.0.0"}"#)?;
+    append("index.json", &serde_json::to_vec(&index)?)?;
+    for blob in [&manifest, &config] {
+        append(&format!("blobs/sha256/{}", &sha256_digest(blob)[7..]), blob)?;
+    }
+    for layer in &layers {
+        let path = store.layer_path(&layer.digest);
+        archive.append_path_with_name(&path, format!("blobs/sha256/{}", &layer.digest[7..]))?;
+    }
+    archive.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// An image's config and manifest as OCI blobs
+pub(super) fn oci_image(store: &ImageStore, image: &Image) -> Result<OciImage> {
+    let mut layers = Vec::new();
+    let mut diff_ids = Vec::new();
+    for digest in &image.layers {
+        let path = store.layer_path(digest);
+        let mut file = fs::File::open(&path)
+            .map_err(|e| RuneError::Image(format!("Failed to open layer {}: {}", digest, e)))?;
+        let mut magic = [0u8; 2];
+        let gzipped = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+        layers.push(Descriptor {
+            media_type: match gzipped {
+                true => media_types::OCI_LAYER,
+                false => media_types::OCI_LAYER_TAR,
+            }
+            .to_string(),
+            digest: digest.clone(),
+            size: fs::metadata(&path)?.len(),
+            urls: Vec::new(),
+            annotations: HashMap::new(),
+        });
+        diff_ids.push(match gzipped {
+            true => diff_id(&path)?,
+            false => digest.clone(),
+        });
+    }
+
+    let run_config = &image.config;
+    let mut config = serde_json::json!({
+        "created": image.created,
+        "architecture": go_arch(),
+        "os": "linux",
+        "config": {},
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+    });
+    let fields = [
+        (
+            "User",
+            serde_json::json!(run_config.user),
+            run_config.user.is_empty(),
+        ),
+        (
+            "Env",
+            serde_json::json!(run_config.env),
+            run_config.env.is_empty(),
+        ),
+        (
+            "Entrypoint",
+            serde_json::json!(run_config.entrypoint),
+            run_config.entrypoint.is_empty(),
+        ),
+        (
+            "Cmd",
+            serde_json::json!(run_config.cmd),
+            run_config.cmd.is_empty(),
+        ),
+        (
+            "WorkingDir",
+            serde_json::json!(run_config.working_dir),
+            run_config.working_dir.is_empty(),
+        ),
+        (
+            "Labels",
+            serde_json::json!(run_config.labels),
+            run_config.labels.is_empty(),
+        ),
+        (
+            "ExposedPorts",
+            serde_json::json!(run_config.exposed_ports),
+            run_config.exposed_ports.is_empty(),
+        ),
+        (
+            "Volumes",
+            serde_json::json!(run_config.volumes),
+            run_config.volumes.is_empty(),
+        ),
+        (
+            "StopSignal",
+            serde_json::json!(run_config.stop_signal),
+            run_config.stop_signal.is_empty(),
+        ),
+    ];
+    for (key, value, empty) in fields {
+        if !empty {
+            config["config"][key] = value;
+        }
+    }
+    let config = serde_json::to_vec(&config)?;
+
+    let manifest = serde_json::to_vec(&ImageManifest {
+        schema_version: 2,
+        media_type: media_types::OCI_MANIFEST.to_string(),
+        config: Descriptor {
+            media_type: media_types::OCI_CONFIG.to_string(),
+            digest: sha256_digest(&config),
+            size: config.len() as u64,
+            urls: Vec::new(),
+            annotations: HashMap::new(),
+        },
+        layers: layers.clone(),
+        annotations: HashMap::new(),
+    })?;
+    Ok(OciImage {
+        config,
+        manifest,
+        layers,
+    })
+}
+
+/// Digest of a gzipped layer's uncompressed tarball
+fn diff_id(path: &Path) -> Result<String> {
+    let mut reader = decompress(fs::File::open(path)?)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Copy a layer into the store, checking its digest if one is expected;
+/// returns its digest and size
+fn import_layer(store: &ImageStore, path: &Path, expected: Option<&str>) -> Result<(String, u64)> {
+    let data = read(path)?;
+    let digest = sha256_digest(&data);
+    if expected.is_some_and(|expected| expected != digest) {
+        return Err(RuneError::Image(format!(
+            "{} does not match its digest",
+            path.display()
+        )));
+    }
+    let dest = store.layer_path(&digest);
+    if !dest.exists() {
+        fs::write(&dest, &data)?;
+    }
+    Ok((digest, data.len() as u64))
+}
+
+/// Read a blob of an OCI layout, checking its digest
+fn read_blob(dir: &Path, digest: &str) -> Result<Vec<u8>> {
+    let path = blob_path(dir, digest)?;
+    let data = read(&path)?;
+    if sha256_digest(&data) != digest {
+        return Err(RuneError::Image(format!(
+            "{} does not match its digest",
+            path.display()
+        )));
+    }
+    Ok(data)
+}
+
+fn blob_path(dir: &Path, digest: &str) -> Result<PathBuf> {
+    match digest.split_once(':') {
+        Some(("sha256", hex)) => archive_path(dir, &format!("blobs/sha256/{}", hex)),
+        _ => Err(RuneError::Image(format!("Unsupported digest {}", digest))),
+    }
+}
+
+/// Path of a file an archive names, which must stay inside the archive
+fn archive_path(dir: &Path, relative: &str) -> Result<PathBuf> {
+    let path = Path::new(relative);
+    if path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(RuneError::Image(format!(
+            "Archive path {} escapes the archive",
+            relative
+        )));
+    }
+    Ok(dir.join(path))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path)
+        .map_err(|e| RuneError::Image(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    const CONFIG: &[u8] = br#"{"architecture": "amd64", "os": "linux", "config": {"Cmd": ["sh"]}}"#;
+
+    #[test]
+    fn test_load_docker_save() {
+        let layer = tarball(&[("etc/motd", b"hello")]);
+        let archive = tarball(&[
+            (
+                "manifest.json",
+                br#"[{"Config": "config.json", "RepoTags": ["app:1.0"], "Layers": ["abc/layer.tar"]}]"#,
+            ),
+            ("config.json", CONFIG),
+            ("abc/layer.tar", &layer),
+        ]);
+        let dir = TempDir::new().unwrap();
+        let store = ImageStore::new(dir.path().join("images")).unwrap();
+        let preload = dir.path().join("preload");
+        fs::create_dir(&preload).unwrap();
+        fs::write(preload.join("app.tar"), archive).unwrap();
+        fs::write(preload.join("README"), "not an image").unwrap();
+        fs::write(preload.join("broken.tar"), "not a tarball").unwrap();
+
+        let images = load(&store, &preload).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].id, sha256_digest(CONFIG));
+        assert_eq!(images[0].config.cmd, ["sh"]);
+        assert_eq!(images[0].layers, [sha256_digest(&layer)]);
+        assert_eq!(store.get("app:1.0").unwrap().size, layer.len() as u64);
+        assert_eq!(
+            fs::read(store.layer_path(&images[0].layers[0])).unwrap(),
+            layer
+        );
+        // Staging directories are cleaned up
+        assert_eq!(fs::read_dir(store.storage_path()).unwrap().count(), 2);
+
+        let escaping = tarball(&[(
+            "manifest.json",
+            br#"[{"Config": "../config.json", "Layers": []}]"#,
+        )]);
+        fs::write(dir.path().join("escaping.tar"), escaping).unwrap();
+        assert!(load(&store, &dir.path().join("escaping.tar")).is_err());
+    }
+
+    #[test]
+    fn test_load_oci_layout() {
+        let layer = tarball(&[("bin/tool", b"#!/bin/sh")]);
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::OCI_MANIFEST,
+            "config": {"mediaType": media_types::OCI_CONFIG, "digest": sha256_digest(CONFIG), "size": CONFIG.len()},
+            "layers": [{"mediaType": media_types::OCI_LAYER, "digest": sha256_digest(&layer), "size": layer.len()}],
+        })
+        .to_string()
+        .into_bytes();
+        let platforms = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": media_types::OCI_MANIFEST,
+                "digest": sha256_digest(&manifest),
+                "size": manifest.len(),
+                "platform": {"os": "linux", "architecture": go_arch()},
+            }],
+        })
+        .to_string()
+        .into_bytes();
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": media_types::OCI_INDEX,
+                "digest": sha256_digest(&platforms),
+                "size": platforms.len(),
+                "annotations": {REF_NAME_ANNOTATION: "ghcr.io/org/tool:2.0"},
+            }],
+        })
+        .to_string();
+
+        let dir = TempDir::new().unwrap();
+        let layout = dir.path().join("tool");
+        fs::create_dir_all(layout.join("blobs/sha256")).unwrap();
+        fs::write(
+            layout.join("oci-layout"),
+            r#"{"imageLayoutVersion": "1.0.0"}"#,
+        )
+        .unwrap();
+        fs::write(layout.join("index.json"), index).unwrap();
+        for blob in [CONFIG, &layer, &manifest, &platforms] {
+            let digest = sha256_digest(blob);
+            fs::write(layout.join("blobs/sha256").join(&digest[7..]), blob).unwrap();
+        }
+        let store = ImageStore::new(dir.path().join("images")).unwrap();
+
+        let images = load(&store, &layout).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].repo_tags, ["ghcr.io/org/tool:2.0"]);
+        assert_eq!(
+            images[0].repo_digests,
+            [format!("ghcr.io/org/tool@{}", sha256_digest(&manifest))]
+        );
+        assert_eq!(
+            store.get("ghcr.io/org/tool:2.0").unwrap().id,
+            sha256_digest(CONFIG)
+        );
+
+        // Blobs that don't match their digest are refused
+        fs::write(
+            layout
+                .join("blobs/sha256")
+                .join(&sha256_digest(&layer)[7..]),
+            "tampered",
+        )
+        .unwrap();
+        assert!(load(&store, &layout).is_err());
+    }
+}
@@ -3,10 +3,21 @@
 //! Runefile is the default build file format for Rune, but Dockerfile
 //! syntax is also supported for Docker compatibility.
 
+use super::context::ContextSource;
+use super::output::BuildOutput;
+use super::progress::{BuildEvent, LayerSummary};
+use super::pull::ImageReference;
+use super::registry::sha256_digest;
+use super::stage::{canonical_json, stage_deps, Stage, StageRunner};
+use super::store::{Image, ImageStore};
 use crate::error::{Result, RuneError};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Instant;
 
 /// Default build file name
 pub const DEFAULT_BUILD_FILE: &str = "Runefile";
@@ -19,7 +30,10 @@ pub const DOCKERFILE_NAME: &str = "Dockerfile";
 pub struct BuildContext {
     /// Context directory
     pub context_dir: PathBuf,
-    /// Build file path
+    /// Remote source the context is fetched from instead
+    pub remote: Option<ContextSource>,
+    /// Build file path; for a remote context, relative to it, and found
+    /// in it if empty
     pub build_file: PathBuf,
     /// Build arguments
     pub build_args: HashMap<String, String>,
@@ -33,22 +47,24 @@ pub struct BuildContext {
     pub tags: Vec<String>,
     /// Labels for the built image
     pub labels: HashMap<String, String>,
+    /// Where the build is exported, besides the image store
+    pub outputs: Vec<BuildOutput>,
+    /// Most stages built at once
+    pub max_parallel: usize,
+    /// Additional contexts, by the names FROM and `COPY --from` use
+    pub named_contexts: HashMap<String, ContextSource>,
+    /// Images fetched before building, whose layers back the build cache
+    pub cache_from: Vec<String>,
 }
 
 impl BuildContext {
     /// Create a new build context
     pub fn new(context_dir: PathBuf) -> Self {
-        // Look for Runefile first, then Dockerfile
-        let build_file = if context_dir.join(DEFAULT_BUILD_FILE).exists() {
-            context_dir.join(DEFAULT_BUILD_FILE)
-        } else if context_dir.join(DOCKERFILE_NAME).exists() {
-            context_dir.join(DOCKERFILE_NAME)
-        } else {
-            context_dir.join(DEFAULT_BUILD_FILE)
-        };
+        let build_file = find_build_file(&context_dir);
 
         Self {
             context_dir,
+            remote: None,
             build_file,
             build_args: HashMap::new(),
             target: None,
@@ -56,6 +72,23 @@ impl BuildContext {
             pull: false,
             tags: Vec::new(),
             labels: HashMap::new(),
+            outputs: Vec::new(),
+            max_parallel: num_cpus::get(),
+            named_contexts: HashMap::new(),
+            cache_from: Vec::new(),
+        }
+    }
+
+    /// Create a build context from a local path, a git repository URL or
+    /// a tarball URL
+    pub fn from_source(source: &str) -> Self {
+        match ContextSource::parse(source) {
+            ContextSource::Local(path) => Self::new(path),
+            remote => Self {
+                remote: Some(remote),
+                build_file: PathBuf::new(),
+                ..Self::new(PathBuf::new())
+            },
         }
     }
 
@@ -88,6 +121,43 @@ impl BuildContext {
         self.labels.insert(key.to_string(), value.to_string());
         self
     }
+
+    /// Add output
+    pub fn output(mut self, output: BuildOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Set how many stages are built at once
+    pub fn max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = max_parallel;
+        self
+    }
+
+    /// Add a named context: a local path, a git repository or tarball
+    /// URL, or `docker-image://<reference>`
+    pub fn named_context(mut self, name: &str, source: &str) -> Self {
+        self.named_contexts
+            .insert(name.to_string(), ContextSource::parse(source));
+        self
+    }
+
+    /// Add an image to take cached layers from
+    pub fn cache_from(mut self, image: &str) -> Self {
+        self.cache_from.push(image.to_string());
+        self
+    }
+}
+
+/// Build file of a context directory: its Runefile, or else its Dockerfile
+fn find_build_file(context_dir: &Path) -> PathBuf {
+    if context_dir.join(DEFAULT_BUILD_FILE).exists() {
+        context_dir.join(DEFAULT_BUILD_FILE)
+    } else if context_dir.join(DOCKERFILE_NAME).exists() {
+        context_dir.join(DOCKERFILE_NAME)
+    } else {
+        context_dir.join(DEFAULT_BUILD_FILE)
+    }
 }
 
 /// Parsed build instruction
@@ -151,6 +221,97 @@ pub enum BuildInstruction {
     Onbuild { instruction: Box<BuildInstruction> },
 }
 
+impl fmt::Display for BuildInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = |values: &Vec<String>| serde_json::to_string(values).unwrap_or_default();
+        let flag = |name: &str, value: &Option<String>| match value {
+            Some(value) => format!("--{}={} ", name, value),
+            None => String::new(),
+        };
+        match self {
+            BuildInstruction::From { image, tag, alias } => {
+                write!(f, "FROM {}", image)?;
+                if let Some(tag) = tag {
+                    write!(f, ":{}", tag)?;
+                }
+                if let Some(alias) = alias {
+                    write!(f, " AS {}", alias)?;
+                }
+                Ok(())
+            }
+            BuildInstruction::Run { command, .. } => write!(f, "RUN {}", command),
+            BuildInstruction::Copy {
+                src,
+                dest,
+                from,
+                chown,
+            } => write!(
+                f,
+                "COPY {}{}{} {}",
+                flag("from", from),
+                flag("chown", chown),
+                src.join(" "),
+                dest
+            ),
+            BuildInstruction::Add { src, dest, chown } => {
+                write!(f, "ADD {}{} {}", flag("chown", chown), src.join(" "), dest)
+            }
+            BuildInstruction::Cmd { command, shell } => match shell {
+                true => write!(f, "CMD {}", command.join(" ")),
+                false => write!(f, "CMD {}", json(command)),
+            },
+            BuildInstruction::Entrypoint { command, shell } => match shell {
+                true => write!(f, "ENTRYPOINT {}", command.join(" ")),
+                false => write!(f, "ENTRYPOINT {}", json(command)),
+            },
+            BuildInstruction::Env { key, value } => write!(f, "ENV {}={}", key, value),
+            BuildInstruction::Arg { name, default } => match default {
+                Some(default) => write!(f, "ARG {}={}", name, default),
+                None => write!(f, "ARG {}", name),
+            },
+            BuildInstruction::Workdir { path } => write!(f, "WORKDIR {}", path),
+            BuildInstruction::User { user, group } => match group {
+                Some(group) => write!(f, "USER {}:{}", user, group),
+                None => write!(f, "USER {}", user),
+            },
+            BuildInstruction::Expose { port, protocol } => {
+                write!(f, "EXPOSE {}/{}", port, protocol)
+            }
+            BuildInstruction::Volume { paths } => write!(f, "VOLUME {}", json(paths)),
+            BuildInstruction::Label { labels } => {
+                let mut labels: Vec<_> = labels.iter().collect();
+                labels.sort();
+                write!(f, "LABEL")?;
+                for (key, value) in labels {
+                    write!(f, " {}={}", key, value)?;
+                }
+                Ok(())
+            }
+            BuildInstruction::Healthcheck {
+                cmd,
+                interval,
+                timeout,
+                start_period,
+                retries,
+            } => match cmd {
+                Some(cmd) => write!(
+                    f,
+                    "HEALTHCHECK {}{}{}{}CMD {}",
+                    flag("interval", interval),
+                    flag("timeout", timeout),
+                    flag("start-period", start_period),
+                    flag("retries", &retries.map(|retries| retries.to_string())),
+                    cmd
+                ),
+                None => write!(f, "HEALTHCHECK NONE"),
+            },
+            BuildInstruction::Stopsignal { signal } => write!(f, "STOPSIGNAL {}", signal),
+            BuildInstruction::Shell { shell } => write!(f, "SHELL {}", json(shell)),
+            BuildInstruction::Onbuild { instruction } => write!(f, "ONBUILD {}", instruction),
+        }
+    }
+}
+
 /// Parsed build file (Runefile or Dockerfile)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedBuildFile {
@@ -175,12 +336,32 @@ pub struct BuildStage {
 pub struct ImageBuilder {
     /// Build context
     context: BuildContext,
+    /// Store of base images, built layers and the built image
+    store: Option<Arc<ImageStore>>,
+    /// Where build progress is reported
+    events: Option<Sender<BuildEvent>>,
 }
 
 impl ImageBuilder {
     /// Create a new image builder
     pub fn new(context: BuildContext) -> Self {
-        Self { context }
+        Self {
+            context,
+            store: None,
+            events: None,
+        }
+    }
+
+    /// Set the image store to build with
+    pub fn with_store(mut self, store: Arc<ImageStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Report build progress on a channel
+    pub fn with_events(mut self, events: Sender<BuildEvent>) -> Self {
+        self.events = Some(events);
+        self
     }
 
     /// Parse a build file (Runefile or Dockerfile)
@@ -579,28 +760,193 @@ impl ImageBuilder {
         Ok(BuildInstruction::Shell { shell })
     }
 
-    /// Build an image from the build context
+    /// Build an image from the build context, returning its ID
     pub async fn build(&self) -> Result<String> {
-        // Parse the build file
-        let parsed = Self::parse_build_file(&self.context.build_file)?;
-
-        // For now, return a placeholder image ID
-        // In a full implementation, this would:
-        // 1. Pull base images
-        // 2. Execute each instruction
-        // 3. Create image layers
-        // 4. Store the final image
-
-        let image_id = uuid::Uuid::new_v4().to_string().replace("-", "")[..12].to_string();
-
-        tracing::info!(
-            "Built image {} from {} with {} stages",
-            image_id,
-            self.context.build_file.display(),
-            parsed.stages.len()
-        );
+        let started = Instant::now();
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| RuneError::Build("No image store to build with".to_string()))?;
+        let workdir = BuildDir::create(
+            store
+                .storage_path()
+                .join(format!("build-{}", uuid::Uuid::new_v4().simple())),
+        )?;
+        let mut runner = StageRunner::new(
+            store.clone(),
+            Arc::new(self.context.clone()),
+            self.events.clone(),
+        )?;
+        runner.load_contexts(&workdir.0).await?;
+        let runner = Arc::new(runner);
+
+        let build_file = match &self.context.remote {
+            Some(_) if self.context.build_file.as_os_str().is_empty() => {
+                find_build_file(runner.context_dir())
+            }
+            Some(_) => runner.context_dir().join(&self.context.build_file),
+            None => self.context.build_file.clone(),
+        };
+        let parsed = Self::parse_build_file(&build_file)?;
+        let target = match &self.context.target {
+            Some(target) => parsed
+                .stages
+                .iter()
+                .position(|stage| stage.name.as_deref() == Some(target.as_str()))
+                .ok_or_else(|| RuneError::Build(format!("Target stage {} not found", target)))?,
+            None => parsed.stages.len() - 1,
+        };
+
+        let stage = self
+            .run_stages(&runner, &parsed.stages, target, &workdir.0)
+            .await?;
+
+        let mut config = stage.config.clone();
+        config.labels.extend(self.context.labels.clone());
+        let id = sha256_digest(canonical_json(&(&config, &stage.layers))?.as_bytes());
+        let tags = self
+            .context
+            .tags
+            .iter()
+            .map(|tag| Ok(ImageReference::parse(tag)?.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let layers = stage
+            .layers
+            .iter()
+            .zip(stage.history.iter().cloned())
+            .map(|(digest, created_by)| {
+                let size = std::fs::metadata(store.layer_path(digest))?.len();
+                Ok(LayerSummary {
+                    digest: digest.clone(),
+                    size,
+                    created_by,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let size = layers.iter().map(|layer| layer.size).sum();
+        let image = Image {
+            id: id.clone(),
+            repo_tags: tags.clone(),
+            config,
+            size,
+            virtual_size: size,
+            layers: stage.layers.clone(),
+            ..Default::default()
+        };
+        store.store(image.clone())?;
+
+        for output in &self.context.outputs {
+            let step = runner.start(format!("exporting to {}", output));
+            let started = Instant::now();
+            let result = output.export(store, &image, &stage.rootfs);
+            runner.finish(step, started, result.map(|()| ((), false)))?;
+        }
+
+        tracing::debug!("Built image {} from {}", id, build_file.display());
+        if let Some(events) = &self.events {
+            let _ = events.send(BuildEvent::Finished {
+                image_id: id.clone(),
+                tags,
+                layers,
+                seconds: started.elapsed().as_secs_f64(),
+            });
+        }
+        Ok(id)
+    }
+
+    /// Run the stages the target stage depends on, at most
+    /// `max_parallel` at once, each as soon as the stages it refers to
+    /// are built; returns the target stage
+    async fn run_stages(
+        &self,
+        runner: &Arc<StageRunner>,
+        stages: &[BuildStage],
+        target: usize,
+        workdir: &Path,
+    ) -> Result<Arc<Stage>> {
+        let deps: Vec<Vec<(String, usize)>> = (0..stages.len())
+            .map(|index| stage_deps(stages, index))
+            .collect();
+        let mut needed = BTreeSet::from([target]);
+        let mut unvisited = vec![target];
+        while let Some(index) = unvisited.pop() {
+            for (_, dep) in &deps[index] {
+                if needed.insert(*dep) {
+                    unvisited.push(*dep);
+                }
+            }
+        }
 
-        Ok(image_id)
+        let labelled = needed.len() > 1;
+        let mut pending: Vec<usize> = needed.into_iter().collect();
+        let mut built: HashMap<usize, Arc<Stage>> = HashMap::new();
+        let mut running = tokio::task::JoinSet::new();
+        loop {
+            while running.len() < self.context.max_parallel.max(1) {
+                let Some(position) = pending
+                    .iter()
+                    .position(|index| deps[*index].iter().all(|(_, dep)| built.contains_key(dep)))
+                else {
+                    break;
+                };
+                let index = pending.remove(position);
+                let stage = stages[index].clone();
+                let label = labelled.then(|| {
+                    stage
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("stage-{}", index))
+                });
+                let stage_deps = deps[index]
+                    .iter()
+                    .map(|(reference, dep)| (reference.clone(), built[dep].clone()))
+                    .collect();
+                let run =
+                    runner
+                        .clone()
+                        .run(stage, label, stage_deps, workdir.join(index.to_string()));
+                running.spawn(async move { (index, run.await) });
+            }
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            let (index, result) =
+                joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            match result {
+                Ok(stage) => built.insert(index, Arc::new(stage)),
+                Err(e) => {
+                    // Dropping the other stages' tasks would leave their RUN
+                    // commands working in the build directory as it is
+                    // removed, so they are killed and the stages awaited
+                    runner.cancel();
+                    while let Some(joined) = running.join_next().await {
+                        if let Err(e) = joined {
+                            std::panic::resume_unwind(e.into_panic());
+                        }
+                    }
+                    return Err(e);
+                }
+            };
+        }
+        Ok(built.remove(&target).expect("the target stage was built"))
+    }
+}
+
+/// Directory a build works in, removed when the build is done
+struct BuildDir(PathBuf);
+
+impl BuildDir {
+    fn create(path: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!("Failed to remove {}: {}", self.0.display(), e);
+        }
     }
 }
 
@@ -648,6 +994,245 @@ CMD ["myapp"]
         assert_eq!(parsed.stages[1].base_image, "debian");
     }
 
+    #[tokio::test]
+    async fn test_build_progress_and_cache() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let context_dir = dir.path().join("context");
+        std::fs::create_dir_all(context_dir.join("app")).unwrap();
+        std::fs::write(context_dir.join("app/run"), "#!/bin/sh\n").unwrap();
+        std::fs::write(context_dir.join("notes.txt"), "notes").unwrap();
+        std::fs::write(
+            context_dir.join(DEFAULT_BUILD_FILE),
+            r#"
+FROM scratch AS files
+COPY app/ /srv/app/
+ENV GREETING=hello
+COPY --chown=1000:1000 notes.txt ${GREETING}.txt
+
+FROM scratch
+WORKDIR /opt
+COPY --from=files /srv/app ./app
+CMD ["/opt/app/run"]
+"#,
+        )
+        .unwrap();
+        let store = Arc::new(ImageStore::new(dir.path().join("images")).unwrap());
+
+        let build = || async {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let context = BuildContext::new(context_dir.clone())
+                .tag("app")
+                .label("version", "1");
+            let id = ImageBuilder::new(context)
+                .with_store(store.clone())
+                .with_events(tx)
+                .build()
+                .await
+                .unwrap();
+            (id, rx.into_iter().collect::<Vec<_>>())
+        };
+
+        let (id, events) = build().await;
+        let names: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                BuildEvent::StepStarted { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "[files 1/3] FROM scratch",
+                "[files 2/3] COPY app/ /srv/app/",
+                "[files 3/3] COPY --chown=1000:1000 notes.txt ${GREETING}.txt",
+                "[stage-1 1/2] FROM scratch",
+                "[stage-1 2/2] COPY --from=files /srv/app ./app",
+            ]
+        );
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, BuildEvent::StepCached { .. })));
+        let Some(BuildEvent::Finished { layers, tags, .. }) = events.last() else {
+            panic!("no summary: {:?}", events.last());
+        };
+        assert_eq!(tags, &["app:latest"]);
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].created_by, "COPY --from=files /srv/app ./app");
+
+        let image = store.get("app:latest").unwrap();
+        assert_eq!(image.id, id);
+        assert_eq!(image.config.working_dir, "/opt");
+        assert_eq!(image.config.cmd, ["/opt/app/run"]);
+        assert_eq!(image.config.labels["version"], "1");
+        let rootfs = dir.path().join("rootfs");
+        store.unpack(&id, &rootfs, None).unwrap();
+        assert!(rootfs.join("opt/app/run").is_file());
+
+        // Nothing changed, so every step but FROM comes from the cache
+        let (again, events) = build().await;
+        assert_eq!(again, id);
+        let cached: Vec<usize> = events
+            .iter()
+            .filter_map(|event| match event {
+                BuildEvent::StepCached { step } => Some(*step),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cached, [2, 3, 5]);
+        // The build's work directory is gone
+        assert!(!std::fs::read_dir(store.storage_path())
+            .unwrap()
+            .any(|entry| {
+                let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+                name.starts_with("build-") && name != "build-cache"
+            }));
+    }
+
+    #[tokio::test]
+    async fn test_build_needed_stages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let context_dir = dir.path().join("context");
+        std::fs::create_dir_all(&context_dir).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(context_dir.join(name), name).unwrap();
+        }
+        std::fs::write(
+            context_dir.join(DEFAULT_BUILD_FILE),
+            r#"
+FROM scratch AS a
+COPY a.txt /
+
+FROM scratch AS unused
+COPY c.txt /
+
+FROM scratch AS b
+COPY b.txt /
+
+FROM b
+COPY --from=0 /a.txt /
+"#,
+        )
+        .unwrap();
+        let store = Arc::new(ImageStore::new(dir.path().join("images")).unwrap());
+
+        let mut ids = Vec::new();
+        for max_parallel in [1, 4] {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let context = BuildContext::new(context_dir.clone()).max_parallel(max_parallel);
+            let id = ImageBuilder::new(context)
+                .with_store(store.clone())
+                .with_events(tx)
+                .build()
+                .await
+                .unwrap();
+            let mut names: Vec<String> = rx
+                .into_iter()
+                .filter_map(|event| match event {
+                    BuildEvent::StepStarted { name, .. } => Some(name),
+                    _ => None,
+                })
+                .collect();
+            // The final stage starts once both stages it uses are built
+            assert!(names[4].starts_with("[stage-3 1/2] FROM b"));
+            names.sort();
+            assert_eq!(
+                names,
+                [
+                    "[a 1/2] FROM scratch",
+                    "[a 2/2] COPY a.txt /",
+                    "[b 1/2] FROM scratch",
+                    "[b 2/2] COPY b.txt /",
+                    "[stage-3 1/2] FROM b",
+                    "[stage-3 2/2] COPY --from=0 /a.txt /",
+                ]
+            );
+            ids.push(id);
+        }
+        assert_eq!(ids[0], ids[1]);
+        let rootfs = dir.path().join("rootfs");
+        store.unpack(&ids[0], &rootfs, None).unwrap();
+        assert!(rootfs.join("a.txt").is_file() && rootfs.join("b.txt").is_file());
+        assert!(!rootfs.join("c.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_build_remote_and_named_contexts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("repo.git");
+        let app = repo.join("app");
+        std::fs::create_dir_all(app.join("logs")).unwrap();
+        std::fs::write(app.join("main.sh"), "main").unwrap();
+        std::fs::write(app.join("debug.log"), "debug").unwrap();
+        std::fs::write(app.join("logs/keep.txt"), "keep").unwrap();
+        std::fs::write(app.join(".runeignore"), "*.log\nlogs\n!logs/keep.txt\n").unwrap();
+        std::fs::write(
+            app.join(DEFAULT_BUILD_FILE),
+            "FROM base\nCOPY . /app/\nCOPY --from=assets logo.svg /app/\n",
+        )
+        .unwrap();
+        for args in [
+            &["init", "--quiet"][..],
+            &["add", "."],
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-qm",
+                "app",
+            ],
+            &["branch", "release"],
+        ] {
+            let status = std::process::Command::new("git")
+                .args(args)
+                .current_dir(&repo)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?}", args);
+        }
+
+        let base = dir.path().join("base");
+        std::fs::create_dir_all(base.join("etc")).unwrap();
+        std::fs::write(base.join("etc/os-release"), "base").unwrap();
+        let assets = dir.path().join("assets");
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("logo.svg"), "<svg/>").unwrap();
+
+        let context = BuildContext::from_source(&format!("file://{}#release:app", repo.display()))
+            .named_context("base", &base.display().to_string())
+            .named_context("assets", &assets.display().to_string());
+        let store = Arc::new(ImageStore::new(dir.path().join("images")).unwrap());
+        let id = ImageBuilder::new(context)
+            .with_store(store.clone())
+            .build()
+            .await
+            .unwrap();
+
+        let rootfs = dir.path().join("rootfs");
+        store.unpack(&id, &rootfs, None).unwrap();
+        assert!(rootfs.join("etc/os-release").is_file());
+        assert!(rootfs.join("app/main.sh").is_file());
+        assert!(rootfs.join("app/logo.svg").is_file());
+        assert!(rootfs.join("app/logs/keep.txt").is_file());
+        assert!(!rootfs.join("app/debug.log").exists());
+
+        // Naming an ignored file fails
+        std::fs::write(
+            assets.join(DEFAULT_BUILD_FILE),
+            "FROM scratch\nCOPY debug.log /\n",
+        )
+        .unwrap();
+        std::fs::write(assets.join("debug.log"), "debug").unwrap();
+        std::fs::write(assets.join(".runeignore"), "*.log\n").unwrap();
+        let result = ImageBuilder::new(BuildContext::new(assets))
+            .with_store(store)
+            .build()
+            .await;
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_default_build_file_name() {
         assert_eq!(DEFAULT_BUILD_FILE, "Runefile");
@@ -0,0 +1,493 @@
+//! Build contexts
+//!
+//! A build context is a local directory, a git repository or a tarball
+//! URL. Remote contexts are fetched into the build's work directory: a git
+//! repository is cloned at the branch, tag or commit after `#`, and a
+//! `:subdir` after that picks the directory to build in, as with
+//! `docker build`. Besides the main context, a build can have named
+//! contexts that `FROM` and `COPY --from` refer to; those can also be
+//! images, given as `docker-image://<reference>`.
+//!
+//! Files matched by a context's `.runeignore`, or `.dockerignore` if it has
+//! none, are left out of what is copied from it.
+
+use crate::error::{Result, RuneError};
+use regex::Regex;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use tokio::process::Command;
+
+/// File listing what a context leaves out
+pub const IGNORE_FILE: &str = ".runeignore";
+
+/// Ignore file read when a context has no `.runeignore`
+const DOCKER_IGNORE_FILE: &str = ".dockerignore";
+
+/// Prefix of a named context that is an image
+const IMAGE_PREFIX: &str = "docker-image://";
+
+/// Where a build context comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextSource {
+    /// Local directory
+    Local(PathBuf),
+    /// Git repository, at a branch, tag or commit, built in a subdirectory
+    Git {
+        url: String,
+        reference: Option<String>,
+        subdir: Option<String>,
+    },
+    /// Tarball, optionally gzipped, to download
+    Tarball(String),
+    /// Image, for named contexts
+    Image(String),
+}
+
+impl ContextSource {
+    /// Parse a context given on the command line
+    pub fn parse(source: &str) -> Self {
+        if let Some(reference) = source.strip_prefix(IMAGE_PREFIX) {
+            return ContextSource::Image(reference.to_string());
+        }
+        let (url, fragment) = match source.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (source, None),
+        };
+        let remote = ["http://", "https://", "ssh://", "file://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme));
+        let git = url.starts_with("git://")
+            || url.starts_with("git@")
+            || url.starts_with("github.com/")
+            || (remote && url.ends_with(".git"));
+        if git {
+            let (reference, subdir) = match fragment.map(|f| f.split_once(':').unwrap_or((f, ""))) {
+                Some((reference, subdir)) => (
+                    Some(reference.to_string()).filter(|r| !r.is_empty()),
+                    Some(subdir.to_string()).filter(|s| !s.is_empty()),
+                ),
+                None => (None, None),
+            };
+            let url = match url.strip_prefix("github.com/") {
+                Some(path) => format!("https://github.com/{}", path),
+                None => url.to_string(),
+            };
+            return ContextSource::Git {
+                url,
+                reference,
+                subdir,
+            };
+        }
+        if source.starts_with("http://") || source.starts_with("https://") {
+            return ContextSource::Tarball(source.to_string());
+        }
+        ContextSource::Local(PathBuf::from(source))
+    }
+
+    /// Whether the context is fetched rather than read where it is
+    pub fn is_remote(&self) -> bool {
+        matches!(self, ContextSource::Git { .. } | ContextSource::Tarball(_))
+    }
+
+    /// Fetch a context into `dest`, returning the directory to build in;
+    /// a local context is used where it is
+    pub async fn fetch(&self, dest: &Path) -> Result<PathBuf> {
+        match self {
+            ContextSource::Local(path) => Ok(path.clone()),
+            ContextSource::Git {
+                url,
+                reference,
+                subdir,
+            } => {
+                clone(url, reference.as_deref(), dest).await?;
+                match subdir {
+                    Some(subdir) => {
+                        let dir = dest.join(checked(subdir)?);
+                        if !dir.is_dir() {
+                            return Err(RuneError::Build(format!(
+                                "{} has no directory {}",
+                                url, subdir
+                            )));
+                        }
+                        // The repository's symlinks may lead anywhere
+                        if !dir.canonicalize()?.starts_with(dest.canonicalize()?) {
+                            return Err(RuneError::Build(format!(
+                                "{} leaves the repository",
+                                subdir
+                            )));
+                        }
+                        Ok(dir)
+                    }
+                    None => Ok(dest.to_path_buf()),
+                }
+            }
+            ContextSource::Tarball(url) => {
+                let data = download(url).await?;
+                std::fs::create_dir_all(dest)?;
+                let mut archive = tar::Archive::new(super::layer::decompress(data.as_slice())?);
+                for entry in archive.entries()? {
+                    // unpack_in leaves out entries escaping dest
+                    entry?.unpack_in(dest)?;
+                }
+                Ok(dest.to_path_buf())
+            }
+            ContextSource::Image(reference) => Err(RuneError::Build(format!(
+                "Image {} is no build context",
+                reference
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ContextSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextSource::Local(path) => write!(f, "{}", path.display()),
+            ContextSource::Git {
+                url,
+                reference,
+                subdir,
+            } => {
+                write!(f, "{}", url)?;
+                if reference.is_some() || subdir.is_some() {
+                    write!(f, "#{}", reference.as_deref().unwrap_or(""))?;
+                }
+                match subdir {
+                    Some(subdir) => write!(f, ":{}", subdir),
+                    None => Ok(()),
+                }
+            }
+            ContextSource::Tarball(url) => write!(f, "{}", url),
+            ContextSource::Image(reference) => write!(f, "{}{}", IMAGE_PREFIX, reference),
+        }
+    }
+}
+
+/// Shallowly clone a repository at a branch, tag or commit, with its
+/// submodules
+async fn clone(url: &str, reference: Option<&str>, dest: &Path) -> Result<()> {
+    let reference = reference.unwrap_or("HEAD");
+    // Neither may pass for an option, such as --upload-pack
+    for arg in [url, reference] {
+        if arg.starts_with('-') {
+            return Err(RuneError::Build(format!("Invalid git context {}", arg)));
+        }
+    }
+    std::fs::create_dir_all(dest)?;
+    let dest = dest.to_string_lossy();
+    let commands: [&[&str]; 5] = [
+        &["init", "--quiet", "--", &dest],
+        &["-C", &dest, "remote", "add", "--", "origin", url],
+        &[
+            "-C", &dest, "fetch", "--quiet", "--depth", "1", "--", "origin", reference,
+        ],
+        &["-C", &dest, "checkout", "--quiet", "FETCH_HEAD"],
+        &[
+            "-C",
+            &dest,
+            "submodule",
+            "update",
+            "--quiet",
+            "--init",
+            "--recursive",
+            "--depth",
+            "1",
+        ],
+    ];
+    for args in commands {
+        let output = Command::new("git")
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .await
+            .map_err(|e| RuneError::Build(format!("Failed to run git: {}", e)))?;
+        if !output.status.success() {
+            return Err(RuneError::Build(format!(
+                "Failed to fetch {} at {}: {}",
+                url,
+                reference,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Download a file
+pub(super) async fn download(url: &str) -> Result<Vec<u8>> {
+    let failed = |e: reqwest::Error| RuneError::Build(format!("Failed to download {}: {}", url, e));
+    let response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(failed)?;
+    Ok(response.bytes().await.map_err(failed)?.to_vec())
+}
+
+/// A relative path that stays inside the directory it is relative to
+fn checked(path: &str) -> Result<&Path> {
+    let checked = Path::new(path);
+    match checked
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        true => Ok(checked),
+        false => Err(RuneError::Build(format!("{} leaves the context", path))),
+    }
+}
+
+/// A directory files are copied from, leaving out what its ignore file
+/// matches
+#[derive(Debug, Clone)]
+pub struct ContextDir {
+    /// The directory
+    pub path: PathBuf,
+    /// What is left out
+    pub ignore: Ignore,
+}
+
+impl ContextDir {
+    /// A directory with nothing left out
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            ignore: Ignore::default(),
+        }
+    }
+
+    /// A context directory, leaving out what its ignore file matches
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let ignore = [IGNORE_FILE, DOCKER_IGNORE_FILE]
+            .iter()
+            .map(|name| path.join(name))
+            .find(|file| file.is_file())
+            .map_or(Ok(Ignore::default()), |file| {
+                Ignore::parse(&std::fs::read_to_string(file)?)
+            })?;
+        Ok(Self { path, ignore })
+    }
+
+    /// Whether a path under the directory is left out
+    pub fn excludes(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.path)
+            .is_ok_and(|relative| self.ignore.matches(relative))
+    }
+}
+
+/// Patterns of an ignore file: globs where `**` matches any number of
+/// directories, matching a path or any directory it is in. A pattern
+/// starting with `!` brings back what earlier ones left out; the last
+/// pattern matching a path decides.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    /// Each pattern, and whether it is an exception
+    patterns: Vec<(Regex, bool)>,
+}
+
+impl Ignore {
+    /// Parse an ignore file
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut patterns = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, exception) = match line.strip_prefix('!') {
+                Some(pattern) => (pattern.trim(), true),
+                None => (line, false),
+            };
+            let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+            let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+            if pattern.is_empty() {
+                continue;
+            }
+            let regex = Regex::new(&glob_regex(pattern)).map_err(|e| {
+                RuneError::InvalidConfig(format!("Invalid ignore pattern {}: {}", line, e))
+            })?;
+            patterns.push((regex, exception));
+        }
+        Ok(Self { patterns })
+    }
+
+    /// Whether a path relative to the context is left out
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        // The path and each directory it is in
+        let prefixes: Vec<&str> = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .chain(std::iter::once(path.as_str()))
+            .collect();
+        let mut ignored = false;
+        for (pattern, exception) in &self.patterns {
+            if prefixes.iter().any(|prefix| pattern.is_match(prefix)) {
+                ignored = !exception;
+            }
+        }
+        ignored
+    }
+}
+
+/// Regex matching the paths a glob does: `*` and `?` within a path
+/// component, `**` across any number of them, and `[...]` classes
+pub(super) fn glob_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.next_if_eq(&'/').is_some() {
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.next_if(|c| *c == '!' || *c == '^').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_context_source() {
+        assert_eq!(
+            ContextSource::parse("https://github.com/org/repo.git#v1.2:docker/app"),
+            ContextSource::Git {
+                url: "https://github.com/org/repo.git".to_string(),
+                reference: Some("v1.2".to_string()),
+                subdir: Some("docker/app".to_string()),
+            }
+        );
+        assert_eq!(
+            ContextSource::parse("github.com/org/repo#:sub"),
+            ContextSource::Git {
+                url: "https://github.com/org/repo".to_string(),
+                reference: None,
+                subdir: Some("sub".to_string()),
+            }
+        );
+        assert_eq!(
+            ContextSource::parse("https://example.com/context.tar.gz"),
+            ContextSource::Tarball("https://example.com/context.tar.gz".to_string())
+        );
+        assert_eq!(
+            ContextSource::parse("docker-image://alpine:3.19"),
+            ContextSource::Image("alpine:3.19".to_string())
+        );
+        let source = "https://example.com/app.git#main:web";
+        assert_eq!(ContextSource::parse(source).to_string(), source);
+        assert_eq!(
+            ContextSource::parse("./app"),
+            ContextSource::Local(PathBuf::from("./app"))
+        );
+    }
+
+    #[test]
+    fn test_ignore() {
+        let ignore = Ignore::parse(
+            "# build output\n/target\n*.log\n**/node_modules\ndocs/**/*.md\n!docs/README.md\n",
+        )
+        .unwrap();
+        for ignored in [
+            "target",
+            "target/debug/app",
+            "server.log",
+            "web/node_modules/x/index.js",
+            "docs/guide/intro.md",
+            "docs/intro.md",
+        ] {
+            assert!(ignore.matches(Path::new(ignored)), "{}", ignored);
+        }
+        for kept in [
+            "src/target.rs",
+            "logs/app.txt",
+            "sub/server.log",
+            "docs/README.md",
+        ] {
+            assert!(!ignore.matches(Path::new(kept)), "{}", kept);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_git_context() {
+        let run = |args: &[&str], dir: &Path| {
+            let status = std::process::Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .env("GIT_AUTHOR_NAME", "test")
+                .env("GIT_AUTHOR_EMAIL", "test@example.com")
+                .env("GIT_COMMITTER_NAME", "test")
+                .env("GIT_COMMITTER_EMAIL", "test@example.com")
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?}", args);
+        };
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("repo.git");
+        std::fs::create_dir_all(repo.join("app")).unwrap();
+        std::fs::write(repo.join("app/Runefile"), "FROM scratch\n").unwrap();
+        run(&["init", "--quiet", "--initial-branch", "main"], &repo);
+        run(&["add", "."], &repo);
+        run(&["commit", "--quiet", "-m", "app"], &repo);
+        run(&["tag", "v1"], &repo);
+
+        let source = ContextSource::parse(&format!("file://{}#v1:app", repo.display()));
+        assert!(source.is_remote());
+        let fetched = source.fetch(&dir.path().join("clone")).await.unwrap();
+        assert_eq!(fetched, dir.path().join("clone/app"));
+        assert!(fetched.join("Runefile").is_file());
+
+        let missing = ContextSource::parse(&format!("file://{}#v1:nope", repo.display()));
+        assert!(missing.fetch(&dir.path().join("other")).await.is_err());
+
+        // Nor symlinks nor options get out of the clone
+        std::os::unix::fs::symlink(dir.path(), repo.join("outside")).unwrap();
+        run(&["add", "."], &repo);
+        run(&["commit", "--quiet", "-m", "outside"], &repo);
+        run(&["tag", "v2"], &repo);
+        let escaping = ContextSource::parse(&format!("file://{}#v2:outside", repo.display()));
+        assert!(escaping.fetch(&dir.path().join("escaping")).await.is_err());
+        let option = ContextSource::Git {
+            url: format!("file://{}", repo.display()),
+            reference: Some("--upload-pack=touch pwned".to_string()),
+            subdir: None,
+        };
+        assert!(option.fetch(&dir.path().join("option")).await.is_err());
+        assert!(!dir.path().join("option").exists());
+    }
+}
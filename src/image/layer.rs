@@ -0,0 +1,170 @@
+//! Image layer unpacking
+//!
+//! Layers are tar archives, optionally gzipped, applied on top of each
+//! other. OCI whiteout entries delete what lower layers put there. When the
+//! daemon remaps user namespaces, every unpacked file is chowned into the
+//! remapped range so the container's root owns what it expects to own.
+
+use crate::error::{Result, RuneError};
+use crate::runtime::userns::UsernsRemap;
+use flate2::read::GzDecoder;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path};
+
+/// Prefix of a whiteout entry, which deletes the file it names
+pub(crate) const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whiteout entry that empties its directory
+pub(crate) const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Unpack a layer into a root filesystem
+pub fn unpack_layer<R: Read>(reader: R, dest: &Path, remap: Option<&UsernsRemap>) -> Result<()> {
+    std::fs::create_dir_all(dest)?;
+    let mut archive = tar::Archive::new(decompress(reader)?);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path
+            .components()
+            .any(|component| matches!(component, Component::ParentDir))
+        {
+            return Err(RuneError::Image(format!(
+                "Layer entry {} escapes the root filesystem",
+                path.display()
+            )));
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let parent = dest.join(path.parent().unwrap_or_else(|| Path::new("")));
+        if name == OPAQUE_WHITEOUT {
+            if parent.is_dir() {
+                for child in std::fs::read_dir(&parent)? {
+                    remove_path(&child?.path())?;
+                }
+            }
+            continue;
+        }
+        if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            remove_path(&parent.join(hidden))?;
+            continue;
+        }
+
+        if !entry.unpack_in(dest)? {
+            continue;
+        }
+        if let Some(remap) = remap {
+            let header = entry.header();
+            remap.chown(
+                &dest.join(&path),
+                header.uid()? as u32,
+                header.gid()? as u32,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a layer's tar archive, gunzipping it if it is gzipped
+pub(crate) fn decompress<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path)?,
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    fn layer(entries: &[(&str, &[u8], u32)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data, uid) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_uid(*uid as u64);
+            header.set_gid(*uid as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_unpack_layers_with_whiteouts() {
+        let dir = TempDir::new().unwrap();
+        let rootfs = dir.path().join("rootfs");
+
+        let base = layer(&[
+            ("etc/hostname", b"base", 0),
+            ("etc/motd", b"hello", 0),
+            ("var/cache/a", b"a", 0),
+            ("var/cache/b", b"b", 0),
+        ]);
+        unpack_layer(&base[..], &rootfs, None).unwrap();
+
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        std::io::Write::write_all(
+            &mut gzipped,
+            &layer(&[
+                ("etc/.wh.motd", b"", 0),
+                ("var/cache/.wh..wh..opq", b"", 0),
+                ("var/cache/c", b"c", 0),
+            ]),
+        )
+        .unwrap();
+        unpack_layer(&gzipped.finish().unwrap()[..], &rootfs, None).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(rootfs.join("etc/hostname")).unwrap(),
+            "base"
+        );
+        assert!(!rootfs.join("etc/motd").exists());
+        assert!(!rootfs.join("var/cache/a").exists());
+        assert!(rootfs.join("var/cache/c").exists());
+    }
+
+    #[test]
+    fn test_unpack_layer_remaps_owners() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let dir = TempDir::new().unwrap();
+        let remap = UsernsRemap::from_ranges(
+            "runeremap",
+            "runeremap:200000:65536\n",
+            "runeremap:300000:65536\n",
+        )
+        .unwrap();
+
+        let archive = layer(&[("root/.profile", b"", 0), ("home/app/data", b"", 1000)]);
+        unpack_layer(&archive[..], dir.path(), Some(&remap)).unwrap();
+
+        let profile = std::fs::metadata(dir.path().join("root/.profile")).unwrap();
+        assert_eq!((profile.uid(), profile.gid()), (200000, 300000));
+        let data = std::fs::metadata(dir.path().join("home/app/data")).unwrap();
+        assert_eq!((data.uid(), data.gid()), (201000, 301000));
+
+        let outside = layer(&[("etc/shadow", b"", 70000)]);
+        assert!(unpack_layer(&outside[..], dir.path(), Some(&remap)).is_err());
+    }
+}
@@ -3,10 +3,25 @@
 //! This module provides functionality for managing container images,
 //! including pulling, building, and storing images.
 
+pub mod analyze;
+pub mod archive;
 pub mod builder;
+pub mod context;
+pub mod layer;
+pub mod output;
+pub mod progress;
+pub mod pull;
 pub mod registry;
+pub mod snapshot;
+mod stage;
 pub mod store;
 
+pub use analyze::{ImageAnalysis, ImageDiff};
 pub use builder::{BuildContext, ImageBuilder};
+pub use context::ContextSource;
+pub use layer::unpack_layer;
+pub use output::BuildOutput;
+pub use progress::{BuildEvent, ProgressMode, ProgressPrinter};
+pub use pull::{ImagePuller, RegistryAuths, RegistryHosts};
 pub use registry::Registry;
-pub use store::{Image, ImageStore};
+pub use store::{Image, ImageStore, IMAGE_FILTERS};
@@ -0,0 +1,219 @@
+//! Build outputs
+//!
+//! Besides storing the image it built, a build can export it: the final
+//! stage's root filesystem as a directory or a tarball, or the image as an
+//! OCI layout tarball. Outputs are given the way `docker buildx` takes
+//! them, as `type=local,dest=out`; a bare path is a local directory and
+//! `-` a tarball written to stdout.
+
+use super::archive;
+use super::store::{Image, ImageStore};
+use crate::error::{Result, RuneError};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+/// Destination meaning stdout
+const STDOUT: &str = "-";
+
+/// Where a build exports what it built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildOutput {
+    /// The final stage's root filesystem, as a directory
+    Local { dest: PathBuf },
+    /// The final stage's root filesystem, as a tarball
+    Tar { dest: PathBuf },
+    /// The image, as an OCI layout tarball, optionally named
+    Oci { dest: PathBuf, name: Option<String> },
+}
+
+impl FromStr for BuildOutput {
+    type Err = RuneError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            RuneError::InvalidConfig(format!("Invalid output '{}': {}", spec, reason))
+        };
+        if !spec.contains('=') {
+            let dest = PathBuf::from(spec);
+            return Ok(match spec {
+                STDOUT => BuildOutput::Tar { dest },
+                _ => BuildOutput::Local { dest },
+            });
+        }
+
+        let (mut kind, mut dest, mut name) = (None, None, None);
+        for field in spec.split(',') {
+            match field.split_once('=') {
+                Some(("type", value)) => kind = Some(value),
+                Some(("dest", value)) => dest = Some(PathBuf::from(value)),
+                Some(("name", value)) => name = Some(value.to_string()),
+                _ => return Err(invalid(&format!("unknown field {}", field))),
+            }
+        }
+        let dest = dest.ok_or_else(|| invalid("dest is required"))?;
+        match kind {
+            Some("local") => Ok(BuildOutput::Local { dest }),
+            Some("tar") => Ok(BuildOutput::Tar { dest }),
+            Some("oci") => Ok(BuildOutput::Oci { dest, name }),
+            Some(kind) => Err(invalid(&format!(
+                "unknown type {}; expected local, tar or oci",
+                kind
+            ))),
+            None => Err(invalid("type is required")),
+        }
+    }
+}
+
+impl fmt::Display for BuildOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildOutput::Local { dest } => write!(f, "local directory {}", dest.display()),
+            BuildOutput::Tar { dest } => write!(f, "tarball {}", dest.display()),
+            BuildOutput::Oci { dest, .. } => write!(f, "OCI layout {}", dest.display()),
+        }
+    }
+}
+
+impl BuildOutput {
+    /// Export a built image, whose root filesystem is `rootfs`
+    pub(super) fn export(&self, store: &ImageStore, image: &Image, rootfs: &Path) -> Result<()> {
+        match self {
+            BuildOutput::Local { dest } => store.unpack(&image.id, dest, None),
+            BuildOutput::Tar { dest } => write_to(dest, |out| write_rootfs(rootfs, out)),
+            BuildOutput::Oci { dest, name } => write_to(dest, |out| {
+                let name = name
+                    .as_deref()
+                    .or(image.repo_tags.first().map(String::as_str));
+                archive::save_oci(store, image, name, out)
+            }),
+        }
+    }
+}
+
+/// Write to a file, or to stdout for `-`
+fn write_to(dest: &Path, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    if dest == Path::new(STDOUT) {
+        return write(&mut std::io::stdout().lock());
+    }
+    if let Some(parent) = dest
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file =
+        std::io::BufWriter::new(std::fs::File::create(dest).map_err(|e| {
+            RuneError::Build(format!("Failed to create {}: {}", dest.display(), e))
+        })?);
+    write(&mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Tar a root filesystem, its paths relative to it
+fn write_rootfs(rootfs: &Path, out: &mut dyn Write) -> Result<()> {
+    let mut archive = tar::Builder::new(out);
+    archive.follow_symlinks(false);
+    for entry in WalkDir::new(rootfs).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(|e| RuneError::Build(format!("Failed to read rootfs: {}", e)))?;
+        let name = entry
+            .path()
+            .strip_prefix(rootfs)
+            .map_err(|e| RuneError::Build(e.to_string()))?;
+        archive.append_path_with_name(entry.path(), name)?;
+    }
+    archive.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::builder::{BuildContext, ImageBuilder, DEFAULT_BUILD_FILE};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_output() {
+        let parse = |spec: &str| spec.parse::<BuildOutput>();
+        assert_eq!(
+            parse("type=local,dest=out").unwrap(),
+            BuildOutput::Local { dest: "out".into() }
+        );
+        assert_eq!(
+            parse("out").unwrap(),
+            BuildOutput::Local { dest: "out".into() }
+        );
+        assert_eq!(parse("-").unwrap(), BuildOutput::Tar { dest: "-".into() });
+        assert_eq!(
+            parse("type=oci,dest=app.tar,name=app:1").unwrap(),
+            BuildOutput::Oci {
+                dest: "app.tar".into(),
+                name: Some("app:1".to_string())
+            }
+        );
+        for invalid in [
+            "type=image,dest=x",
+            "type=tar",
+            "dest=x",
+            "type=tar,dest=x,push=true",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_outputs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let context_dir = dir.path().join("context");
+        std::fs::create_dir_all(&context_dir).unwrap();
+        std::fs::write(context_dir.join("hello.txt"), "hello").unwrap();
+        std::fs::write(
+            context_dir.join(DEFAULT_BUILD_FILE),
+            "FROM scratch\nCOPY hello.txt /srv/\nCMD [\"/srv/hello.txt\"]\n",
+        )
+        .unwrap();
+
+        let out = dir.path().join("out");
+        let context = BuildContext::new(context_dir)
+            .tag("hello:1")
+            .output(BuildOutput::Local {
+                dest: out.join("rootfs"),
+            })
+            .output(BuildOutput::Tar {
+                dest: out.join("rootfs.tar"),
+            })
+            .output(BuildOutput::Oci {
+                dest: out.join("oci.tar"),
+                name: None,
+            });
+        let store = Arc::new(ImageStore::new(dir.path().join("images")).unwrap());
+        let id = ImageBuilder::new(context)
+            .with_store(store.clone())
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(out.join("rootfs/srv/hello.txt")).unwrap(),
+            "hello"
+        );
+        let mut archive = tar::Archive::new(std::fs::File::open(out.join("rootfs.tar")).unwrap());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(paths, ["srv", "srv/hello.txt"]);
+
+        // The OCI layout loads back as the same image
+        let other = ImageStore::new(dir.path().join("other")).unwrap();
+        let loaded = other.load(&out.join("oci.tar")).unwrap();
+        let built = store.get(&id).unwrap();
+        assert_eq!(loaded[0].repo_tags, ["hello:1"]);
+        assert_eq!(loaded[0].layers, built.layers);
+        assert_eq!(loaded[0].config.cmd, built.config.cmd);
+    }
+}
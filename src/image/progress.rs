@@ -0,0 +1,332 @@
+//! Build progress
+//!
+//! An [`ImageBuilder`](super::ImageBuilder) reports what it does as
+//! [`BuildEvent`]s on a channel. A [`ProgressPrinter`] renders them the way
+//! BuildKit does: as numbered plain lines, as a terminal display whose step
+//! output collapses once the step is done, or as one JSON object per line.
+
+use crate::error::{Result, RuneError};
+use crate::tui::stats::format_bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Lines of a running step's output the terminal display shows
+const TTY_OUTPUT_LINES: usize = 6;
+
+/// Something a build did
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BuildEvent {
+    /// A step started; steps are numbered from 1 across all stages
+    StepStarted { step: usize, name: String },
+    /// A line a step's command printed
+    StepOutput { step: usize, line: String },
+    /// A step's result was taken from the build cache
+    StepCached { step: usize },
+    /// A step finished
+    StepFinished { step: usize, seconds: f64 },
+    /// A step failed, failing the build
+    StepFailed { step: usize, error: String },
+    /// The image was built
+    Finished {
+        image_id: String,
+        tags: Vec<String>,
+        layers: Vec<LayerSummary>,
+        seconds: f64,
+    },
+}
+
+/// Layer of a built image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerSummary {
+    /// Digest of the layer tarball
+    pub digest: String,
+    /// Size of the layer tarball in bytes
+    pub size: u64,
+    /// Instruction that created it
+    pub created_by: String,
+}
+
+/// How build progress is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Numbered lines with every line of output
+    Plain,
+    /// Live display collapsing the output of finished steps
+    Tty,
+    /// Events as JSON lines
+    Json,
+}
+
+impl ProgressMode {
+    /// Terminal display on a terminal, plain lines otherwise
+    pub fn auto() -> Self {
+        if io::stderr().is_terminal() {
+            ProgressMode::Tty
+        } else {
+            ProgressMode::Plain
+        }
+    }
+}
+
+impl FromStr for ProgressMode {
+    type Err = RuneError;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "auto" => Ok(Self::auto()),
+            "plain" => Ok(ProgressMode::Plain),
+            "tty" => Ok(ProgressMode::Tty),
+            "json" => Ok(ProgressMode::Json),
+            _ => Err(RuneError::InvalidConfig(format!(
+                "Invalid progress mode '{}'; expected auto, plain, tty or json",
+                mode
+            ))),
+        }
+    }
+}
+
+/// Step being shown
+struct Step {
+    name: String,
+    started: Instant,
+    /// Output shown for the step on the terminal display
+    output: Vec<String>,
+}
+
+/// Renders build events
+pub struct ProgressPrinter<W: Write> {
+    mode: ProgressMode,
+    out: W,
+    steps: HashMap<usize, Step>,
+    /// Step whose output the terminal display shows below its line
+    live: Option<usize>,
+    /// Lines the live step's output takes on the terminal display
+    live_lines: usize,
+}
+
+impl<W: Write> ProgressPrinter<W> {
+    /// Create a printer writing to `out`
+    pub fn new(mode: ProgressMode, out: W) -> Self {
+        Self {
+            mode,
+            out,
+            steps: HashMap::new(),
+            live: None,
+            live_lines: 0,
+        }
+    }
+
+    /// Render an event
+    pub fn print(&mut self, event: &BuildEvent) -> io::Result<()> {
+        if self.mode == ProgressMode::Json {
+            let line = serde_json::to_string(event).map_err(io::Error::other)?;
+            return writeln!(self.out, "{}", line);
+        }
+        match event {
+            BuildEvent::StepStarted { step, name } => {
+                self.steps.insert(
+                    *step,
+                    Step {
+                        name: name.clone(),
+                        started: Instant::now(),
+                        output: Vec::new(),
+                    },
+                );
+                match self.mode {
+                    ProgressMode::Tty => {
+                        self.settle();
+                        writeln!(self.out, " => {}", name)?;
+                        self.live = Some(*step);
+                    }
+                    _ => writeln!(self.out, "#{} {}", step, name)?,
+                }
+            }
+            BuildEvent::StepOutput { step, line } => match self.mode {
+                ProgressMode::Tty => {
+                    if self.live != Some(*step) {
+                        return Ok(());
+                    }
+                    if let Some(state) = self.steps.get_mut(step) {
+                        state.output.push(line.clone());
+                    }
+                    self.redraw_output()?;
+                }
+                _ => {
+                    let elapsed = self
+                        .steps
+                        .get(step)
+                        .map_or(0.0, |s| s.started.elapsed().as_secs_f64());
+                    writeln!(self.out, "#{} {:.3} {}", step, elapsed, line)?;
+                }
+            },
+            BuildEvent::StepCached { step } => match self.mode {
+                ProgressMode::Tty => self.collapse(*step, "CACHED ", "")?,
+                _ => writeln!(self.out, "#{} CACHED", step)?,
+            },
+            BuildEvent::StepFinished { step, seconds } => match self.mode {
+                ProgressMode::Tty => self.collapse(*step, "", &format!(" {:.1}s", seconds))?,
+                _ => writeln!(self.out, "#{} DONE {:.1}s", step, seconds)?,
+            },
+            BuildEvent::StepFailed { step, error } => {
+                if self.mode == ProgressMode::Tty {
+                    // The output of a failed step stays
+                    self.settle();
+                    let name = self.steps.get(step).map_or("", |s| s.name.as_str());
+                    writeln!(self.out, " => ERROR {}", name)?;
+                }
+                writeln!(self.out, "#{} ERROR: {}", step, error)?;
+            }
+            BuildEvent::Finished {
+                image_id,
+                tags,
+                layers,
+                seconds,
+            } => {
+                self.settle();
+                writeln!(self.out)?;
+                writeln!(self.out, "{:<10} CREATED BY", "SIZE")?;
+                for layer in layers {
+                    writeln!(
+                        self.out,
+                        "{:<10} {}",
+                        format_bytes(layer.size),
+                        layer.created_by
+                    )?;
+                }
+                let total: u64 = layers.iter().map(|layer| layer.size).sum();
+                writeln!(self.out, "{:<10} total", format_bytes(total))?;
+                writeln!(self.out)?;
+                writeln!(self.out, "Built {} in {:.1}s", image_id, seconds)?;
+                for tag in tags {
+                    writeln!(self.out, "Tagged {}", tag)?;
+                }
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Redraw the last lines of the live step's output below its line
+    fn redraw_output(&mut self) -> io::Result<()> {
+        let Some(step) = self.live.and_then(|step| self.steps.get(&step)) else {
+            return Ok(());
+        };
+        let shown = &step.output[step.output.len().saturating_sub(TTY_OUTPUT_LINES)..];
+        if self.live_lines > 0 {
+            write!(self.out, "\x1b[{}A\x1b[J", self.live_lines)?;
+        }
+        for line in shown {
+            writeln!(self.out, "\x1b[2m    {}\x1b[0m", line)?;
+        }
+        self.live_lines = shown.len();
+        Ok(())
+    }
+
+    /// Replace a finished step's output with its line on the terminal
+    /// display, marked with a prefix and suffix
+    fn collapse(&mut self, step: usize, prefix: &str, suffix: &str) -> io::Result<()> {
+        let Some(name) = self.steps.get(&step).map(|s| s.name.clone()) else {
+            return Ok(());
+        };
+        if self.live == Some(step) {
+            // Back over the output and the step's own line
+            write!(self.out, "\x1b[{}A\x1b[J", self.live_lines + 1)?;
+            self.live = None;
+            self.live_lines = 0;
+        }
+        writeln!(self.out, " => {}{}{}", prefix, name, suffix)
+    }
+
+    /// Leave the live step's output where it is
+    fn settle(&mut self) {
+        self.live = None;
+        self.live_lines = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<BuildEvent> {
+        vec![
+            BuildEvent::StepStarted {
+                step: 1,
+                name: "[1/3] FROM scratch".to_string(),
+            },
+            BuildEvent::StepFinished {
+                step: 1,
+                seconds: 0.0,
+            },
+            BuildEvent::StepStarted {
+                step: 2,
+                name: "[2/3] COPY app /app".to_string(),
+            },
+            BuildEvent::StepCached { step: 2 },
+            BuildEvent::StepStarted {
+                step: 3,
+                name: "[3/3] RUN make".to_string(),
+            },
+            BuildEvent::StepOutput {
+                step: 3,
+                line: "compiling".to_string(),
+            },
+            BuildEvent::StepFinished {
+                step: 3,
+                seconds: 1.25,
+            },
+            BuildEvent::Finished {
+                image_id: "sha256:abc".to_string(),
+                tags: vec!["app:latest".to_string()],
+                layers: vec![LayerSummary {
+                    digest: "sha256:def".to_string(),
+                    size: 2048,
+                    created_by: "COPY app /app".to_string(),
+                }],
+                seconds: 1.5,
+            },
+        ]
+    }
+
+    fn render(mode: ProgressMode) -> String {
+        let mut printer = ProgressPrinter::new(mode, Vec::new());
+        for event in events() {
+            printer.print(&event).unwrap();
+        }
+        String::from_utf8(printer.out).unwrap()
+    }
+
+    #[test]
+    fn test_plain_progress() {
+        let output = render(ProgressMode::Plain);
+        assert!(output.contains("#1 [1/3] FROM scratch\n#1 DONE 0.0s\n"));
+        assert!(output.contains("#2 CACHED\n"));
+        assert!(output.contains(" compiling\n#3 DONE 1.2s\n"));
+        assert!(output.contains("2.0KiB     COPY app /app\n"));
+        assert!(output.contains("Built sha256:abc in 1.5s\nTagged app:latest\n"));
+    }
+
+    #[test]
+    fn test_tty_progress() {
+        let output = render(ProgressMode::Tty);
+        assert!(output.contains(" => CACHED [2/3] COPY app /app\n"));
+        // The output is drawn, then erased along with the step's line
+        assert!(output.contains("    compiling"));
+        assert!(output.contains("\x1b[2A\x1b[J => [3/3] RUN make 1.2s\n"));
+    }
+
+    #[test]
+    fn test_json_progress() {
+        let output = render(ProgressMode::Json);
+        let lines: Vec<BuildEvent> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines, events());
+        assert!(output.starts_with(r#"{"type":"step_started","step":1"#));
+        assert!("yaml".parse::<ProgressMode>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Root filesystem snapshots
+//!
+//! The layer a build step makes holds what the step changed in the stage's
+//! root filesystem. A snapshot records the metadata of every path before
+//! the step; comparing it with the filesystem afterwards gives the files to
+//! put in the layer, and the whiteouts of those the step deleted.
+
+use super::layer::WHITEOUT_PREFIX;
+use crate::error::{Result, RuneError};
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// What tells a path apart from the same path changed; the change time
+/// covers changes to contents and metadata alike
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    ino: u64,
+    mode: u32,
+    size: u64,
+    ctime: (i64, i64),
+    mtime: (i64, i64),
+}
+
+impl Stamp {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            ino: metadata.ino(),
+            mode: metadata.mode(),
+            size: metadata.size(),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+        }
+    }
+}
+
+/// Metadata of every path under a root filesystem
+pub struct Snapshot {
+    root: PathBuf,
+    stamps: HashMap<PathBuf, Stamp>,
+}
+
+impl Snapshot {
+    /// Record the paths under a root filesystem
+    pub fn take(root: &Path) -> Result<Self> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            stamps: stamps(root)?,
+        })
+    }
+
+    /// Layer tarball of what changed under the root filesystem since the
+    /// snapshot, or `None` if nothing did
+    pub fn diff(&self) -> Result<Option<Vec<u8>>> {
+        let now = stamps(&self.root)?;
+        let mut changed: Vec<&PathBuf> = now
+            .iter()
+            .filter(|(path, stamp)| self.stamps.get(*path) != Some(stamp))
+            .map(|(path, _)| path)
+            .collect();
+        // Deleted paths under deleted directories go with their directory
+        let mut deleted: Vec<&PathBuf> = self
+            .stamps
+            .keys()
+            .filter(|path| !now.contains_key(*path))
+            .filter(|path| {
+                path.parent()
+                    .is_none_or(|parent| parent.as_os_str().is_empty() || now.contains_key(parent))
+            })
+            .collect();
+        if changed.is_empty() && deleted.is_empty() {
+            return Ok(None);
+        }
+        changed.sort();
+        deleted.sort();
+
+        let mut layer = tar::Builder::new(Vec::new());
+        layer.follow_symlinks(false);
+        for path in deleted {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let whiteout = path.with_file_name(format!("{}{}", WHITEOUT_PREFIX, name));
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(0);
+            header.set_mode(0o644);
+            layer.append_data(&mut header, &whiteout, std::io::empty())?;
+        }
+        for path in changed {
+            let full = self.root.join(path);
+            let file_type = std::fs::symlink_metadata(&full)?.file_type();
+            if !(file_type.is_file() || file_type.is_dir() || file_type.is_symlink()) {
+                // Devices, sockets and pipes have no place in a layer
+                continue;
+            }
+            layer.append_path_with_name(&full, path).map_err(|e| {
+                RuneError::Image(format!("Failed to add {} to layer: {}", path.display(), e))
+            })?;
+        }
+        Ok(Some(layer.into_inner()?))
+    }
+}
+
+/// Stamps of the paths under a root, relative to it
+fn stamps(root: &Path) -> Result<HashMap<PathBuf, Stamp>> {
+    let mut stamps = HashMap::new();
+    for entry in WalkDir::new(root).min_depth(1).follow_links(false) {
+        let entry = entry.map_err(|e| RuneError::Image(format!("Failed to walk rootfs: {}", e)))?;
+        let metadata = entry
+            .metadata()
+            .map_err(|e| RuneError::Image(format!("Failed to stat rootfs: {}", e)))?;
+        let path = entry
+            .path()
+            .strip_prefix(root)
+            .map_err(|e| RuneError::Image(e.to_string()))?;
+        stamps.insert(path.to_path_buf(), Stamp::of(&metadata));
+    }
+    Ok(stamps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::analyze::{read_layer, LayerEntry};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("etc/old")).unwrap();
+        std::fs::write(root.join("etc/old/file"), "old").unwrap();
+        std::fs::write(root.join("etc/keep"), "keep").unwrap();
+        std::fs::write(root.join("etc/edit"), "before").unwrap();
+
+        let snapshot = Snapshot::take(root).unwrap();
+        assert!(snapshot.diff().unwrap().is_none());
+
+        std::fs::remove_dir_all(root.join("etc/old")).unwrap();
+        std::fs::write(root.join("etc/edit"), "after!").unwrap();
+        std::fs::create_dir(root.join("app")).unwrap();
+        std::fs::write(root.join("app/new"), "new").unwrap();
+        std::os::unix::fs::symlink("new", root.join("app/link")).unwrap();
+
+        let layer = snapshot.diff().unwrap().unwrap();
+        let mut paths: Vec<String> = read_layer(layer.as_slice())
+            .unwrap()
+            .into_iter()
+            .map(|entry| match entry {
+                LayerEntry::File { path, .. } | LayerEntry::Dir { path } => path,
+                LayerEntry::Whiteout { path } => format!("-{}", path),
+                LayerEntry::Opaque { path } => format!("-{}/*", path),
+            })
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "-/etc/old",
+                "/app",
+                "/app/link",
+                "/app/new",
+                "/etc",
+                "/etc/edit"
+            ]
+        );
+    }
+}
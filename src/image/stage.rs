@@ -0,0 +1,1323 @@
+//! Build stage execution
+//!
+//! Each stage of a build works on a root filesystem of its own. FROM
+//! unpacks the base image into it, COPY and ADD add files from the build
+//! context, a named context or an earlier stage, and RUN runs a command chrooted into it;
+//! each of these makes a layer of what it changed. The other instructions
+//! only change the image config.
+//!
+//! Every step has a cache key chained from the keys of the steps before
+//! it. The build cache maps a key to the layer the step made, so a step
+//! whose key is unchanged reuses its layer instead of running again, if
+//! the layer is still in the store or comes back with a cache image.
+
+use super::builder::{BuildContext, BuildInstruction, BuildStage};
+use super::context::glob_regex;
+use super::context::{download, ContextDir, ContextSource, IGNORE_FILE};
+use super::layer::{decompress, unpack_layer};
+use super::progress::BuildEvent;
+use super::pull::{ImagePuller, ImageReference, RegistryHosts};
+use super::registry::sha256_digest;
+use super::snapshot::Snapshot;
+use super::store::{HealthConfig, Image, ImageConfig, ImageStore};
+use crate::error::{Result, RuneError};
+use crate::runtime::signal::parse_signal;
+use crate::swarm::stack::parse_duration;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use walkdir::WalkDir;
+
+/// PATH of RUN commands whose image sets none
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// A built stage
+pub(super) struct Stage {
+    /// Root filesystem the stage's steps ran in
+    pub rootfs: PathBuf,
+    /// Config of the stage's image
+    pub config: ImageConfig,
+    /// Layer digests, bottom first
+    pub layers: Vec<String>,
+    /// Instruction that created each layer
+    pub history: Vec<String>,
+    /// Cache key of the stage's last instruction
+    pub key: String,
+    /// Build arguments the stage declared, with their values
+    args: BTreeMap<String, String>,
+    /// Whether the stage set CMD, which ENTRYPOINT then keeps
+    cmd_set: bool,
+}
+
+/// Context a build refers to by name, in FROM or `COPY --from`
+struct NamedContext {
+    /// Its files
+    dir: ContextDir,
+    /// The image it is, if it is one
+    image: Option<Image>,
+}
+
+/// Runs the stages of a build, numbering their steps across the build;
+/// shared by the stages running at once
+pub(super) struct StageRunner {
+    store: Arc<ImageStore>,
+    context: Arc<BuildContext>,
+    events: Option<Sender<BuildEvent>>,
+    /// Main build context
+    context_dir: ContextDir,
+    /// Named build contexts
+    named: HashMap<String, NamedContext>,
+    /// Directory of the build cache's index
+    cache: PathBuf,
+    /// Number of the last step started
+    step: AtomicUsize,
+    /// Process groups of the RUN commands running now; none once the build
+    /// is cancelled
+    commands: Mutex<Option<HashSet<u32>>>,
+}
+
+impl StageRunner {
+    pub fn new(
+        store: Arc<ImageStore>,
+        context: Arc<BuildContext>,
+        events: Option<Sender<BuildEvent>>,
+    ) -> Result<Self> {
+        let cache = store.storage_path().join("build-cache");
+        std::fs::create_dir_all(&cache)?;
+        Ok(Self {
+            store,
+            context_dir: ContextDir::new(context.context_dir.clone()),
+            context,
+            events,
+            named: HashMap::new(),
+            cache,
+            step: AtomicUsize::new(0),
+            commands: Mutex::new(Some(HashSet::new())),
+        })
+    }
+
+    /// Cancel the build: kill the RUN commands running now, and fail the
+    /// steps of the stages still running before they start
+    pub fn cancel(&self) {
+        let Ok(mut commands) = self.commands.lock() else {
+            return;
+        };
+        for pgid in commands.take().unwrap_or_default() {
+            kill_group(pgid);
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.commands.lock() {
+            Ok(commands) if commands.is_some() => Ok(()),
+            _ => Err(cancelled()),
+        }
+    }
+
+    /// Track a RUN command's process group until it exited, killing it
+    /// right away if the build is cancelled
+    fn track(&self, pgid: u32) -> Result<()> {
+        let mut commands = self
+            .commands
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire commands lock".to_string()))?;
+        match commands.as_mut() {
+            Some(commands) => {
+                commands.insert(pgid);
+                Ok(())
+            }
+            None => {
+                kill_group(pgid);
+                Err(cancelled())
+            }
+        }
+    }
+
+    fn untrack(&self, pgid: u32) {
+        if let Ok(mut commands) = self.commands.lock() {
+            if let Some(commands) = commands.as_mut() {
+                commands.remove(&pgid);
+            }
+        }
+    }
+
+    /// Load the build contexts, fetching remote ones and images into
+    /// `workdir`; each fetch is a step
+    pub async fn load_contexts(&mut self, workdir: &Path) -> Result<()> {
+        let dir = match &self.context.remote {
+            Some(source) => {
+                let name = format!("[internal] load build context {}", source);
+                self.fetch(name, source, &workdir.join("context")).await?
+            }
+            None => self.context.context_dir.clone(),
+        };
+        self.context_dir = ContextDir::load(dir)?;
+
+        for (name, source) in &self.context.named_contexts {
+            let dest = workdir.join("contexts").join(name);
+            let step_name = format!("[context {}] load {}", name, source);
+            let named = match source {
+                ContextSource::Image(reference) => {
+                    let step = self.start(step_name);
+                    let started = Instant::now();
+                    let result = async {
+                        let image = image(&self.store, reference, self.context.pull).await?;
+                        self.store.unpack(&image.id, &dest, None)?;
+                        Ok((image, false))
+                    }
+                    .await;
+                    let image = self.finish(step, started, result)?;
+                    NamedContext {
+                        dir: ContextDir::new(dest),
+                        image: Some(image),
+                    }
+                }
+                ContextSource::Local(path) => NamedContext {
+                    dir: ContextDir::load(path.clone())?,
+                    image: None,
+                },
+                remote => NamedContext {
+                    dir: ContextDir::load(self.fetch(step_name, remote, &dest).await?)?,
+                    image: None,
+                },
+            };
+            self.named.insert(name.clone(), named);
+        }
+
+        // A cache image that cannot be had only makes for a colder cache
+        for reference in &self.context.cache_from {
+            let step = self.start(format!("importing cache from {}", reference));
+            let started = Instant::now();
+            if let Err(e) = image(&self.store, reference, false).await {
+                tracing::warn!("Failed to import cache from {}: {}", reference, e);
+            }
+            self.finish(step, started, Ok(((), false)))?;
+        }
+        Ok(())
+    }
+
+    /// Directory of the main build context, once loaded
+    pub fn context_dir(&self) -> &Path {
+        &self.context_dir.path
+    }
+
+    /// Fetch a remote context as a step
+    async fn fetch(&self, name: String, source: &ContextSource, dest: &Path) -> Result<PathBuf> {
+        let step = self.start(name);
+        let started = Instant::now();
+        let result = source.fetch(dest).await;
+        self.finish(step, started, result.map(|dir| (dir, false)))
+    }
+
+    /// Run a stage in `rootfs`. `deps` are the built stages it refers to,
+    /// by the names or indexes it uses; `label` names it in step names when
+    /// the build has several stages.
+    pub async fn run(
+        self: Arc<Self>,
+        stage: BuildStage,
+        label: Option<String>,
+        deps: HashMap<String, Arc<Stage>>,
+        rootfs: PathBuf,
+    ) -> Result<Stage> {
+        let total = 1 + stage
+            .instructions
+            .iter()
+            .filter(|instruction| is_step(instruction))
+            .count();
+        let name = |n: usize, instruction: &dyn std::fmt::Display| match &label {
+            Some(label) => format!("[{} {}/{}] {}", label, n, total, instruction),
+            None => format!("[{}/{}] {}", n, total, instruction),
+        };
+        let from = BuildInstruction::From {
+            image: stage.base_image.clone(),
+            tag: stage.base_tag.clone(),
+            alias: None,
+        };
+
+        self.check_cancelled()?;
+        let step = self.start(name(1, &from));
+        let started = Instant::now();
+        let result = self.from(&stage, &deps, &rootfs).await;
+        let mut built = self.finish(step, started, result.map(|stage| (stage, false)))?;
+
+        let mut n = 1;
+        for instruction in &stage.instructions {
+            if !is_step(instruction) {
+                built.apply(instruction, &self.context)?;
+                continue;
+            }
+            n += 1;
+            self.check_cancelled()?;
+            let step = self.start(name(n, instruction));
+            let started = Instant::now();
+            let result = match instruction {
+                BuildInstruction::Run { command, shell } => {
+                    // Commands block, so they run off the async workers
+                    let runner = self.clone();
+                    let (instruction, command, shell) =
+                        (instruction.clone(), command.clone(), *shell);
+                    let (stage, result) = tokio::task::spawn_blocking(move || {
+                        let result =
+                            runner.run_command(&mut built, &instruction, &command, shell, step);
+                        (built, result)
+                    })
+                    .await
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+                    built = stage;
+                    result
+                }
+                BuildInstruction::Copy {
+                    src,
+                    dest,
+                    from,
+                    chown,
+                } => {
+                    let root = match from {
+                        Some(from) => self.source(&deps, from),
+                        None => Ok(self.context_dir.clone()),
+                    };
+                    match root {
+                        Ok(root) => {
+                            self.copy(&mut built, instruction, &root, src, dest, chown, false)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                BuildInstruction::Add { src, dest, chown } => {
+                    let root = self.context_dir.clone();
+                    self.copy(&mut built, instruction, &root, src, dest, chown, true)
+                        .await
+                }
+                _ => unreachable!("only RUN, COPY and ADD are steps"),
+            };
+            self.finish(step, started, result.map(|cached| ((), cached)))?;
+        }
+        Ok(built)
+    }
+
+    fn send(&self, event: BuildEvent) {
+        if let Some(events) = &self.events {
+            // The build goes on if nobody is watching
+            let _ = events.send(event);
+        }
+    }
+
+    pub fn start(&self, name: String) -> usize {
+        let step = self.step.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send(BuildEvent::StepStarted { step, name });
+        step
+    }
+
+    /// Report how a step ended: cached, done or failed
+    pub fn finish<T>(&self, step: usize, started: Instant, result: Result<(T, bool)>) -> Result<T> {
+        match result {
+            Ok((value, true)) => {
+                self.send(BuildEvent::StepCached { step });
+                Ok(value)
+            }
+            Ok((value, false)) => {
+                self.send(BuildEvent::StepFinished {
+                    step,
+                    seconds: started.elapsed().as_secs_f64(),
+                });
+                Ok(value)
+            }
+            Err(e) => {
+                self.send(BuildEvent::StepFailed {
+                    step,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Files `COPY --from` names: an earlier stage's, by name or index, or
+    /// a named context's
+    fn source(&self, deps: &HashMap<String, Arc<Stage>>, from: &str) -> Result<ContextDir> {
+        if let Some(stage) = deps.get(from) {
+            return Ok(ContextDir::new(stage.rootfs.clone()));
+        }
+        self.named
+            .get(from)
+            .map(|named| named.dir.clone())
+            .ok_or_else(|| {
+                RuneError::Build(format!(
+                    "COPY --from={} names no earlier stage or build context",
+                    from
+                ))
+            })
+    }
+
+    /// Start a stage from scratch, an earlier stage, a named context or an
+    /// image
+    async fn from(
+        &self,
+        stage: &BuildStage,
+        deps: &HashMap<String, Arc<Stage>>,
+        rootfs: &Path,
+    ) -> Result<Stage> {
+        std::fs::create_dir_all(rootfs)?;
+        let mut built = Stage {
+            rootfs: rootfs.to_path_buf(),
+            config: ImageConfig::default(),
+            layers: Vec::new(),
+            history: Vec::new(),
+            key: sha256_digest(b"FROM scratch"),
+            args: BTreeMap::new(),
+            cmd_set: false,
+        };
+        if stage.base_tag.is_none() {
+            if stage.base_image == "scratch" {
+                return Ok(built);
+            }
+            if let Some(base) = deps.get(&stage.base_image) {
+                for digest in &base.layers {
+                    self.unpack(digest, rootfs)?;
+                }
+                built.config = base.config.clone();
+                built.layers = base.layers.clone();
+                built.history = base.history.clone();
+                built.key = base.key.clone();
+                return Ok(built);
+            }
+        }
+
+        let reference = match &stage.base_tag {
+            Some(tag) => format!("{}:{}", stage.base_image, tag),
+            None => stage.base_image.clone(),
+        };
+        // A named context stands in for the image it is named after
+        let (image, reference) = match self.named.get(&reference) {
+            Some(NamedContext {
+                image: Some(image), ..
+            }) => (image.clone(), reference),
+            Some(NamedContext { dir, image: None }) => {
+                // Its files are the stage's first layer
+                let mut layer = tar::Builder::new(Vec::new());
+                append_tree(&mut layer, dir, &dir.path, Path::new(""), None)?;
+                let data = layer.into_inner()?;
+                let digest = sha256_digest(&data);
+                let path = self.store.layer_path(&digest);
+                if !path.exists() {
+                    std::fs::write(&path, &data)?;
+                }
+                unpack_layer(data.as_slice(), rootfs, None)?;
+                built.history = vec![format!("FROM {}", reference)];
+                built.key = sha256_digest(format!("FROM {}", digest).as_bytes());
+                built.layers = vec![digest];
+                return Ok(built);
+            }
+            None => {
+                let reference = ImageReference::parse(&reference)?.to_string();
+                (
+                    image(&self.store, &reference, self.context.pull).await?,
+                    reference,
+                )
+            }
+        };
+        self.store.unpack(&image.id, rootfs, None)?;
+        built.history = vec![format!("FROM {}", reference); image.layers.len()];
+        built.layers = image.layers;
+        built.config = image.config;
+        built.key = sha256_digest(format!("FROM {}", image.id).as_bytes());
+        Ok(built)
+    }
+
+    /// COPY or ADD files from `root` into the stage
+    #[allow(clippy::too_many_arguments)]
+    async fn copy(
+        &self,
+        stage: &mut Stage,
+        instruction: &BuildInstruction,
+        root: &ContextDir,
+        src: &[String],
+        dest: &str,
+        chown: &Option<String>,
+        add: bool,
+    ) -> Result<bool> {
+        let verb = if add { "ADD" } else { "COPY" };
+        if src.is_empty() || dest.is_empty() {
+            return Err(RuneError::Build(format!(
+                "{} needs at least one source and a destination",
+                verb
+            )));
+        }
+        let vars = stage.vars();
+        let dest = expand(dest, &vars);
+        let owner = match chown {
+            Some(chown) => Some(resolve_user(&stage.rootfs, &expand(chown, &vars))?),
+            None => None,
+        };
+
+        // Sources, with wildcards expanded; remote ones are fetched
+        let mut sources = Vec::new();
+        for src in src {
+            let src = expand(src, &vars);
+            if add && (src.starts_with("http://") || src.starts_with("https://")) {
+                let name = src
+                    .rsplit('/')
+                    .next()
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or("index.html")
+                    .to_string();
+                sources.push(Source::Remote(name, download(&src).await?));
+            } else {
+                sources.extend(glob(root, &src)?.into_iter().map(Source::Local));
+            }
+        }
+
+        let dest = stage.resolve(&dest);
+        let into_dir =
+            dest.ends_with('/') || sources.len() > 1 || stage.rootfs.join(relative(&dest)).is_dir();
+        let dest = relative(&dest);
+
+        let mut layer = tar::Builder::new(Vec::new());
+        for source in sources {
+            match source {
+                Source::Remote(name, data) => {
+                    let path = if into_dir {
+                        dest.join(name)
+                    } else {
+                        dest.clone()
+                    };
+                    let mut header = tar::Header::new_gnu();
+                    header.set_size(data.len() as u64);
+                    header.set_mode(0o600);
+                    header.set_mtime(0);
+                    set_owner(&mut header, owner);
+                    layer.append_data(&mut header, &path, data.as_slice())?;
+                }
+                Source::Local(path) if path.is_dir() => {
+                    append_tree(&mut layer, root, &path, &dest, owner)?;
+                }
+                Source::Local(path) if add && is_archive(&path) => {
+                    append_archive(&mut layer, &path, &dest, owner)?;
+                }
+                Source::Local(path) => {
+                    let name = path.file_name().unwrap_or_default();
+                    let target = if into_dir {
+                        dest.join(name)
+                    } else {
+                        dest.clone()
+                    };
+                    append_entry(&mut layer, &path, &target, owner)?;
+                }
+            }
+        }
+        let data = layer.into_inner()?;
+        let digest = sha256_digest(&data);
+
+        let key = stage.chain(&[&canonical_json(instruction)?, &digest]);
+        let cached = !self.context.no_cache && self.cached(&key).as_deref() == Some(&digest);
+        let path = self.store.layer_path(&digest);
+        if !path.exists() {
+            std::fs::write(&path, &data)?;
+        }
+        unpack_layer(data.as_slice(), &stage.rootfs, None)?;
+        self.remember(&key, &digest)?;
+        stage.layers.push(digest);
+        stage.history.push(instruction.to_string());
+        stage.key = key;
+        Ok(cached)
+    }
+
+    /// RUN a command chrooted into the stage's root filesystem
+    fn run_command(
+        &self,
+        stage: &mut Stage,
+        instruction: &BuildInstruction,
+        command: &str,
+        shell: bool,
+        step: usize,
+    ) -> Result<bool> {
+        let args: Vec<String> = stage
+            .args
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        let key = stage.chain(&[&canonical_json(instruction)?, &args.join("\n")]);
+        if !self.context.no_cache {
+            if let Some(digest) = self.cached(&key) {
+                if digest.is_empty() || self.store.layer_path(&digest).exists() {
+                    if !digest.is_empty() {
+                        self.unpack(&digest, &stage.rootfs)?;
+                        stage.layers.push(digest);
+                        stage.history.push(instruction.to_string());
+                    }
+                    stage.key = key;
+                    return Ok(true);
+                }
+            }
+        }
+
+        let argv = if shell {
+            let mut argv = match stage.config.shell.is_empty() {
+                true => vec!["/bin/sh".to_string(), "-c".to_string()],
+                false => stage.config.shell.clone(),
+            };
+            argv.push(command.to_string());
+            argv
+        } else {
+            serde_json::from_str::<Vec<String>>(command)
+                .ok()
+                .filter(|argv| !argv.is_empty())
+                .ok_or_else(|| RuneError::Build(format!("Invalid RUN command: {}", command)))?
+        };
+
+        let snapshot = Snapshot::take(&stage.rootfs)?;
+        let workdir = stage.resolve(if stage.config.working_dir.is_empty() {
+            "/"
+        } else {
+            &stage.config.working_dir
+        });
+        std::fs::create_dir_all(stage.rootfs.join(relative(&workdir)))?;
+        let user = match stage.config.user.as_str() {
+            "" => None,
+            user => Some(resolve_user(&stage.rootfs, user)?),
+        };
+
+        // ENV wins over an ARG of the same name
+        let mut env: BTreeMap<String, String> = stage.args.clone();
+        for var in &stage.config.env {
+            let (name, value) = var.split_once('=').unwrap_or((var, ""));
+            env.insert(name.to_string(), value.to_string());
+        }
+        env.entry("PATH".to_string())
+            .or_insert_with(|| DEFAULT_PATH.to_string());
+
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..])
+            .env_clear()
+            .envs(&env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // What the command starts is killed with it
+            .process_group(0);
+        let jail = Jail::new(&stage.rootfs, &workdir, user)?;
+        // SAFETY: the closure only makes async-signal-safe system calls
+        unsafe {
+            cmd.pre_exec(move || jail.enter());
+        }
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| RuneError::Build(format!("Failed to run {}: {}", command, e)))?;
+        if let Err(e) = self.track(child.id()) {
+            let _ = child.wait();
+            return Err(e);
+        }
+
+        let readers: Vec<Box<dyn Read + Send>> = vec![
+            Box::new(child.stdout.take().expect("stdout is piped")),
+            Box::new(child.stderr.take().expect("stderr is piped")),
+        ];
+        let forwarders: Vec<_> = readers
+            .into_iter()
+            .map(|reader| {
+                let events = self.events.clone();
+                std::thread::spawn(move || {
+                    for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+                        if let Some(events) = &events {
+                            let _ = events.send(BuildEvent::StepOutput { step, line });
+                        }
+                    }
+                })
+            })
+            .collect();
+        let status = child.wait();
+        self.untrack(child.id());
+        let status = status?;
+        for forwarder in forwarders {
+            let _ = forwarder.join();
+        }
+        if !status.success() {
+            return Err(RuneError::Build(format!(
+                "{} did not complete successfully: {}",
+                instruction, status
+            )));
+        }
+
+        let digest = match snapshot.diff()? {
+            Some(data) => {
+                let digest = sha256_digest(&data);
+                std::fs::write(self.store.layer_path(&digest), &data)?;
+                stage.layers.push(digest.clone());
+                stage.history.push(instruction.to_string());
+                digest
+            }
+            None => String::new(),
+        };
+        self.remember(&key, &digest)?;
+        stage.key = key;
+        Ok(false)
+    }
+
+    fn unpack(&self, digest: &str, rootfs: &Path) -> Result<()> {
+        let file = std::fs::File::open(self.store.layer_path(digest))
+            .map_err(|e| RuneError::Image(format!("Failed to open layer {}: {}", digest, e)))?;
+        unpack_layer(file, rootfs, None)
+    }
+
+    /// Layer digest the cache has for a key, empty for a step that changed
+    /// nothing
+    fn cached(&self, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.cache.join(hex(key))).ok()
+    }
+
+    fn remember(&self, key: &str, digest: &str) -> Result<()> {
+        std::fs::write(self.cache.join(hex(key)), digest)?;
+        Ok(())
+    }
+}
+
+impl Stage {
+    /// Apply an instruction that only changes the config
+    fn apply(&mut self, instruction: &BuildInstruction, context: &BuildContext) -> Result<()> {
+        let vars = self.vars();
+        let mut resolved = String::new();
+        match instruction {
+            BuildInstruction::Env { key, value } => {
+                let value = expand(value, &vars);
+                let prefix = format!("{}=", key);
+                self.config.env.retain(|var| !var.starts_with(&prefix));
+                self.config.env.push(format!("{}{}", prefix, value));
+                resolved = value;
+            }
+            BuildInstruction::Arg { name, default } => {
+                let value = context
+                    .build_args
+                    .get(name)
+                    .cloned()
+                    .or_else(|| default.as_ref().map(|default| expand(default, &vars)));
+                if let Some(value) = value {
+                    self.args.insert(name.clone(), value.clone());
+                    resolved = value;
+                }
+            }
+            BuildInstruction::Workdir { path } => {
+                self.config.working_dir = self.resolve(&expand(path, &vars));
+            }
+            BuildInstruction::User { user, group } => {
+                let user = expand(user, &vars);
+                self.config.user = match group {
+                    Some(group) => format!("{}:{}", user, expand(group, &vars)),
+                    None => user,
+                };
+            }
+            BuildInstruction::Expose { port, protocol } => {
+                self.config
+                    .exposed_ports
+                    .insert(format!("{}/{}", port, protocol), HashMap::new());
+            }
+            BuildInstruction::Volume { paths } => {
+                for path in paths {
+                    self.config
+                        .volumes
+                        .insert(expand(path, &vars), HashMap::new());
+                }
+            }
+            BuildInstruction::Label { labels } => {
+                for (key, value) in labels {
+                    self.config
+                        .labels
+                        .insert(expand(key, &vars), expand(value, &vars));
+                }
+            }
+            BuildInstruction::Cmd { command, shell } => {
+                self.config.cmd = self.command(command, *shell);
+                self.cmd_set = true;
+            }
+            BuildInstruction::Entrypoint { command, shell } => {
+                self.config.entrypoint = self.command(command, *shell);
+                // A CMD from the base image was meant for its entrypoint
+                if !self.cmd_set {
+                    self.config.cmd.clear();
+                }
+            }
+            BuildInstruction::Healthcheck {
+                cmd,
+                interval,
+                timeout,
+                start_period,
+                retries,
+            } => {
+                let duration = |value: &Option<String>| -> Result<u64> {
+                    value
+                        .as_deref()
+                        .map_or(Ok(0), |value| Ok(parse_duration(value)?.max(0) as u64))
+                };
+                self.config.healthcheck = Some(HealthConfig {
+                    test: match cmd {
+                        Some(cmd) => vec!["CMD-SHELL".to_string(), cmd.clone()],
+                        None => vec!["NONE".to_string()],
+                    },
+                    interval: duration(interval)?,
+                    timeout: duration(timeout)?,
+                    start_period: duration(start_period)?,
+                    retries: retries.unwrap_or(0),
+                });
+            }
+            BuildInstruction::Stopsignal { signal } => {
+                let signal = expand(signal, &vars);
+                parse_signal(&signal)?;
+                self.config.stop_signal = signal;
+            }
+            BuildInstruction::Shell { shell } => {
+                self.config.shell = shell.clone();
+            }
+            BuildInstruction::Onbuild { instruction } => {
+                self.config.on_build.push(instruction.to_string());
+            }
+            BuildInstruction::From { .. }
+            | BuildInstruction::Run { .. }
+            | BuildInstruction::Copy { .. }
+            | BuildInstruction::Add { .. } => {}
+        }
+        self.key = self.chain(&[&canonical_json(instruction)?, &resolved]);
+        Ok(())
+    }
+
+    /// Command of a CMD or ENTRYPOINT; the shell form runs in SHELL
+    fn command(&self, command: &[String], shell: bool) -> Vec<String> {
+        if !shell {
+            return command.to_vec();
+        }
+        let mut argv = match self.config.shell.is_empty() {
+            true => vec!["/bin/sh".to_string(), "-c".to_string()],
+            false => self.config.shell.clone(),
+        };
+        argv.push(command.join(" "));
+        argv
+    }
+
+    /// Variables instructions can refer to: the declared build arguments
+    /// and the environment, which wins
+    fn vars(&self) -> HashMap<String, String> {
+        let mut vars: HashMap<String, String> = self.args.clone().into_iter().collect();
+        for var in &self.config.env {
+            let (name, value) = var.split_once('=').unwrap_or((var, ""));
+            vars.insert(name.to_string(), value.to_string());
+        }
+        vars
+    }
+
+    /// Absolute path in the image of a path relative to WORKDIR
+    fn resolve(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            return path.to_string();
+        }
+        let workdir = self.config.working_dir.trim_end_matches('/');
+        let resolved = format!("{}/{}", workdir, path.trim_start_matches("./"));
+        match resolved.starts_with('/') {
+            true => resolved,
+            false => format!("/{}", resolved),
+        }
+    }
+
+    /// Key of the next step: the current key and what the step depends on
+    fn chain(&self, parts: &[&str]) -> String {
+        let mut input = self.key.clone();
+        for part in parts {
+            input.push('\n');
+            input.push_str(part);
+        }
+        sha256_digest(input.as_bytes())
+    }
+}
+
+/// Whether an instruction is a step of its own, making a layer
+fn is_step(instruction: &BuildInstruction) -> bool {
+    matches!(
+        instruction,
+        BuildInstruction::Run { .. } | BuildInstruction::Copy { .. } | BuildInstruction::Add { .. }
+    )
+}
+
+/// An image from the store, or pulled if it is not there or `pull` is set
+async fn image(store: &ImageStore, reference: &str, pull: bool) -> Result<Image> {
+    match store.get(reference) {
+        Ok(image) if !pull => Ok(image),
+        _ => Ok(ImagePuller::new(RegistryHosts::default())?
+            .pull(reference, store)
+            .await?
+            .image),
+    }
+}
+
+/// Index of the stage before `before` a FROM or `COPY --from` refers to,
+/// by name or index
+fn stage_index(stages: &[BuildStage], before: usize, reference: &str) -> Option<usize> {
+    stages[..before]
+        .iter()
+        .rposition(|stage| stage.name.as_deref() == Some(reference))
+        .or_else(|| reference.parse().ok().filter(|index| *index < before))
+}
+
+/// References a stage makes to earlier stages, with the stages' indexes:
+/// its FROM by name, and its `COPY --from`s by name or index
+pub(super) fn stage_deps(stages: &[BuildStage], index: usize) -> Vec<(String, usize)> {
+    let stage = &stages[index];
+    let mut deps: Vec<(String, usize)> = Vec::new();
+    if stage.base_tag.is_none() {
+        if let Some(dep) = stages[..index]
+            .iter()
+            .rposition(|earlier| earlier.name.as_deref() == Some(stage.base_image.as_str()))
+        {
+            deps.push((stage.base_image.clone(), dep));
+        }
+    }
+    for instruction in &stage.instructions {
+        if let BuildInstruction::Copy {
+            from: Some(from), ..
+        } = instruction
+        {
+            if let Some(dep) = stage_index(stages, index, from) {
+                if !deps.iter().any(|(known, _)| known == from) {
+                    deps.push((from.clone(), dep));
+                }
+            }
+        }
+    }
+    deps
+}
+
+/// A file to COPY or ADD
+enum Source {
+    /// File or directory in the source root
+    Local(PathBuf),
+    /// Downloaded file and its name
+    Remote(String, Vec<u8>),
+}
+
+/// Paths under `root` a source names, expanding `*`, `?` and `[...]` in its
+/// last component; what the root's ignore file matches is left out
+fn glob(root: &ContextDir, src: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(src);
+    if path
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+        return Err(RuneError::Build(format!(
+            "{} is outside of the build context",
+            src
+        )));
+    }
+    let relative = relative(src);
+    let name = relative
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?', '[']) {
+        let full = root.path.join(&relative);
+        if root.excludes(&full) {
+            return Err(RuneError::Build(format!(
+                "{} is excluded by {}",
+                src, IGNORE_FILE
+            )));
+        }
+        return match std::fs::symlink_metadata(&full) {
+            Ok(_) => Ok(vec![full]),
+            Err(_) => Err(RuneError::Build(format!(
+                "{}: no such file or directory",
+                src
+            ))),
+        };
+    }
+
+    let parent = root
+        .path
+        .join(relative.parent().unwrap_or_else(|| Path::new("")));
+    let pattern = regex::Regex::new(&glob_regex(&name))
+        .map_err(|e| RuneError::Build(format!("Invalid pattern {}: {}", src, e)))?;
+    let mut matches: Vec<PathBuf> = std::fs::read_dir(&parent)
+        .map_err(|e| RuneError::Build(format!("{}: {}", src, e)))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| pattern.is_match(&entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .filter(|path| !root.excludes(path))
+        .collect();
+    if matches.is_empty() {
+        return Err(RuneError::Build(format!("{}: no source files match", src)));
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Path in a layer of an absolute path in the image
+fn relative(path: &str) -> PathBuf {
+    Path::new(path.trim_start_matches('/')).to_path_buf()
+}
+
+fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [".tar", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+fn set_owner(header: &mut tar::Header, owner: Option<(u32, u32)>) {
+    let (uid, gid) = owner.unwrap_or((0, 0));
+    header.set_uid(uid as u64);
+    header.set_gid(gid as u64);
+}
+
+/// Add a file, directory or symlink to a layer as `target`
+fn append_entry(
+    layer: &mut tar::Builder<Vec<u8>>,
+    path: &Path,
+    target: &Path,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+    set_owner(&mut header, owner);
+    if metadata.file_type().is_symlink() {
+        header.set_size(0);
+        layer.append_link(&mut header, target, std::fs::read_link(path)?)?;
+    } else if metadata.is_dir() {
+        header.set_size(0);
+        layer.append_data(&mut header, target, std::io::empty())?;
+    } else if metadata.is_file() {
+        layer.append_data(&mut header, target, std::fs::File::open(path)?)?;
+    }
+    Ok(())
+}
+
+/// Add the contents of a directory under `root` to a layer under `dest`,
+/// leaving out what the root's ignore file matches
+fn append_tree(
+    layer: &mut tar::Builder<Vec<u8>>,
+    root: &ContextDir,
+    dir: &Path,
+    dest: &Path,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    if !dest.as_os_str().is_empty() {
+        append_entry(layer, dir, dest, owner)?;
+    }
+    for entry in WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+        let entry = entry
+            .map_err(|e| RuneError::Build(format!("Failed to read {}: {}", dir.display(), e)))?;
+        if root.excludes(entry.path()) {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(dir)
+            .map_err(|e| RuneError::Build(e.to_string()))?;
+        append_entry(layer, entry.path(), &dest.join(name), owner)?;
+    }
+    Ok(())
+}
+
+/// Add the entries of a local tar archive, gzipped or not, under `dest`
+fn append_archive(
+    layer: &mut tar::Builder<Vec<u8>>,
+    path: &Path,
+    dest: &Path,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(decompress(std::fs::File::open(path)?)?);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.into_owned();
+        if name
+            .components()
+            .any(|component| matches!(component, Component::ParentDir))
+        {
+            return Err(RuneError::Build(format!(
+                "{} in {} escapes the destination",
+                name.display(),
+                path.display()
+            )));
+        }
+        let mut header = entry.header().clone();
+        if owner.is_some() {
+            set_owner(&mut header, owner);
+        }
+        let target = dest.join(name);
+        match entry.link_name()? {
+            Some(link) => {
+                let link = link.into_owned();
+                layer.append_link(&mut header, &target, link)?;
+            }
+            None => layer.append_data(&mut header, &target, entry)?,
+        }
+    }
+    Ok(())
+}
+
+/// User and group IDs of `user[:group]`, looking names up in the root
+/// filesystem's /etc/passwd and /etc/group
+fn resolve_user(rootfs: &Path, spec: &str) -> Result<(u32, u32)> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let entries = |file: &str| -> Vec<Vec<String>> {
+        std::fs::read_to_string(rootfs.join("etc").join(file))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.split(':').map(str::to_string).collect())
+            .collect()
+    };
+    let id = |entry: &Vec<String>, field: usize| entry.get(field).and_then(|id| id.parse().ok());
+
+    let passwd = entries("passwd");
+    let (uid, primary) = match user.parse::<u32>() {
+        Ok(uid) => {
+            let entry = passwd.iter().find(|entry| id(entry, 2) == Some(uid));
+            (uid, entry.and_then(|entry| id(entry, 3)).unwrap_or(uid))
+        }
+        Err(_) => passwd
+            .iter()
+            .find(|entry| entry[0] == user)
+            .and_then(|entry| Some((id(entry, 2)?, id(entry, 3)?)))
+            .ok_or_else(|| RuneError::Build(format!("Unknown user {}", user)))?,
+    };
+    let gid = match group {
+        None => primary,
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => entries("group")
+                .iter()
+                .find(|entry| entry[0] == group)
+                .and_then(|entry| id(entry, 2))
+                .ok_or_else(|| RuneError::Build(format!("Unknown group {}", group)))?,
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Expand `$NAME`, `${NAME}`, `${NAME:-default}` and `${NAME:+value}`
+fn expand(value: &str, vars: &HashMap<String, String>) -> String {
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                expanded.push('$');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let inner: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (name, modifier) = match inner.split_once(':') {
+                    Some((name, modifier)) => (name, Some(modifier)),
+                    None => (inner.as_str(), None),
+                };
+                let value = vars.get(name).filter(|value| !value.is_empty());
+                match (modifier.and_then(|m| m.split_at_checked(1)), value) {
+                    (Some(("-", default)), None) => expanded.push_str(default),
+                    (Some(("+", alternative)), Some(_)) => expanded.push_str(alternative),
+                    (Some(("+", _)), None) => {}
+                    (_, value) => expanded.push_str(value.map_or("", String::as_str)),
+                }
+            }
+            '$' if chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+            {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                expanded.push_str(vars.get(&name).map_or("", String::as_str));
+            }
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// JSON of a value with its map keys sorted, for cache keys and image IDs
+pub(super) fn canonical_json<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_value(value)?.to_string())
+}
+
+fn hex(digest: &str) -> &str {
+    digest.split_once(':').map_or(digest, |(_, hex)| hex)
+}
+
+/// Error of the steps a cancelled build doesn't run
+fn cancelled() -> RuneError {
+    RuneError::Build("Build cancelled".to_string())
+}
+
+/// Kill a RUN command and whatever it started
+fn kill_group(pgid: u32) {
+    // SAFETY: kill only sends a signal
+    unsafe { libc::kill(-(pgid as libc::pid_t), libc::SIGKILL) };
+}
+
+/// What a RUN command's process does before it executes: moving into the
+/// stage's root filesystem, with /proc and /dev, as the stage's user
+struct Jail {
+    rootfs: CString,
+    proc: Option<CString>,
+    dev: Option<CString>,
+    workdir: CString,
+    user: Option<(u32, u32)>,
+}
+
+impl Jail {
+    fn new(rootfs: &Path, workdir: &str, user: Option<(u32, u32)>) -> Result<Self> {
+        let c_path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|_| RuneError::Build(format!("Invalid path {}", path.display())))
+        };
+        let mount_point = |name: &str| {
+            let path = rootfs.join(name);
+            match path.is_dir() {
+                true => c_path(&path).map(Some),
+                false => Ok(None),
+            }
+        };
+        Ok(Self {
+            rootfs: c_path(rootfs)?,
+            proc: mount_point("proc")?,
+            dev: mount_point("dev")?,
+            workdir: c_path(Path::new(workdir))?,
+            user,
+        })
+    }
+
+    /// Runs in the forked child, so it only makes system calls
+    fn enter(&self) -> std::io::Result<()> {
+        let check = |result: libc::c_int| match result {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        };
+        let null = std::ptr::null();
+        // SAFETY: every pointer is null or a NUL-terminated string owned
+        // by self
+        unsafe {
+            check(libc::unshare(libc::CLONE_NEWNS))?;
+            check(libc::mount(
+                null,
+                c"/".as_ptr(),
+                null,
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            if let Some(proc) = &self.proc {
+                check(libc::mount(
+                    c"proc".as_ptr(),
+                    proc.as_ptr(),
+                    c"proc".as_ptr(),
+                    0,
+                    std::ptr::null(),
+                ))?;
+            }
+            if let Some(dev) = &self.dev {
+                check(libc::mount(
+                    c"/dev".as_ptr(),
+                    dev.as_ptr(),
+                    null,
+                    libc::MS_BIND | libc::MS_REC,
+                    std::ptr::null(),
+                ))?;
+            }
+            check(libc::chroot(self.rootfs.as_ptr()))?;
+            check(libc::chdir(self.workdir.as_ptr()))?;
+            if let Some((uid, gid)) = self.user {
+                check(libc::setgroups(0, std::ptr::null()))?;
+                check(libc::setgid(gid))?;
+                check(libc::setuid(uid))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let vars = HashMap::from([
+            ("VERSION".to_string(), "1.2".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        assert_eq!(expand("app-$VERSION.tar", &vars), "app-1.2.tar");
+        assert_eq!(expand("${VERSION}_x", &vars), "1.2_x");
+        assert_eq!(expand("${EMPTY:-none} ${MISSING}", &vars), "none ");
+        assert_eq!(expand("${VERSION:+set}${EMPTY:+set}", &vars), "set");
+        assert_eq!(expand("\\$VERSION costs $5", &vars), "$VERSION costs $5");
+    }
+
+    #[test]
+    fn test_cancel() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ImageStore::new(dir.path().join("images")).unwrap());
+        let context = Arc::new(BuildContext::new(dir.path().to_path_buf()));
+        let runner = StageRunner::new(store, context, None).unwrap();
+        let spawn = || {
+            Command::new("sleep")
+                .arg("30")
+                .process_group(0)
+                .spawn()
+                .unwrap()
+        };
+
+        let mut running = spawn();
+        runner.track(running.id()).unwrap();
+        runner.check_cancelled().unwrap();
+        runner.cancel();
+        assert_eq!(running.wait().unwrap().signal(), Some(libc::SIGKILL));
+        assert!(runner.check_cancelled().is_err());
+
+        // Commands started as the build is cancelled don't outlive it
+        let mut late = spawn();
+        assert!(runner.track(late.id()).is_err());
+        assert_eq!(late.wait().unwrap().signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn test_glob_and_users() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        let context = ContextDir::new(root.to_path_buf());
+        for name in ["a.txt", "b.txt", "c.md"] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+        let names = |src| -> Vec<String> {
+            glob(&context, src)
+                .unwrap()
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(names("*.txt"), ["a.txt", "b.txt"]);
+        assert_eq!(names("./[!a]*"), ["b.txt", "c.md"]);
+        assert!(glob(&context, "*.rs").is_err());
+        assert!(glob(&context, "../etc/passwd").is_err());
+
+        std::fs::create_dir(root.join("etc")).unwrap();
+        std::fs::write(
+            root.join("etc/passwd"),
+            "app:x:1000:1001::/home/app:/bin/sh\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("etc/group"), "staff:x:50:\n").unwrap();
+        assert_eq!(resolve_user(root, "app").unwrap(), (1000, 1001));
+        assert_eq!(resolve_user(root, "app:staff").unwrap(), (1000, 50));
+        assert_eq!(resolve_user(root, "42:7").unwrap(), (42, 7));
+        assert!(resolve_user(root, "nobody").is_err());
+    }
+}
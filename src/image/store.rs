@@ -1,12 +1,19 @@
 //! Image store - manages local container images
 
+use super::analyze::{self, ImageAnalysis, ImageDiff, LayerEntry};
+use super::archive;
 use crate::error::{Result, RuneError};
+use crate::filter::{self, Filters};
+use crate::runtime::userns::UsernsRemap;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
+/// Filters images can be listed by
+pub const IMAGE_FILTERS: &[&str] = &["before", "dangling", "label", "reference", "since"];
+
 /// Container image
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
@@ -224,6 +231,38 @@ impl ImageStore {
         Ok(images.values().cloned().collect())
     }
 
+    /// List images matching filters
+    pub fn list_filtered(&self, filters: &Filters) -> Result<Vec<Image>> {
+        filters.validate(IMAGE_FILTERS)?;
+        let dangling = filters.bool("dangling")?;
+        let created = |key: &str| -> Result<Vec<_>> {
+            filters
+                .get(key)
+                .iter()
+                .map(|reference| self.get(reference).map(|image| image.created))
+                .collect()
+        };
+        let before = created("before")?;
+        let since = created("since")?;
+
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|image| {
+                dangling.is_none_or(|dangling| image.repo_tags.is_empty() == dangling)
+                    && filters.matches_labels(&image.config.labels)
+                    && filters.matches("reference", |reference| {
+                        image
+                            .repo_tags
+                            .iter()
+                            .any(|tag| filter::image_matches(tag, reference))
+                    })
+                    && before.iter().all(|t| image.created < *t)
+                    && since.iter().all(|t| image.created > *t)
+            })
+            .collect())
+    }
+
     /// Remove an image
     pub fn remove(&self, reference: &str, force: bool) -> Result<()> {
         let mut images = self
@@ -306,6 +345,60 @@ impl ImageStore {
         Ok(())
     }
 
+    /// Path of a stored layer
+    pub fn layer_path(&self, digest: &str) -> PathBuf {
+        let digest = digest.split_once(':').map_or(digest, |(_, hex)| hex);
+        self.storage_path.join("layers").join(digest)
+    }
+
+    /// Unpack an image's layers into a root filesystem, shifting file
+    /// owners into the remapped range if given
+    pub fn unpack(&self, reference: &str, dest: &Path, remap: Option<&UsernsRemap>) -> Result<()> {
+        let image = self.get(reference)?;
+        for digest in &image.layers {
+            let path = self.layer_path(digest);
+            let file = std::fs::File::open(&path)
+                .map_err(|e| RuneError::Image(format!("Failed to open layer {}: {}", digest, e)))?;
+            super::layer::unpack_layer(file, dest, remap)?;
+        }
+        Ok(())
+    }
+
+    /// What each of an image's layers adds, modifies and removes, and the
+    /// space files overwritten or deleted by later layers waste
+    pub fn analyze(&self, reference: &str) -> Result<ImageAnalysis> {
+        Ok(analyze::analyze_layers(&self.layer_entries(reference)?))
+    }
+
+    /// How the root filesystem of one image differs from another's
+    pub fn diff(&self, from: &str, to: &str) -> Result<ImageDiff> {
+        Ok(analyze::diff_layers(
+            &self.layer_entries(from)?,
+            &self.layer_entries(to)?,
+        ))
+    }
+
+    /// Load the images of a `docker save` or OCI layout archive, either a
+    /// tarball or unpacked, or of every archive in a preload directory
+    pub fn load(&self, path: &Path) -> Result<Vec<Image>> {
+        archive::load(self, path)
+    }
+
+    /// Digests and entries of an image's layers, bottom first
+    fn layer_entries(&self, reference: &str) -> Result<Vec<(String, Vec<LayerEntry>)>> {
+        let image = self.get(reference)?;
+        image
+            .layers
+            .iter()
+            .map(|digest| {
+                let file = std::fs::File::open(self.layer_path(digest)).map_err(|e| {
+                    RuneError::Image(format!("Failed to open layer {}: {}", digest, e))
+                })?;
+                Ok((digest.clone(), analyze::read_layer(file)?))
+            })
+            .collect()
+    }
+
     /// Get storage path
     pub fn storage_path(&self) -> &PathBuf {
         &self.storage_path
@@ -335,3 +428,113 @@ impl ImageStore {
         Ok(dangling)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_list_filtered() {
+        let dir = TempDir::new().unwrap();
+        let store = ImageStore::new(dir.path().to_path_buf()).unwrap();
+        for (i, (id, tags)) in [
+            ("aaa", vec!["nginx:1.25"]),
+            ("bbb", vec![]),
+            ("ccc", vec!["redis:latest"]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut image = Image {
+                id: id.to_string(),
+                repo_tags: tags.into_iter().map(str::to_string).collect(),
+                created: Utc::now() + chrono::Duration::seconds(i as i64),
+                ..Default::default()
+            };
+            if id == "ccc" {
+                image
+                    .config
+                    .labels
+                    .insert("tier".to_string(), "cache".to_string());
+            }
+            store.store(image).unwrap();
+        }
+
+        let ids = |filters: Filters| -> Vec<String> {
+            let mut ids: Vec<String> = store
+                .list_filtered(&filters)
+                .unwrap()
+                .into_iter()
+                .map(|image| image.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(Filters::new()).len(), 3);
+        assert_eq!(ids(Filters::new().with("dangling", "true")), ["bbb"]);
+        assert_eq!(
+            ids(Filters::new().with("dangling", "false")),
+            ["aaa", "ccc"]
+        );
+        assert_eq!(ids(Filters::new().with("reference", "nginx")), ["aaa"]);
+        assert_eq!(ids(Filters::new().with("label", "tier=cache")), ["ccc"]);
+        assert_eq!(
+            ids(Filters::new().with("since", "nginx:1.25")),
+            ["bbb", "ccc"]
+        );
+        assert_eq!(ids(Filters::new().with("before", "bbb")), ["aaa"]);
+        assert!(store
+            .list_filtered(&Filters::new().with("before", "gone"))
+            .is_err());
+        assert!(store
+            .list_filtered(&Filters::new().with("status", "x"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_analyze_and_diff() {
+        let dir = TempDir::new().unwrap();
+        let store = ImageStore::new(dir.path().to_path_buf()).unwrap();
+        let write_layer = |digest: &str, entries: &[(&str, &[u8])]| {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (path, data) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, path, *data).unwrap();
+            }
+            std::fs::write(store.layer_path(digest), builder.into_inner().unwrap()).unwrap();
+        };
+        write_layer(
+            "sha256:base",
+            &[("etc/motd", b"hello"), ("tmp/build.log", b"log")],
+        );
+        write_layer("sha256:clean", &[("tmp/.wh.build.log", b"")]);
+        for (id, layers) in [
+            ("app", vec!["sha256:base", "sha256:clean"]),
+            ("base", vec!["sha256:base"]),
+        ] {
+            store
+                .store(Image {
+                    id: id.to_string(),
+                    layers: layers.into_iter().map(str::to_string).collect(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let analysis = store.analyze("app").unwrap();
+        assert_eq!(analysis.layers.len(), 2);
+        assert_eq!(analysis.size(), 8);
+        assert_eq!(analysis.wasted_size(), 3);
+
+        let diff = store.diff("base", "app").unwrap();
+        assert_eq!(diff.shared_layers, 1);
+        assert_eq!(diff.layers.len(), 1);
+        assert_eq!(diff.layers[0].digest, "sha256:clean");
+        assert_eq!(diff.layers[0].changes[0].path, "/tmp/build.log");
+        assert!(store.analyze("missing").is_err());
+    }
+}
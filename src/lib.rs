@@ -5,6 +5,7 @@
 //!
 //! - Container lifecycle management
 //! - Image building (from Runefile or Dockerfile)
+//! - Image layer diffs and wasted space analysis (`image diff`, `image analyze`)
 //! - Docker Compose compatibility
 //! - Docker Swarm compatibility
 //! - OCI-compatible container registry
@@ -18,9 +19,21 @@
 //! without external dependencies. The runtime provides:
 //!
 //! - Linux namespace isolation (PID, NET, MNT, UTS, IPC, USER, CGROUP)
-//! - Cgroup v1/v2 resource management
+//! - Cgroup v1/v2 resource management, preferring the unified hierarchy,
+//!   with OOM notifications and pressure stall (PSI) metrics
 //! - Root filesystem setup with pivot_root
 //! - Process execution and management
+//! - Seccomp filtering with Docker's default syscall allowlist
+//! - AppArmor profiles and SELinux labels, depending on the host's LSM
+//! - Daemon-wide user namespace remapping (`userns-remap`) onto subordinate
+//!   uid/gid ranges
+//! - OCI runtime spec bundles, run by the built-in runtime or an external
+//!   OCI runtime such as runc, crun or youki
+//! - Checkpoint and restore of running containers with CRIU
+//! - Built-in init (`--init`) that forwards signals and reaps zombies
+//! - Device passthrough (`--device`) and GPUs (`--gpus`) from CDI specs
+//! - OCI lifecycle hooks, per container and daemon-wide
+//! - Ulimits, namespaced sysctls and OOM tuning per container
 //!
 //! ## Healthcheck Support
 //!
@@ -32,6 +45,31 @@
 //! - `--timeout`: Check timeout (default: 30s)
 //! - `--start-period`: Grace period on startup (default: 0s)
 //! - `--retries`: Consecutive failures needed (default: 3)
+//!
+//! The daemon runs each running container's check inside it, reports its
+//! health in `ps`, inspect and the API, and emits `health_status` events
+//! when it changes.
+//!
+//! ## Log Drivers
+//!
+//! Container output goes to a log driver chosen with `--log-driver` and
+//! `--log-opt`, or to the daemon's default: `json-file` (the default) or
+//! `local`, both rotated by `max-size` and `max-file`, `journald` or
+//! `syslog`.
+//!
+//! ## Plugins
+//!
+//! Volume, network and log drivers can also come from plugins speaking
+//! Docker's plugin protocol on a Unix socket, managed with `rune plugin
+//! install`, `enable`, `disable`, `ls` and `rm`.
+//!
+//! ## Filters
+//!
+//! `ps`, `image ls`, `network ls` and `volume ls` take `--filter key=value`,
+//! and the matching API endpoints a `filters` query parameter, with the
+//! keys Docker accepts: `status`, `name`, `label`, `ancestor`, `before` and
+//! `since` for containers, and `dangling`, `label`, `reference`, `driver`
+//! and the like for images, networks and volumes.
 
 #![recursion_limit = "256"]
 
@@ -39,13 +77,15 @@ pub mod compose;
 pub mod container;
 pub mod daemon;
 pub mod error;
+pub mod filter;
 pub mod image;
 pub mod lsp;
 pub mod network;
+pub mod plugin;
 pub mod registry;
 pub mod runtime;
 pub mod storage;
 pub mod swarm;
 pub mod tui;
 
-pub use error::{Result, RuneError};
+pub use error::{ErrorKind, Result, ResultExt, RuneError};
@@ -0,0 +1,316 @@
+//! Code Action Provider for Runefile LSP
+
+use super::server::{CodeAction, Position, Range, TextEdit, WorkspaceEdit};
+use super::syntax::{is_absolute_path, Instruction, InstructionKind, RunefileParser};
+
+/// Characters that need a shell, so shell form cannot be split into words
+const SHELL_SYNTAX: &[char] = &['$', '&', '|', ';', '<', '>', '`', '*', '?', '(', ')', '~'];
+
+/// Code action provider for Runefile
+pub struct CodeActionProvider {}
+
+impl CodeActionProvider {
+    /// Create a new code action provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Get code actions for the instructions within a range
+    pub fn get_code_actions(
+        &self,
+        uri: &str,
+        content: &str,
+        parser: &RunefileParser,
+        range: &Range,
+    ) -> Vec<CodeAction> {
+        let lines: Vec<&str> = content.lines().collect();
+        let mut actions = Vec::new();
+
+        for (index, inst) in parser.instructions.iter().enumerate() {
+            if inst.line > range.end.line as usize || inst.end_line < range.start.line as usize {
+                continue;
+            }
+
+            match &inst.kind {
+                InstructionKind::Unknown(keyword) => {
+                    if let Some(suggestion) = InstructionKind::suggest(keyword) {
+                        actions.push(action(
+                            format!("Change to {}", suggestion),
+                            "quickfix",
+                            uri,
+                            replace(inst.line, inst.keyword_span, suggestion),
+                        ));
+                    }
+                }
+                InstructionKind::Cmd | InstructionKind::Entrypoint => {
+                    if let Some(edit) = exec_form(inst) {
+                        actions.push(action(
+                            "Convert to exec form".to_string(),
+                            "refactor.rewrite",
+                            uri,
+                            edit,
+                        ));
+                    }
+                }
+                InstructionKind::From => {
+                    if let Some((image, edit)) = pin_digest(inst, parser) {
+                        actions.push(action(
+                            format!("Pin {} to a digest", image),
+                            "refactor.rewrite",
+                            uri,
+                            edit,
+                        ));
+                    }
+                }
+                InstructionKind::Workdir if !is_absolute_path(&inst.arguments) => {
+                    if let Some((start, _)) = inst.arguments_span {
+                        actions.push(action(
+                            "Make WORKDIR absolute".to_string(),
+                            "quickfix",
+                            uri,
+                            replace(inst.line, (start, start), "/"),
+                        ));
+                    }
+                }
+                InstructionKind::Run => {
+                    let next = parser.instructions.get(index + 1);
+                    if let Some(edit) = next.and_then(|next| merge_runs(&lines, inst, next)) {
+                        actions.push(action(
+                            "Merge with the next RUN".to_string(),
+                            "refactor.rewrite",
+                            uri,
+                            edit,
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        actions
+    }
+}
+
+impl Default for CodeActionProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build a code action editing a single document
+fn action(title: String, kind: &str, uri: &str, edit: TextEdit) -> CodeAction {
+    let mut workspace_edit = WorkspaceEdit::default();
+    workspace_edit.changes.insert(uri.to_string(), vec![edit]);
+    CodeAction {
+        title,
+        kind: Some(kind.to_string()),
+        edit: Some(workspace_edit),
+    }
+}
+
+/// Replace a span of a line
+fn replace(line: usize, (start, end): (usize, usize), text: &str) -> TextEdit {
+    TextEdit {
+        range: Range {
+            start: Position {
+                line: line as u32,
+                character: start as u32,
+            },
+            end: Position {
+                line: line as u32,
+                character: end as u32,
+            },
+        },
+        new_text: text.to_string(),
+    }
+}
+
+/// Rewrite shell-form CMD or ENTRYPOINT arguments as a JSON array
+///
+/// Commands using shell syntax are wrapped in `/bin/sh -c` so they behave
+/// the same.
+fn exec_form(inst: &Instruction) -> Option<TextEdit> {
+    // Spans of continued instructions do not map onto the document
+    let span = inst.arguments_span.filter(|_| inst.line == inst.end_line)?;
+    let command = inst.arguments.trim();
+    if command.starts_with('[') {
+        return None;
+    }
+
+    let words = shell_words(command)
+        .unwrap_or_else(|| vec!["/bin/sh".to_string(), "-c".to_string(), command.to_string()]);
+    let array = words
+        .iter()
+        .map(|word| serde_json::Value::from(word.as_str()).to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(replace(inst.line, span, &format!("[{}]", array)))
+}
+
+/// Split a command into words, honouring quotes and backslash escapes
+///
+/// Returns `None` for unbalanced quotes or commands that need a shell.
+fn shell_words(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) if SHELL_SYNTAX.contains(&c) => return None,
+            (Some('"'), '$' | '`') => return None,
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => word.get_or_insert_with(String::new).push(chars.next()?),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return None;
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// Append a digest placeholder to an image that is not pinned
+fn pin_digest(inst: &Instruction, parser: &RunefileParser) -> Option<(String, TextEdit)> {
+    let (start, _) = inst.arguments_span.filter(|_| inst.line == inst.end_line)?;
+    let args = &inst.raw[start..];
+    let image = args
+        .split_whitespace()
+        .find(|word| !word.starts_with("--"))?;
+    let is_stage = parser.references_to(image).any(|r| r.line == inst.line);
+    if image.contains(['@', '$']) || image.eq_ignore_ascii_case("scratch") || is_stage {
+        return None;
+    }
+
+    let end = start + args.find(image)? + image.len();
+    Some((
+        image.to_string(),
+        replace(inst.line, (end, end), "@sha256:<digest>"),
+    ))
+}
+
+/// Join a shell-form RUN with the RUN right after it using `&&`
+fn merge_runs(lines: &[&str], first: &Instruction, second: &Instruction) -> Option<TextEdit> {
+    let is_shell_form = |inst: &Instruction| {
+        inst.kind == InstructionKind::Run
+            && !inst.arguments.starts_with('[')
+            && !inst.arguments.contains("<<")
+    };
+    // Flags such as --mount cannot move into the middle of a command
+    if !is_shell_form(first) || !is_shell_form(second) || second.arguments.starts_with("--") {
+        return None;
+    }
+
+    let first_text = lines.get(first.line..=first.end_line)?.join("\n");
+    let second_text = lines.get(second.line..=second.end_line)?.join("\n");
+    let second_command = second_text
+        .trim_start()
+        .get(second.keyword_span.1 - second.keyword_span.0..)?
+        .trim_start();
+    let indent = " ".repeat(first.arguments_span?.0);
+    let end = lines[second.end_line].len();
+
+    Some(TextEdit {
+        range: Range {
+            start: Position {
+                line: first.line as u32,
+                character: 0,
+            },
+            end: Position {
+                line: second.end_line as u32,
+                character: end as u32,
+            },
+        },
+        new_text: format!(
+            "{} && \\\n{}{}",
+            first_text.trim_end(),
+            indent,
+            second_command
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(content: &str, line: u32) -> Vec<CodeAction> {
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        let position = Position { line, character: 0 };
+        CodeActionProvider::new().get_code_actions(
+            "file:///Runefile",
+            content,
+            &parser,
+            &Range {
+                start: position,
+                end: position,
+            },
+        )
+    }
+
+    fn first_edit(action: &CodeAction) -> &TextEdit {
+        &action.edit.as_ref().unwrap().changes["file:///Runefile"][0]
+    }
+
+    #[test]
+    fn test_exec_form() {
+        let content = "FROM alpine\nCMD nginx -g 'daemon off;'\nENTRYPOINT echo $HOME\n";
+
+        let cmd = actions(content, 1);
+        assert_eq!(cmd[0].title, "Convert to exec form");
+        assert_eq!(
+            first_edit(&cmd[0]).new_text,
+            r#"["nginx", "-g", "daemon off;"]"#
+        );
+
+        let entrypoint = actions(content, 2);
+        assert_eq!(
+            first_edit(&entrypoint[0]).new_text,
+            r#"["/bin/sh", "-c", "echo $HOME"]"#
+        );
+    }
+
+    #[test]
+    fn test_quick_fixes() {
+        let content = "FORM alpine:3.19 AS base\nFROM base\nWORKDIR app\n";
+
+        let misspelled = actions(content, 0);
+        assert_eq!(misspelled[0].title, "Change to FROM");
+        assert_eq!(misspelled[0].kind.as_deref(), Some("quickfix"));
+
+        let pinned = actions("FROM alpine:3.19 AS base\nFROM base\n", 0);
+        assert_eq!(pinned[0].title, "Pin alpine:3.19 to a digest");
+        assert_eq!(first_edit(&pinned[0]).range.start.character, 16);
+        // Stages are not pinned
+        assert!(actions("FROM alpine@sha256:abc AS base\nFROM base\n", 1).is_empty());
+
+        let workdir = actions(content, 2);
+        assert_eq!(workdir[0].title, "Make WORKDIR absolute");
+        assert_eq!(first_edit(&workdir[0]).range.start.character, 8);
+    }
+
+    #[test]
+    fn test_merge_runs() {
+        let content = "FROM alpine@sha256:abc\nRUN apk add curl\nRUN curl -V && \\\n    echo ok\nRUN --mount=type=cache echo\n";
+
+        let merge = actions(content, 1);
+        let edit = first_edit(&merge[0]);
+        assert_eq!(edit.range.end.line, 3);
+        assert_eq!(
+            edit.new_text,
+            "RUN apk add curl && \\\n    curl -V && \\\n    echo ok"
+        );
+
+        // The next RUN has flags
+        assert!(actions(content, 2).is_empty());
+    }
+}
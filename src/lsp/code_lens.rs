@@ -0,0 +1,303 @@
+//! Code Lens Provider for Runefile LSP
+//!
+//! Puts build commands above each FROM line. Running one shells out to
+//! `rune build` in the background, which builds the Runefile as saved on
+//! disk, and reports how it went as a message to show.
+
+use super::server::{CodeLens, Command, Position, Range, ShowMessageParams};
+use super::syntax::{InstructionKind, RunefileParser};
+use std::path::Path;
+use std::process;
+use std::thread;
+
+/// Command that builds one stage and the stages it depends on
+pub const BUILD_STAGE_COMMAND: &str = "runefile.buildStage";
+
+/// Command that builds every stage up to and including one
+pub const BUILD_UP_TO_COMMAND: &str = "runefile.buildUpTo";
+
+/// `window/showMessage` type of a failed build
+const MESSAGE_ERROR: u8 = 1;
+
+/// `window/showMessage` type of a finished build
+const MESSAGE_INFO: u8 = 3;
+
+/// A build stage of the document
+struct Stage {
+    line: usize,
+    keyword_span: (usize, usize),
+    name: Option<String>,
+}
+
+/// Code lens provider for Runefile
+pub struct CodeLensProvider {}
+
+impl CodeLensProvider {
+    /// Create a new code lens provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Get build lenses above each stage that can be built
+    ///
+    /// Stages are targeted by their `AS` name, so unnamed stages other than
+    /// the last get no lenses. Commands take the document URI and the stage
+    /// index as arguments.
+    pub fn get_code_lenses(&self, uri: &str, parser: &RunefileParser) -> Vec<CodeLens> {
+        let stages = stages(parser);
+        let last = stages.len().saturating_sub(1);
+        let mut lenses = Vec::new();
+
+        for (index, stage) in stages.iter().enumerate() {
+            if stage.name.is_none() && index != last {
+                continue;
+            }
+            let range = Range {
+                start: Position {
+                    line: stage.line as u32,
+                    character: stage.keyword_span.0 as u32,
+                },
+                end: Position {
+                    line: stage.line as u32,
+                    character: stage.keyword_span.1 as u32,
+                },
+            };
+            for (title, command) in [
+                ("Build this stage", BUILD_STAGE_COMMAND),
+                ("Build up to here", BUILD_UP_TO_COMMAND),
+            ] {
+                lenses.push(CodeLens {
+                    range,
+                    command: Some(Command {
+                        title: title.to_string(),
+                        command: command.to_string(),
+                        arguments: vec![uri.into(), index.into()],
+                    }),
+                });
+            }
+        }
+
+        lenses
+    }
+
+    /// Get the `rune build` arguments of a command, one list per build
+    pub fn build_arguments(
+        &self,
+        command: &str,
+        file: &Path,
+        parser: &RunefileParser,
+        index: usize,
+    ) -> Result<Vec<Vec<String>>, String> {
+        let stages = stages(parser);
+        if index >= stages.len() {
+            return Err(format!("Stage {} does not exist", index));
+        }
+        let targets: Vec<usize> = match command {
+            BUILD_STAGE_COMMAND => vec![index],
+            // Earlier unnamed stages are built when a later stage uses them
+            BUILD_UP_TO_COMMAND => (0..=index)
+                .filter(|&i| i == index || stages[i].name.is_some())
+                .collect(),
+            _ => return Err(format!("Unknown command '{}'", command)),
+        };
+
+        let context = file.parent().unwrap_or(Path::new("."));
+        targets
+            .into_iter()
+            .map(|i| {
+                let mut args = vec![
+                    "build".to_string(),
+                    "--file".to_string(),
+                    file.display().to_string(),
+                ];
+                match &stages[i].name {
+                    Some(name) => args.extend(["--target".to_string(), name.clone()]),
+                    None if i + 1 == stages.len() => {}
+                    None => {
+                        return Err(format!(
+                            "Stage {} has no name; add `AS <name>` to build it",
+                            i
+                        ))
+                    }
+                }
+                args.push(context.display().to_string());
+                Ok(args)
+            })
+            .collect()
+    }
+
+    /// Run a command's builds in order on their own thread, passing how
+    /// they went to `notify`
+    ///
+    /// Stops at the first build that fails. A command that cannot be run is
+    /// an error straight away.
+    pub fn execute(
+        &self,
+        command: &str,
+        file: &Path,
+        parser: &RunefileParser,
+        index: usize,
+        notify: impl FnOnce(ShowMessageParams) + Send + 'static,
+    ) -> Result<thread::JoinHandle<()>, String> {
+        let builds = self.build_arguments(command, file, parser, index)?;
+        Ok(thread::spawn(move || {
+            let message = match run_builds(&builds) {
+                Ok(message) => ShowMessageParams {
+                    typ: MESSAGE_INFO,
+                    message,
+                },
+                Err(message) => ShowMessageParams {
+                    typ: MESSAGE_ERROR,
+                    message,
+                },
+            };
+            notify(message);
+        }))
+    }
+}
+
+impl Default for CodeLensProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run `rune build` with each list of arguments in turn, stopping at the
+/// first that fails
+fn run_builds(builds: &[Vec<String>]) -> Result<String, String> {
+    for args in builds {
+        let result = process::Command::new("rune")
+            .args(args)
+            .output()
+            .map_err(|e| format!("Failed to run rune: {}", e))?;
+        if !result.status.success() {
+            return Err(format!(
+                "rune {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&result.stderr).trim()
+            ));
+        }
+    }
+    let last = builds.last().map(|args| args.join(" ")).unwrap_or_default();
+    Ok(format!("rune {} finished", last))
+}
+
+/// The stages of a document, in order
+fn stages(parser: &RunefileParser) -> Vec<Stage> {
+    parser
+        .instructions
+        .iter()
+        .filter(|inst| inst.kind == InstructionKind::From)
+        .map(|inst| Stage {
+            line: inst.line,
+            keyword_span: inst.keyword_span,
+            name: parser
+                .stage_definitions
+                .iter()
+                .find(|definition| definition.line == inst.line)
+                .map(|definition| definition.name.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str =
+        "FROM rust AS deps\nRUN cargo fetch\n\nFROM deps AS build\nRUN cargo build\n\nFROM alpine\nCOPY --from=build /app /app\n";
+
+    fn parse(content: &str) -> RunefileParser {
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        parser
+    }
+
+    #[test]
+    fn test_code_lenses() {
+        let parser = parse(CONTENT);
+        let lenses = CodeLensProvider::new().get_code_lenses("file:///app/Runefile", &parser);
+
+        let found: Vec<(u32, &str, serde_json::Value)> = lenses
+            .iter()
+            .map(|lens| {
+                let command = lens.command.as_ref().unwrap();
+                (
+                    lens.range.start.line,
+                    command.title.as_str(),
+                    command.arguments[1].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(found.len(), 6);
+        assert_eq!(found[0], (0, "Build this stage", 0.into()));
+        assert_eq!(found[3], (3, "Build up to here", 1.into()));
+        assert_eq!(found[4], (6, "Build this stage", 2.into()));
+
+        // Unnamed stages other than the last cannot be targeted
+        let parser = parse("FROM rust\nFROM alpine\n");
+        let lenses = CodeLensProvider::new().get_code_lenses("file:///Runefile", &parser);
+        assert!(lenses.iter().all(|lens| lens.range.start.line == 1));
+    }
+
+    #[test]
+    fn test_build_arguments() {
+        let provider = CodeLensProvider::new();
+        let parser = parse(CONTENT);
+        let file = Path::new("/app/Runefile");
+
+        assert_eq!(
+            provider
+                .build_arguments(BUILD_STAGE_COMMAND, file, &parser, 1)
+                .unwrap(),
+            vec![vec![
+                "build",
+                "--file",
+                "/app/Runefile",
+                "--target",
+                "build",
+                "/app"
+            ]]
+        );
+
+        let builds = provider
+            .build_arguments(BUILD_UP_TO_COMMAND, file, &parser, 2)
+            .unwrap();
+        let targets: Vec<Option<&str>> = builds
+            .iter()
+            .map(|args| {
+                let target = args.iter().position(|arg| arg == "--target");
+                target.map(|i| args[i + 1].as_str())
+            })
+            .collect();
+        assert_eq!(targets, vec![Some("deps"), Some("build"), None]);
+
+        assert!(provider
+            .build_arguments(BUILD_STAGE_COMMAND, file, &parser, 3)
+            .is_err());
+        assert!(provider
+            .build_arguments("runefile.unknown", file, &parser, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_execute_reports_in_background() {
+        let provider = CodeLensProvider::new();
+        let parser = parse(CONTENT);
+        let file = Path::new("/nonexistent/Runefile");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        provider
+            .execute(BUILD_STAGE_COMMAND, file, &parser, 0, move |message| {
+                tx.send(message).unwrap()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(rx.recv().unwrap().typ, MESSAGE_ERROR);
+
+        assert!(provider
+            .execute(BUILD_STAGE_COMMAND, file, &parser, 3, |_| {})
+            .is_err());
+    }
+}
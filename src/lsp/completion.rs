@@ -1,7 +1,19 @@
 //! Completion Provider for Runefile LSP
 
+use super::inlay_hints::{arg_definitions, env_pairs};
 use super::server::CompletionItem;
 use super::syntax::{InstructionKind, RunefileParser};
+use runefile_lint::syntax::{flags, PREDEFINED_ARGS};
+
+/// A variable declared with ARG or ENV
+pub(super) struct Variable {
+    pub name: String,
+    /// The default or value, if declared with one
+    pub value: Option<String>,
+    /// `ARG` or `ENV`
+    pub source: &'static str,
+    pub line: usize,
+}
 
 /// Completion provider for Runefile
 pub struct CompletionProvider {
@@ -46,9 +58,15 @@ impl CompletionProvider {
     ) -> Vec<CompletionItem> {
         let lines: Vec<&str> = content.lines().collect();
         let current_line = lines.get(line).copied().unwrap_or("");
-        let before_cursor = &current_line[..column.min(current_line.len())];
+        let before_cursor = current_line.get(..column).unwrap_or(current_line);
         let trimmed = before_cursor.trim();
 
+        // Variable references in any instruction
+        if let Some((prefix, braced)) = variable_prefix(before_cursor) {
+            let closed = current_line[before_cursor.len()..].starts_with('}');
+            return self.variable_completions(parser, line, prefix, braced && !closed);
+        }
+
         // Empty line or start of line - suggest instructions
         if trimmed.is_empty() {
             return self.instruction_completions(snippet_support);
@@ -81,6 +99,56 @@ impl CompletionProvider {
         }
     }
 
+    /// Completions for the ARG and ENV names declared before a line, and
+    /// the predefined build arguments
+    fn variable_completions(
+        &self,
+        parser: &RunefileParser,
+        line: usize,
+        prefix: &str,
+        close_brace: bool,
+    ) -> Vec<CompletionItem> {
+        let variables = variables_before(parser, line);
+        let declared = variables.iter().map(|variable| {
+            let detail = match &variable.value {
+                Some(value) => format!("{} {}={}", variable.source, variable.name, value),
+                None => format!("{} {}", variable.source, variable.name),
+            };
+            (
+                variable.name.as_str(),
+                detail,
+                format!("Declared on line {}", variable.line + 1),
+            )
+        });
+        let predefined = PREDEFINED_ARGS
+            .iter()
+            .filter(|(name, _)| !variables.iter().any(|v| v.name == *name))
+            .map(|(name, description)| {
+                (
+                    *name,
+                    "Predefined build argument".to_string(),
+                    description.to_string(),
+                )
+            });
+
+        declared
+            .chain(predefined)
+            .filter(|(name, _, _)| name.starts_with(prefix))
+            .map(|(name, detail, documentation)| CompletionItem {
+                label: name.to_string(),
+                kind: Some(6), // Variable
+                detail: Some(detail),
+                documentation: Some(documentation),
+                insert_text: Some(if close_brace {
+                    format!("{}}}", name)
+                } else {
+                    name.to_string()
+                }),
+                insert_text_format: Some(1),
+            })
+            .collect()
+    }
+
     /// Get instruction completions
     fn instruction_completions(&self, snippet_support: bool) -> Vec<CompletionItem> {
         self.instructions
@@ -161,34 +229,8 @@ impl CompletionProvider {
     fn copy_completions(&self, args: &str, parser: &RunefileParser) -> Vec<CompletionItem> {
         let mut items = Vec::new();
 
-        // --from flag for multi-stage builds
         if args.is_empty() || args.starts_with("--") {
-            items.push(CompletionItem {
-                label: "--from".to_string(),
-                kind: Some(6), // Variable
-                detail: Some("Copy from a build stage".to_string()),
-                documentation: Some("Copy files from a named build stage".to_string()),
-                insert_text: Some("--from=".to_string()),
-                insert_text_format: Some(1),
-            });
-
-            items.push(CompletionItem {
-                label: "--chown".to_string(),
-                kind: Some(6),
-                detail: Some("Set file ownership".to_string()),
-                documentation: Some("Set user:group ownership of copied files".to_string()),
-                insert_text: Some("--chown=".to_string()),
-                insert_text_format: Some(1),
-            });
-
-            items.push(CompletionItem {
-                label: "--chmod".to_string(),
-                kind: Some(6),
-                detail: Some("Set file permissions".to_string()),
-                documentation: Some("Set permissions of copied files".to_string()),
-                insert_text: Some("--chmod=".to_string()),
-                insert_text_format: Some(1),
-            });
+            items.extend(flag_completions("COPY"));
         }
 
         // Stage names after --from=
@@ -254,41 +296,7 @@ impl CompletionProvider {
         let mut items = Vec::new();
 
         if args.is_empty() || args.starts_with("--") {
-            items.push(CompletionItem {
-                label: "--interval".to_string(),
-                kind: Some(6),
-                detail: Some("Time between health checks (default: 30s)".to_string()),
-                documentation: None,
-                insert_text: Some("--interval=30s ".to_string()),
-                insert_text_format: Some(1),
-            });
-
-            items.push(CompletionItem {
-                label: "--timeout".to_string(),
-                kind: Some(6),
-                detail: Some("Health check timeout (default: 30s)".to_string()),
-                documentation: None,
-                insert_text: Some("--timeout=30s ".to_string()),
-                insert_text_format: Some(1),
-            });
-
-            items.push(CompletionItem {
-                label: "--start-period".to_string(),
-                kind: Some(6),
-                detail: Some("Initialization grace period (default: 0s)".to_string()),
-                documentation: None,
-                insert_text: Some("--start-period=0s ".to_string()),
-                insert_text_format: Some(1),
-            });
-
-            items.push(CompletionItem {
-                label: "--retries".to_string(),
-                kind: Some(6),
-                detail: Some("Consecutive failures needed (default: 3)".to_string()),
-                documentation: None,
-                insert_text: Some("--retries=3 ".to_string()),
-                insert_text_format: Some(1),
-            });
+            items.extend(flag_completions("HEALTHCHECK"));
 
             items.push(CompletionItem {
                 label: "NONE".to_string(),
@@ -417,6 +425,76 @@ impl CompletionProvider {
     }
 }
 
+/// The variable name typed so far after `$` or `${`, and whether it is
+/// braced
+fn variable_prefix(before_cursor: &str) -> Option<(&str, bool)> {
+    let head = before_cursor.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    let prefix = &before_cursor[head.len()..];
+    if let Some(head) = head.strip_suffix("${") {
+        (!head.ends_with('\\')).then_some((prefix, true))
+    } else if let Some(head) = head.strip_suffix('$') {
+        (!head.ends_with('\\')).then_some((prefix, false))
+    } else {
+        None
+    }
+}
+
+/// Variables declared by instructions ending before a line, in order of
+/// their latest declaration
+pub(super) fn variables_before(parser: &RunefileParser, line: usize) -> Vec<Variable> {
+    let mut variables: Vec<Variable> = Vec::new();
+    let mut declare = |name: &str, value: Option<String>, source, line| {
+        variables.retain(|v| v.name != name);
+        variables.push(Variable {
+            name: name.to_string(),
+            value,
+            source,
+            line,
+        });
+    };
+
+    for inst in parser.instructions.iter().filter(|i| i.end_line < line) {
+        match inst.kind {
+            InstructionKind::Arg => {
+                for (name, default) in arg_definitions(&inst.arguments) {
+                    declare(name, default.map(str::to_string), "ARG", inst.line);
+                }
+            }
+            InstructionKind::Env => {
+                for (name, value) in env_pairs(&inst.arguments) {
+                    declare(&name, Some(value), "ENV", inst.line);
+                }
+            }
+            _ => {}
+        }
+    }
+    variables
+}
+
+/// Completions for the flags of an instruction
+///
+/// Flags with a default are inserted with it.
+fn flag_completions(keyword: &str) -> impl Iterator<Item = CompletionItem> {
+    flags(keyword).iter().map(|flag| {
+        let (detail, insert_text) = match (flag.default, flag.value.is_empty()) {
+            (Some(default), _) => (
+                format!("{} (default: {})", flag.documentation, default),
+                format!("{}={} ", flag.name, default),
+            ),
+            (None, true) => (flag.documentation.to_string(), format!("{} ", flag.name)),
+            (None, false) => (flag.documentation.to_string(), format!("{}=", flag.name)),
+        };
+        CompletionItem {
+            label: flag.name.to_string(),
+            kind: Some(6), // Variable
+            detail: Some(detail),
+            documentation: Some(flag.label()),
+            insert_text: Some(insert_text),
+            insert_text_format: Some(1),
+        }
+    })
+}
+
 impl Default for CompletionProvider {
     fn default() -> Self {
         Self::new()
@@ -448,4 +526,35 @@ mod tests {
         assert!(completions.iter().any(|c| c.label == "CMD"));
         assert!(completions.iter().any(|c| c.label == "NONE"));
     }
+
+    #[test]
+    fn test_variable_completions() {
+        let content = "ARG VERSION=1.0\nFROM alpine:${VER\nENV APP_HOME=/app\nWORKDIR $APP\nRUN echo ${TARGET}\nENV LATE=1\n";
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        let provider = CompletionProvider::new();
+        let complete =
+            |line, column| provider.get_completions(content, &parser, line, column, false);
+
+        let version = complete(1, 17);
+        assert_eq!(version.len(), 1);
+        assert_eq!(version[0].insert_text.as_deref(), Some("VERSION}"));
+        assert_eq!(version[0].detail.as_deref(), Some("ARG VERSION=1.0"));
+
+        let home = complete(3, 12);
+        assert_eq!(home[0].label, "APP_HOME");
+        assert_eq!(home[0].insert_text.as_deref(), Some("APP_HOME"));
+
+        // Predefined arguments; the closing brace is already there
+        let target = complete(4, 17);
+        assert!(target.iter().any(|c| c.label == "TARGETARCH"));
+        assert!(target
+            .iter()
+            .all(|c| !c.insert_text.as_ref().unwrap().ends_with('}')));
+
+        // Only earlier declarations
+        assert!(complete(4, 11).iter().all(|c| c.label != "LATE"));
+        assert!(complete(4, 11).iter().any(|c| c.label == "VERSION"));
+        assert!(variable_prefix("echo \\$").is_none());
+    }
 }
@@ -0,0 +1,158 @@
+//! Compose File Provider for Runefile LSP
+//!
+//! Completion, hover and validation come from `runefile-lint`, shared with
+//! the WebAssembly server. Diagnostics add the compose parser's own checks.
+
+use super::server::{CompletionItem, Diagnostic, Hover, MarkupContent, Position, Range};
+use crate::compose::config::ComposeConfig;
+use crate::compose::ComposeParser;
+use runefile_lint::compose::{self, CompletionKind, Problem};
+use runefile_lint::ranges::Span;
+use runefile_lint::Severity;
+
+/// Compose file provider
+pub struct ComposeProvider {}
+
+impl ComposeProvider {
+    /// Create a new compose provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Whether a document URI names a compose file
+    pub fn is_compose_file(&self, uri: &str) -> bool {
+        compose::is_compose_file(uri)
+    }
+
+    /// Get completions for keys, service names and known values
+    pub fn get_completions(
+        &self,
+        content: &str,
+        line: usize,
+        character: usize,
+    ) -> Vec<CompletionItem> {
+        compose::completions(content, line, character)
+            .into_iter()
+            .map(|completion| {
+                let (kind, detail, insert_text) = match completion.kind {
+                    CompletionKind::Key => (10, None, Some(format!("{}: ", completion.label))), // Property
+                    CompletionKind::Service => (7, Some("Service".to_string()), None), // Class
+                    CompletionKind::Value => (12, None, None),                         // Value
+                };
+                CompletionItem {
+                    label: completion.label,
+                    kind: Some(kind),
+                    detail,
+                    documentation: completion.documentation.map(str::to_string),
+                    insert_text,
+                    insert_text_format: Some(1),
+                }
+            })
+            .collect()
+    }
+
+    /// Get hover documentation for a key
+    pub fn get_hover(&self, content: &str, line: usize, character: usize) -> Option<Hover> {
+        let (documentation, span) = compose::hover(content, line, character)?;
+        Some(Hover {
+            contents: MarkupContent {
+                kind: "markdown".to_string(),
+                value: documentation,
+            },
+            range: Some(range(span)),
+        })
+    }
+
+    /// Get diagnostics for a compose file
+    ///
+    /// Adds the compose parser's schema errors and warnings to the shared
+    /// checks.
+    pub fn get_diagnostics(&self, content: &str) -> Vec<Diagnostic> {
+        let mut problems = compose::validate(content);
+        // Once the YAML parses, the parser's schema errors and warnings;
+        // it stops at its first error, which the shared checks report
+        if serde_yaml::from_str::<serde_yaml::Value>(content).is_ok() {
+            match serde_yaml::from_str::<ComposeConfig>(content) {
+                Ok(config) => {
+                    for warning in ComposeParser::validate(&config).unwrap_or_default() {
+                        let service = warning.split('\'').nth(1).unwrap_or("");
+                        problems.push(Problem {
+                            span: compose::key_span(content, &["services", service]),
+                            severity: Severity::Warning,
+                            message: warning,
+                        });
+                    }
+                }
+                Err(e) => problems.push(compose::yaml_error(content, &e)),
+            }
+        }
+        problems.into_iter().map(diagnostic).collect()
+    }
+}
+
+impl Default for ComposeProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn range(span: Span) -> Range {
+    Range {
+        start: Position {
+            line: span.start_line as u32,
+            character: span.start as u32,
+        },
+        end: Position {
+            line: span.end_line as u32,
+            character: span.end as u32,
+        },
+    }
+}
+
+fn diagnostic(problem: Problem) -> Diagnostic {
+    Diagnostic {
+        range: range(problem.span),
+        severity: Some(problem.severity.lsp_code()),
+        code: None,
+        source: Some("runefile-lsp".to_string()),
+        message: problem.message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics() {
+        let provider = ComposeProvider::new();
+        let content = "services:\n  web:\n    image: nginx\n    networks:\n      - back\n    depends_on:\n      - db\n";
+        let found: Vec<(u32, u32, Option<u8>)> = provider
+            .get_diagnostics(content)
+            .iter()
+            .map(|d| (d.range.start.line, d.range.start.character, d.severity))
+            .collect();
+        // The unknown service from the shared checks; the parser stops there
+        assert_eq!(found, vec![(6, 8, Some(1))]);
+
+        let content = "services:\n  web:\n    image: nginx\n    networks:\n      - back\n";
+        let diagnostics = provider.get_diagnostics(content);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(2));
+        assert_eq!(diagnostics[0].range.start.line, 1);
+        assert!(diagnostics[0].message.contains("undefined network 'back'"));
+
+        // Schema errors the YAML itself does not have
+        let diagnostics = provider.get_diagnostics("services:\n  web:\n    image: [a]\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(1));
+    }
+
+    #[test]
+    fn test_completion_items() {
+        let items = ComposeProvider::new().get_completions("services:\n  web:\n    ", 2, 4);
+        let image = items.iter().find(|item| item.label == "image").unwrap();
+        assert_eq!(image.kind, Some(10));
+        assert_eq!(image.insert_text.as_deref(), Some("image: "));
+    }
+}
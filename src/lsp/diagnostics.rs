@@ -2,14 +2,47 @@
 
 use super::server::{Diagnostic, Position, Range};
 use super::syntax::{ErrorSeverity, RunefileParser};
+use runefile_lint::LintConfig;
 
 /// Diagnostics provider for Runefile
-pub struct DiagnosticsProvider {}
+pub struct DiagnosticsProvider {
+    lint_config: LintConfig,
+}
 
 impl DiagnosticsProvider {
     /// Create a new diagnostics provider
     pub fn new() -> Self {
-        Self {}
+        Self {
+            lint_config: LintConfig::default(),
+        }
+    }
+
+    /// Set the lint rule configuration
+    pub fn set_lint_config(&mut self, config: LintConfig) {
+        self.lint_config = config;
+    }
+
+    /// Get diagnostics from the lint rules, with the rule ID as code
+    pub fn get_lint_diagnostics(&self, content: &str) -> Vec<Diagnostic> {
+        runefile_lint::lint(content, &self.lint_config)
+            .into_iter()
+            .map(|issue| Diagnostic {
+                range: Range {
+                    start: Position {
+                        line: issue.line as u32,
+                        character: issue.column as u32,
+                    },
+                    end: Position {
+                        line: issue.line as u32,
+                        character: issue.end_column as u32,
+                    },
+                },
+                severity: Some(issue.severity.lsp_code()),
+                code: Some(issue.rule.to_string()),
+                source: Some("rune-lint".to_string()),
+                message: issue.message,
+            })
+            .collect()
     }
 
     /// Get diagnostics for the parsed Runefile
@@ -17,28 +50,26 @@ impl DiagnosticsProvider {
         parser
             .errors
             .iter()
-            .map(|error| {
-                Diagnostic {
-                    range: Range {
-                        start: Position {
-                            line: error.line as u32,
-                            character: error.column as u32,
-                        },
-                        end: Position {
-                            line: error.line as u32,
-                            character: (error.column + 10) as u32, // Approximate end
-                        },
+            .map(|error| Diagnostic {
+                range: Range {
+                    start: Position {
+                        line: error.line as u32,
+                        character: error.column as u32,
                     },
-                    severity: Some(match error.severity {
-                        ErrorSeverity::Error => 1,
-                        ErrorSeverity::Warning => 2,
-                        ErrorSeverity::Info => 3,
-                        ErrorSeverity::Hint => 4,
-                    }),
-                    code: None,
-                    source: Some("runefile-lsp".to_string()),
-                    message: error.message.clone(),
-                }
+                    end: Position {
+                        line: error.line as u32,
+                        character: error.end_column as u32,
+                    },
+                },
+                severity: Some(match error.severity {
+                    ErrorSeverity::Error => 1,
+                    ErrorSeverity::Warning => 2,
+                    ErrorSeverity::Info => 3,
+                    ErrorSeverity::Hint => 4,
+                }),
+                code: None,
+                source: Some("runefile-lsp".to_string()),
+                message: error.message.clone(),
             })
             .collect()
     }
@@ -75,6 +106,48 @@ mod tests {
         assert!(diagnostics.iter().any(|d| d.severity == Some(2))); // Warning
     }
 
+    #[test]
+    fn test_lint_diagnostics() {
+        let mut provider = DiagnosticsProvider::new();
+        let content = "FROM alpine:latest\nRUN sudo apk add curl";
+
+        let codes: Vec<Option<String>> = provider
+            .get_lint_diagnostics(content)
+            .into_iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(
+            codes,
+            vec![Some("RUNE1002".to_string()), Some("RUNE1003".to_string())]
+        );
+
+        provider.set_lint_config(LintConfig::parse("[rules]\nRUNE1002 = \"off\"").unwrap());
+        assert_eq!(provider.get_lint_diagnostics(content).len(), 1);
+    }
+
+    #[test]
+    fn test_diagnostic_ranges() {
+        let provider = DiagnosticsProvider::new();
+        let mut parser = RunefileParser::new();
+        parser.parse("FROM alpine\nEXPOSE 80 8o/tcp 53/udp\nCOPY --chwon=app src\nRUNN true");
+
+        let ranges: Vec<(u32, u32, u32)> = provider
+            .get_diagnostics(&parser)
+            .into_iter()
+            .map(|d| {
+                (
+                    d.range.start.line,
+                    d.range.start.character,
+                    d.range.end.character,
+                )
+            })
+            .collect();
+        assert_eq!(ranges.len(), 4);
+        for range in [(1, 10, 16), (2, 5, 12), (2, 17, 20), (3, 0, 4)] {
+            assert!(ranges.contains(&range), "{:?} not in {:?}", range, ranges);
+        }
+    }
+
     #[test]
     fn test_diagnostics_valid_file() {
         let provider = DiagnosticsProvider::new();
@@ -0,0 +1,34 @@
+//! Formatting Provider for Runefile LSP
+//!
+//! The formatter comes from `runefile-lint`, shared with the WebAssembly
+//! server.
+
+use super::server::FormatOptions;
+use runefile_lint::formatting::{format, Style};
+
+/// Formatting provider for Runefile
+pub struct FormattingProvider {}
+
+impl FormattingProvider {
+    /// Create a new formatting provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Format a document, indenting continuation lines with `indent`
+    pub fn format(&self, content: &str, options: &FormatOptions, indent: &str) -> String {
+        let style = Style {
+            indent: indent.to_string(),
+            align_continuations: options.align_continuations,
+            sort_keys: options.sort_keys,
+            normalize_json: options.normalize_json,
+        };
+        format(content, &style)
+    }
+}
+
+impl Default for FormattingProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,7 +1,9 @@
 //! Hover Provider for Runefile LSP
 
+use super::completion::variables_before;
 use super::server::{Hover, MarkupContent, Position, Range};
-use super::syntax::RunefileParser;
+use super::syntax::{RunefileParser, Symbol};
+use runefile_lint::syntax::PREDEFINED_ARGS;
 
 /// Hover provider for Runefile
 pub struct HoverProvider {}
@@ -20,6 +22,10 @@ impl HoverProvider {
         line: usize,
         column: usize,
     ) -> Option<Hover> {
+        if let Some(hover) = self.variable_hover(parser, line, column) {
+            return Some(hover);
+        }
+
         // Find the instruction at this line
         let instruction = parser.instruction_at(line, column)?;
 
@@ -47,6 +53,61 @@ impl HoverProvider {
             }),
         })
     }
+
+    /// Show where a `$VAR` reference was declared, and its value
+    fn variable_hover(&self, parser: &RunefileParser, line: usize, column: usize) -> Option<Hover> {
+        let reference = parser
+            .variable_references
+            .iter()
+            .find(|r| r.line == line && (r.start..=r.end).contains(&column))?;
+
+        let value = match variables_before(parser, line)
+            .into_iter()
+            .rfind(|v| v.name == reference.name)
+        {
+            Some(variable) => {
+                let declaration = match &variable.value {
+                    Some(value) => format!("{} {}={}", variable.source, variable.name, value),
+                    None => format!("{} {}", variable.source, variable.name),
+                };
+                format!(
+                    "```dockerfile\n{}\n```\n\nDeclared on line {}",
+                    declaration,
+                    variable.line + 1
+                )
+            }
+            None => {
+                let (_, description) = PREDEFINED_ARGS
+                    .iter()
+                    .find(|(name, _)| *name == reference.name)?;
+                format!(
+                    "`{}`: {}\n\nPredefined build argument",
+                    reference.name, description
+                )
+            }
+        };
+
+        Some(Hover {
+            contents: MarkupContent {
+                kind: "markdown".to_string(),
+                value,
+            },
+            range: Some(symbol_range(reference)),
+        })
+    }
+}
+
+fn symbol_range(symbol: &Symbol) -> Range {
+    Range {
+        start: Position {
+            line: symbol.line as u32,
+            character: symbol.start as u32,
+        },
+        end: Position {
+            line: symbol.line as u32,
+            character: symbol.end as u32,
+        },
+    }
 }
 
 impl Default for HoverProvider {
@@ -88,4 +149,24 @@ mod tests {
         assert!(hover.contents.value.contains("HEALTHCHECK"));
         assert!(hover.contents.value.contains("--interval"));
     }
+
+    #[test]
+    fn test_hover_on_variable() {
+        let content = "ARG VERSION=1.0\nFROM alpine:${VERSION}\nRUN echo $TARGETOS $OTHER";
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        let provider = HoverProvider::new();
+
+        let hover = provider.get_hover(content, &parser, 1, 15).unwrap();
+        assert!(hover.contents.value.contains("ARG VERSION=1.0"));
+        assert!(hover.contents.value.contains("line 1"));
+        assert_eq!(hover.range.unwrap().start.character, 14);
+
+        let hover = provider.get_hover(content, &parser, 2, 11).unwrap();
+        assert!(hover.contents.value.contains("Predefined build argument"));
+
+        // Undeclared variables fall back to the instruction
+        let hover = provider.get_hover(content, &parser, 2, 21).unwrap();
+        assert!(hover.contents.value.contains("```dockerfile\nRUN"));
+    }
 }
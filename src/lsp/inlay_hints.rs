@@ -0,0 +1,428 @@
+//! Inlay Hint Provider for Runefile LSP
+//!
+//! Shows the value a `$VAR` or `${VAR}` reference expands to, following the
+//! build's scoping: global ARGs apply to FROM lines, each stage starts with
+//! only the ENV of its base stage, and ENV takes precedence over ARG.
+
+use super::server::{InlayHint, Position, Range};
+use super::syntax::{InstructionKind, RunefileParser};
+use std::collections::HashMap;
+
+/// Values longer than this are shortened
+const MAX_VALUE_LEN: usize = 40;
+
+/// A value and where it came from
+type Value = (String, &'static str);
+
+/// Inlay hint provider for Runefile
+pub struct InlayHintProvider {
+    /// Build arguments configured by the client
+    build_args: HashMap<String, String>,
+}
+
+/// A `$NAME` or `${NAME...}` within a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reference<'a> {
+    start: usize,
+    end: usize,
+    name: &'a str,
+    /// Operator and word of `${NAME:-word}` and similar
+    modifier: Option<(&'a str, &'a str)>,
+}
+
+/// Variables in scope at an instruction
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    /// Alias of the stage
+    alias: Option<String>,
+    args: HashMap<String, Value>,
+    env: HashMap<String, Value>,
+}
+
+impl Scope {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.env.get(name).or_else(|| self.args.get(name))
+    }
+}
+
+impl InlayHintProvider {
+    /// Create a new inlay hint provider
+    pub fn new() -> Self {
+        Self {
+            build_args: HashMap::new(),
+        }
+    }
+
+    /// Use build arguments in place of ARG defaults
+    pub fn set_build_args(&mut self, build_args: HashMap<String, String>) {
+        self.build_args = build_args;
+    }
+
+    /// Get hints for the references on the lines of a range
+    pub fn get_inlay_hints(
+        &self,
+        content: &str,
+        parser: &RunefileParser,
+        range: &Range,
+    ) -> Vec<InlayHint> {
+        let lines: Vec<&str> = content.lines().collect();
+        let mut hints = Vec::new();
+        let mut globals = Scope::default();
+        let mut scope: Option<Scope> = None;
+        let mut stage_env: HashMap<String, HashMap<String, Value>> = HashMap::new();
+
+        for inst in &parser.instructions {
+            // FROM is expanded with the global ARGs
+            let current = match (&inst.kind, &scope) {
+                (InstructionKind::From, _) | (_, None) => &globals,
+                (_, Some(scope)) => scope,
+            };
+
+            let visible =
+                inst.line <= range.end.line as usize && inst.end_line >= range.start.line as usize;
+            if visible && inst.kind != InstructionKind::Comment {
+                let instruction_lines = lines.iter().enumerate().skip(inst.line);
+                for (line, text) in instruction_lines.take(inst.end_line + 1 - inst.line) {
+                    for reference in references(text) {
+                        if let Some((value, source)) = resolve(&reference, current) {
+                            hints.push(hint(line, &reference, &value, source));
+                        }
+                    }
+                }
+            }
+
+            match inst.kind {
+                InstructionKind::From => {
+                    // Keep the finished stage's ENV for stages built on it
+                    if let Some(Scope {
+                        alias: Some(alias),
+                        env,
+                        ..
+                    }) = scope.take()
+                    {
+                        stage_env.insert(alias, env);
+                    }
+
+                    let mut words = inst
+                        .arguments
+                        .split_whitespace()
+                        .filter(|w| !w.starts_with("--"));
+                    let base = words.next().unwrap_or("").to_lowercase();
+                    let alias = match (words.next(), words.next()) {
+                        (Some(keyword), Some(alias)) if keyword.eq_ignore_ascii_case("AS") => {
+                            Some(alias.to_lowercase())
+                        }
+                        _ => None,
+                    };
+                    // ENV carries over from a base stage, ARG does not
+                    scope = Some(Scope {
+                        alias,
+                        args: HashMap::new(),
+                        env: stage_env.get(&base).cloned().unwrap_or_default(),
+                    });
+                }
+                InstructionKind::Arg => {
+                    for (name, default) in arg_definitions(&inst.arguments) {
+                        let value = self.arg_value(name, default, &scope, &globals);
+                        let target = match scope.as_mut() {
+                            Some(scope) => &mut scope.args,
+                            None => &mut globals.args,
+                        };
+                        match value {
+                            Some(value) => target.insert(name.to_string(), value),
+                            None => target.remove(name),
+                        };
+                    }
+                }
+                InstructionKind::Env => {
+                    if let Some(current) = scope.as_mut() {
+                        let pairs: Vec<(String, String)> = env_pairs(&inst.arguments)
+                            .into_iter()
+                            .map(|(name, value)| (name, expand(&value, current)))
+                            .collect();
+                        for (name, value) in pairs {
+                            current.env.insert(name, (value, "ENV"));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        hints
+    }
+
+    /// Value of an ARG: a build argument, its default, or the global value
+    fn arg_value(
+        &self,
+        name: &str,
+        default: Option<&str>,
+        scope: &Option<Scope>,
+        globals: &Scope,
+    ) -> Option<Value> {
+        if let Some(value) = self.build_args.get(name) {
+            return Some((value.clone(), "build argument"));
+        }
+        match (default, scope) {
+            (Some(default), Some(scope)) => Some((expand(default, scope), "ARG default")),
+            (Some(default), None) => Some((expand(default, globals), "global ARG")),
+            // A stage ARG without a default inherits the global value
+            (None, Some(_)) => globals.args.get(name).cloned(),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Default for InlayHintProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hint(line: usize, reference: &Reference, value: &str, source: &str) -> InlayHint {
+    let shown = if value.chars().count() > MAX_VALUE_LEN {
+        let cut: String = value.chars().take(MAX_VALUE_LEN - 1).collect();
+        format!("{}…", cut)
+    } else {
+        value.to_string()
+    };
+    InlayHint {
+        position: Position {
+            line: line as u32,
+            character: reference.end as u32,
+        },
+        label: format!("= {}", shown),
+        kind: None,
+        padding_left: Some(true),
+        tooltip: Some(format!("{} from {}: {}", reference.name, source, value)),
+    }
+}
+
+/// Find the variable references in a line, skipping `\$`
+fn references(text: &str) -> Vec<Reference<'_>> {
+    let bytes = text.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                let Some(close) = text[i..].find('}').map(|p| i + p) else {
+                    break;
+                };
+                let inner = &text[i + 2..close];
+                let name_len = name_len(inner);
+                let rest = &inner[name_len..];
+                let modifier = [":-", ":+", "-", "+"]
+                    .into_iter()
+                    .find(|op| rest.starts_with(op))
+                    .map(|op| (op, &rest[op.len()..]));
+                if name_len > 0 && (rest.is_empty() || modifier.is_some()) {
+                    found.push(Reference {
+                        start: i,
+                        end: close + 1,
+                        name: &inner[..name_len],
+                        modifier,
+                    });
+                }
+                i = close + 1;
+            }
+            b'$' => {
+                let len = name_len(&text[i + 1..]);
+                if len > 0 {
+                    found.push(Reference {
+                        start: i,
+                        end: i + 1 + len,
+                        name: &text[i + 1..i + 1 + len],
+                        modifier: None,
+                    });
+                }
+                i += 1 + len;
+            }
+            _ => i += 1,
+        }
+    }
+
+    found
+}
+
+/// Length of the variable name at the start of `text`
+fn name_len(text: &str) -> usize {
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        return 0;
+    }
+    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len())
+}
+
+/// Value of a reference, if it expands to anything known
+fn resolve(reference: &Reference, scope: &Scope) -> Option<Value> {
+    let value = scope.get(reference.name);
+    let set = value.filter(|(v, _)| !v.is_empty());
+    match reference.modifier {
+        None => value.cloned(),
+        Some((":-", word)) => set
+            .cloned()
+            .or_else(|| Some((expand(word, scope), "default value"))),
+        Some(("-", word)) => value
+            .cloned()
+            .or_else(|| Some((expand(word, scope), "default value"))),
+        Some((":+", word)) => Some(match set {
+            Some(_) => (expand(word, scope), "alternate value"),
+            None => (String::new(), "unset variable"),
+        }),
+        Some((_, word)) => Some(match value {
+            Some(_) => (expand(word, scope), "alternate value"),
+            None => (String::new(), "unset variable"),
+        }),
+    }
+}
+
+/// Substitute every reference in a value; unknown variables become empty
+fn expand(text: &str, scope: &Scope) -> String {
+    let mut expanded = String::new();
+    let mut last = 0;
+    for reference in references(text) {
+        expanded.push_str(&text[last..reference.start]);
+        if let Some((value, _)) = resolve(&reference, scope) {
+            expanded.push_str(&value);
+        }
+        last = reference.end;
+    }
+    expanded.push_str(&text[last..]);
+    expanded
+}
+
+/// Names and defaults of `ARG NAME[=default] ...`
+pub(super) fn arg_definitions(arguments: &str) -> Vec<(&str, Option<&str>)> {
+    arguments
+        .split_whitespace()
+        .map(|word| match word.split_once('=') {
+            Some((name, default)) => (name, Some(unquote(default))),
+            None => (word, None),
+        })
+        .collect()
+}
+
+/// Pairs of `ENV KEY=value ...` or the legacy `ENV KEY value`
+pub(super) fn env_pairs(arguments: &str) -> Vec<(String, String)> {
+    let arguments = arguments.trim();
+    let first = arguments.split_whitespace().next().unwrap_or("");
+    if !first.contains('=') {
+        let value = arguments[first.len()..].trim();
+        return vec![(first.to_string(), value.to_string())];
+    }
+
+    let mut pairs = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = arguments.chars().peekable();
+    loop {
+        let c = chars.next();
+        match (quote, c) {
+            (None, None) | (None, Some(' ' | '\t')) => {
+                if let Some((name, value)) = word.split_once('=') {
+                    pairs.push((name.to_string(), value.to_string()));
+                }
+                word.clear();
+                if c.is_none() {
+                    break;
+                }
+            }
+            (Some(_), None) => break,
+            (None, Some(q @ ('"' | '\''))) => quote = Some(q),
+            (Some(q), Some(c)) if c == q => quote = None,
+            (_, Some('\\')) if chars.peek().is_some_and(|n| *n == '"' || *n == ' ') => {
+                word.extend(chars.next())
+            }
+            (_, Some(c)) => word.push(c),
+        }
+    }
+    pairs
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hints(provider: &InlayHintProvider, content: &str) -> Vec<(u32, u32, String)> {
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+        let range = Range {
+            start: Position {
+                line: 0,
+                character: 0,
+            },
+            end: Position {
+                line: 100,
+                character: 0,
+            },
+        };
+        provider
+            .get_inlay_hints(content, &parser, &range)
+            .into_iter()
+            .map(|h| (h.position.line, h.position.character, h.label))
+            .collect()
+    }
+
+    #[test]
+    fn test_arg_and_env_values() {
+        let content = r#"ARG TAG=3.19
+FROM alpine:${TAG} AS base
+ARG TAG
+ENV HOME_DIR="/home/app" PATH=$HOME_DIR/bin
+RUN echo $TAG ${HOME_DIR} ${MISSING:-none}
+FROM base
+RUN echo $HOME_DIR $TAG
+"#;
+        let provider = InlayHintProvider::new();
+
+        assert_eq!(
+            hints(&provider, content),
+            vec![
+                (1, 18, "= 3.19".to_string()),
+                (4, 13, "= 3.19".to_string()),
+                (4, 25, "= /home/app".to_string()),
+                (4, 42, "= none".to_string()),
+                (6, 18, "= /home/app".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_args() {
+        let mut provider = InlayHintProvider::new();
+        provider.set_build_args(HashMap::from([("VERSION".to_string(), "2.0".to_string())]));
+
+        let content = "FROM alpine\nARG VERSION=1.0\nLABEL version=$VERSION\n";
+        assert_eq!(
+            hints(&provider, content),
+            vec![(2, 22, "= 2.0".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_env_pairs() {
+        assert_eq!(
+            env_pairs(r#"A=1 B="two words" C=a\ b"#),
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two words".to_string()),
+                ("C".to_string(), "a b".to_string()),
+            ]
+        );
+        assert_eq!(
+            env_pairs("NAME some value"),
+            vec![("NAME".to_string(), "some value".to_string())]
+        );
+    }
+}
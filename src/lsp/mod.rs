@@ -1,18 +1,35 @@
 //! Runefile Language Server Protocol Implementation
 //!
 //! Provides IDE support for Runefile editing including:
-//! - Syntax highlighting
+//! - Syntax highlighting, including semantic tokens
 //! - Auto-completion
 //! - Hover documentation
-//! - Diagnostics (linting)
+//! - Inlay hints with resolved ARG and ENV values
+//! - Signature help for instruction flags
+//! - Diagnostics (linting), for open documents and the whole workspace,
+//!   including compose builds that reference missing build files
 //! - Go to definition
-//! - Document formatting
+//! - Code actions and quick fixes
+//! - Code lenses that build each stage
+//! - Folding and selection ranges
+//! - Completion, hover and validation of compose files
+//! - Document formatting, with configurable continuation and argument style
 
+mod code_actions;
+mod code_lens;
 mod completion;
+mod compose;
 mod diagnostics;
+mod formatting;
 mod hover;
+mod inlay_hints;
+mod ranges;
 mod server;
+mod signature_help;
 mod syntax;
+mod workspace;
 
 pub use server::RunefileLanguageServer;
-pub use syntax::{Instruction, InstructionKind, RunefileParser};
+pub use syntax::{Instruction, InstructionKind, RunefileParser, Symbol, SymbolKind};
+
+pub(crate) use syntax::edit_distance;
@@ -0,0 +1,75 @@
+//! Folding and Selection Range Provider for Runefile LSP
+//!
+//! The ranges come from `runefile-lint`, shared with the WebAssembly server.
+
+use super::server::{FoldingRange, Position, Range, SelectionRange};
+use runefile_lint::ranges::{folding_ranges, selection_ranges, Span};
+
+/// Folding and selection range provider for Runefile
+pub struct RangeProvider {}
+
+impl RangeProvider {
+    /// Create a new range provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Get folding ranges for stages, continued instructions, heredocs and
+    /// comment blocks
+    pub fn get_folding_ranges(&self, content: &str) -> Vec<FoldingRange> {
+        folding_ranges(content)
+            .into_iter()
+            .map(|fold| FoldingRange {
+                start_line: fold.start_line as u32,
+                end_line: fold.end_line as u32,
+                kind: fold.kind.map(str::to_string),
+            })
+            .collect()
+    }
+
+    /// Get nested selection ranges for each position
+    pub fn get_selection_ranges(
+        &self,
+        content: &str,
+        positions: &[Position],
+    ) -> Vec<SelectionRange> {
+        positions
+            .iter()
+            .map(|position| {
+                let mut spans =
+                    selection_ranges(content, position.line as usize, position.character as usize);
+                // Innermost first, and never empty
+                let innermost = spans.remove(0);
+                let parent = spans.into_iter().rev().fold(None, |parent, span| {
+                    Some(Box::new(SelectionRange {
+                        range: range(span),
+                        parent,
+                    }))
+                });
+                SelectionRange {
+                    range: range(innermost),
+                    parent,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for RangeProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn range(span: Span) -> Range {
+    Range {
+        start: Position {
+            line: span.start_line as u32,
+            character: span.start as u32,
+        },
+        end: Position {
+            line: span.end_line as u32,
+            character: span.end as u32,
+        },
+    }
+}
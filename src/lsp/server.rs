@@ -1,12 +1,24 @@
 //! Runefile LSP Server Implementation
 
+use super::code_actions::CodeActionProvider;
+use super::code_lens::{CodeLensProvider, BUILD_STAGE_COMMAND, BUILD_UP_TO_COMMAND};
 use super::completion::CompletionProvider;
+use super::compose::ComposeProvider;
 use super::diagnostics::DiagnosticsProvider;
+use super::formatting::FormattingProvider;
 use super::hover::HoverProvider;
-use super::syntax::{InstructionKind, RunefileParser};
+use super::inlay_hints::InlayHintProvider;
+use super::ranges::RangeProvider;
+use super::signature_help::SignatureHelpProvider;
+use super::syntax::{RunefileParser, Symbol, SymbolKind};
+use super::workspace::WorkspaceProvider;
+use runefile_lint::semantic_tokens::{self, TOKEN_TYPES};
+use runefile_lint::LintConfig;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
+use std::thread;
 
 /// LSP message types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +37,20 @@ pub enum LspMessage {
     #[serde(rename = "exit")]
     Exit,
 
+    #[serde(rename = "workspace/didChangeConfiguration")]
+    DidChangeConfiguration {
+        params: DidChangeConfigurationParams,
+    },
+
+    #[serde(rename = "workspace/didChangeWatchedFiles")]
+    DidChangeWatchedFiles { params: DidChangeWatchedFilesParams },
+
+    #[serde(rename = "workspace/diagnostic")]
+    WorkspaceDiagnostic {
+        id: i64,
+        params: WorkspaceDiagnosticParams,
+    },
+
     #[serde(rename = "textDocument/didOpen")]
     DidOpen { params: DidOpenParams },
 
@@ -43,9 +69,57 @@ pub enum LspMessage {
     #[serde(rename = "textDocument/hover")]
     Hover { id: i64, params: HoverParams },
 
+    #[serde(rename = "textDocument/signatureHelp")]
+    SignatureHelp {
+        id: i64,
+        params: SignatureHelpParams,
+    },
+
     #[serde(rename = "textDocument/definition")]
     Definition { id: i64, params: DefinitionParams },
 
+    #[serde(rename = "textDocument/references")]
+    References { id: i64, params: ReferenceParams },
+
+    #[serde(rename = "textDocument/codeAction")]
+    CodeAction { id: i64, params: CodeActionParams },
+
+    #[serde(rename = "textDocument/prepareRename")]
+    PrepareRename {
+        id: i64,
+        params: PrepareRenameParams,
+    },
+
+    #[serde(rename = "textDocument/rename")]
+    Rename { id: i64, params: RenameParams },
+
+    #[serde(rename = "textDocument/inlayHint")]
+    InlayHint { id: i64, params: InlayHintParams },
+
+    #[serde(rename = "textDocument/codeLens")]
+    CodeLens { id: i64, params: CodeLensParams },
+
+    #[serde(rename = "workspace/executeCommand")]
+    ExecuteCommand {
+        id: i64,
+        params: ExecuteCommandParams,
+    },
+
+    #[serde(rename = "textDocument/foldingRange")]
+    FoldingRange { id: i64, params: FoldingRangeParams },
+
+    #[serde(rename = "textDocument/selectionRange")]
+    SelectionRange {
+        id: i64,
+        params: SelectionRangeParams,
+    },
+
+    #[serde(rename = "textDocument/semanticTokens/full")]
+    SemanticTokens {
+        id: i64,
+        params: SemanticTokensParams,
+    },
+
     #[serde(rename = "textDocument/formatting")]
     Formatting { id: i64, params: FormattingParams },
 }
@@ -57,6 +131,98 @@ pub struct InitializeParams {
     pub process_id: Option<i64>,
     pub root_uri: Option<String>,
     pub capabilities: ClientCapabilities,
+    #[serde(default)]
+    pub initialization_options: Option<RunefileSettings>,
+}
+
+/// Client settings, sent as initialization options or in the `runefile`
+/// section of the configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunefileSettings {
+    /// Build arguments used to resolve ARG values
+    #[serde(default)]
+    pub build_args: HashMap<String, String>,
+    /// Document formatting style
+    #[serde(default)]
+    pub format: FormatOptions,
+}
+
+/// Formatting style beyond the editor's tab settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatOptions {
+    /// Spaces before continuation lines; defaults to the editor's indent
+    pub continuation_indent: Option<usize>,
+    /// Line up the `\` of continued lines
+    pub align_continuations: bool,
+    /// Sort multi-key LABEL and ENV instructions, one key per line
+    pub sort_keys: bool,
+    /// Rewrite CMD and ENTRYPOINT JSON arrays as `["a", "b"]`
+    pub normalize_json: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            continuation_indent: None,
+            align_continuations: false,
+            sort_keys: false,
+            normalize_json: true,
+        }
+    }
+}
+
+/// Did change configuration params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DidChangeConfigurationParams {
+    pub settings: ConfigurationSettings,
+}
+
+/// Did change watched files params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DidChangeWatchedFilesParams {
+    pub changes: Vec<FileEvent>,
+}
+
+/// A watched file that was created (1), changed (2) or deleted (3)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEvent {
+    pub uri: String,
+    #[serde(rename = "type")]
+    pub kind: u8,
+}
+
+/// Workspace diagnostic params
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDiagnosticParams {
+    #[serde(default)]
+    pub identifier: Option<String>,
+}
+
+/// Diagnostics of every file in the workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceDiagnosticReport {
+    pub items: Vec<WorkspaceDocumentDiagnosticReport>,
+}
+
+/// Full diagnostics of one workspace file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceDocumentDiagnosticReport {
+    pub uri: String,
+    /// Version of the open document, `None` for files read from disk
+    pub version: Option<i64>,
+    /// Always `"full"`
+    pub kind: String,
+    pub items: Vec<Diagnostic>,
+}
+
+/// Configuration sections the server reads
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigurationSettings {
+    #[serde(default)]
+    pub runefile: Option<RunefileSettings>,
 }
 
 /// Client capabilities
@@ -132,6 +298,14 @@ pub struct HoverParams {
     pub position: Position,
 }
 
+/// Signature help params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureHelpParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
 /// Definition params
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -140,6 +314,99 @@ pub struct DefinitionParams {
     pub position: Position,
 }
 
+/// References params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    pub context: ReferenceContext,
+}
+
+/// References context
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceContext {
+    pub include_declaration: bool,
+}
+
+/// Code action params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    pub context: CodeActionContext,
+}
+
+/// Code action context
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CodeActionContext {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Prepare rename params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareRenameParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+/// Rename params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    pub new_name: String,
+}
+
+/// Inlay hint params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// Code lens params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeLensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// Execute command params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecuteCommandParams {
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<serde_json::Value>,
+}
+
+/// Folding range params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldingRangeParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// Selection range params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectionRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    pub positions: Vec<Position>,
+}
+
+/// Semantic tokens params
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
 /// Formatting params
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -180,20 +447,27 @@ pub struct VersionedTextDocumentIdentifier {
 }
 
 /// Text document change event
+///
+/// Without a range, `text` replaces the whole document.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TextDocumentContentChangeEvent {
+    #[serde(default)]
+    pub range: Option<Range>,
+    #[serde(default)]
+    pub range_length: Option<u32>,
     pub text: String,
 }
 
 /// Position in a document
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Position {
     pub line: u32,
     pub character: u32,
 }
 
 /// Range in a document
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Range {
     pub start: Position,
     pub end: Position,
@@ -213,8 +487,69 @@ pub struct ServerCapabilities {
     pub text_document_sync: TextDocumentSyncOptions,
     pub completion_provider: Option<CompletionOptions>,
     pub hover_provider: Option<bool>,
+    pub signature_help_provider: Option<SignatureHelpOptions>,
     pub definition_provider: Option<bool>,
+    pub references_provider: Option<bool>,
+    pub rename_provider: Option<RenameOptions>,
+    pub code_action_provider: Option<bool>,
+    pub code_lens_provider: Option<CodeLensOptions>,
+    pub execute_command_provider: Option<ExecuteCommandOptions>,
+    pub inlay_hint_provider: Option<bool>,
+    pub folding_range_provider: Option<bool>,
+    pub selection_range_provider: Option<bool>,
+    pub semantic_tokens_provider: Option<SemanticTokensOptions>,
     pub document_formatting_provider: Option<bool>,
+    pub diagnostic_provider: Option<DiagnosticOptions>,
+}
+
+/// Pull diagnostics options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticOptions {
+    pub inter_file_dependencies: bool,
+    pub workspace_diagnostics: bool,
+}
+
+/// Signature help options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureHelpOptions {
+    pub trigger_characters: Vec<String>,
+}
+
+/// Code lens options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeLensOptions {
+    pub resolve_provider: bool,
+}
+
+/// Execute command options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecuteCommandOptions {
+    pub commands: Vec<String>,
+}
+
+/// Rename options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameOptions {
+    pub prepare_provider: bool,
+}
+
+/// Semantic tokens options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemanticTokensOptions {
+    pub legend: SemanticTokensLegend,
+    pub full: bool,
+}
+
+/// Semantic tokens legend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
 }
 
 /// Text document sync options
@@ -266,6 +601,30 @@ pub struct Hover {
     pub range: Option<Range>,
 }
 
+/// Signature help result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureHelp {
+    pub signatures: Vec<SignatureInformation>,
+    pub active_signature: Option<u32>,
+    pub active_parameter: Option<u32>,
+}
+
+/// A signature and its parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureInformation {
+    pub label: String,
+    pub documentation: Option<String>,
+    pub parameters: Vec<ParameterInformation>,
+}
+
+/// A parameter, labelled by its offsets within the signature label
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterInformation {
+    pub label: [u32; 2],
+    pub documentation: Option<String>,
+}
+
 /// Markup content
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarkupContent {
@@ -283,6 +642,69 @@ pub struct Diagnostic {
     pub message: String,
 }
 
+/// Workspace edit
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkspaceEdit {
+    pub changes: HashMap<String, Vec<TextEdit>>,
+}
+
+/// Code action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: Option<String>,
+    pub edit: Option<WorkspaceEdit>,
+}
+
+/// Code lens shown above a line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeLens {
+    pub range: Range,
+    pub command: Option<Command>,
+}
+
+/// Command run through `workspace/executeCommand`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<serde_json::Value>,
+}
+
+/// Inlay hint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+    pub kind: Option<u8>,
+    pub padding_left: Option<bool>,
+    pub tooltip: Option<String>,
+}
+
+/// Folding range; `kind` is "comment" or "region" when set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldingRange {
+    pub start_line: u32,
+    pub end_line: u32,
+    pub kind: Option<String>,
+}
+
+/// Selection range and the range containing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionRange {
+    pub range: Range,
+    pub parent: Option<Box<SelectionRange>>,
+}
+
+/// Semantic tokens, as relative positions in groups of five
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemanticTokens {
+    pub data: Vec<u32>,
+}
+
 /// Publish diagnostics params
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
@@ -291,6 +713,15 @@ pub struct PublishDiagnosticsParams {
     pub diagnostics: Vec<Diagnostic>,
 }
 
+/// `window/showMessage` params
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShowMessageParams {
+    /// 1 for errors, 3 for information
+    #[serde(rename = "type")]
+    pub typ: u8,
+    pub message: String,
+}
+
 /// Text edit
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -311,8 +742,17 @@ struct DocumentState {
 pub struct RunefileLanguageServer {
     documents: Arc<RwLock<HashMap<String, DocumentState>>>,
     completion_provider: CompletionProvider,
+    compose_provider: ComposeProvider,
     hover_provider: HoverProvider,
     diagnostics_provider: DiagnosticsProvider,
+    code_action_provider: CodeActionProvider,
+    code_lens_provider: CodeLensProvider,
+    formatting_provider: FormattingProvider,
+    format_options: FormatOptions,
+    workspace_provider: WorkspaceProvider,
+    range_provider: RangeProvider,
+    inlay_hint_provider: InlayHintProvider,
+    signature_help_provider: SignatureHelpProvider,
     snippet_support: bool,
 }
 
@@ -322,8 +762,17 @@ impl RunefileLanguageServer {
         Self {
             documents: Arc::new(RwLock::new(HashMap::new())),
             completion_provider: CompletionProvider::new(),
+            compose_provider: ComposeProvider::new(),
             hover_provider: HoverProvider::new(),
             diagnostics_provider: DiagnosticsProvider::new(),
+            code_action_provider: CodeActionProvider::new(),
+            code_lens_provider: CodeLensProvider::new(),
+            formatting_provider: FormattingProvider::new(),
+            format_options: FormatOptions::default(),
+            workspace_provider: WorkspaceProvider::new(),
+            range_provider: RangeProvider::new(),
+            inlay_hint_provider: InlayHintProvider::new(),
+            signature_help_provider: SignatureHelpProvider::new(),
             snippet_support: false,
         }
     }
@@ -337,20 +786,78 @@ impl RunefileLanguageServer {
             }
         }
 
+        // Use the workspace's lint configuration
+        let root = params
+            .root_uri
+            .as_deref()
+            .and_then(|uri| uri.strip_prefix("file://"));
+        if let Some(path) = root.and_then(LintConfig::find) {
+            match LintConfig::load(&path) {
+                Ok(config) => self.diagnostics_provider.set_lint_config(config),
+                Err(e) => tracing::warn!("Ignoring lint configuration: {}", e),
+            }
+        }
+        if let Some(root) = root {
+            self.workspace_provider.set_root(Path::new(root));
+        }
+
+        if let Some(settings) = &params.initialization_options {
+            self.inlay_hint_provider
+                .set_build_args(settings.build_args.clone());
+            self.format_options = settings.format.clone();
+        }
+
         InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: TextDocumentSyncOptions {
                     open_close: true,
-                    change: 1, // Full sync
+                    change: 2, // Incremental sync
                     save: Some(SaveOptions { include_text: true }),
                 },
                 completion_provider: Some(CompletionOptions {
-                    trigger_characters: vec![" ".to_string(), "-".to_string(), "=".to_string()],
+                    trigger_characters: vec![
+                        " ".to_string(),
+                        "-".to_string(),
+                        "=".to_string(),
+                        "$".to_string(),
+                        "{".to_string(),
+                    ],
                     resolve_provider: false,
                 }),
                 hover_provider: Some(true),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: vec!["-".to_string(), "=".to_string(), ",".to_string()],
+                }),
                 definition_provider: Some(true),
+                references_provider: Some(true),
+                rename_provider: Some(RenameOptions {
+                    prepare_provider: true,
+                }),
+                code_action_provider: Some(true),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: false,
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        BUILD_STAGE_COMMAND.to_string(),
+                        BUILD_UP_TO_COMMAND.to_string(),
+                    ],
+                }),
+                inlay_hint_provider: Some(true),
+                folding_range_provider: Some(true),
+                selection_range_provider: Some(true),
+                semantic_tokens_provider: Some(SemanticTokensOptions {
+                    legend: SemanticTokensLegend {
+                        token_types: TOKEN_TYPES.iter().map(|t| t.to_string()).collect(),
+                        token_modifiers: Vec::new(),
+                    },
+                    full: true,
+                }),
                 document_formatting_provider: Some(true),
+                diagnostic_provider: Some(DiagnosticOptions {
+                    inter_file_dependencies: true,
+                    workspace_diagnostics: true,
+                }),
             },
         }
     }
@@ -359,8 +866,11 @@ impl RunefileLanguageServer {
     pub fn did_open(&self, params: &DidOpenParams) -> Vec<Diagnostic> {
         let mut parser = RunefileParser::new();
         parser.parse(&params.text_document.text);
-
-        let diagnostics = self.diagnostics_provider.get_diagnostics(&parser);
+        let diagnostics = self.document_diagnostics(
+            &params.text_document.uri,
+            &params.text_document.text,
+            &parser,
+        );
 
         let mut docs = self.documents.write().unwrap();
         docs.insert(
@@ -376,27 +886,137 @@ impl RunefileLanguageServer {
     }
 
     /// Handle document change
+    ///
+    /// Ranged changes are applied in order to the stored document, and only
+    /// the instructions they touch are parsed again.
     pub fn did_change(&self, params: &DidChangeParams) -> Vec<Diagnostic> {
-        if let Some(change) = params.content_changes.first() {
-            let mut parser = RunefileParser::new();
-            parser.parse(&change.text);
-
-            let diagnostics = self.diagnostics_provider.get_diagnostics(&parser);
-
-            let mut docs = self.documents.write().unwrap();
-            docs.insert(
-                params.text_document.uri.clone(),
-                DocumentState {
-                    content: change.text.clone(),
-                    version: params.text_document.version,
-                    parser,
+        if params.content_changes.is_empty() {
+            return Vec::new();
+        }
+
+        let mut docs = self.documents.write().unwrap();
+        let doc = docs
+            .entry(params.text_document.uri.clone())
+            .or_insert_with(|| DocumentState {
+                content: String::new(),
+                version: params.text_document.version,
+                parser: RunefileParser::new(),
+            });
+
+        for change in &params.content_changes {
+            match &change.range {
+                Some(range) => {
+                    let start = offset_at(&doc.content, range.start);
+                    let end = offset_at(&doc.content, range.end).max(start);
+                    doc.content.replace_range(start..end, &change.text);
+
+                    let start_line = range.start.line as usize;
+                    let new_end_line = start_line + change.text.matches('\n').count();
+                    doc.parser.reparse(
+                        &doc.content,
+                        start_line,
+                        (range.end.line as usize).max(start_line),
+                        new_end_line,
+                    );
+                }
+                None => {
+                    doc.content = change.text.clone();
+                    doc.parser.parse(&doc.content);
+                }
+            }
+        }
+        doc.version = params.text_document.version;
+
+        self.document_diagnostics(&params.text_document.uri, &doc.content, &doc.parser)
+    }
+
+    /// Parser and lint diagnostics of a document, or compose diagnostics
+    /// for compose files
+    fn document_diagnostics(
+        &self,
+        uri: &str,
+        content: &str,
+        parser: &RunefileParser,
+    ) -> Vec<Diagnostic> {
+        if self.compose_provider.is_compose_file(uri) {
+            return self.compose_provider.get_diagnostics(content);
+        }
+        let mut diagnostics = self.diagnostics_provider.get_diagnostics(parser);
+        diagnostics.extend(self.diagnostics_provider.get_lint_diagnostics(content));
+        diagnostics
+    }
+
+    /// Handle configuration change
+    pub fn did_change_configuration(&mut self, params: &DidChangeConfigurationParams) {
+        if let Some(settings) = &params.settings.runefile {
+            self.inlay_hint_provider
+                .set_build_args(settings.build_args.clone());
+            self.format_options = settings.format.clone();
+        }
+    }
+
+    /// Handle watched file changes, scanning the workspace again when files
+    /// were created or deleted
+    pub fn did_change_watched_files(&mut self, params: &DidChangeWatchedFilesParams) {
+        if params.changes.iter().any(|change| change.kind != 2) {
+            self.workspace_provider.scan();
+        }
+    }
+
+    /// Handle workspace diagnostic request
+    ///
+    /// Open documents are checked as edited, other files as saved on disk.
+    pub fn workspace_diagnostic(
+        &self,
+        _params: &WorkspaceDiagnosticParams,
+    ) -> WorkspaceDiagnosticReport {
+        let docs = self.documents.read().unwrap();
+        let mut items = Vec::new();
+
+        for path in self.workspace_provider.runefiles() {
+            let uri = format!("file://{}", path.display());
+            let diagnostics = match docs.get(&uri) {
+                Some(doc) => self.document_diagnostics(&uri, &doc.content, &doc.parser),
+                None => {
+                    let Ok(content) = std::fs::read_to_string(path) else {
+                        continue;
+                    };
+                    let mut parser = RunefileParser::new();
+                    parser.parse(&content);
+                    self.document_diagnostics(&uri, &content, &parser)
+                }
+            };
+            items.push(WorkspaceDocumentDiagnosticReport {
+                version: docs.get(&uri).map(|doc| doc.version),
+                uri,
+                kind: "full".to_string(),
+                items: diagnostics,
+            });
+        }
+
+        for path in self.workspace_provider.compose_files() {
+            let uri = format!("file://{}", path.display());
+            let content = match docs.get(&uri) {
+                Some(doc) => doc.content.clone(),
+                None => match std::fs::read_to_string(path) {
+                    Ok(content) => content,
+                    Err(_) => continue,
                 },
+            };
+            let mut diagnostics = self.compose_provider.get_diagnostics(&content);
+            diagnostics.extend(
+                self.workspace_provider
+                    .get_compose_diagnostics(path, &content),
             );
-
-            return diagnostics;
+            items.push(WorkspaceDocumentDiagnosticReport {
+                version: docs.get(&uri).map(|doc| doc.version),
+                uri,
+                kind: "full".to_string(),
+                items: diagnostics,
+            });
         }
 
-        Vec::new()
+        WorkspaceDiagnosticReport { items }
     }
 
     /// Handle document close
@@ -410,6 +1030,16 @@ impl RunefileLanguageServer {
         let docs = self.documents.read().unwrap();
 
         if let Some(doc) = docs.get(&params.text_document.uri) {
+            if self
+                .compose_provider
+                .is_compose_file(&params.text_document.uri)
+            {
+                return self.compose_provider.get_completions(
+                    &doc.content,
+                    params.position.line as usize,
+                    params.position.character as usize,
+                );
+            }
             return self.completion_provider.get_completions(
                 &doc.content,
                 &doc.parser,
@@ -427,6 +1057,16 @@ impl RunefileLanguageServer {
         let docs = self.documents.read().unwrap();
 
         if let Some(doc) = docs.get(&params.text_document.uri) {
+            if self
+                .compose_provider
+                .is_compose_file(&params.text_document.uri)
+            {
+                return self.compose_provider.get_hover(
+                    &doc.content,
+                    params.position.line as usize,
+                    params.position.character as usize,
+                );
+            }
             return self.hover_provider.get_hover(
                 &doc.content,
                 &doc.parser,
@@ -439,106 +1079,301 @@ impl RunefileLanguageServer {
     }
 
     /// Handle definition request
+    ///
+    /// Jumps from a stage name to the `AS` alias that defines it.
     pub fn definition(&self, params: &DefinitionParams) -> Option<Location> {
         let docs = self.documents.read().unwrap();
+        let doc = docs.get(&params.text_document.uri)?;
+
+        let stage = doc.parser.stage_at(
+            params.position.line as usize,
+            params.position.character as usize,
+        )?;
+        let definition = doc.parser.stage_definition(&stage.name)?;
+        Some(symbol_location(&params.text_document.uri, definition))
+    }
+
+    /// Handle references request
+    ///
+    /// Finds every use of the stage name under the cursor.
+    pub fn references(&self, params: &ReferenceParams) -> Vec<Location> {
+        let docs = self.documents.read().unwrap();
+        let Some(doc) = docs.get(&params.text_document.uri) else {
+            return Vec::new();
+        };
+        let Some(stage) = doc.parser.stage_at(
+            params.position.line as usize,
+            params.position.character as usize,
+        ) else {
+            return Vec::new();
+        };
+
+        let definition = doc
+            .parser
+            .stage_definition(&stage.name)
+            .filter(|_| params.context.include_declaration);
+        definition
+            .into_iter()
+            .chain(doc.parser.references_to(&stage.name))
+            .map(|stage| symbol_location(&params.text_document.uri, stage))
+            .collect()
+    }
+
+    /// Handle code action request
+    pub fn code_action(&self, params: &CodeActionParams) -> Vec<CodeAction> {
+        let docs = self.documents.read().unwrap();
 
         if let Some(doc) = docs.get(&params.text_document.uri) {
-            // Find definitions for COPY --from=stage or variable references
-            let line = params.position.line as usize;
-            let col = params.position.character as usize;
-
-            if let Some(inst) = doc.parser.instruction_at(line, col) {
-                // Check for --from=stage in COPY
-                if inst.kind == InstructionKind::Copy {
-                    if let Some(from_match) = inst.arguments.find("--from=") {
-                        let stage_start = from_match + 7;
-                        let stage_end = inst.arguments[stage_start..]
-                            .find(char::is_whitespace)
-                            .map(|i| stage_start + i)
-                            .unwrap_or(inst.arguments.len());
-                        let stage_name = &inst.arguments[stage_start..stage_end];
-
-                        // Find the FROM instruction that defines this stage
-                        for i in &doc.parser.instructions {
-                            if i.kind == InstructionKind::From
-                                && (i.arguments.contains(&format!("AS {}", stage_name))
-                                    || i.arguments.contains(&format!("as {}", stage_name)))
-                            {
-                                return Some(Location {
-                                    uri: params.text_document.uri.clone(),
-                                    range: Range {
-                                        start: Position {
-                                            line: i.line as u32,
-                                            character: 0,
-                                        },
-                                        end: Position {
-                                            line: i.line as u32,
-                                            character: i.raw.len() as u32,
-                                        },
-                                    },
-                                });
-                            }
-                        }
-                    }
-                }
-            }
+            return self.code_action_provider.get_code_actions(
+                &params.text_document.uri,
+                &doc.content,
+                &doc.parser,
+                &params.range,
+            );
         }
 
-        None
+        Vec::new()
     }
 
-    /// Handle formatting request
-    pub fn formatting(&self, params: &FormattingParams) -> Vec<TextEdit> {
+    /// Handle signature help request
+    pub fn signature_help(&self, params: &SignatureHelpParams) -> Option<SignatureHelp> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs.get(&params.text_document.uri)?;
+
+        self.signature_help_provider.get_signature_help(
+            &doc.content,
+            params.position.line as usize,
+            params.position.character as usize,
+        )
+    }
+
+    /// Handle code lens request
+    pub fn code_lens(&self, params: &CodeLensParams) -> Vec<CodeLens> {
         let docs = self.documents.read().unwrap();
 
         if let Some(doc) = docs.get(&params.text_document.uri) {
-            return self.format_document(&doc.content, &params.options);
+            return self
+                .code_lens_provider
+                .get_code_lenses(&params.text_document.uri, &doc.parser);
         }
 
         Vec::new()
     }
 
-    /// Format a document
-    fn format_document(&self, content: &str, _options: &FormattingOptions) -> Vec<TextEdit> {
-        let mut edits = Vec::new();
-        let lines: Vec<&str> = content.lines().collect();
+    /// Handle execute command request
+    ///
+    /// Build commands take a document URI and a stage index, and return the
+    /// build output.
+    ///
+    /// Builds run on their own thread, so the request is answered at once;
+    /// their outcome is passed to `notify` to send as `window/showMessage`.
+    pub fn execute_command(
+        &self,
+        params: &ExecuteCommandParams,
+        notify: impl FnOnce(ShowMessageParams) + Send + 'static,
+    ) -> Result<thread::JoinHandle<()>, String> {
+        let uri = params
+            .arguments
+            .first()
+            .and_then(|uri| uri.as_str())
+            .ok_or("Missing document argument")?;
+        let index = params
+            .arguments
+            .get(1)
+            .and_then(|index| index.as_u64())
+            .ok_or("Missing stage index argument")?;
+        let path = uri
+            .strip_prefix("file://")
+            .ok_or_else(|| format!("{} is not a file", uri))?;
 
-        for (i, line) in lines.iter().enumerate() {
-            let trimmed = line.trim();
+        let docs = self.documents.read().unwrap();
+        let doc = docs
+            .get(uri)
+            .ok_or_else(|| format!("{} is not open", uri))?;
+        self.code_lens_provider.execute(
+            &params.command,
+            Path::new(path),
+            &doc.parser,
+            index as usize,
+            notify,
+        )
+    }
 
-            // Format instruction lines
-            if !trimmed.is_empty() && !trimmed.starts_with('#') {
-                let parts: Vec<&str> = trimmed.splitn(2, char::is_whitespace).collect();
-                if !parts.is_empty() {
-                    let keyword = parts[0].to_uppercase();
-                    let args = parts.get(1).map(|s| s.trim()).unwrap_or("");
+    /// Handle inlay hint request
+    pub fn inlay_hint(&self, params: &InlayHintParams) -> Vec<InlayHint> {
+        let docs = self.documents.read().unwrap();
 
-                    let formatted = if args.is_empty() {
-                        keyword
-                    } else {
-                        format!("{} {}", keyword, args)
-                    };
+        if let Some(doc) = docs.get(&params.text_document.uri) {
+            return self.inlay_hint_provider.get_inlay_hints(
+                &doc.content,
+                &doc.parser,
+                &params.range,
+            );
+        }
 
-                    if *line != formatted {
-                        edits.push(TextEdit {
-                            range: Range {
-                                start: Position {
-                                    line: i as u32,
-                                    character: 0,
-                                },
-                                end: Position {
-                                    line: i as u32,
-                                    character: line.len() as u32,
-                                },
-                            },
-                            new_text: formatted,
-                        });
-                    }
-                }
-            }
+        Vec::new()
+    }
+
+    /// Handle folding range request
+    pub fn folding_range(&self, params: &FoldingRangeParams) -> Vec<FoldingRange> {
+        let docs = self.documents.read().unwrap();
+
+        if let Some(doc) = docs.get(&params.text_document.uri) {
+            return self.range_provider.get_folding_ranges(&doc.content);
+        }
+
+        Vec::new()
+    }
+
+    /// Handle selection range request
+    pub fn selection_range(&self, params: &SelectionRangeParams) -> Vec<SelectionRange> {
+        let docs = self.documents.read().unwrap();
+
+        if let Some(doc) = docs.get(&params.text_document.uri) {
+            return self
+                .range_provider
+                .get_selection_ranges(&doc.content, &params.positions);
+        }
+
+        Vec::new()
+    }
+
+    /// Handle semantic tokens request
+    pub fn semantic_tokens(&self, params: &SemanticTokensParams) -> Option<SemanticTokens> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs.get(&params.text_document.uri)?;
+
+        let tokens = semantic_tokens::tokens(&doc.content);
+        Some(SemanticTokens {
+            data: semantic_tokens::encode(&tokens),
+        })
+    }
+
+    /// Handle prepare rename request
+    ///
+    /// Returns the range of the stage alias or ARG under the cursor, or
+    /// `None` if there is nothing there to rename.
+    pub fn prepare_rename(&self, params: &PrepareRenameParams) -> Option<Range> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs.get(&params.text_document.uri)?;
+
+        let (_, symbol) = doc.parser.symbol_at(
+            params.position.line as usize,
+            params.position.character as usize,
+        )?;
+        Some(symbol_location(&params.text_document.uri, symbol).range)
+    }
+
+    /// Handle rename request
+    ///
+    /// Renames a stage alias together with every `--from` use, or an ARG
+    /// together with every `$ARG` and `${ARG}` use. The error message is
+    /// meant to be shown to the user.
+    pub fn rename(&self, params: &RenameParams) -> Result<WorkspaceEdit, String> {
+        let docs = self.documents.read().unwrap();
+        let doc = docs
+            .get(&params.text_document.uri)
+            .ok_or_else(|| format!("Unknown document: {}", params.text_document.uri))?;
+
+        let (kind, symbol) = doc
+            .parser
+            .symbol_at(
+                params.position.line as usize,
+                params.position.character as usize,
+            )
+            .ok_or("Only stage aliases and ARGs can be renamed")?;
+
+        let new_name = params.new_name.trim();
+        if !kind.is_valid_name(new_name) {
+            return Err(format!("'{}' is not a valid name", new_name));
+        }
+        let taken = match kind {
+            SymbolKind::Stage => doc
+                .parser
+                .stage_definition(new_name)
+                .is_some_and(|d| !d.is_stage(&symbol.name)),
+            SymbolKind::Arg => doc
+                .parser
+                .arg_definitions
+                .iter()
+                .any(|d| d.name == new_name),
+        };
+        if taken {
+            return Err(format!("'{}' is already defined", new_name));
         }
 
-        edits
+        let edits = doc
+            .parser
+            .occurrences(kind, &symbol.name)
+            .into_iter()
+            .map(|occurrence| TextEdit {
+                range: symbol_location(&params.text_document.uri, occurrence).range,
+                new_text: new_name.to_string(),
+            })
+            .collect();
+
+        let mut edit = WorkspaceEdit::default();
+        edit.changes.insert(params.text_document.uri.clone(), edits);
+        Ok(edit)
+    }
+
+    /// Handle formatting request
+    pub fn formatting(&self, params: &FormattingParams) -> Vec<TextEdit> {
+        let docs = self.documents.read().unwrap();
+
+        if let Some(doc) = docs.get(&params.text_document.uri) {
+            return self.format_document(&doc.content, &params.options);
+        }
+
+        Vec::new()
+    }
+
+    /// Format a document as a single edit replacing its content
+    fn format_document(&self, content: &str, options: &FormattingOptions) -> Vec<TextEdit> {
+        let indent = match self.format_options.continuation_indent {
+            Some(width) => " ".repeat(width),
+            None if options.insert_spaces => " ".repeat(options.tab_size as usize),
+            None => "\t".to_string(),
+        };
+        let formatted = self
+            .formatting_provider
+            .format(content, &self.format_options, &indent);
+        if formatted == content {
+            return Vec::new();
+        }
+
+        let lines = content.split('\n').count() - 1;
+        let last = content.rsplit('\n').next().unwrap_or("");
+        vec![TextEdit {
+            range: Range {
+                start: Position {
+                    line: 0,
+                    character: 0,
+                },
+                end: Position {
+                    line: lines as u32,
+                    character: last.len() as u32,
+                },
+            },
+            new_text: formatted,
+        }]
+    }
+}
+
+/// Location of a symbol
+fn symbol_location(uri: &str, symbol: &Symbol) -> Location {
+    Location {
+        uri: uri.to_string(),
+        range: Range {
+            start: Position {
+                line: symbol.line as u32,
+                character: symbol.start as u32,
+            },
+            end: Position {
+                line: symbol.line as u32,
+                character: symbol.end as u32,
+            },
+        },
     }
 }
 
@@ -548,10 +1383,48 @@ impl Default for RunefileLanguageServer {
     }
 }
 
+/// Byte offset of a position, clamped to the document
+///
+/// `character` counts UTF-16 code units, as LSP positions do by default.
+fn offset_at(content: &str, position: Position) -> usize {
+    let line_start = if position.line == 0 {
+        0
+    } else {
+        match content.match_indices('\n').nth(position.line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return content.len(),
+        }
+    };
+    let line = content[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        units += c.len_utf16() as u32;
+        if units > position.character {
+            return line_start + i;
+        }
+    }
+    line_start + line.len()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_offset_at_counts_utf16_units() {
+        let content = "FROM alpine\nLABEL note=\"héllo 🦀 x\"\nRUN true";
+        let at = |line, character| offset_at(content, Position { line, character });
+        assert_eq!(at(0, 4), 4);
+        // é is one UTF-16 unit but two bytes
+        assert_eq!(&content[at(1, 15)..at(1, 17)], "lo");
+        // 🦀 is two UTF-16 units and four bytes
+        assert_eq!(&content[at(1, 18)..at(1, 22)], "🦀 x");
+        // Past the end of a line clamps to the line, past the end of the
+        // document to the document
+        assert_eq!(at(1, 100), content.find("\nRUN").unwrap());
+        assert_eq!(at(5, 0), content.len());
+    }
+
     #[test]
     fn test_server_initialization() {
         let mut server = RunefileLanguageServer::new();
@@ -559,11 +1432,14 @@ mod tests {
             process_id: Some(1234),
             root_uri: Some("file:///test".to_string()),
             capabilities: ClientCapabilities::default(),
+            initialization_options: None,
         };
 
         let result = server.initialize(&params);
         assert!(result.capabilities.hover_provider.unwrap());
         assert!(result.capabilities.completion_provider.is_some());
+        let semantic_tokens = result.capabilities.semantic_tokens_provider.unwrap();
+        assert_eq!(semantic_tokens.legend.token_types[0], "keyword");
     }
 
     #[test]
@@ -582,6 +1458,102 @@ mod tests {
         assert!(diagnostics.is_empty());
     }
 
+    #[test]
+    fn test_stage_definition_and_references() {
+        let server = RunefileLanguageServer::new();
+        let uri = "file:///test/Runefile".to_string();
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 1,
+                text: "FROM rust AS builder\nFROM alpine\nCOPY --from=builder /a /a\n".to_string(),
+            },
+        });
+        let document = TextDocumentIdentifier { uri };
+        let at = |line, character| Position { line, character };
+
+        let definition = server
+            .definition(&DefinitionParams {
+                text_document: document.clone(),
+                position: at(2, 14),
+            })
+            .unwrap();
+        assert_eq!(definition.range.start.line, 0);
+        assert_eq!(definition.range.start.character, 13);
+        assert_eq!(definition.range.end.character, 20);
+
+        let references = |include_declaration| {
+            server
+                .references(&ReferenceParams {
+                    text_document: document.clone(),
+                    position: at(0, 15),
+                    context: ReferenceContext {
+                        include_declaration,
+                    },
+                })
+                .iter()
+                .map(|l| l.range.start.line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(references(true), vec![0, 2]);
+        assert_eq!(references(false), vec![2]);
+        assert!(server
+            .definition(&DefinitionParams {
+                text_document: document,
+                position: at(1, 7),
+            })
+            .is_none());
+    }
+
+    #[test]
+    fn test_rename() {
+        let server = RunefileLanguageServer::new();
+        let uri = "file:///test/Runefile".to_string();
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 1,
+                text: "ARG TAG=3\nFROM alpine:$TAG AS build\nFROM build AS test\nFROM alpine\nCOPY --from=build /a /a\n"
+                    .to_string(),
+            },
+        });
+        let document = TextDocumentIdentifier { uri: uri.clone() };
+        let rename = |line, character, new_name: &str| {
+            server.rename(&RenameParams {
+                text_document: document.clone(),
+                position: Position { line, character },
+                new_name: new_name.to_string(),
+            })
+        };
+
+        let edit = rename(4, 13, "compile").unwrap();
+        let lines: Vec<u32> = edit.changes[&uri]
+            .iter()
+            .map(|e| e.range.start.line)
+            .collect();
+        assert_eq!(lines, vec![1, 2, 4]);
+        assert!(edit.changes[&uri].iter().all(|e| e.new_text == "compile"));
+
+        let edit = rename(1, 14, "ALPINE_TAG").unwrap();
+        assert_eq!(edit.changes[&uri].len(), 2);
+
+        assert!(rename(4, 13, "test").is_err());
+        assert!(rename(0, 5, "1TAG").is_err());
+        // Images cannot be renamed
+        assert!(rename(3, 7, "other").is_err());
+        assert!(server
+            .prepare_rename(&PrepareRenameParams {
+                text_document: document.clone(),
+                position: Position {
+                    line: 3,
+                    character: 7
+                },
+            })
+            .is_none());
+    }
+
     #[test]
     fn test_document_with_errors() {
         let server = RunefileLanguageServer::new();
@@ -597,4 +1569,197 @@ mod tests {
         let diagnostics = server.did_open(&params);
         assert!(!diagnostics.is_empty());
     }
+
+    #[test]
+    fn test_code_lens_commands() {
+        let server = RunefileLanguageServer::new();
+        let uri = "file:///test/Runefile".to_string();
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 1,
+                text: "FROM rust AS build\nFROM alpine\n".to_string(),
+            },
+        });
+
+        let lenses = server.code_lens(&CodeLensParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        });
+        assert_eq!(lenses.len(), 4);
+
+        let execute = |command: &str, arguments: Vec<serde_json::Value>| {
+            server
+                .execute_command(
+                    &ExecuteCommandParams {
+                        command: command.to_string(),
+                        arguments,
+                    },
+                    |_| {},
+                )
+                .map(|_| ())
+        };
+        assert!(execute(BUILD_STAGE_COMMAND, vec![uri.clone().into()]).is_err());
+        assert!(execute(BUILD_STAGE_COMMAND, vec!["untitled:1".into(), 0.into()]).is_err());
+        assert_eq!(
+            execute("runefile.unknown", vec![uri.into(), 0.into()]),
+            Err("Unknown command 'runefile.unknown'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_incremental_change() {
+        let server = RunefileLanguageServer::new();
+        let uri = "file:///test/Runefile".to_string();
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 1,
+                text: "FROM alpine\nRUN echo hello\n".to_string(),
+            },
+        });
+
+        let change = |line, start, end, text: &str| TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position {
+                    line,
+                    character: start,
+                },
+                end: Position {
+                    line,
+                    character: end,
+                },
+            }),
+            range_length: None,
+            text: text.to_string(),
+        };
+        let diagnostics = server.did_change(&DidChangeParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: 2,
+            },
+            content_changes: vec![change(1, 9, 14, "héllo"), change(1, 15, 15, "\nRUNN true")],
+        });
+        assert!(diagnostics.iter().any(|d| d.range.start.line == 2));
+
+        let docs = server.documents.read().unwrap();
+        let doc = &docs[&uri];
+        assert_eq!(doc.content, "FROM alpine\nRUN echo héllo\nRUNN true\n");
+        assert_eq!(doc.version, 2);
+        assert_eq!(doc.parser.instructions.len(), 3);
+    }
+
+    #[test]
+    fn test_compose_document() {
+        let server = RunefileLanguageServer::new();
+        let uri = "file:///app/compose.yaml".to_string();
+        let diagnostics = server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "yaml".to_string(),
+                version: 1,
+                text: "services:\n  web:\n    image: nginx\n    depends_on: [db]\n    \n"
+                    .to_string(),
+            },
+        });
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("unknown service 'db'"));
+
+        let items = server.completion(&CompletionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: Position {
+                line: 4,
+                character: 4,
+            },
+        });
+        assert!(items.iter().any(|item| item.label == "ports"));
+        assert!(!items.iter().any(|item| item.label == "FROM"));
+    }
+
+    #[test]
+    fn test_workspace_diagnostic() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::write(root.join("app/Runefile"), "FROM alpine\n").unwrap();
+        std::fs::write(
+            root.join("compose.yaml"),
+            "services:\n  app:\n    build:\n      context: app\n      dockerfile: prod.runefile\n",
+        )
+        .unwrap();
+
+        let mut server = RunefileLanguageServer::new();
+        server.initialize(&InitializeParams {
+            process_id: None,
+            root_uri: Some(format!("file://{}", root.display())),
+            capabilities: ClientCapabilities::default(),
+            initialization_options: None,
+        });
+        let uri = format!("file://{}", root.join("app/Runefile").display());
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 3,
+                text: "RUN echo hello\n".to_string(),
+            },
+        });
+
+        let report = server.workspace_diagnostic(&WorkspaceDiagnosticParams::default());
+        assert_eq!(report.items.len(), 2);
+        let runefile = &report.items[0];
+        assert_eq!(runefile.uri, uri);
+        assert_eq!(runefile.version, Some(3));
+        assert!(!runefile.items.is_empty());
+        let compose = &report.items[1];
+        assert_eq!(compose.items.len(), 1);
+        assert_eq!(compose.items[0].range.start.line, 4);
+
+        // New files are picked up once the client reports them
+        std::fs::write(root.join("app/prod.runefile"), "FROM alpine\n").unwrap();
+        server.did_change_watched_files(&DidChangeWatchedFilesParams {
+            changes: vec![FileEvent {
+                uri: format!("file://{}", root.join("app/prod.runefile").display()),
+                kind: 1,
+            }],
+        });
+        let report = server.workspace_diagnostic(&WorkspaceDiagnosticParams::default());
+        assert_eq!(report.items.len(), 3);
+        assert!(report.items[2].items.is_empty());
+    }
+
+    #[test]
+    fn test_formatting_settings() {
+        let mut server = RunefileLanguageServer::new();
+        let uri = "file:///test/Runefile".to_string();
+        server.did_open(&DidOpenParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "runefile".to_string(),
+                version: 1,
+                text: "from alpine\nrun apk add \\\ncurl\n".to_string(),
+            },
+        });
+        let params = FormattingParams {
+            text_document: TextDocumentIdentifier { uri },
+            options: FormattingOptions {
+                tab_size: 2,
+                insert_spaces: true,
+            },
+        };
+
+        let edits = server.formatting(&params);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.end.line, 3);
+        assert_eq!(edits[0].new_text, "FROM alpine\nRUN apk add \\\n  curl\n");
+
+        let settings: ConfigurationSettings = serde_json::from_value(serde_json::json!({
+            "runefile": { "format": { "continuationIndent": 4 } }
+        }))
+        .unwrap();
+        server.did_change_configuration(&DidChangeConfigurationParams { settings });
+        let edits = server.formatting(&params);
+        assert_eq!(edits[0].new_text, "FROM alpine\nRUN apk add \\\n    curl\n");
+    }
 }
@@ -0,0 +1,55 @@
+//! Signature Help Provider for Runefile LSP
+//!
+//! The signatures come from `runefile-lint`, shared with the WebAssembly
+//! server.
+
+use super::server::{ParameterInformation, SignatureHelp, SignatureInformation};
+use runefile_lint::signature_help::{signature_help, Signature};
+
+/// Signature help provider for Runefile
+pub struct SignatureHelpProvider {}
+
+impl SignatureHelpProvider {
+    /// Create a new signature help provider
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Get the flags of the instruction whose flag is being typed
+    ///
+    /// Inside `RUN --mount=` the mount options are shown instead. Returns
+    /// `None` unless the cursor is in a flag before the first argument.
+    pub fn get_signature_help(
+        &self,
+        content: &str,
+        line: usize,
+        column: usize,
+    ) -> Option<SignatureHelp> {
+        signature_help(content, line, column).map(convert)
+    }
+}
+
+impl Default for SignatureHelpProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn convert(signature: Signature) -> SignatureHelp {
+    SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: signature.label,
+            documentation: signature.documentation.map(str::to_string),
+            parameters: signature
+                .parameters
+                .into_iter()
+                .map(|parameter| ParameterInformation {
+                    label: [parameter.start as u32, parameter.end as u32],
+                    documentation: Some(parameter.documentation),
+                })
+                .collect(),
+        }],
+        active_signature: Some(0),
+        active_parameter: signature.active.map(|i| i as u32),
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Parses Runefile/Dockerfile syntax for LSP features.
 
+use runefile_lint::syntax::{flags, KEYWORDS};
 use std::collections::HashMap;
 
 /// Runefile instruction kinds
@@ -30,6 +31,17 @@ pub enum InstructionKind {
 }
 
 impl InstructionKind {
+    /// Suggest the keyword closest to a misspelled one, if any is close
+    pub fn suggest(keyword: &str) -> Option<&'static str> {
+        let keyword = keyword.to_uppercase();
+        KEYWORDS
+            .iter()
+            .map(|candidate| (edit_distance(&keyword, candidate), *candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate)
+    }
+
     /// Parse instruction from string
     pub fn parse(s: &str) -> Self {
         match s.to_uppercase().as_str() {
@@ -333,6 +345,8 @@ pub struct Instruction {
     pub arguments: String,
     /// Line number (0-indexed)
     pub line: usize,
+    /// Last line (0-indexed), after any line continuations
+    pub end_line: usize,
     /// Column offset
     pub column: usize,
     /// Span of the instruction keyword
@@ -341,6 +355,68 @@ pub struct Instruction {
     pub arguments_span: Option<(usize, usize)>,
 }
 
+/// Kinds of names that can be renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Build stage alias
+    Stage,
+    /// Build argument
+    Arg,
+}
+
+impl SymbolKind {
+    /// Whether a name is valid for this kind of symbol
+    pub fn is_valid_name(&self, name: &str) -> bool {
+        let mut chars = name.chars();
+        match self {
+            Self::Stage => {
+                chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                    && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+            }
+            Self::Arg => {
+                chars
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+        }
+    }
+}
+
+/// A name in the source: a stage alias, an ARG, or a use of one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The name as written
+    pub name: String,
+    /// Line number (0-indexed)
+    pub line: usize,
+    /// Start column of the name
+    pub start: usize,
+    /// End column of the name
+    pub end: usize,
+}
+
+impl Symbol {
+    fn new(name: &str, line: usize, start: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            line,
+            start,
+            end: start + name.len(),
+        }
+    }
+
+    /// Whether the name refers to the given stage; stage names are
+    /// case-insensitive
+    pub fn is_stage(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    fn contains(&self, line: usize, column: usize) -> bool {
+        self.line == line && (self.start..=self.end).contains(&column)
+    }
+}
+
 /// Parser for Runefile/Dockerfile syntax
 pub struct RunefileParser {
     /// Parsed instructions
@@ -353,8 +429,19 @@ pub struct RunefileParser {
     pub labels: HashMap<String, String>,
     /// Build stages (FROM ... AS name)
     pub stages: Vec<String>,
+    /// Stage aliases with their positions
+    pub stage_definitions: Vec<Symbol>,
+    /// Uses of stage names in FROM, `COPY --from` and `RUN --mount=from=`
+    pub stage_references: Vec<Symbol>,
+    /// Names declared by ARG instructions
+    pub arg_definitions: Vec<Symbol>,
+    /// `$VAR` and `${VAR}` uses
+    pub variable_references: Vec<Symbol>,
     /// Parser errors
     pub errors: Vec<ParseError>,
+    /// First line and its length, of a line continuation that runs to the
+    /// end of the file
+    unclosed_continuation: Option<(usize, usize)>,
 }
 
 /// Parse error
@@ -362,7 +449,10 @@ pub struct RunefileParser {
 pub struct ParseError {
     pub message: String,
     pub line: usize,
+    /// Start column of the text the error is about
     pub column: usize,
+    /// End column of the text the error is about
+    pub end_column: usize,
     pub severity: ErrorSeverity,
 }
 
@@ -384,29 +474,105 @@ impl RunefileParser {
             envs: HashMap::new(),
             labels: HashMap::new(),
             stages: Vec::new(),
+            stage_definitions: Vec::new(),
+            stage_references: Vec::new(),
+            arg_definitions: Vec::new(),
+            variable_references: Vec::new(),
             errors: Vec::new(),
+            unclosed_continuation: None,
         }
     }
 
     /// Parse a Runefile/Dockerfile
     pub fn parse(&mut self, content: &str) {
+        let lines: Vec<&str> = content.lines().collect();
         self.instructions.clear();
-        self.errors.clear();
-        self.args.clear();
-        self.envs.clear();
-        self.labels.clear();
-        self.stages.clear();
+        self.unclosed_continuation = None;
+        self.split(&lines, 0, |_| false);
+        self.analyze();
+    }
+
+    /// Re-parse after an edit replaced lines `start_line..=old_end_line`
+    /// with lines `start_line..=new_end_line` of `content`
+    ///
+    /// Only instructions touching the edited lines are split again; later
+    /// ones are kept and moved. Stages, symbols and errors are then rebuilt
+    /// from the instructions.
+    pub fn reparse(
+        &mut self,
+        content: &str,
+        start_line: usize,
+        old_end_line: usize,
+        new_end_line: usize,
+    ) {
+        let lines: Vec<&str> = content.lines().collect();
+        let shift = |line: usize| line + new_end_line - old_end_line;
+
+        let first = self
+            .instructions
+            .iter()
+            .position(|i| i.end_line >= start_line)
+            .unwrap_or(self.instructions.len());
+        let mut from = self
+            .instructions
+            .get(first)
+            .map_or(start_line, |i| i.line.min(start_line));
+        let mut tail = self.instructions.split_off(first);
+
+        // A continuation running to the end of the file swallows every line
+        // after it, so the whole rest is split again
+        let unclosed = self.unclosed_continuation.take();
+        let split_to_end = unclosed.is_some_and(|(line, _)| line <= old_end_line);
+        if let Some((line, _)) = unclosed.filter(|_| split_to_end) {
+            from = from.min(line);
+        }
+
+        // Instructions continuing past the edit are split again too
+        let split_through = tail
+            .iter()
+            .filter(|i| i.line <= old_end_line && i.end_line > old_end_line)
+            .map(|i| shift(i.end_line))
+            .max()
+            .unwrap_or(new_end_line);
+        tail.retain(|i| i.line > old_end_line);
+
+        let stop = self.split(&lines, from, |line| !split_to_end && line > split_through);
+        for mut inst in tail {
+            inst.line = shift(inst.line);
+            inst.end_line = shift(inst.end_line);
+            if inst.line >= stop {
+                self.instructions.push(inst);
+            }
+        }
+        if stop < lines.len() {
+            self.unclosed_continuation = unclosed
+                .filter(|&(line, _)| line > old_end_line)
+                .map(|(line, length)| (shift(line), length));
+        }
 
+        self.analyze();
+    }
+
+    /// Split lines into instructions, starting at line `from`
+    ///
+    /// Stops at the first line outside a line continuation for which `stop`
+    /// returns true, and returns that line, or the line count at the end.
+    fn split(&mut self, lines: &[&str], from: usize, stop: impl Fn(usize) -> bool) -> usize {
         let mut continuation_buffer = String::new();
         let mut continuation_start_line = 0;
+        let mut continuation_start_length = 0;
 
-        for (line_num, line) in content.lines().enumerate() {
+        for (line_num, line) in lines.iter().enumerate().skip(from) {
+            if continuation_buffer.is_empty() && stop(line_num) {
+                return line_num;
+            }
             let trimmed = line.trim();
 
             // Handle line continuations
             if let Some(trimmed_without_backslash) = trimmed.strip_suffix('\\') {
                 if continuation_buffer.is_empty() {
                     continuation_start_line = line_num;
+                    continuation_start_length = line.trim_end().len();
                 }
                 continuation_buffer.push_str(trimmed_without_backslash);
                 continuation_buffer.push(' ');
@@ -420,18 +586,53 @@ impl RunefileParser {
                 continuation_buffer.clear();
                 (result, start)
             } else {
-                (trimmed.to_string(), line_num)
+                // Keep the indentation so columns match the document
+                (line.trim_end().to_string(), line_num)
             };
 
-            self.parse_line(&full_line, actual_line);
+            self.split_line(&full_line, actual_line, line_num);
         }
 
-        // Check for unclosed continuation
         if !continuation_buffer.is_empty() {
+            self.unclosed_continuation = Some((continuation_start_line, continuation_start_length));
+        }
+        lines.len()
+    }
+
+    /// Collect metadata, stages and symbols from the instructions and
+    /// validate the file
+    fn analyze(&mut self) {
+        self.errors.clear();
+        self.args.clear();
+        self.envs.clear();
+        self.labels.clear();
+        self.stages.clear();
+        self.stage_definitions.clear();
+        self.stage_references.clear();
+        self.arg_definitions.clear();
+        self.variable_references.clear();
+
+        // Stage indexes are checked against the stages defined so far
+        for inst in std::mem::take(&mut self.instructions) {
+            match &inst.kind {
+                InstructionKind::Arg => self.parse_arg(&inst.arguments),
+                InstructionKind::Env => self.parse_env(&inst.arguments),
+                InstructionKind::Label => self.parse_label(&inst.arguments),
+                InstructionKind::From => self.parse_from(&inst.arguments),
+                _ => {}
+            }
+            self.scan_stages(&inst);
+            self.scan_variables(&inst);
+            self.instructions.push(inst);
+        }
+
+        // Check for unclosed continuation
+        if let Some((line, length)) = self.unclosed_continuation {
             self.errors.push(ParseError {
                 message: "Unclosed line continuation".to_string(),
-                line: continuation_start_line,
+                line,
                 column: 0,
+                end_column: length,
                 severity: ErrorSeverity::Error,
             });
         }
@@ -440,8 +641,8 @@ impl RunefileParser {
         self.validate();
     }
 
-    /// Parse a single line
-    fn parse_line(&mut self, line: &str, line_num: usize) {
+    /// Turn a single line, with any continuations joined, into an instruction
+    fn split_line(&mut self, line: &str, line_num: usize, end_line: usize) {
         let trimmed = line.trim();
 
         // Skip empty lines
@@ -468,6 +669,7 @@ impl RunefileParser {
                 raw: line.to_string(),
                 arguments: trimmed.strip_prefix('#').unwrap_or("").trim().to_string(),
                 line: line_num,
+                end_line,
                 column: 0,
                 keyword_span: (0, 1),
                 arguments_span: Some((1, trimmed.len())),
@@ -498,34 +700,145 @@ impl RunefileParser {
             None
         };
 
-        // Extract metadata
-        match &kind {
-            InstructionKind::Arg => {
-                self.parse_arg(arguments);
-            }
-            InstructionKind::Env => {
-                self.parse_env(arguments);
-            }
-            InstructionKind::Label => {
-                self.parse_label(arguments);
-            }
-            InstructionKind::From => {
-                self.parse_from(arguments);
-            }
-            _ => {}
-        }
-
         self.instructions.push(Instruction {
             kind,
             raw: line.to_string(),
             arguments: arguments.to_string(),
             line: line_num,
+            end_line,
             column: keyword_start,
             keyword_span: (keyword_start, keyword_end),
             arguments_span,
         });
     }
 
+    /// Record the stage alias a FROM defines and the stage names an
+    /// instruction uses
+    fn scan_stages(&mut self, inst: &Instruction) {
+        let Some((start, _)) = inst.arguments_span else {
+            return;
+        };
+        let words = words(&inst.raw[start..]).map(|(offset, word)| (start + offset, word));
+
+        match inst.kind {
+            InstructionKind::From => {
+                let mut words = words.filter(|(_, word)| !word.starts_with("--"));
+                // FROM may build on an earlier stage instead of an image
+                if let Some((offset, image)) = words.next() {
+                    if self.stage_definitions.iter().any(|d| d.is_stage(image)) {
+                        self.stage_references
+                            .push(Symbol::new(image, inst.line, offset));
+                    }
+                }
+                if let (Some((_, keyword)), Some((offset, alias))) = (words.next(), words.next()) {
+                    if keyword.eq_ignore_ascii_case("AS") {
+                        self.stage_definitions
+                            .push(Symbol::new(alias, inst.line, offset));
+                    }
+                }
+            }
+            InstructionKind::Copy => {
+                for (offset, word) in words {
+                    if let Some(name) = word.strip_prefix("--from=") {
+                        self.stage_reference(name, inst.line, offset + "--from=".len());
+                    }
+                }
+            }
+            InstructionKind::Run => {
+                for (offset, word) in words {
+                    let Some(mount) = word.strip_prefix("--mount=") else {
+                        continue;
+                    };
+                    let mut option_start = offset + "--mount=".len();
+                    for option in mount.split(',') {
+                        if let Some(name) = option.strip_prefix("from=") {
+                            self.stage_reference(name, inst.line, option_start + "from=".len());
+                        }
+                        option_start += option.len() + 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the names an ARG declares and the variables an instruction
+    /// uses
+    fn scan_variables(&mut self, inst: &Instruction) {
+        let Some((start, _)) = inst.arguments_span else {
+            return;
+        };
+        if inst.kind == InstructionKind::Comment {
+            return;
+        }
+        let text = &inst.raw[start..];
+
+        if inst.kind == InstructionKind::Arg {
+            for (offset, word) in words(text) {
+                let name = word.split_once('=').map_or(word, |(name, _)| name);
+                self.arg_definitions
+                    .push(Symbol::new(name, inst.line, start + offset));
+            }
+        }
+
+        let bytes = text.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                // Skip escaped characters, including `\$`
+                b'\\' => i += 2,
+                b'$' => {
+                    let name_start = i + if bytes.get(i + 1) == Some(&b'{') {
+                        2
+                    } else {
+                        1
+                    };
+                    let name_len = text[name_start..]
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(text.len() - name_start);
+                    if name_len > 0 && !bytes[name_start].is_ascii_digit() {
+                        self.variable_references.push(Symbol::new(
+                            &text[name_start..name_start + name_len],
+                            inst.line,
+                            start + name_start,
+                        ));
+                    }
+                    i = name_start + name_len;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// Record a `--from` value, which is a stage name, a stage index or an
+    /// image
+    fn stage_reference(&mut self, name: &str, line: usize, column: usize) {
+        if name.is_empty() {
+            return;
+        }
+        if let Ok(index) = name.parse::<usize>() {
+            let stages = self
+                .instructions
+                .iter()
+                .filter(|i| i.kind == InstructionKind::From)
+                .count();
+            if index >= stages {
+                self.errors.push(ParseError {
+                    message: format!(
+                        "Stage index {} is out of range; {} stage(s) defined so far",
+                        index, stages
+                    ),
+                    line,
+                    column,
+                    end_column: column + name.len(),
+                    severity: ErrorSeverity::Error,
+                });
+            }
+            return;
+        }
+        self.stage_references.push(Symbol::new(name, line, column));
+    }
+
     fn parse_arg(&mut self, arguments: &str) {
         if let Some((name, default)) = arguments.split_once('=') {
             self.args
@@ -575,15 +888,23 @@ impl RunefileParser {
     /// Validate the parsed Runefile
     fn validate(&mut self) {
         // Check for FROM instruction
+        // Reported on the first instruction's keyword
+        let first = self
+            .instructions
+            .iter()
+            .find(|i| i.kind != InstructionKind::Comment);
         let has_from = self
             .instructions
             .iter()
             .any(|i| i.kind == InstructionKind::From);
         if !has_from {
+            let (line, (column, end_column)) =
+                first.map_or((0, (0, 0)), |i| (i.line, i.keyword_span));
             self.errors.push(ParseError {
                 message: "Runefile must have at least one FROM instruction".to_string(),
-                line: 0,
-                column: 0,
+                line,
+                column,
+                end_column,
                 severity: ErrorSeverity::Error,
             });
         }
@@ -599,12 +920,54 @@ impl RunefileParser {
                 self.errors.push(ParseError {
                     message: "First instruction must be FROM (except for ARG)".to_string(),
                     line: inst.line,
-                    column: inst.column,
+                    column: inst.keyword_span.0,
+                    end_column: inst.keyword_span.1,
                     severity: ErrorSeverity::Error,
                 });
             }
         }
 
+        self.validate_stages();
+
+        for inst in &self.instructions {
+            match &inst.kind {
+                InstructionKind::Unknown(keyword) => {
+                    let message = match InstructionKind::suggest(keyword) {
+                        Some(suggestion) => format!(
+                            "Unknown instruction '{}'; did you mean {}?",
+                            keyword, suggestion
+                        ),
+                        None => format!("Unknown instruction '{}'", keyword),
+                    };
+                    self.errors.push(ParseError {
+                        message,
+                        line: inst.line,
+                        column: inst.keyword_span.0,
+                        end_column: inst.keyword_span.1,
+                        severity: ErrorSeverity::Error,
+                    });
+                }
+                InstructionKind::Workdir if !is_absolute_path(&inst.arguments) => {
+                    let (column, end_column) = inst.arguments_span.unwrap_or(inst.keyword_span);
+                    self.errors.push(ParseError {
+                        message: "WORKDIR should be an absolute path".to_string(),
+                        line: inst.line,
+                        column,
+                        end_column,
+                        severity: ErrorSeverity::Warning,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let argument_issues: Vec<ParseError> = self
+            .instructions
+            .iter()
+            .flat_map(Self::check_arguments)
+            .collect();
+        self.errors.extend(argument_issues);
+
         // Check for deprecated MAINTAINER
         for inst in &self.instructions {
             if inst.kind == InstructionKind::Maintainer {
@@ -612,27 +975,14 @@ impl RunefileParser {
                     message: "MAINTAINER is deprecated, use LABEL maintainer=\"...\" instead"
                         .to_string(),
                     line: inst.line,
-                    column: inst.column,
+                    column: inst.keyword_span.0,
+                    end_column: inst.keyword_span.1,
                     severity: ErrorSeverity::Warning,
                 });
             }
         }
 
-        // Check for multiple CMD instructions
-        let cmd_count = self
-            .instructions
-            .iter()
-            .filter(|i| i.kind == InstructionKind::Cmd)
-            .count();
-        if cmd_count > 1 {
-            self.errors.push(ParseError {
-                message: "Multiple CMD instructions found; only the last one will be used"
-                    .to_string(),
-                line: 0,
-                column: 0,
-                severity: ErrorSeverity::Warning,
-            });
-        }
+        // Multiple CMD instructions are reported by the RUNE1004 lint rule
 
         // Check for HEALTHCHECK issues
         let healthcheck_issues: Vec<ParseError> = self
@@ -645,6 +995,53 @@ impl RunefileParser {
         self.errors.extend(healthcheck_issues);
     }
 
+    fn validate_stages(&mut self) {
+        for (i, stage) in self.stage_definitions.iter().enumerate() {
+            if self.stage_definitions[..i]
+                .iter()
+                .any(|d| d.is_stage(&stage.name))
+            {
+                self.errors.push(ParseError {
+                    message: format!("Duplicate stage name '{}'", stage.name),
+                    line: stage.line,
+                    column: stage.start,
+                    end_column: stage.end,
+                    severity: ErrorSeverity::Error,
+                });
+            }
+        }
+
+        for reference in &self.stage_references {
+            let (message, severity) = match self.stage_definition(&reference.name) {
+                Some(definition) if definition.line > reference.line => (
+                    format!(
+                        "Stage '{}' is used before it is defined on line {}",
+                        reference.name,
+                        definition.line + 1
+                    ),
+                    ErrorSeverity::Error,
+                ),
+                Some(_) => continue,
+                // Registry, tag and digest separators mark an image reference
+                None if reference.name.contains(['/', ':', '@', '.']) => continue,
+                None => (
+                    format!(
+                        "Undefined stage '{}'; it will be pulled as an image",
+                        reference.name
+                    ),
+                    ErrorSeverity::Warning,
+                ),
+            };
+            self.errors.push(ParseError {
+                message,
+                line: reference.line,
+                column: reference.start,
+                end_column: reference.end,
+                severity,
+            });
+        }
+    }
+
     fn check_healthcheck(inst: &Instruction) -> Option<ParseError> {
         let args = inst.arguments.to_uppercase();
 
@@ -655,10 +1052,12 @@ impl RunefileParser {
 
         // Must have CMD
         if !args.contains("CMD") {
+            let (column, end_column) = inst.arguments_span.unwrap_or(inst.keyword_span);
             return Some(ParseError {
                 message: "HEALTHCHECK must specify CMD or NONE".to_string(),
                 line: inst.line,
-                column: inst.column,
+                column,
+                end_column,
                 severity: ErrorSeverity::Error,
             });
         }
@@ -666,6 +1065,70 @@ impl RunefileParser {
         None
     }
 
+    /// Check the flags and arguments of an instruction, reporting each
+    /// problem on the word it is about
+    fn check_arguments(inst: &Instruction) -> Vec<ParseError> {
+        let Some((start, _)) = inst.arguments_span else {
+            return Vec::new();
+        };
+        let issue = |message: String, offset: usize, word: &str, severity| ParseError {
+            message,
+            line: inst.line,
+            column: start + offset,
+            end_column: start + offset + word.len(),
+            severity,
+        };
+        let keyword = inst.raw[inst.keyword_span.0..inst.keyword_span.1].to_uppercase();
+        let mut issues = Vec::new();
+
+        let flags = flags(&keyword);
+        let mut words = words(&inst.raw[start..]).peekable();
+        while let Some((offset, flag)) = words.next_if(|(_, word)| word.starts_with("--")) {
+            let name = flag.split('=').next().unwrap_or(flag);
+            if !flags.is_empty() && !flags.iter().any(|f| f.name == name) {
+                issues.push(issue(
+                    format!("Unknown flag '{}' for {}", name, keyword),
+                    offset,
+                    name,
+                    ErrorSeverity::Warning,
+                ));
+            }
+        }
+        let arguments: Vec<(usize, &str)> = words.collect();
+
+        match inst.kind {
+            // The JSON form and heredocs carry their own sources
+            InstructionKind::Copy | InstructionKind::Add
+                if arguments.len() == 1
+                    && !arguments[0].1.starts_with('[')
+                    && !arguments[0].1.starts_with("<<") =>
+            {
+                let (offset, source) = arguments[0];
+                issues.push(issue(
+                    format!("{} is missing a destination", keyword),
+                    offset,
+                    source,
+                    ErrorSeverity::Error,
+                ));
+            }
+            InstructionKind::Expose => {
+                for (offset, port) in arguments {
+                    if !is_valid_port(port) {
+                        issues.push(issue(
+                            format!("Invalid port '{}'", port),
+                            offset,
+                            port,
+                            ErrorSeverity::Error,
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        issues
+    }
+
     /// Get instruction at a specific position
     pub fn instruction_at(&self, line: usize, _column: usize) -> Option<&Instruction> {
         self.instructions.iter().find(|i| i.line == line)
@@ -676,12 +1139,114 @@ impl RunefileParser {
         &self.stages
     }
 
+    /// Get the alias that defines a stage
+    pub fn stage_definition(&self, name: &str) -> Option<&Symbol> {
+        self.stage_definitions.iter().find(|d| d.is_stage(name))
+    }
+
+    /// Get every use of a stage name
+    pub fn references_to<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Symbol> {
+        self.stage_references
+            .iter()
+            .filter(move |r| r.is_stage(name))
+    }
+
+    /// Get the stage alias or stage use at a specific position
+    pub fn stage_at(&self, line: usize, column: usize) -> Option<&Symbol> {
+        self.stage_definitions
+            .iter()
+            .chain(&self.stage_references)
+            .find(|stage| stage.contains(line, column))
+    }
+
+    /// Get the stage or ARG name at a specific position that can be
+    /// renamed
+    ///
+    /// Stage names must be defined in the file and variables must be
+    /// declared with ARG.
+    pub fn symbol_at(&self, line: usize, column: usize) -> Option<(SymbolKind, &Symbol)> {
+        if let Some(stage) = self.stage_at(line, column) {
+            return self
+                .stage_definition(&stage.name)
+                .map(|_| (SymbolKind::Stage, stage));
+        }
+        self.arg_definitions
+            .iter()
+            .chain(&self.variable_references)
+            .find(|symbol| symbol.contains(line, column))
+            .filter(|symbol| self.arg_definitions.iter().any(|d| d.name == symbol.name))
+            .map(|symbol| (SymbolKind::Arg, symbol))
+    }
+
+    /// Get every definition and use of a symbol
+    pub fn occurrences(&self, kind: SymbolKind, name: &str) -> Vec<&Symbol> {
+        match kind {
+            SymbolKind::Stage => self
+                .stage_definitions
+                .iter()
+                .chain(&self.stage_references)
+                .filter(|symbol| symbol.is_stage(name))
+                .collect(),
+            SymbolKind::Arg => self
+                .arg_definitions
+                .iter()
+                .chain(&self.variable_references)
+                .filter(|symbol| symbol.name == name)
+                .collect(),
+        }
+    }
+
     /// Get all defined ARGs
     pub fn get_args(&self) -> &HashMap<String, Option<String>> {
         &self.args
     }
 }
 
+/// Whether a WORKDIR path is absolute; paths starting with a variable are
+/// assumed to be
+pub fn is_absolute_path(path: &str) -> bool {
+    let path = path.trim_matches(|c| c == '"' || c == '\'');
+    path.is_empty() || path.starts_with(['/', '$'])
+}
+
+/// Levenshtein distance between two strings
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Whitespace-separated words of a line with their byte offsets
+/// Whether an EXPOSE argument is a port or port range with an optional
+/// protocol; variables are checked at build time
+fn is_valid_port(value: &str) -> bool {
+    if value.contains('$') {
+        return true;
+    }
+    let (ports, protocol) = value.split_once('/').unwrap_or((value, "tcp"));
+    let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+    matches!(
+        protocol.to_ascii_lowercase().as_str(),
+        "tcp" | "udp" | "sctp"
+    ) && matches!(
+        (first.parse::<u16>(), last.parse::<u16>()),
+        (Ok(first), Ok(last)) if first <= last
+    )
+}
+
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+}
+
 impl Default for RunefileParser {
     fn default() -> Self {
         Self::new()
@@ -749,6 +1314,106 @@ CMD ["myapp"]
         assert_eq!(parser.stages[0], "builder");
     }
 
+    #[test]
+    fn test_stage_references() {
+        let content = r#"FROM rust:1.70 AS Builder
+RUN --mount=type=cache,from=builder,target=/cache cargo build
+  FROM builder AS tester
+FROM alpine
+COPY --from=builder /app /app
+COPY --from=tester /out /out
+COPY --from=nginx:alpine /etc/nginx /etc/nginx
+COPY --from=3 /x /x
+"#;
+
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+
+        let definition = parser.stage_definition("builder").unwrap();
+        assert_eq!((definition.line, definition.start), (0, 18));
+        let uses: Vec<(usize, usize)> = parser
+            .references_to("BUILDER")
+            .map(|r| (r.line, r.start))
+            .collect();
+        assert_eq!(uses, vec![(1, 28), (2, 7), (4, 12)]);
+
+        // Indentation is kept, so the alias is found by column
+        assert_eq!(parser.stage_at(2, 20).unwrap().name, "tester");
+        assert!(parser.stage_at(2, 2).is_none());
+
+        // Images are not stages; index 3 is past the three stages so far
+        assert_eq!(parser.errors.len(), 1);
+        assert!(parser.errors[0].message.contains("index 3"));
+    }
+
+    #[test]
+    fn test_undefined_stages() {
+        let content = r#"FROM alpine AS base
+COPY --from=buidler /app /app
+COPY --from=later /app /app
+FROM base AS later
+FROM base AS LATER
+"#;
+
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+
+        let messages: Vec<(usize, ErrorSeverity)> =
+            parser.errors.iter().map(|e| (e.line, e.severity)).collect();
+        assert_eq!(
+            messages,
+            vec![
+                (4, ErrorSeverity::Error),
+                (1, ErrorSeverity::Warning),
+                (2, ErrorSeverity::Error),
+            ]
+        );
+        assert!(parser.errors[1].message.contains("'buidler'"));
+    }
+
+    #[test]
+    fn test_arg_symbols() {
+        let content = r#"ARG VERSION=1.0
+FROM alpine:${VERSION}
+ARG VERSION
+ENV HOME_DIR=$HOME \$VERSION
+RUN echo "$VERSION-${VERSION:-dev}" $1
+"#;
+
+        let mut parser = RunefileParser::new();
+        parser.parse(content);
+
+        let occurrences: Vec<(usize, usize)> = parser
+            .occurrences(SymbolKind::Arg, "VERSION")
+            .iter()
+            .map(|s| (s.line, s.start))
+            .collect();
+        assert_eq!(occurrences, vec![(0, 4), (2, 4), (1, 14), (4, 11), (4, 21)]);
+
+        let (kind, symbol) = parser.symbol_at(4, 23).unwrap();
+        assert_eq!((kind, symbol.name.as_str()), (SymbolKind::Arg, "VERSION"));
+        // HOME is not an ARG, so it cannot be renamed
+        assert!(parser.symbol_at(3, 15).is_none());
+
+        assert!(SymbolKind::Arg.is_valid_name("_BUILD_2"));
+        assert!(!SymbolKind::Arg.is_valid_name("2FAST"));
+        assert!(SymbolKind::Stage.is_valid_name("build-1.0"));
+        assert!(!SymbolKind::Stage.is_valid_name("build stage"));
+    }
+
+    #[test]
+    fn test_unknown_instructions() {
+        let mut parser = RunefileParser::new();
+        parser.parse("FORM alpine\nWORKDIR app\nWORKDIR $HOME/app\nFETCH x\n");
+
+        let messages: Vec<&str> = parser.errors.iter().map(|e| e.message.as_str()).collect();
+        assert!(messages.contains(&"Unknown instruction 'FORM'; did you mean FROM?"));
+        assert!(messages.contains(&"Unknown instruction 'FETCH'"));
+        assert!(messages.contains(&"WORKDIR should be an absolute path"));
+        assert_eq!(InstructionKind::suggest("entrypiont"), Some("ENTRYPOINT"));
+        assert_eq!(InstructionKind::suggest("EXPOSED"), Some("EXPOSE"));
+    }
+
     #[test]
     fn test_missing_from() {
         let content = r#"
@@ -777,4 +1442,53 @@ MAINTAINER John Doe
             .iter()
             .any(|e| e.severity == ErrorSeverity::Warning && e.message.contains("deprecated")));
     }
+
+    #[test]
+    fn test_reparse_matches_parse() {
+        let summary = |parser: &RunefileParser| {
+            let instructions: Vec<_> = parser
+                .instructions
+                .iter()
+                .map(|i| (i.line, i.end_line, i.raw.clone()))
+                .collect();
+            let errors: Vec<_> = parser
+                .errors
+                .iter()
+                .map(|e| (e.line, e.message.clone()))
+                .collect();
+            (instructions, errors, parser.stages.clone())
+        };
+
+        let before = "FROM rust AS build\nRUN cargo build \\\n    --release\n\nFROM alpine\nCOPY --from=build /a /b\nCMD [\"app\"]\n";
+        // (content after the edit, start line, old end line, new end line)
+        let edits = [
+            // Edit inside a continued instruction
+            ("FROM rust AS build\nRUN cargo test \\\n    --release\n\nFROM alpine\nCOPY --from=build /a /b\nCMD [\"app\"]\n", 1, 1, 1),
+            // Break the continuation
+            ("FROM rust AS build\nRUN cargo build\n    --release\n\nFROM alpine\nCOPY --from=build /a /b\nCMD [\"app\"]\n", 1, 1, 1),
+            // Insert lines, renaming the stage
+            ("FROM rust AS compile\nUSER app\nWORKDIR /src\nRUN cargo build \\\n    --release\n\nFROM alpine\nCOPY --from=build /a /b\nCMD [\"app\"]\n", 0, 0, 2),
+            // Delete lines, leaving a continuation running to the end
+            ("FROM rust AS build\nRUN cargo build \\\n", 2, 6, 2),
+            // Join the blank line into a continuation
+            ("FROM rust AS build\nRUN cargo build \\\n    --release \\\n\nFROM alpine\nCOPY --from=build /a /b\nCMD [\"app\"]\n", 2, 2, 2),
+        ];
+
+        for (after, start_line, old_end_line, new_end_line) in edits {
+            let mut incremental = RunefileParser::new();
+            incremental.parse(before);
+            incremental.reparse(after, start_line, old_end_line, new_end_line);
+
+            let mut full = RunefileParser::new();
+            full.parse(after);
+            assert_eq!(summary(&incremental), summary(&full), "{}", after);
+        }
+
+        // Closing a continuation that ran to the end
+        let mut parser = RunefileParser::new();
+        parser.parse("FROM alpine\nRUN echo \\\n");
+        parser.reparse("FROM alpine\nRUN echo\nUSER app\n", 1, 2, 2);
+        assert!(parser.errors.is_empty());
+        assert_eq!(parser.instructions.len(), 3);
+    }
 }
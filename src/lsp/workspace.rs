@@ -0,0 +1,333 @@
+//! Workspace Provider for Runefile LSP
+//!
+//! Finds the Runefiles and compose files under the workspace root, and checks
+//! that compose builds point at build files that exist.
+
+use super::server::{Diagnostic, Position, Range};
+use super::syntax::RunefileParser;
+use crate::compose::config::BuildConfig;
+use crate::compose::parser::DEFAULT_COMPOSE_FILES;
+use crate::compose::ComposeParser;
+use crate::image::builder::{DEFAULT_BUILD_FILE, DOCKERFILE_NAME};
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// Directories that are never scanned, besides hidden ones
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Workspace provider for Runefile
+pub struct WorkspaceProvider {
+    root: Option<PathBuf>,
+    runefiles: Vec<PathBuf>,
+    compose_files: Vec<PathBuf>,
+}
+
+impl WorkspaceProvider {
+    /// Create a new workspace provider
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            runefiles: Vec::new(),
+            compose_files: Vec::new(),
+        }
+    }
+
+    /// Set the workspace root and scan it
+    pub fn set_root(&mut self, root: &Path) {
+        self.root = Some(root.to_path_buf());
+        self.scan();
+    }
+
+    /// Scan the workspace root again for Runefiles and compose files
+    pub fn scan(&mut self) {
+        self.runefiles.clear();
+        self.compose_files.clear();
+        let Some(root) = &self.root else {
+            return;
+        };
+
+        let files = WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !is_skipped(entry))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file());
+        for entry in files {
+            let name = entry.file_name().to_string_lossy();
+            if is_runefile(&name) {
+                self.runefiles.push(entry.into_path());
+            } else if DEFAULT_COMPOSE_FILES.contains(&name.as_ref()) {
+                self.compose_files.push(entry.into_path());
+            }
+        }
+        self.runefiles.sort();
+        self.compose_files.sort();
+    }
+
+    /// Runefiles found in the workspace
+    pub fn runefiles(&self) -> &[PathBuf] {
+        &self.runefiles
+    }
+
+    /// Compose files found in the workspace
+    pub fn compose_files(&self) -> &[PathBuf] {
+        &self.compose_files
+    }
+
+    /// Check the service builds of a compose file
+    ///
+    /// Reports build contexts and build files that do not exist, and targets
+    /// the build file does not define.
+    pub fn get_compose_diagnostics(&self, path: &Path, content: &str) -> Vec<Diagnostic> {
+        let config = match ComposeParser::parse_str(content) {
+            Ok(config) => config,
+            Err(e) => return vec![diagnostic(Range::default(), 1, e.to_string())],
+        };
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut services: Vec<_> = config.services.iter().collect();
+        services.sort_by_key(|(name, _)| name.as_str());
+        let mut diagnostics = Vec::new();
+
+        for (name, service) in services {
+            let (context, dockerfile, target) = match &service.build {
+                Some(BuildConfig::Simple(context)) => (context.as_str(), None, None),
+                Some(BuildConfig::Full(full)) => (
+                    full.context.as_deref().unwrap_or("."),
+                    full.dockerfile.as_deref(),
+                    full.target.as_deref(),
+                ),
+                None => continue,
+            };
+
+            let context_dir = dir.join(context);
+            if !context_dir.is_dir() {
+                diagnostics.push(diagnostic(
+                    key_range(content, name, &["context", "build"]),
+                    1,
+                    format!(
+                        "Build context '{}' of service '{}' does not exist",
+                        context, name
+                    ),
+                ));
+                continue;
+            }
+
+            let build_file = match dockerfile {
+                Some(file) => context_dir.join(file),
+                None => [DEFAULT_BUILD_FILE, DOCKERFILE_NAME]
+                    .iter()
+                    .map(|file| context_dir.join(file))
+                    .find(|path| path.is_file())
+                    .unwrap_or_else(|| context_dir.join(DEFAULT_BUILD_FILE)),
+            };
+            let Ok(build_content) = std::fs::read_to_string(&build_file) else {
+                let message = match dockerfile {
+                    Some(file) => {
+                        format!("Build file '{}' of service '{}' does not exist", file, name)
+                    }
+                    None => format!(
+                        "No {} or {} in build context '{}' of service '{}'",
+                        DEFAULT_BUILD_FILE, DOCKERFILE_NAME, context, name
+                    ),
+                };
+                diagnostics.push(diagnostic(
+                    key_range(content, name, &["dockerfile", "context", "build"]),
+                    1,
+                    message,
+                ));
+                continue;
+            };
+
+            if let Some(target) = target {
+                let mut parser = RunefileParser::new();
+                parser.parse(&build_content);
+                if !parser.stage_definitions.iter().any(|s| s.name == target) {
+                    diagnostics.push(diagnostic(
+                        key_range(content, name, &["target", "build"]),
+                        2,
+                        format!(
+                            "Stage '{}' is not defined in {}",
+                            target,
+                            build_file.display()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+impl Default for WorkspaceProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a file name is a Runefile or Dockerfile
+fn is_runefile(name: &str) -> bool {
+    name == DEFAULT_BUILD_FILE
+        || name == DOCKERFILE_NAME
+        || name.ends_with(".runefile")
+        || name.ends_with(".dockerfile")
+        || name.starts_with("Runefile.")
+        || name.starts_with("Dockerfile.")
+}
+
+/// Whether a directory is left out of the scan
+fn is_skipped(entry: &DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    entry.file_type().is_dir() && (name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref()))
+}
+
+fn diagnostic(range: Range, severity: u8, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        code: None,
+        source: Some("runefile-lsp".to_string()),
+        message,
+    }
+}
+
+/// Range of the value of the first of `keys` found in a service's block,
+/// falling back to the service's own key
+fn key_range(content: &str, service: &str, keys: &[&str]) -> Range {
+    let lines: Vec<&str> = content.lines().collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let is_key = |line: &str, key: &str| {
+        let trimmed = line.trim_start().trim_start_matches("- ");
+        let trimmed = trimmed.trim_start_matches(['"', '\'']);
+        trimmed
+            .strip_prefix(key)
+            .map(|rest| rest.trim_start_matches(['"', '\'']))
+            .is_some_and(|rest| rest.starts_with(':'))
+    };
+
+    let Some(start) = lines
+        .iter()
+        .position(|line| indent(line) > 0 && is_key(line, service))
+    else {
+        return Range::default();
+    };
+    let service_indent = indent(lines[start]);
+    let block: Vec<usize> = (start + 1..lines.len())
+        .take_while(|&i| lines[i].trim().is_empty() || indent(lines[i]) > service_indent)
+        .collect();
+
+    let line = keys
+        .iter()
+        .find_map(|key| block.iter().copied().find(|&i| is_key(lines[i], key)))
+        .unwrap_or(start);
+    let text = lines[line];
+    let key_start = indent(text);
+    let colon = text[key_start..]
+        .find(':')
+        .map_or(text.len(), |i| key_start + i);
+    let value = &text[(colon + 1).min(text.len())..];
+    let value_start = text.len() - value.trim_start().len();
+    let (start, end) = if value_start < text.trim_end().len() {
+        (value_start, text.trim_end().len())
+    } else {
+        (key_start, colon)
+    };
+
+    Range {
+        start: Position {
+            line: line as u32,
+            character: start as u32,
+        },
+        end: Position {
+            line: line as u32,
+            character: end as u32,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scan() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        for file in [
+            "Runefile",
+            "web/Dockerfile",
+            "web/dev.runefile",
+            "web/compose.yaml",
+            "target/Runefile",
+            ".git/Dockerfile",
+            "README.md",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "FROM alpine\n").unwrap();
+        }
+
+        let mut workspace = WorkspaceProvider::new();
+        workspace.set_root(root);
+        assert_eq!(
+            workspace.runefiles(),
+            &[
+                root.join("Runefile"),
+                root.join("web/Dockerfile"),
+                root.join("web/dev.runefile")
+            ]
+        );
+        assert_eq!(workspace.compose_files(), &[root.join("web/compose.yaml")]);
+    }
+
+    #[test]
+    fn test_compose_diagnostics() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api")).unwrap();
+        fs::write(root.join("api/Runefile"), "FROM rust AS build\n").unwrap();
+
+        let content = "services:\n  api:\n    build:\n      context: ./api\n      target: release\n  web:\n    build:\n      context: .\n      dockerfile: web.runefile\n  db:\n    build: ./db\n  cache:\n    image: redis\n";
+        let compose = root.join("compose.yaml");
+        let diagnostics = WorkspaceProvider::new().get_compose_diagnostics(&compose, content);
+
+        let found: Vec<(u32, u32, u8, &str)> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.range.start.line,
+                    d.range.start.character,
+                    d.severity.unwrap(),
+                    d.message.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].0, 4);
+        assert_eq!(found[0].1, 14);
+        assert_eq!(found[0].2, 2);
+        assert!(found[0].3.starts_with("Stage 'release' is not defined"));
+        assert_eq!(
+            found[1],
+            (
+                10,
+                11,
+                1,
+                "Build context './db' of service 'db' does not exist"
+            )
+        );
+        assert_eq!(
+            found[2],
+            (
+                8,
+                18,
+                1,
+                "Build file 'web.runefile' of service 'web' does not exist"
+            )
+        );
+
+        let diagnostics = WorkspaceProvider::new().get_compose_diagnostics(&compose, "services: [");
+        assert_eq!(diagnostics.len(), 1);
+    }
+}
@@ -4,13 +4,56 @@
 
 use clap::{Parser, Subcommand};
 use rune::compose::{ComposeOrchestrator, ComposeParser};
-use rune::container::{ContainerConfig, ContainerManager};
-use rune::error::Result;
+use rune::container::{
+    ContainerConfig, ContainerManager, ContainerStatus, HealthcheckConfig, HealthcheckOverrides,
+    LogConfig,
+};
+use rune::daemon::{
+    write_bundle, AuditEntry, AuditLog, Caller, Context, ContextStore, DaemonClient, LogBuffer,
+    TlsFiles, AUDIT_FILTERS,
+};
+use rune::error::{Result, ResultExt, RuneError};
+use rune::filter::Filters;
+use rune::image::analyze::{ChangeKind, LayerAnalysis};
 use rune::image::builder::{BuildContext, ImageBuilder};
-use rune::swarm::{SwarmCluster, SwarmConfig};
-use rune::tui::App;
+use rune::image::{
+    BuildOutput, ImagePuller, ImageStore, ProgressMode, ProgressPrinter, RegistryAuths,
+    RegistryHosts,
+};
+use rune::network::bridge::NetworkManager;
+use rune::network::{NetworkConfig, Veth};
+use rune::plugin::PluginManager;
+use rune::registry::auth::AuthMode;
+use rune::registry::notifications::EndpointConfig;
+use rune::registry::proxy::ProxyConfig;
+use rune::registry::s3::S3Config;
+use rune::registry::server::RegistryConfig;
+use rune::registry::RegistryServer;
+use rune::runtime::criu::{CheckpointOptions, TcpMode};
+use rune::runtime::init;
+use rune::runtime::seccomp::read_seccomp_profiles;
+use rune::runtime::signal::parse_signal;
+use rune::runtime::terminal::{self, AttachEnd, DetachKeys, DEFAULT_DETACH_KEYS};
+use rune::storage::{VolumeDriver, VolumeManager};
+use rune::swarm::cluster::{NodeUpdate, TokenType, DEFAULT_STATE_DIR};
+use rune::swarm::logs::DEFAULT_CONTAINER_ROOT;
+use rune::swarm::service::{
+    ContainerSpec, EndpointSpec, Mount, Placement, PlacementPreference, PortConfig, ServiceMode,
+    ServiceSpec, SpreadOver, TaskSpec,
+};
+use rune::swarm::stack::{parse_bytes, parse_duration};
+use rune::swarm::{
+    Constraint, FileLogSource, KeyStore, NodeRole, Service, StackDeployment, SwarmCluster,
+    SwarmConfig, Task, TaskState,
+};
+use rune::tui::stats::{format_bytes, sample_rates};
+use rune::tui::{App, TuiConfig};
+use runefile_lint::{LintConfig, Severity, RULES};
+use std::os::fd::AsFd;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
 use tracing_subscriber::EnvFilter;
 
 /// Rune - Docker-compatible container service
@@ -40,18 +83,115 @@ enum Commands {
         /// Run in detached mode
         #[arg(short, long)]
         detach: bool,
+        /// Keep standard input open
+        #[arg(short, long)]
+        interactive: bool,
+        /// Allocate a pseudo-terminal
+        #[arg(short, long)]
+        tty: bool,
+        /// Keys that detach from the container, e.g. ctrl-p,ctrl-q
+        #[arg(long)]
+        detach_keys: Option<String>,
+        /// Remove the container and its anonymous volumes when it exits
+        #[arg(long)]
+        rm: bool,
         /// Port mapping (host:container)
         #[arg(short, long)]
         publish: Vec<String>,
         /// Environment variable
         #[arg(short, long)]
         env: Vec<String>,
-        /// Volume mount
+        /// Volume mount (host:container[:ro]), or a container path for an
+        /// anonymous volume
         #[arg(short, long)]
         volume: Vec<String>,
         /// Working directory
         #[arg(short, long)]
         workdir: Option<String>,
+        /// Network to connect the container to; given more than once, the
+        /// container also joins the others
+        #[arg(long)]
+        network: Vec<String>,
+        /// IPv4 address on the first network
+        #[arg(long)]
+        ip: Option<String>,
+        /// MAC address on the first network
+        #[arg(long)]
+        mac_address: Option<String>,
+        /// Name the container is also known by on the first network
+        #[arg(long)]
+        network_alias: Vec<String>,
+        /// Log driver for the container
+        #[arg(long)]
+        log_driver: Option<String>,
+        /// Log driver option (key=value)
+        #[arg(long)]
+        log_opt: Vec<String>,
+        /// Add a Linux capability, or ALL
+        #[arg(long)]
+        cap_add: Vec<String>,
+        /// Drop a Linux capability, or ALL
+        #[arg(long)]
+        cap_drop: Vec<String>,
+        /// Give the container every capability and no seccomp filter
+        #[arg(long)]
+        privileged: bool,
+        /// Security option, e.g. seccomp=profile.json or seccomp=unconfined
+        #[arg(long)]
+        security_opt: Vec<String>,
+        /// User namespace mode; `host` opts out of the daemon's remapping
+        #[arg(long)]
+        userns: Option<String>,
+        /// Run an init inside the container that forwards signals and reaps
+        /// processes
+        #[arg(long)]
+        init: bool,
+        /// Add a host device (/dev/host[:/dev/container][:rwm]) or a CDI
+        /// device (vendor.com/class=name)
+        #[arg(long)]
+        device: Vec<String>,
+        /// GPUs to add from the CDI specs: all, a count or device=<id>,...
+        #[arg(long)]
+        gpus: Option<String>,
+        /// Add an OCI lifecycle hook: <stage>=/path/to/binary [args...]
+        #[arg(long)]
+        hook: Vec<String>,
+        /// Set a resource limit: <name>=<soft>[:<hard>], -1 for unlimited
+        #[arg(long)]
+        ulimit: Vec<String>,
+        /// Set a namespaced kernel parameter: <name>=<value>
+        #[arg(long)]
+        sysctl: Vec<String>,
+        /// Keep the OOM killer from killing the container
+        #[arg(long)]
+        oom_kill_disable: bool,
+        /// Adjust the container's OOM score (-1000 to 1000)
+        #[arg(long, allow_hyphen_values = true)]
+        oom_score_adj: Option<i32>,
+        /// Signal that stops the container, SIGTERM by default
+        #[arg(long)]
+        stop_signal: Option<String>,
+        /// Seconds the container gets to stop before it is killed
+        #[arg(long)]
+        stop_timeout: Option<u64>,
+        /// Command to check the container's health, run by a shell
+        #[arg(long)]
+        health_cmd: Option<String>,
+        /// Time between health checks (e.g. 30s, 1m30s)
+        #[arg(long)]
+        health_interval: Option<String>,
+        /// Time before a health check fails
+        #[arg(long)]
+        health_timeout: Option<String>,
+        /// Time after starting in which failed health checks do not count
+        #[arg(long)]
+        health_start_period: Option<String>,
+        /// Failed health checks in a row before the container is unhealthy
+        #[arg(long)]
+        health_retries: Option<u32>,
+        /// Disable the image's healthcheck
+        #[arg(long)]
+        no_healthcheck: bool,
         /// Command to run
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
@@ -64,21 +204,127 @@ enum Commands {
         /// Container name
         #[arg(long)]
         name: Option<String>,
+        /// Keep standard input open
+        #[arg(short, long)]
+        interactive: bool,
+        /// Allocate a pseudo-terminal
+        #[arg(short, long)]
+        tty: bool,
+        /// Network to connect the container to; given more than once, the
+        /// container also joins the others
+        #[arg(long)]
+        network: Vec<String>,
+        /// IPv4 address on the first network
+        #[arg(long)]
+        ip: Option<String>,
+        /// MAC address on the first network
+        #[arg(long)]
+        mac_address: Option<String>,
+        /// Name the container is also known by on the first network
+        #[arg(long)]
+        network_alias: Vec<String>,
+        /// Log driver for the container
+        #[arg(long)]
+        log_driver: Option<String>,
+        /// Log driver option (key=value)
+        #[arg(long)]
+        log_opt: Vec<String>,
+        /// Add a Linux capability, or ALL
+        #[arg(long)]
+        cap_add: Vec<String>,
+        /// Drop a Linux capability, or ALL
+        #[arg(long)]
+        cap_drop: Vec<String>,
+        /// Give the container every capability and no seccomp filter
+        #[arg(long)]
+        privileged: bool,
+        /// Security option, e.g. seccomp=profile.json or seccomp=unconfined
+        #[arg(long)]
+        security_opt: Vec<String>,
+        /// User namespace mode; `host` opts out of the daemon's remapping
+        #[arg(long)]
+        userns: Option<String>,
+        /// Run an init inside the container that forwards signals and reaps
+        /// processes
+        #[arg(long)]
+        init: bool,
+        /// Add a host device (/dev/host[:/dev/container][:rwm]) or a CDI
+        /// device (vendor.com/class=name)
+        #[arg(long)]
+        device: Vec<String>,
+        /// GPUs to add from the CDI specs: all, a count or device=<id>,...
+        #[arg(long)]
+        gpus: Option<String>,
+        /// Add an OCI lifecycle hook: <stage>=/path/to/binary [args...]
+        #[arg(long)]
+        hook: Vec<String>,
+        /// Set a resource limit: <name>=<soft>[:<hard>], -1 for unlimited
+        #[arg(long)]
+        ulimit: Vec<String>,
+        /// Set a namespaced kernel parameter: <name>=<value>
+        #[arg(long)]
+        sysctl: Vec<String>,
+        /// Keep the OOM killer from killing the container
+        #[arg(long)]
+        oom_kill_disable: bool,
+        /// Adjust the container's OOM score (-1000 to 1000)
+        #[arg(long, allow_hyphen_values = true)]
+        oom_score_adj: Option<i32>,
+        /// Signal that stops the container, SIGTERM by default
+        #[arg(long)]
+        stop_signal: Option<String>,
+        /// Seconds the container gets to stop before it is killed
+        #[arg(long)]
+        stop_timeout: Option<u64>,
+        /// Command to check the container's health, run by a shell
+        #[arg(long)]
+        health_cmd: Option<String>,
+        /// Time between health checks (e.g. 30s, 1m30s)
+        #[arg(long)]
+        health_interval: Option<String>,
+        /// Time before a health check fails
+        #[arg(long)]
+        health_timeout: Option<String>,
+        /// Time after starting in which failed health checks do not count
+        #[arg(long)]
+        health_start_period: Option<String>,
+        /// Failed health checks in a row before the container is unhealthy
+        #[arg(long)]
+        health_retries: Option<u32>,
+        /// Disable the image's healthcheck
+        #[arg(long)]
+        no_healthcheck: bool,
     },
 
     /// Start a container
     Start {
         /// Container ID or name
         container: String,
+        /// Restore from this checkpoint
+        #[arg(long)]
+        checkpoint: Option<String>,
+        /// Directory to find the checkpoint in
+        #[arg(long)]
+        checkpoint_dir: Option<PathBuf>,
     },
 
     /// Stop a container
     Stop {
         /// Container ID or name
         container: String,
-        /// Timeout in seconds
-        #[arg(short, long, default_value = "10")]
-        time: u64,
+        /// Seconds to wait before killing it, the container's stop timeout
+        /// by default
+        #[arg(short, long)]
+        time: Option<u64>,
+    },
+
+    /// Send a signal to a container
+    Kill {
+        /// Container ID or name
+        container: String,
+        /// Signal to send, by name or number
+        #[arg(short, long, default_value = "KILL")]
+        signal: String,
     },
 
     /// Restart a container
@@ -106,6 +352,9 @@ enum Commands {
         /// Only show numeric IDs
         #[arg(short, long)]
         quiet: bool,
+        /// Filter output (e.g., status=exited, label=tier=web, ancestor=nginx)
+        #[arg(short, long)]
+        filter: Vec<String>,
     },
 
     /// Show container logs
@@ -120,6 +369,18 @@ enum Commands {
         tail: Option<usize>,
     },
 
+    /// Show a container's resource use as the daemon recorded it
+    Stats {
+        /// Container ID or name
+        container: String,
+        /// Show every sample in this period (e.g. 1h) instead of the latest
+        #[arg(long)]
+        history: Option<String>,
+        /// Context of the daemon instead of the current one
+        #[arg(long)]
+        context: Option<String>,
+    },
+
     /// Execute command in container
     Exec {
         /// Container ID or name
@@ -137,13 +398,13 @@ enum Commands {
 
     /// Build an image from a Runefile
     Build {
-        /// Build context path
+        /// Build context: a path, a git repository URL (url#ref:subdir) or a tarball URL
         #[arg(default_value = ".")]
-        path: PathBuf,
+        path: String,
         /// Name and optionally tag
         #[arg(short, long)]
         tag: Vec<String>,
-        /// Buildfile path
+        /// Buildfile path (relative to the context for a remote context)
         #[arg(short, long)]
         file: Option<PathBuf>,
         /// Build arguments
@@ -155,6 +416,31 @@ enum Commands {
         /// Target build stage
         #[arg(long)]
         target: Option<String>,
+        /// Progress output: auto, plain, tty or json
+        #[arg(long, default_value = "auto")]
+        progress: String,
+        /// Export the build: type=local|tar|oci,dest=<path>
+        #[arg(short, long)]
+        output: Vec<String>,
+        /// Most stages built at once (default: number of CPUs)
+        #[arg(long)]
+        max_parallel: Option<usize>,
+        /// Additional named context: name=path|url|docker-image://ref
+        #[arg(long)]
+        build_context: Vec<String>,
+    },
+
+    /// Check a Runefile against the lint rules
+    Lint {
+        /// Runefile, or a directory containing one
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Lint configuration (default: the closest .runelint.toml)
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// List the rules instead of linting
+        #[arg(long)]
+        list_rules: bool,
     },
 
     /// Manage images
@@ -175,6 +461,18 @@ enum Commands {
         command: VolumeCommands,
     },
 
+    /// Manage plugins
+    Plugin {
+        #[command(subcommand)]
+        command: PluginCommands,
+    },
+
+    /// Manage checkpoints
+    Checkpoint {
+        #[command(subcommand)]
+        command: CheckpointCommands,
+    },
+
     /// Docker Compose commands
     Compose {
         #[command(subcommand)]
@@ -199,6 +497,36 @@ enum Commands {
         command: NodeCommands,
     },
 
+    /// Manage stacks (Swarm mode)
+    Stack {
+        #[command(subcommand)]
+        command: StackCommands,
+    },
+
+    /// Run an OCI registry
+    Registry {
+        #[command(subcommand)]
+        command: RegistryCommands,
+    },
+
+    /// Manage daemon contexts
+    Context {
+        #[command(subcommand)]
+        command: ContextCommands,
+    },
+
+    /// Troubleshoot the daemon
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommands,
+    },
+
+    /// Review the actions recorded in the audit log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+
     /// Display system-wide information
     Info,
 
@@ -207,7 +535,11 @@ enum Commands {
 
     /// Launch the Terminal User Interface
     #[command(name = "tui")]
-    Tui,
+    Tui {
+        /// Context to connect to instead of the current one
+        #[arg(long)]
+        context: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -218,12 +550,21 @@ enum ImageCommands {
         /// Show all images
         #[arg(short, long)]
         all: bool,
+        /// Filter output (e.g., dangling=true, reference=nginx)
+        #[arg(short, long)]
+        filter: Vec<String>,
     },
     /// Pull an image
     Pull {
         /// Image name
         name: String,
     },
+    /// Load images from a tar archive, OCI layout or directory of archives
+    Load {
+        /// Archive or directory to load from
+        #[arg(short, long)]
+        input: PathBuf,
+    },
     /// Push an image
     Push {
         /// Image name
@@ -255,6 +596,21 @@ enum ImageCommands {
         /// Image ID or name
         image: String,
     },
+    /// Show how one image's files differ from another's, by layer
+    Diff {
+        /// Image to compare from
+        from: String,
+        /// Image to compare to
+        to: String,
+    },
+    /// Show what each layer of an image changes and the space it wastes
+    Analyze {
+        /// Image ID or name
+        image: String,
+        /// List the files each layer changes
+        #[arg(long)]
+        files: bool,
+    },
     /// Remove unused images
     Prune {
         /// Remove all unused images
@@ -270,7 +626,11 @@ enum ImageCommands {
 enum NetworkCommands {
     /// List networks
     #[command(name = "ls")]
-    List,
+    List {
+        /// Filter output (e.g., driver=bridge, type=custom)
+        #[arg(short, long)]
+        filter: Vec<String>,
+    },
     /// Create a network
     Create {
         /// Network name
@@ -284,6 +644,12 @@ enum NetworkCommands {
         /// Gateway
         #[arg(long)]
         gateway: Option<String>,
+        /// Keep the network's containers from reaching outside it
+        #[arg(long)]
+        internal: bool,
+        /// Driver option (key=value)
+        #[arg(short, long)]
+        opt: Vec<String>,
     },
     /// Remove a network
     #[command(name = "rm")]
@@ -302,6 +668,12 @@ enum NetworkCommands {
         network: String,
         /// Container name
         container: String,
+        /// IPv4 address on the network
+        #[arg(long)]
+        ip: Option<String>,
+        /// Name the container is also known by on the network
+        #[arg(long)]
+        alias: Vec<String>,
     },
     /// Disconnect container from network
     Disconnect {
@@ -309,6 +681,9 @@ enum NetworkCommands {
         network: String,
         /// Container name
         container: String,
+        /// Disconnect even if the container's interface can't be removed
+        #[arg(short, long)]
+        force: bool,
     },
     /// Remove unused networks
     Prune {
@@ -318,11 +693,61 @@ enum NetworkCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum CheckpointCommands {
+    /// Checkpoint a running container
+    Create {
+        /// Container ID or name
+        container: String,
+        /// Checkpoint name
+        name: String,
+        /// Leave the container running after the checkpoint
+        #[arg(long)]
+        leave_running: bool,
+        /// Directory to store the checkpoint in
+        #[arg(long)]
+        checkpoint_dir: Option<PathBuf>,
+        /// Checkpoint established TCP connections, to restore them
+        #[arg(long, conflicts_with = "tcp_close")]
+        tcp_established: bool,
+        /// Close established TCP connections on restore
+        #[arg(long)]
+        tcp_close: bool,
+        /// Checkpoint file locks
+        #[arg(long)]
+        file_locks: bool,
+    },
+    /// List a container's checkpoints
+    #[command(name = "ls")]
+    List {
+        /// Container ID or name
+        container: String,
+        /// Directory the checkpoints are stored in
+        #[arg(long)]
+        checkpoint_dir: Option<PathBuf>,
+    },
+    /// Remove a checkpoint
+    #[command(name = "rm")]
+    Remove {
+        /// Container ID or name
+        container: String,
+        /// Checkpoint name
+        name: String,
+        /// Directory the checkpoint is stored in
+        #[arg(long)]
+        checkpoint_dir: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 enum VolumeCommands {
     /// List volumes
     #[command(name = "ls")]
-    List,
+    List {
+        /// Filter output (e.g., dangling=true, label=backup)
+        #[arg(short, long)]
+        filter: Vec<String>,
+    },
     /// Create a volume
     Create {
         /// Volume name
@@ -353,6 +778,48 @@ enum VolumeCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum PluginCommands {
+    /// List plugins
+    #[command(name = "ls")]
+    List,
+    /// Install the plugin listening on a socket
+    Install {
+        /// Plugin name, which drivers are looked up by
+        name: String,
+        /// Unix socket the plugin listens on
+        #[arg(long)]
+        socket: PathBuf,
+        /// Do not enable the plugin on install
+        #[arg(long)]
+        disable: bool,
+    },
+    /// Enable a plugin
+    Enable {
+        /// Plugin name
+        plugin: String,
+    },
+    /// Disable a plugin
+    Disable {
+        /// Plugin name
+        plugin: String,
+    },
+    /// Remove a plugin
+    #[command(name = "rm")]
+    Remove {
+        /// Plugin name
+        plugin: String,
+        /// Remove an enabled plugin
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Inspect a plugin
+    Inspect {
+        /// Plugin name
+        plugin: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum ComposeCommands {
     /// Create and start containers
@@ -369,6 +836,9 @@ enum ComposeCommands {
         /// Scale services
         #[arg(long)]
         scale: Vec<String>,
+        /// Remove containers of services not in the compose file
+        #[arg(long)]
+        remove_orphans: bool,
     },
     /// Stop and remove containers
     Down {
@@ -381,6 +851,9 @@ enum ComposeCommands {
         /// Remove images
         #[arg(long)]
         rmi: Option<String>,
+        /// Remove containers of services not in the compose file
+        #[arg(long)]
+        remove_orphans: bool,
     },
     /// List containers
     Ps {
@@ -410,6 +883,25 @@ enum ComposeCommands {
         #[arg(long)]
         no_cache: bool,
     },
+    /// Pull service images
+    Pull {
+        /// Compose file
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Service names
+        services: Vec<String>,
+        /// Carry on when images fail to pull
+        #[arg(long)]
+        ignore_pull_failures: bool,
+    },
+    /// Push service images
+    Push {
+        /// Compose file
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Service names
+        services: Vec<String>,
+    },
     /// Start services
     Start {
         /// Compose file
@@ -434,11 +926,23 @@ enum ComposeCommands {
         /// Service names
         services: Vec<String>,
     },
-    /// Validate compose file
+    /// Validate compose file and print it normalized
     Config {
         /// Compose file
         #[arg(short, long)]
         file: Option<PathBuf>,
+        /// Print the service names
+        #[arg(long)]
+        services: bool,
+        /// Print the images of the services
+        #[arg(long)]
+        images: bool,
+        /// Print the volume names
+        #[arg(long)]
+        volumes: bool,
+        /// Print the configuration hash of services, comma-separated or `*`
+        #[arg(long, value_name = "SERVICE")]
+        hash: Option<String>,
     },
 }
 
@@ -449,18 +953,29 @@ enum SwarmCommands {
         /// Listen address
         #[arg(long, default_value = "0.0.0.0:2377")]
         listen_addr: String,
-        /// Advertise address
+        /// Address other nodes reach this node at, by default the one on the
+        /// default route
         #[arg(long)]
         advertise_addr: Option<String>,
         /// Force new cluster
         #[arg(long)]
         force_new_cluster: bool,
+        /// Require an unlock key to start a stopped manager
+        #[arg(long)]
+        autolock: bool,
     },
     /// Join a swarm
     Join {
         /// Join token
         #[arg(long)]
         token: String,
+        /// Listen address
+        #[arg(long, default_value = "0.0.0.0:2377")]
+        listen_addr: String,
+        /// Address other nodes reach this node at, by default the one this
+        /// node reaches the manager from
+        #[arg(long)]
+        advertise_addr: Option<String>,
         /// Remote address
         remote: String,
     },
@@ -488,7 +1003,7 @@ enum SwarmCommands {
         #[arg(long)]
         task_history_limit: Option<i64>,
     },
-    /// Unlock the swarm
+    /// Unlock the swarm, so the daemon can serve it again after a restart
     Unlock,
     /// Manage unlock key
     #[command(name = "unlock-key")]
@@ -511,6 +1026,9 @@ enum ServiceCommands {
         name: String,
         /// Image
         image: String,
+        /// Service mode
+        #[arg(long, default_value = "replicated", value_parser = ["replicated", "global", "replicated-job", "global-job"])]
+        mode: String,
         /// Number of replicas
         #[arg(long)]
         replicas: Option<u64>,
@@ -523,6 +1041,12 @@ enum ServiceCommands {
         /// Mount
         #[arg(long)]
         mount: Vec<String>,
+        /// Placement constraint (e.g. node.labels.region==east)
+        #[arg(long)]
+        constraint: Vec<String>,
+        /// Placement preference (e.g. spread=node.labels.zone)
+        #[arg(long = "placement-pref")]
+        placement_pref: Vec<String>,
     },
     /// Update a service
     Update {
@@ -566,6 +1090,9 @@ enum ServiceCommands {
         /// Follow log output
         #[arg(short, long)]
         follow: bool,
+        /// Number of lines to show from the end of each task's logs
+        #[arg(short = 'n', long)]
+        tail: Option<usize>,
     },
     /// List service processes
     Ps {
@@ -589,7 +1116,7 @@ enum NodeCommands {
         /// Node ID
         node: String,
         /// Availability (active, pause, drain)
-        #[arg(long)]
+        #[arg(long, value_parser = ["active", "pause", "drain"])]
         availability: Option<String>,
         /// Role (worker, manager)
         #[arg(long)]
@@ -627,36 +1154,326 @@ enum NodeCommands {
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum StackCommands {
+    /// Deploy a new stack or update an existing stack
+    Deploy {
+        /// Stack name
+        name: String,
+        /// Path to a compose file
+        #[arg(short = 'c', long = "compose-file")]
+        compose_file: Vec<PathBuf>,
+        /// Prune services that are no longer referenced
+        #[arg(long)]
+        prune: bool,
+    },
+    /// List stacks
+    #[command(name = "ls")]
+    List,
+    /// List the tasks in the stack
+    Ps {
+        /// Stack name
+        name: String,
+    },
+    /// Remove one or more stacks
+    #[command(name = "rm")]
+    Remove {
+        /// Stack names
+        names: Vec<String>,
+    },
+    /// List the services in the stack
+    Services {
+        /// Stack name
+        name: String,
+    },
+}
 
-    // Initialize logging
-    let filter = if cli.debug {
-        EnvFilter::new("debug")
-    } else {
-        EnvFilter::new("info")
-    };
+#[derive(Subcommand)]
+enum RegistryCommands {
+    /// Serve the OCI Distribution API
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0")]
+        address: String,
+        /// Port to listen on
+        #[arg(short, long, default_value = "5000")]
+        port: u16,
+        /// Directory to store blobs and manifests in
+        #[arg(long, default_value = "/var/lib/rune/registry")]
+        storage: PathBuf,
+        /// Store content in S3 at this endpoint instead; credentials are
+        /// read from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY
+        #[arg(long, requires = "s3_bucket")]
+        s3_endpoint: Option<String>,
+        /// S3 bucket
+        #[arg(long, requires = "s3_endpoint")]
+        s3_bucket: Option<String>,
+        /// S3 region
+        #[arg(long, default_value = "us-east-1")]
+        s3_region: String,
+        /// Key prefix within the S3 bucket
+        #[arg(long, default_value = "")]
+        s3_root: String,
+        /// TLS certificate (PEM)
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// TLS private key (PEM)
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// Reject manifest and blob deletes
+        #[arg(long)]
+        disable_delete: bool,
+        /// Require users from an htpasswd file (bcrypt entries)
+        #[arg(long)]
+        htpasswd: Option<PathBuf>,
+        /// How clients authenticate when --htpasswd is set
+        #[arg(long, default_value = "basic", value_parser = ["basic", "token"])]
+        auth_mode: String,
+        /// Let anonymous clients pull when --htpasswd is set
+        #[arg(long)]
+        anonymous_read: bool,
+        /// Run as a read-only pull-through cache of this registry
+        /// (e.g. https://registry-1.docker.io)
+        #[arg(long)]
+        proxy_remote_url: Option<String>,
+        /// Username for the upstream registry
+        #[arg(long, requires = "proxy_remote_url")]
+        proxy_username: Option<String>,
+        /// Password for the upstream registry
+        #[arg(long, requires = "proxy_username")]
+        proxy_password: Option<String>,
+        /// How long cached tags are served before they are revalidated
+        #[arg(long, default_value = "1h")]
+        proxy_ttl: String,
+        /// Most blob content to keep cached (e.g. 50G)
+        #[arg(long, requires = "proxy_remote_url")]
+        proxy_max_size: Option<String>,
+        /// POST push and delete events to this webhook URL (repeatable)
+        #[arg(long = "notify")]
+        notify: Vec<String>,
+    },
+}
 
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+#[derive(Subcommand)]
+enum DebugCommands {
+    /// Collect the daemon's diagnostics, logs, config and recent events
+    /// into a tarball
+    Bundle {
+        /// File to write the tarball to
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Context of the daemon instead of the current one
+        #[arg(long)]
+        context: Option<String>,
+        /// How far back to collect events from
+        #[arg(long, default_value = "1h")]
+        since: String,
+    },
+}
 
-    // Get base path for rune data
-    let base_path = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("/var/lib"))
-        .join("rune");
+#[derive(Subcommand)]
+enum AuditCommands {
+    /// List recorded actions
+    #[command(name = "ls")]
+    List {
+        /// Only show actions from this far back, e.g. 1h
+        #[arg(long)]
+        since: Option<String>,
+        /// Filter output (e.g., action=container.create, uid=1000, context=prod)
+        #[arg(short, long)]
+        filter: Vec<String>,
+        /// Context of the daemon instead of the current one
+        #[arg(long)]
+        context: Option<String>,
+        /// Read the actions this CLI performed itself instead of the daemon's
+        #[arg(long, conflicts_with = "context")]
+        local: bool,
+    },
+    /// Write recorded actions as JSON lines
+    Export {
+        /// File to write to instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Only export actions from this far back, e.g. 24h
+        #[arg(long)]
+        since: Option<String>,
+        /// Filter output (e.g., action=image.pull)
+        #[arg(short, long)]
+        filter: Vec<String>,
+        /// Context of the daemon instead of the current one
+        #[arg(long)]
+        context: Option<String>,
+        /// Read the actions this CLI performed itself instead of the daemon's
+        #[arg(long, conflicts_with = "context")]
+        local: bool,
+    },
+}
 
-    // Initialize container manager
-    let container_manager = Arc::new(ContainerManager::new(base_path.join("containers"))?);
+#[derive(Subcommand)]
+enum ContextCommands {
+    /// List contexts
+    #[command(name = "ls")]
+    List,
+    /// Create or replace a context
+    Create {
+        /// Context name
+        name: String,
+        /// Daemon endpoint (unix:///path or tcp://host:port)
+        #[arg(long)]
+        endpoint: String,
+        /// Description
+        #[arg(long, default_value = "")]
+        description: String,
+        /// CA certificate to verify the daemon with; enables TLS
+        #[arg(long)]
+        tls_ca: Option<PathBuf>,
+        /// Client certificate
+        #[arg(long, requires = "tls_ca")]
+        tls_cert: Option<PathBuf>,
+        /// Client private key
+        #[arg(long, requires = "tls_ca")]
+        tls_key: Option<PathBuf>,
+    },
+    /// Set the current context
+    Use {
+        /// Context name
+        name: String,
+    },
+    /// Remove a context
+    #[command(name = "rm")]
+    Remove {
+        /// Context name
+        name: String,
+    },
+    /// Inspect a context
+    Inspect {
+        /// Context name
+        name: String,
+    },
+}
+
+fn main() {
+    // Invoked as a container's init; answer before any runtime thread
+    // starts, since the init waits for signals in its only thread
+    let mut args = std::env::args();
+    if args.next().is_some_and(|argv0| init::is_init(&argv0)) {
+        std::process::exit(init::main(&args.collect::<Vec<_>>()));
+    }
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(RuneError::from)
+        .and_then(|runtime| runtime.block_on(run()));
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Initialize logging
+    let filter = if cli.debug {
+        EnvFilter::new("debug")
+    } else {
+        EnvFilter::new("info")
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr.and(LogBuffer::global().clone()))
+        .init();
+
+    // Get base path for rune data
+    let base_path = dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("/var/lib"))
+        .join("rune");
+
+    // Initialize container manager
+    let plugins = Arc::new(PluginManager::open(base_path.join("plugins"))?);
+    let volume_manager =
+        Arc::new(VolumeManager::new(base_path.join("volumes"))?.with_plugins(plugins.clone()));
+    let network_manager = Arc::new(
+        NetworkManager::new()?
+            .with_plugins(plugins)
+            .with_interfaces(Arc::new(Veth::default())),
+    );
+    let container_manager = Arc::new(
+        ContainerManager::new(base_path.join("containers"))?
+            .with_volumes(volume_manager.clone())
+            .with_networks(network_manager.clone()),
+    );
+
+    let action = cli_action(&cli.command);
+    let result = execute(
+        cli.command,
+        base_path.clone(),
+        volume_manager,
+        network_manager,
+        container_manager,
+    )
+    .await;
+    if let Some((action, target)) = action {
+        let status = match &result {
+            Ok(()) => 200,
+            Err(e) => e.status_code(),
+        };
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let entry = AuditEntry::new(Caller::local(), action, target, &args.join(" "), status);
+        if let Err(e) = local_audit_log(&base_path).and_then(|log| log.record(&entry)) {
+            tracing::warn!("Failed to record the action in the audit log: {}", e);
+        }
+    }
+    result
+}
 
-    match cli.command {
+async fn execute(
+    command: Commands,
+    base_path: PathBuf,
+    volume_manager: Arc<VolumeManager>,
+    network_manager: Arc<NetworkManager>,
+    container_manager: Arc<ContainerManager>,
+) -> Result<()> {
+    match command {
         Commands::Run {
             image,
             name,
             detach,
+            interactive,
+            tty,
+            detach_keys,
+            rm,
             publish: _,
             env,
-            volume: _,
+            volume,
             workdir,
+            network,
+            ip,
+            mac_address,
+            network_alias,
+            log_driver,
+            log_opt,
+            cap_add,
+            cap_drop,
+            privileged,
+            security_opt,
+            userns,
+            init,
+            device,
+            gpus,
+            hook,
+            ulimit,
+            sysctl,
+            oom_kill_disable,
+            oom_score_adj,
+            stop_signal,
+            stop_timeout,
+            health_cmd,
+            health_interval,
+            health_timeout,
+            health_start_period,
+            health_retries,
+            no_healthcheck,
             command,
         } => {
             let container_name =
@@ -681,8 +1498,127 @@ async fn main() -> Result<()> {
                 config.working_dir = wd;
             }
 
-            let id = container_manager.create(config)?;
-            container_manager.start(&id)?;
+            for volume in &volume {
+                config.add_volume(volume)?;
+            }
+            config.auto_remove = rm;
+            let mut network = network.into_iter();
+            if let Some(network) = network.next() {
+                config.network_mode = network;
+            }
+            if ip.is_some() || mac_address.is_some() || !network_alias.is_empty() {
+                config.endpoints.insert(
+                    config.network_mode.clone(),
+                    rune::network::EndpointConfig {
+                        ipv4_address: ip,
+                        mac_address,
+                        aliases: network_alias,
+                    },
+                );
+            }
+            for network in network {
+                config.endpoints.entry(network).or_default();
+            }
+            if log_driver.is_some() || !log_opt.is_empty() {
+                config.log_config = Some(LogConfig::from_args(log_driver.as_deref(), &log_opt)?);
+            }
+            config.cap_add = cap_add;
+            config.cap_drop = cap_drop;
+            config.privileged = privileged;
+            config.security_opt = read_seccomp_profiles(&security_opt, &std::env::current_dir()?)?;
+            config.userns_mode = userns.unwrap_or_default();
+            config.init = init;
+            for device in &device {
+                config.add_device(device)?;
+            }
+            config.gpus = gpus.as_deref().map(str::parse).transpose()?;
+            for hook in &hook {
+                config.add_hook(hook)?;
+            }
+            for ulimit in &ulimit {
+                config.add_ulimit(ulimit)?;
+            }
+            for sysctl in &sysctl {
+                config.add_sysctl(sysctl)?;
+            }
+            config.oom_kill_disable = oom_kill_disable;
+            config.oom_score_adj = oom_score_adj;
+            // Stop and check the container as its image says unless told
+            // otherwise
+            let image_config = ImageStore::new(base_path.join("images"))?
+                .get(&image)
+                .ok()
+                .map(|image| image.config);
+            config.stop_signal = stop_signal.or_else(|| {
+                image_config
+                    .as_ref()
+                    .map(|c| c.stop_signal.clone())
+                    .filter(|signal| !signal.is_empty())
+            });
+            config.stop_timeout = stop_timeout.or_else(|| {
+                image_config
+                    .as_ref()
+                    .and_then(|c| c.stop_timeout)
+                    .map(u64::from)
+            });
+            let image_healthcheck = image_config
+                .and_then(|c| c.healthcheck)
+                .map(|healthcheck| HealthcheckConfig::from(&healthcheck));
+            config.healthcheck = HealthcheckOverrides {
+                cmd: health_cmd,
+                interval: health_interval,
+                timeout: health_timeout,
+                start_period: health_start_period,
+                retries: health_retries,
+                disable: no_healthcheck,
+            }
+            .apply(image_healthcheck)?;
+            config.tty = tty;
+            config.open_stdin = interactive;
+            let detach_keys: DetachKeys = detach_keys
+                .as_deref()
+                .unwrap_or(DEFAULT_DETACH_KEYS)
+                .parse()?;
+
+            // Containers run in the foreground are run by the daemon, which
+            // hands this process their terminal or standard input
+            if !detach && (tty || interactive) {
+                let store = ContextStore::open_default()?;
+                let client = DaemonClient::new(&store.get(&store.current_name())?)?;
+                let name = config.name.clone();
+                let mut run = client
+                    .run_attached(&config)
+                    .with_context(|| format!("Cannot run container {}", name))?;
+                let console = if tty { run.input.take() } else { None };
+                let input = match &console {
+                    _ if !interactive => None,
+                    Some(console) => Some(console.try_clone()?),
+                    None => run.input.take(),
+                };
+                let id = run.id.clone();
+                let forward = |signal| {
+                    let _ = client.kill_container(&id, signal);
+                };
+                match terminal::attach(run.as_fd(), console.as_ref(), input, forward, detach_keys)?
+                {
+                    AttachEnd::Detached => eprintln!("Detached from container {}", id),
+                    AttachEnd::Exited => {
+                        let exit_code = run.exit_code()?;
+                        if exit_code != 0 {
+                            std::process::exit(exit_code);
+                        }
+                    }
+                }
+                return Ok(());
+            }
+
+            let name = config.name.clone();
+            let id = container_manager
+                .create(config)
+                .with_context(|| format!("Cannot create container {}", name))?;
+            container_manager
+                .start(&id)
+                .with_context(|| format!("Cannot start container {}", name))?;
 
             if detach {
                 println!("{}", id);
@@ -691,28 +1627,156 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::Create { image, name } => {
+        Commands::Create {
+            image,
+            name,
+            interactive,
+            tty,
+            network,
+            ip,
+            mac_address,
+            network_alias,
+            log_driver,
+            log_opt,
+            cap_add,
+            cap_drop,
+            privileged,
+            security_opt,
+            userns,
+            init,
+            device,
+            gpus,
+            hook,
+            ulimit,
+            sysctl,
+            oom_kill_disable,
+            oom_score_adj,
+            stop_signal,
+            stop_timeout,
+            health_cmd,
+            health_interval,
+            health_timeout,
+            health_start_period,
+            health_retries,
+            no_healthcheck,
+        } => {
             let container_name =
                 name.unwrap_or_else(|| format!("rune-{}", &uuid::Uuid::new_v4().to_string()[..8]));
 
-            let config = ContainerConfig::new(&container_name, &image);
-            let id = container_manager.create(config)?;
+            let mut config = ContainerConfig::new(&container_name, &image);
+            let mut network = network.into_iter();
+            if let Some(network) = network.next() {
+                config.network_mode = network;
+            }
+            if ip.is_some() || mac_address.is_some() || !network_alias.is_empty() {
+                config.endpoints.insert(
+                    config.network_mode.clone(),
+                    rune::network::EndpointConfig {
+                        ipv4_address: ip,
+                        mac_address,
+                        aliases: network_alias,
+                    },
+                );
+            }
+            for network in network {
+                config.endpoints.entry(network).or_default();
+            }
+            if log_driver.is_some() || !log_opt.is_empty() {
+                config.log_config = Some(LogConfig::from_args(log_driver.as_deref(), &log_opt)?);
+            }
+            config.cap_add = cap_add;
+            config.cap_drop = cap_drop;
+            config.privileged = privileged;
+            config.security_opt = read_seccomp_profiles(&security_opt, &std::env::current_dir()?)?;
+            config.userns_mode = userns.unwrap_or_default();
+            config.init = init;
+            for device in &device {
+                config.add_device(device)?;
+            }
+            config.gpus = gpus.as_deref().map(str::parse).transpose()?;
+            for hook in &hook {
+                config.add_hook(hook)?;
+            }
+            for ulimit in &ulimit {
+                config.add_ulimit(ulimit)?;
+            }
+            for sysctl in &sysctl {
+                config.add_sysctl(sysctl)?;
+            }
+            config.oom_kill_disable = oom_kill_disable;
+            config.oom_score_adj = oom_score_adj;
+            // Stop and check the container as its image says unless told
+            // otherwise
+            let image_config = ImageStore::new(base_path.join("images"))?
+                .get(&image)
+                .ok()
+                .map(|image| image.config);
+            config.stop_signal = stop_signal.or_else(|| {
+                image_config
+                    .as_ref()
+                    .map(|c| c.stop_signal.clone())
+                    .filter(|signal| !signal.is_empty())
+            });
+            config.stop_timeout = stop_timeout.or_else(|| {
+                image_config
+                    .as_ref()
+                    .and_then(|c| c.stop_timeout)
+                    .map(u64::from)
+            });
+            let image_healthcheck = image_config
+                .and_then(|c| c.healthcheck)
+                .map(|healthcheck| HealthcheckConfig::from(&healthcheck));
+            config.healthcheck = HealthcheckOverrides {
+                cmd: health_cmd,
+                interval: health_interval,
+                timeout: health_timeout,
+                start_period: health_start_period,
+                retries: health_retries,
+                disable: no_healthcheck,
+            }
+            .apply(image_healthcheck)?;
+            config.tty = tty;
+            config.open_stdin = interactive;
+            let name = config.name.clone();
+            let id = container_manager
+                .create(config)
+                .with_context(|| format!("Cannot create container {}", name))?;
             println!("{}", id);
         }
 
-        Commands::Start { container } => {
-            container_manager.start(&container)?;
+        Commands::Start {
+            container,
+            checkpoint,
+            checkpoint_dir,
+        } => {
+            match checkpoint {
+                Some(checkpoint) => container_manager
+                    .restore(&container, &checkpoint, checkpoint_dir.as_deref())
+                    .with_context(|| {
+                        format!("Cannot restore container {} from {}", container, checkpoint)
+                    })?,
+                None => container_manager
+                    .start(&container)
+                    .with_context(|| format!("Cannot start container {}", container))?,
+            }
+            println!("{}", container);
+        }
+
+        Commands::Stop { container, time } => {
+            container_manager.stop_with_timeout(&container, time.map(Duration::from_secs))?;
             println!("{}", container);
         }
 
-        Commands::Stop { container, time: _ } => {
-            container_manager.stop(&container)?;
+        Commands::Kill { container, signal } => {
+            container_manager.kill(&container, Some(parse_signal(&signal)?))?;
             println!("{}", container);
         }
 
         Commands::Restart { container } => {
             let _ = container_manager.stop(&container);
-            container_manager.start(&container)?;
+            container_manager
+                .start(&container)
+                .with_context(|| format!("Cannot start container {}", container))?;
             println!("{}", container);
         }
 
@@ -721,8 +1785,8 @@ async fn main() -> Result<()> {
             println!("{}", container);
         }
 
-        Commands::Ps { all, quiet } => {
-            let containers = container_manager.list(all)?;
+        Commands::Ps { all, quiet, filter } => {
+            let containers = container_manager.list_filtered(all, &Filters::parse(&filter)?)?;
 
             if quiet {
                 for c in containers {
@@ -730,22 +1794,74 @@ async fn main() -> Result<()> {
                 }
             } else {
                 println!(
-                    "{:<14} {:<20} {:<25} {:<12} {:<20}",
+                    "{:<14} {:<20} {:<25} {:<20} {:<20}",
                     "CONTAINER ID", "NAME", "IMAGE", "STATUS", "CREATED"
                 );
                 for c in containers {
+                    let status = match (&c.health, c.status) {
+                        (Some(health), ContainerStatus::Running) => {
+                            format!("{} ({})", c.status, health.status)
+                        }
+                        _ => c.status.to_string(),
+                    };
                     println!(
-                        "{:<14} {:<20} {:<25} {:<12} {:<20}",
+                        "{:<14} {:<20} {:<25} {:<20} {:<20}",
                         &c.id[..12],
                         c.name,
                         c.image,
-                        c.status.to_string(),
+                        status,
                         c.created_at.format("%Y-%m-%d %H:%M:%S")
                     );
                 }
             }
         }
 
+        Commands::Stats {
+            container,
+            history,
+            context,
+        } => {
+            let store = ContextStore::open_default()?;
+            let context = store.get(&context.unwrap_or_else(|| store.current_name()))?;
+            let client = DaemonClient::new(&context)?;
+            // The latest rates need the two latest samples
+            let period = match &history {
+                Some(history) => parse_duration(history)?,
+                None => 60_000_000_000,
+            };
+            let since = chrono::Utc::now() - chrono::Duration::nanoseconds(period);
+            let mut rates = sample_rates(&client.stats_history(&container, since)?);
+            if history.is_none() {
+                rates = rates.split_off(rates.len().saturating_sub(1));
+            }
+            if rates.is_empty() {
+                println!("No stats recorded for {} in that period", container);
+                return Ok(());
+            }
+            println!(
+                "{:<20} {:>8} {:>22} {:>22}",
+                "TIME", "CPU %", "MEM USAGE / LIMIT", "NET I/O (RX / TX)"
+            );
+            for (time, rates) in rates {
+                let limit = rates
+                    .memory_limit
+                    .map(format_bytes)
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:<20} {:>7.2}% {:>22} {:>22}",
+                    time.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S"),
+                    rates.cpu_percent,
+                    format!("{} / {}", format_bytes(rates.memory_usage), limit),
+                    format!(
+                        "{}/s / {}/s",
+                        format_bytes(rates.rx_rate),
+                        format_bytes(rates.tx_rate)
+                    ),
+                );
+            }
+        }
+
         Commands::Logs {
             container,
             follow: _,
@@ -772,8 +1888,13 @@ async fn main() -> Result<()> {
             build_arg,
             no_cache,
             target,
+            progress,
+            output,
+            max_parallel,
+            build_context,
         } => {
-            let mut context = BuildContext::new(path.clone());
+            let mode: ProgressMode = progress.parse()?;
+            let mut context = BuildContext::from_source(&path);
 
             if let Some(f) = file {
                 context = context.build_file(f);
@@ -789,72 +1910,303 @@ async fn main() -> Result<()> {
                 context = context.tag(&t);
             }
 
+            for o in output {
+                context = context.output(o.parse::<BuildOutput>()?);
+            }
+
+            if let Some(n) = max_parallel {
+                if n == 0 {
+                    return Err(RuneError::InvalidConfig(
+                        "--max-parallel must be at least 1".to_string(),
+                    ));
+                }
+                context = context.max_parallel(n);
+            }
+
             for arg in build_arg {
                 if let Some((key, value)) = arg.split_once('=') {
                     context = context.arg(key, value);
                 }
             }
 
-            let builder = ImageBuilder::new(context);
-            let image_id = builder.build().await?;
-            println!("Successfully built {}", image_id);
+            for named in build_context {
+                match named.split_once('=') {
+                    Some((name, source)) if !name.is_empty() && !source.is_empty() => {
+                        context = context.named_context(name, source);
+                    }
+                    _ => {
+                        return Err(RuneError::InvalidConfig(format!(
+                            "Invalid build context '{}'; expected name=value",
+                            named
+                        )))
+                    }
+                }
+            }
+
+            let (events, received) = std::sync::mpsc::channel();
+            let printer = std::thread::spawn(move || {
+                let mut printer = ProgressPrinter::new(mode, std::io::stderr());
+                for event in received {
+                    let _ = printer.print(&event);
+                }
+            });
+            let store = Arc::new(ImageStore::new(base_path.join("images"))?);
+            let builder = ImageBuilder::new(context)
+                .with_store(store)
+                .with_events(events);
+            let result = builder.build().await;
+            // The printer is done once the builder's sender is gone
+            drop(builder);
+            let _ = printer.join();
+            result?;
         }
 
-        Commands::Image { command } => {
-            match command {
-                ImageCommands::List { all: _ } => {
-                    println!("REPOSITORY          TAG       IMAGE ID       SIZE");
-                    // List images
+        Commands::Lint {
+            path,
+            config,
+            list_rules,
+        } => {
+            if list_rules {
+                for rule in RULES {
+                    println!("{}  {:<8} {}", rule.id, rule.severity, rule.description);
                 }
-                ImageCommands::Pull { name } => {
-                    println!("Pulling image {}...", name);
+            } else {
+                let file = if path.is_dir() {
+                    BuildContext::new(path).build_file
+                } else {
+                    path
+                };
+                let content = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+
+                let config_path = config.or_else(|| {
+                    let dir = file.canonicalize().ok()?.parent()?.to_path_buf();
+                    LintConfig::find(dir)
+                });
+                let config = match config_path {
+                    Some(path) => LintConfig::load(path)
+                        .map_err(|e| RuneError::InvalidConfig(e.to_string()))?,
+                    None => LintConfig::default(),
+                };
+
+                let issues = runefile_lint::lint(&content, &config);
+                for issue in &issues {
+                    println!(
+                        "{}:{}:{} {} {}: {}",
+                        file.display(),
+                        issue.line + 1,
+                        issue.column + 1,
+                        issue.rule,
+                        issue.severity,
+                        issue.message
+                    );
                 }
-                ImageCommands::Push { name } => {
-                    println!("Pushing image {}...", name);
+                let errors = issues
+                    .iter()
+                    .filter(|issue| issue.severity == Severity::Error)
+                    .count();
+                if errors > 0 {
+                    return Err(RuneError::Build(format!(
+                        "{} lint error(s) in {}",
+                        errors,
+                        file.display()
+                    )));
                 }
-                ImageCommands::Remove { image, force: _ } => {
-                    println!("Removing image {}...", image);
+            }
+        }
+
+        Commands::Image { command } => match command {
+            ImageCommands::List { all: _, filter } => {
+                let images = ImageStore::new(base_path.join("images"))?
+                    .list_filtered(&Filters::parse(&filter)?)?;
+                println!(
+                    "{:<30} {:<15} {:<14} {:>12}",
+                    "REPOSITORY", "TAG", "IMAGE ID", "SIZE"
+                );
+                for image in images {
+                    let id = image.id.trim_start_matches("sha256:");
+                    let tags = if image.repo_tags.is_empty() {
+                        vec!["<none>:<none>".to_string()]
+                    } else {
+                        image.repo_tags.clone()
+                    };
+                    for tag in tags {
+                        let (repository, tag) = tag.rsplit_once(':').unwrap_or((&tag, ""));
+                        println!(
+                            "{:<30} {:<15} {:<14} {:>12}",
+                            repository,
+                            tag,
+                            &id[..id.len().min(12)],
+                            image.size
+                        );
+                    }
                 }
-                ImageCommands::Tag { source, target } => {
-                    println!("Tagging {} as {}", source, target);
+            }
+            ImageCommands::Pull { name } => {
+                println!("Pulling image {}...", name);
+                let store = ImageStore::new(base_path.join("images"))?;
+                let report = ImagePuller::new(RegistryHosts::default())?
+                    .with_auths(RegistryAuths::load_default()?)
+                    .pull(&name, &store)
+                    .await?;
+                for (digest, source) in &report.sources {
+                    let id = digest.strip_prefix("sha256:").unwrap_or(digest);
+                    println!("{}: pulled from {}", &id[..id.len().min(12)], source);
                 }
-                ImageCommands::History { image: _ } => {
-                    println!("IMAGE          CREATED       CREATED BY                                      SIZE");
+                println!("Digest: {}", report.digest);
+            }
+            ImageCommands::Load { input } => {
+                let images = ImageStore::new(base_path.join("images"))?.load(&input)?;
+                for image in images {
+                    if image.repo_tags.is_empty() {
+                        println!("Loaded image ID: {}", image.id);
+                    }
+                    for tag in &image.repo_tags {
+                        println!("Loaded image: {}", tag);
+                    }
                 }
-                ImageCommands::Inspect { image } => {
-                    println!("Inspecting image {}...", image);
+            }
+            ImageCommands::Push { name } => {
+                println!("Pushing image {}...", name);
+                let store = ImageStore::new(base_path.join("images"))?;
+                let digest = ImagePuller::new(RegistryHosts::default())?
+                    .with_auths(RegistryAuths::load_default()?)
+                    .push(&name, &store)
+                    .await?;
+                println!("Digest: {}", digest);
+            }
+            ImageCommands::Remove { image, force: _ } => {
+                println!("Removing image {}...", image);
+            }
+            ImageCommands::Tag { source, target } => {
+                println!("Tagging {} as {}", source, target);
+            }
+            ImageCommands::History { image: _ } => {
+                println!("IMAGE          CREATED       CREATED BY                                      SIZE");
+            }
+            ImageCommands::Inspect { image } => {
+                println!("Inspecting image {}...", image);
+            }
+            ImageCommands::Diff { from, to } => {
+                let diff = ImageStore::new(base_path.join("images"))?.diff(&from, &to)?;
+                println!("{} shared layer(s)", diff.shared_layers);
+                for layer in diff.layers {
+                    println!();
+                    print_layer_summary(&layer);
+                    print_layer_changes(&layer);
+                }
+            }
+            ImageCommands::Analyze { image, files } => {
+                let analysis = ImageStore::new(base_path.join("images"))?.analyze(&image)?;
+                println!(
+                    "{:<14} {:>10} {:>8} {:>8} {:>8}",
+                    "LAYER", "SIZE", "ADDED", "MODIFIED", "REMOVED"
+                );
+                for layer in &analysis.layers {
+                    print_layer_summary(layer);
+                    if files {
+                        print_layer_changes(layer);
+                    }
                 }
-                ImageCommands::Prune { all: _, force: _ } => {
-                    println!("Pruning unused images...");
+                println!();
+                println!("Total size:   {}", format_bytes(analysis.size()));
+                println!("Wasted space: {}", format_bytes(analysis.wasted_size()));
+                println!("Efficiency:   {:.1}%", analysis.efficiency() * 100.0);
+                if !analysis.wasted.is_empty() {
+                    println!();
+                    println!("{:>10} {:>6}  PATH", "WASTED", "COPIES");
+                    for file in &analysis.wasted {
+                        println!(
+                            "{:>10} {:>6}  {}",
+                            format_bytes(file.size),
+                            file.count,
+                            file.path
+                        );
+                    }
                 }
             }
-        }
+            ImageCommands::Prune { all: _, force: _ } => {
+                println!("Pruning unused images...");
+            }
+        },
 
         Commands::Network { command } => match command {
-            NetworkCommands::List => {
-                println!("NETWORK ID     NAME      DRIVER    SCOPE");
-                println!("abc123def456   bridge    bridge    local");
-                println!("def456ghi789   host      host      local");
-                println!("ghi789jkl012   none      null      local");
+            NetworkCommands::List { filter } => {
+                let mut networks =
+                    NetworkManager::new()?.list_filtered(&Filters::parse(&filter)?)?;
+                networks.sort_by(|a, b| a.name.cmp(&b.name));
+                println!("{:<14} {:<20} {:<10} SCOPE", "NETWORK ID", "NAME", "DRIVER");
+                for network in networks {
+                    println!(
+                        "{:<14} {:<20} {:<10} {}",
+                        network.id,
+                        network.name,
+                        network.driver,
+                        serde_json::to_value(network.scope)?
+                            .as_str()
+                            .unwrap_or_default()
+                    );
+                }
             }
             NetworkCommands::Create {
                 name,
-                driver: _,
-                subnet: _,
-                gateway: _,
+                driver,
+                subnet,
+                gateway,
+                internal,
+                opt,
             } => {
-                println!("Created network {}", name);
+                let mut config = NetworkConfig::new(&name)
+                    .driver(driver.parse()?)
+                    .internal(internal);
+                if let Some(subnet) = subnet {
+                    config = config.subnet(&subnet);
+                }
+                if let Some(gateway) = gateway {
+                    config = config.gateway(&gateway);
+                }
+                for option in opt {
+                    let (key, value) = option.split_once('=').ok_or_else(|| {
+                        RuneError::InvalidConfig(format!(
+                            "Invalid network option '{}'; expected key=value",
+                            option
+                        ))
+                    })?;
+                    config.options.insert(key.to_string(), value.to_string());
+                }
+                println!("{}", network_manager.create(config)?);
             }
             NetworkCommands::Remove { network } => {
+                network_manager.remove(&network)?;
                 println!("Removed network {}", network);
             }
             NetworkCommands::Inspect { network } => {
-                println!("Inspecting network {}...", network);
+                let network = network_manager.get(&network)?;
+                println!("{}", serde_json::to_string_pretty(&network)?);
             }
-            NetworkCommands::Connect { network, container } => {
+            NetworkCommands::Connect {
+                network,
+                container,
+                ip,
+                alias,
+            } => {
+                container_manager.connect_network(
+                    &container,
+                    &network,
+                    rune::network::EndpointConfig {
+                        ipv4_address: ip,
+                        mac_address: None,
+                        aliases: alias,
+                    },
+                )?;
                 println!("Connected {} to {}", container, network);
             }
-            NetworkCommands::Disconnect { network, container } => {
+            NetworkCommands::Disconnect {
+                network,
+                container,
+                force,
+            } => {
+                container_manager.disconnect_network(&container, &network, force)?;
                 println!("Disconnected {} from {}", container, network);
             }
             NetworkCommands::Prune { force: _ } => {
@@ -863,13 +2215,21 @@ async fn main() -> Result<()> {
         },
 
         Commands::Volume { command } => match command {
-            VolumeCommands::List => {
-                println!("DRIVER    VOLUME NAME");
+            VolumeCommands::List { filter } => {
+                let volumes = volume_manager.list_filtered(&Filters::parse(&filter)?)?;
+                println!("{:<10} VOLUME NAME", "DRIVER");
+                for volume in volumes {
+                    println!("{:<10} {}", volume.driver, volume.name);
+                }
             }
-            VolumeCommands::Create { name, driver: _ } => {
-                let vol_name =
-                    name.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()[..12].to_string());
-                println!("{}", vol_name);
+            VolumeCommands::Create { name, driver } => {
+                let volume = volume_manager.create(
+                    &name.unwrap_or_default(),
+                    Some(VolumeDriver::from(driver.as_str())),
+                    std::collections::HashMap::new(),
+                    std::collections::HashMap::new(),
+                )?;
+                println!("{}", volume.name);
             }
             VolumeCommands::Remove { volume, force: _ } => {
                 println!("Removed volume {}", volume);
@@ -882,6 +2242,99 @@ async fn main() -> Result<()> {
             }
         },
 
+        Commands::Plugin { command } => {
+            let plugins = PluginManager::open(base_path.join("plugins"))?;
+            match command {
+                PluginCommands::List => {
+                    println!("{:<14} {:<20} {:<10} IMPLEMENTS", "ID", "NAME", "ENABLED");
+                    for plugin in plugins.list()? {
+                        println!(
+                            "{:<14} {:<20} {:<10} {}",
+                            &plugin.id[..12],
+                            plugin.name,
+                            plugin.enabled,
+                            plugin.implements.join(", ")
+                        );
+                    }
+                }
+                PluginCommands::Install {
+                    name,
+                    socket,
+                    disable,
+                } => {
+                    let plugin = plugins.install(&name, &socket, !disable)?;
+                    println!("Installed plugin {}", plugin.name);
+                }
+                PluginCommands::Enable { plugin } => {
+                    println!("{}", plugins.enable(&plugin)?.name);
+                }
+                PluginCommands::Disable { plugin } => {
+                    println!("{}", plugins.disable(&plugin)?.name);
+                }
+                PluginCommands::Remove { plugin, force } => {
+                    plugins.remove(&plugin, force)?;
+                    println!("{}", plugin);
+                }
+                PluginCommands::Inspect { plugin } => {
+                    println!("{}", serde_json::to_string_pretty(&plugins.get(&plugin)?)?);
+                }
+            }
+        }
+
+        Commands::Checkpoint { command } => match command {
+            CheckpointCommands::Create {
+                container,
+                name,
+                leave_running,
+                checkpoint_dir,
+                tcp_established,
+                tcp_close,
+                file_locks,
+            } => {
+                let tcp = if tcp_established {
+                    TcpMode::Established
+                } else if tcp_close {
+                    TcpMode::Close
+                } else {
+                    TcpMode::Refuse
+                };
+                let options = CheckpointOptions {
+                    leave_running,
+                    tcp,
+                    file_locks,
+                };
+                let checkpoint = container_manager.checkpoint(
+                    &container,
+                    &name,
+                    checkpoint_dir.as_deref(),
+                    options,
+                )?;
+                println!("{}", checkpoint.name);
+            }
+            CheckpointCommands::List {
+                container,
+                checkpoint_dir,
+            } => {
+                println!("CHECKPOINT NAME");
+                for checkpoint in
+                    container_manager.checkpoints(&container, checkpoint_dir.as_deref())?
+                {
+                    println!("{}", checkpoint.name);
+                }
+            }
+            CheckpointCommands::Remove {
+                container,
+                name,
+                checkpoint_dir,
+            } => {
+                container_manager.remove_checkpoint(
+                    &container,
+                    &name,
+                    checkpoint_dir.as_deref(),
+                )?;
+            }
+        },
+
         Commands::Compose { command } => {
             let working_dir = std::env::current_dir()?;
 
@@ -891,54 +2344,112 @@ async fn main() -> Result<()> {
                     detach,
                     build,
                     scale: _,
+                    remove_orphans,
                 } => {
-                    let compose_file = file.unwrap_or_else(|| {
-                        ComposeParser::find_compose_file(&working_dir)
-                            .unwrap_or_else(|| working_dir.join("compose.yaml"))
-                    });
-
-                    let config = ComposeParser::parse_file(&compose_file)?;
-                    let project_name = config.name.clone().unwrap_or_else(|| {
-                        working_dir
-                            .file_name()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("default")
-                            .to_string()
-                    });
-
-                    let mut orchestrator = ComposeOrchestrator::new(
-                        &project_name,
-                        config,
-                        container_manager.clone(),
-                        working_dir,
-                    );
-
-                    orchestrator.up(detach, build).await?;
-                    println!("Started project {}", project_name);
+                    let mut orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?
+                            .with_image_store(Arc::new(ImageStore::new(base_path.join("images"))?));
+                    orchestrator.up(detach, build, remove_orphans).await?;
+                    println!("Started project {}", orchestrator.project_name());
                 }
                 ComposeCommands::Down {
-                    file: _,
-                    volumes: _,
+                    file,
+                    volumes,
                     rmi: _,
+                    remove_orphans,
                 } => {
-                    println!("Stopping compose project...");
+                    let mut orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    orchestrator.down(volumes, remove_orphans).await?;
+                    println!("Removed project {}", orchestrator.project_name());
                 }
-                ComposeCommands::Ps { file: _ } => {
-                    println!("NAME      SERVICE   STATUS    PORTS");
+                ComposeCommands::Ps { file } => {
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    println!("{:<30} {:<20} {:<20} PORTS", "NAME", "SERVICE", "STATUS");
+                    for c in orchestrator.containers(None)? {
+                        let ports: Vec<String> = c
+                            .exposed_ports
+                            .iter()
+                            .map(|p| format!("{}->{}", p.host_port, p.container_port))
+                            .collect();
+                        println!(
+                            "{:<30} {:<20} {:<20} {}",
+                            c.name,
+                            c.labels
+                                .get(rune::compose::orchestrator::SERVICE_LABEL)
+                                .map_or("", String::as_str),
+                            c.status.to_string(),
+                            ports.join(", ")
+                        );
+                    }
                 }
                 ComposeCommands::Logs {
-                    file: _,
-                    service: _,
-                    follow: _,
+                    file,
+                    service,
+                    follow,
                 } => {
-                    println!("Fetching compose logs...");
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    if follow {
+                        orchestrator
+                            .follow_logs(service.as_deref(), None, |line| println!("{}", line))
+                            .await?;
+                    } else {
+                        for line in orchestrator.logs(service.as_deref(), None).await? {
+                            println!("{}", line);
+                        }
+                    }
                 }
                 ComposeCommands::Build {
-                    file: _,
-                    service: _,
-                    no_cache: _,
+                    file,
+                    service,
+                    no_cache,
                 } => {
                     println!("Building compose services...");
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    let services: Vec<String> = service.into_iter().collect();
+                    orchestrator
+                        .with_image_store(Arc::new(ImageStore::new(base_path.join("images"))?))
+                        .build_services(&services, no_cache)
+                        .await?;
+                }
+                ComposeCommands::Pull {
+                    file,
+                    services,
+                    ignore_pull_failures,
+                } => {
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    let puller = Arc::new(
+                        ImagePuller::new(RegistryHosts::default())?
+                            .with_auths(RegistryAuths::load_default()?),
+                    );
+                    let store = Arc::new(ImageStore::new(base_path.join("images"))?);
+                    orchestrator
+                        .pull(
+                            &services,
+                            puller,
+                            store,
+                            ignore_pull_failures,
+                            |service, progress| println!("{} {}", service, progress),
+                        )
+                        .await?;
+                }
+                ComposeCommands::Push { file, services } => {
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir, container_manager.clone())?;
+                    let puller = Arc::new(
+                        ImagePuller::new(RegistryHosts::default())?
+                            .with_auths(RegistryAuths::load_default()?),
+                    );
+                    let store = Arc::new(ImageStore::new(base_path.join("images"))?);
+                    orchestrator
+                        .push(&services, puller, store, |service, progress| {
+                            println!("{} {}", service, progress)
+                        })
+                        .await?;
                 }
                 ComposeCommands::Start {
                     file: _,
@@ -958,20 +2469,55 @@ async fn main() -> Result<()> {
                 } => {
                     println!("Restarting services...");
                 }
-                ComposeCommands::Config { file } => {
-                    let compose_file = file.unwrap_or_else(|| {
-                        ComposeParser::find_compose_file(&working_dir)
-                            .unwrap_or_else(|| working_dir.join("compose.yaml"))
-                    });
-
-                    let config = ComposeParser::parse_file(&compose_file)?;
-                    let warnings = ComposeParser::validate(&config)?;
-
-                    for warning in warnings {
-                        println!("Warning: {}", warning);
+                ComposeCommands::Config {
+                    file,
+                    services,
+                    images,
+                    volumes,
+                    hash,
+                } => {
+                    let orchestrator =
+                        compose_orchestrator(file, working_dir.clone(), container_manager)?;
+                    let mut config = orchestrator.config().clone();
+                    ComposeParser::normalize(&mut config, &working_dir)?;
+                    let mut names: Vec<&String> = config.services.keys().collect();
+                    names.sort();
+
+                    if services {
+                        for name in names {
+                            println!("{}", name);
+                        }
+                    } else if images {
+                        for name in names {
+                            println!(
+                                "{}",
+                                orchestrator.service_image(name, &config.services[name])
+                            );
+                        }
+                    } else if volumes {
+                        let mut volumes: Vec<&String> = config.volumes.keys().collect();
+                        volumes.sort();
+                        for volume in volumes {
+                            println!("{}", volume);
+                        }
+                    } else if let Some(hash) = hash {
+                        let names: Vec<&str> = match hash.as_str() {
+                            "*" => names.iter().map(|name| name.as_str()).collect(),
+                            list => list.split(',').map(str::trim).collect(),
+                        };
+                        for name in names {
+                            let service = config
+                                .services
+                                .get(name)
+                                .ok_or_else(|| RuneError::ServiceNotFound(name.to_string()))?;
+                            println!("{} {}", name, ComposeParser::service_hash(service)?);
+                        }
+                    } else {
+                        for warning in ComposeParser::validate(&config)? {
+                            eprintln!("Warning: {}", warning);
+                        }
+                        print!("{}", ComposeParser::to_yaml(&config)?);
                     }
-
-                    println!("{}", serde_yaml::to_string(&config).unwrap());
                 }
             }
         }
@@ -981,13 +2527,20 @@ async fn main() -> Result<()> {
                 listen_addr,
                 advertise_addr,
                 force_new_cluster,
+                autolock,
             } => {
-                let config = SwarmConfig {
+                let advertise_addr = match advertise_addr {
+                    Some(addr) => addr,
+                    None => default_advertise_addr(DEFAULT_ROUTE_PROBE, &listen_addr)?,
+                };
+                let mut config = SwarmConfig {
                     listen_addr,
-                    advertise_addr: advertise_addr.unwrap_or_else(|| "0.0.0.0:2377".to_string()),
+                    advertise_addr,
                     force_new_cluster,
+                    state_dir: Some(PathBuf::from(DEFAULT_STATE_DIR)),
                     ..SwarmConfig::default()
                 };
+                config.encryption_config.auto_lock_managers = autolock;
 
                 let cluster = SwarmCluster::init(config)?;
                 println!(
@@ -997,34 +2550,113 @@ async fn main() -> Result<()> {
                 println!("\nTo add a worker to this swarm, run:");
                 println!(
                     "    rune swarm join --token {} <manager-ip>:2377",
-                    cluster.join_token(rune::swarm::cluster::TokenType::Worker)
+                    cluster.join_token(TokenType::Worker)
                 );
                 println!("\nTo add a manager to this swarm, run:");
                 println!(
                     "    rune swarm join --token {} <manager-ip>:2377",
-                    cluster.join_token(rune::swarm::cluster::TokenType::Manager)
+                    cluster.join_token(TokenType::Manager)
                 );
+                if let Some(key) = cluster.unlock_key() {
+                    print_unlock_key(key);
+                }
             }
-            SwarmCommands::Join { token: _, remote } => {
+            SwarmCommands::Join {
+                token,
+                listen_addr,
+                advertise_addr,
+                remote,
+            } => {
+                let advertise_addr = match advertise_addr {
+                    Some(addr) => addr,
+                    None => default_advertise_addr(&remote, &listen_addr)?,
+                };
                 println!("Joining swarm at {}...", remote);
+                let mut cluster =
+                    SwarmCluster::join(&token, vec![remote], &listen_addr, &advertise_addr).await?;
+                cluster.persist(&PathBuf::from(DEFAULT_STATE_DIR))?;
+                let role = match cluster.credentials().identity()?.role {
+                    NodeRole::Manager => "manager",
+                    NodeRole::Worker => "worker",
+                };
+                println!("This node joined a swarm as a {}.", role);
             }
             SwarmCommands::Leave { force: _ } => {
                 println!("Leaving swarm...");
             }
-            SwarmCommands::JoinToken { role, rotate: _ } => {
-                println!("Join token for {}: SWMTKN-...", role);
+            SwarmCommands::JoinToken { role, rotate } => {
+                let token_type = match role.as_str() {
+                    "worker" => TokenType::Worker,
+                    "manager" => TokenType::Manager,
+                    _ => {
+                        return Err(RuneError::InvalidConfig(format!(
+                            "Unknown role {}: expected worker or manager",
+                            role
+                        )))
+                    }
+                };
+                let mut cluster = open_swarm()?;
+                let token = if rotate {
+                    let token = cluster.rotate_join_token(token_type)?;
+                    println!("Successfully rotated {} join token.\n", role);
+                    token
+                } else {
+                    cluster.join_token(token_type).to_string()
+                };
+                if token.is_empty() {
+                    return Err(RuneError::Swarm(
+                        "This node does not hold the swarm's join tokens; run the command on the manager that initialized the swarm".to_string(),
+                    ));
+                }
+                println!(
+                    "To add a {} to this swarm, run the following command:\n",
+                    role
+                );
+                println!("    rune swarm join --token {} <manager-ip>:2377", token);
             }
             SwarmCommands::Update {
-                autolock: _,
+                autolock,
                 task_history_limit: _,
             } => {
-                println!("Swarm updated");
+                let mut cluster = open_swarm()?;
+                let key = match autolock {
+                    Some(enabled) => cluster.set_autolock(enabled)?,
+                    None => None,
+                };
+                println!("Swarm updated.");
+                if let Some(key) = key {
+                    print_unlock_key(&key);
+                }
             }
             SwarmCommands::Unlock => {
-                println!("Please enter unlock key:");
+                let state_dir = PathBuf::from(DEFAULT_STATE_DIR);
+                if !KeyStore::open(&state_dir)?.is_locked()? {
+                    return Err(RuneError::Swarm("Swarm is not locked".to_string()));
+                }
+                let key = read_unlock_key()?;
+                KeyStore::open(&state_dir)?.load(Some(&key))?;
+                let store = ContextStore::open_default()?;
+                let context = store.get(&store.current_name())?;
+                DaemonClient::new(&context)?.unlock_swarm(&key)?;
             }
-            SwarmCommands::UnlockKey { rotate: _ } => {
-                println!("Unlock key: SWMKEY-...");
+            SwarmCommands::UnlockKey { rotate } => {
+                let mut cluster = open_swarm()?;
+                let key = if rotate {
+                    let key = cluster.rotate_unlock_key()?;
+                    println!("Successfully rotated manager unlock key.\n");
+                    key
+                } else {
+                    cluster
+                        .unlock_key()
+                        .map(str::to_string)
+                        .ok_or_else(|| {
+                            RuneError::Swarm(
+                                "No unlock key is set; enable one with \"rune swarm update --autolock=true\""
+                                    .to_string(),
+                            )
+                        })?
+                };
+                print_unlock_key(&key);
             }
         },
 
@@ -1034,13 +2666,77 @@ async fn main() -> Result<()> {
             }
             ServiceCommands::Create {
                 name,
-                image: _,
-                replicas: _,
-                publish: _,
-                env: _,
-                mount: _,
+                image,
+                mode,
+                replicas,
+                publish,
+                env,
+                mount,
+                constraint,
+                placement_pref,
             } => {
-                println!("Created service {}", name);
+                if replicas.is_some() && mode.starts_with("global") {
+                    return Err(RuneError::InvalidConfig(
+                        "replicas can only be used with replicated or replicated-job mode"
+                            .to_string(),
+                    ));
+                }
+                for expr in &constraint {
+                    Constraint::parse(expr)?;
+                }
+                let mut preferences = Vec::new();
+                for pref in &placement_pref {
+                    match pref.split_once('=') {
+                        Some(("spread", descriptor)) if !descriptor.is_empty() => {
+                            preferences.push(PlacementPreference {
+                                spread: Some(SpreadOver {
+                                    spread_descriptor: descriptor.to_string(),
+                                }),
+                            })
+                        }
+                        _ => {
+                            return Err(RuneError::InvalidConfig(format!(
+                                "Invalid placement preference '{}': expected spread=<label>",
+                                pref
+                            )))
+                        }
+                    }
+                }
+                let mut ports = Vec::new();
+                for spec in &publish {
+                    ports.extend(PortConfig::parse(spec)?);
+                }
+                let mounts = mount
+                    .iter()
+                    .map(|spec| Mount::parse(spec))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let spec = ServiceSpec {
+                    name: name.clone(),
+                    task_template: TaskSpec {
+                        container_spec: Some(ContainerSpec {
+                            image,
+                            env,
+                            mounts,
+                            ..Default::default()
+                        }),
+                        placement: (!constraint.is_empty() || !preferences.is_empty()).then(|| {
+                            Placement {
+                                constraints: constraint,
+                                preferences,
+                                max_replicas: None,
+                                platforms: Vec::new(),
+                            }
+                        }),
+                        ..Default::default()
+                    },
+                    mode: Some(ServiceMode::parse(&mode, replicas)?),
+                    endpoint_spec: (!ports.is_empty())
+                        .then_some(EndpointSpec { mode: None, ports }),
+                    ..Default::default()
+                };
+                let id = open_swarm()?.create_service(Service::new(spec))?;
+                println!("{}", id);
             }
             ServiceCommands::Update {
                 service,
@@ -1066,11 +2762,28 @@ async fn main() -> Result<()> {
             ServiceCommands::Inspect { service } => {
                 println!("Inspecting service {}...", service);
             }
-            ServiceCommands::Logs { service, follow: _ } => {
-                println!("Fetching logs for service {}...", service);
+            ServiceCommands::Logs {
+                service,
+                follow,
+                tail,
+            } => {
+                let source = FileLogSource::new(PathBuf::from(DEFAULT_CONTAINER_ROOT));
+                let cluster = open_swarm()?.with_log_source(Arc::new(source));
+                if follow {
+                    cluster
+                        .follow_service_logs(&service, tail, |line| println!("{}", line))
+                        .await?;
+                } else {
+                    for line in cluster.service_logs(&service, tail).await? {
+                        println!("{}", line);
+                    }
+                }
             }
-            ServiceCommands::Ps { service: _ } => {
-                println!("ID             NAME              IMAGE     NODE      DESIRED STATE   CURRENT STATE");
+            ServiceCommands::Ps { service } => {
+                let cluster = open_swarm()?;
+                let service = cluster.get_service(&service)?;
+                let tasks = cluster.list_tasks(Some(&service.id))?;
+                print_tasks(&cluster, &tasks)?;
             }
         },
 
@@ -1083,12 +2796,46 @@ async fn main() -> Result<()> {
             }
             NodeCommands::Update {
                 node,
-                availability: _,
-                role: _,
-                label_add: _,
-                label_rm: _,
+                availability,
+                role,
+                label_add,
+                label_rm,
             } => {
-                println!("Updated node {}", node);
+                let role = match role.as_deref() {
+                    None => None,
+                    Some("worker") => Some(NodeRole::Worker),
+                    Some("manager") => Some(NodeRole::Manager),
+                    Some(role) => {
+                        return Err(RuneError::InvalidConfig(format!(
+                            "Invalid role '{}': expected worker or manager",
+                            role
+                        )))
+                    }
+                };
+                let cluster = open_swarm()?;
+                let current = cluster.get_node(&node)?;
+                let labels = if label_add.is_empty() && label_rm.is_empty() {
+                    None
+                } else {
+                    let mut labels = current.labels.clone();
+                    for label in label_add {
+                        let (key, value) = label.split_once('=').unwrap_or((&label, ""));
+                        labels.insert(key.to_string(), value.to_string());
+                    }
+                    for key in label_rm {
+                        labels.remove(&key);
+                    }
+                    Some(labels)
+                };
+                cluster.update_node(
+                    &current.id,
+                    NodeUpdate {
+                        role,
+                        availability,
+                        labels,
+                    },
+                )?;
+                println!("{}", node);
             }
             NodeCommands::Promote { nodes } => {
                 for node in nodes {
@@ -1110,6 +2857,282 @@ async fn main() -> Result<()> {
             }
         },
 
+        Commands::Stack { command } => match command {
+            StackCommands::Deploy {
+                name,
+                compose_file,
+                prune,
+            } => {
+                let working_dir = std::env::current_dir()?;
+                let files = if compose_file.is_empty() {
+                    vec![ComposeParser::find_compose_file(&working_dir)
+                        .unwrap_or_else(|| working_dir.join("compose.yaml"))]
+                } else {
+                    compose_file
+                };
+                let paths: Vec<&std::path::Path> = files.iter().map(|p| p.as_path()).collect();
+
+                let config = ComposeParser::parse_files(&paths)?;
+                for warning in ComposeParser::validate(&config)? {
+                    println!("Warning: {}", warning);
+                }
+
+                let deployment = StackDeployment::from_compose(&name, &config)?;
+                let report = open_swarm()?.deploy_stack(deployment, prune)?;
+                for network in &report.created_networks {
+                    println!("Creating network {}", network);
+                }
+                for service in &report.removed_services {
+                    println!("Removing service {}", service);
+                }
+                for service in &report.created_services {
+                    println!("Creating service {}", service);
+                }
+                for service in &report.updated_services {
+                    println!("Updating service {}", service);
+                }
+            }
+            StackCommands::List => {
+                println!("{:<20} SERVICES", "NAME");
+                for stack in open_swarm()?.list_stacks()? {
+                    println!("{:<20} {}", stack.name, stack.services);
+                }
+            }
+            StackCommands::Ps { name } => {
+                let cluster = open_swarm()?;
+                let tasks = cluster.stack_tasks(&name)?;
+                print_tasks(&cluster, &tasks)?;
+            }
+            StackCommands::Remove { names } => {
+                let cluster = open_swarm()?;
+                for name in names {
+                    println!("Removing stack {}", name);
+                    cluster.remove_stack(&name)?;
+                }
+            }
+            StackCommands::Services { name } => {
+                let cluster = open_swarm()?;
+                let services = cluster.stack_services(&name)?;
+                print_services(&cluster, &services)?;
+            }
+        },
+
+        Commands::Registry { command } => match command {
+            RegistryCommands::Serve {
+                address,
+                port,
+                storage,
+                s3_endpoint,
+                s3_bucket,
+                s3_region,
+                s3_root,
+                tls_cert,
+                tls_key,
+                disable_delete,
+                htpasswd,
+                auth_mode,
+                anonymous_read,
+                proxy_remote_url,
+                proxy_username,
+                proxy_password,
+                proxy_ttl,
+                proxy_max_size,
+                notify,
+            } => {
+                let auth_mode = if auth_mode == "token" {
+                    AuthMode::Token
+                } else {
+                    AuthMode::Basic
+                };
+                let s3 = match (s3_endpoint, s3_bucket) {
+                    (Some(endpoint), Some(bucket)) => Some(S3Config {
+                        endpoint,
+                        region: s3_region,
+                        bucket,
+                        access_key: None,
+                        secret_key: None,
+                        session_token: None,
+                        root: s3_root,
+                    }),
+                    _ => None,
+                };
+                let proxy = match proxy_remote_url {
+                    Some(url) => Some(ProxyConfig {
+                        username: proxy_username,
+                        password: proxy_password,
+                        ttl: (parse_duration(&proxy_ttl)? / 1_000_000_000) as u64,
+                        max_size: proxy_max_size
+                            .map(|size| parse_bytes(&size).map(|b| b as u64))
+                            .transpose()?,
+                        ..ProxyConfig::new(url)
+                    }),
+                    None => None,
+                };
+                let config = RegistryConfig {
+                    address,
+                    port,
+                    storage_path: storage,
+                    s3,
+                    proxy,
+                    notifications: notify
+                        .into_iter()
+                        .map(|url| EndpointConfig::new(url.clone(), url))
+                        .collect(),
+                    tls_enabled: tls_cert.is_some(),
+                    tls_cert,
+                    tls_key,
+                    delete_enabled: !disable_delete,
+                    auth_enabled: htpasswd.is_some(),
+                    auth_mode,
+                    htpasswd,
+                    anonymous_pull: anonymous_read,
+                    anonymous_push: false,
+                    ..RegistryConfig::default()
+                };
+                let location = match config.s3 {
+                    Some(ref s3) => format!("s3://{}/{}", s3.bucket, s3.root),
+                    None => config.storage_path.display().to_string(),
+                };
+                println!(
+                    "Serving registry on {}:{} from {}",
+                    config.address, config.port, location
+                );
+                if let Some(ref proxy) = config.proxy {
+                    println!("Mirroring {}", proxy.remote_url);
+                }
+                Arc::new(RegistryServer::new(config)?).serve().await?;
+            }
+        },
+
+        Commands::Context { command } => {
+            let mut store = ContextStore::open_default()?;
+            match command {
+                ContextCommands::List => {
+                    let current = store.current_name();
+                    println!("{:<20} {:<40} DESCRIPTION", "NAME", "ENDPOINT");
+                    for context in store.list() {
+                        let name = if context.name == current {
+                            format!("{} *", context.name)
+                        } else {
+                            context.name.clone()
+                        };
+                        println!(
+                            "{:<20} {:<40} {}",
+                            name, context.endpoint, context.description
+                        );
+                    }
+                }
+                ContextCommands::Create {
+                    name,
+                    endpoint,
+                    description,
+                    tls_ca,
+                    tls_cert,
+                    tls_key,
+                } => {
+                    let context = Context {
+                        description,
+                        tls: tls_ca.map(|ca| TlsFiles {
+                            ca,
+                            cert: tls_cert,
+                            key: tls_key,
+                        }),
+                        ..Context::new(name.clone(), endpoint)
+                    };
+                    store.add(context)?;
+                    store.save()?;
+                    println!("{}", name);
+                }
+                ContextCommands::Use { name } => {
+                    store.use_context(&name)?;
+                    store.save()?;
+                    println!("Current context is now \"{}\"", name);
+                }
+                ContextCommands::Remove { name } => {
+                    store.remove(&name)?;
+                    store.save()?;
+                    println!("{}", name);
+                }
+                ContextCommands::Inspect { name } => {
+                    let context = store.get(&name)?;
+                    println!("{}", serde_json::to_string_pretty(&context)?);
+                }
+            }
+        }
+
+        Commands::Debug { command } => match command {
+            DebugCommands::Bundle {
+                output,
+                context,
+                since,
+            } => {
+                let store = ContextStore::open_default()?;
+                let context = store.get(&context.unwrap_or_else(|| store.current_name()))?;
+                let client = DaemonClient::new(&context)?;
+                let since =
+                    chrono::Utc::now().timestamp() - parse_duration(&since)? / 1_000_000_000;
+                let output = output.unwrap_or_else(|| {
+                    PathBuf::from(format!(
+                        "rune-debug-{}.tar.gz",
+                        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+                    ))
+                });
+                let files = debug_bundle(&client, since);
+                write_bundle(std::fs::File::create(&output)?, &files)?;
+                println!("Wrote {}", output.display());
+            }
+        },
+
+        Commands::Audit { command } => {
+            let (since, filter, context, local) = match &command {
+                AuditCommands::List {
+                    since,
+                    filter,
+                    context,
+                    local,
+                }
+                | AuditCommands::Export {
+                    since,
+                    filter,
+                    context,
+                    local,
+                    ..
+                } => (since, filter, context, *local),
+            };
+            let filters = Filters::parse(filter)?;
+            filters.validate(AUDIT_FILTERS)?;
+            let since = since
+                .as_deref()
+                .map(parse_duration)
+                .transpose()?
+                .map(|nanos| chrono::Utc::now() - chrono::Duration::nanoseconds(nanos));
+            let entries = if local {
+                local_audit_log(&base_path)?.entries(since, &filters)?
+            } else {
+                let store = ContextStore::open_default()?;
+                let context =
+                    store.get(&context.clone().unwrap_or_else(|| store.current_name()))?;
+                DaemonClient::new(&context)?.audit(since, &filters)?
+            };
+            match command {
+                AuditCommands::List { .. } => print_audit(&entries),
+                AuditCommands::Export { output, .. } => {
+                    let mut lines = String::new();
+                    for entry in &entries {
+                        lines.push_str(&serde_json::to_string(entry)?);
+                        lines.push('\n');
+                    }
+                    match output {
+                        Some(output) => {
+                            std::fs::write(&output, lines)?;
+                            eprintln!("Exported {} entries to {}", entries.len(), output.display());
+                        }
+                        None => print!("{}", lines),
+                    }
+                }
+            }
+        }
+
         Commands::Info => {
             println!("Client:");
             println!(" Version:    {}", env!("CARGO_PKG_VERSION"));
@@ -1142,11 +3165,299 @@ async fn main() -> Result<()> {
             );
         }
 
-        Commands::Tui => {
-            let mut app = App::new(container_manager);
+        Commands::Tui { context } => {
+            let store = ContextStore::open_default()?;
+            let context = context.unwrap_or_else(|| store.current_name());
+            let mut app = App::with_contexts(store.list(), &context).with_config(TuiConfig::load());
             app.run()?;
         }
     }
 
     Ok(())
 }
+
+/// Audit log of the actions this CLI performs itself
+fn local_audit_log(base_path: &std::path::Path) -> Result<AuditLog> {
+    AuditLog::new(base_path.join("audit").join("audit.log"))
+}
+
+/// Action and target of a command the CLI performs itself, if it changes
+/// anything worth auditing
+fn cli_action(command: &Commands) -> Option<(&'static str, String)> {
+    match command {
+        Commands::Run { image, name, .. } => Some((
+            "container.run",
+            name.clone().unwrap_or_else(|| image.clone()),
+        )),
+        Commands::Create { image, name, .. } => Some((
+            "container.create",
+            name.clone().unwrap_or_else(|| image.clone()),
+        )),
+        Commands::Start { container, .. } => Some(("container.start", container.clone())),
+        Commands::Stop { container, .. } => Some(("container.stop", container.clone())),
+        Commands::Kill { container, .. } => Some(("container.kill", container.clone())),
+        Commands::Restart { container } => Some(("container.restart", container.clone())),
+        Commands::Remove { container, .. } => Some(("container.remove", container.clone())),
+        Commands::Exec { container, .. } => Some(("container.exec", container.clone())),
+        Commands::Image { command } => match command {
+            ImageCommands::Pull { name } => Some(("image.pull", name.clone())),
+            ImageCommands::Remove { image, .. } => Some(("image.remove", image.clone())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Print audit entries as a table
+fn print_audit(entries: &[AuditEntry]) {
+    println!(
+        "{:<20} {:<6} {:<21} {:<22} {:<24} STATUS",
+        "TIME", "UID", "ORIGIN", "ACTION", "TARGET"
+    );
+    for entry in entries {
+        let uid = entry
+            .caller
+            .uid
+            .map(|uid| uid.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let origin = entry
+            .caller
+            .remote_addr
+            .as_deref()
+            .or(entry.caller.context.as_deref())
+            .unwrap_or("local");
+        println!(
+            "{:<20} {:<6} {:<21} {:<22} {:<24} {}",
+            entry.time.format("%Y-%m-%d %H:%M:%S"),
+            uid,
+            origin,
+            entry.action,
+            entry.target,
+            entry.status
+        );
+    }
+}
+
+/// Files of a debug bundle, from what a daemon reports; what it fails to
+/// report is noted in `errors.txt` rather than failing the bundle
+fn debug_bundle(client: &DaemonClient, since: i64) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut errors = String::new();
+    for (name, path) in [
+        ("version.json", "/version".to_string()),
+        ("info.json", "/info".to_string()),
+        ("debug.json", "/debug".to_string()),
+        ("daemon.log", "/debug/logs".to_string()),
+        ("events.jsonl", format!("/events?since={}", since)),
+    ] {
+        match client.request("GET", &path, None) {
+            Ok(body) => files.push((name.to_string(), body.into_bytes())),
+            Err(e) => errors.push_str(&format!("GET {}: {}\n", path, e)),
+        }
+    }
+    if !errors.is_empty() {
+        if files.iter().all(|(name, _)| name != "debug.json") {
+            errors.push_str(
+                "The debug endpoints are served when the daemon runs with debugging on\n",
+            );
+        }
+        files.push(("errors.txt".to_string(), errors.into_bytes()));
+    }
+    files
+}
+
+/// Orchestrator of the compose project in `file`, or in the compose file
+/// found in the working directory
+fn compose_orchestrator(
+    file: Option<PathBuf>,
+    working_dir: PathBuf,
+    container_manager: Arc<ContainerManager>,
+) -> Result<ComposeOrchestrator> {
+    let compose_file = file.unwrap_or_else(|| {
+        ComposeParser::find_compose_file(&working_dir)
+            .unwrap_or_else(|| working_dir.join("compose.yaml"))
+    });
+
+    let config = ComposeParser::parse_file(&compose_file)?;
+    let project_name = config.name.clone().unwrap_or_else(|| {
+        working_dir
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("default")
+            .to_string()
+    });
+
+    Ok(ComposeOrchestrator::new(
+        &project_name,
+        config,
+        container_manager,
+        working_dir,
+    ))
+}
+
+/// Open the local manager's swarm state, asking for the unlock key if locked
+fn open_swarm() -> Result<SwarmCluster> {
+    let state_dir = PathBuf::from(DEFAULT_STATE_DIR);
+    let key = if KeyStore::open(&state_dir)?.is_locked()? {
+        Some(read_unlock_key()?)
+    } else {
+        None
+    };
+    SwarmCluster::restore(&state_dir, key.as_deref())
+        .with_context(|| format!("Failed to open swarm state in {}", state_dir.display()))
+}
+
+/// An address off every local network, so connections to it take the
+/// default route
+const DEFAULT_ROUTE_PROBE: &str = "192.0.2.1:2377";
+
+/// Address to advertise on the listen port: the local address connections
+/// to `remote` leave from
+fn default_advertise_addr(remote: &str, listen_addr: &str) -> Result<String> {
+    let port = listen_addr
+        .rsplit_once(':')
+        .map_or("2377", |(_, port)| port);
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(remote).map_err(|e| {
+        RuneError::Swarm(format!(
+            "Could not find an address to advertise for reaching {} ({}); pass --advertise-addr",
+            remote, e
+        ))
+    })?;
+    Ok(format!("{}:{}", socket.local_addr()?.ip(), port))
+}
+
+/// Read an unlock key from standard input
+fn read_unlock_key() -> Result<String> {
+    use std::io::Write;
+
+    print!("Please enter unlock key: ");
+    std::io::stdout().flush()?;
+    let mut key = String::new();
+    std::io::stdin().read_line(&mut key)?;
+    Ok(key.trim().to_string())
+}
+
+/// Print swarm services as a table, with their running and desired
+/// replicas
+fn print_services(cluster: &SwarmCluster, services: &[Service]) -> Result<()> {
+    let tasks = cluster.list_tasks(None)?;
+    println!(
+        "{:<14} {:<20} {:<12} {:<10} IMAGE",
+        "ID", "NAME", "MODE", "REPLICAS"
+    );
+    for service in services {
+        let tasks: Vec<&Task> = tasks
+            .iter()
+            .filter(|t| t.service_id == service.id)
+            .collect();
+        let running = tasks
+            .iter()
+            .filter(|t| t.status.state == TaskState::Running)
+            .count();
+        let (mode, desired) = match service.spec.mode {
+            Some(ServiceMode::Global) => (
+                "global",
+                tasks
+                    .iter()
+                    .filter(|t| t.desired_state == TaskState::Running)
+                    .count() as u64,
+            ),
+            Some(ServiceMode::GlobalJob) => ("global-job", tasks.len() as u64),
+            Some(ServiceMode::ReplicatedJob { .. }) => ("replicated-job", service.replicas()),
+            _ => ("replicated", service.replicas()),
+        };
+        let image = service
+            .spec
+            .task_template
+            .container_spec
+            .as_ref()
+            .map(|c| c.image.as_str())
+            .unwrap_or("");
+        println!(
+            "{:<14} {:<20} {:<12} {:<10} {}",
+            &service.id[..service.id.len().min(12)],
+            service.spec.name,
+            mode,
+            format!("{}/{}", running, desired),
+            image
+        );
+    }
+    Ok(())
+}
+
+/// Print swarm tasks as a table, naming each after its service and slot
+fn print_tasks(cluster: &SwarmCluster, tasks: &[Task]) -> Result<()> {
+    println!(
+        "{:<14} {:<24} {:<20} {:<16} {:<14} CURRENT STATE",
+        "ID", "NAME", "IMAGE", "NODE", "DESIRED STATE"
+    );
+    for task in tasks {
+        let service = cluster.get_service(&task.service_id)?;
+        let name = match task.slot {
+            Some(slot) => format!("{}.{}", service.spec.name, slot),
+            None => service.spec.name.clone(),
+        };
+        let image = service
+            .spec
+            .task_template
+            .container_spec
+            .as_ref()
+            .map(|c| c.image.clone())
+            .unwrap_or_default();
+        let node = task
+            .node_id
+            .as_deref()
+            .map(|id| {
+                cluster
+                    .get_node(id)
+                    .map(|node| node.hostname)
+                    .unwrap_or_else(|_| id.to_string())
+            })
+            .unwrap_or_default();
+        println!(
+            "{:<14} {:<24} {:<20} {:<16} {:<14} {:?}",
+            &task.id[..task.id.len().min(12)],
+            name,
+            image,
+            node,
+            format!("{:?}", task.desired_state),
+            task.status.state
+        );
+    }
+    Ok(())
+}
+
+/// Print a layer's digest, size and number of changes of each kind
+fn print_layer_summary(layer: &LayerAnalysis) {
+    let digest = layer.digest.trim_start_matches("sha256:");
+    println!(
+        "{:<14} {:>10} {:>8} {:>8} {:>8}",
+        &digest[..digest.len().min(12)],
+        format_bytes(layer.size()),
+        layer.count(ChangeKind::Added),
+        layer.count(ChangeKind::Modified),
+        layer.count(ChangeKind::Removed)
+    );
+}
+
+/// Print the files a layer changes
+fn print_layer_changes(layer: &LayerAnalysis) {
+    for change in &layer.changes {
+        println!(
+            "  {} {:>10}  {}",
+            change.kind,
+            format_bytes(change.size),
+            change.path
+        );
+    }
+}
+
+/// Tell the user how to unlock a restarted manager
+fn print_unlock_key(key: &str) {
+    println!("\nTo unlock a swarm manager after it restarts, run the `rune swarm unlock`");
+    println!("command and provide the following key:\n");
+    println!("    {}\n", key);
+    println!("Please remember to store this key in a password manager, since without it you");
+    println!("will not be able to restart the manager.");
+}
@@ -1,11 +1,22 @@
 //! Bridge network implementation
 
-use super::config::{IpAllocator, NetworkConfig, NetworkContainer, NetworkDriver};
+use super::config::{EndpointConfig, IpAllocator, NetworkConfig, NetworkContainer, NetworkDriver};
+use super::veth::Interfaces;
 use crate::error::{Result, RuneError};
+use crate::filter::Filters;
+use crate::plugin::{NetworkPlugin, PluginManager};
 use std::collections::HashMap;
+use std::net::Ipv4Addr;
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
+/// Filters networks can be listed by
+pub const NETWORK_FILTERS: &[&str] =
+    &["dangling", "driver", "id", "label", "name", "scope", "type"];
+
+/// Networks every daemon has
+const BUILTIN_NETWORKS: &[&str] = &["bridge", "host", "none"];
+
 /// Bridge network manager
 pub struct BridgeNetwork {
     /// Network configuration
@@ -29,22 +40,72 @@ impl BridgeNetwork {
         Ok(Self { config, allocator })
     }
 
-    /// Connect a container to this network
+    /// Connect a container to this network, at the address and MAC
+    /// address it asks for, if any
     pub fn connect(
         &mut self,
         container_id: &str,
         container_name: &str,
+        endpoint: &EndpointConfig,
     ) -> Result<NetworkContainer> {
-        let ip = self.allocator.allocate()?;
+        let user_defined = !BUILTIN_NETWORKS.contains(&self.config.name.as_str());
+        if !user_defined && (endpoint.ipv4_address.is_some() || !endpoint.aliases.is_empty()) {
+            return Err(RuneError::InvalidConfig(format!(
+                "Static addresses and aliases are only supported on user-defined networks, not {}",
+                self.config.name
+            )));
+        }
+        let mac_address = match &endpoint.mac_address {
+            Some(mac) => {
+                let mac = parse_mac_address(mac)?;
+                if self
+                    .config
+                    .containers
+                    .values()
+                    .any(|c| c.mac_address == mac)
+                {
+                    return Err(RuneError::Network(format!(
+                        "MAC address {} is already in use on network {}",
+                        mac, self.config.name
+                    )));
+                }
+                mac
+            }
+            None => generate_mac_address(),
+        };
+        let ip = match &endpoint.ipv4_address {
+            Some(address) => {
+                let ip: Ipv4Addr = address.parse().map_err(|_| {
+                    RuneError::InvalidConfig(format!("Invalid IPv4 address: {}", address))
+                })?;
+                if !self.allocator.contains(ip) {
+                    return Err(RuneError::Network(format!(
+                        "Address {} is not in the subnet of network {}",
+                        ip, self.config.name
+                    )));
+                }
+                if self.allocator.is_allocated(ip) {
+                    return Err(RuneError::Network(format!(
+                        "Address {} is already in use on network {}",
+                        ip, self.config.name
+                    )));
+                }
+                self.allocator.reserve(ip);
+                ip
+            }
+            None => self.allocator.allocate()?,
+        };
         let endpoint_id = Uuid::new_v4().to_string().replace("-", "")[..12].to_string();
 
         let container = NetworkContainer {
             container_id: container_id.to_string(),
             name: container_name.to_string(),
             endpoint_id,
-            mac_address: generate_mac_address(),
-            ipv4_address: Some(format!("{}/16", ip)),
+            mac_address,
+            ipv4_address: Some(format!("{}/{}", ip, self.allocator.prefix_len())),
             ipv6_address: None,
+            aliases: endpoint.aliases.clone(),
+            interface: String::new(),
         };
 
         self.config
@@ -86,6 +147,10 @@ pub struct NetworkManager {
     networks: Arc<RwLock<HashMap<String, BridgeNetwork>>>,
     /// Name to ID mapping
     names: Arc<RwLock<HashMap<String, String>>>,
+    /// Plugins drivers other than the built-in ones are looked up in
+    plugins: Option<Arc<PluginManager>>,
+    /// Sets up the interfaces of running containers on bridge networks
+    interfaces: Option<Arc<dyn Interfaces>>,
 }
 
 impl NetworkManager {
@@ -94,6 +159,8 @@ impl NetworkManager {
         let manager = Self {
             networks: Arc::new(RwLock::new(HashMap::new())),
             names: Arc::new(RwLock::new(HashMap::new())),
+            plugins: None,
+            interfaces: None,
         };
 
         // Create default networks
@@ -102,6 +169,30 @@ impl NetworkManager {
         Ok(manager)
     }
 
+    /// Look drivers other than the built-in ones up in plugins
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Set up the interfaces of running containers on bridge networks;
+    /// without this, endpoints are only tracked
+    pub fn with_interfaces(mut self, interfaces: Arc<dyn Interfaces>) -> Self {
+        self.interfaces = Some(interfaces);
+        self
+    }
+
+    /// Plugin implementing a driver, or `None` for the built-in ones
+    fn plugin(&self, driver: &NetworkDriver) -> Result<Option<NetworkPlugin>> {
+        let NetworkDriver::Plugin(name) = driver else {
+            return Ok(None);
+        };
+        match &self.plugins {
+            Some(plugins) => plugins.network_driver(name).map(Some),
+            None => Err(RuneError::PluginNotFound(name.clone())),
+        }
+    }
+
     /// Create default networks (bridge, host, none)
     fn create_default_networks(&self) -> Result<()> {
         // Default bridge network
@@ -146,6 +237,9 @@ impl NetworkManager {
                 name
             )));
         }
+        if let Some(plugin) = self.plugin(&network.config.driver)? {
+            plugin.create_network(&network.config)?;
+        }
 
         networks.insert(id.clone(), network);
         names.insert(name, id.clone());
@@ -182,6 +276,14 @@ impl NetworkManager {
                 )));
             }
 
+            if let Some(plugin) = self.plugin(&network.config.driver)? {
+                plugin.delete_network(&id)?;
+            } else if let (Some(interfaces), NetworkDriver::Bridge) =
+                (&self.interfaces, &network.config.driver)
+            {
+                interfaces.remove(&network.config)?;
+            }
+
             // Remove name mapping
             names.remove(&network.config.name);
         }
@@ -226,12 +328,50 @@ impl NetworkManager {
         Ok(networks.values().map(|n| n.config.clone()).collect())
     }
 
+    /// List networks matching filters
+    pub fn list_filtered(&self, filters: &Filters) -> Result<Vec<NetworkConfig>> {
+        filters.validate(NETWORK_FILTERS)?;
+        let dangling = filters.bool("dangling")?;
+        for kind in filters.get("type") {
+            if kind != "builtin" && kind != "custom" {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Invalid filter 'type={}'",
+                    kind
+                )));
+            }
+        }
+
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|network| {
+                let builtin = BUILTIN_NETWORKS.contains(&network.name.as_str());
+                dangling
+                    .is_none_or(|dangling| (network.containers.is_empty() && !builtin) == dangling)
+                    && filters.matches_exact("driver", &network.driver.to_string())
+                    && filters.matches("id", |id| network.id.starts_with(id))
+                    && filters.matches_labels(&network.labels)
+                    && filters.matches("name", |name| network.name.contains(name))
+                    && filters.matches_exact(
+                        "scope",
+                        serde_json::to_value(network.scope)
+                            .ok()
+                            .as_ref()
+                            .and_then(|scope| scope.as_str())
+                            .unwrap_or_default(),
+                    )
+                    && filters.matches_exact("type", if builtin { "builtin" } else { "custom" })
+            })
+            .collect())
+    }
+
     /// Connect a container to a network
     pub fn connect(
         &self,
         network_id_or_name: &str,
         container_id: &str,
         container_name: &str,
+        endpoint: &EndpointConfig,
     ) -> Result<NetworkContainer> {
         let mut networks = self
             .networks
@@ -254,7 +394,19 @@ impl NetworkManager {
             .get_mut(&id)
             .ok_or_else(|| RuneError::NetworkNotFound(network_id_or_name.to_string()))?;
 
-        network.connect(container_id, container_name)
+        let container = network.connect(container_id, container_name, endpoint)?;
+        if let Some(plugin) = self.plugin(&network.config.driver)? {
+            if let Err(e) = plugin.create_endpoint(
+                &id,
+                &container.endpoint_id,
+                container.ipv4_address.as_deref(),
+                &container.mac_address,
+            ) {
+                network.disconnect(container_id)?;
+                return Err(e);
+            }
+        }
+        Ok(container)
     }
 
     /// Disconnect a container from a network
@@ -280,9 +432,134 @@ impl NetworkManager {
             .get_mut(&id)
             .ok_or_else(|| RuneError::NetworkNotFound(network_id_or_name.to_string()))?;
 
+        if let Some(plugin) = self.plugin(&network.config.driver)? {
+            if let Some(container) = network.config.containers.get(container_id) {
+                plugin.delete_endpoint(&id, &container.endpoint_id)?;
+            }
+        }
         network.disconnect(container_id)
     }
 
+    /// Give a running container, process `pid`, the interface of its
+    /// endpoint on a network, named after the first `ethN` its other
+    /// endpoints leave free
+    pub fn attach(&self, network_id_or_name: &str, container_id: &str, pid: u32) -> Result<()> {
+        let mut networks = self
+            .networks
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        let id = self.id(&networks, network_id_or_name)?;
+        let taken: Vec<String> = networks
+            .values()
+            .filter_map(|network| network.config.containers.get(container_id))
+            .map(|endpoint| endpoint.interface.clone())
+            .collect();
+        let interface = (0..)
+            .map(|n| format!("eth{}", n))
+            .find(|name| !taken.contains(name))
+            .expect("some interface name is free");
+
+        let network = networks
+            .get_mut(&id)
+            .ok_or_else(|| RuneError::NetworkNotFound(network_id_or_name.to_string()))?;
+        let endpoint = network.config.containers.get(container_id).ok_or_else(|| {
+            RuneError::Network(format!(
+                "Container {} not connected to network {}",
+                container_id, network.config.name
+            ))
+        })?;
+        if let (Some(interfaces), NetworkDriver::Bridge) =
+            (&self.interfaces, &network.config.driver)
+        {
+            interfaces.attach(&network.config, endpoint, pid, &interface)?;
+        }
+        if let Some(endpoint) = network.config.containers.get_mut(container_id) {
+            endpoint.interface = interface;
+        }
+        Ok(())
+    }
+
+    /// Remove the interface of a running container's endpoint on a network
+    pub fn detach(&self, network_id_or_name: &str, container_id: &str) -> Result<()> {
+        let mut networks = self
+            .networks
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        let id = self.id(&networks, network_id_or_name)?;
+        let network = networks
+            .get_mut(&id)
+            .ok_or_else(|| RuneError::NetworkNotFound(network_id_or_name.to_string()))?;
+        let Some(endpoint) = network.config.containers.get(container_id) else {
+            return Ok(());
+        };
+        if endpoint.interface.is_empty() {
+            return Ok(());
+        }
+        if let (Some(interfaces), NetworkDriver::Bridge) =
+            (&self.interfaces, &network.config.driver)
+        {
+            interfaces.detach(&network.config, endpoint)?;
+        }
+        if let Some(endpoint) = network.config.containers.get_mut(container_id) {
+            endpoint.interface.clear();
+        }
+        Ok(())
+    }
+
+    /// Forget the interfaces of a container whose process exited, which
+    /// went with its network namespace
+    pub fn reset(&self, container_id: &str) -> Result<()> {
+        let mut networks = self
+            .networks
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        for network in networks.values_mut() {
+            if let Some(endpoint) = network.config.containers.get_mut(container_id) {
+                endpoint.interface.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Addresses a name resolves to on a network: those of the containers
+    /// on it with that name, alias or ID
+    pub fn resolve(&self, network_id_or_name: &str, name: &str) -> Result<Vec<Ipv4Addr>> {
+        let network = self.get(network_id_or_name)?;
+        let mut addresses: Vec<Ipv4Addr> = network
+            .containers
+            .values()
+            .filter(|endpoint| {
+                endpoint.name == name
+                    || endpoint.aliases.iter().any(|alias| alias == name)
+                    || (name.len() >= 12 && endpoint.container_id.starts_with(name))
+            })
+            .filter_map(|endpoint| {
+                endpoint
+                    .ipv4_address
+                    .as_deref()?
+                    .split('/')
+                    .next()?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        addresses.sort();
+        Ok(addresses)
+    }
+
+    /// ID of a network by ID or name
+    fn id(&self, networks: &HashMap<String, BridgeNetwork>, id_or_name: &str) -> Result<String> {
+        if networks.contains_key(id_or_name) {
+            return Ok(id_or_name.to_string());
+        }
+        self.names
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?
+            .get(id_or_name)
+            .cloned()
+            .ok_or_else(|| RuneError::NetworkNotFound(id_or_name.to_string()))
+    }
+
     /// Prune unused networks
     pub fn prune(&self) -> Result<Vec<String>> {
         let networks = self
@@ -319,6 +596,23 @@ impl Default for NetworkManager {
     }
 }
 
+/// A MAC address in its usual form, six colon-separated hex bytes, lower
+/// case
+fn parse_mac_address(mac: &str) -> Result<String> {
+    let bytes: Vec<&str> = mac.split(':').collect();
+    if bytes.len() != 6
+        || bytes
+            .iter()
+            .any(|byte| byte.len() != 2 || u8::from_str_radix(byte, 16).is_err())
+    {
+        return Err(RuneError::InvalidConfig(format!(
+            "Invalid MAC address: {}",
+            mac
+        )));
+    }
+    Ok(mac.to_ascii_lowercase())
+}
+
 /// Generate a random MAC address
 fn generate_mac_address() -> String {
     use rand::Rng;
@@ -359,6 +653,51 @@ mod tests {
         assert_eq!(none.driver, NetworkDriver::None);
     }
 
+    #[test]
+    fn test_plugin_network() {
+        use crate::plugin::client::tests::fake_plugin;
+
+        let temp = tempfile::tempdir().unwrap();
+        let (socket, calls) = fake_plugin(
+            &temp,
+            &[("Plugin.Activate", r#"{"Implements": ["NetworkDriver"]}"#)],
+        );
+        let plugins = Arc::new(crate::plugin::PluginManager::open(temp.path()).unwrap());
+        plugins.install("weave", &socket, true).unwrap();
+        let manager = NetworkManager::new().unwrap().with_plugins(plugins);
+
+        let config = NetworkConfig::new("mesh")
+            .driver(NetworkDriver::Plugin("weave".to_string()))
+            .subnet("10.32.0.0/24");
+        let id = manager.create(config).unwrap();
+        let endpoint = manager
+            .connect("mesh", "abc", "web", &EndpointConfig::default())
+            .unwrap();
+        manager.disconnect("mesh", "abc").unwrap();
+        manager.remove("mesh").unwrap();
+
+        let unknown = NetworkConfig::new("other").driver(NetworkDriver::Plugin("x".to_string()));
+        assert!(manager.create(unknown).is_err());
+        assert!(manager.get("other").is_err());
+
+        let calls = calls.lock().unwrap();
+        let methods: Vec<&str> = calls.iter().map(|(method, _)| method.as_str()).collect();
+        assert_eq!(
+            methods,
+            [
+                "Plugin.Activate",
+                "NetworkDriver.CreateNetwork",
+                "NetworkDriver.CreateEndpoint",
+                "NetworkDriver.DeleteEndpoint",
+                "NetworkDriver.DeleteNetwork"
+            ]
+        );
+        assert_eq!(calls[1].1["NetworkID"], id);
+        let pools = calls[1].1["IPv4Data"].as_array().unwrap();
+        assert!(pools.iter().any(|pool| pool["Pool"] == "10.32.0.0/24"));
+        assert_eq!(calls[2].1["EndpointID"], endpoint.endpoint_id);
+    }
+
     #[test]
     fn test_create_network() {
         let manager = NetworkManager::new().unwrap();
@@ -380,8 +719,96 @@ mod tests {
         manager.create(config).unwrap();
 
         let container = manager
-            .connect("test-network", "container1", "test-container")
+            .connect(
+                "test-network",
+                "container1",
+                "test-container",
+                &EndpointConfig::default(),
+            )
             .unwrap();
         assert!(container.ipv4_address.is_some());
     }
+
+    #[test]
+    fn test_connect_static_endpoint() {
+        let manager = NetworkManager::new().unwrap();
+        manager
+            .create(NetworkConfig::new("app").subnet("10.9.0.0/24"))
+            .unwrap();
+
+        let endpoint = EndpointConfig {
+            ipv4_address: Some("10.9.0.50".to_string()),
+            mac_address: Some("02:42:AC:11:00:32".to_string()),
+            aliases: vec!["db".to_string()],
+        };
+        let container = manager.connect("app", "abc", "pg", &endpoint).unwrap();
+        assert_eq!(container.ipv4_address.as_deref(), Some("10.9.0.50/24"));
+        assert_eq!(container.mac_address, "02:42:ac:11:00:32");
+        assert_eq!(container.aliases, ["db"]);
+
+        // The address and MAC address are taken now
+        assert!(manager.connect("app", "def", "other", &endpoint).is_err());
+        let outside = EndpointConfig {
+            ipv4_address: Some("10.10.0.5".to_string()),
+            ..Default::default()
+        };
+        assert!(manager.connect("app", "def", "other", &outside).is_err());
+        let bad_mac = EndpointConfig {
+            mac_address: Some("02:42".to_string()),
+            ..Default::default()
+        };
+        assert!(manager.connect("app", "def", "other", &bad_mac).is_err());
+        let static_on_default = EndpointConfig {
+            ipv4_address: Some("172.17.0.9".to_string()),
+            ..Default::default()
+        };
+        assert!(manager
+            .connect("bridge", "def", "other", &static_on_default)
+            .is_err());
+
+        // Released addresses can be asked for again
+        manager.disconnect("app", "abc").unwrap();
+        manager.connect("app", "def", "pg", &endpoint).unwrap();
+    }
+
+    #[test]
+    fn test_list_filtered() {
+        let manager = NetworkManager::new().unwrap();
+        let mut backend = NetworkConfig::new("backend").subnet("10.40.0.0/24");
+        backend.labels.insert("tier".to_string(), "db".to_string());
+        manager.create(backend).unwrap();
+        manager
+            .connect("backend", "abc", "db", &EndpointConfig::default())
+            .unwrap();
+        manager
+            .create(NetworkConfig::new("frontend").subnet("10.41.0.0/24"))
+            .unwrap();
+
+        let names = |filters: Filters| -> Vec<String> {
+            let mut names: Vec<String> = manager
+                .list_filtered(&filters)
+                .unwrap()
+                .into_iter()
+                .map(|n| n.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(Filters::new()).len(), 5);
+        assert_eq!(
+            names(Filters::new().with("type", "custom")),
+            ["backend", "frontend"]
+        );
+        assert_eq!(names(Filters::new().with("driver", "host")), ["host"]);
+        assert_eq!(
+            names(Filters::new().with("name", "end")),
+            ["backend", "frontend"]
+        );
+        assert_eq!(names(Filters::new().with("label", "tier=db")), ["backend"]);
+        assert_eq!(names(Filters::new().with("dangling", "true")), ["frontend"]);
+        assert_eq!(names(Filters::new().with("scope", "local")).len(), 5);
+        assert!(manager
+            .list_filtered(&Filters::new().with("type", "other"))
+            .is_err());
+    }
 }
@@ -8,7 +8,7 @@ use std::net::Ipv4Addr;
 use uuid::Uuid;
 
 /// Network driver types
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum NetworkDriver {
     /// Bridge network (default)
@@ -24,6 +24,8 @@ pub enum NetworkDriver {
     Macvlan,
     /// IPvlan network
     Ipvlan,
+    /// Driver implemented by a plugin
+    Plugin(String),
 }
 
 impl std::fmt::Display for NetworkDriver {
@@ -35,10 +37,29 @@ impl std::fmt::Display for NetworkDriver {
             NetworkDriver::Overlay => write!(f, "overlay"),
             NetworkDriver::Macvlan => write!(f, "macvlan"),
             NetworkDriver::Ipvlan => write!(f, "ipvlan"),
+            NetworkDriver::Plugin(name) => write!(f, "{}", name),
         }
     }
 }
 
+impl std::str::FromStr for NetworkDriver {
+    type Err = RuneError;
+
+    /// Parse a driver name; names that aren't built in are plugins'
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" => return Err(RuneError::InvalidConfig("Empty network driver".to_string())),
+            "bridge" => NetworkDriver::Bridge,
+            "host" => NetworkDriver::Host,
+            "none" | "null" => NetworkDriver::None,
+            "overlay" => NetworkDriver::Overlay,
+            "macvlan" => NetworkDriver::Macvlan,
+            "ipvlan" => NetworkDriver::Ipvlan,
+            name => NetworkDriver::Plugin(name.to_string()),
+        })
+    }
+}
+
 /// Network scope
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -118,8 +139,10 @@ impl NetworkConfig {
         self
     }
 
-    /// Set subnet
+    /// Set subnet, in place of the default one
     pub fn subnet(mut self, subnet: &str) -> Self {
+        let default = IpamPoolConfig::default().subnet;
+        self.ipam.config.retain(|pool| pool.subnet != default);
         self.ipam.config.push(IpamPoolConfig {
             subnet: subnet.to_string(),
             gateway: None,
@@ -210,6 +233,25 @@ pub struct NetworkContainer {
     pub ipv4_address: Option<String>,
     /// IPv6 address
     pub ipv6_address: Option<String>,
+    /// Names the container is also known by on the network
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Interface in the container, once it is running
+    #[serde(default)]
+    pub interface: String,
+}
+
+/// Settings a container asks for on a network; what is not set is
+/// allocated
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    /// IPv4 address, in the network's subnet
+    pub ipv4_address: Option<String>,
+    /// MAC address
+    pub mac_address: Option<String>,
+    /// Names the container is also known by on the network
+    #[serde(default)]
+    pub aliases: Vec<String>,
 }
 
 /// IP address allocator
@@ -217,6 +259,10 @@ pub struct NetworkContainer {
 pub struct IpAllocator {
     /// Network subnet
     subnet: String,
+    /// Address of the subnet
+    network: Ipv4Addr,
+    /// Length of the subnet's prefix
+    prefix_len: u32,
     /// Allocated addresses
     allocated: Vec<Ipv4Addr>,
     /// Next available address
@@ -235,6 +281,11 @@ impl IpAllocator {
         let base: Ipv4Addr = parts[0]
             .parse()
             .map_err(|_| RuneError::Network(format!("Invalid IP: {}", parts[0])))?;
+        let prefix_len: u32 = parts[1]
+            .parse()
+            .ok()
+            .filter(|len| *len <= 32)
+            .ok_or_else(|| RuneError::Network(format!("Invalid subnet: {}", subnet)))?;
 
         // Start from .2 (gateway is typically .1)
         let octets = base.octets();
@@ -242,6 +293,8 @@ impl IpAllocator {
 
         Ok(Self {
             subnet: subnet.to_string(),
+            network: Ipv4Addr::from(u32::from(base) & mask(prefix_len)),
+            prefix_len,
             allocated: vec![Ipv4Addr::new(octets[0], octets[1], octets[2], 1)], // Reserve gateway
             next,
         })
@@ -276,10 +329,37 @@ impl IpAllocator {
         Ok(ip)
     }
 
+    /// Mark an IP address as already in use
+    pub fn reserve(&mut self, ip: Ipv4Addr) {
+        if !self.allocated.contains(&ip) {
+            self.allocated.push(ip);
+        }
+    }
+
     /// Release an IP address
     pub fn release(&mut self, ip: Ipv4Addr) {
         self.allocated.retain(|&a| a != ip);
     }
+
+    /// Whether an address is in use
+    pub fn is_allocated(&self, ip: Ipv4Addr) -> bool {
+        self.allocated.contains(&ip)
+    }
+
+    /// Whether an address is in the subnet
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & mask(self.prefix_len) == u32::from(self.network)
+    }
+
+    /// Length of the subnet's prefix
+    pub fn prefix_len(&self) -> u32 {
+        self.prefix_len
+    }
+}
+
+/// Netmask of a prefix length
+fn mask(prefix_len: u32) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -318,4 +398,18 @@ mod tests {
 
         allocator.release(ip1);
     }
+
+    #[test]
+    fn test_ip_allocator_subnet() {
+        let mut allocator = IpAllocator::new("10.1.2.0/24").unwrap();
+        assert_eq!(allocator.prefix_len(), 24);
+        assert!(allocator.contains(Ipv4Addr::new(10, 1, 2, 200)));
+        assert!(!allocator.contains(Ipv4Addr::new(10, 1, 3, 1)));
+
+        // Reserved addresses are skipped by allocation
+        allocator.reserve(Ipv4Addr::new(10, 1, 2, 2));
+        assert!(allocator.is_allocated(Ipv4Addr::new(10, 1, 2, 2)));
+        assert_eq!(allocator.allocate().unwrap(), Ipv4Addr::new(10, 1, 2, 3));
+        assert!(IpAllocator::new("10.1.2.0/33").is_err());
+    }
 }
@@ -0,0 +1,266 @@
+//! Bridge network firewall rules
+//!
+//! Each bridge network gets iptables rules that keep it apart from the
+//! other bridges: traffic between containers on the bridge is accepted
+//! unless inter-container communication is off, traffic to other bridges
+//! is dropped, and traffic leaving the host is masqueraded. Internal
+//! networks drop everything that crosses the bridge instead.
+//!
+//! Traffic between ports of one bridge is switched by the bridge without
+//! meeting iptables unless `br_netfilter` passes it on, so it is turned on
+//! for networks whose containers can't reach each other.
+
+use super::config::NetworkConfig;
+use super::veth::{bridge_name, run, DEFAULT_BRIDGE};
+use crate::error::{Result, RuneError};
+use std::path::Path;
+
+/// Network option that turns inter-container communication off for one
+/// network
+pub const ICC_OPTION: &str = "com.docker.network.bridge.enable_icc";
+
+/// Prefix of the bridges of networks other than the default one
+const BRIDGE_PREFIX: &str = "br-";
+
+/// Sysctl passing bridged traffic through iptables, there once
+/// `br_netfilter` is loaded
+const BRIDGE_NF_CALL_IPTABLES: &str = "/proc/sys/net/bridge/bridge-nf-call-iptables";
+
+/// An iptables rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// Table the rule is in
+    pub table: &'static str,
+    /// Chain the rule is in
+    pub chain: &'static str,
+    /// Match and target of the rule
+    pub spec: Vec<String>,
+}
+
+impl Rule {
+    fn new(table: &'static str, chain: &'static str, spec: &[&str]) -> Self {
+        Self {
+            table,
+            chain,
+            spec: spec.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// iptables command doing `action` (`-C`, `-I` or `-D`) with the rule
+    fn command(&self, action: &str) -> Vec<String> {
+        ["iptables", "-t", self.table, action, self.chain]
+            .iter()
+            .map(|arg| arg.to_string())
+            .chain(self.spec.iter().cloned())
+            .collect()
+    }
+}
+
+/// Whether containers on a network can reach each other, given whether the
+/// daemon allows it
+pub fn icc(network: &NetworkConfig, daemon_icc: bool) -> bool {
+    daemon_icc
+        && network
+            .options
+            .get(ICC_OPTION)
+            .is_none_or(|value| value != "false")
+}
+
+/// Rules of a bridge network, in the order they are matched
+pub fn rules(network: &NetworkConfig, daemon_icc: bool) -> Vec<Rule> {
+    let bridge = bridge_name(network);
+    let bridge = bridge.as_str();
+    let icc = if icc(network, daemon_icc) {
+        "ACCEPT"
+    } else {
+        "DROP"
+    };
+    let mut rules = vec![Rule::new(
+        "filter",
+        "FORWARD",
+        &["-i", bridge, "-o", bridge, "-j", icc],
+    )];
+    if network.internal {
+        rules.push(Rule::new(
+            "filter",
+            "FORWARD",
+            &["-i", bridge, "!", "-o", bridge, "-j", "DROP"],
+        ));
+        rules.push(Rule::new(
+            "filter",
+            "FORWARD",
+            &["-o", bridge, "!", "-i", bridge, "-j", "DROP"],
+        ));
+        return rules;
+    }
+
+    // The default bridge's own traffic is left to its icc rule
+    if bridge != DEFAULT_BRIDGE {
+        rules.push(Rule::new(
+            "filter",
+            "FORWARD",
+            &["-i", bridge, "-o", DEFAULT_BRIDGE, "-j", "DROP"],
+        ));
+    }
+    let others = format!("{}+", BRIDGE_PREFIX);
+    rules.extend([
+        Rule::new(
+            "filter",
+            "FORWARD",
+            &["-i", bridge, "-o", &others, "-j", "DROP"],
+        ),
+        Rule::new(
+            "filter",
+            "FORWARD",
+            &["-i", bridge, "!", "-o", bridge, "-j", "ACCEPT"],
+        ),
+        Rule::new(
+            "filter",
+            "FORWARD",
+            &[
+                "-o",
+                bridge,
+                "-m",
+                "conntrack",
+                "--ctstate",
+                "RELATED,ESTABLISHED",
+                "-j",
+                "ACCEPT",
+            ],
+        ),
+    ]);
+    for pool in &network.ipam.config {
+        rules.push(Rule::new(
+            "nat",
+            "POSTROUTING",
+            &["-s", &pool.subnet, "!", "-o", bridge, "-j", "MASQUERADE"],
+        ));
+    }
+    rules
+}
+
+/// Pass traffic between ports of a bridge through iptables, loading
+/// `br_netfilter` if need be, so that rules dropping it take effect
+pub fn filter_bridged() -> Result<()> {
+    let sysctl = Path::new(BRIDGE_NF_CALL_IPTABLES);
+    if !sysctl.exists() {
+        run(&["modprobe".to_string(), "br_netfilter".to_string()]).map_err(|e| {
+            RuneError::Network(format!(
+                "Inter-container communication can't be turned off without br_netfilter: {}",
+                e
+            ))
+        })?;
+    }
+    std::fs::write(sysctl, "1").map_err(|e| {
+        RuneError::Network(format!(
+            "Failed to pass bridged traffic through iptables: {}",
+            e
+        ))
+    })
+}
+
+/// Add rules that aren't there yet, ahead of the chains' other rules
+pub fn apply(rules: &[Rule]) -> Result<()> {
+    // Each rule goes first, so going backwards keeps them in order
+    for rule in rules.iter().rev() {
+        if run(&rule.command("-C")).is_ok() {
+            continue;
+        }
+        let mut command = rule.command("-I");
+        command.insert(5, "1".to_string());
+        run(&command)?;
+    }
+    Ok(())
+}
+
+/// Delete rules, returning the last failure once all are tried
+pub fn remove(rules: &[Rule]) -> Result<()> {
+    let mut result = Ok(());
+    for rule in rules {
+        if let Err(e) = run(&rule.command("-D")) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(rules: &[Rule]) -> Vec<String> {
+        rules
+            .iter()
+            .map(|rule| rule.command("-A").join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let network = NetworkConfig::new("backend").subnet("10.20.0.0/24");
+        let bridge = bridge_name(&network);
+        assert_eq!(
+            commands(&rules(&network, true)),
+            [
+                format!("iptables -t filter -A FORWARD -i {0} -o {0} -j ACCEPT", bridge),
+                format!("iptables -t filter -A FORWARD -i {} -o rune0 -j DROP", bridge),
+                format!("iptables -t filter -A FORWARD -i {} -o br-+ -j DROP", bridge),
+                format!("iptables -t filter -A FORWARD -i {0} ! -o {0} -j ACCEPT", bridge),
+                format!(
+                    "iptables -t filter -A FORWARD -o {} -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
+                    bridge
+                ),
+                format!(
+                    "iptables -t nat -A POSTROUTING -s 10.20.0.0/24 ! -o {} -j MASQUERADE",
+                    bridge
+                ),
+            ]
+        );
+
+        // Internal networks don't reach past their bridge, nor get
+        // masqueraded
+        let internal = network.clone().internal(true);
+        assert_eq!(
+            commands(&rules(&internal, true)),
+            [
+                format!(
+                    "iptables -t filter -A FORWARD -i {0} -o {0} -j ACCEPT",
+                    bridge
+                ),
+                format!(
+                    "iptables -t filter -A FORWARD -i {0} ! -o {0} -j DROP",
+                    bridge
+                ),
+                format!(
+                    "iptables -t filter -A FORWARD -o {0} ! -i {0} -j DROP",
+                    bridge
+                ),
+            ]
+        );
+
+        // Inter-container communication is off for the daemon, or for one
+        // network
+        let drop = format!(
+            "iptables -t filter -A FORWARD -i {0} -o {0} -j DROP",
+            bridge
+        );
+        assert_eq!(commands(&rules(&network, false))[0], drop);
+        let mut isolated = network.clone();
+        isolated
+            .options
+            .insert(ICC_OPTION.to_string(), "false".to_string());
+        assert!(!icc(&isolated, true));
+        assert_eq!(commands(&rules(&isolated, true))[0], drop);
+
+        // Nothing drops the default bridge's own traffic but its icc rule
+        let default = NetworkConfig::new("bridge").subnet("172.17.0.0/16");
+        assert_eq!(
+            commands(&rules(&default, true))[..3],
+            [
+                "iptables -t filter -A FORWARD -i rune0 -o rune0 -j ACCEPT",
+                "iptables -t filter -A FORWARD -i rune0 -o br-+ -j DROP",
+                "iptables -t filter -A FORWARD -i rune0 ! -o rune0 -j ACCEPT",
+            ]
+        );
+    }
+}
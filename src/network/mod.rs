@@ -4,6 +4,9 @@
 
 pub mod bridge;
 pub mod config;
+pub mod firewall;
+pub mod veth;
 
-pub use bridge::BridgeNetwork;
-pub use config::{NetworkConfig, NetworkDriver};
+pub use bridge::{BridgeNetwork, NETWORK_FILTERS};
+pub use config::{EndpointConfig, NetworkConfig, NetworkDriver};
+pub use veth::{Interfaces, Veth};
@@ -0,0 +1,266 @@
+//! Container network interfaces
+//!
+//! A container is on a bridge network through a veth pair: one end stays
+//! on the host, on the network's bridge, and the other is in the
+//! container's network namespace with the endpoint's MAC and IP address.
+//! Interfaces are set up with iproute2's `ip`, entering the container's
+//! namespace with `nsenter`, so they can be added to and removed from
+//! running containers. A bridge is made, with its firewall rules, when
+//! the first container joins its network.
+
+use super::config::{NetworkConfig, NetworkContainer};
+use super::firewall;
+use crate::error::{Result, RuneError};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Bridge of the default network
+pub(super) const DEFAULT_BRIDGE: &str = "rune0";
+
+/// Sets up the interfaces of endpoints in running containers
+pub trait Interfaces: Send + Sync {
+    /// Add an endpoint's interface, named `interface`, to the network
+    /// namespace of process `pid`
+    fn attach(
+        &self,
+        network: &NetworkConfig,
+        endpoint: &NetworkContainer,
+        pid: u32,
+        interface: &str,
+    ) -> Result<()>;
+
+    /// Remove an endpoint's interface
+    fn detach(&self, network: &NetworkConfig, endpoint: &NetworkContainer) -> Result<()>;
+
+    /// Tear down what a removed network was set up with
+    fn remove(&self, network: &NetworkConfig) -> Result<()>;
+}
+
+/// Interfaces made of veth pairs on Linux bridges
+#[derive(Debug, Clone, Copy)]
+pub struct Veth {
+    /// Whether containers on the same network can reach each other
+    icc: bool,
+}
+
+impl Default for Veth {
+    fn default() -> Self {
+        Self { icc: true }
+    }
+}
+
+impl Veth {
+    /// Allow or block traffic between containers on the same network, for
+    /// networks that don't turn it off themselves
+    pub fn with_icc(mut self, icc: bool) -> Self {
+        self.icc = icc;
+        self
+    }
+}
+
+impl Interfaces for Veth {
+    fn attach(
+        &self,
+        network: &NetworkConfig,
+        endpoint: &NetworkContainer,
+        pid: u32,
+        interface: &str,
+    ) -> Result<()> {
+        let bridge = bridge_name(network);
+        if !Path::new("/sys/class/net").join(&bridge).exists() {
+            for command in bridge_commands(network) {
+                run(&command)?;
+            }
+            // Masqueraded traffic is routed out of the host
+            if !network.internal {
+                std::fs::write("/proc/sys/net/ipv4/ip_forward", "1").map_err(|e| {
+                    RuneError::Network(format!("Failed to enable IP forwarding: {}", e))
+                })?;
+            }
+        }
+        if !firewall::icc(network, self.icc) {
+            firewall::filter_bridged()?;
+        }
+        firewall::apply(&firewall::rules(network, self.icc))?;
+        let commands = attach_commands(network, endpoint, pid, interface);
+        for (index, command) in commands.iter().enumerate() {
+            if let Err(e) = run(command) {
+                // Deleting the host end deletes the pair, once made
+                if index > 0 {
+                    let _ = run(&detach_command(endpoint));
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn detach(&self, _network: &NetworkConfig, endpoint: &NetworkContainer) -> Result<()> {
+        run(&detach_command(endpoint))
+    }
+
+    fn remove(&self, network: &NetworkConfig) -> Result<()> {
+        let bridge = bridge_name(network);
+        if !Path::new("/sys/class/net").join(&bridge).exists() {
+            return Ok(());
+        }
+        firewall::remove(&firewall::rules(network, self.icc))?;
+        run(&ip(&["link", "del", &bridge]))
+    }
+}
+
+/// Name of a network's bridge
+pub fn bridge_name(network: &NetworkConfig) -> String {
+    match network.name.as_str() {
+        "bridge" => DEFAULT_BRIDGE.to_string(),
+        _ => format!("br-{}", &network.id[..network.id.len().min(12)]),
+    }
+}
+
+/// Name of the host end of an endpoint's veth pair
+fn host_name(endpoint: &NetworkContainer) -> String {
+    format!(
+        "veth{}",
+        &endpoint.endpoint_id[..endpoint.endpoint_id.len().min(7)]
+    )
+}
+
+/// Gateway of a network with its prefix length, the bridge's address
+fn gateway(network: &NetworkConfig) -> Option<String> {
+    let pool = network.ipam.config.first()?;
+    let (subnet, prefix_len) = pool.subnet.split_once('/')?;
+    let gateway = match &pool.gateway {
+        Some(gateway) => gateway.clone(),
+        None => {
+            let subnet: std::net::Ipv4Addr = subnet.parse().ok()?;
+            std::net::Ipv4Addr::from(u32::from(subnet) + 1).to_string()
+        }
+    };
+    Some(format!("{}/{}", gateway, prefix_len))
+}
+
+/// Commands making a network's bridge
+fn bridge_commands(network: &NetworkConfig) -> Vec<Vec<String>> {
+    let bridge = bridge_name(network);
+    let mut commands = vec![ip(&["link", "add", "name", &bridge, "type", "bridge"])];
+    if let Some(gateway) = gateway(network) {
+        commands.push(ip(&["addr", "add", &gateway, "dev", &bridge]));
+    }
+    commands.push(ip(&["link", "set", &bridge, "up"]));
+    commands
+}
+
+/// Commands adding an endpoint's veth pair, with its container end in the
+/// network namespace of `pid`
+fn attach_commands(
+    network: &NetworkConfig,
+    endpoint: &NetworkContainer,
+    pid: u32,
+    interface: &str,
+) -> Vec<Vec<String>> {
+    let host = host_name(endpoint);
+    let pid = pid.to_string();
+    let in_container = |args: &[&str]| -> Vec<String> {
+        ["nsenter", "--target", &pid, "--net", "ip"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect()
+    };
+    let mut commands = vec![
+        ip(&[
+            "link",
+            "add",
+            &host,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            interface,
+            "address",
+            &endpoint.mac_address,
+            "netns",
+            &pid,
+        ]),
+        ip(&["link", "set", &host, "master", &bridge_name(network)]),
+        ip(&["link", "set", &host, "up"]),
+    ];
+    if let Some(address) = &endpoint.ipv4_address {
+        commands.push(in_container(&["addr", "add", address, "dev", interface]));
+    }
+    commands.push(in_container(&["link", "set", interface, "up"]));
+    commands
+}
+
+fn detach_command(endpoint: &NetworkContainer) -> Vec<String> {
+    ip(&["link", "del", &host_name(endpoint)])
+}
+
+fn ip(args: &[&str]) -> Vec<String> {
+    std::iter::once("ip")
+        .chain(args.iter().copied())
+        .map(str::to_string)
+        .collect()
+}
+
+pub(super) fn run(command: &[String]) -> Result<()> {
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| RuneError::Network(format!("Failed to run {}: {}", command[0], e)))?;
+    if !output.status.success() {
+        return Err(RuneError::Network(format!(
+            "{} failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_commands() {
+        let network = NetworkConfig::new("backend").subnet("10.20.0.0/24");
+        let endpoint = NetworkContainer {
+            container_id: "abc".to_string(),
+            name: "db".to_string(),
+            endpoint_id: "0123456789ab".to_string(),
+            mac_address: "02:42:0a:14:00:02".to_string(),
+            ipv4_address: Some("10.20.0.2/24".to_string()),
+            ipv6_address: None,
+            aliases: Vec::new(),
+            interface: String::new(),
+        };
+        let bridge = format!("br-{}", network.id);
+        assert_eq!(bridge_name(&network), bridge);
+        assert_eq!(
+            bridge_commands(&network)[1].join(" "),
+            format!("ip addr add 10.20.0.1/24 dev {}", bridge)
+        );
+
+        let commands: Vec<String> = attach_commands(&network, &endpoint, 42, "eth1")
+            .iter()
+            .map(|command| command.join(" "))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                "ip link add veth0123456 type veth peer name eth1 address 02:42:0a:14:00:02 netns 42"
+                    .to_string(),
+                format!("ip link set veth0123456 master {}", bridge),
+                "ip link set veth0123456 up".to_string(),
+                "nsenter --target 42 --net ip addr add 10.20.0.2/24 dev eth1".to_string(),
+                "nsenter --target 42 --net ip link set eth1 up".to_string(),
+            ]
+        );
+        assert_eq!(
+            detach_command(&endpoint).join(" "),
+            "ip link del veth0123456"
+        );
+    }
+}
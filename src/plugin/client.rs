@@ -0,0 +1,302 @@
+//! Plugin protocol client
+//!
+//! Plugins answer HTTP POSTs on a Unix socket, one connection per call, with
+//! JSON bodies. Errors come back in an `Err` field, with either an error
+//! status or, from some plugins, a success one.
+
+use crate::error::{Result, RuneError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Media type of plugin protocol version 1
+pub const PLUGIN_MEDIA_TYPE: &str = "application/vnd.docker.plugins.v1.2+json";
+
+/// How long to wait for a plugin to answer a call
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Answer to `/Plugin.Activate`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Activation {
+    #[serde(default)]
+    implements: Vec<String>,
+}
+
+/// Answer carrying nothing but a possible error
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrResponse {
+    #[serde(default)]
+    err: String,
+}
+
+/// Client of a plugin listening on a Unix socket
+#[derive(Debug, Clone)]
+pub struct PluginClient {
+    /// Plugin name, for errors
+    name: String,
+    socket: PathBuf,
+    timeout: Duration,
+}
+
+impl PluginClient {
+    /// Create a client of the plugin listening on `socket`
+    pub fn new(name: &str, socket: &Path) -> Self {
+        Self {
+            name: name.to_string(),
+            socket: socket.to_path_buf(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Name of the plugin
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Handshake with the plugin, returning the subsystems it implements,
+    /// like `VolumeDriver`
+    pub fn activate(&self) -> Result<Vec<String>> {
+        let activation: Activation = self.call("Plugin.Activate", &serde_json::json!({}))?;
+        Ok(activation.implements)
+    }
+
+    /// Call a method, decoding its JSON answer
+    pub fn call<T: Serialize, R: DeserializeOwned>(&self, method: &str, request: &T) -> Result<R> {
+        let body = self.call_raw(method, request)?;
+        let text = String::from_utf8_lossy(&body);
+        let text = if text.trim().is_empty() {
+            "{}"
+        } else {
+            text.as_ref()
+        };
+        if let Ok(ErrResponse { err }) = serde_json::from_str(text) {
+            if !err.is_empty() {
+                return Err(self.error(method, &err));
+            }
+        }
+        serde_json::from_str(text)
+            .map_err(|e| self.error(method, &format!("invalid answer: {}", e)))
+    }
+
+    /// Call a method whose answer carries nothing but a possible error
+    pub fn call_unit<T: Serialize>(&self, method: &str, request: &T) -> Result<()> {
+        self.call::<T, serde_json::Value>(method, request)
+            .map(|_| ())
+    }
+
+    /// Call a method, returning its raw answer, such as a stream of log
+    /// entries
+    pub fn call_raw<T: Serialize>(&self, method: &str, request: &T) -> Result<Vec<u8>> {
+        let body = serde_json::to_vec(request)?;
+        let mut stream = UnixStream::connect(&self.socket).map_err(|e| {
+            RuneError::Plugin(format!(
+                "{}: failed to connect to {}: {}",
+                self.name,
+                self.socket.display(),
+                e
+            ))
+        })?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let head = format!(
+            "POST /{} HTTP/1.1\r\n\
+             Host: plugin\r\n\
+             Accept: {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n",
+            method,
+            PLUGIN_MEDIA_TYPE,
+            PLUGIN_MEDIA_TYPE,
+            body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let (status, body) = read_response(&mut BufReader::new(stream))
+            .map_err(|e| self.error(method, &e.to_string()))?;
+        if !(200..300).contains(&status) {
+            let message = serde_json::from_slice::<ErrResponse>(&body)
+                .ok()
+                .map(|response| response.err)
+                .filter(|err| !err.is_empty())
+                .unwrap_or_else(|| format!("plugin answered {}", status));
+            return Err(self.error(method, &message));
+        }
+        Ok(body)
+    }
+
+    fn error(&self, method: &str, message: &str) -> RuneError {
+        RuneError::Plugin(format!("{}: {}: {}", self.name, method, message))
+    }
+}
+
+/// Read a response's status and body, plain or chunked
+fn read_response(reader: &mut impl BufRead) -> std::io::Result<(u16, Vec<u8>)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size)?;
+            let size = size.split(';').next().unwrap_or("").trim();
+            let size =
+                usize::from_str_radix(size, 16).map_err(|_| invalid("malformed chunk size"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            let mut crlf = [0u8; 2];
+            reader.read_exact(&mut crlf)?;
+        }
+    } else if let Some(len) = content_length {
+        body.resize(len, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    Ok((status, body))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    /// Calls a fake plugin received, as `(method, body)`
+    pub(crate) type Calls = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    /// Serve plugin calls on a socket in a directory, answering each
+    /// method with the given body, or `{}`
+    pub(crate) fn fake_plugin(dir: &TempDir, answers: &[(&str, &str)]) -> (PathBuf, Calls) {
+        let socket = dir.path().join("plugin.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let answers: Vec<(String, String)> = answers
+            .iter()
+            .map(|(method, body)| (method.to_string(), body.to_string()))
+            .collect();
+        let calls: Calls = Arc::default();
+        let received = calls.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let method = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap()
+                    .trim_start_matches('/')
+                    .to_string();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(len) = header.strip_prefix("Content-Length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+                received.lock().unwrap().push((
+                    method.clone(),
+                    serde_json::from_slice(&body).unwrap_or_default(),
+                ));
+
+                let answer = answers
+                    .iter()
+                    .find(|(m, _)| *m == method)
+                    .map(|(_, body)| body.as_str())
+                    .unwrap_or("{}");
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                    PLUGIN_MEDIA_TYPE,
+                    answer.len(),
+                    answer
+                );
+                let _ = reader.get_mut().write_all(response.as_bytes());
+            }
+        });
+        (socket, calls)
+    }
+
+    #[test]
+    fn test_plugin_calls() {
+        let dir = TempDir::new().unwrap();
+        let (socket, calls) = fake_plugin(
+            &dir,
+            &[
+                ("Plugin.Activate", r#"{"Implements": ["VolumeDriver"]}"#),
+                ("VolumeDriver.Remove", r#"{"Err": "volume is busy"}"#),
+            ],
+        );
+        let client = PluginClient::new("sshfs", &socket);
+        assert_eq!(client.activate().unwrap(), ["VolumeDriver"]);
+
+        let error = client
+            .call_unit("VolumeDriver.Remove", &serde_json::json!({"Name": "data"}))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Plugin error: sshfs: VolumeDriver.Remove: volume is busy"
+        );
+        assert_eq!(calls.lock().unwrap()[1].1["Name"], "data");
+
+        let missing = PluginClient::new("gone", &dir.path().join("gone.sock"));
+        assert!(missing.activate().is_err());
+    }
+
+    #[test]
+    fn test_read_response() {
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       4\r\n{\"Er\r\n6\r\nr\": \"\"\r\n1\r\n}\r\n0\r\n\r\n";
+        let (status, body) = read_response(&mut chunked.as_bytes()).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, br#"{"Err": ""}"#);
+
+        let plain = "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(read_response(&mut plain.as_bytes()).unwrap().0, 500);
+        assert!(read_response(&mut "garbage".as_bytes()).is_err());
+    }
+}
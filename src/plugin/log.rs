@@ -0,0 +1,338 @@
+//! Log driver plugins
+//!
+//! The daemon hands a log plugin a FIFO per container and writes the
+//! container's output into it as `LogEntry` protocol buffer messages, each
+//! preceded by its length as a big-endian `u32`. Plugins that can read
+//! logs back answer `ReadLogs` with a stream in the same format.
+
+use super::client::PluginClient;
+use crate::container::{ContainerConfig, LogConfig, LogDriver};
+use crate::error::{Result, RuneError};
+use crate::runtime::syscall;
+use crate::swarm::logs::{LogLine, LogRequest};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Subsystem log driver plugins implement
+pub const LOG_DRIVER: &str = "LogDriver";
+
+/// Answer to `/LogDriver.Capabilities`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CapabilitiesResponse {
+    #[serde(default)]
+    cap: Capabilities,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Capabilities {
+    #[serde(default)]
+    read_logs: bool,
+}
+
+/// Log driver implemented by a plugin
+#[derive(Debug, Clone)]
+pub struct LogPlugin {
+    client: PluginClient,
+}
+
+impl LogPlugin {
+    /// Log driver speaking to a plugin
+    pub fn new(client: PluginClient) -> Self {
+        Self { client }
+    }
+
+    /// Driver name
+    pub fn name(&self) -> &str {
+        self.client.name()
+    }
+
+    /// Start logging a container whose files live in `dir`
+    pub fn open(
+        &self,
+        container: &ContainerConfig,
+        config: &LogConfig,
+        dir: &Path,
+    ) -> Result<PluginLogDriver> {
+        std::fs::create_dir_all(dir)?;
+        let fifo = dir.join("log-plugin.fifo");
+        let _ = std::fs::remove_file(&fifo);
+        syscall::mknod(&fifo.to_string_lossy(), libc::S_IFIFO | 0o600, 0, 0).map_err(|e| {
+            RuneError::Plugin(format!(
+                "{}: failed to create {}: {}",
+                self.name(),
+                fifo.display(),
+                e
+            ))
+        })?;
+        // Opened for reading too, so opening doesn't wait for the plugin
+        let writer = OpenOptions::new().read(true).write(true).open(&fifo)?;
+
+        let info = json!({
+            "Config": config.config,
+            "ContainerID": container.id,
+            "ContainerName": format!("/{}", container.name),
+            "ContainerEntrypoint": container.entrypoint.first().cloned().unwrap_or_default(),
+            "ContainerArgs": container.cmd,
+            "ContainerImageID": container.image,
+            "ContainerImageName": container.image,
+            "ContainerCreated": container.created_at.to_rfc3339(),
+            "ContainerEnv": container
+                .env
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>(),
+            "ContainerLabels": container.labels,
+            "LogPath": "",
+            "DaemonName": "rune",
+        });
+        if let Err(e) = self.client.call_unit(
+            "LogDriver.StartLogging",
+            &json!({"File": fifo, "Info": info}),
+        ) {
+            let _ = std::fs::remove_file(&fifo);
+            return Err(e);
+        }
+
+        let read_logs = self
+            .client
+            .call::<_, CapabilitiesResponse>("LogDriver.Capabilities", &json!({}))
+            .map(|response| response.cap.read_logs)
+            .unwrap_or(false);
+        Ok(PluginLogDriver {
+            client: self.client.clone(),
+            fifo,
+            writer: Mutex::new(writer),
+            info,
+            read_logs,
+        })
+    }
+}
+
+/// Sends a container's output to a log plugin, stopping when dropped
+pub struct PluginLogDriver {
+    client: PluginClient,
+    fifo: PathBuf,
+    writer: Mutex<File>,
+    info: Value,
+    read_logs: bool,
+}
+
+impl LogDriver for PluginLogDriver {
+    fn name(&self) -> &str {
+        self.client.name()
+    }
+
+    fn log(&self, line: &LogLine) -> Result<()> {
+        let entry = encode_entry(line);
+        let mut record = (entry.len() as u32).to_be_bytes().to_vec();
+        record.extend(entry);
+        self.writer
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire log plugin lock".to_string()))?
+            .write_all(&record)?;
+        Ok(())
+    }
+
+    fn read(&self, request: &LogRequest) -> Result<Vec<LogLine>> {
+        if !self.read_logs {
+            return Err(RuneError::Container(format!(
+                "The {} log driver does not support reading",
+                self.name()
+            )));
+        }
+        let stream = self.client.call_raw(
+            "LogDriver.ReadLogs",
+            &json!({
+                "Info": self.info,
+                "Config": {
+                    "ShowStdout": true,
+                    "ShowStderr": true,
+                    "Since": request.since.map(|since| since.to_rfc3339()),
+                    "Tail": request.tail.map(|tail| tail as i64).unwrap_or(-1),
+                    "Follow": false,
+                },
+            }),
+        )?;
+        Ok(request.select(decode_entries(&stream)?))
+    }
+}
+
+impl Drop for PluginLogDriver {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .client
+            .call_unit("LogDriver.StopLogging", &json!({"File": self.fifo}))
+        {
+            tracing::warn!("{}", e);
+        }
+        let _ = std::fs::remove_file(&self.fifo);
+    }
+}
+
+/// Encode a line as a `LogEntry` message
+fn encode_entry(line: &LogLine) -> Vec<u8> {
+    let mut entry = Vec::new();
+    put_bytes(&mut entry, 1, line.stream.as_bytes());
+    put_varint(&mut entry, 2 << 3);
+    put_varint(
+        &mut entry,
+        line.timestamp.timestamp_nanos_opt().unwrap_or(0) as u64,
+    );
+    put_bytes(&mut entry, 3, line.message.as_bytes());
+    entry
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buf, field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Decode a stream of length-prefixed `LogEntry` messages
+fn decode_entries(mut stream: &[u8]) -> Result<Vec<LogLine>> {
+    let truncated = || RuneError::Plugin("Truncated log entry".to_string());
+    let mut lines = Vec::new();
+    while !stream.is_empty() {
+        let len = stream
+            .get(..4)
+            .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .ok_or_else(truncated)?;
+        let entry = stream.get(4..4 + len).ok_or_else(truncated)?;
+        lines.push(decode_entry(entry).ok_or_else(truncated)?);
+        stream = &stream[4 + len..];
+    }
+    Ok(lines)
+}
+
+/// Decode a `LogEntry` message, skipping fields other than the source,
+/// time and line
+fn decode_entry(mut entry: &[u8]) -> Option<LogLine> {
+    let mut line = LogLine {
+        timestamp: DateTime::from_timestamp_nanos(0),
+        stream: String::new(),
+        message: String::new(),
+    };
+    while !entry.is_empty() {
+        let key = get_varint(&mut entry)?;
+        match key & 7 {
+            0 => {
+                let value = get_varint(&mut entry)?;
+                if key >> 3 == 2 {
+                    line.timestamp = DateTime::from_timestamp_nanos(value as i64);
+                }
+            }
+            1 => entry = entry.get(8..)?,
+            2 => {
+                let len = get_varint(&mut entry)? as usize;
+                let bytes = entry.get(..len)?;
+                match key >> 3 {
+                    1 => line.stream = String::from_utf8_lossy(bytes).into_owned(),
+                    3 => line.message = String::from_utf8_lossy(bytes).into_owned(),
+                    _ => {}
+                }
+                entry = &entry[len..];
+            }
+            5 => entry = entry.get(4..)?,
+            _ => return None,
+        }
+    }
+    Some(line)
+}
+
+fn get_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::tests::fake_plugin;
+    use super::*;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn line(message: &str) -> LogLine {
+        LogLine {
+            timestamp: DateTime::from_timestamp(1700000000, 5).unwrap(),
+            stream: "stdout".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_log_entries() {
+        let entry = encode_entry(&line("hi"));
+        // Source, then the time as a varint, then the line
+        assert_eq!(&entry[..8], b"\x0a\x06stdout");
+        assert_eq!(&entry[entry.len() - 4..], b"\x1a\x02hi");
+
+        let mut stream = Vec::new();
+        for message in ["one", "two"] {
+            let entry = encode_entry(&line(message));
+            stream.extend((entry.len() as u32).to_be_bytes());
+            stream.extend(entry);
+        }
+        assert_eq!(decode_entries(&stream).unwrap(), [line("one"), line("two")]);
+        assert!(decode_entries(&stream[..stream.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_plugin_log_driver() {
+        let dir = TempDir::new().unwrap();
+        let (socket, calls) = fake_plugin(&dir, &[]);
+        let plugin = LogPlugin::new(PluginClient::new("fluent", &socket));
+        let container = ContainerConfig::new("web", "nginx");
+        let config = LogConfig::new("fluent");
+
+        let driver = plugin
+            .open(&container, &config, &dir.path().join(&container.id))
+            .unwrap();
+        driver.log(&line("ready")).unwrap();
+        assert!(driver.read(&LogRequest::default()).is_err());
+
+        // Read back what the plugin would
+        let fifo = driver.fifo.clone();
+        let mut reader = File::open(&fifo).unwrap();
+        let mut record = vec![0u8; encode_entry(&line("ready")).len() + 4];
+        reader.read_exact(&mut record).unwrap();
+        assert_eq!(decode_entries(&record).unwrap(), [line("ready")]);
+
+        drop(driver);
+        assert!(!fifo.exists());
+        let calls = calls.lock().unwrap();
+        let methods: Vec<&str> = calls.iter().map(|(method, _)| method.as_str()).collect();
+        assert_eq!(
+            methods,
+            [
+                "LogDriver.StartLogging",
+                "LogDriver.Capabilities",
+                "LogDriver.StopLogging"
+            ]
+        );
+        assert_eq!(calls[0].1["Info"]["ContainerName"], "/web");
+        assert_eq!(calls[0].1["File"], calls[2].1["File"]);
+    }
+}
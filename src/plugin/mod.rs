@@ -0,0 +1,297 @@
+//! Plugins
+//!
+//! Third-party volume, network and log drivers run as plugins: processes
+//! speaking Docker's plugin protocol, JSON over HTTP on a Unix socket. A
+//! plugin is installed under a name with `rune plugin install`, which
+//! enables it with a handshake recording the driver subsystems it
+//! implements. The volume, network and container managers look drivers
+//! they don't know up among the enabled plugins by that name.
+//!
+//! Installed plugins are kept in `plugins.json` in the plugin directory,
+//! read on every lookup so the CLI and the daemon see the same plugins.
+
+pub mod client;
+pub mod log;
+pub mod network;
+pub mod volume;
+
+pub use client::PluginClient;
+pub use log::{LogPlugin, PluginLogDriver, LOG_DRIVER};
+pub use network::{NetworkPlugin, NETWORK_DRIVER};
+pub use volume::{VolumePlugin, VOLUME_DRIVER};
+
+use crate::error::{ErrorKind, Result, RuneError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// An installed plugin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plugin {
+    /// Plugin ID
+    pub id: String,
+    /// Name drivers are looked up by
+    pub name: String,
+    /// Socket the plugin listens on
+    pub socket: PathBuf,
+    /// Whether drivers are looked up in the plugin
+    pub enabled: bool,
+    /// Subsystems the plugin implements, like `VolumeDriver`, as of its
+    /// last handshake
+    #[serde(default)]
+    pub implements: Vec<String>,
+    /// Time the plugin was installed
+    pub created: DateTime<Utc>,
+}
+
+impl Plugin {
+    /// Whether the plugin implements a subsystem
+    pub fn implements(&self, kind: &str) -> bool {
+        self.implements.iter().any(|k| k == kind)
+    }
+
+    /// Client of the plugin
+    pub fn client(&self) -> PluginClient {
+        PluginClient::new(&self.name, &self.socket)
+    }
+}
+
+/// Installed plugins
+pub struct PluginManager {
+    /// Path of `plugins.json`
+    path: PathBuf,
+    /// Serializes changes to the plugin file
+    lock: Mutex<()>,
+}
+
+impl PluginManager {
+    /// Open the plugins installed in a directory
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            path: dir.as_ref().join("plugins.json"),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// All installed plugins
+    pub fn list(&self) -> Result<Vec<Plugin>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(&self.path)?)?)
+    }
+
+    /// Look up a plugin by name or ID prefix
+    pub fn get(&self, name: &str) -> Result<Plugin> {
+        let plugins = self.list()?;
+        plugins
+            .iter()
+            .find(|p| p.name == name)
+            .or_else(|| plugins.iter().find(|p| p.id.starts_with(name)))
+            .cloned()
+            .ok_or_else(|| RuneError::PluginNotFound(name.to_string()))
+    }
+
+    /// Install the plugin listening on a socket under a name, enabling it
+    /// unless told not to
+    pub fn install(&self, name: &str, socket: &Path, enable: bool) -> Result<Plugin> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid plugin name: {:?}",
+                name
+            )));
+        }
+        let plugin = Plugin {
+            id: format!(
+                "{:016x}{:016x}",
+                rand::random::<u64>(),
+                rand::random::<u64>()
+            ),
+            name: name.to_string(),
+            socket: socket.to_path_buf(),
+            enabled: false,
+            implements: Vec::new(),
+            created: Utc::now(),
+        };
+        self.update(|plugins| {
+            if plugins.iter().any(|p| p.name == name) {
+                return Err(RuneError::new(
+                    ErrorKind::Conflict,
+                    format!("plugin {} already exists", name),
+                ));
+            }
+            plugins.push(plugin.clone());
+            Ok(())
+        })?;
+        if enable {
+            return self.enable(name);
+        }
+        Ok(plugin)
+    }
+
+    /// Handshake with a plugin and look drivers up in it
+    pub fn enable(&self, name: &str) -> Result<Plugin> {
+        let plugin = self.get(name)?;
+        if plugin.enabled {
+            return Err(RuneError::new(
+                ErrorKind::Conflict,
+                format!("plugin {} is already enabled", plugin.name),
+            ));
+        }
+        let implements = plugin.client().activate()?;
+        if implements.is_empty() {
+            return Err(RuneError::Plugin(format!(
+                "{} implements no subsystem",
+                plugin.name
+            )));
+        }
+        self.set(&plugin.name, |p| {
+            p.enabled = true;
+            p.implements = implements.clone();
+        })
+    }
+
+    /// Stop looking drivers up in a plugin
+    pub fn disable(&self, name: &str) -> Result<Plugin> {
+        let plugin = self.get(name)?;
+        if !plugin.enabled {
+            return Err(RuneError::new(
+                ErrorKind::Conflict,
+                format!("plugin {} is already disabled", plugin.name),
+            ));
+        }
+        self.set(&plugin.name, |p| p.enabled = false)
+    }
+
+    /// Remove a plugin, which must be disabled unless forced
+    pub fn remove(&self, name: &str, force: bool) -> Result<()> {
+        let plugin = self.get(name)?;
+        if plugin.enabled && !force {
+            return Err(RuneError::new(
+                ErrorKind::Conflict,
+                format!(
+                    "plugin {} is enabled, disable it first or force removal",
+                    plugin.name
+                ),
+            ));
+        }
+        self.update(|plugins| {
+            plugins.retain(|p| p.id != plugin.id);
+            Ok(())
+        })
+    }
+
+    /// Names of the enabled plugins implementing a subsystem
+    pub fn drivers(&self, kind: &str) -> Result<Vec<String>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|p| p.enabled && p.implements(kind))
+            .map(|p| p.name)
+            .collect())
+    }
+
+    /// Volume driver of an enabled plugin
+    pub fn volume_driver(&self, name: &str) -> Result<VolumePlugin> {
+        self.lookup(name, VOLUME_DRIVER).map(VolumePlugin::new)
+    }
+
+    /// Network driver of an enabled plugin
+    pub fn network_driver(&self, name: &str) -> Result<NetworkPlugin> {
+        self.lookup(name, NETWORK_DRIVER).map(NetworkPlugin::new)
+    }
+
+    /// Log driver of an enabled plugin
+    pub fn log_driver(&self, name: &str) -> Result<LogPlugin> {
+        self.lookup(name, LOG_DRIVER).map(LogPlugin::new)
+    }
+
+    fn lookup(&self, name: &str, kind: &str) -> Result<PluginClient> {
+        let plugin = self
+            .list()?
+            .into_iter()
+            .find(|p| p.name == name && p.enabled)
+            .ok_or_else(|| RuneError::PluginNotFound(name.to_string()))?;
+        if !plugin.implements(kind) {
+            return Err(RuneError::Plugin(format!(
+                "{} does not implement {}",
+                name, kind
+            )));
+        }
+        Ok(plugin.client())
+    }
+
+    /// Change a plugin, returning it changed
+    fn set(&self, name: &str, change: impl FnOnce(&mut Plugin)) -> Result<Plugin> {
+        let mut changed = None;
+        self.update(|plugins| {
+            let plugin = plugins
+                .iter_mut()
+                .find(|p| p.name == name)
+                .ok_or_else(|| RuneError::PluginNotFound(name.to_string()))?;
+            change(plugin);
+            changed = Some(plugin.clone());
+            Ok(())
+        })?;
+        changed.ok_or_else(|| RuneError::PluginNotFound(name.to_string()))
+    }
+
+    /// Change the plugin list and write it back
+    fn update(&self, change: impl FnOnce(&mut Vec<Plugin>) -> Result<()>) -> Result<()> {
+        let _lock = self
+            .lock
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire plugin lock".to_string()))?;
+        let mut plugins = self.list()?;
+        change(&mut plugins)?;
+        fs::write(&self.path, serde_json::to_string_pretty(&plugins)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client::tests::fake_plugin;
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_plugin_lifecycle() {
+        let dir = TempDir::new().unwrap();
+        let (socket, _) = fake_plugin(
+            &dir,
+            &[("Plugin.Activate", r#"{"Implements": ["VolumeDriver"]}"#)],
+        );
+        let manager = PluginManager::open(dir.path().join("plugins")).unwrap();
+
+        let plugin = manager.install("sshfs", &socket, true).unwrap();
+        assert!(plugin.enabled);
+        assert_eq!(plugin.implements, ["VolumeDriver"]);
+        assert!(manager.install("sshfs", &socket, true).is_err());
+        assert_eq!(manager.get(&plugin.id[..8]).unwrap().name, "sshfs");
+        assert_eq!(manager.drivers(VOLUME_DRIVER).unwrap(), ["sshfs"]);
+
+        assert_eq!(manager.volume_driver("sshfs").unwrap().name(), "sshfs");
+        assert!(manager.log_driver("sshfs").is_err());
+        assert!(matches!(
+            manager.volume_driver("nfs"),
+            Err(RuneError::PluginNotFound(_))
+        ));
+
+        // Another manager sees the same plugins
+        let other = PluginManager::open(dir.path().join("plugins")).unwrap();
+        assert!(other.remove("sshfs", false).is_err());
+        other.disable("sshfs").unwrap();
+        assert!(manager.volume_driver("sshfs").is_err());
+        manager.remove("sshfs", false).unwrap();
+        assert!(manager.list().unwrap().is_empty());
+
+        // A plugin that isn't listening can be installed, but not enabled
+        let gone = dir.path().join("gone.sock");
+        assert!(manager.install("gone", &gone, true).is_err());
+        assert!(!manager.get("gone").unwrap().enabled);
+    }
+}
@@ -0,0 +1,141 @@
+//! Network driver plugins
+
+use super::client::PluginClient;
+use crate::error::Result;
+use crate::network::NetworkConfig;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Subsystem network driver plugins implement
+pub const NETWORK_DRIVER: &str = "NetworkDriver";
+
+/// Interface a plugin gives a container joining a network
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JoinResponse {
+    /// Host interface to move into the container, and the prefix to name
+    /// it by there
+    #[serde(default)]
+    pub interface_name: Option<InterfaceName>,
+    /// Default gateway of the container
+    #[serde(default)]
+    pub gateway: String,
+}
+
+/// Interface to move into a container
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InterfaceName {
+    /// Name of the interface on the host
+    pub src_name: String,
+    /// Prefix of its name in the container, like `eth`
+    pub dst_prefix: String,
+}
+
+/// Network driver implemented by a plugin
+#[derive(Debug, Clone)]
+pub struct NetworkPlugin {
+    client: PluginClient,
+}
+
+impl NetworkPlugin {
+    /// Network driver speaking to a plugin
+    pub fn new(client: PluginClient) -> Self {
+        Self { client }
+    }
+
+    /// Driver name
+    pub fn name(&self) -> &str {
+        self.client.name()
+    }
+
+    /// Create a network with its driver options and address pools
+    pub fn create_network(&self, config: &NetworkConfig) -> Result<()> {
+        let ipv4_data: Vec<Value> = config
+            .ipam
+            .config
+            .iter()
+            .map(|pool| {
+                json!({
+                    "AddressSpace": "",
+                    "Pool": pool.subnet,
+                    "Gateway": pool.gateway.clone().unwrap_or_default(),
+                })
+            })
+            .collect();
+        self.client.call_unit(
+            "NetworkDriver.CreateNetwork",
+            &json!({
+                "NetworkID": config.id,
+                "Options": {"com.docker.network.generic": config.options},
+                "IPv4Data": ipv4_data,
+                "IPv6Data": [],
+            }),
+        )
+    }
+
+    /// Delete a network
+    pub fn delete_network(&self, network_id: &str) -> Result<()> {
+        self.client.call_unit(
+            "NetworkDriver.DeleteNetwork",
+            &json!({"NetworkID": network_id}),
+        )
+    }
+
+    /// Create the endpoint of a container in a network
+    pub fn create_endpoint(
+        &self,
+        network_id: &str,
+        endpoint_id: &str,
+        address: Option<&str>,
+        mac_address: &str,
+    ) -> Result<()> {
+        self.client.call_unit(
+            "NetworkDriver.CreateEndpoint",
+            &json!({
+                "NetworkID": network_id,
+                "EndpointID": endpoint_id,
+                "Interface": {
+                    "Address": address.unwrap_or(""),
+                    "AddressIPv6": "",
+                    "MacAddress": mac_address,
+                },
+                "Options": {},
+            }),
+        )
+    }
+
+    /// Delete the endpoint of a container
+    pub fn delete_endpoint(&self, network_id: &str, endpoint_id: &str) -> Result<()> {
+        self.client.call_unit(
+            "NetworkDriver.DeleteEndpoint",
+            &json!({"NetworkID": network_id, "EndpointID": endpoint_id}),
+        )
+    }
+
+    /// Join an endpoint to the network namespace at `sandbox_key`
+    pub fn join(
+        &self,
+        network_id: &str,
+        endpoint_id: &str,
+        sandbox_key: &str,
+    ) -> Result<JoinResponse> {
+        self.client.call(
+            "NetworkDriver.Join",
+            &json!({
+                "NetworkID": network_id,
+                "EndpointID": endpoint_id,
+                "SandboxKey": sandbox_key,
+                "Options": {},
+            }),
+        )
+    }
+
+    /// Take an endpoint out of its network namespace
+    pub fn leave(&self, network_id: &str, endpoint_id: &str) -> Result<()> {
+        self.client.call_unit(
+            "NetworkDriver.Leave",
+            &json!({"NetworkID": network_id, "EndpointID": endpoint_id}),
+        )
+    }
+}
@@ -0,0 +1,79 @@
+//! Volume driver plugins
+
+use super::client::PluginClient;
+use crate::error::Result;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Subsystem volume driver plugins implement
+pub const VOLUME_DRIVER: &str = "VolumeDriver";
+
+/// Answer carrying a mountpoint
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MountpointResponse {
+    #[serde(default)]
+    mountpoint: String,
+}
+
+/// Volume driver implemented by a plugin
+#[derive(Debug, Clone)]
+pub struct VolumePlugin {
+    client: PluginClient,
+}
+
+impl VolumePlugin {
+    /// Volume driver speaking to a plugin
+    pub fn new(client: PluginClient) -> Self {
+        Self { client }
+    }
+
+    /// Driver name
+    pub fn name(&self) -> &str {
+        self.client.name()
+    }
+
+    /// Create a volume with driver options
+    pub fn create(&self, name: &str, options: &HashMap<String, String>) -> Result<()> {
+        self.client.call_unit(
+            "VolumeDriver.Create",
+            &json!({"Name": name, "Opts": options}),
+        )
+    }
+
+    /// Remove a volume and its data
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.client
+            .call_unit("VolumeDriver.Remove", &json!({"Name": name}))
+    }
+
+    /// Mountpoint of a volume on the host, if it is mounted
+    pub fn path(&self, name: &str) -> Result<Option<PathBuf>> {
+        let response: MountpointResponse = self
+            .client
+            .call("VolumeDriver.Path", &json!({"Name": name}))?;
+        Ok(mountpoint(response))
+    }
+
+    /// Mount a volume for a container, returning where it is mounted
+    pub fn mount(&self, name: &str, id: &str) -> Result<PathBuf> {
+        let response: MountpointResponse = self
+            .client
+            .call("VolumeDriver.Mount", &json!({"Name": name, "ID": id}))?;
+        Ok(PathBuf::from(response.mountpoint))
+    }
+
+    /// Release a container's mount of a volume
+    pub fn unmount(&self, name: &str, id: &str) -> Result<()> {
+        self.client
+            .call_unit("VolumeDriver.Unmount", &json!({"Name": name, "ID": id}))
+    }
+}
+
+fn mountpoint(response: MountpointResponse) -> Option<PathBuf> {
+    Some(response.mountpoint)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
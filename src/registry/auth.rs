@@ -1,17 +1,39 @@
 //! Registry Authentication
 //!
-//! Implements authentication for the OCI registry.
+//! Implements authentication for the OCI registry. In basic mode clients
+//! send htpasswd credentials with every request; in token mode they trade
+//! them for a short-lived bearer token scoped to the repositories and
+//! actions they asked for and are allowed.
 
 use crate::error::{Result, RuneError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use ring::rand::SystemRandom;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::RwLock;
 
+/// How clients authenticate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// HTTP basic auth on every request
+    #[default]
+    Basic,
+    /// Bearer tokens issued by the registry's token endpoint
+    Token,
+}
+
 /// Authentication configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     /// Enable authentication
     pub enabled: bool,
+    /// Authentication mode
+    #[serde(default)]
+    pub mode: AuthMode,
     /// Realm for WWW-Authenticate header
     pub realm: String,
     /// Service name
@@ -26,6 +48,7 @@ impl Default for AuthConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            mode: AuthMode::default(),
             realm: "Rune Registry".to_string(),
             service: "rune-registry".to_string(),
             issuer: "rune".to_string(),
@@ -63,6 +86,27 @@ pub enum Action {
     Delete,
 }
 
+impl Action {
+    /// Name of the action in token scopes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Pull => "pull",
+            Action::Push => "push",
+            Action::Delete => "delete",
+        }
+    }
+
+    /// Parse an action from a token scope
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pull" => Some(Action::Pull),
+            "push" => Some(Action::Push),
+            "delete" => Some(Action::Delete),
+            _ => None,
+        }
+    }
+}
+
 /// Token claim for JWT
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenClaim {
@@ -85,7 +129,7 @@ pub struct TokenClaim {
 }
 
 /// Access claim
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AccessClaim {
     /// Resource type
     #[serde(rename = "type")]
@@ -96,6 +140,17 @@ pub struct AccessClaim {
     pub actions: Vec<String>,
 }
 
+impl TokenClaim {
+    /// Check whether the token grants an action on a resource
+    pub fn allows(&self, resource_type: &str, name: &str, action: &str) -> bool {
+        self.access.iter().any(|a| {
+            a.resource_type == resource_type
+                && a.name == name
+                && a.actions.iter().any(|act| act == action || act == "*")
+        })
+    }
+}
+
 /// Token response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenResponse {
@@ -115,25 +170,76 @@ pub struct RegistryAuth {
     config: AuthConfig,
     /// Users database
     users: RwLock<HashMap<String, User>>,
+    /// Key tokens are signed with; tokens do not survive a restart
+    signing_key: hmac::Key,
 }
 
 impl RegistryAuth {
     /// Create a new authentication handler
     pub fn new() -> Self {
-        Self {
-            config: AuthConfig::default(),
-            users: RwLock::new(HashMap::new()),
-        }
+        Self::with_config(AuthConfig::default())
     }
 
     /// Create with configuration
     pub fn with_config(config: AuthConfig) -> Self {
+        let signing_key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("system random number generator failed");
         Self {
             config,
             users: RwLock::new(HashMap::new()),
+            signing_key,
         }
     }
 
+    /// Load users from an htpasswd file
+    ///
+    /// Only bcrypt entries (`htpasswd -B`) are supported. Users from the
+    /// file may pull, push and delete in every repository.
+    pub fn load_htpasswd(&self, path: &Path) -> Result<usize> {
+        let content = std::fs::read_to_string(path)?;
+        let mut users = self
+            .users
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+
+        let mut loaded = 0;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (username, hash) = line.split_once(':').ok_or_else(|| {
+                RuneError::InvalidConfig(format!(
+                    "{}:{}: expected user:hash",
+                    path.display(),
+                    number + 1
+                ))
+            })?;
+            if !["$2y$", "$2a$", "$2b$"].iter().any(|p| hash.starts_with(p)) {
+                return Err(RuneError::InvalidConfig(format!(
+                    "{}:{}: only bcrypt passwords are supported (htpasswd -B)",
+                    path.display(),
+                    number + 1
+                )));
+            }
+
+            users.insert(
+                username.to_string(),
+                User {
+                    username: username.to_string(),
+                    password_hash: hash.to_string(),
+                    permissions: vec![Permission {
+                        repository: "*".to_string(),
+                        actions: vec![Action::Pull, Action::Push, Action::Delete],
+                    }],
+                },
+            );
+            loaded += 1;
+        }
+
+        Ok(loaded)
+    }
+
     /// Add a user
     pub fn add_user(
         &self,
@@ -205,14 +311,64 @@ impl RegistryAuth {
         Ok(false)
     }
 
+    /// Narrow requested access to what a user may do
+    ///
+    /// `None` stands for an anonymous client, which may only do what the
+    /// `anonymous` actions allow.
+    pub fn grant(
+        &self,
+        username: Option<&str>,
+        requested: Vec<AccessClaim>,
+        anonymous: &[Action],
+    ) -> Result<Vec<AccessClaim>> {
+        let mut granted = Vec::new();
+        for mut claim in requested {
+            let allowed = |action: Action| -> Result<bool> {
+                match username {
+                    Some(user) => self.is_allowed(user, &claim.name, action),
+                    None => Ok(anonymous.contains(&action)),
+                }
+            };
+            let mut actions = Vec::new();
+            match claim.resource_type.as_str() {
+                "repository" => {
+                    for action in claim.actions.iter().filter_map(|a| Action::parse(a)) {
+                        if allowed(action)? {
+                            actions.push(action.as_str().to_string());
+                        }
+                    }
+                }
+                // Listing every repository needs pull access to all of them
+                "registry" if claim.name == "catalog" => {
+                    let all = match username {
+                        Some(user) => self.is_allowed(user, "*", Action::Pull)?,
+                        None => anonymous.contains(&Action::Pull),
+                    };
+                    if all {
+                        actions.push("*".to_string());
+                    }
+                }
+                _ => {}
+            }
+            if !actions.is_empty() {
+                claim.actions = actions;
+                granted.push(claim);
+            }
+        }
+        Ok(granted)
+    }
+
     /// Generate a token for authenticated user
+    ///
+    /// The token carries only the requested actions the user is allowed.
     pub fn generate_token(&self, username: &str, scope: &str) -> Result<TokenResponse> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let access = self.grant(Some(username), parse_scope(scope), &[])?;
+        self.issue_token(username, access)
+    }
 
-        let access = parse_scope(scope);
+    /// Sign a token granting access
+    pub fn issue_token(&self, username: &str, access: Vec<AccessClaim>) -> Result<TokenResponse> {
+        let now = unix_now();
 
         let claim = TokenClaim {
             iss: self.config.issuer.clone(),
@@ -225,9 +381,12 @@ impl RegistryAuth {
             access,
         };
 
-        // In production, this would be a proper JWT
-        // For now, we'll use a simple base64-encoded JSON
-        let token = base64_encode(&serde_json::to_string(&claim)?);
+        // An HS256 JWT
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claim)?);
+        let signed = format!("{}.{}", header, payload);
+        let signature = hmac::sign(&self.signing_key, signed.as_bytes());
+        let token = format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()));
 
         Ok(TokenResponse {
             token: token.clone(),
@@ -237,16 +396,21 @@ impl RegistryAuth {
         })
     }
 
-    /// Verify a token
+    /// Verify a token's signature, audience and lifetime
     pub fn verify_token(&self, token: &str) -> Result<TokenClaim> {
-        let decoded = base64_decode(token)?;
-        let claim: TokenClaim = serde_json::from_str(&decoded)?;
-
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let invalid = || RuneError::PermissionDenied("Invalid token".to_string());
+        let (signed, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        hmac::verify(&self.signing_key, signed.as_bytes(), &signature).map_err(|_| invalid())?;
+
+        let (_, payload) = signed.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let claim: TokenClaim = serde_json::from_slice(&payload)?;
+        if claim.iss != self.config.issuer || claim.aud != self.config.service {
+            return Err(invalid());
+        }
 
+        let now = unix_now();
         if claim.exp < now {
             return Err(RuneError::PermissionDenied("Token expired".to_string()));
         }
@@ -261,10 +425,22 @@ impl RegistryAuth {
     }
 
     /// Get WWW-Authenticate header value
-    pub fn www_authenticate(&self, scope: Option<&str>) -> String {
+    ///
+    /// In token mode the realm must be the token endpoint's URL; a
+    /// configured realm that is not a URL is replaced by `token_url`.
+    pub fn www_authenticate(&self, token_url: &str, scope: Option<&str>) -> String {
+        if self.config.mode == AuthMode::Basic {
+            return format!(r#"Basic realm="{}""#, self.config.realm);
+        }
+
+        let realm = if self.config.realm.starts_with("http") {
+            self.config.realm.as_str()
+        } else {
+            token_url
+        };
         let mut header = format!(
             r#"Bearer realm="{}",service="{}""#,
-            self.config.realm, self.config.service
+            realm, self.config.service
         );
 
         if let Some(s) = scope {
@@ -287,16 +463,21 @@ impl Default for RegistryAuth {
 }
 
 /// Parse scope string into access claims
-fn parse_scope(scope: &str) -> Vec<AccessClaim> {
+///
+/// Scopes look like `repository:library/nginx:pull,push` and may be
+/// separated by spaces.
+pub fn parse_scope(scope: &str) -> Vec<AccessClaim> {
     let mut claims = Vec::new();
 
     for part in scope.split(' ') {
         let parts: Vec<&str> = part.split(':').collect();
         if parts.len() >= 3 {
+            // Names may carry a registry port, e.g. `localhost:5000/app`
+            let actions = parts[parts.len() - 1];
             claims.push(AccessClaim {
                 resource_type: parts[0].to_string(),
-                name: parts[1].to_string(),
-                actions: parts[2].split(',').map(|s| s.to_string()).collect(),
+                name: parts[1..parts.len() - 1].join(":"),
+                actions: actions.split(',').map(|s| s.to_string()).collect(),
             });
         }
     }
@@ -304,6 +485,13 @@ fn parse_scope(scope: &str) -> Vec<AccessClaim> {
     claims
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 /// Check if repository matches pattern
 fn matches_repository(pattern: &str, repository: &str) -> bool {
     if pattern == "*" {
@@ -334,21 +522,6 @@ fn verify_password(password: &str, hash: &str) -> bool {
     bcrypt::verify(password, hash).unwrap_or(false)
 }
 
-/// Base64 encode
-fn base64_encode(data: &str) -> String {
-    use base64::{engine::general_purpose::STANDARD, Engine as _};
-    STANDARD.encode(data.as_bytes())
-}
-
-/// Base64 decode
-fn base64_decode(data: &str) -> Result<String> {
-    use base64::{engine::general_purpose::STANDARD, Engine as _};
-    let bytes = STANDARD
-        .decode(data)
-        .map_err(|e| RuneError::InvalidConfig(format!("Invalid base64: {}", e)))?;
-    String::from_utf8(bytes).map_err(|e| RuneError::InvalidConfig(format!("Invalid UTF-8: {}", e)))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -411,6 +584,48 @@ mod tests {
         assert_eq!(claim.sub, "testuser");
     }
 
+    #[test]
+    fn test_token_scopes_and_signature() {
+        let temp = tempfile::tempdir().unwrap();
+        let htpasswd = temp.path().join("htpasswd");
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        std::fs::write(&htpasswd, format!("# users\nalice:{}\n", hash)).unwrap();
+
+        let auth = RegistryAuth::new();
+        assert_eq!(auth.load_htpasswd(&htpasswd).unwrap(), 1);
+        assert!(auth.verify_credentials("alice", "secret").unwrap());
+        auth.add_user(
+            "bob",
+            "pw",
+            vec![Permission {
+                repository: "bob/*".to_string(),
+                actions: vec![Action::Pull, Action::Push],
+            }],
+        )
+        .unwrap();
+
+        let requested = parse_scope("repository:bob/app:pull,push repository:alice/app:pull");
+        let granted = auth.grant(Some("bob"), requested.clone(), &[]).unwrap();
+        assert_eq!(granted.len(), 1);
+        assert_eq!(granted[0].actions, vec!["pull", "push"]);
+
+        let anonymous = auth.grant(None, requested, &[Action::Pull]).unwrap();
+        assert!(anonymous.iter().all(|c| c.actions == vec!["pull"]));
+
+        let token = auth.issue_token("bob", granted).unwrap().token;
+        let claim = auth.verify_token(&token).unwrap();
+        assert!(claim.allows("repository", "bob/app", "push"));
+        assert!(!claim.allows("repository", "alice/app", "pull"));
+
+        // Tampered or foreign tokens are rejected
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        assert!(auth.verify_token(&format!("{}.AAAA", signed)).is_err());
+        assert!(RegistryAuth::new().verify_token(&token).is_err());
+
+        std::fs::write(&htpasswd, "carol:{SHA}abc\n").unwrap();
+        assert!(auth.load_htpasswd(&htpasswd).is_err());
+    }
+
     #[test]
     fn test_matches_repository() {
         assert!(matches_repository("*", "anything"));
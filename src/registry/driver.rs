@@ -0,0 +1,202 @@
+//! Registry Storage Drivers
+//!
+//! Registry content is kept in an object store addressed by `/`-separated
+//! keys such as `blobs/sha256/<hash>`. Drivers implement the store; the
+//! registry's layout on top of it lives in [`super::storage`].
+
+use crate::error::Result;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::pin::Pin;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Future returned by storage driver operations
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Object store the registry keeps blobs, manifests and uploads in
+pub trait StorageDriver: Send + Sync {
+    /// Read an object, or `None` if it does not exist
+    fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Vec<u8>>>;
+
+    /// Write an object, replacing any existing one
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> StorageFuture<'a, ()>;
+
+    /// Size of an object, or `None` if it does not exist
+    fn stat<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<u64>>;
+
+    /// Names directly under a prefix, like a directory listing
+    fn list<'a>(&'a self, prefix: &'a str) -> StorageFuture<'a, Vec<String>>;
+
+    /// Delete an object, or everything under a prefix
+    fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()>;
+
+    /// Append to an object, creating it if missing
+    ///
+    /// The default reads and rewrites the whole object.
+    fn append<'a>(&'a self, key: &'a str, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let mut content = self.get(key).await?.unwrap_or_default();
+            content.extend_from_slice(data);
+            self.put(key, &content).await
+        })
+    }
+
+    /// Move an object to a new key
+    ///
+    /// The default copies the object and deletes the original.
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let content = self.get(from).await?.ok_or_else(|| {
+                crate::error::RuneError::ImageNotFound(format!("Object {} not found", from))
+            })?;
+            self.put(to, &content).await?;
+            self.delete(from).await
+        })
+    }
+}
+
+/// Keeps objects as files under a root directory
+pub struct FilesystemDriver {
+    root: PathBuf,
+}
+
+impl FilesystemDriver {
+    /// Create a driver rooted at a directory
+    pub fn new(root: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key.trim_matches('/'))
+    }
+
+    /// Create the parent directory of a key
+    async fn create_parent(&self, key: &str) -> Result<PathBuf> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(path)
+    }
+}
+
+/// Treat a missing file as `None`
+fn not_found_as_none<T>(result: std::io::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl StorageDriver for FilesystemDriver {
+    fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let path = self.path(key);
+            if path.is_dir() {
+                return Ok(None);
+            }
+            not_found_as_none(fs::read(path).await)
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.create_parent(key).await?;
+            // Write beside the target and rename so readers never see a partial object
+            let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+            fs::write(&tmp, data).await?;
+            fs::rename(&tmp, &path).await?;
+            Ok(())
+        })
+    }
+
+    fn stat<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<u64>> {
+        Box::pin(async move {
+            let metadata = not_found_as_none(fs::metadata(self.path(key)).await)?;
+            Ok(metadata.filter(|m| m.is_file()).map(|m| m.len()))
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> StorageFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut names = Vec::new();
+            let Some(mut entries) = not_found_as_none(fs::read_dir(self.path(prefix)).await)?
+            else {
+                return Ok(names);
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+            names.sort();
+            Ok(names)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key);
+            let result = if path.is_dir() {
+                fs::remove_dir_all(&path).await
+            } else {
+                fs::remove_file(&path).await
+            };
+            not_found_as_none(result)?;
+            Ok(())
+        })
+    }
+
+    fn append<'a>(&'a self, key: &'a str, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.create_parent(key).await?;
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(data).await?;
+            Ok(())
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let to = self.create_parent(to).await?;
+            fs::rename(self.path(from), to).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_filesystem_driver() {
+        let temp = tempfile::tempdir().unwrap();
+        let driver = FilesystemDriver::new(temp.path().to_path_buf()).unwrap();
+
+        driver.put("a/b/one", b"1").await.unwrap();
+        driver.append("a/b/two", b"2").await.unwrap();
+        driver.append("a/b/two", b"2").await.unwrap();
+        assert_eq!(driver.get("a/b/two").await.unwrap().unwrap(), b"22");
+        assert_eq!(driver.stat("a/b/one").await.unwrap(), Some(1));
+        assert_eq!(driver.stat("a/b").await.unwrap(), None);
+        assert_eq!(driver.list("a/b").await.unwrap(), vec!["one", "two"]);
+        assert!(driver.list("missing").await.unwrap().is_empty());
+
+        driver.rename("a/b/one", "c/one").await.unwrap();
+        assert!(driver.get("a/b/one").await.unwrap().is_none());
+        assert_eq!(driver.get("c/one").await.unwrap().unwrap(), b"1");
+
+        driver.delete("a").await.unwrap();
+        driver.delete("a").await.unwrap();
+        assert!(driver.list("a").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+//! Registry HTTP Transport
+//!
+//! A small HTTP/1.1 implementation for the Distribution API. Request bodies
+//! are read in full, either by `Content-Length` or chunked transfer encoding,
+//! and connections are kept alive until the client closes them.
+
+use crate::error::{Result, RuneError};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest request line or header we accept
+const MAX_LINE: usize = 16 * 1024;
+
+/// Most headers we accept in one request
+const MAX_HEADERS: usize = 100;
+
+/// An HTTP request
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    /// Request method
+    pub method: String,
+    /// Decoded path without the query string
+    pub path: String,
+    /// Decoded query parameters, in order; names may repeat
+    pub query: Vec<(String, String)>,
+    /// Headers, keyed by lowercase name
+    pub headers: HashMap<String, String>,
+    /// Request body
+    pub body: Vec<u8>,
+    /// Address of the client, when known
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl Request {
+    /// Create a request for a target such as `/v2/_catalog?n=10`
+    pub fn new(method: &str, target: &str) -> Self {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                (percent_decode(k), percent_decode(v))
+            })
+            .collect();
+
+        Self {
+            method: method.to_uppercase(),
+            path: percent_decode(path),
+            query,
+            ..Default::default()
+        }
+    }
+
+    /// Add a header
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .insert(name.to_lowercase(), value.trim().to_string());
+        self
+    }
+
+    /// Set the body
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Get a header by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+
+    /// Get the first value of a query parameter
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query_all(name).into_iter().next()
+    }
+
+    /// Get every value of a repeated query parameter
+    pub fn query_all(&self, name: &str) -> Vec<&str> {
+        self.query
+            .iter()
+            .filter(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Whether the client wants the connection closed after this request
+    pub fn wants_close(&self) -> bool {
+        self.header("connection")
+            .map(|c| c.eq_ignore_ascii_case("close"))
+            .unwrap_or(false)
+    }
+}
+
+/// An HTTP response
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// Status code
+    pub status: u16,
+    /// Headers in the order they are sent
+    pub headers: Vec<(String, String)>,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Create an empty response
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Create a JSON response
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let body = serde_json::to_vec(value).unwrap_or_default();
+        Self::new(status).with_body("application/json", body)
+    }
+
+    /// Add a header
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Set the body and its content type
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.body = body;
+        self.header("Content-Type", content_type)
+    }
+
+    /// Get a header by case-insensitive name
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Drop the body but keep its length, as for a HEAD request
+    pub fn without_body(mut self) -> Self {
+        if self.header_value("Content-Length").is_none() {
+            let len = self.body.len();
+            self = self.header("Content-Length", len.to_string());
+        }
+        self.body.clear();
+        self
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+fn bad_request(message: &str) -> RuneError {
+    RuneError::Api(format!("Bad HTTP request: {}", message))
+}
+
+/// Decode `%XX` escapes and `+` in a URL component
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                {
+                    Some(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Read one CRLF-terminated line, or `None` at end of stream
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let n = (&mut *reader)
+        .take(MAX_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if line.len() > MAX_LINE {
+        return Err(bad_request("line too long"));
+    }
+    let line = String::from_utf8(line).map_err(|_| bad_request("invalid UTF-8"))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Read a request line and headers, leaving the body unread
+///
+/// Returns `None` when the client closed the connection between requests.
+pub async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Request>> {
+    let line = match read_line(reader).await? {
+        Some(line) if !line.is_empty() => line,
+        Some(_) => return Err(bad_request("empty request line")),
+        None => return Ok(None),
+    };
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad_request(&line));
+    };
+    let mut request = Request::new(method, target);
+
+    loop {
+        let line = read_line(reader)
+            .await?
+            .ok_or_else(|| bad_request("unexpected end of headers"))?;
+        if line.is_empty() {
+            break;
+        }
+        if request.headers.len() >= MAX_HEADERS {
+            return Err(bad_request("too many headers"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| bad_request("malformed header"))?;
+        request = request.with_header(name.trim(), value);
+    }
+
+    Ok(Some(request))
+}
+
+/// Read the body of a request whose head has been read
+///
+/// Bodies larger than `limit` are rejected.
+pub async fn read_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    request: &mut Request,
+    limit: u64,
+) -> Result<()> {
+    let too_large = || RuneError::ResourceLimit(format!("Request body exceeds {} bytes", limit));
+
+    let chunked = request
+        .header("transfer-encoding")
+        .map(|te| te.to_lowercase().contains("chunked"))
+        .unwrap_or(false);
+
+    if chunked {
+        let mut body = Vec::new();
+        loop {
+            let line = read_line(reader)
+                .await?
+                .ok_or_else(|| bad_request("unexpected end of chunked body"))?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size =
+                u64::from_str_radix(size, 16).map_err(|_| bad_request("invalid chunk size"))?;
+            if size == 0 {
+                // Skip trailers
+                while let Some(line) = read_line(reader).await? {
+                    if line.is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            if body.len() as u64 + size > limit {
+                return Err(too_large());
+            }
+            let start = body.len();
+            body.resize(start + size as usize, 0);
+            reader.read_exact(&mut body[start..]).await?;
+            read_line(reader).await?;
+        }
+        request.body = body;
+    } else if let Some(len) = request.header("content-length") {
+        let len: u64 = len
+            .parse()
+            .map_err(|_| bad_request("invalid Content-Length"))?;
+        if len > limit {
+            return Err(too_large());
+        }
+        let mut body = vec![0u8; len as usize];
+        reader.read_exact(&mut body).await?;
+        request.body = body;
+    }
+
+    Ok(())
+}
+
+/// Write a response
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &Response,
+) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if response.header_value("Content-Length").is_none() {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Tell a client waiting on `Expect: 100-continue` to send its body
+pub async fn write_continue<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<()> {
+    writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn test_read_chunked_request() {
+        let raw = b"PATCH /v2/library/app/blobs/uploads/abc?x=a%3Ab HTTP/1.1\r\n\
+                    Host: localhost\r\n\
+                    Transfer-Encoding: chunked\r\n\
+                    \r\n\
+                    5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n\
+                    GET /v2/ HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(&raw[..]);
+
+        let mut request = read_head(&mut reader).await.unwrap().unwrap();
+        assert_eq!(request.method, "PATCH");
+        assert_eq!(request.path, "/v2/library/app/blobs/uploads/abc");
+        assert_eq!(request.query("x"), Some("a:b"));
+        assert_eq!(request.header("Host"), Some("localhost"));
+        read_body(&mut reader, &mut request, 1024).await.unwrap();
+        assert_eq!(request.body, b"hello world");
+
+        let next = read_head(&mut reader).await.unwrap().unwrap();
+        assert_eq!(next.path, "/v2/");
+        assert!(read_head(&mut reader).await.unwrap().is_none());
+
+        let mut reader = BufReader::new(&b"PUT / HTTP/1.1\r\nContent-Length: 10\r\n\r\n"[..]);
+        let mut request = read_head(&mut reader).await.unwrap().unwrap();
+        assert!(read_body(&mut reader, &mut request, 4).await.is_err());
+    }
+}
@@ -4,6 +4,11 @@
 //! that is compatible with Docker, Podman, and other OCI-compliant tools.
 
 pub mod auth;
+pub mod driver;
+pub mod http;
+pub mod notifications;
+pub mod proxy;
+pub mod s3;
 pub mod server;
 pub mod storage;
 
@@ -0,0 +1,387 @@
+//! Registry Notifications
+//!
+//! Pushes and deletes are reported to webhook endpoints as Docker
+//! Distribution event envelopes. Each endpoint has its own queue, so a slow
+//! or failing endpoint only holds up its own events, and failed deliveries
+//! are retried with exponential backoff. Recent events and how their
+//! deliveries went are kept in a backlog for debugging.
+
+use crate::error::{Result, RuneError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Content type of event envelopes
+pub const EVENTS_MEDIA_TYPE: &str = "application/vnd.docker.distribution.events.v1+json";
+
+/// Number of recent events kept in the backlog
+const BACKLOG_SIZE: usize = 100;
+
+/// Longest wait between delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
+/// Webhook endpoint configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    /// Name shown in the backlog
+    pub name: String,
+    /// URL events are POSTed to
+    pub url: String,
+    /// Extra headers sent with each request, e.g. Authorization
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Request timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Retries after a failed delivery before the event is dropped
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Wait before the first retry in milliseconds; doubles on each retry
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    /// Media types whose events are not sent to this endpoint
+    #[serde(default)]
+    pub ignored_media_types: Vec<String>,
+}
+
+impl EndpointConfig {
+    /// Send events to a URL with the default timeout and retries
+    pub fn new(name: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            url: url.into(),
+            headers: HashMap::new(),
+            timeout: default_timeout(),
+            max_retries: default_max_retries(),
+            backoff_ms: default_backoff_ms(),
+            ignored_media_types: Vec::new(),
+        }
+    }
+}
+
+/// What happened to the event's target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventAction {
+    /// A manifest or blob was pushed
+    Push,
+    /// A blob was mounted from another repository
+    Mount,
+    /// A manifest or blob was deleted
+    Delete,
+}
+
+/// Manifest or blob an event is about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    /// Media type of the content, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Content digest
+    pub digest: String,
+    /// Content size, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Repository the content belongs to
+    pub repository: String,
+    /// Repository a mounted blob came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_repository: Option<String>,
+    /// URL the content can be fetched from
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    /// Tag the manifest was pushed or deleted by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// Request that caused an event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    /// Request ID
+    pub id: String,
+    /// Client address
+    pub addr: String,
+    /// Host the client addressed
+    pub host: String,
+    /// Request method
+    pub method: String,
+    /// Client user agent
+    #[serde(rename = "useragent")]
+    pub user_agent: String,
+}
+
+/// Who caused an event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    /// Authenticated user, empty when anonymous
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+}
+
+/// Registry instance that emitted an event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    /// Address the registry listens on
+    pub addr: String,
+    /// ID of the registry process
+    #[serde(rename = "instanceID")]
+    pub instance_id: String,
+}
+
+/// A registry event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Event ID
+    pub id: String,
+    /// When the event happened
+    pub timestamp: DateTime<Utc>,
+    /// What happened
+    pub action: EventAction,
+    /// What it happened to
+    pub target: Target,
+    /// Request that caused it
+    pub request: RequestRecord,
+    /// Who caused it
+    pub actor: Actor,
+    /// Registry it happened on
+    pub source: Source,
+}
+
+/// Events sent in one webhook request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The events
+    pub events: Vec<Event>,
+}
+
+/// State of an event's delivery to one endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Queued or being retried
+    Pending,
+    /// Accepted by the endpoint
+    Delivered,
+    /// Dropped after the last retry failed
+    Failed,
+}
+
+/// Delivery of an event to one endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    /// Endpoint name
+    pub endpoint: String,
+    /// Delivery state
+    pub status: DeliveryStatus,
+    /// Attempts made so far
+    pub attempts: u32,
+    /// Error of the last failed attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// An event in the backlog with its deliveries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// The event
+    pub event: Event,
+    /// Its delivery to each endpoint that wants it
+    pub deliveries: Vec<Delivery>,
+}
+
+type Backlog = Arc<Mutex<VecDeque<EventRecord>>>;
+
+/// A webhook endpoint and its queue, started on first use
+struct Endpoint {
+    config: EndpointConfig,
+    queue: OnceLock<mpsc::UnboundedSender<Event>>,
+}
+
+/// Sends registry events to webhook endpoints
+pub struct Notifier {
+    endpoints: Vec<Endpoint>,
+    client: reqwest::Client,
+    backlog: Backlog,
+}
+
+impl Notifier {
+    /// Create a notifier for a set of endpoints
+    pub fn new(endpoints: Vec<EndpointConfig>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| RuneError::Network(e.to_string()))?;
+        Ok(Self {
+            endpoints: endpoints
+                .into_iter()
+                .map(|config| Endpoint {
+                    config,
+                    queue: OnceLock::new(),
+                })
+                .collect(),
+            client,
+            backlog: Arc::new(Mutex::new(VecDeque::new())),
+        })
+    }
+
+    /// Record an event and queue it for every endpoint that wants it
+    ///
+    /// Must be called within a Tokio runtime, which delivers the events.
+    pub fn notify(&self, event: Event) {
+        let endpoints: Vec<&Endpoint> = self
+            .endpoints
+            .iter()
+            .filter(|e| match event.target.media_type {
+                Some(ref media_type) => !e.config.ignored_media_types.contains(media_type),
+                None => true,
+            })
+            .collect();
+
+        {
+            let mut backlog = self.backlog.lock().unwrap();
+            if backlog.len() == BACKLOG_SIZE {
+                backlog.pop_front();
+            }
+            backlog.push_back(EventRecord {
+                event: event.clone(),
+                deliveries: endpoints
+                    .iter()
+                    .map(|e| Delivery {
+                        endpoint: e.config.name.clone(),
+                        status: DeliveryStatus::Pending,
+                        attempts: 0,
+                        last_error: None,
+                    })
+                    .collect(),
+            });
+        }
+
+        for endpoint in endpoints {
+            let queue = endpoint.queue.get_or_init(|| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                tokio::spawn(run_endpoint(
+                    endpoint.config.clone(),
+                    self.client.clone(),
+                    self.backlog.clone(),
+                    rx,
+                ));
+                tx
+            });
+            let _ = queue.send(event.clone());
+        }
+    }
+
+    /// Recent events, oldest first
+    pub fn backlog(&self) -> Vec<EventRecord> {
+        self.backlog.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Deliver an endpoint's events in order until the notifier is dropped
+async fn run_endpoint(
+    config: EndpointConfig,
+    client: reqwest::Client,
+    backlog: Backlog,
+    mut queue: mpsc::UnboundedReceiver<Event>,
+) {
+    while let Some(event) = queue.recv().await {
+        let mut backoff = Duration::from_millis(config.backoff_ms);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = deliver(&client, &config, &event).await;
+            let status = match result {
+                Ok(()) => DeliveryStatus::Delivered,
+                Err(_) if attempts > config.max_retries => DeliveryStatus::Failed,
+                Err(_) => DeliveryStatus::Pending,
+            };
+            if let Err(ref e) = result {
+                tracing::debug!(
+                    "Delivering event {} to {} failed: {}",
+                    event.id,
+                    config.name,
+                    e
+                );
+            }
+            record(&backlog, &event.id, &config.name, |delivery| {
+                delivery.status = status;
+                delivery.attempts = attempts;
+                delivery.last_error = result.err().map(|e| e.to_string());
+            });
+
+            match status {
+                DeliveryStatus::Pending => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                DeliveryStatus::Failed => {
+                    tracing::warn!(
+                        "Dropping event {} for {} after {} attempts",
+                        event.id,
+                        config.name,
+                        attempts
+                    );
+                    break;
+                }
+                DeliveryStatus::Delivered => break,
+            }
+        }
+    }
+}
+
+/// POST one event to an endpoint
+async fn deliver(client: &reqwest::Client, config: &EndpointConfig, event: &Event) -> Result<()> {
+    let envelope = Envelope {
+        events: vec![event.clone()],
+    };
+    let mut request = client
+        .post(&config.url)
+        .timeout(Duration::from_secs(config.timeout))
+        .header("Content-Type", EVENTS_MEDIA_TYPE)
+        .body(serde_json::to_vec(&envelope)?);
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| RuneError::Network(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(RuneError::Network(format!(
+            "Endpoint returned {}",
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+/// Update the backlog entry of an event's delivery, if still kept
+fn record(backlog: &Backlog, event_id: &str, endpoint: &str, update: impl FnOnce(&mut Delivery)) {
+    let mut backlog = backlog.lock().unwrap();
+    if let Some(delivery) = backlog
+        .iter_mut()
+        .rev()
+        .find(|r| r.event.id == event_id)
+        .and_then(|r| r.deliveries.iter_mut().find(|d| d.endpoint == endpoint))
+    {
+        update(delivery);
+    }
+}
@@ -0,0 +1,663 @@
+//! Registry Pull-Through Cache
+//!
+//! In proxy mode the registry mirrors an upstream registry such as Docker
+//! Hub. Manifests and blobs are fetched on first pull and kept in storage.
+//! Tags are revalidated against the upstream once their TTL has passed, and
+//! the least recently used blobs are evicted when the cache outgrows its
+//! size limit.
+
+use super::server::media_types;
+use super::storage::{self, RegistryStorage};
+use crate::error::{Result, RuneError};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::Regex;
+use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Default seconds a cached tag is served before it is revalidated
+pub const DEFAULT_TTL: u64 = 3600;
+
+/// Manifest types requested from the upstream
+const MANIFEST_ACCEPT: [&str; 4] = [
+    media_types::OCI_INDEX_V1,
+    media_types::OCI_MANIFEST_V1,
+    media_types::MANIFEST_LIST_V2,
+    media_types::MANIFEST_V2,
+];
+
+/// `key="value"` parameters of a WWW-Authenticate challenge
+static CHALLENGE_PARAM_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(\w+)="([^"]*)""#).expect("valid regex"));
+
+fn default_ttl() -> u64 {
+    DEFAULT_TTL
+}
+
+/// Pull-through cache configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Upstream registry URL, e.g. `https://registry-1.docker.io`
+    pub remote_url: String,
+    /// Username for the upstream
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password for the upstream
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Seconds a cached tag is served before it is revalidated
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    /// Most bytes of blobs to keep cached
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+impl ProxyConfig {
+    /// Mirror a registry with the default TTL and no size limit
+    pub fn new(remote_url: impl Into<String>) -> Self {
+        Self {
+            remote_url: remote_url.into(),
+            username: None,
+            password: None,
+            ttl: DEFAULT_TTL,
+            max_size: None,
+        }
+    }
+}
+
+fn network_error(e: reqwest::Error) -> RuneError {
+    RuneError::Network(format!("Upstream registry: {}", e))
+}
+
+fn upstream_error(status: StatusCode, what: &str) -> RuneError {
+    RuneError::Network(format!(
+        "Upstream registry returned {} for {}",
+        status, what
+    ))
+}
+
+/// Token endpoint response; registries use either field name
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Client for the registry being mirrored
+///
+/// Requests start out anonymous and answer the upstream's basic or bearer
+/// challenges, keeping the resulting credentials per repository until the
+/// upstream rejects them.
+pub struct Upstream {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    client: reqwest::Client,
+    /// Authorization header value for each repository
+    authorization: RwLock<HashMap<String, String>>,
+}
+
+impl Upstream {
+    /// Create a client for the upstream of a proxy configuration
+    pub fn new(config: &ProxyConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| RuneError::Network(e.to_string()))?;
+        Ok(Self {
+            url: config.remote_url.trim_end_matches('/').to_string(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            client,
+            authorization: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Send a request for a repository, authenticating if challenged
+    async fn send(&self, method: Method, name: &str, path: &str) -> Result<reqwest::Response> {
+        let url = format!("{}/v2/{}/{}", self.url, name, path);
+        let request = |authorization: Option<&str>| {
+            let request = self
+                .client
+                .request(method.clone(), &url)
+                .header("Accept", MANIFEST_ACCEPT.join(", "));
+            match authorization {
+                Some(authorization) => request.header(AUTHORIZATION, authorization),
+                None => request,
+            }
+        };
+
+        let authorization = self.authorization.read().unwrap().get(name).cloned();
+        let response = request(authorization.as_deref())
+            .send()
+            .await
+            .map_err(network_error)?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let Some(authorization) = self.authenticate(response.headers(), name).await? else {
+            return Ok(response);
+        };
+        self.authorization
+            .write()
+            .unwrap()
+            .insert(name.to_string(), authorization.clone());
+        request(Some(&authorization))
+            .send()
+            .await
+            .map_err(network_error)
+    }
+
+    /// Answer a challenge with an Authorization header value
+    ///
+    /// Bearer challenges are answered with a token from the upstream's
+    /// realm, basic ones with the configured credentials. Returns `None` if
+    /// the challenge cannot be answered.
+    async fn authenticate(&self, headers: &HeaderMap, name: &str) -> Result<Option<String>> {
+        let Some(challenge) = headers.get(WWW_AUTHENTICATE).and_then(|v| v.to_str().ok()) else {
+            return Ok(None);
+        };
+        let Some(challenge) = challenge.strip_prefix("Bearer ") else {
+            return Ok(self.username.as_ref().map(|username| {
+                let credentials = format!(
+                    "{}:{}",
+                    username,
+                    self.password.as_deref().unwrap_or_default()
+                );
+                format!("Basic {}", STANDARD.encode(credentials))
+            }));
+        };
+        let params: HashMap<&str, &str> = CHALLENGE_PARAM_RE
+            .captures_iter(challenge)
+            .filter_map(|c| Some((c.get(1)?.as_str(), c.get(2)?.as_str())))
+            .collect();
+        let realm = params
+            .get("realm")
+            .ok_or_else(|| RuneError::Network("Upstream challenge has no realm".to_string()))?;
+
+        let scope = format!("repository:{}:pull", name);
+        let mut query = vec![("scope", params.get("scope").copied().unwrap_or(&scope))];
+        if let Some(service) = params.get("service") {
+            query.push(("service", service));
+        }
+        let mut request = self.client.get(*realm).query(&query);
+        if let Some(ref username) = self.username {
+            request = request.basic_auth(username, self.password.as_ref());
+        }
+
+        let response = request.send().await.map_err(network_error)?;
+        if !response.status().is_success() {
+            return Err(upstream_error(response.status(), "a token"));
+        }
+        let token: TokenResponse = response.json().await.map_err(network_error)?;
+        token
+            .token
+            .or(token.access_token)
+            .map(|token| Some(format!("Bearer {}", token)))
+            .ok_or_else(|| RuneError::Network("Upstream token response is empty".to_string()))
+    }
+
+    /// Fetch a manifest and its content type, or `None` if it does not exist
+    pub async fn manifest(&self, name: &str, reference: &str) -> Result<Option<(String, Vec<u8>)>> {
+        let path = format!("manifests/{}", reference);
+        let response = self.send(Method::GET, name, &path).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(upstream_error(status, &path)),
+            _ => {
+                let content_type = response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or(media_types::OCI_MANIFEST_V1)
+                    .to_string();
+                let body = response.bytes().await.map_err(network_error)?;
+                Ok(Some((content_type, body.to_vec())))
+            }
+        }
+    }
+
+    /// Digest a tag currently points at, or `None` if it does not exist
+    ///
+    /// Uses a HEAD request, which registries such as Docker Hub do not count
+    /// against pull rate limits.
+    pub async fn manifest_digest(&self, name: &str, reference: &str) -> Result<Option<String>> {
+        let path = format!("manifests/{}", reference);
+        let response = self.send(Method::HEAD, name, &path).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(upstream_error(status, &path)),
+            _ => Ok(response
+                .headers()
+                .get("Docker-Content-Digest")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())),
+        }
+    }
+
+    /// Fetch a blob, or `None` if it does not exist
+    pub async fn blob(&self, name: &str, digest: &str) -> Result<Option<Vec<u8>>> {
+        let path = format!("blobs/{}", digest);
+        let response = self.send(Method::GET, name, &path).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(upstream_error(status, &path)),
+            _ => Ok(Some(
+                response.bytes().await.map_err(network_error)?.to_vec(),
+            )),
+        }
+    }
+
+    /// List a repository's tags, or `None` if it does not exist
+    pub async fn tags(&self, name: &str) -> Result<Option<Vec<String>>> {
+        #[derive(Deserialize)]
+        struct TagList {
+            #[serde(default)]
+            tags: Option<Vec<String>>,
+        }
+
+        let response = self.send(Method::GET, name, "tags/list").await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(upstream_error(status, "tags/list")),
+            _ => {
+                let list: TagList = response.json().await.map_err(network_error)?;
+                Ok(Some(list.tags.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+/// Cached blobs in least recently used order
+#[derive(Default)]
+struct BlobIndex {
+    /// Size and last use of each blob
+    entries: HashMap<String, (u64, u64)>,
+    /// Total size of the cached blobs
+    total: u64,
+    /// Logical clock ordering uses
+    clock: u64,
+}
+
+impl BlobIndex {
+    /// Record a use of a blob, adding it if new
+    fn touch(&mut self, digest: &str, size: u64) {
+        self.clock += 1;
+        match self.entries.get_mut(digest) {
+            Some(entry) => entry.1 = self.clock,
+            None => {
+                self.entries.insert(digest.to_string(), (size, self.clock));
+                self.total += size;
+            }
+        }
+    }
+
+    /// Drop blobs, oldest use first, until the cache fits in `max_size`
+    ///
+    /// The most recently used blob is kept even if it alone is too large.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut by_age: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|(digest, (_, used))| (*used, digest.clone()))
+            .collect();
+        by_age.sort();
+        by_age.pop();
+
+        let mut evicted = Vec::new();
+        for (_, digest) in by_age {
+            if self.total <= max_size {
+                break;
+            }
+            if let Some((size, _)) = self.entries.remove(&digest) {
+                self.total -= size;
+                evicted.push(digest);
+            }
+        }
+        evicted
+    }
+}
+
+/// Serves pulls from storage, filling it from the upstream on a miss
+pub struct ProxyCache {
+    upstream: Upstream,
+    storage: Arc<RegistryStorage>,
+    ttl: Duration,
+    max_size: Option<u64>,
+    /// When each cached tag was last checked against the upstream
+    checked: Mutex<HashMap<String, Instant>>,
+    /// Cached blobs, read from storage on first use
+    blobs: tokio::sync::Mutex<Option<BlobIndex>>,
+}
+
+impl ProxyCache {
+    /// Create a cache in front of the upstream of a proxy configuration
+    pub fn new(config: &ProxyConfig, storage: Arc<RegistryStorage>) -> Result<Self> {
+        Ok(Self {
+            upstream: Upstream::new(config)?,
+            storage,
+            ttl: Duration::from_secs(config.ttl),
+            max_size: config.max_size,
+            checked: Mutex::new(HashMap::new()),
+            blobs: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Whether a cached tag was checked within the TTL
+    ///
+    /// Tags cached before a restart are revalidated on their next pull.
+    fn is_fresh(&self, name: &str, tag: &str) -> bool {
+        self.checked
+            .lock()
+            .unwrap()
+            .get(&format!("{}:{}", name, tag))
+            .map(|checked| checked.elapsed() < self.ttl)
+            .unwrap_or(false)
+    }
+
+    fn mark_fresh(&self, name: &str, tag: &str) {
+        self.checked
+            .lock()
+            .unwrap()
+            .insert(format!("{}:{}", name, tag), Instant::now());
+    }
+
+    /// Get a manifest, fetching or revalidating it as needed
+    ///
+    /// Manifests pulled by digest never change and are served from the
+    /// cache once fetched. If the upstream cannot be reached, stale tags are
+    /// served rather than failing the pull.
+    pub async fn manifest(&self, name: &str, reference: &str) -> Result<(String, Vec<u8>)> {
+        let by_digest = reference.starts_with("sha256:");
+        let cached = self.storage.get_manifest(name, reference).await.ok();
+
+        if let Some((_, ref body)) = cached {
+            if by_digest || self.is_fresh(name, reference) {
+                return Ok(cached.unwrap());
+            }
+            match self.upstream.manifest_digest(name, reference).await {
+                Ok(Some(current)) if current == storage::digest(body) => {
+                    self.mark_fresh(name, reference);
+                    return Ok(cached.unwrap());
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Serving stale {}:{}: {}", name, reference, e);
+                    return Ok(cached.unwrap());
+                }
+            }
+        }
+
+        let fetched = match self.upstream.manifest(name, reference).await {
+            Ok(fetched) => fetched,
+            Err(e) => return cached.ok_or(e),
+        };
+        let (content_type, body) =
+            fetched.ok_or_else(|| RuneError::ImageNotFound(format!("{}:{}", name, reference)))?;
+
+        let digest = storage::digest(&body);
+        if by_digest && digest != reference {
+            return Err(RuneError::Image(format!(
+                "Upstream manifest {} has digest {}",
+                reference, digest
+            )));
+        }
+        self.storage
+            .put_manifest(name, reference, &content_type, &body)
+            .await?;
+        if !by_digest {
+            self.mark_fresh(name, reference);
+        }
+        Ok((content_type, body))
+    }
+
+    /// Get a blob, fetching it from the upstream on a miss
+    pub async fn blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
+        if let Ok(data) = self.storage.get_blob(name, digest).await {
+            self.touch(digest, data.len() as u64).await?;
+            return Ok(data);
+        }
+
+        let data = self
+            .upstream
+            .blob(name, digest)
+            .await?
+            .ok_or_else(|| RuneError::ImageNotFound(digest.to_string()))?;
+        let actual = storage::digest(&data);
+        if actual != digest {
+            return Err(RuneError::Image(format!(
+                "Upstream blob {} has digest {}",
+                digest, actual
+            )));
+        }
+
+        self.storage.put_blob(name, &data).await?;
+        self.touch(digest, data.len() as u64).await?;
+        Ok(data)
+    }
+
+    /// Get a blob's size, fetching the blob from the upstream on a miss
+    pub async fn blob_size(&self, name: &str, digest: &str) -> Result<u64> {
+        match self.storage.get_blob_size(name, digest).await {
+            Ok(size) => {
+                self.touch(digest, size).await?;
+                Ok(size)
+            }
+            Err(_) => Ok(self.blob(name, digest).await?.len() as u64),
+        }
+    }
+
+    /// List a repository's tags from the upstream, or the cache if it is
+    /// unreachable
+    pub async fn tags(&self, name: &str) -> Result<Vec<String>> {
+        match self.upstream.tags(name).await {
+            Ok(Some(tags)) => Ok(tags),
+            Ok(None) => Err(RuneError::ImageNotFound(name.to_string())),
+            Err(e) => {
+                tracing::warn!("Listing cached tags of {}: {}", name, e);
+                self.storage.list_tags(name).await
+            }
+        }
+    }
+
+    /// Record a use of a blob and evict others if the cache is too large
+    async fn touch(&self, digest: &str, size: u64) -> Result<()> {
+        let mut blobs = self.blobs.lock().await;
+        if blobs.is_none() {
+            let mut index = BlobIndex::default();
+            for (digest, size) in self.storage.list_blobs().await? {
+                index.touch(&digest, size);
+            }
+            *blobs = Some(index);
+        }
+        let index = blobs.as_mut().expect("index loaded");
+
+        index.touch(digest, size);
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+        for evicted in index.evict(max_size) {
+            tracing::debug!("Evicting {} from the registry cache", evicted);
+            self.storage.purge_blob(&evicted).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// What the fake upstream serves, and what it was asked for
+    #[derive(Default)]
+    struct UpstreamState {
+        /// Content type and body of each manifest, by tag or digest
+        manifests: HashMap<String, (String, Vec<u8>)>,
+        /// Blob contents, by the digest they are served under
+        blobs: HashMap<String, Vec<u8>>,
+        /// Whether every request fails as if the upstream were down
+        down: bool,
+        /// Method and path of each request
+        requests: Vec<String>,
+    }
+
+    /// Serve a registry from `state` on a local port, returning its URL
+    async fn fake_upstream(state: Arc<Mutex<UpstreamState>>) -> String {
+        use crate::registry::http::{self, Response};
+        use tokio::io::BufReader;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = tokio::io::split(stream);
+                    let mut reader = BufReader::new(reader);
+                    while let Ok(Some(request)) = http::read_head(&mut reader).await {
+                        let response = {
+                            let mut state = state.lock().unwrap();
+                            state
+                                .requests
+                                .push(format!("{} {}", request.method, request.path));
+                            let path = request.path.trim_start_matches("/v2/library/app/");
+                            if state.down {
+                                Response::new(503)
+                            } else if let Some(reference) = path.strip_prefix("manifests/") {
+                                match state.manifests.get(reference) {
+                                    Some((content_type, body)) => Response::new(200)
+                                        .header("Docker-Content-Digest", storage::digest(body))
+                                        .with_body(content_type, body.clone()),
+                                    None => Response::new(404),
+                                }
+                            } else if let Some(digest) = path.strip_prefix("blobs/") {
+                                match state.blobs.get(digest) {
+                                    Some(data) => Response::new(200)
+                                        .with_body("application/octet-stream", data.clone()),
+                                    None => Response::new(404),
+                                }
+                            } else {
+                                Response::new(404)
+                            }
+                        };
+                        let response = if request.method == "HEAD" {
+                            response.without_body()
+                        } else {
+                            response
+                        };
+                        http::write_response(&mut writer, &response).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_blob_index_eviction() {
+        let mut index = BlobIndex::default();
+        index.touch("a", 40);
+        index.touch("b", 40);
+        index.touch("c", 40);
+        index.touch("a", 40);
+        assert_eq!(index.total, 120);
+
+        assert_eq!(index.evict(100), vec!["b"]);
+        assert_eq!(index.total, 80);
+        assert!(index.evict(100).is_empty());
+
+        index.touch("huge", 500);
+        let mut evicted = index.evict(100);
+        evicted.sort();
+        assert_eq!(evicted, vec!["a", "c"]);
+        assert_eq!(index.total, 500);
+    }
+
+    #[tokio::test]
+    async fn test_pull_through() {
+        let dir = TempDir::new().unwrap();
+        let state = Arc::new(Mutex::new(UpstreamState::default()));
+        let requests = || std::mem::take(&mut state.lock().unwrap().requests);
+        let v1 = br#"{"schemaVersion":2,"tag":"v1"}"#.to_vec();
+        let v2 = br#"{"schemaVersion":2,"tag":"v2"}"#.to_vec();
+        let layer = b"layer".to_vec();
+        {
+            let mut state = state.lock().unwrap();
+            let manifest = (media_types::OCI_MANIFEST_V1.to_string(), v1.clone());
+            state.manifests.insert("latest".to_string(), manifest);
+            state.blobs.insert(storage::digest(&layer), layer.clone());
+        }
+
+        let config = ProxyConfig::new(fake_upstream(state.clone()).await);
+        let storage = Arc::new(RegistryStorage::new(dir.path().to_path_buf()).unwrap());
+        let cache = ProxyCache::new(&config, storage.clone()).unwrap();
+        let name = "library/app";
+
+        // A miss is fetched and kept
+        let (content_type, body) = cache.manifest(name, "latest").await.unwrap();
+        assert_eq!(
+            (content_type.as_str(), &body),
+            (media_types::OCI_MANIFEST_V1, &v1)
+        );
+        assert_eq!(storage.get_manifest(name, "latest").await.unwrap().1, v1);
+        assert_eq!(requests(), ["GET /v2/library/app/manifests/latest"]);
+        let digest = storage::digest(&layer);
+        assert_eq!(cache.blob(name, &digest).await.unwrap(), layer);
+        assert_eq!(cache.blob(name, &digest).await.unwrap(), layer);
+        assert_eq!(requests().len(), 1);
+
+        // Within the TTL the tag isn't checked, even once it has moved
+        state.lock().unwrap().manifests.get_mut("latest").unwrap().1 = v2.clone();
+        assert_eq!(cache.manifest(name, "latest").await.unwrap().1, v1);
+        assert!(requests().is_empty());
+
+        // Past it, the tag is revalidated and the new manifest fetched
+        let expire = || cache.checked.lock().unwrap().clear();
+        expire();
+        assert_eq!(cache.manifest(name, "latest").await.unwrap().1, v2);
+        assert_eq!(
+            requests(),
+            [
+                "HEAD /v2/library/app/manifests/latest",
+                "GET /v2/library/app/manifests/latest"
+            ]
+        );
+
+        // With the upstream down, what's cached is served stale
+        state.lock().unwrap().down = true;
+        expire();
+        assert_eq!(cache.manifest(name, "latest").await.unwrap().1, v2);
+        assert_eq!(cache.blob(name, &digest).await.unwrap(), layer);
+        assert!(cache.manifest(name, "other").await.is_err());
+        state.lock().unwrap().down = false;
+
+        // Content that doesn't match its digest is rejected, not cached
+        let forged = storage::digest(b"expected");
+        state
+            .lock()
+            .unwrap()
+            .blobs
+            .insert(forged.clone(), b"forged".to_vec());
+        assert!(cache.blob(name, &forged).await.is_err());
+        assert!(storage.get_blob(name, &forged).await.is_err());
+        let manifest = (media_types::OCI_MANIFEST_V1.to_string(), v1.clone());
+        state
+            .lock()
+            .unwrap()
+            .manifests
+            .insert(forged.clone(), manifest);
+        assert!(cache.manifest(name, &forged).await.is_err());
+        assert!(storage.get_manifest(name, &forged).await.is_err());
+    }
+}
@@ -2,18 +2,44 @@
 //!
 //! Implements the OCI Distribution Specification for a Docker-compatible registry.
 
-use super::auth::RegistryAuth;
-use super::storage::RegistryStorage;
+use super::auth::{self, Action, AuthConfig, AuthMode, RegistryAuth, TokenClaim};
+use super::http::{self, Request, Response};
+use super::notifications::{
+    Actor, EndpointConfig, Event, EventAction, EventRecord, Notifier, RequestRecord, Source, Target,
+};
+use super::proxy::{ProxyCache, ProxyConfig};
+use super::s3::{S3Config, S3Driver};
+use super::storage::{self, RegistryStorage};
 use crate::error::{Result, RuneError};
+use regex::Regex;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, LazyLock};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 
 /// OCI Distribution API version
 pub const API_VERSION: &str = "registry/2.0";
 
+/// Repository name grammar from the Distribution spec
+static NAME_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[a-z0-9]+((\.|_|__|-+)[a-z0-9]+)*(/[a-z0-9]+((\.|_|__|-+)[a-z0-9]+)*)*$")
+        .expect("valid regex")
+});
+
+/// Tag grammar from the Distribution spec
+static TAG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}$").expect("valid regex"));
+
+/// Digests this registry can verify
+static DIGEST_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^sha256:[a-f0-9]{64}$").expect("valid regex"));
+
 /// Supported media types
 pub mod media_types {
     pub const MANIFEST_V2: &str = "application/vnd.docker.distribution.manifest.v2+json";
@@ -36,6 +62,15 @@ pub struct RegistryConfig {
     pub port: u16,
     /// Storage path
     pub storage_path: PathBuf,
+    /// Keep content in an S3 bucket instead of `storage_path`
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+    /// Mirror an upstream registry as a read-only pull-through cache
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Webhook endpoints notified of pushes and deletes
+    #[serde(default)]
+    pub notifications: Vec<EndpointConfig>,
     /// Enable TLS
     pub tls_enabled: bool,
     /// TLS certificate path
@@ -46,6 +81,12 @@ pub struct RegistryConfig {
     pub auth_enabled: bool,
     /// Realm for authentication
     pub auth_realm: String,
+    /// Authentication mode
+    #[serde(default)]
+    pub auth_mode: AuthMode,
+    /// htpasswd file with the registry's users
+    #[serde(default)]
+    pub htpasswd: Option<PathBuf>,
     /// Allow anonymous pull
     pub anonymous_pull: bool,
     /// Allow anonymous push
@@ -64,11 +105,16 @@ impl Default for RegistryConfig {
             address: "0.0.0.0".to_string(),
             port: 5000,
             storage_path: PathBuf::from("/var/lib/rune/registry"),
+            s3: None,
+            proxy: None,
+            notifications: Vec::new(),
             tls_enabled: false,
             tls_cert: None,
             tls_key: None,
             auth_enabled: false,
             auth_realm: "Rune Registry".to_string(),
+            auth_mode: AuthMode::default(),
+            htpasswd: None,
             anonymous_pull: true,
             anonymous_push: false,
             delete_enabled: true,
@@ -231,6 +277,175 @@ pub struct UploadSession {
     pub last_chunk_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// Distribution API endpoint addressed by a request path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Route {
+    /// `/token`, the bearer token endpoint
+    Token,
+    /// `/v2/`
+    Base,
+    /// `/v2/_catalog`
+    Catalog,
+    /// `/v2/_events`, the backlog of recent notifications
+    Events,
+    /// `/v2/{name}/tags/list`
+    Tags(String),
+    /// `/v2/{name}/manifests/{reference}`
+    Manifest(String, String),
+    /// `/v2/{name}/blobs/{digest}`
+    Blob(String, String),
+    /// `/v2/{name}/blobs/uploads/`
+    Uploads(String),
+    /// `/v2/{name}/blobs/uploads/{uuid}`
+    Upload(String, String),
+}
+
+impl Route {
+    /// Parse a request path; repository names may contain slashes
+    fn parse(path: &str) -> Option<Self> {
+        if path == "/token" {
+            return Some(Route::Token);
+        }
+        if path == "/v2" || path == "/v2/" {
+            return Some(Route::Base);
+        }
+        let rest = path.strip_prefix("/v2/")?;
+        let owned = |(a, b): (&str, &str)| (a.to_string(), b.to_string());
+
+        if rest == "_catalog" {
+            Some(Route::Catalog)
+        } else if rest == "_events" {
+            Some(Route::Events)
+        } else if let Some(name) = rest.strip_suffix("/tags/list") {
+            Some(Route::Tags(name.to_string()))
+        } else if let Some(name) = rest
+            .strip_suffix("/blobs/uploads/")
+            .or_else(|| rest.strip_suffix("/blobs/uploads"))
+        {
+            Some(Route::Uploads(name.to_string()))
+        } else if let Some(parts) = rest.rsplit_once("/blobs/uploads/") {
+            let (name, uuid) = owned(parts);
+            Some(Route::Upload(name, uuid))
+        } else if let Some(parts) = rest.rsplit_once("/manifests/") {
+            let (name, reference) = owned(parts);
+            Some(Route::Manifest(name, reference))
+        } else if let Some(parts) = rest.rsplit_once("/blobs/") {
+            let (name, digest) = owned(parts);
+            Some(Route::Blob(name, digest))
+        } else {
+            None
+        }
+    }
+
+    /// Repository the route addresses
+    fn name(&self) -> Option<&str> {
+        match self {
+            Route::Token | Route::Base | Route::Catalog | Route::Events => None,
+            Route::Tags(name)
+            | Route::Manifest(name, _)
+            | Route::Blob(name, _)
+            | Route::Uploads(name)
+            | Route::Upload(name, _) => Some(name),
+        }
+    }
+}
+
+/// Who a request comes from
+#[derive(Debug, Clone)]
+enum Identity {
+    /// Authentication is disabled
+    Open,
+    /// No credentials were presented
+    Anonymous,
+    /// A user authenticated with basic auth
+    User(String),
+    /// A client presenting a bearer token
+    Token(TokenClaim),
+}
+
+/// Resource and action a request needs, e.g. pull on `repository:app`
+struct Access {
+    resource_type: &'static str,
+    name: String,
+    action: Action,
+}
+
+impl Access {
+    /// Access a route needs, or `None` if any authenticated client may use it
+    fn required(route: &Route, method: &str) -> Option<Self> {
+        let (resource_type, name) = match route {
+            Route::Token | Route::Base => return None,
+            Route::Catalog | Route::Events => ("registry", "catalog".to_string()),
+            Route::Tags(name)
+            | Route::Manifest(name, _)
+            | Route::Blob(name, _)
+            | Route::Uploads(name)
+            | Route::Upload(name, _) => ("repository", name.clone()),
+        };
+        let action = match (route, method) {
+            (_, "GET" | "HEAD") => Action::Pull,
+            (Route::Manifest(..) | Route::Blob(..), "DELETE") => Action::Delete,
+            _ => Action::Push,
+        };
+        Some(Self {
+            resource_type,
+            name,
+            action,
+        })
+    }
+
+    /// Scope to ask a token for, as in `repository:app:pull,push`
+    fn scope(&self) -> String {
+        let actions = match (self.resource_type, self.action) {
+            ("registry", _) => "*",
+            (_, Action::Pull) => "pull",
+            (_, Action::Push) => "pull,push",
+            (_, Action::Delete) => "delete",
+        };
+        format!("{}:{}:{}", self.resource_type, self.name, actions)
+    }
+}
+
+/// Build an OCI error response
+fn error_response(status: u16, code: &str, message: impl Into<String>) -> Response {
+    Response::json(
+        status,
+        &ErrorResponse {
+            errors: vec![RegistryError {
+                code: code.to_string(),
+                message: message.into(),
+                detail: None,
+            }],
+        },
+    )
+}
+
+/// `Range` header value for an upload that has received `offset` bytes
+fn upload_range(offset: u64) -> String {
+    format!("0-{}", offset.saturating_sub(1))
+}
+
+/// Parse a `Content-Range` header of the form `start-end`
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let value = value
+        .trim()
+        .trim_start_matches("bytes")
+        .trim_start_matches([' ', '=']);
+    let (start, end) = value.split_once('-')?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
+
+/// Append a `Link` header pointing at the next page of a listing
+fn paginate(response: Response, path: &str, n: Option<usize>, items: &[String]) -> Response {
+    match (n, items.last()) {
+        (Some(n), Some(last)) if items.len() == n => response.header(
+            "Link",
+            format!("<{}?n={}&last={}>; rel=\"next\"", path, n, last),
+        ),
+        _ => response,
+    }
+}
+
 /// Registry server
 #[allow(dead_code)]
 pub struct RegistryServer {
@@ -240,21 +455,45 @@ pub struct RegistryServer {
     storage: Arc<RegistryStorage>,
     /// Authentication
     auth: Arc<RegistryAuth>,
-    /// Active upload sessions
-    uploads: Arc<RwLock<HashMap<String, UploadSession>>>,
+    /// Pull-through cache, when mirroring an upstream
+    proxy: Option<ProxyCache>,
+    /// Webhook notifications
+    notifier: Notifier,
+    /// ID of this registry process in events
+    instance_id: String,
 }
 
 impl RegistryServer {
     /// Create a new registry server
     pub fn new(config: RegistryConfig) -> Result<Self> {
-        let storage = Arc::new(RegistryStorage::new(config.storage_path.clone())?);
-        let auth = Arc::new(RegistryAuth::new());
+        let storage = match config.s3 {
+            Some(ref s3) => RegistryStorage::with_driver(Arc::new(S3Driver::new(s3.clone())?)),
+            None => RegistryStorage::new(config.storage_path.clone())?,
+        };
+        let storage = Arc::new(storage);
+        let auth = RegistryAuth::with_config(AuthConfig {
+            enabled: config.auth_enabled,
+            mode: config.auth_mode,
+            realm: config.auth_realm.clone(),
+            ..AuthConfig::default()
+        });
+        if let Some(ref htpasswd) = config.htpasswd {
+            auth.load_htpasswd(htpasswd)?;
+        }
+        let auth = Arc::new(auth);
+        let proxy = match config.proxy {
+            Some(ref proxy) => Some(ProxyCache::new(proxy, storage.clone())?),
+            None => None,
+        };
+        let notifier = Notifier::new(config.notifications.clone())?;
 
         Ok(Self {
             config,
             storage,
             auth,
-            uploads: Arc::new(RwLock::new(HashMap::new())),
+            proxy,
+            notifier,
+            instance_id: uuid::Uuid::new_v4().to_string(),
         })
     }
 
@@ -305,7 +544,10 @@ impl RegistryServer {
         n: Option<usize>,
         last: Option<String>,
     ) -> Result<TagsListResponse> {
-        let tags = self.storage.list_tags(name).await?;
+        let tags = match self.proxy {
+            Some(ref proxy) => proxy.tags(name).await?,
+            None => self.storage.list_tags(name).await?,
+        };
 
         let mut filtered: Vec<String> = tags
             .into_iter()
@@ -332,12 +574,21 @@ impl RegistryServer {
 
     /// Check if manifest exists (HEAD /v2/{name}/manifests/{reference})
     pub async fn manifest_exists(&self, name: &str, reference: &str) -> Result<(String, u64)> {
-        self.storage.get_manifest_info(name, reference).await
+        match self.proxy {
+            Some(ref proxy) => {
+                let (content_type, body) = proxy.manifest(name, reference).await?;
+                Ok((content_type, body.len() as u64))
+            }
+            None => self.storage.get_manifest_info(name, reference).await,
+        }
     }
 
     /// Get manifest (GET /v2/{name}/manifests/{reference})
     pub async fn get_manifest(&self, name: &str, reference: &str) -> Result<(String, Vec<u8>)> {
-        self.storage.get_manifest(name, reference).await
+        match self.proxy {
+            Some(ref proxy) => proxy.manifest(name, reference).await,
+            None => self.storage.get_manifest(name, reference).await,
+        }
     }
 
     /// Put manifest (PUT /v2/{name}/manifests/{reference})
@@ -382,12 +633,18 @@ impl RegistryServer {
 
     /// Check if blob exists (HEAD /v2/{name}/blobs/{digest})
     pub async fn blob_exists(&self, name: &str, digest: &str) -> Result<u64> {
-        self.storage.get_blob_size(name, digest).await
+        match self.proxy {
+            Some(ref proxy) => proxy.blob_size(name, digest).await,
+            None => self.storage.get_blob_size(name, digest).await,
+        }
     }
 
     /// Get blob (GET /v2/{name}/blobs/{digest})
     pub async fn get_blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
-        self.storage.get_blob(name, digest).await
+        match self.proxy {
+            Some(ref proxy) => proxy.blob(name, digest).await,
+            None => self.storage.get_blob(name, digest).await,
+        }
     }
 
     /// Delete blob (DELETE /v2/{name}/blobs/{digest})
@@ -424,27 +681,16 @@ impl RegistryServer {
 
         // Create upload session
         let uuid = uuid::Uuid::new_v4().to_string();
-        let session = UploadSession {
-            uuid: uuid.clone(),
-            repository: name.to_string(),
-            offset: 0,
-            started_at: chrono::Utc::now(),
-            last_chunk_at: chrono::Utc::now(),
-        };
-
-        self.storage.create_upload(&uuid).await?;
-
-        let mut uploads = self.uploads.write().await;
-        uploads.insert(uuid.clone(), session);
+        self.storage.create_upload(&uuid, name).await?;
 
         Ok((uuid, None))
     }
 
     /// Get upload status (GET /v2/{name}/blobs/uploads/{uuid})
-    pub async fn get_upload_status(&self, _name: &str, uuid: &str) -> Result<u64> {
-        let uploads = self.uploads.read().await;
-        let session = uploads
-            .get(uuid)
+    pub async fn get_upload_status(&self, name: &str, uuid: &str) -> Result<u64> {
+        let session = self
+            .upload_session(name, uuid)
+            .await
             .ok_or_else(|| RuneError::Internal(format!("Upload {} not found", uuid)))?;
 
         Ok(session.offset)
@@ -453,14 +699,14 @@ impl RegistryServer {
     /// Upload chunk (PATCH /v2/{name}/blobs/uploads/{uuid})
     pub async fn upload_chunk(
         &self,
-        _name: &str,
+        name: &str,
         uuid: &str,
         data: Vec<u8>,
         content_range: Option<(u64, u64)>,
     ) -> Result<u64> {
-        let mut uploads = self.uploads.write().await;
-        let session = uploads
-            .get_mut(uuid)
+        let session = self
+            .upload_session(name, uuid)
+            .await
             .ok_or_else(|| RuneError::Internal(format!("Upload {} not found", uuid)))?;
 
         // Validate range if provided
@@ -476,11 +722,7 @@ impl RegistryServer {
         // Append data to upload
         self.storage.append_upload(uuid, &data).await?;
 
-        // Update session
-        session.offset += data.len() as u64;
-        session.last_chunk_at = chrono::Utc::now();
-
-        Ok(session.offset)
+        Ok(session.offset + data.len() as u64)
     }
 
     /// Complete upload (PUT /v2/{name}/blobs/uploads/{uuid})
@@ -507,19 +749,830 @@ impl RegistryServer {
             )));
         }
 
-        // Remove upload session
-        let mut uploads = self.uploads.write().await;
-        uploads.remove(uuid);
-
         Ok(actual_digest)
     }
 
     /// Cancel upload (DELETE /v2/{name}/blobs/uploads/{uuid})
     pub async fn cancel_upload(&self, _name: &str, uuid: &str) -> Result<()> {
-        self.storage.delete_upload(uuid).await?;
+        self.storage.delete_upload(uuid).await
+    }
 
-        let mut uploads = self.uploads.write().await;
-        uploads.remove(uuid);
+    /// Look up an upload session of a repository
+    async fn upload_session(&self, name: &str, uuid: &str) -> Option<UploadSession> {
+        // Session IDs end up in storage keys, so only accept our own
+        uuid::Uuid::parse_str(uuid).ok()?;
+        self.storage
+            .upload_session(uuid)
+            .await
+            .ok()
+            .flatten()
+            .filter(|s| s.repository == name)
+    }
+
+    /// Check that every blob or manifest a manifest references is present
+    async fn check_references(&self, name: &str, content_type: &str, body: &[u8]) -> Result<()> {
+        let missing = |digest: &str| {
+            RuneError::ImageNotFound(format!("{} references unknown {}", name, digest))
+        };
+        match content_type {
+            media_types::OCI_INDEX_V1 | media_types::MANIFEST_LIST_V2 => {
+                let index: ImageIndex = serde_json::from_slice(body)?;
+                for manifest in &index.manifests {
+                    if self
+                        .storage
+                        .get_manifest(name, &manifest.digest)
+                        .await
+                        .is_err()
+                    {
+                        return Err(missing(&manifest.digest));
+                    }
+                }
+            }
+            media_types::OCI_MANIFEST_V1 | media_types::MANIFEST_V2 => {
+                let manifest: ImageManifest = serde_json::from_slice(body)?;
+                for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+                    if self.storage.blob_exists(name, &blob.digest).await.is_err() {
+                        return Err(missing(&blob.digest));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Actions anonymous clients may take
+    fn anonymous_actions(&self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if self.config.anonymous_pull {
+            actions.push(Action::Pull);
+        }
+        if self.config.anonymous_push {
+            actions.push(Action::Push);
+        }
+        actions
+    }
+
+    /// Work out who sent a request; `None` if its credentials are invalid
+    fn identify(&self, request: &Request) -> Option<Identity> {
+        if !self.config.auth_enabled {
+            return Some(Identity::Open);
+        }
+        let Some(authorization) = request.header("authorization") else {
+            return Some(Identity::Anonymous);
+        };
+
+        let (scheme, credentials) = authorization.split_once(' ').unwrap_or((authorization, ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            use base64::Engine;
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(credentials.trim())
+                .ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            self.auth
+                .verify_credentials(username, password)
+                .ok()
+                .filter(|ok| *ok)
+                .map(|_| Identity::User(username.to_string()))
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            self.auth
+                .verify_token(credentials.trim())
+                .ok()
+                .map(Identity::Token)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether a client may take an action on a resource
+    fn allows(&self, identity: &Identity, resource_type: &str, name: &str, action: Action) -> bool {
+        let catalog = resource_type == "registry";
+        match identity {
+            Identity::Open => true,
+            Identity::Anonymous => self.anonymous_actions().contains(&action),
+            Identity::User(user) => {
+                let name = if catalog { "*" } else { name };
+                self.auth.is_allowed(user, name, action).unwrap_or(false)
+            }
+            Identity::Token(claim) => {
+                let action = if catalog { "*" } else { action.as_str() };
+                claim.allows(resource_type, name, action)
+            }
+        }
+    }
+
+    /// URL of the registry as seen by the client
+    fn base_url(&self, request: &Request) -> String {
+        let scheme = if self.config.tls_enabled {
+            "https"
+        } else {
+            "http"
+        };
+        let host = request
+            .header("host")
+            .map(|h| h.to_string())
+            .unwrap_or_else(|| format!("{}:{}", self.config.address, self.config.port));
+        format!("{}://{}", scheme, host)
+    }
+
+    /// URL of the token endpoint as seen by the client
+    fn token_url(&self, request: &Request) -> String {
+        format!("{}/token", self.base_url(request))
+    }
+
+    /// Event target for content of a repository
+    fn target(name: &str, digest: &str) -> Target {
+        Target {
+            media_type: None,
+            digest: digest.to_string(),
+            size: None,
+            repository: name.to_string(),
+            from_repository: None,
+            url: String::new(),
+            tag: None,
+        }
+    }
+
+    /// Event target for a stored blob
+    async fn blob_target(&self, name: &str, digest: &str, request: &Request) -> Target {
+        Target {
+            media_type: Some("application/octet-stream".to_string()),
+            size: self.storage.get_blob_size(name, digest).await.ok(),
+            url: format!("{}/v2/{}/blobs/{}", self.base_url(request), name, digest),
+            ..Self::target(name, digest)
+        }
+    }
+
+    /// Queue a notification of a change a request made
+    fn notify(&self, action: EventAction, target: Target, request: &Request, identity: &Identity) {
+        let actor = match identity {
+            Identity::User(user) => user.clone(),
+            Identity::Token(claim) => claim.sub.clone(),
+            Identity::Open | Identity::Anonymous => String::new(),
+        };
+        self.notifier.notify(Event {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: chrono::Utc::now(),
+            action,
+            target,
+            request: RequestRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                addr: request
+                    .remote_addr
+                    .map(|a| a.to_string())
+                    .unwrap_or_default(),
+                host: request.header("host").unwrap_or_default().to_string(),
+                method: request.method.clone(),
+                user_agent: request.header("user-agent").unwrap_or_default().to_string(),
+            },
+            actor: Actor { name: actor },
+            source: Source {
+                addr: format!("{}:{}", self.config.address, self.config.port),
+                instance_id: self.instance_id.clone(),
+            },
+        });
+    }
+
+    /// Recent notifications and their delivery state, oldest first
+    pub fn events(&self) -> Vec<EventRecord> {
+        self.notifier.backlog()
+    }
+
+    /// Ask the client to authenticate, optionally for a scope
+    fn unauthorized(
+        &self,
+        request: &Request,
+        scope: Option<String>,
+        identity: &Identity,
+    ) -> Response {
+        let mut challenge = self
+            .auth
+            .www_authenticate(&self.token_url(request), scope.as_deref());
+        if matches!(identity, Identity::Token(_)) {
+            challenge.push_str(r#",error="insufficient_scope""#);
+        }
+        error_response(401, error_codes::UNAUTHORIZED, "Authentication required")
+            .header("WWW-Authenticate", challenge)
+    }
+
+    /// Handle a Distribution API request
+    pub async fn handle(&self, request: &Request) -> Response {
+        let response = match Route::parse(&request.path) {
+            None => error_response(404, error_codes::NAME_UNKNOWN, "Unknown endpoint"),
+            Some(route) => match route.name() {
+                Some(name) if !NAME_RE.is_match(name) => error_response(
+                    400,
+                    error_codes::NAME_INVALID,
+                    format!("Invalid repository name: {}", name),
+                ),
+                _ => self.authorize(route, request).await,
+            },
+        };
+
+        let response = response.header("Docker-Distribution-API-Version", API_VERSION);
+        if request.method == "HEAD" {
+            response.without_body()
+        } else {
+            response
+        }
+    }
+
+    /// Check the client's credentials and access, then handle the request
+    async fn authorize(&self, route: Route, request: &Request) -> Response {
+        let Some(identity) = self.identify(request) else {
+            return self.unauthorized(request, None, &Identity::Anonymous);
+        };
+
+        match (&route, Access::required(&route, &request.method)) {
+            (Route::Token, _) => return self.handle_token(request, &identity),
+            (Route::Base, _) if matches!(identity, Identity::Anonymous) => {
+                return self.unauthorized(request, None, &identity);
+            }
+            (_, Some(access))
+                if !self.allows(&identity, access.resource_type, &access.name, access.action) =>
+            {
+                return match identity {
+                    Identity::User(user) => error_response(
+                        403,
+                        error_codes::DENIED,
+                        format!(
+                            "{} may not {} {}",
+                            user,
+                            access.action.as_str(),
+                            access.name
+                        ),
+                    ),
+                    _ => self.unauthorized(request, Some(access.scope()), &identity),
+                };
+            }
+            _ => {}
+        }
+
+        self.dispatch(route, request, &identity).await
+    }
+
+    /// Issue a bearer token (GET /token)
+    fn handle_token(&self, request: &Request, identity: &Identity) -> Response {
+        if !self.config.auth_enabled || self.config.auth_mode != AuthMode::Token {
+            return error_response(
+                404,
+                error_codes::UNSUPPORTED,
+                "Token authentication is not enabled",
+            );
+        }
+        if request.method != "GET" {
+            return Self::method_not_allowed();
+        }
+
+        let username = match identity {
+            Identity::User(user) => Some(user.as_str()),
+            _ => None,
+        };
+        let requested = request
+            .query_all("scope")
+            .into_iter()
+            .flat_map(auth::parse_scope)
+            .collect();
+
+        match self
+            .auth
+            .grant(username, requested, &self.anonymous_actions())
+            .and_then(|access| self.auth.issue_token(username.unwrap_or_default(), access))
+        {
+            Ok(token) => Response::json(200, &token),
+            Err(e) => error_response(500, error_codes::UNSUPPORTED, e.to_string()),
+        }
+    }
+
+    async fn dispatch(&self, route: Route, request: &Request, identity: &Identity) -> Response {
+        let method = request.method.as_str();
+        if self.proxy.is_some() && !matches!(method, "GET" | "HEAD") {
+            return error_response(
+                405,
+                error_codes::UNSUPPORTED,
+                "A pull-through cache is read-only",
+            );
+        }
+        match (route, method) {
+            (Route::Base, "GET" | "HEAD") => match self.check_api().await {
+                Ok(()) => Response::json(200, &serde_json::json!({})),
+                Err(e) => error_response(500, error_codes::UNSUPPORTED, e.to_string()),
+            },
+            (Route::Catalog, "GET") => self.handle_catalog(request).await,
+            (Route::Events, "GET") => {
+                Response::json(200, &serde_json::json!({ "events": self.events() }))
+            }
+            (Route::Tags(name), "GET") => self.handle_tags(&name, request).await,
+            (Route::Manifest(name, reference), _) => {
+                if !TAG_RE.is_match(&reference) && !DIGEST_RE.is_match(&reference) {
+                    return error_response(
+                        400,
+                        error_codes::MANIFEST_INVALID,
+                        format!("Invalid reference: {}", reference),
+                    );
+                }
+                match method {
+                    "GET" | "HEAD" => self.handle_get_manifest(&name, &reference).await,
+                    "PUT" => {
+                        self.handle_put_manifest(&name, &reference, request, identity)
+                            .await
+                    }
+                    "DELETE" => {
+                        self.handle_delete_manifest(&name, &reference, request, identity)
+                            .await
+                    }
+                    _ => Self::method_not_allowed(),
+                }
+            }
+            (Route::Blob(name, digest), _) => {
+                if !DIGEST_RE.is_match(&digest) {
+                    return error_response(
+                        400,
+                        error_codes::DIGEST_INVALID,
+                        format!("Invalid digest: {}", digest),
+                    );
+                }
+                match method {
+                    "GET" | "HEAD" => self.handle_get_blob(&name, &digest, method).await,
+                    "DELETE" => {
+                        self.handle_delete_blob(&name, &digest, request, identity)
+                            .await
+                    }
+                    _ => Self::method_not_allowed(),
+                }
+            }
+            (Route::Uploads(name), "POST") => {
+                self.handle_start_upload(&name, request, identity).await
+            }
+            (Route::Upload(name, uuid), _) => {
+                let Some(session) = self.upload_session(&name, &uuid).await else {
+                    return error_response(
+                        404,
+                        error_codes::BLOB_UPLOAD_UNKNOWN,
+                        format!("Upload {} not found", uuid),
+                    );
+                };
+                match method {
+                    "GET" => Self::upload_accepted(&name, &session, 204),
+                    "PATCH" => self.handle_upload_chunk(&name, &uuid, request).await,
+                    "PUT" => {
+                        self.handle_complete_upload(&name, &uuid, request, identity)
+                            .await
+                    }
+                    "DELETE" => match self.cancel_upload(&name, &uuid).await {
+                        Ok(()) => Response::new(204),
+                        Err(e) => {
+                            error_response(500, error_codes::BLOB_UPLOAD_INVALID, e.to_string())
+                        }
+                    },
+                    _ => Self::method_not_allowed(),
+                }
+            }
+            _ => Self::method_not_allowed(),
+        }
+    }
+
+    fn method_not_allowed() -> Response {
+        error_response(405, error_codes::UNSUPPORTED, "Method not allowed")
+    }
+
+    /// Response for an upload session that is ready for more data
+    fn upload_accepted(name: &str, session: &UploadSession, status: u16) -> Response {
+        Response::new(status)
+            .header(
+                "Location",
+                format!("/v2/{}/blobs/uploads/{}", name, session.uuid),
+            )
+            .header("Range", upload_range(session.offset))
+            .header("Docker-Upload-UUID", session.uuid.clone())
+    }
+
+    /// Response for a blob that has been stored
+    fn blob_created(name: &str, digest: &str) -> Response {
+        Response::new(201)
+            .header("Location", format!("/v2/{}/blobs/{}", name, digest))
+            .header("Docker-Content-Digest", digest)
+    }
+
+    async fn handle_catalog(&self, request: &Request) -> Response {
+        let n = request.query("n").and_then(|n| n.parse().ok());
+        let last = request.query("last").map(|l| l.to_string());
+        match self.list_repositories(n, last).await {
+            Ok(catalog) => {
+                let response = Response::json(200, &catalog);
+                paginate(response, "/v2/_catalog", n, &catalog.repositories)
+            }
+            Err(e) => error_response(500, error_codes::UNSUPPORTED, e.to_string()),
+        }
+    }
+
+    async fn handle_tags(&self, name: &str, request: &Request) -> Response {
+        let n = request.query("n").and_then(|n| n.parse().ok());
+        let last = request.query("last").map(|l| l.to_string());
+        match self.list_tags(name, n, last).await {
+            Ok(tags) => {
+                let path = format!("/v2/{}/tags/list", name);
+                let response = Response::json(200, &tags);
+                paginate(response, &path, n, &tags.tags)
+            }
+            Err(_) => error_response(
+                404,
+                error_codes::NAME_UNKNOWN,
+                format!("Repository {} not found", name),
+            ),
+        }
+    }
+
+    async fn handle_get_manifest(&self, name: &str, reference: &str) -> Response {
+        match self.get_manifest(name, reference).await {
+            Ok((content_type, body)) => {
+                let digest = storage::digest(&body);
+                Response::new(200)
+                    .with_body(&content_type, body)
+                    .header("Docker-Content-Digest", digest)
+            }
+            Err(_) => error_response(
+                404,
+                error_codes::MANIFEST_UNKNOWN,
+                format!("Manifest {}:{} not found", name, reference),
+            ),
+        }
+    }
+
+    async fn handle_put_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+        request: &Request,
+        identity: &Identity,
+    ) -> Response {
+        let body = request.body.clone();
+        if body.len() > self.config.max_manifest_size {
+            return error_response(
+                413,
+                error_codes::SIZE_INVALID,
+                format!("Manifest exceeds {} bytes", self.config.max_manifest_size),
+            );
+        }
+
+        // Fall back to the mediaType the manifest declares
+        let content_type = match request.header("content-type") {
+            Some(ct) if !ct.is_empty() => ct.to_string(),
+            _ => serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|v| v["mediaType"].as_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| media_types::OCI_MANIFEST_V1.to_string()),
+        };
+
+        let digest = storage::digest(&body);
+        if DIGEST_RE.is_match(reference) && reference != digest {
+            return error_response(
+                400,
+                error_codes::DIGEST_INVALID,
+                format!("Manifest digest {} does not match {}", digest, reference),
+            );
+        }
+        if let Err(e) = self.validate_manifest(&content_type, &body) {
+            return error_response(400, error_codes::MANIFEST_INVALID, e.to_string());
+        }
+        if let Err(e) = self.check_references(name, &content_type, &body).await {
+            return error_response(400, error_codes::MANIFEST_BLOB_UNKNOWN, e.to_string());
+        }
+
+        let size = body.len() as u64;
+        match self
+            .put_manifest(name, reference, &content_type, body)
+            .await
+        {
+            Ok(digest) => {
+                let target = Target {
+                    media_type: Some(content_type),
+                    size: Some(size),
+                    url: format!(
+                        "{}/v2/{}/manifests/{}",
+                        self.base_url(request),
+                        name,
+                        digest
+                    ),
+                    tag: (!DIGEST_RE.is_match(reference)).then(|| reference.to_string()),
+                    ..Self::target(name, &digest)
+                };
+                self.notify(EventAction::Push, target, request, identity);
+                Response::new(201)
+                    .header("Location", format!("/v2/{}/manifests/{}", name, digest))
+                    .header("Docker-Content-Digest", digest)
+            }
+            Err(e) => error_response(400, error_codes::MANIFEST_INVALID, e.to_string()),
+        }
+    }
+
+    async fn handle_delete_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+        request: &Request,
+        identity: &Identity,
+    ) -> Response {
+        // A deleted tag's digest can no longer be looked up afterwards
+        let digest = match self.storage.get_manifest(name, reference).await {
+            Ok((_, body)) => storage::digest(&body),
+            Err(_) => reference.to_string(),
+        };
+        match self.delete_manifest(name, reference).await {
+            Ok(()) => {
+                let target = Target {
+                    tag: (!DIGEST_RE.is_match(reference)).then(|| reference.to_string()),
+                    ..Self::target(name, &digest)
+                };
+                self.notify(EventAction::Delete, target, request, identity);
+                Response::new(202)
+            }
+            Err(RuneError::PermissionDenied(e)) => error_response(405, error_codes::UNSUPPORTED, e),
+            Err(_) => error_response(
+                404,
+                error_codes::MANIFEST_UNKNOWN,
+                format!("Manifest {}:{} not found", name, reference),
+            ),
+        }
+    }
+
+    async fn handle_get_blob(&self, name: &str, digest: &str, method: &str) -> Response {
+        let blob_unknown = || {
+            error_response(
+                404,
+                error_codes::BLOB_UNKNOWN,
+                format!("Blob {} not found", digest),
+            )
+        };
+        let response = if method == "HEAD" {
+            match self.blob_exists(name, digest).await {
+                Ok(size) => Response::new(200)
+                    .header("Content-Type", "application/octet-stream")
+                    .header("Content-Length", size.to_string()),
+                Err(_) => return blob_unknown(),
+            }
+        } else {
+            match self.get_blob(name, digest).await {
+                Ok(body) => Response::new(200).with_body("application/octet-stream", body),
+                Err(_) => return blob_unknown(),
+            }
+        };
+        response.header("Docker-Content-Digest", digest)
+    }
+
+    async fn handle_delete_blob(
+        &self,
+        name: &str,
+        digest: &str,
+        request: &Request,
+        identity: &Identity,
+    ) -> Response {
+        match self.delete_blob(name, digest).await {
+            Ok(()) => {
+                let target = Self::target(name, digest);
+                self.notify(EventAction::Delete, target, request, identity);
+                Response::new(202)
+            }
+            Err(RuneError::PermissionDenied(e)) => error_response(405, error_codes::UNSUPPORTED, e),
+            Err(_) => error_response(
+                404,
+                error_codes::BLOB_UNKNOWN,
+                format!("Blob {} not found", digest),
+            ),
+        }
+    }
+
+    async fn handle_start_upload(
+        &self,
+        name: &str,
+        request: &Request,
+        identity: &Identity,
+    ) -> Response {
+        let digest = request.query("digest");
+        let mount = request.query("mount");
+        let from = request.query("from");
+
+        for d in digest.iter().chain(mount.iter()) {
+            if !DIGEST_RE.is_match(d) {
+                return error_response(
+                    400,
+                    error_codes::DIGEST_INVALID,
+                    format!("Invalid digest: {}", d),
+                );
+            }
+        }
+        if let Some(from) = from.filter(|f| !NAME_RE.is_match(f)) {
+            return error_response(
+                400,
+                error_codes::NAME_INVALID,
+                format!("Invalid repository name: {}", from),
+            );
+        }
+
+        // A mount that cannot be satisfied, or from a repository the client
+        // may not pull, falls back to a regular upload
+        let (mount, from) = match (mount, from) {
+            (Some(mount), Some(from))
+                if self.allows(identity, "repository", from, Action::Pull) =>
+            {
+                (Some(mount.to_string()), Some(from.to_string()))
+            }
+            _ => (None, None),
+        };
+        let (uuid, mounted) = match self.start_upload(name, mount, from.clone()).await {
+            Ok(started) => started,
+            Err(e) => return error_response(500, error_codes::BLOB_UPLOAD_INVALID, e.to_string()),
+        };
+        if let Some(mounted) = mounted {
+            let target = Target {
+                from_repository: from,
+                ..self.blob_target(name, &mounted, request).await
+            };
+            self.notify(EventAction::Mount, target, request, identity);
+            return Self::blob_created(name, &mounted);
+        }
+
+        // Monolithic upload in a single POST
+        if let Some(digest) = digest {
+            return match self
+                .complete_upload(name, &uuid, digest, Some(request.body.clone()))
+                .await
+            {
+                Ok(digest) => {
+                    let target = self.blob_target(name, &digest, request).await;
+                    self.notify(EventAction::Push, target, request, identity);
+                    Self::blob_created(name, &digest)
+                }
+                Err(e) => {
+                    let _ = self.cancel_upload(name, &uuid).await;
+                    error_response(400, error_codes::DIGEST_INVALID, e.to_string())
+                }
+            };
+        }
+
+        match self.upload_session(name, &uuid).await {
+            Some(session) => Self::upload_accepted(name, &session, 202),
+            None => error_response(404, error_codes::BLOB_UPLOAD_UNKNOWN, "Upload vanished"),
+        }
+    }
+
+    async fn handle_upload_chunk(&self, name: &str, uuid: &str, request: &Request) -> Response {
+        let range = request
+            .header("content-range")
+            .and_then(parse_content_range);
+        if request.header("content-range").is_some() && range.is_none() {
+            return error_response(
+                416,
+                error_codes::BLOB_UPLOAD_INVALID,
+                "Malformed Content-Range",
+            );
+        }
+
+        match self
+            .upload_chunk(name, uuid, request.body.clone(), range)
+            .await
+        {
+            Ok(_) => match self.upload_session(name, uuid).await {
+                Some(session) => Self::upload_accepted(name, &session, 202),
+                None => error_response(404, error_codes::BLOB_UPLOAD_UNKNOWN, "Upload vanished"),
+            },
+            Err(e) => error_response(416, error_codes::BLOB_UPLOAD_INVALID, e.to_string()),
+        }
+    }
+
+    async fn handle_complete_upload(
+        &self,
+        name: &str,
+        uuid: &str,
+        request: &Request,
+        identity: &Identity,
+    ) -> Response {
+        let Some(digest) = request.query("digest").filter(|d| DIGEST_RE.is_match(d)) else {
+            return error_response(
+                400,
+                error_codes::DIGEST_INVALID,
+                "A sha256 digest is required to complete an upload",
+            );
+        };
+
+        let data = (!request.body.is_empty()).then(|| request.body.clone());
+        match self.complete_upload(name, uuid, digest, data).await {
+            Ok(digest) => {
+                let target = self.blob_target(name, &digest, request).await;
+                self.notify(EventAction::Push, target, request, identity);
+                Self::blob_created(name, &digest)
+            }
+            Err(e) => error_response(400, error_codes::DIGEST_INVALID, e.to_string()),
+        }
+    }
+
+    /// Build the TLS acceptor when TLS is enabled
+    fn tls_acceptor(&self) -> Result<Option<TlsAcceptor>> {
+        if !self.config.tls_enabled {
+            return Ok(None);
+        }
+        let tls_error = |e: &dyn std::fmt::Display| RuneError::InvalidConfig(format!("TLS: {}", e));
+        let (Some(cert), Some(key)) = (&self.config.tls_cert, &self.config.tls_key) else {
+            return Err(RuneError::InvalidConfig(
+                "TLS requires both a certificate and a key".to_string(),
+            ));
+        };
+
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| tls_error(&e))?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| tls_error(&e))?;
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| tls_error(&e))?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| tls_error(&e))?;
+
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    /// Listen on the configured address and serve until the listener fails
+    pub async fn serve(self: Arc<Self>) -> Result<()> {
+        if !self.config.auth_enabled {
+            tracing::warn!("Registry authentication is disabled; anyone who can reach it may push");
+        }
+        let listener = TcpListener::bind((self.config.address.as_str(), self.config.port)).await?;
+        tracing::info!(
+            "Registry listening on {}:{}",
+            self.config.address,
+            self.config.port
+        );
+        self.serve_listener(listener).await
+    }
+
+    /// Serve the Distribution API on a bound listener
+    pub async fn serve_listener(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        let acceptor = self.tls_acceptor()?;
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let server = self.clone();
+            let acceptor = acceptor.clone();
+
+            tokio::spawn(async move {
+                let result = match acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => server.handle_connection(stream, addr).await,
+                        Err(e) => Err(e.into()),
+                    },
+                    None => server.handle_connection(stream, addr).await,
+                };
+                if let Err(e) = result {
+                    tracing::debug!("Registry connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Serve requests on one connection until the client closes it
+    async fn handle_connection<S>(&self, stream: S, addr: SocketAddr) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        while let Some(mut request) = http::read_head(&mut reader).await? {
+            request.remote_addr = Some(addr);
+            if request
+                .header("expect")
+                .map(|e| e.eq_ignore_ascii_case("100-continue"))
+                .unwrap_or(false)
+            {
+                http::write_continue(&mut writer).await?;
+            }
+
+            if let Err(e) =
+                http::read_body(&mut reader, &mut request, self.config.max_layer_size).await
+            {
+                let response = match e {
+                    RuneError::ResourceLimit(message) => {
+                        error_response(413, error_codes::SIZE_INVALID, message)
+                    }
+                    e => error_response(400, error_codes::UNSUPPORTED, e.to_string()),
+                };
+                let response = response.header("Connection", "close");
+                http::write_response(&mut writer, &response).await?;
+                return Ok(());
+            }
+
+            tracing::debug!("{} {}", request.method, request.path);
+            let response = self.handle(&request).await;
+            http::write_response(&mut writer, &response).await?;
+
+            if request.wants_close() {
+                break;
+            }
+        }
 
         Ok(())
     }
@@ -547,6 +1600,11 @@ impl RegistryServer {
         &self.config
     }
 
+    /// Get authentication
+    pub fn auth(&self) -> &Arc<RegistryAuth> {
+        &self.auth
+    }
+
     /// Get storage
     pub fn storage(&self) -> &Arc<RegistryStorage> {
         &self.storage
@@ -582,6 +1640,611 @@ mod tests {
         assert!(server.check_api().await.is_ok());
     }
 
+    fn test_server() -> (tempfile::TempDir, RegistryServer) {
+        let temp = tempdir().unwrap();
+        let config = RegistryConfig {
+            storage_path: temp.path().to_path_buf(),
+            ..RegistryConfig::default()
+        };
+        let server = RegistryServer::new(config).unwrap();
+        (temp, server)
+    }
+
+    #[test]
+    fn test_route_parse() {
+        assert_eq!(Route::parse("/v2/"), Some(Route::Base));
+        assert_eq!(
+            Route::parse("/v2/library/nginx/tags/list"),
+            Some(Route::Tags("library/nginx".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/v2/a/b/blobs/uploads/"),
+            Some(Route::Uploads("a/b".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/v2/a/blobs/uploads/123"),
+            Some(Route::Upload("a".to_string(), "123".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/v2/a/manifests/latest"),
+            Some(Route::Manifest("a".to_string(), "latest".to_string()))
+        );
+        assert_eq!(Route::parse("/v1/_ping"), None);
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull() {
+        let (_temp, server) = test_server();
+
+        let response = server.handle(&Request::new("GET", "/v2/")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header_value("Docker-Distribution-API-Version"),
+            Some(API_VERSION)
+        );
+
+        // Chunked upload of the layer
+        let layer = b"layer contents".to_vec();
+        let layer_digest = storage::digest(&layer);
+        let response = server
+            .handle(&Request::new("POST", "/v2/library/app/blobs/uploads/"))
+            .await;
+        assert_eq!(response.status, 202);
+        let location = response.header_value("Location").unwrap().to_string();
+
+        let response = server
+            .handle(
+                &Request::new("PATCH", &location)
+                    .with_header("Content-Range", "0-4")
+                    .with_body(&layer[..5]),
+            )
+            .await;
+        assert_eq!(response.status, 202);
+        assert_eq!(response.header_value("Range"), Some("0-4"));
+
+        let response = server
+            .handle(
+                &Request::new("PATCH", &location)
+                    .with_header("Content-Range", "0-4")
+                    .with_body(&layer[5..]),
+            )
+            .await;
+        assert_eq!(response.status, 416);
+
+        let response = server
+            .handle(&Request::new("PATCH", &location).with_body(&layer[5..]))
+            .await;
+        assert_eq!(response.status, 202);
+        let response = server
+            .handle(&Request::new(
+                "PUT",
+                &format!("{}?digest={}", location, layer_digest),
+            ))
+            .await;
+        assert_eq!(response.status, 201);
+        assert_eq!(
+            response.header_value("Docker-Content-Digest"),
+            Some(layer_digest.as_str())
+        );
+
+        // Monolithic upload of the config
+        let config = b"{}".to_vec();
+        let config_digest = storage::digest(&config);
+        let response = server
+            .handle(
+                &Request::new(
+                    "POST",
+                    &format!("/v2/library/app/blobs/uploads/?digest={}", config_digest),
+                )
+                .with_body(config.clone()),
+            )
+            .await;
+        assert_eq!(response.status, 201);
+
+        let response = server
+            .handle(&Request::new(
+                "HEAD",
+                &format!("/v2/library/app/blobs/{}", layer_digest),
+            ))
+            .await;
+        assert_eq!(response.status, 200);
+        assert!(response.body.is_empty());
+        assert_eq!(
+            response.header_value("Content-Length"),
+            Some(layer.len().to_string().as_str())
+        );
+
+        // Manifests must reference known blobs
+        let manifest = |layer: &str| {
+            serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": media_types::OCI_MANIFEST_V1,
+                "config": {"mediaType": media_types::OCI_CONFIG_V1, "digest": config_digest, "size": 2},
+                "layers": [{"mediaType": media_types::OCI_LAYER_TAR_GZIP, "digest": layer, "size": 14}],
+            })
+            .to_string()
+        };
+        let unknown = storage::digest(b"missing");
+        let response = server
+            .handle(
+                &Request::new("PUT", "/v2/library/app/manifests/v1")
+                    .with_header("Content-Type", media_types::OCI_MANIFEST_V1)
+                    .with_body(manifest(&unknown)),
+            )
+            .await;
+        assert_eq!(response.status, 400);
+
+        let body = manifest(&layer_digest);
+        let response = server
+            .handle(
+                &Request::new("PUT", "/v2/library/app/manifests/v1")
+                    .with_header("Content-Type", media_types::OCI_MANIFEST_V1)
+                    .with_body(body.clone()),
+            )
+            .await;
+        assert_eq!(response.status, 201);
+        let digest = response
+            .header_value("Docker-Content-Digest")
+            .unwrap()
+            .to_string();
+        assert_eq!(digest, storage::digest(body.as_bytes()));
+
+        for reference in ["v1", digest.as_str()] {
+            let response = server
+                .handle(&Request::new(
+                    "GET",
+                    &format!("/v2/library/app/manifests/{}", reference),
+                ))
+                .await;
+            assert_eq!(response.status, 200);
+            assert_eq!(response.body, body.as_bytes());
+            assert_eq!(
+                response.header_value("Content-Type"),
+                Some(media_types::OCI_MANIFEST_V1)
+            );
+        }
+
+        let response = server
+            .handle(&Request::new("GET", "/v2/library/app/tags/list"))
+            .await;
+        let tags: TagsListResponse = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(tags.tags, vec!["v1"]);
+
+        // Deleting by digest also removes the tags pointing at it
+        let response = server
+            .handle(&Request::new(
+                "DELETE",
+                &format!("/v2/library/app/manifests/{}", digest),
+            ))
+            .await;
+        assert_eq!(response.status, 202);
+        let response = server
+            .handle(&Request::new("GET", "/v2/library/app/manifests/v1"))
+            .await;
+        assert_eq!(response.status, 404);
+
+        let response = server
+            .handle(&Request::new("GET", "/v2/../etc/tags/list"))
+            .await;
+        assert_eq!(response.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_serve_over_tcp() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_temp, server) = test_server();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(server).serve_listener(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /v2/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Docker-Distribution-API-Version: registry/2.0"));
+    }
+
+    fn auth_server(mode: AuthMode) -> (tempfile::TempDir, RegistryServer) {
+        let temp = tempdir().unwrap();
+        let htpasswd = temp.path().join("htpasswd");
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        std::fs::write(&htpasswd, format!("alice:{}\n", hash)).unwrap();
+        let config = RegistryConfig {
+            storage_path: temp.path().join("storage"),
+            auth_enabled: true,
+            auth_mode: mode,
+            htpasswd: Some(htpasswd),
+            anonymous_pull: true,
+            ..RegistryConfig::default()
+        };
+        let server = RegistryServer::new(config).unwrap();
+        (temp, server)
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        use base64::Engine;
+        let credentials = format!("{}:{}", user, password);
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let (_temp, server) = auth_server(AuthMode::Basic);
+        let upload = "/v2/app/blobs/uploads/";
+
+        let response = server.handle(&Request::new("GET", "/v2/")).await;
+        assert_eq!(response.status, 401);
+        assert_eq!(
+            response.header_value("WWW-Authenticate"),
+            Some(r#"Basic realm="Rune Registry""#)
+        );
+
+        // Anonymous clients may read but not write
+        let response = server
+            .handle(&Request::new("GET", "/v2/app/tags/list"))
+            .await;
+        assert_eq!(response.status, 404);
+        let response = server.handle(&Request::new("POST", upload)).await;
+        assert_eq!(response.status, 401);
+
+        let response = server
+            .handle(
+                &Request::new("POST", upload).with_header("Authorization", &basic("alice", "nope")),
+            )
+            .await;
+        assert_eq!(response.status, 401);
+        let response = server
+            .handle(
+                &Request::new("POST", upload)
+                    .with_header("Authorization", &basic("alice", "secret")),
+            )
+            .await;
+        assert_eq!(response.status, 202);
+    }
+
+    #[tokio::test]
+    async fn test_token_auth() {
+        let (_temp, server) = auth_server(AuthMode::Token);
+        let upload = "/v2/app/blobs/uploads/";
+
+        let response = server
+            .handle(&Request::new("POST", upload).with_header("Host", "registry:5000"))
+            .await;
+        assert_eq!(response.status, 401);
+        assert_eq!(
+            response.header_value("WWW-Authenticate"),
+            Some(
+                r#"Bearer realm="http://registry:5000/token",service="rune-registry",scope="repository:app:pull,push""#
+            )
+        );
+
+        let token = |request: Request| {
+            let server = &server;
+            async move {
+                let response = server.handle(&request).await;
+                assert_eq!(response.status, 200);
+                let token: auth::TokenResponse = serde_json::from_slice(&response.body).unwrap();
+                format!("Bearer {}", token.token)
+            }
+        };
+
+        // An anonymous token only carries pull
+        let anonymous = token(Request::new(
+            "GET",
+            "/token?service=rune-registry&scope=repository:app:pull,push",
+        ))
+        .await;
+        let response = server
+            .handle(
+                &Request::new("GET", "/v2/app/tags/list").with_header("Authorization", &anonymous),
+            )
+            .await;
+        assert_eq!(response.status, 404);
+        let response = server
+            .handle(&Request::new("POST", upload).with_header("Authorization", &anonymous))
+            .await;
+        assert_eq!(response.status, 401);
+        assert!(response
+            .header_value("WWW-Authenticate")
+            .unwrap()
+            .ends_with(r#"error="insufficient_scope""#));
+
+        let alice = token(
+            Request::new("GET", "/token?scope=repository:app:pull,push")
+                .with_header("Authorization", &basic("alice", "secret")),
+        )
+        .await;
+        let response = server
+            .handle(&Request::new("POST", upload).with_header("Authorization", &alice))
+            .await;
+        assert_eq!(response.status, 202);
+
+        // The token is scoped to the repository it was issued for
+        let response = server
+            .handle(
+                &Request::new("POST", "/v2/other/blobs/uploads/")
+                    .with_header("Authorization", &alice),
+            )
+            .await;
+        assert_eq!(response.status, 401);
+
+        let response = server
+            .handle(
+                &Request::new("GET", "/token").with_header("Authorization", &basic("alice", "x")),
+            )
+            .await;
+        assert_eq!(response.status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_blobs_are_scoped_to_repositories() {
+        let (_temp, server) = auth_server(AuthMode::Token);
+        let blob = server.storage().put_blob("y", b"layer").await.unwrap();
+
+        let response = server
+            .handle(&Request::new(
+                "GET",
+                "/token?scope=repository:x:pull,push,delete",
+            ))
+            .await;
+        let token: auth::TokenResponse = serde_json::from_slice(&response.body).unwrap();
+        let bearer = format!("Bearer {}", token.token);
+        let x = format!("/v2/x/blobs/{}", blob);
+
+        // A client of x can't read y's layer through x
+        for method in ["GET", "HEAD"] {
+            let response = server
+                .handle(&Request::new(method, &x).with_header("Authorization", &bearer))
+                .await;
+            assert_eq!(response.status, 404);
+        }
+
+        // Nor delete it out from under y
+        let response = server
+            .handle(
+                &Request::new("GET", "/token?scope=repository:x:pull,push,delete")
+                    .with_header("Authorization", &basic("alice", "secret")),
+            )
+            .await;
+        let token: auth::TokenResponse = serde_json::from_slice(&response.body).unwrap();
+        let alice = format!("Bearer {}", token.token);
+        let response = server
+            .handle(&Request::new("DELETE", &x).with_header("Authorization", &alice))
+            .await;
+        assert_eq!(response.status, 404);
+        assert!(server.storage().get_blob("y", &blob).await.is_ok());
+
+        // Mounting links the layer into x; deleting it there leaves y's
+        server.storage().mount_blob("y", "x", &blob).await.unwrap();
+        let response = server
+            .handle(&Request::new("GET", &x).with_header("Authorization", &bearer))
+            .await;
+        assert_eq!(response.status, 200);
+        server.delete_blob("x", &blob).await.unwrap();
+        assert!(server.storage().get_blob("x", &blob).await.is_err());
+        assert!(server.storage().get_blob("y", &blob).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pull_through_cache() {
+        // Upstream that only serves clients holding a bearer token
+        let (_upstream_temp, mut upstream) = auth_server(AuthMode::Token);
+        upstream.config.anonymous_pull = false;
+        let upstream = Arc::new(upstream);
+        let blob = upstream
+            .storage()
+            .put_blob("library/app", b"layer")
+            .await
+            .unwrap();
+        let manifest = |tag: &str| {
+            format!(
+                r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"{}","digest":"{}","size":5}},"layers":[],"annotations":{{"tag":"{}"}}}}"#,
+                media_types::OCI_MANIFEST_V1,
+                media_types::OCI_CONFIG_V1,
+                blob,
+                tag
+            )
+        };
+        upstream
+            .storage()
+            .put_manifest(
+                "library/app",
+                "latest",
+                media_types::OCI_MANIFEST_V1,
+                manifest("one").as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serving = tokio::spawn(upstream.clone().serve_listener(listener));
+
+        let temp = tempdir().unwrap();
+        let server = RegistryServer::new(RegistryConfig {
+            storage_path: temp.path().to_path_buf(),
+            proxy: Some(ProxyConfig {
+                username: Some("alice".to_string()),
+                password: Some("secret".to_string()),
+                ttl: 0,
+                ..ProxyConfig::new(format!("http://{}", addr))
+            }),
+            ..RegistryConfig::default()
+        })
+        .unwrap();
+        let get = |path: String| {
+            let server = &server;
+            async move { server.handle(&Request::new("GET", &path)).await }
+        };
+
+        let response = get("/v2/library/app/manifests/latest".to_string()).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, manifest("one").as_bytes());
+        let response = get(format!("/v2/library/app/blobs/{}", blob)).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"layer");
+        assert!(server
+            .storage()
+            .get_blob("library/app", &blob)
+            .await
+            .is_ok());
+        let response = get("/v2/library/app/tags/list".to_string()).await;
+        assert_eq!(response.status, 200);
+        let response = get("/v2/library/missing/manifests/latest".to_string()).await;
+        assert_eq!(response.status, 404);
+
+        // Expired tags are revalidated against the upstream
+        upstream
+            .storage()
+            .put_manifest(
+                "library/app",
+                "latest",
+                media_types::OCI_MANIFEST_V1,
+                manifest("two").as_bytes(),
+            )
+            .await
+            .unwrap();
+        let response = get("/v2/library/app/manifests/latest".to_string()).await;
+        assert_eq!(response.body, manifest("two").as_bytes());
+
+        // Pushes are rejected
+        let response = server
+            .handle(&Request::new("POST", "/v2/library/app/blobs/uploads/"))
+            .await;
+        assert_eq!(response.status, 405);
+
+        // Cached content is still served once the upstream is gone
+        serving.abort();
+        let _ = serving.await;
+        let response = get("/v2/library/app/manifests/latest".to_string()).await;
+        assert_eq!(response.body, manifest("two").as_bytes());
+        let response = get(format!("/v2/library/app/blobs/{}", blob)).await;
+        assert_eq!(response.body, b"layer");
+    }
+
+    #[tokio::test]
+    async fn test_notifications() {
+        use super::super::notifications::{DeliveryStatus, Envelope, EVENTS_MEDIA_TYPE};
+        use tokio::sync::mpsc;
+
+        // Webhook that fails its first request and records the rest
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut failed = false;
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = tokio::io::split(stream);
+                let mut reader = BufReader::new(reader);
+                while let Ok(Some(mut request)) = http::read_head(&mut reader).await {
+                    http::read_body(&mut reader, &mut request, 1 << 20)
+                        .await
+                        .unwrap();
+                    assert_eq!(request.header("content-type"), Some(EVENTS_MEDIA_TYPE));
+                    assert_eq!(request.header("x-secret"), Some("hunter2"));
+                    let status = if failed { 200 } else { 500 };
+                    failed = true;
+                    if status == 200 {
+                        let envelope: Envelope = serde_json::from_slice(&request.body).unwrap();
+                        tx.send(envelope).unwrap();
+                    }
+                    http::write_response(&mut writer, &Response::new(status))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let temp = tempdir().unwrap();
+        let mut endpoint = EndpointConfig::new("hook", format!("http://{}/events", addr));
+        endpoint.backoff_ms = 10;
+        endpoint
+            .headers
+            .insert("X-Secret".to_string(), "hunter2".to_string());
+        endpoint.ignored_media_types = vec!["application/octet-stream".to_string()];
+        let server = RegistryServer::new(RegistryConfig {
+            storage_path: temp.path().to_path_buf(),
+            notifications: vec![endpoint],
+            ..RegistryConfig::default()
+        })
+        .unwrap();
+
+        let config = b"{}".to_vec();
+        let config_digest = storage::digest(&config);
+        let response = server
+            .handle(
+                &Request::new(
+                    "POST",
+                    &format!("/v2/library/app/blobs/uploads/?digest={}", config_digest),
+                )
+                .with_body(config),
+            )
+            .await;
+        assert_eq!(response.status, 201);
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::OCI_MANIFEST_V1,
+            "config": {
+                "mediaType": media_types::OCI_CONFIG_V1,
+                "digest": config_digest,
+                "size": 2
+            },
+            "layers": []
+        }))
+        .unwrap();
+        let manifest_digest = storage::digest(&manifest);
+        let response = server
+            .handle(
+                &Request::new("PUT", "/v2/library/app/manifests/v1")
+                    .with_header("Host", "registry:5000")
+                    .with_header("User-Agent", "docker/24.0")
+                    .with_header("Content-Type", media_types::OCI_MANIFEST_V1)
+                    .with_body(manifest),
+            )
+            .await;
+        assert_eq!(response.status, 201);
+        let response = server
+            .handle(&Request::new("DELETE", "/v2/library/app/manifests/v1"))
+            .await;
+        assert_eq!(response.status, 202);
+
+        // The blob push is filtered out; the manifest push is retried
+        let push = received.recv().await.unwrap().events.remove(0);
+        assert_eq!(push.action, EventAction::Push);
+        assert_eq!(push.target.repository, "library/app");
+        assert_eq!(push.target.digest, manifest_digest);
+        assert_eq!(push.target.tag.as_deref(), Some("v1"));
+        assert_eq!(
+            push.target.url,
+            format!(
+                "http://registry:5000/v2/library/app/manifests/{}",
+                manifest_digest
+            )
+        );
+        assert_eq!(push.request.user_agent, "docker/24.0");
+        let delete = received.recv().await.unwrap().events.remove(0);
+        assert_eq!(delete.action, EventAction::Delete);
+        assert_eq!(delete.target.digest, manifest_digest);
+        assert_eq!(delete.target.tag.as_deref(), Some("v1"));
+
+        let response = server.handle(&Request::new("GET", "/v2/_events")).await;
+        assert_eq!(response.status, 200);
+        let events = server.events();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].deliveries.is_empty());
+        assert_eq!(events[1].deliveries[0].status, DeliveryStatus::Delivered);
+        assert_eq!(events[1].deliveries[0].attempts, 2);
+    }
+
     #[test]
     fn test_manifest_serialization() {
         let manifest = ImageManifest {
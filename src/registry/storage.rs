@@ -1,123 +1,133 @@
 //! Registry Storage Backend
 //!
-//! Implements storage for the OCI registry using the filesystem.
+//! Lays out blobs, manifests and upload sessions on a pluggable
+//! [`StorageDriver`], using the same layout as the Docker registry.
 
+use super::driver::{FilesystemDriver, StorageDriver, StorageFuture};
+use super::server::UploadSession;
 use crate::error::{Result, RuneError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use std::sync::Arc;
+
+/// Default content type of manifests stored without one
+const DEFAULT_MANIFEST_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Compute the sha256 digest of content, e.g. `sha256:2cf2...`
+pub fn digest(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+/// Upload session metadata kept beside the upload's data
+#[derive(Serialize, Deserialize)]
+struct UploadInfo {
+    repository: String,
+    started_at: DateTime<Utc>,
+}
 
 /// Registry storage backend
 pub struct RegistryStorage {
-    /// Root storage path
-    root: PathBuf,
+    /// Object store holding the registry's content
+    driver: Arc<dyn StorageDriver>,
 }
 
 impl RegistryStorage {
-    /// Create a new registry storage
+    /// Create a registry storage on the local filesystem
     pub fn new(root: PathBuf) -> Result<Self> {
         // Create directory structure
-        std::fs::create_dir_all(&root)?;
         std::fs::create_dir_all(root.join("blobs").join("sha256"))?;
         std::fs::create_dir_all(root.join("repositories"))?;
         std::fs::create_dir_all(root.join("uploads"))?;
 
-        Ok(Self { root })
+        Ok(Self::with_driver(Arc::new(FilesystemDriver::new(root)?)))
+    }
+
+    /// Create a registry storage on any storage driver
+    pub fn with_driver(driver: Arc<dyn StorageDriver>) -> Self {
+        Self { driver }
     }
 
-    /// Get blob path
-    fn blob_path(&self, digest: &str) -> PathBuf {
+    /// Get blob key
+    fn blob_key(&self, digest: &str) -> String {
         let hash = digest.strip_prefix("sha256:").unwrap_or(digest);
-        self.root.join("blobs").join("sha256").join(hash)
+        format!("blobs/sha256/{}", hash)
     }
 
-    /// Get repository path
-    fn repo_path(&self, name: &str) -> PathBuf {
-        self.root.join("repositories").join(name)
+    /// Get repository key
+    fn repo_key(&self, name: &str) -> String {
+        format!("repositories/{}", name)
     }
 
-    /// Get manifest path
-    fn manifest_path(&self, name: &str, reference: &str) -> PathBuf {
-        let repo = self.repo_path(name);
+    /// Get the key of a manifest revision
+    fn revision_key(&self, name: &str, digest: &str) -> String {
+        let hash = digest.strip_prefix("sha256:").unwrap_or(digest);
+        format!(
+            "{}/_manifests/revisions/sha256/{}",
+            self.repo_key(name),
+            hash
+        )
+    }
 
-        // If reference is a digest, store in _manifests/revisions
-        if reference.starts_with("sha256:") {
-            let hash = reference.strip_prefix("sha256:").unwrap_or(reference);
-            repo.join("_manifests")
-                .join("revisions")
-                .join("sha256")
-                .join(hash)
-        } else {
-            // Tag reference
-            repo.join("_manifests")
-                .join("tags")
-                .join(reference)
-                .join("current")
-        }
+    /// Get the key of a repository's link to a blob
+    ///
+    /// Blob content is shared between repositories, so a repository can
+    /// only reach the blobs it has a link to.
+    fn layer_key(&self, name: &str, digest: &str) -> String {
+        let hash = digest.strip_prefix("sha256:").unwrap_or(digest);
+        format!("{}/_layers/sha256/{}", self.repo_key(name), hash)
     }
 
-    /// Get upload path
-    fn upload_path(&self, uuid: &str) -> PathBuf {
-        self.root.join("uploads").join(uuid)
+    /// Get the key of a tag
+    fn tag_key(&self, name: &str, tag: &str) -> String {
+        format!("{}/_manifests/tags/{}", self.repo_key(name), tag)
     }
 
-    /// List all repositories
-    pub async fn list_repositories(&self) -> Result<Vec<String>> {
-        let repos_dir = self.root.join("repositories");
-        let mut repos = Vec::new();
+    /// Get upload key
+    fn upload_key(&self, uuid: &str) -> String {
+        format!("uploads/{}", uuid)
+    }
 
-        if !repos_dir.exists() {
-            return Ok(repos);
+    /// Resolve a tag or digest to the manifest's digest
+    async fn resolve(&self, name: &str, reference: &str) -> Result<String> {
+        if reference.starts_with("sha256:") {
+            return Ok(reference.to_string());
         }
+        let link = self
+            .driver
+            .get(&format!("{}/current/link", self.tag_key(name, reference)))
+            .await?
+            .ok_or_else(|| RuneError::ImageNotFound(format!("{}:{}", name, reference)))?;
+        Ok(String::from_utf8_lossy(&link).trim().to_string())
+    }
 
-        let mut entries = fs::read_dir(&repos_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    // Check for nested repositories (e.g., library/nginx)
-                    let nested = self.list_nested_repos(&entry.path(), name).await?;
-                    if nested.is_empty() {
-                        repos.push(name.to_string());
-                    } else {
-                        repos.extend(nested);
-                    }
-                }
-            }
+    /// List all repositories
+    pub async fn list_repositories(&self) -> Result<Vec<String>> {
+        let mut repos = Vec::new();
+        for name in self.driver.list("repositories").await? {
+            repos.extend(self.list_nested_repos(self.repo_key(&name), name).await?);
         }
-
         Ok(repos)
     }
 
-    /// List nested repositories
-    #[allow(clippy::only_used_in_recursion)]
-    fn list_nested_repos<'a>(
-        &'a self,
-        path: &'a PathBuf,
-        prefix: &'a str,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<String>>> + Send + 'a>> {
+    /// List nested repositories (e.g. library/nginx)
+    fn list_nested_repos(&self, key: String, prefix: String) -> StorageFuture<'_, Vec<String>> {
         Box::pin(async move {
             let mut repos = Vec::new();
 
-            // Check if this is a repo (has _manifests dir)
-            if path.join("_manifests").exists() {
-                repos.push(prefix.to_string());
-            }
-
-            let mut entries = fs::read_dir(path).await?;
-            while let Some(entry) = entries.next_entry().await? {
-                let name = entry.file_name();
-                let name_str = name.to_str().unwrap_or("");
-
-                // Skip internal directories
-                if name_str.starts_with('_') {
-                    continue;
-                }
-
-                if entry.file_type().await?.is_dir() {
-                    let nested_prefix = format!("{}/{}", prefix, name_str);
+            for child in self.driver.list(&key).await? {
+                // A repository has a _manifests directory; skip other internals
+                if child == "_manifests" {
+                    repos.push(prefix.clone());
+                } else if !child.starts_with('_') {
                     let nested = self
-                        .list_nested_repos(&entry.path(), &nested_prefix)
+                        .list_nested_repos(
+                            format!("{}/{}", key, child),
+                            format!("{}/{}", prefix, child),
+                        )
                         .await?;
                     repos.extend(nested);
                 }
@@ -129,99 +139,40 @@ impl RegistryStorage {
 
     /// List tags for a repository
     pub async fn list_tags(&self, name: &str) -> Result<Vec<String>> {
-        let tags_dir = self.repo_path(name).join("_manifests").join("tags");
-        let mut tags = Vec::new();
+        let manifests = format!("{}/_manifests", self.repo_key(name));
+        let tags = self.driver.list(&format!("{}/tags", manifests)).await?;
 
-        if !tags_dir.exists() {
+        // Object stores have no empty directories, so a repository whose
+        // tags were all deleted is recognized by its other manifest data
+        if tags.is_empty() && self.driver.list(&manifests).await?.is_empty() {
             return Err(RuneError::ImageNotFound(name.to_string()));
         }
 
-        let mut entries = fs::read_dir(&tags_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            if entry.file_type().await?.is_dir() {
-                if let Some(tag) = entry.file_name().to_str() {
-                    tags.push(tag.to_string());
-                }
-            }
-        }
-
         Ok(tags)
     }
 
     /// Get manifest info (content type and size)
-    #[allow(clippy::type_complexity)]
-    pub fn get_manifest_info<'a>(
-        &'a self,
-        name: &'a str,
-        reference: &'a str,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(String, u64)>> + Send + 'a>>
-    {
-        Box::pin(async move {
-            let path = self.manifest_path(name, reference);
-
-            if !path.exists() {
-                // Try to resolve tag to digest
-                let link_path = path.join("link");
-                if link_path.exists() {
-                    let digest = fs::read_to_string(&link_path).await?;
-                    return self.get_manifest_info(name, digest.trim()).await;
-                }
-                return Err(RuneError::ImageNotFound(format!("{}:{}", name, reference)));
-            }
-
-            let content_type_path = path.with_extension("content-type");
-            let content_type = if content_type_path.exists() {
-                fs::read_to_string(&content_type_path).await?
-            } else {
-                "application/vnd.oci.image.manifest.v1+json".to_string()
-            };
-
-            let metadata = fs::metadata(&path.join("data"))
-                .await
-                .or_else(|_| std::fs::metadata(&path))?;
-
-            Ok((content_type, metadata.len()))
-        })
+    pub async fn get_manifest_info(&self, name: &str, reference: &str) -> Result<(String, u64)> {
+        let (content_type, content) = self.get_manifest(name, reference).await?;
+        Ok((content_type, content.len() as u64))
     }
 
     /// Get manifest content
     pub async fn get_manifest(&self, name: &str, reference: &str) -> Result<(String, Vec<u8>)> {
-        let path = self.manifest_path(name, reference);
-
-        // Check for tag link
-        let link_path = if reference.starts_with("sha256:") {
-            path.join("data")
-        } else {
-            let link = path.join("link");
-            if link.exists() {
-                let digest = fs::read_to_string(&link).await?;
-                let hash = digest
-                    .trim()
-                    .strip_prefix("sha256:")
-                    .unwrap_or(digest.trim());
-                self.repo_path(name)
-                    .join("_manifests")
-                    .join("revisions")
-                    .join("sha256")
-                    .join(hash)
-                    .join("data")
-            } else {
-                return Err(RuneError::ImageNotFound(format!("{}:{}", name, reference)));
-            }
-        };
+        let revision = self.revision_key(name, &self.resolve(name, reference).await?);
 
-        if !link_path.exists() {
-            return Err(RuneError::ImageNotFound(format!("{}:{}", name, reference)));
-        }
+        let content = self
+            .driver
+            .get(&format!("{}/data", revision))
+            .await?
+            .ok_or_else(|| RuneError::ImageNotFound(format!("{}:{}", name, reference)))?;
 
-        let content = fs::read(&link_path).await?;
-
-        let content_type_path = link_path.with_file_name("content-type");
-        let content_type = if content_type_path.exists() {
-            fs::read_to_string(&content_type_path).await?
-        } else {
-            "application/vnd.oci.image.manifest.v1+json".to_string()
-        };
+        let content_type = self
+            .driver
+            .get(&format!("{}/content-type", revision))
+            .await?
+            .map(|ct| String::from_utf8_lossy(&ct).to_string())
+            .unwrap_or_else(|| DEFAULT_MANIFEST_TYPE.to_string());
 
         Ok((content_type, content))
     }
@@ -234,73 +185,64 @@ impl RegistryStorage {
         content_type: &str,
         body: &[u8],
     ) -> Result<String> {
-        // Calculate digest
-        let mut hasher = Sha256::new();
-        hasher.update(body);
-        let hash = hasher.finalize();
-        let digest = format!("sha256:{:x}", hash);
-
-        // Create repository structure
-        let repo = self.repo_path(name);
-        fs::create_dir_all(repo.join("_manifests").join("revisions").join("sha256")).await?;
-        fs::create_dir_all(repo.join("_manifests").join("tags")).await?;
+        let digest = digest(body);
 
         // Store by digest
-        let hash_str = format!("{:x}", hash);
-        let revision_path = repo
-            .join("_manifests")
-            .join("revisions")
-            .join("sha256")
-            .join(&hash_str);
-        fs::create_dir_all(&revision_path).await?;
-        fs::write(revision_path.join("data"), body).await?;
-        fs::write(revision_path.join("content-type"), content_type).await?;
+        let revision = self.revision_key(name, &digest);
+        self.driver.put(&format!("{}/data", revision), body).await?;
+        self.driver
+            .put(
+                &format!("{}/content-type", revision),
+                content_type.as_bytes(),
+            )
+            .await?;
 
         // If reference is a tag, create link
         if !reference.starts_with("sha256:") {
-            let tag_path = repo
-                .join("_manifests")
-                .join("tags")
-                .join(reference)
-                .join("current");
-            fs::create_dir_all(&tag_path).await?;
-            fs::write(tag_path.join("link"), &digest).await?;
+            let tag = self.tag_key(name, reference);
+            self.driver
+                .put(&format!("{}/current/link", tag), digest.as_bytes())
+                .await?;
 
             // Also store in index
-            let index_path = repo
-                .join("_manifests")
-                .join("tags")
-                .join(reference)
-                .join("index")
-                .join("sha256")
-                .join(&hash_str);
-            fs::create_dir_all(&index_path).await?;
-            fs::write(index_path.join("link"), &digest).await?;
+            let hash = digest.trim_start_matches("sha256:");
+            self.driver
+                .put(
+                    &format!("{}/index/sha256/{}/link", tag, hash),
+                    digest.as_bytes(),
+                )
+                .await?;
         }
 
         Ok(digest)
     }
 
     /// Delete manifest
+    ///
+    /// Deleting a tag removes only the tag. Deleting a digest removes the
+    /// revision along with every tag that points at it.
     pub async fn delete_manifest(&self, name: &str, reference: &str) -> Result<()> {
-        let path = self.manifest_path(name, reference);
+        let not_found = || RuneError::ImageNotFound(format!("{}:{}", name, reference));
 
-        if path.exists() {
-            fs::remove_dir_all(&path).await?;
+        if !reference.starts_with("sha256:") {
+            self.resolve(name, reference).await?;
+            return self.driver.delete(&self.tag_key(name, reference)).await;
         }
 
-        // If it's a tag, we don't delete the revision
-        // If it's a digest, delete the revision
-        if reference.starts_with("sha256:") {
-            let hash = reference.strip_prefix("sha256:").unwrap_or(reference);
-            let revision_path = self
-                .repo_path(name)
-                .join("_manifests")
-                .join("revisions")
-                .join("sha256")
-                .join(hash);
-            if revision_path.exists() {
-                fs::remove_dir_all(&revision_path).await?;
+        let revision = self.revision_key(name, reference);
+        if self
+            .driver
+            .stat(&format!("{}/data", revision))
+            .await?
+            .is_none()
+        {
+            return Err(not_found());
+        }
+        self.driver.delete(&revision).await?;
+
+        for tag in self.list_tags(name).await.unwrap_or_default() {
+            if self.resolve(name, &tag).await.ok().as_deref() == Some(reference) {
+                self.driver.delete(&self.tag_key(name, &tag)).await?;
             }
         }
 
@@ -308,89 +250,152 @@ impl RegistryStorage {
     }
 
     /// Check if blob exists
-    pub async fn blob_exists(&self, _name: &str, digest: &str) -> Result<()> {
-        let path = self.blob_path(digest);
-        if path.exists() {
-            Ok(())
-        } else {
-            Err(RuneError::ImageNotFound(digest.to_string()))
+    pub async fn blob_exists(&self, name: &str, digest: &str) -> Result<()> {
+        self.get_blob_size(name, digest).await.map(|_| ())
+    }
+
+    /// Check that a repository links to a blob
+    async fn check_link(&self, name: &str, digest: &str) -> Result<()> {
+        let link = format!("{}/link", self.layer_key(name, digest));
+        match self.driver.stat(&link).await? {
+            Some(_) => Ok(()),
+            None => Err(RuneError::ImageNotFound(digest.to_string())),
         }
     }
 
-    /// Get blob size
-    pub async fn get_blob_size(&self, _name: &str, digest: &str) -> Result<u64> {
-        let path = self.blob_path(digest);
-        let metadata = fs::metadata(&path)
+    /// Link a blob into a repository
+    async fn link_blob(&self, name: &str, digest: &str) -> Result<()> {
+        self.driver
+            .put(
+                &format!("{}/link", self.layer_key(name, digest)),
+                digest.as_bytes(),
+            )
             .await
-            .map_err(|_| RuneError::ImageNotFound(digest.to_string()))?;
-        Ok(metadata.len())
+    }
+
+    /// Get blob size
+    pub async fn get_blob_size(&self, name: &str, digest: &str) -> Result<u64> {
+        self.check_link(name, digest).await?;
+        self.driver
+            .stat(&self.blob_key(digest))
+            .await?
+            .ok_or_else(|| RuneError::ImageNotFound(digest.to_string()))
     }
 
     /// Get blob content
-    pub async fn get_blob(&self, _name: &str, digest: &str) -> Result<Vec<u8>> {
-        let path = self.blob_path(digest);
-        fs::read(&path)
-            .await
-            .map_err(|_| RuneError::ImageNotFound(digest.to_string()))
+    pub async fn get_blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
+        self.check_link(name, digest).await?;
+        self.driver
+            .get(&self.blob_key(digest))
+            .await?
+            .ok_or_else(|| RuneError::ImageNotFound(digest.to_string()))
+    }
+
+    /// Store a blob in a repository and return its digest
+    pub async fn put_blob(&self, name: &str, data: &[u8]) -> Result<String> {
+        let digest = digest(data);
+        self.driver.put(&self.blob_key(&digest), data).await?;
+        self.link_blob(name, &digest).await?;
+        Ok(digest)
+    }
+
+    /// List stored blobs with their sizes
+    pub async fn list_blobs(&self) -> Result<Vec<(String, u64)>> {
+        let mut blobs = Vec::new();
+        for hash in self.driver.list("blobs/sha256").await? {
+            let digest = format!("sha256:{}", hash);
+            if let Some(size) = self.driver.stat(&self.blob_key(&digest)).await? {
+                blobs.push((digest, size));
+            }
+        }
+        Ok(blobs)
     }
 
     /// Delete blob
-    pub async fn delete_blob(&self, _name: &str, digest: &str) -> Result<()> {
-        let path = self.blob_path(digest);
-        fs::remove_file(&path)
-            .await
-            .map_err(|_| RuneError::ImageNotFound(digest.to_string()))
+    ///
+    /// Only the repository's link is removed; the content may still be
+    /// used by other repositories and is left to garbage collection.
+    pub async fn delete_blob(&self, name: &str, digest: &str) -> Result<()> {
+        self.blob_exists(name, digest).await?;
+        self.driver.delete(&self.layer_key(name, digest)).await
+    }
+
+    /// Delete a blob's content, whichever repositories link to it
+    pub async fn purge_blob(&self, digest: &str) -> Result<()> {
+        self.driver.delete(&self.blob_key(digest)).await
     }
 
     /// Mount blob from another repository (cross-repo mount)
-    pub async fn mount_blob(&self, _from: &str, _to: &str, digest: &str) -> Result<()> {
-        // Blobs are content-addressed and shared, so mounting is a no-op
-        // Just verify the blob exists
-        let path = self.blob_path(digest);
-        if !path.exists() {
-            return Err(RuneError::ImageNotFound(digest.to_string()));
-        }
-        Ok(())
+    pub async fn mount_blob(&self, from: &str, to: &str, digest: &str) -> Result<()> {
+        // Blobs are content-addressed and shared, so mounting only links
+        // the existing content into the target repository
+        self.blob_exists(from, digest).await?;
+        self.link_blob(to, digest).await
     }
 
     /// Create upload session
-    pub async fn create_upload(&self, uuid: &str) -> Result<()> {
-        let path = self.upload_path(uuid);
-        fs::create_dir_all(&path).await?;
-        fs::write(path.join("data"), &[]).await?;
-        Ok(())
+    pub async fn create_upload(&self, uuid: &str, repository: &str) -> Result<()> {
+        let key = self.upload_key(uuid);
+        let info = UploadInfo {
+            repository: repository.to_string(),
+            started_at: Utc::now(),
+        };
+        self.driver.put(&format!("{}/data", key), &[]).await?;
+        self.driver
+            .put(&format!("{}/info", key), &serde_json::to_vec(&info)?)
+            .await
+    }
+
+    /// Look up an upload session
+    ///
+    /// Sessions live in storage, so any registry sharing it can continue
+    /// an upload another one started.
+    pub async fn upload_session(&self, uuid: &str) -> Result<Option<UploadSession>> {
+        let key = self.upload_key(uuid);
+        let Some(info) = self.driver.get(&format!("{}/info", key)).await? else {
+            return Ok(None);
+        };
+        let info: UploadInfo = serde_json::from_slice(&info)?;
+        let offset = self
+            .driver
+            .stat(&format!("{}/data", key))
+            .await?
+            .unwrap_or(0);
+
+        Ok(Some(UploadSession {
+            uuid: uuid.to_string(),
+            repository: info.repository,
+            offset,
+            started_at: info.started_at,
+            last_chunk_at: info.started_at,
+        }))
     }
 
     /// Append data to upload
     pub async fn append_upload(&self, uuid: &str, data: &[u8]) -> Result<()> {
-        let path = self.upload_path(uuid).join("data");
-        let mut file = fs::OpenOptions::new()
-            .append(true)
-            .open(&path)
-            .await
-            .map_err(|_| RuneError::Internal(format!("Upload {} not found", uuid)))?;
-        file.write_all(data).await?;
-        Ok(())
+        let key = self.upload_key(uuid);
+        if self.driver.stat(&format!("{}/info", key)).await?.is_none() {
+            return Err(RuneError::Internal(format!("Upload {} not found", uuid)));
+        }
+        self.driver.append(&format!("{}/data", key), data).await
     }
 
     /// Complete upload and move to blobs
     pub async fn complete_upload(
         &self,
-        _name: &str,
+        name: &str,
         uuid: &str,
         expected_digest: &str,
     ) -> Result<String> {
-        let upload_path = self.upload_path(uuid).join("data");
+        let data_key = format!("{}/data", self.upload_key(uuid));
 
         // Read and hash the content
-        let content = fs::read(&upload_path)
-            .await
-            .map_err(|_| RuneError::Internal(format!("Upload {} not found", uuid)))?;
-
-        let mut hasher = Sha256::new();
-        hasher.update(&content);
-        let hash = hasher.finalize();
-        let actual_digest = format!("sha256:{:x}", hash);
+        let content = self
+            .driver
+            .get(&data_key)
+            .await?
+            .ok_or_else(|| RuneError::Internal(format!("Upload {} not found", uuid)))?;
+        let actual_digest = digest(&content);
 
         // Verify digest
         if actual_digest != expected_digest {
@@ -400,24 +405,19 @@ impl RegistryStorage {
             )));
         }
 
-        // Move to blobs
-        let blob_path = self.blob_path(&actual_digest);
-        fs::rename(&upload_path, &blob_path).await?;
-
-        // Clean up upload directory
-        let upload_dir = self.upload_path(uuid);
-        fs::remove_dir_all(&upload_dir).await?;
+        // Move to blobs and clean up the session
+        self.driver
+            .rename(&data_key, &self.blob_key(&actual_digest))
+            .await?;
+        self.driver.delete(&self.upload_key(uuid)).await?;
+        self.link_blob(name, &actual_digest).await?;
 
         Ok(actual_digest)
     }
 
     /// Delete upload
     pub async fn delete_upload(&self, uuid: &str) -> Result<()> {
-        let path = self.upload_path(uuid);
-        if path.exists() {
-            fs::remove_dir_all(&path).await?;
-        }
-        Ok(())
+        self.driver.delete(&self.upload_key(uuid)).await
     }
 
     /// Garbage collect unreferenced blobs
@@ -448,19 +448,13 @@ impl RegistryStorage {
         }
 
         // Find unreferenced blobs
-        let blobs_dir = self.root.join("blobs").join("sha256");
         let mut deleted = Vec::new();
-
-        if blobs_dir.exists() {
-            let mut entries = fs::read_dir(&blobs_dir).await?;
-            while let Some(entry) = entries.next_entry().await? {
-                if let Some(hash) = entry.file_name().to_str() {
-                    let digest = format!("sha256:{}", hash);
-                    if !referenced.contains(&digest) && fs::remove_file(entry.path()).await.is_ok()
-                    {
-                        deleted.push(digest);
-                    }
-                }
+        for hash in self.driver.list("blobs/sha256").await? {
+            let digest = format!("sha256:{}", hash);
+            if !referenced.contains(&digest)
+                && self.driver.delete(&self.blob_key(&digest)).await.is_ok()
+            {
+                deleted.push(digest);
             }
         }
 
@@ -513,7 +507,7 @@ mod tests {
         let storage = RegistryStorage::new(temp.path().to_path_buf()).unwrap();
 
         let uuid = "test-upload-123";
-        storage.create_upload(uuid).await.unwrap();
+        storage.create_upload(uuid, "test/repo").await.unwrap();
 
         let data = b"hello world";
         storage.append_upload(uuid, data).await.unwrap();
@@ -533,4 +527,26 @@ mod tests {
         let content = storage.get_blob("test/repo", &digest).await.unwrap();
         assert_eq!(content, data);
     }
+
+    #[tokio::test]
+    async fn test_blobs_are_linked_per_repository() {
+        let temp = tempdir().unwrap();
+        let storage = RegistryStorage::new(temp.path().to_path_buf()).unwrap();
+
+        let digest = storage.put_blob("a", b"layer").await.unwrap();
+        assert!(storage.get_blob("b", &digest).await.is_err());
+        assert!(storage.get_blob_size("b", &digest).await.is_err());
+        assert!(storage.delete_blob("b", &digest).await.is_err());
+
+        storage.mount_blob("a", "b", &digest).await.unwrap();
+        assert_eq!(storage.get_blob("b", &digest).await.unwrap(), b"layer");
+
+        // Deleting from one repository leaves the other's link intact
+        storage.delete_blob("b", &digest).await.unwrap();
+        assert!(storage.get_blob("b", &digest).await.is_err());
+        assert_eq!(storage.get_blob("a", &digest).await.unwrap(), b"layer");
+
+        // Links aren't mistaken for repositories
+        assert!(storage.list_repositories().await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,236 @@
+//! Linux capabilities
+//!
+//! Containers start from Docker's default capability set, which
+//! `--cap-add` and `--cap-drop` adjust, `ALL` standing for every
+//! capability. Privileged containers get them all. The container's init
+//! process drops everything else from its bounding set and keeps only the
+//! computed set across the switch to its user.
+
+use super::syscall;
+use crate::error::{Result, RuneError};
+use crate::lsp::edit_distance;
+
+/// Capabilities containers get by default
+pub const DEFAULT_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FSETID",
+    "CAP_FOWNER",
+    "CAP_MKNOD",
+    "CAP_NET_RAW",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETFCAP",
+    "CAP_SETPCAP",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_SYS_CHROOT",
+    "CAP_KILL",
+    "CAP_AUDIT_WRITE",
+];
+
+/// Every capability, indexed by number
+pub const ALL_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Canonical name of a capability, accepting any case and no `CAP_` prefix
+///
+/// `ALL` is returned as is.
+pub fn capability_name(name: &str) -> Result<&'static str> {
+    let upper = name.trim().to_uppercase();
+    if upper == "ALL" {
+        return Ok("ALL");
+    }
+    let upper = if upper.starts_with("CAP_") {
+        upper
+    } else {
+        format!("CAP_{}", upper)
+    };
+    if let Some(capability) = ALL_CAPABILITIES.iter().find(|cap| **cap == upper) {
+        return Ok(capability);
+    }
+
+    let suggestion = ALL_CAPABILITIES
+        .iter()
+        .map(|candidate| (edit_distance(&upper, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("; did you mean {}?", candidate))
+        .unwrap_or_default();
+    Err(RuneError::InvalidConfig(format!(
+        "Unknown capability '{}'{}",
+        name, suggestion
+    )))
+}
+
+/// Number of a capability
+pub fn capability_number(name: &str) -> Option<u32> {
+    let name = capability_name(name).ok()?;
+    ALL_CAPABILITIES
+        .iter()
+        .position(|cap| *cap == name)
+        .map(|n| n as u32)
+}
+
+/// Capabilities of a container, computed like Docker does
+///
+/// Adding `ALL` keeps every capability not dropped; dropping `ALL` keeps
+/// only the added ones.
+pub fn effective_capabilities(
+    cap_add: &[String],
+    cap_drop: &[String],
+    privileged: bool,
+) -> Result<Vec<String>> {
+    let add = cap_add
+        .iter()
+        .map(|cap| capability_name(cap))
+        .collect::<Result<Vec<_>>>()?;
+    let drop = cap_drop
+        .iter()
+        .map(|cap| capability_name(cap))
+        .collect::<Result<Vec<_>>>()?;
+
+    let capabilities: Vec<&str> = if privileged {
+        ALL_CAPABILITIES.to_vec()
+    } else if add.contains(&"ALL") {
+        ALL_CAPABILITIES
+            .iter()
+            .filter(|cap| !drop.contains(cap))
+            .copied()
+            .collect()
+    } else if drop.contains(&"ALL") {
+        add
+    } else {
+        let mut capabilities: Vec<&str> = DEFAULT_CAPABILITIES
+            .iter()
+            .filter(|cap| !drop.contains(cap))
+            .copied()
+            .collect();
+        for cap in add {
+            if !capabilities.contains(&cap) {
+                capabilities.push(cap);
+            }
+        }
+        capabilities
+    };
+    Ok(capabilities.into_iter().map(str::to_string).collect())
+}
+
+/// Bit mask of capabilities
+pub fn capability_mask(capabilities: &[String]) -> u64 {
+    capabilities
+        .iter()
+        .filter_map(|cap| capability_number(cap))
+        .fold(0, |mask, n| mask | (1 << n))
+}
+
+/// Drop every capability outside the set from the bounding set
+///
+/// Call this while still root, before changing user.
+pub fn restrict_bounding_set(capabilities: &[String]) -> Result<()> {
+    let mask = capability_mask(capabilities);
+    let last = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|last| last.trim().parse::<u32>().ok())
+        .unwrap_or(ALL_CAPABILITIES.len() as u32 - 1);
+    for n in (0..=last).filter(|n| mask & (1 << n) == 0) {
+        syscall::capbset_drop(n)
+            .map_err(|e| RuneError::Runtime(format!("Failed to drop capability {}: {}", n, e)))?;
+    }
+    Ok(())
+}
+
+/// Set the permitted, effective and inheritable capabilities of the calling
+/// thread
+pub fn set_capabilities(capabilities: &[String]) -> Result<()> {
+    let mask = capability_mask(capabilities);
+    syscall::capset(mask, mask, mask)
+        .map_err(|e| RuneError::Runtime(format!("Failed to set capabilities: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_capability_names() {
+        assert_eq!(capability_name("net_admin").unwrap(), "CAP_NET_ADMIN");
+        assert_eq!(capability_name("CAP_SYS_ADMIN").unwrap(), "CAP_SYS_ADMIN");
+        assert_eq!(capability_name("all").unwrap(), "ALL");
+        assert_eq!(capability_number("SYS_ADMIN"), Some(21));
+        assert_eq!(capability_number("CAP_CHECKPOINT_RESTORE"), Some(40));
+
+        let error = capability_name("SYS_ADMN").unwrap_err().to_string();
+        assert!(error.contains("Unknown capability 'SYS_ADMN'; did you mean CAP_SYS_ADMIN?"));
+        let error = capability_name("TELEPORT").unwrap_err().to_string();
+        assert!(!error.contains("did you mean"));
+    }
+
+    #[test]
+    fn test_effective_capabilities() {
+        let default = effective_capabilities(&[], &[], false).unwrap();
+        assert_eq!(default.len(), DEFAULT_CAPABILITIES.len());
+
+        let adjusted =
+            effective_capabilities(&caps(&["net_admin"]), &caps(&["NET_RAW"]), false).unwrap();
+        assert!(adjusted.contains(&"CAP_NET_ADMIN".to_string()));
+        assert!(!adjusted.contains(&"CAP_NET_RAW".to_string()));
+        assert_eq!(adjusted.len(), DEFAULT_CAPABILITIES.len());
+
+        let only = effective_capabilities(&caps(&["chown"]), &caps(&["ALL"]), false).unwrap();
+        assert_eq!(only, ["CAP_CHOWN"]);
+
+        let all = effective_capabilities(&caps(&["ALL"]), &caps(&["SYS_ADMIN"]), false).unwrap();
+        assert_eq!(all.len(), ALL_CAPABILITIES.len() - 1);
+
+        let privileged = effective_capabilities(&[], &caps(&["ALL"]), true).unwrap();
+        assert_eq!(privileged.len(), ALL_CAPABILITIES.len());
+
+        assert!(effective_capabilities(&caps(&["SYS_FOO"]), &[], false).is_err());
+        assert_eq!(capability_mask(&caps(&["CAP_CHOWN", "CAP_KILL"])), 0b100001);
+    }
+}
@@ -0,0 +1,549 @@
+//! Container Device Interface
+//!
+//! CDI specs, which vendor tools such as `nvidia-ctk cdi generate` or AMD's
+//! container toolkit write into `/etc/cdi` and `/var/run/cdi`, name devices
+//! `vendor.com/class=name` and describe the edits a container needs to use
+//! them: device nodes, mounts, environment variables and hooks. Specs in
+//! later directories override devices of the same name in earlier ones.
+
+use super::devices;
+use super::oci::{Hook, LinuxDevice, LinuxResources, Mount, Spec};
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Directories CDI specs are read from, by increasing priority
+pub const DEFAULT_SPEC_DIRS: [&str; 2] = ["/etc/cdi", "/var/run/cdi"];
+
+/// Device class GPUs are requested by
+const GPU_CLASS: &str = "gpu";
+
+/// CDI spec file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CdiSpec {
+    /// Version of the CDI spec
+    pub cdi_version: String,
+    /// `vendor.com/class` of the devices
+    pub kind: String,
+    /// Devices of the kind
+    pub devices: Vec<CdiDevice>,
+    /// Edits needed by any of the devices
+    #[serde(default)]
+    pub container_edits: ContainerEdits,
+}
+
+/// Device of a CDI spec
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CdiDevice {
+    /// Name, unique within the kind
+    pub name: String,
+    /// Edits needed by the device
+    #[serde(default)]
+    pub container_edits: ContainerEdits,
+}
+
+/// Edits a container needs for a device
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerEdits {
+    /// Environment variables, as `KEY=value`
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Device nodes
+    #[serde(default)]
+    pub device_nodes: Vec<DeviceNode>,
+    /// Mounts
+    #[serde(default)]
+    pub mounts: Vec<CdiMount>,
+    /// Hooks
+    #[serde(default)]
+    pub hooks: Vec<CdiHook>,
+    /// Supplementary groups of the process
+    #[serde(default)]
+    pub additional_gids: Vec<u32>,
+}
+
+/// Device node of a CDI device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceNode {
+    /// Path inside the container
+    pub path: String,
+    /// Path on the host, the same as inside when unset
+    #[serde(default)]
+    pub host_path: Option<String>,
+    /// `c`, `b` or `p`, read from the host node when unset
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    /// Major number, read from the host node when unset
+    #[serde(default)]
+    pub major: Option<i64>,
+    /// Minor number, read from the host node when unset
+    #[serde(default)]
+    pub minor: Option<i64>,
+    /// Permission bits of the node
+    #[serde(default)]
+    pub file_mode: Option<u32>,
+    /// Device cgroup permissions, `rwm` when unset
+    #[serde(default)]
+    pub permissions: Option<String>,
+    /// Owner of the node
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group of the node
+    #[serde(default)]
+    pub gid: Option<u32>,
+}
+
+/// Mount of a CDI device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CdiMount {
+    /// Path on the host
+    pub host_path: String,
+    /// Path inside the container
+    pub container_path: String,
+    /// Filesystem type, a bind mount when unset
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    /// Mount options
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+/// Hook of a CDI device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CdiHook {
+    /// OCI hook the command runs as, such as `createContainer`
+    pub hook_name: String,
+    /// Absolute path of the executable
+    pub path: String,
+    /// Arguments, the first being the executable
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment, as `KEY=value`
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Seconds before the hook is aborted
+    #[serde(default)]
+    pub timeout: Option<u32>,
+}
+
+/// GPUs requested with `--gpus`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuRequest {
+    /// Every GPU
+    All,
+    /// The first GPUs
+    Count(usize),
+    /// GPUs by name or index, such as `0` or `nvidia.com/gpu=0`
+    Devices(Vec<String>),
+}
+
+impl FromStr for GpuRequest {
+    type Err = RuneError;
+
+    /// Parse `all`, a count, `count=<n|all>` or `device=<id>[,<id>...]`
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim().trim_matches('"');
+        let count = value.strip_prefix("count=").unwrap_or(value);
+        if count == "all" {
+            return Ok(Self::All);
+        }
+        if let Ok(count) = count.parse() {
+            return Ok(Self::Count(count));
+        }
+        if let Some(devices) = value.strip_prefix("device=") {
+            let devices: Vec<String> = devices
+                .split(',')
+                .map(str::trim)
+                .filter(|device| !device.is_empty())
+                .map(String::from)
+                .collect();
+            if !devices.is_empty() {
+                return Ok(Self::Devices(devices));
+            }
+        }
+        Err(RuneError::InvalidConfig(format!(
+            "Invalid GPU request '{}'; use all, a count or device=<id>[,<id>...]",
+            value
+        )))
+    }
+}
+
+/// Whether a device name is a fully qualified CDI name,
+/// `vendor.com/class=name`
+pub fn is_qualified_name(name: &str) -> bool {
+    let Some((kind, device)) = name.split_once('=') else {
+        return false;
+    };
+    let Some((vendor, class)) = kind.split_once('/') else {
+        return false;
+    };
+    let valid = |part: &str, extra: &str| {
+        part.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+    };
+    valid(vendor, "-_.") && valid(class, "-_") && valid(device, "-_.:")
+}
+
+/// Devices of the CDI specs in a set of directories
+#[derive(Debug, Default)]
+pub struct Registry {
+    /// Specs, in the order they were read
+    specs: Vec<CdiSpec>,
+    /// Qualified device names, with their spec and device index
+    devices: BTreeMap<String, (usize, usize)>,
+}
+
+impl Registry {
+    /// Read the specs in directories, skipping those that don't exist
+    ///
+    /// Invalid spec files are skipped with a warning, so one broken vendor
+    /// spec doesn't make the devices of others unusable.
+    pub fn load(dirs: &[PathBuf]) -> Result<Self> {
+        let mut registry = Self::default();
+        for dir in dirs {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            let mut paths: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    matches!(
+                        path.extension().and_then(|ext| ext.to_str()),
+                        Some("json" | "yaml" | "yml")
+                    )
+                })
+                .collect();
+            paths.sort();
+            for path in paths {
+                match read_spec(&path) {
+                    Ok(spec) => registry.add(spec),
+                    Err(e) => tracing::warn!("Skipping CDI spec {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Add a spec, overriding devices of the same name
+    pub fn add(&mut self, spec: CdiSpec) {
+        let index = self.specs.len();
+        for (device, entry) in spec.devices.iter().enumerate() {
+            self.devices
+                .insert(format!("{}={}", spec.kind, entry.name), (index, device));
+        }
+        self.specs.push(spec);
+    }
+
+    /// Qualified names of every device, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.devices.keys().cloned().collect()
+    }
+
+    /// Qualified names of the GPUs a request selects
+    pub fn gpus(&self, request: &GpuRequest) -> Result<Vec<String>> {
+        let gpus: Vec<&String> = self
+            .devices
+            .keys()
+            .filter(|name| {
+                name.split_once('=')
+                    .and_then(|(kind, _)| kind.split_once('/'))
+                    .is_some_and(|(_, class)| class == GPU_CLASS)
+            })
+            .collect();
+        if gpus.is_empty() {
+            return Err(RuneError::InvalidConfig(
+                "No GPUs found in the CDI specs; generate them with the vendor's tools, \
+                 e.g. nvidia-ctk cdi generate"
+                    .to_string(),
+            ));
+        }
+
+        match request {
+            GpuRequest::All => Ok(gpus.into_iter().cloned().collect()),
+            GpuRequest::Count(count) if *count <= gpus.len() => {
+                Ok(gpus.into_iter().take(*count).cloned().collect())
+            }
+            GpuRequest::Count(count) => Err(RuneError::InvalidConfig(format!(
+                "Requested {} GPUs but only {} are available",
+                count,
+                gpus.len()
+            ))),
+            GpuRequest::Devices(ids) => ids
+                .iter()
+                .map(|id| {
+                    gpus.iter()
+                        .find(|name| {
+                            **name == id || name.split_once('=').is_some_and(|(_, n)| n == id)
+                        })
+                        .map(|name| name.to_string())
+                        .ok_or_else(|| {
+                            RuneError::InvalidConfig(format!("No GPU '{}' in the CDI specs", id))
+                        })
+                })
+                .collect(),
+        }
+    }
+
+    /// Check that devices are known
+    pub fn check(&self, names: &[String]) -> Result<()> {
+        let unresolved: Vec<&str> = names
+            .iter()
+            .filter(|name| !self.devices.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+        if !unresolved.is_empty() {
+            return Err(RuneError::InvalidConfig(format!(
+                "Unresolvable CDI devices: {}",
+                unresolved.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Apply the edits of devices to an OCI spec
+    pub fn apply(&self, spec: &mut Spec, names: &[String]) -> Result<()> {
+        self.check(names)?;
+
+        // Edits of a spec apply once, before those of its devices
+        let mut applied_specs = Vec::new();
+        for name in names {
+            let (index, device) = self.devices[name];
+            let cdi_spec = &self.specs[index];
+            if !applied_specs.contains(&index) {
+                applied_specs.push(index);
+                apply_edits(spec, &cdi_spec.container_edits)?;
+            }
+            apply_edits(spec, &cdi_spec.devices[device].container_edits)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_spec(path: &Path) -> Result<CdiSpec> {
+    let contents = std::fs::read_to_string(path)?;
+    let spec: CdiSpec = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&contents)?
+    } else {
+        serde_yaml::from_str(&contents).map_err(|e| RuneError::InvalidConfig(e.to_string()))?
+    };
+    if spec.kind.split_once('/').is_none() {
+        return Err(RuneError::InvalidConfig(format!(
+            "Invalid kind '{}'; expected vendor.com/class",
+            spec.kind
+        )));
+    }
+    Ok(spec)
+}
+
+fn apply_edits(spec: &mut Spec, edits: &ContainerEdits) -> Result<()> {
+    for var in &edits.env {
+        let key = var.split_once('=').map_or(var.as_str(), |(key, _)| key);
+        spec.process
+            .env
+            .retain(|existing| existing.split_once('=').map(|(k, _)| k) != Some(key));
+        spec.process.env.push(var.clone());
+    }
+
+    for node in &edits.device_nodes {
+        let host_path = node.host_path.as_deref().unwrap_or(&node.path);
+        let mut device = match (&node.kind, node.major, node.minor) {
+            (Some(kind), Some(major), Some(minor)) => LinuxDevice {
+                path: node.path.clone(),
+                kind: kind.clone(),
+                major,
+                minor,
+                file_mode: None,
+                uid: None,
+                gid: None,
+            },
+            _ => devices::host_devices(Path::new(host_path), &node.path)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    RuneError::InvalidConfig(format!("{} is not a device", host_path))
+                })?,
+        };
+        device.file_mode = node.file_mode.or(device.file_mode);
+        device.uid = node.uid.or(device.uid);
+        device.gid = node.gid.or(device.gid);
+
+        let resources = spec
+            .linux
+            .resources
+            .get_or_insert_with(LinuxResources::default);
+        resources.devices.push(devices::rule(
+            &device,
+            node.permissions.as_deref().unwrap_or("rwm"),
+        ));
+        spec.linux
+            .devices
+            .retain(|existing| existing.path != device.path);
+        spec.linux.devices.push(device);
+    }
+
+    for mount in &edits.mounts {
+        spec.mounts.push(Mount {
+            destination: mount.container_path.clone(),
+            kind: mount.kind.clone().unwrap_or_else(|| "bind".to_string()),
+            source: mount.host_path.clone(),
+            options: mount.options.clone(),
+        });
+    }
+
+    for hook in &edits.hooks {
+        spec.hooks.get_or_insert_with(Default::default).add(
+            &hook.hook_name,
+            Hook {
+                path: hook.path.clone(),
+                args: hook.args.clone(),
+                env: hook.env.clone(),
+                timeout: hook.timeout,
+            },
+        )?;
+    }
+
+    for gid in &edits.additional_gids {
+        if !spec.process.user.additional_gids.contains(gid) {
+            spec.process.user.additional_gids.push(*gid);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NVIDIA: &str = r#"
+cdiVersion: 0.6.0
+kind: nvidia.com/gpu
+devices:
+  - name: "0"
+    containerEdits:
+      deviceNodes:
+        - path: /dev/nvidia0
+          hostPath: /dev/null
+  - name: "1"
+    containerEdits:
+      deviceNodes:
+        - path: /dev/nvidia1
+          type: c
+          major: 195
+          minor: 1
+          permissions: rw
+containerEdits:
+  env:
+    - NVIDIA_VISIBLE_DEVICES=void
+  mounts:
+    - hostPath: /usr/lib/libcuda.so.1
+      containerPath: /usr/lib/libcuda.so.1
+      options: [ro, nosuid, nodev, bind]
+  hooks:
+    - hookName: createContainer
+      path: /usr/bin/nvidia-ctk
+      args: [nvidia-ctk, hook, update-ldcache]
+"#;
+
+    fn registry() -> (TempDir, Registry) {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("nvidia.yaml"), NVIDIA).unwrap();
+        std::fs::write(dir.path().join("broken.json"), "{").unwrap();
+        std::fs::write(
+            dir.path().join("vendor.json"),
+            r#"{"cdiVersion": "0.6.0", "kind": "vendor.com/fpga",
+                "devices": [{"name": "a", "containerEdits": {"env": ["FPGA=a"]}}]}"#,
+        )
+        .unwrap();
+        let registry = Registry::load(&[dir.path().to_path_buf()]).unwrap();
+        (dir, registry)
+    }
+
+    #[test]
+    fn test_gpu_request() {
+        assert_eq!("all".parse::<GpuRequest>().unwrap(), GpuRequest::All);
+        assert_eq!("2".parse::<GpuRequest>().unwrap(), GpuRequest::Count(2));
+        assert_eq!("count=all".parse::<GpuRequest>().unwrap(), GpuRequest::All);
+        assert_eq!(
+            "\"device=0,1\"".parse::<GpuRequest>().unwrap(),
+            GpuRequest::Devices(vec!["0".to_string(), "1".to_string()])
+        );
+        assert!("some".parse::<GpuRequest>().is_err());
+
+        assert!(is_qualified_name("nvidia.com/gpu=0"));
+        assert!(is_qualified_name("vendor.com/class=all"));
+        assert!(!is_qualified_name("/dev/sda"));
+        assert!(!is_qualified_name("nvidia.com/gpu"));
+    }
+
+    #[test]
+    fn test_registry() {
+        let (_dir, registry) = registry();
+        assert_eq!(
+            registry.names(),
+            ["nvidia.com/gpu=0", "nvidia.com/gpu=1", "vendor.com/fpga=a"]
+        );
+        assert_eq!(registry.gpus(&GpuRequest::All).unwrap().len(), 2);
+        assert_eq!(
+            registry.gpus(&GpuRequest::Count(1)).unwrap(),
+            ["nvidia.com/gpu=0"]
+        );
+        assert!(registry.gpus(&GpuRequest::Count(3)).is_err());
+        assert_eq!(
+            registry
+                .gpus(&GpuRequest::Devices(vec!["1".to_string()]))
+                .unwrap(),
+            ["nvidia.com/gpu=1"]
+        );
+        assert!(Registry::default().gpus(&GpuRequest::All).is_err());
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let (_dir, registry) = registry();
+        let mut spec: Spec = serde_json::from_value(serde_json::json!({
+            "ociVersion": "1.0.2",
+            "process": {"user": {"uid": 0, "gid": 0}, "args": ["sh"], "cwd": "/",
+                        "env": ["PATH=/bin", "NVIDIA_VISIBLE_DEVICES=all"]},
+            "root": {"path": "rootfs"},
+            "linux": {}
+        }))
+        .unwrap();
+
+        let names = registry.gpus(&GpuRequest::All).unwrap();
+        registry.apply(&mut spec, &names).unwrap();
+        assert_eq!(
+            spec.process.env,
+            ["PATH=/bin", "NVIDIA_VISIBLE_DEVICES=void"]
+        );
+        assert_eq!(spec.linux.devices.len(), 2);
+        // Numbers come from the host node when the spec leaves them out
+        assert_eq!(
+            (spec.linux.devices[0].major, spec.linux.devices[0].minor),
+            (1, 3)
+        );
+        let rules = &spec.linux.resources.as_ref().unwrap().devices;
+        assert_eq!(rules[1].to_v1(), "c 195:1 rw");
+        assert_eq!(spec.mounts.len(), 1);
+        assert_eq!(spec.hooks.as_ref().unwrap().create_container.len(), 1);
+
+        let error = registry
+            .apply(&mut spec, &["nvidia.com/gpu=7".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("nvidia.com/gpu=7"));
+    }
+}
@@ -3,11 +3,25 @@
 //! Provides functionality for creating and managing cgroups
 //! for container resource isolation and limits.
 
+use super::device_filter;
+use super::oci::LinuxDeviceCgroup;
+use super::syscall;
 use crate::error::{Result, RuneError};
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
 use std::path::{Path, PathBuf};
 
+/// Where cgroup hierarchies are mounted
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Controllers the container cgroups use on cgroup v2
+const V2_CONTROLLERS: &[&str] = &["cpu", "cpuset", "io", "memory", "pids"];
+
+/// How long a watch waits for events before checking its cgroup still
+/// exists, in milliseconds
+const WATCH_INTERVAL_MS: i32 = 1000;
+
 /// Cgroup version
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CgroupVersion {
@@ -109,8 +123,59 @@ pub struct CgroupConfig {
     pub pids_limit: Option<i64>,
     /// Block I/O weight
     pub blkio_weight: Option<u16>,
+    /// Block I/O rate limits, per device
+    pub blkio_throttle: Vec<BlkioThrottle>,
     /// OOM kill disable
     pub oom_kill_disable: bool,
+    /// Device access rules, in order
+    pub devices: Vec<LinuxDeviceCgroup>,
+}
+
+/// I/O rate limits of a block device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlkioThrottle {
+    pub major: i64,
+    pub minor: i64,
+    /// Read bytes per second
+    pub read_bps: Option<u64>,
+    /// Write bytes per second
+    pub write_bps: Option<u64>,
+    /// Read operations per second
+    pub read_iops: Option<u64>,
+    /// Write operations per second
+    pub write_iops: Option<u64>,
+}
+
+impl BlkioThrottle {
+    fn limits(&self) -> [(&'static str, &'static str, Option<u64>); 4] {
+        [
+            ("rbps", "blkio.throttle.read_bps_device", self.read_bps),
+            ("wbps", "blkio.throttle.write_bps_device", self.write_bps),
+            ("riops", "blkio.throttle.read_iops_device", self.read_iops),
+            ("wiops", "blkio.throttle.write_iops_device", self.write_iops),
+        ]
+    }
+
+    /// Line of the cgroup v2 `io.max` file, like `8:0 rbps=1048576`
+    pub fn io_max(&self) -> String {
+        let mut line = format!("{}:{}", self.major, self.minor);
+        for (key, _, rate) in self.limits() {
+            if let Some(rate) = rate {
+                line.push_str(&format!(" {}={}", key, rate));
+            }
+        }
+        line
+    }
+
+    /// Cgroup v1 files and the lines setting the limits
+    fn v1_files(&self) -> Vec<(&'static str, String)> {
+        self.limits()
+            .into_iter()
+            .filter_map(|(_, file, rate)| {
+                rate.map(|rate| (file, format!("{}:{} {}", self.major, self.minor, rate)))
+            })
+            .collect()
+    }
 }
 
 /// Cgroup manager for container resource limits
@@ -126,11 +191,7 @@ pub struct CgroupManager {
 impl CgroupManager {
     /// Create a new cgroup manager
     pub fn new() -> Result<Self> {
-        let version = Self::detect_version()?;
-        let base_path = match version {
-            CgroupVersion::V1 => PathBuf::from("/sys/fs/cgroup"),
-            CgroupVersion::V2 => PathBuf::from("/sys/fs/cgroup"),
-        };
+        let (version, base_path) = Self::detect_version(Path::new(CGROUP_ROOT));
         let rune_path = base_path.join("rune");
 
         Ok(Self {
@@ -140,22 +201,29 @@ impl CgroupManager {
         })
     }
 
-    /// Detect the cgroup version in use
-    fn detect_version() -> Result<CgroupVersion> {
-        // Check for cgroup v2 unified hierarchy
-        let cgroup2_path = Path::new("/sys/fs/cgroup/cgroup.controllers");
-        if cgroup2_path.exists() {
-            return Ok(CgroupVersion::V2);
+    /// Detect the cgroup version in use and the root of its hierarchy,
+    /// preferring the unified hierarchy
+    fn detect_version(root: &Path) -> (CgroupVersion, PathBuf) {
+        if is_cgroup2(root) {
+            return (CgroupVersion::V2, root.to_path_buf());
+        }
+
+        // Hybrid hosts mount the unified hierarchy beside the v1 ones; it
+        // only limits anything once controllers are bound to it
+        let unified = root.join("unified");
+        if is_cgroup2(&unified)
+            && fs::read_to_string(unified.join("cgroup.controllers"))
+                .is_ok_and(|controllers| controllers.split_whitespace().any(|c| c == "memory"))
+        {
+            return (CgroupVersion::V2, unified);
         }
 
-        // Check for cgroup v1
-        let cgroup1_path = Path::new("/sys/fs/cgroup/memory");
-        if cgroup1_path.exists() {
-            return Ok(CgroupVersion::V1);
+        if root.join("memory").exists() {
+            return (CgroupVersion::V1, root.to_path_buf());
         }
 
         // Default to v2 if we can't detect
-        Ok(CgroupVersion::V2)
+        (CgroupVersion::V2, root.to_path_buf())
     }
 
     /// Get the cgroup version
@@ -174,7 +242,10 @@ impl CgroupManager {
     /// Create cgroup v1 hierarchy
     fn create_v1(&self, container_id: &str, config: &CgroupConfig) -> Result<()> {
         // Create memory cgroup
-        if config.memory_limit.is_some() || config.memory_reservation.is_some() {
+        if config.memory_limit.is_some()
+            || config.memory_reservation.is_some()
+            || config.oom_kill_disable
+        {
             let memory_path = self.base_path.join("memory/rune").join(container_id);
             self.create_cgroup_dir(&memory_path)?;
 
@@ -235,14 +306,35 @@ impl CgroupManager {
         if let Some(limit) = config.pids_limit {
             let pids_path = self.base_path.join("pids/rune").join(container_id);
             self.create_cgroup_dir(&pids_path)?;
-            self.write_cgroup_file(&pids_path.join("pids.max"), &limit.to_string())?;
+            self.write_cgroup_file(&pids_path.join("pids.max"), &v2_limit(limit))?;
         }
 
         // Create blkio cgroup
-        if let Some(weight) = config.blkio_weight {
+        if config.blkio_weight.is_some() || !config.blkio_throttle.is_empty() {
             let blkio_path = self.base_path.join("blkio/rune").join(container_id);
             self.create_cgroup_dir(&blkio_path)?;
-            self.write_cgroup_file(&blkio_path.join("blkio.weight"), &weight.to_string())?;
+            if let Some(weight) = config.blkio_weight {
+                self.write_cgroup_file(&blkio_path.join("blkio.weight"), &weight.to_string())?;
+            }
+            for throttle in &config.blkio_throttle {
+                for (file, rate) in throttle.v1_files() {
+                    self.write_cgroup_file(&blkio_path.join(file), &rate)?;
+                }
+            }
+        }
+
+        // Create devices cgroup
+        if !config.devices.is_empty() {
+            let devices_path = self.base_path.join("devices/rune").join(container_id);
+            self.create_cgroup_dir(&devices_path)?;
+            for rule in &config.devices {
+                let file = if rule.allow {
+                    "devices.allow"
+                } else {
+                    "devices.deny"
+                };
+                self.write_cgroup_file(&devices_path.join(file), &rule.to_v1())?;
+            }
         }
 
         Ok(())
@@ -250,21 +342,14 @@ impl CgroupManager {
 
     /// Create cgroup v2 unified hierarchy
     fn create_v2(&self, container_id: &str, config: &CgroupConfig) -> Result<()> {
+        // Controllers have to be enabled down the tree, and only for a
+        // cgroup's children, before the container's cgroup uses them
+        self.create_cgroup_dir(&self.rune_path)?;
+        self.enable_controllers(&self.base_path)?;
+        self.enable_controllers(&self.rune_path)?;
         let container_path = self.rune_path.join(container_id);
         self.create_cgroup_dir(&container_path)?;
 
-        // Enable controllers on the rune cgroup first
-        // This may fail if controllers are already enabled or not available
-        if let Err(e) = self.write_cgroup_file(
-            &self.rune_path.join("cgroup.subtree_control"),
-            "+cpu +memory +pids +io",
-        ) {
-            tracing::warn!(
-                "Failed to enable cgroup controllers (may already be enabled): {}",
-                e
-            );
-        }
-
         // Memory settings
         if let Some(limit) = config.memory_limit {
             self.write_cgroup_file(&container_path.join("memory.max"), &limit.to_string())?;
@@ -273,35 +358,26 @@ impl CgroupManager {
             self.write_cgroup_file(&container_path.join("memory.low"), &reservation.to_string())?;
         }
         if let Some(swap_limit) = config.memory_swap_limit {
-            if swap_limit < 0 {
-                self.write_cgroup_file(&container_path.join("memory.swap.max"), "max")?;
-            } else {
-                self.write_cgroup_file(
-                    &container_path.join("memory.swap.max"),
-                    &swap_limit.to_string(),
-                )?;
-            }
+            self.write_cgroup_file(
+                &container_path.join("memory.swap.max"),
+                &v2_swap_max(swap_limit, config.memory_limit),
+            )?;
         }
 
         // CPU settings
-        if let Some(cpus) = config.cpus {
-            let period = config.cpu_period.unwrap_or(100_000);
-            let quota = (cpus * period as f64) as u64;
-            self.write_cgroup_file(
-                &container_path.join("cpu.max"),
-                &format!("{} {}", quota, period),
-            )?;
-        } else if let Some(quota) = config.cpu_quota {
-            let period = config.cpu_period.unwrap_or(100_000);
-            self.write_cgroup_file(
-                &container_path.join("cpu.max"),
-                &format!("{} {}", quota, period),
-            )?;
+        let period = config.cpu_period.unwrap_or(100_000);
+        let quota = config
+            .cpus
+            .map(|cpus| (cpus * period as f64) as i64)
+            .or(config.cpu_quota);
+        if quota.is_some() || config.cpu_period.is_some() {
+            self.write_cgroup_file(&container_path.join("cpu.max"), &v2_cpu_max(quota, period))?;
         }
         if let Some(shares) = config.cpu_shares {
-            // Convert shares to weight (shares 1024 = weight 100)
-            let weight = ((shares * 100) / 1024).clamp(1, 10000);
-            self.write_cgroup_file(&container_path.join("cpu.weight"), &weight.to_string())?;
+            self.write_cgroup_file(
+                &container_path.join("cpu.weight"),
+                &v2_cpu_weight(shares).to_string(),
+            )?;
         }
 
         // Cpuset settings
@@ -314,14 +390,31 @@ impl CgroupManager {
 
         // PIDs limit
         if let Some(limit) = config.pids_limit {
-            self.write_cgroup_file(&container_path.join("pids.max"), &limit.to_string())?;
+            self.write_cgroup_file(&container_path.join("pids.max"), &v2_limit(limit))?;
         }
 
-        // IO weight
+        // IO weight and throttling
         if let Some(weight) = config.blkio_weight {
-            // Convert to cgroup v2 io.weight (1-10000)
-            let io_weight = (weight as u64).clamp(1, 10000);
-            self.write_cgroup_file(&container_path.join("io.weight"), &io_weight.to_string())?;
+            self.write_cgroup_file(
+                &container_path.join("io.weight"),
+                &v2_io_weight(weight).to_string(),
+            )?;
+        }
+        for throttle in &config.blkio_throttle {
+            self.write_cgroup_file(&container_path.join("io.max"), &throttle.io_max())?;
+        }
+
+        // Cgroup v2 has no way to turn the OOM killer off
+        if config.oom_kill_disable {
+            tracing::warn!(
+                "The OOM killer can't be disabled for {} on cgroup v2",
+                container_id
+            );
+        }
+
+        // Device rules take an eBPF program on cgroup v2
+        if !config.devices.is_empty() {
+            device_filter::attach(&container_path, &config.devices)?;
         }
 
         Ok(())
@@ -336,7 +429,7 @@ impl CgroupManager {
     }
 
     fn add_process_v1(&self, container_id: &str, pid: u32) -> Result<()> {
-        let controllers = ["memory", "cpu", "cpuset", "pids", "blkio"];
+        let controllers = ["memory", "cpu", "cpuset", "pids", "blkio", "devices"];
 
         for controller in controllers {
             let cgroup_path = self
@@ -375,7 +468,7 @@ impl CgroupManager {
     }
 
     fn remove_v1(&self, container_id: &str) -> Result<()> {
-        let controllers = ["memory", "cpu", "cpuset", "pids", "blkio"];
+        let controllers = ["memory", "cpu", "cpuset", "pids", "blkio", "devices"];
 
         for controller in controllers {
             let cgroup_path = self
@@ -474,6 +567,110 @@ impl CgroupManager {
         })
     }
 
+    /// Get CPU statistics
+    pub fn get_cpu_stats(&self, container_id: &str) -> Result<CpuStats> {
+        let usage_usec = match self.version {
+            CgroupVersion::V1 => {
+                let cpuacct_path = self.base_path.join("cpuacct/rune").join(container_id);
+                self.read_cgroup_u64(&cpuacct_path.join("cpuacct.usage"))? / 1000
+            }
+            CgroupVersion::V2 => {
+                let path = self.rune_path.join(container_id).join("cpu.stat");
+                let content = fs::read_to_string(&path).map_err(|e| {
+                    RuneError::Runtime(format!("Failed to read cgroup file {:?}: {}", path, e))
+                })?;
+                parse_cpu_usage(&content)
+                    .ok_or_else(|| RuneError::Runtime(format!("No usage_usec in {:?}", path)))?
+            }
+        };
+
+        Ok(CpuStats { usage_usec })
+    }
+
+    /// Counts of the memory events of a container's cgroup; only
+    /// `oom_kill` is counted on cgroup v1
+    pub fn memory_events(&self, container_id: &str) -> Result<MemoryEvents> {
+        let path = self.memory_events_path(container_id);
+        let content = fs::read_to_string(&path).map_err(|e| {
+            RuneError::Runtime(format!("Failed to read cgroup file {:?}: {}", path, e))
+        })?;
+        Ok(parse_memory_events(&content))
+    }
+
+    /// Watch the memory events of a container's cgroup, such as OOM kills,
+    /// as they happen
+    pub fn watch_memory_events(&self, container_id: &str) -> Result<MemoryEventsWatcher> {
+        if self.version != CgroupVersion::V2 {
+            return Err(RuneError::Runtime(
+                "Watching memory events needs cgroup v2".to_string(),
+            ));
+        }
+        let path = self.memory_events_path(container_id);
+        let last = self.memory_events(container_id)?;
+        let fd = syscall::inotify_init()
+            .map_err(|e| RuneError::Runtime(format!("Failed to create inotify: {}", e)))?;
+        // The file descriptor is owned from here on, closing it on errors
+        let inotify = unsafe { File::from_raw_fd(fd) };
+        syscall::inotify_add_watch(fd, &path.to_string_lossy(), libc::IN_MODIFY)
+            .map_err(|e| RuneError::Runtime(format!("Failed to watch {:?}: {}", path, e)))?;
+        Ok(MemoryEventsWatcher {
+            inotify,
+            path,
+            last,
+        })
+    }
+
+    fn memory_events_path(&self, container_id: &str) -> PathBuf {
+        match self.version {
+            CgroupVersion::V1 => self
+                .base_path
+                .join("memory/rune")
+                .join(container_id)
+                .join("memory.oom_control"),
+            CgroupVersion::V2 => self.rune_path.join(container_id).join("memory.events"),
+        }
+    }
+
+    /// Pressure stall information of a container's cgroup; empty on cgroup
+    /// v1 and on kernels without PSI
+    pub fn get_pressure(&self, container_id: &str) -> PressureStats {
+        if self.version != CgroupVersion::V2 {
+            return PressureStats::default();
+        }
+        let container_path = self.rune_path.join(container_id);
+        let pressure = |resource: &str| {
+            fs::read_to_string(container_path.join(format!("{}.pressure", resource)))
+                .ok()
+                .and_then(|content| parse_pressure(&content))
+        };
+        PressureStats {
+            cpu: pressure("cpu"),
+            memory: pressure("memory"),
+            io: pressure("io"),
+        }
+    }
+
+    /// Let the children of a cgroup use the controllers it has
+    fn enable_controllers(&self, path: &Path) -> Result<()> {
+        let available = fs::read_to_string(path.join("cgroup.controllers")).unwrap_or_default();
+        let controllers: Vec<String> = available
+            .split_whitespace()
+            .filter(|controller| V2_CONTROLLERS.contains(controller))
+            .map(|controller| format!("+{}", controller))
+            .collect();
+        if controllers.is_empty() {
+            return Ok(());
+        }
+        // Fails where the controllers are already enabled or the cgroup
+        // has processes of its own
+        if let Err(e) =
+            self.write_cgroup_file(&path.join("cgroup.subtree_control"), &controllers.join(" "))
+        {
+            tracing::warn!("Failed to enable cgroup controllers in {:?}: {}", path, e);
+        }
+        Ok(())
+    }
+
     /// Create cgroup directory
     fn create_cgroup_dir(&self, path: &Path) -> Result<()> {
         if !path.exists() {
@@ -521,6 +718,186 @@ pub struct MemoryStats {
     pub max_usage: u64,
 }
 
+/// CPU statistics
+#[derive(Debug, Clone)]
+pub struct CpuStats {
+    /// Total CPU time used in microseconds
+    pub usage_usec: u64,
+}
+
+/// Counts of a cgroup's memory events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryEvents {
+    /// Times usage went over the high boundary
+    pub high: u64,
+    /// Times usage was about to go over the limit
+    pub max: u64,
+    /// Times the OOM killer was invoked
+    pub oom: u64,
+    /// Processes killed by the OOM killer
+    pub oom_kill: u64,
+}
+
+/// Watches a cgroup's `memory.events`, yielding the counts each time they
+/// change until the cgroup is removed
+pub struct MemoryEventsWatcher {
+    inotify: File,
+    path: PathBuf,
+    last: MemoryEvents,
+}
+
+impl Iterator for MemoryEventsWatcher {
+    type Item = MemoryEvents;
+
+    fn next(&mut self) -> Option<MemoryEvents> {
+        loop {
+            // Removed cgroups don't reliably notify, so check every so often
+            match syscall::poll_readable(self.inotify.as_raw_fd(), WATCH_INTERVAL_MS) {
+                Ok(true) => {
+                    let mut buffer = [0u8; 4096];
+                    let _ = self.inotify.read(&mut buffer);
+                }
+                Ok(false) => {}
+                Err(_) => return None,
+            }
+            let events = parse_memory_events(&fs::read_to_string(&self.path).ok()?);
+            if events != self.last {
+                self.last = events;
+                return Some(events);
+            }
+        }
+    }
+}
+
+/// Share of time tasks stalled on a resource
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PressureValues {
+    /// Percentage over the last 10 seconds
+    pub avg10: f64,
+    /// Percentage over the last 60 seconds
+    pub avg60: f64,
+    /// Percentage over the last 300 seconds
+    pub avg300: f64,
+    /// Total stall time in microseconds
+    pub total: u64,
+}
+
+/// Pressure stall information of a resource
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pressure {
+    /// Time some tasks stalled
+    pub some: PressureValues,
+    /// Time all tasks stalled at once
+    pub full: Option<PressureValues>,
+}
+
+/// Pressure stall information of a cgroup, per resource
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PressureStats {
+    pub cpu: Option<Pressure>,
+    pub memory: Option<Pressure>,
+    pub io: Option<Pressure>,
+}
+
+/// Whether a path is the root of a cgroup v2 hierarchy
+fn is_cgroup2(path: &Path) -> bool {
+    syscall::filesystem_type(&path.to_string_lossy())
+        .is_ok_and(|kind| kind == libc::CGROUP2_SUPER_MAGIC)
+}
+
+/// `memory.swap.max` for an OCI swap limit, which counts memory and swap
+/// together while cgroup v2 limits swap alone
+fn v2_swap_max(swap: i64, memory_limit: Option<u64>) -> String {
+    match memory_limit {
+        _ if swap < 0 => "max".to_string(),
+        Some(memory) => (swap as u64).saturating_sub(memory).to_string(),
+        None => swap.to_string(),
+    }
+}
+
+/// `cpu.max` for a quota and period, in microseconds
+fn v2_cpu_max(quota: Option<i64>, period: u64) -> String {
+    match quota {
+        Some(quota) if quota > 0 => format!("{} {}", quota, period),
+        _ => format!("max {}", period),
+    }
+}
+
+/// `cpu.weight`, 1 to 10000, for v1 CPU shares, 2 to 262144
+fn v2_cpu_weight(shares: u64) -> u64 {
+    let shares = shares.clamp(2, 262_144);
+    1 + ((shares - 2) * 9999) / 262_142
+}
+
+/// `io.weight`, 1 to 10000, for a v1 block I/O weight, 10 to 1000
+fn v2_io_weight(weight: u16) -> u64 {
+    let weight = weight.clamp(10, 1000) as u64;
+    1 + ((weight - 10) * 9999) / 990
+}
+
+/// Limit file value, with zero or less meaning no limit
+fn v2_limit(limit: i64) -> String {
+    if limit > 0 {
+        limit.to_string()
+    } else {
+        "max".to_string()
+    }
+}
+
+/// Parse `memory.events`, or the counts of a v1 `memory.oom_control`
+fn parse_memory_events(content: &str) -> MemoryEvents {
+    let mut events = MemoryEvents::default();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        let value = value.trim().parse().unwrap_or(0);
+        match key {
+            "high" => events.high = value,
+            "max" => events.max = value,
+            "oom" => events.oom = value,
+            "oom_kill" => events.oom_kill = value,
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Parse a PSI file such as `memory.pressure`
+fn parse_pressure(content: &str) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next()?;
+        let mut values = PressureValues::default();
+        for field in fields {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "avg10" => values.avg10 = value.parse().ok()?,
+                "avg60" => values.avg60 = value.parse().ok()?,
+                "avg300" => values.avg300 = value.parse().ok()?,
+                "total" => values.total = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        match kind {
+            "some" => some = Some(values),
+            "full" => full = Some(values),
+            _ => {}
+        }
+    }
+    Some(Pressure { some: some?, full })
+}
+
+/// Read `usage_usec` from a cgroup v2 `cpu.stat` file
+fn parse_cpu_usage(content: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        (key == "usage_usec").then(|| value.trim().parse().ok())?
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -542,6 +919,75 @@ mod tests {
         assert!(!config.oom_kill_disable);
     }
 
+    #[test]
+    fn test_parse_cpu_usage() {
+        let stat = "usage_usec 123456\nuser_usec 100000\nsystem_usec 23456\n";
+        assert_eq!(parse_cpu_usage(stat), Some(123456));
+        assert_eq!(parse_cpu_usage("user_usec 1\n"), None);
+    }
+
+    #[test]
+    fn test_v2_values() {
+        assert_eq!(v2_swap_max(-1, Some(1 << 20)), "max");
+        assert_eq!(v2_swap_max(3 << 20, Some(1 << 20)), (2 << 20).to_string());
+        assert_eq!(v2_swap_max(1 << 20, Some(1 << 20)), "0");
+        assert_eq!(v2_cpu_max(Some(50_000), 100_000), "50000 100000");
+        assert_eq!(v2_cpu_max(Some(-1), 100_000), "max 100000");
+        assert_eq!(v2_cpu_weight(2), 1);
+        assert_eq!(v2_cpu_weight(1024), 39);
+        assert_eq!(v2_cpu_weight(262_144), 10000);
+        assert_eq!(v2_io_weight(10), 1);
+        assert_eq!(v2_io_weight(1000), 10000);
+        assert_eq!(v2_limit(100), "100");
+        assert_eq!(v2_limit(-1), "max");
+
+        let throttle = BlkioThrottle {
+            major: 8,
+            minor: 0,
+            read_bps: Some(1 << 20),
+            write_iops: Some(100),
+            ..BlkioThrottle::default()
+        };
+        assert_eq!(throttle.io_max(), "8:0 rbps=1048576 wiops=100");
+        assert_eq!(
+            throttle.v1_files(),
+            [
+                ("blkio.throttle.read_bps_device", "8:0 1048576".to_string()),
+                ("blkio.throttle.write_iops_device", "8:0 100".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_memory_events() {
+        let events = "low 0\nhigh 2\nmax 5\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(
+            parse_memory_events(events),
+            MemoryEvents {
+                high: 2,
+                max: 5,
+                oom: 1,
+                oom_kill: 1
+            }
+        );
+        let oom_control = "oom_kill_disable 0\nunder_oom 0\noom_kill 3\n";
+        assert_eq!(parse_memory_events(oom_control).oom_kill, 3);
+    }
+
+    #[test]
+    fn test_parse_pressure() {
+        let pressure = "some avg10=1.50 avg60=0.25 avg300=0.00 total=123456\n\
+                        full avg10=0.50 avg60=0.00 avg300=0.00 total=4567\n";
+        let pressure = parse_pressure(pressure).unwrap();
+        assert_eq!(pressure.some.avg10, 1.5);
+        assert_eq!(pressure.some.total, 123456);
+        assert_eq!(pressure.full.unwrap().total, 4567);
+
+        let cpu = parse_pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=9\n").unwrap();
+        assert_eq!((cpu.some.total, cpu.full), (9, None));
+        assert_eq!(parse_pressure(""), None);
+    }
+
     #[test]
     fn test_cgroup_manager_creation() {
         // This might fail in non-Linux environments, just ensure no panic
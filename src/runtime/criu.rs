@@ -0,0 +1,157 @@
+//! Checkpoint and restore with CRIU
+//!
+//! CRIU freezes a container's process tree and dumps it into a directory of
+//! images, from which it can later be restored, here or on another host
+//! with the same root filesystem. External OCI runtimes drive CRIU
+//! themselves; the built-in runtime runs the `criu` binary directly.
+
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What happens to established TCP connections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TcpMode {
+    /// Checkpointing fails while the container has any
+    #[default]
+    Refuse,
+    /// They are dumped and restored, for live migration
+    Established,
+    /// They are not dumped, and closed on restore
+    Close,
+}
+
+/// Options of a checkpoint, reused when it is restored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CheckpointOptions {
+    /// Keep the container running after the checkpoint
+    #[serde(default)]
+    pub leave_running: bool,
+    /// Handling of established TCP connections
+    #[serde(default)]
+    pub tcp: TcpMode,
+    /// Checkpoint file locks
+    #[serde(default)]
+    pub file_locks: bool,
+}
+
+impl CheckpointOptions {
+    /// CRIU arguments for the options, when dumping or restoring
+    pub fn criu_args(&self, dump: bool) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if dump && self.leave_running {
+            args.push("--leave-running");
+        }
+        match self.tcp {
+            TcpMode::Refuse => {}
+            TcpMode::Established => args.push("--tcp-established"),
+            TcpMode::Close => args.push("--tcp-close"),
+        }
+        if self.file_locks {
+            args.push("--file-locks");
+        }
+        args
+    }
+}
+
+/// Path of the `criu` binary
+pub fn criu_path() -> Result<PathBuf> {
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join("criu"))
+                .find(|path| path.is_file())
+        })
+        .ok_or_else(|| {
+            RuneError::Runtime("CRIU not found; install criu to checkpoint containers".to_string())
+        })
+}
+
+/// Dump a process tree into a directory of images
+pub fn dump(pid: u32, images: &Path, options: &CheckpointOptions) -> Result<()> {
+    std::fs::create_dir_all(images)?;
+    let pid = pid.to_string();
+    let mut args = vec!["dump", "--tree", &pid, "--manage-cgroups"];
+    args.extend(options.criu_args(true));
+    run(images, "dump", &args)
+}
+
+/// Restore a process tree from a directory of images into a root
+/// filesystem, returning the PID of its root process
+pub fn restore(images: &Path, rootfs: &Path, options: &CheckpointOptions) -> Result<u32> {
+    let pidfile = images.join("restore.pid");
+    let _ = std::fs::remove_file(&pidfile);
+    let rootfs = rootfs.to_string_lossy();
+    let pidfile_arg = pidfile.to_string_lossy();
+    let mut args = vec![
+        "restore",
+        "--restore-detached",
+        "--manage-cgroups",
+        "--root",
+        &rootfs,
+        "--pidfile",
+        &pidfile_arg,
+    ];
+    args.extend(options.criu_args(false));
+    run(images, "restore", &args)?;
+
+    std::fs::read_to_string(&pidfile)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok())
+        .ok_or_else(|| RuneError::Runtime("CRIU restore did not report a PID".to_string()))
+}
+
+/// Run CRIU on a directory of images, pointing at its log on failure
+fn run(images: &Path, action: &str, args: &[&str]) -> Result<()> {
+    let log = format!("{}.log", action);
+    let status = Command::new(criu_path()?)
+        .args(args)
+        .arg("--images-dir")
+        .arg(images)
+        .args(["--log-file", &log])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| RuneError::Runtime(format!("Failed to run criu: {}", e)))?;
+    if !status.success() {
+        return Err(RuneError::Runtime(format!(
+            "CRIU {} failed; see {}",
+            action,
+            images.join(log).display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_criu_args() {
+        let options = CheckpointOptions {
+            leave_running: true,
+            tcp: TcpMode::Established,
+            file_locks: true,
+        };
+        assert_eq!(
+            options.criu_args(true),
+            ["--leave-running", "--tcp-established", "--file-locks"]
+        );
+        assert_eq!(
+            options.criu_args(false),
+            ["--tcp-established", "--file-locks"]
+        );
+
+        let close = CheckpointOptions {
+            tcp: TcpMode::Close,
+            ..CheckpointOptions::default()
+        };
+        assert_eq!(close.criu_args(false), ["--tcp-close"]);
+        assert!(CheckpointOptions::default().criu_args(true).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Device cgroup rules on cgroup v2
+//!
+//! Cgroup v2 has no `devices.allow` file; device access is decided by an
+//! eBPF program attached to the container's cgroup. Rules are compiled to
+//! such a program, checked from the last rule to the first so the last
+//! matching rule wins, as it does with cgroup v1, and denying anything no
+//! rule matches.
+
+use super::oci::LinuxDeviceCgroup;
+use crate::error::{Result, RuneError};
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_PROG_ATTACH: libc::c_long = 8;
+const BPF_PROG_TYPE_CGROUP_DEVICE: u32 = 15;
+const BPF_CGROUP_DEVICE: u32 = 6;
+const BPF_F_ALLOW_MULTI: u32 = 2;
+
+const BPF_LDX_MEM_W: u8 = 0x61;
+const BPF_ALU_AND_K: u8 = 0x54;
+const BPF_ALU_RSH_K: u8 = 0x74;
+const BPF_ALU_MOV_X: u8 = 0xbc;
+const BPF_ALU64_MOV_K: u8 = 0xb7;
+const BPF_JMP_JNE_K: u8 = 0x55;
+const BPF_JMP_JNE_X: u8 = 0x5d;
+const BPF_EXIT: u8 = 0x95;
+
+/// Device types in `bpf_cgroup_dev_ctx.access_type`
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+
+/// Accesses in the upper half of `bpf_cgroup_dev_ctx.access_type`
+const BPF_DEVCG_ACC_MKNOD: i32 = 1;
+const BPF_DEVCG_ACC_READ: i32 = 2;
+const BPF_DEVCG_ACC_WRITE: i32 = 4;
+
+/// Registers the program keeps the device it is asked about in
+const R_TYPE: u8 = 2;
+const R_ACCESS: u8 = 3;
+const R_MAJOR: u8 = 4;
+const R_MINOR: u8 = 5;
+
+/// An eBPF instruction, as `struct bpf_insn`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfInsn {
+    code: u8,
+    /// Destination register in the low nibble, source in the high one
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl BpfInsn {
+    fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Self {
+        Self {
+            code,
+            regs: dst | (src << 4),
+            off,
+            imm,
+        }
+    }
+}
+
+/// The `BPF_PROG_LOAD` part of `union bpf_attr`
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+/// The `BPF_PROG_ATTACH` part of `union bpf_attr`
+#[repr(C)]
+struct ProgAttachAttr {
+    target_fd: u32,
+    attach_bpf_fd: u32,
+    attach_type: u32,
+    attach_flags: u32,
+}
+
+/// Compile device rules to a `BPF_PROG_TYPE_CGROUP_DEVICE` program
+pub fn compile(rules: &[LinuxDeviceCgroup]) -> Vec<BpfInsn> {
+    // Unpack bpf_cgroup_dev_ctx { access_type, major, minor } from r1
+    let mut program = vec![
+        BpfInsn::new(BPF_LDX_MEM_W, R_TYPE, 1, 0, 0),
+        BpfInsn::new(BPF_ALU_AND_K, R_TYPE, 0, 0, 0xffff),
+        BpfInsn::new(BPF_LDX_MEM_W, R_ACCESS, 1, 0, 0),
+        BpfInsn::new(BPF_ALU_RSH_K, R_ACCESS, 0, 0, 16),
+        BpfInsn::new(BPF_LDX_MEM_W, R_MAJOR, 1, 4, 0),
+        BpfInsn::new(BPF_LDX_MEM_W, R_MINOR, 1, 8, 0),
+    ];
+
+    for rule in rules.iter().rev() {
+        // Each check jumps past the rule when the device doesn't match;
+        // offsets are filled in once the rule's length is known
+        let mut block = Vec::new();
+        match rule.kind.as_deref() {
+            Some("c") => block.push(BpfInsn::new(
+                BPF_JMP_JNE_K,
+                R_TYPE,
+                0,
+                0,
+                BPF_DEVCG_DEV_CHAR,
+            )),
+            Some("b") => block.push(BpfInsn::new(
+                BPF_JMP_JNE_K,
+                R_TYPE,
+                0,
+                0,
+                BPF_DEVCG_DEV_BLOCK,
+            )),
+            _ => {}
+        }
+        let access = access_mask(rule.access.as_deref().unwrap_or("rwm"));
+        let all = BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE;
+        if access != all {
+            // The access asked for has to be within the rule's
+            block.push(BpfInsn::new(BPF_ALU_MOV_X, 1, R_ACCESS, 0, 0));
+            block.push(BpfInsn::new(BPF_ALU_AND_K, 1, 0, 0, access));
+            block.push(BpfInsn::new(BPF_JMP_JNE_X, 1, R_ACCESS, 0, 0));
+        }
+        if let Some(major) = rule.major {
+            block.push(BpfInsn::new(BPF_JMP_JNE_K, R_MAJOR, 0, 0, major as i32));
+        }
+        if let Some(minor) = rule.minor {
+            block.push(BpfInsn::new(BPF_JMP_JNE_K, R_MINOR, 0, 0, minor as i32));
+        }
+        let matches_all = block.is_empty();
+        block.push(BpfInsn::new(BPF_ALU64_MOV_K, 0, 0, 0, rule.allow as i32));
+        block.push(BpfInsn::new(BPF_EXIT, 0, 0, 0, 0));
+
+        let len = block.len();
+        for (i, insn) in block.iter_mut().enumerate() {
+            if insn.code == BPF_JMP_JNE_K || insn.code == BPF_JMP_JNE_X {
+                insn.off = (len - i - 1) as i16;
+            }
+        }
+        program.extend(block);
+
+        // Earlier rules are never reached, which the verifier rejects
+        if matches_all {
+            return program;
+        }
+    }
+
+    // Nothing matched
+    program.push(BpfInsn::new(BPF_ALU64_MOV_K, 0, 0, 0, 0));
+    program.push(BpfInsn::new(BPF_EXIT, 0, 0, 0, 0));
+    program
+}
+
+/// Mask of the accesses in a rule's `r`, `w` and `m` letters
+fn access_mask(access: &str) -> i32 {
+    access.chars().fold(0, |mask, c| match c {
+        'r' => mask | BPF_DEVCG_ACC_READ,
+        'w' => mask | BPF_DEVCG_ACC_WRITE,
+        'm' => mask | BPF_DEVCG_ACC_MKNOD,
+        _ => mask,
+    })
+}
+
+/// Enforce device rules on the cgroup v2 directory `cgroup`
+pub fn attach(cgroup: &Path, rules: &[LinuxDeviceCgroup]) -> Result<()> {
+    let device_error = |e: io::Error| {
+        RuneError::Runtime(format!(
+            "Failed to enforce device rules on {}: {}",
+            cgroup.display(),
+            e
+        ))
+    };
+
+    let program = compile(rules);
+    let license = CString::new("Apache").expect("license has no NUL");
+    let load = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_CGROUP_DEVICE,
+        insn_cnt: program.len() as u32,
+        insns: program.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        expected_attach_type: BPF_CGROUP_DEVICE,
+        ..Default::default()
+    };
+    let prog = bpf(BPF_PROG_LOAD, &load).map_err(device_error)?;
+    let prog = unsafe { OwnedFd::from_raw_fd(prog) };
+
+    let dir = File::open(cgroup).map_err(device_error)?;
+    let attach = ProgAttachAttr {
+        target_fd: dir.as_raw_fd() as u32,
+        attach_bpf_fd: prog.as_raw_fd() as u32,
+        attach_type: BPF_CGROUP_DEVICE,
+        attach_flags: BPF_F_ALLOW_MULTI,
+    };
+    // The cgroup holds on to the program once attached
+    bpf(BPF_PROG_ATTACH, &attach).map_err(device_error)?;
+    Ok(())
+}
+
+/// Issue a `bpf` command, returning what it returns
+fn bpf<T>(cmd: libc::c_long, attr: &T) -> io::Result<i32> {
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            std::mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::devices::default_rules;
+
+    /// Run a compiled program the way the kernel would for one device
+    fn run(program: &[BpfInsn], kind: i32, major: u32, minor: u32, access: i32) -> u64 {
+        let ctx = [(kind | (access << 16)) as u32, major, minor];
+        let mut regs = [0u64; 11];
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            let (dst, src) = ((insn.regs & 0xf) as usize, (insn.regs >> 4) as usize);
+            let mut jump = false;
+            match insn.code {
+                BPF_LDX_MEM_W => regs[dst] = ctx[insn.off as usize / 4] as u64,
+                BPF_ALU_AND_K => regs[dst] = (regs[dst] as u32 & insn.imm as u32) as u64,
+                BPF_ALU_RSH_K => regs[dst] = (regs[dst] as u32 >> insn.imm) as u64,
+                BPF_ALU_MOV_X => regs[dst] = regs[src] as u32 as u64,
+                BPF_ALU64_MOV_K => regs[dst] = insn.imm as u64,
+                BPF_JMP_JNE_K => jump = regs[dst] != insn.imm as u64,
+                BPF_JMP_JNE_X => jump = regs[dst] != regs[src],
+                BPF_EXIT => return regs[0],
+                code => panic!("unexpected instruction {:#x}", code),
+            }
+            pc += 1;
+            if jump {
+                pc += insn.off as usize;
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let program = compile(&default_rules());
+        let (c, b) = (BPF_DEVCG_DEV_CHAR, BPF_DEVCG_DEV_BLOCK);
+        let rw = BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE;
+
+        // /dev/null and ptys are usable, /dev/mem and disks are not
+        assert_eq!(run(&program, c, 1, 3, rw), 1);
+        assert_eq!(run(&program, c, 136, 4, rw), 1);
+        assert_eq!(run(&program, c, 1, 1, BPF_DEVCG_ACC_READ), 0);
+        assert_eq!(run(&program, b, 8, 0, BPF_DEVCG_ACC_READ), 0);
+        // Any node may be created, not opened
+        assert_eq!(run(&program, b, 8, 0, BPF_DEVCG_ACC_MKNOD), 1);
+
+        // The deny-all rule ends the program, leaving nothing unreachable
+        let exits = program.iter().filter(|i| i.code == BPF_EXIT).count();
+        assert_eq!(exits, default_rules().len());
+    }
+
+    #[test]
+    fn test_last_matching_rule_wins() {
+        let mut rules = vec![LinuxDeviceCgroup::allow("a", None, None, "rwm")];
+        rules.push(LinuxDeviceCgroup {
+            allow: false,
+            ..LinuxDeviceCgroup::allow("c", Some(10), Some(200), "w")
+        });
+        let program = compile(&rules);
+        let c = BPF_DEVCG_DEV_CHAR;
+
+        assert_eq!(run(&program, c, 10, 200, BPF_DEVCG_ACC_WRITE), 0);
+        // Reading isn't covered by the deny rule, so the allow rule applies
+        assert_eq!(run(&program, c, 10, 200, BPF_DEVCG_ACC_READ), 1);
+        assert_eq!(run(&program, c, 10, 201, BPF_DEVCG_ACC_WRITE), 1);
+    }
+}
@@ -0,0 +1,147 @@
+//! Device passthrough
+//!
+//! Host devices given with `--device` are recreated in the container with
+//! the same type and numbers, and allowed by the container's device cgroup
+//! on top of the small set every container may use.
+
+use super::oci::{LinuxDevice, LinuxDeviceCgroup};
+use crate::error::{Result, RuneError};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+/// Device cgroup rules every unprivileged container starts with: deny all,
+/// then allow creating any node and using the devices Docker allows
+pub fn default_rules() -> Vec<LinuxDeviceCgroup> {
+    let mut rules = vec![
+        LinuxDeviceCgroup {
+            allow: false,
+            kind: None,
+            major: None,
+            minor: None,
+            access: Some("rwm".to_string()),
+        },
+        LinuxDeviceCgroup::allow("c", None, None, "m"),
+        LinuxDeviceCgroup::allow("b", None, None, "m"),
+    ];
+    let allowed = [
+        (1, Some(3)),    // null
+        (1, Some(8)),    // random
+        (1, Some(7)),    // full
+        (5, Some(0)),    // tty
+        (1, Some(5)),    // zero
+        (1, Some(9)),    // urandom
+        (5, Some(1)),    // console
+        (136, None),     // pts
+        (5, Some(2)),    // ptmx
+        (10, Some(200)), // tun
+    ];
+    rules.extend(
+        allowed
+            .into_iter()
+            .map(|(major, minor)| LinuxDeviceCgroup::allow("c", Some(major), minor, "rwm")),
+    );
+    rules
+}
+
+/// Device cgroup rule of a privileged container, allowing every device
+pub fn privileged_rules() -> Vec<LinuxDeviceCgroup> {
+    vec![LinuxDeviceCgroup::allow("a", None, None, "rwm")]
+}
+
+/// Rule allowing a device node
+pub fn rule(device: &LinuxDevice, access: &str) -> LinuxDeviceCgroup {
+    LinuxDeviceCgroup::allow(&device.kind, Some(device.major), Some(device.minor), access)
+}
+
+/// Whether device cgroup permissions are a combination of `r`, `w` and `m`
+pub fn valid_permissions(permissions: &str) -> bool {
+    !permissions.is_empty()
+        && permissions.len() <= 3
+        && permissions
+            .chars()
+            .enumerate()
+            .all(|(i, c)| "rwm".contains(c) && !permissions[..i].contains(c))
+}
+
+/// Devices of a host path mapped into the container: the device itself,
+/// or every device below a directory
+pub fn host_devices(host_path: &Path, container_path: &str) -> Result<Vec<LinuxDevice>> {
+    let metadata = std::fs::metadata(host_path).map_err(|e| {
+        RuneError::InvalidConfig(format!(
+            "Error gathering device information for {}: {}",
+            host_path.display(),
+            e
+        ))
+    })?;
+    if !metadata.is_dir() {
+        return device(container_path, &metadata)
+            .map(|device| vec![device])
+            .ok_or_else(|| {
+                RuneError::InvalidConfig(format!("{} is not a device", host_path.display()))
+            });
+    }
+
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir(host_path)? {
+        let entry = entry?;
+        let path = format!(
+            "{}/{}",
+            container_path.trim_end_matches('/'),
+            entry.file_name().to_string_lossy()
+        );
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            devices.extend(host_devices(&entry.path(), &path)?);
+        } else if let Some(device) = device(&path, &metadata) {
+            devices.push(device);
+        }
+    }
+    Ok(devices)
+}
+
+/// Device node at a container path for a host file, if it is a device
+fn device(path: &str, metadata: &std::fs::Metadata) -> Option<LinuxDevice> {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_char_device() {
+        "c"
+    } else if file_type.is_block_device() {
+        "b"
+    } else {
+        return None;
+    };
+    let rdev = metadata.rdev();
+    Some(LinuxDevice {
+        path: path.to_string(),
+        kind: kind.to_string(),
+        major: libc::major(rdev) as i64,
+        minor: libc::minor(rdev) as i64,
+        file_mode: Some(metadata.mode() & 0o7777),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_devices() {
+        let devices = host_devices(Path::new("/dev/null"), "/dev/mynull").unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].path, "/dev/mynull");
+        assert_eq!(
+            (devices[0].kind.as_str(), devices[0].major, devices[0].minor),
+            ("c", 1, 3)
+        );
+        assert_eq!(rule(&devices[0], "rw").to_v1(), "c 1:3 rw");
+
+        assert!(host_devices(Path::new("/etc/passwd"), "/dev/x").is_err());
+        assert!(host_devices(Path::new("/dev/nonexistent"), "/dev/x").is_err());
+
+        assert!(valid_permissions("rwm") && valid_permissions("mr"));
+        assert!(!valid_permissions("") && !valid_permissions("rr") && !valid_permissions("rx"));
+        assert_eq!(default_rules()[0].to_v1(), "a *:* rwm");
+        assert_eq!(default_rules()[1].to_v1(), "c *:* m");
+    }
+}
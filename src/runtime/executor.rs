@@ -0,0 +1,807 @@
+//! Container executors
+//!
+//! An executor runs containers from OCI bundles. Rune's built-in runtime
+//! does it in process; [`OciRuntime`] shells out to an external runtime
+//! such as runc, crun or youki, driving it with the standard `create`,
+//! `start`, `state`, `kill` and `delete` commands, and `checkpoint` and
+//! `restore` for CRIU.
+
+use super::cgroup::{BlkioThrottle, CgroupConfig, CgroupManager, CgroupVersion};
+use super::criu::{self, CheckpointOptions, TcpMode};
+use super::hooks;
+use super::namespace::NamespaceType;
+use super::oci::{LinuxResources, Spec, State, OCI_VERSION};
+use super::process::{ContainerProcess, ProcessConfig, ProcessState, ProcessStreams};
+use super::userns::UsernsRemap;
+use crate::error::{Result, RuneError};
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Name of the built-in runtime
+pub const BUILTIN_RUNTIME: &str = "rune";
+
+/// Runs containers from OCI bundles
+pub trait Executor: Send + Sync {
+    /// Name of the runtime
+    fn name(&self) -> &str;
+
+    /// Path of the runtime binary
+    fn path(&self) -> PathBuf;
+
+    /// Create a container from a bundle, without starting its process
+    fn create(&self, id: &str, bundle: &Path) -> Result<()>;
+
+    /// Start a created container's process, returning its PID
+    fn start(&self, id: &str) -> Result<u32>;
+
+    /// State of a container
+    fn state(&self, id: &str) -> Result<State>;
+
+    /// Send a signal to a container's process
+    fn kill(&self, id: &str, signal: i32) -> Result<()>;
+
+    /// Delete a container, killing its process if it still runs
+    fn delete(&self, id: &str) -> Result<()>;
+
+    /// Freeze a container's processes
+    fn pause(&self, id: &str) -> Result<()>;
+
+    /// Thaw a container's processes
+    fn resume(&self, id: &str) -> Result<()>;
+
+    /// Dump a running container's processes into a directory of CRIU images
+    fn checkpoint(&self, id: &str, images: &Path, options: &CheckpointOptions) -> Result<()>;
+
+    /// Create a container from a bundle with its processes restored from a
+    /// checkpoint, returning the PID of its init process
+    fn restore(
+        &self,
+        id: &str,
+        bundle: &Path,
+        images: &Path,
+        options: &CheckpointOptions,
+    ) -> Result<u32>;
+
+    /// Master side of the terminal of a started container's process
+    fn console(&self, id: &str) -> Result<OwnedFd> {
+        Err(RuneError::Runtime(format!(
+            "{} can't hand over the terminal of container {}",
+            self.name(),
+            id
+        )))
+    }
+
+    /// Wait for a started container's process to exit, returning its exit
+    /// code
+    fn wait(&self, id: &str) -> Result<i32> {
+        Err(RuneError::Runtime(format!(
+            "{} can't wait for container {}",
+            self.name(),
+            id
+        )))
+    }
+
+    /// Give a created container's process the standard streams it starts
+    /// with
+    fn set_stdio(&self, id: &str, _stdio: ProcessStreams) -> Result<()> {
+        Err(RuneError::Runtime(format!(
+            "{} can't attach to the standard streams of container {}",
+            self.name(),
+            id
+        )))
+    }
+
+    /// Side of a started container's standard input pipe that writes to
+    /// its process
+    fn stdin(&self, id: &str) -> Result<OwnedFd> {
+        Err(RuneError::Runtime(format!(
+            "{} can't hand over the standard input of container {}",
+            self.name(),
+            id
+        )))
+    }
+}
+
+/// Executor for a runtime: `rune` for the built-in one, or the name or path
+/// of an external OCI runtime
+///
+/// External runtimes keep their state under `root`.
+pub fn executor_for(runtime: &str, root: &Path) -> Result<Arc<dyn Executor>> {
+    if runtime == BUILTIN_RUNTIME {
+        return Ok(Arc::new(BuiltinExecutor::new()));
+    }
+    Ok(Arc::new(OciRuntime::find(runtime, root.to_path_buf())?))
+}
+
+/// Container created by the built-in runtime
+struct BuiltinContainer {
+    process: ContainerProcess,
+    spec: Spec,
+    bundle: PathBuf,
+}
+
+/// Rune's built-in runtime
+#[derive(Default)]
+pub struct BuiltinExecutor {
+    containers: Mutex<HashMap<String, BuiltinContainer>>,
+}
+
+impl BuiltinExecutor {
+    /// Create the built-in runtime
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn containers(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, BuiltinContainer>>> {
+        self.containers
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire executor lock".to_string()))
+    }
+}
+
+impl Executor for BuiltinExecutor {
+    fn name(&self) -> &str {
+        BUILTIN_RUNTIME
+    }
+
+    fn path(&self) -> PathBuf {
+        std::env::current_exe().unwrap_or_else(|_| PathBuf::from("/usr/bin/rune"))
+    }
+
+    fn create(&self, id: &str, bundle: &Path) -> Result<()> {
+        let spec = Spec::load(bundle)?;
+        let process = builtin_process(id, bundle, &spec)?;
+        let mut containers = self.containers()?;
+        if containers.contains_key(id) {
+            return Err(RuneError::ContainerExists(id.to_string()));
+        }
+        containers.insert(
+            id.to_string(),
+            BuiltinContainer {
+                process,
+                spec,
+                bundle: bundle.to_path_buf(),
+            },
+        );
+        Ok(())
+    }
+
+    fn start(&self, id: &str) -> Result<u32> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        let hooks = container.spec.hooks.clone().unwrap_or_default();
+        let created = |pid| container_state(id, &container.bundle, "created", pid);
+        // The process waits for the hooks that run before its command
+        let held = !hooks.prestart.is_empty()
+            || !hooks.create_runtime.is_empty()
+            || !hooks.create_container.is_empty();
+        container.process.set_hold(held);
+        if !hooks.start_container.is_empty() {
+            container
+                .process
+                .set_start_hooks(hooks.start_container.clone(), created(0));
+        }
+        let pid = container.process.start()?;
+
+        let prepared = (|| {
+            if let Some(resources) = &container.spec.linux.resources {
+                let config = cgroup_config(resources);
+                let cgroups = CgroupManager::new()?;
+                cgroups.create(id, &config)?;
+                cgroups.add_process(id, pid)?;
+                if cgroups.version() == CgroupVersion::V2 {
+                    watch_oom(&cgroups, id);
+                }
+            }
+            // The built-in runtime has no separate create step, so the
+            // create hooks run here, against the waiting process
+            let state = created(pid);
+            hooks::run("prestart", &hooks.prestart, &state)?;
+            hooks::run("createRuntime", &hooks.create_runtime, &state)?;
+            hooks::run("createContainer", &hooks.create_container, &state)?;
+            container.process.release()
+        })();
+        if let Err(e) = prepared {
+            let _ = container.process.kill(libc::SIGKILL);
+            let _ = container.process.wait();
+            return Err(e);
+        }
+
+        hooks::run_logged(
+            "poststart",
+            &hooks.poststart,
+            &container_state(id, &container.bundle, "running", pid),
+        );
+        Ok(pid)
+    }
+
+    fn state(&self, id: &str) -> Result<State> {
+        let containers = self.containers()?;
+        let container = containers
+            .get(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        let status = match container.process.state() {
+            ProcessState::Creating | ProcessState::Created => "created",
+            _ if container.process.is_running() => "running",
+            _ => "stopped",
+        };
+        Ok(container_state(
+            id,
+            &container.bundle,
+            status,
+            container.process.pid().unwrap_or(0),
+        ))
+    }
+
+    fn kill(&self, id: &str, signal: i32) -> Result<()> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        container.process.kill(signal)
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        let Some(mut container) = self.containers()?.remove(id) else {
+            return Ok(());
+        };
+        if container.process.is_running() {
+            container.process.kill(libc::SIGKILL)?;
+        }
+        if container.process.pid().is_some() {
+            let _ = container.process.wait();
+        }
+        if container.spec.linux.resources.is_some() {
+            let _ = CgroupManager::new().and_then(|cgroups| cgroups.remove(id));
+        }
+        if let Some(hooks) = &container.spec.hooks {
+            hooks::run_logged(
+                "poststop",
+                &hooks.poststop,
+                &container_state(id, &container.bundle, "stopped", 0),
+            );
+        }
+        Ok(())
+    }
+
+    fn pause(&self, id: &str) -> Result<()> {
+        CgroupManager::new()?.freeze(id)
+    }
+
+    fn resume(&self, id: &str) -> Result<()> {
+        CgroupManager::new()?.thaw(id)
+    }
+
+    fn checkpoint(&self, id: &str, images: &Path, options: &CheckpointOptions) -> Result<()> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        let pid = container
+            .process
+            .pid()
+            .filter(|_| container.process.is_running())
+            .ok_or_else(|| RuneError::ContainerNotRunning(id.to_string()))?;
+        criu::dump(pid, images, options)?;
+        if !options.leave_running {
+            let _ = container.process.wait();
+        }
+        Ok(())
+    }
+
+    fn restore(
+        &self,
+        id: &str,
+        bundle: &Path,
+        images: &Path,
+        options: &CheckpointOptions,
+    ) -> Result<u32> {
+        self.create(id, bundle)?;
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        let pid = criu::restore(images, &container.spec.rootfs(bundle), options)?;
+        container.process.adopt(pid);
+        Ok(pid)
+    }
+
+    fn console(&self, id: &str) -> Result<OwnedFd> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        container.process.take_console().ok_or_else(|| {
+            RuneError::Runtime(format!("Container {} has no terminal to attach to", id))
+        })
+    }
+
+    fn set_stdio(&self, id: &str, stdio: ProcessStreams) -> Result<()> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        container.process.set_stdio(stdio);
+        Ok(())
+    }
+
+    fn stdin(&self, id: &str) -> Result<OwnedFd> {
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        container.process.take_stdin().ok_or_else(|| {
+            RuneError::Runtime(format!(
+                "Container {} has no standard input to attach to",
+                id
+            ))
+        })
+    }
+
+    fn wait(&self, id: &str) -> Result<i32> {
+        let pid = self
+            .containers()?
+            .get(id)
+            .and_then(|container| container.process.pid())
+            .ok_or_else(|| RuneError::ContainerNotRunning(id.to_string()))?;
+        // Wait without reaping, so the lock isn't held meanwhile
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::waitid(libc::P_PID, pid, &mut info, libc::WEXITED | libc::WNOWAIT) } < 0 {
+            return Err(RuneError::Runtime(format!(
+                "Failed to wait for container {}: {}",
+                id,
+                std::io::Error::last_os_error()
+            )));
+        }
+        let mut containers = self.containers()?;
+        let container = containers
+            .get_mut(id)
+            .ok_or_else(|| RuneError::ContainerNotFound(id.to_string()))?;
+        match container.process.exit_code() {
+            Some(code) => Ok(code),
+            None => container.process.wait(),
+        }
+    }
+}
+
+/// State of a container of the built-in runtime
+fn container_state(id: &str, bundle: &Path, status: &str, pid: u32) -> State {
+    State {
+        oci_version: OCI_VERSION.to_string(),
+        id: id.to_string(),
+        status: status.to_string(),
+        pid,
+        bundle: bundle.display().to_string(),
+    }
+}
+
+/// Process the built-in runtime runs for a spec
+fn builtin_process(id: &str, bundle: &Path, spec: &Spec) -> Result<ContainerProcess> {
+    let capabilities = spec
+        .process
+        .capabilities
+        .as_ref()
+        .map(|caps| caps.bounding.clone())
+        .unwrap_or_default();
+    let seccomp = match &spec.linux.seccomp {
+        Some(profile) => Some(profile.compile(&capabilities)?),
+        None => None,
+    };
+    let config = ProcessConfig {
+        args: spec.process.args.clone(),
+        env: spec
+            .process
+            .env
+            .iter()
+            .filter_map(|var| var.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        cwd: spec.process.cwd.clone(),
+        uid: spec.process.user.uid,
+        gid: spec.process.user.gid,
+        groups: spec.process.user.additional_gids.clone(),
+        terminal: spec.process.terminal,
+        capabilities_add: capabilities,
+        capabilities_drop: vec!["ALL".to_string()],
+        privileged: false,
+        no_new_privileges: spec.process.no_new_privileges,
+        oom_score_adj: spec.process.oom_score_adj,
+        rlimits: spec.process.rlimits.clone(),
+        apparmor_profile: spec.process.apparmor_profile.clone(),
+        selinux_label: spec.process.selinux_label.clone(),
+        seccomp,
+    };
+
+    let namespaces = spec
+        .linux
+        .namespaces
+        .iter()
+        .map(|ns| {
+            if ns.path.is_some() {
+                return Err(RuneError::Runtime(format!(
+                    "The built-in runtime can't join an existing {} namespace",
+                    ns.kind
+                )));
+            }
+            match ns.kind.as_str() {
+                "pid" => Ok(NamespaceType::Pid),
+                "network" => Ok(NamespaceType::Net),
+                "mount" => Ok(NamespaceType::Mount),
+                "uts" => Ok(NamespaceType::Uts),
+                "ipc" => Ok(NamespaceType::Ipc),
+                "user" => Ok(NamespaceType::User),
+                "cgroup" => Ok(NamespaceType::Cgroup),
+                kind => Err(RuneError::InvalidConfig(format!(
+                    "Unknown namespace type '{}'",
+                    kind
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut process = ContainerProcess::new(config, namespaces)?;
+    process.set_container_id(id.to_string());
+    process.set_rootfs(spec.rootfs(bundle));
+    if !spec.hostname.is_empty() {
+        process.set_hostname(spec.hostname.clone());
+    }
+    process.set_devices(spec.linux.devices.clone());
+    process.set_sysctls(spec.linux.sysctl.clone());
+    if !spec.linux.uid_mappings.is_empty() {
+        process.set_userns_remap(UsernsRemap {
+            user: String::new(),
+            group: String::new(),
+            uid_maps: spec.linux.uid_mappings.clone(),
+            gid_maps: spec.linux.gid_mappings.clone(),
+        });
+    }
+    Ok(process)
+}
+
+fn cgroup_config(resources: &LinuxResources) -> CgroupConfig {
+    let memory = resources.memory.clone().unwrap_or_default();
+    let cpu = resources.cpu.clone().unwrap_or_default();
+    let block_io = resources.block_io.clone().unwrap_or_default();
+    // Limits are given per kind; cgroups take them per device
+    let mut blkio_throttle: Vec<BlkioThrottle> = Vec::new();
+    let limits = [
+        &block_io.throttle_read_bps_device,
+        &block_io.throttle_write_bps_device,
+        &block_io.throttle_read_iops_device,
+        &block_io.throttle_write_iops_device,
+    ];
+    for (kind, devices) in limits.into_iter().enumerate() {
+        for device in devices {
+            let index = match blkio_throttle
+                .iter()
+                .position(|t| (t.major, t.minor) == (device.major, device.minor))
+            {
+                Some(index) => index,
+                None => {
+                    blkio_throttle.push(BlkioThrottle {
+                        major: device.major,
+                        minor: device.minor,
+                        ..BlkioThrottle::default()
+                    });
+                    blkio_throttle.len() - 1
+                }
+            };
+            let throttle = &mut blkio_throttle[index];
+            let rate = Some(device.rate);
+            match kind {
+                0 => throttle.read_bps = rate,
+                1 => throttle.write_bps = rate,
+                2 => throttle.read_iops = rate,
+                _ => throttle.write_iops = rate,
+            }
+        }
+    }
+    CgroupConfig {
+        memory_limit: memory.limit.map(|limit| limit as u64),
+        memory_reservation: memory.reservation.map(|reservation| reservation as u64),
+        memory_swap_limit: memory.swap,
+        oom_kill_disable: memory.disable_oom_killer.unwrap_or(false),
+        cpu_shares: cpu.shares,
+        cpu_quota: cpu.quota,
+        cpu_period: cpu.period,
+        cpuset_cpus: cpu.cpus,
+        cpuset_mems: cpu.mems,
+        pids_limit: resources.pids.as_ref().map(|pids| pids.limit),
+        devices: resources.devices.clone(),
+        blkio_weight: block_io.weight,
+        blkio_throttle,
+        ..CgroupConfig::default()
+    }
+}
+
+/// Log the OOM kills of a container as they happen, until its cgroup is
+/// removed
+fn watch_oom(cgroups: &CgroupManager, id: &str) {
+    let watcher = match cgroups.watch_memory_events(id) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::debug!("Not watching {} for OOM kills: {}", id, e);
+            return;
+        }
+    };
+    let id = id.to_string();
+    let mut oom_kills = 0;
+    std::thread::spawn(move || {
+        for events in watcher {
+            if events.oom_kill > oom_kills {
+                tracing::warn!(
+                    "Container {} ran out of memory; {} processes OOM-killed",
+                    id,
+                    events.oom_kill
+                );
+                oom_kills = events.oom_kill;
+            }
+        }
+    });
+}
+
+/// External OCI runtime
+#[derive(Debug, Clone)]
+pub struct OciRuntime {
+    /// Runtime binary
+    path: PathBuf,
+    /// Directory the runtime keeps container state in
+    root: PathBuf,
+}
+
+impl OciRuntime {
+    /// Runtime at a path
+    pub fn new(path: PathBuf, root: PathBuf) -> Self {
+        Self { path, root }
+    }
+
+    /// Runtime by name, looked up in `PATH`, or by path
+    pub fn find(runtime: &str, root: PathBuf) -> Result<Self> {
+        let path = if runtime.contains('/') {
+            Some(PathBuf::from(runtime)).filter(|path| path.is_file())
+        } else {
+            std::env::var_os("PATH").and_then(|paths| {
+                std::env::split_paths(&paths)
+                    .map(|dir| dir.join(runtime))
+                    .find(|path| path.is_file())
+            })
+        };
+        path.map(|path| Self::new(path, root))
+            .ok_or_else(|| RuneError::InvalidConfig(format!("OCI runtime '{}' not found", runtime)))
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.path);
+        command.arg("--root").arg(&self.root).args(args);
+        command
+    }
+
+    /// Run a runtime command to completion, returning its output
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let output = self
+            .command(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| {
+                RuneError::Runtime(format!("Failed to run {}: {}", self.path.display(), e))
+            })?;
+        if !output.status.success() {
+            return Err(self.failure(args[0], &String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run a runtime command that leaves the container's process behind
+    ///
+    /// The container inherits the runtime's stdio, so it is collected in the
+    /// bundle's `output.log` rather than a pipe that would stay open while
+    /// the container runs.
+    fn run_detached(&self, bundle: &Path, args: &[&str]) -> Result<()> {
+        let output_path = bundle.join("output.log");
+        let output = std::fs::File::create(&output_path)?;
+        let status = self
+            .command(args)
+            .stdin(Stdio::null())
+            .stdout(output.try_clone()?)
+            .stderr(output)
+            .status()
+            .map_err(|e| {
+                RuneError::Runtime(format!("Failed to run {}: {}", self.path.display(), e))
+            })?;
+        if !status.success() {
+            let stderr = std::fs::read_to_string(&output_path).unwrap_or_default();
+            return Err(self.failure(args[0], &stderr));
+        }
+        Ok(())
+    }
+
+    /// Runtime flags for checkpoint options; OCI runtimes can only keep
+    /// TCP connections, not close them
+    fn criu_args(&self, options: &CheckpointOptions, dump: bool) -> Result<Vec<&'static str>> {
+        if options.tcp == TcpMode::Close {
+            return Err(RuneError::InvalidConfig(format!(
+                "{} can't close TCP connections on restore",
+                self.name()
+            )));
+        }
+        Ok(options.criu_args(dump))
+    }
+
+    fn failure(&self, command: &str, stderr: &str) -> RuneError {
+        RuneError::Runtime(format!(
+            "{} {} failed: {}",
+            self.name(),
+            command,
+            stderr.trim()
+        ))
+    }
+}
+
+impl Executor for OciRuntime {
+    fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("oci")
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn create(&self, id: &str, bundle: &Path) -> Result<()> {
+        let bundle_arg = bundle.to_string_lossy();
+        self.run_detached(bundle, &["create", "--bundle", &bundle_arg, id])
+    }
+
+    fn start(&self, id: &str) -> Result<u32> {
+        self.run(&["start", id])?;
+        Ok(self.state(id)?.pid)
+    }
+
+    fn state(&self, id: &str) -> Result<State> {
+        let output = self.run(&["state", id])?;
+        serde_json::from_str(&output)
+            .map_err(|e| RuneError::Runtime(format!("Invalid state from {}: {}", self.name(), e)))
+    }
+
+    fn kill(&self, id: &str, signal: i32) -> Result<()> {
+        self.run(&["kill", id, &signal.to_string()]).map(|_| ())
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        self.run(&["delete", "--force", id]).map(|_| ())
+    }
+
+    fn pause(&self, id: &str) -> Result<()> {
+        self.run(&["pause", id]).map(|_| ())
+    }
+
+    fn resume(&self, id: &str) -> Result<()> {
+        self.run(&["resume", id]).map(|_| ())
+    }
+
+    fn checkpoint(&self, id: &str, images: &Path, options: &CheckpointOptions) -> Result<()> {
+        let images = images.to_string_lossy();
+        let mut args = vec!["checkpoint", "--image-path", &images];
+        args.extend(self.criu_args(options, true)?);
+        args.push(id);
+        self.run(&args).map(|_| ())
+    }
+
+    fn restore(
+        &self,
+        id: &str,
+        bundle: &Path,
+        images: &Path,
+        options: &CheckpointOptions,
+    ) -> Result<u32> {
+        let images = images.to_string_lossy();
+        let bundle_arg = bundle.to_string_lossy();
+        let mut args = vec![
+            "restore",
+            "--detach",
+            "--bundle",
+            &bundle_arg,
+            "--image-path",
+            &images,
+        ];
+        args.extend(self.criu_args(options, false)?);
+        args.push(id);
+        self.run_detached(bundle, &args)?;
+        Ok(self.state(id)?.pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    /// Runtime that records its arguments and reports every container
+    /// running
+    const FAKE_RUNTIME: &str = r#"#!/bin/sh
+echo "$@" >> "$(dirname "$0")/calls"
+case "$3" in
+    create) [ "$6" = broken ] && { echo "rootfs not found" >&2; exit 1; } ;;
+    state) echo "{\"ociVersion\":\"1.0.2\",\"id\":\"$4\",\"status\":\"running\",\"pid\":4242}" ;;
+esac
+exit 0
+"#;
+
+    #[test]
+    fn test_external_runtime() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fakerun");
+        std::fs::write(&path, FAKE_RUNTIME).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let bundle = dir.path().join("bundle");
+        std::fs::create_dir_all(&bundle).unwrap();
+
+        let root = dir.path().join("state");
+        let runtime = executor_for(path.to_str().unwrap(), &root).unwrap();
+        assert_eq!(runtime.name(), "fakerun");
+
+        runtime.create("c1", &bundle).unwrap();
+        assert_eq!(runtime.start("c1").unwrap(), 4242);
+        assert!(!runtime.state("c1").unwrap().is_stopped());
+        runtime.kill("c1", libc::SIGTERM).unwrap();
+        runtime.delete("c1").unwrap();
+
+        let options = CheckpointOptions {
+            leave_running: true,
+            tcp: TcpMode::Established,
+            ..CheckpointOptions::default()
+        };
+        let images = dir.path().join("cp1");
+        runtime.checkpoint("c1", &images, &options).unwrap();
+        assert_eq!(
+            runtime.restore("c2", &bundle, &images, &options).unwrap(),
+            4242
+        );
+        let close = CheckpointOptions {
+            tcp: TcpMode::Close,
+            ..CheckpointOptions::default()
+        };
+        assert!(runtime.checkpoint("c1", &images, &close).is_err());
+
+        let root = root.display();
+        let calls = std::fs::read_to_string(dir.path().join("calls")).unwrap();
+        let expected = [
+            format!("--root {} create --bundle {} c1", root, bundle.display()),
+            format!("--root {} start c1", root),
+            format!("--root {} state c1", root),
+            format!("--root {} state c1", root),
+            format!("--root {} kill c1 15", root),
+            format!("--root {} delete --force c1", root),
+            format!(
+                "--root {} checkpoint --image-path {} --leave-running --tcp-established c1",
+                root,
+                images.display()
+            ),
+            format!(
+                "--root {} restore --detach --bundle {} --image-path {} --tcp-established c2",
+                root,
+                bundle.display(),
+                images.display()
+            ),
+            format!("--root {} state c2", root),
+        ];
+        assert_eq!(calls.lines().collect::<Vec<_>>(), expected);
+
+        let error = runtime.create("broken", &bundle).unwrap_err().to_string();
+        assert!(error.contains("fakerun create failed: rootfs not found"));
+
+        assert!(executor_for("no-such-runtime", dir.path()).is_err());
+        assert_eq!(
+            executor_for(BUILTIN_RUNTIME, dir.path()).unwrap().name(),
+            "rune"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! OCI lifecycle hooks
+//!
+//! Hooks are commands run at points of a container's lifecycle with the
+//! container's state as JSON on stdin, so they can, say, plumb networking
+//! into its namespaces before its process starts. External runtimes run
+//! the hooks of a bundle themselves; the built-in runtime runs them here.
+
+use super::oci::{Hook, State};
+use crate::error::{Result, RuneError};
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How often a running hook is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run hooks in order, failing on the first that fails
+pub fn run(stage: &str, hooks: &[Hook], state: &State) -> Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let state = serde_json::to_vec(state)?;
+    for hook in hooks {
+        run_hook(hook, &state).map_err(|e| {
+            RuneError::Runtime(format!("{} hook {} failed: {}", stage, hook.path, e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Run hooks whose failure can't undo the lifecycle step they follow,
+/// like `poststart` and `poststop`, logging failures
+pub fn run_logged(stage: &str, hooks: &[Hook], state: &State) {
+    if let Err(e) = run(stage, hooks, state) {
+        tracing::warn!("{}", e);
+    }
+}
+
+fn run_hook(hook: &Hook, state: &[u8]) -> std::result::Result<(), String> {
+    let mut command = Command::new(&hook.path);
+    if let Some((arg0, args)) = hook.args.split_first() {
+        command.arg0(arg0).args(args);
+    }
+    command
+        .env_clear()
+        .envs(hook.env.iter().filter_map(|var| var.split_once('=')))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(|e| e.to_string())?;
+
+    // A hook that exits without reading its state is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(state);
+    }
+
+    let deadline = hook
+        .timeout
+        .map(|timeout| Instant::now() + Duration::from_secs(timeout as u64));
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("timed out after {}s", hook.timeout.unwrap_or(0)));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    if status.success() {
+        return Ok(());
+    }
+
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = std::io::Read::read_to_string(&mut pipe, &mut stderr);
+    }
+    Err(match stderr.trim() {
+        "" => status.to_string(),
+        stderr => format!("{}: {}", status, stderr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_hooks() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("state.json");
+        let state = State {
+            oci_version: "1.0.2".to_string(),
+            id: "abc".to_string(),
+            status: "created".to_string(),
+            pid: 42,
+            bundle: "/bundle".to_string(),
+        };
+        let hook = |script: String| Hook {
+            path: "/bin/sh".to_string(),
+            args: vec!["sh".to_string(), "-c".to_string(), script],
+            env: vec!["STAGE=prestart".to_string()],
+            timeout: Some(5),
+        };
+
+        let hooks = [hook(format!("cat > {}; echo $STAGE >> {0}", out.display()))];
+        run("prestart", &hooks, &state).unwrap();
+        let written = std::fs::read_to_string(&out).unwrap();
+        let (json, stage) = written.split_once('}').unwrap();
+        let json: serde_json::Value = serde_json::from_str(&format!("{}}}", json)).unwrap();
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["id"], "abc");
+        assert_eq!(stage.trim(), "prestart");
+
+        let error = run(
+            "createRuntime",
+            &[hook("echo no >&2; exit 3".to_string())],
+            &state,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("createRuntime hook /bin/sh failed"));
+        assert!(error.contains("no"));
+
+        let mut slow = hook("sleep 5".to_string());
+        slow.timeout = Some(1);
+        assert!(run("poststart", &[slow], &state)
+            .unwrap_err()
+            .to_string()
+            .contains("timed out"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Built-in init process
+//!
+//! With `--init`, a container's command runs under a minimal init, like
+//! tini: it forwards the signals it receives to the command, reaps the
+//! zombies orphaned processes leave behind, and exits with the command's
+//! exit code. The init is compiled into the rune binary, which acts as the
+//! init when invoked as `rune-init`; external runtimes get the binary
+//! bind-mounted at [`INIT_PATH`], while the built-in runtime runs the init
+//! in its own container process without executing anything.
+
+use super::syscall;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Path of the init inside containers
+pub const INIT_PATH: &str = "/sbin/rune-init";
+
+/// Name the rune binary answers to as the init
+pub const INIT_NAME: &str = "rune-init";
+
+/// How long to wait for a signal before checking for zombies anyway
+const REAP_INTERVAL: libc::timespec = libc::timespec {
+    tv_sec: 1,
+    tv_nsec: 0,
+};
+
+/// Arguments running a command under the init
+pub fn wrap(args: &[String]) -> Vec<String> {
+    [INIT_PATH.to_string(), "--".to_string()]
+        .into_iter()
+        .chain(args.iter().cloned())
+        .collect()
+}
+
+/// The command of arguments that run it under the init, or `None` when
+/// they don't
+pub fn unwrap(args: &[String]) -> Option<&[String]> {
+    let (init, command) = args.split_first()?;
+    if init != INIT_PATH {
+        return None;
+    }
+    Some(match command.split_first() {
+        Some((separator, command)) if separator == "--" => command,
+        _ => command,
+    })
+}
+
+/// Whether the binary was invoked as the init, judging by `argv[0]`
+pub fn is_init(argv0: &str) -> bool {
+    Path::new(argv0)
+        .file_name()
+        .is_some_and(|name| name == INIT_NAME)
+}
+
+/// Entry point of `rune-init [--] <command> [args...]`, returning the exit
+/// code
+pub fn main(args: &[String]) -> i32 {
+    let args = match args.split_first() {
+        Some((separator, args)) if separator == "--" => args,
+        _ => args,
+    };
+    let Some((program, args)) = args.split_first() else {
+        eprintln!("Usage: {} [--] <command> [args...]", INIT_NAME);
+        return 1;
+    };
+    let mut command = Command::new(program);
+    command.args(args);
+    run(command)
+}
+
+/// Run a command under the init until it exits, returning its exit code,
+/// or 128 plus the signal that killed it
+///
+/// Signals are blocked in the calling thread while the command runs, so
+/// this should run in a single-threaded process.
+pub fn run(mut command: Command) -> i32 {
+    // Unless running as PID 1, orphans only come back to us as a subreaper
+    if syscall::getpid() != 1 {
+        unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    }
+
+    // Block every signal so they can be waited for, while the command
+    // starts with none blocked
+    let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+    let mut previous: libc::sigset_t = unsafe { std::mem::zeroed() };
+    let mut none: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigfillset(&mut signals);
+        libc::sigemptyset(&mut none);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &signals, &mut previous);
+        command.pre_exec(move || {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &none, std::ptr::null_mut());
+            Ok(())
+        });
+    }
+    let restore_mask = || unsafe {
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+    };
+
+    let child = match command.spawn() {
+        Ok(child) => child.id() as i32,
+        Err(e) => {
+            restore_mask();
+            eprintln!(
+                "{}: failed to run {:?}: {}",
+                INIT_NAME,
+                command.get_program(),
+                e
+            );
+            return if e.kind() == std::io::ErrorKind::NotFound {
+                127
+            } else {
+                126
+            };
+        }
+    };
+
+    let exit_code = loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let signal = unsafe { libc::sigtimedwait(&signals, &mut info, &REAP_INTERVAL) };
+        if signal > 0 && signal != libc::SIGCHLD {
+            let _ = syscall::kill(child, signal);
+        }
+        if let Some(code) = reap(child) {
+            break code;
+        }
+    };
+    restore_mask();
+    exit_code
+}
+
+/// Reap every exited child, returning the command's exit code once it has
+/// exited
+fn reap(command: i32) -> Option<i32> {
+    let mut exit_code = None;
+    while let Ok((pid, status)) = syscall::waitpid(-1, libc::WNOHANG) {
+        if pid == 0 {
+            break;
+        }
+        if pid == command {
+            exit_code = Some(exit_status(status));
+        }
+    }
+    exit_code
+}
+
+/// Exit code of a wait status, the shell's way
+fn exit_status(status: i32) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    /// Run `command` under the init in a forked process of its own, as it
+    /// would be in a container, returning the init's PID
+    fn spawn_init(command: Command) -> i32 {
+        match syscall::fork().unwrap() {
+            0 => unsafe { libc::_exit(run(command)) },
+            pid => pid as i32,
+        }
+    }
+
+    /// Exit code the init exited with, which it must not have been killed
+    fn wait_init(pid: i32) -> i32 {
+        let (_, status) = syscall::waitpid(pid, 0).unwrap();
+        assert!(libc::WIFEXITED(status), "init died with {:#x}", status);
+        libc::WEXITSTATUS(status)
+    }
+
+    #[test]
+    fn test_init_args() {
+        let command = vec!["nginx".to_string(), "-g".to_string()];
+        let wrapped = wrap(&command);
+        assert_eq!(wrapped, ["/sbin/rune-init", "--", "nginx", "-g"]);
+        assert_eq!(unwrap(&wrapped), Some(&command[..]));
+        assert_eq!(unwrap(&wrapped[..1]), Some(&[][..]));
+        assert_eq!(unwrap(&command), None);
+
+        assert!(is_init("/sbin/rune-init"));
+        assert!(is_init("rune-init"));
+        assert!(!is_init("/usr/bin/rune"));
+
+        assert_eq!(exit_status(3 << 8), 3);
+        assert_eq!(exit_status(libc::SIGTERM), 143);
+        assert_eq!(main(&[]), 1);
+    }
+
+    #[test]
+    fn test_run_exit_code() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        assert_eq!(wait_init(spawn_init(command)), 3);
+    }
+
+    #[test]
+    fn test_run_forwards_signals() {
+        let dir = TempDir::new().unwrap();
+        let started = dir.path().join("started");
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "touch \"$0\" && exec sleep 30"])
+            .arg(&started);
+        let init = spawn_init(command);
+
+        // Signals sent before the init blocks them would kill it instead
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !started.exists() {
+            assert!(Instant::now() < deadline, "command never started");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        syscall::kill(init, libc::SIGTERM).unwrap();
+        assert_eq!(wait_init(init), 143);
+    }
+}
@@ -0,0 +1,365 @@
+//! AppArmor and SELinux confinement
+//!
+//! Containers are confined by whichever of the two the host runs: AppArmor
+//! hosts run them under a default profile, loaded when missing, while
+//! SELinux hosts run them with the `container_t` type at a level of their
+//! own, with their files labelled to match. `--security-opt apparmor=` and
+//! `--security-opt label=` override either; privileged containers are
+//! unconfined.
+
+use super::syscall;
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Profile containers run under on AppArmor hosts
+pub const DEFAULT_APPARMOR_PROFILE: &str = "rune-default";
+
+/// Profiles the kernel has loaded, one `name (mode)` per line
+const APPARMOR_PROFILES: &str = "/sys/kernel/security/apparmor/profiles";
+
+/// Extended attribute holding SELinux file labels
+const SELINUX_XATTR: &str = "security.selinux";
+
+/// Source of the default AppArmor profile, after Docker's
+const DEFAULT_APPARMOR_TEMPLATE: &str = r#"#include <tunables/global>
+
+profile {name} flags=(attach_disconnected,mediate_deleted) {
+  #include <abstractions/base>
+
+  network,
+  capability,
+  file,
+  umount,
+  signal (receive) peer=unconfined,
+  signal (send,receive) peer={name},
+
+  deny @{PROC}/* w,
+  deny @{PROC}/{[^1-9],[^1-9][^0-9],[^1-9s][^0-9y][^0-9s],[^1-9][^0-9][^0-9][^0-9/]*}/** w,
+  deny @{PROC}/sys/[^k]** w,
+  deny @{PROC}/sys/kernel/{?,??,[^s][^h][^m]**} w,
+  deny @{PROC}/sysrq-trigger rwklx,
+  deny @{PROC}/kcore rwklx,
+
+  deny mount,
+
+  deny /sys/[^f]*/** wklx,
+  deny /sys/f[^s]*/** wklx,
+  deny /sys/fs/[^c]*/** wklx,
+  deny /sys/fs/c[^g]*/** wklx,
+  deny /sys/fs/cg[^r]*/** wklx,
+  deny /sys/firmware/** rwklx,
+  deny /sys/kernel/security/** rwklx,
+
+  ptrace (trace,read,tracedby,readby) peer={name},
+}
+"#;
+
+/// Linux security module confining containers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lsm {
+    AppArmor,
+    SeLinux,
+}
+
+impl Lsm {
+    /// Module the host runs, if either
+    pub fn detect() -> Option<Self> {
+        let apparmor = std::fs::read_to_string("/sys/module/apparmor/parameters/enabled")
+            .is_ok_and(|enabled| enabled.trim() == "Y");
+        if apparmor && Path::new(APPARMOR_PROFILES).exists() {
+            return Some(Self::AppArmor);
+        }
+        if Path::new("/sys/fs/selinux/enforce").exists() {
+            return Some(Self::SeLinux);
+        }
+        None
+    }
+}
+
+/// Overrides given with `--security-opt apparmor=` and `label=`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelOptions {
+    /// AppArmor profile
+    pub apparmor: Option<String>,
+    /// Whether SELinux labelling is off
+    pub disable: bool,
+    pub user: Option<String>,
+    pub role: Option<String>,
+    /// Process type
+    pub kind: Option<String>,
+    pub level: Option<String>,
+    /// File type
+    pub filetype: Option<String>,
+}
+
+impl LabelOptions {
+    /// Pick the LSM overrides out of security options
+    pub fn from_security_opts(options: &[String]) -> Result<Self> {
+        let mut labels = Self::default();
+        for option in options {
+            let Some((key, value)) = option.split_once(['=', ':']) else {
+                continue;
+            };
+            match key {
+                "apparmor" => labels.apparmor = Some(value.to_string()),
+                "label" if value == "disable" => labels.disable = true,
+                "label" => {
+                    let (field, label) = value.split_once(':').ok_or_else(|| {
+                        RuneError::InvalidConfig(format!("Invalid --security-opt: {}", option))
+                    })?;
+                    let label = Some(label.to_string());
+                    match field {
+                        "user" => labels.user = label,
+                        "role" => labels.role = label,
+                        "type" => labels.kind = label,
+                        "level" => labels.level = label,
+                        "filetype" => labels.filetype = label,
+                        _ => {
+                            return Err(RuneError::InvalidConfig(format!(
+                                "Invalid --security-opt: {}",
+                                option
+                            )))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(labels)
+    }
+}
+
+/// Labels a container runs with; empty where nothing applies
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityLabels {
+    /// AppArmor profile of the container process
+    #[serde(default)]
+    pub apparmor_profile: String,
+    /// SELinux label of the container process
+    #[serde(default)]
+    pub process_label: String,
+    /// SELinux label of the container's files
+    #[serde(default)]
+    pub mount_label: String,
+}
+
+impl SecurityLabels {
+    /// Labels of a container on a host running an LSM
+    pub fn new(lsm: Option<Lsm>, options: &LabelOptions, privileged: bool) -> Self {
+        match lsm {
+            Some(Lsm::AppArmor) => Self {
+                apparmor_profile: match &options.apparmor {
+                    Some(profile) => profile.clone(),
+                    None if privileged => "unconfined".to_string(),
+                    None => DEFAULT_APPARMOR_PROFILE.to_string(),
+                },
+                ..Self::default()
+            },
+            Some(Lsm::SeLinux) if !privileged && !options.disable => {
+                let level = options.level.clone().unwrap_or_else(random_level);
+                let field = |value: &Option<String>, default: &str| {
+                    value.clone().unwrap_or_else(|| default.to_string())
+                };
+                Self {
+                    process_label: format!(
+                        "{}:{}:{}:{}",
+                        field(&options.user, "system_u"),
+                        field(&options.role, "system_r"),
+                        field(&options.kind, "container_t"),
+                        level
+                    ),
+                    mount_label: format!(
+                        "{}:object_r:{}:{}",
+                        field(&options.user, "system_u"),
+                        field(&options.filetype, "container_file_t"),
+                        level
+                    ),
+                    ..Self::default()
+                }
+            }
+            _ => Self::default(),
+        }
+    }
+}
+
+/// MCS level of two categories no other container is likely to share
+fn random_level() -> String {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    let first = u16::from_le_bytes([bytes[0], bytes[1]]) % 1024;
+    let second = u16::from_le_bytes([bytes[2], bytes[3]]) % 1023;
+    // Categories are distinct and in order
+    let second = if second >= first { second + 1 } else { second };
+    let (low, high) = (first.min(second), first.max(second));
+    format!("s0:c{},c{}", low, high)
+}
+
+/// Make sure an AppArmor profile is loaded, loading the default one when
+/// it's missing
+pub fn ensure_apparmor_profile(profile: &str) -> Result<()> {
+    if profile.is_empty() || profile == "unconfined" || apparmor_profile_loaded(profile)? {
+        return Ok(());
+    }
+    if profile != DEFAULT_APPARMOR_PROFILE {
+        return Err(RuneError::InvalidConfig(format!(
+            "AppArmor profile '{}' is not loaded",
+            profile
+        )));
+    }
+
+    let mut parser = Command::new("apparmor_parser")
+        .arg("-Kr")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| RuneError::Runtime(format!("Failed to run apparmor_parser: {}", e)))?;
+    if let Some(mut stdin) = parser.stdin.take() {
+        stdin.write_all(default_apparmor_profile(profile).as_bytes())?;
+    }
+    let output = parser.wait_with_output()?;
+    if !output.status.success() {
+        return Err(RuneError::Runtime(format!(
+            "Failed to load AppArmor profile {}: {}",
+            profile,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Source of the default AppArmor profile under a name
+fn default_apparmor_profile(name: &str) -> String {
+    DEFAULT_APPARMOR_TEMPLATE.replace("{name}", name)
+}
+
+fn apparmor_profile_loaded(profile: &str) -> Result<bool> {
+    let profiles = std::fs::read_to_string(APPARMOR_PROFILES)
+        .map_err(|e| RuneError::Runtime(format!("Failed to list AppArmor profiles: {}", e)))?;
+    Ok(profiles
+        .lines()
+        .any(|line| line.split(" (").next() == Some(profile)))
+}
+
+/// Confine the command the calling process executes next: under an
+/// AppArmor profile, or with an SELinux label
+pub fn set_exec_labels(apparmor_profile: &str, process_label: &str) -> Result<()> {
+    if !apparmor_profile.is_empty() && apparmor_profile != "unconfined" {
+        // Kernels with LSM stacking keep AppArmor's attributes apart
+        let path = if Path::new("/proc/self/attr/apparmor/exec").exists() {
+            "/proc/self/attr/apparmor/exec"
+        } else {
+            "/proc/self/attr/exec"
+        };
+        std::fs::write(path, format!("exec {}", apparmor_profile)).map_err(|e| {
+            RuneError::Runtime(format!(
+                "Failed to set AppArmor profile {}: {}",
+                apparmor_profile, e
+            ))
+        })?;
+    }
+    if !process_label.is_empty() {
+        std::fs::write("/proc/self/attr/exec", process_label).map_err(|e| {
+            RuneError::Runtime(format!(
+                "Failed to set SELinux label {}: {}",
+                process_label, e
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// A mount label any container can use: the label at level `s0`, without
+/// the categories that keep it to one container
+pub fn shared_label(mount_label: &str) -> String {
+    let fields: Vec<&str> = mount_label.splitn(4, ':').collect();
+    match fields[..] {
+        [user, role, kind, _] => format!("{}:{}:{}:s0", user, role, kind),
+        _ => mount_label.to_string(),
+    }
+}
+
+/// Label a tree of files for SELinux, without following symlinks
+pub fn relabel(path: &Path, label: &str) -> Result<()> {
+    syscall::lsetxattr(&path.to_string_lossy(), SELINUX_XATTR, label.as_bytes())
+        .map_err(|e| RuneError::Runtime(format!("Failed to relabel {}: {}", path.display(), e)))?;
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            relabel(&entry?.path(), label)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(options: &[&str]) -> LabelOptions {
+        let options: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+        LabelOptions::from_security_opts(&options).unwrap()
+    }
+
+    #[test]
+    fn test_apparmor_labels() {
+        let labels = SecurityLabels::new(Some(Lsm::AppArmor), &opts(&[]), false);
+        assert_eq!(labels.apparmor_profile, DEFAULT_APPARMOR_PROFILE);
+        assert!(labels.process_label.is_empty());
+        let privileged = SecurityLabels::new(Some(Lsm::AppArmor), &opts(&[]), true);
+        assert_eq!(privileged.apparmor_profile, "unconfined");
+        let custom = SecurityLabels::new(Some(Lsm::AppArmor), &opts(&["apparmor=web"]), true);
+        assert_eq!(custom.apparmor_profile, "web");
+
+        assert_eq!(
+            SecurityLabels::new(None, &opts(&["apparmor=web"]), false),
+            SecurityLabels::default()
+        );
+        let profile = default_apparmor_profile("rune-default");
+        assert!(profile.contains("profile rune-default flags="));
+        assert!(profile.contains("peer=rune-default"));
+    }
+
+    #[test]
+    fn test_selinux_labels() {
+        let labels = SecurityLabels::new(Some(Lsm::SeLinux), &opts(&[]), false);
+        assert!(labels.apparmor_profile.is_empty());
+        let level = labels
+            .process_label
+            .strip_prefix("system_u:system_r:container_t:");
+        let level = level.unwrap();
+        assert!(level.starts_with("s0:c"));
+        assert_eq!(
+            labels.mount_label,
+            format!("system_u:object_r:container_file_t:{}", level)
+        );
+
+        let custom = SecurityLabels::new(
+            Some(Lsm::SeLinux),
+            &opts(&[
+                "label=type:spc_t",
+                "label:level:s0:c1,c2",
+                "label=filetype:web_t",
+            ]),
+            false,
+        );
+        assert_eq!(custom.process_label, "system_u:system_r:spc_t:s0:c1,c2");
+        assert_eq!(custom.mount_label, "system_u:object_r:web_t:s0:c1,c2");
+
+        let disabled = SecurityLabels::new(Some(Lsm::SeLinux), &opts(&["label=disable"]), false);
+        assert_eq!(disabled, SecurityLabels::default());
+        assert_eq!(
+            SecurityLabels::new(Some(Lsm::SeLinux), &opts(&[]), true),
+            SecurityLabels::default()
+        );
+
+        assert!(LabelOptions::from_security_opts(&["label=bogus".to_string()]).is_err());
+        assert!(LabelOptions::from_security_opts(&["label=color:red".to_string()]).is_err());
+        for _ in 0..100 {
+            let level = random_level();
+            let (low, high) = level[4..].split_once(",c").unwrap();
+            assert!(low.parse::<u16>().unwrap() < high.parse::<u16>().unwrap());
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Container resource metrics
+//!
+//! Samples a running container's CPU and memory use, OOM kills and, on
+//! cgroup v2, pressure stall information from its cgroup, and its
+//! network traffic from `/proc/<pid>/net/dev`, which lists the interfaces
+//! of the container's network namespace. Samples are cumulative counters;
+//! callers compute rates from consecutive samples.
+
+use super::cgroup::{CgroupManager, PressureStats};
+use crate::error::Result;
+use std::fs;
+use std::path::PathBuf;
+
+/// Network traffic counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Bytes received
+    pub rx_bytes: u64,
+    /// Bytes sent
+    pub tx_bytes: u64,
+}
+
+/// A sample of a container's resource usage
+#[derive(Debug, Clone, Default)]
+pub struct ContainerMetrics {
+    /// Total CPU time used in microseconds
+    pub cpu_usage_usec: u64,
+    /// Memory in use in bytes
+    pub memory_usage: u64,
+    /// Memory limit in bytes, if one is set
+    pub memory_limit: Option<u64>,
+    /// Network traffic of all non-loopback interfaces
+    pub network: NetworkStats,
+    /// Processes killed by the OOM killer
+    pub oom_kills: u64,
+    /// Time tasks stalled on CPU, memory and I/O
+    pub pressure: PressureStats,
+}
+
+/// Source of container resource samples
+pub trait MetricsSource: Send + Sync {
+    /// Sample a running container's resource usage
+    fn sample(&self, container_id: &str, pid: Option<u32>) -> Result<ContainerMetrics>;
+}
+
+/// Reads metrics from container cgroups and `/proc`
+pub struct CgroupMetrics {
+    cgroups: CgroupManager,
+    proc_root: PathBuf,
+}
+
+impl CgroupMetrics {
+    /// Create a metrics source for this host's cgroup hierarchy
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            cgroups: CgroupManager::new()?,
+            proc_root: PathBuf::from("/proc"),
+        })
+    }
+}
+
+impl MetricsSource for CgroupMetrics {
+    fn sample(&self, container_id: &str, pid: Option<u32>) -> Result<ContainerMetrics> {
+        let cpu = self.cgroups.get_cpu_stats(container_id)?;
+        let memory = self.cgroups.get_memory_stats(container_id)?;
+        // Containers without a network namespace of their own report nothing
+        let network = pid
+            .and_then(|pid| {
+                fs::read_to_string(self.proc_root.join(pid.to_string()).join("net/dev")).ok()
+            })
+            .map(|dev| parse_net_dev(&dev))
+            .unwrap_or_default();
+
+        Ok(ContainerMetrics {
+            cpu_usage_usec: cpu.usage_usec,
+            memory_usage: memory.usage,
+            memory_limit: (memory.limit != u64::MAX).then_some(memory.limit),
+            network,
+            oom_kills: self
+                .cgroups
+                .memory_events(container_id)
+                .map(|events| events.oom_kill)
+                .unwrap_or(0),
+            pressure: self.cgroups.get_pressure(container_id),
+        })
+    }
+}
+
+/// Sum the traffic of all non-loopback interfaces in `/proc/net/dev`
+pub fn parse_net_dev(content: &str) -> NetworkStats {
+    let mut stats = NetworkStats::default();
+    // Two header lines, then `iface: rx_bytes packets ... tx_bytes ...`
+    for line in content.lines().skip(2) {
+        let Some((iface, counters)) = line.split_once(':') else {
+            continue;
+        };
+        if iface.trim() == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters
+            .split_whitespace()
+            .filter_map(|c| c.parse().ok())
+            .collect();
+        if counters.len() >= 9 {
+            stats.rx_bytes += counters[0];
+            stats.tx_bytes += counters[8];
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_net_dev() {
+        let dev = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1000      10    0    0    0     0          0         0     1000      10    0    0    0     0       0          0
+  eth0:    5000      50    0    0    0     0          0         0     2000      20    0    0    0     0       0          0
+  eth1:     500       5    0    0    0     0          0         0      200       2    0    0    0     0       0          0
+";
+        assert_eq!(
+            parse_net_dev(dev),
+            NetworkStats {
+                rx_bytes: 5500,
+                tx_bytes: 2200
+            }
+        );
+    }
+}
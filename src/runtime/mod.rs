@@ -4,16 +4,37 @@
 //! Provides Linux namespace isolation, cgroup resource management, and
 //! process execution for containers.
 
+pub mod capabilities;
+pub mod cdi;
 pub mod cgroup;
+pub mod criu;
+pub mod device_filter;
+pub mod devices;
+pub mod executor;
+pub mod hooks;
+pub mod init;
+pub mod lsm;
+pub mod metrics;
 pub mod mount;
 pub mod namespace;
+pub mod oci;
 pub mod process;
+pub mod rlimit;
+pub mod seccomp;
+pub mod signal;
 pub mod syscall;
+pub mod sysctl;
+pub mod terminal;
+pub mod userns;
 
-pub use cgroup::{CgroupConfig, CgroupManager};
+pub use cgroup::{CgroupConfig, CgroupManager, Pressure, PressureStats, PressureValues};
+pub use executor::{BuiltinExecutor, Executor, OciRuntime};
+pub use metrics::{CgroupMetrics, ContainerMetrics, MetricsSource};
 pub use mount::MountManager;
 pub use namespace::{Namespace, NamespaceType};
-pub use process::{ContainerProcess, ProcessConfig};
+pub use process::{ContainerProcess, ProcessConfig, ProcessStreams};
+pub use seccomp::{Seccomp, SeccompFilter, SeccompProfile};
+pub use userns::UsernsRemap;
 
 use crate::error::Result;
 
@@ -3,7 +3,8 @@
 //! Provides functionality for setting up container filesystems,
 //! including pivot_root and bind mounts.
 
-use super::syscall::{chdir, chroot, mount, mount_flags, pivot_root, umount2, umount_flags};
+use super::oci::LinuxDevice;
+use super::syscall::{chdir, chroot, mknod, mount, mount_flags, pivot_root, umount2, umount_flags};
 use crate::error::{Result, RuneError};
 use std::fs;
 use std::path::Path;
@@ -389,6 +390,35 @@ impl MountManager {
 
         Ok(())
     }
+
+    /// Create a device node in the root filesystem, bind mounting the host
+    /// node of the same path where creating nodes isn't permitted, as in a
+    /// user namespace
+    pub fn create_device_node(&self, rootfs: &str, device: &LinuxDevice) -> Result<()> {
+        let path = format!("{}/{}", rootfs, device.path.trim_start_matches('/'));
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _ = fs::remove_file(&path);
+
+        let file_type = match device.kind.as_str() {
+            "b" => libc::S_IFBLK,
+            "p" => libc::S_IFIFO,
+            _ => libc::S_IFCHR,
+        };
+        let mode = file_type | device.file_mode.unwrap_or(0o666);
+        if mknod(&path, mode, device.major as u32, device.minor as u32).is_err() {
+            fs::File::create(&path)?;
+            mount(Some(&device.path), &path, None, mount_flags::MS_BIND, None).map_err(|e| {
+                RuneError::Runtime(format!("Failed to create device {}: {}", device.path, e))
+            })?;
+            return Ok(());
+        }
+        if device.uid.is_some() || device.gid.is_some() {
+            std::os::unix::fs::chown(&path, device.uid, device.gid)?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for MountManager {
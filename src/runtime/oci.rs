@@ -0,0 +1,581 @@
+//! OCI runtime spec
+//!
+//! Containers are started from a bundle: a directory holding the root
+//! filesystem and a `config.json` following the OCI runtime spec, so any
+//! compliant runtime (runc, crun, youki or Rune's own) can run them. Only
+//! the parts of the spec Rune fills in are modeled; unknown fields are
+//! ignored when a spec is read back.
+
+use super::seccomp::SeccompProfile;
+use super::userns::IdMap;
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Version of the OCI runtime spec bundles follow
+pub const OCI_VERSION: &str = "1.0.2";
+
+/// Name of the spec file in a bundle
+pub const CONFIG_FILE: &str = "config.json";
+
+/// OCI runtime spec, the `config.json` of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spec {
+    /// Version of the spec
+    pub oci_version: String,
+    /// Process to run
+    pub process: Process,
+    /// Root filesystem
+    pub root: Root,
+    /// Hostname of the container
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hostname: String,
+    /// Domain name of the container
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub domainname: String,
+    /// Mounts, in order
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    /// Hooks run by the runtime around the container's lifecycle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Hooks>,
+    /// Annotations
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+    /// Linux specific configuration
+    pub linux: Linux,
+}
+
+/// Process of a container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Process {
+    /// Whether the process gets a terminal
+    #[serde(default)]
+    pub terminal: bool,
+    /// User the process runs as
+    pub user: User,
+    /// Arguments, the first being the executable
+    pub args: Vec<String>,
+    /// Environment, as `KEY=value`
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Working directory
+    pub cwd: String,
+    /// Capabilities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+    /// Whether the process may not gain privileges
+    #[serde(default)]
+    pub no_new_privileges: bool,
+    /// OOM score adjustment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    /// Resource limits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rlimits: Vec<PosixRlimit>,
+    /// AppArmor profile
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub apparmor_profile: String,
+    /// SELinux label
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub selinux_label: String,
+}
+
+/// Resource limit of a process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PosixRlimit {
+    /// Resource, like `RLIMIT_NOFILE`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Ceiling of the soft limit
+    pub hard: u64,
+    /// Limit enforced by the kernel
+    pub soft: u64,
+}
+
+/// User of a process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    /// User ID
+    pub uid: u32,
+    /// Group ID
+    pub gid: u32,
+    /// Supplementary groups
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_gids: Vec<u32>,
+}
+
+/// Capability sets of a process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Capabilities the process can ever gain
+    #[serde(default)]
+    pub bounding: Vec<String>,
+    /// Capabilities checked by the kernel
+    #[serde(default)]
+    pub effective: Vec<String>,
+    /// Capabilities kept across exec
+    #[serde(default)]
+    pub inheritable: Vec<String>,
+    /// Capabilities the process may enable
+    #[serde(default)]
+    pub permitted: Vec<String>,
+    /// Capabilities kept across exec of unprivileged programs
+    #[serde(default)]
+    pub ambient: Vec<String>,
+}
+
+/// Root filesystem of a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Root {
+    /// Path, relative to the bundle or absolute
+    pub path: String,
+    /// Whether the root filesystem is mounted read-only
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Mount in a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mount {
+    /// Path inside the container
+    pub destination: String,
+    /// Filesystem type
+    #[serde(rename = "type", default, skip_serializing_if = "String::is_empty")]
+    pub kind: String,
+    /// Device or host path
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
+    /// Mount options
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+impl Mount {
+    /// Mount of a filesystem type
+    pub fn new(destination: &str, kind: &str, source: &str, options: &[&str]) -> Self {
+        Self {
+            destination: destination.to_string(),
+            kind: kind.to_string(),
+            source: source.to_string(),
+            options: options.iter().map(|option| option.to_string()).collect(),
+        }
+    }
+}
+
+/// Lifecycle hooks
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hooks {
+    /// Run in the runtime namespace after the container is created; the
+    /// deprecated predecessor of `createRuntime`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prestart: Vec<Hook>,
+    /// Run in the runtime namespace after the container is created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_runtime: Vec<Hook>,
+    /// Run in the container namespace after the container is created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_container: Vec<Hook>,
+    /// Run in the container namespace before the process starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub start_container: Vec<Hook>,
+    /// Run after the process starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststart: Vec<Hook>,
+    /// Run after the container is deleted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poststop: Vec<Hook>,
+}
+
+impl Hooks {
+    /// Lifecycle stages hooks can run at
+    pub const STAGES: [&'static str; 6] = [
+        "prestart",
+        "createRuntime",
+        "createContainer",
+        "startContainer",
+        "poststart",
+        "poststop",
+    ];
+
+    /// Hooks of a stage
+    pub fn stage(&self, stage: &str) -> &[Hook] {
+        match stage {
+            "prestart" => &self.prestart,
+            "createRuntime" => &self.create_runtime,
+            "createContainer" => &self.create_container,
+            "startContainer" => &self.start_container,
+            "poststart" => &self.poststart,
+            "poststop" => &self.poststop,
+            _ => &[],
+        }
+    }
+
+    /// Add a hook to a stage
+    pub fn add(&mut self, stage: &str, hook: Hook) -> Result<()> {
+        if !hook.path.starts_with('/') {
+            return Err(RuneError::InvalidConfig(format!(
+                "Hook path '{}' must be absolute",
+                hook.path
+            )));
+        }
+        let hooks = match stage {
+            "prestart" => &mut self.prestart,
+            "createRuntime" => &mut self.create_runtime,
+            "createContainer" => &mut self.create_container,
+            "startContainer" => &mut self.start_container,
+            "poststart" => &mut self.poststart,
+            "poststop" => &mut self.poststop,
+            _ => {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Unknown hook stage '{}'; expected one of {}",
+                    stage,
+                    Self::STAGES.join(", ")
+                )))
+            }
+        };
+        hooks.push(hook);
+        Ok(())
+    }
+
+    /// Append the hooks of another set, stage by stage
+    pub fn extend(&mut self, other: &Hooks) -> Result<()> {
+        for stage in Self::STAGES {
+            for hook in other.stage(stage) {
+                self.add(stage, hook.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every hook has an absolute path
+    pub fn validate(&self) -> Result<()> {
+        Hooks::default().extend(self)
+    }
+
+    /// Whether there are no hooks at all
+    pub fn is_empty(&self) -> bool {
+        Self::STAGES
+            .iter()
+            .all(|stage| self.stage(stage).is_empty())
+    }
+}
+
+/// Hook command
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hook {
+    /// Absolute path of the executable
+    pub path: String,
+    /// Arguments, the first being the executable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Environment, as `KEY=value`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Seconds before the hook is aborted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+}
+
+/// Linux specific configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux {
+    /// Namespaces the container gets
+    #[serde(default)]
+    pub namespaces: Vec<LinuxNamespace>,
+    /// Uid mapping of the user namespace
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uid_mappings: Vec<IdMap>,
+    /// Gid mapping of the user namespace
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gid_mappings: Vec<IdMap>,
+    /// Resource limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<LinuxResources>,
+    /// Device nodes created in the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<LinuxDevice>,
+    /// Cgroup of the container
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cgroups_path: String,
+    /// Seccomp profile, with rules already resolved for the container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seccomp: Option<SeccompProfile>,
+    /// Paths made inaccessible in the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masked_paths: Vec<String>,
+    /// Paths made read-only in the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub readonly_paths: Vec<String>,
+    /// Kernel parameters set in the container's namespaces
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sysctl: HashMap<String, String>,
+    /// SELinux label of the container's mounts
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub mount_label: String,
+}
+
+/// Namespace of a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinuxNamespace {
+    /// Type: pid, network, mount, ipc, uts, user or cgroup
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Existing namespace to join instead of creating one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl LinuxNamespace {
+    /// New namespace of a type
+    pub fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            path: None,
+        }
+    }
+}
+
+/// Device node of a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxDevice {
+    /// Path inside the container
+    pub path: String,
+    /// `c` for character, `b` for block or `p` for FIFO devices
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Major number
+    #[serde(default)]
+    pub major: i64,
+    /// Minor number
+    #[serde(default)]
+    pub minor: i64,
+    /// Permission bits of the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<u32>,
+    /// Owner of the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// Group of the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+/// Device cgroup rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinuxDeviceCgroup {
+    /// Whether the rule allows or denies access
+    pub allow: bool,
+    /// `c`, `b` or `a` for all devices
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Major number, any when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major: Option<i64>,
+    /// Minor number, any when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor: Option<i64>,
+    /// Any of `r`ead, `w`rite and `m`knod
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>,
+}
+
+impl LinuxDeviceCgroup {
+    /// Rule allowing access to a device
+    pub fn allow(kind: &str, major: Option<i64>, minor: Option<i64>, access: &str) -> Self {
+        Self {
+            allow: true,
+            kind: Some(kind.to_string()),
+            major,
+            minor,
+            access: Some(access.to_string()),
+        }
+    }
+
+    /// The rule in the cgroup v1 `devices.allow` format, like `c 1:3 rwm`
+    pub fn to_v1(&self) -> String {
+        let number = |n: Option<i64>| n.map_or("*".to_string(), |n| n.to_string());
+        format!(
+            "{} {}:{} {}",
+            self.kind.as_deref().unwrap_or("a"),
+            number(self.major),
+            number(self.minor),
+            self.access.as_deref().unwrap_or("rwm")
+        )
+    }
+}
+
+/// Resource limits of a container
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxResources {
+    /// Device cgroup rules, in order; later rules override earlier ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<LinuxDeviceCgroup>,
+    /// Memory limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<LinuxMemory>,
+    /// CPU limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<LinuxCpu>,
+    /// Process count limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids: Option<LinuxPids>,
+    /// Block I/O weight and throttling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_io: Option<LinuxBlockIo>,
+}
+
+/// Block I/O weight and throttling
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxBlockIo {
+    /// Relative I/O weight, 10 to 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    /// Read bytes per second, per device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_read_bps_device: Vec<LinuxThrottleDevice>,
+    /// Write bytes per second, per device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_write_bps_device: Vec<LinuxThrottleDevice>,
+    /// Read operations per second, per device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_read_iops_device: Vec<LinuxThrottleDevice>,
+    /// Write operations per second, per device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_write_iops_device: Vec<LinuxThrottleDevice>,
+}
+
+/// I/O rate limit of a block device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinuxThrottleDevice {
+    pub major: i64,
+    pub minor: i64,
+    pub rate: u64,
+}
+
+/// Memory limits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxMemory {
+    /// Memory limit in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    /// Memory soft limit in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reservation: Option<i64>,
+    /// Memory plus swap limit in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<i64>,
+    /// Whether the OOM killer leaves the container alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable_oom_killer: Option<bool>,
+}
+
+/// CPU limits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinuxCpu {
+    /// Relative CPU weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shares: Option<u64>,
+    /// CPU time allowed per period, in microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<i64>,
+    /// CPU period in microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    /// CPUs the container may run on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    /// Memory nodes the container may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mems: Option<String>,
+}
+
+/// Process count limit
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinuxPids {
+    /// Maximum number of processes
+    pub limit: i64,
+}
+
+impl Hook {
+    /// Hook running a command line, `/path/to/binary [args...]`
+    pub fn parse(command: &str) -> Result<Self> {
+        let args: Vec<String> = command.split_whitespace().map(String::from).collect();
+        let path = args
+            .first()
+            .ok_or_else(|| RuneError::InvalidConfig("Empty hook command".to_string()))?
+            .clone();
+        Ok(Self {
+            path,
+            args,
+            ..Self::default()
+        })
+    }
+}
+
+impl Spec {
+    /// Read the spec of a bundle
+    pub fn load(bundle: &Path) -> Result<Self> {
+        let path = bundle.join(CONFIG_FILE);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| RuneError::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&contents).map_err(|e| {
+            RuneError::InvalidConfig(format!("Invalid OCI spec {}: {}", path.display(), e))
+        })
+    }
+
+    /// Write the spec into a bundle
+    pub fn save(&self, bundle: &Path) -> Result<()> {
+        std::fs::create_dir_all(bundle)?;
+        std::fs::write(bundle.join(CONFIG_FILE), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Root filesystem path, resolved against the bundle
+    pub fn rootfs(&self, bundle: &Path) -> std::path::PathBuf {
+        bundle.join(&self.root.path)
+    }
+
+    /// Whether the container gets a namespace of a type
+    pub fn has_namespace(&self, kind: &str) -> bool {
+        self.linux.namespaces.iter().any(|ns| ns.kind == kind)
+    }
+}
+
+/// State of a container, as OCI runtimes report it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct State {
+    /// Version of the spec
+    #[serde(default)]
+    pub oci_version: String,
+    /// Container ID
+    pub id: String,
+    /// `creating`, `created`, `running` or `stopped`
+    pub status: String,
+    /// Process ID, while the container has one
+    #[serde(default)]
+    pub pid: u32,
+    /// Bundle the container runs from
+    #[serde(default)]
+    pub bundle: String,
+}
+
+impl State {
+    /// Whether the container's process has exited
+    pub fn is_stopped(&self) -> bool {
+        self.status == "stopped"
+    }
+}
@@ -3,11 +3,23 @@
 //! Provides functionality for creating and managing container processes
 //! with proper namespace isolation.
 
+use super::capabilities;
+use super::hooks;
+use super::init;
+use super::lsm;
 use super::mount::MountManager;
 use super::namespace::{NamespaceManager, NamespaceType};
+use super::oci::{Hook, LinuxDevice, PosixRlimit, State};
+use super::rlimit;
+use super::seccomp::SeccompFilter;
 use super::syscall;
+use super::sysctl;
+use super::terminal::{self, Pty};
+use super::userns::UsernsRemap;
 use crate::error::{Result, RuneError};
 use std::collections::HashMap;
+use std::io::{PipeReader, PipeWriter, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
 use std::path::PathBuf;
 
 /// Process configuration for a container
@@ -31,10 +43,20 @@ pub struct ProcessConfig {
     pub capabilities_add: Vec<String>,
     /// Capabilities to drop
     pub capabilities_drop: Vec<String>,
+    /// Privileged, with every capability
+    pub privileged: bool,
     /// No new privileges flag
     pub no_new_privileges: bool,
     /// OOM score adjustment
     pub oom_score_adj: Option<i32>,
+    /// Resource limits
+    pub rlimits: Vec<PosixRlimit>,
+    /// AppArmor profile the command runs under
+    pub apparmor_profile: String,
+    /// SELinux label the command runs with
+    pub selinux_label: String,
+    /// Seccomp filter loaded before the command is executed
+    pub seccomp: Option<SeccompFilter>,
 }
 
 impl Default for ProcessConfig {
@@ -49,8 +71,13 @@ impl Default for ProcessConfig {
             terminal: false,
             capabilities_add: Vec::new(),
             capabilities_drop: Vec::new(),
+            privileged: false,
             no_new_privileges: true,
             oom_score_adj: None,
+            rlimits: Vec::new(),
+            apparmor_profile: String::new(),
+            selinux_label: String::new(),
+            seccomp: None,
         }
     }
 }
@@ -99,6 +126,21 @@ impl ProcessConfig {
         self.terminal = terminal;
         self
     }
+
+    /// Set the seccomp filter
+    pub fn seccomp(mut self, filter: Option<SeccompFilter>) -> Self {
+        self.seccomp = filter;
+        self
+    }
+
+    /// Capabilities the process keeps
+    pub fn capabilities(&self) -> Result<Vec<String>> {
+        capabilities::effective_capabilities(
+            &self.capabilities_add,
+            &self.capabilities_drop,
+            self.privileged,
+        )
+    }
 }
 
 /// Container process state
@@ -132,6 +174,39 @@ pub struct ContainerProcess {
     rootfs: Option<PathBuf>,
     /// Container ID
     container_id: Option<String>,
+    /// Daemon-wide uid/gid remapping
+    userns_remap: Option<UsernsRemap>,
+    /// Hostname, the container ID if unset
+    hostname: Option<String>,
+    /// Device nodes created in the root filesystem
+    devices: Vec<LinuxDevice>,
+    /// Kernel parameters set in the container's namespaces
+    sysctls: HashMap<String, String>,
+    /// Hooks run in the container right before its command, with their
+    /// state
+    start_hooks: Option<(Vec<Hook>, State)>,
+    /// Whether a started process waits to be released before its command
+    hold: bool,
+    /// Pipe a held process waits on
+    release: Option<PipeWriter>,
+    /// Master side of the process's terminal, if it has one
+    console: Option<OwnedFd>,
+    /// Standard streams of a process without a terminal
+    stdio: ProcessStreams,
+    /// Side of the process's standard input pipe the runtime writes to
+    stdin: Option<OwnedFd>,
+}
+
+/// Standard streams of a container's process that has no terminal, in
+/// place of the ones it would inherit from the runtime
+#[derive(Debug, Default)]
+pub struct ProcessStreams {
+    /// Keep standard input open on a pipe the runtime writes to
+    pub open_stdin: bool,
+    /// Where standard output goes
+    pub stdout: Option<OwnedFd>,
+    /// Where standard error goes
+    pub stderr: Option<OwnedFd>,
 }
 
 impl ContainerProcess {
@@ -145,6 +220,16 @@ impl ContainerProcess {
             state: ProcessState::Creating,
             rootfs: None,
             container_id: None,
+            userns_remap: None,
+            hostname: None,
+            devices: Vec::new(),
+            sysctls: HashMap::new(),
+            start_hooks: None,
+            hold: false,
+            release: None,
+            console: None,
+            stdio: ProcessStreams::default(),
+            stdin: None,
         })
     }
 
@@ -158,6 +243,71 @@ impl ContainerProcess {
         self.container_id = Some(id);
     }
 
+    /// Set the hostname
+    pub fn set_hostname(&mut self, hostname: String) {
+        self.hostname = Some(hostname);
+    }
+
+    /// Map the user namespace onto a remapped range instead of the
+    /// calling user
+    pub fn set_userns_remap(&mut self, remap: UsernsRemap) {
+        self.userns_remap = Some(remap);
+    }
+
+    /// Set the device nodes to create in the root filesystem
+    pub fn set_devices(&mut self, devices: Vec<LinuxDevice>) {
+        self.devices = devices;
+    }
+
+    /// Set kernel parameters in the container's namespaces
+    pub fn set_sysctls(&mut self, sysctls: HashMap<String, String>) {
+        self.sysctls = sysctls;
+    }
+
+    /// Run hooks in the container, after its root filesystem is set up and
+    /// before its command executes; the state gets the process's PID
+    pub fn set_start_hooks(&mut self, hooks: Vec<Hook>, state: State) {
+        self.start_hooks = Some((hooks, state));
+    }
+
+    /// Keep a started process waiting until it is released, so the runtime
+    /// can run hooks against it first
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    /// Let a held process run its command
+    pub fn release(&mut self) -> Result<()> {
+        if let Some(mut release) = self.release.take() {
+            release.write_all(&[1])?;
+        }
+        Ok(())
+    }
+
+    /// Track a process restored from a checkpoint instead of starting one
+    pub fn adopt(&mut self, pid: u32) {
+        self.pid = Some(pid);
+        self.state = ProcessState::Running;
+    }
+
+    /// Take the master side of the process's terminal, which only one
+    /// caller can attach to
+    pub fn take_console(&mut self) -> Option<OwnedFd> {
+        self.console.take()
+    }
+
+    /// Give the process standard streams of its own; a process with a
+    /// terminal uses the terminal instead
+    pub fn set_stdio(&mut self, stdio: ProcessStreams) {
+        self.stdio = stdio;
+    }
+
+    /// Take the side of the process's standard input pipe the runtime
+    /// writes to, which only one caller can attach to
+    pub fn take_stdin(&mut self) -> Option<OwnedFd> {
+        self.stdin.take()
+    }
+
     /// Get the process ID
     pub fn pid(&self) -> Option<u32> {
         self.pid
@@ -187,25 +337,54 @@ impl ContainerProcess {
         let ns_manager = NamespaceManager::new(self.container_id.as_deref().unwrap_or("unknown"));
         let clone_flags = ns_manager.get_clone_flags(&self.namespaces);
 
+        let barrier = if self.hold {
+            Some(std::io::pipe()?)
+        } else {
+            None
+        };
+        let pty = if self.config.terminal {
+            Some(Pty::open()?)
+        } else {
+            None
+        };
+        let stdin = if self.stdio.open_stdin && pty.is_none() {
+            Some(std::io::pipe()?)
+        } else {
+            None
+        };
+
         // Fork the process with new namespaces
         let pid = self.fork_with_namespaces(clone_flags)?;
 
         if pid == 0 {
             // Child process
-            self.child_process()?;
+            let console = pty.as_ref().map(|pty| pty.slave.as_raw_fd());
+            let stdin = stdin.map(|(reader, _)| reader);
+            self.child_process(barrier.map(|(reader, _)| reader), console, stdin)?;
             std::process::exit(0);
         } else {
             // Parent process
+            self.release = barrier.map(|(_, writer)| writer);
+            self.console = pty.map(|pty| pty.master);
+            self.stdin = stdin.map(|(_, writer)| writer.into());
+            // The process has its own copies of the streams it was given
+            self.stdio.stdout = None;
+            self.stdio.stderr = None;
             self.pid = Some(pid);
             self.state = ProcessState::Running;
 
             // Set up user namespace mappings if needed
             if self.namespaces.contains(&NamespaceType::User) {
-                // Map root in container to current user
-                let uid = unsafe { libc::getuid() };
-                let gid = unsafe { libc::getgid() };
-                let uid_map = format!("0 {} 1", uid);
-                let gid_map = format!("0 {} 1", gid);
+                // Map root in container to the remapped range, or else to
+                // the current user
+                let (uid_map, gid_map) = match &self.userns_remap {
+                    Some(remap) => (remap.uid_map(), remap.gid_map()),
+                    None => {
+                        let uid = unsafe { libc::getuid() };
+                        let gid = unsafe { libc::getgid() };
+                        (format!("0 {} 1", uid), format!("0 {} 1", gid))
+                    }
+                };
 
                 // Give the child time to start
                 std::thread::sleep(std::time::Duration::from_millis(10));
@@ -233,10 +412,41 @@ impl ContainerProcess {
     }
 
     /// Child process setup
-    fn child_process(&self) -> Result<()> {
+    fn child_process(
+        &self,
+        barrier: Option<PipeReader>,
+        console: Option<RawFd>,
+        stdin: Option<PipeReader>,
+    ) -> Result<()> {
+        // The terminal's session leader is the container's process
+        if let Some(console) = console {
+            terminal::make_controlling(console)?;
+        } else {
+            let streams = [
+                stdin.as_ref().map(|stdin| stdin.as_raw_fd()),
+                self.stdio.stdout.as_ref().map(|fd| fd.as_raw_fd()),
+                self.stdio.stderr.as_ref().map(|fd| fd.as_raw_fd()),
+            ];
+            for (stream, fd) in streams.into_iter().enumerate() {
+                if let Some(fd) = fd {
+                    if unsafe { libc::dup2(fd, stream as RawFd) } < 0 {
+                        return Err(RuneError::Runtime(format!(
+                            "Failed to attach standard stream {}: {}",
+                            stream,
+                            std::io::Error::last_os_error()
+                        )));
+                    }
+                }
+            }
+        }
+
         // Set hostname if UTS namespace is used
         if self.namespaces.contains(&NamespaceType::Uts) {
-            let hostname = self.container_id.as_deref().unwrap_or("rune-container");
+            let hostname = self
+                .hostname
+                .as_deref()
+                .or(self.container_id.as_deref())
+                .unwrap_or("rune-container");
             let _ = syscall::sethostname(&hostname[..std::cmp::min(64, hostname.len())]);
         }
 
@@ -250,6 +460,9 @@ impl ContainerProcess {
 
             // Create devices
             mount_manager.create_devices(&rootfs_str)?;
+            for device in &self.devices {
+                mount_manager.create_device_node(&rootfs_str, device)?;
+            }
 
             // Pivot to new root
             mount_manager.pivot_root(&rootfs_str, "/.pivot_root")?;
@@ -258,6 +471,43 @@ impl ContainerProcess {
         // Change to working directory
         let _ = syscall::chdir(&self.config.cwd);
 
+        // The container's /proc is in place, showing its namespaces
+        sysctl::apply(&self.sysctls)?;
+
+        // Wait for the runtime's hooks; the pipe closes unwritten when the
+        // runtime gives up on the container
+        if let Some(mut barrier) = barrier {
+            let mut byte = [0u8; 1];
+            if !matches!(barrier.read(&mut byte), Ok(1)) {
+                std::process::exit(1);
+            }
+        }
+        if let Some((start_hooks, state)) = &self.start_hooks {
+            let state = State {
+                pid: syscall::getpid(),
+                ..state.clone()
+            };
+            hooks::run("startContainer", start_hooks, &state)?;
+        }
+
+        // Raising limits and lowering the OOM score take privileges the
+        // process is about to drop
+        rlimit::apply(&self.config.rlimits)?;
+        if let Some(adj) = self.config.oom_score_adj {
+            std::fs::write("/proc/self/oom_score_adj", adj.to_string()).map_err(|e| {
+                RuneError::Runtime(format!("Failed to set OOM score adjustment: {}", e))
+            })?;
+        }
+
+        // Labels apply from the command's exec on; no_new_privs would keep
+        // them from changing
+        lsm::set_exec_labels(&self.config.apparmor_profile, &self.config.selinux_label)?;
+
+        // Limit capabilities while still root, keeping them across setuid
+        let capabilities = self.config.capabilities()?;
+        capabilities::restrict_bounding_set(&capabilities)?;
+        let _ = syscall::set_keepcaps(true);
+
         // Set UID/GID
         if self.config.gid != 0 {
             let _ = syscall::setgid(self.config.gid);
@@ -265,6 +515,29 @@ impl ContainerProcess {
         if self.config.uid != 0 {
             let _ = syscall::setuid(self.config.uid);
         }
+        capabilities::set_capabilities(&capabilities)?;
+
+        if self.config.no_new_privileges {
+            syscall::set_no_new_privs()
+                .map_err(|e| RuneError::Runtime(format!("Failed to set no_new_privs: {}", e)))?;
+        }
+
+        // Load seccomp last so the setup above isn't filtered
+        if let Some(ref filter) = self.config.seccomp {
+            filter.apply()?;
+        }
+
+        // Stay on as the init of a command run under it
+        if let Some(command) = init::unwrap(&self.config.args) {
+            let Some((program, args)) = command.split_first() else {
+                return Err(RuneError::Runtime(
+                    "No command to run under init".to_string(),
+                ));
+            };
+            let mut command = std::process::Command::new(program);
+            command.args(args).env_clear().envs(&self.config.env);
+            std::process::exit(init::run(command));
+        }
 
         // Execute the command
         if !self.config.args.is_empty() {
@@ -326,12 +599,24 @@ impl ContainerProcess {
 
     /// Check if the process is running
     pub fn is_running(&self) -> bool {
-        if let Some(pid) = self.pid {
-            // Check if process exists
-            syscall::kill(pid as i32, 0).is_ok()
-        } else {
-            false
+        let Some(pid) = self.pid.filter(|_| self.exit_code.is_none()) else {
+            return false;
+        };
+        // An exited child lingers as a zombie until reaped, so check for
+        // that before whether the process exists
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let waited = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if waited == 0 && unsafe { info.si_pid() } != 0 {
+            return false;
         }
+        syscall::kill(pid as i32, 0).is_ok()
     }
 }
 
@@ -360,7 +645,7 @@ impl ContainerExec {
 
         if pid == 0 {
             // Child process: enter namespaces
-            self.enter_namespaces()?;
+            enter_namespaces(self.container_pid)?;
 
             // Execute the command
             if !self.config.args.is_empty() {
@@ -382,38 +667,41 @@ impl ContainerExec {
 
         Ok(pid)
     }
+}
 
-    /// Enter the container's namespaces
-    fn enter_namespaces(&self) -> Result<()> {
-        use std::fs::File;
-        use std::os::unix::io::AsRawFd;
-
-        use super::syscall::clone_flags;
-
-        let ns_types = [
-            ("user", libc::CLONE_NEWUSER),
-            ("mnt", libc::CLONE_NEWNS),
-            ("uts", libc::CLONE_NEWUTS),
-            ("ipc", libc::CLONE_NEWIPC),
-            ("net", libc::CLONE_NEWNET),
-            ("pid", libc::CLONE_NEWPID),
-            ("cgroup", clone_flags::CLONE_NEWCGROUP),
-        ];
-
-        for (ns_name, ns_flag) in ns_types {
-            let ns_path = format!("/proc/{}/ns/{}", self.container_pid, ns_name);
-
-            if let Ok(file) = File::open(&ns_path) {
-                let fd = file.as_raw_fd();
-                let result = unsafe { libc::setns(fd, ns_flag) };
-                if result < 0 {
-                    tracing::warn!("Failed to enter {} namespace", ns_name);
-                }
+/// Move the calling process into the namespaces of a container's process
+///
+/// Call this from a forked child: the PID namespace only applies to the
+/// child's own children.
+pub fn enter_namespaces(container_pid: u32) -> Result<()> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    use super::syscall::clone_flags;
+
+    let ns_types = [
+        ("user", libc::CLONE_NEWUSER),
+        ("mnt", libc::CLONE_NEWNS),
+        ("uts", libc::CLONE_NEWUTS),
+        ("ipc", libc::CLONE_NEWIPC),
+        ("net", libc::CLONE_NEWNET),
+        ("pid", libc::CLONE_NEWPID),
+        ("cgroup", clone_flags::CLONE_NEWCGROUP),
+    ];
+
+    for (ns_name, ns_flag) in ns_types {
+        let ns_path = format!("/proc/{}/ns/{}", container_pid, ns_name);
+
+        if let Ok(file) = File::open(&ns_path) {
+            let fd = file.as_raw_fd();
+            let result = unsafe { libc::setns(fd, ns_flag) };
+            if result < 0 {
+                tracing::warn!("Failed to enter {} namespace", ns_name);
             }
         }
-
-        Ok(())
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -445,6 +733,23 @@ mod tests {
         assert!(config.terminal);
     }
 
+    #[test]
+    fn test_process_capabilities() {
+        let mut config = ProcessConfig::new(vec!["/bin/sh".to_string()]);
+        config.capabilities_add = vec!["sys_admin".to_string()];
+        config.capabilities_drop = vec!["CAP_NET_RAW".to_string()];
+        let capabilities = config.capabilities().unwrap();
+        assert!(capabilities.contains(&"CAP_SYS_ADMIN".to_string()));
+        assert!(capabilities.contains(&"CAP_CHOWN".to_string()));
+        assert!(!capabilities.contains(&"CAP_NET_RAW".to_string()));
+
+        config.capabilities_drop = vec!["all".to_string()];
+        assert_eq!(config.capabilities().unwrap(), vec!["CAP_SYS_ADMIN"]);
+
+        config.capabilities_add = vec!["NET_ADMINN".to_string()];
+        assert!(config.capabilities().is_err());
+    }
+
     #[test]
     fn test_container_process_creation() {
         let config = ProcessConfig::new(vec!["/bin/sh".to_string()]);
@@ -0,0 +1,78 @@
+//! Process resource limits
+//!
+//! Limits given with `--ulimit` are named like `ulimit`'s, `nofile` for
+//! `RLIMIT_NOFILE`, and set on the container process before it executes
+//! its command, while it may still raise hard limits.
+
+use super::oci::PosixRlimit;
+use super::syscall;
+use crate::error::{Result, RuneError};
+
+/// Resources by their `--ulimit` names
+const RESOURCES: &[(&str, libc::__rlimit_resource_t)] = &[
+    ("as", libc::RLIMIT_AS),
+    ("core", libc::RLIMIT_CORE),
+    ("cpu", libc::RLIMIT_CPU),
+    ("data", libc::RLIMIT_DATA),
+    ("fsize", libc::RLIMIT_FSIZE),
+    ("locks", libc::RLIMIT_LOCKS),
+    ("memlock", libc::RLIMIT_MEMLOCK),
+    ("msgqueue", libc::RLIMIT_MSGQUEUE),
+    ("nice", libc::RLIMIT_NICE),
+    ("nofile", libc::RLIMIT_NOFILE),
+    ("nproc", libc::RLIMIT_NPROC),
+    ("rss", libc::RLIMIT_RSS),
+    ("rtprio", libc::RLIMIT_RTPRIO),
+    ("rttime", libc::RLIMIT_RTTIME),
+    ("sigpending", libc::RLIMIT_SIGPENDING),
+    ("stack", libc::RLIMIT_STACK),
+];
+
+/// OCI type of a limit by its `--ulimit` name, like `RLIMIT_NOFILE`
+pub fn oci_type(name: &str) -> Option<String> {
+    RESOURCES
+        .iter()
+        .any(|(resource, _)| *resource == name)
+        .then(|| format!("RLIMIT_{}", name.to_uppercase()))
+}
+
+/// Resource of an OCI limit type
+fn resource(kind: &str) -> Option<libc::__rlimit_resource_t> {
+    let name = kind.strip_prefix("RLIMIT_")?.to_lowercase();
+    RESOURCES
+        .iter()
+        .find(|(resource, _)| *resource == name)
+        .map(|(_, resource)| *resource)
+}
+
+/// Set limits on the calling process
+pub fn apply(rlimits: &[PosixRlimit]) -> Result<()> {
+    for rlimit in rlimits {
+        let resource = resource(&rlimit.kind).ok_or_else(|| {
+            RuneError::InvalidConfig(format!("Unknown resource limit '{}'", rlimit.kind))
+        })?;
+        syscall::setrlimit(resource as i32, rlimit.soft, rlimit.hard)
+            .map_err(|e| RuneError::Runtime(format!("Failed to set {}: {}", rlimit.kind, e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resources() {
+        assert_eq!(oci_type("nofile").as_deref(), Some("RLIMIT_NOFILE"));
+        assert_eq!(oci_type("files"), None);
+        assert_eq!(resource("RLIMIT_NOFILE"), Some(libc::RLIMIT_NOFILE));
+        assert_eq!(resource("RLIMIT_AS"), Some(libc::RLIMIT_AS));
+        assert_eq!(resource("NOFILE"), None);
+        assert!(apply(&[PosixRlimit {
+            kind: "RLIMIT_FILES".to_string(),
+            hard: 1,
+            soft: 1,
+        }])
+        .is_err());
+    }
+}
@@ -0,0 +1,1584 @@
+//! Seccomp syscall filtering
+//!
+//! Containers run under Docker's default profile, a syscall allowlist,
+//! unless started with `--security-opt seccomp=unconfined` or a custom
+//! profile in Docker's JSON format. Profiles are compiled to a BPF program
+//! for the native architecture that the container process loads right
+//! before it executes its command.
+
+use super::syscall;
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+/// Errno the default profile fails blocked syscalls with
+const EPERM: u32 = 1;
+/// Errno for syscalls a container should fall back from
+const ENOSYS: u32 = 38;
+
+const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_ALU_AND_K: u16 = 0x54;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JGT_K: u16 = 0x25;
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+/// Longest program the kernel accepts
+const BPF_MAXINSNS: usize = 4096;
+/// Jump target standing for the end of the current rule
+const NEXT_RULE: u8 = u8::MAX;
+
+/// Offsets into `struct seccomp_data`
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Names profiles use for the native architecture
+#[cfg(target_arch = "x86_64")]
+const ARCH_NAMES: &[&str] = &["amd64", "x86_64", "SCMP_ARCH_X86_64"];
+#[cfg(target_arch = "aarch64")]
+const ARCH_NAMES: &[&str] = &["arm64", "aarch64", "SCMP_ARCH_AARCH64"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ARCH_NAMES: &[&str] = &[];
+
+macro_rules! syscall_table {
+    ($($nr:ident),* $(,)?) => {
+        &[$((stringify!($nr), libc::$nr)),*]
+    };
+}
+
+/// Syscalls known on both x86_64 and aarch64
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const COMMON_SYSCALLS: &[(&str, libc::c_long)] = syscall_table![
+    SYS_accept,
+    SYS_accept4,
+    SYS_acct,
+    SYS_adjtimex,
+    SYS_bind,
+    SYS_bpf,
+    SYS_brk,
+    SYS_capget,
+    SYS_capset,
+    SYS_chdir,
+    SYS_chroot,
+    SYS_clock_adjtime,
+    SYS_clock_getres,
+    SYS_clock_gettime,
+    SYS_clock_nanosleep,
+    SYS_clock_settime,
+    SYS_clone,
+    SYS_clone3,
+    SYS_close,
+    SYS_close_range,
+    SYS_connect,
+    SYS_copy_file_range,
+    SYS_delete_module,
+    SYS_dup,
+    SYS_dup3,
+    SYS_epoll_create1,
+    SYS_epoll_ctl,
+    SYS_epoll_pwait,
+    SYS_epoll_pwait2,
+    SYS_eventfd2,
+    SYS_execve,
+    SYS_execveat,
+    SYS_exit,
+    SYS_exit_group,
+    SYS_faccessat,
+    SYS_faccessat2,
+    SYS_fallocate,
+    SYS_fanotify_init,
+    SYS_fanotify_mark,
+    SYS_fchdir,
+    SYS_fchmod,
+    SYS_fchmodat,
+    SYS_fchown,
+    SYS_fchownat,
+    SYS_fcntl,
+    SYS_fdatasync,
+    SYS_fgetxattr,
+    SYS_finit_module,
+    SYS_flistxattr,
+    SYS_flock,
+    SYS_fremovexattr,
+    SYS_fsconfig,
+    SYS_fsetxattr,
+    SYS_fsmount,
+    SYS_fsopen,
+    SYS_fspick,
+    SYS_fstat,
+    SYS_fstatfs,
+    SYS_fsync,
+    SYS_ftruncate,
+    SYS_futex,
+    SYS_futex_waitv,
+    SYS_get_mempolicy,
+    SYS_get_robust_list,
+    SYS_getcpu,
+    SYS_getcwd,
+    SYS_getdents64,
+    SYS_getegid,
+    SYS_geteuid,
+    SYS_getgid,
+    SYS_getgroups,
+    SYS_getitimer,
+    SYS_getpeername,
+    SYS_getpgid,
+    SYS_getpid,
+    SYS_getppid,
+    SYS_getpriority,
+    SYS_getrandom,
+    SYS_getresgid,
+    SYS_getresuid,
+    SYS_getrusage,
+    SYS_getsid,
+    SYS_getsockname,
+    SYS_getsockopt,
+    SYS_gettid,
+    SYS_gettimeofday,
+    SYS_getuid,
+    SYS_getxattr,
+    SYS_init_module,
+    SYS_inotify_add_watch,
+    SYS_inotify_init1,
+    SYS_inotify_rm_watch,
+    SYS_io_cancel,
+    SYS_io_destroy,
+    SYS_io_getevents,
+    SYS_io_setup,
+    SYS_io_submit,
+    SYS_io_uring_enter,
+    SYS_io_uring_register,
+    SYS_io_uring_setup,
+    SYS_ioctl,
+    SYS_ioprio_get,
+    SYS_ioprio_set,
+    SYS_kcmp,
+    SYS_kill,
+    SYS_landlock_add_rule,
+    SYS_landlock_create_ruleset,
+    SYS_landlock_restrict_self,
+    SYS_lgetxattr,
+    SYS_linkat,
+    SYS_listen,
+    SYS_listxattr,
+    SYS_llistxattr,
+    SYS_lookup_dcookie,
+    SYS_lremovexattr,
+    SYS_lseek,
+    SYS_lsetxattr,
+    SYS_madvise,
+    SYS_mbind,
+    SYS_membarrier,
+    SYS_memfd_create,
+    SYS_memfd_secret,
+    SYS_mincore,
+    SYS_mkdirat,
+    SYS_mknodat,
+    SYS_mlock,
+    SYS_mlock2,
+    SYS_mlockall,
+    SYS_mmap,
+    SYS_mount,
+    SYS_mount_setattr,
+    SYS_move_mount,
+    SYS_mprotect,
+    SYS_mq_getsetattr,
+    SYS_mq_notify,
+    SYS_mq_open,
+    SYS_mq_timedreceive,
+    SYS_mq_timedsend,
+    SYS_mq_unlink,
+    SYS_mremap,
+    SYS_msgctl,
+    SYS_msgget,
+    SYS_msgrcv,
+    SYS_msgsnd,
+    SYS_msync,
+    SYS_munlock,
+    SYS_munlockall,
+    SYS_munmap,
+    SYS_name_to_handle_at,
+    SYS_nanosleep,
+    SYS_newfstatat,
+    SYS_open_tree,
+    SYS_openat,
+    SYS_openat2,
+    SYS_perf_event_open,
+    SYS_personality,
+    SYS_pidfd_getfd,
+    SYS_pidfd_open,
+    SYS_pidfd_send_signal,
+    SYS_pipe2,
+    SYS_pkey_alloc,
+    SYS_pkey_free,
+    SYS_pkey_mprotect,
+    SYS_ppoll,
+    SYS_prctl,
+    SYS_pread64,
+    SYS_preadv,
+    SYS_preadv2,
+    SYS_prlimit64,
+    SYS_process_madvise,
+    SYS_process_mrelease,
+    SYS_process_vm_readv,
+    SYS_process_vm_writev,
+    SYS_pselect6,
+    SYS_ptrace,
+    SYS_pwrite64,
+    SYS_pwritev,
+    SYS_pwritev2,
+    SYS_quotactl,
+    SYS_quotactl_fd,
+    SYS_read,
+    SYS_readahead,
+    SYS_readlinkat,
+    SYS_readv,
+    SYS_reboot,
+    SYS_recvfrom,
+    SYS_recvmmsg,
+    SYS_recvmsg,
+    SYS_remap_file_pages,
+    SYS_removexattr,
+    SYS_renameat2,
+    SYS_restart_syscall,
+    SYS_rseq,
+    SYS_rt_sigaction,
+    SYS_rt_sigpending,
+    SYS_rt_sigprocmask,
+    SYS_rt_sigqueueinfo,
+    SYS_rt_sigreturn,
+    SYS_rt_sigsuspend,
+    SYS_rt_sigtimedwait,
+    SYS_rt_tgsigqueueinfo,
+    SYS_sched_get_priority_max,
+    SYS_sched_get_priority_min,
+    SYS_sched_getaffinity,
+    SYS_sched_getattr,
+    SYS_sched_getparam,
+    SYS_sched_getscheduler,
+    SYS_sched_rr_get_interval,
+    SYS_sched_setaffinity,
+    SYS_sched_setattr,
+    SYS_sched_setparam,
+    SYS_sched_setscheduler,
+    SYS_sched_yield,
+    SYS_seccomp,
+    SYS_semctl,
+    SYS_semget,
+    SYS_semop,
+    SYS_semtimedop,
+    SYS_sendmmsg,
+    SYS_sendmsg,
+    SYS_sendto,
+    SYS_set_mempolicy,
+    SYS_set_mempolicy_home_node,
+    SYS_set_robust_list,
+    SYS_set_tid_address,
+    SYS_setdomainname,
+    SYS_setfsgid,
+    SYS_setfsuid,
+    SYS_setgid,
+    SYS_setgroups,
+    SYS_sethostname,
+    SYS_setitimer,
+    SYS_setns,
+    SYS_setpgid,
+    SYS_setpriority,
+    SYS_setregid,
+    SYS_setresgid,
+    SYS_setresuid,
+    SYS_setreuid,
+    SYS_setsid,
+    SYS_setsockopt,
+    SYS_settimeofday,
+    SYS_setuid,
+    SYS_setxattr,
+    SYS_shmat,
+    SYS_shmctl,
+    SYS_shmdt,
+    SYS_shmget,
+    SYS_shutdown,
+    SYS_sigaltstack,
+    SYS_signalfd4,
+    SYS_socket,
+    SYS_socketpair,
+    SYS_splice,
+    SYS_statfs,
+    SYS_statx,
+    SYS_symlinkat,
+    SYS_sync,
+    SYS_syncfs,
+    SYS_sysinfo,
+    SYS_syslog,
+    SYS_tee,
+    SYS_tgkill,
+    SYS_timer_create,
+    SYS_timer_delete,
+    SYS_timer_getoverrun,
+    SYS_timer_gettime,
+    SYS_timer_settime,
+    SYS_timerfd_create,
+    SYS_timerfd_gettime,
+    SYS_timerfd_settime,
+    SYS_times,
+    SYS_tkill,
+    SYS_truncate,
+    SYS_umask,
+    SYS_umount2,
+    SYS_uname,
+    SYS_unlinkat,
+    SYS_unshare,
+    SYS_utimensat,
+    SYS_vhangup,
+    SYS_vmsplice,
+    SYS_wait4,
+    SYS_waitid,
+    SYS_write,
+    SYS_writev,
+];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const COMMON_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+/// Syscalls only x86_64 has
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = syscall_table![
+    SYS_access,
+    SYS_alarm,
+    SYS_arch_prctl,
+    SYS_chmod,
+    SYS_chown,
+    SYS_creat,
+    SYS_dup2,
+    SYS_epoll_create,
+    SYS_epoll_ctl_old,
+    SYS_epoll_wait,
+    SYS_epoll_wait_old,
+    SYS_eventfd,
+    SYS_fadvise64,
+    SYS_fchmodat2,
+    SYS_fork,
+    SYS_futimesat,
+    SYS_get_thread_area,
+    SYS_getdents,
+    SYS_getpgrp,
+    SYS_getrlimit,
+    SYS_inotify_init,
+    SYS_ioperm,
+    SYS_iopl,
+    SYS_lchown,
+    SYS_link,
+    SYS_lstat,
+    SYS_mkdir,
+    SYS_mknod,
+    SYS_modify_ldt,
+    SYS_open,
+    SYS_pause,
+    SYS_pipe,
+    SYS_poll,
+    SYS_readlink,
+    SYS_rename,
+    SYS_renameat,
+    SYS_rmdir,
+    SYS_select,
+    SYS_sendfile,
+    SYS_set_thread_area,
+    SYS_setrlimit,
+    SYS_signalfd,
+    SYS_stat,
+    SYS_symlink,
+    SYS_sync_file_range,
+    SYS_time,
+    SYS_unlink,
+    SYS_utime,
+    SYS_utimes,
+    SYS_vfork,
+];
+#[cfg(not(target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+/// Syscalls the default profile allows every container
+const DEFAULT_ALLOWED: &[&str] = &[
+    "accept",
+    "accept4",
+    "access",
+    "adjtimex",
+    "alarm",
+    "bind",
+    "brk",
+    "cachestat",
+    "capget",
+    "capset",
+    "chdir",
+    "chmod",
+    "chown",
+    "chown32",
+    "clock_adjtime",
+    "clock_adjtime64",
+    "clock_getres",
+    "clock_getres_time64",
+    "clock_gettime",
+    "clock_gettime64",
+    "clock_nanosleep",
+    "clock_nanosleep_time64",
+    "close",
+    "close_range",
+    "connect",
+    "copy_file_range",
+    "creat",
+    "dup",
+    "dup2",
+    "dup3",
+    "epoll_create",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_ctl_old",
+    "epoll_pwait",
+    "epoll_pwait2",
+    "epoll_wait",
+    "epoll_wait_old",
+    "eventfd",
+    "eventfd2",
+    "execve",
+    "execveat",
+    "exit",
+    "exit_group",
+    "faccessat",
+    "faccessat2",
+    "fadvise64",
+    "fadvise64_64",
+    "fallocate",
+    "fanotify_mark",
+    "fchdir",
+    "fchmod",
+    "fchmodat",
+    "fchmodat2",
+    "fchown",
+    "fchown32",
+    "fchownat",
+    "fcntl",
+    "fcntl64",
+    "fdatasync",
+    "fgetxattr",
+    "flistxattr",
+    "flock",
+    "fork",
+    "fremovexattr",
+    "fsetxattr",
+    "fstat",
+    "fstat64",
+    "fstatat64",
+    "fstatfs",
+    "fstatfs64",
+    "fsync",
+    "ftruncate",
+    "ftruncate64",
+    "futex",
+    "futex_requeue",
+    "futex_time64",
+    "futex_wait",
+    "futex_waitv",
+    "futex_wake",
+    "futimesat",
+    "getcpu",
+    "getcwd",
+    "getdents",
+    "getdents64",
+    "getegid",
+    "getegid32",
+    "geteuid",
+    "geteuid32",
+    "getgid",
+    "getgid32",
+    "getgroups",
+    "getgroups32",
+    "getitimer",
+    "getpeername",
+    "getpgid",
+    "getpgrp",
+    "getpid",
+    "getppid",
+    "getpriority",
+    "getrandom",
+    "getresgid",
+    "getresgid32",
+    "getresuid",
+    "getresuid32",
+    "getrlimit",
+    "get_robust_list",
+    "getrusage",
+    "getsid",
+    "getsockname",
+    "getsockopt",
+    "get_thread_area",
+    "gettid",
+    "gettimeofday",
+    "getuid",
+    "getuid32",
+    "getxattr",
+    "inotify_add_watch",
+    "inotify_init",
+    "inotify_init1",
+    "inotify_rm_watch",
+    "io_cancel",
+    "ioctl",
+    "io_destroy",
+    "io_getevents",
+    "io_pgetevents",
+    "io_pgetevents_time64",
+    "ioprio_get",
+    "ioprio_set",
+    "io_setup",
+    "io_submit",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "ipc",
+    "kill",
+    "landlock_add_rule",
+    "landlock_create_ruleset",
+    "landlock_restrict_self",
+    "lchown",
+    "lchown32",
+    "lgetxattr",
+    "link",
+    "linkat",
+    "listen",
+    "listxattr",
+    "llistxattr",
+    "_llseek",
+    "lremovexattr",
+    "lseek",
+    "lsetxattr",
+    "lstat",
+    "lstat64",
+    "madvise",
+    "map_shadow_stack",
+    "membarrier",
+    "memfd_create",
+    "memfd_secret",
+    "mincore",
+    "mkdir",
+    "mkdirat",
+    "mknod",
+    "mknodat",
+    "mlock",
+    "mlock2",
+    "mlockall",
+    "mmap",
+    "mmap2",
+    "mprotect",
+    "mq_getsetattr",
+    "mq_notify",
+    "mq_open",
+    "mq_timedreceive",
+    "mq_timedreceive_time64",
+    "mq_timedsend",
+    "mq_timedsend_time64",
+    "mq_unlink",
+    "mremap",
+    "msgctl",
+    "msgget",
+    "msgrcv",
+    "msgsnd",
+    "msync",
+    "munlock",
+    "munlockall",
+    "munmap",
+    "name_to_handle_at",
+    "nanosleep",
+    "newfstatat",
+    "_newselect",
+    "open",
+    "openat",
+    "openat2",
+    "pause",
+    "pidfd_open",
+    "pidfd_send_signal",
+    "pipe",
+    "pipe2",
+    "pkey_alloc",
+    "pkey_free",
+    "pkey_mprotect",
+    "poll",
+    "ppoll",
+    "ppoll_time64",
+    "prctl",
+    "pread64",
+    "preadv",
+    "preadv2",
+    "prlimit64",
+    "process_mrelease",
+    "pselect6",
+    "pselect6_time64",
+    "ptrace",
+    "pwrite64",
+    "pwritev",
+    "pwritev2",
+    "read",
+    "readahead",
+    "readlink",
+    "readlinkat",
+    "readv",
+    "recv",
+    "recvfrom",
+    "recvmmsg",
+    "recvmmsg_time64",
+    "recvmsg",
+    "remap_file_pages",
+    "removexattr",
+    "rename",
+    "renameat",
+    "renameat2",
+    "restart_syscall",
+    "rmdir",
+    "rseq",
+    "rt_sigaction",
+    "rt_sigpending",
+    "rt_sigprocmask",
+    "rt_sigqueueinfo",
+    "rt_sigreturn",
+    "rt_sigsuspend",
+    "rt_sigtimedwait",
+    "rt_sigtimedwait_time64",
+    "rt_tgsigqueueinfo",
+    "sched_getaffinity",
+    "sched_getattr",
+    "sched_getparam",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_getscheduler",
+    "sched_rr_get_interval",
+    "sched_rr_get_interval_time64",
+    "sched_setaffinity",
+    "sched_setattr",
+    "sched_setparam",
+    "sched_setscheduler",
+    "sched_yield",
+    "seccomp",
+    "select",
+    "semctl",
+    "semget",
+    "semop",
+    "semtimedop",
+    "semtimedop_time64",
+    "send",
+    "sendfile",
+    "sendfile64",
+    "sendmmsg",
+    "sendmsg",
+    "sendto",
+    "setfsgid",
+    "setfsgid32",
+    "setfsuid",
+    "setfsuid32",
+    "setgid",
+    "setgid32",
+    "setgroups",
+    "setgroups32",
+    "setitimer",
+    "setpgid",
+    "setpriority",
+    "setregid",
+    "setregid32",
+    "setresgid",
+    "setresgid32",
+    "setresuid",
+    "setresuid32",
+    "setreuid",
+    "setreuid32",
+    "setrlimit",
+    "set_robust_list",
+    "setsid",
+    "setsockopt",
+    "set_thread_area",
+    "set_tid_address",
+    "setuid",
+    "setuid32",
+    "setxattr",
+    "shmat",
+    "shmctl",
+    "shmdt",
+    "shmget",
+    "shutdown",
+    "sigaltstack",
+    "signalfd",
+    "signalfd4",
+    "sigprocmask",
+    "sigreturn",
+    "socket",
+    "socketcall",
+    "socketpair",
+    "splice",
+    "stat",
+    "stat64",
+    "statfs",
+    "statfs64",
+    "statx",
+    "symlink",
+    "symlinkat",
+    "sync",
+    "sync_file_range",
+    "syncfs",
+    "sysinfo",
+    "tee",
+    "tgkill",
+    "time",
+    "timer_create",
+    "timer_delete",
+    "timer_getoverrun",
+    "timer_gettime",
+    "timer_gettime64",
+    "timer_settime",
+    "timer_settime64",
+    "timerfd_create",
+    "timerfd_gettime",
+    "timerfd_gettime64",
+    "timerfd_settime",
+    "timerfd_settime64",
+    "times",
+    "tkill",
+    "truncate",
+    "truncate64",
+    "ugetrlimit",
+    "umask",
+    "uname",
+    "unlink",
+    "unlinkat",
+    "utime",
+    "utimensat",
+    "utimensat_time64",
+    "utimes",
+    "vfork",
+    "vmsplice",
+    "wait4",
+    "waitid",
+    "waitpid",
+    "write",
+    "writev",
+];
+
+/// Syscalls the default profile allows containers with a capability
+const CAPABILITY_ALLOWED: &[(&str, &[&str])] = &[
+    (
+        "CAP_SYS_ADMIN",
+        &[
+            "bpf",
+            "clone",
+            "clone3",
+            "fanotify_init",
+            "fsconfig",
+            "fsmount",
+            "fsopen",
+            "fspick",
+            "lookup_dcookie",
+            "mount",
+            "mount_setattr",
+            "move_mount",
+            "open_tree",
+            "perf_event_open",
+            "quotactl",
+            "quotactl_fd",
+            "setdomainname",
+            "sethostname",
+            "setns",
+            "syslog",
+            "umount",
+            "umount2",
+            "unshare",
+        ],
+    ),
+    ("CAP_SYS_BOOT", &["reboot"]),
+    ("CAP_SYS_CHROOT", &["chroot"]),
+    (
+        "CAP_SYS_MODULE",
+        &["delete_module", "init_module", "finit_module"],
+    ),
+    ("CAP_SYS_PACCT", &["acct"]),
+    (
+        "CAP_SYS_PTRACE",
+        &[
+            "kcmp",
+            "pidfd_getfd",
+            "process_madvise",
+            "process_vm_readv",
+            "process_vm_writev",
+        ],
+    ),
+    ("CAP_SYS_RAWIO", &["iopl", "ioperm"]),
+    (
+        "CAP_SYS_TIME",
+        &["settimeofday", "stime", "clock_settime", "clock_settime64"],
+    ),
+    ("CAP_SYS_TTY_CONFIG", &["vhangup"]),
+    (
+        "CAP_SYS_NICE",
+        &[
+            "get_mempolicy",
+            "mbind",
+            "set_mempolicy",
+            "set_mempolicy_home_node",
+        ],
+    ),
+    ("CAP_SYSLOG", &["syslog"]),
+    ("CAP_BPF", &["bpf"]),
+    ("CAP_PERFMON", &["perf_event_open"]),
+];
+
+/// `clone` flags creating namespaces
+const CLONE_NAMESPACE_FLAGS: u64 = 0x7e02_0000;
+
+/// Personalities the default profile allows switching to
+const DEFAULT_PERSONALITIES: &[u64] = &[0x0, 0x8, 0x20000, 0x20008, 0xffff_ffff];
+
+/// Number of a syscall on the native architecture
+pub fn syscall_number(name: &str) -> Option<libc::c_long> {
+    COMMON_SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS)
+        .find(|(nr, _)| nr.strip_prefix("SYS_") == Some(name))
+        .map(|(_, nr)| *nr)
+}
+
+/// What happens when a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeccompAction {
+    #[serde(rename = "SCMP_ACT_KILL", alias = "SCMP_ACT_KILL_THREAD")]
+    Kill,
+    #[serde(rename = "SCMP_ACT_KILL_PROCESS")]
+    KillProcess,
+    #[serde(rename = "SCMP_ACT_TRAP")]
+    Trap,
+    #[serde(rename = "SCMP_ACT_ERRNO")]
+    Errno,
+    #[serde(rename = "SCMP_ACT_TRACE")]
+    Trace,
+    #[serde(rename = "SCMP_ACT_LOG")]
+    Log,
+    #[serde(rename = "SCMP_ACT_ALLOW")]
+    Allow,
+}
+
+impl SeccompAction {
+    /// Value the filter returns for the action
+    fn ret(self, errno: u32) -> u32 {
+        match self {
+            Self::Kill => SECCOMP_RET_KILL_THREAD,
+            Self::KillProcess => SECCOMP_RET_KILL_PROCESS,
+            Self::Trap => SECCOMP_RET_TRAP,
+            Self::Errno => SECCOMP_RET_ERRNO | (errno & 0xffff),
+            Self::Trace => SECCOMP_RET_TRACE | (errno & 0xffff),
+            Self::Log => SECCOMP_RET_LOG,
+            Self::Allow => SECCOMP_RET_ALLOW,
+        }
+    }
+}
+
+/// Comparison of a syscall argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeccompOperator {
+    #[serde(rename = "SCMP_CMP_NE")]
+    NotEqual,
+    #[serde(rename = "SCMP_CMP_LT")]
+    LessThan,
+    #[serde(rename = "SCMP_CMP_LE")]
+    LessOrEqual,
+    #[serde(rename = "SCMP_CMP_EQ")]
+    Equal,
+    #[serde(rename = "SCMP_CMP_GE")]
+    GreaterOrEqual,
+    #[serde(rename = "SCMP_CMP_GT")]
+    GreaterThan,
+    #[serde(rename = "SCMP_CMP_MASKED_EQ")]
+    MaskedEqual,
+}
+
+/// Condition on a syscall argument
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompArg {
+    /// Argument index, 0 to 5
+    pub index: u32,
+    /// Value to compare with, or the mask for `SCMP_CMP_MASKED_EQ`
+    pub value: u64,
+    /// Value the masked argument must equal
+    #[serde(default)]
+    pub value_two: u64,
+    /// Comparison
+    pub op: SeccompOperator,
+}
+
+/// Capabilities and architectures a rule applies to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompCondition {
+    /// Capabilities
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caps: Vec<String>,
+    /// Architectures
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arches: Vec<String>,
+}
+
+impl SeccompCondition {
+    fn is_empty(&self) -> bool {
+        self.caps.is_empty() && self.arches.is_empty()
+    }
+}
+
+/// Whether a capability is in a set, ignoring case and the `CAP_` prefix
+fn has_capability<S: AsRef<str>>(capabilities: &[S], cap: &str) -> bool {
+    let cap = cap.trim_start_matches("CAP_");
+    capabilities.iter().any(|c| {
+        c.as_ref()
+            .trim_start_matches("CAP_")
+            .eq_ignore_ascii_case(cap)
+    })
+}
+
+/// Rule of a seccomp profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompSyscall {
+    /// Syscalls the rule matches
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// Single syscall, as older profiles give it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Action taken on a match
+    pub action: SeccompAction,
+    /// Errno for `SCMP_ACT_ERRNO`, instead of the profile's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno_ret: Option<u32>,
+    /// Conditions on the syscall's arguments, all of which must hold
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<SeccompArg>,
+    /// Only apply to containers with all of these
+    #[serde(default, skip_serializing_if = "SeccompCondition::is_empty")]
+    pub includes: SeccompCondition,
+    /// Don't apply to containers with any of these
+    #[serde(default, skip_serializing_if = "SeccompCondition::is_empty")]
+    pub excludes: SeccompCondition,
+}
+
+impl SeccompSyscall {
+    fn new(names: &[&str], action: SeccompAction) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            name: None,
+            action,
+            errno_ret: None,
+            args: Vec::new(),
+            includes: SeccompCondition::default(),
+            excludes: SeccompCondition::default(),
+        }
+    }
+
+    /// Whether the rule applies to a container with the given capabilities
+    fn applies(&self, has_capability: impl Fn(&str) -> bool) -> bool {
+        let native = |arch: &String| ARCH_NAMES.contains(&arch.as_str());
+        self.includes.caps.iter().all(|cap| has_capability(cap))
+            && !self.excludes.caps.iter().any(|cap| has_capability(cap))
+            && (self.includes.arches.is_empty() || self.includes.arches.iter().any(native))
+            && !self.excludes.arches.iter().any(native)
+    }
+}
+
+/// Seccomp profile in Docker's JSON format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompProfile {
+    /// Action for syscalls no rule matches
+    pub default_action: SeccompAction,
+    /// Errno for `SCMP_ACT_ERRNO` actions, EPERM if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_errno_ret: Option<u32>,
+    /// Architectures the profile is meant for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub architectures: Vec<String>,
+    /// Rules, the first matching one applies
+    #[serde(default)]
+    pub syscalls: Vec<SeccompSyscall>,
+}
+
+impl SeccompProfile {
+    /// Docker's default profile
+    pub fn docker_default() -> Self {
+        let mut syscalls = vec![SeccompSyscall::new(DEFAULT_ALLOWED, SeccompAction::Allow)];
+
+        for personality in DEFAULT_PERSONALITIES {
+            let mut rule = SeccompSyscall::new(&["personality"], SeccompAction::Allow);
+            rule.args.push(SeccompArg {
+                index: 0,
+                value: *personality,
+                value_two: 0,
+                op: SeccompOperator::Equal,
+            });
+            syscalls.push(rule);
+        }
+
+        for (capability, names) in CAPABILITY_ALLOWED {
+            let mut rule = SeccompSyscall::new(names, SeccompAction::Allow);
+            rule.includes.caps.push(capability.to_string());
+            syscalls.push(rule);
+        }
+
+        let mut arch_specific =
+            SeccompSyscall::new(&["arch_prctl", "modify_ldt"], SeccompAction::Allow);
+        arch_specific.includes.arches = vec!["amd64".to_string(), "x32".to_string()];
+        syscalls.push(arch_specific);
+
+        // Without CAP_SYS_ADMIN, clone may not create namespaces, and clone3,
+        // whose flags can't be inspected, fails so libc falls back to clone
+        let mut clone = SeccompSyscall::new(&["clone"], SeccompAction::Allow);
+        clone.args.push(SeccompArg {
+            index: 0,
+            value: CLONE_NAMESPACE_FLAGS,
+            value_two: 0,
+            op: SeccompOperator::MaskedEqual,
+        });
+        clone.excludes.caps.push("CAP_SYS_ADMIN".to_string());
+        syscalls.push(clone);
+
+        let mut clone3 = SeccompSyscall::new(&["clone3"], SeccompAction::Errno);
+        clone3.errno_ret = Some(ENOSYS);
+        clone3.excludes.caps.push("CAP_SYS_ADMIN".to_string());
+        syscalls.push(clone3);
+
+        Self {
+            default_action: SeccompAction::Errno,
+            default_errno_ret: Some(EPERM),
+            architectures: vec![
+                "SCMP_ARCH_X86_64".to_string(),
+                "SCMP_ARCH_X86".to_string(),
+                "SCMP_ARCH_X32".to_string(),
+                "SCMP_ARCH_AARCH64".to_string(),
+                "SCMP_ARCH_ARM".to_string(),
+            ],
+            syscalls,
+        }
+    }
+
+    /// The rules that apply to a container with the given capabilities,
+    /// stripped of their conditions as OCI runtimes expect them
+    pub fn resolve<S: AsRef<str>>(&self, capabilities: &[S]) -> Self {
+        let syscalls = self
+            .syscalls
+            .iter()
+            .filter(|rule| rule.applies(|cap| has_capability(capabilities, cap)))
+            .map(|rule| SeccompSyscall {
+                names: rule.names.iter().chain(&rule.name).cloned().collect(),
+                name: None,
+                includes: SeccompCondition::default(),
+                excludes: SeccompCondition::default(),
+                ..rule.clone()
+            })
+            .collect();
+        Self {
+            syscalls,
+            ..self.clone()
+        }
+    }
+
+    /// Compile the profile for a container with the given capabilities
+    ///
+    /// Syscalls the native architecture doesn't have are skipped, and
+    /// syscalls of other architectures kill the process.
+    pub fn compile<S: AsRef<str>>(&self, capabilities: &[S]) -> Result<SeccompFilter> {
+        let arch = AUDIT_ARCH.ok_or_else(|| {
+            RuneError::Runtime("Seccomp is not supported on this architecture".to_string())
+        })?;
+        let has_capability = |cap: &str| has_capability(capabilities, cap);
+        let default_errno = self.default_errno_ret.unwrap_or(EPERM);
+
+        let mut program = vec![
+            BpfInstruction::load(DATA_ARCH),
+            BpfInstruction::jump(BPF_JMP_JEQ_K, arch, 1, 0),
+            BpfInstruction::ret(SECCOMP_RET_KILL_PROCESS),
+        ];
+        let mut nr_loaded = false;
+
+        for rule in &self.syscalls {
+            if let Some(arg) = rule.args.iter().find(|arg| arg.index > 5) {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Invalid seccomp argument index: {}",
+                    arg.index
+                )));
+            }
+            if !rule.applies(has_capability) {
+                continue;
+            }
+            let action = rule.action.ret(rule.errno_ret.unwrap_or(default_errno));
+
+            for name in rule.names.iter().chain(&rule.name) {
+                let Some(nr) = syscall_number(name) else {
+                    continue;
+                };
+                let mut block = Vec::new();
+                if !nr_loaded {
+                    block.push(BpfInstruction::load(DATA_NR));
+                }
+                block.push(BpfInstruction::jump(BPF_JMP_JEQ_K, nr as u32, 0, NEXT_RULE));
+                for arg in &rule.args {
+                    push_arg_check(&mut block, arg);
+                }
+                block.push(BpfInstruction::ret(action));
+
+                // Point jumps to the next rule past the end of this one
+                let len = block.len();
+                for (i, instruction) in block.iter_mut().enumerate() {
+                    let next_rule = (len - i - 1) as u8;
+                    if instruction.jt == NEXT_RULE {
+                        instruction.jt = next_rule;
+                    }
+                    if instruction.jf == NEXT_RULE {
+                        instruction.jf = next_rule;
+                    }
+                }
+                program.extend(block);
+                nr_loaded = rule.args.is_empty();
+            }
+        }
+
+        program.push(BpfInstruction::ret(self.default_action.ret(default_errno)));
+        if program.len() > BPF_MAXINSNS {
+            return Err(RuneError::InvalidConfig(format!(
+                "Seccomp profile is too large: {} instructions",
+                program.len()
+            )));
+        }
+        Ok(SeccompFilter { program })
+    }
+}
+
+/// Append instructions falling through when an argument matches and jumping
+/// to the next rule when it doesn't
+fn push_arg_check(block: &mut Vec<BpfInstruction>, arg: &SeccompArg) {
+    let low = DATA_ARGS + arg.index * 8;
+    let (low, high) = if cfg!(target_endian = "little") {
+        (low, low + 4)
+    } else {
+        (low + 4, low)
+    };
+    let (value_low, value_high) = (arg.value as u32, (arg.value >> 32) as u32);
+
+    use BpfInstruction as I;
+    let instructions = match arg.op {
+        SeccompOperator::Equal => vec![
+            I::load(high),
+            I::jump(BPF_JMP_JEQ_K, value_high, 0, NEXT_RULE),
+            I::load(low),
+            I::jump(BPF_JMP_JEQ_K, value_low, 0, NEXT_RULE),
+        ],
+        SeccompOperator::NotEqual => vec![
+            I::load(high),
+            I::jump(BPF_JMP_JEQ_K, value_high, 0, 2),
+            I::load(low),
+            I::jump(BPF_JMP_JEQ_K, value_low, NEXT_RULE, 0),
+        ],
+        SeccompOperator::MaskedEqual => vec![
+            I::load(high),
+            I::and(value_high),
+            I::jump(BPF_JMP_JEQ_K, (arg.value_two >> 32) as u32, 0, NEXT_RULE),
+            I::load(low),
+            I::and(value_low),
+            I::jump(BPF_JMP_JEQ_K, arg.value_two as u32, 0, NEXT_RULE),
+        ],
+        SeccompOperator::GreaterThan | SeccompOperator::GreaterOrEqual => vec![
+            I::load(high),
+            I::jump(BPF_JMP_JGT_K, value_high, 3, 0),
+            I::jump(BPF_JMP_JEQ_K, value_high, 0, NEXT_RULE),
+            I::load(low),
+            if arg.op == SeccompOperator::GreaterThan {
+                I::jump(BPF_JMP_JGT_K, value_low, 0, NEXT_RULE)
+            } else {
+                I::jump(BPF_JMP_JGE_K, value_low, 0, NEXT_RULE)
+            },
+        ],
+        SeccompOperator::LessThan | SeccompOperator::LessOrEqual => vec![
+            I::load(high),
+            I::jump(BPF_JMP_JGE_K, value_high, 0, 3),
+            I::jump(BPF_JMP_JGT_K, value_high, NEXT_RULE, 0),
+            I::load(low),
+            if arg.op == SeccompOperator::LessThan {
+                I::jump(BPF_JMP_JGE_K, value_low, NEXT_RULE, 0)
+            } else {
+                I::jump(BPF_JMP_JGT_K, value_low, NEXT_RULE, 0)
+            },
+        ],
+    };
+    block.extend(instructions);
+}
+
+/// Classic BPF instruction, laid out as `struct sock_filter`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl BpfInstruction {
+    fn load(offset: u32) -> Self {
+        Self::jump(BPF_LD_W_ABS, offset, 0, 0)
+    }
+
+    fn and(mask: u32) -> Self {
+        Self::jump(BPF_ALU_AND_K, mask, 0, 0)
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        Self { code, jt, jf, k }
+    }
+
+    fn ret(value: u32) -> Self {
+        Self::jump(BPF_RET_K, value, 0, 0)
+    }
+}
+
+/// Compiled seccomp profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeccompFilter {
+    program: Vec<BpfInstruction>,
+}
+
+impl SeccompFilter {
+    /// The BPF program
+    pub fn instructions(&self) -> &[BpfInstruction] {
+        &self.program
+    }
+
+    /// Load the filter into the calling thread, which can't be undone
+    ///
+    /// The thread must have no_new_privs set or CAP_SYS_ADMIN.
+    pub fn apply(&self) -> Result<()> {
+        let program: Vec<libc::sock_filter> = self
+            .program
+            .iter()
+            .map(|i| libc::sock_filter {
+                code: i.code,
+                jt: i.jt,
+                jf: i.jf,
+                k: i.k,
+            })
+            .collect();
+        syscall::seccomp_set_mode_filter(&program)
+            .map_err(|e| RuneError::Runtime(format!("Failed to load seccomp filter: {}", e)))
+    }
+}
+
+/// Seccomp mode of a container
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Seccomp {
+    /// Docker's default profile
+    #[default]
+    Default,
+    /// No filtering
+    Unconfined,
+    /// Custom profile
+    Custom(Box<SeccompProfile>),
+}
+
+impl Seccomp {
+    /// Seccomp mode selected by `--security-opt` options
+    ///
+    /// A `seccomp` option holds `unconfined`, `builtin` or a profile's JSON.
+    pub fn from_security_opts(options: &[String]) -> Result<Self> {
+        let mut seccomp = Self::Default;
+        for option in options {
+            let (key, value) = option.split_once(['=', ':']).unwrap_or((option, ""));
+            match key {
+                "seccomp" => {
+                    seccomp = match value {
+                        "unconfined" => Self::Unconfined,
+                        "builtin" => Self::Default,
+                        profile => {
+                            Self::Custom(Box::new(serde_json::from_str(profile).map_err(|e| {
+                                RuneError::InvalidConfig(format!("Invalid seccomp profile: {}", e))
+                            })?))
+                        }
+                    }
+                }
+                "apparmor" | "label" | "no-new-privileges" => {}
+                _ => {
+                    return Err(RuneError::InvalidConfig(format!(
+                        "Invalid --security-opt: {}",
+                        option
+                    )))
+                }
+            }
+        }
+        Ok(seccomp)
+    }
+
+    /// Name of the mode, as shown by inspect
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Unconfined => "unconfined",
+            Self::Custom(_) => "custom",
+        }
+    }
+
+    /// Profile of the mode, if it filters
+    pub fn profile(&self) -> Option<Cow<'_, SeccompProfile>> {
+        match self {
+            Self::Default => Some(Cow::Owned(SeccompProfile::docker_default())),
+            Self::Unconfined => None,
+            Self::Custom(profile) => Some(Cow::Borrowed(profile)),
+        }
+    }
+
+    /// Filter for a container with the given capabilities
+    ///
+    /// The default profile is skipped where seccomp isn't supported.
+    pub fn filter<S: AsRef<str>>(&self, capabilities: &[S]) -> Result<Option<SeccompFilter>> {
+        if *self == Self::Default && AUDIT_ARCH.is_none() {
+            return Ok(None);
+        }
+        self.profile()
+            .map(|profile| profile.compile(capabilities))
+            .transpose()
+    }
+}
+
+/// Replace `seccomp=<file>` options with the file's profile, relative to `dir`
+pub fn read_seccomp_profiles(options: &[String], dir: &Path) -> Result<Vec<String>> {
+    options
+        .iter()
+        .map(|option| match option.split_once('=') {
+            Some(("seccomp", value))
+                if !matches!(value, "unconfined" | "builtin") && !value.starts_with('{') =>
+            {
+                let path = dir.join(value);
+                let profile = fs::read_to_string(&path).map_err(|e| {
+                    RuneError::InvalidConfig(format!(
+                        "Failed to read seccomp profile {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                Ok(format!("seccomp={}", profile))
+            }
+            _ => Ok(option.clone()),
+        })
+        .collect()
+}
+
+#[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Run a filter on a syscall like the kernel would
+    fn run(filter: &SeccompFilter, arch: u32, nr: libc::c_long, args: [u64; 6]) -> u32 {
+        let mut data = [0u8; 64];
+        data[0..4].copy_from_slice(&(nr as u32).to_ne_bytes());
+        data[4..8].copy_from_slice(&arch.to_ne_bytes());
+        for (i, arg) in args.iter().enumerate() {
+            data[16 + i * 8..24 + i * 8].copy_from_slice(&arg.to_ne_bytes());
+        }
+
+        let program = filter.instructions();
+        let (mut pc, mut acc) = (0, 0u32);
+        loop {
+            let i = program[pc];
+            pc += 1;
+            match i.code {
+                BPF_LD_W_ABS => {
+                    let k = i.k as usize;
+                    acc = u32::from_ne_bytes(data[k..k + 4].try_into().unwrap());
+                }
+                BPF_ALU_AND_K => acc &= i.k,
+                BPF_RET_K => return i.k,
+                code => {
+                    let taken = match code {
+                        BPF_JMP_JEQ_K => acc == i.k,
+                        BPF_JMP_JGT_K => acc > i.k,
+                        BPF_JMP_JGE_K => acc >= i.k,
+                        _ => panic!("unexpected opcode {:#x}", code),
+                    };
+                    pc += if taken { i.jt } else { i.jf } as usize;
+                }
+            }
+        }
+    }
+
+    fn call(filter: &SeccompFilter, name: &str, args: [u64; 6]) -> u32 {
+        run(
+            filter,
+            AUDIT_ARCH.unwrap(),
+            syscall_number(name).unwrap(),
+            args,
+        )
+    }
+
+    #[test]
+    fn test_default_profile() {
+        let capabilities = ["CAP_CHOWN", "CAP_SYS_CHROOT"];
+        let filter = SeccompProfile::docker_default()
+            .compile(&capabilities)
+            .unwrap();
+        assert!(filter.instructions().len() < BPF_MAXINSNS);
+
+        assert_eq!(call(&filter, "read", [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(&filter, "chroot", [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(&filter, "reboot", [0; 6]), SECCOMP_RET_ERRNO | EPERM);
+        assert_eq!(call(&filter, "mount", [0; 6]), SECCOMP_RET_ERRNO | EPERM);
+        assert_eq!(call(&filter, "clone3", [0; 6]), SECCOMP_RET_ERRNO | ENOSYS);
+
+        // clone may start threads but not create namespaces
+        let thread = 0x3d0f00;
+        assert_eq!(
+            call(&filter, "clone", [thread, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        let new_user = 0x1000_0000;
+        assert_eq!(
+            call(&filter, "clone", [thread | new_user, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | EPERM
+        );
+
+        assert_eq!(
+            call(&filter, "personality", [0x8, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            call(&filter, "personality", [0x0040_0000, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ERRNO | EPERM
+        );
+
+        // Other architectures' syscalls kill the process
+        assert_eq!(
+            run(&filter, 0x4000_0003, 0, [0; 6]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+
+        let admin = SeccompProfile::docker_default()
+            .compile(&["SYS_ADMIN"])
+            .unwrap();
+        assert_eq!(call(&admin, "mount", [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(
+            call(&admin, "clone", [thread | new_user, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(call(&admin, "chroot", [0; 6]), SECCOMP_RET_ERRNO | EPERM);
+    }
+
+    #[test]
+    fn test_custom_profile() {
+        let profile: SeccompProfile = serde_json::from_str(
+            r#"{
+                "defaultAction": "SCMP_ACT_ALLOW",
+                "syscalls": [
+                    {"name": "mkdir", "action": "SCMP_ACT_ERRNO", "errnoRet": 13},
+                    {
+                        "names": ["write"],
+                        "action": "SCMP_ACT_KILL_PROCESS",
+                        "args": [{"index": 0, "value": 2, "op": "SCMP_CMP_GT"}]
+                    },
+                    {
+                        "names": ["read"],
+                        "action": "SCMP_ACT_LOG",
+                        "args": [
+                            {"index": 2, "value": 4294967296, "op": "SCMP_CMP_LE"},
+                            {"index": 0, "value": 0, "op": "SCMP_CMP_NE"}
+                        ]
+                    },
+                    {"names": ["no_such_syscall"], "action": "SCMP_ACT_TRAP"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let filter = profile.compile::<&str>(&[]).unwrap();
+
+        assert_eq!(call(&filter, "getpid", [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(call(&filter, "mkdirat", [0; 6]), SECCOMP_RET_ALLOW);
+        if syscall_number("mkdir").is_some() {
+            assert_eq!(call(&filter, "mkdir", [0; 6]), SECCOMP_RET_ERRNO | 13);
+        }
+
+        assert_eq!(
+            call(&filter, "write", [2, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            call(&filter, "write", [3, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        assert_eq!(
+            call(&filter, "write", [1 << 32, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_KILL_PROCESS
+        );
+
+        assert_eq!(
+            call(&filter, "read", [3, 0, 1 << 32, 0, 0, 0]),
+            SECCOMP_RET_LOG
+        );
+        assert_eq!(
+            call(&filter, "read", [3, 0, (1 << 32) + 1, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            call(&filter, "read", [0, 0, 10, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+
+        let invalid = r#"{"defaultAction": "SCMP_ACT_NOPE"}"#;
+        assert!(serde_json::from_str::<SeccompProfile>(invalid).is_err());
+        let mut out_of_range = profile.clone();
+        out_of_range.syscalls[1].args[0].index = 6;
+        assert!(out_of_range.compile::<&str>(&[]).is_err());
+    }
+
+    #[test]
+    fn test_security_opts() {
+        assert_eq!(Seccomp::from_security_opts(&[]).unwrap(), Seccomp::Default);
+        let unconfined = Seccomp::from_security_opts(&["seccomp=unconfined".to_string()]).unwrap();
+        assert_eq!(unconfined.name(), "unconfined");
+        assert!(unconfined.filter(&["CAP_CHOWN"]).unwrap().is_none());
+        assert!(Seccomp::from_security_opts(&["seccomp=nope".to_string()]).is_err());
+        assert!(Seccomp::from_security_opts(&["selinux=x".to_string()]).is_err());
+
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("profile.json"),
+            r#"{"defaultAction": "SCMP_ACT_ERRNO", "syscalls": []}"#,
+        )
+        .unwrap();
+        let options = vec![
+            "no-new-privileges".to_string(),
+            "seccomp=profile.json".to_string(),
+        ];
+        let options = read_seccomp_profiles(&options, dir.path()).unwrap();
+        assert_eq!(options[0], "no-new-privileges");
+        let custom = Seccomp::from_security_opts(&options).unwrap();
+        assert_eq!(custom.name(), "custom");
+        let filter = custom.filter(&["CAP_CHOWN"]).unwrap().unwrap();
+        assert_eq!(call(&filter, "read", [0; 6]), SECCOMP_RET_ERRNO | EPERM);
+
+        let missing = vec!["seccomp=missing.json".to_string()];
+        assert!(read_seccomp_profiles(&missing, dir.path()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Signal names
+//!
+//! Signals are given by name, with or without the `SIG` prefix, or by
+//! number, as `kill`, `--stop-signal` and the image's STOPSIGNAL do.
+
+use crate::error::{Result, RuneError};
+
+/// Signals by name, without the `SIG` prefix
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("IOT", libc::SIGIOT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("POLL", libc::SIGPOLL),
+    ("PWR", libc::SIGPWR),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Parse a signal name such as `SIGTERM` or `term`, a real-time signal such
+/// as `RTMIN+3`, or a signal number
+pub fn parse_signal(signal: &str) -> Result<i32> {
+    let invalid = || RuneError::InvalidConfig(format!("Invalid signal '{}'", signal));
+    if let Ok(number) = signal.parse::<i32>() {
+        return match number {
+            1..=64 => Ok(number),
+            _ => Err(invalid()),
+        };
+    }
+
+    let name = signal.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    if let Some((_, number)) = SIGNALS.iter().find(|(known, _)| *known == name) {
+        return Ok(*number);
+    }
+    let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+    let number = match name {
+        "RTMIN" => min,
+        "RTMAX" => max,
+        _ => match (name.strip_prefix("RTMIN+"), name.strip_prefix("RTMAX-")) {
+            (Some(offset), _) => min + offset.parse::<i32>().map_err(|_| invalid())?,
+            (_, Some(offset)) => max - offset.parse::<i32>().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        },
+    };
+    if (min..=max).contains(&number) {
+        Ok(number)
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGTERM").unwrap(), libc::SIGTERM);
+        assert_eq!(parse_signal("kill").unwrap(), libc::SIGKILL);
+        assert_eq!(parse_signal("sigusr1").unwrap(), libc::SIGUSR1);
+        assert_eq!(parse_signal("9").unwrap(), 9);
+        assert_eq!(parse_signal("RTMIN+2").unwrap(), libc::SIGRTMIN() + 2);
+        assert_eq!(parse_signal("SIGRTMAX").unwrap(), libc::SIGRTMAX());
+
+        for invalid in ["", "0", "65", "SIGNOPE", "RTMIN+100", "RTMAX-x"] {
+            assert!(parse_signal(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -238,6 +238,91 @@ pub fn chdir(path: &str) -> SyscallResult<()> {
     }
 }
 
+/// Create a device node
+pub fn mknod(path: &str, mode: u32, major: u32, minor: u32) -> SyscallResult<()> {
+    let path = std::ffi::CString::new(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let result = unsafe { libc::mknod(path.as_ptr(), mode, libc::makedev(major, minor)) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Magic number of the filesystem a path is on, like
+/// `CGROUP2_SUPER_MAGIC`
+pub fn filesystem_type(path: &str) -> SyscallResult<i64> {
+    let path = std::ffi::CString::new(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::statfs(path.as_ptr(), &mut stat) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(stat.f_type as i64)
+    }
+}
+
+/// Set an extended attribute of a path, without following symlinks
+pub fn lsetxattr(path: &str, name: &str, value: &[u8]) -> SyscallResult<()> {
+    let path = std::ffi::CString::new(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let name = std::ffi::CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid name"))?;
+    let result = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Create an inotify instance, returning its file descriptor
+pub fn inotify_init() -> SyscallResult<i32> {
+    let result = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Watch a path for the events of a mask
+pub fn inotify_add_watch(fd: i32, path: &str, mask: u32) -> SyscallResult<i32> {
+    let path = std::ffi::CString::new(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let result = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Wait up to a timeout for a file descriptor to become readable
+pub fn poll_readable(fd: i32, timeout_ms: i32) -> SyscallResult<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let result = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result > 0)
+    }
+}
+
 /// Set user and group IDs
 pub fn setuid(uid: u32) -> SyscallResult<()> {
     let result = unsafe { libc::setuid(uid) };
@@ -376,6 +461,99 @@ pub fn setrlimit(resource: i32, soft: u64, hard: u64) -> SyscallResult<()> {
     }
 }
 
+/// Set the no_new_privs bit of the calling thread
+pub fn set_no_new_privs() -> SyscallResult<()> {
+    let result = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Drop a capability from the calling thread's bounding set
+pub fn capbset_drop(capability: u32) -> SyscallResult<()> {
+    let result =
+        unsafe { libc::prctl(libc::PR_CAPBSET_DROP, capability as libc::c_ulong, 0, 0, 0) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Keep permitted capabilities when switching from root to another user
+pub fn set_keepcaps(keep: bool) -> SyscallResult<()> {
+    let result = unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, keep as libc::c_ulong, 0, 0, 0) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Set the capability sets of the calling thread from bit masks
+pub fn capset(effective: u64, permitted: u64, inheritable: u64) -> SyscallResult<()> {
+    /// `_LINUX_CAPABILITY_VERSION_3`, with 64-bit sets split in two words
+    const VERSION_3: u32 = 0x2008_0522;
+
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    let header = Header {
+        version: VERSION_3,
+        pid: 0,
+    };
+    let word = |mask: u64, i: u32| (mask >> (32 * i)) as u32;
+    let data = [0, 1].map(|i| Data {
+        effective: word(effective, i),
+        permitted: word(permitted, i),
+        inheritable: word(inheritable, i),
+    });
+
+    let result =
+        unsafe { libc::syscall(libc::SYS_capset, &header as *const Header, data.as_ptr()) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Load a seccomp BPF program into the calling thread
+pub fn seccomp_set_mode_filter(program: &[libc::sock_filter]) -> SyscallResult<()> {
+    let prog = libc::sock_fprog {
+        len: program.len() as libc::c_ushort,
+        filter: program.as_ptr() as *mut libc::sock_filter,
+    };
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
 /// Resource limit constants
 pub mod rlimit {
     pub const RLIMIT_CPU: i32 = 0;
@@ -0,0 +1,76 @@
+//! Namespaced kernel parameters
+//!
+//! Containers may only set the sysctls that are scoped to one of their
+//! namespaces, so they can't change the host's: those of the IPC
+//! namespace, and those of the network namespace unless they share the
+//! host's network.
+
+use crate::error::{Result, RuneError};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Sysctls of the IPC namespace
+const IPC_SYSCTLS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// Check a container may set a sysctl
+pub fn validate(name: &str, host_network: bool) -> Result<()> {
+    if IPC_SYSCTLS.contains(&name) || name.starts_with("fs.mqueue.") {
+        return Ok(());
+    }
+    if name.starts_with("net.") {
+        if host_network {
+            return Err(RuneError::InvalidConfig(format!(
+                "Sysctl '{}' is not allowed in the host's network namespace",
+                name
+            )));
+        }
+        return Ok(());
+    }
+    Err(RuneError::InvalidConfig(format!(
+        "Sysctl '{}' is not allowed: it is not namespaced",
+        name
+    )))
+}
+
+/// Path of a sysctl under `/proc/sys`
+fn path(name: &str) -> PathBuf {
+    PathBuf::from("/proc/sys").join(name.replace('.', "/"))
+}
+
+/// Set sysctls in the namespaces of the calling process
+pub fn apply(sysctls: &HashMap<String, String>) -> Result<()> {
+    for (name, value) in sysctls {
+        std::fs::write(path(name), value).map_err(|e| {
+            RuneError::Runtime(format!("Failed to set sysctl {}={}: {}", name, value, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("kernel.shmmax", true).is_ok());
+        assert!(validate("fs.mqueue.msg_max", false).is_ok());
+        assert!(validate("net.ipv4.ip_forward", false).is_ok());
+        assert!(validate("net.ipv4.ip_forward", true).is_err());
+        assert!(validate("kernel.hostname", false).is_err());
+        assert!(validate("vm.swappiness", false).is_err());
+        assert_eq!(
+            path("net.ipv4.ip_forward"),
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+    }
+}
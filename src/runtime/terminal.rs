@@ -0,0 +1,511 @@
+//! Container terminals
+//!
+//! A container run with a terminal gets the slave side of a pseudo-terminal
+//! as its controlling terminal and standard streams, while the runtime keeps
+//! the master side. Attaching to a container puts the host terminal in raw
+//! mode, copies input and output between the two, resizes the container's
+//! terminal with the host's and forwards the signals the attached process
+//! gets, until the container exits or the detach keys are typed.
+
+use super::syscall;
+use crate::error::{Result, RuneError};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Keys that detach from a container unless others are given
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Signals an attached process forwards to the container
+const FORWARDED_SIGNALS: [i32; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// Write end of the pipe signal handlers report signals on
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// A pseudo-terminal
+pub struct Pty {
+    /// Side the runtime reads output from and writes input to
+    pub master: OwnedFd,
+    /// Side the container's process uses as its terminal
+    pub slave: OwnedFd,
+}
+
+impl Pty {
+    /// Open a pseudo-terminal; its master side isn't inherited across exec
+    pub fn open() -> Result<Self> {
+        let (mut master, mut slave) = (-1, -1);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        if result < 0 {
+            return Err(RuneError::Runtime(format!(
+                "Failed to open a pseudo-terminal: {}",
+                io::Error::last_os_error()
+            )));
+        }
+        let pty = unsafe {
+            Self {
+                master: OwnedFd::from_raw_fd(master),
+                slave: OwnedFd::from_raw_fd(slave),
+            }
+        };
+        unsafe { libc::fcntl(pty.master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+        Ok(pty)
+    }
+}
+
+/// Make a terminal the calling process's controlling terminal and its
+/// standard streams, in a new session
+///
+/// Call this from a forked child before it execs the container's command.
+pub fn make_controlling(slave: RawFd) -> Result<()> {
+    let failed = |what: &str| {
+        RuneError::Runtime(format!(
+            "Failed to {}: {}",
+            what,
+            io::Error::last_os_error()
+        ))
+    };
+    if unsafe { libc::setsid() } < 0 {
+        return Err(failed("start a session"));
+    }
+    if unsafe { libc::ioctl(slave, libc::TIOCSCTTY as _, 0) } < 0 {
+        return Err(failed("take the controlling terminal"));
+    }
+    for stream in 0..3 {
+        if unsafe { libc::dup2(slave, stream) } < 0 {
+            return Err(failed("attach the terminal"));
+        }
+    }
+    if slave > 2 {
+        unsafe { libc::close(slave) };
+    }
+    Ok(())
+}
+
+/// Size of a terminal, in characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// Size of the terminal a file descriptor refers to, if it is one
+pub fn window_size(fd: RawFd) -> Option<WindowSize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } < 0 {
+        return None;
+    }
+    Some(WindowSize {
+        rows: size.ws_row,
+        cols: size.ws_col,
+    })
+}
+
+/// Resize a terminal, which signals its foreground process group
+pub fn set_window_size(fd: RawFd, size: WindowSize) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(RuneError::Runtime(format!(
+            "Failed to resize the terminal: {}",
+            io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+/// A terminal in raw mode, restored to its former mode when dropped
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put a terminal in raw mode: input is passed on as typed, without
+    /// echo, line editing or the keys that signal
+    pub fn enter(fd: RawFd) -> Result<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } < 0 {
+            return Err(RuneError::Runtime(format!(
+                "Failed to read the terminal mode: {}",
+                io::Error::last_os_error()
+            )));
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // Output still turns newlines into carriage return and newline
+        raw.c_oflag |= libc::OPOST;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } < 0 {
+            return Err(RuneError::Runtime(format!(
+                "Failed to set the terminal to raw mode: {}",
+                io::Error::last_os_error()
+            )));
+        }
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Key sequence that detaches from a container, like `ctrl-p,ctrl-q`;
+/// an empty one never detaches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachKeys {
+    keys: Vec<u8>,
+    /// How many of the keys were last typed, held back from the container
+    matched: usize,
+}
+
+impl DetachKeys {
+    /// Input to pass on to the container, holding back what may start the
+    /// sequence, and whether the sequence was completed
+    pub fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len());
+        for &byte in input {
+            if self.keys.is_empty() {
+                forward.push(byte);
+                continue;
+            }
+            if byte != self.keys[self.matched] {
+                // What was held back wasn't the sequence after all
+                forward.extend_from_slice(&self.keys[..self.matched]);
+                self.matched = 0;
+                if byte != self.keys[0] {
+                    forward.push(byte);
+                    continue;
+                }
+            }
+            self.matched += 1;
+            if self.matched == self.keys.len() {
+                self.matched = 0;
+                return (forward, true);
+            }
+        }
+        (forward, false)
+    }
+}
+
+impl Default for DetachKeys {
+    fn default() -> Self {
+        DEFAULT_DETACH_KEYS
+            .parse()
+            .expect("default detach keys are valid")
+    }
+}
+
+impl FromStr for DetachKeys {
+    type Err = RuneError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || RuneError::InvalidConfig(format!("Invalid detach keys: {}", s));
+        let keys = s
+            .split(',')
+            .filter(|key| !s.is_empty() || !key.is_empty())
+            .map(|key| match key.strip_prefix("ctrl-") {
+                Some(control) => match control.as_bytes() {
+                    [c @ b'a'..=b'z'] => Ok(c - b'a' + 1),
+                    [c @ (b'@' | b'[' | b'\\' | b']' | b'^' | b'_')] => Ok(c - b'@'),
+                    _ => Err(invalid()),
+                },
+                None => match key.as_bytes() {
+                    [c] if c.is_ascii_graphic() => Ok(*c),
+                    _ => Err(invalid()),
+                },
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { keys, matched: 0 })
+    }
+}
+
+/// How an attachment to a container ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachEnd {
+    /// The container's process exited
+    Exited,
+    /// The detach keys were typed; the container keeps running
+    Detached,
+}
+
+/// Stay attached to a container until `exited` becomes readable, as it
+/// does once the container's process exits, or the detach keys are typed,
+/// passing the signals this process gets to `forward`. With a terminal, its
+/// output is copied to standard output. Standard input is copied to
+/// `input`, the container's terminal or standard input, if given, and
+/// `input` closed at its end; when that is a terminal, the host terminal,
+/// if standard input is one, is in raw mode meanwhile. Output the container
+/// doesn't write to a terminal goes to the streams it was started with.
+pub fn attach(
+    exited: BorrowedFd<'_>,
+    console: Option<&OwnedFd>,
+    mut input: Option<OwnedFd>,
+    mut forward: impl FnMut(i32),
+    mut detach_keys: DetachKeys,
+) -> Result<AttachEnd> {
+    let signals = SignalPipe::install()?;
+    let stdin = io::stdin().as_raw_fd();
+    let _raw_mode = match (console, &input) {
+        (Some(_), Some(_)) if unsafe { libc::isatty(stdin) } == 1 => Some(RawMode::enter(stdin)?),
+        _ => None,
+    };
+    let resize = || {
+        if let (Some(console), Some(size)) = (console, window_size(stdin)) {
+            let _ = set_window_size(console.as_raw_fd(), size);
+        }
+    };
+    resize();
+
+    let mut stdout = io::stdout();
+    let mut buffer = [0u8; 4096];
+    let mut reading_console = console.is_some();
+    loop {
+        let mut fds = vec![
+            pollfd(exited.as_raw_fd()),
+            pollfd(signals.reader.as_raw_fd()),
+        ];
+        if let Some(console) = console.filter(|_| reading_console) {
+            fds.push(pollfd(console.as_raw_fd()));
+        }
+        if input.is_some() {
+            fds.push(pollfd(stdin));
+        }
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(RuneError::Runtime(format!("Failed to poll: {}", e)));
+        }
+
+        for fd in &fds {
+            if fd.revents == 0 {
+                continue;
+            }
+            if fd.fd == signals.reader.as_raw_fd() {
+                for signal in signals.take() {
+                    if signal == libc::SIGWINCH {
+                        resize();
+                    } else {
+                        forward(signal);
+                    }
+                }
+            } else if Some(fd.fd) == console.map(|c| c.as_raw_fd()) {
+                // The console reads nothing once the process is gone
+                match read(fd.fd, &mut buffer) {
+                    Some(n) => {
+                        stdout.write_all(&buffer[..n])?;
+                        stdout.flush()?;
+                    }
+                    None => reading_console = false,
+                }
+            } else if fd.fd == stdin {
+                let Some(target) = &input else {
+                    continue;
+                };
+                let Some(n) = read(stdin, &mut buffer) else {
+                    // The container reads the end of its input too
+                    input = None;
+                    continue;
+                };
+                let (forwarded, detached) = detach_keys.feed(&buffer[..n]);
+                write_all(target.as_raw_fd(), &forwarded)?;
+                if detached {
+                    return Ok(AttachEnd::Detached);
+                }
+            }
+        }
+
+        if fds[0].revents != 0 {
+            // Pass on what the process wrote before it exited
+            if let Some(console) = console.filter(|_| reading_console) {
+                while syscall::poll_readable(console.as_raw_fd(), 0).unwrap_or(false) {
+                    let Some(n) = read(console.as_raw_fd(), &mut buffer) else {
+                        break;
+                    };
+                    stdout.write_all(&buffer[..n])?;
+                }
+                stdout.flush()?;
+            }
+            return Ok(AttachEnd::Exited);
+        }
+    }
+}
+
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// Read what is available; `None` at the end of input or on an error, as
+/// reading a console whose other side is closed fails
+fn read(fd: RawFd, buffer: &mut [u8]) -> Option<usize> {
+    loop {
+        let n = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if n > 0 {
+            return Some(n as usize);
+        }
+        if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        return None;
+    }
+}
+
+fn write_all(fd: RawFd, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        let n = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+        data = &data[n as usize..];
+    }
+    Ok(())
+}
+
+/// Handlers of the forwarded signals and of window size changes that
+/// report them on a pipe; dropping it restores the former handlers
+struct SignalPipe {
+    reader: OwnedFd,
+    _writer: OwnedFd,
+    previous: Vec<(i32, libc::sigaction)>,
+}
+
+impl SignalPipe {
+    fn install() -> Result<Self> {
+        let mut fds = [-1; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let (reader, writer) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        SIGNAL_PIPE.store(writer.as_raw_fd(), Ordering::SeqCst);
+
+        let mut previous = Vec::new();
+        for signal in FORWARDED_SIGNALS.into_iter().chain([libc::SIGWINCH]) {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = report_signal as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_RESTART;
+            let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+            if unsafe { libc::sigaction(signal, &action, &mut old) } == 0 {
+                previous.push((signal, old));
+            }
+        }
+        Ok(Self {
+            reader,
+            _writer: writer,
+            previous,
+        })
+    }
+
+    /// Signals reported since last taken
+    fn take(&self) -> Vec<i32> {
+        let mut buffer = [0u8; 64];
+        let mut signals = Vec::new();
+        while let Some(n) = read(self.reader.as_raw_fd(), &mut buffer) {
+            signals.extend(buffer[..n].iter().map(|&signal| signal as i32));
+        }
+        signals
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for (signal, old) in &self.previous {
+            unsafe { libc::sigaction(*signal, old, std::ptr::null_mut()) };
+        }
+        SIGNAL_PIPE.store(-1, Ordering::SeqCst);
+    }
+}
+
+extern "C" fn report_signal(signal: libc::c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = signal as u8;
+        unsafe { libc::write(fd, (&byte as *const u8).cast(), 1) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detach_keys() {
+        let mut keys = DetachKeys::default();
+        assert_eq!(keys.feed(b"ls\r"), (b"ls\r".to_vec(), false));
+        // The first key is held back until the next shows it isn't the
+        // sequence
+        assert_eq!(keys.feed(&[0x10]), (Vec::new(), false));
+        assert_eq!(keys.feed(b"x"), (vec![0x10, b'x'], false));
+        assert_eq!(
+            keys.feed(&[b'a', 0x10, 0x10, 0x11]),
+            (vec![b'a', 0x10], true)
+        );
+
+        let mut single: DetachKeys = "ctrl-\\".parse().unwrap();
+        assert_eq!(single.feed(&[b'q', 0x1c, b'r']), (vec![b'q'], true));
+        let mut letters: DetachKeys = "a,b".parse().unwrap();
+        assert_eq!(letters.feed(b"aab"), (b"a".to_vec(), true));
+        let mut none: DetachKeys = "".parse().unwrap();
+        assert_eq!(none.feed(&[0x10, 0x11]), (vec![0x10, 0x11], false));
+
+        for keys in ["ctrl-", "ctrl-1", "ab", "ctrl-p,", " "] {
+            assert!(keys.parse::<DetachKeys>().is_err(), "{}", keys);
+        }
+    }
+
+    #[test]
+    fn test_pty() {
+        let pty = Pty::open().unwrap();
+        let size = WindowSize {
+            rows: 40,
+            cols: 120,
+        };
+        set_window_size(pty.master.as_raw_fd(), size).unwrap();
+        assert_eq!(window_size(pty.slave.as_raw_fd()), Some(size));
+
+        {
+            let _raw = RawMode::enter(pty.slave.as_raw_fd()).unwrap();
+            let mut mode: libc::termios = unsafe { std::mem::zeroed() };
+            unsafe { libc::tcgetattr(pty.slave.as_raw_fd(), &mut mode) };
+            assert_eq!(mode.c_lflag & libc::ECHO, 0);
+        }
+        let mut mode: libc::termios = unsafe { std::mem::zeroed() };
+        unsafe { libc::tcgetattr(pty.slave.as_raw_fd(), &mut mode) };
+        assert_ne!(mode.c_lflag & libc::ECHO, 0);
+
+        write_all(pty.master.as_raw_fd(), b"hi\n").unwrap();
+        let mut buffer = [0u8; 16];
+        assert_eq!(read(pty.slave.as_raw_fd(), &mut buffer), Some(3));
+        assert_eq!(&buffer[..3], b"hi\n");
+    }
+}
@@ -0,0 +1,210 @@
+//! User namespace remapping
+//!
+//! With `userns-remap` set, the daemon runs every container in a user
+//! namespace whose uids and gids map onto the subordinate ranges that
+//! `/etc/subuid` and `/etc/subgid` grant to the remap user, so root in a
+//! container is an unprivileged user on the host. Several ranges for the
+//! same user are stacked one after the other inside the container.
+
+use crate::error::{Result, RuneError};
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// User the daemon remaps to when `userns-remap` is `default`
+pub const DEFAULT_REMAP_USER: &str = "runeremap";
+
+/// One contiguous range of a uid or gid mapping, as the OCI runtime spec
+/// writes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMap {
+    /// First id inside the container
+    #[serde(rename = "containerID")]
+    pub container_id: u32,
+    /// First id on the host
+    #[serde(rename = "hostID")]
+    pub host_id: u32,
+    /// Number of ids in the range
+    pub size: u32,
+}
+
+/// Daemon-wide uid/gid remapping
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsernsRemap {
+    /// User the ranges belong to
+    pub user: String,
+    /// Group the gid ranges belong to
+    pub group: String,
+    /// Uid mapping
+    pub uid_maps: Vec<IdMap>,
+    /// Gid mapping
+    pub gid_maps: Vec<IdMap>,
+}
+
+impl UsernsRemap {
+    /// Remapping for a `userns-remap` setting, read from `/etc/subuid` and
+    /// `/etc/subgid`
+    ///
+    /// The setting is `default`, `user` or `user:group`.
+    pub fn load(setting: &str) -> Result<Self> {
+        let read = |path: &str| {
+            std::fs::read_to_string(path)
+                .map_err(|e| RuneError::InvalidConfig(format!("Failed to read {}: {}", path, e)))
+        };
+        Self::from_ranges(setting, &read("/etc/subuid")?, &read("/etc/subgid")?)
+    }
+
+    /// Remapping for a `userns-remap` setting, given the contents of the
+    /// subordinate id files
+    pub fn from_ranges(setting: &str, subuid: &str, subgid: &str) -> Result<Self> {
+        let setting = setting.trim();
+        let (user, group) = match setting {
+            "" => {
+                return Err(RuneError::InvalidConfig(
+                    "userns-remap must name a user".to_string(),
+                ))
+            }
+            "default" => (DEFAULT_REMAP_USER, DEFAULT_REMAP_USER),
+            _ => setting.split_once(':').unwrap_or((setting, setting)),
+        };
+
+        let uid_maps = subordinate_ranges(subuid, user);
+        if uid_maps.is_empty() {
+            return Err(RuneError::InvalidConfig(format!(
+                "No subordinate uid range for '{}' in /etc/subuid",
+                user
+            )));
+        }
+        let gid_maps = subordinate_ranges(subgid, group);
+        if gid_maps.is_empty() {
+            return Err(RuneError::InvalidConfig(format!(
+                "No subordinate gid range for '{}' in /etc/subgid",
+                group
+            )));
+        }
+
+        Ok(Self {
+            user: user.to_string(),
+            group: group.to_string(),
+            uid_maps,
+            gid_maps,
+        })
+    }
+
+    /// Host uid of a container uid
+    pub fn host_uid(&self, uid: u32) -> Option<u32> {
+        to_host(&self.uid_maps, uid)
+    }
+
+    /// Host gid of a container gid
+    pub fn host_gid(&self, gid: u32) -> Option<u32> {
+        to_host(&self.gid_maps, gid)
+    }
+
+    /// Host uid and gid of root in the container
+    pub fn root_pair(&self) -> (u32, u32) {
+        (self.uid_maps[0].host_id, self.gid_maps[0].host_id)
+    }
+
+    /// Contents for `/proc/<pid>/uid_map`
+    pub fn uid_map(&self) -> String {
+        format_map(&self.uid_maps)
+    }
+
+    /// Contents for `/proc/<pid>/gid_map`
+    pub fn gid_map(&self) -> String {
+        format_map(&self.gid_maps)
+    }
+
+    /// Change the owner of a file from a container id to its host id,
+    /// without following symlinks
+    pub fn chown(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        let host_uid = self.host_uid(uid).ok_or_else(|| {
+            RuneError::Runtime(format!("uid {} of {} is not mapped", uid, path.display()))
+        })?;
+        let host_gid = self.host_gid(gid).ok_or_else(|| {
+            RuneError::Runtime(format!("gid {} of {} is not mapped", gid, path.display()))
+        })?;
+        std::os::unix::fs::lchown(path, Some(host_uid), Some(host_gid))
+            .map_err(|e| RuneError::Runtime(format!("Failed to chown {}: {}", path.display(), e)))
+    }
+
+    /// Shift the owners of a whole tree into the remapped range
+    pub fn chown_tree(&self, path: &Path) -> Result<()> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                self.chown_tree(&entry?.path())?;
+            }
+        }
+        self.chown(path, metadata.uid(), metadata.gid())
+    }
+}
+
+/// Ranges of a user in a subordinate id file, stacked from id 0
+fn subordinate_ranges(contents: &str, name: &str) -> Vec<IdMap> {
+    let mut container_id = 0u32;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let owner = fields.next()?;
+            let start = fields.next()?.trim().parse::<u32>().ok()?;
+            let size = fields.next()?.trim().parse::<u32>().ok()?;
+            (owner == name && size > 0).then_some((start, size))
+        })
+        .map(|(host_id, size)| {
+            let map = IdMap {
+                container_id,
+                host_id,
+                size,
+            };
+            container_id = container_id.saturating_add(size);
+            map
+        })
+        .collect()
+}
+
+fn to_host(maps: &[IdMap], id: u32) -> Option<u32> {
+    maps.iter()
+        .find(|map| id >= map.container_id && id - map.container_id < map.size)
+        .map(|map| map.host_id + (id - map.container_id))
+}
+
+fn format_map(maps: &[IdMap]) -> String {
+    maps.iter()
+        .map(|map| format!("{} {} {}\n", map.container_id, map.host_id, map.size))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBUID: &str =
+        "alice:100000:65536\nruneremap:200000:1000\n# comment\nruneremap:300000:500\n";
+    const SUBGID: &str = "alice:100000:65536\nruneremap:400000:65536\n";
+
+    #[test]
+    fn test_parse_remap() {
+        let remap = UsernsRemap::from_ranges("default", SUBUID, SUBGID).unwrap();
+        assert_eq!(remap.user, "runeremap");
+        assert_eq!(remap.root_pair(), (200000, 400000));
+        assert_eq!(remap.uid_map(), "0 200000 1000\n1000 300000 500\n");
+        assert_eq!(remap.host_uid(999), Some(200999));
+        assert_eq!(remap.host_uid(1000), Some(300000));
+        assert_eq!(remap.host_uid(1500), None);
+        assert_eq!(remap.host_gid(65535), Some(465535));
+
+        let remap = UsernsRemap::from_ranges("alice:runeremap", SUBUID, SUBGID).unwrap();
+        assert_eq!(remap.root_pair(), (100000, 400000));
+
+        let error = UsernsRemap::from_ranges("bob", SUBUID, SUBGID)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No subordinate uid range for 'bob'"));
+        assert!(UsernsRemap::from_ranges("alice:bob", SUBUID, SUBGID).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Container metrics history
+//!
+//! Samples of running containers' resource use are kept on disk, one JSON
+//! line per sample in a file per container, for as long as the retention
+//! lets them. Samples are the cumulative counters a metrics source reports,
+//! so rates between any two of them can be worked out afterwards.
+
+use crate::container::{ContainerManager, ContainerStatus};
+use crate::error::{Result, RuneError};
+use crate::runtime::metrics::NetworkStats;
+use crate::runtime::{ContainerMetrics, MetricsSource};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long samples are kept by default
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time between samples by default
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time between dropping expired samples
+const PRUNE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A sample of a container's resource use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MetricsSample {
+    /// When the sample was taken
+    pub time: DateTime<Utc>,
+    /// Total CPU time used in microseconds
+    pub cpu_usage_usec: u64,
+    /// Memory in use in bytes
+    pub memory_usage: u64,
+    /// Memory limit in bytes, if one is set
+    pub memory_limit: Option<u64>,
+    /// Bytes received
+    pub rx_bytes: u64,
+    /// Bytes sent
+    pub tx_bytes: u64,
+    /// Processes killed by the OOM killer
+    pub oom_kills: u64,
+}
+
+impl MetricsSample {
+    /// Sample from metrics taken at `time`
+    pub fn new(time: DateTime<Utc>, metrics: &ContainerMetrics) -> Self {
+        Self {
+            time,
+            cpu_usage_usec: metrics.cpu_usage_usec,
+            memory_usage: metrics.memory_usage,
+            memory_limit: metrics.memory_limit,
+            rx_bytes: metrics.network.rx_bytes,
+            tx_bytes: metrics.network.tx_bytes,
+            oom_kills: metrics.oom_kills,
+        }
+    }
+}
+
+impl From<&MetricsSample> for ContainerMetrics {
+    fn from(sample: &MetricsSample) -> Self {
+        Self {
+            cpu_usage_usec: sample.cpu_usage_usec,
+            memory_usage: sample.memory_usage,
+            memory_limit: sample.memory_limit,
+            network: NetworkStats {
+                rx_bytes: sample.rx_bytes,
+                tx_bytes: sample.tx_bytes,
+            },
+            oom_kills: sample.oom_kills,
+            ..Self::default()
+        }
+    }
+}
+
+/// Samples of containers on disk
+pub struct MetricsStore {
+    dir: PathBuf,
+    retention: Duration,
+    /// Held while a file is written, so appends don't race a prune
+    lock: Mutex<()>,
+}
+
+impl MetricsStore {
+    /// Open the store in `dir`, keeping samples for `retention`
+    pub fn new(dir: PathBuf, retention: Duration) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            retention,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// How long samples are kept
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    fn path(&self, container_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", container_id))
+    }
+
+    /// Add a sample of a container
+    pub fn record(&self, container_id: &str, sample: &MetricsSample) -> Result<()> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire metrics lock".to_string()))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(container_id))?;
+        writeln!(file, "{}", serde_json::to_string(sample)?)?;
+        Ok(())
+    }
+
+    /// Samples of a container taken since `since`, or all kept, oldest
+    /// first
+    pub fn history(
+        &self,
+        container_id: &str,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<MetricsSample>> {
+        let samples = read_samples(&self.path(container_id))?;
+        Ok(samples
+            .into_iter()
+            .filter(|sample| since.is_none_or(|since| sample.time >= since))
+            .collect())
+    }
+
+    /// Forget the samples of a container
+    pub fn remove(&self, container_id: &str) -> Result<()> {
+        match fs::remove_file(self.path(container_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop samples older than the retention as of `now`, and the files of
+    /// containers left with none
+    pub fn prune(&self, now: DateTime<Utc>) -> Result<()> {
+        let cutoff = now - chrono::Duration::from_std(self.retention).unwrap_or_default();
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire metrics lock".to_string()))?;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "jsonl") {
+                continue;
+            }
+            let samples = read_samples(&path)?;
+            if samples.first().is_some_and(|first| first.time >= cutoff) {
+                continue;
+            }
+            let kept: Vec<String> = samples
+                .iter()
+                .filter(|sample| sample.time >= cutoff)
+                .map(serde_json::to_string)
+                .collect::<std::result::Result<_, _>>()?;
+            if kept.is_empty() {
+                fs::remove_file(&path)?;
+            } else {
+                fs::write(&path, kept.join("\n") + "\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sample the running containers of a manager every `interval`, on a
+    /// thread of its own, dropping expired samples now and then
+    pub fn spawn_recorder(
+        self: Arc<Self>,
+        containers: Arc<ContainerManager>,
+        source: Arc<dyn MetricsSource>,
+        interval: Duration,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut last_prune = Instant::now();
+            loop {
+                if let Err(e) = self.record_running(&containers, source.as_ref()) {
+                    warn!("Failed to record container metrics: {}", e);
+                }
+                if last_prune.elapsed() >= PRUNE_INTERVAL {
+                    if let Err(e) = self.prune(Utc::now()) {
+                        warn!("Failed to prune container metrics: {}", e);
+                    }
+                    last_prune = Instant::now();
+                }
+                thread::sleep(interval);
+            }
+        })
+    }
+
+    /// Record a sample of each running container
+    fn record_running(
+        &self,
+        containers: &ContainerManager,
+        source: &dyn MetricsSource,
+    ) -> Result<()> {
+        let now = Utc::now();
+        for container in containers.list(false)? {
+            if container.status != ContainerStatus::Running {
+                continue;
+            }
+            // Containers whose cgroup is gone until they are reaped have
+            // nothing to sample
+            if let Ok(metrics) = source.sample(&container.id, container.pid) {
+                self.record(&container.id, &MetricsSample::new(now, &metrics))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Samples in a file, skipping lines a crash left torn
+fn read_samples(path: &Path) -> Result<Vec<MetricsSample>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample(time: DateTime<Utc>, cpu_usage_usec: u64) -> MetricsSample {
+        MetricsSample::new(
+            time,
+            &ContainerMetrics {
+                cpu_usage_usec,
+                memory_usage: 1024,
+                ..ContainerMetrics::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_history_and_prune() {
+        let dir = TempDir::new().unwrap();
+        let store = MetricsStore::new(dir.path().to_path_buf(), Duration::from_secs(3600)).unwrap();
+        let now = Utc::now();
+        for minutes in [90, 30, 10] {
+            store
+                .record(
+                    "web",
+                    &sample(now - chrono::Duration::minutes(minutes), minutes as u64),
+                )
+                .unwrap();
+        }
+        store
+            .record("old", &sample(now - chrono::Duration::hours(2), 0))
+            .unwrap();
+
+        assert_eq!(store.history("web", None).unwrap().len(), 3);
+        let recent = store
+            .history("web", Some(now - chrono::Duration::minutes(20)))
+            .unwrap();
+        assert_eq!(recent, [sample(now - chrono::Duration::minutes(10), 10)]);
+        assert!(store.history("missing", None).unwrap().is_empty());
+
+        // Samples past the retention go, and containers left with none
+        store.prune(now).unwrap();
+        let kept: Vec<u64> = store
+            .history("web", None)
+            .unwrap()
+            .iter()
+            .map(|sample| sample.cpu_usage_usec)
+            .collect();
+        assert_eq!(kept, [30, 10]);
+        assert!(!dir.path().join("old.jsonl").exists());
+
+        store.remove("web").unwrap();
+        assert!(store.history("web", None).unwrap().is_empty());
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! This module provides storage functionality for containers and images.
 
+pub mod metrics;
 pub mod volume;
 
-pub use volume::{Volume, VolumeManager};
+pub use metrics::{MetricsSample, MetricsStore};
+pub use volume::{Volume, VolumeDriver, VolumeManager, VOLUME_FILTERS};
@@ -1,6 +1,8 @@
 //! Volume management
 
 use crate::error::{Result, RuneError};
+use crate::filter::Filters;
+use crate::plugin::{PluginManager, VolumePlugin};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -8,6 +10,9 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
+/// Filters volumes can be listed by
+pub const VOLUME_FILTERS: &[&str] = &["dangling", "driver", "label", "name"];
+
 /// Volume driver types
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -31,6 +36,16 @@ impl std::fmt::Display for VolumeDriver {
     }
 }
 
+impl From<&str> for VolumeDriver {
+    fn from(name: &str) -> Self {
+        match name {
+            "local" => VolumeDriver::Local,
+            "nfs" => VolumeDriver::Nfs,
+            name => VolumeDriver::Custom(name.to_string()),
+        }
+    }
+}
+
 /// Volume scope
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -134,6 +149,8 @@ pub struct VolumeManager {
     volumes: Arc<RwLock<HashMap<String, Volume>>>,
     /// Base path for volume storage
     base_path: PathBuf,
+    /// Plugins drivers other than the built-in ones are looked up in
+    plugins: Option<Arc<PluginManager>>,
 }
 
 impl VolumeManager {
@@ -144,9 +161,27 @@ impl VolumeManager {
         Ok(Self {
             volumes: Arc::new(RwLock::new(HashMap::new())),
             base_path,
+            plugins: None,
         })
     }
 
+    /// Look drivers other than the built-in ones up in plugins
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Plugin implementing a driver, or `None` for the built-in ones
+    fn plugin(&self, driver: &VolumeDriver) -> Result<Option<VolumePlugin>> {
+        let VolumeDriver::Custom(name) = driver else {
+            return Ok(None);
+        };
+        match &self.plugins {
+            Some(plugins) => plugins.volume_driver(name).map(Some),
+            None => Err(RuneError::PluginNotFound(name.clone())),
+        }
+    }
+
     /// Create a new volume
     pub fn create(
         &self,
@@ -179,8 +214,15 @@ impl VolumeManager {
         volume.options = options;
         volume.labels = labels;
 
-        // Create the volume directory
-        std::fs::create_dir_all(&volume.mountpoint)?;
+        match self.plugin(&volume.driver)? {
+            // Plugin volumes have a mountpoint once mounted, if not before
+            Some(plugin) => {
+                plugin.create(&volume.name, &volume.options)?;
+                volume.mountpoint = plugin.path(&volume.name)?.unwrap_or_default();
+            }
+            // Create the volume directory
+            None => std::fs::create_dir_all(&volume.mountpoint)?,
+        }
 
         volumes.insert(volume_name.clone(), volume.clone());
 
@@ -210,6 +252,28 @@ impl VolumeManager {
         Ok(volumes.values().cloned().collect())
     }
 
+    /// List volumes matching filters; dangling volumes are used by no
+    /// container
+    pub fn list_filtered(&self, filters: &Filters) -> Result<Vec<Volume>> {
+        filters.validate(VOLUME_FILTERS)?;
+        let dangling = filters.bool("dangling")?;
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|volume| {
+                let unused = volume
+                    .usage_data
+                    .as_ref()
+                    .map(|u| u.ref_count == 0)
+                    .unwrap_or(true);
+                dangling.is_none_or(|dangling| unused == dangling)
+                    && filters.matches_exact("driver", &volume.driver.to_string())
+                    && filters.matches_labels(&volume.labels)
+                    && filters.matches("name", |name| volume.name.contains(name))
+            })
+            .collect())
+    }
+
     /// Remove a volume
     pub fn remove(&self, name: &str, force: bool) -> Result<()> {
         let mut volumes = self
@@ -231,9 +295,11 @@ impl VolumeManager {
             }
         }
 
-        // Remove the directory
-        if volume.mountpoint.exists() {
-            std::fs::remove_dir_all(&volume.mountpoint)?;
+        match self.plugin(&volume.driver)? {
+            Some(plugin) => plugin.remove(name)?,
+            // Remove the directory
+            None if volume.mountpoint.exists() => std::fs::remove_dir_all(&volume.mountpoint)?,
+            None => {}
         }
 
         volumes.remove(name);
@@ -270,6 +336,24 @@ impl VolumeManager {
         Ok(to_remove)
     }
 
+    /// Mount a volume for a container, returning its path on the host
+    pub fn mount(&self, name: &str, container_id: &str) -> Result<PathBuf> {
+        let volume = self.get(name)?;
+        match self.plugin(&volume.driver)? {
+            Some(plugin) => plugin.mount(name, container_id),
+            None => Ok(volume.mountpoint),
+        }
+    }
+
+    /// Release a container's mount of a volume
+    pub fn unmount(&self, name: &str, container_id: &str) -> Result<()> {
+        let volume = self.get(name)?;
+        match self.plugin(&volume.driver)? {
+            Some(plugin) => plugin.unmount(name, container_id),
+            None => Ok(()),
+        }
+    }
+
     /// Increment reference count for a volume
     pub fn add_reference(&self, name: &str) -> Result<()> {
         let mut volumes = self
@@ -343,6 +427,66 @@ mod tests {
         assert!(manager.get("test-volume").is_err());
     }
 
+    #[test]
+    fn test_plugin_volume() {
+        use crate::plugin::client::tests::fake_plugin;
+
+        let temp = tempdir().unwrap();
+        let (socket, calls) = fake_plugin(
+            &temp,
+            &[
+                ("Plugin.Activate", r#"{"Implements": ["VolumeDriver"]}"#),
+                ("VolumeDriver.Mount", r#"{"Mountpoint": "/mnt/sshfs/data"}"#),
+            ],
+        );
+        let plugins = Arc::new(PluginManager::open(temp.path().join("plugins")).unwrap());
+        plugins.install("sshfs", &socket, true).unwrap();
+        let manager = VolumeManager::new(temp.path().join("volumes"))
+            .unwrap()
+            .with_plugins(plugins);
+
+        let options = HashMap::from([("host".to_string(), "example.com".to_string())]);
+        let volume = manager
+            .create(
+                "data",
+                Some(VolumeDriver::Custom("sshfs".to_string())),
+                options,
+                HashMap::new(),
+            )
+            .unwrap();
+        assert_eq!(volume.mountpoint, PathBuf::new());
+        assert_eq!(
+            manager.mount("data", "abc").unwrap(),
+            PathBuf::from("/mnt/sshfs/data")
+        );
+        manager.unmount("data", "abc").unwrap();
+        manager.remove("data", false).unwrap();
+        assert!(manager
+            .create(
+                "other",
+                Some(VolumeDriver::Custom("nfs3".to_string())),
+                HashMap::new(),
+                HashMap::new()
+            )
+            .is_err());
+
+        let calls = calls.lock().unwrap();
+        let methods: Vec<&str> = calls.iter().map(|(method, _)| method.as_str()).collect();
+        assert_eq!(
+            methods,
+            [
+                "Plugin.Activate",
+                "VolumeDriver.Create",
+                "VolumeDriver.Path",
+                "VolumeDriver.Mount",
+                "VolumeDriver.Unmount",
+                "VolumeDriver.Remove"
+            ]
+        );
+        assert_eq!(calls[1].1["Opts"]["host"], "example.com");
+        assert_eq!(calls[3].1["ID"], "abc");
+    }
+
     #[test]
     fn test_volume_reference_counting() {
         let temp = tempdir().unwrap();
@@ -358,4 +502,40 @@ mod tests {
         let volume = manager.get("test-volume").unwrap();
         assert_eq!(volume.usage_data.unwrap().ref_count, 2);
     }
+
+    #[test]
+    fn test_list_filtered() {
+        let temp = tempdir().unwrap();
+        let manager = VolumeManager::new(temp.path().to_path_buf()).unwrap();
+        let labels = HashMap::from([("backup".to_string(), "daily".to_string())]);
+        manager
+            .create("data", None, HashMap::new(), labels)
+            .unwrap();
+        manager
+            .create("scratch", None, HashMap::new(), HashMap::new())
+            .unwrap();
+        manager.add_reference("data").unwrap();
+
+        let names = |filters: Filters| -> Vec<String> {
+            let mut names: Vec<String> = manager
+                .list_filtered(&filters)
+                .unwrap()
+                .into_iter()
+                .map(|v| v.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(Filters::new()), ["data", "scratch"]);
+        assert_eq!(names(Filters::new().with("dangling", "true")), ["scratch"]);
+        assert_eq!(names(Filters::new().with("label", "backup")), ["data"]);
+        assert_eq!(
+            names(Filters::new().with("driver", "nfs")),
+            Vec::<String>::new()
+        );
+        assert_eq!(names(Filters::new().with("name", "scr")), ["scratch"]);
+        assert!(manager
+            .list_filtered(&Filters::new().with("scope", "local"))
+            .is_err());
+    }
 }
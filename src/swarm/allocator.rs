@@ -0,0 +1,392 @@
+//! Swarm network allocator
+//!
+//! Assigns subnets to swarm-scoped networks, published ports and virtual IPs
+//! to service endpoints, and addresses to tasks on the networks they attach
+//! to. The allocator is rebuilt from the current cluster state whenever it
+//! is needed, so there is no separate bookkeeping to keep in sync.
+
+use super::ingress::INGRESS_NETWORK;
+use super::service::{Endpoint, PortConfig, Service, VirtualIP};
+use super::task::{NetworkAttachment, NetworkRef, Task};
+use crate::error::{Result, RuneError};
+use crate::network::config::{IpAllocator, IpamPoolConfig, NetworkConfig};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+
+/// Ports handed out to services that publish without choosing one
+pub const DYNAMIC_PORT_RANGE: RangeInclusive<u16> = 30000..=32767;
+
+/// Endpoint mode with a virtual IP in front of the tasks
+pub const ENDPOINT_MODE_VIP: &str = "vip";
+/// Endpoint mode with DNS round robin over task addresses
+pub const ENDPOINT_MODE_DNSRR: &str = "dnsrr";
+
+/// Publish mode that exposes a port on every node through the routing mesh
+pub const PUBLISH_MODE_INGRESS: &str = "ingress";
+/// Publish mode that exposes a port only on nodes running a task
+pub const PUBLISH_MODE_HOST: &str = "host";
+
+/// Parse an IPv4 CIDR into its network address and prefix length
+fn parse_cidr(cidr: &str) -> Result<(u32, u8)> {
+    let invalid = || RuneError::Network(format!("Invalid subnet: {}", cidr));
+    let (addr, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+    let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    if prefix > 32 {
+        return Err(invalid());
+    }
+
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix)
+    };
+    Ok((u32::from(addr) & mask, prefix))
+}
+
+/// Check whether two CIDRs overlap
+fn overlaps(a: &str, b: &str) -> bool {
+    match (parse_cidr(a), parse_cidr(b)) {
+        (Ok((a, a_prefix)), Ok((b, b_prefix))) => {
+            let prefix = a_prefix.min(b_prefix);
+            let mask = if prefix == 0 {
+                0
+            } else {
+                u32::MAX << (32 - prefix)
+            };
+            a & mask == b & mask
+        }
+        _ => false,
+    }
+}
+
+/// Pick the first subnet of `size` bits from the pools not used by a network
+pub fn next_subnet(pools: &[String], size: u8, networks: &[NetworkConfig]) -> Result<String> {
+    let used: Vec<&str> = networks
+        .iter()
+        .flat_map(|n| n.ipam.config.iter().map(|p| p.subnet.as_str()))
+        .collect();
+
+    for pool in pools {
+        let (base, prefix) = parse_cidr(pool)?;
+        if size < prefix || size > 30 {
+            return Err(RuneError::InvalidConfig(format!(
+                "Subnet size /{} does not fit address pool {}",
+                size, pool
+            )));
+        }
+
+        let step = 1u64 << (32 - size);
+        let count = 1u64 << (size - prefix);
+        for i in 0..count {
+            let subnet = format!("{}/{}", Ipv4Addr::from(base + (i * step) as u32), size);
+            if !used.iter().any(|u| overlaps(u, &subnet)) {
+                return Ok(subnet);
+            }
+        }
+    }
+
+    Err(RuneError::Network(
+        "Address pools are exhausted; no subnet available for the network".to_string(),
+    ))
+}
+
+/// Check whether a network still has the local bridge default pool
+pub fn has_default_pool(network: &NetworkConfig) -> bool {
+    match network.ipam.config.as_slice() {
+        [] => true,
+        [pool] => pool.subnet == IpamPoolConfig::default().subnet,
+        _ => false,
+    }
+}
+
+/// Subnet addresses are allocated from, preferring explicitly configured pools
+pub fn primary_subnet(network: &NetworkConfig) -> Option<&str> {
+    let default = IpamPoolConfig::default().subnet;
+    network
+        .ipam
+        .config
+        .iter()
+        .find(|p| p.subnet != default)
+        .or_else(|| network.ipam.config.first())
+        .map(|p| p.subnet.as_str())
+}
+
+/// Replace a network's pools with a single subnet
+pub fn set_subnet(network: &mut NetworkConfig, subnet: &str) -> Result<()> {
+    let (base, _) = parse_cidr(subnet)?;
+    network.ipam.config = vec![IpamPoolConfig {
+        subnet: subnet.to_string(),
+        gateway: Some(Ipv4Addr::from(base + 1).to_string()),
+        ip_range: None,
+        aux_addresses: HashMap::new(),
+    }];
+    Ok(())
+}
+
+/// Address pool of one network
+struct Pool {
+    network: NetworkConfig,
+    prefix: u8,
+    allocator: IpAllocator,
+}
+
+/// Allocates endpoint ports, virtual IPs and task addresses
+pub struct NetworkAllocator {
+    pools: HashMap<String, Pool>,
+    /// Published ingress ports by (protocol, port), with the owning service
+    ports: HashMap<(String, u16), String>,
+}
+
+impl NetworkAllocator {
+    /// Build an allocator that knows about every existing allocation
+    pub fn new(networks: &[NetworkConfig], services: &[Service], tasks: &[Task]) -> Result<Self> {
+        let mut pools = HashMap::new();
+        for network in networks {
+            let subnet = primary_subnet(network).ok_or_else(|| {
+                RuneError::Network(format!("Network {} has no subnet", network.name))
+            })?;
+            let (_, prefix) = parse_cidr(subnet)?;
+            pools.insert(
+                network.id.clone(),
+                Pool {
+                    network: network.clone(),
+                    prefix,
+                    allocator: IpAllocator::new(subnet)?,
+                },
+            );
+        }
+
+        let mut allocator = Self {
+            pools,
+            ports: HashMap::new(),
+        };
+
+        for service in services {
+            for vip in &service.endpoint.virtual_ips {
+                allocator.reserve(&vip.network_id, &vip.addr);
+            }
+            for port in service.endpoint.ports.iter().filter(|p| is_ingress(p)) {
+                if let Some(published) = port.published_port {
+                    allocator
+                        .ports
+                        .insert((protocol(port), published), service.id.clone());
+                }
+            }
+        }
+        for task in tasks.iter().filter(|t| !t.is_terminal()) {
+            for attachment in &task.network_attachments {
+                for addr in &attachment.addresses {
+                    allocator.reserve(&attachment.network.id, addr);
+                }
+            }
+        }
+
+        Ok(allocator)
+    }
+
+    fn reserve(&mut self, network_id: &str, addr: &str) {
+        if let (Some(pool), Some(Ok(ip))) = (
+            self.pools.get_mut(network_id),
+            addr.split('/').next().map(str::parse::<Ipv4Addr>),
+        ) {
+            pool.allocator.reserve(ip);
+        }
+    }
+
+    fn allocate_addr(&mut self, network_id: &str) -> Result<String> {
+        let pool = self
+            .pools
+            .get_mut(network_id)
+            .ok_or_else(|| RuneError::NetworkNotFound(network_id.to_string()))?;
+        let ip = pool.allocator.allocate()?;
+        Ok(format!("{}/{}", ip, pool.prefix))
+    }
+
+    /// Find a network by ID or name
+    pub fn network(&self, id_or_name: &str) -> Option<&NetworkConfig> {
+        self.pools
+            .get(id_or_name)
+            .or_else(|| self.pools.values().find(|p| p.network.name == id_or_name))
+            .map(|p| &p.network)
+    }
+
+    /// IDs of the networks a service's tasks attach to
+    ///
+    /// Services publishing ingress ports are also attached to the ingress
+    /// network so the routing mesh can reach their tasks.
+    pub fn service_networks(&self, service: &Service) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+
+        let publishes = service.endpoint.ports.iter().any(is_ingress)
+            || service
+                .spec
+                .endpoint_spec
+                .as_ref()
+                .map(|e| e.ports.iter().any(is_ingress))
+                .unwrap_or(false);
+        if publishes {
+            let ingress = self.network(INGRESS_NETWORK).ok_or_else(|| {
+                RuneError::NetworkNotFound(format!(
+                    "{} (required to publish ports through the routing mesh)",
+                    INGRESS_NETWORK
+                ))
+            })?;
+            ids.push(ingress.id.clone());
+        }
+
+        for attachment in service
+            .spec
+            .networks
+            .iter()
+            .chain(&service.spec.task_template.networks)
+        {
+            let network = self
+                .network(&attachment.target)
+                .ok_or_else(|| RuneError::NetworkNotFound(attachment.target.clone()))?;
+            if !ids.contains(&network.id) {
+                ids.push(network.id.clone());
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Assign published ports and virtual IPs to a service endpoint
+    ///
+    /// Allocations the service already holds are kept, so updating a
+    /// service does not move its VIPs or dynamically published ports.
+    pub fn allocate_service(&mut self, service: &mut Service) -> Result<()> {
+        let spec = service.spec.endpoint_spec.clone().unwrap_or_default();
+        let mode = spec.mode.as_deref().unwrap_or(ENDPOINT_MODE_VIP);
+        if mode != ENDPOINT_MODE_VIP && mode != ENDPOINT_MODE_DNSRR {
+            return Err(RuneError::Service(format!(
+                "Invalid endpoint mode '{}': expected vip or dnsrr",
+                mode
+            )));
+        }
+
+        // Forget the service's own ports so they can be re-claimed
+        self.ports.retain(|_, owner| owner != &service.id);
+
+        let mut ports = Vec::new();
+        for requested in &spec.ports {
+            let mut port = requested.clone();
+            port.protocol = Some(protocol(requested));
+            port.publish_mode = Some(
+                requested
+                    .publish_mode
+                    .clone()
+                    .unwrap_or_else(|| PUBLISH_MODE_INGRESS.to_string()),
+            );
+
+            if is_ingress(&port) {
+                if mode == ENDPOINT_MODE_DNSRR {
+                    return Err(RuneError::Service(format!(
+                        "Port {} is published in ingress mode, which cannot be used with dnsrr mode",
+                        port.target_port
+                    )));
+                }
+
+                let published = match port.published_port {
+                    Some(published) => published,
+                    None => self.reuse_or_pick_port(service, &port)?,
+                };
+                let key = (protocol(&port), published);
+                if let Some(owner) = self.ports.get(&key) {
+                    if owner != &service.id {
+                        return Err(RuneError::Service(format!(
+                            "Port {}/{} is already in use by service {}",
+                            published, key.0, owner
+                        )));
+                    }
+                }
+                self.ports.insert(key, service.id.clone());
+                port.published_port = Some(published);
+            }
+
+            ports.push(port);
+        }
+
+        let mut virtual_ips = Vec::new();
+        if mode == ENDPOINT_MODE_VIP {
+            let mut endpoint = service.endpoint.clone();
+            endpoint.ports = ports.clone();
+            let probe = Service {
+                endpoint,
+                ..service.clone()
+            };
+            for network_id in self.service_networks(&probe)? {
+                let existing = service
+                    .endpoint
+                    .virtual_ips
+                    .iter()
+                    .find(|v| v.network_id == network_id);
+                let addr = match existing {
+                    Some(vip) => vip.addr.clone(),
+                    None => self.allocate_addr(&network_id)?,
+                };
+                virtual_ips.push(VirtualIP { network_id, addr });
+            }
+        }
+
+        service.endpoint = Endpoint {
+            spec: Some(spec),
+            ports,
+            virtual_ips,
+        };
+        Ok(())
+    }
+
+    /// Keep a dynamically published port across updates, or pick a free one
+    fn reuse_or_pick_port(&self, service: &Service, port: &PortConfig) -> Result<u16> {
+        let previous = service.endpoint.ports.iter().find(|p| {
+            p.target_port == port.target_port && protocol(p) == protocol(port) && is_ingress(p)
+        });
+        if let Some(published) = previous.and_then(|p| p.published_port) {
+            return Ok(published);
+        }
+
+        DYNAMIC_PORT_RANGE
+            .clone()
+            .find(|p| !self.ports.contains_key(&(protocol(port), *p)))
+            .ok_or_else(|| {
+                RuneError::Service("No free port left to publish the service on".to_string())
+            })
+    }
+
+    /// Give a task an address on every network of its service
+    pub fn allocate_task(&mut self, service: &Service, task: &mut Task) -> Result<()> {
+        if !task.network_attachments.is_empty() {
+            return Ok(());
+        }
+
+        for network_id in self.service_networks(service)? {
+            let addr = self.allocate_addr(&network_id)?;
+            let name = self
+                .pools
+                .get(&network_id)
+                .map(|p| p.network.name.clone())
+                .unwrap_or_default();
+            task.network_attachments.push(NetworkAttachment {
+                network: NetworkRef {
+                    id: network_id,
+                    name,
+                },
+                addresses: vec![addr],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Port protocol, defaulting to TCP
+pub fn protocol(port: &PortConfig) -> String {
+    port.protocol.as_deref().unwrap_or("tcp").to_lowercase()
+}
+
+/// Check whether a port is published through the routing mesh
+pub fn is_ingress(port: &PortConfig) -> bool {
+    port.publish_mode.as_deref().unwrap_or(PUBLISH_MODE_INGRESS) == PUBLISH_MODE_INGRESS
+}
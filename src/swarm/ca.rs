@@ -0,0 +1,371 @@
+//! Swarm certificate authority and join tokens
+//!
+//! Every swarm has a root CA whose key is held by the managers. A joining
+//! node generates its own key pair and sends a certificate signing request
+//! together with a join token; the CA answers with a certificate whose
+//! subject records the node ID, its role and the cluster. Join tokens embed
+//! the root CA digest so the joining node can pin the CA before trusting
+//! anything a manager sends back.
+
+use super::node::NodeRole;
+use crate::error::{Result, RuneError};
+use chrono::Utc;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateSigningRequestParams, DistinguishedName,
+    DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Common name of the swarm root CA
+pub const CA_COMMON_NAME: &str = "swarm-ca";
+/// Organizational unit (and DNS name) of manager certificates
+pub const MANAGER_OU: &str = "swarm-manager";
+/// Organizational unit (and DNS name) of worker certificates
+pub const WORKER_OU: &str = "swarm-worker";
+
+/// Root CA validity (20 years)
+const ROOT_CA_EXPIRY_SECS: i64 = 20 * 365 * 24 * 60 * 60;
+
+/// Swarm root CA; managers also hold its key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RootCa {
+    cert_pem: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_pem: Option<String>,
+}
+
+impl RootCa {
+    /// Generate a new self-signed root CA
+    pub fn generate() -> Result<Self> {
+        let key = KeyPair::generate().map_err(ca_error)?;
+
+        let mut params = CertificateParams::default();
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, CA_COMMON_NAME);
+        params.distinguished_name = name;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        params.not_before = offset_time(0)?;
+        params.not_after = offset_time(ROOT_CA_EXPIRY_SECS)?;
+
+        let cert = params.self_signed(&key).map_err(ca_error)?;
+        Ok(Self {
+            cert_pem: cert.pem(),
+            key_pem: Some(key.serialize_pem()),
+        })
+    }
+
+    /// Load a root CA from PEM, with or without its key
+    pub fn from_pem(cert_pem: &str, key_pem: Option<&str>) -> Result<Self> {
+        let ca = Self {
+            cert_pem: cert_pem.to_string(),
+            key_pem: key_pem.map(str::to_string),
+        };
+        ca.cert_der()?;
+        if let Some(key) = key_pem {
+            KeyPair::from_pem(key).map_err(ca_error)?;
+        }
+        Ok(ca)
+    }
+
+    /// Root certificate in PEM form
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// Root key in PEM form, if this node can issue certificates
+    pub fn key_pem(&self) -> Option<&str> {
+        self.key_pem.as_deref()
+    }
+
+    /// Root certificate in DER form
+    pub fn cert_der(&self) -> Result<CertificateDer<'static>> {
+        CertificateDer::from_pem_slice(self.cert_pem.as_bytes())
+            .map_err(|e| RuneError::Swarm(format!("Invalid root CA certificate: {}", e)))
+    }
+
+    /// SHA-256 digest of the root certificate, as embedded in join tokens
+    pub fn digest(&self) -> Result<String> {
+        Ok(format!("{:x}", Sha256::digest(self.cert_der()?.as_ref())))
+    }
+
+    /// Sign a node's certificate signing request
+    ///
+    /// The subject requested by the node is ignored: the CA decides the
+    /// node ID, role and cluster recorded in the certificate.
+    pub fn issue(&self, csr_pem: &str, identity: &NodeIdentity, expiry_ns: i64) -> Result<String> {
+        let key_pem = self
+            .key_pem
+            .as_deref()
+            .ok_or_else(|| RuneError::Swarm("This node cannot issue certificates".to_string()))?;
+        let key = KeyPair::from_pem(key_pem).map_err(ca_error)?;
+        let issuer = CertificateParams::from_ca_cert_pem(&self.cert_pem)
+            .and_then(|params| params.self_signed(&key))
+            .map_err(ca_error)?;
+
+        let mut csr = CertificateSigningRequestParams::from_pem(csr_pem)
+            .map_err(|e| RuneError::Swarm(format!("Invalid certificate signing request: {}", e)))?;
+
+        let role_ou = identity.role_ou();
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, identity.node_id.as_str());
+        name.push(DnType::OrganizationalUnitName, role_ou);
+        name.push(DnType::OrganizationName, identity.cluster_id.as_str());
+
+        let params = &mut csr.params;
+        params.distinguished_name = name;
+        params.subject_alt_names = vec![
+            SanType::DnsName(identity.node_id.clone().try_into().map_err(ca_error)?),
+            SanType::DnsName(role_ou.try_into().map_err(ca_error)?),
+        ];
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = offset_time(0)?;
+        params.not_after = offset_time(expiry_ns / 1_000_000_000)?;
+
+        let cert = csr.signed_by(&issuer, &key).map_err(ca_error)?;
+        Ok(cert.pem())
+    }
+}
+
+/// Node identity recorded in a swarm certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeIdentity {
+    /// Node ID (certificate common name)
+    pub node_id: String,
+    /// Node role (certificate organizational unit)
+    pub role: NodeRole,
+    /// Cluster ID (certificate organization)
+    pub cluster_id: String,
+}
+
+impl NodeIdentity {
+    /// Read the identity from a DER-encoded certificate
+    pub fn from_cert_der(der: &[u8]) -> Result<Self> {
+        let invalid =
+            || RuneError::Swarm("Certificate is not a swarm node certificate".to_string());
+
+        let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(|_| invalid())?;
+        let subject = cert.subject();
+
+        let node_id = field(subject.iter_common_name()).ok_or_else(invalid)?;
+        let role = match field(subject.iter_organizational_unit()).as_deref() {
+            Some(MANAGER_OU) => NodeRole::Manager,
+            Some(WORKER_OU) => NodeRole::Worker,
+            _ => return Err(invalid()),
+        };
+        let cluster_id = field(subject.iter_organization()).ok_or_else(invalid)?;
+
+        Ok(Self {
+            node_id,
+            role,
+            cluster_id,
+        })
+    }
+
+    /// Read the identity from a PEM-encoded certificate
+    pub fn from_cert_pem(pem: &str) -> Result<Self> {
+        let der = CertificateDer::from_pem_slice(pem.as_bytes())
+            .map_err(|e| RuneError::Swarm(format!("Invalid node certificate: {}", e)))?;
+        Self::from_cert_der(der.as_ref())
+    }
+
+    /// Organizational unit for the node's role
+    fn role_ou(&self) -> &'static str {
+        match self.role {
+            NodeRole::Manager => MANAGER_OU,
+            NodeRole::Worker => WORKER_OU,
+        }
+    }
+}
+
+/// TLS credentials of a swarm node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeCredentials {
+    /// Node certificate (PEM)
+    pub cert_pem: String,
+    /// Node private key (PEM)
+    pub key_pem: String,
+    /// Root CA certificate (PEM)
+    pub root_ca_pem: String,
+}
+
+impl NodeCredentials {
+    /// Identity recorded in the node certificate
+    pub fn identity(&self) -> Result<NodeIdentity> {
+        NodeIdentity::from_cert_pem(&self.cert_pem)
+    }
+}
+
+/// Generate a node key pair and a signing request for it
+///
+/// Returns the private key and the request, both PEM encoded.
+pub fn generate_csr() -> Result<(String, String)> {
+    let key = KeyPair::generate().map_err(ca_error)?;
+    let csr = CertificateParams::default()
+        .serialize_request(&key)
+        .and_then(|csr| csr.pem())
+        .map_err(ca_error)?;
+    Ok((key.serialize_pem(), csr))
+}
+
+/// Parsed swarm join token
+///
+/// Tokens have the form `SWMTKN-1-<cluster>-<role>-<secret>-<ca digest>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinToken {
+    /// First characters of the cluster ID
+    pub cluster_prefix: String,
+    /// Role granted by the token
+    pub role: NodeRole,
+    /// Random secret
+    pub secret: String,
+    /// SHA-256 digest of the root CA certificate
+    pub ca_digest: String,
+}
+
+impl JoinToken {
+    /// Generate a fresh token for a role
+    pub fn generate(role: NodeRole, cluster_id: &str, ca_digest: &str) -> String {
+        let role = match role {
+            NodeRole::Worker => "worker",
+            NodeRole::Manager => "manager",
+        };
+        let random = Uuid::new_v4().to_string().replace("-", "");
+        format!(
+            "SWMTKN-1-{}-{}-{}-{}",
+            &cluster_id[..8.min(cluster_id.len())],
+            role,
+            &random[..25],
+            ca_digest
+        )
+    }
+
+    /// Parse a token
+    pub fn parse(token: &str) -> Result<Self> {
+        let invalid = || RuneError::Swarm("Invalid join token".to_string());
+
+        let rest = token.trim().strip_prefix("SWMTKN-1-").ok_or_else(invalid)?;
+        let parts: Vec<&str> = rest.split('-').collect();
+        let [cluster_prefix, role, secret, ca_digest] = parts.as_slice() else {
+            return Err(invalid());
+        };
+
+        let role = match *role {
+            "worker" => NodeRole::Worker,
+            "manager" => NodeRole::Manager,
+            _ => return Err(invalid()),
+        };
+        if cluster_prefix.is_empty()
+            || secret.is_empty()
+            || ca_digest.len() != 64
+            || !ca_digest.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            cluster_prefix: cluster_prefix.to_string(),
+            role,
+            secret: secret.to_string(),
+            ca_digest: ca_digest.to_lowercase(),
+        })
+    }
+
+    /// Check that a root CA is the one the token was issued for
+    pub fn verify_root_ca(&self, ca: &RootCa) -> Result<()> {
+        if ca.digest()? == self.ca_digest {
+            Ok(())
+        } else {
+            Err(RuneError::Swarm(
+                "Root CA does not match the join token; refusing to join".to_string(),
+            ))
+        }
+    }
+}
+
+/// First value of a certificate subject attribute
+fn field<'a>(
+    mut iter: impl Iterator<Item = &'a x509_parser::x509::AttributeTypeAndValue<'a>>,
+) -> Option<String> {
+    iter.next()
+        .and_then(|attr| attr.as_str().ok())
+        .map(str::to_string)
+}
+
+/// Compare two tokens without leaking where they differ
+pub(crate) fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Convert a number of seconds from now to a certificate timestamp
+fn offset_time(secs_from_now: i64) -> Result<time::OffsetDateTime> {
+    // Backdate slightly to tolerate clock skew between nodes
+    let timestamp = Utc::now().timestamp() + secs_from_now - 60;
+    time::OffsetDateTime::from_unix_timestamp(timestamp)
+        .map_err(|e| RuneError::Swarm(format!("Invalid certificate validity: {}", e)))
+}
+
+fn ca_error(e: rcgen::Error) -> RuneError {
+    RuneError::Swarm(format!("Certificate error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_node_certificate() {
+        let ca = RootCa::generate().unwrap();
+        let (_key, csr) = generate_csr().unwrap();
+        let identity = NodeIdentity {
+            node_id: "node1".to_string(),
+            role: NodeRole::Manager,
+            cluster_id: "cluster1".to_string(),
+        };
+
+        let cert = ca.issue(&csr, &identity, 3600 * 1_000_000_000).unwrap();
+        assert_eq!(NodeIdentity::from_cert_pem(&cert).unwrap(), identity);
+
+        let without_key = RootCa::from_pem(ca.cert_pem(), None).unwrap();
+        assert!(without_key.issue(&csr, &identity, 1).is_err());
+    }
+
+    #[test]
+    fn test_join_token_roundtrip() {
+        let ca = RootCa::generate().unwrap();
+        let digest = ca.digest().unwrap();
+        let token = JoinToken::generate(NodeRole::Manager, "abcdef123456", &digest);
+
+        let parsed = JoinToken::parse(&token).unwrap();
+        assert_eq!(parsed.cluster_prefix, "abcdef12");
+        assert_eq!(parsed.role, NodeRole::Manager);
+        parsed.verify_root_ca(&ca).unwrap();
+
+        let other = RootCa::generate().unwrap();
+        assert!(parsed.verify_root_ca(&other).is_err());
+
+        assert!(JoinToken::parse("SWMTKN-1-abc-worker-secret").is_err());
+        assert!(JoinToken::parse("not-a-token").is_err());
+    }
+}
@@ -1,12 +1,33 @@
 //! Swarm cluster management
 
+use super::allocator::{self, NetworkAllocator};
+use super::ca::{self, JoinToken, NodeCredentials, NodeIdentity, RootCa};
+use super::encryption::{self, DataKey, KeyStore};
+use super::ingress;
+use super::logs::{self, LogCursor, LogLine, LogRequest, LogSource, ServiceLogLine};
 use super::node::{Node, NodeRole, NodeState};
-use super::service::Service;
+use super::orchestrator::Orchestrator;
+use super::raft::{ClusterStore, FileStorage, RaftNode, StoreAction};
+use super::rpc::{self, JoinRequest, JoinResponse, RpcHandler, RpcRequest, RpcResponse};
+use super::scheduler::Scheduler;
+use super::service::{Service, ServiceMode};
+use super::stack::{
+    parse_duration, StackDeployReport, StackDeployment, StackSummary, STACK_NAMESPACE_LABEL,
+};
+use super::task::{Task, TaskState};
 use crate::error::{Result, RuneError};
+use crate::network::config::NetworkConfig;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
 use uuid::Uuid;
 
 /// Swarm cluster configuration
@@ -40,6 +61,9 @@ pub struct SwarmConfig {
     pub encryption_config: EncryptionConfig,
     /// Task history retention limit
     pub task_history_retention_limit: i64,
+    /// Directory a manager persists its state in (in memory when unset)
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
 }
 
 impl Default for SwarmConfig {
@@ -59,6 +83,7 @@ impl Default for SwarmConfig {
             ca_config: CaConfig::default(),
             encryption_config: EncryptionConfig::default(),
             task_history_retention_limit: 5,
+            state_dir: None,
         }
     }
 }
@@ -145,6 +170,30 @@ pub struct ExternalCa {
     pub ca_cert: String,
 }
 
+/// Default directory managers persist swarm state in
+pub const DEFAULT_STATE_DIR: &str = "/var/lib/rune/swarm";
+
+/// How often followed logs are polled for new lines
+const LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a change may take to be committed by a quorum of managers
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a change waiting to be committed is checked on
+const COMMIT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Length of a Raft tick, the unit election and heartbeat timeouts count in
+const RAFT_TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a manager checks for Raft messages to send
+const RAFT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a Raft message may take to reach another manager
+const RAFT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// File the encrypted cluster identity is kept in
+const CLUSTER_FILE: &str = "cluster.json";
+
 /// Encryption configuration
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct EncryptionConfig {
@@ -184,16 +233,30 @@ pub struct SwarmCluster {
     config: SwarmConfig,
     /// Cluster state
     state: SwarmState,
-    /// Nodes in the cluster
-    nodes: Arc<RwLock<HashMap<String, Node>>>,
-    /// Services
-    services: Arc<RwLock<HashMap<String, Service>>>,
+    /// Nodes, services, tasks and networks: on managers the state Raft
+    /// has applied, on workers what they were told
+    store: RwLock<ClusterStore>,
+    /// Raft index `store` was last brought up to date with
+    synced: AtomicU64,
+    /// Held while a change is worked out from the current state, so
+    /// concurrent changes don't work from the same state
+    changing: Mutex<()>,
+    /// Raft consensus state (managers only)
+    raft: Option<Arc<Mutex<RaftNode>>>,
+    /// Root CA (with its key on managers)
+    root_ca: RootCa,
+    /// This node's TLS credentials
+    credentials: NodeCredentials,
     /// Worker join token
     worker_token: String,
     /// Manager join token
     manager_token: String,
     /// Unlock key
     unlock_key: Option<String>,
+    /// Key encrypting the state on disk (persisting managers only)
+    data_key: Option<DataKey>,
+    /// Logs of the containers running on this node
+    log_source: Option<Arc<dyn LogSource>>,
     /// Created timestamp
     created_at: DateTime<Utc>,
     /// Updated timestamp
@@ -206,58 +269,242 @@ impl SwarmCluster {
     /// Initialize a new swarm cluster
     pub fn init(config: SwarmConfig) -> Result<Self> {
         let id = Uuid::new_v4().to_string();
-        let worker_token = generate_token(TokenType::Worker, &id);
-        let manager_token = generate_token(TokenType::Manager, &id);
+        let root_ca = RootCa::generate()?;
+        let ca_digest = root_ca.digest()?;
+        let worker_token = generate_token(TokenType::Worker, &id, &ca_digest);
+        let manager_token = generate_token(TokenType::Manager, &id, &ca_digest);
 
         let unlock_key = if config.encryption_config.auto_lock_managers {
-            Some(generate_unlock_key())
+            Some(encryption::generate_unlock_key())
         } else {
             None
         };
 
         let now = Utc::now();
 
+        // The first manager issues its own certificate, bootstraps a
+        // single-member Raft group and becomes its leader straight away
+        let mut local_node = Node::new_local(NodeRole::Manager);
+        if !config.advertise_addr.is_empty() {
+            // Managers joining later reach this one there
+            local_node.addr = config.advertise_addr.clone();
+            local_node.status.addr = config.advertise_addr.clone();
+            if let Some(status) = local_node.manager_status.as_mut() {
+                status.addr = config.advertise_addr.clone();
+            }
+        }
+        let (key_pem, csr_pem) = ca::generate_csr()?;
+        let identity = NodeIdentity {
+            node_id: local_node.id.clone(),
+            role: NodeRole::Manager,
+            cluster_id: id.clone(),
+        };
+        let expiry = parse_duration(&config.ca_config.node_cert_expiry)?;
+        let credentials = NodeCredentials {
+            cert_pem: root_ca.issue(&csr_pem, &identity, expiry)?,
+            key_pem,
+            root_ca_pem: root_ca.cert_pem().to_string(),
+        };
+
+        // Persisting managers encrypt their state; with autolock the data
+        // key is sealed with the unlock key
+        let (mut raft, data_key) = match &config.state_dir {
+            Some(dir) => {
+                let data_key = DataKey::generate();
+                KeyStore::open(dir)?.save(&data_key, unlock_key.as_deref())?;
+                let storage = FileStorage::open_encrypted(dir.join("raft"), data_key.clone())?;
+                let raft = RaftNode::with_storage(
+                    &local_node.id,
+                    Vec::new(),
+                    config.raft.clone(),
+                    Box::new(storage),
+                )?;
+                (raft, Some(data_key))
+            }
+            None => (
+                RaftNode::new(&local_node.id, Vec::new(), config.raft.clone()),
+                None,
+            ),
+        };
+        raft.campaign()?;
+
         let cluster = Self {
             id: id.clone(),
             config,
             state: SwarmState::Active,
-            nodes: Arc::new(RwLock::new(HashMap::new())),
-            services: Arc::new(RwLock::new(HashMap::new())),
+            store: RwLock::new(ClusterStore::default()),
+            synced: AtomicU64::new(0),
+            changing: Mutex::new(()),
+            raft: Some(Arc::new(Mutex::new(raft))),
+            root_ca,
+            credentials,
             worker_token,
             manager_token,
             unlock_key,
+            data_key,
+            log_source: None,
             created_at: now,
             updated_at: now,
             root_rotation_in_progress: false,
         };
 
-        // Create the local node as first manager
-        let local_node = Node::new_local(NodeRole::Manager);
+        // Register the local node as first manager
         cluster.add_node(local_node)?;
+        cluster.create_network(ingress::ingress_network())?;
+        cluster.save()?;
 
         Ok(cluster)
     }
 
+    /// Restore a manager from its state directory
+    ///
+    /// A manager with autolock enabled needs the unlock key to decrypt its
+    /// state; without it, or with a wrong key, the swarm stays locked.
+    pub fn restore(state_dir: &Path, unlock_key: Option<&str>) -> Result<Self> {
+        let data_key = KeyStore::open(state_dir)?.load(unlock_key)?;
+        let persisted = PersistedCluster::read(state_dir, &data_key)?;
+
+        let identity = persisted.credentials.identity()?;
+        let (raft, store, synced) = if identity.role == NodeRole::Manager {
+            let storage = FileStorage::open_encrypted(state_dir.join("raft"), data_key.clone())?;
+            let mut raft = RaftNode::with_storage(
+                &identity.node_id,
+                persisted.peers,
+                persisted.config.raft.clone(),
+                Box::new(storage),
+            )?;
+            add_manager_peers(&mut raft);
+            if raft.peers().is_empty() {
+                // A single manager is its own quorum; others elect a leader
+                // once they are served
+                raft.campaign()?;
+            }
+            let store = raft.store().clone();
+            let synced = raft.applied_index();
+            (Some(Arc::new(Mutex::new(raft))), store, synced)
+        } else {
+            (None, ClusterStore::default(), 0)
+        };
+
+        let mut config = persisted.config;
+        config.state_dir = Some(state_dir.to_path_buf());
+
+        Ok(Self {
+            id: persisted.id,
+            config,
+            state: SwarmState::Active,
+            store: RwLock::new(store),
+            synced: AtomicU64::new(synced),
+            changing: Mutex::new(()),
+            raft,
+            root_ca: persisted.root_ca,
+            credentials: persisted.credentials,
+            worker_token: persisted.worker_token,
+            manager_token: persisted.manager_token,
+            unlock_key: persisted.unlock_key,
+            data_key: Some(data_key),
+            log_source: None,
+            created_at: persisted.created_at,
+            updated_at: Utc::now(),
+            root_rotation_in_progress: false,
+        })
+    }
+
+    /// Whether `state_dir` holds the state of a node in a swarm
+    pub fn exists(state_dir: &Path) -> bool {
+        state_dir.join(CLUSTER_FILE).exists()
+    }
+
+    /// The swarm state a manager has committed to `state_dir`, read without
+    /// taking part in the swarm
+    ///
+    /// This is how another process, such as the daemon, follows the state
+    /// the CLI changes. It holds on to the data key rather than the unlock
+    /// key, which stays the same when the unlock key is rotated.
+    pub fn read_state(state_dir: &Path, data_key: &DataKey) -> Result<ClusterStore> {
+        let persisted = PersistedCluster::read(state_dir, data_key)?;
+        let identity = persisted.credentials.identity()?;
+        if identity.role != NodeRole::Manager {
+            return Err(RuneError::Swarm(
+                "This node is not a swarm manager".to_string(),
+            ));
+        }
+        let storage = FileStorage::open_encrypted(state_dir.join("raft"), data_key.clone())?;
+        let raft = RaftNode::with_storage(
+            &identity.node_id,
+            persisted.peers,
+            persisted.config.raft,
+            Box::new(storage),
+        )?;
+        Ok(raft.store().clone())
+    }
+
+    /// Persist the cluster identity, encrypted with the data key
+    fn save(&self) -> Result<()> {
+        let (Some(dir), Some(data_key)) = (&self.config.state_dir, &self.data_key) else {
+            return Ok(());
+        };
+
+        let peers = match &self.raft {
+            Some(raft) => raft
+                .lock()
+                .map_err(|_| RuneError::Lock("Failed to acquire raft lock".to_string()))?
+                .peers()
+                .to_vec(),
+            None => Vec::new(),
+        };
+        let persisted = PersistedCluster {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            root_ca: self.root_ca.clone(),
+            credentials: self.credentials.clone(),
+            worker_token: self.worker_token.clone(),
+            manager_token: self.manager_token.clone(),
+            unlock_key: self.unlock_key.clone(),
+            peers,
+            created_at: self.created_at,
+        };
+
+        encryption::write_private(
+            &dir.join(CLUSTER_FILE),
+            &data_key.seal(&serde_json::to_vec(&persisted)?)?,
+        )
+    }
+
     /// Join an existing swarm
-    pub fn join(
+    ///
+    /// Each remote manager is tried in turn until one accepts the token and
+    /// issues this node a certificate.
+    pub async fn join(
         join_token: &str,
-        _remote_addrs: Vec<String>,
+        remote_addrs: Vec<String>,
         listen_addr: &str,
         advertise_addr: &str,
     ) -> Result<Self> {
-        // Parse token to determine role
-        let role = if join_token.contains("SWMTKN-1-") {
-            if join_token.contains("-manager-") {
-                NodeRole::Manager
-            } else {
-                NodeRole::Worker
+        let local_node = Node::new_local(JoinToken::parse(join_token)?.role);
+
+        let mut last_error = RuneError::Swarm("No remote manager address given".to_string());
+        let mut joined = None;
+        for addr in &remote_addrs {
+            match rpc::request_join(
+                addr,
+                join_token,
+                local_node.description.clone(),
+                advertise_addr,
+            )
+            .await
+            {
+                Ok(result) => {
+                    joined = Some(result);
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to join swarm through {}: {}", addr, e);
+                    last_error = e;
+                }
             }
-        } else {
-            return Err(RuneError::Swarm("Invalid join token".to_string()));
-        };
-
-        // In a real implementation, we would connect to remote managers
-        // and join the cluster via the Raft consensus protocol
+        }
+        let (response, credentials) = joined.ok_or(last_error)?;
 
         let config = SwarmConfig {
             listen_addr: listen_addr.to_string(),
@@ -265,37 +512,175 @@ impl SwarmCluster {
             ..Default::default()
         };
 
+        // Managers join the Raft group as followers; the leader has added
+        // them as a peer and brings their log up to date
+        let local_node = Node::joined(
+            &response.node_id,
+            response.role,
+            local_node.description,
+            advertise_addr,
+        );
+        let raft = if response.role == NodeRole::Manager {
+            let node = RaftNode::new(&local_node.id, Vec::new(), config.raft.clone());
+            Some(Arc::new(Mutex::new(node)))
+        } else {
+            None
+        };
+
         let cluster = Self {
-            id: extract_cluster_id(join_token)?,
+            id: response.cluster_id,
             config,
             state: SwarmState::Active,
-            nodes: Arc::new(RwLock::new(HashMap::new())),
-            services: Arc::new(RwLock::new(HashMap::new())),
+            store: RwLock::new(ClusterStore::default()),
+            synced: AtomicU64::new(0),
+            changing: Mutex::new(()),
+            raft,
+            root_ca: RootCa::from_pem(&response.root_ca_pem, response.root_ca_key_pem.as_deref())?,
+            credentials,
             worker_token: String::new(),
             manager_token: String::new(),
             unlock_key: None,
+            data_key: None,
+            log_source: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
             root_rotation_in_progress: false,
         };
 
-        // Create local node
-        let local_node = Node::new_local(role);
-        cluster.add_node(local_node)?;
+        // Register local node; the rest of the state arrives from the leader
+        cluster
+            .store
+            .write()
+            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?
+            .nodes
+            .insert(local_node.id.clone(), local_node);
 
         Ok(cluster)
     }
 
+    /// Keep a node that joined in `state_dir`, so it can be restored
+    ///
+    /// Its state is encrypted like a manager started with `init`. A manager's
+    /// Raft log is kept there from now on, so this is done before the node
+    /// starts replicating.
+    pub fn persist(&mut self, state_dir: &Path) -> Result<()> {
+        let data_key = DataKey::generate();
+        KeyStore::open(state_dir)?.save(&data_key, None)?;
+        if let Some(raft) = &self.raft {
+            let mut raft = lock_raft(raft)?;
+            let storage = FileStorage::open_encrypted(state_dir.join("raft"), data_key.clone())?;
+            *raft = RaftNode::with_storage(
+                raft.id(),
+                raft.peers().to_vec(),
+                self.config.raft.clone(),
+                Box::new(storage),
+            )?;
+        }
+        self.config.state_dir = Some(state_dir.to_path_buf());
+        self.data_key = Some(data_key);
+        self.save()
+    }
+
+    /// Handle a join request from another node
+    ///
+    /// The token must match one of the current join tokens; rotated tokens
+    /// are rejected. The node is registered with the role the token grants
+    /// and receives a certificate signed by the root CA.
+    pub fn accept_join(&self, request: &JoinRequest) -> Result<JoinResponse> {
+        let (worker_token, manager_token) = self.current_join_tokens()?;
+        let role = if !manager_token.is_empty() && ca::tokens_match(&request.token, &manager_token)
+        {
+            NodeRole::Manager
+        } else if !worker_token.is_empty() && ca::tokens_match(&request.token, &worker_token) {
+            NodeRole::Worker
+        } else {
+            return Err(RuneError::Swarm(
+                "Invalid join token; it may have been rotated".to_string(),
+            ));
+        };
+
+        let identity = NodeIdentity {
+            node_id: Uuid::new_v4().to_string(),
+            role,
+            cluster_id: self.id.clone(),
+        };
+        let expiry = parse_duration(&self.config.ca_config.node_cert_expiry)?;
+        let cert_pem = self.root_ca.issue(&request.csr_pem, &identity, expiry)?;
+
+        let node = Node::joined(
+            &identity.node_id,
+            role,
+            request.description.clone(),
+            &request.advertise_addr,
+        );
+        self.add_node(node)?;
+
+        if role == NodeRole::Manager {
+            if let Some(raft) = &self.raft {
+                raft.lock()
+                    .map_err(|_| RuneError::Lock("Failed to acquire raft lock".to_string()))?
+                    .add_peer(&identity.node_id);
+            }
+            self.save()?;
+        }
+
+        Ok(JoinResponse {
+            cluster_id: self.id.clone(),
+            node_id: identity.node_id,
+            role,
+            cert_pem,
+            root_ca_pem: self.root_ca.cert_pem().to_string(),
+            root_ca_key_pem: match role {
+                NodeRole::Manager => self.root_ca.key_pem().map(str::to_string),
+                NodeRole::Worker => None,
+            },
+        })
+    }
+
+    /// The worker and manager join tokens in effect
+    ///
+    /// The CLI rotates tokens in the state directory while the daemon serves
+    /// joins, so persisted tokens are read back from there.
+    fn current_join_tokens(&self) -> Result<(String, String)> {
+        match (&self.config.state_dir, &self.data_key) {
+            (Some(dir), Some(data_key)) => {
+                let persisted = PersistedCluster::read(dir, data_key)?;
+                Ok((persisted.worker_token, persisted.manager_token))
+            }
+            _ => Ok((self.worker_token.clone(), self.manager_token.clone())),
+        }
+    }
+
+    /// Whether the node a certificate was issued to is still in the swarm
+    ///
+    /// Only managers track membership; workers trust their root CA. A
+    /// manager that just joined knows only itself until the leader's log
+    /// reaches it, and takes other managers at their certificate until then.
+    fn is_member(&self, peer: &NodeIdentity) -> Result<bool> {
+        if self.raft.is_none() {
+            return Ok(true);
+        }
+        let store = self.read_store()?;
+        Ok(store.nodes.contains_key(&peer.node_id)
+            || (store.nodes.len() <= 1 && peer.role == NodeRole::Manager))
+    }
+
+    /// Root CA of the swarm
+    pub fn root_ca(&self) -> &RootCa {
+        &self.root_ca
+    }
+
+    /// TLS credentials of this node
+    pub fn credentials(&self) -> &NodeCredentials {
+        &self.credentials
+    }
+
     /// Leave the swarm
     pub fn leave(&mut self, force: bool) -> Result<()> {
         // Check if this is the last manager
-        let nodes = self
-            .nodes
-            .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
-
-        let manager_count = nodes
-            .values()
+        let manager_count = self
+            .list_nodes()?
+            .iter()
             .filter(|n| n.role == NodeRole::Manager && n.state == NodeState::Ready)
             .count();
 
@@ -330,7 +715,7 @@ impl SwarmCluster {
 
     /// Rotate join token
     pub fn rotate_join_token(&mut self, token_type: TokenType) -> Result<String> {
-        let new_token = generate_token(token_type, &self.id);
+        let new_token = generate_token(token_type, &self.id, &self.root_ca.digest()?);
 
         match token_type {
             TokenType::Worker => self.worker_token = new_token.clone(),
@@ -338,6 +723,7 @@ impl SwarmCluster {
         }
 
         self.updated_at = Utc::now();
+        self.save()?;
         Ok(new_token)
     }
 
@@ -346,14 +732,46 @@ impl SwarmCluster {
         self.unlock_key.as_deref()
     }
 
+    /// Turn autolock on or off, returning the unlock key when enabled
+    ///
+    /// Enabling autolock seals the data key on disk with a new unlock key;
+    /// disabling it stores the data key in the clear again.
+    pub fn set_autolock(&mut self, enabled: bool) -> Result<Option<String>> {
+        if enabled == self.unlock_key.is_some() {
+            return Ok(self.unlock_key.clone());
+        }
+
+        let unlock_key = enabled.then(encryption::generate_unlock_key);
+        self.seal_data_key(unlock_key.as_deref())?;
+        self.unlock_key = unlock_key.clone();
+        self.config.encryption_config.auto_lock_managers = enabled;
+        self.updated_at = Utc::now();
+        self.save()?;
+        Ok(unlock_key)
+    }
+
     /// Rotate unlock key
     pub fn rotate_unlock_key(&mut self) -> Result<String> {
-        let new_key = generate_unlock_key();
+        if self.unlock_key.is_none() {
+            return Err(RuneError::Swarm("Auto-lock is not enabled".to_string()));
+        }
+
+        let new_key = encryption::generate_unlock_key();
+        self.seal_data_key(Some(&new_key))?;
         self.unlock_key = Some(new_key.clone());
         self.updated_at = Utc::now();
+        self.save()?;
         Ok(new_key)
     }
 
+    /// Re-seal the data key on disk for a new unlock key
+    fn seal_data_key(&self, unlock_key: Option<&str>) -> Result<()> {
+        match (&self.config.state_dir, &self.data_key) {
+            (Some(dir), Some(data_key)) => KeyStore::open(dir)?.save(data_key, unlock_key),
+            _ => Ok(()),
+        }
+    }
+
     /// Lock the cluster
     pub fn lock(&mut self) -> Result<()> {
         if self.unlock_key.is_none() {
@@ -366,7 +784,7 @@ impl SwarmCluster {
     /// Unlock the cluster
     pub fn unlock(&mut self, key: &str) -> Result<()> {
         if let Some(ref unlock_key) = self.unlock_key {
-            if key == unlock_key {
+            if ca::tokens_match(key.trim(), unlock_key) {
                 self.state = SwarmState::Active;
                 Ok(())
             } else {
@@ -377,56 +795,378 @@ impl SwarmCluster {
         }
     }
 
+    /// Read container logs through the given source when asked for them
+    pub fn with_log_source(mut self, source: Arc<dyn LogSource>) -> Self {
+        self.log_source = Some(source);
+        self
+    }
+
+    /// Read logs of containers running on this node
+    fn local_logs(&self, requests: &[LogRequest]) -> Result<Vec<Vec<logs::LogLine>>> {
+        let source = self
+            .log_source
+            .as_ref()
+            .ok_or_else(|| RuneError::Swarm("Logs are not available on this node".to_string()))?;
+        requests.iter().map(|r| source.container_logs(r)).collect()
+    }
+
+    /// Gather the logs of a service's tasks from every node
+    ///
+    /// Lines are interleaved by timestamp. `tail` limits the lines read per
+    /// task. Nodes that cannot be reached are skipped with a warning.
+    pub async fn service_logs(
+        &self,
+        id_or_name: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<ServiceLogLine>> {
+        self.gather_logs(
+            id_or_name,
+            |_, container_id| LogRequest {
+                container_id: container_id.to_string(),
+                tail,
+                since: None,
+            },
+            |_, lines| lines,
+        )
+        .await
+    }
+
+    /// Follow the logs of a service's tasks, passing new lines to `emit`
+    ///
+    /// Runs until an error occurs; tasks started later are picked up as
+    /// they appear.
+    pub async fn follow_service_logs(
+        &self,
+        id_or_name: &str,
+        tail: Option<usize>,
+        mut emit: impl FnMut(&ServiceLogLine),
+    ) -> Result<()> {
+        let mut cursors: HashMap<String, LogCursor> = HashMap::new();
+        let mut first = true;
+        loop {
+            let polled_at = Utc::now();
+            let read_from = cursors.clone();
+            let lines = self
+                .gather_logs(
+                    id_or_name,
+                    |task_id, container_id| {
+                        let cursor = read_from.get(task_id).cloned().unwrap_or_default();
+                        cursor.request(container_id, tail.filter(|_| first))
+                    },
+                    |task_id, lines| {
+                        cursors
+                            .entry(task_id.to_string())
+                            .or_default()
+                            .advance(lines)
+                    },
+                )
+                .await?;
+
+            for task in self.list_tasks(Some(&self.get_service(id_or_name)?.id))? {
+                cursors.entry(task.id).or_default().start_at(polled_at);
+            }
+            for line in &lines {
+                emit(line);
+            }
+
+            first = false;
+            tokio::time::sleep(LOG_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Read the logs of a service's tasks, node by node
+    ///
+    /// `request` says which lines to read of a task's container, and
+    /// `select` which of those read to keep.
+    async fn gather_logs(
+        &self,
+        id_or_name: &str,
+        request: impl Fn(&str, &str) -> LogRequest,
+        mut select: impl FnMut(&str, Vec<LogLine>) -> Vec<LogLine>,
+    ) -> Result<Vec<ServiceLogLine>> {
+        let service = self.get_service(id_or_name)?;
+        let nodes = self.list_nodes()?;
+        let local_id = self.credentials.identity()?.node_id;
+
+        // Tasks with a container, grouped by the node running them
+        let mut by_node: HashMap<String, Vec<Task>> = HashMap::new();
+        for task in self.list_tasks(Some(&service.id))? {
+            if let (Some(node_id), Some(_)) = (&task.node_id, &task.status.container_status) {
+                by_node.entry(node_id.clone()).or_default().push(task);
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (node_id, tasks) in by_node {
+            let node = nodes.iter().find(|n| n.id == node_id);
+            let requests: Vec<LogRequest> = tasks
+                .iter()
+                .map(|t| {
+                    let container_id = t
+                        .status
+                        .container_status
+                        .as_ref()
+                        .map(|c| c.container_id.as_str())
+                        .unwrap_or_default();
+                    request(&t.id, container_id)
+                })
+                .collect();
+
+            let result = if node_id == local_id {
+                self.local_logs(&requests)
+            } else {
+                match node {
+                    Some(node) => self.remote_logs(node, requests).await,
+                    None => Err(RuneError::NodeNotFound(node_id.clone())),
+                }
+            };
+            let per_task = match result {
+                Ok(per_task) => per_task,
+                Err(e) => {
+                    tracing::warn!("Failed to read logs from node {}: {}", node_id, e);
+                    continue;
+                }
+            };
+
+            for (task, task_lines) in tasks.iter().zip(per_task) {
+                let task_name = logs::task_name(&service, task, node);
+                let task_lines = select(&task.id, task_lines);
+                lines.extend(task_lines.into_iter().map(|line| ServiceLogLine {
+                    task_id: task.id.clone(),
+                    task_name: task_name.clone(),
+                    line,
+                }));
+            }
+        }
+
+        Ok(logs::interleave(lines))
+    }
+
+    /// Ask another node for container logs
+    async fn remote_logs(
+        &self,
+        node: &Node,
+        requests: Vec<LogRequest>,
+    ) -> Result<Vec<Vec<logs::LogLine>>> {
+        let config = rpc::client_config(&self.credentials)?;
+        match rpc::call_node(&node.addr, node.role, config, &RpcRequest::Logs(requests)).await? {
+            RpcResponse::Logs(lines) => Ok(lines),
+            other => Err(RuneError::Swarm(format!(
+                "Unexpected response to a log request: {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// Serve RPC from other nodes on `listener` and, on managers, drive Raft
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        let config = rpc::server_config(&self.credentials)?;
+        tokio::try_join!(
+            rpc::serve(listener, config, self.clone()),
+            self.drive_raft()
+        )?;
+        Ok(())
+    }
+
+    /// Tick this manager's Raft node and deliver what it sends to the other
+    /// managers over RPC
+    ///
+    /// Managers in the replicated state join the Raft group as they appear,
+    /// so a manager that just joined learns the others from the log. Until
+    /// it knows of another manager a node isn't ticked: it has no one to
+    /// elect or to send heartbeats to.
+    async fn drive_raft(self: Arc<Self>) -> Result<()> {
+        let Some(raft) = self.raft.clone() else {
+            return Ok(());
+        };
+        let config = rpc::client_config(&self.credentials)?;
+        let mut last_tick = Instant::now();
+        loop {
+            let messages = {
+                let mut raft = lock_raft(&raft)?;
+                if last_tick.elapsed() >= RAFT_TICK_INTERVAL {
+                    last_tick = Instant::now();
+                    add_manager_peers(&mut raft);
+                    if !raft.peers().is_empty() {
+                        raft.tick()?;
+                    }
+                }
+                raft.take_messages()
+            };
+
+            for envelope in messages {
+                let addr = match self.get_node(&envelope.to) {
+                    Ok(node) => node.addr,
+                    Err(e) => {
+                        tracing::debug!("No address for manager {}: {}", envelope.to, e);
+                        continue;
+                    }
+                };
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let request = RpcRequest::Raft(envelope);
+                    let sent =
+                        tokio::time::timeout(RAFT_SEND_TIMEOUT, rpc::call(&addr, config, &request))
+                            .await
+                            .unwrap_or_else(|_| Err(RuneError::Swarm("timed out".to_string())));
+                    if let Err(e) = sent {
+                        tracing::debug!("Failed to send a Raft message to {}: {}", addr, e);
+                    }
+                });
+            }
+
+            tokio::time::sleep(RAFT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Serve RPC on the listen address from a thread of its own
+    ///
+    /// Returns the address listened on.
+    pub fn spawn(self: Arc<Self>) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(&self.config.listen_addr).map_err(|e| {
+            RuneError::Swarm(format!(
+                "Failed to listen on {}: {}",
+                self.config.listen_addr, e
+            ))
+        })?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                let served = match TcpListener::from_std(listener) {
+                    Ok(listener) => self.serve(listener).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = served {
+                    tracing::error!("Stopped serving swarm RPC: {}", e);
+                }
+            })
+        });
+        Ok(addr)
+    }
+
+    /// Raft consensus state, if this node is a manager
+    ///
+    /// While the node is served, [`SwarmCluster::serve`] drives it: ticking
+    /// it, stepping the messages other managers send and sending its own.
+    pub fn raft(&self) -> Option<Arc<Mutex<RaftNode>>> {
+        self.raft.clone()
+    }
+
+    /// Commit a change to the cluster state
+    ///
+    /// Managers propose the change through Raft and wait for a quorum of
+    /// managers to store it; only then is it applied here, from the
+    /// committed log like on every other manager. Fails when this manager
+    /// is not the leader or the change isn't committed in time.
+    fn commit(&self, action: StoreAction) -> Result<()> {
+        let Some(raft) = &self.raft else {
+            self.store
+                .write()
+                .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?
+                .apply(&action);
+            return Ok(());
+        };
+
+        let (index, term) = {
+            let mut raft = lock_raft(raft)?;
+            raft.reload()?;
+            (raft.propose(action)?, raft.term())
+        };
+        let deadline = Instant::now() + COMMIT_TIMEOUT;
+        loop {
+            {
+                let raft = lock_raft(raft)?;
+                if raft.applied_index() >= index {
+                    break;
+                }
+                if raft.term() != term {
+                    return Err(RuneError::Swarm(
+                        "Leadership changed before the change was committed".to_string(),
+                    ));
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(RuneError::Swarm(
+                    "The change was not committed in time; the swarm may have lost quorum"
+                        .to_string(),
+                ));
+            }
+            std::thread::sleep(COMMIT_POLL_INTERVAL);
+        }
+        self.sync()
+    }
+
+    /// Bring the local state up to date with what Raft has applied
+    ///
+    /// The daemon and the CLI both open the state directory, so what the
+    /// other one committed is picked up here as well.
+    fn sync(&self) -> Result<()> {
+        let Some(raft) = &self.raft else {
+            return Ok(());
+        };
+        let mut raft = lock_raft(raft)?;
+        let reloaded = raft.reload()?;
+        let applied = raft.applied_index();
+        if reloaded || applied != self.synced.load(Ordering::Acquire) {
+            *self
+                .store
+                .write()
+                .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))? =
+                raft.store().clone();
+            self.synced.store(applied, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// The current cluster state
+    fn read_store(&self) -> Result<RwLockReadGuard<'_, ClusterStore>> {
+        self.sync()?;
+        self.store
+            .read()
+            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))
+    }
+
+    /// Held while working out a change from the current state
+    fn changing(&self) -> Result<MutexGuard<'_, ()>> {
+        self.changing
+            .lock()
+            .map_err(|_| RuneError::Lock("Failed to acquire change lock".to_string()))
+    }
+
     /// Add a node to the cluster
     pub fn add_node(&self, node: Node) -> Result<()> {
-        let mut nodes = self
-            .nodes
-            .write()
-            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        self.commit(StoreAction::PutNode(node))?;
 
-        nodes.insert(node.id.clone(), node);
-        Ok(())
+        // New capacity may let pending tasks run
+        self.reconcile_services()
     }
 
     /// Remove a node from the cluster
     pub fn remove_node(&self, node_id: &str, force: bool) -> Result<()> {
-        let mut nodes = self
-            .nodes
-            .write()
-            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
-
-        let node = nodes
-            .get(node_id)
-            .ok_or_else(|| RuneError::NodeNotFound(node_id.to_string()))?;
-
+        let node = self.get_node(node_id)?;
         if node.state == NodeState::Ready && !force {
             return Err(RuneError::Swarm(
                 "Cannot remove active node. Drain it first or use force.".to_string(),
             ));
         }
 
-        nodes.remove(node_id);
-        Ok(())
+        self.commit(StoreAction::RemoveNode(node_id.to_string()))?;
+        self.reconcile_services()
     }
 
     /// List all nodes
     pub fn list_nodes(&self) -> Result<Vec<Node>> {
-        let nodes = self
-            .nodes
-            .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
-
-        Ok(nodes.values().cloned().collect())
+        Ok(self.read_store()?.nodes.values().cloned().collect())
     }
 
     /// Get a node by ID
     pub fn get_node(&self, node_id: &str) -> Result<Node> {
-        let nodes = self
+        self.read_store()?
             .nodes
-            .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
-
-        nodes
             .get(node_id)
             .cloned()
             .ok_or_else(|| RuneError::NodeNotFound(node_id.to_string()))
@@ -434,65 +1174,129 @@ impl SwarmCluster {
 
     /// Update node
     pub fn update_node(&self, node_id: &str, updates: NodeUpdate) -> Result<()> {
-        let mut nodes = self
-            .nodes
-            .write()
-            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
-
-        let node = nodes
-            .get_mut(node_id)
-            .ok_or_else(|| RuneError::NodeNotFound(node_id.to_string()))?;
-
+        let mut updated = self.get_node(node_id)?;
         if let Some(role) = updates.role {
-            node.role = role;
+            updated.role = role;
         }
         if let Some(availability) = updates.availability {
-            node.availability = availability;
+            if !matches!(availability.as_str(), "active" | "pause" | "drain") {
+                return Err(RuneError::InvalidConfig(format!(
+                    "Invalid availability '{}': expected active, pause or drain",
+                    availability
+                )));
+            }
+            updated.availability = availability;
         }
         if let Some(labels) = updates.labels {
-            node.labels = labels;
+            updated.labels = labels;
         }
 
-        Ok(())
+        self.commit(StoreAction::PutNode(updated))?;
+        self.reconcile_services()
     }
 
     /// Create a service
-    pub fn create_service(&self, service: Service) -> Result<String> {
-        let mut services = self
-            .services
-            .write()
-            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
-
+    pub fn create_service(&self, mut service: Service) -> Result<String> {
         let id = service.id.clone();
-        services.insert(id.clone(), service);
+        {
+            let _changing = self.changing()?;
+            self.network_allocator()?.allocate_service(&mut service)?;
+            self.commit(StoreAction::PutService(service))?;
+        }
 
+        self.reconcile_service(&id)?;
         Ok(id)
     }
 
+    /// Change the replica count of a replicated service
+    pub fn scale_service(&self, id_or_name: &str, replicas: u64) -> Result<()> {
+        let mut service = self.get_service(id_or_name)?;
+        if !matches!(
+            service.spec.mode,
+            None | Some(ServiceMode::Replicated { .. })
+        ) {
+            return Err(RuneError::Service(format!(
+                "{}: scale can only be used with replicated mode",
+                service.spec.name
+            )));
+        }
+
+        service.scale(replicas);
+        let id = service.id.clone();
+        self.commit(StoreAction::PutService(service))?;
+
+        self.reconcile_service(&id)
+    }
+
+    /// Bring a service's tasks in line with its spec
+    ///
+    /// Missing replicas get new tasks, surplus ones are shut down and every
+    /// unassigned task is offered to the scheduler. Tasks that no node can
+    /// run stay pending with the reason in their status message.
+    pub fn reconcile_service(&self, id_or_name: &str) -> Result<()> {
+        let _changing = self.changing()?;
+        let service = self.get_service(id_or_name)?;
+        let nodes = self.list_nodes()?;
+        let mut allocator = self.network_allocator()?;
+
+        let mut current = self.list_tasks(None)?;
+        let plan = Orchestrator::reconcile(&service, &current, &nodes)?;
+
+        let mut changed = Vec::new();
+        for task in current.iter_mut().filter(|t| plan.shutdown.contains(&t.id)) {
+            task.shutdown();
+            changed.push(task.clone());
+        }
+
+        let mut scheduler = Scheduler::new(&nodes, &current);
+        for task in current.iter().filter(|t| {
+            t.service_id == service.id
+                && t.status.state == TaskState::Pending
+                && t.desired_state == TaskState::Running
+        }) {
+            let mut task = task.clone();
+            if scheduler.schedule(&service, &mut task)? {
+                changed.push(task);
+            }
+        }
+        for mut task in plan.create {
+            allocator.allocate_task(&service, &mut task)?;
+            scheduler.schedule(&service, &mut task)?;
+            changed.push(task);
+        }
+
+        for task in changed {
+            self.commit(StoreAction::PutTask(task))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reconcile every service
+    pub fn reconcile_services(&self) -> Result<()> {
+        let ids: Vec<String> = self.list_services()?.into_iter().map(|s| s.id).collect();
+        for id in ids {
+            self.reconcile_service(&id)?;
+        }
+        Ok(())
+    }
+
     /// List services
     pub fn list_services(&self) -> Result<Vec<Service>> {
-        let services = self
-            .services
-            .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
-
-        Ok(services.values().cloned().collect())
+        Ok(self.read_store()?.services.values().cloned().collect())
     }
 
     /// Get a service by ID or name
     pub fn get_service(&self, id_or_name: &str) -> Result<Service> {
-        let services = self
-            .services
-            .read()
-            .map_err(|_| RuneError::Lock("Failed to acquire read lock".to_string()))?;
+        let store = self.read_store()?;
 
         // Try ID first
-        if let Some(service) = services.get(id_or_name) {
+        if let Some(service) = store.services.get(id_or_name) {
             return Ok(service.clone());
         }
 
         // Try name
-        for service in services.values() {
+        for service in store.services.values() {
             if service.spec.name == id_or_name {
                 return Ok(service.clone());
             }
@@ -501,30 +1305,233 @@ impl SwarmCluster {
         Err(RuneError::ServiceNotFound(id_or_name.to_string()))
     }
 
-    /// Remove a service
+    /// Remove a service and its tasks
     pub fn remove_service(&self, id_or_name: &str) -> Result<()> {
-        let mut services = self
-            .services
-            .write()
-            .map_err(|_| RuneError::Lock("Failed to acquire write lock".to_string()))?;
+        let id = self.get_service(id_or_name)?.id;
+        self.commit(StoreAction::RemoveService(id))
+    }
 
-        // Try ID first
-        if services.remove(id_or_name).is_some() {
-            return Ok(());
+    /// List tasks, optionally restricted to a single service
+    pub fn list_tasks(&self, service_id: Option<&str>) -> Result<Vec<Task>> {
+        Ok(self
+            .read_store()?
+            .tasks
+            .values()
+            .filter(|t| service_id.map(|id| t.service_id == id).unwrap_or(true))
+            .cloned()
+            .collect())
+    }
+
+    /// Build a network allocator over the current cluster state
+    fn network_allocator(&self) -> Result<NetworkAllocator> {
+        NetworkAllocator::new(
+            &self.list_networks()?,
+            &self.list_services()?,
+            &self.list_tasks(None)?,
+        )
+    }
+
+    /// Create a swarm-scoped network
+    ///
+    /// Networks without an explicit subnet get the next free one from the
+    /// swarm's default address pools.
+    pub fn create_network(&self, mut network: NetworkConfig) -> Result<String> {
+        let _changing = self.changing()?;
+        let existing = self.list_networks()?;
+        if existing.iter().any(|n| n.name == network.name) {
+            return Err(RuneError::Network(format!(
+                "Network {} already exists",
+                network.name
+            )));
         }
 
-        // Try name
-        let id = services
+        if allocator::has_default_pool(&network) {
+            let subnet = allocator::next_subnet(
+                &self.config.default_addr_pool,
+                self.config.subnet_size,
+                &existing,
+            )?;
+            allocator::set_subnet(&mut network, &subnet)?;
+        }
+
+        let id = network.id.clone();
+        self.commit(StoreAction::PutNetwork(network))?;
+        Ok(id)
+    }
+
+    /// List swarm-scoped networks
+    pub fn list_networks(&self) -> Result<Vec<NetworkConfig>> {
+        Ok(self.read_store()?.networks.values().cloned().collect())
+    }
+
+    /// Remove a swarm-scoped network by ID or name
+    pub fn remove_network(&self, id_or_name: &str) -> Result<()> {
+        let services = self.list_services()?;
+        let id = self
+            .list_networks()?
             .iter()
-            .find(|(_, s)| s.spec.name == id_or_name)
-            .map(|(id, _)| id.clone());
+            .find(|n| n.id == id_or_name || n.name == id_or_name)
+            .map(|n| n.id.clone())
+            .ok_or_else(|| RuneError::NetworkNotFound(id_or_name.to_string()))?;
 
-        if let Some(id) = id {
-            services.remove(&id);
-            Ok(())
-        } else {
-            Err(RuneError::ServiceNotFound(id_or_name.to_string()))
+        let users: Vec<&str> = services
+            .iter()
+            .filter(|s| s.endpoint.virtual_ips.iter().any(|v| v.network_id == id))
+            .map(|s| s.spec.name.as_str())
+            .collect();
+        if !users.is_empty() {
+            return Err(RuneError::Network(format!(
+                "Network {} is in use by service(s): {}",
+                id_or_name,
+                users.join(", ")
+            )));
+        }
+
+        self.commit(StoreAction::RemoveNetwork(id))
+    }
+
+    /// Deploy or update a stack
+    ///
+    /// Missing networks are created, existing services are updated in place
+    /// and new services are created. With `prune`, services labelled with the
+    /// stack namespace that are no longer part of the deployment are removed.
+    pub fn deploy_stack(
+        &self,
+        deployment: StackDeployment,
+        prune: bool,
+    ) -> Result<StackDeployReport> {
+        let mut report = StackDeployReport::default();
+
+        let existing_networks: Vec<String> =
+            self.list_networks()?.into_iter().map(|n| n.name).collect();
+
+        for name in &deployment.external_networks {
+            if !existing_networks.contains(name) {
+                return Err(RuneError::NetworkNotFound(format!(
+                    "{} (declared as external, but could not be found)",
+                    name
+                )));
+            }
+        }
+
+        for network in deployment.networks {
+            if !existing_networks.contains(&network.name) {
+                report.created_networks.push(network.name.clone());
+                self.create_network(network)?;
+            }
+        }
+
+        let current = self.stack_services(&deployment.namespace)?;
+
+        if prune {
+            for service in &current {
+                if !deployment
+                    .services
+                    .iter()
+                    .any(|s| s.name == service.spec.name)
+                {
+                    self.remove_service(&service.id)?;
+                    report.removed_services.push(service.spec.name.clone());
+                }
+            }
+        }
+
+        for spec in deployment.services {
+            let _changing = self.changing()?;
+            let mut allocator = self.network_allocator()?;
+            let existing = self
+                .list_services()?
+                .into_iter()
+                .find(|s| s.spec.name == spec.name);
+
+            let service = match existing {
+                Some(mut service) => {
+                    service.update(spec);
+                    allocator.allocate_service(&mut service)?;
+                    report.updated_services.push(service.spec.name.clone());
+                    service
+                }
+                None => {
+                    let mut service = Service::new(spec);
+                    allocator.allocate_service(&mut service)?;
+                    report.created_services.push(service.spec.name.clone());
+                    service
+                }
+            };
+            self.commit(StoreAction::PutService(service))?;
+        }
+
+        self.reconcile_services()?;
+        Ok(report)
+    }
+
+    /// List stacks deployed on the cluster
+    pub fn list_stacks(&self) -> Result<Vec<StackSummary>> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for service in self.list_services()? {
+            if let Some(namespace) = service.spec.labels.get(STACK_NAMESPACE_LABEL) {
+                *counts.entry(namespace.clone()).or_default() += 1;
+            }
+        }
+
+        let mut stacks: Vec<StackSummary> = counts
+            .into_iter()
+            .map(|(name, services)| StackSummary { name, services })
+            .collect();
+        stacks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(stacks)
+    }
+
+    /// List the services belonging to a stack
+    pub fn stack_services(&self, namespace: &str) -> Result<Vec<Service>> {
+        Ok(self
+            .list_services()?
+            .into_iter()
+            .filter(|s| {
+                s.spec.labels.get(STACK_NAMESPACE_LABEL).map(String::as_str) == Some(namespace)
+            })
+            .collect())
+    }
+
+    /// List the tasks belonging to a stack
+    pub fn stack_tasks(&self, namespace: &str) -> Result<Vec<Task>> {
+        let service_ids: Vec<String> = self
+            .stack_services(namespace)?
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+
+        Ok(self
+            .list_tasks(None)?
+            .into_iter()
+            .filter(|t| service_ids.contains(&t.service_id))
+            .collect())
+    }
+
+    /// Remove a stack's services and networks
+    pub fn remove_stack(&self, namespace: &str) -> Result<()> {
+        let services = self.stack_services(namespace)?;
+        let networks: Vec<NetworkConfig> = self
+            .list_networks()?
+            .into_iter()
+            .filter(|n| n.labels.get(STACK_NAMESPACE_LABEL).map(String::as_str) == Some(namespace))
+            .collect();
+
+        if services.is_empty() && networks.is_empty() {
+            return Err(RuneError::Swarm(format!(
+                "Nothing found in stack: {}",
+                namespace
+            )));
+        }
+
+        for service in services {
+            self.remove_service(&service.id)?;
         }
+        for network in networks {
+            self.remove_network(&network.id)?;
+        }
+
+        Ok(())
     }
 
     /// Update cluster configuration
@@ -536,10 +1543,10 @@ impl SwarmCluster {
 
     /// Get cluster info
     pub fn info(&self) -> SwarmInfo {
-        let nodes = self.nodes.read().unwrap();
-        let services = self.services.read().unwrap();
+        let store = self.read_store().unwrap();
 
-        let manager_count = nodes
+        let manager_count = store
+            .nodes
             .values()
             .filter(|n| n.role == NodeRole::Manager)
             .count();
@@ -548,15 +1555,109 @@ impl SwarmCluster {
             id: self.id.clone(),
             name: self.config.name.clone(),
             state: self.state,
-            node_count: nodes.len(),
+            node_count: store.nodes.len(),
             manager_count,
-            service_count: services.len(),
+            service_count: store.services.len(),
             created_at: self.created_at,
             updated_at: self.updated_at,
         }
     }
 }
 
+impl RpcHandler for SwarmCluster {
+    fn handle(&self, peer: Option<&NodeIdentity>, request: RpcRequest) -> Result<RpcResponse> {
+        if let RpcRequest::Join(request) = request {
+            return self.accept_join(&request).map(RpcResponse::Joined);
+        }
+
+        // Everything else needs a certificate for a node still in the swarm
+        let peer = peer.ok_or_else(|| {
+            RuneError::PermissionDenied("Client certificate required".to_string())
+        })?;
+        if peer.cluster_id != self.id || !self.is_member(peer)? {
+            return Err(RuneError::PermissionDenied(format!(
+                "Node {} is not a member of this swarm",
+                peer.node_id
+            )));
+        }
+
+        match request {
+            RpcRequest::Join(_) => unreachable!(),
+            RpcRequest::Raft(envelope) => {
+                if peer.role != NodeRole::Manager || envelope.from != peer.node_id {
+                    return Err(RuneError::PermissionDenied(
+                        "Raft messages must come from the sending manager".to_string(),
+                    ));
+                }
+                let raft = self
+                    .raft
+                    .as_ref()
+                    .ok_or_else(|| RuneError::Swarm("This node is not a manager".to_string()))?;
+                raft.lock()
+                    .map_err(|_| RuneError::Lock("Failed to acquire raft lock".to_string()))?
+                    .step(envelope)?;
+                Ok(RpcResponse::Ok)
+            }
+            RpcRequest::Heartbeat { node_id } => {
+                if node_id != peer.node_id {
+                    return Err(RuneError::PermissionDenied(
+                        "Nodes may only report their own heartbeat".to_string(),
+                    ));
+                }
+                Ok(RpcResponse::Ok)
+            }
+            RpcRequest::Logs(requests) => {
+                if peer.role != NodeRole::Manager {
+                    return Err(RuneError::PermissionDenied(
+                        "Only managers may read task logs".to_string(),
+                    ));
+                }
+                Ok(RpcResponse::Logs(self.local_logs(&requests)?))
+            }
+        }
+    }
+}
+
+/// Add the managers in the replicated state to the Raft group
+fn add_manager_peers(raft: &mut RaftNode) {
+    let managers: Vec<String> = raft
+        .store()
+        .nodes
+        .values()
+        .filter(|n| n.role == NodeRole::Manager && n.id != raft.id())
+        .map(|n| n.id.clone())
+        .collect();
+    for manager in &managers {
+        raft.add_peer(manager);
+    }
+}
+
+fn lock_raft(raft: &Mutex<RaftNode>) -> Result<MutexGuard<'_, RaftNode>> {
+    raft.lock()
+        .map_err(|_| RuneError::Lock("Failed to acquire raft lock".to_string()))
+}
+
+/// Cluster identity a manager persists alongside its Raft state
+#[derive(Serialize, Deserialize)]
+struct PersistedCluster {
+    id: String,
+    config: SwarmConfig,
+    root_ca: RootCa,
+    credentials: NodeCredentials,
+    worker_token: String,
+    manager_token: String,
+    unlock_key: Option<String>,
+    peers: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl PersistedCluster {
+    fn read(state_dir: &Path, data_key: &DataKey) -> Result<Self> {
+        let data = data_key.open(&fs::read(state_dir.join(CLUSTER_FILE))?)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
 /// Node update parameters
 pub struct NodeUpdate {
     pub role: Option<NodeRole>,
@@ -578,35 +1679,12 @@ pub struct SwarmInfo {
 }
 
 /// Generate a join token
-fn generate_token(token_type: TokenType, cluster_id: &str) -> String {
-    let type_str = match token_type {
-        TokenType::Worker => "worker",
-        TokenType::Manager => "manager",
+fn generate_token(token_type: TokenType, cluster_id: &str, ca_digest: &str) -> String {
+    let role = match token_type {
+        TokenType::Worker => NodeRole::Worker,
+        TokenType::Manager => NodeRole::Manager,
     };
-
-    let random = Uuid::new_v4().to_string().replace("-", "");
-    format!(
-        "SWMTKN-1-{}-{}-{}",
-        &cluster_id[..8],
-        type_str,
-        &random[..25]
-    )
-}
-
-/// Generate an unlock key
-fn generate_unlock_key() -> String {
-    let random = Uuid::new_v4().to_string().replace("-", "");
-    format!("SWMKEY-1-{}", random)
-}
-
-/// Extract cluster ID from token
-fn extract_cluster_id(token: &str) -> Result<String> {
-    if let Some(rest) = token.strip_prefix("SWMTKN-1-") {
-        if let Some(idx) = rest.find('-') {
-            return Ok(rest[..idx].to_string());
-        }
-    }
-    Err(RuneError::Swarm("Invalid token format".to_string()))
+    JoinToken::generate(role, cluster_id, ca_digest)
 }
 
 #[cfg(test)]
@@ -635,9 +1713,665 @@ mod tests {
         assert_eq!(cluster.join_token(TokenType::Worker), new_token);
     }
 
+    #[test]
+    fn test_deploy_and_remove_stack() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx:latest
+  db:
+    image: postgres:13
+"#;
+        let config = crate::compose::ComposeParser::parse_str(yaml).unwrap();
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+
+        let deployment = StackDeployment::from_compose("app", &config).unwrap();
+        let report = cluster.deploy_stack(deployment, false).unwrap();
+        assert_eq!(report.created_services.len(), 2);
+        assert_eq!(report.created_networks, vec!["app_default".to_string()]);
+
+        // Redeploying updates the existing services in place
+        let deployment = StackDeployment::from_compose("app", &config).unwrap();
+        let report = cluster.deploy_stack(deployment, false).unwrap();
+        assert!(report.created_services.is_empty());
+        assert_eq!(report.updated_services.len(), 2);
+
+        let stacks = cluster.list_stacks().unwrap();
+        assert_eq!(
+            stacks,
+            vec![StackSummary {
+                name: "app".to_string(),
+                services: 2
+            }]
+        );
+
+        let networks = cluster.list_networks().unwrap();
+        let network = networks.iter().find(|n| n.name == "app_default").unwrap();
+        assert_eq!(network.ipam.config[0].subnet, "10.0.1.0/24");
+        assert!(cluster.remove_network("app_default").is_err());
+
+        cluster.remove_stack("app").unwrap();
+        assert!(cluster.list_services().unwrap().is_empty());
+        let networks: Vec<String> = cluster
+            .list_networks()
+            .unwrap()
+            .into_iter()
+            .map(|n| n.name)
+            .collect();
+        assert_eq!(networks, vec![ingress::INGRESS_NETWORK.to_string()]);
+        assert!(cluster.remove_stack("app").is_err());
+    }
+
+    #[test]
+    fn test_published_ports_and_vips_are_allocated() {
+        use crate::swarm::service::{EndpointSpec, PortConfig, ServiceSpec};
+
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+        let port = |published| PortConfig {
+            name: None,
+            protocol: None,
+            target_port: 80,
+            published_port: published,
+            publish_mode: None,
+        };
+        let spec = |name: &str, published| ServiceSpec {
+            name: name.to_string(),
+            endpoint_spec: Some(EndpointSpec {
+                mode: None,
+                ports: vec![port(published)],
+            }),
+            ..Default::default()
+        };
+
+        let id = cluster
+            .create_service(Service::new(spec("web", None)))
+            .unwrap();
+        let service = cluster.get_service(&id).unwrap();
+        assert_eq!(service.endpoint.ports[0].published_port, Some(30000));
+        assert_eq!(service.endpoint.virtual_ips.len(), 1);
+        assert_eq!(service.endpoint.virtual_ips[0].addr, "10.0.0.2/24");
+
+        let tasks = cluster.list_tasks(Some(&id)).unwrap();
+        assert_eq!(tasks[0].network_attachments[0].network.name, "ingress");
+        assert_eq!(
+            tasks[0].network_attachments[0].addresses,
+            vec!["10.0.0.3/24"]
+        );
+
+        // A second service cannot take the same published port
+        let err = cluster.create_service(Service::new(spec("api", Some(30000))));
+        assert!(err.is_err());
+        let id = cluster
+            .create_service(Service::new(spec("api", Some(8080))))
+            .unwrap();
+        let service = cluster.get_service(&id).unwrap();
+        assert_eq!(service.endpoint.virtual_ips[0].addr, "10.0.0.4/24");
+
+        assert!(cluster.remove_network("ingress").is_err());
+    }
+
+    #[test]
+    fn test_service_tasks_are_scheduled() {
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+
+        let mut spec = crate::swarm::service::ServiceSpec {
+            name: "web".to_string(),
+            mode: Some(ServiceMode::Replicated { replicas: 3 }),
+            ..Default::default()
+        };
+        spec.task_template.placement = Some(crate::swarm::service::Placement {
+            constraints: vec!["node.labels.tier==frontend".to_string()],
+            ..Default::default()
+        });
+        let id = cluster.create_service(Service::new(spec)).unwrap();
+
+        // No node matches the constraint yet
+        let tasks = cluster.list_tasks(Some(&id)).unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert!(tasks.iter().all(|t| t.status.state == TaskState::Pending));
+
+        let mut worker = Node::new_local(NodeRole::Worker);
+        worker.add_label("tier", "frontend");
+        let worker_id = worker.id.clone();
+        cluster.add_node(worker).unwrap();
+
+        let tasks = cluster.list_tasks(Some(&id)).unwrap();
+        assert!(tasks
+            .iter()
+            .all(|t| t.node_id.as_deref() == Some(worker_id.as_str())));
+
+        cluster.scale_service("web", 1).unwrap();
+        let running = cluster
+            .list_tasks(Some(&id))
+            .unwrap()
+            .into_iter()
+            .filter(|t| t.desired_state == TaskState::Running)
+            .count();
+        assert_eq!(running, 1);
+    }
+
+    #[test]
+    fn test_drain_reschedules_tasks() {
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+        let manager_id = cluster.list_nodes().unwrap()[0].id.clone();
+        let worker = Node::new_local(NodeRole::Worker);
+        let worker_id = worker.id.clone();
+        cluster.add_node(worker).unwrap();
+
+        let id = cluster
+            .create_service(Service::new(crate::swarm::service::ServiceSpec {
+                name: "web".to_string(),
+                mode: Some(ServiceMode::Replicated { replicas: 4 }),
+                ..Default::default()
+            }))
+            .unwrap();
+
+        let drain = |node: &str| {
+            cluster.update_node(
+                node,
+                NodeUpdate {
+                    role: None,
+                    availability: Some("drain".to_string()),
+                    labels: None,
+                },
+            )
+        };
+        drain(&worker_id).unwrap();
+
+        let tasks = cluster.list_tasks(Some(&id)).unwrap();
+        let (running, shutdown): (Vec<&Task>, Vec<&Task>) = tasks
+            .iter()
+            .partition(|t| t.desired_state == TaskState::Running);
+        assert_eq!(running.len(), 4);
+        assert!(running
+            .iter()
+            .all(|t| t.node_id.as_deref() == Some(manager_id.as_str())));
+        assert_eq!(shutdown.len(), 2);
+        assert!(shutdown
+            .iter()
+            .all(|t| t.node_id.as_deref() == Some(worker_id.as_str())));
+
+        assert!(cluster
+            .update_node(
+                &worker_id,
+                NodeUpdate {
+                    role: None,
+                    availability: Some("sleep".to_string()),
+                    labels: None,
+                },
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_global_service_runs_on_every_node() {
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+        let id = cluster
+            .create_service(Service::new(crate::swarm::service::ServiceSpec {
+                name: "agent".to_string(),
+                mode: Some(ServiceMode::Global),
+                ..Default::default()
+            }))
+            .unwrap();
+        assert_eq!(cluster.list_tasks(Some(&id)).unwrap().len(), 1);
+
+        let worker = Node::new_local(NodeRole::Worker);
+        let worker_id = worker.id.clone();
+        cluster.add_node(worker).unwrap();
+
+        let tasks = cluster.list_tasks(Some(&id)).unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|t| t.status.state == TaskState::Assigned));
+
+        cluster.remove_node(&worker_id, true).unwrap();
+        let running: Vec<Task> = cluster
+            .list_tasks(Some(&id))
+            .unwrap()
+            .into_iter()
+            .filter(|t| t.desired_state == TaskState::Running)
+            .collect();
+        assert_eq!(running.len(), 1);
+        assert_ne!(running[0].node_id.as_deref(), Some(worker_id.as_str()));
+    }
+
+    #[test]
+    fn test_changes_are_replicated_through_raft() {
+        let cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+        let raft = cluster.raft().unwrap();
+        assert!(raft.lock().unwrap().is_leader());
+
+        let service = Service::new(crate::swarm::service::ServiceSpec {
+            name: "web".to_string(),
+            ..Default::default()
+        });
+        let id = cluster.create_service(service).unwrap();
+
+        {
+            let raft = raft.lock().unwrap();
+            assert!(raft.store().services.contains_key(&id));
+            assert_eq!(raft.store().nodes.len(), 1);
+        }
+
+        cluster.remove_service("web").unwrap();
+        assert!(raft.lock().unwrap().store().services.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_join_over_mtls() {
+        let manager = Arc::new(SwarmCluster::init(SwarmConfig::default()).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = rpc::server_config(manager.credentials()).unwrap();
+        tokio::spawn(rpc::serve(listener, config, manager.clone()));
+
+        let token = manager.join_token(TokenType::Manager).to_string();
+        let joined = SwarmCluster::join(&token, vec![addr.clone()], "0.0.0.0:2377", "10.0.0.2")
+            .await
+            .unwrap();
+        assert_eq!(joined.id(), manager.id());
+        assert_eq!(manager.list_nodes().unwrap().len(), 2);
+
+        // A follower without a leader refuses writes
+        assert!(!joined.raft().unwrap().lock().unwrap().is_leader());
+        let service = Service::new(Default::default());
+        assert!(joined.create_service(service).is_err());
+        assert!(joined.list_services().unwrap().is_empty());
+
+        // The issued certificate authenticates the node over mTLS
+        let identity = joined.credentials().identity().unwrap();
+        assert_eq!(identity.role, NodeRole::Manager);
+        let client = rpc::client_config(joined.credentials()).unwrap();
+        let heartbeat = RpcRequest::Heartbeat {
+            node_id: identity.node_id.clone(),
+        };
+        assert!(matches!(
+            rpc::call(&addr, client, &heartbeat).await.unwrap(),
+            RpcResponse::Ok
+        ));
+
+        // Without a certificate only joining is allowed
+        let parsed = JoinToken::parse(&token).unwrap();
+        let anonymous = rpc::join_client_config(&parsed).unwrap();
+        assert!(rpc::call(&addr, anonymous, &heartbeat).await.is_err());
+
+        // A token pinned to another CA never reaches the manager
+        let forged = JoinToken::generate(
+            NodeRole::Worker,
+            manager.id(),
+            &RootCa::generate().unwrap().digest().unwrap(),
+        );
+        assert!(
+            SwarmCluster::join(&forged, vec![addr], "0.0.0.0:2377", "10.0.0.3")
+                .await
+                .is_err()
+        );
+        assert_eq!(manager.list_nodes().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_joined_node_is_restored() {
+        let manager = Arc::new(SwarmCluster::init(SwarmConfig::default()).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = rpc::server_config(manager.credentials()).unwrap();
+        tokio::spawn(rpc::serve(listener, config, manager.clone()));
+
+        for (token_type, role) in [
+            (TokenType::Worker, NodeRole::Worker),
+            (TokenType::Manager, NodeRole::Manager),
+        ] {
+            let temp = tempfile::tempdir().unwrap();
+            let token = manager.join_token(token_type).to_string();
+            let mut joined =
+                SwarmCluster::join(&token, vec![addr.clone()], "0.0.0.0:2377", "10.0.0.2:2377")
+                    .await
+                    .unwrap();
+            joined.persist(temp.path()).unwrap();
+            let node_id = joined.credentials().identity().unwrap().node_id;
+            drop(joined);
+
+            let restored = SwarmCluster::restore(temp.path(), None).unwrap();
+            assert_eq!(restored.id(), manager.id());
+            let identity = restored.credentials().identity().unwrap();
+            assert_eq!(identity.node_id, node_id);
+            assert_eq!(identity.role, role);
+            assert_eq!(restored.raft().is_some(), role == NodeRole::Manager);
+        }
+    }
+
+    /// A loopback address nothing listens on
+    fn free_addr() -> String {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Serve `cluster` on `addr`, like the daemon does on its listen address
+    async fn serve_at(cluster: &Arc<SwarmCluster>, addr: &str) -> tokio::task::JoinHandle<()> {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let cluster = cluster.clone();
+        tokio::spawn(async move { cluster.serve(listener).await.unwrap() })
+    }
+
+    /// Wait up to `timeout` for `condition` to hold
+    async fn wait_for(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !condition() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+
+    fn is_leader(cluster: &SwarmCluster) -> bool {
+        cluster.raft().unwrap().lock().unwrap().is_leader()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_follower_sees_committed_changes() {
+        let addr = free_addr();
+        let leader = Arc::new(
+            SwarmCluster::init(SwarmConfig {
+                listen_addr: addr.clone(),
+                advertise_addr: addr.clone(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let _serving = serve_at(&leader, &addr).await;
+
+        let follower_addr = free_addr();
+        let token = leader.join_token(TokenType::Manager).to_string();
+        let follower = SwarmCluster::join(&token, vec![addr], &follower_addr, &follower_addr)
+            .await
+            .unwrap();
+        let follower = Arc::new(follower);
+        let serving = serve_at(&follower, &follower_addr).await;
+
+        // With two managers a write waits for the follower to store it
+        let service = Service::new(crate::swarm::service::ServiceSpec {
+            name: "web".to_string(),
+            ..Default::default()
+        });
+        let id = tokio::task::block_in_place(|| leader.create_service(service)).unwrap();
+        let tasks = leader.list_tasks(Some(&id)).unwrap().len();
+        assert_eq!(tasks, 1);
+
+        let seen = || follower.list_tasks(Some(&id)).unwrap().len() == tasks;
+        assert!(wait_for(Duration::from_secs(5), seen).await);
+        assert_eq!(follower.get_service("web").unwrap().id, id);
+        assert_eq!(follower.list_nodes().unwrap().len(), 2);
+
+        // Without the follower the leader has no quorum, and nothing changes
+        serving.abort();
+        let _ = serving.await;
+        let service = Service::new(crate::swarm::service::ServiceSpec {
+            name: "db".to_string(),
+            ..Default::default()
+        });
+        let result = tokio::task::block_in_place(|| leader.create_service(service));
+        assert!(result.is_err());
+        assert!(leader.get_service("db").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_election_and_failover_between_managers() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let addrs = [free_addr(), free_addr()];
+        let service = |name: &str| {
+            Service::new(crate::swarm::service::ServiceSpec {
+                name: name.to_string(),
+                ..Default::default()
+            })
+        };
+
+        let first = Arc::new(
+            SwarmCluster::init(SwarmConfig {
+                listen_addr: addrs[0].clone(),
+                advertise_addr: addrs[0].clone(),
+                state_dir: Some(dirs[0].path().to_path_buf()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let serving = serve_at(&first, &addrs[0]).await;
+
+        let token = first.join_token(TokenType::Manager).to_string();
+        let mut second = SwarmCluster::join(&token, vec![addrs[0].clone()], &addrs[1], &addrs[1])
+            .await
+            .unwrap();
+        second.persist(dirs[1].path()).unwrap();
+        let second = Arc::new(second);
+        let _serving = serve_at(&second, &addrs[1]).await;
+
+        // The second manager learns of the first from the log
+        tokio::task::block_in_place(|| first.create_service(service("web"))).unwrap();
+        assert!(wait_for(Duration::from_secs(5), || second.get_service("web").is_ok()).await);
+        let first_id = first.credentials().identity().unwrap().node_id;
+        let learned = || second.raft().unwrap().lock().unwrap().peers() == [first_id.clone()];
+        assert!(wait_for(RAFT_TICK_INTERVAL * 2, learned).await);
+
+        // Alone, the second manager can't elect a leader
+        serving.abort();
+        let _ = serving.await;
+        drop(first);
+        tokio::time::sleep(RAFT_TICK_INTERVAL * 30).await;
+        assert!(!is_leader(&second));
+        let result = tokio::task::block_in_place(|| second.create_service(service("db")));
+        assert!(result.is_err());
+
+        // Restarted, the first manager campaigns only on an election timeout
+        let first = Arc::new(SwarmCluster::restore(dirs[0].path(), None).unwrap());
+        assert!(!is_leader(&first));
+        let _serving = serve_at(&first, &addrs[0]).await;
+        let managers = [first, second];
+        let elected = || managers.iter().filter(|m| is_leader(m)).count() == 1;
+        assert!(wait_for(Duration::from_secs(10), elected).await);
+
+        let leader = managers.iter().find(|m| is_leader(m)).unwrap();
+        tokio::task::block_in_place(|| leader.create_service(service("db"))).unwrap();
+        for manager in &managers {
+            assert!(wait_for(Duration::from_secs(5), || manager.get_service("db").is_ok()).await);
+        }
+    }
+
+    /// Log source returning fixed lines tagged with its node
+    struct StubLogs(&'static str, Vec<i64>);
+
+    impl LogSource for StubLogs {
+        fn container_logs(&self, request: &LogRequest) -> Result<Vec<logs::LogLine>> {
+            let lines = self
+                .1
+                .iter()
+                .map(|secs| logs::LogLine {
+                    timestamp: DateTime::from_timestamp(*secs, 0).unwrap(),
+                    stream: "stdout".to_string(),
+                    message: format!("{}{}", self.0, secs),
+                })
+                .collect();
+            Ok(request.select(lines))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service_logs_across_nodes() {
+        let manager = SwarmCluster::init(SwarmConfig::default())
+            .unwrap()
+            .with_log_source(Arc::new(StubLogs("m", vec![1, 3])));
+        let manager = Arc::new(manager);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = rpc::server_config(manager.credentials()).unwrap();
+        tokio::spawn(rpc::serve(listener, config, manager.clone()));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let worker_addr = listener.local_addr().unwrap().to_string();
+        let token = manager.join_token(TokenType::Worker).to_string();
+        let worker = SwarmCluster::join(&token, vec![addr], "0.0.0.0:2377", &worker_addr)
+            .await
+            .unwrap()
+            .with_log_source(Arc::new(StubLogs("w", vec![2])));
+        let worker = Arc::new(worker);
+        let config = rpc::server_config(worker.credentials()).unwrap();
+        tokio::spawn(rpc::serve(listener, config, worker.clone()));
+
+        let id = manager
+            .create_service(Service::new(crate::swarm::service::ServiceSpec {
+                name: "web".to_string(),
+                mode: Some(ServiceMode::Replicated { replicas: 2 }),
+                ..Default::default()
+            }))
+            .unwrap();
+        for mut task in manager.list_tasks(None).unwrap() {
+            task.status.container_status = Some(crate::swarm::task::ContainerStatus {
+                container_id: task.id.clone(),
+                pid: None,
+                exit_code: None,
+                health: None,
+            });
+            manager.commit(StoreAction::PutTask(task)).unwrap();
+        }
+
+        let lines = manager.service_logs(&id, None).await.unwrap();
+        let messages: Vec<&str> = lines.iter().map(|l| l.line.message.as_str()).collect();
+        assert_eq!(messages, vec!["m1", "w2", "m3"]);
+        assert!(lines[0].to_string().contains("| m1"));
+        assert!(lines[0].task_name.starts_with("web."));
+
+        let lines = manager.service_logs("web", Some(1)).await.unwrap();
+        let messages: Vec<&str> = lines.iter().map(|l| l.line.message.as_str()).collect();
+        assert_eq!(messages, vec!["w2", "m3"]);
+    }
+
+    /// Log source whose lines are written as a test goes
+    #[derive(Default)]
+    struct GrowingLogs(std::sync::Mutex<Vec<logs::LogLine>>);
+
+    impl GrowingLogs {
+        fn write(&self, secs: i64, message: &str) {
+            self.0.lock().unwrap().push(logs::LogLine {
+                timestamp: DateTime::from_timestamp(secs, 0).unwrap(),
+                stream: "stdout".to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    impl LogSource for GrowingLogs {
+        fn container_logs(&self, request: &LogRequest) -> Result<Vec<logs::LogLine>> {
+            Ok(request.select(self.0.lock().unwrap().clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_service_logs() {
+        let source = Arc::new(GrowingLogs::default());
+        let manager = SwarmCluster::init(SwarmConfig::default())
+            .unwrap()
+            .with_log_source(source.clone());
+        let id = manager
+            .create_service(Service::new(crate::swarm::service::ServiceSpec {
+                name: "web".to_string(),
+                ..Default::default()
+            }))
+            .unwrap();
+        for mut task in manager.list_tasks(None).unwrap() {
+            task.status.container_status = Some(crate::swarm::task::ContainerStatus {
+                container_id: task.id.clone(),
+                pid: None,
+                exit_code: None,
+                health: None,
+            });
+            manager.commit(StoreAction::PutTask(task)).unwrap();
+        }
+
+        source.write(1, "one");
+        let mut seen = Vec::new();
+        {
+            let follow =
+                manager.follow_service_logs(&id, None, |l| seen.push(l.line.message.clone()));
+            let write = async {
+                // Written in the same second as the line already read
+                tokio::time::sleep(LOG_POLL_INTERVAL / 2).await;
+                source.write(1, "two");
+                tokio::time::sleep(LOG_POLL_INTERVAL).await;
+                source.write(1, "three");
+                source.write(2, "four");
+                tokio::time::sleep(LOG_POLL_INTERVAL).await;
+            };
+            tokio::select! {
+                result = follow => panic!("following ended with {:?}", result),
+                _ = write => {}
+            }
+        }
+        assert_eq!(seen, ["one", "two", "three", "four"]);
+    }
+
+    #[test]
+    fn test_autolock_requires_unlock_after_restart() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = SwarmConfig {
+            state_dir: Some(temp.path().to_path_buf()),
+            ..Default::default()
+        };
+        let mut cluster = SwarmCluster::init(config).unwrap();
+        let id = cluster
+            .create_service(Service::new(crate::swarm::service::ServiceSpec {
+                name: "web".to_string(),
+                ..Default::default()
+            }))
+            .unwrap();
+        assert!(cluster.rotate_unlock_key().is_err());
+        let key = cluster.set_autolock(true).unwrap().unwrap();
+        drop(cluster);
+
+        // The Raft log on disk is not readable in the clear
+        let log = std::fs::read(temp.path().join("raft").join("log.json")).unwrap();
+        assert!(!String::from_utf8_lossy(&log).contains("web"));
+
+        assert!(SwarmCluster::restore(temp.path(), None).is_err());
+        assert!(SwarmCluster::restore(temp.path(), Some("SWMKEY-1-wrong")).is_err());
+        let mut cluster = SwarmCluster::restore(temp.path(), Some(&key)).unwrap();
+        assert_eq!(cluster.get_service(&id).unwrap().spec.name, "web");
+        assert_eq!(cluster.unlock_key(), Some(key.as_str()));
+
+        let rotated = cluster.rotate_unlock_key().unwrap();
+        drop(cluster);
+        assert!(SwarmCluster::restore(temp.path(), Some(&key)).is_err());
+        let mut cluster = SwarmCluster::restore(temp.path(), Some(&rotated)).unwrap();
+
+        assert_eq!(cluster.set_autolock(false).unwrap(), None);
+        drop(cluster);
+        assert!(SwarmCluster::restore(temp.path(), None).is_ok());
+    }
+
+    #[test]
+    fn test_rotated_token_is_rejected() {
+        let mut cluster = SwarmCluster::init(SwarmConfig::default()).unwrap();
+        let request = |token: &str| JoinRequest {
+            token: token.to_string(),
+            csr_pem: ca::generate_csr().unwrap().1,
+            description: Default::default(),
+            advertise_addr: "10.0.0.2".to_string(),
+        };
+
+        let old_token = cluster.join_token(TokenType::Worker).to_string();
+        let response = cluster.accept_join(&request(&old_token)).unwrap();
+        assert_eq!(response.role, NodeRole::Worker);
+        assert!(response.root_ca_key_pem.is_none());
+
+        let new_token = cluster.rotate_join_token(TokenType::Worker).unwrap();
+        assert!(cluster.accept_join(&request(&old_token)).is_err());
+        assert!(cluster.accept_join(&request(&new_token)).is_ok());
+    }
+
     #[test]
     fn test_generate_token() {
-        let token = generate_token(TokenType::Worker, "abc12345");
+        let token = generate_token(TokenType::Worker, "abc12345", &"0".repeat(64));
         assert!(token.starts_with("SWMTKN-1-"));
         assert!(token.contains("worker"));
     }
@@ -0,0 +1,256 @@
+//! Swarm state encryption
+//!
+//! A manager encrypts everything it keeps on disk with a random data key.
+//! With autolock enabled the data key is itself sealed with a key derived
+//! from the swarm's unlock key, so a restarted manager cannot read its state
+//! until it is given the unlock key.
+
+use crate::error::{Result, RuneError};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix of swarm unlock keys
+pub const UNLOCK_KEY_PREFIX: &str = "SWMKEY-1-";
+
+/// File the data key is kept in
+const KEY_FILE: &str = "key.json";
+
+/// HKDF context for keys derived from an unlock key
+const UNLOCK_KEY_INFO: &[u8] = b"rune swarm unlock key";
+
+/// Generate a new unlock key
+pub fn generate_unlock_key() -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    format!("{}{}", UNLOCK_KEY_PREFIX, URL_SAFE_NO_PAD.encode(secret))
+}
+
+fn crypto_error(_: ring::error::Unspecified) -> RuneError {
+    RuneError::Swarm("Swarm state encryption failed".to_string())
+}
+
+/// Symmetric key for the swarm state on disk
+#[derive(Clone)]
+pub struct DataKey([u8; 32]);
+
+impl DataKey {
+    /// Generate a random data key
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Derive a key from an unlock key
+    fn from_unlock_key(unlock_key: &str, salt: &[u8]) -> Result<Self> {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(unlock_key.trim().as_bytes());
+        let mut key = [0u8; 32];
+        prk.expand(&[UNLOCK_KEY_INFO], hkdf::HKDF_SHA256)
+            .and_then(|okm| okm.fill(&mut key))
+            .map_err(crypto_error)?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> Result<LessSafeKey> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &self.0).map_err(crypto_error)?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// Encrypt and authenticate data; the random nonce is prepended
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut data = plaintext.to_vec();
+        self.cipher()?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(crypto_error)?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        Ok(sealed)
+    }
+
+    /// Decrypt data produced by [`DataKey::seal`]
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(RuneError::Swarm(
+                "Encrypted swarm state is truncated".to_string(),
+            ));
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(crypto_error)?;
+
+        let mut data = data.to_vec();
+        let plaintext = self
+            .cipher()?
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| RuneError::Swarm("Swarm state could not be decrypted".to_string()))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+/// On-disk form of the data key
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    /// Whether the key is sealed with the unlock key
+    locked: bool,
+    /// HKDF salt for the unlock key
+    #[serde(default)]
+    salt: String,
+    /// Data key, sealed when locked
+    key: String,
+}
+
+/// Keeps a manager's data key in its state directory
+pub struct KeyStore {
+    path: PathBuf,
+}
+
+impl KeyStore {
+    /// Open the key store of a state directory
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            path: dir.join(KEY_FILE),
+        })
+    }
+
+    /// Check whether a data key has been saved
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    fn read(&self) -> Result<KeyFile> {
+        if !self.exists() {
+            return Err(RuneError::Swarm(
+                "This node is not part of a swarm".to_string(),
+            ));
+        }
+        Ok(serde_json::from_slice(&fs::read(&self.path)?)?)
+    }
+
+    /// Check whether the data key needs an unlock key
+    pub fn is_locked(&self) -> Result<bool> {
+        Ok(self.read()?.locked)
+    }
+
+    /// Read the data key, unsealing it with the unlock key if locked
+    pub fn load(&self, unlock_key: Option<&str>) -> Result<DataKey> {
+        let file = self.read()?;
+        let decode = |s: &str| {
+            STANDARD
+                .decode(s)
+                .map_err(|_| RuneError::Swarm("Swarm key file is corrupt".to_string()))
+        };
+        let key = decode(&file.key)?;
+
+        let key = if file.locked {
+            let unlock_key = unlock_key.ok_or_else(|| {
+                RuneError::Swarm(
+                    "Swarm is encrypted and needs to be unlocked before it can be used. \
+                     Please use \"rune swarm unlock\" to unlock it."
+                        .to_string(),
+                )
+            })?;
+            DataKey::from_unlock_key(unlock_key, &decode(&file.salt)?)?
+                .open(&key)
+                .map_err(|_| RuneError::Swarm("Invalid unlock key".to_string()))?
+        } else {
+            key
+        };
+
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| RuneError::Swarm("Swarm key file is corrupt".to_string()))?;
+        Ok(DataKey(key))
+    }
+
+    /// Save the data key, sealing it with the unlock key if one is given
+    pub fn save(&self, key: &DataKey, unlock_key: Option<&str>) -> Result<()> {
+        let file = match unlock_key {
+            Some(unlock_key) => {
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let sealed = DataKey::from_unlock_key(unlock_key, &salt)?.seal(&key.0)?;
+                KeyFile {
+                    locked: true,
+                    salt: STANDARD.encode(salt),
+                    key: STANDARD.encode(sealed),
+                }
+            }
+            None => KeyFile {
+                locked: false,
+                salt: String::new(),
+                key: STANDARD.encode(key.0),
+            },
+        };
+
+        write_private(&self.path, &serde_json::to_vec(&file)?)
+    }
+}
+
+/// Atomically write a file only the owner can read
+pub(crate) fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = DataKey::generate();
+        let sealed = key.seal(b"raft state").unwrap();
+        assert_ne!(&sealed[NONCE_LEN..], b"raft state");
+        assert_eq!(key.open(&sealed).unwrap(), b"raft state");
+        assert!(DataKey::generate().open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_locked_key_store() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = KeyStore::open(temp.path()).unwrap();
+        let key = DataKey::generate();
+        let unlock_key = generate_unlock_key();
+        assert!(unlock_key.starts_with(UNLOCK_KEY_PREFIX));
+
+        store.save(&key, None).unwrap();
+        assert!(!store.is_locked().unwrap());
+        assert_eq!(store.load(None).unwrap().0, key.0);
+
+        store.save(&key, Some(&unlock_key)).unwrap();
+        assert!(store.is_locked().unwrap());
+        assert!(store.load(None).is_err());
+        assert!(store.load(Some(&generate_unlock_key())).is_err());
+        assert_eq!(store.load(Some(&unlock_key)).unwrap().0, key.0);
+    }
+}
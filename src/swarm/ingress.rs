@@ -0,0 +1,582 @@
+//! Swarm routing mesh
+//!
+//! Every node listens on the published ports of all services and forwards
+//! connections to running tasks over the ingress network, whichever node
+//! they run on. Services also get a virtual IP on each network they are
+//! attached to, which resolves to one of their running tasks. Tasks whose
+//! healthcheck is starting or failing are kept out of rotation.
+//!
+//! Forwarding is done by a userspace proxy. TCP connections are forwarded
+//! whole; UDP datagrams are forwarded per client, with replies relayed back
+//! until the client's session goes idle. Other protocols are not served.
+
+use super::allocator::{is_ingress, protocol};
+use super::raft::ClusterStore;
+use super::service::Service;
+use super::task::{NetworkAttachment, Task, TaskState};
+use crate::container::HealthStatus;
+use crate::error::{Result, RuneError};
+use crate::network::config::{IpamPoolConfig, NetworkConfig, NetworkDriver, NetworkScope};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// Name of the routing mesh network
+pub const INGRESS_NETWORK: &str = "ingress";
+
+/// Subnet of the routing mesh network
+pub const INGRESS_SUBNET: &str = "10.0.0.0/24";
+
+/// How long to wait for a backend before trying the next one
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a UDP client's session with a backend lasts without replies
+const UDP_SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a running mesh is brought in line with the swarm state
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Create the routing mesh network
+pub fn ingress_network() -> NetworkConfig {
+    let mut network = NetworkConfig::new(INGRESS_NETWORK);
+    network.driver = NetworkDriver::Overlay;
+    network.scope = NetworkScope::Swarm;
+    network.ingress = true;
+    network.ipam.config = vec![IpamPoolConfig {
+        subnet: INGRESS_SUBNET.to_string(),
+        gateway: Some("10.0.0.1".to_string()),
+        ip_range: None,
+        aux_addresses: HashMap::new(),
+    }];
+    network
+}
+
+/// Round-robin balancer over task addresses
+#[derive(Debug, Default)]
+pub struct LoadBalancer {
+    backends: RwLock<Vec<IpAddr>>,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    /// Create a balancer over a set of backends
+    pub fn new(backends: Vec<IpAddr>) -> Self {
+        Self {
+            backends: RwLock::new(backends),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Replace the backends
+    pub fn set_backends(&self, backends: Vec<IpAddr>) {
+        if let Ok(mut current) = self.backends.write() {
+            *current = backends;
+        }
+    }
+
+    /// Current backends
+    pub fn backends(&self) -> Vec<IpAddr> {
+        self.backends.read().map(|b| b.clone()).unwrap_or_default()
+    }
+
+    /// Pick the next backend
+    pub fn pick(&self) -> Option<IpAddr> {
+        self.candidates().into_iter().next()
+    }
+
+    /// All backends, starting from the next one in turn
+    ///
+    /// Callers try them in order so a dead backend does not fail the
+    /// connection while another task is still reachable.
+    pub fn candidates(&self) -> Vec<IpAddr> {
+        let backends = self.backends();
+        if backends.is_empty() {
+            return backends;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % backends.len();
+        backends[start..]
+            .iter()
+            .chain(&backends[..start])
+            .copied()
+            .collect()
+    }
+}
+
+/// A published port served by this node
+struct PublishedPort {
+    service_id: String,
+    target_port: u16,
+    balancer: Arc<LoadBalancer>,
+    handle: JoinHandle<()>,
+}
+
+/// Published ports, by port and protocol, and virtual IPs of the services
+/// in a swarm
+pub struct RoutingMesh {
+    bind_addr: IpAddr,
+    ports: HashMap<(u16, String), PublishedPort>,
+    vips: HashMap<IpAddr, Arc<LoadBalancer>>,
+}
+
+impl RoutingMesh {
+    /// Create a routing mesh that listens on `bind_addr`
+    pub fn new(bind_addr: IpAddr) -> Self {
+        Self {
+            bind_addr,
+            ports: HashMap::new(),
+            vips: HashMap::new(),
+        }
+    }
+
+    /// Serve a routing mesh on `bind_addr` from a thread of its own,
+    /// syncing it every `interval` with the swarm state `state` reads
+    pub fn spawn(
+        state: impl Fn() -> Result<ClusterStore> + Send + 'static,
+        bind_addr: IpAddr,
+        interval: Duration,
+    ) -> Result<thread::JoinHandle<()>> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(thread::spawn(move || {
+            runtime.block_on(async move {
+                let mut mesh = RoutingMesh::new(bind_addr);
+                loop {
+                    let synced = match state() {
+                        Ok(store) => {
+                            let services: Vec<Service> = store.services.into_values().collect();
+                            let tasks: Vec<Task> = store.tasks.into_values().collect();
+                            mesh.sync(&services, &tasks).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = synced {
+                        tracing::warn!("Failed to sync the routing mesh: {}", e);
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            })
+        }))
+    }
+
+    /// Bring listeners and virtual IPs in line with the swarm state
+    ///
+    /// Ports that are no longer published are closed and newly published
+    /// ports start listening. Backends are the running, healthy tasks of
+    /// each service; tasks that stop or fail their healthcheck are taken
+    /// out of rotation.
+    pub async fn sync(&mut self, services: &[Service], tasks: &[Task]) -> Result<()> {
+        let mut wanted: HashMap<(u16, String), (&Service, u16)> = HashMap::new();
+        for service in services {
+            for port in service.endpoint.ports.iter().filter(|p| is_ingress(p)) {
+                let Some(published) = port.published_port else {
+                    continue;
+                };
+                let protocol = protocol(port);
+                if protocol != "tcp" && protocol != "udp" {
+                    tracing::warn!(
+                        "Port {}/{} of service {} is not served by the routing mesh",
+                        published,
+                        protocol,
+                        service.spec.name
+                    );
+                    continue;
+                }
+                wanted.insert((published, protocol), (service, port.target_port));
+            }
+        }
+
+        // Close ports that went away or now point elsewhere
+        self.ports.retain(|port, published| {
+            let keep = wanted
+                .get(port)
+                .map(|(s, target)| s.id == published.service_id && *target == published.target_port)
+                .unwrap_or(false);
+            if !keep {
+                published.handle.abort();
+            }
+            keep
+        });
+
+        for ((port, protocol), (service, target_port)) in wanted {
+            let backends = running_addresses(service, tasks, |a| a.network.name == INGRESS_NETWORK);
+            let key = (port, protocol);
+            if let Some(published) = self.ports.get(&key) {
+                published.balancer.set_backends(backends);
+                continue;
+            }
+
+            let publish_error = |e: std::io::Error| {
+                RuneError::Network(format!("Failed to publish port {}/{}: {}", port, key.1, e))
+            };
+            let balancer = Arc::new(LoadBalancer::new(backends));
+            let handle = if key.1 == "udp" {
+                let socket = UdpSocket::bind((self.bind_addr, port))
+                    .await
+                    .map_err(publish_error)?;
+                tokio::spawn(serve_udp(socket, balancer.clone(), target_port))
+            } else {
+                let listener = TcpListener::bind((self.bind_addr, port))
+                    .await
+                    .map_err(publish_error)?;
+                tokio::spawn(serve(listener, balancer.clone(), target_port))
+            };
+            self.ports.insert(
+                key,
+                PublishedPort {
+                    service_id: service.id.clone(),
+                    target_port,
+                    balancer,
+                    handle,
+                },
+            );
+        }
+
+        let mut vips = HashMap::new();
+        for service in services {
+            for vip in &service.endpoint.virtual_ips {
+                let Some(addr) = parse_addr(&vip.addr) else {
+                    continue;
+                };
+                let backends =
+                    running_addresses(service, tasks, |a| a.network.id == vip.network_id);
+                let balancer = self.vips.remove(&addr).unwrap_or_default();
+                balancer.set_backends(backends);
+                vips.insert(addr, balancer);
+            }
+        }
+        self.vips = vips;
+
+        Ok(())
+    }
+
+    /// Ports this node is publishing, over any protocol
+    pub fn published_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.ports.keys().map(|(port, _)| *port).collect();
+        ports.sort();
+        ports.dedup();
+        ports
+    }
+
+    /// Pick the task a connection to a virtual IP goes to
+    pub fn resolve_vip(&self, vip: SocketAddr) -> Option<SocketAddr> {
+        self.vips
+            .get(&vip.ip())
+            .and_then(|b| b.pick())
+            .map(|ip| SocketAddr::new(ip, vip.port()))
+    }
+}
+
+impl Drop for RoutingMesh {
+    fn drop(&mut self) {
+        for published in self.ports.values() {
+            published.handle.abort();
+        }
+    }
+}
+
+/// Addresses of a service's running tasks on the matching networks, leaving
+/// out tasks whose healthcheck has not passed
+fn running_addresses(
+    service: &Service,
+    tasks: &[Task],
+    network: impl Fn(&NetworkAttachment) -> bool,
+) -> Vec<IpAddr> {
+    tasks
+        .iter()
+        .filter(|t| {
+            let health = t.status.container_status.as_ref().and_then(|c| c.health);
+            t.service_id == service.id
+                && t.status.state == TaskState::Running
+                && t.desired_state == TaskState::Running
+                && matches!(health, None | Some(HealthStatus::Healthy))
+        })
+        .flat_map(|t| &t.network_attachments)
+        .filter(|a| network(a))
+        .flat_map(|a| a.addresses.iter().filter_map(|addr| parse_addr(addr)))
+        .collect()
+}
+
+/// Parse an address in CIDR notation
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.split('/').next().and_then(|a| a.parse().ok())
+}
+
+/// Accept connections on a published port
+async fn serve(listener: TcpListener, balancer: Arc<LoadBalancer>, target_port: u16) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(forward(stream, balancer.clone(), target_port));
+            }
+            Err(e) => {
+                tracing::warn!("Routing mesh failed to accept a connection: {}", e);
+            }
+        }
+    }
+}
+
+/// Forward one connection to the first reachable backend
+async fn forward(mut inbound: TcpStream, balancer: Arc<LoadBalancer>, target_port: u16) {
+    for ip in balancer.candidates() {
+        let addr = SocketAddr::new(ip, target_port);
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(mut outbound)) => {
+                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                return;
+            }
+            _ => tracing::debug!("Routing mesh backend {} is unreachable", addr),
+        }
+    }
+    tracing::warn!("No running task to forward port {} to", target_port);
+}
+
+/// Relay datagrams on a published UDP port
+///
+/// Each client gets a socket of its own towards one backend, so replies
+/// find their way back to it.
+async fn serve_udp(socket: UdpSocket, balancer: Arc<LoadBalancer>, target_port: u16) {
+    let socket = Arc::new(socket);
+    let sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>> = Arc::default();
+    let mut buf = vec![0u8; u16::MAX as usize];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!("Routing mesh failed to receive a datagram: {}", e);
+                continue;
+            }
+        };
+        let session = sessions.lock().ok().and_then(|s| s.get(&client).cloned());
+        let upstream = match session {
+            Some(upstream) => upstream,
+            None => {
+                let Some(ip) = balancer.pick() else {
+                    tracing::warn!("No running task to forward port {}/udp to", target_port);
+                    continue;
+                };
+                match udp_session(ip, target_port).await {
+                    Ok(upstream) => {
+                        let upstream = Arc::new(upstream);
+                        if let Ok(mut sessions) = sessions.lock() {
+                            sessions.insert(client, upstream.clone());
+                        }
+                        tokio::spawn(relay_replies(
+                            socket.clone(),
+                            upstream.clone(),
+                            client,
+                            sessions.clone(),
+                        ));
+                        upstream
+                    }
+                    Err(e) => {
+                        tracing::debug!("Routing mesh backend {} is unreachable: {}", ip, e);
+                        continue;
+                    }
+                }
+            }
+        };
+        if let Err(e) = upstream.send(&buf[..len]).await {
+            tracing::debug!("Routing mesh failed to forward a datagram: {}", e);
+        }
+    }
+}
+
+/// Socket sending a client's datagrams to a backend
+async fn udp_session(ip: IpAddr, target_port: u16) -> std::io::Result<UdpSocket> {
+    let local: SocketAddr = match ip {
+        IpAddr::V4(_) => (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let upstream = UdpSocket::bind(local).await?;
+    upstream.connect((ip, target_port)).await?;
+    Ok(upstream)
+}
+
+/// Send a backend's replies back to the client until the session goes idle
+async fn relay_replies(
+    socket: Arc<UdpSocket>,
+    upstream: Arc<UdpSocket>,
+    client: SocketAddr,
+    sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
+) {
+    let mut buf = vec![0u8; u16::MAX as usize];
+    while let Ok(Ok(len)) = tokio::time::timeout(UDP_SESSION_TIMEOUT, upstream.recv(&mut buf)).await
+    {
+        if let Err(e) = socket.send_to(&buf[..len], client).await {
+            tracing::debug!("Routing mesh failed to reply to {}: {}", client, e);
+            break;
+        }
+    }
+    if let Ok(mut sessions) = sessions.lock() {
+        sessions.remove(&client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swarm::service::{Endpoint, PortConfig, ServiceSpec, VirtualIP};
+    use crate::swarm::task::NetworkRef;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_load_balancer_round_robin() {
+        let a: IpAddr = "10.0.0.2".parse().unwrap();
+        let b: IpAddr = "10.0.0.3".parse().unwrap();
+        let balancer = LoadBalancer::new(vec![a, b]);
+
+        assert_eq!(balancer.pick(), Some(a));
+        assert_eq!(balancer.pick(), Some(b));
+        assert_eq!(balancer.candidates(), vec![a, b]);
+
+        balancer.set_backends(Vec::new());
+        assert_eq!(balancer.pick(), None);
+    }
+
+    #[tokio::test]
+    async fn test_routing_mesh_forwards_to_tasks() {
+        // Backend standing in for a task on the ingress network
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_port = backend.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = backend.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let published = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut service = Service::new(ServiceSpec {
+            name: "web".to_string(),
+            ..Default::default()
+        });
+        service.endpoint = Endpoint {
+            spec: None,
+            ports: vec![PortConfig {
+                name: None,
+                protocol: Some("tcp".to_string()),
+                target_port,
+                published_port: Some(published),
+                publish_mode: Some("ingress".to_string()),
+            }],
+            virtual_ips: vec![VirtualIP {
+                network_id: "ingress-id".to_string(),
+                addr: "10.0.0.2/24".to_string(),
+            }],
+        };
+
+        let mut task = Task::new(&service.id, Some(1));
+        task.status.state = TaskState::Running;
+        task.network_attachments.push(NetworkAttachment {
+            network: NetworkRef {
+                id: "ingress-id".to_string(),
+                name: INGRESS_NETWORK.to_string(),
+            },
+            addresses: vec!["127.0.0.1/8".to_string()],
+        });
+
+        let mut mesh = RoutingMesh::new("127.0.0.1".parse().unwrap());
+        mesh.sync(&[service.clone()], &[task]).await.unwrap();
+        assert_eq!(mesh.published_ports(), vec![published]);
+        assert_eq!(
+            mesh.resolve_vip("10.0.0.2:80".parse().unwrap()),
+            Some("127.0.0.1:80".parse().unwrap())
+        );
+
+        let mut client = TcpStream::connect(("127.0.0.1", published)).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        mesh.sync(&[], &[]).await.unwrap();
+        assert!(mesh.published_ports().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_routing_mesh_relays_udp_to_healthy_tasks() {
+        // Backend echoing datagrams back, standing in for a task
+        let backend = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target_port = backend.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            loop {
+                let (len, from) = backend.recv_from(&mut buf).await.unwrap();
+                backend.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+
+        let published = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut service = Service::new(ServiceSpec {
+            name: "dns".to_string(),
+            ..Default::default()
+        });
+        service.endpoint.ports = vec![PortConfig {
+            name: None,
+            protocol: Some("udp".to_string()),
+            target_port,
+            published_port: Some(published),
+            publish_mode: Some("ingress".to_string()),
+        }];
+
+        let task = |address: &str, health| {
+            let mut task = Task::new(&service.id, Some(1));
+            task.set_running("container");
+            if let Some(status) = task.status.container_status.as_mut() {
+                status.health = health;
+            }
+            task.network_attachments.push(NetworkAttachment {
+                network: NetworkRef {
+                    id: "ingress-id".to_string(),
+                    name: INGRESS_NETWORK.to_string(),
+                },
+                addresses: vec![address.to_string()],
+            });
+            task
+        };
+        let healthy = task("127.0.0.1/8", Some(HealthStatus::Healthy));
+        let unhealthy = task("127.0.0.2/8", Some(HealthStatus::Unhealthy));
+        let starting = task("127.0.0.3/8", Some(HealthStatus::Starting));
+
+        let backends = running_addresses(
+            &service,
+            &[healthy.clone(), unhealthy.clone(), starting.clone()],
+            |_| true,
+        );
+        assert_eq!(backends, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+
+        let mut mesh = RoutingMesh::new("127.0.0.1".parse().unwrap());
+        mesh.sync(&[service], &[healthy, unhealthy, starting])
+            .await
+            .unwrap();
+        assert_eq!(mesh.published_ports(), vec![published]);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(("127.0.0.1", published)).await.unwrap();
+        for message in [&b"ping"[..], b"pong"] {
+            client.send(message).await.unwrap();
+            let mut buf = [0u8; 64];
+            let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..len], message);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Swarm service logs
+//!
+//! Each node reads the logs of the tasks it runs from their containers'
+//! json-file logs. Managers gather them from every node over the node RPC
+//! and interleave them by timestamp, prefixing each line with its task.
+
+use super::node::Node;
+use super::service::Service;
+use super::task::Task;
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Default directory containers keep their logs in
+pub const DEFAULT_CONTAINER_ROOT: &str = "/var/lib/rune/containers";
+
+/// A single line of container output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogLine {
+    /// Time the line was written
+    pub timestamp: DateTime<Utc>,
+    /// Output stream (stdout or stderr)
+    pub stream: String,
+    /// Line without its trailing newline
+    pub message: String,
+}
+
+/// Which lines to read from a container
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogRequest {
+    /// Container to read
+    pub container_id: String,
+    /// Only the last `tail` lines
+    pub tail: Option<usize>,
+    /// Only lines written after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl LogRequest {
+    /// Apply the request's filters to a container's lines
+    pub fn select(&self, lines: Vec<LogLine>) -> Vec<LogLine> {
+        let mut lines: Vec<LogLine> = lines
+            .into_iter()
+            .filter(|l| self.since.map(|since| l.timestamp > since).unwrap_or(true))
+            .collect();
+        if let Some(tail) = self.tail {
+            let skip = lines.len().saturating_sub(tail);
+            lines.drain(..skip);
+        }
+        lines
+    }
+}
+
+/// How far a follower has read a container's log: the time of the last
+/// line it saw, and how many lines it saw with that time
+///
+/// Lines can share a timestamp, so reading on from the time alone would
+/// drop or repeat some of them.
+#[derive(Debug, Clone, Default)]
+pub struct LogCursor {
+    last: Option<(DateTime<Utc>, usize)>,
+}
+
+impl LogCursor {
+    /// Request for the lines of a container from the cursor on, or its last
+    /// `tail` lines when nothing was read yet
+    pub fn request(&self, container_id: &str, tail: Option<usize>) -> LogRequest {
+        match self.last {
+            // Lines are only selected after `since`, so step back to take in
+            // those written in the same instant as the last one seen
+            Some((time, _)) => LogRequest {
+                container_id: container_id.to_string(),
+                tail: None,
+                since: Some(time - chrono::Duration::nanoseconds(1)),
+            },
+            None => LogRequest {
+                container_id: container_id.to_string(),
+                tail,
+                since: None,
+            },
+        }
+    }
+
+    /// Read on from `time` if nothing was read yet, so lines a tail left
+    /// out aren't read later
+    pub fn start_at(&mut self, time: DateTime<Utc>) {
+        self.last.get_or_insert((time, 0));
+    }
+
+    /// Lines of a read from `request` not seen before, moving the cursor
+    /// past them
+    pub fn advance(&mut self, lines: Vec<LogLine>) -> Vec<LogLine> {
+        let skip = match self.last {
+            Some((time, seen)) => lines
+                .iter()
+                .take(seen)
+                .take_while(|line| line.timestamp == time)
+                .count(),
+            None => 0,
+        };
+        let new: Vec<LogLine> = lines.into_iter().skip(skip).collect();
+        for line in &new {
+            match &mut self.last {
+                Some((time, seen)) if *time == line.timestamp => *seen += 1,
+                last => *last = Some((line.timestamp, 1)),
+            }
+        }
+        new
+    }
+}
+
+/// Reads the logs of containers on this node
+pub trait LogSource: Send + Sync {
+    /// Read a container's log lines, oldest first
+    fn container_logs(&self, request: &LogRequest) -> Result<Vec<LogLine>>;
+}
+
+/// Reads json-file container logs from `<root>/<id>/<id>-json.log`
+pub struct FileLogSource {
+    root: PathBuf,
+}
+
+/// Entry of a json-file log
+#[derive(Deserialize)]
+struct JsonLogEntry {
+    log: String,
+    #[serde(default)]
+    stream: String,
+    time: DateTime<Utc>,
+}
+
+impl FileLogSource {
+    /// Create a log source over a container directory
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl LogSource for FileLogSource {
+    fn container_logs(&self, request: &LogRequest) -> Result<Vec<LogLine>> {
+        let id = &request.container_id;
+        let path = self.root.join(id).join(format!("{}-json.log", id));
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let lines = fs::read_to_string(path)?
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                let entry: JsonLogEntry = serde_json::from_str(l)?;
+                Ok(LogLine {
+                    timestamp: entry.time,
+                    stream: entry.stream,
+                    message: entry.log.trim_end_matches('\n').to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(request.select(lines))
+    }
+}
+
+/// A log line of one of a service's tasks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceLogLine {
+    /// Task the line came from
+    pub task_id: String,
+    /// Task name shown as the line prefix
+    pub task_name: String,
+    /// The line itself
+    pub line: LogLine,
+}
+
+impl fmt::Display for ServiceLogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}    | {}",
+            self.line
+                .timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+            self.task_name,
+            self.line.message
+        )
+    }
+}
+
+/// Name of a task as shown in logs, e.g. `web.1.3f2a9c1e4b7d@node-1`
+///
+/// Replicated tasks are named by slot and global tasks by node.
+pub fn task_name(service: &Service, task: &Task, node: Option<&Node>) -> String {
+    let instance = match task.slot {
+        Some(slot) => slot.to_string(),
+        None => task.node_id.clone().unwrap_or_default(),
+    };
+    let id: String = task.id.chars().filter(|c| *c != '-').take(12).collect();
+    let mut name = format!("{}.{}.{}", service.spec.name, instance, id);
+    if let Some(node) = node {
+        name.push('@');
+        name.push_str(&node.description.hostname);
+    }
+    name
+}
+
+/// Interleave the lines of several tasks by timestamp
+pub fn interleave(mut lines: Vec<ServiceLogLine>) -> Vec<ServiceLogLine> {
+    // Stable, so lines with equal timestamps keep their per-task order
+    lines.sort_by_key(|l| l.line.timestamp);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_log_source() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("abc");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("abc-json.log"),
+            concat!(
+                r#"{"log":"one\n","stream":"stdout","time":"2024-01-01T00:00:01Z"}"#,
+                "\n",
+                r#"{"log":"two\n","stream":"stderr","time":"2024-01-01T00:00:02Z"}"#,
+                "\n",
+                r#"{"log":"three\n","stream":"stdout","time":"2024-01-01T00:00:03Z"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let source = FileLogSource::new(temp.path().to_path_buf());
+        let read = |tail, since: Option<&str>| {
+            source
+                .container_logs(&LogRequest {
+                    container_id: "abc".to_string(),
+                    tail,
+                    since: since.map(|s| s.parse().unwrap()),
+                })
+                .unwrap()
+                .into_iter()
+                .map(|l| l.message)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(read(None, None), vec!["one", "two", "three"]);
+        assert_eq!(read(Some(2), None), vec!["two", "three"]);
+        assert_eq!(read(None, Some("2024-01-01T00:00:02Z")), vec!["three"]);
+        assert!(source
+            .container_logs(&LogRequest {
+                container_id: "missing".to_string(),
+                ..Default::default()
+            })
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_log_cursor_keeps_lines_of_the_same_instant() {
+        let line = |time: &str, message: &str| LogLine {
+            timestamp: time.parse().unwrap(),
+            stream: "stdout".to_string(),
+            message: message.to_string(),
+        };
+        let mut written = vec![
+            line("2024-01-01T00:00:01Z", "one"),
+            line("2024-01-01T00:00:02Z", "two"),
+        ];
+        let read = |cursor: &mut LogCursor, written: &[LogLine]| {
+            let request = cursor.request("abc", None);
+            let lines = cursor.advance(request.select(written.to_vec()));
+            lines.into_iter().map(|l| l.message).collect::<Vec<_>>()
+        };
+
+        let mut cursor = LogCursor::default();
+        assert_eq!(cursor.request("abc", Some(1)).tail, Some(1));
+        assert_eq!(read(&mut cursor, &written), vec!["one", "two"]);
+        assert_eq!(read(&mut cursor, &written), Vec::<String>::new());
+
+        // Written in the same instant as the last line already read
+        written.push(line("2024-01-01T00:00:02Z", "three"));
+        written.push(line("2024-01-01T00:00:02Z", "four"));
+        assert_eq!(read(&mut cursor, &written), vec!["three", "four"]);
+        written.push(line("2024-01-01T00:00:03Z", "five"));
+        assert_eq!(read(&mut cursor, &written), vec!["five"]);
+
+        // A tail of nothing starts reading from when following began
+        let mut cursor = LogCursor::default();
+        let request = cursor.request("abc", Some(0));
+        assert!(cursor.advance(request.select(written.clone())).is_empty());
+        cursor.start_at("2024-01-01T00:00:03Z".parse().unwrap());
+        assert_eq!(read(&mut cursor, &written), vec!["five"]);
+        written.push(line("2024-01-01T00:00:04Z", "six"));
+        assert_eq!(read(&mut cursor, &written), vec!["six"]);
+    }
+}
@@ -3,14 +3,35 @@
 //! This module provides Docker Swarm compatibility for cluster
 //! management and service orchestration.
 
+pub mod allocator;
+pub mod ca;
 pub mod cluster;
 pub mod config;
+pub mod encryption;
+pub mod ingress;
+pub mod logs;
 pub mod node;
+pub mod orchestrator;
+pub mod raft;
+pub mod rpc;
+pub mod scheduler;
+pub mod secret;
 pub mod service;
+pub mod stack;
 pub mod task;
 
+pub use allocator::NetworkAllocator;
+pub use ca::{JoinToken, NodeCredentials, NodeIdentity, RootCa};
 pub use cluster::{SwarmCluster, SwarmConfig};
 pub use config::{Config, ConfigManager, ConfigSpec};
+pub use encryption::{DataKey, KeyStore};
+pub use ingress::{LoadBalancer, RoutingMesh};
+pub use logs::{FileLogSource, LogSource, ServiceLogLine};
 pub use node::{Node, NodeRole, NodeState};
+pub use orchestrator::Orchestrator;
+pub use raft::{ClusterStore, RaftNode, StoreAction};
+pub use scheduler::{Constraint, Scheduler};
+pub use secret::{Secret, SecretSpec};
 pub use service::{Service, ServiceSpec};
+pub use stack::{StackDeployment, StackSummary};
 pub use task::{Task, TaskState};
@@ -132,6 +132,41 @@ impl Node {
         }
     }
 
+    /// Create a node that joined the swarm from another host
+    pub fn joined(id: &str, role: NodeRole, description: NodeDescription, addr: &str) -> Self {
+        let now = Utc::now();
+
+        let manager_status = if role == NodeRole::Manager {
+            Some(ManagerStatus {
+                leader: false,
+                reachability: "reachable".to_string(),
+                addr: addr.to_string(),
+            })
+        } else {
+            None
+        };
+
+        Self {
+            id: id.to_string(),
+            hostname: description.hostname.clone(),
+            role,
+            state: NodeState::Ready,
+            availability: "active".to_string(),
+            addr: addr.to_string(),
+            labels: HashMap::new(),
+            description,
+            manager_status,
+            status: NodeStatus {
+                state: NodeState::Ready,
+                message: String::new(),
+                addr: addr.to_string(),
+            },
+            version: NodeVersion { index: 1 },
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
     /// Promote node to manager
     pub fn promote(&mut self) -> Result<()> {
         self.role = NodeRole::Manager;
@@ -192,6 +227,11 @@ impl Node {
     pub fn is_available(&self) -> bool {
         self.is_ready() && self.availability == "active"
     }
+
+    /// Check if node is being drained of its tasks
+    pub fn is_drained(&self) -> bool {
+        self.availability == "drain"
+    }
 }
 
 /// Node description
@@ -0,0 +1,305 @@
+//! Swarm service orchestrator
+//!
+//! The orchestrator compares a service's desired state with its tasks and
+//! decides which tasks to create and which to shut down. New tasks are left
+//! pending for the [`Scheduler`](super::scheduler::Scheduler) to place;
+//! tasks of global services are created for a specific node.
+
+use super::node::Node;
+use super::scheduler;
+use super::service::{Service, ServiceMode, TaskSpec};
+use super::task::{self, ContainerSpecRef, Task, TaskSpecRef, TaskState};
+use crate::error::Result;
+use std::collections::HashSet;
+
+/// Changes needed to bring a service to its desired state
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// New tasks waiting to be scheduled
+    pub create: Vec<Task>,
+    /// IDs of tasks to shut down
+    pub shutdown: Vec<String>,
+}
+
+/// Reconciles services with their tasks
+pub struct Orchestrator;
+
+impl Orchestrator {
+    /// Work out the task changes for a service
+    ///
+    /// For replicated services every slot up to the replica count gets one
+    /// live task; failed tasks are replaced and tasks in slots beyond the
+    /// replica count are shut down. Tasks on drained or removed nodes are
+    /// shut down and their slots filled again elsewhere.
+    pub fn reconcile(service: &Service, tasks: &[Task], nodes: &[Node]) -> Result<Reconciliation> {
+        let mut result = Reconciliation::default();
+
+        let (replicas, is_job, max_concurrent) = match service.spec.mode {
+            Some(ServiceMode::Global) => {
+                return Self::reconcile_global(service, tasks, nodes, false)
+            }
+            Some(ServiceMode::GlobalJob) => {
+                return Self::reconcile_global(service, tasks, nodes, true)
+            }
+            Some(ServiceMode::ReplicatedJob { max_concurrent, .. }) => {
+                (service.replicas(), true, max_concurrent)
+            }
+            _ => (service.replicas(), false, u64::MAX),
+        };
+
+        let mut live: Vec<&Task> = Vec::new();
+        for task in tasks.iter().filter(|t| {
+            t.service_id == service.id && !t.is_terminal() && t.desired_state == TaskState::Running
+        }) {
+            if is_evicted(task, nodes) {
+                result.shutdown.push(task.id.clone());
+            } else {
+                live.push(task);
+            }
+        }
+        let evicted = result.shutdown.len();
+
+        // Slots already filled, including completed job runs
+        let mut filled: HashSet<u64> = live.iter().filter_map(|t| t.slot).collect();
+        if is_job {
+            filled.extend(
+                tasks
+                    .iter()
+                    .filter(|t| t.service_id == service.id && t.status.state == TaskState::Complete)
+                    .filter_map(|t| t.slot),
+            );
+        }
+
+        for task in &live {
+            if task.slot.map(|s| s > replicas).unwrap_or(false) {
+                result.shutdown.push(task.id.clone());
+            }
+        }
+
+        let running = live.len() as u64 - (result.shutdown.len() - evicted) as u64;
+        let room = max_concurrent.saturating_sub(running);
+        for slot in (1..=replicas)
+            .filter(|s| !filled.contains(s))
+            .take(room as usize)
+        {
+            result.create.push(Self::new_task(service, Some(slot)));
+        }
+
+        Ok(result)
+    }
+
+    /// Work out the task changes for a global service
+    ///
+    /// Each available node that satisfies the placement gets one task. Tasks
+    /// on nodes that were drained, removed or no longer match are shut down;
+    /// paused nodes keep their tasks but get no new ones. A global job runs
+    /// once per node, so nodes with a completed task are left alone.
+    fn reconcile_global(
+        service: &Service,
+        tasks: &[Task],
+        nodes: &[Node],
+        is_job: bool,
+    ) -> Result<Reconciliation> {
+        let mut result = Reconciliation::default();
+
+        let mut eligible = HashSet::new();
+        for node in nodes {
+            if !node.is_drained() && scheduler::satisfies_placement(service, node)? {
+                eligible.insert(node.id.as_str());
+            }
+        }
+
+        let mut filled: HashSet<&str> = HashSet::new();
+        if is_job {
+            filled.extend(
+                tasks
+                    .iter()
+                    .filter(|t| t.service_id == service.id && t.status.state == TaskState::Complete)
+                    .filter_map(|t| t.node_id.as_deref()),
+            );
+        }
+
+        for task in tasks.iter().filter(|t| {
+            t.service_id == service.id && !t.is_terminal() && t.desired_state == TaskState::Running
+        }) {
+            match task.node_id.as_deref() {
+                // Keep one task per eligible node
+                Some(node_id) if eligible.contains(node_id) && filled.insert(node_id) => {}
+                _ => result.shutdown.push(task.id.clone()),
+            }
+        }
+
+        for node in nodes
+            .iter()
+            .filter(|n| n.is_available() && eligible.contains(n.id.as_str()))
+        {
+            if !filled.contains(node.id.as_str()) {
+                let mut task = Self::new_task(service, None);
+                task.node_id = Some(node.id.clone());
+                result.create.push(task);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Create a pending task for a service slot
+    fn new_task(service: &Service, slot: Option<u64>) -> Task {
+        let mut task = Task::new(&service.id, slot);
+        task.spec = task_spec(&service.spec.task_template);
+        task.status.state = TaskState::Pending;
+        task.status.message = "pending task scheduling".to_string();
+        task
+    }
+}
+
+/// Check whether a task has to leave the node it was assigned to
+fn is_evicted(task: &Task, nodes: &[Node]) -> bool {
+    match &task.node_id {
+        Some(node_id) => nodes
+            .iter()
+            .find(|n| &n.id == node_id)
+            .map(|n| n.is_drained())
+            .unwrap_or(true),
+        None => false,
+    }
+}
+
+/// Copy the parts of a service task template a task carries
+fn task_spec(template: &TaskSpec) -> TaskSpecRef {
+    TaskSpecRef {
+        container_spec: template.container_spec.as_ref().map(|c| ContainerSpecRef {
+            image: c.image.clone(),
+            labels: c.labels.clone(),
+            command: c.command.clone(),
+            args: c.args.clone(),
+            hostname: c.hostname.clone(),
+            env: c.env.clone(),
+            dir: c.dir.clone(),
+            user: c.user.clone(),
+        }),
+        resources: template
+            .resources
+            .as_ref()
+            .map(|r| task::ResourceRequirements {
+                limits: r.limits.as_ref().map(resource_spec),
+                reservations: r.reservations.as_ref().map(resource_spec),
+            }),
+        runtime: template.runtime.clone(),
+        force_update: template.force_update,
+        ..Default::default()
+    }
+}
+
+/// Convert a service resource spec to its task form
+fn resource_spec(spec: &super::service::ResourceSpec) -> task::ResourceSpec {
+    task::ResourceSpec {
+        nano_cpus: spec.nano_cpus,
+        memory_bytes: spec.memory_bytes,
+        pids: spec.pids,
+        generic_resources: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swarm::node::NodeRole;
+    use crate::swarm::service::ServiceSpec;
+
+    fn service(mode: ServiceMode) -> Service {
+        Service::new(ServiceSpec {
+            name: "web".to_string(),
+            mode: Some(mode),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_creates_missing_replicas() {
+        let service = service(ServiceMode::Replicated { replicas: 3 });
+        let result = Orchestrator::reconcile(&service, &[], &[]).unwrap();
+
+        let mut slots: Vec<u64> = result.create.iter().filter_map(|t| t.slot).collect();
+        slots.sort();
+        assert_eq!(slots, vec![1, 2, 3]);
+        assert!(result
+            .create
+            .iter()
+            .all(|t| t.status.state == TaskState::Pending));
+        assert!(result.shutdown.is_empty());
+    }
+
+    #[test]
+    fn test_scale_down_and_replace_failed() {
+        let mut service = service(ServiceMode::Replicated { replicas: 3 });
+        let mut tasks = Orchestrator::reconcile(&service, &[], &[]).unwrap().create;
+        tasks[0].fail("exited");
+
+        service.scale(2);
+        let result = Orchestrator::reconcile(&service, &tasks, &[]).unwrap();
+
+        assert_eq!(result.shutdown, vec![tasks[2].id.clone()]);
+        assert_eq!(result.create.len(), 1);
+        assert_eq!(result.create[0].slot, Some(1));
+    }
+
+    #[test]
+    fn test_drained_node_tasks_are_replaced() {
+        let service = service(ServiceMode::Replicated { replicas: 2 });
+        let mut nodes = vec![
+            Node::new_local(NodeRole::Worker),
+            Node::new_local(NodeRole::Worker),
+        ];
+        let mut tasks = Orchestrator::reconcile(&service, &[], &nodes)
+            .unwrap()
+            .create;
+        tasks[0].node_id = Some(nodes[0].id.clone());
+        tasks[1].node_id = Some(nodes[1].id.clone());
+
+        nodes[0].set_availability("drain");
+        let result = Orchestrator::reconcile(&service, &tasks, &nodes).unwrap();
+
+        assert_eq!(result.shutdown, vec![tasks[0].id.clone()]);
+        assert_eq!(result.create.len(), 1);
+        assert_eq!(result.create[0].slot, tasks[0].slot);
+        assert!(result.create[0].node_id.is_none());
+    }
+
+    #[test]
+    fn test_global_service_follows_nodes() {
+        let mut service = service(ServiceMode::Global);
+        service.spec.task_template.placement = Some(crate::swarm::service::Placement {
+            constraints: vec!["node.labels.tier==web".to_string()],
+            ..Default::default()
+        });
+
+        let mut nodes = vec![
+            Node::new_local(NodeRole::Worker),
+            Node::new_local(NodeRole::Worker),
+        ];
+        nodes[0].add_label("tier", "web");
+        nodes[1].add_label("tier", "db");
+
+        let mut tasks = Orchestrator::reconcile(&service, &[], &nodes)
+            .unwrap()
+            .create;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].node_id.as_ref(), Some(&nodes[0].id));
+
+        // A node starting to match gets a task
+        nodes[1].add_label("tier", "web");
+        let result = Orchestrator::reconcile(&service, &tasks, &nodes).unwrap();
+        assert!(result.shutdown.is_empty());
+        assert_eq!(result.create.len(), 1);
+        assert_eq!(result.create[0].node_id.as_ref(), Some(&nodes[1].id));
+        tasks.extend(result.create);
+
+        // Tasks go away when constraints stop matching or the node leaves
+        nodes[0].add_label("tier", "db");
+        nodes.pop();
+        let result = Orchestrator::reconcile(&service, &tasks, &nodes).unwrap();
+        assert!(result.create.is_empty());
+        assert_eq!(result.shutdown.len(), 2);
+    }
+}
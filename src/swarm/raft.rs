@@ -0,0 +1,1248 @@
+//! Raft consensus for swarm managers
+//!
+//! Manager nodes replicate the cluster state (nodes, services, tasks,
+//! secrets and networks) through a Raft log. Every change is proposed to the
+//! leader as a [`StoreAction`], replicated to a quorum of managers and then
+//! applied to each manager's [`ClusterStore`].
+//!
+//! The implementation is message driven: callers feed incoming messages to
+//! [`RaftNode::step`], advance logical time with [`RaftNode::tick`] and send
+//! whatever [`RaftNode::take_messages`] returns over the inter-node transport.
+
+use super::cluster::RaftConfig;
+use super::encryption::DataKey;
+use super::node::Node;
+use super::secret::Secret;
+use super::service::Service;
+use super::task::Task;
+use crate::error::{Result, RuneError};
+use crate::network::config::NetworkConfig;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Identifier of a manager taking part in consensus
+pub type RaftId = String;
+
+/// A change to the replicated cluster state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "Kind", content = "Object")]
+#[allow(clippy::large_enum_variant)]
+pub enum StoreAction {
+    PutNode(Node),
+    RemoveNode(String),
+    PutService(Service),
+    RemoveService(String),
+    PutTask(Task),
+    RemoveTask(String),
+    PutSecret(Secret),
+    RemoveSecret(String),
+    PutNetwork(NetworkConfig),
+    RemoveNetwork(String),
+}
+
+/// Replicated cluster state machine
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClusterStore {
+    /// Nodes by ID
+    pub nodes: HashMap<String, Node>,
+    /// Services by ID
+    pub services: HashMap<String, Service>,
+    /// Tasks by ID
+    pub tasks: HashMap<String, Task>,
+    /// Secrets by ID
+    pub secrets: HashMap<String, Secret>,
+    /// Networks by ID
+    pub networks: HashMap<String, NetworkConfig>,
+}
+
+impl ClusterStore {
+    /// Apply a committed action to the store
+    pub fn apply(&mut self, action: &StoreAction) {
+        match action {
+            StoreAction::PutNode(node) => {
+                self.nodes.insert(node.id.clone(), node.clone());
+            }
+            StoreAction::RemoveNode(id) => {
+                self.nodes.remove(id);
+            }
+            StoreAction::PutService(service) => {
+                self.services.insert(service.id.clone(), service.clone());
+            }
+            StoreAction::RemoveService(id) => {
+                self.services.remove(id);
+                self.tasks.retain(|_, t| &t.service_id != id);
+            }
+            StoreAction::PutTask(task) => {
+                self.tasks.insert(task.id.clone(), task.clone());
+            }
+            StoreAction::RemoveTask(id) => {
+                self.tasks.remove(id);
+            }
+            StoreAction::PutSecret(secret) => {
+                self.secrets.insert(secret.id.clone(), secret.clone());
+            }
+            StoreAction::RemoveSecret(id) => {
+                self.secrets.remove(id);
+            }
+            StoreAction::PutNetwork(network) => {
+                self.networks.insert(network.id.clone(), network.clone());
+            }
+            StoreAction::RemoveNetwork(id) => {
+                self.networks.remove(id);
+            }
+        }
+    }
+}
+
+/// Raft log entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Term in which the entry was proposed
+    pub term: u64,
+    /// Position in the log (1-based)
+    pub index: u64,
+    /// Action to apply, `None` for the no-op a new leader appends
+    pub action: Option<StoreAction>,
+}
+
+/// State that must survive restarts to keep Raft safe
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    /// Current term
+    pub term: u64,
+    /// Candidate voted for in the current term
+    pub voted_for: Option<RaftId>,
+    /// Highest known committed index
+    pub commit: u64,
+}
+
+/// Point-in-time copy of the applied state, used for log compaction
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Index of the last entry included in the snapshot
+    pub index: u64,
+    /// Term of the last entry included in the snapshot
+    pub term: u64,
+    /// Managers taking part in consensus
+    pub members: Vec<RaftId>,
+    /// Applied cluster state
+    pub store: ClusterStore,
+}
+
+/// Role a manager currently plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RaftRole {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Consensus messages exchanged between managers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftMessage {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    RequestVoteResponse {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    },
+    AppendEntriesResponse {
+        term: u64,
+        success: bool,
+        /// Highest replicated index on success, or a retry hint on failure
+        match_index: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        snapshot: Box<Snapshot>,
+    },
+}
+
+impl RaftMessage {
+    fn term(&self) -> u64 {
+        match self {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::RequestVoteResponse { term, .. }
+            | RaftMessage::AppendEntries { term, .. }
+            | RaftMessage::AppendEntriesResponse { term, .. }
+            | RaftMessage::InstallSnapshot { term, .. } => *term,
+        }
+    }
+}
+
+/// Addressed consensus message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Sender
+    pub from: RaftId,
+    /// Recipient
+    pub to: RaftId,
+    /// Payload
+    pub message: RaftMessage,
+}
+
+/// Durable storage for the Raft log and hard state
+pub trait RaftStorage: Send {
+    /// Persist the hard state
+    fn save_hard_state(&mut self, state: &HardState) -> Result<()>;
+    /// Persist the log entries following the latest snapshot
+    fn save_entries(&mut self, entries: &[LogEntry]) -> Result<()>;
+    /// Persist a snapshot
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()>;
+    /// Load everything persisted so far
+    fn load(&self) -> Result<PersistedState>;
+    /// Whether another process wrote the storage since this one last saved
+    /// or loaded it
+    fn written_elsewhere(&self) -> bool {
+        false
+    }
+}
+
+/// Raft state read back from storage
+#[derive(Debug, Clone, Default)]
+pub struct PersistedState {
+    pub hard_state: HardState,
+    pub entries: Vec<LogEntry>,
+    pub snapshot: Option<Snapshot>,
+}
+
+/// Files a [`FileStorage`] keeps its state in
+const STORAGE_FILES: [&str; 3] = ["state.json", "log.json", "snapshot.json"];
+
+/// Raft storage backed by JSON files in a directory
+pub struct FileStorage {
+    dir: PathBuf,
+    key: Option<DataKey>,
+    /// Modification times of the files as of the last save or load
+    seen: Cell<[Option<SystemTime>; 3]>,
+}
+
+impl FileStorage {
+    /// Open (creating if needed) a storage directory
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            key: None,
+            seen: Cell::new([None; 3]),
+        })
+    }
+
+    /// Open a storage directory whose files are encrypted with `key`
+    pub fn open_encrypted(dir: PathBuf, key: DataKey) -> Result<Self> {
+        let mut storage = Self::open(dir)?;
+        storage.key = Some(key);
+        Ok(storage)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        let data = match &self.key {
+            Some(key) => key.seal(data)?,
+            None => data.to_vec(),
+        };
+
+        // The file is replaced by renaming a temporary one, synced first so
+        // the rename can't land ahead of its contents; syncing the directory
+        // then makes the rename itself durable. Raft acts on what it saved
+        // as soon as this returns.
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(name))?;
+        fs::File::open(&self.dir)?.sync_all()?;
+        self.seen.set(self.modified());
+        Ok(())
+    }
+
+    fn modified(&self) -> [Option<SystemTime>; 3] {
+        STORAGE_FILES.map(|name| {
+            fs::metadata(self.dir.join(name))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(path)?;
+        match &self.key {
+            Some(key) => Ok(Some(key.open(&data)?)),
+            None => Ok(Some(data)),
+        }
+    }
+}
+
+impl RaftStorage for FileStorage {
+    fn save_hard_state(&mut self, state: &HardState) -> Result<()> {
+        self.write("state.json", &serde_json::to_vec(state)?)
+    }
+
+    fn save_entries(&mut self, entries: &[LogEntry]) -> Result<()> {
+        self.write("log.json", &serde_json::to_vec(entries)?)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.write("snapshot.json", &serde_json::to_vec(snapshot)?)
+    }
+
+    fn load(&self) -> Result<PersistedState> {
+        self.seen.set(self.modified());
+        let hard_state = match self.read("state.json")? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => HardState::default(),
+        };
+        let entries = match self.read("log.json")? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => Vec::new(),
+        };
+        let snapshot = match self.read("snapshot.json")? {
+            Some(data) => Some(serde_json::from_slice(&data)?),
+            None => None,
+        };
+        Ok(PersistedState {
+            hard_state,
+            entries,
+            snapshot,
+        })
+    }
+
+    fn written_elsewhere(&self) -> bool {
+        self.modified() != self.seen.get()
+    }
+}
+
+/// A manager's consensus state
+pub struct RaftNode {
+    id: RaftId,
+    peers: Vec<RaftId>,
+    config: RaftConfig,
+    hard_state: HardState,
+    /// Entries after the compaction boundary
+    log: Vec<LogEntry>,
+    /// Index of the last entry discarded by compaction
+    snapshot_index: u64,
+    /// Term of the last entry discarded by compaction
+    snapshot_term: u64,
+    store: ClusterStore,
+    last_applied: u64,
+    role: RaftRole,
+    leader: Option<RaftId>,
+    votes: HashSet<RaftId>,
+    next_index: HashMap<RaftId, u64>,
+    match_index: HashMap<RaftId, u64>,
+    election_elapsed: u32,
+    heartbeat_elapsed: u32,
+    randomized_election_timeout: u32,
+    outbox: Vec<Envelope>,
+    storage: Option<Box<dyn RaftStorage>>,
+}
+
+impl RaftNode {
+    /// Create a manager with an empty log
+    pub fn new(id: &str, peers: Vec<RaftId>, config: RaftConfig) -> Self {
+        let mut node = Self {
+            id: id.to_string(),
+            peers: peers.into_iter().filter(|p| p != id).collect(),
+            config,
+            hard_state: HardState::default(),
+            log: Vec::new(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            store: ClusterStore::default(),
+            last_applied: 0,
+            role: RaftRole::Follower,
+            leader: None,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_elapsed: 0,
+            heartbeat_elapsed: 0,
+            randomized_election_timeout: 0,
+            outbox: Vec::new(),
+            storage: None,
+        };
+        node.reset_election_timeout();
+        node
+    }
+
+    /// Create a manager that persists its state, restoring anything saved
+    pub fn with_storage(
+        id: &str,
+        peers: Vec<RaftId>,
+        config: RaftConfig,
+        storage: Box<dyn RaftStorage>,
+    ) -> Result<Self> {
+        let persisted = storage.load()?;
+        let mut node = Self::new(id, peers, config);
+        node.storage = Some(storage);
+        node.restore_persisted(persisted);
+        Ok(node)
+    }
+
+    /// Reload the persisted state if another process wrote it since
+    ///
+    /// The node keeps its peers and comes back as a follower, except that a
+    /// manager leading on its own keeps leading: the other process is the
+    /// same manager. Returns whether it was reloaded.
+    pub fn reload(&mut self) -> Result<bool> {
+        let persisted = match self.storage.as_ref() {
+            Some(storage) if storage.written_elsewhere() => storage.load()?,
+            _ => return Ok(false),
+        };
+        let leading = self.is_leader() && self.peers.is_empty();
+        let mut node = Self::new(&self.id, self.peers.clone(), self.config.clone());
+        node.storage = self.storage.take();
+        node.restore_persisted(persisted);
+        if leading {
+            node.role = RaftRole::Leader;
+            node.leader = Some(node.id.clone());
+        }
+        *self = node;
+        Ok(true)
+    }
+
+    fn restore_persisted(&mut self, persisted: PersistedState) {
+        if let Some(snapshot) = persisted.snapshot {
+            for member in snapshot.members {
+                if member != self.id && !self.peers.contains(&member) {
+                    self.peers.push(member);
+                }
+            }
+            self.store = snapshot.store;
+            self.last_applied = snapshot.index;
+            self.snapshot_index = snapshot.index;
+            self.snapshot_term = snapshot.term;
+        }
+        let snapshot_index = self.snapshot_index;
+        self.log = persisted
+            .entries
+            .into_iter()
+            .filter(|e| e.index > snapshot_index)
+            .collect();
+        self.hard_state = persisted.hard_state;
+        self.apply_committed();
+    }
+
+    /// Manager ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Current role
+    pub fn role(&self) -> RaftRole {
+        self.role
+    }
+
+    /// Whether this manager is the leader
+    pub fn is_leader(&self) -> bool {
+        self.role == RaftRole::Leader
+    }
+
+    /// Known leader, if any
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    /// Current term
+    pub fn term(&self) -> u64 {
+        self.hard_state.term
+    }
+
+    /// Highest committed log index
+    pub fn commit_index(&self) -> u64 {
+        self.hard_state.commit
+    }
+
+    /// Highest log index applied to the store
+    pub fn applied_index(&self) -> u64 {
+        self.last_applied
+    }
+
+    /// Index of the last log entry
+    pub fn last_index(&self) -> u64 {
+        self.log
+            .last()
+            .map(|e| e.index)
+            .unwrap_or(self.snapshot_index)
+    }
+
+    /// Applied cluster state
+    pub fn store(&self) -> &ClusterStore {
+        &self.store
+    }
+
+    /// Other managers taking part in consensus
+    pub fn peers(&self) -> &[RaftId] {
+        &self.peers
+    }
+
+    /// Number of managers needed to make progress
+    pub fn quorum(&self) -> usize {
+        let members = self.peers.len() + 1;
+        members / 2 + 1
+    }
+
+    /// Add a manager to the consensus group
+    ///
+    /// The leader sends a new manager the whole log straight away: it has
+    /// none yet, and learns from it where to reach the other managers.
+    pub fn add_peer(&mut self, peer: &str) {
+        if peer != self.id && !self.peers.iter().any(|p| p == peer) {
+            self.peers.push(peer.to_string());
+            if self.is_leader() {
+                self.next_index.insert(peer.to_string(), 1);
+                self.match_index.insert(peer.to_string(), 0);
+                self.send_append(peer);
+            }
+        }
+    }
+
+    /// Remove a manager from the consensus group
+    pub fn remove_peer(&mut self, peer: &str) {
+        self.peers.retain(|p| p != peer);
+        self.next_index.remove(peer);
+        self.match_index.remove(peer);
+        if self.is_leader() {
+            self.maybe_commit();
+        }
+    }
+
+    /// Drain messages that must be sent to other managers
+    pub fn take_messages(&mut self) -> Vec<Envelope> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Advance logical time by one tick
+    pub fn tick(&mut self) -> Result<()> {
+        if self.is_leader() {
+            self.heartbeat_elapsed += 1;
+            if self.heartbeat_elapsed >= self.config.heartbeat_tick {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_append();
+            }
+        } else {
+            self.election_elapsed += 1;
+            if self.election_elapsed >= self.randomized_election_timeout {
+                self.campaign()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start an election immediately
+    pub fn campaign(&mut self) -> Result<()> {
+        self.role = RaftRole::Candidate;
+        self.leader = None;
+        self.hard_state.term += 1;
+        self.hard_state.voted_for = Some(self.id.clone());
+        self.votes = HashSet::from([self.id.clone()]);
+        self.election_elapsed = 0;
+        self.reset_election_timeout();
+        self.persist_hard_state()?;
+
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+
+        let (last_log_index, last_log_term) = (self.last_index(), self.last_term());
+        for peer in self.peers.clone() {
+            self.send(
+                &peer,
+                RaftMessage::RequestVote {
+                    term: self.hard_state.term,
+                    last_log_index,
+                    last_log_term,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Propose a change to the cluster state
+    ///
+    /// Returns the log index the action was appended at. The action is
+    /// applied once a quorum of managers has stored it.
+    pub fn propose(&mut self, action: StoreAction) -> Result<u64> {
+        if !self.is_leader() {
+            return Err(RuneError::Swarm(match self.leader {
+                Some(ref leader) => format!("This node is not the leader; leader is {}", leader),
+                None => "No leader elected; the swarm does not have quorum".to_string(),
+            }));
+        }
+
+        let index = self.append_entry(Some(action))?;
+        self.maybe_commit();
+        self.broadcast_append();
+        Ok(index)
+    }
+
+    /// Handle a message from another manager
+    pub fn step(&mut self, envelope: Envelope) -> Result<()> {
+        let Envelope { from, message, .. } = envelope;
+
+        if message.term() > self.hard_state.term {
+            let leader = match message {
+                RaftMessage::AppendEntries { .. } | RaftMessage::InstallSnapshot { .. } => {
+                    Some(from.clone())
+                }
+                _ => None,
+            };
+            self.become_follower(message.term(), leader)?;
+        }
+
+        match message {
+            RaftMessage::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = last_log_term > self.last_term()
+                    || (last_log_term == self.last_term() && last_log_index >= self.last_index());
+                let can_vote = match self.hard_state.voted_for {
+                    Some(ref voted) => voted == &from,
+                    None => true,
+                };
+                let granted = term == self.hard_state.term && can_vote && up_to_date;
+                if granted {
+                    self.hard_state.voted_for = Some(from.clone());
+                    self.election_elapsed = 0;
+                    self.persist_hard_state()?;
+                }
+                self.send(
+                    &from,
+                    RaftMessage::RequestVoteResponse {
+                        term: self.hard_state.term,
+                        granted,
+                    },
+                );
+            }
+            RaftMessage::RequestVoteResponse { term, granted } => {
+                if self.role == RaftRole::Candidate && term == self.hard_state.term && granted {
+                    self.votes.insert(from);
+                    if self.votes.len() >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+            }
+            RaftMessage::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                if term < self.hard_state.term {
+                    self.send(
+                        &from,
+                        RaftMessage::AppendEntriesResponse {
+                            term: self.hard_state.term,
+                            success: false,
+                            match_index: 0,
+                        },
+                    );
+                    return Ok(());
+                }
+
+                self.role = RaftRole::Follower;
+                self.leader = Some(from.clone());
+                self.election_elapsed = 0;
+
+                let response =
+                    self.handle_append(prev_log_index, prev_log_term, entries, leader_commit)?;
+                self.send(&from, response);
+            }
+            RaftMessage::AppendEntriesResponse {
+                term,
+                success,
+                match_index,
+            } => {
+                if !self.is_leader() || term != self.hard_state.term {
+                    return Ok(());
+                }
+                if success {
+                    let current = self.match_index.get(&from).copied().unwrap_or(0);
+                    if match_index > current {
+                        self.match_index.insert(from.clone(), match_index);
+                    }
+                    self.next_index.insert(from.clone(), match_index + 1);
+                    self.maybe_commit();
+                    if match_index < self.last_index() {
+                        self.send_append(&from);
+                    }
+                } else {
+                    let next = self.next_index.get(&from).copied().unwrap_or(1);
+                    let retry = (match_index + 1).min(next.saturating_sub(1)).max(1);
+                    self.next_index.insert(from.clone(), retry);
+                    self.send_append(&from);
+                }
+            }
+            RaftMessage::InstallSnapshot { term, snapshot } => {
+                if term < self.hard_state.term {
+                    return Ok(());
+                }
+                self.role = RaftRole::Follower;
+                self.leader = Some(from.clone());
+                self.election_elapsed = 0;
+
+                let index = snapshot.index;
+                if index > self.hard_state.commit {
+                    self.restore_snapshot(*snapshot)?;
+                }
+                self.send(
+                    &from,
+                    RaftMessage::AppendEntriesResponse {
+                        term: self.hard_state.term,
+                        success: true,
+                        match_index: index,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take a snapshot of the applied state and compact the log
+    pub fn compact(&mut self) -> Result<()> {
+        if self.last_applied <= self.snapshot_index {
+            return Ok(());
+        }
+
+        let snapshot = self.current_snapshot();
+        if let Some(storage) = self.storage.as_mut() {
+            storage.save_snapshot(&snapshot)?;
+        }
+
+        // Keep a tail of entries so slow followers can catch up without a snapshot
+        let boundary = snapshot
+            .index
+            .saturating_sub(self.config.log_entries_for_slow_followers)
+            .max(self.snapshot_index);
+        self.snapshot_term = self.term_at(boundary).unwrap_or(self.snapshot_term);
+        self.snapshot_index = boundary;
+        self.log.retain(|e| e.index > boundary);
+        self.persist_entries()
+    }
+
+    /// Snapshot of the currently applied state
+    pub fn current_snapshot(&self) -> Snapshot {
+        let mut members = self.peers.clone();
+        members.push(self.id.clone());
+
+        Snapshot {
+            index: self.last_applied,
+            term: self.term_at(self.last_applied).unwrap_or(0),
+            members,
+            store: self.store.clone(),
+        }
+    }
+
+    fn handle_append(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    ) -> Result<RaftMessage> {
+        let reject = |hint: u64, term: u64| RaftMessage::AppendEntriesResponse {
+            term,
+            success: false,
+            match_index: hint,
+        };
+
+        if prev_log_index > self.last_index() {
+            return Ok(reject(self.last_index(), self.hard_state.term));
+        }
+
+        if prev_log_index >= self.snapshot_index
+            && self.term_at(prev_log_index) != Some(prev_log_term)
+        {
+            return Ok(reject(
+                prev_log_index.saturating_sub(1),
+                self.hard_state.term,
+            ));
+        }
+
+        let mut changed = false;
+        for entry in entries.iter() {
+            if entry.index <= self.snapshot_index {
+                continue;
+            }
+            match self.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    // Conflicting suffix: drop it and everything after
+                    self.log.retain(|e| e.index < entry.index);
+                    self.log.push(entry.clone());
+                    changed = true;
+                }
+                None => {
+                    self.log.push(entry.clone());
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.persist_entries()?;
+        }
+
+        let last_new = prev_log_index + entries.len() as u64;
+        if leader_commit > self.hard_state.commit {
+            self.hard_state.commit = leader_commit.min(last_new.max(self.hard_state.commit));
+            self.persist_hard_state()?;
+            self.apply_committed();
+        }
+
+        Ok(RaftMessage::AppendEntriesResponse {
+            term: self.hard_state.term,
+            success: true,
+            match_index: last_new,
+        })
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<RaftId>) -> Result<()> {
+        self.role = RaftRole::Follower;
+        self.leader = leader;
+        self.hard_state.term = term;
+        self.hard_state.voted_for = None;
+        self.votes.clear();
+        self.election_elapsed = 0;
+        self.reset_election_timeout();
+        self.persist_hard_state()
+    }
+
+    fn become_leader(&mut self) -> Result<()> {
+        self.role = RaftRole::Leader;
+        self.leader = Some(self.id.clone());
+        self.heartbeat_elapsed = 0;
+
+        let next = self.last_index() + 1;
+        self.next_index = self.peers.iter().map(|p| (p.clone(), next)).collect();
+        self.match_index = self.peers.iter().map(|p| (p.clone(), 0)).collect();
+
+        // A no-op entry lets the new leader commit entries from earlier terms
+        self.append_entry(None)?;
+        self.maybe_commit();
+        self.broadcast_append();
+        Ok(())
+    }
+
+    fn append_entry(&mut self, action: Option<StoreAction>) -> Result<u64> {
+        let index = self.last_index() + 1;
+        self.log.push(LogEntry {
+            term: self.hard_state.term,
+            index,
+            action,
+        });
+        self.persist_entries()?;
+        Ok(index)
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers.clone() {
+            self.send_append(&peer);
+        }
+    }
+
+    fn send_append(&mut self, peer: &str) {
+        let next = self
+            .next_index
+            .get(peer)
+            .copied()
+            .unwrap_or(self.last_index() + 1);
+
+        if next <= self.snapshot_index {
+            let snapshot = self.current_snapshot();
+            self.send(
+                peer,
+                RaftMessage::InstallSnapshot {
+                    term: self.hard_state.term,
+                    snapshot: Box::new(snapshot),
+                },
+            );
+            return;
+        }
+
+        let prev_log_index = next - 1;
+        let prev_log_term = self.term_at(prev_log_index).unwrap_or(0);
+        let entries = self
+            .log
+            .iter()
+            .filter(|e| e.index >= next)
+            .cloned()
+            .collect();
+
+        self.send(
+            peer,
+            RaftMessage::AppendEntries {
+                term: self.hard_state.term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit: self.hard_state.commit,
+            },
+        );
+    }
+
+    fn maybe_commit(&mut self) {
+        let mut indexes: Vec<u64> = self.match_index.values().copied().collect();
+        indexes.push(self.last_index());
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+
+        let candidate = indexes[self.quorum() - 1];
+        // Only entries from the current term are committed by counting replicas
+        if candidate > self.hard_state.commit
+            && self.term_at(candidate) == Some(self.hard_state.term)
+        {
+            self.hard_state.commit = candidate;
+            if let Err(e) = self.persist_hard_state() {
+                tracing::warn!("Failed to persist raft state: {}", e);
+            }
+            self.apply_committed();
+        }
+    }
+
+    fn apply_committed(&mut self) {
+        while self.last_applied < self.hard_state.commit {
+            let index = self.last_applied + 1;
+            let Some(entry) = self.log.iter().find(|e| e.index == index) else {
+                break;
+            };
+            if let Some(ref action) = entry.action {
+                self.store.apply(action);
+            }
+            self.last_applied = index;
+        }
+
+        if self.config.snapshot_interval > 0
+            && self.last_applied - self.snapshot_index >= self.config.snapshot_interval
+        {
+            if let Err(e) = self.compact() {
+                tracing::warn!("Failed to compact raft log: {}", e);
+            }
+        }
+    }
+
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.log.retain(|e| e.index > snapshot.index);
+        self.store = snapshot.store.clone();
+        self.last_applied = snapshot.index;
+        self.hard_state.commit = snapshot.index;
+        self.peers = snapshot
+            .members
+            .iter()
+            .filter(|m| **m != self.id)
+            .cloned()
+            .collect();
+        if let Some(storage) = self.storage.as_mut() {
+            storage.save_snapshot(&snapshot)?;
+        }
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        self.persist_hard_state()?;
+        self.persist_entries()
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log
+            .last()
+            .map(|e| e.term)
+            .unwrap_or(self.snapshot_term)
+    }
+
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == 0 {
+            return Some(0);
+        }
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.log.iter().find(|e| e.index == index).map(|e| e.term)
+    }
+
+    fn send(&mut self, to: &str, message: RaftMessage) {
+        self.outbox.push(Envelope {
+            from: self.id.clone(),
+            to: to.to_string(),
+            message,
+        });
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let base = self.config.election_tick.max(1);
+        self.randomized_election_timeout = base + rand::thread_rng().gen_range(0..base);
+    }
+
+    fn persist_hard_state(&mut self) -> Result<()> {
+        match self.storage.as_mut() {
+            Some(storage) => storage.save_hard_state(&self.hard_state),
+            None => Ok(()),
+        }
+    }
+
+    fn persist_entries(&mut self) -> Result<()> {
+        match self.storage.as_mut() {
+            Some(storage) => storage.save_entries(&self.log),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swarm::node::NodeRole;
+    use crate::swarm::service::ServiceSpec;
+    use tempfile::TempDir;
+
+    /// In-memory network of managers with optional partitions
+    struct TestCluster {
+        nodes: HashMap<RaftId, RaftNode>,
+        down: HashSet<RaftId>,
+    }
+
+    impl TestCluster {
+        fn new(size: usize) -> Self {
+            let ids: Vec<RaftId> = (1..=size).map(|i| format!("m{}", i)).collect();
+            let nodes = ids
+                .iter()
+                .map(|id| {
+                    (
+                        id.clone(),
+                        RaftNode::new(id, ids.clone(), RaftConfig::default()),
+                    )
+                })
+                .collect();
+            Self {
+                nodes,
+                down: HashSet::new(),
+            }
+        }
+
+        fn node(&mut self, id: &str) -> &mut RaftNode {
+            self.nodes.get_mut(id).unwrap()
+        }
+
+        fn deliver(&mut self) {
+            loop {
+                let mut messages = Vec::new();
+                for (id, node) in self.nodes.iter_mut() {
+                    let outgoing = node.take_messages();
+                    if !self.down.contains(id) {
+                        messages.extend(outgoing);
+                    }
+                }
+                if messages.is_empty() {
+                    break;
+                }
+                for envelope in messages {
+                    if self.down.contains(&envelope.to) {
+                        continue;
+                    }
+                    self.nodes
+                        .get_mut(&envelope.to)
+                        .unwrap()
+                        .step(envelope)
+                        .unwrap();
+                }
+            }
+        }
+
+        fn leader(&self) -> Option<RaftId> {
+            self.nodes
+                .values()
+                .filter(|n| n.is_leader() && !self.down.contains(n.id()))
+                .max_by_key(|n| n.term())
+                .map(|n| n.id().to_string())
+        }
+
+        fn run_until_leader(&mut self) -> RaftId {
+            for _ in 0..200 {
+                for (id, node) in self.nodes.iter_mut() {
+                    if !self.down.contains(id) {
+                        node.tick().unwrap();
+                    }
+                }
+                self.deliver();
+                if let Some(leader) = self.leader() {
+                    return leader;
+                }
+            }
+            panic!("no leader elected");
+        }
+    }
+
+    fn service(name: &str) -> StoreAction {
+        StoreAction::PutService(Service::new(ServiceSpec {
+            name: name.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_single_manager_commits_immediately() {
+        let mut node = RaftNode::new("m1", vec![], RaftConfig::default());
+        node.campaign().unwrap();
+        assert!(node.is_leader());
+
+        node.propose(service("web")).unwrap();
+        assert_eq!(node.store().services.len(), 1);
+    }
+
+    #[test]
+    fn test_replication_to_all_managers() {
+        let mut cluster = TestCluster::new(3);
+        let leader = cluster.run_until_leader();
+
+        cluster.node(&leader).propose(service("web")).unwrap();
+        cluster.deliver();
+        // A heartbeat carries the new commit index to followers
+        cluster.node(&leader).tick().unwrap();
+        cluster.deliver();
+
+        for node in cluster.nodes.values() {
+            assert_eq!(node.store().services.len(), 1, "node {}", node.id());
+        }
+    }
+
+    #[test]
+    fn test_follower_rejects_proposals() {
+        let mut cluster = TestCluster::new(3);
+        let leader = cluster.run_until_leader();
+        let follower = cluster
+            .nodes
+            .keys()
+            .find(|id| **id != leader)
+            .unwrap()
+            .clone();
+
+        assert!(cluster.node(&follower).propose(service("web")).is_err());
+    }
+
+    #[test]
+    fn test_leader_failover_keeps_committed_state() {
+        let mut cluster = TestCluster::new(3);
+        let leader = cluster.run_until_leader();
+
+        cluster
+            .node(&leader)
+            .propose(StoreAction::PutNode(Node::new_local(NodeRole::Manager)))
+            .unwrap();
+        cluster.deliver();
+
+        cluster.down.insert(leader.clone());
+        let new_leader = cluster.run_until_leader();
+        assert_ne!(leader, new_leader);
+        assert_eq!(cluster.node(&new_leader).store().nodes.len(), 1);
+
+        cluster.node(&new_leader).propose(service("web")).unwrap();
+        cluster.deliver();
+        assert_eq!(cluster.node(&new_leader).store().services.len(), 1);
+    }
+
+    #[test]
+    fn test_no_progress_without_quorum() {
+        let mut cluster = TestCluster::new(3);
+        let leader = cluster.run_until_leader();
+        for id in cluster.nodes.keys().cloned().collect::<Vec<_>>() {
+            if id != leader {
+                cluster.down.insert(id);
+            }
+        }
+
+        cluster.node(&leader).propose(service("web")).unwrap();
+        cluster.deliver();
+        assert!(cluster.node(&leader).store().services.is_empty());
+    }
+
+    #[test]
+    fn test_state_restored_from_storage() {
+        let temp = TempDir::new().unwrap();
+        let storage = FileStorage::open(temp.path().to_path_buf()).unwrap();
+        let mut node =
+            RaftNode::with_storage("m1", vec![], RaftConfig::default(), Box::new(storage)).unwrap();
+        node.campaign().unwrap();
+        node.propose(service("web")).unwrap();
+        let term = node.term();
+        drop(node);
+
+        let storage = FileStorage::open(temp.path().to_path_buf()).unwrap();
+        let node =
+            RaftNode::with_storage("m1", vec![], RaftConfig::default(), Box::new(storage)).unwrap();
+        assert_eq!(node.term(), term);
+        assert_eq!(node.store().services.len(), 1);
+    }
+
+    #[test]
+    fn test_reload_writes_of_another_process() {
+        let temp = TempDir::new().unwrap();
+        let open = || Box::new(FileStorage::open(temp.path().to_path_buf()).unwrap());
+        let mut first =
+            RaftNode::with_storage("m1", vec![], RaftConfig::default(), open()).unwrap();
+        first.campaign().unwrap();
+        first.propose(service("web")).unwrap();
+
+        let mut second =
+            RaftNode::with_storage("m1", vec![], RaftConfig::default(), open()).unwrap();
+        assert!(!second.reload().unwrap());
+        second.campaign().unwrap();
+        second.propose(service("db")).unwrap();
+
+        // Both stand for the same manager, which keeps leading
+        assert!(first.reload().unwrap());
+        assert!(first.is_leader());
+        assert_eq!(first.store().services.len(), 2);
+        first.propose(service("cache")).unwrap();
+        assert!(second.reload().unwrap());
+        assert_eq!(second.store().services.len(), 3);
+        assert!(!second.reload().unwrap());
+    }
+
+    #[test]
+    fn test_compaction_and_snapshot_install() {
+        let config = RaftConfig {
+            snapshot_interval: 2,
+            log_entries_for_slow_followers: 0,
+            ..RaftConfig::default()
+        };
+        let mut leader = RaftNode::new("m1", vec![], config.clone());
+        leader.campaign().unwrap();
+        for i in 0..5 {
+            leader.propose(service(&format!("svc{}", i))).unwrap();
+        }
+        assert!(leader.log.len() < 5);
+
+        let mut follower = RaftNode::new("m2", vec!["m1".to_string()], config);
+        leader.add_peer("m2");
+        for _ in 0..5 {
+            for envelope in leader.take_messages() {
+                follower.step(envelope).unwrap();
+            }
+            for envelope in follower.take_messages() {
+                leader.step(envelope).unwrap();
+            }
+        }
+        assert_eq!(follower.store().services.len(), 5);
+    }
+}
@@ -0,0 +1,412 @@
+//! Inter-node RPC over mutual TLS
+//!
+//! Nodes talk to each other over TLS connections authenticated with
+//! certificates issued by the swarm root CA. Requests and responses are
+//! length-prefixed JSON messages. The only request accepted without a client
+//! certificate is [`RpcRequest::Join`], which is authorized by its join token
+//! instead; a joining node authenticates the manager by pinning the root CA
+//! digest embedded in that token.
+
+use super::ca::{
+    generate_csr, JoinToken, NodeCredentials, NodeIdentity, RootCa, MANAGER_OU, WORKER_OU,
+};
+use super::logs::{LogLine, LogRequest};
+use super::node::{NodeDescription, NodeRole};
+use super::raft::Envelope;
+use crate::error::{Result, RuneError};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Largest message accepted on the wire
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Request to join the swarm
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    /// Join token
+    pub token: String,
+    /// Certificate signing request for the node key (PEM)
+    pub csr_pem: String,
+    /// Description of the joining node
+    pub description: NodeDescription,
+    /// Address other nodes should use to reach this node
+    pub advertise_addr: String,
+}
+
+/// Manager's answer to a join request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinResponse {
+    /// Cluster ID
+    pub cluster_id: String,
+    /// ID assigned to the joining node
+    pub node_id: String,
+    /// Role granted by the join token
+    pub role: NodeRole,
+    /// Node certificate (PEM)
+    pub cert_pem: String,
+    /// Root CA certificate (PEM)
+    pub root_ca_pem: String,
+    /// Root CA key (PEM), handed to managers only
+    pub root_ca_key_pem: Option<String>,
+}
+
+/// Inter-node request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "Method", content = "Params")]
+pub enum RpcRequest {
+    /// Join the swarm
+    Join(Box<JoinRequest>),
+    /// Raft message between managers
+    Raft(Envelope),
+    /// Node liveness report
+    Heartbeat {
+        /// Reporting node
+        node_id: String,
+    },
+    /// Read the logs of containers running on the node
+    Logs(Vec<LogRequest>),
+}
+
+/// Inter-node response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "Result", content = "Value")]
+pub enum RpcResponse {
+    /// Join accepted
+    Joined(JoinResponse),
+    /// Request handled
+    Ok,
+    /// Log lines, one list per requested container
+    Logs(Vec<Vec<LogLine>>),
+    /// Request failed
+    Error(String),
+}
+
+/// Handles requests received by an RPC server
+pub trait RpcHandler: Send + Sync {
+    /// Handle a request
+    ///
+    /// `peer` is the identity from the client certificate, or `None` when
+    /// the client did not present one.
+    fn handle(&self, peer: Option<&NodeIdentity>, request: RpcRequest) -> Result<RpcResponse>;
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn tls_error(e: impl std::fmt::Display) -> RuneError {
+    RuneError::Swarm(format!("TLS error: {}", e))
+}
+
+fn root_store(root_ca_pem: &str) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    let ca = RootCa::from_pem(root_ca_pem, None)?;
+    roots.add(ca.cert_der()?).map_err(tls_error)?;
+    Ok(Arc::new(roots))
+}
+
+/// Certificate chain (node certificate then root) and key for a node
+fn identity_chain(
+    credentials: &NodeCredentials,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert =
+        CertificateDer::from_pem_slice(credentials.cert_pem.as_bytes()).map_err(tls_error)?;
+    let root = RootCa::from_pem(&credentials.root_ca_pem, None)?.cert_der()?;
+    let key = PrivateKeyDer::from_pem_slice(credentials.key_pem.as_bytes()).map_err(tls_error)?;
+    Ok((vec![cert, root], key))
+}
+
+/// TLS configuration for a manager's RPC server
+///
+/// Client certificates are verified against the root CA when presented;
+/// clients without one may only join.
+pub fn server_config(credentials: &NodeCredentials) -> Result<Arc<ServerConfig>> {
+    let verifier = WebPkiClientVerifier::builder_with_provider(
+        root_store(&credentials.root_ca_pem)?,
+        provider(),
+    )
+    .allow_unauthenticated()
+    .build()
+    .map_err(tls_error)?;
+    let (chain, key) = identity_chain(credentials)?;
+
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, key)
+        .map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+/// TLS configuration for a node calling a manager
+pub fn client_config(credentials: &NodeCredentials) -> Result<Arc<ClientConfig>> {
+    let (chain, key) = identity_chain(credentials)?;
+
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_root_certificates(root_store(&credentials.root_ca_pem)?)
+        .with_client_auth_cert(chain, key)
+        .map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+/// TLS configuration for a node that has no certificate yet
+///
+/// The manager must present a chain whose root matches the CA digest in the
+/// join token.
+pub fn join_client_config(token: &JoinToken) -> Result<Arc<ClientConfig>> {
+    let verifier = PinnedRootVerifier {
+        ca_digest: token.ca_digest.clone(),
+        provider: provider(),
+    };
+
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Verifies a server chain against a root CA pinned by digest
+#[derive(Debug)]
+struct PinnedRootVerifier {
+    ca_digest: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedRootVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let root = intermediates
+            .iter()
+            .find(|cert| format!("{:x}", Sha256::digest(cert.as_ref())) == self.ca_digest)
+            .ok_or_else(|| {
+                rustls::Error::General("Root CA does not match the join token".to_string())
+            })?;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(root.clone().into_owned())?;
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), self.provider.clone())
+            .build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?
+            .verify_server_cert(end_entity, &[], server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+async fn read_message<T, S>(stream: &mut S) -> Result<Option<T>>
+where
+    T: for<'de> Deserialize<'de>,
+    S: AsyncRead + Unpin,
+{
+    let len = match stream.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_MESSAGE_SIZE {
+        return Err(RuneError::Swarm(format!(
+            "RPC message too large: {} bytes",
+            len
+        )));
+    }
+
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+async fn write_message<T, S>(stream: &mut S, message: &T) -> Result<()>
+where
+    T: Serialize,
+    S: AsyncWrite + Unpin,
+{
+    let buf = serde_json::to_vec(message)?;
+    stream.write_u32(buf.len() as u32).await?;
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Serve RPC requests until the listener fails
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    handler: Arc<dyn RpcHandler>,
+) -> Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let handler = handler.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(acceptor, stream, handler).await {
+                tracing::debug!("RPC connection from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    handler: Arc<dyn RpcHandler>,
+) -> Result<()> {
+    let mut stream = acceptor.accept(stream).await?;
+
+    let peer = match stream.get_ref().1.peer_certificates() {
+        Some([cert, ..]) => Some(NodeIdentity::from_cert_der(cert.as_ref())?),
+        _ => None,
+    };
+
+    while let Some(request) = read_message::<RpcRequest, _>(&mut stream).await? {
+        // Handlers may wait on a change being committed by other managers,
+        // whose messages are served here too
+        let handler = handler.clone();
+        let peer = peer.clone();
+        let response = tokio::task::spawn_blocking(move || handler.handle(peer.as_ref(), request))
+            .await
+            .map_err(|e| RuneError::Swarm(format!("RPC handler failed: {}", e)))?
+            .unwrap_or_else(|e| RpcResponse::Error(e.to_string()));
+        write_message(&mut stream, &response).await?;
+    }
+
+    Ok(())
+}
+
+/// Send a request to a manager and wait for the response
+pub async fn call(
+    addr: &str,
+    config: Arc<ClientConfig>,
+    request: &RpcRequest,
+) -> Result<RpcResponse> {
+    call_node(addr, NodeRole::Manager, config, request).await
+}
+
+/// Send a request to a node with the given role and wait for the response
+pub async fn call_node(
+    addr: &str,
+    role: NodeRole,
+    config: Arc<ClientConfig>,
+    request: &RpcRequest,
+) -> Result<RpcResponse> {
+    let stream = TcpStream::connect(addr).await?;
+    let server_name = match role {
+        NodeRole::Manager => MANAGER_OU,
+        NodeRole::Worker => WORKER_OU,
+    };
+    let server_name = ServerName::try_from(server_name).map_err(tls_error)?;
+    let mut stream = TlsConnector::from(config)
+        .connect(server_name, stream)
+        .await?;
+
+    write_message(&mut stream, request).await?;
+    match read_message(&mut stream).await? {
+        Some(RpcResponse::Error(message)) => Err(RuneError::Swarm(message)),
+        Some(response) => Ok(response),
+        None => Err(RuneError::Swarm(
+            "Connection closed before a response was received".to_string(),
+        )),
+    }
+}
+
+/// Join the swarm through a manager
+///
+/// Generates a fresh node key, has the manager sign it and checks the
+/// returned root CA against the token.
+pub async fn request_join(
+    addr: &str,
+    token: &str,
+    description: NodeDescription,
+    advertise_addr: &str,
+) -> Result<(JoinResponse, NodeCredentials)> {
+    let parsed = JoinToken::parse(token)?;
+    let (key_pem, csr_pem) = generate_csr()?;
+
+    let request = RpcRequest::Join(Box::new(JoinRequest {
+        token: token.to_string(),
+        csr_pem,
+        description,
+        advertise_addr: advertise_addr.to_string(),
+    }));
+    let response = match call(addr, join_client_config(&parsed)?, &request).await? {
+        RpcResponse::Joined(response) => response,
+        other => {
+            return Err(RuneError::Swarm(format!(
+                "Unexpected response to join request: {:?}",
+                other
+            )))
+        }
+    };
+
+    parsed.verify_root_ca(&RootCa::from_pem(&response.root_ca_pem, None)?)?;
+    let credentials = NodeCredentials {
+        cert_pem: response.cert_pem.clone(),
+        key_pem,
+        root_ca_pem: response.root_ca_pem.clone(),
+    };
+    let identity = credentials.identity()?;
+    if identity.node_id != response.node_id || identity.role != parsed.role {
+        return Err(RuneError::Swarm(
+            "Issued certificate does not match the join request".to_string(),
+        ));
+    }
+
+    Ok((response, credentials))
+}
@@ -0,0 +1,541 @@
+//! Swarm task scheduler
+//!
+//! The scheduler places pending tasks on nodes. A node is eligible when it is
+//! ready and active, satisfies every placement constraint and platform, has
+//! room for the task's resource reservations and is below the service's
+//! per-node replica limit. Among eligible nodes, placement preferences spread
+//! tasks evenly over label values, then the least loaded node wins.
+
+use super::node::Node;
+use super::service::Service;
+use super::task::{Task, TaskState};
+use crate::error::{Result, RuneError};
+use std::collections::{BTreeMap, HashMap};
+
+/// Constraint comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    NotEq,
+}
+
+/// Placement constraint such as `node.labels.region==east`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    /// Node attribute the constraint applies to
+    pub key: String,
+    /// Comparison operator
+    pub op: ConstraintOp,
+    /// Expected value
+    pub value: String,
+}
+
+impl Constraint {
+    /// Parse a constraint expression
+    pub fn parse(expr: &str) -> Result<Self> {
+        let (key, op, value) = if let Some((key, value)) = expr.split_once("!=") {
+            (key, ConstraintOp::NotEq, value)
+        } else if let Some((key, value)) = expr.split_once("==") {
+            (key, ConstraintOp::Eq, value)
+        } else {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid constraint '{}': expected == or !=",
+                expr
+            )));
+        };
+
+        let key = key.trim();
+        let value = value.trim();
+        if key.is_empty() || value.is_empty() || !is_known_attribute(key) {
+            return Err(RuneError::InvalidConfig(format!(
+                "Invalid constraint '{}'",
+                expr
+            )));
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    /// Check whether a node satisfies the constraint
+    pub fn matches(&self, node: &Node) -> bool {
+        let equal = node_attribute(node, &self.key)
+            .map(|v| v.eq_ignore_ascii_case(&self.value))
+            .unwrap_or(false);
+
+        match self.op {
+            ConstraintOp::Eq => equal,
+            ConstraintOp::NotEq => !equal,
+        }
+    }
+}
+
+/// Check whether a constraint or spread key names a node attribute
+fn is_known_attribute(key: &str) -> bool {
+    let key = key.to_lowercase();
+    matches!(
+        key.as_str(),
+        "node.id" | "node.hostname" | "node.role" | "node.platform.os" | "node.platform.arch"
+    ) || (key.starts_with("node.labels.") && key.len() > "node.labels.".len())
+        || (key.starts_with("engine.labels.") && key.len() > "engine.labels.".len())
+}
+
+/// Look up a node attribute by constraint key
+fn node_attribute(node: &Node, key: &str) -> Option<String> {
+    // Label names are case sensitive, the attribute prefix is not
+    if let Some(label) = strip_prefix_ignore_case(key, "node.labels.") {
+        return node.labels.get(label).cloned();
+    }
+    if let Some(label) = strip_prefix_ignore_case(key, "engine.labels.") {
+        return node.description.engine.labels.get(label).cloned();
+    }
+
+    match key.to_lowercase().as_str() {
+        "node.id" => Some(node.id.clone()),
+        "node.hostname" => Some(node.description.hostname.clone()),
+        "node.role" => Some(
+            if node.is_manager() {
+                "manager"
+            } else {
+                "worker"
+            }
+            .to_string(),
+        ),
+        "node.platform.os" => Some(node.description.platform.os.clone()),
+        "node.platform.arch" => Some(node.description.platform.architecture.clone()),
+        _ => None,
+    }
+}
+
+/// Strip an ASCII prefix, ignoring case
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+/// Check whether a task holds resources on its node
+fn is_active(task: &Task) -> bool {
+    task.node_id.is_some()
+        && !matches!(task.status.state, TaskState::New | TaskState::Pending)
+        && !task.is_terminal()
+        && task.desired_state == TaskState::Running
+}
+
+/// Parse a service's placement constraints
+fn constraints(service: &Service) -> Result<Vec<Constraint>> {
+    service
+        .spec
+        .task_template
+        .placement
+        .as_ref()
+        .map(|p| p.constraints.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|c| Constraint::parse(c))
+        .collect()
+}
+
+/// Check whether a node runs one of a service's platforms
+fn platforms_match(service: &Service, node: &Node) -> bool {
+    service
+        .spec
+        .task_template
+        .placement
+        .as_ref()
+        .map(|p| p.platforms.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .all(|p| {
+            p.os.as_ref()
+                .map(|os| os.eq_ignore_ascii_case(&node.description.platform.os))
+                .unwrap_or(true)
+                && p.architecture
+                    .as_ref()
+                    .map(|a| a.eq_ignore_ascii_case(&node.description.platform.architecture))
+                    .unwrap_or(true)
+        })
+}
+
+/// Check whether a node satisfies a service's constraints and platforms
+pub fn satisfies_placement(service: &Service, node: &Node) -> Result<bool> {
+    Ok(constraints(service)?.iter().all(|c| c.matches(node)) && platforms_match(service, node))
+}
+
+/// Scheduling state of a single node
+struct NodeInfo {
+    node: Node,
+    reserved_cpus: i64,
+    reserved_memory: i64,
+    total_tasks: u64,
+    service_tasks: HashMap<String, u64>,
+}
+
+impl NodeInfo {
+    fn add_task(&mut self, task: &Task) {
+        if let Some(reservations) = task
+            .spec
+            .resources
+            .as_ref()
+            .and_then(|r| r.reservations.as_ref())
+        {
+            self.reserved_cpus += reservations.nano_cpus.unwrap_or(0);
+            self.reserved_memory += reservations.memory_bytes.unwrap_or(0);
+        }
+        self.total_tasks += 1;
+        *self
+            .service_tasks
+            .entry(task.service_id.clone())
+            .or_default() += 1;
+    }
+
+    fn service_tasks(&self, service_id: &str) -> u64 {
+        self.service_tasks.get(service_id).copied().unwrap_or(0)
+    }
+}
+
+/// Why a node was rejected for a task
+#[derive(Default)]
+struct Rejections {
+    unavailable: usize,
+    constraints: usize,
+    platform: usize,
+    resources: usize,
+    max_replicas: usize,
+}
+
+impl Rejections {
+    fn message(&self) -> String {
+        let reasons: Vec<String> = [
+            (self.unavailable, "node not available"),
+            (self.constraints, "scheduling constraints not satisfied"),
+            (self.platform, "unsupported platform"),
+            (self.resources, "insufficient resources"),
+            (self.max_replicas, "max replicas per node limit exceeded"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, reason)| {
+            format!(
+                "{} on {} node{}",
+                reason,
+                count,
+                if *count == 1 { "" } else { "s" }
+            )
+        })
+        .collect();
+
+        if reasons.is_empty() {
+            "no suitable node".to_string()
+        } else {
+            format!("no suitable node ({})", reasons.join("; "))
+        }
+    }
+}
+
+/// Places tasks on nodes
+pub struct Scheduler {
+    nodes: Vec<NodeInfo>,
+}
+
+impl Scheduler {
+    /// Create a scheduler over the given nodes and their current tasks
+    pub fn new(nodes: &[Node], tasks: &[Task]) -> Self {
+        let mut nodes: Vec<NodeInfo> = nodes
+            .iter()
+            .map(|node| NodeInfo {
+                node: node.clone(),
+                reserved_cpus: 0,
+                reserved_memory: 0,
+                total_tasks: 0,
+                service_tasks: HashMap::new(),
+            })
+            .collect();
+        // Keep placement deterministic regardless of map iteration order
+        nodes.sort_by(|a, b| a.node.id.cmp(&b.node.id));
+
+        for task in tasks.iter().filter(|t| is_active(t)) {
+            if let Some(info) = nodes
+                .iter_mut()
+                .find(|n| Some(&n.node.id) == task.node_id.as_ref())
+            {
+                info.add_task(task);
+            }
+        }
+
+        Self { nodes }
+    }
+
+    /// Assign a task to the best eligible node
+    ///
+    /// Tasks that already name a node, such as those of global services, are
+    /// only checked against that node. Returns `false` and leaves the task
+    /// pending, with the reason in its status message, when no node can run
+    /// it.
+    pub fn schedule(&mut self, service: &Service, task: &mut Task) -> Result<bool> {
+        let template = &service.spec.task_template;
+        let placement = template.placement.as_ref();
+
+        let constraints = constraints(service)?;
+
+        let spreads = placement
+            .map(|p| p.preferences.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| p.spread.as_ref())
+            .map(|s| {
+                if is_known_attribute(&s.spread_descriptor) {
+                    Ok(s.spread_descriptor.clone())
+                } else {
+                    Err(RuneError::InvalidConfig(format!(
+                        "Invalid placement preference '{}'",
+                        s.spread_descriptor
+                    )))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let reservations = template
+            .resources
+            .as_ref()
+            .and_then(|r| r.reservations.as_ref());
+        let cpus = reservations.and_then(|r| r.nano_cpus).unwrap_or(0);
+        let memory = reservations.and_then(|r| r.memory_bytes).unwrap_or(0);
+        let max_replicas = placement.and_then(|p| p.max_replicas).filter(|m| *m > 0);
+
+        let mut rejections = Rejections::default();
+        let mut eligible = Vec::new();
+        for (i, info) in self.nodes.iter().enumerate() {
+            let node = &info.node;
+            let resources = &node.description.resources;
+
+            if task.node_id.as_ref().is_some_and(|id| id != &node.id) {
+                continue;
+            } else if !node.is_available() {
+                rejections.unavailable += 1;
+            } else if !constraints.iter().all(|c| c.matches(node)) {
+                rejections.constraints += 1;
+            } else if !platforms_match(service, node) {
+                rejections.platform += 1;
+            } else if info.reserved_cpus + cpus > resources.nano_cpus
+                || info.reserved_memory + memory > resources.memory_bytes
+            {
+                rejections.resources += 1;
+            } else if max_replicas
+                .map(|max| info.service_tasks(&service.id) >= max)
+                .unwrap_or(false)
+            {
+                rejections.max_replicas += 1;
+            } else {
+                eligible.push(i);
+            }
+        }
+
+        let Some(index) = self.pick(&service.id, eligible, &spreads) else {
+            task.status.state = TaskState::Pending;
+            task.status.message = rejections.message();
+            return Ok(false);
+        };
+
+        let info = &mut self.nodes[index];
+        task.assign(&info.node.id);
+        task.status.message = "scheduler assigned task to node".to_string();
+        info.add_task(task);
+        Ok(true)
+    }
+
+    /// Pick a node, spreading over each preference in turn
+    fn pick(
+        &self,
+        service_id: &str,
+        mut candidates: Vec<usize>,
+        spreads: &[String],
+    ) -> Option<usize> {
+        for descriptor in spreads {
+            // Group candidates by the spread value; nodes without the
+            // attribute form their own group
+            let mut groups: BTreeMap<String, (u64, Vec<usize>)> = BTreeMap::new();
+            for &i in &candidates {
+                let info = &self.nodes[i];
+                let value = node_attribute(&info.node, descriptor).unwrap_or_default();
+                let group = groups.entry(value).or_default();
+                group.0 += info.service_tasks(service_id);
+                group.1.push(i);
+            }
+
+            match groups.into_values().min_by_key(|(count, _)| *count) {
+                Some((_, group)) => candidates = group,
+                None => return None,
+            }
+        }
+
+        candidates.into_iter().min_by_key(|&i| {
+            let info = &self.nodes[i];
+            (info.service_tasks(service_id), info.total_tasks)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swarm::node::NodeRole;
+    use crate::swarm::service::{
+        Placement, PlacementPreference, ResourceRequirements, ResourceSpec, ServiceSpec, SpreadOver,
+    };
+
+    fn node(id: &str, labels: &[(&str, &str)]) -> Node {
+        let mut node = Node::new_local(NodeRole::Worker);
+        node.id = id.to_string();
+        node.description.resources.nano_cpus = 4_000_000_000;
+        node.description.resources.memory_bytes = 8 << 30;
+        for (k, v) in labels {
+            node.add_label(k, v);
+        }
+        node
+    }
+
+    fn service(placement: Placement) -> Service {
+        let mut spec = ServiceSpec {
+            name: "web".to_string(),
+            ..Default::default()
+        };
+        spec.task_template.placement = Some(placement);
+        Service::new(spec)
+    }
+
+    fn schedule_all(scheduler: &mut Scheduler, service: &Service, count: u64) -> Vec<Task> {
+        (1..=count)
+            .map(|slot| {
+                let mut task = Task::new(&service.id, Some(slot));
+                scheduler.schedule(service, &mut task).unwrap();
+                task
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_constraint() {
+        let c = Constraint::parse("node.labels.region == east").unwrap();
+        assert_eq!(c.key, "node.labels.region");
+        assert_eq!(c.op, ConstraintOp::Eq);
+        assert_eq!(c.value, "east");
+
+        let c = Constraint::parse("node.role!=manager").unwrap();
+        assert_eq!(c.op, ConstraintOp::NotEq);
+
+        assert!(Constraint::parse("node.labels.region").is_err());
+        assert!(Constraint::parse("node.color==red").is_err());
+    }
+
+    #[test]
+    fn test_constraints_filter_nodes() {
+        let nodes = vec![
+            node("a", &[("region", "east")]),
+            node("b", &[("region", "west")]),
+        ];
+        let service = service(Placement {
+            constraints: vec!["node.labels.region==west".to_string()],
+            ..Default::default()
+        });
+
+        let mut scheduler = Scheduler::new(&nodes, &[]);
+        let tasks = schedule_all(&mut scheduler, &service, 3);
+        assert!(tasks.iter().all(|t| t.node_id.as_deref() == Some("b")));
+        assert!(tasks.iter().all(|t| t.status.state == TaskState::Assigned));
+    }
+
+    #[test]
+    fn test_drained_and_paused_nodes_are_skipped() {
+        let mut nodes = vec![node("a", &[]), node("b", &[]), node("c", &[])];
+        nodes[0].set_availability("drain");
+        nodes[1].set_availability("pause");
+
+        let service = service(Placement::default());
+        let mut scheduler = Scheduler::new(&nodes, &[]);
+        let tasks = schedule_all(&mut scheduler, &service, 2);
+        assert!(tasks.iter().all(|t| t.node_id.as_deref() == Some("c")));
+    }
+
+    #[test]
+    fn test_spread_over_labels() {
+        let nodes = vec![
+            node("a", &[("zone", "1")]),
+            node("b", &[("zone", "1")]),
+            node("c", &[("zone", "1")]),
+            node("d", &[("zone", "2")]),
+        ];
+        let service = service(Placement {
+            preferences: vec![PlacementPreference {
+                spread: Some(SpreadOver {
+                    spread_descriptor: "node.labels.zone".to_string(),
+                }),
+            }],
+            ..Default::default()
+        });
+
+        let mut scheduler = Scheduler::new(&nodes, &[]);
+        let tasks = schedule_all(&mut scheduler, &service, 4);
+        let on_d = tasks
+            .iter()
+            .filter(|t| t.node_id.as_deref() == Some("d"))
+            .count();
+        assert_eq!(on_d, 2);
+    }
+
+    #[test]
+    fn test_resource_reservations() {
+        let nodes = vec![node("a", &[]), node("b", &[])];
+        let mut service = service(Placement::default());
+        service.spec.task_template.resources = Some(ResourceRequirements {
+            limits: None,
+            reservations: Some(ResourceSpec {
+                nano_cpus: Some(3_000_000_000),
+                memory_bytes: None,
+                pids: None,
+                generic_resources: Vec::new(),
+            }),
+        });
+
+        let mut scheduler = Scheduler::new(&nodes, &[]);
+        let mut tasks = Vec::new();
+        for slot in 1..=3 {
+            let mut task = Task::new(&service.id, Some(slot));
+            task.spec.resources = Some(crate::swarm::task::ResourceRequirements {
+                limits: None,
+                reservations: Some(crate::swarm::task::ResourceSpec {
+                    nano_cpus: Some(3_000_000_000),
+                    ..Default::default()
+                }),
+            });
+            scheduler.schedule(&service, &mut task).unwrap();
+            tasks.push(task);
+        }
+
+        assert!(tasks[0].node_id.is_some());
+        assert!(tasks[1].node_id.is_some());
+        assert_ne!(tasks[0].node_id, tasks[1].node_id);
+        assert!(tasks[2].node_id.is_none());
+        assert_eq!(tasks[2].status.state, TaskState::Pending);
+        assert!(tasks[2].status.message.contains("insufficient resources"));
+    }
+
+    #[test]
+    fn test_max_replicas_per_node() {
+        let nodes = vec![node("a", &[]), node("b", &[])];
+        let service = service(Placement {
+            max_replicas: Some(1),
+            ..Default::default()
+        });
+
+        let mut scheduler = Scheduler::new(&nodes, &[]);
+        let tasks = schedule_all(&mut scheduler, &service, 3);
+        assert_eq!(tasks.iter().filter(|t| t.node_id.is_some()).count(), 2);
+        assert!(tasks[2].status.message.contains("max replicas"));
+    }
+}
@@ -0,0 +1,62 @@
+//! Docker Secret objects for Swarm
+//!
+//! Secrets hold sensitive data that is replicated through the manager state
+//! store and only delivered to tasks that reference them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Docker Secret specification
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecretSpec {
+    /// Secret name
+    pub name: String,
+    /// Labels
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Base64-encoded secret data (never returned by inspect)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+}
+
+/// Docker Secret object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Secret {
+    /// Secret ID
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// Secret version
+    pub version: SecretVersion,
+    /// Creation timestamp
+    pub created_at: DateTime<Utc>,
+    /// Last update timestamp
+    pub updated_at: DateTime<Utc>,
+    /// Secret specification
+    pub spec: SecretSpec,
+}
+
+impl Secret {
+    /// Create a new secret
+    pub fn new(spec: SecretSpec) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            version: SecretVersion { index: 1 },
+            created_at: now,
+            updated_at: now,
+            spec,
+        }
+    }
+}
+
+/// Secret version
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecretVersion {
+    /// Version index
+    pub index: u64,
+}
@@ -328,6 +328,73 @@ pub struct Mount {
     pub tmpfs_options: Option<TmpfsOptions>,
 }
 
+impl Mount {
+    /// Parse a `--mount` flag, comma-separated `key=value` fields such as
+    /// `type=bind,source=/srv,target=/data,readonly`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid =
+            |why: &str| RuneError::InvalidConfig(format!("Invalid mount '{}': {}", spec, why));
+
+        let mut mount = Mount {
+            target: String::new(),
+            source: None,
+            mount_type: "volume".to_string(),
+            read_only: None,
+            consistency: None,
+            bind_options: None,
+            volume_options: None,
+            tmpfs_options: None,
+        };
+        for field in spec.split(',') {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (field, None),
+            };
+            let flag = || match value {
+                None | Some("true") | Some("1") => Ok(true),
+                Some("false") | Some("0") => Ok(false),
+                Some(_) => Err(invalid(&format!("{} takes true or false", key))),
+            };
+            match key {
+                "type" => mount.mount_type = value.unwrap_or_default().to_string(),
+                "source" | "src" => mount.source = value.map(str::to_string),
+                "target" | "destination" | "dst" => {
+                    mount.target = value.unwrap_or_default().to_string()
+                }
+                "readonly" | "ro" => mount.read_only = Some(flag()?),
+                "consistency" => mount.consistency = value.map(str::to_string),
+                "bind-propagation" => {
+                    mount
+                        .bind_options
+                        .get_or_insert(BindOptions {
+                            propagation: None,
+                            non_recursive: None,
+                        })
+                        .propagation = value.map(str::to_string)
+                }
+                "volume-nocopy" => {
+                    mount
+                        .volume_options
+                        .get_or_insert_with(VolumeOptions::default)
+                        .no_copy = Some(flag()?)
+                }
+                _ => return Err(invalid(&format!("unknown field '{}'", key))),
+            }
+        }
+
+        if !matches!(mount.mount_type.as_str(), "bind" | "volume" | "tmpfs") {
+            return Err(invalid("type must be bind, volume or tmpfs"));
+        }
+        if !mount.target.starts_with('/') {
+            return Err(invalid("target must be an absolute path"));
+        }
+        if mount.mount_type == "bind" && mount.source.is_none() {
+            return Err(invalid("bind mounts need a source"));
+        }
+        Ok(mount)
+    }
+}
+
 /// Bind options
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BindOptions {
@@ -598,6 +665,28 @@ pub enum ServiceMode {
     GlobalJob,
 }
 
+impl ServiceMode {
+    /// Mode named `mode`, one of `replicated`, `global`, `replicated-job`
+    /// and `global-job`, running `replicas` tasks where that applies
+    pub fn parse(mode: &str, replicas: Option<u64>) -> Result<Self> {
+        match mode {
+            "replicated" => Ok(ServiceMode::Replicated {
+                replicas: replicas.unwrap_or(1),
+            }),
+            "global" => Ok(ServiceMode::Global),
+            "replicated-job" => Ok(ServiceMode::ReplicatedJob {
+                max_concurrent: 1,
+                total_completions: replicas.unwrap_or(1),
+            }),
+            "global-job" => Ok(ServiceMode::GlobalJob),
+            other => Err(RuneError::InvalidConfig(format!(
+                "Unknown deploy mode: {}",
+                other
+            ))),
+        }
+    }
+}
+
 /// Update configuration
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UpdateConfig {
@@ -653,6 +742,92 @@ pub struct PortConfig {
     pub publish_mode: Option<String>,
 }
 
+impl PortConfig {
+    /// Parse a `--publish` flag, either `[[ip:]published:]target[/protocol]`
+    /// with port ranges, or comma-separated `key=value` fields such as
+    /// `published=8080,target=80,protocol=udp,mode=host`
+    pub fn parse(spec: &str) -> Result<Vec<Self>> {
+        let invalid = || RuneError::InvalidConfig(format!("Invalid port specification: {}", spec));
+
+        if spec.contains('=') {
+            let mut port = PortConfig {
+                name: None,
+                protocol: Some("tcp".to_string()),
+                target_port: 0,
+                published_port: None,
+                publish_mode: Some("ingress".to_string()),
+            };
+            for field in spec.split(',') {
+                let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+                match key {
+                    "target" => port.target_port = value.parse().map_err(|_| invalid())?,
+                    "published" => {
+                        port.published_port = Some(value.parse().map_err(|_| invalid())?)
+                    }
+                    "protocol" => port.protocol = Some(value.to_string()),
+                    "mode" => port.publish_mode = Some(value.to_string()),
+                    _ => return Err(invalid()),
+                }
+            }
+            if port.target_port == 0 {
+                return Err(invalid());
+            }
+            return Ok(vec![port]);
+        }
+
+        let (ports, protocol) = match spec.rsplit_once('/') {
+            Some((ports, proto)) => (ports, proto.to_string()),
+            None => (spec, "tcp".to_string()),
+        };
+
+        let parts: Vec<&str> = ports.split(':').collect();
+        let (published, target) = match parts.as_slice() {
+            [target] => (None, *target),
+            [published, target] => (Some(*published), *target),
+            [_ip, published, target] => (Some(*published), *target),
+            _ => return Err(invalid()),
+        };
+
+        let targets = parse_port_range(target).ok_or_else(invalid)?;
+        let published = match published {
+            Some(p) if !p.is_empty() => Some(parse_port_range(p).ok_or_else(invalid)?),
+            _ => None,
+        };
+
+        if let Some(ref published) = published {
+            if published.len() != targets.len() {
+                return Err(invalid());
+            }
+        }
+
+        Ok(targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| PortConfig {
+                name: None,
+                protocol: Some(protocol.clone()),
+                target_port: *target,
+                published_port: published.as_ref().map(|p| p[i]),
+                publish_mode: Some("ingress".to_string()),
+            })
+            .collect())
+    }
+}
+
+fn parse_port_range(s: &str) -> Option<Vec<u16>> {
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let start: u16 = start.parse().ok()?;
+            let end: u16 = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            Some((start..=end).collect())
+        }
+        None => s.parse().ok().map(|p| vec![p]),
+    }
+}
+
 /// Endpoint
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Endpoint {
@@ -783,4 +958,25 @@ mod tests {
             "nginx:1.0"
         );
     }
+
+    #[test]
+    fn test_parse_publish_and_mount_flags() {
+        let ports = PortConfig::parse("published=5353,target=53,protocol=udp,mode=host").unwrap();
+        assert_eq!(ports[0].published_port, Some(5353));
+        assert_eq!(ports[0].target_port, 53);
+        assert_eq!(ports[0].protocol.as_deref(), Some("udp"));
+        assert_eq!(ports[0].publish_mode.as_deref(), Some("host"));
+        assert!(PortConfig::parse("published=8080").is_err());
+
+        let mount = Mount::parse("type=bind,src=/srv/www,dst=/www,readonly").unwrap();
+        assert_eq!(mount.mount_type, "bind");
+        assert_eq!(mount.source.as_deref(), Some("/srv/www"));
+        assert_eq!(mount.target, "/www");
+        assert_eq!(mount.read_only, Some(true));
+        let mount = Mount::parse("source=data,target=/data,volume-nocopy").unwrap();
+        assert_eq!(mount.mount_type, "volume");
+        assert_eq!(mount.volume_options.unwrap().no_copy, Some(true));
+        assert!(Mount::parse("type=bind,target=/www").is_err());
+        assert!(Mount::parse("target=/data,size=1g").is_err());
+    }
 }
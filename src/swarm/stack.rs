@@ -0,0 +1,957 @@
+//! Swarm stack deployment
+//!
+//! Converts compose files into swarm services and overlay networks that are
+//! grouped under a stack namespace, mirroring `docker stack deploy`.
+
+use super::service::{
+    ConfigFile, ConfigReference, ContainerSpec, DnsConfig, EndpointSpec, HealthConfig, Mount,
+    NetworkAttachmentConfig, Placement, PlacementPreference, PortConfig, ResourceRequirements,
+    ResourceSpec, RestartPolicy, SecretFile, SecretReference, ServiceMode, ServiceSpec, SpreadOver,
+    TaskSpec, Ulimit, UpdateConfig, VolumeOptions,
+};
+use crate::compose::config::{
+    CommandConfig, ComposeConfig, ConfigRef, EnvironmentConfig, ExternalConfig, HealthcheckTest,
+    LabelsConfig, NetworksConfig, PortConfig as ComposePortConfig,
+    ResourceSpec as ComposeResources, SecretRef, ServiceConfig, UlimitConfig,
+    UpdateConfig as ComposeUpdateConfig, VolumeMount,
+};
+use crate::error::{Result, RuneError};
+use crate::network::config::{NetworkConfig, NetworkDriver, NetworkScope};
+use std::collections::{BTreeSet, HashMap};
+
+/// Label used to associate services and networks with a stack
+pub const STACK_NAMESPACE_LABEL: &str = "com.docker.stack.namespace";
+
+/// Name of the implicit network services join when none are listed
+const DEFAULT_NETWORK: &str = "default";
+
+/// Resources to create or update for a stack
+#[derive(Debug, Clone)]
+pub struct StackDeployment {
+    /// Stack namespace
+    pub namespace: String,
+    /// Service specifications
+    pub services: Vec<ServiceSpec>,
+    /// Overlay networks owned by the stack
+    pub networks: Vec<NetworkConfig>,
+    /// External networks the stack expects to exist
+    pub external_networks: Vec<String>,
+}
+
+impl StackDeployment {
+    /// Convert a compose configuration into a stack deployment
+    pub fn from_compose(namespace: &str, config: &ComposeConfig) -> Result<Self> {
+        validate_namespace(namespace)?;
+
+        let converter = Converter { namespace, config };
+
+        let mut service_names: Vec<&String> = config.services.keys().collect();
+        service_names.sort();
+
+        let mut services = Vec::new();
+        let mut used_networks = BTreeSet::new();
+        for name in service_names {
+            let service = &config.services[name];
+            used_networks.extend(service_network_names(service));
+            services.push(converter.service_spec(name, service)?);
+        }
+
+        let mut networks = Vec::new();
+        let mut external_networks = Vec::new();
+        for name in used_networks {
+            if converter.is_external_network(&name) {
+                external_networks.push(converter.network_name(&name));
+            } else {
+                networks.push(converter.network_config(&name)?);
+            }
+        }
+
+        Ok(Self {
+            namespace: namespace.to_string(),
+            services,
+            networks,
+            external_networks,
+        })
+    }
+}
+
+/// Result of deploying a stack onto the cluster
+#[derive(Debug, Clone, Default)]
+pub struct StackDeployReport {
+    /// Networks that were created
+    pub created_networks: Vec<String>,
+    /// Services that were created
+    pub created_services: Vec<String>,
+    /// Services whose specification was updated
+    pub updated_services: Vec<String>,
+    /// Services removed because they are no longer in the compose file
+    pub removed_services: Vec<String>,
+}
+
+/// Stack listing entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackSummary {
+    /// Stack namespace
+    pub name: String,
+    /// Number of services in the stack
+    pub services: usize,
+}
+
+/// Compose to swarm conversion context
+struct Converter<'a> {
+    namespace: &'a str,
+    config: &'a ComposeConfig,
+}
+
+impl Converter<'_> {
+    fn scoped(&self, name: &str) -> String {
+        format!("{}_{}", self.namespace, name)
+    }
+
+    fn namespace_labels(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        labels.insert(
+            STACK_NAMESPACE_LABEL.to_string(),
+            self.namespace.to_string(),
+        );
+        labels
+    }
+
+    fn is_external_network(&self, name: &str) -> bool {
+        self.config
+            .networks
+            .get(name)
+            .and_then(|n| n.external.as_ref())
+            .map(is_external)
+            .unwrap_or(false)
+    }
+
+    /// Resolve the cluster-wide name of a compose network
+    fn network_name(&self, name: &str) -> String {
+        match self.config.networks.get(name) {
+            Some(network) => match &network.external {
+                Some(ExternalConfig::Named { name: external }) => external.clone(),
+                Some(ExternalConfig::Bool(true)) => {
+                    network.name.clone().unwrap_or_else(|| name.to_string())
+                }
+                _ => network.name.clone().unwrap_or_else(|| self.scoped(name)),
+            },
+            None => self.scoped(name),
+        }
+    }
+
+    fn network_config(&self, name: &str) -> Result<NetworkConfig> {
+        let mut network = NetworkConfig::new(&self.network_name(name));
+        network.driver = NetworkDriver::Overlay;
+        network.scope = NetworkScope::Swarm;
+        network.labels = self.namespace_labels();
+
+        if let Some(compose) = self.config.networks.get(name) {
+            if let Some(ref driver) = compose.driver {
+                network.driver = parse_network_driver(driver)?;
+            }
+            if let Some(ref opts) = compose.driver_opts {
+                network.options = opts.clone();
+            }
+            if let Some(ref labels) = compose.labels {
+                network.labels.extend(labels_to_map(labels));
+            }
+            network.internal = compose.internal.unwrap_or(false);
+            network.attachable = compose.attachable.unwrap_or(false);
+            network.enable_ipv6 = compose.enable_ipv6.unwrap_or(false);
+
+            if let Some(pools) = compose.ipam.as_ref().and_then(|i| i.config.as_ref()) {
+                network.ipam.config.clear();
+                for pool in pools {
+                    if let Some(ref subnet) = pool.subnet {
+                        network = network.subnet(subnet);
+                        if let Some(ref gateway) = pool.gateway {
+                            network = network.gateway(gateway);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(network)
+    }
+
+    fn service_spec(&self, name: &str, service: &ServiceConfig) -> Result<ServiceSpec> {
+        let image = service.image.clone().ok_or_else(|| {
+            RuneError::Compose(format!(
+                "Service '{}' has no image; stack deploy does not build images",
+                name
+            ))
+        })?;
+
+        let deploy = service.deploy.clone().unwrap_or_default();
+
+        let mut labels = self.namespace_labels();
+        if let Some(ref deploy_labels) = deploy.labels {
+            labels.extend(labels_to_map(deploy_labels));
+        }
+
+        let mut container_labels = self.namespace_labels();
+        if let Some(ref service_labels) = service.labels {
+            container_labels.extend(labels_to_map(service_labels));
+        }
+
+        let container_spec = ContainerSpec {
+            image,
+            labels: container_labels,
+            command: service
+                .entrypoint
+                .as_ref()
+                .map(command_to_vec)
+                .unwrap_or_default(),
+            args: service
+                .command
+                .as_ref()
+                .map(command_to_vec)
+                .unwrap_or_default(),
+            hostname: service.hostname.clone(),
+            env: service
+                .environment
+                .as_ref()
+                .map(environment_to_vec)
+                .unwrap_or_default(),
+            dir: service.working_dir.clone(),
+            user: service.user.clone(),
+            tty: service.tty,
+            open_stdin: service.stdin_open,
+            read_only: service.read_only,
+            mounts: self.mounts(service)?,
+            stop_signal: service.stop_signal.clone(),
+            stop_grace_period: service
+                .stop_grace_period
+                .as_deref()
+                .map(parse_duration)
+                .transpose()?,
+            health_check: self.health_check(service)?,
+            hosts: service.extra_hosts.clone().unwrap_or_default(),
+            dns_config: dns_config(service),
+            secrets: self.secrets(service),
+            configs: self.configs(service),
+            init: service.init,
+            sysctls: service.sysctls.clone().unwrap_or_default(),
+            cap_add: service.cap_add.clone().unwrap_or_default(),
+            cap_drop: service.cap_drop.clone().unwrap_or_default(),
+            ulimits: ulimits(service),
+            ..Default::default()
+        };
+
+        let resources = match deploy.resources {
+            Some(ref r) => Some(ResourceRequirements {
+                limits: r.limits.as_ref().map(resource_spec).transpose()?,
+                reservations: r.reservations.as_ref().map(resource_spec).transpose()?,
+            }),
+            None => None,
+        };
+
+        let restart_policy = match deploy.restart_policy {
+            Some(ref p) => Some(RestartPolicy {
+                condition: Some(p.condition.clone().unwrap_or_else(|| "any".to_string())),
+                delay: p.delay.as_deref().map(parse_duration).transpose()?,
+                max_attempts: p.max_attempts.map(u64::from),
+                window: p.window.as_deref().map(parse_duration).transpose()?,
+            }),
+            None => None,
+        };
+
+        let placement = deploy.placement.as_ref().map(|p| Placement {
+            constraints: p.constraints.clone().unwrap_or_default(),
+            preferences: p
+                .preferences
+                .iter()
+                .flatten()
+                .filter_map(|pref| pref.spread.clone())
+                .map(|spread| PlacementPreference {
+                    spread: Some(SpreadOver {
+                        spread_descriptor: spread,
+                    }),
+                })
+                .collect(),
+            max_replicas: p.max_replicas_per_node.map(u64::from),
+            platforms: Vec::new(),
+        });
+
+        let networks = service_network_names(service)
+            .into_iter()
+            .map(|net| NetworkAttachmentConfig {
+                target: self.network_name(&net),
+                aliases: self.network_aliases(name, service, &net),
+                driver_opts: HashMap::new(),
+            })
+            .collect();
+
+        let ports = service
+            .ports
+            .iter()
+            .flatten()
+            .map(port_configs)
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(ServiceSpec {
+            name: self.scoped(name),
+            labels,
+            task_template: TaskSpec {
+                container_spec: Some(container_spec),
+                resources,
+                restart_policy,
+                placement,
+                ..Default::default()
+            },
+            mode: Some(service_mode(&deploy.mode, deploy.replicas)?),
+            update_config: deploy
+                .update_config
+                .as_ref()
+                .map(update_config)
+                .transpose()?,
+            rollback_config: deploy
+                .rollback_config
+                .as_ref()
+                .map(update_config)
+                .transpose()?,
+            networks,
+            endpoint_spec: Some(EndpointSpec {
+                mode: deploy.endpoint_mode.clone(),
+                ports,
+            }),
+        })
+    }
+
+    fn network_aliases(&self, name: &str, service: &ServiceConfig, network: &str) -> Vec<String> {
+        let mut aliases = vec![name.to_string()];
+        if let Some(NetworksConfig::Map(map)) = &service.networks {
+            if let Some(Some(config)) = map.get(network) {
+                aliases.extend(config.aliases.iter().flatten().cloned());
+            }
+        }
+        aliases
+    }
+
+    fn mounts(&self, service: &ServiceConfig) -> Result<Vec<Mount>> {
+        let mut mounts = Vec::new();
+
+        for volume in service.volumes.iter().flatten() {
+            let mount = match volume {
+                VolumeMount::Short(spec) => self.short_mount(spec)?,
+                VolumeMount::Long(long) => {
+                    let mount_type = long
+                        .mount_type
+                        .clone()
+                        .unwrap_or_else(|| "volume".to_string());
+                    let source = match (&long.source, mount_type.as_str()) {
+                        (Some(source), "volume") => Some(self.volume_name(source)),
+                        (source, _) => source.clone(),
+                    };
+                    Mount {
+                        target: long.target.clone(),
+                        source,
+                        mount_type,
+                        read_only: long.read_only,
+                        consistency: long.consistency.clone(),
+                        bind_options: None,
+                        volume_options: self.volume_options(long.source.as_deref()),
+                        tmpfs_options: None,
+                    }
+                }
+            };
+            mounts.push(mount);
+        }
+
+        Ok(mounts)
+    }
+
+    fn short_mount(&self, spec: &str) -> Result<Mount> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (source, target, mode) = match parts.as_slice() {
+            [target] => (None, *target, None),
+            [source, target] => (Some(*source), *target, None),
+            [source, target, mode] => (Some(*source), *target, Some(*mode)),
+            _ => {
+                return Err(RuneError::Compose(format!(
+                    "Invalid volume specification: {}",
+                    spec
+                )))
+            }
+        };
+
+        let read_only = mode.map(|m| m.split(',').any(|o| o == "ro"));
+
+        let mount = match source {
+            Some(source) if source.starts_with('/') || source.starts_with('.') => Mount {
+                target: target.to_string(),
+                source: Some(source.to_string()),
+                mount_type: "bind".to_string(),
+                read_only,
+                consistency: None,
+                bind_options: None,
+                volume_options: None,
+                tmpfs_options: None,
+            },
+            source => Mount {
+                target: target.to_string(),
+                source: source.map(|s| self.volume_name(s)),
+                mount_type: "volume".to_string(),
+                read_only,
+                consistency: None,
+                bind_options: None,
+                volume_options: self.volume_options(source),
+                tmpfs_options: None,
+            },
+        };
+
+        Ok(mount)
+    }
+
+    /// Resolve the cluster-wide name of a compose volume
+    fn volume_name(&self, name: &str) -> String {
+        match self.config.volumes.get(name) {
+            Some(volume) => match &volume.external {
+                Some(ExternalConfig::Named { name: external }) => external.clone(),
+                Some(ExternalConfig::Bool(true)) => {
+                    volume.name.clone().unwrap_or_else(|| name.to_string())
+                }
+                _ => volume.name.clone().unwrap_or_else(|| self.scoped(name)),
+            },
+            None => self.scoped(name),
+        }
+    }
+
+    fn volume_options(&self, source: Option<&str>) -> Option<VolumeOptions> {
+        let volume = self.config.volumes.get(source?)?;
+        if volume.external.as_ref().map(is_external).unwrap_or(false) {
+            return None;
+        }
+
+        let mut labels = self.namespace_labels();
+        if let Some(ref extra) = volume.labels {
+            labels.extend(labels_to_map(extra));
+        }
+
+        Some(VolumeOptions {
+            no_copy: None,
+            labels,
+            driver_config: volume
+                .driver
+                .as_ref()
+                .map(|driver| super::service::DriverConfig {
+                    name: Some(driver.clone()),
+                    options: volume.driver_opts.clone().unwrap_or_default(),
+                }),
+        })
+    }
+
+    fn health_check(&self, service: &ServiceConfig) -> Result<Option<HealthConfig>> {
+        let Some(ref hc) = service.healthcheck else {
+            return Ok(None);
+        };
+
+        if hc.disable.unwrap_or(false) {
+            return Ok(Some(HealthConfig {
+                test: vec!["NONE".to_string()],
+                interval: None,
+                timeout: None,
+                retries: None,
+                start_period: None,
+            }));
+        }
+
+        let test = match &hc.test {
+            Some(HealthcheckTest::Command(cmd)) => vec!["CMD-SHELL".to_string(), cmd.clone()],
+            Some(HealthcheckTest::Array(arr)) => arr.clone(),
+            None => Vec::new(),
+        };
+
+        Ok(Some(HealthConfig {
+            test,
+            interval: hc.interval.as_deref().map(parse_duration).transpose()?,
+            timeout: hc.timeout.as_deref().map(parse_duration).transpose()?,
+            retries: hc.retries.map(|r| r as i32),
+            start_period: hc.start_period.as_deref().map(parse_duration).transpose()?,
+        }))
+    }
+
+    fn secrets(&self, service: &ServiceConfig) -> Vec<SecretReference> {
+        service
+            .secrets
+            .iter()
+            .flatten()
+            .map(|secret| {
+                let (source, target, uid, gid, mode) = match secret {
+                    SecretRef::Short(source) => (source.clone(), None, None, None, None),
+                    SecretRef::Long(long) => (
+                        long.source.clone(),
+                        long.target.clone(),
+                        long.uid.clone(),
+                        long.gid.clone(),
+                        long.mode,
+                    ),
+                };
+                let secret_name = self
+                    .config
+                    .secrets
+                    .get(&source)
+                    .map(|s| external_or_scoped(self, &source, &s.external, &s.name))
+                    .unwrap_or_else(|| self.scoped(&source));
+                SecretReference {
+                    file: Some(SecretFile {
+                        name: target.unwrap_or_else(|| source.clone()),
+                        uid: Some(uid.unwrap_or_else(|| "0".to_string())),
+                        gid: Some(gid.unwrap_or_else(|| "0".to_string())),
+                        mode: Some(mode.unwrap_or(0o444)),
+                    }),
+                    secret_id: String::new(),
+                    secret_name,
+                }
+            })
+            .collect()
+    }
+
+    fn configs(&self, service: &ServiceConfig) -> Vec<ConfigReference> {
+        service
+            .configs
+            .iter()
+            .flatten()
+            .map(|config| {
+                let (source, target, uid, gid, mode) = match config {
+                    ConfigRef::Short(source) => (source.clone(), None, None, None, None),
+                    ConfigRef::Long(long) => (
+                        long.source.clone(),
+                        long.target.clone(),
+                        long.uid.clone(),
+                        long.gid.clone(),
+                        long.mode,
+                    ),
+                };
+                let config_name = self
+                    .config
+                    .configs
+                    .get(&source)
+                    .map(|c| external_or_scoped(self, &source, &c.external, &c.name))
+                    .unwrap_or_else(|| self.scoped(&source));
+                ConfigReference {
+                    file: Some(ConfigFile {
+                        name: target.unwrap_or_else(|| format!("/{}", source)),
+                        uid: Some(uid.unwrap_or_else(|| "0".to_string())),
+                        gid: Some(gid.unwrap_or_else(|| "0".to_string())),
+                        mode: Some(mode.unwrap_or(0o444)),
+                    }),
+                    runtime: None,
+                    config_id: String::new(),
+                    config_name,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Check that a stack namespace is a valid name prefix
+fn validate_namespace(namespace: &str) -> Result<()> {
+    let valid = !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && namespace
+            .chars()
+            .next()
+            .map(|c| c.is_ascii_alphanumeric())
+            .unwrap_or(false);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(RuneError::InvalidConfig(format!(
+            "Invalid stack name: {}",
+            namespace
+        )))
+    }
+}
+
+fn is_external(external: &ExternalConfig) -> bool {
+    match external {
+        ExternalConfig::Bool(b) => *b,
+        ExternalConfig::Named { .. } => true,
+    }
+}
+
+fn external_or_scoped(
+    converter: &Converter<'_>,
+    source: &str,
+    external: &Option<ExternalConfig>,
+    name: &Option<String>,
+) -> String {
+    match external {
+        Some(ExternalConfig::Named { name: external }) => external.clone(),
+        Some(ExternalConfig::Bool(true)) => name.clone().unwrap_or_else(|| source.to_string()),
+        _ => name.clone().unwrap_or_else(|| converter.scoped(source)),
+    }
+}
+
+/// Networks a service is attached to, in a stable order
+fn service_network_names(service: &ServiceConfig) -> Vec<String> {
+    if service.network_mode.is_some() {
+        return Vec::new();
+    }
+
+    let mut names: Vec<String> = match &service.networks {
+        Some(NetworksConfig::Array(arr)) => arr.clone(),
+        Some(NetworksConfig::Map(map)) => map.keys().cloned().collect(),
+        None => vec![DEFAULT_NETWORK.to_string()],
+    };
+    names.sort();
+    names
+}
+
+fn parse_network_driver(driver: &str) -> Result<NetworkDriver> {
+    match driver {
+        "overlay" => Ok(NetworkDriver::Overlay),
+        "bridge" => Ok(NetworkDriver::Bridge),
+        "host" => Ok(NetworkDriver::Host),
+        "none" => Ok(NetworkDriver::None),
+        "macvlan" => Ok(NetworkDriver::Macvlan),
+        "ipvlan" => Ok(NetworkDriver::Ipvlan),
+        other => Err(RuneError::InvalidConfig(format!(
+            "Unsupported network driver: {}",
+            other
+        ))),
+    }
+}
+
+fn service_mode(mode: &Option<String>, replicas: Option<u32>) -> Result<ServiceMode> {
+    ServiceMode::parse(
+        mode.as_deref().unwrap_or("replicated"),
+        replicas.map(u64::from),
+    )
+}
+
+fn update_config(config: &ComposeUpdateConfig) -> Result<UpdateConfig> {
+    Ok(UpdateConfig {
+        parallelism: config.parallelism.map(u64::from),
+        delay: config.delay.as_deref().map(parse_duration).transpose()?,
+        failure_action: config.failure_action.clone(),
+        monitor: config.monitor.as_deref().map(parse_duration).transpose()?,
+        max_failure_ratio: config.max_failure_ratio,
+        order: config.order.clone(),
+    })
+}
+
+fn resource_spec(spec: &ComposeResources) -> Result<ResourceSpec> {
+    let nano_cpus = match spec.cpus {
+        Some(ref cpus) => {
+            let value: f64 = cpus
+                .trim()
+                .parse()
+                .map_err(|_| RuneError::InvalidConfig(format!("Invalid cpus value: {}", cpus)))?;
+            Some((value * 1_000_000_000.0) as i64)
+        }
+        None => None,
+    };
+
+    Ok(ResourceSpec {
+        nano_cpus,
+        memory_bytes: spec.memory.as_deref().map(parse_bytes).transpose()?,
+        pids: spec.pids,
+        generic_resources: Vec::new(),
+    })
+}
+
+/// Command as an argument vector; the string form runs through `/bin/sh -c`
+/// so quoting and shell syntax keep their meaning, as compose does
+fn command_to_vec(command: &CommandConfig) -> Vec<String> {
+    match command {
+        CommandConfig::Shell(s) => vec!["/bin/sh".to_string(), "-c".to_string(), s.clone()],
+        CommandConfig::Exec(arr) => arr.clone(),
+    }
+}
+
+fn environment_to_vec(env: &EnvironmentConfig) -> Vec<String> {
+    let mut vars: Vec<String> = match env {
+        EnvironmentConfig::Array(arr) => arr.clone(),
+        EnvironmentConfig::Map(map) => map
+            .iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| format!("{}={}", k, v)))
+            .collect(),
+    };
+    vars.sort();
+    vars
+}
+
+fn labels_to_map(labels: &LabelsConfig) -> HashMap<String, String> {
+    match labels {
+        LabelsConfig::Map(map) => map.clone(),
+        LabelsConfig::Array(arr) => arr
+            .iter()
+            .map(|l| match l.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (l.clone(), String::new()),
+            })
+            .collect(),
+    }
+}
+
+fn dns_config(service: &ServiceConfig) -> Option<DnsConfig> {
+    if service.dns.is_none() && service.dns_search.is_none() {
+        return None;
+    }
+    Some(DnsConfig {
+        nameservers: service.dns.clone().unwrap_or_default(),
+        search: service.dns_search.clone().unwrap_or_default(),
+        options: Vec::new(),
+    })
+}
+
+fn ulimits(service: &ServiceConfig) -> Vec<Ulimit> {
+    let mut limits: Vec<Ulimit> = service
+        .ulimits
+        .iter()
+        .flatten()
+        .map(|(name, limit)| {
+            let (soft, hard) = match limit {
+                UlimitConfig::Single(v) => (*v, *v),
+                UlimitConfig::SoftHard { soft, hard } => (*soft, *hard),
+            };
+            Ulimit {
+                name: name.clone(),
+                soft,
+                hard,
+            }
+        })
+        .collect();
+    limits.sort_by(|a, b| a.name.cmp(&b.name));
+    limits
+}
+
+/// Convert a compose port entry into swarm port configs
+fn port_configs(port: &ComposePortConfig) -> Result<Vec<PortConfig>> {
+    match port {
+        ComposePortConfig::Long(long) => {
+            let published = match long.published {
+                Some(ref p) => Some(p.parse::<u16>().map_err(|_| {
+                    RuneError::InvalidConfig(format!("Invalid published port: {}", p))
+                })?),
+                None => None,
+            };
+            Ok(vec![PortConfig {
+                name: None,
+                protocol: Some(long.protocol.clone().unwrap_or_else(|| "tcp".to_string())),
+                target_port: long.target,
+                published_port: published,
+                publish_mode: Some(long.mode.clone().unwrap_or_else(|| "ingress".to_string())),
+            }])
+        }
+        ComposePortConfig::Short(spec) => PortConfig::parse(spec),
+    }
+}
+
+/// Parse a Go-style duration (e.g. `1m30s`, `500ms`) into nanoseconds
+pub fn parse_duration(s: &str) -> Result<i64> {
+    let invalid = || RuneError::InvalidConfig(format!("Invalid duration: {}", s));
+    let s = s.trim();
+
+    if s == "0" {
+        return Ok(0);
+    }
+
+    let mut total: f64 = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let num_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        if num_end == 0 {
+            return Err(invalid());
+        }
+        let value: f64 = rest[..num_end].parse().map_err(|_| invalid())?;
+        rest = &rest[num_end..];
+
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_end] {
+            "ns" => 1.0,
+            "us" | "µs" => 1_000.0,
+            "ms" => 1_000_000.0,
+            "s" => 1_000_000_000.0,
+            "m" => 60.0 * 1_000_000_000.0,
+            "h" => 3600.0 * 1_000_000_000.0,
+            _ => return Err(invalid()),
+        };
+        total += value * multiplier;
+        rest = &rest[unit_end..];
+    }
+
+    Ok(total as i64)
+}
+
+/// Parse a byte size such as `512M`, `1.5g` or `1024` into bytes
+pub fn parse_bytes(s: &str) -> Result<i64> {
+    let invalid = || RuneError::InvalidConfig(format!("Invalid byte size: {}", s));
+    let lower = s.trim().to_lowercase();
+    let lower = lower.trim_end_matches('b');
+
+    let (number, multiplier) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 1024_f64),
+        Some('m') => (&lower[..lower.len() - 1], 1024_f64.powi(2)),
+        Some('g') => (&lower[..lower.len() - 1], 1024_f64.powi(3)),
+        Some('t') => (&lower[..lower.len() - 1], 1024_f64.powi(4)),
+        Some(_) => (lower, 1.0),
+        None => return Err(invalid()),
+    };
+
+    let value: f64 = number.trim().parse().map_err(|_| invalid())?;
+    Ok((value * multiplier) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose::ComposeParser;
+
+    const STACK: &str = r#"
+version: "3.8"
+services:
+  web:
+    image: nginx:latest
+    command: nginx -g "daemon off;"
+    ports:
+      - "8080:80"
+    networks:
+      - frontend
+    deploy:
+      replicas: 3
+      update_config:
+        parallelism: 2
+        delay: 10s
+      resources:
+        limits:
+          cpus: "0.5"
+          memory: 512M
+      placement:
+        constraints:
+          - node.role == worker
+  agent:
+    image: agent:1.0
+    deploy:
+      mode: global
+networks:
+  frontend:
+    driver: overlay
+"#;
+
+    #[test]
+    fn test_convert_stack() {
+        let config = ComposeParser::parse_str(STACK).unwrap();
+        let deployment = StackDeployment::from_compose("app", &config).unwrap();
+
+        assert_eq!(deployment.services.len(), 2);
+        let web = deployment
+            .services
+            .iter()
+            .find(|s| s.name == "app_web")
+            .unwrap();
+        assert_eq!(
+            web.labels.get(STACK_NAMESPACE_LABEL),
+            Some(&"app".to_string())
+        );
+        assert!(matches!(
+            web.mode,
+            Some(ServiceMode::Replicated { replicas: 3 })
+        ));
+        let container = web.task_template.container_spec.as_ref().unwrap();
+        assert_eq!(
+            container.args,
+            ["/bin/sh", "-c", r#"nginx -g "daemon off;""#]
+        );
+
+        let update = web.update_config.as_ref().unwrap();
+        assert_eq!(update.parallelism, Some(2));
+        assert_eq!(update.delay, Some(10_000_000_000));
+
+        let limits = web
+            .task_template
+            .resources
+            .as_ref()
+            .and_then(|r| r.limits.as_ref())
+            .unwrap();
+        assert_eq!(limits.nano_cpus, Some(500_000_000));
+        assert_eq!(limits.memory_bytes, Some(512 * 1024 * 1024));
+
+        assert_eq!(web.networks[0].target, "app_frontend");
+        let ports = &web.endpoint_spec.as_ref().unwrap().ports;
+        assert_eq!(ports[0].published_port, Some(8080));
+        assert_eq!(ports[0].target_port, 80);
+
+        let agent = deployment
+            .services
+            .iter()
+            .find(|s| s.name == "app_agent")
+            .unwrap();
+        assert!(matches!(agent.mode, Some(ServiceMode::Global)));
+        assert_eq!(agent.networks[0].target, "app_default");
+
+        let mut networks: Vec<&str> = deployment
+            .networks
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect();
+        networks.sort();
+        assert_eq!(networks, vec!["app_default", "app_frontend"]);
+        assert!(deployment
+            .networks
+            .iter()
+            .all(|n| n.driver == NetworkDriver::Overlay));
+    }
+
+    #[test]
+    fn test_external_network_not_created() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    networks:
+      - proxy
+networks:
+  proxy:
+    external: true
+"#;
+        let config = ComposeParser::parse_str(yaml).unwrap();
+        let deployment = StackDeployment::from_compose("app", &config).unwrap();
+
+        assert!(deployment.networks.is_empty());
+        assert_eq!(deployment.external_networks, vec!["proxy".to_string()]);
+        assert_eq!(deployment.services[0].networks[0].target, "proxy");
+    }
+
+    #[test]
+    fn test_service_without_image_rejected() {
+        let yaml = r#"
+services:
+  web:
+    build: .
+"#;
+        let config = ComposeParser::parse_str(yaml).unwrap();
+        assert!(StackDeployment::from_compose("app", &config).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10s").unwrap(), 10_000_000_000);
+        assert_eq!(parse_duration("1m30s").unwrap(), 90_000_000_000);
+        assert_eq!(parse_duration("500ms").unwrap(), 500_000_000);
+        assert!(parse_duration("10").is_err());
+    }
+
+    #[test]
+    fn test_parse_short_port_range() {
+        let ports = PortConfig::parse("8000-8001:80-81/udp").unwrap();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[1].published_port, Some(8001));
+        assert_eq!(ports[1].target_port, 81);
+        assert_eq!(ports[1].protocol.as_deref(), Some("udp"));
+    }
+}
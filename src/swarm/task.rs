@@ -1,5 +1,6 @@
 //! Swarm task management
 
+use crate::container::HealthStatus;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -111,6 +112,7 @@ impl Task {
             container_id: container_id.to_string(),
             pid: None,
             exit_code: None,
+            health: None,
         });
         self.updated_at = Utc::now();
     }
@@ -322,6 +324,9 @@ pub struct ContainerStatus {
     pub pid: Option<i64>,
     /// Exit code
     pub exit_code: Option<i64>,
+    /// Health, for containers with a healthcheck
+    #[serde(default)]
+    pub health: Option<HealthStatus>,
 }
 
 /// Port status
@@ -1,10 +1,16 @@
 //! Rune TUI (Terminal User Interface)
 //!
 //! This module provides a terminal-based user interface for managing
-//! containers, images, networks, and volumes.
+//! containers, images, networks, and volumes on a daemon chosen from the
+//! saved contexts.
 
-use crate::container::{ContainerConfig, ContainerManager, ContainerStatus};
-use crate::error::Result;
+use super::config::{Action, KeyMap, Theme, TuiConfig};
+use super::connection::{Connection, ConnectionState, ContainerService};
+use super::filter::{ContainerFilter, SavedFilters};
+use super::stats::{format_bytes, StatsCollector, StatsHistory};
+use crate::container::{ContainerConfig, ContainerStatus};
+use crate::daemon::{Context, DaemonClient};
+use crate::error::{Result, RuneError};
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
     execute,
@@ -12,15 +18,31 @@ use crossterm::{
 };
 use ratatui::{
     prelude::*,
-    widgets::{Block, Borders, Clear, Paragraph, Row, Table, TableState, Tabs},
+    widgets::{
+        Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Row, Sparkline, Table,
+        TableState, Tabs, Wrap,
+    },
 };
+use std::collections::VecDeque;
 use std::io;
-use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How far back recorded stats are shown
+const RECORDED_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Time between fetches of the recorded stats shown
+const RECORDED_REFRESH: Duration = Duration::from_secs(10);
 
 /// TUI application state
 pub struct App {
-    /// Container manager
-    container_manager: Arc<ContainerManager>,
+    /// Connection to the container service, once one is chosen
+    connection: Option<Connection>,
+    /// Contexts offered by the connection picker
+    contexts: Vec<Context>,
+    /// Index of the connected context
+    current_context: Option<usize>,
+    /// Connection picker selection, while the picker is shown
+    picker: Option<ListState>,
     /// Current tab index
     current_tab: usize,
     /// Tab titles
@@ -41,13 +63,64 @@ pub struct App {
     status_message: Option<String>,
     /// Containers cache
     containers: Vec<ContainerConfig>,
+    /// Containers passing the filter, as shown in the table
+    visible: Vec<ContainerConfig>,
+    /// Filter query as typed
+    filter_query: String,
+    /// Parsed filter query
+    filter: ContainerFilter,
+    /// Typing into the filter bar
+    editing_filter: bool,
+    /// Saved filter queries, if they could be loaded
+    saved_filters: Option<SavedFilters>,
+    /// Show containers that are not running
+    show_stopped: bool,
+    /// Live container stats, sampled through the connection's service
+    stats: StatsCollector,
+    /// Show the stats panel below the container list
+    show_stats: bool,
+    /// Show stats of all containers instead of the selected one
+    stats_aggregate: bool,
+    /// Show the stats the daemon recorded instead of live ones
+    stats_recorded: bool,
+    /// Recorded stats of a container, and when they were fetched
+    recorded: Option<(String, Instant, StatsHistory)>,
+    /// Colors
+    theme: Theme,
+    /// Key bindings
+    keys: KeyMap,
+    /// Configuration problems, shown until a key is pressed
+    config_errors: Vec<String>,
 }
 
 impl App {
-    /// Create a new TUI application
-    pub fn new(container_manager: Arc<ContainerManager>) -> Self {
+    /// Create a TUI managing containers through a service
+    pub fn new(service: Box<dyn ContainerService>) -> Self {
+        Self::with_connection(Some(Connection::new(service)), Vec::new())
+    }
+
+    /// Create a TUI connected to the daemon of a context
+    ///
+    /// The other contexts can be switched to with the connection picker,
+    /// which opens straight away if the context cannot be used.
+    pub fn with_contexts(contexts: Vec<Context>, current: &str) -> Self {
+        let mut app = Self::with_connection(None, contexts);
+        match app.contexts.iter().position(|c| c.name == current) {
+            Some(index) => app.connect_context(index),
+            None => {
+                app.status_message = Some(format!("Error: No such context: {}", current));
+                app.open_picker();
+            }
+        }
+        app
+    }
+
+    fn with_connection(connection: Option<Connection>, contexts: Vec<Context>) -> Self {
         Self {
-            container_manager,
+            connection,
+            contexts,
+            current_context: None,
+            picker: None,
             current_tab: 0,
             tabs: vec!["Containers", "Images", "Networks", "Volumes", "Swarm"],
             container_state: TableState::default(),
@@ -58,9 +131,31 @@ impl App {
             show_help: false,
             status_message: None,
             containers: Vec::new(),
+            visible: Vec::new(),
+            filter_query: String::new(),
+            filter: ContainerFilter::default(),
+            editing_filter: false,
+            saved_filters: SavedFilters::load(SavedFilters::default_path()).ok(),
+            show_stopped: true,
+            stats: StatsCollector::new(),
+            show_stats: true,
+            stats_aggregate: false,
+            stats_recorded: false,
+            recorded: None,
+            theme: Theme::default(),
+            keys: KeyMap::default(),
+            config_errors: Vec::new(),
         }
     }
 
+    /// Use the colors and key bindings of a configuration
+    pub fn with_config(mut self, config: TuiConfig) -> Self {
+        self.theme = config.theme;
+        self.keys = config.keys;
+        self.config_errors = config.errors;
+        self
+    }
+
     /// Run the TUI application
     pub fn run(&mut self) -> Result<()> {
         // Setup terminal
@@ -111,40 +206,233 @@ impl App {
 
     /// Refresh data from managers
     fn refresh_data(&mut self) -> Result<()> {
-        self.containers = self.container_manager.list(true)?;
+        let Some(ref mut connection) = self.connection else {
+            return Ok(());
+        };
+        let first_poll = *connection.state() == ConnectionState::Connecting;
+
+        match connection.poll(Instant::now()) {
+            Some(containers) => {
+                self.containers = containers;
+                self.stats.poll(connection.service(), &self.containers);
+                self.apply_filter();
+                self.refresh_recorded();
+            }
+            // Offer the other contexts when a new connection fails at once
+            None if first_poll && self.contexts.len() > 1 => self.open_picker(),
+            None => {}
+        }
         Ok(())
     }
 
+    /// Connect to the daemon of a context
+    fn connect_context(&mut self, index: usize) {
+        let context = &self.contexts[index];
+        match DaemonClient::new(context) {
+            Ok(client) => {
+                self.status_message = Some(format!("Connecting to {}", context.name));
+                self.connection = Some(Connection::new(Box::new(client)));
+                self.current_context = Some(index);
+                self.containers.clear();
+                self.visible.clear();
+                self.container_state.select(None);
+                self.stats = StatsCollector::new();
+                self.recorded = None;
+                self.picker = None;
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Error: {}", e));
+                self.open_picker();
+            }
+        }
+    }
+
+    /// Recompute the visible containers, keeping the selection in range
+    fn apply_filter(&mut self) {
+        let show_stopped = self.show_stopped;
+        self.visible = self
+            .containers
+            .iter()
+            .filter(|c| {
+                show_stopped
+                    || matches!(c.status, ContainerStatus::Running | ContainerStatus::Paused)
+            })
+            .filter(|c| self.filter.matches(c))
+            .cloned()
+            .collect();
+
+        match self.container_state.selected() {
+            _ if self.visible.is_empty() => self.container_state.select(None),
+            Some(i) if i >= self.visible.len() => {
+                self.container_state.select(Some(self.visible.len() - 1))
+            }
+            _ => {}
+        }
+    }
+
+    /// Replace the filter query
+    fn set_filter(&mut self, query: String) {
+        self.filter = ContainerFilter::parse(&query);
+        self.filter_query = query;
+        self.container_state
+            .select((!self.containers.is_empty()).then_some(0));
+        self.apply_filter();
+    }
+
+    /// Handle a key press while typing into the filter bar
+    fn handle_filter_key(&mut self, key: KeyCode) {
+        let mut query = self.filter_query.clone();
+        match key {
+            KeyCode::Enter => self.editing_filter = false,
+            KeyCode::Esc => {
+                self.editing_filter = false;
+                query.clear();
+            }
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) => query.push(c),
+            _ => {}
+        }
+        if query != self.filter_query {
+            self.set_filter(query);
+        }
+    }
+
+    /// Apply the saved filter after the current one
+    fn handle_next_filter(&mut self) {
+        let next = self
+            .saved_filters
+            .as_ref()
+            .and_then(|saved| saved.next_after(&self.filter_query))
+            .map(String::from);
+        match next {
+            Some(query) => {
+                self.status_message = Some(format!("Filter: {}", query));
+                self.set_filter(query);
+            }
+            None => self.status_message = Some("No saved filters".to_string()),
+        }
+    }
+
+    /// Save the current filter
+    fn handle_save_filter(&mut self) {
+        let Some(ref mut saved) = self.saved_filters else {
+            self.status_message = Some("Saved filters are not available".to_string());
+            return;
+        };
+        self.status_message = Some(match saved.add(&self.filter_query) {
+            Ok(true) => format!("Saved filter: {}", self.filter_query.trim()),
+            Ok(false) if self.filter_query.trim().is_empty() => "No filter to save".to_string(),
+            Ok(false) => "Filter is already saved".to_string(),
+            Err(e) => format!("Error: {}", e),
+        });
+    }
+
+    /// Show the connection picker with the current context selected
+    fn open_picker(&mut self) {
+        if self.contexts.is_empty() {
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(self.current_context.unwrap_or(0)));
+        self.picker = Some(state);
+    }
+
+    /// Handle a key press in the connection picker
+    fn handle_picker_key(&mut self, key: KeyCode) {
+        let Some(ref mut picker) = self.picker else {
+            return;
+        };
+        let selected = picker.selected().unwrap_or(0);
+
+        if key == KeyCode::Esc && self.connection.is_some() {
+            self.picker = None;
+            return;
+        }
+        match self.keys.action(key) {
+            Some(Action::Quit) => self.should_quit = true,
+            Some(Action::Up) => picker.select(Some(selected.saturating_sub(1))),
+            Some(Action::Down) => picker.select(Some((selected + 1).min(self.contexts.len() - 1))),
+            Some(Action::Select) => self.connect_context(selected),
+            _ => {}
+        }
+    }
+
+    /// The service containers are managed through
+    fn service(&self) -> Result<&dyn ContainerService> {
+        self.connection
+            .as_ref()
+            .map(|c| c.service())
+            .ok_or_else(|| RuneError::Daemon("Not connected to a daemon".to_string()))
+    }
+
     /// Handle key press
     fn handle_key(&mut self, key: KeyCode) -> Result<()> {
         if self.show_help {
             self.show_help = false;
             return Ok(());
         }
+        if !self.config_errors.is_empty() {
+            self.config_errors.clear();
+            return Ok(());
+        }
+        if self.picker.is_some() {
+            self.handle_picker_key(key);
+            return Ok(());
+        }
+        if self.editing_filter {
+            self.handle_filter_key(key);
+            return Ok(());
+        }
 
-        match key {
-            KeyCode::Char('q') => self.should_quit = true,
-            KeyCode::Char('?') | KeyCode::F(1) => self.show_help = true,
-            KeyCode::Tab | KeyCode::Right => {
+        let Some(action) = self.keys.action(key) else {
+            return Ok(());
+        };
+        match action {
+            Action::Quit => self.should_quit = true,
+            Action::Help => self.show_help = true,
+            Action::NextTab => {
                 self.current_tab = (self.current_tab + 1) % self.tabs.len();
             }
-            KeyCode::BackTab | KeyCode::Left => {
+            Action::PreviousTab => {
                 if self.current_tab == 0 {
                     self.current_tab = self.tabs.len() - 1;
                 } else {
                     self.current_tab -= 1;
                 }
             }
-            KeyCode::Up | KeyCode::Char('k') => self.select_previous(),
-            KeyCode::Down | KeyCode::Char('j') => self.select_next(),
-            KeyCode::Enter => self.handle_enter()?,
-            KeyCode::Char('s') => self.handle_start()?,
-            KeyCode::Char('S') => self.handle_stop()?,
-            KeyCode::Char('r') => self.handle_restart()?,
-            KeyCode::Char('d') | KeyCode::Delete => self.handle_delete()?,
-            KeyCode::Char('p') => self.handle_pause()?,
-            KeyCode::Char('u') => self.handle_unpause()?,
-            _ => {}
+            Action::Up => self.select_previous(),
+            Action::Down => self.select_next(),
+            Action::Select => self.handle_enter()?,
+            Action::Start => self.handle_start()?,
+            Action::Stop => self.handle_stop()?,
+            Action::Restart => self.handle_restart()?,
+            Action::Delete => self.handle_delete()?,
+            Action::Pause => self.handle_pause()?,
+            Action::Unpause => self.handle_unpause()?,
+            Action::ToggleStats => self.show_stats = !self.show_stats,
+            Action::ToggleAggregate => self.stats_aggregate = !self.stats_aggregate,
+            Action::ToggleHistory => self.stats_recorded = !self.stats_recorded,
+            Action::SwitchContext => self.open_picker(),
+            Action::Filter => {
+                self.current_tab = 0;
+                self.editing_filter = true;
+            }
+            Action::NextFilter => self.handle_next_filter(),
+            Action::SaveFilter => self.handle_save_filter(),
+            Action::ToggleStopped => {
+                self.show_stopped = !self.show_stopped;
+                self.status_message = Some(
+                    if self.show_stopped {
+                        "Showing all containers"
+                    } else {
+                        "Showing running containers only"
+                    }
+                    .to_string(),
+                );
+                self.apply_filter();
+            }
         }
 
         Ok(())
@@ -176,7 +464,7 @@ impl App {
     /// Select next item
     fn select_next(&mut self) {
         let (state, len) = match self.current_tab {
-            0 => (&mut self.container_state, self.containers.len()),
+            0 => (&mut self.container_state, self.visible.len()),
             1 => (&mut self.image_state, 0), // TODO: Get image count
             2 => (&mut self.network_state, 0), // TODO: Get network count
             3 => (&mut self.volume_state, 0), // TODO: Get volume count
@@ -210,8 +498,8 @@ impl App {
     fn handle_start(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    match self.container_manager.start(&container.id) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.start(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Started container {}", container.name));
@@ -230,8 +518,8 @@ impl App {
     fn handle_stop(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    match self.container_manager.stop(&container.id) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.stop(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Stopped container {}", container.name));
@@ -250,9 +538,8 @@ impl App {
     fn handle_restart(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    let _ = self.container_manager.stop(&container.id);
-                    match self.container_manager.start(&container.id) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.restart(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Restarted container {}", container.name));
@@ -271,8 +558,8 @@ impl App {
     fn handle_delete(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    match self.container_manager.remove(&container.id, true) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.remove(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Removed container {}", container.name));
@@ -291,8 +578,8 @@ impl App {
     fn handle_pause(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    match self.container_manager.pause(&container.id) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.pause(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Paused container {}", container.name));
@@ -311,8 +598,8 @@ impl App {
     fn handle_unpause(&mut self) -> Result<()> {
         if self.current_tab == 0 {
             if let Some(i) = self.container_state.selected() {
-                if let Some(container) = self.containers.get(i) {
-                    match self.container_manager.unpause(&container.id) {
+                if let Some(container) = self.visible.get(i) {
+                    match self.service().and_then(|s| s.unpause(&container.id)) {
                         Ok(_) => {
                             self.status_message =
                                 Some(format!("Unpaused container {}", container.name));
@@ -358,27 +645,55 @@ impl App {
         // Status bar
         self.render_status_bar(f, chunks[3]);
 
+        // Connection picker
+        if self.picker.is_some() {
+            self.render_picker(f);
+        }
+
         // Help overlay
         if self.show_help {
             self.render_help(f);
         }
+
+        // Configuration problems
+        if !self.config_errors.is_empty() {
+            self.render_config_errors(f);
+        }
     }
 
     /// Render header
     fn render_header(&self, f: &mut Frame, area: Rect) {
-        let title = Paragraph::new(vec![Line::from(vec![
+        let mut spans = vec![
             Span::styled(
                 "🔮 Rune",
                 Style::default()
-                    .fg(Color::Cyan)
+                    .fg(self.theme.accent)
                     .add_modifier(Modifier::BOLD),
             ),
             Span::raw(" - Docker-compatible Container Service"),
-        ])])
-        .block(
+        ];
+        if let Some(ref connection) = self.connection {
+            spans.push(Span::raw(format!(" | {} ", connection.service().name())));
+            spans.push(match connection.state() {
+                ConnectionState::Connecting => {
+                    Span::styled("connecting", Style::default().fg(self.theme.warning))
+                }
+                ConnectionState::Connected => {
+                    Span::styled("connected", Style::default().fg(self.theme.success))
+                }
+                ConnectionState::Lost {
+                    error, attempts, ..
+                } => Span::styled(
+                    format!("disconnected, retry {}: {}", attempts, error),
+                    Style::default().fg(self.theme.error),
+                ),
+            });
+        }
+
+        let title = Paragraph::new(vec![Line::from(spans)]).block(
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Blue)),
+                .border_style(Style::default().fg(self.theme.border)),
         );
         f.render_widget(title, area);
     }
@@ -389,10 +704,10 @@ impl App {
         let tabs = Tabs::new(titles)
             .block(Block::default().borders(Borders::ALL).title("Navigation"))
             .select(self.current_tab)
-            .style(Style::default().fg(Color::White))
+            .style(Style::default().fg(self.theme.text))
             .highlight_style(
                 Style::default()
-                    .fg(Color::Yellow)
+                    .fg(self.theme.heading)
                     .add_modifier(Modifier::BOLD),
             );
         f.render_widget(tabs, area);
@@ -400,23 +715,45 @@ impl App {
 
     /// Render containers tab
     fn render_containers(&mut self, f: &mut Frame, area: Rect) {
+        let area = if self.show_stats {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(5), Constraint::Length(10)])
+                .split(area);
+            self.render_stats(f, chunks[1]);
+            chunks[0]
+        } else {
+            area
+        };
+
+        let area = if self.editing_filter || !self.filter_query.is_empty() {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(3), Constraint::Min(3)])
+                .split(area);
+            self.render_filter_bar(f, chunks[0]);
+            chunks[1]
+        } else {
+            area
+        };
+
         let header = Row::new(vec!["ID", "Name", "Image", "Status", "Created"])
             .style(
                 Style::default()
-                    .fg(Color::Yellow)
+                    .fg(self.theme.heading)
                     .add_modifier(Modifier::BOLD),
             )
             .bottom_margin(1);
 
         let rows: Vec<Row> = self
-            .containers
+            .visible
             .iter()
             .map(|c| {
                 let _status_color = match c.status {
-                    ContainerStatus::Running => Color::Green,
-                    ContainerStatus::Paused => Color::Yellow,
-                    ContainerStatus::Stopped | ContainerStatus::Exited => Color::Red,
-                    _ => Color::Gray,
+                    ContainerStatus::Running => self.theme.success,
+                    ContainerStatus::Paused => self.theme.warning,
+                    ContainerStatus::Stopped | ContainerStatus::Exited => self.theme.error,
+                    _ => self.theme.muted,
                 };
 
                 Row::new(vec![
@@ -426,11 +763,21 @@ impl App {
                     format!("{}", c.status),
                     c.created_at.format("%Y-%m-%d %H:%M").to_string(),
                 ])
-                .style(Style::default().fg(Color::White))
+                .style(Style::default().fg(self.theme.text))
                 .height(1)
             })
             .collect();
 
+        let title = if self.visible.len() == self.containers.len() {
+            "Containers".to_string()
+        } else {
+            format!(
+                "Containers ({} of {})",
+                self.visible.len(),
+                self.containers.len()
+            )
+        };
+
         let widths = [
             Constraint::Length(14),
             Constraint::Percentage(20),
@@ -441,20 +788,212 @@ impl App {
 
         let table = Table::new(rows, widths)
             .header(header)
-            .block(Block::default().borders(Borders::ALL).title("Containers"))
-            .row_highlight_style(Style::default().bg(Color::DarkGray))
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .row_highlight_style(Style::default().bg(self.theme.selection))
             .highlight_symbol("▶ ");
 
         f.render_stateful_widget(table, area, &mut self.container_state);
     }
 
+    /// Render the filter bar above the container list
+    fn render_filter_bar(&self, f: &mut Frame, area: Rect) {
+        let (title, style) = if self.editing_filter {
+            (
+                "Filter (Enter: apply, Esc: clear)",
+                Style::default().fg(self.theme.heading),
+            )
+        } else {
+            ("Filter", Style::default().fg(self.theme.border))
+        };
+        let cursor = if self.editing_filter { "█" } else { "" };
+
+        let bar = Paragraph::new(Line::from(vec![
+            Span::styled("/", Style::default().fg(self.theme.accent)),
+            Span::styled(
+                format!("{}{}", self.filter_query, cursor),
+                Style::default().fg(self.theme.text),
+            ),
+        ]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(style),
+        );
+        f.render_widget(bar, area);
+    }
+
+    /// Fetch the recorded stats of the selected container, if they are
+    /// shown and those fetched are for another container or getting old
+    fn refresh_recorded(&mut self) {
+        if !self.show_stats || !self.stats_recorded || self.stats_aggregate {
+            return;
+        }
+        let Some(id) = self
+            .container_state
+            .selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|container| container.id.clone())
+        else {
+            return;
+        };
+        if self
+            .recorded
+            .as_ref()
+            .is_some_and(|(recorded, fetched, _)| {
+                *recorded == id && fetched.elapsed() < RECORDED_REFRESH
+            })
+        {
+            return;
+        }
+        let Some(ref connection) = self.connection else {
+            return;
+        };
+        let since =
+            chrono::Utc::now() - chrono::Duration::from_std(RECORDED_WINDOW).unwrap_or_default();
+        let history = match connection.service().stats_history(&id, since) {
+            Ok(samples) => StatsHistory::from_samples(&samples),
+            Err(e) => {
+                self.status_message = Some(format!("Error: {}", e));
+                StatsHistory::default()
+            }
+        };
+        // Failures are retried when the samples would have been refetched
+        self.recorded = Some((id, Instant::now(), history));
+    }
+
+    /// Render the stats panel for the selected container or all of them
+    fn render_stats(&self, f: &mut Frame, area: Rect) {
+        let selected = self
+            .container_state
+            .selected()
+            .and_then(|i| self.visible.get(i));
+        let recorded = self
+            .recorded
+            .as_ref()
+            .filter(|(id, _, _)| selected.is_some_and(|container| container.id == *id))
+            .map(|(_, _, history)| history);
+        let (title, history) = match selected {
+            Some(container) if self.stats_recorded && !self.stats_aggregate => {
+                (format!("Recorded stats: {}", container.name), recorded)
+            }
+            _ if self.stats_aggregate => (
+                "Stats: all containers".to_string(),
+                Some(self.stats.aggregate()),
+            ),
+            Some(container) => (
+                format!("Stats: {}", container.name),
+                self.stats.history(&container.id),
+            ),
+            None => ("Stats".to_string(), None),
+        };
+        let block = Block::default().borders(Borders::ALL).title(format!(
+            "{} ({}: hide, {}: all/selected, {}: live/recorded)",
+            title,
+            self.keys.label(Action::ToggleStats),
+            self.keys.label(Action::ToggleAggregate),
+            self.keys.label(Action::ToggleHistory)
+        ));
+
+        let Some(history) = history.filter(|h| !h.cpu.is_empty()) else {
+            let message = match (self.stats.error(), selected) {
+                (_, Some(_)) if self.stats_recorded && !self.stats_aggregate => {
+                    "No stats recorded for this container in the last hour".to_string()
+                }
+                (Some(error), _) => format!("Container metrics are not available: {}", error),
+                (_, None) if !self.stats_aggregate => {
+                    "Select a container to see its stats".to_string()
+                }
+                _ => "Waiting for samples from a running container...".to_string(),
+            };
+            let text = Paragraph::new(message)
+                .block(block)
+                .style(Style::default().fg(self.theme.muted));
+            f.render_widget(text, area);
+            return;
+        };
+
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Percentage(34),
+                Constraint::Percentage(33),
+                Constraint::Percentage(33),
+            ])
+            .split(inner);
+        let latest = history.latest;
+
+        // CPU
+        render_sparkline(
+            f,
+            columns[0],
+            format!("CPU {:.1}%", latest.cpu_percent),
+            &history.cpu,
+            self.theme.success,
+        );
+
+        // Memory usage against the limit, if there is one
+        let memory = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(columns[1]);
+        match latest.memory_limit.filter(|limit| *limit > 0) {
+            Some(limit) => {
+                let gauge = Gauge::default()
+                    .gauge_style(Style::default().fg(self.theme.memory))
+                    .ratio((latest.memory_usage as f64 / limit as f64).min(1.0))
+                    .label(format!(
+                        "{} / {}",
+                        format_bytes(latest.memory_usage),
+                        format_bytes(limit)
+                    ));
+                f.render_widget(gauge, memory[0]);
+            }
+            None => {
+                let usage =
+                    Paragraph::new(format!("{} / no limit", format_bytes(latest.memory_usage)))
+                        .style(Style::default().fg(self.theme.memory));
+                f.render_widget(usage, memory[0]);
+            }
+        }
+        render_sparkline(
+            f,
+            memory[1],
+            "Memory".to_string(),
+            &history.memory,
+            self.theme.memory,
+        );
+
+        // Network
+        let network = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(columns[2]);
+        render_sparkline(
+            f,
+            network[0],
+            format!("Net RX {}/s", format_bytes(latest.rx_rate)),
+            &history.rx,
+            self.theme.accent,
+        );
+        render_sparkline(
+            f,
+            network[1],
+            format!("Net TX {}/s", format_bytes(latest.tx_rate)),
+            &history.tx,
+            self.theme.border,
+        );
+    }
+
     /// Render images tab
     fn render_images(&mut self, f: &mut Frame, area: Rect) {
         let block = Block::default().borders(Borders::ALL).title("Images");
 
         let text = Paragraph::new("No images found. Pull or build images to see them here.")
             .block(block)
-            .style(Style::default().fg(Color::Gray));
+            .style(Style::default().fg(self.theme.muted));
 
         f.render_widget(text, area);
     }
@@ -465,7 +1004,7 @@ impl App {
 
         let text = Paragraph::new("Default networks:\n  • bridge\n  • host\n  • none")
             .block(block)
-            .style(Style::default().fg(Color::White));
+            .style(Style::default().fg(self.theme.text));
 
         f.render_widget(text, area);
     }
@@ -476,7 +1015,7 @@ impl App {
 
         let text = Paragraph::new("No volumes found. Create volumes to see them here.")
             .block(block)
-            .style(Style::default().fg(Color::Gray));
+            .style(Style::default().fg(self.theme.muted));
 
         f.render_widget(text, area);
     }
@@ -488,7 +1027,7 @@ impl App {
         let text =
             Paragraph::new("Swarm mode is not active.\n\nInitialize swarm with: rune swarm init")
                 .block(block)
-                .style(Style::default().fg(Color::Gray));
+                .style(Style::default().fg(self.theme.muted));
 
         f.render_widget(text, area);
     }
@@ -498,99 +1037,179 @@ impl App {
         let status = if let Some(ref msg) = self.status_message {
             msg.clone()
         } else {
-            format!(
-                "Containers: {} | Tab/←→: Switch tabs | ↑↓/jk: Navigate | ?: Help | q: Quit",
-                self.containers.len()
-            )
+            let hint =
+                |action: Action, what: &str| format!("{}: {}", self.keys.label(action), what);
+            [
+                format!("Containers: {}", self.containers.len()),
+                hint(Action::NextTab, "Next tab"),
+                hint(Action::Filter, "Filter"),
+                hint(Action::ToggleStats, "Stats"),
+                hint(Action::SwitchContext, "Context"),
+                hint(Action::Help, "Help"),
+                hint(Action::Quit, "Quit"),
+            ]
+            .join(" | ")
         };
 
         let status_bar = Paragraph::new(status)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Blue)),
+                    .border_style(Style::default().fg(self.theme.border)),
             )
-            .style(Style::default().fg(Color::Cyan));
+            .style(Style::default().fg(self.theme.accent));
 
         f.render_widget(status_bar, area);
     }
 
+    /// Render the connection picker overlay
+    fn render_picker(&mut self, f: &mut Frame) {
+        let area = centered_rect(60, 50, f.area());
+        f.render_widget(Clear, area);
+
+        let items: Vec<ListItem> = self
+            .contexts
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let marker = if Some(i) == self.current_context {
+                    "* "
+                } else {
+                    "  "
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(marker),
+                    Span::styled(
+                        c.name.clone(),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(
+                        format!("  {}", c.endpoint),
+                        Style::default().fg(self.theme.accent),
+                    ),
+                    Span::styled(
+                        format!("  {}", c.description),
+                        Style::default().fg(self.theme.muted),
+                    ),
+                ]))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Connect to daemon (Enter: connect, Esc: close)")
+                    .border_style(Style::default().fg(self.theme.heading)),
+            )
+            .highlight_style(Style::default().bg(self.theme.selection))
+            .highlight_symbol("▶ ");
+
+        if let Some(ref mut state) = self.picker {
+            f.render_stateful_widget(list, area, state);
+        }
+    }
+
     /// Render help overlay
     fn render_help(&self, f: &mut Frame) {
         let area = centered_rect(60, 70, f.area());
 
         f.render_widget(Clear, area);
 
-        let help_text = vec![
+        let key_style = Style::default().fg(self.theme.accent);
+        let labels: Vec<(String, &str)> = Action::ALL
+            .into_iter()
+            .map(|action| (self.keys.label(action), action.description()))
+            .collect();
+        let width = labels
+            .iter()
+            .map(|(keys, _)| keys.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut help_text = vec![
             Line::from(Span::styled(
                 "Keyboard Shortcuts",
                 Style::default()
-                    .fg(Color::Yellow)
+                    .fg(self.theme.heading)
                     .add_modifier(Modifier::BOLD),
             )),
             Line::from(""),
+        ];
+        help_text.extend(labels.into_iter().map(|(keys, description)| {
             Line::from(vec![
-                Span::styled("Tab / ← →", Style::default().fg(Color::Cyan)),
-                Span::raw("  Switch tabs"),
-            ]),
-            Line::from(vec![
-                Span::styled("↑ ↓ / j k", Style::default().fg(Color::Cyan)),
-                Span::raw("  Navigate list"),
-            ]),
-            Line::from(vec![
-                Span::styled("Enter", Style::default().fg(Color::Cyan)),
-                Span::raw("      View details"),
-            ]),
-            Line::from(vec![
-                Span::styled("s", Style::default().fg(Color::Cyan)),
-                Span::raw("          Start container"),
-            ]),
-            Line::from(vec![
-                Span::styled("S", Style::default().fg(Color::Cyan)),
-                Span::raw("          Stop container"),
-            ]),
-            Line::from(vec![
-                Span::styled("r", Style::default().fg(Color::Cyan)),
-                Span::raw("          Restart container"),
-            ]),
-            Line::from(vec![
-                Span::styled("p", Style::default().fg(Color::Cyan)),
-                Span::raw("          Pause container"),
-            ]),
-            Line::from(vec![
-                Span::styled("u", Style::default().fg(Color::Cyan)),
-                Span::raw("          Unpause container"),
-            ]),
-            Line::from(vec![
-                Span::styled("d / Del", Style::default().fg(Color::Cyan)),
-                Span::raw("    Delete container"),
-            ]),
-            Line::from(vec![
-                Span::styled("? / F1", Style::default().fg(Color::Cyan)),
-                Span::raw("     Show this help"),
-            ]),
-            Line::from(vec![
-                Span::styled("q", Style::default().fg(Color::Cyan)),
-                Span::raw("          Quit"),
-            ]),
+                Span::styled(format!("{:<width$}", keys, width = width), key_style),
+                Span::raw(format!("  {}", description)),
+            ])
+        }));
+        help_text.extend([
             Line::from(""),
             Line::from(Span::styled(
                 "Press any key to close",
-                Style::default().fg(Color::Gray),
+                Style::default().fg(self.theme.muted),
             )),
-        ];
+        ]);
 
         let help = Paragraph::new(help_text)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Help")
-                    .border_style(Style::default().fg(Color::Yellow)),
+                    .border_style(Style::default().fg(self.theme.heading)),
             )
             .alignment(Alignment::Left);
 
         f.render_widget(help, area);
     }
+
+    /// Render the problems found in the configuration file
+    fn render_config_errors(&self, f: &mut Frame) {
+        let area = centered_rect(70, 50, f.area());
+        f.render_widget(Clear, area);
+
+        let mut lines: Vec<Line> = self
+            .config_errors
+            .iter()
+            .map(|e| Line::from(format!("• {}", e)))
+            .collect();
+        lines.extend([
+            Line::from(""),
+            Line::from(Span::styled(
+                "Defaults are used instead. Press any key to continue",
+                Style::default().fg(self.theme.muted),
+            )),
+        ]);
+
+        let errors = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Configuration errors in tui.toml")
+                    .border_style(Style::default().fg(self.theme.error)),
+            )
+            .style(Style::default().fg(self.theme.text))
+            .wrap(Wrap { trim: false });
+
+        f.render_widget(errors, area);
+    }
+}
+
+/// Render the most recent samples of a series that fit in an area
+fn render_sparkline(
+    f: &mut Frame,
+    area: Rect,
+    title: String,
+    series: &VecDeque<u64>,
+    color: Color,
+) {
+    let width = area.width as usize;
+    let skip = series.len().saturating_sub(width);
+    let data: Vec<u64> = series.iter().skip(skip).copied().collect();
+    let sparkline = Sparkline::default()
+        .block(Block::default().title(title))
+        .data(&data)
+        .style(Style::default().fg(color));
+    f.render_widget(sparkline, area);
 }
 
 /// Helper function to create a centered rect
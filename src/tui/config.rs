@@ -0,0 +1,633 @@
+//! TUI configuration
+//!
+//! Colors and key bindings are read from `tui.toml` in the Rune config
+//! directory:
+//!
+//! ```toml
+//! theme = "light"        # "dark" (default) or "light"
+//! color_mode = "256"     # "auto" (default), "truecolor" or "256"
+//!
+//! [colors]
+//! accent = "#005faf"     # names, "#rrggbb" or 256-color indexes
+//!
+//! [keys]
+//! quit = ["q", "Esc"]
+//! start = "F5"
+//! ```
+//!
+//! Invalid entries are skipped and reported, so a typo never keeps the TUI
+//! from starting.
+
+use crossterm::event::KeyCode;
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Colors used throughout the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Logo, key names and highlighted values
+    pub accent: Color,
+    /// Header and status bar borders
+    pub border: Color,
+    /// Table headers, popup titles and the current tab
+    pub heading: Color,
+    /// Regular text
+    pub text: Color,
+    /// Hints and placeholders
+    pub muted: Color,
+    /// Background of the selected row
+    pub selection: Color,
+    /// Running containers and CPU use
+    pub success: Color,
+    /// Paused containers and pending connections
+    pub warning: Color,
+    /// Stopped containers and errors
+    pub error: Color,
+    /// Memory use
+    pub memory: Color,
+}
+
+impl Theme {
+    /// Theme for dark terminal backgrounds
+    pub fn dark() -> Self {
+        Self {
+            accent: Color::Cyan,
+            border: Color::Blue,
+            heading: Color::Yellow,
+            text: Color::White,
+            muted: Color::Gray,
+            selection: Color::DarkGray,
+            success: Color::Green,
+            warning: Color::Yellow,
+            error: Color::Red,
+            memory: Color::Magenta,
+        }
+    }
+
+    /// Theme for light terminal backgrounds
+    pub fn light() -> Self {
+        Self {
+            accent: Color::Rgb(0x00, 0x5f, 0xaf),
+            border: Color::Rgb(0x00, 0x5f, 0x87),
+            heading: Color::Rgb(0x87, 0x5f, 0x00),
+            text: Color::Rgb(0x1c, 0x1c, 0x1c),
+            muted: Color::Rgb(0x6c, 0x6c, 0x6c),
+            selection: Color::Rgb(0xd0, 0xd0, 0xd0),
+            success: Color::Rgb(0x00, 0x87, 0x00),
+            warning: Color::Rgb(0xaf, 0x5f, 0x00),
+            error: Color::Rgb(0xaf, 0x00, 0x00),
+            memory: Color::Rgb(0x87, 0x00, 0xaf),
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> Option<&mut Color> {
+        Some(match name {
+            "accent" => &mut self.accent,
+            "border" => &mut self.border,
+            "heading" => &mut self.heading,
+            "text" => &mut self.text,
+            "muted" => &mut self.muted,
+            "selection" => &mut self.selection,
+            "success" => &mut self.success,
+            "warning" => &mut self.warning,
+            "error" => &mut self.error,
+            "memory" => &mut self.memory,
+            _ => return None,
+        })
+    }
+
+    /// The theme with RGB colors replaced by their nearest 256-color match
+    pub fn to_256(mut self) -> Self {
+        for color in [
+            &mut self.accent,
+            &mut self.border,
+            &mut self.heading,
+            &mut self.text,
+            &mut self.muted,
+            &mut self.selection,
+            &mut self.success,
+            &mut self.warning,
+            &mut self.error,
+            &mut self.memory,
+        ] {
+            if let Color::Rgb(r, g, b) = *color {
+                *color = Color::Indexed(rgb_to_256(r, g, b));
+            }
+        }
+        self
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+/// Nearest color of the xterm 256-color palette, from its color cube or
+/// grayscale ramp
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |c: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&i| (LEVELS[i] as i32 - c as i32).abs())
+            .unwrap()
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    // Grays 232..=255 run from 8 to 238 in steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + gray_step * 10;
+
+    if distance((gray, gray, gray)) < distance(cube) {
+        232 + gray_step
+    } else {
+        cube_index as u8
+    }
+}
+
+/// Something a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Quit the TUI
+    Quit,
+    /// Show the help
+    Help,
+    /// Switch to the next tab
+    NextTab,
+    /// Switch to the previous tab
+    PreviousTab,
+    /// Move the selection up
+    Up,
+    /// Move the selection down
+    Down,
+    /// Act on the selection
+    Select,
+    /// Start the selected container
+    Start,
+    /// Stop the selected container
+    Stop,
+    /// Restart the selected container
+    Restart,
+    /// Delete the selected container
+    Delete,
+    /// Pause the selected container
+    Pause,
+    /// Unpause the selected container
+    Unpause,
+    /// Show or hide the stats panel
+    ToggleStats,
+    /// Show stats of all containers or the selected one
+    ToggleAggregate,
+    /// Show live stats or those the daemon recorded
+    ToggleHistory,
+    /// Open the connection picker
+    SwitchContext,
+    /// Edit the container filter
+    Filter,
+    /// Apply the next saved filter
+    NextFilter,
+    /// Save the current filter
+    SaveFilter,
+    /// Show or hide stopped containers
+    ToggleStopped,
+}
+
+impl Action {
+    /// All actions in the order they are listed in the help
+    pub const ALL: [Action; 21] = [
+        Action::NextTab,
+        Action::PreviousTab,
+        Action::Up,
+        Action::Down,
+        Action::Select,
+        Action::Start,
+        Action::Stop,
+        Action::Restart,
+        Action::Pause,
+        Action::Unpause,
+        Action::Delete,
+        Action::Filter,
+        Action::NextFilter,
+        Action::SaveFilter,
+        Action::ToggleStopped,
+        Action::ToggleStats,
+        Action::ToggleAggregate,
+        Action::ToggleHistory,
+        Action::SwitchContext,
+        Action::Help,
+        Action::Quit,
+    ];
+
+    /// Name of the action in the `[keys]` table
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "help",
+            Action::NextTab => "next_tab",
+            Action::PreviousTab => "previous_tab",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Select => "select",
+            Action::Start => "start",
+            Action::Stop => "stop",
+            Action::Restart => "restart",
+            Action::Delete => "delete",
+            Action::Pause => "pause",
+            Action::Unpause => "unpause",
+            Action::ToggleStats => "toggle_stats",
+            Action::ToggleAggregate => "toggle_aggregate",
+            Action::ToggleHistory => "toggle_history",
+            Action::SwitchContext => "switch_context",
+            Action::Filter => "filter",
+            Action::NextFilter => "next_filter",
+            Action::SaveFilter => "save_filter",
+            Action::ToggleStopped => "toggle_stopped",
+        }
+    }
+
+    /// Description shown in the help
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Help => "Show this help",
+            Action::NextTab => "Next tab",
+            Action::PreviousTab => "Previous tab",
+            Action::Up => "Move up",
+            Action::Down => "Move down",
+            Action::Select => "View details",
+            Action::Start => "Start container",
+            Action::Stop => "Stop container",
+            Action::Restart => "Restart container",
+            Action::Delete => "Delete container",
+            Action::Pause => "Pause container",
+            Action::Unpause => "Unpause container",
+            Action::ToggleStats => "Toggle stats panel",
+            Action::ToggleAggregate => "Stats of all / selected containers",
+            Action::ToggleHistory => "Live / recorded stats",
+            Action::SwitchContext => "Switch daemon context",
+            Action::Filter => "Filter containers",
+            Action::NextFilter => "Next saved filter",
+            Action::SaveFilter => "Save filter",
+            Action::ToggleStopped => "Show / hide stopped containers",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        use KeyCode::*;
+        match self {
+            Action::Quit => vec![Char('q')],
+            Action::Help => vec![Char('?'), F(1)],
+            Action::NextTab => vec![Tab, Right],
+            Action::PreviousTab => vec![BackTab, Left],
+            Action::Up => vec![KeyCode::Up, Char('k')],
+            Action::Down => vec![KeyCode::Down, Char('j')],
+            Action::Select => vec![Enter],
+            Action::Start => vec![Char('s')],
+            Action::Stop => vec![Char('S')],
+            Action::Restart => vec![Char('r')],
+            Action::Delete => vec![Char('d'), KeyCode::Delete],
+            Action::Pause => vec![Char('p')],
+            Action::Unpause => vec![Char('u')],
+            Action::ToggleStats => vec![Char('t')],
+            Action::ToggleAggregate => vec![Char('a')],
+            Action::ToggleHistory => vec![Char('H')],
+            Action::SwitchContext => vec![Char('c')],
+            Action::Filter => vec![Char('/')],
+            Action::NextFilter => vec![Char('f')],
+            Action::SaveFilter => vec![Char('F')],
+            Action::ToggleStopped => vec![Char('A')],
+        }
+    }
+}
+
+/// Parse a key name: a single character, `F1`-`F12`, or one of `Enter`,
+/// `Esc`, `Tab`, `BackTab`, `Backspace`, `Delete`, `Insert`, `Home`, `End`,
+/// `PageUp`, `PageDown`, `Up`, `Down`, `Left`, `Right` and `Space`
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    if let Some(n) = name
+        .strip_prefix(['F', 'f'])
+        .and_then(|n| n.parse::<u8>().ok())
+    {
+        return (1..=12).contains(&n).then_some(KeyCode::F(n));
+    }
+    Some(match name.to_ascii_lowercase().as_str() {
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        _ => return None,
+    })
+}
+
+/// Short name of a key for the help and status bar
+pub fn key_label(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::PageUp => "PgUp".to_string(),
+        KeyCode::PageDown => "PgDn".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Keys bound to each action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: HashMap<Action, Vec<KeyCode>>,
+}
+
+impl KeyMap {
+    /// Action bound to a key
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|action| self.keys(*action).contains(&key))
+    }
+
+    /// Keys bound to an action
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Keys of an action for display, e.g. `? / F1`
+    pub fn label(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|k| key_label(*k))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+
+    /// First key bound to both actions, if any
+    fn conflict(&self) -> Option<(KeyCode, Action, Action)> {
+        for (i, a) in Action::ALL.iter().enumerate() {
+            for b in &Action::ALL[i + 1..] {
+                if let Some(key) = self.keys(*a).iter().find(|k| self.keys(*b).contains(k)) {
+                    return Some((*key, *a, *b));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            bindings: Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+/// One key or several in the `[keys]` table
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum KeySpec {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Layout of `tui.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    color_mode: Option<String>,
+    colors: HashMap<String, String>,
+    keys: HashMap<String, KeySpec>,
+}
+
+/// Loaded TUI configuration
+#[derive(Debug, Clone, Default)]
+pub struct TuiConfig {
+    /// Colors
+    pub theme: Theme,
+    /// Key bindings
+    pub keys: KeyMap,
+    /// Problems found while loading, shown when the TUI starts
+    pub errors: Vec<String>,
+}
+
+impl TuiConfig {
+    /// Default location of the config file
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("/etc"))
+            .join("rune")
+            .join("tui.toml")
+    }
+
+    /// Load the config file at its default location
+    pub fn load() -> Self {
+        Self::load_from(Self::default_path())
+    }
+
+    /// Load a config file; a missing file gives the defaults
+    pub fn load_from(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => Self {
+                errors: vec![format!("Cannot read {}: {}", path.display(), e)],
+                ..Self::default()
+            },
+        }
+    }
+
+    /// Parse a config file, keeping the defaults for invalid entries
+    pub fn parse(content: &str) -> Self {
+        let mut config = Self::default();
+        let file: ConfigFile = match toml::from_str(content) {
+            Ok(file) => file,
+            Err(e) => {
+                config
+                    .errors
+                    .push(format!("Invalid tui.toml: {}", e.message()));
+                return config;
+            }
+        };
+
+        config.theme = match file.theme.as_deref() {
+            None | Some("dark") => Theme::dark(),
+            Some("light") => Theme::light(),
+            Some(other) => {
+                config
+                    .errors
+                    .push(format!("Unknown theme '{}', expected dark or light", other));
+                Theme::dark()
+            }
+        };
+
+        let mut colors: Vec<_> = file.colors.into_iter().collect();
+        colors.sort();
+        for (slot, value) in colors {
+            let Some(color) = config.theme.slot(&slot) else {
+                config.errors.push(format!("Unknown color '{}'", slot));
+                continue;
+            };
+            match value.parse::<Color>() {
+                Ok(parsed) => *color = parsed,
+                Err(_) => config
+                    .errors
+                    .push(format!("Invalid color '{}' for {}", value, slot)),
+            }
+        }
+
+        let truecolor = match file.color_mode.as_deref() {
+            None | Some("auto") => std::env::var("COLORTERM")
+                .map(|v| v == "truecolor" || v == "24bit")
+                .unwrap_or(false),
+            Some("truecolor") => true,
+            Some("256") => false,
+            Some(other) => {
+                config.errors.push(format!(
+                    "Unknown color mode '{}', expected auto, truecolor or 256",
+                    other
+                ));
+                true
+            }
+        };
+        if !truecolor {
+            config.theme = config.theme.to_256();
+        }
+
+        let mut keys: Vec<_> = file.keys.into_iter().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut overridden = Vec::new();
+        for (name, spec) in keys {
+            let Some(action) = Action::from_name(&name) else {
+                config.errors.push(format!("Unknown action '{}'", name));
+                continue;
+            };
+            let names = match spec {
+                KeySpec::One(name) => vec![name],
+                KeySpec::Many(names) => names,
+            };
+            let mut bound = Vec::new();
+            for key in &names {
+                match parse_key(key) {
+                    Some(code) if !bound.contains(&code) => bound.push(code),
+                    Some(_) => {}
+                    None => config
+                        .errors
+                        .push(format!("Unknown key '{}' for {}", key, name)),
+                }
+            }
+            if bound.is_empty() {
+                config
+                    .errors
+                    .push(format!("No usable keys for {}, keeping the default", name));
+                continue;
+            }
+            config.keys.bindings.insert(action, bound);
+            overridden.push(action);
+        }
+
+        // Resolve clashes by restoring the defaults of a remapped action
+        while let Some((key, a, b)) = config.keys.conflict() {
+            let reset = if overridden.contains(&b) { b } else { a };
+            config.errors.push(format!(
+                "Key '{}' is bound to both {} and {}, keeping the default for {}",
+                key_label(key),
+                a.name(),
+                b.name(),
+                reset.name()
+            ));
+            config.keys.bindings.insert(reset, reset.default_keys());
+            overridden.retain(|action| *action != reset);
+        }
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = TuiConfig::parse(
+            r##"
+theme = "light"
+color_mode = "truecolor"
+
+[colors]
+accent = "#ff0000"
+border = "not-a-color"
+
+[keys]
+quit = ["x", "Esc"]
+start = "F5"
+stop = "j"
+frobnicate = "z"
+"##,
+        );
+        assert_eq!(config.theme.accent, Color::Rgb(0xff, 0, 0));
+        assert_eq!(config.theme.text, Theme::light().text);
+        assert_eq!(config.keys.action(KeyCode::Esc), Some(Action::Quit));
+        assert_eq!(config.keys.action(KeyCode::Char('q')), None);
+        assert_eq!(config.keys.action(KeyCode::F(5)), Some(Action::Start));
+        // `j` already moves down, so stop keeps its default
+        assert_eq!(config.keys.action(KeyCode::Char('j')), Some(Action::Down));
+        assert_eq!(config.keys.label(Action::Stop), "S");
+        assert_eq!(config.errors.len(), 3, "{:?}", config.errors);
+
+        let broken = TuiConfig::parse("theme = ");
+        assert_eq!(broken.errors.len(), 1);
+        assert_eq!(broken.keys, KeyMap::default());
+    }
+
+    #[test]
+    fn test_256_color_fallback() {
+        assert_eq!(rgb_to_256(0xaf, 0x00, 0x00), 124);
+        assert_eq!(rgb_to_256(0x6c, 0x6c, 0x6c), 242);
+        assert_eq!(rgb_to_256(0xff, 0xff, 0xff), 231);
+
+        let theme = Theme::light().to_256();
+        assert!(!matches!(theme.accent, Color::Rgb(..)));
+        assert_eq!(Theme::dark().to_256(), Theme::dark());
+    }
+}
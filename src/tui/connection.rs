@@ -0,0 +1,285 @@
+//! Daemon connection for the TUI
+//!
+//! The TUI manages containers through a [`ContainerService`], normally the
+//! daemon of a context. When the daemon stops answering, the connection is
+//! marked lost and retried every few seconds, so the TUI carries on once
+//! the daemon is back, e.g. after a restart.
+
+use crate::container::{ContainerConfig, ContainerManager};
+use crate::daemon::DaemonClient;
+use crate::error::Result;
+use crate::runtime::{CgroupMetrics, ContainerMetrics, MetricsSource};
+use crate::storage::MetricsSample;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time between attempts to reach a lost daemon
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Container operations the TUI needs
+pub trait ContainerService {
+    /// Name shown in the header, e.g. the context name
+    fn name(&self) -> String;
+    /// List all containers, including stopped ones
+    fn list(&self) -> Result<Vec<ContainerConfig>>;
+    /// Start a container
+    fn start(&self, id: &str) -> Result<()>;
+    /// Stop a container
+    fn stop(&self, id: &str) -> Result<()>;
+    /// Restart a container
+    fn restart(&self, id: &str) -> Result<()>;
+    /// Pause a container
+    fn pause(&self, id: &str) -> Result<()>;
+    /// Unpause a container
+    fn unpause(&self, id: &str) -> Result<()>;
+    /// Remove a container, stopping it first
+    fn remove(&self, id: &str) -> Result<()>;
+    /// Current resource use of a running container
+    fn stats(&self, container: &ContainerConfig) -> Result<ContainerMetrics>;
+    /// Samples of a container kept since `since`, oldest first
+    fn stats_history(&self, id: &str, since: DateTime<Utc>) -> Result<Vec<MetricsSample>>;
+}
+
+impl ContainerService for DaemonClient {
+    fn name(&self) -> String {
+        format!("{} ({})", self.context_name(), self.endpoint())
+    }
+
+    fn list(&self) -> Result<Vec<ContainerConfig>> {
+        self.list_containers(true)
+    }
+
+    fn start(&self, id: &str) -> Result<()> {
+        self.start_container(id)
+    }
+
+    fn stop(&self, id: &str) -> Result<()> {
+        self.stop_container(id)
+    }
+
+    fn restart(&self, id: &str) -> Result<()> {
+        self.restart_container(id)
+    }
+
+    fn pause(&self, id: &str) -> Result<()> {
+        self.pause_container(id)
+    }
+
+    fn unpause(&self, id: &str) -> Result<()> {
+        self.unpause_container(id)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.remove_container(id, true)
+    }
+
+    /// Sampled on the daemon's host, which need not be this one
+    fn stats(&self, container: &ContainerConfig) -> Result<ContainerMetrics> {
+        self.container_stats(&container.id)
+    }
+
+    fn stats_history(&self, id: &str, since: DateTime<Utc>) -> Result<Vec<MetricsSample>> {
+        DaemonClient::stats_history(self, id, since)
+    }
+}
+
+/// Containers managed in-process, without a daemon
+impl ContainerService for Arc<ContainerManager> {
+    fn name(&self) -> String {
+        "local".to_string()
+    }
+
+    fn list(&self) -> Result<Vec<ContainerConfig>> {
+        ContainerManager::list(self, true)
+    }
+
+    fn start(&self, id: &str) -> Result<()> {
+        ContainerManager::start(self, id)
+    }
+
+    fn stop(&self, id: &str) -> Result<()> {
+        ContainerManager::stop(self, id)
+    }
+
+    fn restart(&self, id: &str) -> Result<()> {
+        let _ = ContainerManager::stop(self, id);
+        ContainerManager::start(self, id)
+    }
+
+    fn pause(&self, id: &str) -> Result<()> {
+        ContainerManager::pause(self, id)
+    }
+
+    fn unpause(&self, id: &str) -> Result<()> {
+        ContainerManager::unpause(self, id)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        ContainerManager::remove(self, id, true)
+    }
+
+    /// The containers run on this host, so their cgroups are read directly
+    fn stats(&self, container: &ContainerConfig) -> Result<ContainerMetrics> {
+        CgroupMetrics::new()?.sample(&container.id, container.pid)
+    }
+
+    /// Only a daemon keeps samples
+    fn stats_history(&self, _id: &str, _since: DateTime<Utc>) -> Result<Vec<MetricsSample>> {
+        Ok(Vec::new())
+    }
+}
+
+/// State of the connection to a service
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// Not polled yet
+    Connecting,
+    /// The last poll succeeded
+    Connected,
+    /// The last poll failed
+    Lost {
+        /// Error of the last attempt
+        error: String,
+        /// Failed attempts so far
+        attempts: u32,
+        /// When to try again
+        retry_at: Instant,
+    },
+}
+
+/// A container service and whether it is reachable
+pub struct Connection {
+    service: Box<dyn ContainerService>,
+    state: ConnectionState,
+}
+
+impl Connection {
+    /// Wrap a service; nothing is sent until the first poll
+    pub fn new(service: Box<dyn ContainerService>) -> Self {
+        Self {
+            service,
+            state: ConnectionState::Connecting,
+        }
+    }
+
+    /// The service
+    pub fn service(&self) -> &dyn ContainerService {
+        self.service.as_ref()
+    }
+
+    /// State of the connection
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// List containers unless waiting to retry a lost connection
+    ///
+    /// Returns `None` while the service is unreachable.
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<ContainerConfig>> {
+        let attempts = match self.state {
+            ConnectionState::Lost { retry_at, .. } if now < retry_at => return None,
+            ConnectionState::Lost { attempts, .. } => attempts,
+            _ => 0,
+        };
+
+        match self.service.list() {
+            Ok(containers) => {
+                if attempts > 0 {
+                    tracing::debug!("Reconnected to {}", self.service.name());
+                }
+                self.state = ConnectionState::Connected;
+                Some(containers)
+            }
+            Err(e) => {
+                self.state = ConnectionState::Lost {
+                    error: e.to_string(),
+                    attempts: attempts + 1,
+                    retry_at: now + RECONNECT_INTERVAL,
+                };
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RuneError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    struct FlakyService {
+        up: Arc<AtomicBool>,
+    }
+
+    impl ContainerService for FlakyService {
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn list(&self) -> Result<Vec<ContainerConfig>> {
+            if self.up.load(Ordering::SeqCst) {
+                Ok(vec![ContainerConfig::default()])
+            } else {
+                Err(RuneError::Daemon("connection refused".to_string()))
+            }
+        }
+
+        fn start(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn stop(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn restart(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn pause(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn unpause(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove(&self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn stats(&self, _: &ContainerConfig) -> Result<ContainerMetrics> {
+            Ok(ContainerMetrics::default())
+        }
+
+        fn stats_history(&self, _: &str, _: DateTime<Utc>) -> Result<Vec<MetricsSample>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let up = Arc::new(AtomicBool::new(true));
+        let mut connection = Connection::new(Box::new(FlakyService { up: up.clone() }));
+        let start = Instant::now();
+
+        assert_eq!(connection.poll(start).map(|c| c.len()), Some(1));
+        assert_eq!(connection.state(), &ConnectionState::Connected);
+
+        // The daemon goes away
+        up.store(false, Ordering::SeqCst);
+        assert!(connection.poll(start).is_none());
+        assert!(matches!(
+            connection.state(),
+            ConnectionState::Lost { attempts: 1, .. }
+        ));
+
+        // Back again, but not retried before the interval has passed
+        up.store(true, Ordering::SeqCst);
+        assert!(connection.poll(start + Duration::from_secs(1)).is_none());
+        assert!(connection.poll(start + RECONNECT_INTERVAL).is_some());
+        assert_eq!(connection.state(), &ConnectionState::Connected);
+    }
+}
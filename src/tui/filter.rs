@@ -0,0 +1,207 @@
+//! Container list filtering
+//!
+//! A filter is a list of whitespace-separated terms that must all match.
+//! Bare words match a container's name, image or ID; `name:`, `image:`,
+//! `status:` and `label:key[=value]` match one field only. Matching is
+//! case-insensitive and by substring, except that statuses match by prefix
+//! and label keys must match exactly.
+//!
+//! Filters worth keeping are saved to `tui-filters.json` in the Rune config
+//! directory.
+
+use crate::container::ContainerConfig;
+use crate::error::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One term of a filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Text(String),
+    Name(String),
+    Image(String),
+    Status(String),
+    Label { key: String, value: Option<String> },
+}
+
+/// A parsed filter query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerFilter {
+    terms: Vec<Term>,
+}
+
+impl ContainerFilter {
+    /// Parse a filter query
+    pub fn parse(query: &str) -> Self {
+        let terms = query
+            .split_whitespace()
+            .map(|term| {
+                let lower = term.to_lowercase();
+                match lower.split_once(':') {
+                    Some(("name", name)) => Term::Name(name.to_string()),
+                    Some(("image", image)) => Term::Image(image.to_string()),
+                    Some(("status", status)) => Term::Status(status.to_string()),
+                    // Label keys and values keep their case
+                    Some(("label", _)) => {
+                        let label = &term["label:".len()..];
+                        match label.split_once('=') {
+                            Some((key, value)) => Term::Label {
+                                key: key.to_string(),
+                                value: Some(value.to_lowercase()),
+                            },
+                            None => Term::Label {
+                                key: label.to_string(),
+                                value: None,
+                            },
+                        }
+                    }
+                    _ => Term::Text(lower),
+                }
+            })
+            .collect();
+        Self { terms }
+    }
+
+    /// Whether the filter has no terms and matches everything
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether a container matches every term
+    pub fn matches(&self, container: &ContainerConfig) -> bool {
+        let contains = |field: &str, needle: &str| field.to_lowercase().contains(needle);
+        self.terms.iter().all(|term| match term {
+            Term::Text(text) => {
+                contains(&container.name, text)
+                    || contains(&container.image, text)
+                    || container.id.starts_with(text.as_str())
+            }
+            Term::Name(name) => contains(&container.name, name),
+            Term::Image(image) => contains(&container.image, image),
+            Term::Status(status) => container.status.to_string().starts_with(status.as_str()),
+            Term::Label { key, value } => match (container.labels.get(key), value) {
+                (Some(actual), Some(value)) => contains(actual, value),
+                (Some(_), None) => true,
+                (None, _) => false,
+            },
+        })
+    }
+}
+
+/// Filter queries saved for reuse
+#[derive(Debug, Clone)]
+pub struct SavedFilters {
+    path: PathBuf,
+    queries: Vec<String>,
+}
+
+impl SavedFilters {
+    /// Default location of the saved filters
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("/etc"))
+            .join("rune")
+            .join("tui-filters.json")
+    }
+
+    /// Load saved filters; a missing file gives none
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let queries = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self { path, queries })
+    }
+
+    /// Saved queries, oldest first
+    pub fn queries(&self) -> &[String] {
+        &self.queries
+    }
+
+    /// Save a query, unless it is empty or already saved
+    ///
+    /// Returns whether the query was added.
+    pub fn add(&mut self, query: &str) -> Result<bool> {
+        let query = query.trim();
+        if query.is_empty() || self.queries.iter().any(|q| q == query) {
+            return Ok(false);
+        }
+        self.queries.push(query.to_string());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.queries)?)?;
+        Ok(true)
+    }
+
+    /// Saved query following `current`, wrapping around; the first one if
+    /// `current` is not saved
+    pub fn next_after(&self, current: &str) -> Option<&str> {
+        let next = match self.queries.iter().position(|q| q == current.trim()) {
+            Some(i) => (i + 1) % self.queries.len(),
+            None => 0,
+        };
+        self.queries.get(next).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerStatus;
+    use tempfile::TempDir;
+
+    fn container(name: &str, image: &str, status: ContainerStatus) -> ContainerConfig {
+        ContainerConfig {
+            name: name.to_string(),
+            image: image.to_string(),
+            status,
+            ..ContainerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let mut web = container("web-1", "nginx:latest", ContainerStatus::Running);
+        web.labels
+            .insert("com.example.Tier".to_string(), "Frontend".to_string());
+        let db = container("db", "postgres:16", ContainerStatus::Exited);
+
+        let matching = |query: &str| -> Vec<String> {
+            let filter = ContainerFilter::parse(query);
+            [&web, &db]
+                .into_iter()
+                .filter(|c| filter.matches(c))
+                .map(|c| c.name.clone())
+                .collect()
+        };
+
+        assert_eq!(matching(""), vec!["web-1", "db"]);
+        assert_eq!(matching("NGINX"), vec!["web-1"]);
+        assert_eq!(matching("status:run"), vec!["web-1"]);
+        assert_eq!(matching("image:postgres status:exited"), vec!["db"]);
+        assert_eq!(matching("label:com.example.Tier=front"), vec!["web-1"]);
+        assert!(matching("label:com.example.tier").is_empty());
+        assert!(matching("name:web status:exited").is_empty());
+    }
+
+    #[test]
+    fn test_saved_filters() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tui-filters.json");
+
+        let mut saved = SavedFilters::load(&path).unwrap();
+        assert_eq!(saved.next_after(""), None);
+        assert!(saved.add("status:running").unwrap());
+        assert!(saved.add(" image:nginx ").unwrap());
+        assert!(!saved.add("status:running").unwrap());
+
+        let saved = SavedFilters::load(&path).unwrap();
+        assert_eq!(saved.queries(), ["status:running", "image:nginx"]);
+        assert_eq!(saved.next_after(""), Some("status:running"));
+        assert_eq!(saved.next_after("status:running"), Some("image:nginx"));
+        assert_eq!(saved.next_after("image:nginx"), Some("status:running"));
+    }
+}
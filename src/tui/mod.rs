@@ -1,5 +1,11 @@
 //! TUI module
 
 pub mod app;
+pub mod config;
+pub mod connection;
+pub mod filter;
+pub mod stats;
 
 pub use app::App;
+pub use config::TuiConfig;
+pub use connection::{Connection, ConnectionState, ContainerService};
@@ -0,0 +1,310 @@
+//! Container stats for the TUI
+//!
+//! Samples running containers about once a second through the
+//! [`ContainerService`] that manages them, so a remote daemon reports the
+//! containers on its own host, and keeps a short history of CPU, memory and
+//! network rates per container and for all of them together, ready to draw
+//! as sparklines. Histories can also be made from
+//! the samples a daemon keeps, going further back.
+
+use super::connection::ContainerService;
+use crate::container::{ContainerConfig, ContainerStatus};
+use crate::runtime::ContainerMetrics;
+use crate::storage::MetricsSample;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Samples kept per history
+pub const HISTORY_LEN: usize = 120;
+
+/// Time between samples
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resource use over one sampling interval
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rates {
+    /// CPU use as a percentage of one CPU
+    pub cpu_percent: f64,
+    /// Memory in use in bytes
+    pub memory_usage: u64,
+    /// Memory limit in bytes, if one is set
+    pub memory_limit: Option<u64>,
+    /// Bytes received per second
+    pub rx_rate: u64,
+    /// Bytes sent per second
+    pub tx_rate: u64,
+}
+
+/// Recent rates, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct StatsHistory {
+    /// CPU use in hundredths of a percent
+    pub cpu: VecDeque<u64>,
+    /// Memory in use in bytes
+    pub memory: VecDeque<u64>,
+    /// Bytes received per second
+    pub rx: VecDeque<u64>,
+    /// Bytes sent per second
+    pub tx: VecDeque<u64>,
+    /// Latest rates
+    pub latest: Rates,
+}
+
+impl StatsHistory {
+    /// History of the rates between stored samples, keeping the latest
+    pub fn from_samples(samples: &[MetricsSample]) -> Self {
+        let mut history = Self::default();
+        for (_, rates) in sample_rates(samples) {
+            history.push(rates);
+        }
+        history
+    }
+
+    fn push(&mut self, rates: Rates) {
+        let cpu = (rates.cpu_percent * 100.0).round() as u64;
+        for (series, value) in [
+            (&mut self.cpu, cpu),
+            (&mut self.memory, rates.memory_usage),
+            (&mut self.rx, rates.rx_rate),
+            (&mut self.tx, rates.tx_rate),
+        ] {
+            if series.len() == HISTORY_LEN {
+                series.pop_front();
+            }
+            series.push_back(value);
+        }
+        self.latest = rates;
+    }
+}
+
+/// Rates between two samples of the same container
+fn rates(previous: &ContainerMetrics, current: &ContainerMetrics, elapsed: Duration) -> Rates {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    let per_sec = |before: u64, after: u64| (after.saturating_sub(before) as f64 / secs) as u64;
+    let cpu_usec = current
+        .cpu_usage_usec
+        .saturating_sub(previous.cpu_usage_usec);
+
+    Rates {
+        cpu_percent: cpu_usec as f64 / (secs * 1_000_000.0) * 100.0,
+        memory_usage: current.memory_usage,
+        memory_limit: current.memory_limit,
+        rx_rate: per_sec(previous.network.rx_bytes, current.network.rx_bytes),
+        tx_rate: per_sec(previous.network.tx_bytes, current.network.tx_bytes),
+    }
+}
+
+/// Rates between consecutive stored samples, at the time of the later one
+pub fn sample_rates(samples: &[MetricsSample]) -> Vec<(DateTime<Utc>, Rates)> {
+    samples
+        .windows(2)
+        .map(|pair| {
+            let elapsed = (pair[1].time - pair[0].time).to_std().unwrap_or_default();
+            let rates = rates(
+                &ContainerMetrics::from(&pair[0]),
+                &ContainerMetrics::from(&pair[1]),
+                elapsed,
+            );
+            (pair[1].time, rates)
+        })
+        .collect()
+}
+
+/// Collects stats of running containers
+#[derive(Default)]
+pub struct StatsCollector {
+    /// Last sample of each container
+    previous: HashMap<String, (Instant, ContainerMetrics)>,
+    /// History of each container
+    histories: HashMap<String, StatsHistory>,
+    /// History of all containers together
+    aggregate: StatsHistory,
+    last_poll: Option<Instant>,
+    /// Why no running container could be sampled in the last poll
+    error: Option<String>,
+}
+
+impl StatsCollector {
+    /// Create a collector with no samples yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sample the running containers of a service if the poll interval has
+    /// passed
+    pub fn poll(&mut self, service: &dyn ContainerService, containers: &[ContainerConfig]) {
+        let now = Instant::now();
+        if self
+            .last_poll
+            .is_some_and(|last| now.duration_since(last) < POLL_INTERVAL)
+        {
+            return;
+        }
+        self.last_poll = Some(now);
+
+        let mut samples = Vec::new();
+        let mut error = None;
+        for container in containers
+            .iter()
+            .filter(|c| c.status == ContainerStatus::Running)
+        {
+            match service.stats(container) {
+                Ok(metrics) => samples.push((container.id.clone(), metrics)),
+                Err(e) => error = Some(e.to_string()),
+            }
+        }
+        // One container going away between listing and sampling is no error
+        self.error = error.filter(|_| samples.is_empty());
+        self.record(now, samples);
+    }
+
+    /// Add a round of samples taken at `now`
+    fn record(&mut self, now: Instant, samples: Vec<(String, ContainerMetrics)>) {
+        // Forget containers that stopped or went away
+        self.previous
+            .retain(|id, _| samples.iter().any(|(sampled, _)| sampled == id));
+        self.histories
+            .retain(|id, _| samples.iter().any(|(sampled, _)| sampled == id));
+
+        let mut total = Rates {
+            memory_limit: Some(0),
+            ..Rates::default()
+        };
+        let mut any = false;
+        for (id, metrics) in samples {
+            // A container's first sample only sets the baseline
+            if let Some((at, previous)) = self.previous.get(&id) {
+                let rates = rates(previous, &metrics, now.duration_since(*at));
+                self.histories.entry(id.clone()).or_default().push(rates);
+
+                total.cpu_percent += rates.cpu_percent;
+                total.memory_usage += rates.memory_usage;
+                total.memory_limit = total
+                    .memory_limit
+                    .zip(rates.memory_limit)
+                    .map(|(a, b)| a + b);
+                total.rx_rate += rates.rx_rate;
+                total.tx_rate += rates.tx_rate;
+                any = true;
+            }
+            self.previous.insert(id, (now, metrics));
+        }
+
+        if !any {
+            total.memory_limit = None;
+        }
+        self.aggregate.push(total);
+    }
+
+    /// Why the last poll could not sample any running container
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// History of a container, once it has been sampled twice
+    pub fn history(&self, container_id: &str) -> Option<&StatsHistory> {
+        self.histories.get(container_id)
+    }
+
+    /// History of all running containers together
+    pub fn aggregate(&self) -> &StatsHistory {
+        &self.aggregate
+    }
+}
+
+/// Format a byte count, e.g. `1.5MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::metrics::NetworkStats;
+
+    fn metrics(cpu_usage_usec: u64, memory_usage: u64, rx_bytes: u64) -> ContainerMetrics {
+        ContainerMetrics {
+            cpu_usage_usec,
+            memory_usage,
+            memory_limit: Some(1 << 30),
+            network: NetworkStats {
+                rx_bytes,
+                tx_bytes: 0,
+            },
+            ..ContainerMetrics::default()
+        }
+    }
+
+    #[test]
+    fn test_collector_rates() {
+        let mut collector = StatsCollector::new();
+        let start = Instant::now();
+        let later = start + Duration::from_secs(2);
+
+        collector.record(
+            start,
+            vec![
+                ("a".to_string(), metrics(0, 100, 0)),
+                ("b".to_string(), metrics(0, 200, 0)),
+            ],
+        );
+        assert!(collector.history("a").is_none());
+
+        collector.record(
+            later,
+            vec![
+                ("a".to_string(), metrics(1_000_000, 150, 4096)),
+                ("b".to_string(), metrics(500_000, 250, 0)),
+            ],
+        );
+        let a = collector.history("a").unwrap();
+        assert_eq!(a.latest.cpu_percent, 50.0);
+        assert_eq!(a.latest.rx_rate, 2048);
+        assert_eq!(a.cpu.back(), Some(&5000));
+
+        let total = collector.aggregate().latest;
+        assert_eq!(total.cpu_percent, 75.0);
+        assert_eq!(total.memory_usage, 400);
+        assert_eq!(total.memory_limit, Some(2 << 30));
+
+        // Stopped containers are forgotten
+        collector.record(later + Duration::from_secs(1), vec![]);
+        assert!(collector.history("a").is_none());
+        assert_eq!(format_bytes(1536), "1.5KiB");
+    }
+
+    #[test]
+    fn test_sample_rates() {
+        let start = Utc::now();
+        let samples: Vec<MetricsSample> = [(0, 0), (10, 5_000_000), (20, 7_500_000)]
+            .into_iter()
+            .map(|(secs, cpu)| {
+                MetricsSample::new(
+                    start + chrono::Duration::seconds(secs),
+                    &metrics(cpu, 100, 0),
+                )
+            })
+            .collect();
+        let rates = sample_rates(&samples);
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0].0, samples[1].time);
+        assert_eq!(rates[0].1.cpu_percent, 50.0);
+        assert_eq!(rates[1].1.cpu_percent, 25.0);
+
+        let history = StatsHistory::from_samples(&samples);
+        assert_eq!(history.cpu, [5000, 2500]);
+        assert_eq!(history.latest.memory_limit, Some(1 << 30));
+    }
+}